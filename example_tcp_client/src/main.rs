@@ -235,6 +235,9 @@ fn read_from_kanata(s: TcpStream) {
                 ServerResponse::Error { msg } => {
                     log::error!("✗ Command failed: {}", msg);
                 }
+                ServerResponse::Forbidden { command, msg } => {
+                    log::error!("✗ {} is not allowed: {}", command, msg);
+                }
             }
             continue;
         }
@@ -0,0 +1,78 @@
+/*
+ * C bindings for the kanata remapping engine and TCP protocol.
+ *
+ * Functions that fail return NULL or a negative number;
+ * kanata_last_error() then describes the failure.
+ * Strings returned as `char *` must be freed with kanata_string_free().
+ */
+
+#ifndef KANATA_H
+#define KANATA_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct KanataEngine KanataEngine;
+
+/* Values of KanataOutput.kind. */
+#define KANATA_OUTPUT_KEY 0           /* code: key code, value: 0 release, 1 press, 2 repeat */
+#define KANATA_OUTPUT_CODE 1          /* code: raw output code, value: as for KANATA_OUTPUT_KEY */
+#define KANATA_OUTPUT_UNICODE 2       /* code: unicode scalar value, or 0 and text: the text */
+#define KANATA_OUTPUT_MOUSE_PRESS 3   /* code: 0 left, 1 right, 2 middle, 3 forward, 4 backward */
+#define KANATA_OUTPUT_MOUSE_RELEASE 4 /* code: as for KANATA_OUTPUT_MOUSE_PRESS */
+#define KANATA_OUTPUT_SCROLL 5        /* code: 0 up, 1 down, 2 left, 3 right, value: distance */
+#define KANATA_OUTPUT_MOUSE_MOVE 6    /* code: as for KANATA_OUTPUT_SCROLL, value: distance */
+#define KANATA_OUTPUT_MOUSE_SET 7     /* x, y: absolute position */
+#define KANATA_OUTPUT_MOUSE_WARP 8    /* code: monitor, x, y: fraction of the monitor's size */
+#define KANATA_OUTPUT_MIDI 9          /* code: the three message bytes, most significant first */
+#define KANATA_OUTPUT_SOUND 10        /* code: 0 beep, 1 file, text: file path */
+#define KANATA_OUTPUT_OBS 11          /* text: JSON array of the request and its arguments */
+#define KANATA_OUTPUT_WEBHOOK 12      /* text: JSON object with the webhook name and request body */
+#define KANATA_OUTPUT_NOTIFY 13       /* text: JSON object with the notification title and body */
+#define KANATA_OUTPUT_MPRIS 14        /* text: JSON object with the media player command and player */
+#define KANATA_OUTPUT_OS_LAYOUT 15    /* text: the OS keyboard layout to switch to */
+#define KANATA_OUTPUT_SECRET 16       /* text: the name of the secret to type */
+#define KANATA_OUTPUT_MOUSE_TOWARD 17 /* code: monitor or 0 for the pointer's, value: distance %,
+                                         x, y: anchor as a fraction of the monitor's size */
+#define KANATA_OUTPUT_HID_USAGE 18    /* code: usage page << 16 | usage id, value: as for KEY */
+#define KANATA_OUTPUT_OPENRGB 19      /* code: the color as 0xRRGGBB */
+
+typedef struct KanataOutput {
+    uint32_t kind;
+    uint32_t code;
+    int32_t value;
+    double x;
+    double y;
+    /* Valid until the next kanata_engine_poll_output() call. NULL if unused. */
+    const char *text;
+} KanataOutput;
+
+/* Valid until the next failing call on the same thread. */
+const char *kanata_last_error(void);
+void kanata_string_free(char *s);
+
+/* Returns the key code for a kanata key name such as "a" or "lctl", or -1. */
+int32_t kanata_key_code(const char *name);
+
+KanataEngine *kanata_engine_new(const char *cfg);
+void kanata_engine_free(KanataEngine *engine);
+/* value: 0 release, 1 press, 2 repeat. Returns 0 on success. */
+int32_t kanata_engine_push_key(KanataEngine *engine, uint16_t code, int32_t value);
+/* Returns 1 if the engine is idle until the next input, 0 if not. */
+int32_t kanata_engine_tick(KanataEngine *engine, uint16_t ms);
+/* Returns 1 and writes to out if there was an output, or 0. */
+int32_t kanata_engine_poll_output(KanataEngine *engine, KanataOutput *out);
+
+/* Validates a client message and returns it as compact JSON. */
+char *kanata_client_message_json(const char *json);
+/* Returns the kind of a server message, e.g. "LayerChange". */
+char *kanata_server_message_kind(const char *json);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* KANATA_H */
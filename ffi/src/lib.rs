@@ -0,0 +1,610 @@
+//! C bindings for kanata.
+//!
+//! The engine functions wrap [`kanata_state_machine::engine::Engine`]: the caller loads a
+//! configuration, pushes input events, advances time, and polls for outputs. The protocol
+//! functions help frontends that talk to a kanata TCP server work with its JSON messages.
+//!
+//! See `include/kanata.h` for the C declarations. Functions that fail return null or a negative
+//! number, and [`kanata_last_error`] describes the failure.
+
+use anyhow::{Result, anyhow};
+use kanata_parser::custom_action::{Btn, MWheelDirection, MoveDirection};
+use kanata_parser::keys::OsCode;
+use kanata_state_machine::engine::Engine;
+use kanata_state_machine::oskbd::{KeyEvent, KeyValue, OutputEvent};
+use kanata_state_machine::str_to_oscode;
+use kanata_tcp_protocol::{ClientMessage, ServerMessage};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString, c_char};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(e: impl std::fmt::Display) {
+    let msg = CString::new(e.to_string().replace('\0', "")).expect("nul bytes are removed");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(msg));
+}
+
+/// Returns a description of the last error on this thread, or null if there was none.
+/// The string is valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn kanata_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map(|msg| msg.as_ptr())
+            .unwrap_or(ptr::null())
+    })
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(anyhow!("string argument is null"));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| anyhow!("string argument is not UTF-8: {e}"))
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    match CString::new(s) {
+        Ok(s) => s.into_raw(),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Frees a string returned by a `kanata_` function.
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by a `kanata_` function that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn kanata_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Returns the key code for a kanata key name, e.g. `"a"` or `"lctl"`, or -1 if the name is not
+/// known.
+///
+/// # Safety
+///
+/// `name` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn kanata_key_code(name: *const c_char) -> i32 {
+    match str_arg(name)
+        .and_then(|name| str_to_oscode(name).ok_or_else(|| anyhow!("unknown key name: {name}")))
+    {
+        Ok(osc) => i32::from(u16::from(osc)),
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+/// A kanata engine and the outputs that have not been polled yet.
+pub struct KanataEngine {
+    engine: Engine,
+    outputs: Arc<Mutex<VecDeque<OutputEvent>>>,
+    /// Backs the `text` field of the last polled output.
+    text: Option<CString>,
+}
+
+/// Creates an engine from configuration text. Returns null on error.
+///
+/// # Safety
+///
+/// `cfg` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn kanata_engine_new(cfg: *const c_char) -> *mut KanataEngine {
+    let res = str_arg(cfg).and_then(|cfg| {
+        let outputs = Arc::new(Mutex::new(VecDeque::new()));
+        let sink_outputs = outputs.clone();
+        let engine = Engine::new(cfg, move |ev| {
+            sink_outputs
+                .lock()
+                .expect("output queue lock is not poisoned")
+                .push_back(ev)
+        })?;
+        Ok(KanataEngine {
+            engine,
+            outputs,
+            text: None,
+        })
+    });
+    match res {
+        Ok(engine) => Box::into_raw(Box::new(engine)),
+        Err(e) => {
+            set_last_error(format!("{e:?}"));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Frees an engine.
+///
+/// # Safety
+///
+/// `engine` must be null or an engine returned by [`kanata_engine_new`] that has not been freed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn kanata_engine_free(engine: *mut KanataEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Pushes an input event. `value` is 0 for release, 1 for press and 2 for repeat.
+/// Returns 0 on success and -1 on error.
+///
+/// # Safety
+///
+/// `engine` must be a valid engine.
+#[no_mangle]
+pub unsafe extern "C" fn kanata_engine_push_key(
+    engine: *mut KanataEngine,
+    code: u16,
+    value: i32,
+) -> i32 {
+    let engine = &mut *engine;
+    let res = OsCode::from_u16(code)
+        .ok_or_else(|| anyhow!("unknown key code: {code}"))
+        .and_then(|osc| {
+            let value = match value {
+                0 => KeyValue::Release,
+                1 => KeyValue::Press,
+                2 => KeyValue::Repeat,
+                _ => return Err(anyhow!("invalid key value: {value}")),
+            };
+            engine.engine.handle_input(KeyEvent::new(osc, value))
+        });
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+/// Advances the engine by `ms` milliseconds. Returns 1 if the engine is idle and does not need to
+/// be ticked until the next input event, 0 if it is not idle, and -1 on error.
+///
+/// # Safety
+///
+/// `engine` must be a valid engine.
+#[no_mangle]
+pub unsafe extern "C" fn kanata_engine_tick(engine: *mut KanataEngine, ms: u16) -> i32 {
+    let engine = &mut *engine;
+    match engine.engine.tick(ms) {
+        Ok(idle) => i32::from(idle),
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+pub const KANATA_OUTPUT_KEY: u32 = 0;
+pub const KANATA_OUTPUT_CODE: u32 = 1;
+pub const KANATA_OUTPUT_UNICODE: u32 = 2;
+pub const KANATA_OUTPUT_MOUSE_PRESS: u32 = 3;
+pub const KANATA_OUTPUT_MOUSE_RELEASE: u32 = 4;
+pub const KANATA_OUTPUT_SCROLL: u32 = 5;
+pub const KANATA_OUTPUT_MOUSE_MOVE: u32 = 6;
+pub const KANATA_OUTPUT_MOUSE_SET: u32 = 7;
+pub const KANATA_OUTPUT_MOUSE_WARP: u32 = 8;
+pub const KANATA_OUTPUT_MIDI: u32 = 9;
+pub const KANATA_OUTPUT_SOUND: u32 = 10;
+pub const KANATA_OUTPUT_OBS: u32 = 11;
+pub const KANATA_OUTPUT_WEBHOOK: u32 = 12;
+pub const KANATA_OUTPUT_NOTIFY: u32 = 13;
+pub const KANATA_OUTPUT_MPRIS: u32 = 14;
+pub const KANATA_OUTPUT_OS_LAYOUT: u32 = 15;
+pub const KANATA_OUTPUT_SECRET: u32 = 16;
+pub const KANATA_OUTPUT_MOUSE_TOWARD: u32 = 17;
+pub const KANATA_OUTPUT_HID_USAGE: u32 = 18;
+pub const KANATA_OUTPUT_OPENRGB: u32 = 19;
+
+/// An output of the engine. See `include/kanata.h` for the meaning of the fields for each kind.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct KanataOutput {
+    pub kind: u32,
+    pub code: u32,
+    pub value: i32,
+    pub x: f64,
+    pub y: f64,
+    pub text: *const c_char,
+}
+
+impl KanataOutput {
+    fn new(kind: u32, code: u32, value: i32) -> Self {
+        Self {
+            kind,
+            code,
+            value,
+            x: 0.0,
+            y: 0.0,
+            text: ptr::null(),
+        }
+    }
+}
+
+fn key_value_code(value: KeyValue) -> i32 {
+    match value {
+        KeyValue::Release => 0,
+        KeyValue::Press => 1,
+        KeyValue::Repeat => 2,
+        KeyValue::Tap => 3,
+        KeyValue::WakeUp => 4,
+    }
+}
+
+fn btn_code(btn: Btn) -> u32 {
+    match btn {
+        Btn::Left => 0,
+        Btn::Right => 1,
+        Btn::Mid => 2,
+        Btn::Forward => 3,
+        Btn::Backward => 4,
+    }
+}
+
+fn scroll_code(direction: MWheelDirection) -> u32 {
+    match direction {
+        MWheelDirection::Up => 0,
+        MWheelDirection::Down => 1,
+        MWheelDirection::Left => 2,
+        MWheelDirection::Right => 3,
+    }
+}
+
+fn move_code(direction: MoveDirection) -> u32 {
+    match direction {
+        MoveDirection::Up => 0,
+        MoveDirection::Down => 1,
+        MoveDirection::Left => 2,
+        MoveDirection::Right => 3,
+    }
+}
+
+impl KanataEngine {
+    fn c_output(&mut self, event: OutputEvent) -> KanataOutput {
+        use kanata_parser::cfg::SoundCue;
+        self.text = None;
+        match event {
+            OutputEvent::Key { code, value } => KanataOutput::new(
+                KANATA_OUTPUT_KEY,
+                u16::from(code).into(),
+                key_value_code(value),
+            ),
+            OutputEvent::Code { code, value } => {
+                KanataOutput::new(KANATA_OUTPUT_CODE, code, key_value_code(value))
+            }
+            OutputEvent::Unicode(c) => KanataOutput::new(KANATA_OUTPUT_UNICODE, c.into(), 0),
+            OutputEvent::UnicodeStr(text) => {
+                self.text = CString::new(text).ok();
+                KanataOutput {
+                    text: self
+                        .text
+                        .as_ref()
+                        .map(|t| t.as_ptr())
+                        .unwrap_or(ptr::null()),
+                    ..KanataOutput::new(KANATA_OUTPUT_UNICODE, 0, 0)
+                }
+            }
+            OutputEvent::MousePress(btn) => {
+                KanataOutput::new(KANATA_OUTPUT_MOUSE_PRESS, btn_code(btn), 0)
+            }
+            OutputEvent::MouseRelease(btn) => {
+                KanataOutput::new(KANATA_OUTPUT_MOUSE_RELEASE, btn_code(btn), 0)
+            }
+            OutputEvent::Scroll {
+                direction,
+                distance,
+            } => KanataOutput::new(
+                KANATA_OUTPUT_SCROLL,
+                scroll_code(direction),
+                distance.into(),
+            ),
+            OutputEvent::MouseMove {
+                direction,
+                distance,
+            } => KanataOutput::new(
+                KANATA_OUTPUT_MOUSE_MOVE,
+                move_code(direction),
+                distance.into(),
+            ),
+            OutputEvent::MouseSet { x, y } => KanataOutput {
+                x: x.into(),
+                y: y.into(),
+                ..KanataOutput::new(KANATA_OUTPUT_MOUSE_SET, 0, 0)
+            },
+            OutputEvent::MouseWarp { monitor, x, y } => KanataOutput {
+                x,
+                y,
+                ..KanataOutput::new(KANATA_OUTPUT_MOUSE_WARP, monitor as u32, 0)
+            },
+            OutputEvent::Midi(msg) => KanataOutput::new(
+                KANATA_OUTPUT_MIDI,
+                u32::from_be_bytes([0, msg[0], msg[1], msg[2]]),
+                0,
+            ),
+            OutputEvent::Obs(action) => {
+                let mut request = vec![action.name()];
+                request.extend(action.args());
+                self.text = serde_json::to_string(&request)
+                    .ok()
+                    .and_then(|json| CString::new(json).ok());
+                KanataOutput {
+                    text: self
+                        .text
+                        .as_ref()
+                        .map(|t| t.as_ptr())
+                        .unwrap_or(ptr::null()),
+                    ..KanataOutput::new(KANATA_OUTPUT_OBS, 0, 0)
+                }
+            }
+            OutputEvent::OpenRgb(rgb) => KanataOutput::new(
+                KANATA_OUTPUT_OPENRGB,
+                u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]),
+                0,
+            ),
+            OutputEvent::Webhook { name, body } => {
+                self.text =
+                    CString::new(serde_json::json!({ "name": name, "body": body }).to_string())
+                        .ok();
+                KanataOutput {
+                    text: self
+                        .text
+                        .as_ref()
+                        .map(|t| t.as_ptr())
+                        .unwrap_or(ptr::null()),
+                    ..KanataOutput::new(KANATA_OUTPUT_WEBHOOK, 0, 0)
+                }
+            }
+            OutputEvent::Notify { title, body } => {
+                self.text =
+                    CString::new(serde_json::json!({ "title": title, "body": body }).to_string())
+                        .ok();
+                KanataOutput {
+                    text: self
+                        .text
+                        .as_ref()
+                        .map(|t| t.as_ptr())
+                        .unwrap_or(ptr::null()),
+                    ..KanataOutput::new(KANATA_OUTPUT_NOTIFY, 0, 0)
+                }
+            }
+            OutputEvent::Mpris(action) => {
+                let json = serde_json::json!({
+                    "command": action.command.name(),
+                    "player": action.player,
+                });
+                self.text = CString::new(json.to_string()).ok();
+                KanataOutput {
+                    text: self
+                        .text
+                        .as_ref()
+                        .map(|t| t.as_ptr())
+                        .unwrap_or(ptr::null()),
+                    ..KanataOutput::new(KANATA_OUTPUT_MPRIS, 0, 0)
+                }
+            }
+            OutputEvent::OsLayout(layout) => {
+                self.text = CString::new(layout).ok();
+                KanataOutput {
+                    text: self
+                        .text
+                        .as_ref()
+                        .map(|t| t.as_ptr())
+                        .unwrap_or(ptr::null()),
+                    ..KanataOutput::new(KANATA_OUTPUT_OS_LAYOUT, 0, 0)
+                }
+            }
+            OutputEvent::Secret(name) => {
+                self.text = CString::new(name).ok();
+                KanataOutput {
+                    text: self
+                        .text
+                        .as_ref()
+                        .map(|t| t.as_ptr())
+                        .unwrap_or(ptr::null()),
+                    ..KanataOutput::new(KANATA_OUTPUT_SECRET, 0, 0)
+                }
+            }
+            OutputEvent::HidUsage { usage, value } => KanataOutput::new(
+                KANATA_OUTPUT_HID_USAGE,
+                usage.page_id() << 16 | u32::from(usage.usage),
+                key_value_code(value),
+            ),
+            OutputEvent::MouseToward(action) => KanataOutput {
+                x: f64::from(action.anchor_x) / 100.0,
+                y: f64::from(action.anchor_y) / 100.0,
+                ..KanataOutput::new(
+                    KANATA_OUTPUT_MOUSE_TOWARD,
+                    action.monitor.map_or(0, |monitor| u32::from(monitor) + 1),
+                    action.percent.into(),
+                )
+            },
+            OutputEvent::Sound(cue) => match cue {
+                SoundCue::Silent | SoundCue::Beep => KanataOutput::new(KANATA_OUTPUT_SOUND, 0, 0),
+                SoundCue::File(path) => {
+                    self.text = CString::new(path).ok();
+                    KanataOutput {
+                        text: self
+                            .text
+                            .as_ref()
+                            .map(|t| t.as_ptr())
+                            .unwrap_or(ptr::null()),
+                        ..KanataOutput::new(KANATA_OUTPUT_SOUND, 1, 0)
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Takes the oldest output that has not been polled yet. Returns 1 and writes it to `out` if there
+/// was one, or returns 0. The `text` field of the output is valid until the next poll.
+///
+/// # Safety
+///
+/// `engine` must be a valid engine and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn kanata_engine_poll_output(
+    engine: *mut KanataEngine,
+    out: *mut KanataOutput,
+) -> i32 {
+    let engine = &mut *engine;
+    let event = engine
+        .outputs
+        .lock()
+        .expect("output queue lock is not poisoned")
+        .pop_front();
+    match event {
+        Some(event) => {
+            *out = engine.c_output(event);
+            1
+        }
+        None => 0,
+    }
+}
+
+/// Validates a client message and returns it as compact JSON, ready to send to the server
+/// followed by a newline. Returns null if the message is not valid.
+///
+/// # Safety
+///
+/// `json` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn kanata_client_message_json(json: *const c_char) -> *mut c_char {
+    let res = str_arg(json).and_then(|json| {
+        let msg: ClientMessage = json.parse()?;
+        Ok(serde_json::to_string(&msg)?)
+    });
+    match res {
+        Ok(json) => into_c_string(json),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Returns the kind of a server message, e.g. `"LayerChange"`, or null if the message is not
+/// valid. The message's fields can then be read with any JSON library.
+///
+/// # Safety
+///
+/// `json` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn kanata_server_message_kind(json: *const c_char) -> *mut c_char {
+    let res = str_arg(json).and_then(|json| {
+        let msg: ServerMessage = serde_json::from_str(json)?;
+        let kind = match serde_json::to_value(&msg)? {
+            serde_json::Value::Object(obj) => obj.keys().next().cloned(),
+            serde_json::Value::String(kind) => Some(kind),
+            _ => None,
+        };
+        kind.ok_or_else(|| anyhow!("server message has no kind"))
+    });
+    match res {
+        Ok(kind) => into_c_string(kind),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CFG_PARSE_LOCK: Mutex<()> = Mutex::new(());
+
+    fn lock() -> std::sync::MutexGuard<'static, ()> {
+        CFG_PARSE_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    unsafe fn take_string(s: *mut c_char) -> String {
+        assert!(!s.is_null());
+        let owned = CStr::from_ptr(s).to_str().unwrap().to_owned();
+        kanata_string_free(s);
+        owned
+    }
+
+    unsafe fn poll_all(engine: *mut KanataEngine) -> Vec<(u32, u32, i32)> {
+        let mut out = KanataOutput::new(0, 0, 0);
+        let mut outputs = vec![];
+        while kanata_engine_poll_output(engine, &mut out) == 1 {
+            outputs.push((out.kind, out.code, out.value));
+        }
+        outputs
+    }
+
+    #[test]
+    fn engine_remaps_pushed_keys() {
+        let _lk = lock();
+        unsafe {
+            let cfg = c("(defsrc a) (deflayer base b)");
+            let engine = kanata_engine_new(cfg.as_ptr());
+            assert!(!engine.is_null());
+            let a = kanata_key_code(c("a").as_ptr());
+            let b = kanata_key_code(c("b").as_ptr()) as u32;
+            assert_eq!(kanata_engine_push_key(engine, a as u16, 1), 0);
+            assert!(kanata_engine_tick(engine, 1) >= 0);
+            assert_eq!(kanata_engine_push_key(engine, a as u16, 0), 0);
+            assert!(kanata_engine_tick(engine, 1) >= 0);
+            assert_eq!(
+                poll_all(engine),
+                vec![(KANATA_OUTPUT_KEY, b, 1), (KANATA_OUTPUT_KEY, b, 0)]
+            );
+            assert_eq!(kanata_engine_push_key(engine, a as u16, 5), -1);
+            kanata_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn engine_reports_config_errors() {
+        let _lk = lock();
+        unsafe {
+            let cfg = c("(defsrc a) (deflayer base b c)");
+            assert!(kanata_engine_new(cfg.as_ptr()).is_null());
+            assert!(!kanata_last_error().is_null());
+            assert_eq!(kanata_key_code(c("notakey").as_ptr()), -1);
+        }
+    }
+
+    #[test]
+    fn protocol_helpers() {
+        unsafe {
+            let json =
+                kanata_client_message_json(c(r#" {"ChangeLayer": {"new": "nav"}} "#).as_ptr());
+            assert_eq!(take_string(json), r#"{"ChangeLayer":{"new":"nav"}}"#);
+            assert!(kanata_client_message_json(c(r#"{"NotAMessage":{}}"#).as_ptr()).is_null());
+
+            let kind = kanata_server_message_kind(c(r#"{"LayerChange":{"new":"nav"}}"#).as_ptr());
+            assert_eq!(take_string(kind), "LayerChange");
+            assert!(kanata_server_message_kind(c("{}").as_ptr()).is_null());
+        }
+    }
+}
@@ -192,6 +192,29 @@ where
     pub require_prior_idle: Option<u16>,
 }
 
+/// Parameters that a [`HoldTapAction`] uses instead of its own while they are set with
+/// [`Layout::set_hold_tap_tuning`](crate::layout::Layout::set_hold_tap_tuning), e.g. to tune
+/// them without changing the layers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HoldTapTuning<'a> {
+    /// Replaces [`HoldTapAction::timeout`].
+    pub timeout: u16,
+    /// Replaces [`HoldTapAction::config`].
+    pub config: HoldTapConfig<'a>,
+    /// Replaces [`HoldTapAction::tap_hold_interval`].
+    pub tap_hold_interval: u16,
+}
+
+impl<'a, T> From<&HoldTapAction<'a, T>> for HoldTapTuning<'a> {
+    fn from(action: &HoldTapAction<'a, T>) -> Self {
+        Self {
+            timeout: action.timeout,
+            config: action.config,
+            tap_hold_interval: action.tap_hold_interval,
+        }
+    }
+}
+
 /// Define one shot key behaviour.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct OneShot<'a, T = core::convert::Infallible>
@@ -312,12 +335,13 @@ pub const MAX_CHORD_KEYS: usize = ChordKeys::BITS as usize;
 
 /// An action that can do one of two actions. The `left` action is the default. The `right` action
 /// will trigger if any of the key codes in `right_triggers` are active in the current layout
-/// state.
+/// state, or if any of the coordinates in `right_inputs` are held.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ForkConfig<'a, T> {
     pub left: Action<'a, T>,
     pub right: Action<'a, T>,
     pub right_triggers: &'a [KeyCode],
+    pub right_inputs: &'a [KCoord],
 }
 
 /// The different actions that can be done.
@@ -406,9 +430,6 @@ where
     Fork(&'a ForkConfig<'a, T>),
     /// Action that can activate 0 to N actions based on what keys are currently
     /// active and the boolean logic of each case.
-    ///
-    /// The maximum number of actions that can activate the same time is governed by
-    /// `ACTION_QUEUE_LEN`.
     Switch(&'a Switch<'a, T>),
     /// Disregard the entire layer stack, i.e. the current base layer and any while-held layers,
     /// and select the action from `Layout.src_keys`.
@@ -190,7 +190,9 @@ impl<'a, T> ChordsV2<'a, T> {
         &self.chords
     }
 
-    pub(crate) fn get_action_chv2(&mut self) -> QueuedAction<'a, T> {
+    /// Returns the action of an activated chord that the layout has not read yet, and the keys
+    /// of the chord.
+    pub(crate) fn get_action_chv2(&mut self) -> (QueuedAction<'a, T>, &'a [u16]) {
         self.active_chords
             .iter_mut()
             .find_map(|ach| match ach.status {
@@ -199,25 +201,31 @@ impl<'a, T> ChordsV2<'a, T> {
                     // Note on LayerStack being default (empty):
                     // A chordv2 is not allowed to use transparency,
                     // so it does not need to handle this case.
-                    Some(Some((
-                        (0, ach.coordinate),
-                        ach.delay,
-                        ach.action,
-                        Default::default(),
-                    )))
+                    Some((
+                        Some((
+                            (0, ach.coordinate),
+                            ach.delay,
+                            ach.action,
+                            Default::default(),
+                        )),
+                        ach.participating_keys,
+                    ))
                 }
                 UnreadReleased => {
                     ach.status = Released;
-                    Some(Some((
-                        (0, ach.coordinate),
-                        ach.delay,
-                        ach.action,
-                        Default::default(),
-                    )))
+                    Some((
+                        Some((
+                            (0, ach.coordinate),
+                            ach.delay,
+                            ach.action,
+                            Default::default(),
+                        )),
+                        ach.participating_keys,
+                    ))
                 }
                 Releasable | Released => None,
             })
-            .unwrap_or_default()
+            .unwrap_or((None, &[]))
     }
 
     /// Update the times in the queue without activating any chords yet.
@@ -0,0 +1,82 @@
+//! Records how the layout resolved keys with delayed decisions, e.g. whether a tap-hold key
+//! became a tap or a hold, for external consumers such as a trace of the events.
+//!
+//! Recording is off by default, and the layout then does not allocate for it.
+
+use crate::layout::{KCoord, WaitingAction, WaitingConfig};
+
+/// The kind of action that waited for its decision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitingKind {
+    HoldTap,
+    TapDance,
+    Chord,
+}
+
+/// A decision of the layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision<'a> {
+    /// A waiting action was resolved by its timeout or by other events.
+    Waiting {
+        coord: KCoord,
+        kind: WaitingKind,
+        action: WaitingAction,
+        /// Ticks that the action waited for.
+        ticks: u16,
+    },
+    /// A chord of `defchordsv2` was activated by the keys.
+    ChordV2 { coord: KCoord, keys: &'a [u16] },
+}
+
+#[derive(Debug, Default)]
+pub struct DecisionTrace<'a> {
+    enabled: bool,
+    decisions: std::vec::Vec<Decision<'a>>,
+}
+
+impl<'a> DecisionTrace<'a> {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.decisions = Default::default();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn record_waiting<T: std::fmt::Debug>(
+        &mut self,
+        coord: KCoord,
+        config: &WaitingConfig<'a, T>,
+        action: WaitingAction,
+        ticks: u16,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let kind = match config {
+            WaitingConfig::HoldTap(..) => WaitingKind::HoldTap,
+            WaitingConfig::TapDance(_) => WaitingKind::TapDance,
+            WaitingConfig::Chord(_) => WaitingKind::Chord,
+        };
+        self.decisions.push(Decision::Waiting {
+            coord,
+            kind,
+            action,
+            ticks,
+        });
+    }
+
+    pub(crate) fn record_chord_v2(&mut self, coord: KCoord, keys: &'a [u16]) {
+        if self.enabled {
+            self.decisions.push(Decision::ChordV2 { coord, keys });
+        }
+    }
+
+    /// Returns the decisions recorded since the previous call.
+    pub fn drain(&mut self) -> impl Iterator<Item = Decision<'a>> + '_ {
+        self.decisions.drain(..)
+    }
+}
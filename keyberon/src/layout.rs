@@ -25,6 +25,7 @@ pub use kanata_keyberon_macros::*;
 mod contextual_execution;
 use contextual_execution::*;
 
+use std::collections::VecDeque;
 use std::num::NonZeroU16;
 
 use crate::chord::*;
@@ -50,6 +51,109 @@ pub type KCoord = (u8, u16);
 pub type Layers<'a, const C: usize, const R: usize, T = core::convert::Infallible> =
     &'a [[[Action<'a, T>; C]; R]];
 
+/// The layers used by a [`Layout`].
+///
+/// Dense layers cost `C * R` actions each. With many columns and layers that mostly contain the
+/// same action, e.g. transparent keys, sparse rows use much less memory.
+#[derive(Debug)]
+pub enum LayerStore<'a, const C: usize, const R: usize, T = core::convert::Infallible>
+where
+    T: 'a,
+{
+    /// Every action of every layer.
+    Dense(Layers<'a, C, R, T>),
+    /// Each layer is made of `R` rows of `C` actions.
+    Rows(&'a [[LayerRow<'a, T>; R]]),
+}
+
+/// A row of a layer in [`LayerStore::Rows`].
+#[derive(Debug, Clone, Copy)]
+pub enum LayerRow<'a, T = core::convert::Infallible>
+where
+    T: 'a,
+{
+    /// Every action of the row.
+    Dense(&'a [Action<'a, T>]),
+    /// The actions that differ from `fill`, sorted by column.
+    Sparse {
+        fill: Action<'a, T>,
+        actions: &'a [(u16, Action<'a, T>)],
+    },
+}
+
+impl<const C: usize, const R: usize, T> Clone for LayerStore<'_, C, R, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<const C: usize, const R: usize, T> Copy for LayerStore<'_, C, R, T> {}
+
+impl<'a, const C: usize, const R: usize, T: 'a> LayerStore<'a, C, R, T> {
+    /// Returns the number of layers.
+    pub fn len(&self) -> usize {
+        match self {
+            LayerStore::Dense(layers) => layers.len(),
+            LayerStore::Rows(layers) => layers.len(),
+        }
+    }
+
+    /// Returns true if there are no layers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the action at `row` and `col` of `layer`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a coordinate is out of range.
+    pub fn get(&self, layer: usize, row: usize, col: usize) -> &'a Action<'a, T> {
+        match *self {
+            LayerStore::Dense(layers) => &layers[layer][row][col],
+            LayerStore::Rows(layers) => layers[layer][row].get(col),
+        }
+    }
+
+    /// Iterates on the actions of `row` in `layer`, in column order.
+    pub fn row(
+        &self,
+        layer: usize,
+        row: usize,
+    ) -> impl Iterator<Item = &'a Action<'a, T>> + Clone + 'a {
+        let store = *self;
+        (0..C).map(move |col| store.get(layer, row, col))
+    }
+}
+
+impl<'a, T: 'a> LayerRow<'a, T> {
+    fn get(&'a self, col: usize) -> &'a Action<'a, T> {
+        match self {
+            LayerRow::Dense(actions) => &actions[col],
+            LayerRow::Sparse { fill, actions } => {
+                match actions.binary_search_by_key(&col, |(c, _)| usize::from(*c)) {
+                    Ok(i) => &actions[i].1,
+                    Err(_) => fill,
+                }
+            }
+        }
+    }
+}
+
+impl<'a, const C: usize, const R: usize, T: 'a> From<Layers<'a, C, R, T>>
+    for LayerStore<'a, C, R, T>
+{
+    fn from(layers: Layers<'a, C, R, T>) -> Self {
+        LayerStore::Dense(layers)
+    }
+}
+
+impl<const C: usize, const R: usize, T> Default for LayerStore<'_, C, R, T> {
+    fn default() -> Self {
+        LayerStore::Dense(&[])
+    }
+}
+
 const QUEUE_SIZE: usize = 32;
 pub type QueueLen = u8;
 
@@ -68,20 +172,23 @@ pub(crate) type Queue = ArrayDeque<Queued, QUEUE_SIZE, arraydeque::behavior::Wra
 /// that occur during a Waiting event.
 type PressedQueue = ArrayDeque<KCoord, QUEUE_SIZE>;
 
-/// The maximum number of actions that can be activated concurrently via chord decomposition or
-/// activation of multiple switch cases using fallthrough.
+/// The former capacity of the action queue, which now grows as needed.
+#[deprecated(note = "the action queue grows as needed and no longer has a maximum length")]
 pub const ACTION_QUEUE_LEN: usize = 8;
 
+/// The former capacity of the custom event release queue, which now grows as needed.
+#[deprecated(
+    note = "the custom event release queue grows as needed and no longer has a maximum length"
+)]
 pub const CUSTOM_EVENT_RELEASE_QUEUE_LEN: usize = 16;
 
-/// The queue is currently only used for chord decomposition when a longer chord does not result in
-/// an action, but splitting it into smaller chords would. The buffer size of 8 should be more than
-/// enough for real world usage, but if one wanted to be extra safe, this should be ChordKeys::BITS
-/// since that should guarantee that all potentially queueable actions can fit.
-type ActionQueue<'a, T> =
-    ArrayDeque<QueuedAction<'a, T>, ACTION_QUEUE_LEN, arraydeque::behavior::Wrapping>;
-type CustomEventReleaseQueue<'a, T> =
-    ArrayDeque<&'a T, CUSTOM_EVENT_RELEASE_QUEUE_LEN, arraydeque::behavior::Wrapping>;
+/// Actions that are waiting to be activated, one per tick. These come from chord decomposition when
+/// a longer chord does not result in an action but splitting it into smaller chords would, from
+/// custom actions beyond the first in one activation, e.g. in a `multi` or a `switch`, and from
+/// the start action. The queue grows as needed so that no deeply composed action is dropped.
+type ActionQueue<'a, T> = VecDeque<QueuedAction<'a, T>>;
+/// Releases of custom actions beyond the first released on the same tick.
+type CustomEventReleaseQueue<'a, T> = VecDeque<&'a T>;
 type Delay = u16;
 pub(crate) type QueuedAction<'a, T> = Option<(KCoord, Delay, &'a Action<'a, T>, LayerStack)>;
 
@@ -102,7 +209,7 @@ where
 {
     /// Fallback for transparent keys inside actions that are on `default_layer`.
     pub src_keys: &'a [Action<'a, T>; C],
-    pub layers: &'a [[[Action<'a, T>; C]; R]],
+    pub layers: LayerStore<'a, C, R, T>,
     pub default_layer: usize,
     /// Key states.
     pub states: Vec<State<'a, T>, 64>,
@@ -139,11 +246,22 @@ where
     rpt_multikey_key_buffer: MultiKeyBuffer<'a, T>,
     trans_resolution_behavior_v2: bool,
     delegate_to_first_layer: bool,
+    /// Layers to resolve transparent keys through after the other layers, set at runtime.
+    /// Replaces the fallback of `delegate_to_first_layer` when not empty.
+    fallback_layers: LayerStack,
+    /// Runtime aliases of layers: actions of a key layer are taken from its value layer.
+    layer_aliases: rustc_hash::FxHashMap<u16, u16>,
+    /// The percentage that the delays of sequences are scaled by, set at runtime.
+    sequence_delay_percent: u16,
+    /// Parameters that hold-tap actions use instead of their own, set at runtime.
+    hold_tap_tunings: std::vec::Vec<(&'a HoldTapAction<'a, T>, HoldTapTuning<'a>)>,
     contextual_execution: ContextualExecution,
     /// Tracks tap-hold activation events (hold/tap resolved).
     /// Only stores data when the `tap_hold_tracker` feature is enabled;
     /// otherwise this is a zero-sized no-op.
     pub tap_hold_tracker: crate::tap_hold_tracker::TapHoldTracker,
+    /// Records the decisions of waiting actions and chords while enabled.
+    pub decision_trace: crate::decision_trace::DecisionTrace<'a>,
 }
 
 pub use crate::tap_hold_tracker::{HoldActivatedInfo, TapActivatedInfo};
@@ -382,9 +500,7 @@ impl<'a, T: 'a> State<'a, T> {
                 match custom {
                     CustomEvent::NoEvent => custom.update(CustomEvent::Release(value)),
                     _ => {
-                        if custom_release_queue.push_back(value).is_some() {
-                            panic!("overflowed custom action release queue");
-                        }
+                        custom_release_queue.push_back(value);
                     }
                 }
                 None
@@ -905,7 +1021,7 @@ impl<'a, T: std::fmt::Debug> WaitingState<'a, T> {
                 // and could result in an infinite loop, because
                 // the queue activating code falls back to top-level resolution order
                 // if the stored layer stack is empty.
-                let _ = action_queue.push_back(Some((coord, delay, action, Default::default())));
+                action_queue.push_back(Some((coord, delay, action, Default::default())));
             } else {
                 end -= 1;
                 // shrink from end until something is found, or have checked up to and including
@@ -919,12 +1035,7 @@ impl<'a, T: std::fmt::Debug> WaitingState<'a, T> {
                         .unwrap_or(0);
                     if let Some(action) = config.get_chord(chord_mask) {
                         let coord = get_coord_for_chord(chord_mask);
-                        let _ = action_queue.push_back(Some((
-                            coord,
-                            delay,
-                            action,
-                            Default::default(),
-                        )));
+                        action_queue.push_back(Some((coord, delay, action, Default::default())));
                         break;
                     }
                     end -= 1;
@@ -1198,7 +1309,8 @@ impl LastPressTracker {
 
 impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<'a, C, R, T> {
     /// Creates a new `Layout` object.
-    fn new(layers: &'a [[[Action<T>; C]; R]]) -> Self {
+    fn new(layers: impl Into<LayerStore<'a, C, R, T>>) -> Self {
+        let layers = layers.into();
         assert!(layers.len() < MAX_LAYERS);
         Self {
             src_keys: &[Action::NoOp; C],
@@ -1224,8 +1336,8 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
             keys_to_suppress_for_one_cycle: Vec::new(),
             last_press_tracker: Default::default(),
             active_sequences: ArrayDeque::new(),
-            action_queue: ArrayDeque::new(),
-            custom_event_release_queue: ArrayDeque::new(),
+            action_queue: VecDeque::new(),
+            custom_event_release_queue: VecDeque::new(),
             rpt_action: None,
             historical_keys: History::new(),
             historical_inputs: History::new(),
@@ -1235,15 +1347,20 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
             tap_hold_require_prior_idle: 0,
             trans_resolution_behavior_v2: true,
             delegate_to_first_layer: false,
+            fallback_layers: Vec::new(),
+            layer_aliases: Default::default(),
+            sequence_delay_percent: 100,
+            hold_tap_tunings: Default::default(),
             chords_v2: None,
             device_history: ArrayDeque::new(),
             contextual_execution: ContextualExecution::new(),
             tap_hold_tracker: Default::default(),
+            decision_trace: Default::default(),
         }
     }
     pub fn new_with_trans_action_settings(
         src_keys: &'a [Action<T>; C],
-        layers: &'a [[[Action<T>; C]; R]],
+        layers: impl Into<LayerStore<'a, C, R, T>>,
         trans_resolution_behavior_v2: bool,
         delegate_to_first_layer: bool,
     ) -> Self {
@@ -1297,6 +1414,8 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
             };
             let layer_stack = w.layer_stack.clone();
             self.tap_hold_tracker.set_hold_activated(coord, &w.config);
+            self.decision_trace
+                .record_waiting(coord, &w.config, WaitingAction::Hold, w.ticks);
             if idx < 0 {
                 self.waiting = None;
             } else {
@@ -1337,6 +1456,8 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
             };
             let layer_stack = w.layer_stack.clone();
             self.tap_hold_tracker.set_tap_activated(coord, &w.config);
+            self.decision_trace
+                .record_waiting(coord, &w.config, WaitingAction::Tap, w.ticks);
             if idx < 0 {
                 self.waiting = None;
             } else {
@@ -1434,6 +1555,8 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
             };
             let layer_stack = w.layer_stack.clone();
             self.tap_hold_tracker.set_hold_activated(coord, &w.config);
+            self.decision_trace
+                .record_waiting(coord, &w.config, WaitingAction::Timeout, w.ticks);
             if idx < 0 {
                 self.waiting = None;
             } else {
@@ -1479,7 +1602,8 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
         let active_layer = self.current_layer() as u16;
         if let Some(chv2) = self.chords_v2.as_mut() {
             self.queue.extend(chv2.tick_chv2(active_layer).drain(0..));
-            if let chord_action @ Some(_) = chv2.get_action_chv2() {
+            if let (chord_action @ Some((coord, ..)), keys) = chv2.get_action_chv2() {
+                self.decision_trace.record_chord_v2(coord, keys);
                 self.action_queue.push_back(chord_action);
                 self.oneshot.pause_input_processing_ticks =
                     self.oneshot.pause_input_processing_delay;
@@ -1609,7 +1733,7 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
                         Some(SequenceEvent::Delay { duration }) if duration > 0 => {
                             // Setup a delay that will be decremented once per tick until 0
                             // -1 to start since this tick counts
-                            seq.delay = duration - 1;
+                            seq.delay = self.scale_sequence_delay(duration) - 1;
                         }
                         Some(SequenceEvent::Custom(custom)) => {
                             let _ = self.states.push(State::SeqCustomPending(custom));
@@ -1856,11 +1980,15 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
         use crate::action::Action::*;
         let x = coord.0 as usize;
         let y = coord.1 as usize;
-        assert!(x <= self.layers[0].len());
-        assert!(y <= self.layers[0][0].len());
+        assert!(x <= R);
+        assert!(y <= C);
         for layer in layer_stack {
+            let layer = match self.layer_aliases.is_empty() {
+                true => layer,
+                false => self.layer_aliases.get(&layer).copied().unwrap_or(layer),
+            };
             assert!(usize::from(layer) <= self.layers.len());
-            let action = &self.layers[usize::from(layer)][x][y];
+            let action = self.layers.get(usize::from(layer), x, y);
             match action {
                 Trans => continue,
                 action => return action,
@@ -1977,16 +2105,22 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
                     );
                 }
             }
-            HoldTap(HoldTapAction {
-                timeout,
-                hold,
-                tap,
-                timeout_action,
-                config,
-                tap_hold_interval,
-                on_press_reset_timeout_to,
-                require_prior_idle,
-            }) => {
+            HoldTap(hold_tap) => {
+                let HoldTapAction {
+                    hold,
+                    tap,
+                    timeout_action,
+                    on_press_reset_timeout_to,
+                    require_prior_idle,
+                    ..
+                } = hold_tap;
+                let HoldTapTuning {
+                    timeout,
+                    config,
+                    tap_hold_interval,
+                } = self
+                    .hold_tap_tuning(hold_tap)
+                    .unwrap_or_else(|| HoldTapTuning::from(*hold_tap));
                 // Typing streak detection: if a different physical key was pressed
                 // recently, resolve as tap immediately without entering WaitingState.
                 // Per-action override takes precedence over the global defcfg value.
@@ -2024,7 +2158,7 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
                     }
                 }
                 let mut custom = CustomEvent::NoEvent;
-                if *tap_hold_interval == 0
+                if tap_hold_interval == 0
                     || coord != self.last_press_tracker.coord
                     || self.last_press_tracker.tap_hold_timeout == 0
                 {
@@ -2042,7 +2176,7 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
                         hold,
                         tap,
                         timeout_action,
-                        config: WaitingConfig::HoldTap(*config),
+                        config: WaitingConfig::HoldTap(config),
                         layer_stack: layer_stack.collect(),
                         prev_queue_len: QueueLen::MAX,
                     };
@@ -2051,7 +2185,7 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
                     } else {
                         self.waiting = Some(waiting);
                     }
-                    self.last_press_tracker.tap_hold_timeout = *tap_hold_interval;
+                    self.last_press_tracker.tap_hold_timeout = tap_hold_interval;
                 } else {
                     self.last_press_tracker.tap_hold_timeout = 0;
                     custom.update(self.do_action(
@@ -2395,11 +2529,14 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
                 self.rpt_action = Some(action);
             }
             Fork(fcfg) => {
-                let ret = match self.states.iter().any(|s| match s {
-                    NormalKey { keycode, .. } | FakeKey { keycode } => {
-                        fcfg.right_triggers.contains(keycode)
-                    }
-                    _ => false,
+                let ret = match self.states.iter().any(|s| {
+                    let key_triggers = match s {
+                        NormalKey { keycode, .. } | FakeKey { keycode } => {
+                            fcfg.right_triggers.contains(keycode)
+                        }
+                        _ => false,
+                    };
+                    key_triggers || s.coord().is_some_and(|c| fcfg.right_inputs.contains(&c))
                 }) {
                     false => self.do_action(
                         &fcfg.left,
@@ -2472,6 +2609,12 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
             .unwrap_or(self.default_layer)
     }
 
+    /// Returns the action that a press of the key would do on the active layers, with
+    /// transparent actions resolved.
+    pub fn active_action(&self, coord: KCoord) -> &'a Action<'a, T> {
+        self.resolve_coord(coord, &mut self.trans_resolution_layer_order().into_iter())
+    }
+
     pub fn active_held_layers(&self) -> impl Iterator<Item = u16> + Clone + '_ {
         self.states
             .iter()
@@ -2485,20 +2628,120 @@ impl<'a, const C: usize, const R: usize, T: 'a + Copy + std::fmt::Debug> Layout<
         if self.trans_resolution_behavior_v2 {
             let mut v = self.active_held_layers().collect::<LayerStack>();
             let _ = v.push(self.default_layer as u16);
-            if self.delegate_to_first_layer && current_layer != 0 && self.default_layer != 0 {
+            if !self.fallback_layers.is_empty() {
+                v.extend(
+                    self.fallback_layers
+                        .iter()
+                        .copied()
+                        .take(v.capacity() - v.len()),
+                );
+            } else if self.delegate_to_first_layer && current_layer != 0 && self.default_layer != 0
+            {
                 let _ = v.push(0);
             }
             v
         } else {
             let mut v = Vec::new();
             let _ = v.push(current_layer as u16);
-            if self.delegate_to_first_layer && current_layer != 0 {
+            if !self.fallback_layers.is_empty() {
+                v.extend(
+                    self.fallback_layers
+                        .iter()
+                        .copied()
+                        .take(v.capacity() - v.len()),
+                );
+            } else if self.delegate_to_first_layer && current_layer != 0 {
                 let _ = v.push(0);
             }
             v
         }
     }
 
+    /// Sets the layers that transparent keys fall back to, in priority order, after the active
+    /// layers. An empty slice restores the fallback behaviour the layout was created with.
+    ///
+    /// Returns false without changing anything if a layer is out of range or there are too many
+    /// layers.
+    pub fn set_fallback_layers(&mut self, layers: &[u16]) -> bool {
+        if layers.iter().any(|l| usize::from(*l) >= self.layers.len()) {
+            return false;
+        }
+        match Vec::from_slice(layers) {
+            Ok(layers) => {
+                self.fallback_layers = layers;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the layers set by [`Layout::set_fallback_layers`].
+    pub fn fallback_layers(&self) -> &[u16] {
+        &self.fallback_layers
+    }
+
+    /// Scales the delays of sequences by the percentage, from the next delay that starts, e.g. 200
+    /// makes them twice as long. 100 restores the delays they were created with.
+    pub fn set_sequence_delay_percent(&mut self, percent: u16) {
+        self.sequence_delay_percent = percent.max(1);
+    }
+
+    /// Returns the percentage set by [`Layout::set_sequence_delay_percent`].
+    pub fn sequence_delay_percent(&self) -> u16 {
+        self.sequence_delay_percent
+    }
+
+    /// Returns the delay scaled by the percentage of [`Layout::set_sequence_delay_percent`],
+    /// which is at least 1 tick.
+    pub fn scale_sequence_delay(&self, duration: u32) -> u32 {
+        let scaled = u64::from(duration) * u64::from(self.sequence_delay_percent) / 100;
+        u32::try_from(scaled).unwrap_or(u32::MAX).max(1)
+    }
+
+    /// Makes the hold-tap action use the parameters of `tuning` instead of its own, from its
+    /// next press. Setting the parameters of the action itself removes the tuning.
+    pub fn set_hold_tap_tuning(
+        &mut self,
+        action: &'a HoldTapAction<'a, T>,
+        tuning: HoldTapTuning<'a>,
+    ) {
+        self.hold_tap_tunings
+            .retain(|(tuned, _)| !core::ptr::eq(*tuned, action));
+        if tuning != HoldTapTuning::from(action) {
+            self.hold_tap_tunings.push((action, tuning));
+        }
+    }
+
+    /// Returns the parameters set with [`Layout::set_hold_tap_tuning`] that the hold-tap action
+    /// uses instead of its own, if any.
+    pub fn hold_tap_tuning(&self, action: &HoldTapAction<'a, T>) -> Option<HoldTapTuning<'a>> {
+        self.hold_tap_tunings
+            .iter()
+            .find(|(tuned, _)| core::ptr::eq(*tuned, action))
+            .map(|(_, tuning)| *tuning)
+    }
+
+    /// Makes keys on `layer` use the actions of `target` instead. Aliasing a layer to itself
+    /// removes its alias.
+    ///
+    /// Returns false without changing anything if a layer is out of range.
+    pub fn set_layer_alias(&mut self, layer: u16, target: u16) -> bool {
+        if usize::from(layer) >= self.layers.len() || usize::from(target) >= self.layers.len() {
+            return false;
+        }
+        if layer == target {
+            self.layer_aliases.remove(&layer);
+        } else {
+            self.layer_aliases.insert(layer, target);
+        }
+        true
+    }
+
+    /// Returns the layer whose actions are used for `layer`.
+    pub fn layer_alias(&self, layer: u16) -> u16 {
+        self.layer_aliases.get(&layer).copied().unwrap_or(layer)
+    }
+
     /// Sets the default layer for the layout
     pub fn set_default_layer(&mut self, value: usize) {
         if value < self.layers.len() {
@@ -2581,6 +2824,34 @@ mod test {
         assert_keys(&[], layout.keycodes());
     }
 
+    #[test]
+    fn sparse_layer_rows() {
+        static ROWS: &[[LayerRow; 1]] = &[
+            [LayerRow::Dense(&[k(A), l(1), k(C)])],
+            [LayerRow::Sparse {
+                fill: Trans,
+                actions: &[(0, k(X))],
+            }],
+        ];
+        let layers = LayerStore::<3, 1>::Rows(ROWS);
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers.get(1, 0, 0), &k(X));
+        assert_eq!(layers.get(1, 0, 2), &Trans);
+        assert_eq!(
+            layers.row(1, 0).collect::<std::vec::Vec<_>>(),
+            [&k(X), &Trans, &Trans]
+        );
+
+        let mut layout = Layout::new(layers);
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[X, C], layout.keycodes());
+    }
+
     #[test]
     fn basic_hold_tap_repress_timeout() {
         static LAYERS: Layers<2, 1> = &[
@@ -4613,6 +4884,7 @@ mod test {
                 left: k(Kb1),
                 right: k(Kb2),
                 right_triggers: &[Space],
+                right_inputs: &[],
             }),
             k(Space),
         ]]];
@@ -4791,6 +5063,115 @@ mod test {
         assert_keys(&[], layout.keycodes());
     }
 
+    #[test]
+    fn test_runtime_fallback_layers() {
+        static DEFSRC_LAYER: [Action; 2] = [NoOp, k(X)];
+        static LAYERS: Layers<2, 1> = &[
+            [[Layer(3), k(A)]],
+            [[NoOp, k(B)]],
+            [[NoOp, Trans]],
+            [[NoOp, Trans]],
+        ];
+        let mut layout = Layout::new_with_trans_action_settings(&DEFSRC_LAYER, LAYERS, true, true);
+        layout.set_default_layer(2);
+        assert!(layout.set_fallback_layers(&[1, 0]));
+        assert!(!layout.set_fallback_layers(&[4]));
+        assert_eq!(layout.fallback_layers(), &[1, 0]);
+
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // Restores delegate-to-first-layer.
+        assert!(layout.set_fallback_layers(&[]));
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+    }
+
+    #[test]
+    fn test_runtime_layer_alias() {
+        static LAYERS: Layers<2, 1> = &[[[Layer(1), k(A)]], [[NoOp, k(B)]], [[NoOp, k(C)]]];
+        let mut layout = Layout::new(LAYERS);
+        assert!(layout.set_layer_alias(1, 2));
+        assert!(!layout.set_layer_alias(1, 3));
+        assert_eq!(layout.layer_alias(1), 2);
+
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[C], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(layout.current_layer(), 1);
+
+        assert!(layout.set_layer_alias(1, 1));
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+    }
+
+    #[test]
+    fn test_hold_tap_tuning() {
+        static HOLD_TAP: HoldTapAction<core::convert::Infallible> = HoldTapAction {
+            on_press_reset_timeout_to: None,
+            require_prior_idle: None,
+            timeout: 200,
+            hold: k(LCtrl),
+            timeout_action: k(LCtrl),
+            tap: k(Space),
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+        };
+        static LAYERS: Layers<2, 1> = &[[[HoldTap(&HOLD_TAP), k(A)]]];
+        let mut layout = Layout::new(LAYERS);
+        let tuning = HoldTapTuning {
+            timeout: 10,
+            config: HoldTapConfig::HoldOnOtherKeyPress,
+            tap_hold_interval: 0,
+        };
+        layout.set_hold_tap_tuning(&HOLD_TAP, tuning);
+        assert_eq!(layout.hold_tap_tuning(&HOLD_TAP), Some(tuning));
+
+        // The tuned timeout.
+        layout.event(Press(0, 0));
+        for _ in 0..10 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // The tuned flavor.
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // Setting the parameters of the action removes the tuning.
+        layout.set_hold_tap_tuning(&HOLD_TAP, HoldTapTuning::from(&HOLD_TAP));
+        assert_eq!(layout.hold_tap_tuning(&HOLD_TAP), None);
+        layout.event(Press(0, 0));
+        for _ in 0..20 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+    }
+
     #[test]
     fn test_trans_in_action_on_first_layer() {
         static DEFSRC_LAYER: [Action; 2] = [NoOp, k(X)];
@@ -5043,6 +5424,7 @@ mod test {
                     left: Trans,
                     right: Trans,
                     right_triggers: &[Space],
+                    right_inputs: &[],
                 }),
             ]],
         ];
@@ -5238,6 +5620,65 @@ mod test {
         }
     }
 
+    #[test]
+    fn decision_trace_records_waiting_decisions() {
+        use crate::decision_trace::{Decision, WaitingKind};
+        static LAYERS: Layers<1, 1> = &[[[HoldTap(&HoldTapAction {
+            timeout: 5,
+            hold: k(LAlt),
+            timeout_action: k(LAlt),
+            tap: k(Space),
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+            on_press_reset_timeout_to: None,
+            require_prior_idle: None,
+        })]]];
+        let mut layout = Layout::new(LAYERS);
+        layout.event(Press(0, 0));
+        for _ in 0..6 {
+            let _ = layout.tick();
+        }
+        // Nothing is recorded while disabled.
+        assert_eq!(layout.decision_trace.drain().count(), 0);
+        layout.event(Release(0, 0));
+        let _ = layout.tick();
+
+        layout.decision_trace.set_enabled(true);
+        layout.event(Press(0, 0));
+        let _ = layout.tick();
+        layout.event(Release(0, 0));
+        let _ = layout.tick();
+        let decisions: std::vec::Vec<_> = layout.decision_trace.drain().collect();
+        assert_eq!(
+            decisions,
+            [Decision::Waiting {
+                coord: (0, 0),
+                kind: WaitingKind::HoldTap,
+                action: WaitingAction::Tap,
+                ticks: 1,
+            }]
+        );
+        assert_eq!(layout.decision_trace.drain().count(), 0);
+
+        for _ in 0..10 {
+            let _ = layout.tick();
+        }
+        layout.event(Press(0, 0));
+        for _ in 0..6 {
+            let _ = layout.tick();
+        }
+        let decisions: std::vec::Vec<_> = layout.decision_trace.drain().collect();
+        assert_eq!(
+            decisions,
+            [Decision::Waiting {
+                coord: (0, 0),
+                kind: WaitingKind::HoldTap,
+                action: WaitingAction::Timeout,
+                ticks: 5,
+            }]
+        );
+    }
+
     #[cfg(feature = "tap_hold_tracker")]
     #[test]
     fn hold_activated_is_set_on_hold_timeout() {
@@ -5421,4 +5862,15 @@ mod test {
         assert!(layout.tap_hold_tracker.take_hold_activated().is_none());
         assert!(layout.tap_hold_tracker.take_tap_activated().is_none());
     }
+
+    #[test]
+    fn active_action_resolves_trans() {
+        static LAYERS: Layers<2, 1> = &[[[k(A), Layer(1)]], [[k(B), Trans]]];
+        let mut layout = Layout::new(LAYERS);
+        assert_eq!(layout.active_action((0, 0)), &k(A));
+        layout.event(Press(0, 1));
+        let _ = layout.tick();
+        assert_eq!(layout.active_action((0, 0)), &k(B));
+        assert_eq!(layout.active_action((0, 1)), &Layer(1));
+    }
 }
@@ -0,0 +1,133 @@
+// Types of the kanata Node.js bindings and of the TCP protocol messages.
+// The messages mirror the kanata-tcp-protocol crate; keep them in sync with it.
+
+export type FakeKeyAction = 'Press' | 'Release' | 'Tap' | 'Toggle'
+
+interface ReloadOptions {
+  wait?: boolean
+  timeout_ms?: number
+}
+
+export type ClientMessage =
+  | { ChangeLayer: { new: string } }
+  | { RequestLayerNames: {} }
+  | { RequestFakeKeyNames: {} }
+  | { RequestCurrentLayerInfo: {} }
+  | { RequestCurrentLayerName: {} }
+  | { ActOnFakeKey: { name: string; action: FakeKeyAction } }
+  | { SetMouse: { x: number; y: number } }
+  | { Reload: ReloadOptions }
+  | { ReloadNext: ReloadOptions }
+  | { ReloadPrev: ReloadOptions }
+  | { ReloadNum: { index: number } & ReloadOptions }
+  | { ReloadFile: { path: string } & ReloadOptions }
+  | { Hello: {} }
+  | { Authenticate: { token: string } }
+  | { RevokeToken: { token: string } }
+  | { SetLayerFallback: { names: string[] } }
+  | { SetLayerAlias: { name: string; target: string } }
+  | { RequestStats: {} }
+  | { RequestMousePosition: {} }
+
+export type ServerMessage =
+  | { LayerChange: { new: string } }
+  | { LayerNames: { names: string[] } }
+  | { FakeKeyNames: { names: string[] } }
+  | { CurrentLayerInfo: { name: string; cfg_text: string } }
+  | { ConfigFileReload: { new: string; changes?: ReloadChanges } }
+  | { CurrentLayerName: { name: string } }
+  | { MessagePush: { message: unknown } }
+  | { Error: { msg: string } }
+  | { HelloOk: { version: string; protocol: number; capabilities: string[] } }
+  | { ReloadResult: { ok: boolean; timeout_ms?: number } }
+  | { HoldActivated: { key: string } }
+  | { TapActivated: { key: string } }
+  | { EmergencyPassthrough: { active: boolean } }
+  | { ProcessingPaused: { paused: boolean } }
+  | { OsLayoutChange: { layout: string } }
+  | { Stats: { presses: number; latency?: LatencyStats } }
+  | { MousePosition: { x: number; y: number } }
+  | { NotificationsDropped: { count: number } }
+
+export interface LatencyStats {
+  count: number
+  p50_us: number
+  p99_us: number
+  max_us: number
+}
+
+export interface ReloadChanges {
+  added_layers: string[]
+  removed_layers: string[]
+  modified_layers: string[]
+  changed_options: string[]
+  changed_aliases: string[]
+}
+
+export type ServerResponse =
+  | { status: 'Ok' }
+  | { status: 'Error'; msg: string }
+  | { status: 'Forbidden'; command: string; msg: string }
+
+export type Output =
+  | { kind: 'press' | 'release' | 'repeat' | 'tap' | 'wakeup'; key: string }
+  | { kind: 'code'; code: number; value: string }
+  | { kind: 'unicode'; text: string }
+  | { kind: 'mouse_press' | 'mouse_release'; button: string }
+  | { kind: 'scroll' | 'mouse_move'; direction: string; distance: number }
+  | { kind: 'mouse_set'; x: number; y: number }
+  | { kind: 'mouse_warp'; monitor: number; x: number; y: number }
+  | { kind: 'midi'; bytes: number[] }
+  | { kind: 'sound'; file?: string }
+  | { kind: 'obs'; request: string; args: string[] }
+  | { kind: 'webhook'; name: string; body: Record<string, unknown> }
+  | { kind: 'notify'; title: string; body: string }
+  | { kind: 'mpris'; command: string; player: string | null }
+  | { kind: 'os_layout'; layout: string }
+  | { kind: 'secret'; name: string }
+  | { kind: 'hid_usage'; page: number; usage: number; value: string }
+  | { kind: 'mouse_toward'; monitor: number | null; x: number; y: number; percent: number }
+
+/** Throws if the configuration is not valid. */
+export function checkConfig(cfg: string): void
+/** Returns the names of the layers in the configuration. */
+export function layerNames(cfg: string): string[]
+/** Validates a server message and returns it as an object. Throws if it is not valid. */
+export function parseServerMessage(json: string): ServerMessage
+
+/** Runs a configuration on simulated input. */
+export class Simulator {
+  constructor(cfg: string)
+  press(key: string): void
+  release(key: string): void
+  repeat(key: string): void
+  /** Advances time by `ms` milliseconds, 1 by default. Returns true if the engine is idle. */
+  tick(ms?: number): boolean
+  /** Runs simulator input in the same format as the kanata simulator, e.g. `"d:a t:50 u:a t:50"`. */
+  run(sim: string): void
+  /** Returns the outputs since the last call. */
+  outputs(): Output[]
+  /** Returns the name of the active layer. */
+  layer(): string
+}
+
+/**
+ * A connection to the TCP server of a running kanata.
+ * The connection keeps Node.js running until `close` is called or the server disconnects.
+ */
+export class Client {
+  /** Connects to `address`, which is either a port on localhost or `host:port`. */
+  constructor(address: string, onMessage: (message: ServerMessage | ServerResponse) => void)
+  /** Sends a client message. Throws if it is not a valid message. */
+  send(message: ClientMessage): void
+  /** Requests the server version and capabilities. The reply is a `HelloOk` message. */
+  hello(): void
+  changeLayer(name: string): void
+  /** Requests the layer names. The reply is a `LayerNames` message. */
+  requestLayerNames(): void
+  /** Requests the active layer. The reply is a `CurrentLayerName` message. */
+  requestCurrentLayerName(): void
+  /** Acts on a virtual key. */
+  actOnFakeKey(name: string, action?: FakeKeyAction): void
+  close(): void
+}
@@ -0,0 +1,287 @@
+//! Node.js bindings for kanata.
+//!
+//! - `checkConfig` and `layerNames` parse configurations.
+//! - `Simulator` runs the remapping engine on input given by JavaScript, e.g. to preview a layout
+//!   in a visualizer.
+//! - `Client` talks to a running kanata over the TCP protocol.
+//!
+//! The TypeScript types of the exports and of the protocol messages are in `index.d.ts`.
+
+use kanata_parser::custom_action::FakeKeyAction;
+use kanata_state_machine::engine::Engine;
+use kanata_state_machine::kanata::handle_fakekey_action;
+use kanata_state_machine::oskbd::{KeyEvent, KeyValue, OutputEvent};
+use kanata_state_machine::{FAKE_KEY_ROW, Kanata, str_to_oscode};
+use kanata_tcp_protocol::{ClientMessage, FakeKeyActionMessage, ServerMessage};
+use napi::JsFunction;
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{
+    ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi_derive::napi;
+use serde_json::Value;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+
+fn invalid(e: impl std::fmt::Debug) -> Error {
+    Error::new(Status::InvalidArg, format!("{e:?}"))
+}
+
+fn conn_err(e: impl std::fmt::Display) -> Error {
+    Error::new(Status::GenericFailure, e.to_string())
+}
+
+/// Throws if the configuration is not valid.
+#[napi]
+pub fn check_config(cfg: String) -> Result<()> {
+    Kanata::new_from_str(&cfg, Default::default()).map_err(invalid)?;
+    Ok(())
+}
+
+/// Returns the names of the layers in the configuration.
+#[napi]
+pub fn layer_names(cfg: String) -> Result<Vec<String>> {
+    let k = Kanata::new_from_str(&cfg, Default::default()).map_err(invalid)?;
+    Ok(k.layer_info.iter().map(|l| l.name.clone()).collect())
+}
+
+fn key(name: &str) -> Result<kanata_parser::keys::OsCode> {
+    str_to_oscode(name).ok_or_else(|| invalid(format!("unknown key: {name}")))
+}
+
+/// Runs a configuration on simulated input.
+#[napi]
+pub struct Simulator {
+    engine: Engine,
+    outputs: Arc<Mutex<Vec<OutputEvent>>>,
+}
+
+impl Simulator {
+    fn input(&mut self, name: &str, value: KeyValue) -> Result<()> {
+        self.engine
+            .handle_input(KeyEvent::new(key(name)?, value))
+            .map_err(invalid)
+    }
+}
+
+#[napi]
+impl Simulator {
+    #[napi(constructor)]
+    pub fn new(cfg: String) -> Result<Self> {
+        let outputs = Arc::new(Mutex::new(vec![]));
+        let sink_outputs = outputs.clone();
+        let engine = Engine::new(&cfg, move |ev| {
+            sink_outputs
+                .lock()
+                .expect("output lock is not poisoned")
+                .push(ev)
+        })
+        .map_err(invalid)?;
+        Ok(Self { engine, outputs })
+    }
+
+    #[napi]
+    pub fn press(&mut self, key: String) -> Result<()> {
+        self.input(&key, KeyValue::Press)
+    }
+
+    #[napi]
+    pub fn release(&mut self, key: String) -> Result<()> {
+        self.input(&key, KeyValue::Release)
+    }
+
+    #[napi]
+    pub fn repeat(&mut self, key: String) -> Result<()> {
+        self.input(&key, KeyValue::Repeat)
+    }
+
+    /// Advances time by `ms` milliseconds, 1 by default. Returns true if the engine is idle.
+    #[napi]
+    pub fn tick(&mut self, ms: Option<u32>) -> Result<bool> {
+        let mut remaining = ms.unwrap_or(1);
+        let mut idle = self.engine.tick(0).map_err(invalid)?;
+        while remaining > 0 {
+            let step = remaining.min(u32::from(u16::MAX));
+            idle = self.engine.tick(step as u16).map_err(invalid)?;
+            remaining -= step;
+        }
+        Ok(idle)
+    }
+
+    /// Runs simulator input in the same format as the kanata simulator,
+    /// e.g. `"d:a t:50 u:a t:50"`.
+    #[napi]
+    pub fn run(&mut self, sim: String) -> Result<()> {
+        for item in sim.split_whitespace() {
+            let (kind, val) = item
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("invalid item: {item}")))?;
+            match kind {
+                "tick" | "🕐" | "t" => {
+                    let ms = val
+                        .parse::<u32>()
+                        .map_err(|e| invalid(format!("invalid tick {val}: {e}")))?;
+                    self.tick(Some(ms))?;
+                }
+                "press" | "↓" | "d" | "down" => self.press(val.to_owned())?,
+                "release" | "↑" | "u" | "up" => self.release(val.to_owned())?,
+                "repeat" | "⟳" | "r" => self.repeat(val.to_owned())?,
+                "vk" | "fakekey" | "virtualkey" | "🎭" => {
+                    let (name, action) = match val.split_once(':') {
+                        Some((name, action)) => (name, action),
+                        None => (val, "press"),
+                    };
+                    let action = match action {
+                        "press" | "p" => FakeKeyAction::Press,
+                        "release" => FakeKeyAction::Release,
+                        "tap" | "t" => FakeKeyAction::Tap,
+                        "toggle" | "g" => FakeKeyAction::Toggle,
+                        _ => return Err(invalid(format!("unknown virtual key action: {action}"))),
+                    };
+                    let k = self.engine.kanata();
+                    let index = *k
+                        .virtual_keys
+                        .get(name)
+                        .ok_or_else(|| invalid(format!("unknown virtual key: {name}")))?;
+                    handle_fakekey_action(action, k.layout.bm(), FAKE_KEY_ROW, index as u16);
+                }
+                "ls" | "layer-switch" | "🔀" => {
+                    let k = self.engine.kanata();
+                    let layer = k
+                        .layer_info
+                        .iter()
+                        .position(|l| l.name == val)
+                        .ok_or_else(|| invalid(format!("unknown layer: {val}")))?;
+                    k.layout.bm().set_default_layer(layer);
+                }
+                _ => return Err(invalid(format!("invalid action: {kind}"))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the outputs since the last call.
+    #[napi]
+    pub fn outputs(&mut self) -> Vec<Value> {
+        let outputs = std::mem::take(&mut *self.outputs.lock().expect("not poisoned"));
+        outputs.iter().map(OutputEvent::to_json).collect()
+    }
+
+    /// Returns the name of the active layer.
+    #[napi]
+    pub fn layer(&mut self) -> String {
+        let k = self.engine.kanata();
+        let idx = k.layout.b().current_layer();
+        k.layer_info[idx].name.clone()
+    }
+}
+
+/// A connection to the TCP server of a running kanata.
+///
+/// Every message from the server is passed to the callback given to the constructor. The
+/// connection keeps Node.js running until `close` is called or the server disconnects.
+#[napi]
+pub struct Client {
+    writer: TcpStream,
+}
+
+#[napi]
+impl Client {
+    /// Connects to `address`, which is either a port on localhost or `host:port`.
+    #[napi(constructor)]
+    pub fn new(address: String, on_message: JsFunction) -> Result<Self> {
+        let address = match address.parse::<u16>() {
+            Ok(port) => format!("127.0.0.1:{port}"),
+            Err(_) => address,
+        };
+        let writer = TcpStream::connect(&address).map_err(conn_err)?;
+        let reader = BufReader::new(writer.try_clone().map_err(conn_err)?);
+        let on_message: ThreadsafeFunction<Value, ErrorStrategy::Fatal> = on_message
+            .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<Value>| {
+                Ok(vec![ctx.env.to_js_value(&ctx.value)?])
+            })?;
+        std::thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                // Notifications and responses have different formats, so pass on any JSON.
+                if let Ok(message) = serde_json::from_str::<Value>(&line) {
+                    on_message.call(message, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+        });
+        Ok(Self { writer })
+    }
+
+    /// Sends a client message. Throws if it is not a valid message.
+    #[napi]
+    pub fn send(&mut self, message: Value) -> Result<()> {
+        let message: ClientMessage = serde_json::from_value(message).map_err(invalid)?;
+        self.send_message(&message)
+    }
+
+    /// Requests the server version and capabilities. The reply is a `HelloOk` message.
+    #[napi]
+    pub fn hello(&mut self) -> Result<()> {
+        self.send_message(&ClientMessage::Hello {
+            framing: None,
+            client_name: None,
+        })
+    }
+
+    #[napi]
+    pub fn change_layer(&mut self, name: String) -> Result<()> {
+        self.send_message(&ClientMessage::ChangeLayer { new: name })
+    }
+
+    /// Requests the layer names. The reply is a `LayerNames` message.
+    #[napi]
+    pub fn request_layer_names(&mut self) -> Result<()> {
+        self.send_message(&ClientMessage::RequestLayerNames {})
+    }
+
+    /// Requests the active layer. The reply is a `CurrentLayerName` message.
+    #[napi]
+    pub fn request_current_layer_name(&mut self) -> Result<()> {
+        self.send_message(&ClientMessage::RequestCurrentLayerName {})
+    }
+
+    /// Acts on a virtual key. `action` is one of "Press", "Release", "Tap" (the default) or
+    /// "Toggle".
+    #[napi]
+    pub fn act_on_fake_key(&mut self, name: String, action: Option<String>) -> Result<()> {
+        let action = match action.as_deref().unwrap_or("Tap") {
+            "Press" => FakeKeyActionMessage::Press,
+            "Release" => FakeKeyActionMessage::Release,
+            "Tap" => FakeKeyActionMessage::Tap,
+            "Toggle" => FakeKeyActionMessage::Toggle,
+            action => return Err(invalid(format!("unknown virtual key action: {action}"))),
+        };
+        self.send_message(&ClientMessage::ActOnFakeKey { name, action })
+    }
+
+    /// Closes the connection.
+    #[napi]
+    pub fn close(&mut self) -> Result<()> {
+        self.writer.shutdown(Shutdown::Both).map_err(conn_err)
+    }
+}
+
+impl Client {
+    fn send_message(&mut self, message: &ClientMessage) -> Result<()> {
+        let mut msg = serde_json::to_vec(message).map_err(invalid)?;
+        msg.push(b'\n');
+        self.writer.write_all(&msg).map_err(conn_err)
+    }
+}
+
+/// Validates a server message, e.g. one received by a WebSocket client, and returns it as an
+/// object. Throws if it is not a valid message.
+#[napi]
+pub fn parse_server_message(json: String) -> Result<Value> {
+    let message: ServerMessage = serde_json::from_str(&json).map_err(invalid)?;
+    serde_json::to_value(message).map_err(invalid)
+}
@@ -0,0 +1,69 @@
+//! Short labels of what actions do, e.g. `lctrl+w` or `to nav`, for diagrams of layers and for
+//! clients of the TCP server that show what keys do.
+
+use super::*;
+
+fn key_name(key: KeyCode) -> String {
+    let osc = OsCode::from(key);
+    oscode_to_str(osc).map_or_else(|| osc.to_string(), str::to_owned)
+}
+
+/// Returns a short label of what the action does, e.g. `lshift+a` or `to nav`.
+pub fn action_label(action: &Action<&CustomAction>, layer_names: &[&str]) -> String {
+    let layer_name = |idx: usize| layer_names.get(idx).copied().unwrap_or("?").to_owned();
+    match action {
+        Action::NoOp => "XX".to_owned(),
+        Action::Trans => "_".to_owned(),
+        Action::KeyCode(key) => key_name(*key),
+        Action::MultipleKeyCodes(keys) => keys
+            .iter()
+            .map(|key| key_name(*key))
+            .collect::<Vec<_>>()
+            .join("+"),
+        Action::MultipleActions(actions) => actions
+            .iter()
+            .map(|action| action_label(action, layer_names))
+            .collect::<Vec<_>>()
+            .join("+"),
+        Action::Layer(idx) => layer_name(*idx),
+        Action::DefaultLayer(idx) => format!("to {}", layer_name(*idx)),
+        Action::Sequence { .. } | Action::RepeatableSequence { .. } => "macro".to_owned(),
+        Action::CancelSequences => "cancel".to_owned(),
+        Action::ReleaseState(_) => "release".to_owned(),
+        Action::HoldTap(hold_tap) => action_label(&hold_tap.tap, layer_names),
+        Action::Custom(custom) => custom_label(custom),
+        Action::OneShot(one_shot) => format!("os {}", action_label(one_shot.action, layer_names)),
+        Action::OneShotIgnoreEventsTicks(_) => "os-ignore".to_owned(),
+        Action::TapDance(tap_dance) => match tap_dance.actions.first() {
+            Some(action) => format!("td {}", action_label(action, layer_names)),
+            None => "td".to_owned(),
+        },
+        Action::Chords(_) => "chord".to_owned(),
+        Action::Repeat => "repeat".to_owned(),
+        Action::Fork(fork) => action_label(&fork.left, layer_names),
+        Action::Switch(_) => "switch".to_owned(),
+        Action::Src => "src".to_owned(),
+    }
+}
+
+/// Returns the text that a custom action types, or else the name of the action in kebab case,
+/// e.g. `cmd` or `mouse-tap`.
+fn custom_label(custom: &CustomAction) -> String {
+    match custom {
+        CustomAction::Unicode(c) => c.to_string(),
+        CustomAction::UnicodeStr(s) => s.to_string(),
+        custom => {
+            let mut label = String::new();
+            for c in format!("{custom:?}")
+                .chars()
+                .take_while(char::is_ascii_alphanumeric)
+            {
+                if c.is_ascii_uppercase() && !label.is_empty() {
+                    label.push('-');
+                }
+                label.push(c.to_ascii_lowercase());
+            }
+            label
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Parsing of `var-set` and `alias-concat`. An `alias-concat` action is replaced on press by the
+//! alias that its parts name, where the `$variable` parts take their current values, which start
+//! as the values of `defvar` and are changed by `var-set`.
+//!
+//! The aliases that an `alias-concat` can name are put on virtual keys at the end of the virtual
+//! key row, after all layers are parsed, so that they can be pressed and released at runtime.
+
+use super::*;
+
+use crate::{anyhow_expr, bail, bail_expr};
+
+pub(crate) fn parse_var_set(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "var-set expects 2 params: <variable-name> <value>";
+    if ac_params.len() != 2 {
+        bail!("{ERR_MSG}\nfound {} items", ac_params.len());
+    }
+    // The name is not substituted, since that would use the value of the variable.
+    let name = ac_params[0]
+        .atom(None)
+        .map(|name| name.strip_prefix('$').unwrap_or(name))
+        .ok_or_else(|| anyhow_expr!(&ac_params[0], "{ERR_MSG}\nThe name must not be a list"))?;
+    if runtime_var_default(name, s).is_none() {
+        bail_expr!(
+            &ac_params[0],
+            "{ERR_MSG}\nThe variable must be declared in defvar with a value that is not a list"
+        );
+    }
+    let value = ac_params[1]
+        .atom(s.vars())
+        .ok_or_else(|| anyhow_expr!(&ac_params[1], "{ERR_MSG}\nThe value must not be a list"))?;
+    custom(
+        CustomAction::VarSet {
+            name: s.a.sref_str(name.to_owned()),
+            value: s.a.sref_str(value.trim_atom_quotes().to_owned()),
+        },
+        &s.a,
+    )
+}
+
+pub(crate) fn parse_alias_concat(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str =
+        "alias-concat expects 1 or more params: text or $variables that form the name of an alias";
+    if ac_params.is_empty() {
+        bail!("{ERR_MSG}\nfound no items");
+    }
+    if let Some(reason) = s.alias_concat_keys_placed.get() {
+        bail!("alias-concat cannot be used {reason}");
+    }
+    let parts = ac_params
+        .iter()
+        .map(|expr| {
+            let part = expr
+                .atom(None)
+                .ok_or_else(|| anyhow_expr!(expr, "{ERR_MSG}\nA param must not be a list"))?;
+            let Some(name) = part.strip_prefix('$') else {
+                return Ok(AliasConcatPart::Text(
+                    s.a.sref_str(part.trim_atom_quotes().to_owned()),
+                ));
+            };
+            let default = runtime_var_default(name, s).ok_or_else(|| {
+                anyhow_expr!(
+                    expr,
+                    "{ERR_MSG}\nThe variable must be declared in defvar with a value that is not a list"
+                )
+            })?;
+            Ok(AliasConcatPart::Var {
+                name: s.a.sref_str(name.to_owned()),
+                default: s.a.sref_str(default.trim_atom_quotes().to_owned()),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if !parts
+        .iter()
+        .any(|part| matches!(part, AliasConcatPart::Var { .. }))
+    {
+        bail!("{ERR_MSG}\nWithout a $variable the name does not change, use @<name> instead");
+    }
+    let mut names = s
+        .aliases
+        .keys()
+        .filter(|name| can_name(name, &parts))
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        bail!(
+            "{ERR_MSG}\nNo alias has a name that the params can form. Note that order of declarations matter."
+        );
+    }
+    names.sort();
+    let targets = names
+        .into_iter()
+        .map(|name| {
+            (
+                s.a.sref_str(name.clone()),
+                alias_key_coord(name, s.aliases[name], s),
+            )
+        })
+        .collect::<Vec<_>>();
+    custom(
+        CustomAction::AliasConcat(s.a.sref(AliasConcat {
+            parts: s.a.sref_vec(parts),
+            targets: s.a.sref_vec(targets),
+        })),
+        &s.a,
+    )
+}
+
+/// Returns the virtual key that holds the alias, which is added if the alias has none yet. These
+/// keys are also used by the aliases of `defapp`.
+pub(crate) fn alias_key_coord(name: &str, action: &'static KanataAction, s: &ParserState) -> Coord {
+    let mut keys = s.alias_concat_keys.borrow_mut();
+    let idx = match keys.iter().position(|(key, _)| key == name) {
+        Some(idx) => idx,
+        None => {
+            keys.push((name.to_owned(), action));
+            keys.len() - 1
+        }
+    };
+    let (x, y) = get_fake_key_coords(KEYS_IN_ROW - 1 - idx);
+    Coord { x, y }
+}
+
+/// The value that a variable has before `var-set` changes it.
+fn runtime_var_default<'a>(name: &str, s: &'a ParserState) -> Option<&'a str> {
+    s.vars.get(name)?.atom(s.vars())
+}
+
+/// Returns whether the parts form the name for some non-empty values of the variables.
+fn can_name(name: &str, parts: &[AliasConcatPart]) -> bool {
+    match parts.split_first() {
+        None => name.is_empty(),
+        Some((AliasConcatPart::Text(text), rest)) => name
+            .strip_prefix(text)
+            .is_some_and(|name| can_name(name, rest)),
+        Some((AliasConcatPart::Var { .. }, rest)) => (1..=name.len())
+            .filter(|&i| name.is_char_boundary(i))
+            .any(|i| can_name(&name[i..], rest)),
+    }
+}
+
+/// Puts the aliases of `alias-concat` and `defapp` on their virtual keys in every layer.
+pub(crate) fn place_alias_concat_keys(
+    layers: &mut IntermediateLayers,
+    s: &ParserState,
+) -> Result<()> {
+    s.alias_concat_keys_placed.set(Some(
+        "outside of defalias, deflayer and layer options, e.g. in defchordsv2",
+    ));
+    let keys = s.alias_concat_keys.borrow();
+    if s.virtual_keys.len() + keys.len() > KEYS_IN_ROW {
+        bail!(
+            "Maximum number of virtual keys is {KEYS_IN_ROW}, and each alias that alias-concat or defapp can press uses one, found {}",
+            s.virtual_keys.len() + keys.len()
+        );
+    }
+    for layer in layers.iter_mut() {
+        for (idx, (_, action)) in keys.iter().enumerate() {
+            let (x, y) = get_fake_key_coords(KEYS_IN_ROW - 1 - idx);
+            layer[x as usize][y as usize] = **action;
+        }
+    }
+    Ok(())
+}
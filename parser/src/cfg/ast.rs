@@ -0,0 +1,227 @@
+//! A syntax tree of a configuration, for tools such as formatters, visualizers and linters.
+//!
+//! [`parse_ast`] reads the s-expressions of a single file and groups the items of `defcfg`,
+//! `defsrc`, `deflayer`, `deflayermap`, `defalias` and `defvar` into their parts. It does not
+//! check what the parts mean: keys, actions and variables are left unresolved as [`SExpr`]s, and
+//! `include` is not followed. Every node carries the [`Span`] of its source text.
+//!
+//! Other configuration items are kept as [`ItemKind::Other`].
+//!
+//! ```
+//! use kanata_parser::cfg::ast::{parse_ast, ItemKind};
+//!
+//! let ast = parse_ast("(defsrc a b) ;; base layer\n(deflayer base c d)", "kanata.kbd").unwrap();
+//! let ItemKind::Deflayer(layer) = &ast.items[1].kind else {
+//!     panic!("not a layer");
+//! };
+//! assert_eq!(layer.name.name.t, "base");
+//! assert_eq!(layer.keys.len(), 2);
+//! assert_eq!(ast.comments[0].t, ";; base layer\n");
+//! ```
+
+use super::sexpr::{self, SExpr, SExprMetaData, Span, Spanned};
+use super::{CfgOptions, DEFLAYER, DEFLAYER_MAPPED, ParseError, Result, parse_defcfg};
+
+/// The items of a configuration file, in source order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub items: Vec<Item>,
+    /// Line and block comments, in source order. Line comments include their newline.
+    pub comments: Vec<Spanned<String>>,
+}
+
+/// A top-level list of the configuration, e.g. `(defsrc ...)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    /// The name of the item, e.g. `defsrc`.
+    pub keyword: Spanned<String>,
+    pub kind: ItemKind,
+    /// Covers the whole item including its parentheses.
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ItemKind {
+    Defcfg(Defcfg),
+    /// The keys of `defsrc`.
+    Defsrc(Vec<SExpr>),
+    /// A `deflayer`; its keys correspond to those of `defsrc` by position.
+    Deflayer(Deflayer),
+    /// A `deflayermap`, which maps input keys to actions.
+    Deflayermap(Deflayermap),
+    Defalias(Vec<Binding>),
+    Defvar(Vec<Binding>),
+    /// Any other item. The arguments follow the keyword.
+    Other(Vec<SExpr>),
+}
+
+/// A name followed by its value, e.g. an alias or a `defcfg` option.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub name: Spanned<String>,
+    pub value: SExpr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Defcfg {
+    pub options: Vec<Binding>,
+    exprs: Vec<SExpr>,
+}
+
+impl Defcfg {
+    /// Returns the value of the first option with this name.
+    pub fn option(&self, name: &str) -> Option<&SExpr> {
+        self.options
+            .iter()
+            .find(|b| b.name.t == name)
+            .map(|b| &b.value)
+    }
+
+    /// Checks the options and returns them as kanata uses them.
+    pub fn to_options(&self) -> Result<CfgOptions> {
+        parse_defcfg(&self.exprs)
+    }
+}
+
+/// The name of a layer together with its options, as in `(deflayer (nav icon nav.png) ...)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerName {
+    pub name: Spanned<String>,
+    pub options: Vec<Binding>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deflayer {
+    pub name: LayerName,
+    pub keys: Vec<SExpr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deflayermap {
+    pub name: LayerName,
+    /// Input keys bound to actions. The input key may also be one of the special names such as
+    /// `___` that bind all unmapped keys.
+    pub mappings: Vec<Binding>,
+}
+
+/// Parses the text of a configuration file into its syntax tree.
+pub fn parse_ast(cfg: &str, file_name: &str) -> Result<Config> {
+    let (exprs, metadata) = sexpr::parse_(cfg, file_name, false)?;
+    let items = exprs.into_iter().map(parse_item).collect::<Result<_>>()?;
+    let comments = metadata
+        .into_iter()
+        .filter_map(|m| match m {
+            SExprMetaData::LineComment(c) | SExprMetaData::BlockComment(c) => Some(c),
+            SExprMetaData::Whitespace(_) => None,
+        })
+        .collect();
+    Ok(Config { items, comments })
+}
+
+fn parse_item(expr: Spanned<Vec<SExpr>>) -> Result<Item> {
+    let Spanned { t: exprs, span } = expr;
+    let keyword = match exprs.first() {
+        Some(SExpr::Atom(a)) => a.clone(),
+        Some(first @ SExpr::List(_)) => {
+            return Err(ParseError::from_expr(
+                first,
+                "Invalid: found list as first item in a configuration item",
+            ));
+        }
+        None => {
+            return Err(ParseError::new(
+                span,
+                "Found empty list as a configuration item, you should delete this",
+            ));
+        }
+    };
+    let args = &exprs[1..];
+    let kind = match keyword.t.as_str() {
+        "defcfg" => ItemKind::Defcfg(Defcfg {
+            options: parse_bindings(args, &keyword)?,
+            exprs: exprs.clone(),
+        }),
+        "defsrc" => ItemKind::Defsrc(args.to_vec()),
+        DEFLAYER => {
+            let (name, keys) = parse_layer_name(args, &keyword)?;
+            ItemKind::Deflayer(Deflayer {
+                name,
+                keys: keys.to_vec(),
+            })
+        }
+        DEFLAYER_MAPPED => {
+            let (name, mappings) = parse_layer_name(args, &keyword)?;
+            ItemKind::Deflayermap(Deflayermap {
+                name,
+                mappings: parse_bindings(mappings, &keyword)?,
+            })
+        }
+        "defalias" => ItemKind::Defalias(parse_bindings(args, &keyword)?),
+        "defvar" => ItemKind::Defvar(parse_bindings(args, &keyword)?),
+        _ => ItemKind::Other(args.to_vec()),
+    };
+    Ok(Item {
+        keyword,
+        kind,
+        span,
+    })
+}
+
+fn parse_bindings(exprs: &[SExpr], keyword: &Spanned<String>) -> Result<Vec<Binding>> {
+    exprs
+        .chunks(2)
+        .map(|pair| {
+            let name = match &pair[0] {
+                SExpr::Atom(a) => a.clone(),
+                list => {
+                    return Err(ParseError::from_expr(
+                        list,
+                        format!("Expected a name in {} but found a list", keyword.t),
+                    ));
+                }
+            };
+            let value = pair.get(1).cloned().ok_or_else(|| {
+                ParseError::from_spanned(
+                    &name,
+                    format!("{} has no value after this name", keyword.t),
+                )
+            })?;
+            Ok(Binding { name, value })
+        })
+        .collect()
+}
+
+fn parse_layer_name<'a>(
+    exprs: &'a [SExpr],
+    keyword: &Spanned<String>,
+) -> Result<(LayerName, &'a [SExpr])> {
+    let name_err = || {
+        ParseError::from_spanned(
+            keyword,
+            format!("{} requires a layer name after it", keyword.t),
+        )
+    };
+    let name = match exprs.first().ok_or_else(name_err)? {
+        SExpr::Atom(name) => LayerName {
+            name: name.clone(),
+            options: vec![],
+        },
+        SExpr::List(list) => match list.t.split_first() {
+            Some((SExpr::Atom(name), options)) => LayerName {
+                name: name.clone(),
+                options: parse_bindings(options, keyword)?,
+            },
+            _ => {
+                return Err(ParseError::from_spanned(
+                    list,
+                    format!(
+                        "{} requires a string name within this pair of parentheses",
+                        keyword.t
+                    ),
+                ));
+            }
+        },
+    };
+    Ok((name, &exprs[1..]))
+}
@@ -0,0 +1,112 @@
+//! Auto-shift: holding a key past a timeout outputs its shifted variant.
+//!
+//! Rather than implementing a separate state machine, each layer action that outputs a key listed
+//! in `defautoshift` is replaced with a tap-hold whose tap is the key itself and whose hold is the
+//! shifted variant. This keeps the interaction with other tap-hold keys and with the input queue
+//! identical to an equivalent hand-written tap-hold.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail_expr;
+
+const DEFAUTOSHIFT: &str = "defautoshift";
+
+pub(crate) struct AutoShift {
+    timeout: u16,
+    hold_actions: HashMap<KeyCode, &'static KanataAction>,
+}
+
+pub(crate) fn parse_defautoshift(expr: &[SExpr], s: &ParserState) -> Result<AutoShift> {
+    const ERR_MSG: &str =
+        "defautoshift expects a timeout followed by keys or (key shifted-output) pairs";
+    let mut exprs = check_first_expr(expr.iter(), DEFAUTOSHIFT)?;
+    let timeout_expr = exprs
+        .next()
+        .ok_or_else(|| anyhow_expr!(&expr[0], "{ERR_MSG}"))?;
+    let timeout = parse_non_zero_u16(timeout_expr, s, "auto-shift timeout")?;
+
+    let mut hold_actions = HashMap::default();
+    for item in exprs {
+        let (key, hold) = match item.list(s.vars()) {
+            None => {
+                let key = parse_autoshift_key(item, s)?;
+                let hold: &'static KanataAction = s.a.sref(Action::MultipleKeyCodes(
+                    s.a.sref(s.a.sref_vec(vec![KeyCode::LShift, key])),
+                ));
+                (key, hold)
+            }
+            Some(pair) => {
+                if pair.len() != 2 {
+                    bail_expr!(
+                        item,
+                        "a custom shifted pair expects 2 items: (key shifted-output)"
+                    );
+                }
+                let key = parse_autoshift_key(&pair[0], s)?;
+                let hold = parse_action(&pair[1], s)?;
+                if !matches!(hold, Action::KeyCode(_) | Action::MultipleKeyCodes(_)) {
+                    bail_expr!(
+                        &pair[1],
+                        "the shifted output of auto-shift must be a key or an output chord"
+                    );
+                }
+                (key, hold)
+            }
+        };
+        if hold_actions.insert(key, hold).is_some() {
+            bail_expr!(item, "this key is already configured for auto-shift");
+        }
+    }
+    if hold_actions.is_empty() {
+        bail_expr!(&expr[0], "{ERR_MSG}");
+    }
+    Ok(AutoShift {
+        timeout,
+        hold_actions,
+    })
+}
+
+fn parse_autoshift_key(expr: &SExpr, s: &ParserState) -> Result<KeyCode> {
+    match parse_action(expr, s)? {
+        Action::KeyCode(kc) => Ok(*kc),
+        _ => bail_expr!(expr, "expected a key name"),
+    }
+}
+
+/// Replace the layer actions that output auto-shift keys with the auto-shift tap-hold.
+pub(crate) fn apply_autoshift(
+    layers: &mut IntermediateLayers,
+    autoshift: &AutoShift,
+    s: &ParserState,
+) {
+    let autoshift_actions: HashMap<KeyCode, KanataAction> = autoshift
+        .hold_actions
+        .iter()
+        .map(|(key, hold)| {
+            let tap = Action::KeyCode(*key);
+            (
+                *key,
+                Action::HoldTap(s.a.sref(HoldTapAction {
+                    config: HoldTapConfig::Default,
+                    tap_hold_interval: 0,
+                    timeout: autoshift.timeout,
+                    tap,
+                    hold: **hold,
+                    timeout_action: **hold,
+                    on_press_reset_timeout_to: None,
+                    require_prior_idle: None,
+                })),
+            )
+        })
+        .collect();
+    for layer in layers.iter_mut() {
+        for cell in layer[usize::from(NORMAL_KEY_ROW)].iter_mut() {
+            if let Action::KeyCode(kc) = cell {
+                if let Some(action) = autoshift_actions.get(kc) {
+                    *cell = *action;
+                }
+            }
+        }
+    }
+}
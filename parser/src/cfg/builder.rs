@@ -0,0 +1,286 @@
+//! Build a configuration in code, e.g. to generate it from a different keymap format.
+//!
+//! [`ConfigBuilder`] writes the configuration as `.kbd` text. Its contents are not checked until
+//! the text is parsed, so check the result by parsing it, e.g. by loading it into kanata.
+//!
+//! ```
+//! use kanata_parser::cfg::builder::{Action, ConfigBuilder};
+//!
+//! let cfg = ConfigBuilder::new()
+//!     .option("process-unmapped-keys", "yes")
+//!     .source(["caps", "a"])
+//!     .alias("cap", Action::tap_hold(200, 200, Action::key("esc"), Action::layer_while_held("nav")))
+//!     .layer("base", [Action::alias("cap"), Action::Transparent])
+//!     .layer("nav", [Action::Transparent, Action::key("left")])
+//!     .to_kbd();
+//! assert_eq!(
+//!     cfg,
+//!     "(defcfg\n  process-unmapped-keys yes\n)\n\
+//!      (defsrc caps a)\n\
+//!      (defalias\n  cap (tap-hold 200 200 esc (layer-while-held nav))\n)\n\
+//!      (deflayer base @cap _)\n\
+//!      (deflayer nav _ left)\n"
+//! );
+//! ```
+
+use std::fmt::{self, Display, Write};
+
+/// An action of a key in a layer or an alias.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Action {
+    /// A key name such as `a` or `lctl`, or a key with modifiers such as `C-S-a`.
+    Key(String),
+    /// A reference to an alias, `@name`.
+    Alias(String),
+    /// `_`: the action of the same key in the default layer.
+    Transparent,
+    /// `XX`: does nothing.
+    NoOp,
+    TapHold {
+        tap_timeout: u16,
+        hold_timeout: u16,
+        tap: Box<Action>,
+        hold: Box<Action>,
+    },
+    LayerSwitch(String),
+    LayerWhileHeld(String),
+    OneShot {
+        timeout: u16,
+        action: Box<Action>,
+    },
+    /// Performs all actions at the same time.
+    Multi(Vec<Action>),
+    /// An action written as text, for actions that have no variant here, e.g. `(unicode é)`.
+    Raw(String),
+}
+
+impl Action {
+    pub fn key(name: impl Into<String>) -> Self {
+        Self::Key(name.into())
+    }
+
+    pub fn alias(name: impl Into<String>) -> Self {
+        Self::Alias(name.into())
+    }
+
+    pub fn tap_hold(tap_timeout: u16, hold_timeout: u16, tap: Action, hold: Action) -> Self {
+        Self::TapHold {
+            tap_timeout,
+            hold_timeout,
+            tap: Box::new(tap),
+            hold: Box::new(hold),
+        }
+    }
+
+    pub fn layer_switch(layer: impl Into<String>) -> Self {
+        Self::LayerSwitch(layer.into())
+    }
+
+    pub fn layer_while_held(layer: impl Into<String>) -> Self {
+        Self::LayerWhileHeld(layer.into())
+    }
+
+    pub fn one_shot(timeout: u16, action: Action) -> Self {
+        Self::OneShot {
+            timeout,
+            action: Box::new(action),
+        }
+    }
+
+    pub fn multi(actions: impl IntoIterator<Item = Action>) -> Self {
+        Self::Multi(actions.into_iter().collect())
+    }
+
+    pub fn raw(text: impl Into<String>) -> Self {
+        Self::Raw(text.into())
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(name) => write!(f, "{}", Atom(name)),
+            Self::Alias(name) => write!(f, "@{}", Atom(name)),
+            Self::Transparent => f.write_str("_"),
+            Self::NoOp => f.write_str("XX"),
+            Self::TapHold {
+                tap_timeout,
+                hold_timeout,
+                tap,
+                hold,
+            } => write!(f, "(tap-hold {tap_timeout} {hold_timeout} {tap} {hold})"),
+            Self::LayerSwitch(layer) => write!(f, "(layer-switch {})", Atom(layer)),
+            Self::LayerWhileHeld(layer) => write!(f, "(layer-while-held {})", Atom(layer)),
+            Self::OneShot { timeout, action } => write!(f, "(one-shot {timeout} {action})"),
+            Self::Multi(actions) => {
+                f.write_str("(multi")?;
+                for action in actions {
+                    write!(f, " {action}")?;
+                }
+                f.write_char(')')
+            }
+            Self::Raw(text) => f.write_str(text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Layer {
+    /// Actions in the order of `defsrc`.
+    Keys(Vec<Action>),
+    /// Input keys mapped to actions.
+    Map(Vec<(String, Action)>),
+}
+
+/// Builds the text of a configuration. Items are written in the order: `defcfg`, `defsrc`,
+/// `defvar`, `defalias`, then layers in the order they were added.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigBuilder {
+    options: Vec<(String, String)>,
+    source: Vec<String>,
+    vars: Vec<(String, String)>,
+    aliases: Vec<(String, Action)>,
+    layers: Vec<(String, Layer)>,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a `defcfg` option. The value is written as is, so it may be a list such as
+    /// `(lctl spc esc)`.
+    pub fn option(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the keys of `defsrc`.
+    pub fn source(mut self, keys: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.source = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Adds a variable to `defvar`. The value is written as is.
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn alias(mut self, name: impl Into<String>, action: Action) -> Self {
+        self.aliases.push((name.into(), action));
+        self
+    }
+
+    /// Adds a `deflayer` with one action for each key of `defsrc`. The first layer added is the
+    /// default layer.
+    pub fn layer(
+        mut self,
+        name: impl Into<String>,
+        actions: impl IntoIterator<Item = Action>,
+    ) -> Self {
+        self.layers
+            .push((name.into(), Layer::Keys(actions.into_iter().collect())));
+        self
+    }
+
+    /// Adds a `deflayermap` that maps input keys to actions.
+    pub fn layer_map(
+        mut self,
+        name: impl Into<String>,
+        mappings: impl IntoIterator<Item = (impl Into<String>, Action)>,
+    ) -> Self {
+        let mappings = mappings.into_iter().map(|(k, a)| (k.into(), a)).collect();
+        self.layers.push((name.into(), Layer::Map(mappings)));
+        self
+    }
+
+    /// Returns the configuration as `.kbd` text.
+    pub fn to_kbd(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Display for ConfigBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_pairs = |f: &mut fmt::Formatter<'_>,
+                           keyword: &str,
+                           pairs: &mut dyn Iterator<Item = (&str, String)>|
+         -> fmt::Result {
+            writeln!(f, "({keyword}")?;
+            for (name, value) in pairs {
+                writeln!(f, "  {} {value}", Atom(name))?;
+            }
+            writeln!(f, ")")
+        };
+        if !self.options.is_empty() {
+            write_pairs(
+                f,
+                "defcfg",
+                &mut self.options.iter().map(|(n, v)| (n.as_str(), v.clone())),
+            )?;
+        }
+        f.write_str("(defsrc")?;
+        for key in &self.source {
+            write!(f, " {}", Atom(key))?;
+        }
+        writeln!(f, ")")?;
+        if !self.vars.is_empty() {
+            write_pairs(
+                f,
+                "defvar",
+                &mut self.vars.iter().map(|(n, v)| (n.as_str(), v.clone())),
+            )?;
+        }
+        if !self.aliases.is_empty() {
+            write_pairs(
+                f,
+                "defalias",
+                &mut self
+                    .aliases
+                    .iter()
+                    .map(|(n, a)| (n.as_str(), a.to_string())),
+            )?;
+        }
+        for (name, layer) in &self.layers {
+            match layer {
+                Layer::Keys(actions) => {
+                    write!(f, "(deflayer {}", Atom(name))?;
+                    for action in actions {
+                        write!(f, " {action}")?;
+                    }
+                    writeln!(f, ")")?;
+                }
+                Layer::Map(mappings) => write_pairs(
+                    f,
+                    &format!("deflayermap ({})", Atom(name)),
+                    &mut mappings.iter().map(|(k, a)| (k.as_str(), a.to_string())),
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a name, quoting it if it contains characters that would split it into several items.
+struct Atom<'a>(&'a str);
+
+impl Display for Atom<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.0;
+        let needs_quotes = s.is_empty()
+            || s.starts_with(";;")
+            || s.starts_with("#|")
+            || s.starts_with("r#\"")
+            || s.bytes()
+                .any(|b| b.is_ascii_whitespace() || matches!(b, b'(' | b')' | b'"'));
+        if !needs_quotes {
+            f.write_str(s)
+        } else if s.contains(['"', '\n']) {
+            write!(f, "r#\"{s}\"#")
+        } else {
+            write!(f, "\"{s}\"")
+        }
+    }
+}
@@ -40,7 +40,7 @@ pub(crate) fn parse_defchordv2(
                     &chunk[2],
                     &chunk[3],
                     &chunk[4],
-                    &s.layers[0][0],
+                    s.layers.row(0, 0),
                 );
                 let chord_definitions = parse_chord_file(file_name).unwrap();
                 let processed = chord_definitions.iter().map(|chord_def| {
@@ -91,6 +91,92 @@ pub(crate) fn parse_defchordv2(
     Ok(chords_container)
 }
 
+/// Returns warnings about chords that can never activate, or that activate later than their own
+/// timeout, for `--check` to report. These work without error at runtime but look like misfires.
+pub(crate) fn chord_conflicts(
+    chords: &ChordsForKeys<'static, KanataCustom>,
+    mapped_keys: &MappedKeys,
+    layer_count: usize,
+) -> Vec<String> {
+    // Every chord is in the mapping of each of its keys, keep it once.
+    let mut chords = chords
+        .mapping
+        .iter()
+        .flat_map(|(key, cfk)| {
+            cfk.chords
+                .iter()
+                .filter(move |chord| chord.participating_keys[0] == *key)
+        })
+        .collect::<Vec<_>>();
+    chords.sort_by_key(|chord| chord.participating_keys);
+    let name = |chord: &ChordV2<'static, KanataCustom>| {
+        let keys = chord
+            .participating_keys
+            .iter()
+            .map(|k| {
+                OsCode::from_u16(*k)
+                    .and_then(oscode_to_str)
+                    .map(str::to_owned)
+                    .unwrap_or_else(|| k.to_string())
+            })
+            .collect::<Vec<_>>();
+        format!("({})", keys.join(" "))
+    };
+    let enabled_on = |chord: &ChordV2<'static, KanataCustom>, layer: usize| {
+        !chord.disabled_layers.contains(&(layer as u16))
+    };
+
+    let mut warnings = vec![];
+    for chord in chords.iter() {
+        if let Some(key) = chord
+            .participating_keys
+            .iter()
+            .find(|k| OsCode::from_u16(**k).is_none_or(|osc| !mapped_keys.contains(&osc)))
+        {
+            warnings.push(format!(
+                "chord {} can never activate: {} is not in defsrc and process-unmapped-keys \
+                 is not enabled",
+                name(chord),
+                OsCode::from_u16(*key)
+                    .and_then(oscode_to_str)
+                    .unwrap_or("its key"),
+            ));
+        }
+        if !(0..layer_count).any(|layer| enabled_on(chord, layer)) {
+            warnings.push(format!(
+                "chord {} can never activate: it is disabled on every layer",
+                name(chord)
+            ));
+        }
+        // While the keys pressed so far can still complete a longer chord, the chord waits for
+        // the timeout of the longer chord, so its own shorter timeout has no effect.
+        for longer in chords.iter() {
+            let is_strict_subset = longer.participating_keys.len() > chord.participating_keys.len()
+                && chord
+                    .participating_keys
+                    .iter()
+                    .all(|k| longer.participating_keys.contains(k));
+            if is_strict_subset
+                && longer.pending_duration > chord.pending_duration
+                && (0..layer_count)
+                    .any(|layer| enabled_on(chord, layer) && enabled_on(longer, layer))
+            {
+                warnings.push(format!(
+                    "chord {} with timeout {} is a subset of chord {} with timeout {}, \
+                     so {} waits up to {} ms for the other keys before it activates",
+                    name(chord),
+                    chord.pending_duration,
+                    name(longer),
+                    longer.pending_duration,
+                    name(chord),
+                    longer.pending_duration,
+                ));
+            }
+        }
+    }
+    warnings
+}
+
 fn parse_single_chord(
     chunk: &[SExpr],
     s: &ParserState,
@@ -239,7 +325,7 @@ impl<'a> ChordTranslation<'a> {
         timeout: &'a SExpr,
         release_behaviour: &'a SExpr,
         disabled_layers: &'a SExpr,
-        first_layer: &[Action<'static, KanataCustom>],
+        first_layer: impl Iterator<Item = &'static Action<'static, KanataCustom>>,
     ) -> Self {
         let postprocess_map: FxHashMap<String, String> = [
             ("semicolon", ";"),
@@ -254,7 +340,6 @@ impl<'a> ChordTranslation<'a> {
         .map(|(k, v)| (k.to_string(), v.to_string()))
         .collect();
         let target_map = first_layer
-            .iter()
             .enumerate()
             .filter_map(|(idx, layout)| {
                 layout
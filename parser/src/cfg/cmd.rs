@@ -16,6 +16,156 @@ pub(crate) enum CmdType {
     ClipboardSaveSet,
 }
 
+/// The commands that `cmd-allowlist` in defcfg permits. When it is set, cmd actions may only run
+/// these, which is checked when the configuration is parsed and again before a command runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CmdAllowlist {
+    entries: Vec<AllowedCmd>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AllowedCmd {
+    program: String,
+    /// Patterns for the arguments, one per argument, or None to permit any arguments.
+    args: Option<Vec<String>>,
+}
+
+/// The argument pattern that permits any further arguments.
+const ANY_FURTHER_ARGS: &str = "...";
+
+impl CmdAllowlist {
+    /// Whether the program, which is the first string, may run with the arguments that follow.
+    pub fn allows<S: AsRef<str>>(&self, cmd_and_args: &[S]) -> bool {
+        let Some((program, args)) = cmd_and_args.split_first() else {
+            return false;
+        };
+        self.entries.iter().any(|entry| {
+            entry.program == program.as_ref()
+                && entry
+                    .args
+                    .as_ref()
+                    .is_none_or(|patterns| args_match(patterns, args))
+        })
+    }
+}
+
+fn args_match<S: AsRef<str>>(patterns: &[String], args: &[S]) -> bool {
+    match (patterns.split_first(), args.split_first()) {
+        (Some((pattern, _)), _) if pattern == ANY_FURTHER_ARGS => true,
+        (Some((pattern, patterns)), Some((arg, args))) => {
+            glob_match(pattern, arg.as_ref()) && args_match(patterns, args)
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Whether the text matches the pattern, in which `*` matches any characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = text.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+pub(crate) fn parse_cmd_allowlist(expr: &SExpr, label: &str) -> Result<CmdAllowlist> {
+    let err = "Expected a list of programs, or of lists of a program and patterns for its arguments, \
+               e.g. (/usr/bin/notify-send (/usr/bin/playerctl play-pause) (/usr/bin/pactl set-sink-volume @DEFAULT_SINK@ *)).";
+    let Some(list) = expr.list(None) else {
+        bail_expr!(expr, "The value for {label} must be a list. {err}");
+    };
+    let mut entries = Vec::with_capacity(list.len());
+    for entry in list {
+        let allowed = match (entry.atom(None), entry.list(None)) {
+            (Some(program), _) => AllowedCmd {
+                program: program.trim_atom_quotes().to_owned(),
+                args: None,
+            },
+            (_, Some([program, args @ ..])) => {
+                let Some(program) = program.atom(None) else {
+                    bail_expr!(program, "Expected a program. {err}");
+                };
+                let mut patterns = Vec::with_capacity(args.len());
+                for (i, arg) in args.iter().enumerate() {
+                    let Some(pattern) = arg.atom(None).map(|a| a.trim_atom_quotes()) else {
+                        bail_expr!(arg, "Expected an argument pattern. {err}");
+                    };
+                    if pattern == ANY_FURTHER_ARGS && i + 1 < args.len() {
+                        bail_expr!(arg, "{ANY_FURTHER_ARGS} must be the last pattern.");
+                    }
+                    patterns.push(pattern.to_owned());
+                }
+                AllowedCmd {
+                    program: program.trim_atom_quotes().to_owned(),
+                    args: Some(patterns),
+                }
+            }
+            _ => bail_expr!(entry, "{err}"),
+        };
+        entries.push(allowed);
+    }
+    Ok(CmdAllowlist { entries })
+}
+
+/// Parses a list of names and values of environment variables. A value can be
+/// `(secret <name>)`, which is read when the command starts.
+pub(crate) fn parse_cmd_env(
+    expr: &SExpr,
+    vars: Option<&HashMap<String, SExpr>>,
+    secrets: &[Secret],
+    label: &str,
+) -> Result<CmdEnv> {
+    let err =
+        format!("{label} expects a list of variable names and values, e.g. (LANG C PATH /usr/bin)");
+    let Some(list) = expr.list(vars) else {
+        bail_expr!(expr, "{err}");
+    };
+    let mut env = Vec::with_capacity(list.len() / 2);
+    let mut pairs = list.chunks_exact(2);
+    for pair in pairs.by_ref() {
+        let name = match pair[0].atom(vars) {
+            Some(name) if !name.is_empty() && !name.contains('=') => name.trim_atom_quotes(),
+            _ => bail_expr!(&pair[0], "{err}"),
+        };
+        let value = match (
+            pair[1].atom(vars),
+            parse_secret_ref(&pair[1], vars, secrets)?,
+        ) {
+            (Some(value), _) => EnvValue::Text(value.trim_atom_quotes().to_owned()),
+            (None, Some(secret)) => EnvValue::Secret(secret),
+            (None, None) => bail_expr!(&pair[1], "{err}"),
+        };
+        env.push((name.to_owned(), value));
+    }
+    if let [name] = pairs.remainder() {
+        bail_expr!(name, "This variable is missing a value.");
+    }
+    Ok(env)
+}
+
+/// Fails if `cmd-allowlist` is set and does not permit the command that `exprs` were collected to.
+fn check_cmd_allowed(cmd: &[String], exprs: &[SExpr], s: &ParserState) -> Result<()> {
+    match &s.cmd_allowlist {
+        Some(allowlist) if !allowlist.allows(cmd) => bail_expr!(
+            &exprs[0],
+            "cmd-allowlist in defcfg does not permit this command: {}",
+            cmd.join(" ")
+        ),
+        _ => Ok(()),
+    }
+}
+
 // Parse cmd, but there are 2 arguments before specifying normal log and error log
 pub(crate) fn parse_cmd_log(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
     const ERR_STR: &str =
@@ -45,6 +195,7 @@ pub(crate) fn parse_cmd_log(ac_params: &[SExpr], s: &ParserState) -> Result<&'st
     if cmd.is_empty() {
         bail!(ERR_STR);
     }
+    check_cmd_allowed(&cmd, &ac_params[2..], s)?;
     let cmds = cmd.into_iter().map(|v| s.a.sref_str(v)).collect();
     custom(
         CustomAction::CmdLog(log_level, error_log_level, s.a.sref_vec(cmds)),
@@ -69,7 +220,9 @@ pub(crate) fn parse_cmd(
         if matches!(cmd_type, CmdType::ClipboardSaveSet) {
             const ERR_STR: &str = "expects a save ID and at least one string";
             if !s.is_cmd_enabled {
-                bail!("To use cmd you must put in defcfg: danger-enable-cmd yes.");
+                bail!(
+                    "To use cmd you must put in defcfg: danger-enable-cmd yes, or a cmd-allowlist."
+                );
             }
             if ac_params.len() < 2 {
                 bail!("{CLIPBOARD_SAVE_CMD_SET} {ERR_STR}");
@@ -80,6 +233,7 @@ pub(crate) fn parse_cmd(
             if cmd.is_empty() {
                 bail_expr!(&ac_params[1], "{CLIPBOARD_SAVE_CMD_SET} {ERR_STR}");
             }
+            check_cmd_allowed(&cmd, &ac_params[1..], s)?;
             let cmds = cmd.into_iter().map(|v| s.a.sref_str(v)).collect();
             return custom(
                 CustomAction::ClipboardSaveCmdSet(save_id, s.a.sref_vec(cmds)),
@@ -89,13 +243,14 @@ pub(crate) fn parse_cmd(
 
         const ERR_STR: &str = "cmd expects at least one string";
         if !s.is_cmd_enabled {
-            bail!("To use cmd you must put in defcfg: danger-enable-cmd yes.");
+            bail!("To use cmd you must put in defcfg: danger-enable-cmd yes, or a cmd-allowlist.");
         }
         let mut cmd = vec![];
         collect_strings(ac_params, &mut cmd, s);
         if cmd.is_empty() {
             bail!(ERR_STR);
         }
+        check_cmd_allowed(&cmd, ac_params, s)?;
         let cmds = cmd.into_iter().map(|v| s.a.sref_str(v)).collect();
         let cmds = s.a.sref_vec(cmds);
         custom(
@@ -110,6 +265,106 @@ pub(crate) fn parse_cmd(
     }
 }
 
+#[allow(unused_variables)]
+pub(crate) fn parse_cmd_argv(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    #[cfg(not(feature = "cmd"))]
+    {
+        bail!(
+            "cmd is not enabled for this kanata executable. Use a cmd_allowed prebuilt executable or compile with the feature: cmd."
+        );
+    }
+    #[cfg(feature = "cmd")]
+    {
+        const ERR_STR: &str = "cmd-argv expects a list of the program and its arguments, \
+                               then options: env-clear, env, uid, gid";
+        if !s.is_cmd_enabled {
+            bail!("To use cmd you must put in defcfg: danger-enable-cmd yes, or a cmd-allowlist.");
+        }
+        let Some(argv_expr) = ac_params.first() else {
+            bail!(ERR_STR);
+        };
+        let Some(argv_list) = argv_expr.list(s.vars()).filter(|l| !l.is_empty()) else {
+            bail_expr!(argv_expr, "{ERR_STR}");
+        };
+        // Unlike cmd, lists are not flattened, so that a variable is always one argument.
+        let mut argv = Vec::with_capacity(argv_list.len());
+        for arg in argv_list {
+            match arg.atom(s.vars()) {
+                Some(arg) => argv.push(arg.trim_atom_quotes().to_owned()),
+                None => bail_expr!(
+                    arg,
+                    "Each argument of cmd-argv must be a string, not a list."
+                ),
+            }
+        }
+        check_cmd_allowed(&argv, std::slice::from_ref(argv_expr), s)?;
+        let mut sandbox = CmdSandbox::default();
+        let mut opts = ac_params[1..].chunks_exact(2);
+        for kv in opts.by_ref() {
+            let (key, val) = (&kv[0], &kv[1]);
+            match key.atom(s.vars()) {
+                Some("env-clear") => sandbox.clear_env = parse_defcfg_val_bool(val, "env-clear")?,
+                Some(label @ "env") => {
+                    sandbox.env = parse_cmd_env(val, s.vars(), &s.secrets, label)?
+                }
+                Some(id @ ("uid" | "gid")) => {
+                    if cfg!(not(unix)) {
+                        bail_expr!(key, "{id} is only supported on Unix.");
+                    }
+                    let parsed = val
+                        .atom(s.vars())
+                        .and_then(|v| v.trim_atom_quotes().parse::<u32>().ok());
+                    let Some(parsed) = parsed else {
+                        bail_expr!(val, "{id} must be a number");
+                    };
+                    match id {
+                        "uid" => sandbox.uid = Some(parsed),
+                        _ => sandbox.gid = Some(parsed),
+                    }
+                }
+                _ => bail_expr!(key, "{ERR_STR}"),
+            }
+        }
+        if let [key] = opts.remainder() {
+            bail_expr!(key, "This option is missing a value.");
+        }
+        let argv = argv.into_iter().map(|v| s.a.sref_str(v)).collect();
+        custom(
+            CustomAction::CmdArgv(s.a.sref_vec(argv), s.a.sref(sandbox)),
+            &s.a,
+        )
+    }
+}
+
+#[allow(unused_variables)]
+pub(crate) fn parse_cmd_cancel(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    #[cfg(not(feature = "cmd"))]
+    {
+        bail!(
+            "cmd is not enabled for this kanata executable. Use a cmd_allowed prebuilt executable or compile with the feature: cmd."
+        );
+    }
+    #[cfg(feature = "cmd")]
+    {
+        const ERR_STR: &str = "cmd-cancel expects nothing, or the program of the command to kill";
+        let program = match ac_params {
+            [] => None,
+            [program] => match program.atom(s.vars()) {
+                Some(program) => Some(s.a.sref_str(program.trim_atom_quotes().to_owned())),
+                None => bail_expr!(program, "{ERR_STR}"),
+            },
+            _ => bail_expr!(&ac_params[1], "{ERR_STR}"),
+        };
+        custom(CustomAction::CmdCancel(program), &s.a)
+    }
+}
+
 /// Recurse through all levels of list nesting and collect into a flat list of strings.
 /// Recursion is DFS, which matches left-to-right reading of the strings as they appear,
 /// if everything was on a single line.
@@ -138,3 +393,14 @@ pub(crate) fn test_collect_strings() {
         ]
     );
 }
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("play-pause", "play-pause"));
+    assert!(!glob_match("play-pause", "play-pause2"));
+    assert!(glob_match("*", ""));
+    assert!(glob_match("*%", "+5%"));
+    assert!(!glob_match("*%", "5"));
+    assert!(glob_match("a*b*c", "aXbYbc"));
+    assert!(!glob_match("a*a", "a"));
+}
@@ -0,0 +1,195 @@
+//! Parsing of the `compose` action and of `defcompose`, which maps key sequences typed after
+//! `compose` to unicode output, like the compose key of X11.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+use crate::bail_expr;
+use crate::sequences::mod_mask_for_keycode;
+
+/// Key sequences of `defcompose` and the text that they output. A key in a sequence is the
+/// [`OsCode`] of the key with the modifier bits of [`mod_mask_for_keycode`] for the modifiers
+/// that are held when it is pressed.
+pub type ComposeTable = Trie<Arc<str>>;
+
+const DEFCOMPOSE_ERR: &str =
+    "defcompose expects pairs of parameters: <key-list> <output>\nor: xcompose <file-name>";
+
+pub(crate) fn parse_compose(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "compose expects one param: <timeout>";
+    if ac_params.len() != 1 {
+        bail!("{ERR_MSG}\nfound {} items", ac_params.len());
+    }
+    let timeout = parse_non_zero_u16(&ac_params[0], s, "timeout")?;
+    custom(CustomAction::Compose(timeout), &s.a)
+}
+
+pub(crate) fn parse_defcompose(
+    exprs: &[SExpr],
+    s: &ParserState,
+    f: &mut FileContentProvider,
+) -> Result<ComposeTable> {
+    let mut table = Trie::new();
+    let mut subexprs = check_first_expr(exprs.iter(), "defcompose")?;
+    let mut xcompose_files = vec![];
+    while let Some(keys_expr) = subexprs.next() {
+        let Some(output_expr) = subexprs.next() else {
+            bail_expr!(
+                keys_expr,
+                "{DEFCOMPOSE_ERR}\nMissing the second item of the pair"
+            );
+        };
+        if keys_expr.atom(s.vars()) == Some("xcompose") {
+            let file_name = output_expr.atom(s.vars()).ok_or_else(|| {
+                anyhow_expr!(
+                    output_expr,
+                    "{DEFCOMPOSE_ERR}\nThe file name must not be a list"
+                )
+            })?;
+            xcompose_files.push((output_expr, file_name.trim_atom_quotes()));
+            continue;
+        }
+        let Some(key_list) = keys_expr.list(s.vars()) else {
+            bail_expr!(
+                keys_expr,
+                "{DEFCOMPOSE_ERR}\nThe keys must be a list, or the word xcompose"
+            );
+        };
+        if key_list.is_empty() {
+            bail_expr!(
+                keys_expr,
+                "{DEFCOMPOSE_ERR}\nThe key list must not be empty"
+            );
+        }
+        let keys = key_list
+            .iter()
+            .map(|key_expr| {
+                key_expr
+                    .atom(s.vars())
+                    .and_then(compose_key)
+                    .ok_or_else(|| anyhow_expr!(key_expr, "{DEFCOMPOSE_ERR}\nUnknown key, expected a non-modifier key name with optional prefixes like S-"))
+            })
+            .collect::<Result<Vec<u16>>>()?;
+        let output = output_expr
+            .atom(s.vars())
+            .map(|a| a.trim_atom_quotes())
+            .filter(|a| !a.is_empty())
+            .ok_or_else(|| {
+                anyhow_expr!(
+                    output_expr,
+                    "{DEFCOMPOSE_ERR}\nThe output must be a non-empty string"
+                )
+            })?;
+        if table.ancestor_exists(&keys) || table.descendant_exists(&keys) {
+            bail_expr!(
+                keys_expr,
+                "This key list conflicts with an earlier one: one must not start with the other"
+            );
+        }
+        table.insert(keys, Arc::from(output));
+    }
+    // The sequences of the configuration take precedence over the imported ones.
+    for (file_expr, file_name) in xcompose_files {
+        let content = f
+            .get_file_content(file_name.as_ref())
+            .map_err(|e| anyhow_expr!(file_expr, "Failed to read file:\n{e}"))?;
+        let mut skipped = 0;
+        for (keys, output) in content.lines().filter_map(parse_xcompose_line) {
+            if table.ancestor_exists(&keys) || table.descendant_exists(&keys) {
+                skipped += 1;
+                continue;
+            }
+            table.insert(keys, Arc::from(output));
+        }
+        if skipped > 0 {
+            log::info!(
+                "skipped {skipped} sequences of {file_name} that conflict with earlier sequences"
+            );
+        }
+    }
+    Ok(table)
+}
+
+/// Parses a key name with optional modifier prefixes into an element of [`ComposeTable`].
+fn compose_key(key: &str) -> Option<u16> {
+    let (mods, key) = parse_mod_prefix(key).ok()?;
+    let osc = str_to_oscode(key).filter(|osc| !osc.is_modifier())?;
+    Some(
+        mods.into_iter()
+            .fold(u16::from(osc), |k, m| k | mod_mask_for_keycode(m)),
+    )
+}
+
+/// Parses a line of an XCompose file like `<Multi_key> <apostrophe> <e> : "é" eacute` into the
+/// keys after `<Multi_key>` and the output. Lines that do not start with `<Multi_key>` or that
+/// use keysyms that have no key on a US layout are skipped.
+fn parse_xcompose_line(line: &str) -> Option<(Vec<u16>, &str)> {
+    let (keysyms, output) = line.split_once(':')?;
+    let mut keysyms = keysyms.split_whitespace();
+    if keysyms.next()? != "<Multi_key>" {
+        return None;
+    }
+    let keys = keysyms
+        .map(|keysym| {
+            let keysym = keysym.strip_prefix('<')?.strip_suffix('>')?;
+            compose_key(&xcompose_keysym_key(keysym)?)
+        })
+        .collect::<Option<Vec<u16>>>()?;
+    let output = output.trim_start().strip_prefix('"')?;
+    let output = &output[..output.rfind('"')?];
+    if keys.is_empty() || output.is_empty() || output.contains('\\') {
+        return None;
+    }
+    Some((keys, output))
+}
+
+/// The kanata key name for an X11 keysym, assuming a US layout. The names are ones that
+/// `deflocalkeys` cannot change.
+fn xcompose_keysym_key(keysym: &str) -> Option<String> {
+    if keysym.len() == 1 {
+        let c = keysym.chars().next()?;
+        return match c {
+            'a'..='z' | '0'..='9' => Some(c.to_string()),
+            'A'..='Z' => Some(format!("S-{}", c.to_ascii_lowercase())),
+            _ => None,
+        };
+    }
+    let key = match keysym {
+        "space" => "spc",
+        "apostrophe" => "apo",
+        "quotedbl" => "S-apo",
+        "grave" => "grv",
+        "asciitilde" => "S-grv",
+        "comma" => "comm",
+        "less" => "S-comm",
+        "period" => "Period",
+        "greater" => "S-Period",
+        "slash" => "Slash",
+        "question" => "S-Slash",
+        "semicolon" => "scln",
+        "colon" => "S-scln",
+        "minus" => "min",
+        "underscore" => "S-min",
+        "equal" => "eql",
+        "plus" => "S-eql",
+        "bracketleft" => "lbrc",
+        "braceleft" => "S-lbrc",
+        "bracketright" => "rbrc",
+        "braceright" => "S-rbrc",
+        "backslash" => "bksl",
+        "bar" => "S-bksl",
+        "exclam" => "S-1",
+        "at" => "S-2",
+        "numbersign" => "S-3",
+        "dollar" => "S-4",
+        "percent" => "S-5",
+        "asciicircum" => "S-6",
+        "ampersand" => "S-7",
+        "asterisk" => "S-8",
+        "parenleft" => "S-9",
+        "parenright" => "S-0",
+        _ => return None,
+    };
+    Some(key.to_owned())
+}
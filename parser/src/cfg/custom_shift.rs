@@ -0,0 +1,70 @@
+use super::*;
+
+use crate::{anyhow_expr, bail_expr};
+
+/// Parses `defcustomshift`, which is pairs of `<key> <shifted-output>`, into overrides of the key
+/// with either or both shifts held. Other modifiers exclude the override, so that shortcuts that
+/// include shift still output the key.
+pub(crate) fn parse_defcustomshift(exprs: &[SExpr], s: &ParserState) -> Result<Vec<Override>> {
+    const ERR_MSG: &str = "defcustomshift expects pairs of parameters: <key> <shifted-output>";
+    let mut subexprs = check_first_expr(exprs.iter(), "defcustomshift")?;
+    let other_mods: Box<[OsCode]> = [
+        OsCode::KEY_LEFTCTRL,
+        OsCode::KEY_RIGHTCTRL,
+        OsCode::KEY_LEFTALT,
+        OsCode::KEY_RIGHTALT,
+        OsCode::KEY_LEFTMETA,
+        OsCode::KEY_RIGHTMETA,
+    ]
+    .into();
+    let shifts: [&[OsCode]; 3] = [
+        &[OsCode::KEY_LEFTSHIFT],
+        &[OsCode::KEY_RIGHTSHIFT],
+        // Last, so that it takes precedence when both shifts are held and both are released.
+        &[OsCode::KEY_LEFTSHIFT, OsCode::KEY_RIGHTSHIFT],
+    ];
+
+    let mut overrides = vec![];
+    let mut seen = vec![];
+    while let Some(key_expr) = subexprs.next() {
+        let Some(out_expr) = subexprs.next() else {
+            bail_expr!(
+                key_expr,
+                "{ERR_MSG}\nMissing the shifted output for this key"
+            );
+        };
+        let key = key_expr
+            .atom(s.vars())
+            .and_then(str_to_oscode)
+            .ok_or_else(|| anyhow_expr!(key_expr, "{ERR_MSG}\nUnknown key name"))?;
+        if key.is_modifier() {
+            bail_expr!(key_expr, "{ERR_MSG}\nThe key must not be a modifier");
+        }
+        if seen.contains(&key) {
+            bail_expr!(key_expr, "{ERR_MSG}\nThis key already has a shifted output");
+        }
+        seen.push(key);
+        let out = out_expr
+            .atom(s.vars())
+            .ok_or_else(|| anyhow_expr!(out_expr, "{ERR_MSG}\nThe shifted output must be a key"))?;
+        let (mods, out_key) =
+            parse_mod_prefix(out).map_err(|e| anyhow_expr!(out_expr, "{}", e.msg))?;
+        let out_key = str_to_oscode(out_key).ok_or_else(|| {
+            anyhow_expr!(
+                out_expr,
+                "{ERR_MSG}\nUnknown key name, e.g. use 7 for 7 or S-7 for &"
+            )
+        })?;
+        let mut out_keys: Vec<OsCode> = mods.into_iter().map(OsCode::from).collect();
+        out_keys.push(out_key);
+        for shift in shifts {
+            let mut in_keys = shift.to_vec();
+            in_keys.push(key);
+            overrides.push(
+                Override::try_new_v2(&in_keys, &out_keys, other_mods.clone(), [].into())
+                    .map_err(|e| anyhow_expr!(out_expr, "{ERR_MSG}: {e}"))?,
+            );
+        }
+    }
+    Ok(overrides)
+}
@@ -139,6 +139,34 @@ pub(crate) fn custom_tap_hold_keys(
     keys_tap_on_press_release: &[OsCode],
     keys_hold_on_press: &[OsCode],
     a: &Allocations,
+) -> &'static CustomTapHoldFn {
+    custom_tap_hold_key_lists(
+        keys_tap_on_press,
+        keys_tap_on_press_release,
+        keys_hold_on_press,
+        OtherKeyDecision::HoldOnPressRelease,
+        a,
+    )
+}
+
+/// What the press of a key that is in none of the key lists of a tap-hold decides.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum OtherKeyDecision {
+    /// Nothing, the timeout decides.
+    Timeout,
+    /// Hold, like `HoldTapConfig::HoldOnOtherKeyPress`.
+    HoldOnPress,
+    /// Hold once the key is also released, like `HoldTapConfig::PermissiveHold`.
+    HoldOnPressRelease,
+}
+
+/// Like `custom_tap_hold_keys`, with the decision for other keys given by `other_keys`.
+pub(crate) fn custom_tap_hold_key_lists(
+    keys_tap_on_press: &[OsCode],
+    keys_tap_on_press_release: &[OsCode],
+    keys_hold_on_press: &[OsCode],
+    other_keys: OtherKeyDecision,
+    a: &Allocations,
 ) -> &'static CustomTapHoldFn {
     let keys_tap_on_press = a.sref_vec(keys_tap_on_press.iter().copied().map(u16::from).collect());
     let keys_tap_on_press_release = a.sref_vec(
@@ -168,17 +196,27 @@ pub(crate) fn custom_tap_hold_keys(
                     }
                     // If key is in tap-on-press-release list and has been released,
                     // trigger tap.
+                    let target = Event::Release(i, j);
                     if keys_tap_on_press_release.iter().copied().any(|j2| j2 == j) {
-                        let target = Event::Release(i, j);
                         if queued.clone().copied().any(|q| q.event() == target) {
                             return (Some(WaitingAction::Tap), false);
                         }
+                        if other_keys != OtherKeyDecision::HoldOnPressRelease {
+                            continue;
+                        }
                     }
-                    // Otherwise do the PermissiveHold algorithm:
-                    // if another key was pressed and released, trigger hold.
-                    let target = Event::Release(i, j);
-                    if queued.clone().copied().any(|q| q.event() == target) {
-                        return (Some(WaitingAction::Hold), false);
+                    match other_keys {
+                        OtherKeyDecision::Timeout => {}
+                        OtherKeyDecision::HoldOnPress => {
+                            return (Some(WaitingAction::Hold), false);
+                        }
+                        // Otherwise do the PermissiveHold algorithm:
+                        // if another key was pressed and released, trigger hold.
+                        OtherKeyDecision::HoldOnPressRelease => {
+                            if queued.clone().copied().any(|q| q.event() == target) {
+                                return (Some(WaitingAction::Hold), false);
+                            }
+                        }
                     }
                 }
             }
@@ -0,0 +1,54 @@
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+use crate::bail_expr;
+
+/// The conversion specifiers of `strftime` that are supported by the `datetime` action.
+const DATETIME_SPECIFIERS: &str = "aAbBhCdeDFGHIjmMnprRsStTuVwyYz%";
+
+pub(crate) fn parse_datetime(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects 1 parameter: <strftime format>";
+    if ac_params.len() != 1 {
+        bail!("{DATETIME} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let format = ac_params[0]
+        .atom(s.vars())
+        .map(|format| format.trim_atom_quotes())
+        .filter(|format| !format.is_empty())
+        .ok_or_else(|| {
+            anyhow_expr!(
+                &ac_params[0],
+                "{DATETIME} format should be a string, e.g. \"%Y-%m-%d\""
+            )
+        })?;
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        match chars.next() {
+            Some(spec) if DATETIME_SPECIFIERS.contains(spec) => {}
+            Some(spec) => bail_expr!(
+                &ac_params[0],
+                "{DATETIME} does not support %{spec}. Supported specifiers: {}",
+                DATETIME_SPECIFIERS
+                    .chars()
+                    .map(|spec| format!("%{spec}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            None => bail_expr!(
+                &ac_params[0],
+                "{DATETIME} format ends with %, use %% for a percent sign"
+            ),
+        }
+    }
+    custom(
+        CustomAction::DateTime(s.a.sref_str(format.to_string())),
+        &s.a,
+    )
+}
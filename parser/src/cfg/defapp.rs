@@ -0,0 +1,169 @@
+//! Parsing of `defapp`, which gives applications a layer and aliases that replace other aliases
+//! while the application is active. The active application is set at runtime, e.g. by a TCP
+//! client that watches the focused window.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+use crate::bail_expr;
+
+pub(crate) const DEFAPP: &str = "defapp";
+
+const DEFAPP_ERR: &str = "defapp expects an application identifier followed by options:\n\
+    layer <layer-name>\n\
+    aliases (<alias-name> <replacement-alias-name> ...)\n\
+    windows-altgr <value of the defcfg option windows-altgr>\n\
+    cmd-env (<variable-name> <value> ...)";
+
+/// An application of `defapp`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct App {
+    /// The identifier that selects the application, e.g. the executable name or the bundle id.
+    /// It is compared without case.
+    pub id: String,
+    /// The layer that is switched to while the application is active.
+    pub layer: Option<u16>,
+    /// The `windows-altgr` of the application instead of the one of `defcfg`.
+    pub windows_altgr: Option<AltGrBehaviour>,
+    /// The environment variables of cmd actions while the application is active.
+    pub cmd_env: CmdEnv,
+}
+
+/// The replacements of aliases in `defapp`, by the name of the replaced alias.
+pub(crate) type AppAliases = HashMap<String, Vec<(&'static str, String)>>;
+
+pub(crate) fn parse_defapps(exprs: &[&Vec<SExpr>], s: &mut ParserState) -> Result<Vec<App>> {
+    let mut apps: Vec<App> = vec![];
+    let mut app_aliases = AppAliases::default();
+    for expr in exprs {
+        let mut subexprs = check_first_expr(expr.iter(), DEFAPP)?;
+        let Some(id_expr) = subexprs.next() else {
+            bail!("{DEFAPP_ERR}\nfound no items");
+        };
+        let id = id_expr
+            .atom(s.vars())
+            .map(|id| id.trim_atom_quotes())
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| {
+                anyhow_expr!(id_expr, "{DEFAPP_ERR}\nThe identifier must be a string")
+            })?;
+        if apps.iter().any(|app| app.id.eq_ignore_ascii_case(id)) {
+            bail_expr!(id_expr, "Duplicate application in defapp: {id}");
+        }
+        let app_id = s.a.sref_str(id.to_owned());
+        let mut layer = None;
+        let mut has_aliases = false;
+        let mut windows_altgr = None;
+        let mut cmd_env = vec![];
+        let mut has_cmd_env = false;
+        while let Some(opt_expr) = subexprs.next() {
+            let Some(val_expr) = subexprs.next() else {
+                bail_expr!(opt_expr, "{DEFAPP_ERR}\nThis option has no value");
+            };
+            match opt_expr.atom(s.vars()) {
+                Some("layer") if layer.is_none() => {
+                    let name = val_expr.atom(s.vars()).ok_or_else(|| {
+                        anyhow_expr!(val_expr, "{DEFAPP_ERR}\nThe layer name must not be a list")
+                    })?;
+                    let idx = s
+                        .layer_idxs
+                        .get(name)
+                        .ok_or_else(|| anyhow_expr!(val_expr, "Unknown layer name: {name}"))?;
+                    layer = Some(*idx as u16);
+                }
+                Some("aliases") if !has_aliases => {
+                    has_aliases = true;
+                    let Some(pairs) = val_expr.list(s.vars()) else {
+                        bail_expr!(val_expr, "{DEFAPP_ERR}\nThe aliases must be a list");
+                    };
+                    if pairs.is_empty() || pairs.len() % 2 != 0 {
+                        bail_expr!(val_expr, "{DEFAPP_ERR}\nThe aliases must be pairs");
+                    }
+                    for pair in pairs.chunks_exact(2) {
+                        let [alias, replacement] = [&pair[0], &pair[1]].map(|expr| {
+                            expr.atom(s.vars())
+                                .map(|name| name.strip_prefix('@').unwrap_or(name))
+                                .ok_or_else(|| {
+                                    anyhow_expr!(
+                                        expr,
+                                        "{DEFAPP_ERR}\nAn alias name must not be a list"
+                                    )
+                                })
+                        });
+                        let (alias, replacement) = (alias?, replacement?);
+                        let replacements = app_aliases.entry(alias.to_owned()).or_default();
+                        if replacements.iter().any(|(id, _)| *id == app_id) {
+                            bail_expr!(&pair[0], "Duplicate alias for this application: {alias}");
+                        }
+                        replacements.push((app_id, replacement.to_owned()));
+                    }
+                }
+                Some(label @ "windows-altgr") if windows_altgr.is_none() => {
+                    windows_altgr = Some(parse_altgr_behaviour(val_expr, label)?);
+                }
+                Some(label @ "cmd-env") if !has_cmd_env => {
+                    has_cmd_env = true;
+                    cmd_env = parse_cmd_env(val_expr, s.vars(), &s.secrets, label)?;
+                }
+                Some("layer" | "aliases" | "windows-altgr" | "cmd-env") => {
+                    bail_expr!(opt_expr, "{DEFAPP_ERR}\nThis option is already set");
+                }
+                _ => bail_expr!(opt_expr, "{DEFAPP_ERR}\nUnknown option"),
+            }
+        }
+        apps.push(App {
+            id: id.to_owned(),
+            layer,
+            windows_altgr,
+            cmd_env,
+        });
+    }
+    s.app_aliases = app_aliases;
+    Ok(apps)
+}
+
+/// Checks that the aliases of `defapp` exist, since used aliases are replaced only when they are
+/// referenced.
+pub(crate) fn check_app_aliases(s: &ParserState) -> Result<()> {
+    for (alias, replacements) in s.app_aliases.iter() {
+        for name in std::iter::once(alias).chain(replacements.iter().map(|(_, name)| name)) {
+            if !s.aliases.contains_key(name) {
+                bail!("{DEFAPP} uses the unknown alias {name}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the action for an alias that `defapp` replaces for some applications, which presses
+/// the alias of the active application.
+pub(crate) fn app_alias_action(
+    alias: &str,
+    action: &'static KanataAction,
+    replacements: &[(&'static str, String)],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    if let Some(reason) = s.alias_concat_keys_placed.get() {
+        bail!("aliases that defapp replaces cannot be used {reason}");
+    }
+    let default = alias_key_coord(alias, action, s);
+    let replacements = replacements
+        .iter()
+        .map(|(app, name)| {
+            let Some(replacement) = s.aliases.get(name) else {
+                bail!(
+                    "{DEFAPP} replaces {alias} with {name}, which must be defined before @{alias} is used"
+                );
+            };
+            Ok((*app, alias_key_coord(name, replacement, s)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    custom(
+        CustomAction::AppAlias(s.a.sref(AppAlias {
+            default,
+            replacements: s.a.sref_vec(replacements),
+        })),
+        &s.a,
+    )
+}
@@ -1,5 +1,7 @@
 use super::HashSet;
+use super::cmd::{CmdAllowlist, parse_cmd_allowlist};
 use super::sexpr::SExpr;
+use super::{LAYOUT_TRANSLATION_NAMES, LayoutTranslation, layout_translation};
 use super::{TrimAtomQuotes, error::*};
 use crate::cfg::check_first_expr;
 use crate::custom_action::*;
@@ -32,9 +34,27 @@ pub struct CfgLinuxOptions {
     pub linux_unicode_termination: UnicodeTermination,
     pub linux_x11_repeat_delay_rate: Option<KeyRepeatSettings>,
     pub linux_use_trackpoint_property: bool,
+    /// Whether `setmouse` and `mouse-grid` use an absolute pointer device next to the output
+    /// device.
+    pub linux_output_absolute_pointer: bool,
     pub linux_output_name: String,
     pub linux_output_bus_type: LinuxCfgOutputBusType,
+    pub linux_output_vendor_id: u16,
+    pub linux_output_product_id: u16,
+    pub linux_output_version: u16,
+    pub linux_output_backend: LinuxCfgOutputBackend,
+    pub linux_input_backend: LinuxCfgInputBackend,
+    /// The `output:` listener of the kanata that `linux-output-backend remote` sends to.
+    pub linux_output_remote_address: Option<String>,
+    pub linux_output_remote_token_file: Option<String>,
     pub linux_device_detect_mode: Option<DeviceDetectMode>,
+    /// Only the devices of this logind seat are discovered.
+    pub linux_seat: Option<String>,
+    /// The lock LEDs that the input devices show while a layer is active, by layer name.
+    pub linux_layer_leds: Vec<(String, LockLeds)>,
+    /// The names of the output devices that the output for the input devices of
+    /// `definputdevices` goes to, by device ID.
+    pub linux_output_device_routes: Vec<(std::num::NonZeroU8, String)>,
 }
 #[cfg(any(target_os = "linux", target_os = "android", target_os = "unknown"))]
 impl Default for CfgLinuxOptions {
@@ -50,18 +70,56 @@ impl Default for CfgLinuxOptions {
             linux_unicode_termination: UnicodeTermination::Enter,
             linux_x11_repeat_delay_rate: None,
             linux_use_trackpoint_property: false,
+            linux_output_absolute_pointer: false,
             linux_output_name: "kanata".to_owned(),
             linux_output_bus_type: LinuxCfgOutputBusType::BusI8042,
+            linux_output_vendor_id: 1,
+            linux_output_product_id: 1,
+            linux_output_version: 1,
+            linux_output_backend: LinuxCfgOutputBackend::Uinput,
+            linux_input_backend: LinuxCfgInputBackend::Evdev,
+            linux_output_remote_address: None,
+            linux_output_remote_token_file: None,
             linux_device_detect_mode: None,
+            linux_seat: None,
+            linux_layer_leds: vec![],
+            linux_output_device_routes: vec![],
         }
     }
 }
+/// A pattern of the lock LEDs of a keyboard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockLeds {
+    pub caps: bool,
+    pub num: bool,
+    pub scroll: bool,
+}
 #[cfg(any(target_os = "linux", target_os = "android", target_os = "unknown"))]
 #[derive(Debug, Clone, Copy)]
 pub enum LinuxCfgOutputBusType {
     BusUsb,
     BusI8042,
     BusVirtual,
+    BusBluetooth,
+}
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "unknown"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinuxCfgOutputBackend {
+    /// A virtual device created through `/dev/uinput`.
+    Uinput,
+    /// The XTEST extension of the X server.
+    #[cfg(feature = "x11_xtest")]
+    Xtest,
+    /// Another kanata over the network, which injects the events on its computer.
+    Remote,
+}
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "unknown"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinuxCfgInputBackend {
+    /// The evdev devices in `/dev/input`, which kanata opens and grabs itself.
+    Evdev,
+    /// The devices that libinput opens for kanata, with the device quirks of libinput.
+    Libinput,
 }
 
 #[cfg(any(target_os = "macos", target_os = "unknown"))]
@@ -88,6 +146,9 @@ pub struct CfgWinterceptOptions {
 pub struct CfgWindowsOptions {
     pub windows_altgr: AltGrBehaviour,
     pub sync_keystates: bool,
+    /// The scancodes that `SendInput` sends for the keys instead of the ones from the virtual
+    /// keys. Extended scancodes have the `0xE0` prefix, e.g. `0xE038` for right alt.
+    pub scancode_overrides: Vec<(OsCode, u16)>,
 }
 
 #[cfg(all(any(target_os = "windows", target_os = "unknown"), feature = "gui"))]
@@ -136,26 +197,64 @@ impl Default for CfgOptionsGui {
 pub struct CfgOptions {
     pub process_unmapped_keys: bool,
     pub process_unmapped_keys_exceptions: Option<Vec<(OsCode, SExpr)>>,
+    /// Process the other mouse buttons with `process-unmapped-keys` when a mouse button is in
+    /// defsrc.
+    pub process_unmapped_mouse_buttons: bool,
     pub block_unmapped_keys: bool,
     pub allow_hardware_repeat: bool,
+    pub realtime_priority: bool,
     pub start_alias: Option<String>,
     pub enable_cmd: bool,
+    /// The only commands that cmd actions may run, see [`CmdAllowlist`].
+    pub cmd_allowlist: Option<CmdAllowlist>,
+    /// Milliseconds after which the command of a cmd action is killed, or 0 to let it run.
+    pub cmd_timeout: u32,
+    pub enable_secrets: bool,
     pub sequence_timeout: u16,
     pub sequence_input_mode: SequenceInputMode,
     pub sequence_backtrack_modcancel: bool,
     pub sequence_always_on: bool,
     pub log_layer_changes: bool,
     pub delegate_to_first_layer: bool,
+    pub layout_translation: Option<LayoutTranslation>,
     pub movemouse_inherit_accel_state: bool,
     pub movemouse_smooth_diagonals: bool,
     pub override_release_on_activation: bool,
+    pub preserve_release_order: bool,
     pub dynamic_macro_max_presses: u16,
     pub dynamic_macro_replay_delay_behaviour: ReplayDelayBehaviour,
     pub concurrent_tap_hold: bool,
     pub rapid_event_delay: u16,
+    /// Milliseconds after which an output key that is held without its physical key is
+    /// released. 0 disables it.
+    pub stuck_key_timeout: u16,
+    /// Milliseconds within which a repeated press, release or repeat of a key is dropped as a
+    /// duplicate of the device's event. 0 disables it.
+    pub dedupe_input_ms: u16,
     pub trans_resolution_behavior_v2: bool,
     pub chords_v2_min_idle: u16,
     pub tap_hold_require_prior_idle: u16,
+    /// Changes to the hold timeout of tap-hold actions on specific keys.
+    pub tap_hold_key_timeouts: Vec<(OsCode, KeyTimeout)>,
+    pub midi_output_port: Option<String>,
+    pub obs_websocket_address: Option<String>,
+    pub obs_websocket_password: Option<String>,
+    /// The address of the OpenRGB SDK server.
+    pub openrgb_address: Option<String>,
+    /// Only the OpenRGB devices whose name contains this are changed.
+    pub openrgb_device: Option<String>,
+    /// Whether entering a layer sets the OpenRGB color to the `color` of the layer.
+    pub openrgb_layer_colors: bool,
+    pub sound_layer_change: Option<SoundCue>,
+    pub sound_caps_word: Option<SoundCue>,
+    pub sound_sequence_timeout: Option<SoundCue>,
+    pub emergency_exit_chord: Option<Vec<OsCode>>,
+    pub emergency_passthrough_chord: Option<Vec<OsCode>>,
+    pub emergency_reengage_chord: Option<Vec<OsCode>>,
+    /// The configuration that kanata starts with when this one cannot be used.
+    pub fallback_cfg: Option<String>,
+    /// The names of the options given in `defcfg`, in the order they were given.
+    pub defined_options: Vec<String>,
     #[cfg(any(
         all(target_os = "windows", feature = "interception_driver"),
         target_os = "linux",
@@ -184,26 +283,54 @@ impl Default for CfgOptions {
         Self {
             process_unmapped_keys: false,
             process_unmapped_keys_exceptions: None,
+            process_unmapped_mouse_buttons: false,
             block_unmapped_keys: false,
             allow_hardware_repeat: true,
+            realtime_priority: false,
             start_alias: None,
             enable_cmd: false,
+            cmd_allowlist: None,
+            cmd_timeout: 0,
+            enable_secrets: false,
             sequence_timeout: 1000,
             sequence_input_mode: SequenceInputMode::HiddenSuppressed,
             sequence_backtrack_modcancel: true,
             sequence_always_on: false,
             log_layer_changes: true,
             delegate_to_first_layer: false,
+            layout_translation: None,
             movemouse_inherit_accel_state: false,
             movemouse_smooth_diagonals: false,
             override_release_on_activation: false,
+            preserve_release_order: false,
             dynamic_macro_max_presses: 128,
             dynamic_macro_replay_delay_behaviour: ReplayDelayBehaviour::Recorded,
             concurrent_tap_hold: false,
             rapid_event_delay: 5,
+            stuck_key_timeout: 0,
+            dedupe_input_ms: 0,
             trans_resolution_behavior_v2: true,
             chords_v2_min_idle: 5,
             tap_hold_require_prior_idle: 0,
+            tap_hold_key_timeouts: vec![],
+            midi_output_port: None,
+            obs_websocket_address: None,
+            obs_websocket_password: None,
+            openrgb_address: None,
+            openrgb_device: None,
+            openrgb_layer_colors: false,
+            sound_layer_change: None,
+            sound_caps_word: None,
+            sound_sequence_timeout: None,
+            emergency_exit_chord: Some(vec![
+                OsCode::KEY_LEFTCTRL,
+                OsCode::KEY_SPACE,
+                OsCode::KEY_ESC,
+            ]),
+            emergency_passthrough_chord: None,
+            emergency_reengage_chord: None,
+            fallback_cfg: None,
+            defined_options: vec![],
             #[cfg(any(
                 all(target_os = "windows", feature = "interception_driver"),
                 target_os = "linux",
@@ -245,6 +372,28 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                         "The item process-unmapped-keys is not defined in defcfg. Consider whether process-unmapped-keys should be yes vs. no."
                     );
                 }
+                if cfg.emergency_reengage_chord.is_some()
+                    && cfg.emergency_passthrough_chord.is_none()
+                {
+                    log::warn!(
+                        "emergency-reengage-chord has no effect without emergency-passthrough-chord"
+                    );
+                }
+                #[cfg(any(target_os = "linux", target_os = "android", target_os = "unknown"))]
+                if cfg.linux_opts.linux_output_backend == LinuxCfgOutputBackend::Remote
+                    && (cfg.linux_opts.linux_output_remote_address.is_none()
+                        || cfg.linux_opts.linux_output_remote_token_file.is_none())
+                {
+                    bail!(
+                        "linux-output-backend remote needs linux-output-remote-address and linux-output-remote-token-file"
+                    );
+                }
+                #[cfg(any(target_os = "linux", target_os = "android", target_os = "unknown"))]
+                if cfg.linux_opts.linux_output_backend != LinuxCfgOutputBackend::Uinput
+                    && !cfg.linux_opts.linux_output_device_routes.is_empty()
+                {
+                    bail!("linux-output-device-routes needs linux-output-backend uinput");
+                }
                 return Ok(cfg);
             }
         };
@@ -258,6 +407,7 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                 if !seen_keys.insert(label) {
                     bail_expr!(key, "Duplicate defcfg option {}", label);
                 }
+                cfg.defined_options.push(label.to_owned());
                 match label {
                     "sequence-timeout" => {
                         cfg.sequence_timeout = parse_cfg_val_u16(val, label, true)?;
@@ -399,6 +549,17 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                                 parse_defcfg_val_bool(val, label)?
                         }
                     }
+                    "linux-output-absolute-pointer" => {
+                        #[cfg(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        ))]
+                        {
+                            cfg.linux_opts.linux_output_absolute_pointer =
+                                parse_defcfg_val_bool(val, label)?
+                        }
+                    }
                     "linux-output-device-name" => {
                         #[cfg(any(
                             target_os = "linux",
@@ -419,10 +580,10 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                     "linux-output-device-bus-type" => {
                         let bus_type = sexpr_to_str_or_err(val, label)?;
                         match bus_type {
-                            "USB" | "I8042" | "virtual" => {}
+                            "USB" | "I8042" | "virtual" | "bluetooth" => {}
                             _ => bail_expr!(
                                 val,
-                                "Invalid value for linux-output-device-bus-type.\nExpected one of: USB | I8042 | virtual"
+                                "Invalid value for linux-output-device-bus-type.\nExpected one of: USB | I8042 | virtual | bluetooth"
                             ),
                         };
                         #[cfg(any(
@@ -435,11 +596,169 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                                 "USB" => LinuxCfgOutputBusType::BusUsb,
                                 "I8042" => LinuxCfgOutputBusType::BusI8042,
                                 "virtual" => LinuxCfgOutputBusType::BusVirtual,
+                                "bluetooth" => LinuxCfgOutputBusType::BusBluetooth,
                                 _ => unreachable!("validated earlier"),
                             };
                             cfg.linux_opts.linux_output_bus_type = bus_type;
                         }
                     }
+                    "linux-output-device-vendor-id"
+                    | "linux-output-device-product-id"
+                    | "linux-output-device-version" => {
+                        let id = parse_defcfg_device_id(val, label)?;
+                        #[cfg(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        ))]
+                        {
+                            let field = match label {
+                                "linux-output-device-vendor-id" => {
+                                    &mut cfg.linux_opts.linux_output_vendor_id
+                                }
+                                "linux-output-device-product-id" => {
+                                    &mut cfg.linux_opts.linux_output_product_id
+                                }
+                                _ => &mut cfg.linux_opts.linux_output_version,
+                            };
+                            *field = id;
+                        }
+                        #[cfg(not(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        )))]
+                        let _ = id;
+                    }
+                    "linux-output-backend" => {
+                        let backend = sexpr_to_str_or_err(val, label)?;
+                        match backend {
+                            "uinput" | "xtest" | "remote" => {}
+                            _ => bail_expr!(
+                                val,
+                                "Invalid value for linux-output-backend.\nExpected one of: uinput | xtest | remote"
+                            ),
+                        };
+                        #[cfg(not(feature = "x11_xtest"))]
+                        if backend == "xtest" {
+                            bail_expr!(
+                                val,
+                                "Kanata was not compiled with the \"x11_xtest\" feature.\nThe xtest output backend is unsupported"
+                            );
+                        }
+                        #[cfg(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        ))]
+                        {
+                            cfg.linux_opts.linux_output_backend = match backend {
+                                "uinput" => LinuxCfgOutputBackend::Uinput,
+                                #[cfg(feature = "x11_xtest")]
+                                "xtest" => LinuxCfgOutputBackend::Xtest,
+                                "remote" => LinuxCfgOutputBackend::Remote,
+                                _ => unreachable!("validated earlier"),
+                            };
+                        }
+                    }
+                    "linux-input-backend" => {
+                        let backend = sexpr_to_str_or_err(val, label)?;
+                        match backend {
+                            "evdev" | "libinput" => {}
+                            _ => bail_expr!(
+                                val,
+                                "Invalid value for linux-input-backend.\nExpected one of: evdev | libinput"
+                            ),
+                        };
+                        #[cfg(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        ))]
+                        {
+                            cfg.linux_opts.linux_input_backend = match backend {
+                                "evdev" => LinuxCfgInputBackend::Evdev,
+                                "libinput" => LinuxCfgInputBackend::Libinput,
+                                _ => unreachable!("validated earlier"),
+                            };
+                        }
+                    }
+                    "linux-output-remote-address" => {
+                        let address = sexpr_to_str_or_err(val, label)?;
+                        if address.is_empty() {
+                            bail_expr!(val, "linux-output-remote-address must not be empty");
+                        }
+                        #[cfg(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        ))]
+                        {
+                            cfg.linux_opts.linux_output_remote_address = Some(address.to_owned());
+                        }
+                    }
+                    "linux-output-remote-token-file" => {
+                        let path = sexpr_to_str_or_err(val, label)?;
+                        if path.is_empty() {
+                            bail_expr!(val, "linux-output-remote-token-file must not be empty");
+                        }
+                        #[cfg(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        ))]
+                        {
+                            cfg.linux_opts.linux_output_remote_token_file = Some(path.to_owned());
+                        }
+                    }
+                    "linux-seat" => {
+                        let seat = sexpr_to_str_or_err(val, label)?;
+                        if seat.is_empty() {
+                            bail_expr!(val, "linux-seat must not be empty");
+                        }
+                        #[cfg(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        ))]
+                        {
+                            cfg.linux_opts.linux_seat = Some(seat.to_owned());
+                        }
+                    }
+                    "linux-layer-leds" => {
+                        let layer_leds = parse_defcfg_layer_leds(val, label)?;
+                        #[cfg(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        ))]
+                        {
+                            cfg.linux_opts.linux_layer_leds = layer_leds;
+                        }
+                        #[cfg(not(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        )))]
+                        let _ = layer_leds;
+                    }
+                    "linux-output-device-routes" => {
+                        let routes = parse_defcfg_output_device_routes(val, label)?;
+                        #[cfg(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        ))]
+                        {
+                            cfg.linux_opts.linux_output_device_routes = routes;
+                        }
+                        #[cfg(not(any(
+                            target_os = "linux",
+                            target_os = "android",
+                            target_os = "unknown"
+                        )))]
+                        let _ = routes;
+                    }
                     "linux-device-detect-mode" => {
                         let detect_mode = sexpr_to_str_or_err(val, label)?;
                         match detect_mode {
@@ -465,23 +784,22 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                         }
                     }
                     "windows-altgr" => {
+                        let altgr = parse_altgr_behaviour(val, label)?;
                         #[cfg(any(target_os = "windows", target_os = "unknown"))]
                         {
-                            const CANCEL: &str = "cancel-lctl-press";
-                            const ADD: &str = "add-lctl-release";
-                            let v = sexpr_to_str_or_err(val, label)?;
-                            cfg.windows_opts.windows_altgr = match v {
-                                CANCEL => AltGrBehaviour::CancelLctlPress,
-                                ADD => AltGrBehaviour::AddLctlRelease,
-                                _ => bail_expr!(
-                                    val,
-                                    "Invalid value for {label}: {}. Valid values are {},{}",
-                                    v,
-                                    CANCEL,
-                                    ADD
-                                ),
-                            }
+                            cfg.windows_opts.windows_altgr = altgr;
+                        }
+                        #[cfg(not(any(target_os = "windows", target_os = "unknown")))]
+                        let _ = altgr;
+                    }
+                    "windows-scancode-overrides" => {
+                        let overrides = parse_defcfg_scancode_overrides(val, label)?;
+                        #[cfg(any(target_os = "windows", target_os = "unknown"))]
+                        {
+                            cfg.windows_opts.scancode_overrides = overrides;
                         }
+                        #[cfg(not(any(target_os = "windows", target_os = "unknown")))]
+                        let _ = overrides;
                     }
                     "windows-sync-keystates" => {
                         #[cfg(any(target_os = "windows", target_os = "unknown"))]
@@ -825,16 +1143,27 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                         }
                     }
 
+                    "process-unmapped-mouse-buttons" => {
+                        cfg.process_unmapped_mouse_buttons = parse_defcfg_val_bool(val, label)?
+                    }
                     "block-unmapped-keys" => {
                         cfg.block_unmapped_keys = parse_defcfg_val_bool(val, label)?
                     }
                     "allow-hardware-repeat" => {
                         cfg.allow_hardware_repeat = parse_defcfg_val_bool(val, label)?
                     }
+                    "realtime-priority" => {
+                        cfg.realtime_priority = parse_defcfg_val_bool(val, label)?
+                    }
                     "alias-to-trigger-on-load" => {
                         cfg.start_alias = parse_defcfg_val_string(val, label)?
                     }
                     "danger-enable-cmd" => cfg.enable_cmd = parse_defcfg_val_bool(val, label)?,
+                    "cmd-allowlist" => cfg.cmd_allowlist = Some(parse_cmd_allowlist(val, label)?),
+                    "cmd-timeout" => cfg.cmd_timeout = parse_cfg_val_u32(val, label)?,
+                    "danger-enable-secrets" => {
+                        cfg.enable_secrets = parse_defcfg_val_bool(val, label)?
+                    }
                     "sequence-backtrack-modcancel" => {
                         cfg.sequence_backtrack_modcancel = parse_defcfg_val_bool(val, label)?
                     }
@@ -849,6 +1178,15 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                             );
                         }
                     }
+                    "layout-translation" => {
+                        cfg.layout_translation =
+                            Some(val.atom(None).and_then(layout_translation).ok_or_else(|| {
+                                anyhow_expr!(
+                                    val,
+                                    "this option must be one of: {LAYOUT_TRANSLATION_NAMES}"
+                                )
+                            })?);
+                    }
                     "linux-continue-if-no-devs-found" => {
                         #[cfg(any(
                             target_os = "linux",
@@ -869,12 +1207,21 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                     "override-release-on-activation" => {
                         cfg.override_release_on_activation = parse_defcfg_val_bool(val, label)?
                     }
+                    "preserve-release-order" => {
+                        cfg.preserve_release_order = parse_defcfg_val_bool(val, label)?
+                    }
                     "concurrent-tap-hold" => {
                         cfg.concurrent_tap_hold = parse_defcfg_val_bool(val, label)?
                     }
                     "rapid-event-delay" => {
                         cfg.rapid_event_delay = parse_cfg_val_u16(val, label, false)?
                     }
+                    "stuck-key-timeout" => {
+                        cfg.stuck_key_timeout = parse_cfg_val_u16(val, label, false)?
+                    }
+                    "dedupe-input-ms" => {
+                        cfg.dedupe_input_ms = parse_cfg_val_u16(val, label, false)?
+                    }
                     "transparent-key-resolution" => {
                         let v = sexpr_to_str_or_err(val, label)?;
                         cfg.trans_resolution_behavior_v2 = match v {
@@ -903,6 +1250,72 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                     "tap-hold-require-prior-idle" => {
                         cfg.tap_hold_require_prior_idle = parse_cfg_val_u16(val, label, false)?;
                     }
+                    "tap-hold-key-timeouts" => {
+                        cfg.tap_hold_key_timeouts = parse_defcfg_key_timeouts(val, label)?;
+                    }
+                    "midi-output-port" => {
+                        let port = sexpr_to_str_or_err(val, label)?;
+                        if port.is_empty() {
+                            bail_expr!(val, "{label} cannot be empty");
+                        }
+                        cfg.midi_output_port = Some(port.to_string());
+                    }
+                    "obs-websocket-address" => {
+                        let address = sexpr_to_str_or_err(val, label)?;
+                        if address.is_empty() {
+                            bail_expr!(val, "{label} cannot be empty");
+                        }
+                        cfg.obs_websocket_address = Some(address.to_string());
+                    }
+                    "obs-websocket-password" => {
+                        cfg.obs_websocket_password =
+                            Some(sexpr_to_str_or_err(val, label)?.to_string());
+                    }
+                    "openrgb-address" => {
+                        let address = sexpr_to_str_or_err(val, label)?;
+                        if address.is_empty() {
+                            bail_expr!(val, "{label} cannot be empty");
+                        }
+                        cfg.openrgb_address = Some(address.to_string());
+                    }
+                    "openrgb-device" => {
+                        let device = sexpr_to_str_or_err(val, label)?;
+                        if device.is_empty() {
+                            bail_expr!(val, "{label} cannot be empty");
+                        }
+                        cfg.openrgb_device = Some(device.to_string());
+                    }
+                    "openrgb-layer-colors" => {
+                        cfg.openrgb_layer_colors = parse_defcfg_val_bool(val, label)?;
+                    }
+                    "sound-layer-change" => {
+                        cfg.sound_layer_change = parse_defcfg_sound(val, label)?;
+                    }
+                    "sound-caps-word" => {
+                        cfg.sound_caps_word = parse_defcfg_sound(val, label)?;
+                    }
+                    "sound-sequence-timeout" => {
+                        cfg.sound_sequence_timeout = parse_defcfg_sound(val, label)?;
+                    }
+                    "emergency-exit-chord" => {
+                        cfg.emergency_exit_chord = match val.atom(None) {
+                            Some("none") => None,
+                            _ => Some(parse_defcfg_chord(val, label)?),
+                        };
+                    }
+                    "emergency-passthrough-chord" => {
+                        cfg.emergency_passthrough_chord = Some(parse_defcfg_chord(val, label)?);
+                    }
+                    "emergency-reengage-chord" => {
+                        cfg.emergency_reengage_chord = Some(parse_defcfg_chord(val, label)?);
+                    }
+                    "fallback-cfg" => {
+                        let path = sexpr_to_str_or_err(val, label)?;
+                        if path.is_empty() {
+                            bail_expr!(val, "{label} cannot be empty");
+                        }
+                        cfg.fallback_cfg = Some(path.to_string());
+                    }
                     "mouse-movement-key" => {
                         #[cfg(any(
                             all(target_os = "windows", feature = "interception_driver"),
@@ -944,7 +1357,7 @@ pub const FALSE_VALUES: [&str; 3] = ["no", "false", "0"];
 pub const TRUE_VALUES: [&str; 3] = ["yes", "true", "1"];
 pub const BOOLEAN_VALUES: [&str; 6] = ["yes", "true", "1", "no", "false", "0"];
 
-fn parse_defcfg_val_bool(expr: &SExpr, label: &str) -> Result<bool> {
+pub(crate) fn parse_defcfg_val_bool(expr: &SExpr, label: &str) -> Result<bool> {
     match &expr {
         SExpr::Atom(v) => {
             let val = v.t.trim_atom_quotes().to_ascii_lowercase();
@@ -970,6 +1383,214 @@ fn parse_defcfg_val_bool(expr: &SExpr, label: &str) -> Result<bool> {
     }
 }
 
+fn parse_defcfg_sound(expr: &SExpr, label: &str) -> Result<Option<SoundCue>> {
+    let sound = sexpr_to_str_or_err(expr, label)?;
+    if sound.is_empty() {
+        bail_expr!(expr, "{label} cannot be empty");
+    }
+    Ok(Some(SoundCue::from_cfg_str(sound)))
+}
+
+fn parse_defcfg_chord(expr: &SExpr, label: &str) -> Result<Vec<OsCode>> {
+    let err = "Expected a list of two or more key names, e.g. (lctl spc esc).";
+    let Some(list) = expr.list(None) else {
+        bail_expr!(expr, "The value for {label} must be a list. {err}");
+    };
+    if list.len() < 2 {
+        bail_expr!(expr, "{err}");
+    }
+    // Note: deflocalkeys should already be parsed when parsing defcfg,
+    // so can use safely use str_to_oscode here.
+    let mut keys = Vec::with_capacity(list.len());
+    for key_expr in list.iter() {
+        let key = key_expr
+            .atom(None)
+            .and_then(str_to_oscode)
+            .ok_or_else(|| anyhow_expr!(key_expr, "Expected a known key name."))?;
+        if keys.contains(&key) {
+            bail_expr!(key_expr, "Duplicate key name is not allowed.");
+        }
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+fn parse_defcfg_layer_leds(expr: &SExpr, label: &str) -> Result<Vec<(String, LockLeds)>> {
+    let err = "Expected pairs of a layer name and the LEDs to light, \
+               which are none or a list of caps, num and scroll, e.g. (nav (caps) mouse (num scroll)).";
+    let Some(list) = expr.list(None) else {
+        bail_expr!(expr, "The value for {label} must be a list. {err}");
+    };
+    if list.len() % 2 != 0 {
+        bail_expr!(expr, "{err}");
+    }
+    let mut layer_leds: Vec<(String, LockLeds)> = Vec::with_capacity(list.len() / 2);
+    for pair in list.chunks_exact(2) {
+        let Some(layer) = pair[0].atom(None) else {
+            bail_expr!(&pair[0], "Expected a layer name. {err}");
+        };
+        if layer_leds.iter().any(|(l, _)| l == layer) {
+            bail_expr!(&pair[0], "Duplicate layer name is not allowed.");
+        }
+        let mut leds = LockLeds::default();
+        match (pair[1].atom(None), pair[1].list(None)) {
+            (Some("none"), _) => {}
+            (_, Some(names)) if !names.is_empty() => {
+                for name in names {
+                    let led = match name.atom(None) {
+                        Some("caps") => &mut leds.caps,
+                        Some("num") => &mut leds.num,
+                        Some("scroll") => &mut leds.scroll,
+                        _ => bail_expr!(name, "Expected caps, num or scroll."),
+                    };
+                    if *led {
+                        bail_expr!(name, "Duplicate LED name is not allowed.");
+                    }
+                    *led = true;
+                }
+            }
+            _ => bail_expr!(&pair[1], "{err}"),
+        }
+        layer_leds.push((layer.to_owned(), leds));
+    }
+    Ok(layer_leds)
+}
+
+fn parse_defcfg_output_device_routes(
+    expr: &SExpr,
+    label: &str,
+) -> Result<Vec<(std::num::NonZeroU8, String)>> {
+    let err = "Expected pairs of a device ID of definputdevices and the name of its output device, \
+               e.g. (2 \"kanata macro pad\").";
+    let Some(list) = expr.list(None) else {
+        bail_expr!(expr, "The value for {label} must be a list. {err}");
+    };
+    if list.len() % 2 != 0 {
+        bail_expr!(expr, "{err}");
+    }
+    let mut routes: Vec<(std::num::NonZeroU8, String)> = Vec::with_capacity(list.len() / 2);
+    for pair in list.chunks_exact(2) {
+        let Some(id) = pair[0]
+            .atom(None)
+            .and_then(|id| id.parse::<std::num::NonZeroU8>().ok())
+        else {
+            bail_expr!(&pair[0], "Expected a device ID (1-255). {err}");
+        };
+        let name = match pair[1].atom(None).map(|name| name.trim_atom_quotes()) {
+            Some(name) if !name.is_empty() => name,
+            _ => bail_expr!(&pair[1], "Expected the name of an output device. {err}"),
+        };
+        if routes.iter().any(|(routed, _)| *routed == id) {
+            bail_expr!(&pair[0], "Duplicate device ID is not allowed.");
+        }
+        if routes.iter().any(|(_, routed)| routed == name) {
+            bail_expr!(&pair[1], "Duplicate output device name is not allowed.");
+        }
+        routes.push((id, name.to_owned()));
+    }
+    Ok(routes)
+}
+
+fn parse_defcfg_scancode_overrides(expr: &SExpr, label: &str) -> Result<Vec<(OsCode, u16)>> {
+    let err = "Expected pairs of a key name and a scancode, \
+               e.g. (nubs 0x56 ralt 0xE038).";
+    let Some(list) = expr.list(None) else {
+        bail_expr!(expr, "The value for {label} must be a list. {err}");
+    };
+    if list.len() % 2 != 0 {
+        bail_expr!(expr, "{err}");
+    }
+    let mut overrides: Vec<(OsCode, u16)> = Vec::with_capacity(list.len() / 2);
+    for pair in list.chunks_exact(2) {
+        let Some(key) = pair[0].atom(None).and_then(str_to_oscode) else {
+            bail_expr!(&pair[0], "Expected a known key name. {err}");
+        };
+        if overrides.iter().any(|(k, _)| *k == key) {
+            bail_expr!(&pair[0], "Duplicate key is not allowed.");
+        }
+        let scancode = parse_defcfg_device_id(&pair[1], label)?;
+        if !matches!(scancode, 0x01..=0xFF | 0xE001..=0xE0FF) {
+            bail_expr!(
+                &pair[1],
+                "The scancode must be 0x01-0xff, or 0xe001-0xe0ff for extended keys."
+            );
+        }
+        overrides.push((key, scancode));
+    }
+    Ok(overrides)
+}
+
+/// A new hold timeout for the tap-hold actions of a key, or a change of their own timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyTimeout {
+    Set(u16),
+    Offset(i32),
+}
+
+impl KeyTimeout {
+    pub fn apply(self, timeout: u16) -> u16 {
+        match self {
+            KeyTimeout::Set(timeout) => timeout,
+            KeyTimeout::Offset(offset) => (i32::from(timeout) + offset).clamp(0, 65535) as u16,
+        }
+    }
+}
+
+fn parse_defcfg_key_timeouts(expr: &SExpr, label: &str) -> Result<Vec<(OsCode, KeyTimeout)>> {
+    let err = "Expected pairs of a key name and a timeout in milliseconds, \
+               with + or - to change the timeout of the action, e.g. (a +60 ; +60 spc 300).";
+    let Some(list) = expr.list(None) else {
+        bail_expr!(expr, "The value for {label} must be a list. {err}");
+    };
+    if list.len() % 2 != 0 {
+        bail_expr!(expr, "{err}");
+    }
+    let mut timeouts: Vec<(OsCode, KeyTimeout)> = Vec::with_capacity(list.len() / 2);
+    for pair in list.chunks_exact(2) {
+        let key = pair[0]
+            .atom(None)
+            .and_then(str_to_oscode)
+            .ok_or_else(|| anyhow_expr!(&pair[0], "Expected a known key name."))?;
+        if timeouts.iter().any(|(k, _)| *k == key) {
+            bail_expr!(&pair[0], "Duplicate key name is not allowed.");
+        }
+        let timeout = pair[1].atom(None).and_then(|timeout| {
+            if timeout.starts_with(['+', '-']) {
+                timeout
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|offset| offset.abs() <= 65535)
+                    .map(KeyTimeout::Offset)
+            } else {
+                timeout.parse::<u16>().ok().map(KeyTimeout::Set)
+            }
+        });
+        let Some(timeout) = timeout else {
+            bail_expr!(
+                &pair[1],
+                "Expected a timeout of 0-65535 with an optional + or -."
+            );
+        };
+        timeouts.push((key, timeout));
+    }
+    Ok(timeouts)
+}
+
+/// Parses a USB-style identifier of a device, in hexadecimal with a leading `0x` or in decimal.
+fn parse_defcfg_device_id(expr: &SExpr, label: &str) -> Result<u16> {
+    let id = sexpr_to_str_or_err(expr, label)?;
+    let parsed = match id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => id.parse::<u16>(),
+    };
+    parsed.map_err(|_| {
+        anyhow_expr!(
+            expr,
+            "{label} must be 0-65535, or 0x0000-0xffff in hexadecimal"
+        )
+    })
+}
+
 fn parse_cfg_val_u16(expr: &SExpr, label: &str, exclude_zero: bool) -> Result<u16> {
     let start = if exclude_zero { 1 } else { 0 };
     match &expr {
@@ -992,6 +1613,20 @@ fn parse_cfg_val_u16(expr: &SExpr, label: &str, exclude_zero: bool) -> Result<u1
     }
 }
 
+fn parse_cfg_val_u32(expr: &SExpr, label: &str) -> Result<u32> {
+    match &expr {
+        SExpr::Atom(v) => Ok(str::parse::<u32>(v.t.trim_atom_quotes())
+            .map_err(|_| anyhow_expr!(expr, "{label} must be 0-{}", u32::MAX))?),
+        SExpr::List(_) => {
+            bail_expr!(
+                expr,
+                "The value for {label} cannot be a list, it must be a number 0-{}",
+                u32::MAX
+            )
+        }
+    }
+}
+
 pub fn parse_colon_separated_text(paths: &str) -> Vec<String> {
     let mut all_paths = vec![];
     let mut full_dev_path = String::new();
@@ -1122,13 +1757,36 @@ pub enum UnicodeTermination {
     EnterSpace,
 }
 
-#[cfg(any(target_os = "windows", target_os = "unknown"))]
+/// What kanata does with the `lctl` press that Windows sends with AltGr on layouts that have it.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum AltGrBehaviour {
     #[default]
     DoNothing,
+    /// AltGr is a separate right alt.
     CancelLctlPress,
+    /// AltGr is ctrl+alt, whose lctl is released with it.
     AddLctlRelease,
+    /// `CancelLctlPress` while the OS layout has AltGr, `DoNothing` otherwise.
+    LayoutAware,
+}
+
+/// Parses a value of `windows-altgr`.
+pub(crate) fn parse_altgr_behaviour(expr: &SExpr, label: &str) -> Result<AltGrBehaviour> {
+    const VALUES: [(&str, AltGrBehaviour); 4] = [
+        ("do-nothing", AltGrBehaviour::DoNothing),
+        ("cancel-lctl-press", AltGrBehaviour::CancelLctlPress),
+        ("add-lctl-release", AltGrBehaviour::AddLctlRelease),
+        ("layout-aware", AltGrBehaviour::LayoutAware),
+    ];
+    let v = sexpr_to_str_or_err(expr, label)?;
+    match VALUES.iter().find(|(name, _)| *name == v) {
+        Some((_, behaviour)) => Ok(*behaviour),
+        None => bail_expr!(
+            expr,
+            "Invalid value for {label}: {v}. Valid values are {}",
+            VALUES.map(|(name, _)| name).join(",")
+        ),
+    }
 }
 
 #[cfg(any(target_os = "windows", target_os = "unknown"))]
@@ -1148,3 +1806,24 @@ pub enum ReplayDelayBehaviour {
     /// This is newer behaviour.
     Recorded,
 }
+
+/// A sound that kanata plays as feedback for an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SoundCue {
+    /// Play nothing. Used to silence a layer when `sound-layer-change` is set.
+    Silent,
+    /// The system beep or alert sound.
+    Beep,
+    /// Path to a sound file, e.g. a wav file.
+    File(String),
+}
+
+impl SoundCue {
+    pub(crate) fn from_cfg_str(s: &str) -> Self {
+        match s {
+            "none" => Self::Silent,
+            "beep" => Self::Beep,
+            path => Self::File(path.to_owned()),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Parsing of `defdebounce`, which filters the chatter of worn switches out of the input before
+//! it reaches the layout, where a chattering key would otherwise be pressed more than once.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail_expr;
+
+use std::num::NonZeroU8;
+
+pub(crate) const DEFDEBOUNCE: &str = "defdebounce";
+
+const DEFDEBOUNCE_ERR: &str = "defdebounce expects options:\n\
+    mode <eager | defer>\n\
+    ms <milliseconds>\n\
+    keys (<key | (key ...)> <milliseconds> ...)\n\
+    device <definputdevices ID>";
+
+/// When a change of a debounced key is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebounceMode {
+    /// A change is used at once and further changes are ignored for the debounce time.
+    #[default]
+    Eager,
+    /// A change is used once the key did not change again for the debounce time.
+    Defer,
+}
+
+/// The debouncing of the keys of a `defdebounce`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Debounce {
+    /// The `definputdevices` ID of the device that this applies to, or None for every device
+    /// without its own `defdebounce`.
+    pub device: Option<NonZeroU8>,
+    pub mode: DebounceMode,
+    /// The debounce time of keys that don't have their own.
+    pub ms: u16,
+    /// The debounce time of specific keys, 0 if they are not debounced.
+    pub key_ms: HashMap<OsCode, u16>,
+}
+
+impl Debounce {
+    /// Returns the debounce time of the key, 0 if it is not debounced.
+    pub fn ms_of(&self, osc: OsCode) -> u16 {
+        self.key_ms.get(&osc).copied().unwrap_or(self.ms)
+    }
+}
+
+pub(crate) fn parse_defdebounces(
+    exprs: &[&Vec<SExpr>],
+    input_devices: Option<&[(NonZeroU8, InputDeviceMatcher)]>,
+    s: &ParserState,
+) -> Result<Vec<Debounce>> {
+    let mut debounces: Vec<Debounce> = vec![];
+    for expr in exprs {
+        let mut subexprs = check_first_expr(expr.iter(), DEFDEBOUNCE)?;
+        let mut debounce = Debounce {
+            device: None,
+            mode: DebounceMode::default(),
+            ms: 5,
+            key_ms: HashMap::default(),
+        };
+        let mut seen_options: Vec<&str> = vec![];
+        while let Some(opt_expr) = subexprs.next() {
+            let Some(val_expr) = subexprs.next() else {
+                bail_expr!(opt_expr, "{DEFDEBOUNCE_ERR}\nThis option has no value");
+            };
+            let Some(opt) = opt_expr.atom(s.vars()) else {
+                bail_expr!(opt_expr, "{DEFDEBOUNCE_ERR}\nAn option must be a string");
+            };
+            if seen_options.contains(&opt) {
+                bail_expr!(opt_expr, "This option is already defined");
+            }
+            match opt {
+                "mode" => {
+                    debounce.mode = match val_expr.atom(s.vars()) {
+                        Some("eager") => DebounceMode::Eager,
+                        Some("defer") => DebounceMode::Defer,
+                        _ => bail_expr!(val_expr, "mode must be eager or defer"),
+                    };
+                }
+                "ms" => debounce.ms = parse_u16(val_expr, s, "debounce ms")?,
+                "keys" => debounce.key_ms = parse_debounce_keys(val_expr, s)?,
+                "device" => {
+                    let id = val_expr
+                        .atom(s.vars())
+                        .and_then(|id| id.parse().ok())
+                        .and_then(NonZeroU8::new)
+                        .ok_or_else(|| anyhow_expr!(val_expr, "device ID must be 1-255"))?;
+                    if !input_devices.is_some_and(|devices| devices.iter().any(|(d, _)| *d == id)) {
+                        bail_expr!(val_expr, "device ID is not defined in definputdevices");
+                    }
+                    debounce.device = Some(id);
+                }
+                _ => bail_expr!(opt_expr, "{DEFDEBOUNCE_ERR}\nUnknown option"),
+            }
+            seen_options.push(opt);
+        }
+        if debounces.iter().any(|d| d.device == debounce.device) {
+            bail_expr!(
+                &expr[0],
+                "{DEFDEBOUNCE} already exists for this device; combine them into one"
+            );
+        }
+        debounces.push(debounce);
+    }
+    Ok(debounces)
+}
+
+fn parse_debounce_keys(expr: &SExpr, s: &ParserState) -> Result<HashMap<OsCode, u16>> {
+    let Some(items) = expr.list(s.vars()) else {
+        bail_expr!(expr, "{DEFDEBOUNCE_ERR}\nkeys must be a list");
+    };
+    let mut key_ms = HashMap::default();
+    let mut items = items.iter();
+    while let Some(keys_expr) = items.next() {
+        let Some(ms_expr) = items.next() else {
+            bail_expr!(keys_expr, "missing debounce ms for the key(s)");
+        };
+        let oscs = match keys_expr.atom(s.vars()) {
+            Some(key) => vec![
+                str_to_oscode(key)
+                    .ok_or_else(|| anyhow_expr!(keys_expr, "string of a known key is expected"))?,
+            ],
+            None => parse_key_list(keys_expr, s, "debounce keys")?,
+        };
+        let ms = parse_u16(ms_expr, s, "debounce ms")?;
+        for osc in oscs {
+            if key_ms.insert(osc, ms).is_some() {
+                bail_expr!(keys_expr, "debounce ms for {osc} is defined more than once");
+            }
+        }
+    }
+    Ok(key_ms)
+}
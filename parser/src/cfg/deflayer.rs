@@ -15,14 +15,33 @@ pub(crate) const DEFLAYER_MAPPED: &str = "deflayermap";
 /// - All layers have the same number of items as the defsrc,
 /// - There are no duplicate layer names
 /// - Parentheses weren't used directly or kmonad-style escapes for parentheses weren't used.
+#[allow(clippy::type_complexity)] // return type is not pub
 pub(crate) fn parse_layer_indexes(
     exprs: &[SpannedLayerExprs],
     expected_len: usize,
     vars: &HashMap<String, SExpr>,
+    secrets: &[Secret],
     _lsp_hints: &mut LspHints,
-) -> Result<(LayerIndexes, LayerIcons)> {
+) -> Result<(
+    LayerIndexes,
+    LayerIcons,
+    LayerSounds,
+    LayerLayouts,
+    Vec<LayerHookExprs>,
+    LayerAutoReturns,
+    LayerNoRepeats,
+    LayerDisplays,
+    LayerCmdEnvs,
+)> {
     let mut layer_indexes = HashMap::default();
     let mut layer_icons = HashMap::default();
+    let mut layer_sounds = HashMap::default();
+    let mut layer_layouts = vec![];
+    let mut layer_hooks = vec![];
+    let mut layer_auto_returns = HashMap::default();
+    let mut layer_no_repeats = HashMap::default();
+    let mut layer_displays = HashMap::default();
+    let mut layer_cmd_envs = HashMap::default();
     for (i, expr_type) in exprs.iter().enumerate() {
         let (mut subexprs, expr, do_element_count_check, deflayer_keyword) = match expr_type {
             SpannedLayerExprs::DefsrcMapping(e) => {
@@ -41,10 +60,32 @@ pub(crate) fn parse_layer_indexes(
                 "{deflayer_keyword} requires a layer name after `{deflayer_keyword}` token"
             )
         })?;
-        let (layer_name, _layer_name_span, icon) = {
+        let (
+            layer_name,
+            _layer_name_span,
+            icon,
+            sound,
+            layout,
+            hooks,
+            auto_return,
+            no_repeat,
+            display,
+            cmd_env,
+        ) = {
             let name = layer_expr.atom(Some(vars));
             match name {
-                Some(name) => (name.to_owned(), layer_expr.span(), None),
+                Some(name) => (
+                    name.to_owned(),
+                    layer_expr.span(),
+                    None,
+                    None,
+                    None,
+                    LayerHookExprs::default(),
+                    None,
+                    false,
+                    LayerDisplay::default(),
+                    vec![],
+                ),
                 None => {
                     // unwrap: this **must** be a list due to atom() call above.
                     let list = layer_expr.list(Some(vars)).unwrap();
@@ -57,10 +98,79 @@ pub(crate) fn parse_layer_indexes(
                             "layer name after {deflayer_keyword} must be a string when enclosed within one pair of parentheses"
                         ))?;
                     let layer_opts = parse_layer_opts(&list[1..])?;
-                    let icon = layer_opts
-                        .get(DEFLAYER_ICON[0])
+                    let opt_atom = |name: &str| layer_opts.get(name).and_then(|v| v.atom(None));
+                    let icon = opt_atom(DEFLAYER_ICON[0])
                         .map(|icon_s| icon_s.trim_atom_quotes().to_owned());
-                    (name.to_owned(), first.span(), icon)
+                    let sound = opt_atom(DEFLAYER_SOUND[0])
+                        .map(|sound_s| SoundCue::from_cfg_str(sound_s.trim_atom_quotes()));
+                    let layout = opt_atom(DEFLAYER_LAYOUT[0])
+                        .map(|layout_s| {
+                            layout_translation(layout_s).ok_or_else(|| {
+                                anyhow_expr!(
+                                    layer_expr,
+                                    "Unknown layout {layout_s}, expected one of: {LAYOUT_TRANSLATION_NAMES}"
+                                )
+                            })
+                        })
+                        .transpose()?;
+                    let hooks = LayerHookExprs {
+                        on_enter: layer_opts.get(DEFLAYER_ON_ENTER[0]).cloned(),
+                        on_exit: layer_opts.get(DEFLAYER_ON_EXIT[0]).cloned(),
+                    };
+                    let auto_return = opt_atom(DEFLAYER_AUTO_RETURN[0])
+                        .map(|ms| match ms.parse::<u16>() {
+                            Ok(ms @ 1..) => Ok(ms),
+                            _ => Err(anyhow_expr!(
+                                layer_expr,
+                                "{} must be 1-65535 milliseconds, found {ms}",
+                                DEFLAYER_AUTO_RETURN[0]
+                            )),
+                        })
+                        .transpose()?;
+                    let no_repeat = match opt_atom(DEFLAYER_REPEAT[0]) {
+                        None | Some("os") => false,
+                        Some("none") => true,
+                        Some(repeat) => bail_expr!(
+                            layer_expr,
+                            "{} must be none or os, found {repeat}",
+                            DEFLAYER_REPEAT[0]
+                        ),
+                    };
+                    let color = opt_atom(DEFLAYER_COLOR[0])
+                        .map(|color| {
+                            let color = color.trim_atom_quotes();
+                            match is_hex_color(color) {
+                                true => Ok(color.to_owned()),
+                                false => Err(anyhow_expr!(
+                                    layer_expr,
+                                    "{} must be #rgb or #rrggbb, found {color}",
+                                    DEFLAYER_COLOR[0]
+                                )),
+                            }
+                        })
+                        .transpose()?;
+                    let display = LayerDisplay {
+                        display_name: opt_atom(DEFLAYER_DISPLAY_NAME[0])
+                            .map(|name| name.trim_atom_quotes().to_owned()),
+                        color,
+                        doc: opt_atom(DEFLAYER_DOC[0]).map(|doc| doc.trim_atom_quotes().to_owned()),
+                    };
+                    let cmd_env = match layer_opts.get(DEFLAYER_CMD_ENV[0]) {
+                        Some(env) => parse_cmd_env(env, Some(vars), secrets, DEFLAYER_CMD_ENV[0])?,
+                        None => vec![],
+                    };
+                    (
+                        name.to_owned(),
+                        first.span(),
+                        icon,
+                        sound,
+                        layout,
+                        hooks,
+                        auto_return,
+                        no_repeat,
+                        display,
+                        cmd_env,
+                    )
                 }
             }
         };
@@ -113,10 +223,67 @@ pub(crate) fn parse_layer_indexes(
             .insert(layer_name.clone(), _layer_name_span.clone());
 
         layer_indexes.insert(layer_name.clone(), i);
+        layer_sounds.insert(layer_name.clone(), sound);
+        layer_auto_returns.insert(layer_name.clone(), auto_return);
+        layer_no_repeats.insert(layer_name.clone(), no_repeat);
+        layer_displays.insert(layer_name.clone(), display);
+        layer_cmd_envs.insert(layer_name.clone(), cmd_env);
         layer_icons.insert(layer_name, icon);
+        layer_layouts.push(layout);
+        layer_hooks.push(hooks);
+    }
+
+    Ok((
+        layer_indexes,
+        layer_icons,
+        layer_sounds,
+        layer_layouts,
+        layer_hooks,
+        layer_auto_returns,
+        layer_no_repeats,
+        layer_displays,
+        layer_cmd_envs,
+    ))
+}
+
+/// Parses the `on-enter` and `on-exit` actions of layers into virtual keys, which are tapped when
+/// the current layer changes.
+pub(crate) fn parse_layer_hooks(
+    layer_hooks: &[LayerHookExprs],
+    layer_info: &mut [LayerInfo],
+    s: &mut ParserState,
+) -> Result<()> {
+    for (hooks, info) in layer_hooks.iter().zip(layer_info.iter_mut()) {
+        for (expr, hook, coord) in [
+            (&hooks.on_enter, DEFLAYER_ON_ENTER[0], &mut info.on_enter),
+            (&hooks.on_exit, DEFLAYER_ON_EXIT[0], &mut info.on_exit),
+        ] {
+            let Some(expr) = expr else {
+                continue;
+            };
+            let action = parse_action(expr, s)?;
+            let idx = s.virtual_keys.len();
+            if idx >= KEYS_IN_ROW {
+                bail_expr!(
+                    expr,
+                    "Maximum number of virtual keys is {KEYS_IN_ROW}, and each {hook} action uses one"
+                );
+            }
+            // The space keeps the name from clashing with virtual keys of the configuration.
+            s.virtual_keys
+                .insert(format!("{} {hook}", info.name), (idx, action));
+            *coord = Some(get_fake_key_coords(idx));
+        }
     }
+    Ok(())
+}
 
-    Ok((layer_indexes, layer_icons))
+/// Returns the `unmapped` option of the layer, which is the action of the keys outside defsrc.
+fn layer_unmapped_opt(layer: &[SExpr], s: &ParserState) -> Result<Option<SExpr>> {
+    let Some(list) = layer.get(1).and_then(|expr| expr.list(s.vars())) else {
+        return Ok(None);
+    };
+    Ok(parse_layer_opts(&list[1..])?.remove(DEFLAYER_UNMAPPED[0]))
 }
 
 pub(crate) fn parse_layers(
@@ -130,6 +297,11 @@ pub(crate) fn parse_layers(
     }
     let mut defsrc_layer = s.defsrc_layer;
     for (layer_level, layer) in s.layer_exprs.iter().enumerate() {
+        let unmapped_opt = match layer {
+            LayerExprs::DefsrcMapping(layer) | LayerExprs::CustomMapping(layer) => {
+                layer_unmapped_opt(layer, s)?
+            }
+        };
         match layer {
             // The skip is done to skip the `deflayer` and layer name tokens.
             LayerExprs::DefsrcMapping(layer) => {
@@ -169,6 +341,9 @@ pub(crate) fn parse_layers(
                         if unmapped_anykey_used {
                             bail_expr!(input, "must have only one use of __ within a layer")
                         }
+                        if unmapped_opt.is_some() {
+                            bail_expr!(input, "must either use __ or the unmapped option, not both")
+                        }
                         if !defcfg.process_unmapped_keys {
                             bail_expr!(
                                 input,
@@ -190,6 +365,12 @@ pub(crate) fn parse_layers(
                         if both_anykey_used {
                             bail_expr!(input, "must have only one use of ___ within a layer")
                         }
+                        if unmapped_opt.is_some() {
+                            bail_expr!(
+                                input,
+                                "must either use ___ or the unmapped option, not both"
+                            )
+                        }
                         if defsrc_anykey_used {
                             bail_expr!(input, "must either use _ or ___ within a layer, not both")
                         }
@@ -226,6 +407,22 @@ pub(crate) fn parse_layers(
                 }
             }
         }
+        if let Some(expr) = &unmapped_opt {
+            if !defcfg.process_unmapped_keys {
+                bail_expr!(
+                    expr,
+                    "must set process-unmapped-keys to yes to use the {} layer option",
+                    DEFLAYER_UNMAPPED[0]
+                );
+            }
+            let action = parse_action(expr, s)?;
+            for i in 0..layers_cfg[0][0].len() {
+                if layers_cfg[layer_level][0][i] == DEFAULT_ACTION && !s.mapping_order.contains(&i)
+                {
+                    layers_cfg[layer_level][0][i] = *action;
+                }
+            }
+        }
         for (osc, layer_action) in layers_cfg[layer_level][0].iter_mut().enumerate() {
             if *layer_action == DEFAULT_ACTION {
                 *layer_action = match s.block_unmapped_keys && !is_a_button(osc as u16) {
@@ -234,6 +431,9 @@ pub(crate) fn parse_layers(
                 };
             }
         }
+        if let Some(translation) = s.layer_layouts.get(layer_level).copied().flatten() {
+            translate_transparent_keys(&mut layers_cfg[layer_level][0], translation);
+        }
 
         // Set fake keys on every layer.
         for (y, action) in s.virtual_keys.values() {
@@ -30,6 +30,39 @@ pub(crate) fn deflocalkeys_variant_applies_to_current_os(variant: &str) -> bool
     variant == DEF_LOCAL_KEYS
 }
 
+/// The keyboard layout of the OS, which selects between `deflocalkeys` blocks that start with
+/// `(layout ...)`.
+static OS_KEYBOARD_LAYOUT: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// Sets the keyboard layout of the OS for the configurations that are parsed from now on.
+pub fn set_os_keyboard_layout(layout: Option<String>) {
+    *OS_KEYBOARD_LAYOUT.lock().unwrap_or_else(|e| e.into_inner()) = layout;
+}
+
+pub fn os_keyboard_layout() -> Option<String> {
+    OS_KEYBOARD_LAYOUT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// A parsed `deflocalkeys` block.
+pub(crate) struct LocalKeys {
+    /// The OS layouts from `(layout ...)` that the block is for, or empty if it is for any layout.
+    pub(crate) layouts: Vec<String>,
+    pub(crate) mapping: HashMap<String, OsCode>,
+}
+
+impl LocalKeys {
+    pub(crate) fn applies_to_layout(&self, layout: Option<&str>) -> bool {
+        layout.is_some_and(|layout| {
+            self.layouts
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(layout))
+        })
+    }
+}
+
 pub(crate) const DEFLOCALKEYS_VARIANTS: &[&str] = &[
     "deflocalkeys-win",
     "deflocalkeys-winiov2",
@@ -42,9 +75,25 @@ pub(crate) const DEFLOCALKEYS_VARIANTS: &[&str] = &[
 pub(crate) fn parse_deflocalkeys(
     def_local_keys_variant: &str,
     expr: &[SExpr],
-) -> Result<HashMap<String, OsCode>> {
+) -> Result<LocalKeys> {
     let mut localkeys = HashMap::default();
-    let mut exprs = check_first_expr(expr.iter(), def_local_keys_variant)?;
+    let mut exprs = check_first_expr(expr.iter(), def_local_keys_variant)?.peekable();
+    let mut layouts = vec![];
+    if let Some(list) = exprs.peek().and_then(|e| e.list(None)) {
+        let layout_expr = exprs.next().expect("peeked");
+        if list.first().and_then(|e| e.atom(None)) != Some("layout") || list.len() < 2 {
+            bail_expr!(
+                layout_expr,
+                "The only list allowed in {def_local_keys_variant} is (layout <name> ...) as the first item"
+            );
+        }
+        for name in &list[1..] {
+            let name = name
+                .atom(None)
+                .ok_or_else(|| anyhow_expr!(name, "Layout names must not be lists"))?;
+            layouts.push(name.trim_atom_quotes().to_owned());
+        }
+    }
     // Read k-v pairs from the configuration
     while let Some(key_expr) = exprs.next() {
         let key = key_expr.atom(None).ok_or_else(|| {
@@ -89,5 +138,8 @@ pub(crate) fn parse_deflocalkeys(
         log::debug!("custom mapping: {key} {}", osc.as_u16());
         localkeys.insert(key.to_owned(), osc);
     }
-    Ok(localkeys)
+    Ok(LocalKeys {
+        layouts,
+        mapping: localkeys,
+    })
 }
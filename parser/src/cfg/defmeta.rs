@@ -0,0 +1,75 @@
+//! Parsing of `defmeta`, which describes the configuration for tools that manage or share
+//! configurations, e.g. its author and the keyboard that it is for. It does not change what
+//! kanata does.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail_expr;
+
+pub(crate) const DEFMETA: &str = "defmeta";
+
+const DEFMETA_ERR: &str = "defmeta expects options:\n\
+    author <string>\n\
+    description <string>\n\
+    version <string>\n\
+    keyboard <string>\n\
+    tags (<string> ...)";
+
+/// The description of the configuration in `defmeta`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigMeta {
+    pub author: Option<String>,
+    pub description: Option<String>,
+    /// The version of the configuration, which kanata does not interpret.
+    pub version: Option<String>,
+    /// The keyboard model that the configuration is for.
+    pub keyboard: Option<String>,
+    pub tags: Vec<String>,
+}
+
+pub(crate) fn parse_defmeta(exprs: &[&Vec<SExpr>], s: &ParserState) -> Result<ConfigMeta> {
+    let mut meta = ConfigMeta::default();
+    let Some(expr) = exprs.first() else {
+        return Ok(meta);
+    };
+    if let Some(extra) = exprs.get(1) {
+        bail_expr!(
+            &extra[0],
+            "Only one {DEFMETA} is allowed, found more. Delete the extras."
+        );
+    }
+    let mut subexprs = check_first_expr(expr.iter(), DEFMETA)?;
+    let mut seen_options: Vec<&str> = vec![];
+    while let Some(opt_expr) = subexprs.next() {
+        let Some(val_expr) = subexprs.next() else {
+            bail_expr!(opt_expr, "{DEFMETA_ERR}\nThis option has no value");
+        };
+        let Some(opt) = opt_expr.atom(s.vars()) else {
+            bail_expr!(opt_expr, "{DEFMETA_ERR}\nAn option must be a string");
+        };
+        if seen_options.contains(&opt) {
+            bail_expr!(opt_expr, "This option is already defined");
+        }
+        let string = |expr: &SExpr| {
+            expr.atom(s.vars())
+                .map(|val| val.trim_atom_quotes().to_owned())
+                .ok_or_else(|| anyhow_expr!(expr, "{opt} must be a string"))
+        };
+        match opt {
+            "author" => meta.author = Some(string(val_expr)?),
+            "description" => meta.description = Some(string(val_expr)?),
+            "version" => meta.version = Some(string(val_expr)?),
+            "keyboard" => meta.keyboard = Some(string(val_expr)?),
+            "tags" => {
+                let Some(tags) = val_expr.list(s.vars()) else {
+                    bail_expr!(val_expr, "{DEFMETA_ERR}\ntags must be a list");
+                };
+                meta.tags = tags.iter().map(string).collect::<Result<_>>()?;
+            }
+            _ => bail_expr!(opt_expr, "{DEFMETA_ERR}\nUnknown option"),
+        }
+        seen_options.push(opt);
+    }
+    Ok(meta)
+}
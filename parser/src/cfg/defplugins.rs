@@ -0,0 +1,154 @@
+//! Parsing of `defplugins` and of the `plugin` action, which runs an action of a plugin.
+//!
+//! Kanata loads the plugins before it parses the configuration, and tells the parser which actions
+//! they have with [`set_plugin_actions`].
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+use crate::bail_expr;
+
+pub(crate) const DEFPLUGINS: &str = "defplugins";
+
+/// What a plugin may ask kanata to do, as granted in `defplugins`. A plugin can always log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PluginCapabilities {
+    /// Press and release keys.
+    pub output: bool,
+    /// Change the base layer.
+    pub layer: bool,
+}
+
+/// A plugin of `defplugins`, which its actions can be used with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginGrant {
+    pub name: String,
+    pub capabilities: PluginCapabilities,
+}
+
+/// `(plugin <plugin> <action> <args>...)`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PluginAction {
+    pub plugin: &'static str,
+    pub action: &'static str,
+    pub args: &'static [&'static str],
+    pub capabilities: PluginCapabilities,
+}
+
+/// The loaded plugins with the names of their actions.
+static PLUGIN_ACTIONS: std::sync::Mutex<Vec<(String, Vec<String>)>> =
+    std::sync::Mutex::new(Vec::new());
+
+/// Sets the plugins and their actions for the configurations that are parsed from now on.
+pub fn set_plugin_actions(plugins: Vec<(String, Vec<String>)>) {
+    *PLUGIN_ACTIONS.lock().unwrap_or_else(|e| e.into_inner()) = plugins;
+}
+
+fn plugin_actions(plugin: &str) -> Option<Vec<String>> {
+    PLUGIN_ACTIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|(name, _)| name == plugin)
+        .map(|(_, actions)| actions.clone())
+}
+
+pub(crate) fn parse_defplugins(exprs: &[&Vec<SExpr>], s: &ParserState) -> Result<Vec<PluginGrant>> {
+    const ERR_MSG: &str = "expects pairs of <plugin name> (<capability>...), \
+                           where the capabilities are output and layer";
+    let mut plugins: Vec<PluginGrant> = vec![];
+    for expr in exprs {
+        let mut exprs = check_first_expr(expr.iter(), DEFPLUGINS)?;
+        while let Some(name_expr) = exprs.next() {
+            let name = name_expr
+                .atom(s.vars())
+                .ok_or_else(|| anyhow_expr!(name_expr, "{DEFPLUGINS} {ERR_MSG}"))?;
+            if plugins.iter().any(|plugin| plugin.name == name) {
+                bail_expr!(name_expr, "plugin {name} is defined more than once");
+            }
+            if plugin_actions(name).is_none() {
+                bail_expr!(
+                    name_expr,
+                    "plugin {name} was not loaded, is it in the directory of --plugin-dir?"
+                );
+            }
+            let Some(capabilities_expr) = exprs.next() else {
+                bail_expr!(
+                    name_expr,
+                    "missing the capabilities of plugin {name}, use () for none.\n{ERR_MSG}"
+                );
+            };
+            let mut capabilities = PluginCapabilities::default();
+            let Some(capability_exprs) = capabilities_expr.list(s.vars()) else {
+                bail_expr!(capabilities_expr, "{DEFPLUGINS} {ERR_MSG}");
+            };
+            for capability_expr in capability_exprs {
+                let capability = match capability_expr.atom(s.vars()) {
+                    Some("output") => &mut capabilities.output,
+                    Some("layer") => &mut capabilities.layer,
+                    _ => bail_expr!(
+                        capability_expr,
+                        "unknown capability, expected output or layer"
+                    ),
+                };
+                if *capability {
+                    bail_expr!(capability_expr, "duplicate capability");
+                }
+                *capability = true;
+            }
+            plugins.push(PluginGrant {
+                name: name.to_owned(),
+                capabilities,
+            });
+        }
+    }
+    Ok(plugins)
+}
+
+pub(crate) fn parse_plugin_action(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects at least 2 parameters: <plugin name> <action name> [args...]";
+    if ac_params.len() < 2 {
+        bail!("{PLUGIN} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let plugin = ac_params[0]
+        .atom(s.vars())
+        .ok_or_else(|| anyhow_expr!(&ac_params[0], "{PLUGIN} {ERR_MSG}"))?;
+    let Some(grant) = s.plugins.iter().find(|grant| grant.name == plugin) else {
+        bail_expr!(
+            &ac_params[0],
+            "plugin {plugin} is not defined in {DEFPLUGINS}"
+        );
+    };
+    let action = ac_params[1]
+        .atom(s.vars())
+        .ok_or_else(|| anyhow_expr!(&ac_params[1], "{PLUGIN} {ERR_MSG}"))?;
+    let actions = plugin_actions(plugin).unwrap_or_default();
+    if !actions.iter().any(|known| known == action) {
+        bail_expr!(
+            &ac_params[1],
+            "plugin {plugin} has no action {action}, its actions are: {}",
+            actions.join(" ")
+        );
+    }
+    let args = ac_params[2..]
+        .iter()
+        .map(|arg| {
+            arg.atom(s.vars())
+                .map(|arg| s.a.sref_str(arg.trim_atom_quotes().to_owned()))
+                .ok_or_else(|| anyhow_expr!(arg, "the arguments of {PLUGIN} should be strings"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    custom(
+        CustomAction::Plugin(s.a.sref(PluginAction {
+            plugin: s.a.sref_str(plugin.to_owned()),
+            action: s.a.sref_str(action.to_owned()),
+            args: s.a.sref_vec(args),
+            capabilities: grant.capabilities,
+        })),
+        &s.a,
+    )
+}
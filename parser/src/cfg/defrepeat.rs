@@ -0,0 +1,156 @@
+//! Parsing of `defrepeat` and `defrepeat-layer`, which configure key repeat per output key.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail_expr;
+
+pub(crate) const DEFREPEAT: &str = "defrepeat";
+pub(crate) const DEFREPEAT_LAYER: &str = "defrepeat-layer";
+
+/// How repeats of a key should be output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRepeatBehaviour {
+    /// Repeat events from the OS or keyboard are output as usual.
+    Os,
+    /// Repeat events are never output.
+    Disabled,
+    /// Repeat events from the OS or keyboard are ignored and kanata outputs its own repeats
+    /// instead, starting `delay` milliseconds after the press and then every `interval`
+    /// milliseconds.
+    Software { delay: u16, interval: u16 },
+}
+
+/// Key repeat behaviour per output key, optionally overridden per layer.
+#[derive(Debug, Default, Clone)]
+pub struct KeyRepeatCfg {
+    global: HashMap<OsCode, KeyRepeatBehaviour>,
+    per_layer: HashMap<usize, HashMap<OsCode, KeyRepeatBehaviour>>,
+    /// The layers with `repeat none`, on which keys without a `defrepeat-layer` behaviour are
+    /// not repeated.
+    no_repeat_layers: Vec<usize>,
+}
+
+impl KeyRepeatCfg {
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty() && self.per_layer.is_empty() && self.no_repeat_layers.is_empty()
+    }
+
+    /// Whether the layer has `repeat none`, so that the keys pressed on it are not repeated even
+    /// after the layer is no longer active.
+    pub fn layer_has_no_repeat(&self, layer: usize) -> bool {
+        self.no_repeat_layers.contains(&layer)
+    }
+
+    /// Returns the configured behaviour for the key while the given layer is active.
+    pub fn behaviour(&self, layer: usize, key: OsCode) -> KeyRepeatBehaviour {
+        self.per_layer
+            .get(&layer)
+            .and_then(|keys| keys.get(&key))
+            .or_else(|| {
+                self.layer_has_no_repeat(layer)
+                    .then_some(&KeyRepeatBehaviour::Disabled)
+            })
+            .or_else(|| self.global.get(&key))
+            .copied()
+            .unwrap_or(KeyRepeatBehaviour::Os)
+    }
+}
+
+pub(crate) fn parse_defrepeats(
+    repeat_exprs: &[&Vec<SExpr>],
+    repeat_layer_exprs: &[&Vec<SExpr>],
+    layer_info: &[LayerInfo],
+    s: &ParserState,
+) -> Result<KeyRepeatCfg> {
+    let mut cfg = KeyRepeatCfg {
+        no_repeat_layers: layer_info
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| info.no_repeat)
+            .map(|(layer, _)| layer)
+            .collect(),
+        ..Default::default()
+    };
+    for expr in repeat_exprs {
+        let exprs = check_first_expr(expr.iter(), DEFREPEAT)?;
+        parse_repeat_pairs(exprs, &mut cfg.global, s)?;
+    }
+    for expr in repeat_layer_exprs {
+        let mut exprs = check_first_expr(expr.iter(), DEFREPEAT_LAYER)?;
+        let layer_expr = exprs.next().ok_or_else(|| {
+            anyhow_expr!(&expr[0], "{DEFREPEAT_LAYER} expects a layer name first")
+        })?;
+        let layer_name = layer_expr
+            .atom(s.vars())
+            .ok_or_else(|| anyhow_expr!(layer_expr, "layer name should be a string not a list"))?;
+        let Some(&layer) = s.layer_idxs.get(layer_name) else {
+            bail_expr!(
+                layer_expr,
+                "layer name is not declared in any deflayer: {layer_name}"
+            );
+        };
+        if cfg.per_layer.contains_key(&layer) {
+            bail_expr!(
+                layer_expr,
+                "{DEFREPEAT_LAYER} already exists for this layer; combine them into one"
+            );
+        }
+        let mut keys = HashMap::default();
+        parse_repeat_pairs(exprs, &mut keys, s)?;
+        cfg.per_layer.insert(layer, keys);
+    }
+    Ok(cfg)
+}
+
+fn parse_repeat_pairs<'a>(
+    mut exprs: impl Iterator<Item = &'a SExpr>,
+    keys: &mut HashMap<OsCode, KeyRepeatBehaviour>,
+    s: &ParserState,
+) -> Result<()> {
+    while let Some(keys_expr) = exprs.next() {
+        let Some(behaviour_expr) = exprs.next() else {
+            bail_expr!(
+                keys_expr,
+                "missing repeat behaviour for the key(s).\n{REPEAT_BEHAVIOUR_HELP}"
+            );
+        };
+        let oscs = match keys_expr.atom(s.vars()) {
+            Some(key) => vec![
+                str_to_oscode(key)
+                    .ok_or_else(|| anyhow_expr!(keys_expr, "string of a known key is expected"))?,
+            ],
+            None => parse_key_list(keys_expr, s, "repeat keys")?,
+        };
+        let behaviour = parse_repeat_behaviour(behaviour_expr, s)?;
+        for osc in oscs {
+            if keys.insert(osc, behaviour).is_some() {
+                bail_expr!(
+                    keys_expr,
+                    "repeat behaviour for {osc} is defined more than once"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+const REPEAT_BEHAVIOUR_HELP: &str = "Expected one of: none, os, or (<delay ms> <interval ms>)";
+
+fn parse_repeat_behaviour(expr: &SExpr, s: &ParserState) -> Result<KeyRepeatBehaviour> {
+    if let Some(behaviour) = expr.atom(s.vars()) {
+        return match behaviour {
+            "none" => Ok(KeyRepeatBehaviour::Disabled),
+            "os" => Ok(KeyRepeatBehaviour::Os),
+            _ => bail_expr!(expr, "Unknown repeat behaviour.\n{REPEAT_BEHAVIOUR_HELP}"),
+        };
+    }
+    let params = expr.list(s.vars()).expect("not atom");
+    if params.len() != 2 {
+        bail_expr!(expr, "{REPEAT_BEHAVIOUR_HELP}");
+    }
+    Ok(KeyRepeatBehaviour::Software {
+        delay: parse_non_zero_u16(&params[0], s, "repeat delay")?,
+        interval: parse_non_zero_u16(&params[1], s, "repeat interval")?,
+    })
+}
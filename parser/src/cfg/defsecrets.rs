@@ -0,0 +1,132 @@
+//! Parsing of `defsecrets` and of the `(secret <name>)` values that refer to its secrets.
+//!
+//! Secrets are only read when they are used, so the configuration never holds them and can be
+//! shared without its secrets.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail_expr;
+
+pub(crate) const DEFSECRETS: &str = "defsecrets";
+pub(crate) const SECRET: &str = "secret";
+
+/// Where a secret is read from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SecretSource {
+    /// The credential store of the OS, under the name of the secret, like for `secret-type`.
+    CredentialStore,
+    /// A file that holds only the secret and that only its owner can read.
+    File(PathBuf),
+}
+
+/// A secret from `defsecrets`, or from the credential store if it is not defined there.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Secret {
+    pub name: String,
+    pub source: SecretSource,
+}
+
+/// The value of an environment variable of cmd actions, from `cmd-env` or the `env` of
+/// `cmd-argv`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum EnvValue {
+    Text(String),
+    /// Read when the command starts.
+    Secret(Secret),
+}
+
+impl From<&str> for EnvValue {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_owned())
+    }
+}
+
+/// Shows the name of a secret rather than its source, and text as the string itself.
+impl std::fmt::Debug for EnvValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Text(text) => text.fmt(f),
+            Self::Secret(secret) => write!(f, "({SECRET} {})", secret.name),
+        }
+    }
+}
+
+/// The environment variables of cmd actions.
+pub type CmdEnv = Vec<(String, EnvValue)>;
+
+pub(crate) fn parse_defsecrets(
+    exprs: &[&Vec<SExpr>],
+    vars: &HashMap<String, SExpr>,
+) -> Result<Vec<Secret>> {
+    const ERR_MSG: &str = "expects pairs of <name> <source>, where the source is \
+                           credential-store or (file <path>)";
+    let vars = Some(vars);
+    let mut secrets: Vec<Secret> = vec![];
+    for expr in exprs {
+        let mut exprs = check_first_expr(expr.iter(), DEFSECRETS)?;
+        while let Some(name_expr) = exprs.next() {
+            let name = name_expr
+                .atom(vars)
+                .map(|name| name.trim_atom_quotes())
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| anyhow_expr!(name_expr, "{DEFSECRETS} {ERR_MSG}"))?;
+            if secrets.iter().any(|secret| secret.name == name) {
+                bail_expr!(name_expr, "secret {name} is defined more than once");
+            }
+            let Some(source_expr) = exprs.next() else {
+                bail_expr!(name_expr, "missing the source of secret {name}.\n{ERR_MSG}");
+            };
+            let source = match (source_expr.atom(vars), source_expr.list(vars)) {
+                (Some("credential-store"), _) => SecretSource::CredentialStore,
+                (_, Some([kind, path])) if kind.atom(vars) == Some("file") => {
+                    let path = path
+                        .atom(vars)
+                        .map(|path| path.trim_atom_quotes())
+                        .filter(|path| !path.is_empty())
+                        .ok_or_else(|| anyhow_expr!(path, "the path should be a string"))?;
+                    SecretSource::File(PathBuf::from(path))
+                }
+                _ => bail_expr!(source_expr, "{DEFSECRETS} {ERR_MSG}"),
+            };
+            secrets.push(Secret {
+                name: name.to_owned(),
+                source,
+            });
+        }
+    }
+    Ok(secrets)
+}
+
+/// The secret of `defsecrets` with the name, or the one of the credential store.
+pub(crate) fn find_secret(secrets: &[Secret], name: &str) -> Secret {
+    secrets
+        .iter()
+        .find(|secret| secret.name == name)
+        .cloned()
+        .unwrap_or_else(|| Secret {
+            name: name.to_owned(),
+            source: SecretSource::CredentialStore,
+        })
+}
+
+/// Parses `(secret <name>)`, or returns None if the expression is something else.
+pub(crate) fn parse_secret_ref(
+    expr: &SExpr,
+    vars: Option<&HashMap<String, SExpr>>,
+    secrets: &[Secret],
+) -> Result<Option<Secret>> {
+    let Some(list) = expr.list(vars) else {
+        return Ok(None);
+    };
+    if list.first().and_then(|first| first.atom(vars)) != Some(SECRET) {
+        return Ok(None);
+    }
+    match list {
+        [_, name] => match name.atom(vars).map(|name| name.trim_atom_quotes()) {
+            Some(name) if !name.is_empty() => Ok(Some(find_secret(secrets, name))),
+            _ => bail_expr!(name, "the name of a secret should be a string"),
+        },
+        _ => bail_expr!(expr, "{SECRET} expects 1 parameter: <secret name>"),
+    }
+}
@@ -74,6 +74,25 @@ pub(crate) fn parse_defsrc(
                     // seems strictly incorrect to do, so never do it.
                     // Users can still choose to opt in if they want.
                     // Auto-including mouse activity breaks many scenarios.
+                    //
+                    // With process-unmapped-mouse-buttons, the buttons of a mouse that is already
+                    // intercepted because some of its buttons are in defsrc are processed too.
+                    // Clicks of the other buttons would otherwise skip ahead of the actions of
+                    // the mapped buttons, e.g. a pending tap-hold.
+                    let is_button = !matches!(
+                        osc,
+                        OsCode::MouseWheelUp
+                            | OsCode::MouseWheelDown
+                            | OsCode::MouseWheelLeft
+                            | OsCode::MouseWheelRight
+                    );
+                    if is_button
+                        && defcfg.process_unmapped_mouse_buttons
+                        && matches!(is_mouse_used, MouseInDefsrc::MouseUsed)
+                        && !mapped_exceptions.contains(&osc)
+                    {
+                        mkeys.insert(osc);
+                    }
                     continue;
                 }
                 match KeyCode::from(osc) {
@@ -0,0 +1,226 @@
+//! Parsing of `deftaphold-flavor`, which names a combination of the tap-hold settings that the
+//! built-in tap-hold variants fix, and of the `tap-hold-flavor` action that uses it.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+use crate::bail_expr;
+
+pub(crate) const DEFTAPHOLD_FLAVOR: &str = "deftaphold-flavor";
+
+const DEFTAPHOLD_FLAVOR_ERR: &str = "deftaphold-flavor expects a name followed by options:\n\
+    decision timeout|press|release|order\n\
+    order-buffer <ms>\n\
+    timeout-action tap|hold\n\
+    reset-timeout-on-press yes|no\n\
+    tap-on-press (<keys>)\n\
+    tap-on-press-release (<keys>)\n\
+    hold-on-press (<keys>)\n\
+    require-prior-idle <ms>";
+
+/// The settings of a tap-hold flavor.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TapHoldFlavor {
+    config: HoldTapConfig<'static>,
+    timeout_action: WaitingAction,
+    reset_timeout_on_press: bool,
+    require_prior_idle: Option<u16>,
+}
+
+pub(crate) fn parse_deftaphold_flavors(exprs: &[&Vec<SExpr>], s: &mut ParserState) -> Result<()> {
+    for expr in exprs {
+        let mut subexprs = check_first_expr(expr.iter(), DEFTAPHOLD_FLAVOR)?;
+        let Some(name_expr) = subexprs.next() else {
+            bail!("{DEFTAPHOLD_FLAVOR_ERR}\nfound no items");
+        };
+        let name = name_expr.atom(s.vars()).ok_or_else(|| {
+            anyhow_expr!(
+                name_expr,
+                "{DEFTAPHOLD_FLAVOR_ERR}\nThe name must not be a list"
+            )
+        })?;
+        if s.tap_hold_flavors.contains_key(name) {
+            bail_expr!(name_expr, "Duplicate tap-hold flavor: {name}");
+        }
+        let mut seen_options: HashSet<&str> = HashSet::default();
+        let mut seen_keys: HashSet<OsCode> = HashSet::default();
+        let mut decision = None;
+        let mut order_buffer = None;
+        let mut timeout_action = WaitingAction::Hold;
+        let mut reset_timeout_on_press = false;
+        let mut require_prior_idle = None;
+        let mut tap_on_press = vec![];
+        let mut tap_on_press_release = vec![];
+        let mut hold_on_press = vec![];
+        while let Some(opt_expr) = subexprs.next() {
+            let Some(val_expr) = subexprs.next() else {
+                bail_expr!(
+                    opt_expr,
+                    "{DEFTAPHOLD_FLAVOR_ERR}\nThis option has no value"
+                );
+            };
+            let Some(opt) = opt_expr.atom(s.vars()) else {
+                bail_expr!(opt_expr, "{DEFTAPHOLD_FLAVOR_ERR}\nUnknown option");
+            };
+            if !seen_options.insert(opt) {
+                bail_expr!(
+                    opt_expr,
+                    "{DEFTAPHOLD_FLAVOR_ERR}\nThis option is already set"
+                );
+            }
+            match opt {
+                "decision" => {
+                    decision = Some(match val_expr.atom(s.vars()) {
+                        Some("timeout") => Some(OtherKeyDecision::Timeout),
+                        Some("press") => Some(OtherKeyDecision::HoldOnPress),
+                        Some("release") => Some(OtherKeyDecision::HoldOnPressRelease),
+                        Some("order") => None,
+                        _ => bail_expr!(
+                            val_expr,
+                            "decision must be one of: timeout, press, release, order"
+                        ),
+                    });
+                }
+                "order-buffer" => {
+                    order_buffer = Some(parse_u16(val_expr, s, "order-buffer")?);
+                }
+                "timeout-action" => {
+                    timeout_action = match val_expr.atom(s.vars()) {
+                        Some("tap") => WaitingAction::Tap,
+                        Some("hold") => WaitingAction::Hold,
+                        _ => bail_expr!(val_expr, "timeout-action must be tap or hold"),
+                    };
+                }
+                "reset-timeout-on-press" => {
+                    reset_timeout_on_press = parse_yes_no(val_expr, s, opt)?;
+                }
+                "require-prior-idle" => {
+                    let prior_idle = parse_u16(val_expr, s, opt)?;
+                    s.max_key_timing_check
+                        .set(std::cmp::max(prior_idle, s.max_key_timing_check.get()));
+                    require_prior_idle = Some(prior_idle);
+                }
+                "tap-on-press" | "tap-on-press-release" | "hold-on-press" => {
+                    let Some(keys) = val_expr.list(s.vars()) else {
+                        bail_expr!(val_expr, "{opt} must be a list of keys");
+                    };
+                    let mut option = vec![opt_expr.clone()];
+                    option.extend(keys.iter().cloned());
+                    let keys =
+                        parse_key_list_from_option(&option, val_expr, s, opt, &mut seen_keys)?;
+                    match opt {
+                        "tap-on-press" => tap_on_press = keys,
+                        "tap-on-press-release" => tap_on_press_release = keys,
+                        _ => hold_on_press = keys,
+                    }
+                }
+                _ => bail_expr!(opt_expr, "{DEFTAPHOLD_FLAVOR_ERR}\nUnknown option"),
+            }
+        }
+        let has_key_lists = !(tap_on_press.is_empty()
+            && tap_on_press_release.is_empty()
+            && hold_on_press.is_empty());
+        // None for the decision order.
+        let decision = decision.unwrap_or(Some(OtherKeyDecision::Timeout));
+        let config = match decision {
+            None => {
+                if has_key_lists {
+                    bail_expr!(
+                        name_expr,
+                        "tap-on-press, tap-on-press-release and hold-on-press cannot be used with decision order"
+                    );
+                }
+                HoldTapConfig::Order {
+                    buffer: order_buffer.unwrap_or(0),
+                }
+            }
+            Some(_) if order_buffer.is_some() => {
+                bail_expr!(
+                    name_expr,
+                    "order-buffer can only be used with decision order"
+                );
+            }
+            Some(decision) if has_key_lists => HoldTapConfig::Custom(custom_tap_hold_key_lists(
+                &tap_on_press,
+                &tap_on_press_release,
+                &hold_on_press,
+                decision,
+                &s.a,
+            )),
+            Some(OtherKeyDecision::Timeout) => HoldTapConfig::Default,
+            Some(OtherKeyDecision::HoldOnPress) => HoldTapConfig::HoldOnOtherKeyPress,
+            Some(OtherKeyDecision::HoldOnPressRelease) => HoldTapConfig::PermissiveHold,
+        };
+        s.tap_hold_flavors.insert(
+            name.to_owned(),
+            TapHoldFlavor {
+                config,
+                timeout_action,
+                reset_timeout_on_press,
+                require_prior_idle,
+            },
+        );
+    }
+    Ok(())
+}
+
+fn parse_yes_no(expr: &SExpr, s: &ParserState, label: &str) -> Result<bool> {
+    match expr.atom(s.vars()) {
+        Some("yes") => Ok(true),
+        Some("no") => Ok(false),
+        _ => bail_expr!(expr, "{label} must be yes or no"),
+    }
+}
+
+pub(crate) fn parse_tap_hold_flavor(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    if ac_params.len() != 5 {
+        bail!(
+            r"{TAP_HOLD_FLAVOR} expects 5 items after it, got {}.
+Params in order:
+<flavor-name> <tap-repress-timeout> <hold-timeout> <tap-action> <hold-action>",
+            ac_params.len(),
+        )
+    }
+    let name = ac_params[0]
+        .atom(s.vars())
+        .ok_or_else(|| anyhow_expr!(&ac_params[0], "flavor name must not be a list"))?;
+    let flavor = *s.tap_hold_flavors.get(name).ok_or_else(|| {
+        anyhow_expr!(
+            &ac_params[0],
+            "Unknown tap-hold flavor: {name}. Define it with {DEFTAPHOLD_FLAVOR}."
+        )
+    })?;
+    let tap_repress_timeout = parse_u16(&ac_params[1], s, "tap repress timeout")?;
+    let hold_timeout = parse_non_zero_u16(&ac_params[2], s, "hold timeout")?;
+    let tap_action = parse_action(&ac_params[3], s)?;
+    let hold_action = parse_action(&ac_params[4], s)?;
+    if matches!(tap_action, Action::HoldTap { .. }) {
+        bail!("tap-hold does not work in the tap-action of tap-hold")
+    }
+    let timeout = match flavor.config {
+        // Resolution is purely event-driven, not timeout-based.
+        HoldTapConfig::Order { .. } => u16::MAX,
+        _ => hold_timeout,
+    };
+    Ok(s.a.sref(Action::HoldTap(s.a.sref(HoldTapAction {
+        config: flavor.config,
+        tap_hold_interval: tap_repress_timeout,
+        timeout,
+        tap: *tap_action,
+        hold: *hold_action,
+        timeout_action: match flavor.timeout_action {
+            WaitingAction::Tap => *tap_action,
+            _ => *hold_action,
+        },
+        on_press_reset_timeout_to: if flavor.reset_timeout_on_press {
+            std::num::NonZeroU16::new(timeout)
+        } else {
+            None
+        },
+        require_prior_idle: flavor.require_prior_idle,
+    }))))
+}
@@ -34,7 +34,7 @@ struct Template {
     content: Vec<SExpr>,
 }
 
-/// Parse `deftemplate`s and expand `template-expand`s.
+/// Parse `deftemplate`s and expand `template-expand`s and `foreach`s.
 ///
 /// Syntax of `deftemplate` is:
 ///
@@ -43,6 +43,10 @@ struct Template {
 /// Syntax of `template-expand` is:
 ///
 /// `(template-expand <template name> <template var substitutions>)`
+///
+/// Syntax of `foreach` is:
+///
+/// `(foreach <$item var> <list or defvar with a list value> <content repeated for each item>)`
 pub fn expand_templates(
     mut toplevel_exprs: Vec<TopLevel>,
     lsp_hints: &mut LspHints,
@@ -174,6 +178,8 @@ pub fn expand_templates(
         });
     }
 
+    let vars = foreach_vars(&toplevel_exprs, lsp_hints)?;
+
     // Find and do expansions
     let mut toplevels: Vec<SExpr> = toplevel_exprs
         .into_iter()
@@ -184,7 +190,7 @@ pub fn expand_templates(
             })
         })
         .collect();
-    expand(&mut toplevels, &templates, lsp_hints)?;
+    expand(&mut toplevels, &templates, &vars, lsp_hints)?;
 
     toplevels.into_iter().try_fold(vec![], |mut tls, tl| {
         tls.push(match &tl {
@@ -206,19 +212,50 @@ struct Replacement {
     insert_index: usize,
 }
 
-fn expand(exprs: &mut Vec<SExpr>, templates: &[Template], _lsp_hints: &mut LspHints) -> Result<()> {
+/// The `defvar`s that are not in templates, so that `foreach` can repeat for the items of their
+/// list values.
+fn foreach_vars(
+    toplevel_exprs: &[TopLevel],
+    lsp_hints: &mut LspHints,
+) -> Result<HashMap<String, SExpr>> {
+    let var_exprs = toplevel_exprs
+        .iter()
+        .filter(|tl| {
+            matches!(
+                tl.t.first().and_then(|expr| expr.atom(None)),
+                Some("defvar")
+            )
+        })
+        .map(|tl| &tl.t)
+        .collect::<Vec<_>>();
+    parse_vars(&var_exprs, lsp_hints)
+}
+
+fn expand(
+    exprs: &mut Vec<SExpr>,
+    templates: &[Template],
+    vars: &HashMap<String, SExpr>,
+    _lsp_hints: &mut LspHints,
+) -> Result<()> {
     let mut replacements: Vec<Replacement> = vec![];
     loop {
         for (expr_index, expr) in exprs.iter_mut().enumerate() {
             match expr {
                 SExpr::Atom(_) => continue,
                 SExpr::List(l) => {
-                    if !matches!(
-                        l.t.first().and_then(|expr| expr.atom(None)),
-                        Some("template-expand") | Some("t!")
-                    ) {
-                        expand(&mut l.t, templates, _lsp_hints)?;
-                        continue;
+                    match l.t.first().and_then(|expr| expr.atom(None)) {
+                        Some("template-expand") | Some("t!") => {}
+                        Some("foreach") => {
+                            replacements.push(Replacement {
+                                insert_index: expr_index,
+                                exprs: foreach_replacement(l, vars)?,
+                            });
+                            continue;
+                        }
+                        _ => {
+                            expand(&mut l.t, templates, vars, _lsp_hints)?;
+                            continue;
+                        }
                     }
 
                     // found expand, now parse
@@ -332,6 +369,52 @@ fn expand(exprs: &mut Vec<SExpr>, templates: &[Template], _lsp_hints: &mut LspHi
     Ok(())
 }
 
+/// Returns the content of the `foreach` for each item of its list, with the item variable
+/// substituted by the item.
+fn foreach_replacement(
+    l: &Spanned<Vec<SExpr>>,
+    vars: &HashMap<String, SExpr>,
+) -> Result<Vec<SExpr>> {
+    const ERR_MSG: &str =
+        "foreach expects a $variable name, a list, and the content to repeat for each list item";
+    let (Some(var_expr), Some(list_expr)) = (l.t.get(1), l.t.get(2)) else {
+        bail_span!(l, "{ERR_MSG}");
+    };
+    let var = var_expr
+        .atom(None)
+        .filter(|var| var.len() > 1 && var.starts_with('$'))
+        .ok_or_else(|| anyhow_expr!(var_expr, "{ERR_MSG}\nThe variable name must begin with $"))?;
+    let items = list_expr.list(Some(vars)).ok_or_else(|| {
+        anyhow_expr!(
+            list_expr,
+            "{ERR_MSG}\nThe items must be a list or a defvar with a list value"
+        )
+    })?;
+    let mut expanded = vec![];
+    for item in items {
+        let mut content = l.t[3..].to_vec();
+        visit_mut_all_atoms(&mut content, &mut |expr: &mut SExpr| {
+            if expr.atom(None) == Some(var) {
+                *expr = item.clone();
+            }
+        });
+        expanded.extend(content);
+    }
+    visit_mut_all_lists(&mut expanded, &mut |expr: &mut SExpr| {
+        *expr = match expr {
+            // Below should not be reached because only lists should be visited
+            SExpr::Atom(_) => unreachable!(),
+            SExpr::List(l) => parse_list_var(l, &HashMap::default()),
+        };
+        match expr {
+            SExpr::Atom(_) => true,
+            SExpr::List(_) => false,
+        }
+    });
+    while evaluate_conditionals(&mut expanded)? {}
+    Ok(expanded)
+}
+
 fn visit_validate_all_atoms(
     exprs: &[SExpr],
     visit: &mut dyn FnMut(&Spanned<String>) -> Result<()>,
@@ -0,0 +1,118 @@
+//! Parsing of `deftest`, which has input events in the simulator format and the key outputs that
+//! they are expected to produce, so that a configuration can check its own behaviour. The tests
+//! are run by `kanata check --run-tests`.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+use crate::bail_expr;
+
+pub(crate) const DEFTEST: &str = "deftest";
+
+const DEFTEST_ERR: &str = "deftest expects a test name followed by options:\n\
+    input (<d:key | u:key | r:key | t:ms> ...)\n\
+    expect (<d:key | u:key> ...)";
+
+/// An input event or an expected output of a `deftest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestEvent {
+    Press(OsCode),
+    Release(OsCode),
+    Repeat(OsCode),
+    /// Milliseconds to wait.
+    Wait(u16),
+}
+
+/// A test of `deftest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigTest {
+    pub name: String,
+    pub inputs: Vec<TestEvent>,
+    /// The key presses and releases that the inputs are expected to output, in order. Other
+    /// outputs, and the times of the outputs, are not compared.
+    pub expected: Vec<TestEvent>,
+}
+
+pub(crate) fn parse_deftests(exprs: &[&Vec<SExpr>], s: &ParserState) -> Result<Vec<ConfigTest>> {
+    let mut tests: Vec<ConfigTest> = vec![];
+    for expr in exprs {
+        let mut subexprs = check_first_expr(expr.iter(), DEFTEST)?;
+        let Some(name_expr) = subexprs.next() else {
+            bail!("{DEFTEST_ERR}\nfound no items");
+        };
+        let name = name_expr
+            .atom(s.vars())
+            .map(|name| name.trim_atom_quotes())
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| anyhow_expr!(name_expr, "{DEFTEST_ERR}\nThe name must be a string"))?;
+        if tests.iter().any(|test| test.name == name) {
+            bail_expr!(name_expr, "Duplicate test name in deftest: {name}");
+        }
+        let mut inputs = None;
+        let mut expected = None;
+        while let Some(opt_expr) = subexprs.next() {
+            let Some(val_expr) = subexprs.next() else {
+                bail_expr!(opt_expr, "{DEFTEST_ERR}\nThis option has no value");
+            };
+            match opt_expr.atom(s.vars()) {
+                Some("input") if inputs.is_none() => {
+                    inputs = Some(parse_test_events(val_expr, true, s)?);
+                }
+                Some("expect") if expected.is_none() => {
+                    expected = Some(parse_test_events(val_expr, false, s)?);
+                }
+                Some("input" | "expect") => {
+                    bail_expr!(opt_expr, "This option is already defined")
+                }
+                _ => bail_expr!(opt_expr, "{DEFTEST_ERR}\nUnknown option"),
+            }
+        }
+        let (Some(inputs), Some(expected)) = (inputs, expected) else {
+            bail_expr!(
+                name_expr,
+                "{DEFTEST_ERR}\nThe test needs both input and expect"
+            );
+        };
+        tests.push(ConfigTest {
+            name: name.to_owned(),
+            inputs,
+            expected,
+        });
+    }
+    Ok(tests)
+}
+
+fn parse_test_events(expr: &SExpr, is_input: bool, s: &ParserState) -> Result<Vec<TestEvent>> {
+    let Some(items) = expr.list(s.vars()) else {
+        bail_expr!(expr, "{DEFTEST_ERR}\nThe events must be a list");
+    };
+    items
+        .iter()
+        .map(|item| {
+            let Some((kind, val)) = item.atom(s.vars()).and_then(|a| a.split_once(':')) else {
+                bail_expr!(item, "{DEFTEST_ERR}\nExpected an event like d:a or t:10");
+            };
+            if kind == "t" {
+                if !is_input {
+                    bail_expr!(
+                        item,
+                        "expect lists only key outputs, the times are not compared"
+                    );
+                }
+                let ms = val
+                    .parse()
+                    .map_err(|_| anyhow_expr!(item, "Expected a number of milliseconds"))?;
+                return Ok(TestEvent::Wait(ms));
+            }
+            let key =
+                str_to_oscode(val).ok_or_else(|| anyhow_expr!(item, "Unknown key name: {val}"))?;
+            Ok(match kind {
+                "d" => TestEvent::Press(key),
+                "u" => TestEvent::Release(key),
+                "r" if is_input => TestEvent::Repeat(key),
+                _ => bail_expr!(item, "{DEFTEST_ERR}\nUnknown event kind: {kind}"),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,201 @@
+//! Parsing of `defwebhooks` and the `webhook` action.
+
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+use crate::bail_expr;
+
+pub(crate) const DEFWEBHOOKS: &str = "defwebhooks";
+
+/// An event that fires the webhooks listing it in `defwebhooks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    LayerChange,
+    Reload,
+    /// A live reload failed because of an error in the configuration.
+    Error,
+}
+
+impl WebhookEvent {
+    /// The name of the event in the configuration and in the request body.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::LayerChange => "layer-change",
+            Self::Reload => "reload",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// A URL from `defwebhooks`, which is sent an HTTP POST request with a JSON body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub name: String,
+    /// The `host:port` to connect to.
+    pub address: String,
+    /// The value of the `Host` header, i.e. the host and the port if it is in the URL.
+    pub host: String,
+    /// The path and query of the URL, starting with `/`.
+    pub path: String,
+    pub events: Vec<WebhookEvent>,
+    /// The secret that is sent as the bearer token in the `Authorization` header.
+    pub bearer: Option<Secret>,
+}
+
+impl Webhook {
+    pub fn url(&self) -> String {
+        format!("http://{}{}", self.host, self.path)
+    }
+}
+
+pub(crate) fn parse_defwebhooks(exprs: &[&Vec<SExpr>], s: &ParserState) -> Result<Vec<Webhook>> {
+    const ERR_MSG: &str = "expects triples of <name> <url> (<event>...), each optionally followed by (bearer <secret>)";
+    let mut webhooks: Vec<Webhook> = vec![];
+    for expr in exprs {
+        let mut exprs = check_first_expr(expr.iter(), DEFWEBHOOKS)?.peekable();
+        while let Some(name_expr) = exprs.next() {
+            let name = name_expr
+                .atom(s.vars())
+                .ok_or_else(|| anyhow_expr!(name_expr, "{DEFWEBHOOKS} {ERR_MSG}"))?;
+            if webhooks.iter().any(|w| w.name == name) {
+                bail_expr!(name_expr, "webhook {name} is defined more than once");
+            }
+            let Some(url_expr) = exprs.next() else {
+                bail_expr!(name_expr, "missing url for webhook {name}.\n{ERR_MSG}");
+            };
+            let Some(events_expr) = exprs.next() else {
+                bail_expr!(
+                    url_expr,
+                    "missing the list of events for webhook {name}, use () for none.\n{ERR_MSG}"
+                );
+            };
+            let url = url_expr
+                .atom(s.vars())
+                .ok_or_else(|| anyhow_expr!(url_expr, "url should be a string not a list"))?
+                .trim_atom_quotes();
+            let Some((host, path)) = parse_url(url) else {
+                if url.starts_with("https://") {
+                    bail_expr!(
+                        url_expr,
+                        "https is not supported by webhooks, use an http:// url"
+                    );
+                }
+                bail_expr!(
+                    url_expr,
+                    "expected an http:// url, e.g. http://localhost:8080/kanata"
+                );
+            };
+            let events = events_expr
+                .list(s.vars())
+                .ok_or_else(|| {
+                    anyhow_expr!(
+                        events_expr,
+                        "expected a list of events, which can be empty: (layer-change reload error)"
+                    )
+                })?
+                .iter()
+                .map(|event| match event.atom(s.vars()) {
+                    Some("layer-change") => Ok(WebhookEvent::LayerChange),
+                    Some("reload") => Ok(WebhookEvent::Reload),
+                    Some("error") => Ok(WebhookEvent::Error),
+                    _ => bail_expr!(
+                        event,
+                        "unknown webhook event, expected layer-change, reload or error"
+                    ),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            // Names are atoms, so a list after the events is an option of the webhook.
+            let bearer = match exprs.next_if(|expr| expr.list(s.vars()).is_some()) {
+                Some(bearer_expr) => Some(parse_bearer(bearer_expr, s)?),
+                None => None,
+            };
+            let address = if has_port(host) {
+                host.to_string()
+            } else {
+                format!("{host}:80")
+            };
+            webhooks.push(Webhook {
+                name: name.to_string(),
+                address,
+                host: host.to_string(),
+                path,
+                events,
+                bearer,
+            });
+        }
+    }
+    Ok(webhooks)
+}
+
+/// Parses `(bearer <secret>)`.
+fn parse_bearer(expr: &SExpr, s: &ParserState) -> Result<Secret> {
+    const ERR_MSG: &str = "expected (bearer <secret name>), e.g. (bearer ha-token)";
+    let Some([kind, name]) = expr.list(s.vars()) else {
+        bail_expr!(expr, "{ERR_MSG}");
+    };
+    if kind.atom(s.vars()) != Some("bearer") {
+        bail_expr!(kind, "{ERR_MSG}");
+    }
+    match name.atom(s.vars()).map(|name| name.trim_atom_quotes()) {
+        Some(name) if !name.is_empty() => Ok(find_secret(&s.secrets, name)),
+        _ => bail_expr!(name, "{ERR_MSG}"),
+    }
+}
+
+/// Splits an `http://` URL into the host, with the port if any, and the path.
+fn parse_url(url: &str) -> Option<(&str, String)> {
+    let rest = url.strip_prefix("http://")?;
+    let (host, path) = match rest.find(['/', '?', '#']) {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    if host.is_empty() || host.contains('@') {
+        return None;
+    }
+    // The fragment is not sent to the server.
+    let path = path.split('#').next().unwrap_or_default();
+    match path.starts_with('/') {
+        true => Some((host, path.to_string())),
+        false => Some((host, format!("/{path}"))),
+    }
+}
+
+fn has_port(host: &str) -> bool {
+    // The colons of IPv6 addresses are in brackets.
+    match host.rsplit_once(':') {
+        Some((_, port)) => !port.ends_with(']'),
+        None => false,
+    }
+}
+
+pub(crate) fn parse_webhook(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects 1 or 2 parameters: <webhook name> [data]";
+    if ac_params.is_empty() || ac_params.len() > 2 {
+        bail!("{WEBHOOK} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let name = ac_params[0]
+        .atom(s.vars())
+        .ok_or_else(|| anyhow_expr!(&ac_params[0], "{WEBHOOK} {ERR_MSG}"))?;
+    let Some(webhook) = s.webhooks.iter().find(|w| w.name == name) else {
+        bail_expr!(
+            &ac_params[0],
+            "webhook {name} is not defined in {DEFWEBHOOKS}"
+        );
+    };
+    let data = match ac_params.get(1) {
+        Some(expr) => Some(
+            expr.atom(s.vars())
+                .ok_or_else(|| anyhow_expr!(expr, "webhook data should be a string not a list"))?
+                .trim_atom_quotes(),
+        ),
+        None => None,
+    };
+    custom(
+        CustomAction::Webhook {
+            name: s.a.sref_str(webhook.name.clone()),
+            data: data.map(|data| s.a.sref_str(data.to_string())),
+        },
+        &s.a,
+    )
+}
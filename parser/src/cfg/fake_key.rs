@@ -27,13 +27,20 @@ pub(crate) fn parse_fake_keys(exprs: &[&Vec<SExpr>], s: &mut ParserState) -> Res
                 .atom(s.vars())
                 .ok_or_else(|| anyhow_expr!(key_name_expr, "Fake key name must not be a list."))?
                 .to_owned();
-            let action = match subexprs.next() {
+            let mut action = match subexprs.next() {
                 Some(v) => v,
                 None => bail_expr!(
                     key_name_expr,
                     "Fake key name has no action - you should add an action."
                 ),
             };
+            if let Some(doc) = parse_doc(action, s)? {
+                action = match subexprs.next() {
+                    Some(v) => v,
+                    None => bail_expr!(action, "Found doc without an action - add an action"),
+                };
+                s.virtual_key_docs.insert(key_name.clone(), doc);
+            }
             let action = parse_action(action, s)?;
             let idx = s.virtual_keys.len();
             log::trace!("inserting {key_name}->{idx}:{action:?}");
@@ -70,13 +77,20 @@ pub(crate) fn parse_virtual_keys(exprs: &[&Vec<SExpr>], s: &mut ParserState) ->
                 .atom(s.vars())
                 .ok_or_else(|| anyhow_expr!(key_name_expr, "Virtual key name must not be a list."))?
                 .to_owned();
-            let action = match subexprs.next() {
+            let mut action = match subexprs.next() {
                 Some(v) => v,
                 None => bail_expr!(
                     key_name_expr,
                     "Virtual key name has no action - you must add an action."
                 ),
             };
+            if let Some(doc) = parse_doc(action, s)? {
+                action = match subexprs.next() {
+                    Some(v) => v,
+                    None => bail_expr!(action, "Found doc without an action - add an action"),
+                };
+                s.virtual_key_docs.insert(key_name.clone(), doc);
+            }
             let action = parse_action(action, s)?;
             let idx = s.virtual_keys.len();
             log::trace!("inserting {key_name}->{idx}:{action:?}");
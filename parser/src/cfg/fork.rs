@@ -1,6 +1,8 @@
 use super::*;
 
+use crate::anyhow_expr;
 use crate::bail;
+use crate::bail_expr;
 
 pub(crate) fn parse_fork(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
     const ERR_STR: &str =
@@ -10,15 +12,51 @@ pub(crate) fn parse_fork(ac_params: &[SExpr], s: &ParserState) -> Result<&'stati
     }
     let left = *parse_action(&ac_params[0], s)?;
     let right = *parse_action(&ac_params[1], s)?;
-    let right_triggers = s.a.sref_vec(
-        parse_key_list(&ac_params[2], s, "right-trigger-keys")?
-            .into_iter()
-            .map(KeyCode::from)
-            .collect::<Vec<_>>(),
-    );
+    let (right_triggers, right_inputs) = parse_fork_triggers(&ac_params[2], s)?;
     Ok(s.a.sref(Action::Fork(s.a.sref(ForkConfig {
         left,
         right,
-        right_triggers,
+        right_triggers: s.a.sref_vec(right_triggers),
+        right_inputs: s.a.sref_vec(right_inputs),
     }))))
 }
+
+/// Parses the trigger list of `fork`. Keys are checked against the active output keys, and
+/// `(input real|virtual <key>)` items against the held physical and virtual keys.
+fn parse_fork_triggers(expr: &SExpr, s: &ParserState) -> Result<(Vec<KeyCode>, Vec<KCoord>)> {
+    const ERR_STR: &str = "right-trigger-keys must be a list of keys or (input real|virtual <key>)";
+    let Some(items) = expr.list(s.vars()) else {
+        bail_expr!(expr, "{ERR_STR}");
+    };
+    let mut keys = vec![];
+    let mut inputs = vec![];
+    for item in items {
+        if let Some(key) = item.atom(s.vars()) {
+            let osc = str_to_oscode(key)
+                .ok_or_else(|| anyhow_expr!(item, "string of a known key is expected"))?;
+            keys.push(osc.into());
+            continue;
+        }
+        let input = match item.list(s.vars()) {
+            Some([kw, input_type, key]) if kw.atom(s.vars()) == Some("input") => {
+                match input_type.atom(s.vars()) {
+                    Some("real") => {
+                        let osc = key
+                            .atom(s.vars())
+                            .and_then(str_to_oscode)
+                            .ok_or_else(|| anyhow_expr!(key, "invalid input key name"))?;
+                        (NORMAL_KEY_ROW, u16::from(osc))
+                    }
+                    Some("fake" | "virtual") => {
+                        let coord = parse_vkey_coord(key, s)?;
+                        (coord.x, coord.y)
+                    }
+                    _ => bail_expr!(input_type, "key-type must be virtual|real"),
+                }
+            }
+            _ => bail_expr!(item, "{ERR_STR}"),
+        };
+        inputs.push(input);
+    }
+    Ok((keys, inputs))
+}
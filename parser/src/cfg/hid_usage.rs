@@ -0,0 +1,39 @@
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+use crate::bail_expr;
+
+pub(crate) fn parse_hid_usage(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects 2 parameters: <consumer|system> <usage id>";
+    if ac_params.len() != 2 {
+        bail!("{HID_USAGE} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let page = match ac_params[0].atom(s.vars()) {
+        Some("consumer") => HidUsagePage::Consumer,
+        Some("system") => HidUsagePage::System,
+        _ => {
+            bail_expr!(
+                &ac_params[0],
+                "{HID_USAGE} page should be consumer or system"
+            )
+        }
+    };
+    let usage = ac_params[1]
+        .atom(s.vars())
+        .and_then(|usage| match usage.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => usage.parse::<u16>().ok(),
+        })
+        .filter(|usage| *usage > 0)
+        .ok_or_else(|| {
+            anyhow_expr!(
+                &ac_params[1],
+                "{HID_USAGE} usage id should be 1-65535, in decimal or in hex like 0x0183"
+            )
+        })?;
+    custom(CustomAction::HidUsage(HidUsage { page, usage }), &s.a)
+}
@@ -12,9 +12,9 @@ pub(crate) fn create_key_outputs(
     chords_v2: &Option<ChordsV2<'static, KanataCustom>>,
 ) -> KeyOutputs {
     let mut outs = KeyOutputs::new();
-    for (layer_idx, layer) in layers.iter().enumerate() {
+    for layer_idx in 0..layers.len() {
         let mut layer_outputs = HashMap::default();
-        for (i, action) in layer[0].iter().enumerate() {
+        for (i, action) in layers.row(layer_idx, 0).enumerate() {
             let osc_slot = match i.try_into() {
                 Ok(i) => i,
                 Err(_) => continue,
@@ -89,6 +89,16 @@ impl Overrides {
         Self { overrides_by_osc }
     }
 
+    /// Adds overrides with a lower priority than the existing ones, which win when both match.
+    pub fn add_fallbacks(&mut self, overrides: &[Override]) {
+        for o in overrides.iter().rev() {
+            self.overrides_by_osc
+                .entry(o.in_non_mod_osc)
+                .or_default()
+                .insert(0, o.clone());
+        }
+    }
+
     pub fn override_keys(
         &self,
         kcs: &mut Vec<KeyCode>,
@@ -2,44 +2,127 @@ use crate::cfg::*;
 use crate::*;
 
 pub(crate) const DEFLAYER_ICON: [&str; 3] = ["icon", "🖻", "🖼"];
+pub(crate) const DEFLAYER_SOUND: [&str; 2] = ["sound", "🔊"];
+pub(crate) const DEFLAYER_LAYOUT: [&str; 1] = ["layout"];
+pub(crate) const DEFLAYER_ON_ENTER: [&str; 1] = ["on-enter"];
+pub(crate) const DEFLAYER_ON_EXIT: [&str; 1] = ["on-exit"];
+pub(crate) const DEFLAYER_AUTO_RETURN: [&str; 1] = ["auto-return"];
+pub(crate) const DEFLAYER_UNMAPPED: [&str; 1] = ["unmapped"];
+pub(crate) const DEFLAYER_REPEAT: [&str; 1] = ["repeat"];
+pub(crate) const DEFLAYER_DISPLAY_NAME: [&str; 1] = ["display-name"];
+pub(crate) const DEFLAYER_COLOR: [&str; 2] = ["color", "🎨"];
+pub(crate) const DEFLAYER_DOC: [&str; 1] = ["doc"];
+pub(crate) const DEFLAYER_CMD_ENV: [&str; 1] = ["cmd-env"];
+const DEFLAYER_OPTS: [&[&str]; 12] = [
+    &DEFLAYER_ICON,
+    &DEFLAYER_SOUND,
+    &DEFLAYER_LAYOUT,
+    &DEFLAYER_ON_ENTER,
+    &DEFLAYER_ON_EXIT,
+    &DEFLAYER_AUTO_RETURN,
+    &DEFLAYER_UNMAPPED,
+    &DEFLAYER_REPEAT,
+    &DEFLAYER_DISPLAY_NAME,
+    &DEFLAYER_COLOR,
+    &DEFLAYER_DOC,
+    &DEFLAYER_CMD_ENV,
+];
 pub(crate) type LayerIcons = HashMap<String, Option<String>>;
+pub(crate) type LayerSounds = HashMap<String, Option<SoundCue>>;
+pub(crate) type LayerAutoReturns = HashMap<String, Option<u16>>;
+pub(crate) type LayerNoRepeats = HashMap<String, bool>;
+pub(crate) type LayerLayouts = Vec<Option<LayoutTranslation>>;
+pub(crate) type LayerDisplays = HashMap<String, LayerDisplay>;
+pub(crate) type LayerCmdEnvs = HashMap<String, CmdEnv>;
 
-pub fn parse_layer_opts(list: &[SExpr]) -> Result<HashMap<String, String>> {
-    let mut layer_opts: HashMap<String, String> = HashMap::default();
+/// How a layer is shown by tray icons and on-screen displays, with `display-name` and `color`,
+/// and its description for cheat sheets, with `doc`.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct LayerDisplay {
+    pub(crate) display_name: Option<String>,
+    pub(crate) color: Option<String>,
+    pub(crate) doc: Option<String>,
+}
+
+/// Returns whether the color is of the form `#rgb` or `#rrggbb`.
+pub(crate) fn is_hex_color(color: &str) -> bool {
+    hex_color_rgb(color).is_some()
+}
+
+/// Returns the red, green and blue of a color of the form `#rgb` or `#rrggbb`.
+pub fn hex_color_rgb(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+    match hex.len() {
+        3 => {
+            let mut rgb = [0; 3];
+            for (value, i) in rgb.iter_mut().zip(0..3) {
+                *value = channel(&hex[i..i + 1])? * 0x11;
+            }
+            Some(rgb)
+        }
+        6 => Some([
+            channel(&hex[0..2])?,
+            channel(&hex[2..4])?,
+            channel(&hex[4..6])?,
+        ]),
+        _ => None,
+    }
+}
+
+/// The unparsed `on-enter` and `on-exit` actions of a layer. These are parsed after the aliases.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct LayerHookExprs {
+    pub(crate) on_enter: Option<SExpr>,
+    pub(crate) on_exit: Option<SExpr>,
+}
+
+/// Parses the options after the layer name. Only the values of `on-enter`, `on-exit` and
+/// `unmapped` can be lists, since they are actions, and that of `cmd-env`.
+pub fn parse_layer_opts(list: &[SExpr]) -> Result<HashMap<String, SExpr>> {
+    let mut layer_opts: HashMap<String, SExpr> = HashMap::default();
     let mut opts = list.chunks_exact(2);
     for kv in opts.by_ref() {
         let key_expr = &kv[0];
         let val_expr = &kv[1];
         // Read k-v pairs from the configuration
-        // todo: add hashmap for future options, currently only parse icons
         let opt_key = key_expr.atom(None)
             .ok_or_else(|| anyhow_expr!(key_expr, "No lists are allowed in {DEFLAYER} options"))
             .and_then(|opt_key| {
-                if DEFLAYER_ICON.contains(&opt_key) {
-                    if layer_opts.contains_key(DEFLAYER_ICON[0]) {
-                        // separate dupe check since multi-keys are stored
-                        // with one "canonical" repr, so '🖻' → 'icon'
-                        // and this info will be lost after the loop
-                        bail_expr!(
-                            key_expr,
-                            "Duplicate option found in {DEFLAYER}: {opt_key}, one of {DEFLAYER_ICON:?} already exists"
-                        );
+                match DEFLAYER_OPTS.iter().find(|names| names.contains(&opt_key)) {
+                    Some(names) => {
+                        if layer_opts.contains_key(names[0]) {
+                            // separate dupe check since multi-keys are stored
+                            // with one "canonical" repr, so '🖻' → 'icon'
+                            // and this info will be lost after the loop
+                            bail_expr!(
+                                key_expr,
+                                "Duplicate option found in {DEFLAYER}: {opt_key}, one of {names:?} already exists"
+                            );
+                        }
+                        Ok(names[0])
                     }
-                    Ok(DEFLAYER_ICON[0])
-                } else {
-                    bail_expr!(key_expr, "Invalid option in {DEFLAYER}: {opt_key}, expected one of {DEFLAYER_ICON:?}")
+                    None => bail_expr!(key_expr, "Invalid option in {DEFLAYER}: {opt_key}, expected one of {DEFLAYER_OPTS:?}"),
                 }
             })?;
         if layer_opts.contains_key(opt_key) {
             bail_expr!(key_expr, "Duplicate option found in {DEFLAYER}: {opt_key}");
         }
-        let opt_val = val_expr.atom(None).ok_or_else(|| {
-            anyhow_expr!(
+        if val_expr.atom(None).is_none()
+            && opt_key != DEFLAYER_ON_ENTER[0]
+            && opt_key != DEFLAYER_ON_EXIT[0]
+            && opt_key != DEFLAYER_UNMAPPED[0]
+            && opt_key != DEFLAYER_CMD_ENV[0]
+        {
+            bail_expr!(
                 val_expr,
-                "No lists are allowed in {DEFLAYER}'s option values"
-            )
-        })?;
-        layer_opts.insert(opt_key.to_owned(), opt_val.to_owned());
+                "No lists are allowed in {DEFLAYER}'s option values, except for actions"
+            );
+        }
+        layer_opts.insert(opt_key.to_owned(), val_expr.clone());
     }
     let rem = opts.remainder();
     if !rem.is_empty() {
@@ -0,0 +1,107 @@
+//! Built-in translations from QWERTY to other layouts, for the `layout-translation` option of
+//! `defcfg` and the `layout` option of layers. A translation decides what transparent keys output:
+//! the key of the layout at the position of the QWERTY key.
+
+use super::*;
+
+use OsCode::*;
+
+/// Pairs of a QWERTY key and the key at its position in the translated layout.
+pub type LayoutTranslation = &'static [(OsCode, OsCode)];
+
+pub(crate) const LAYOUT_TRANSLATION_NAMES: &str = "colemak | colemak-dh | dvorak | workman";
+
+#[rustfmt::skip]
+const QWERTY: [OsCode; 35] = [
+    KEY_Q, KEY_W, KEY_E, KEY_R, KEY_T, KEY_Y, KEY_U, KEY_I, KEY_O, KEY_P,
+    KEY_LEFTBRACE, KEY_RIGHTBRACE,
+    KEY_A, KEY_S, KEY_D, KEY_F, KEY_G, KEY_H, KEY_J, KEY_K, KEY_L, KEY_SEMICOLON,
+    KEY_APOSTROPHE,
+    KEY_Z, KEY_X, KEY_C, KEY_V, KEY_B, KEY_N, KEY_M, KEY_COMMA, KEY_DOT, KEY_SLASH,
+    KEY_MINUS, KEY_EQUAL,
+];
+
+const fn translation(layout: [OsCode; 35]) -> [(OsCode, OsCode); 35] {
+    let mut pairs = [(KEY_Q, KEY_Q); 35];
+    let mut i = 0;
+    while i < 35 {
+        pairs[i] = (QWERTY[i], layout[i]);
+        i += 1;
+    }
+    pairs
+}
+
+#[rustfmt::skip]
+const COLEMAK: [(OsCode, OsCode); 35] = translation([
+    KEY_Q, KEY_W, KEY_F, KEY_P, KEY_G, KEY_J, KEY_L, KEY_U, KEY_Y, KEY_SEMICOLON,
+    KEY_LEFTBRACE, KEY_RIGHTBRACE,
+    KEY_A, KEY_R, KEY_S, KEY_T, KEY_D, KEY_H, KEY_N, KEY_E, KEY_I, KEY_O,
+    KEY_APOSTROPHE,
+    KEY_Z, KEY_X, KEY_C, KEY_V, KEY_B, KEY_K, KEY_M, KEY_COMMA, KEY_DOT, KEY_SLASH,
+    KEY_MINUS, KEY_EQUAL,
+]);
+
+#[rustfmt::skip]
+const COLEMAK_DH: [(OsCode, OsCode); 35] = translation([
+    KEY_Q, KEY_W, KEY_F, KEY_P, KEY_B, KEY_J, KEY_L, KEY_U, KEY_Y, KEY_SEMICOLON,
+    KEY_LEFTBRACE, KEY_RIGHTBRACE,
+    KEY_A, KEY_R, KEY_S, KEY_T, KEY_G, KEY_M, KEY_N, KEY_E, KEY_I, KEY_O,
+    KEY_APOSTROPHE,
+    KEY_Z, KEY_X, KEY_C, KEY_D, KEY_V, KEY_K, KEY_H, KEY_COMMA, KEY_DOT, KEY_SLASH,
+    KEY_MINUS, KEY_EQUAL,
+]);
+
+#[rustfmt::skip]
+const DVORAK: [(OsCode, OsCode); 35] = translation([
+    KEY_APOSTROPHE, KEY_COMMA, KEY_DOT, KEY_P, KEY_Y, KEY_F, KEY_G, KEY_C, KEY_R, KEY_L,
+    KEY_SLASH, KEY_EQUAL,
+    KEY_A, KEY_O, KEY_E, KEY_U, KEY_I, KEY_D, KEY_H, KEY_T, KEY_N, KEY_S,
+    KEY_MINUS,
+    KEY_SEMICOLON, KEY_Q, KEY_J, KEY_K, KEY_X, KEY_B, KEY_M, KEY_W, KEY_V, KEY_Z,
+    KEY_LEFTBRACE, KEY_RIGHTBRACE,
+]);
+
+#[rustfmt::skip]
+const WORKMAN: [(OsCode, OsCode); 35] = translation([
+    KEY_Q, KEY_D, KEY_R, KEY_W, KEY_B, KEY_J, KEY_F, KEY_U, KEY_P, KEY_SEMICOLON,
+    KEY_LEFTBRACE, KEY_RIGHTBRACE,
+    KEY_A, KEY_S, KEY_H, KEY_T, KEY_G, KEY_Y, KEY_N, KEY_E, KEY_O, KEY_I,
+    KEY_APOSTROPHE,
+    KEY_Z, KEY_X, KEY_M, KEY_C, KEY_V, KEY_K, KEY_L, KEY_COMMA, KEY_DOT, KEY_SLASH,
+    KEY_MINUS, KEY_EQUAL,
+]);
+
+pub(crate) fn layout_translation(name: &str) -> Option<LayoutTranslation> {
+    match name {
+        "colemak" => Some(&COLEMAK),
+        "colemak-dh" => Some(&COLEMAK_DH),
+        "dvorak" => Some(&DVORAK),
+        "workman" => Some(&WORKMAN),
+        _ => None,
+    }
+}
+
+/// Makes the transparent keys of the layer output the keys of the translation.
+pub(crate) fn translate_transparent_keys(
+    layer: &mut [KanataAction],
+    translation: LayoutTranslation,
+) {
+    for &(qwerty, translated) in translation {
+        let action = &mut layer[usize::from(qwerty)];
+        if *action == Action::Trans {
+            *action = Action::KeyCode(translated.into());
+        }
+    }
+}
+
+#[test]
+fn translations_are_permutations() {
+    for name in LAYOUT_TRANSLATION_NAMES.split(" | ") {
+        let translation = layout_translation(name).expect("name is listed");
+        let mut translated: Vec<_> = translation.iter().map(|(_, k)| *k).collect();
+        translated.sort_by_key(|k| u16::from(*k));
+        let mut qwerty = QWERTY.to_vec();
+        qwerty.sort_by_key(|k| u16::from(*k));
+        assert_eq!(qwerty, translated, "{name}");
+    }
+}
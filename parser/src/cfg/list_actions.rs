@@ -31,6 +31,7 @@ pub const MACRO_RELEASE_CANCEL_A: &str = "macro↑⤫";
 pub const MACRO_REPEAT_RELEASE_CANCEL: &str = "macro-repeat-release-cancel";
 pub const MACRO_REPEAT_RELEASE_CANCEL_A: &str = "macro⟳↑⤫";
 pub const MACRO_CANCEL_ON_NEXT_PRESS: &str = "macro-cancel-on-press";
+pub const MACRO_DELAY_SCALE: &str = "macro-delay-scale";
 pub const MACRO_REPEAT_CANCEL_ON_NEXT_PRESS: &str = "macro-repeat-cancel-on-press";
 pub const MACRO_CANCEL_ON_NEXT_PRESS_CANCEL_ON_RELEASE: &str =
     "macro-release-cancel-and-cancel-on-press";
@@ -98,6 +99,10 @@ pub const MOVEMOUSE_ACCEL_RIGHT_A: &str = "🖱accel→";
 pub const MOVEMOUSE_SPEED_A: &str = "🖱speed";
 pub const SETMOUSE: &str = "setmouse";
 pub const SETMOUSE_A: &str = "set🖱";
+pub const MOUSE_GRID: &str = "mouse-grid";
+pub const MOUSE_GRID_MONITOR: &str = "mouse-grid-monitor";
+pub const MOUSE_TOWARD: &str = "mouse-toward";
+pub const JIGGLE: &str = "jiggle";
 pub const DYNAMIC_MACRO_RECORD: &str = "dynamic-macro-record";
 pub const DYNAMIC_MACRO_PLAY: &str = "dynamic-macro-play";
 pub const ARBITRARY_CODE: &str = "arbitrary-code";
@@ -105,7 +110,10 @@ pub const CMD: &str = "cmd";
 pub const CMD_LOG: &str = "cmd-log";
 pub const PUSH_MESSAGE: &str = "push-msg";
 pub const CMD_OUTPUT_KEYS: &str = "cmd-output-keys";
+pub const CMD_ARGV: &str = "cmd-argv";
+pub const CMD_CANCEL: &str = "cmd-cancel";
 pub const FORK: &str = "fork";
+pub const MOD_MORPH: &str = "mod-morph";
 pub const CAPS_WORD: &str = "caps-word";
 pub const CAPS_WORD_A: &str = "word⇪";
 pub const CAPS_WORD_CUSTOM: &str = "caps-word-custom";
@@ -118,6 +126,7 @@ pub const DYNAMIC_MACRO_RECORD_STOP_TRUNCATE: &str = "dynamic-macro-record-stop-
 pub const SWITCH: &str = "switch";
 pub const SEQUENCE: &str = "sequence";
 pub const SEQUENCE_NOERASE: &str = "sequence-noerase";
+pub const COMPOSE: &str = "compose";
 pub const UNMOD: &str = "unmod";
 pub const UNSHIFT: &str = "unshift";
 pub const UNSHIFT_A: &str = "un⇧";
@@ -140,6 +149,23 @@ pub const CLIPBOARD_SAVE_SWAP: &str = "clipboard-save-swap";
 pub const TAP_HOLD_ORDER: &str = "tap-hold-order";
 pub const TAP_HOLD_OPPOSITE_HAND: &str = "tap-hold-opposite-hand";
 pub const TAP_HOLD_OPPOSITE_HAND_RELEASE: &str = "tap-hold-opposite-hand-release";
+pub const TAP_HOLD_FLAVOR: &str = "tap-hold-flavor";
+pub const MIDI_NOTE: &str = "midi-note";
+pub const MIDI_CC: &str = "midi-cc";
+pub const OBS: &str = "obs";
+pub const OPENRGB: &str = "openrgb";
+pub const WEBHOOK: &str = "webhook";
+pub const PLUGIN: &str = "plugin";
+pub const NOTIFY: &str = "notify";
+pub const MPRIS: &str = "mpris";
+pub const OS_LAYOUT: &str = "os-layout";
+pub const HID_USAGE: &str = "hid-usage";
+pub const DATETIME: &str = "datetime";
+pub const LOG_LEVEL: &str = "log-level";
+pub const SECRET_TYPE: &str = "secret-type";
+pub const VAR_SET: &str = "var-set";
+pub const ALIAS_CONCAT: &str = "alias-concat";
+pub const TRAINING_MODE: &str = "training-mode";
 
 pub fn is_list_action(ac: &str) -> bool {
     const LIST_ACTIONS: &[&str] = &[
@@ -232,14 +258,21 @@ pub fn is_list_action(ac: &str) -> bool {
         MOVEMOUSE_SPEED_A,
         SETMOUSE,
         SETMOUSE_A,
+        MOUSE_GRID,
+        MOUSE_GRID_MONITOR,
+        MOUSE_TOWARD,
+        JIGGLE,
         DYNAMIC_MACRO_RECORD,
         DYNAMIC_MACRO_PLAY,
         ARBITRARY_CODE,
         CMD,
         CMD_OUTPUT_KEYS,
         CMD_LOG,
+        CMD_ARGV,
+        CMD_CANCEL,
         PUSH_MESSAGE,
         FORK,
+        MOD_MORPH,
         CAPS_WORD,
         CAPS_WORD_A,
         CAPS_WORD_TOGGLE,
@@ -252,6 +285,7 @@ pub fn is_list_action(ac: &str) -> bool {
         SWITCH,
         SEQUENCE,
         SEQUENCE_NOERASE,
+        COMPOSE,
         UNMOD,
         UNSHIFT,
         UNSHIFT_A,
@@ -265,6 +299,7 @@ pub fn is_list_action(ac: &str) -> bool {
         ON_PHYSICAL_IDLE,
         HOLD_FOR_DURATION,
         MACRO_CANCEL_ON_NEXT_PRESS,
+        MACRO_DELAY_SCALE,
         MACRO_REPEAT_CANCEL_ON_NEXT_PRESS,
         MACRO_CANCEL_ON_NEXT_PRESS_CANCEL_ON_RELEASE,
         MACRO_REPEAT_CANCEL_ON_NEXT_PRESS_CANCEL_ON_RELEASE,
@@ -279,6 +314,23 @@ pub fn is_list_action(ac: &str) -> bool {
         TAP_HOLD_ORDER,
         TAP_HOLD_OPPOSITE_HAND,
         TAP_HOLD_OPPOSITE_HAND_RELEASE,
+        TAP_HOLD_FLAVOR,
+        MIDI_NOTE,
+        MIDI_CC,
+        OBS,
+        OPENRGB,
+        WEBHOOK,
+        PLUGIN,
+        NOTIFY,
+        MPRIS,
+        OS_LAYOUT,
+        HID_USAGE,
+        DATETIME,
+        LOG_LEVEL,
+        SECRET_TYPE,
+        VAR_SET,
+        ALIAS_CONCAT,
+        TRAINING_MODE,
     ];
     LIST_ACTIONS.contains(&ac)
 }
@@ -0,0 +1,35 @@
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+
+pub(crate) fn parse_log_level(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str =
+        "expects 1 or 2 parameters: <trace|debug|info|warn|error|off> [duration ms]";
+    if ac_params.is_empty() || ac_params.len() > 2 {
+        bail!("{LOG_LEVEL} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let level = ac_params[0]
+        .atom(s.vars())
+        .and_then(|level| level.parse::<log::LevelFilter>().ok())
+        .ok_or_else(|| {
+            anyhow_expr!(
+                &ac_params[0],
+                "{LOG_LEVEL} level must be one of: trace, debug, info, warn, error, off"
+            )
+        })?;
+    let duration_ms = match ac_params.get(1) {
+        Some(expr) => expr
+            .atom(s.vars())
+            .and_then(|ms| ms.parse::<u32>().ok())
+            .filter(|ms| *ms > 0)
+            .ok_or_else(|| {
+                anyhow_expr!(expr, "{LOG_LEVEL} duration must be a positive number of ms")
+            })?,
+        None => 0,
+    };
+    custom(CustomAction::LogLevel { level, duration_ms }, &s.a)
+}
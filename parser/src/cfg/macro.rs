@@ -6,6 +6,13 @@ use crate::bail_expr;
 
 const MACRO_ERR: &str = "Action macro only accepts delays, keys, chords, chorded sub-macros, and a subset of special actions.\nThe macro section of the documentation describes this in more detail:\nhttps://github.com/jtroo/kanata/blob/main/docs/config.adoc#macro";
 
+const MACRO_REPEAT_ERR: &str =
+    "repeat within a macro expects a repeat count followed by the macro items to repeat";
+
+/// The most events that `repeat` within a macro can create, to bound the memory of nested
+/// repeats.
+const MACRO_REPEAT_MAX_EVENTS: usize = 65536;
+
 pub(crate) enum RepeatMacro {
     Yes,
     No,
@@ -117,6 +124,18 @@ pub(crate) fn parse_macro_record_stop_truncate(
     custom(CustomAction::DynamicMacroRecordStop(num_to_truncate), &s.a)
 }
 
+pub(crate) fn parse_macro_delay_scale(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_STR: &str = "macro-delay-scale expects 1 param: <percent>";
+    if ac_params.len() != 1 {
+        bail!("{ERR_STR}\nFound {} params instead of 1", ac_params.len());
+    }
+    let percent = parse_non_zero_u16(&ac_params[0], s, "percent")?;
+    custom(CustomAction::MacroDelayScale(percent), &s.a)
+}
+
 #[derive(PartialEq)]
 pub(crate) enum MacroNumberParseMode {
     Delay,
@@ -137,6 +156,9 @@ pub(crate) fn parse_macro_item_impl<'a>(
     s: &ParserState,
     num_parse_mode: MacroNumberParseMode,
 ) -> Result<(Vec<SequenceEvent<'static, KanataCustom>>, &'a [SExpr])> {
+    if let Some(repeat) = acs[0].list(s.vars()).filter(|l| is_macro_repeat(l, s)) {
+        return Ok((parse_macro_repeat(repeat, &acs[0], s)?, &acs[1..]));
+    }
     if num_parse_mode == MacroNumberParseMode::Delay {
         if let Some(a) = acs[0].atom(s.vars()) {
             match parse_non_zero_u16(&acs[0], s, "delay") {
@@ -226,11 +248,16 @@ pub(crate) fn parse_macro_item_impl<'a>(
                         .ok_or_else(|| anyhow_expr!(&acs[1], "{MACRO_ERR}"))?
                 }
             };
-            let mut submacro_remainder = submacro;
-            let mut events;
-            while !submacro_remainder.is_empty() {
-                (events, submacro_remainder) = parse_macro_item(submacro_remainder, s)?;
-                all_events.append(&mut events);
+            if is_macro_repeat(submacro, s) {
+                let repeat_expr = if rem_start == 1 { &acs[0] } else { &acs[1] };
+                all_events.append(&mut parse_macro_repeat(submacro, repeat_expr, s)?);
+            } else {
+                let mut submacro_remainder = submacro;
+                let mut events;
+                while !submacro_remainder.is_empty() {
+                    (events, submacro_remainder) = parse_macro_item(submacro_remainder, s)?;
+                    all_events.append(&mut events);
+                }
             }
 
             // Lastly, release modifiers
@@ -243,6 +270,37 @@ pub(crate) fn parse_macro_item_impl<'a>(
     }
 }
 
+fn is_macro_repeat(items: &[SExpr], s: &ParserState) -> bool {
+    items.first().and_then(|item| item.atom(s.vars())) == Some("repeat")
+}
+
+/// Parses `(repeat <count> <macro items>...)` within a macro into the events of the items
+/// repeated `count` times.
+fn parse_macro_repeat(
+    items: &[SExpr],
+    repeat_expr: &SExpr,
+    s: &ParserState,
+) -> Result<Vec<SequenceEvent<'static, KanataCustom>>> {
+    if items.len() < 3 {
+        bail_expr!(repeat_expr, "{MACRO_REPEAT_ERR}");
+    }
+    let count = parse_non_zero_u16(&items[1], s, "repeat count")?;
+    let mut repeated_events = vec![];
+    let mut remainder = &items[2..];
+    while !remainder.is_empty() {
+        let mut events;
+        (events, remainder) = parse_macro_item(remainder, s)?;
+        repeated_events.append(&mut events);
+    }
+    if repeated_events.len().saturating_mul(usize::from(count)) > MACRO_REPEAT_MAX_EVENTS {
+        bail_expr!(
+            repeat_expr,
+            "repeat within a macro creates too many events, the maximum is {MACRO_REPEAT_MAX_EVENTS}"
+        );
+    }
+    Ok(repeated_events.repeat(usize::from(count)))
+}
+
 /// Parses mod keys like `C-S-`. Returns the `KeyCode`s for the modifiers parsed and the unparsed
 /// text after any parsed modifier prefixes.
 pub(crate) fn parse_mods_held_for_submacro<'a>(
@@ -0,0 +1,59 @@
+use super::*;
+
+use crate::bail;
+
+const DEFAULT_MIDI_VELOCITY: u8 = 100;
+const DEFAULT_MIDI_CHANNEL: u8 = 1;
+
+pub(crate) fn parse_midi_note(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str =
+        "expects 1 to 3 parameters: <note (0-127)> [velocity (0-127)] [channel (1-16)]";
+    if ac_params.is_empty() || ac_params.len() > 3 {
+        bail!("{MIDI_NOTE} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let note = parse_u8_with_range(&ac_params[0], s, "midi note", 0, 127)?;
+    let velocity = match ac_params.get(1) {
+        Some(expr) => parse_u8_with_range(expr, s, "midi velocity", 0, 127)?,
+        None => DEFAULT_MIDI_VELOCITY,
+    };
+    let channel = parse_midi_channel(ac_params.get(2), s)?;
+    custom(
+        CustomAction::MidiNote(MidiNote {
+            channel,
+            note,
+            velocity,
+        }),
+        &s.a,
+    )
+}
+
+pub(crate) fn parse_midi_cc(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str =
+        "expects 2 or 3 parameters: <controller (0-127)> <value (0-127)> [channel (1-16)]";
+    if ac_params.len() < 2 || ac_params.len() > 3 {
+        bail!("{MIDI_CC} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let controller = parse_u8_with_range(&ac_params[0], s, "midi controller", 0, 127)?;
+    let value = parse_u8_with_range(&ac_params[1], s, "midi controller value", 0, 127)?;
+    let channel = parse_midi_channel(ac_params.get(2), s)?;
+    custom(
+        CustomAction::MidiControlChange(MidiControlChange {
+            channel,
+            controller,
+            value,
+        }),
+        &s.a,
+    )
+}
+
+/// Parses the user-facing 1-16 channel number and returns the 0-15 wire value.
+fn parse_midi_channel(expr: Option<&SExpr>, s: &ParserState) -> Result<u8> {
+    let channel = match expr {
+        Some(expr) => parse_u8_with_range(expr, s, "midi channel", 1, 16)?,
+        None => DEFAULT_MIDI_CHANNEL,
+    };
+    Ok(channel - 1)
+}
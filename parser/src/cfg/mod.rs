@@ -38,10 +38,18 @@
 //!
 //! The specific values in example above applies to Linux, but the same logic applies to Windows.
 
+mod action_label;
+pub use action_label::*;
+mod alias_concat;
+use alias_concat::*;
 pub(crate) mod alloc;
 use alloc::*;
 mod arbitrary_code;
 use arbitrary_code::*;
+pub mod ast;
+mod autoshift;
+use autoshift::*;
+pub mod builder;
 mod caps_word;
 use caps_word::*;
 mod chord;
@@ -51,9 +59,18 @@ use chord_v1::*;
 mod clipboard;
 use clipboard::*;
 mod cmd;
+pub use cmd::CmdAllowlist;
 use cmd::*;
+mod compose;
+pub use compose::*;
+mod custom_shift;
+use custom_shift::*;
 mod custom_tap_hold;
 use custom_tap_hold::*;
+mod datetime;
+use datetime::*;
+mod defapp;
+pub use defapp::*;
 mod defcfg;
 pub use defcfg::*;
 mod definputdevices;
@@ -64,12 +81,31 @@ use defhands::{
 };
 mod deflocalkeys;
 use deflocalkeys::*;
+pub use deflocalkeys::{os_keyboard_layout, set_os_keyboard_layout};
 mod defsrc;
 use defsrc::*;
 mod deflayer;
 use deflayer::*;
+mod defdebounce;
+pub use defdebounce::*;
+mod defmeta;
+pub use defmeta::*;
+mod defrepeat;
+pub use defrepeat::*;
+
+mod defsecrets;
+pub use defsecrets::*;
+mod defwebhooks;
+pub use defwebhooks::*;
+
+mod defplugins;
+pub use defplugins::*;
+mod deftaphold_flavor;
+use deftaphold_flavor::*;
 mod deftemplate;
 pub use deftemplate::*;
+mod deftest;
+pub use deftest::*;
 mod error;
 pub use error::*;
 mod fake_key;
@@ -77,6 +113,8 @@ use fake_key::*;
 mod fork;
 pub use fake_key::{FAKE_KEY_ROW, NORMAL_KEY_ROW};
 use fork::*;
+mod hid_usage;
+use hid_usage::*;
 mod is_a_button;
 use is_a_button::*;
 mod live_reload;
@@ -87,14 +125,32 @@ mod key_override;
 pub use key_override::*;
 pub mod layer_opts;
 use layer_opts::*;
+mod layout_translation;
+pub use layout_translation::*;
 pub mod list_actions;
 use list_actions::*;
+mod log_level;
+use log_level::*;
 mod r#macro;
 use r#macro::*;
+mod midi;
+use midi::*;
+mod mod_morph;
+use mod_morph::*;
 mod mouse;
 use mouse::*;
+mod mpris;
+use mpris::*;
 mod multi;
 use multi::*;
+mod notify;
+use notify::*;
+mod os_layout;
+use os_layout::*;
+mod obs;
+use obs::*;
+mod openrgb;
+use openrgb::*;
 mod oneshot;
 use oneshot::*;
 mod r#override;
@@ -108,6 +164,8 @@ pub use push_msg::SimpleSExpr;
 use push_msg::*;
 mod releases;
 use releases::*;
+mod secret;
+use secret::*;
 mod sequence;
 use sequence::*;
 pub mod sexpr;
@@ -118,8 +176,13 @@ mod switch;
 pub use switch::*;
 mod tap_dance;
 use tap_dance::*;
+mod template_cache;
+pub use template_cache::set_template_cache_dir;
+use template_cache::*;
 mod tap_hold;
 use tap_hold::*;
+mod training_mode;
+use training_mode::*;
 mod unicode;
 use unicode::*;
 mod unmod;
@@ -255,7 +318,19 @@ type KLayout = Layout<'static, KEYS_IN_ROW, 2, KanataCustom>;
 type TapHoldCustomFunc = fn(&[OsCode], &Allocations) -> &'static custom_tap_hold::CustomTapHoldFn;
 
 pub type BorrowedKLayout<'a> = Layout<'a, KEYS_IN_ROW, 2, &'a CustomAction>;
-pub type KeySeqsToFKeys = Trie<(u8, u16)>;
+pub type KeySeqsToFKeys = Trie<SequenceOutput>;
+
+/// What a completed `defseq` sequence does.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SequenceOutput {
+    /// The coordinate of the virtual key to tap.
+    pub coord: (u8, u16),
+    /// Bit `i` is set if key `i` of the sequence was written with `N-` and must be typed without
+    /// modifiers. Overlap markers do not count as keys.
+    pub unmodded_steps: u64,
+    /// Whether the modifiers held when the sequence completes stay held for the virtual key.
+    pub pass_mods: bool,
+}
 
 pub struct KanataLayout {
     layout: KLayout,
@@ -301,14 +376,45 @@ pub struct Cfg {
     pub sequences: KeySeqsToFKeys,
     /// Overrides defined in `defoverrides`.
     pub overrides: Overrides,
+    /// Compose sequences defined in `defcompose`.
+    pub compose: ComposeTable,
     /// Mapping of fake key name to its column in the fake key row.
     pub fake_keys: HashMap<String, usize>,
+    /// Mapping of layer name to its index in `layer_info` and the layout.
+    pub layer_ids: HashMap<String, usize>,
+    /// The tap-hold actions of aliases by alias name, whose parameters can be tuned at runtime.
+    pub hold_tap_aliases: HashMap<String, &'static HoldTapAction<'static, KanataCustom>>,
     /// The maximum value of switch's key-timing item in the configuration.
     pub max_key_timing_check: u16,
     /// Zipchord-like configuration.
     pub zippy: Option<(ZchPossibleChords, ZchConfig)>,
     /// Input device ID mappings from `definputdevices`.
     pub input_devices: Option<Vec<(std::num::NonZeroU8, InputDeviceMatcher)>>,
+    /// Per-key repeat behaviour from `defrepeat` and `defrepeat-layer`.
+    pub key_repeat: KeyRepeatCfg,
+    /// Debouncing of the input from `defdebounce`.
+    pub debounce: Vec<Debounce>,
+    /// Webhooks defined in `defwebhooks`.
+    pub webhooks: Vec<Webhook>,
+    /// Secrets defined in `defsecrets`.
+    pub secrets: Vec<Secret>,
+    /// Plugins granted in `defplugins`.
+    pub plugins: Vec<PluginGrant>,
+    /// Applications defined in `defapp`.
+    pub apps: Vec<App>,
+    /// Tests defined in `deftest`.
+    pub tests: Vec<ConfigTest>,
+    /// The description of the configuration from `defmeta`.
+    pub meta: ConfigMeta,
+    /// The descriptions of aliases from `(doc ...)` in `defalias`, by alias name.
+    pub alias_docs: HashMap<String, String>,
+    /// The descriptions of virtual keys from `(doc ...)` in `defvirtualkeys` and `deffakekeys`,
+    /// by virtual key name.
+    pub virtual_key_docs: HashMap<String, String>,
+    /// Whether the `deflocalkeys` of this OS has blocks for specific OS keyboard layouts.
+    pub localkeys_for_os_layouts: bool,
+    /// The canonical paths of the configuration file and the files it includes.
+    pub files: Vec<PathBuf>,
 }
 
 /// Parse a new configuration from a file.
@@ -316,6 +422,29 @@ pub fn new_from_file(p: &Path) -> MResult<Cfg> {
     parse_cfg(p)
 }
 
+/// Parse a new configuration from text as if it were the content of the file at `p`, e.g. an
+/// unsaved editor buffer. Includes are read relative to the directory of `p`.
+pub fn new_from_str_at_path(cfg_text: &str, p: &Path) -> MResult<Cfg> {
+    new_from_str_at_path_with(cfg_text, p, &mut read_file)
+}
+
+/// Like [`new_from_str_at_path`], with the included files read by `read_file`, which is given
+/// their canonical path, e.g. to refuse files that belong to another user.
+pub fn new_from_str_at_path_with(
+    cfg_text: &str,
+    p: &Path,
+    read_file: &mut dyn FnMut(&Path) -> std::result::Result<String, String>,
+) -> MResult<Cfg> {
+    let mut s = ParserState::default();
+    let icfg = parse_cfg_raw_with_text(p, Some(cfg_text), read_file, &mut s)?;
+    log::info!("config file is valid");
+    Ok(populate_cfg_with_icfg(icfg, s))
+}
+
+fn read_file(path: &Path) -> std::result::Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
 pub fn new_from_str(cfg_text: &str, file_content: HashMap<String, String>) -> MResult<Cfg> {
     let mut s = ParserState::default();
     let icfg = parse_cfg_raw_string(
@@ -344,6 +473,25 @@ pub struct LayerInfo {
     pub name: String,
     pub cfg_text: String,
     pub icon: Option<String>,
+    /// Sound played when entering the layer, overriding `sound-layer-change` in `defcfg`.
+    pub sound: Option<SoundCue>,
+    /// Milliseconds without input after which the layer, while it is the default layer, switches
+    /// back to the layer it was switched from.
+    pub auto_return: Option<u16>,
+    /// The virtual key that is tapped when the layer becomes the current layer.
+    pub on_enter: Option<(u8, u16)>,
+    /// The virtual key that is tapped when the layer stops being the current layer.
+    pub on_exit: Option<(u8, u16)>,
+    /// Whether the keys that are pressed on the layer are not repeated, with `repeat none`.
+    pub no_repeat: bool,
+    /// The name to show for the layer instead of its identifier, with `display-name`.
+    pub display_name: Option<String>,
+    /// The color to show for the layer, `#rgb` or `#rrggbb`, with `color`.
+    pub color: Option<String>,
+    /// The description of the layer, with `doc`.
+    pub doc: Option<String>,
+    /// The environment variables of cmd actions while the layer is active, with `cmd-env`.
+    pub cmd_env: CmdEnv,
 }
 
 #[allow(clippy::type_complexity)] // return type is not pub
@@ -386,6 +534,16 @@ fn populate_cfg_with_icfg(icfg: IntermediateCfg, s: ParserState) -> Cfg {
         .map(|(k, v)| (k.clone(), v.0))
         .collect();
     fake_keys.shrink_to_fit();
+    let mut layer_ids = s.layer_idxs;
+    layer_ids.shrink_to_fit();
+    let hold_tap_aliases = s
+        .aliases
+        .iter()
+        .filter_map(|(name, action)| match action {
+            Action::HoldTap(hold_tap) => Some((name.clone(), *hold_tap)),
+            _ => None,
+        })
+        .collect();
     Cfg {
         options: icfg.options,
         mapped_keys: icfg.mapped_keys,
@@ -394,10 +552,25 @@ fn populate_cfg_with_icfg(icfg: IntermediateCfg, s: ParserState) -> Cfg {
         layout,
         sequences: icfg.sequences,
         overrides: icfg.overrides,
+        compose: icfg.compose,
         fake_keys,
+        layer_ids,
+        hold_tap_aliases,
         max_key_timing_check,
         zippy: icfg.zippy,
         input_devices: s.input_devices,
+        key_repeat: icfg.key_repeat,
+        debounce: icfg.debounce,
+        webhooks: icfg.webhooks,
+        secrets: s.secrets,
+        plugins: s.plugins,
+        apps: icfg.apps,
+        tests: icfg.tests,
+        meta: icfg.meta,
+        alias_docs: icfg.alias_docs,
+        virtual_key_docs: icfg.virtual_key_docs,
+        localkeys_for_os_layouts: icfg.localkeys_for_os_layouts,
+        files: icfg.files,
     }
 }
 
@@ -409,9 +582,20 @@ pub struct IntermediateCfg {
     pub klayers: KanataLayers,
     pub sequences: KeySeqsToFKeys,
     pub overrides: Overrides,
+    pub compose: ComposeTable,
     pub chords_v2: Option<ChordsV2<'static, KanataCustom>>,
     pub start_action: Option<&'static KanataAction>,
     pub zippy: Option<(ZchPossibleChords, ZchConfig)>,
+    pub key_repeat: KeyRepeatCfg,
+    pub debounce: Vec<Debounce>,
+    pub webhooks: Vec<Webhook>,
+    pub apps: Vec<App>,
+    pub tests: Vec<ConfigTest>,
+    pub meta: ConfigMeta,
+    pub alias_docs: HashMap<String, String>,
+    pub virtual_key_docs: HashMap<String, String>,
+    pub localkeys_for_os_layouts: bool,
+    pub files: Vec<PathBuf>,
 }
 
 // A snapshot of enviroment variables, or an error message with an explanation
@@ -420,9 +604,27 @@ pub type EnvVars = std::result::Result<Vec<(String, String)>, String>;
 
 #[allow(clippy::type_complexity)] // return type is not pub
 fn parse_cfg_raw(p: &Path, s: &mut ParserState) -> MResult<IntermediateCfg> {
+    parse_cfg_raw_with_text(p, None, &mut read_file, s)
+}
+
+/// Parses the configuration at `p`, using `text` as its content instead of reading the file if it
+/// is given. Files are read with `read_file`.
+#[allow(clippy::type_complexity)] // return type is not pub
+fn parse_cfg_raw_with_text(
+    p: &Path,
+    text: Option<&str>,
+    read_file: &mut dyn FnMut(&Path) -> std::result::Result<String, String>,
+    s: &mut ParserState,
+) -> MResult<IntermediateCfg> {
     const INVALID_PATH_ERROR: &str = "The provided config file path is not valid";
 
     let mut loaded_files: HashSet<PathBuf> = HashSet::default();
+    if text.is_some() {
+        // The text stands in for the file, so the file must not be included again either.
+        if let Ok(abs_filepath) = p.canonicalize() {
+            loaded_files.insert(abs_filepath);
+        }
+    }
 
     let mut get_file_content_fn_impl = |filepath: &Path| {
         // Make the include paths relative to main config file instead of kanata executable.
@@ -448,7 +650,7 @@ fn parse_cfg_raw(p: &Path, s: &mut ParserState) -> MResult<IntermediateCfg> {
             return Err("The provided config file was already included before".to_string());
         };
 
-        std::fs::read_to_string(abs_filepath.to_str().ok_or(INVALID_PATH_ERROR)?)
+        read_file(Path::new(abs_filepath.to_str().ok_or(INVALID_PATH_ERROR)?))
             .map_err(|e| format!("Failed to include file: {e}"))
     };
     let mut file_content_provider = FileContentProvider::new(&mut get_file_content_fn_impl);
@@ -459,21 +661,26 @@ fn parse_cfg_raw(p: &Path, s: &mut ParserState) -> MResult<IntermediateCfg> {
         .file_name()
         .ok_or_else(|| miette::miette!(INVALID_PATH_ERROR))?
         .into();
-    let text = file_content_provider
-        .get_file_content(&cfg_file_name)
-        .map_err(|e| miette::miette!(e))?;
+    let text = match text {
+        Some(text) => text.to_owned(),
+        None => file_content_provider
+            .get_file_content(&cfg_file_name)
+            .map_err(|e| miette::miette!(e))?,
+    };
 
     let env_vars: EnvVars = Ok(std::env::vars().collect());
 
-    parse_cfg_raw_string(
+    let mut icfg = parse_cfg_raw_string(
         &text,
         s,
         p,
         &mut file_content_provider,
         DEF_LOCAL_KEYS,
         env_vars,
-    )
-    .map_err(|e| e.into())
+    )?;
+    icfg.files = loaded_files.into_iter().collect();
+    icfg.files.sort();
+    Ok(icfg)
 }
 
 fn expand_includes(
@@ -545,7 +752,7 @@ pub fn parse_cfg_raw_string(
             filter_platform_specific_cfg(xs, def_local_keys_variant_to_apply, &mut lsp_hints)
         })
         .and_then(|xs| filter_env_specific_cfg(xs, &env_vars, &mut lsp_hints))
-        .and_then(|xs| expand_templates(xs, &mut lsp_hints))?;
+        .and_then(|xs| expand_templates_cached(xs, cfg_path, &mut lsp_hints))?;
 
     if let Some(spanned) = spanned_root_exprs
         .iter()
@@ -560,46 +767,66 @@ pub fn parse_cfg_raw_string(
 
     let mut local_keys: Option<HashMap<String, OsCode>> = None;
     clear_custom_str_oscode_mapping();
+    let os_layout = os_keyboard_layout();
+    let mut localkeys_for_os_layouts = false;
     for def_local_keys_variant in DEFLOCALKEYS_VARIANTS {
-        let Some((result, _span)) = spanned_root_exprs
+        let mut blocks = vec![];
+        for x in spanned_root_exprs
             .iter()
-            .find(gen_first_atom_filter_spanned(def_local_keys_variant))
-            .map(|x| {
-                (
-                    parse_deflocalkeys(def_local_keys_variant, &x.t),
-                    x.span.clone(),
+            .filter(gen_first_atom_filter_spanned(def_local_keys_variant))
+        {
+            let block = parse_deflocalkeys(def_local_keys_variant, &x.t)?;
+            if block.layouts.is_empty()
+                && blocks
+                    .iter()
+                    .any(|(b, _): &(LocalKeys, _)| b.layouts.is_empty())
+            {
+                bail_span!(
+                    x,
+                    "Only one {def_local_keys_variant} is allowed, found more. Delete the extras.\n\
+                     More blocks can be added for specific OS layouts with (layout <name>)."
                 )
-            })
-        else {
+            }
+            blocks.push((block, x.span.clone()));
+        }
+        if blocks.is_empty() {
             continue;
-        };
+        }
 
-        let mapping = result?;
         if def_local_keys_variant == &def_local_keys_variant_to_apply {
             assert!(
                 local_keys.is_none(),
                 ">1 mutually exclusive deflocalkeys variants were parsed"
             );
-            local_keys = Some(mapping);
+            localkeys_for_os_layouts = blocks.iter().any(|(b, _)| !b.layouts.is_empty());
+            // A block for the OS layout takes precedence over the one for any layout.
+            let active = blocks
+                .iter()
+                .position(|(b, _)| b.applies_to_layout(os_layout.as_deref()))
+                .or_else(|| blocks.iter().position(|(b, _)| b.layouts.is_empty()));
+            if let Some(i) = active {
+                local_keys = Some(blocks.swap_remove(i).0.mapping);
+            }
+            #[cfg(feature = "lsp")]
+            for (_, span) in blocks {
+                lsp_hints.inactive_code.push(lsp_hints::InactiveCode {
+                    span,
+                    reason: format!(
+                        "The OS keyboard layout is {}",
+                        os_layout.as_deref().unwrap_or("unknown")
+                    ),
+                })
+            }
         } else {
             #[cfg(feature = "lsp")]
-            lsp_hints.inactive_code.push(lsp_hints::InactiveCode {
-                span: _span,
-                reason: format!(
-                    "Another localkeys variant is currently active: {def_local_keys_variant_to_apply}"
+            for (_, span) in blocks {
+                lsp_hints.inactive_code.push(lsp_hints::InactiveCode {
+                    span,
+                    reason: format!(
+                        "Another localkeys variant is currently active: {def_local_keys_variant_to_apply}"
                     ),
-            })
-        }
-
-        if let Some(spanned) = spanned_root_exprs
-            .iter()
-            .filter(gen_first_atom_filter_spanned(def_local_keys_variant))
-            .nth(1)
-        {
-            bail_span!(
-                spanned,
-                "Only one {def_local_keys_variant} is allowed, found more. Delete the extras."
-            )
+                })
+            }
         }
     }
     replace_custom_str_oscode_mapping(&local_keys.unwrap_or_default());
@@ -662,6 +889,26 @@ pub fn parse_cfg_raw_string(
             "Only one definputdevices is allowed, found more. Delete the extras."
         )
     }
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "unknown"))]
+    for (id, name) in cfg.linux_opts.linux_output_device_routes.iter() {
+        if !input_devices
+            .iter()
+            .flatten()
+            .any(|(device, _)| device == id)
+        {
+            bail!(
+                "linux-output-device-routes uses the device ID {id}, which definputdevices does not define"
+            );
+        }
+        if *name == cfg.linux_opts.linux_output_name {
+            bail!("linux-output-device-routes uses {name}, the name of the main output device");
+        }
+    }
+    let debounce_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFDEBOUNCE))
+        .collect::<Vec<_>>();
+    let debounce = parse_defdebounces(&debounce_exprs, input_devices.as_deref(), s)?;
 
     let var_exprs = root_exprs
         .iter()
@@ -669,6 +916,12 @@ pub fn parse_cfg_raw_string(
         .collect::<Vec<_>>();
     let vars = parse_vars(&var_exprs, &mut lsp_hints)?;
 
+    let secret_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFSECRETS))
+        .collect::<Vec<_>>();
+    let secrets = parse_defsecrets(&secret_exprs, &vars)?;
+
     let deflayer_labels = [DEFLAYER, DEFLAYER_MAPPED];
     let deflayer_filter = |exprs: &&Vec<SExpr>| -> bool {
         if exprs.is_empty() {
@@ -695,8 +948,29 @@ pub fn parse_cfg_raw_string(
         bail!("No deflayer expressions exist. At least one layer must be defined.")
     }
 
-    let (layer_idxs, layer_icons) =
-        parse_layer_indexes(&layer_exprs, mapping_order.len(), &vars, &mut lsp_hints)?;
+    let (
+        layer_idxs,
+        layer_icons,
+        layer_sounds,
+        layer_layouts,
+        layer_hooks,
+        layer_auto_returns,
+        layer_no_repeats,
+        mut layer_displays,
+        mut layer_cmd_envs,
+    ) = parse_layer_indexes(
+        &layer_exprs,
+        mapping_order.len(),
+        &vars,
+        &secrets,
+        &mut lsp_hints,
+    )?;
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "unknown"))]
+    for (layer, _) in cfg.linux_opts.linux_layer_leds.iter() {
+        if !layer_idxs.contains_key(layer) {
+            bail!("linux-layer-leds uses the unknown layer {layer}");
+        }
+    }
     let mut sorted_idxs: Vec<(&String, &usize)> =
         layer_idxs.iter().map(|tuple| (tuple.0, tuple.1)).collect();
 
@@ -716,17 +990,37 @@ pub fn parse_cfg_raw_string(
         .map(|expr| expr.span.file_content()[expr.span.clone()].to_string())
         .collect::<Vec<_>>();
 
-    let layer_info: Vec<LayerInfo> = layer_names
+    let mut layer_info: Vec<LayerInfo> = layer_names
         .into_iter()
         .zip(layer_strings)
         .map(|(name, cfg_text)| LayerInfo {
             name: name.clone(),
             cfg_text,
             icon: layer_icons.get(&name).unwrap_or(&None).clone(),
+            sound: layer_sounds.get(&name).unwrap_or(&None).clone(),
+            auto_return: layer_auto_returns.get(&name).copied().flatten(),
+            on_enter: None,
+            on_exit: None,
+            no_repeat: layer_no_repeats.get(&name).copied().unwrap_or_default(),
+            display_name: layer_displays
+                .get_mut(&name)
+                .and_then(|display| display.display_name.take()),
+            color: layer_displays
+                .get_mut(&name)
+                .and_then(|display| display.color.take()),
+            doc: layer_displays
+                .get_mut(&name)
+                .and_then(|display| display.doc.take()),
+            cmd_env: layer_cmd_envs.remove(&name).unwrap_or_default(),
         })
         .collect();
 
-    let defsrc_layer = create_defsrc_layer();
+    let mut defsrc_layer = create_defsrc_layer();
+    if let Some(translation) = cfg.layout_translation {
+        for &(qwerty, translated) in translation {
+            defsrc_layer[usize::from(qwerty)] = Action::KeyCode(translated.into());
+        }
+    }
 
     let deflayer_filter = |exprs: &&Vec<SExpr>| -> bool {
         if exprs.is_empty() {
@@ -752,6 +1046,7 @@ pub fn parse_cfg_raw_string(
         a: s.a.clone(),
         layer_exprs,
         layer_idxs,
+        layer_layouts,
         mapping_order,
         defsrc_layer,
         is_cmd_enabled: {
@@ -760,6 +1055,9 @@ pub fn parse_cfg_raw_string(
                 if cfg.enable_cmd {
                     log::warn!("DANGER! cmd action is enabled.");
                     true
+                } else if cfg.cmd_allowlist.is_some() {
+                    log::info!("cmd action is enabled for the commands in cmd-allowlist");
+                    true
                 } else {
                     false
                 }
@@ -770,12 +1068,20 @@ pub fn parse_cfg_raw_string(
                 false
             }
         },
+        cmd_allowlist: cfg.cmd_allowlist.clone(),
+        is_secrets_enabled: {
+            if cfg.enable_secrets {
+                log::warn!("DANGER! secret-type action is enabled.");
+            }
+            cfg.enable_secrets
+        },
         delegate_to_first_layer: cfg.delegate_to_first_layer,
         default_sequence_timeout: cfg.sequence_timeout,
         default_sequence_input_mode: cfg.sequence_input_mode,
         block_unmapped_keys: cfg.block_unmapped_keys,
         lsp_hints: RefCell::new(lsp_hints),
         vars,
+        secrets,
         max_key_timing_check: Cell::new(cfg.rapid_event_delay),
         input_devices,
         ..Default::default()
@@ -804,6 +1110,24 @@ pub fn parse_cfg_raw_string(
         }
     }
 
+    let tap_hold_flavor_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFTAPHOLD_FLAVOR))
+        .collect::<Vec<_>>();
+    parse_deftaphold_flavors(&tap_hold_flavor_exprs, s)?;
+
+    let webhook_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFWEBHOOKS))
+        .collect::<Vec<_>>();
+    s.webhooks = parse_defwebhooks(&webhook_exprs, s)?;
+
+    let plugin_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFPLUGINS))
+        .collect::<Vec<_>>();
+    s.plugins = parse_defplugins(&plugin_exprs, s)?;
+
     let chords_exprs = spanned_root_exprs
         .iter()
         .filter(gen_first_atom_filter_spanned("defchords"))
@@ -832,7 +1156,25 @@ pub fn parse_cfg_raw_string(
         .iter()
         .filter(gen_first_atom_start_filter_spanned("defalias"))
         .collect::<Vec<_>>();
+    let app_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFAPP))
+        .collect::<Vec<_>>();
+    let apps = parse_defapps(&app_exprs, s)?;
+    let test_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFTEST))
+        .collect::<Vec<_>>();
+    let tests = parse_deftests(&test_exprs, s)?;
+    let meta_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFMETA))
+        .collect::<Vec<_>>();
+    let meta = parse_defmeta(&meta_exprs, s)?;
+
     parse_aliases(&alias_exprs, s, &env_vars)?;
+    check_app_aliases(s)?;
+    parse_layer_hooks(&layer_hooks, &mut layer_info, s)?;
 
     let start_action = cfg
         .start_alias
@@ -845,7 +1187,33 @@ pub fn parse_cfg_raw_string(
     let mut klayers = parse_layers(s, &mut mapped_keys, &cfg)?;
 
     resolve_chord_groups(&mut klayers, s)?;
-    let layers = s.a.bref_slice(klayers);
+    apply_tap_hold_key_timeouts(&mut klayers, &cfg.tap_hold_key_timeouts, s);
+
+    let autoshift_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter("defautoshift"))
+        .collect::<Vec<_>>();
+    match autoshift_exprs.len() {
+        0 => {}
+        1 => {
+            let autoshift = parse_defautoshift(autoshift_exprs[0], s)?;
+            apply_autoshift(&mut klayers, &autoshift, s);
+        }
+        _ => {
+            let spanned = spanned_root_exprs
+                .iter()
+                .filter(gen_first_atom_filter_spanned("defautoshift"))
+                .nth(1)
+                .expect(">= 2 defautoshift");
+            bail_span!(
+                spanned,
+                "Only one defautoshift is allowed, found more. Delete the extras."
+            );
+        }
+    }
+
+    place_alias_concat_keys(&mut klayers, s)?;
+    let layers = compact_layers(&klayers, &s.a);
     s.layers = layers;
 
     let override_exprs = root_exprs
@@ -900,6 +1268,30 @@ pub fn parse_cfg_raw_string(
         }
     };
 
+    let custom_shift_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter("defcustomshift"))
+        .collect::<Vec<_>>();
+    let overrides = match custom_shift_exprs.len() {
+        0 => overrides,
+        1 => {
+            let mut overrides = overrides;
+            overrides.add_fallbacks(&parse_defcustomshift(custom_shift_exprs[0], s)?);
+            overrides
+        }
+        _ => {
+            let spanned = spanned_root_exprs
+                .iter()
+                .filter(gen_first_atom_filter_spanned("defcustomshift"))
+                .nth(1)
+                .expect(">= 2 customshift");
+            bail_span!(
+                spanned,
+                "Only one defcustomshift allowed, found more. Delete the extras."
+            )
+        }
+    };
+
     let defchordsv2_filter = |exprs: &&Vec<SExpr>| -> bool {
         if exprs.is_empty() {
             return false;
@@ -922,6 +1314,9 @@ pub fn parse_cfg_raw_string(
         0 => None,
         1 => {
             let cfks = parse_defchordv2(chords_v2_exprs[0], s)?;
+            for warning in chord_conflicts(&cfks, &mapped_keys, s.layer_idxs.len()) {
+                log::warn!("{warning}");
+            }
             Some(ChordsV2::new(cfks, cfg.chords_v2_min_idle))
         }
         _ => {
@@ -981,6 +1376,36 @@ pub fn parse_cfg_raw_string(
         }
     };
 
+    let compose_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter("defcompose"))
+        .collect::<Vec<_>>();
+    let compose = match compose_exprs.len() {
+        0 => ComposeTable::new(),
+        1 => parse_defcompose(compose_exprs[0], s, file_content_provider)?,
+        _ => {
+            let spanned = spanned_root_exprs
+                .iter()
+                .filter(gen_first_atom_filter_spanned("defcompose"))
+                .nth(1)
+                .expect(">= 2 defcompose");
+            bail_span!(
+                spanned,
+                "Only one defcompose allowed, found more. Delete the extras."
+            )
+        }
+    };
+
+    let repeat_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFREPEAT))
+        .collect::<Vec<_>>();
+    let repeat_layer_exprs = root_exprs
+        .iter()
+        .filter(gen_first_atom_filter(DEFREPEAT_LAYER))
+        .collect::<Vec<_>>();
+    let key_repeat = parse_defrepeats(&repeat_exprs, &repeat_layer_exprs, &layer_info, s)?;
+
     #[cfg(feature = "lsp")]
     LSP_VARIABLE_REFERENCES.with_borrow_mut(|refs| {
         s.lsp_hints
@@ -999,9 +1424,20 @@ pub fn parse_cfg_raw_string(
         klayers,
         sequences,
         overrides,
+        compose,
         chords_v2,
         start_action,
         zippy,
+        key_repeat,
+        debounce,
+        webhooks: std::mem::take(&mut s.webhooks),
+        apps,
+        tests,
+        meta,
+        alias_docs: std::mem::take(&mut s.alias_docs),
+        virtual_key_docs: std::mem::take(&mut s.virtual_key_docs),
+        localkeys_for_os_layouts,
+        files: vec![],
     })
 }
 
@@ -1025,6 +1461,8 @@ fn error_on_unknown_top_level_atoms(exprs: &[Spanned<Vec<SExpr>>]) -> Result<()>
                 | DEFLAYER_MAPPED
                 | "defoverrides"
                 | "defoverridesv2"
+                | "defcustomshift"
+                | "defcompose"
                 | "deflocalkeys-macos"
                 | "deflocalkeys-linux"
                 | "deflocalkeys-win"
@@ -1041,6 +1479,17 @@ fn error_on_unknown_top_level_atoms(exprs: &[Spanned<Vec<SExpr>>]) -> Result<()>
                 | "defzippy-experimental"
                 | "defseq"
                 | "defhands"
+                | "defautoshift"
+                | "defrepeat"
+                | "defrepeat-layer"
+                | DEFDEBOUNCE
+                | DEFSECRETS
+                | DEFWEBHOOKS
+                | DEFPLUGINS
+                | DEFAPP
+                | DEFTEST
+                | DEFMETA
+                | DEFTAPHOLD_FLAVOR
                 | "definputdevices" => Ok(()),
                 _ => err_span!(expr, "Found unknown configuration item"),
             })
@@ -1153,13 +1602,30 @@ pub struct ParserState {
     layers: KLayers,
     layer_exprs: Vec<LayerExprs>,
     aliases: Aliases,
+    /// The descriptions of aliases from `(doc ...)`.
+    alias_docs: HashMap<String, String>,
+    /// The descriptions of virtual keys from `(doc ...)`.
+    virtual_key_docs: HashMap<String, String>,
     layer_idxs: LayerIndexes,
+    /// The `layout` option of each layer, by layer index.
+    layer_layouts: Vec<Option<LayoutTranslation>>,
     mapping_order: Vec<usize>,
     virtual_keys: HashMap<String, (usize, &'static KanataAction)>,
+    /// The aliases that `alias-concat` and `defapp` can press, which are put on virtual keys from
+    /// the end of the row.
+    alias_concat_keys: RefCell<Vec<(String, &'static KanataAction)>>,
+    /// Why `alias-concat` cannot be used anymore, once its virtual keys are in the layers.
+    alias_concat_keys_placed: Cell<Option<&'static str>>,
+    /// The aliases that `defapp` replaces for some applications.
+    app_aliases: AppAliases,
     chord_groups: HashMap<String, ChordGroup>,
     defsrc_layer: [KanataAction; KEYS_IN_ROW],
     vars: HashMap<String, SExpr>,
+    /// The secrets of `defsecrets`.
+    secrets: Vec<Secret>,
     is_cmd_enabled: bool,
+    cmd_allowlist: Option<CmdAllowlist>,
+    is_secrets_enabled: bool,
     delegate_to_first_layer: bool,
     default_sequence_timeout: u16,
     default_sequence_input_mode: SequenceInputMode,
@@ -1170,6 +1636,10 @@ pub struct ParserState {
     pctx: ParserContext,
     pub lsp_hints: RefCell<LspHints>,
     hand_map: Option<&'static custom_tap_hold::HandMap>,
+    tap_hold_flavors: HashMap<String, TapHoldFlavor>,
+    webhooks: Vec<Webhook>,
+    /// The plugins of `defplugins`.
+    plugins: Vec<PluginGrant>,
     a: Arc<Allocations>,
 }
 
@@ -1186,13 +1656,22 @@ impl Default for ParserState {
             layers: Default::default(),
             layer_exprs: Default::default(),
             aliases: Default::default(),
+            alias_docs: Default::default(),
+            virtual_key_docs: Default::default(),
             layer_idxs: Default::default(),
+            layer_layouts: Default::default(),
             mapping_order: Default::default(),
             defsrc_layer: [KanataAction::NoOp; KEYS_IN_ROW],
             virtual_keys: Default::default(),
+            alias_concat_keys: Default::default(),
+            alias_concat_keys_placed: Cell::new(None),
+            app_aliases: Default::default(),
             chord_groups: Default::default(),
             vars: Default::default(),
+            secrets: Default::default(),
             is_cmd_enabled: default_cfg.enable_cmd,
+            cmd_allowlist: None,
+            is_secrets_enabled: default_cfg.enable_secrets,
             delegate_to_first_layer: default_cfg.delegate_to_first_layer,
             default_sequence_timeout: default_cfg.sequence_timeout,
             default_sequence_input_mode: default_cfg.sequence_input_mode,
@@ -1202,6 +1681,9 @@ impl Default for ParserState {
             input_devices: None,
             lsp_hints: Default::default(),
             hand_map: None,
+            tap_hold_flavors: Default::default(),
+            webhooks: vec![],
+            plugins: vec![],
             a: unsafe { Allocations::new() },
             pctx: ParserContext::default(),
         }
@@ -1323,14 +1805,27 @@ fn read_alias_name_action_pairs<'a>(
                 alias_expr
             ),
         };
-        let action = match exprs.next() {
+        let mut action = match exprs.next() {
             Some(v) => v,
             None => bail_expr!(alias_expr, "Found alias without an action - add an action"),
         };
+        let doc = match parse_doc(action, s)? {
+            Some(doc) => {
+                action = match exprs.next() {
+                    Some(v) => v,
+                    None => bail_expr!(action, "Found doc without an action - add an action"),
+                };
+                Some(doc)
+            }
+            None => None,
+        };
         let action = parse_action(action, s)?;
         if s.aliases.insert(alias.into(), action).is_some() {
             bail_expr!(alias_expr, "Duplicate alias: {}", alias);
         }
+        if let Some(doc) = doc {
+            s.alias_docs.insert(alias.into(), doc);
+        }
         #[cfg(feature = "lsp")]
         s.lsp_hints
             .borrow_mut()
@@ -1341,6 +1836,25 @@ fn read_alias_name_action_pairs<'a>(
     Ok(())
 }
 
+/// Returns the description of `(doc "description")`, which can be written between the name of an
+/// alias or virtual key and its action.
+fn parse_doc(expr: &SExpr, s: &ParserState) -> Result<Option<String>> {
+    const ERR_MSG: &str = "doc expects one string: (doc \"description\")";
+    let Some(list) = expr.list(s.vars()) else {
+        return Ok(None);
+    };
+    if list.first().and_then(|first| first.atom(s.vars())) != Some("doc") {
+        return Ok(None);
+    }
+    match list {
+        [_, doc] => doc
+            .atom(s.vars())
+            .map(|doc| Some(doc.trim_atom_quotes().to_owned()))
+            .ok_or_else(|| anyhow_expr!(doc, "{ERR_MSG}")),
+        _ => bail_expr!(expr, "{ERR_MSG}"),
+    }
+}
+
 /// Parse a `kanata_keyberon::action::Action` from a `SExpr`.
 fn parse_action(expr: &SExpr, s: &ParserState) -> Result<&'static KanataAction> {
     expr.atom(s.vars())
@@ -1386,6 +1900,8 @@ fn parse_action_atom(ac_span: &Spanned<String>, s: &ParserState) -> Result<&'sta
         "lrld" => return custom(CustomAction::LiveReload, &s.a),
         "lrld-next" | "lrnx" => return custom(CustomAction::LiveReloadNext, &s.a),
         "lrld-prev" | "lrpv" => return custom(CustomAction::LiveReloadPrev, &s.a),
+        "lrld-version-prev" => return custom(CustomAction::LiveReloadVersionPrev, &s.a),
+        "lrld-version-next" => return custom(CustomAction::LiveReloadVersionNext, &s.a),
         "sldr" => {
             return custom(
                 CustomAction::SequenceLeader(
@@ -1440,6 +1956,9 @@ fn parse_action_atom(ac_span: &Spanned<String>, s: &ParserState) -> Result<&'sta
         }
         "rpt" | "repeat" | "rpt-key" => return custom(CustomAction::Repeat, &s.a),
         "rpt-any" => return Ok(s.a.sref(Action::Repeat)),
+        "macro-cancel" => return custom(CustomAction::CancelMacros, &s.a),
+        "mouse-grid-reset" => return custom(CustomAction::MouseGridReset, &s.a),
+        "toggle-processing" => return custom(CustomAction::ToggleProcessing, &s.a),
         "dynamic-macro-record-stop" => {
             return custom(CustomAction::DynamicMacroRecordStop(0), &s.a);
         }
@@ -1475,7 +1994,10 @@ fn parse_action_atom(ac_span: &Spanned<String>, s: &ParserState) -> Result<&'sta
                     .reference_locations
                     .alias
                     .push(alias, ac_span.span.clone());
-                Ok(*ac)
+                match s.app_aliases.get(alias) {
+                    Some(replacements) => app_alias_action(alias, ac, replacements, s),
+                    None => Ok(*ac),
+                }
             }
             None => match s.pctx.is_within_defvirtualkeys {
                 true => bail_span!(
@@ -1490,12 +2012,8 @@ fn parse_action_atom(ac_span: &Spanned<String>, s: &ParserState) -> Result<&'sta
             },
         };
     }
-    if let Some(unisym) = ac.strip_prefix('🔣') {
-        // TODO: when unicode accepts multiple chars, change this to feed the whole string, not just the first char
-        return custom(
-            CustomAction::Unicode(unisym.chars().next().expect("1 char")),
-            &s.a,
-        );
+    if let Some(unisym) = ac.strip_prefix('🔣').filter(|sym| !sym.is_empty()) {
+        return unicode_str_action(unisym, s);
     }
     // Parse a sequence like `C-S-v` or `C-A-del`
     let (mut keys, unparsed_str) = parse_mod_prefix(ac)?;
@@ -1560,6 +2078,7 @@ fn parse_action_list(ac: &[SExpr], s: &ParserState) -> Result<&'static KanataAct
             parse_tap_hold_keys(&ac[1..], s, TAP_HOLD_TAP_KEYS, custom_tap_hold_tap_keys)
         }
         TAP_HOLD_OPPOSITE_HAND => parse_tap_hold_opposite_hand(&ac[1..], s),
+        TAP_HOLD_FLAVOR => parse_tap_hold_flavor(&ac[1..], s),
         TAP_HOLD_OPPOSITE_HAND_RELEASE => parse_tap_hold_opposite_hand_release(&ac[1..], s),
         MULTI => parse_multi(&ac[1..], s),
         MACRO => parse_macro(&ac[1..], s, RepeatMacro::No),
@@ -1573,6 +2092,7 @@ fn parse_action_list(ac: &[SExpr], s: &ParserState) -> Result<&'static KanataAct
         MACRO_CANCEL_ON_NEXT_PRESS => {
             parse_macro_cancel_on_next_press(&ac[1..], s, RepeatMacro::No)
         }
+        MACRO_DELAY_SCALE => parse_macro_delay_scale(&ac[1..], s),
         MACRO_REPEAT_CANCEL_ON_NEXT_PRESS => {
             parse_macro_cancel_on_next_press(&ac[1..], s, RepeatMacro::Yes)
         }
@@ -1641,14 +2161,21 @@ fn parse_action_list(ac: &[SExpr], s: &ParserState) -> Result<&'static KanataAct
         }
         MOVEMOUSE_SPEED | MOVEMOUSE_SPEED_A => parse_move_mouse_speed(&ac[1..], s),
         SETMOUSE | SETMOUSE_A => parse_set_mouse(&ac[1..], s),
+        MOUSE_GRID => parse_mouse_grid(&ac[1..], s),
+        MOUSE_GRID_MONITOR => parse_mouse_grid_monitor(&ac[1..], s),
+        MOUSE_TOWARD => parse_mouse_toward(&ac[1..], s),
+        JIGGLE => parse_jiggle(&ac[1..], s),
         DYNAMIC_MACRO_RECORD => parse_dynamic_macro_record(&ac[1..], s),
         DYNAMIC_MACRO_PLAY => parse_dynamic_macro_play(&ac[1..], s),
         ARBITRARY_CODE => parse_arbitrary_code(&ac[1..], s),
         CMD => parse_cmd(&ac[1..], s, CmdType::Standard),
         CMD_OUTPUT_KEYS => parse_cmd(&ac[1..], s, CmdType::OutputKeys),
         CMD_LOG => parse_cmd_log(&ac[1..], s),
+        CMD_ARGV => parse_cmd_argv(&ac[1..], s),
+        CMD_CANCEL => parse_cmd_cancel(&ac[1..], s),
         PUSH_MESSAGE => parse_push_message(&ac[1..], s),
         FORK => parse_fork(&ac[1..], s),
+        MOD_MORPH => parse_mod_morph(&ac[1..], s),
         CAPS_WORD | CAPS_WORD_A => {
             parse_caps_word(&ac[1..], CapsWordRepressBehaviour::Overwrite, s)
         }
@@ -1665,6 +2192,7 @@ fn parse_action_list(ac: &[SExpr], s: &ParserState) -> Result<&'static KanataAct
         SWITCH => parse_switch(&ac[1..], s),
         SEQUENCE => parse_sequence_start(&ac[1..], s),
         SEQUENCE_NOERASE => parse_sequence_noerase(&ac[1..], s),
+        COMPOSE => parse_compose(&ac[1..], s),
         UNMOD => parse_unmod(UNMOD, &ac[1..], s),
         UNSHIFT | UNSHIFT_A => parse_unmod(UNSHIFT, &ac[1..], s),
         LIVE_RELOAD_NUM => parse_live_reload_num(&ac[1..], s),
@@ -1676,6 +2204,22 @@ fn parse_action_list(ac: &[SExpr], s: &ParserState) -> Result<&'static KanataAct
         CLIPBOARD_SAVE_SET => parse_clipboard_save_set(&ac[1..], s),
         CLIPBOARD_SAVE_CMD_SET => parse_cmd(&ac[1..], s, CmdType::ClipboardSaveSet),
         CLIPBOARD_SAVE_SWAP => parse_clipboard_save_swap(&ac[1..], s),
+        MIDI_NOTE => parse_midi_note(&ac[1..], s),
+        OBS => parse_obs(&ac[1..], s),
+        OPENRGB => parse_openrgb(&ac[1..], s),
+        WEBHOOK => parse_webhook(&ac[1..], s),
+        PLUGIN => parse_plugin_action(&ac[1..], s),
+        NOTIFY => parse_notify(&ac[1..], s),
+        MPRIS => parse_mpris(&ac[1..], s),
+        OS_LAYOUT => parse_os_layout(&ac[1..], s),
+        HID_USAGE => parse_hid_usage(&ac[1..], s),
+        DATETIME => parse_datetime(&ac[1..], s),
+        LOG_LEVEL => parse_log_level(&ac[1..], s),
+        SECRET_TYPE => parse_secret_type(&ac[1..], s),
+        VAR_SET => parse_var_set(&ac[1..], s),
+        ALIAS_CONCAT => parse_alias_concat(&ac[1..], s),
+        TRAINING_MODE => parse_training_mode(&ac[1..], s),
+        MIDI_CC => parse_midi_cc(&ac[1..], s),
         _ => unreachable!(),
     }
 }
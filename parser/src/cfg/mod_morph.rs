@@ -0,0 +1,61 @@
+use super::*;
+
+use crate::{anyhow_expr, bail, bail_expr};
+
+/// Parses `(mod-morph $default $morphed $mods [consume-mods])`, which is a `fork` on modifier
+/// keys. With `consume-mods`, the morphed key is output like `unmod` of the listed modifiers.
+pub(crate) fn parse_mod_morph(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_STR: &str = "mod-morph expects 3 or 4 params: <default-action> <morphed-action> <modifier-keys> [consume-mods]";
+    if !(3..=4).contains(&ac_params.len()) {
+        bail!("{ERR_STR}\nFound {} params instead", ac_params.len());
+    }
+    let consume = match ac_params.get(3) {
+        None => false,
+        Some(param) => match param.atom(s.vars()) {
+            Some("consume-mods") => true,
+            _ => bail_expr!(param, "{ERR_STR}\nThe 4th param must be consume-mods"),
+        },
+    };
+    let Some(mod_list) = ac_params[2].list(s.vars()) else {
+        bail_expr!(&ac_params[2], "{ERR_STR}\n<modifier-keys> must be a list");
+    };
+    if mod_list.is_empty() {
+        bail_expr!(&ac_params[2], "an empty modifier key list is invalid");
+    }
+    let mut mods = UnmodMods::empty();
+    let mut triggers = vec![];
+    for mod_key in mod_list {
+        let (osc, flag) = mod_key
+            .atom(s.vars())
+            .and_then(str_to_oscode)
+            .and_then(|osc| Some((osc, unmod_flag(osc)?)))
+            .ok_or_else(|| anyhow_expr!(mod_key, "mod-morph expects modifier key names"))?;
+        mods |= flag;
+        triggers.push(KeyCode::from(osc));
+    }
+    let left = *parse_action(&ac_params[0], s)?;
+    let right = if consume {
+        let key = ac_params[1]
+            .atom(s.vars())
+            .and_then(str_to_oscode)
+            .ok_or_else(|| {
+                anyhow_expr!(
+                    &ac_params[1],
+                    "with consume-mods, <morphed-action> must be a key name"
+                )
+            })?;
+        let keys = s.a.sref_vec(vec![KeyCode::from(key)]);
+        *custom(CustomAction::Unmodded { keys, mods }, &s.a)?
+    } else {
+        *parse_action(&ac_params[1], s)?
+    };
+    Ok(s.a.sref(Action::Fork(s.a.sref(ForkConfig {
+        left,
+        right,
+        right_triggers: s.a.sref_vec(triggers),
+        right_inputs: &[],
+    }))))
+}
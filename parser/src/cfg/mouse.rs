@@ -2,6 +2,7 @@ use super::*;
 
 use crate::anyhow_expr;
 use crate::bail;
+use crate::bail_expr;
 
 pub(crate) fn parse_distance(expr: &SExpr, s: &ParserState, label: &str) -> Result<u16> {
     expr.atom(s.vars())
@@ -15,18 +16,42 @@ pub(crate) fn parse_mwheel(
     direction: MWheelDirection,
     s: &ParserState,
 ) -> Result<&'static KanataAction> {
-    const ERR_MSG: &str = "mwheel expects 2 parameters: <interval (ms)> <distance>";
-    if ac_params.len() != 2 {
-        bail!("{ERR_MSG}, found {}", ac_params.len());
+    const ERR_MSG: &str = "mwheel expects 2 or 4 parameters and an optional smooth:\n\
+        <interval (ms)> <distance> [<acceleration time (ms)> <max distance>] [smooth]";
+    let (params, smooth) = match ac_params.split_last() {
+        Some((last, rest)) if last.atom(s.vars()) == Some("smooth") => (rest, true),
+        _ => (ac_params, false),
+    };
+    if !matches!(params.len(), 2 | 4) {
+        bail!("{ERR_MSG}\nfound {} parameters", ac_params.len());
     }
-    let interval = parse_non_zero_u16(&ac_params[0], s, "interval")?;
-    let distance = parse_distance(&ac_params[1], s, "distance")?;
+    let interval = parse_non_zero_u16(&params[0], s, "interval")?;
+    let distance = parse_distance(&params[1], s, "distance")?;
+    let accel = match params {
+        [_, _, accel_time, max_distance_expr] => {
+            let accel_time = parse_non_zero_u16(accel_time, s, "acceleration time")?;
+            let max_distance = parse_distance(max_distance_expr, s, "max distance")?;
+            if max_distance < distance {
+                bail_expr!(
+                    max_distance_expr,
+                    "max distance should be at least the distance"
+                );
+            }
+            Some(s.a.sref(MWheelAccel {
+                accel_time,
+                max_distance,
+            }))
+        }
+        _ => None,
+    };
     custom(
         CustomAction::MWheel {
             direction,
             interval,
             distance,
             inertial_scroll_params: None,
+            accel,
+            smooth,
         },
         &s.a,
     )
@@ -60,6 +85,8 @@ pub(crate) fn parse_mwheel_accel(
                 acceleration_multiplier,
                 deceleration_multiplier,
             })),
+            accel: None,
+            smooth: false,
         },
         &s.a,
     )
@@ -144,3 +171,116 @@ pub(crate) fn parse_set_mouse(
     let y = parse_u16(&ac_params[1], s, "y")?;
     custom(CustomAction::SetMouse { x, y }, &s.a)
 }
+
+pub(crate) fn parse_mouse_grid(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    if ac_params.len() != 4 {
+        bail!(
+            "{MOUSE_GRID} expects four parameters, found {}\n<columns> <rows> <column> <row>",
+            ac_params.len()
+        );
+    }
+    let columns = parse_u8_with_range(&ac_params[0], s, "grid columns", 1, 100)?;
+    let rows = parse_u8_with_range(&ac_params[1], s, "grid rows", 1, 100)?;
+    let column = parse_u8_with_range(&ac_params[2], s, "grid column", 1, columns)? - 1;
+    let row = parse_u8_with_range(&ac_params[3], s, "grid row", 1, rows)? - 1;
+    custom(
+        CustomAction::MouseGridCell(MouseGridCell {
+            columns,
+            rows,
+            column,
+            row,
+        }),
+        &s.a,
+    )
+}
+
+pub(crate) fn parse_mouse_grid_monitor(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    if ac_params.len() != 1 {
+        bail!(
+            "{MOUSE_GRID_MONITOR} expects one parameter, found {}\n<monitor number (1-16)>",
+            ac_params.len()
+        );
+    }
+    let monitor = parse_u8_with_range(&ac_params[0], s, "monitor number", 1, 16)? - 1;
+    custom(CustomAction::MouseGridMonitor(monitor), &s.a)
+}
+
+pub(crate) fn parse_mouse_toward(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects two parameters: <anchor> <distance % (1-100)>\n\
+        anchors: center top-left top top-right left right bottom-left bottom bottom-right monitor-N";
+    if ac_params.len() != 2 {
+        bail!(
+            "{MOUSE_TOWARD} {ERR_MSG}\nfound {} parameters",
+            ac_params.len()
+        );
+    }
+    let anchor = ac_params[0].atom(s.vars()).unwrap_or_default();
+    let (monitor, anchor_x, anchor_y) = match anchor {
+        "center" => (None, 50, 50),
+        "top-left" => (None, 0, 0),
+        "top" => (None, 50, 0),
+        "top-right" => (None, 100, 0),
+        "left" => (None, 0, 50),
+        "right" => (None, 100, 50),
+        "bottom-left" => (None, 0, 100),
+        "bottom" => (None, 50, 100),
+        "bottom-right" => (None, 100, 100),
+        _ => {
+            let monitor = anchor
+                .strip_prefix("monitor-")
+                .and_then(|n| n.parse::<u8>().ok())
+                .filter(|n| (1..=16).contains(n))
+                .ok_or_else(|| {
+                    anyhow_expr!(&ac_params[0], "{MOUSE_TOWARD} {ERR_MSG}\nunknown anchor")
+                })?;
+            (Some(monitor - 1), 50, 50)
+        }
+    };
+    let percent = parse_u8_with_range(&ac_params[1], s, "distance %", 1, 100)?;
+    custom(
+        CustomAction::MouseToward(MouseToward {
+            monitor,
+            anchor_x,
+            anchor_y,
+            percent,
+        }),
+        &s.a,
+    )
+}
+
+pub(crate) fn parse_jiggle(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str =
+        "jiggle expects 2 or 3 parameters: <interval (ms)> <distance (px)> [zero-sum]";
+    if !(2..=3).contains(&ac_params.len()) {
+        bail!("{ERR_MSG}, found {}", ac_params.len());
+    }
+    let interval = parse_non_zero_u16(&ac_params[0], s, "interval")?;
+    let distance = parse_distance(&ac_params[1], s, "distance")?;
+    if distance == 0 {
+        bail_expr!(&ac_params[1], "distance must be 1-30000");
+    }
+    let zero_sum = match ac_params.get(2) {
+        None => false,
+        Some(expr) => match expr.atom(s.vars()) {
+            Some("zero-sum") => true,
+            _ => bail_expr!(expr, "{ERR_MSG}"),
+        },
+    };
+    custom(
+        CustomAction::Jiggle(MouseJiggle {
+            interval,
+            distance,
+            zero_sum,
+        }),
+        &s.a,
+    )
+}
@@ -0,0 +1,35 @@
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+use crate::bail_expr;
+
+pub(crate) fn parse_mpris(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects a command: play-pause, play, pause, stop, next or previous, and optionally a player";
+    if ac_params.is_empty() || ac_params.len() > 2 {
+        bail!("{MPRIS} {ERR_MSG}");
+    }
+    let command = match ac_params[0].atom(s.vars()) {
+        Some("play-pause") => MprisCommand::PlayPause,
+        Some("play") => MprisCommand::Play,
+        Some("pause") => MprisCommand::Pause,
+        Some("stop") => MprisCommand::Stop,
+        Some("next") => MprisCommand::Next,
+        Some("previous") => MprisCommand::Previous,
+        _ => bail_expr!(&ac_params[0], "{MPRIS} {ERR_MSG}"),
+    };
+    let player = match ac_params.get(1) {
+        Some(expr) => {
+            let player = expr
+                .atom(s.vars())
+                .map(|p| p.trim_atom_quotes())
+                .filter(|p| !p.is_empty())
+                .ok_or_else(|| {
+                    anyhow_expr!(expr, "{MPRIS} player should be a name, e.g. spotify")
+                })?;
+            Some(s.a.sref_str(player.to_string()))
+        }
+        None => None,
+    };
+    custom(CustomAction::Mpris(MprisAction { command, player }), &s.a)
+}
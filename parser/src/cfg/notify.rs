@@ -0,0 +1,23 @@
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+
+pub(crate) fn parse_notify(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects 1 or 2 parameters: <title> [body]";
+    if ac_params.is_empty() || ac_params.len() > 2 {
+        bail!("{NOTIFY} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let text = |expr: &SExpr| -> Result<&'static str> {
+        let text = expr
+            .atom(s.vars())
+            .ok_or_else(|| anyhow_expr!(expr, "{NOTIFY} text should be a string not a list"))?;
+        Ok(s.a.sref_str(text.trim_atom_quotes().to_string()))
+    };
+    let title = text(&ac_params[0])?;
+    let body = match ac_params.get(1) {
+        Some(expr) => text(expr)?,
+        None => "",
+    };
+    custom(CustomAction::Notify { title, body }, &s.a)
+}
@@ -0,0 +1,56 @@
+use super::*;
+
+use crate::bail;
+use crate::bail_expr;
+
+pub(crate) fn parse_obs(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects a request: switch-scene <scene>, toggle-source <scene> <source>, start-recording, stop-recording or toggle-recording";
+    let Some(request) = ac_params.first() else {
+        bail!("{OBS} {ERR_MSG}");
+    };
+    let Some(request_name) = request.atom(s.vars()) else {
+        bail_expr!(request, "{OBS} {ERR_MSG}");
+    };
+    let names = |expected: usize, what: &str| -> Result<Vec<&'static str>> {
+        let args = &ac_params[1..];
+        if args.len() != expected {
+            bail_expr!(
+                request,
+                "{OBS} {request_name} expects {what}, found {} parameters",
+                args.len()
+            );
+        }
+        args.iter()
+            .map(|arg| match arg.atom(s.vars()) {
+                Some(name) if !name.trim_atom_quotes().is_empty() => {
+                    Ok(s.a.sref_str(name.trim_atom_quotes().to_string()))
+                }
+                _ => bail_expr!(arg, "{OBS} {request_name} expects a name"),
+            })
+            .collect()
+    };
+    let action = match request_name {
+        "switch-scene" => ObsAction::SwitchScene(names(1, "1 parameter: <scene>")?[0]),
+        "toggle-source" => {
+            let names = names(2, "2 parameters: <scene> <source>")?;
+            ObsAction::ToggleSource {
+                scene: names[0],
+                source: names[1],
+            }
+        }
+        "start-recording" => {
+            names(0, "no parameters")?;
+            ObsAction::StartRecording
+        }
+        "stop-recording" => {
+            names(0, "no parameters")?;
+            ObsAction::StopRecording
+        }
+        "toggle-recording" => {
+            names(0, "no parameters")?;
+            ObsAction::ToggleRecording
+        }
+        _ => bail_expr!(request, "{OBS} {ERR_MSG}"),
+    };
+    custom(CustomAction::Obs(action), &s.a)
+}
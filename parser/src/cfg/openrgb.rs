@@ -0,0 +1,23 @@
+use super::*;
+
+use crate::bail;
+use crate::bail_expr;
+
+pub(crate) fn parse_openrgb(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects one parameter: a color #rgb or #rrggbb, or off";
+    let [color_expr] = ac_params else {
+        bail!("{OPENRGB} {ERR_MSG}, found {} parameters", ac_params.len());
+    };
+    let rgb = match color_expr
+        .atom(s.vars())
+        .map(|color| color.trim_atom_quotes())
+    {
+        Some("off") => [0; 3],
+        Some(color) => match hex_color_rgb(color) {
+            Some(rgb) => rgb,
+            None => bail_expr!(color_expr, "{OPENRGB} {ERR_MSG}"),
+        },
+        None => bail_expr!(color_expr, "{OPENRGB} {ERR_MSG}"),
+    };
+    custom(CustomAction::OpenRgb(rgb), &s.a)
+}
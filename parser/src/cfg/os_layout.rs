@@ -0,0 +1,29 @@
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+
+pub(crate) fn parse_os_layout(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects 1 parameter: <OS keyboard layout>";
+    if ac_params.len() != 1 {
+        bail!("{OS_LAYOUT} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let layout = ac_params[0]
+        .atom(s.vars())
+        .map(|layout| layout.trim_atom_quotes())
+        .filter(|layout| !layout.trim().is_empty())
+        .ok_or_else(|| {
+            anyhow_expr!(
+                &ac_params[0],
+                "{OS_LAYOUT} layout should be a name, e.g. de or ibus:mozc-jp on Linux, \
+                 00000407 or de-DE on Windows, German on macOS"
+            )
+        })?;
+    custom(
+        CustomAction::OsLayout(s.a.sref_str(layout.to_string())),
+        &s.a,
+    )
+}
@@ -19,16 +19,42 @@ pub(crate) fn filter_platform_specific_cfg(
         .into_iter()
         .try_fold(vec![], |mut tles, tle| -> Result<Vec<TopLevel>> {
             if !matches!(tle.t.first().and_then(|m| m.atom(None)), Some("platform")) {
-                tles.push(tle);
+                tles.push(Spanned {
+                    t: filter_platform_sections(
+                        tle.t,
+                        &valid_platform_names,
+                        current_platform,
+                        _lsp_hints,
+                    )?,
+                    span: tle.span,
+                });
                 return Ok(tles);
             }
 
-            if tle.t.len() != 3 {
-                bail_span!(
+            let is_platform_list = tle.t.len() == 3
+                && tle.t[1]
+                    .list(None)
+                    .is_some_and(|pfs| pfs.iter().all(|pf| pf.atom(None).is_some()));
+            if !is_platform_list {
+                let configurations = platform_section_items(
                     &tle,
-                    "platform requires exactly two parameters:\n\
-                      applicable-platforms, configuration-item"
-                );
+                    &valid_platform_names,
+                    current_platform,
+                    _lsp_hints,
+                )?
+                .iter()
+                .map(|item| {
+                    item.span_list(None)
+                        .cloned()
+                        .ok_or_else(|| anyhow_expr!(item, "configuration-item must be a list"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+                tles.extend(filter_platform_specific_cfg(
+                    configurations,
+                    deflocalkeys_variant_to_apply,
+                    _lsp_hints,
+                )?);
+                return Ok(tles);
             }
 
             let configuration = tle.t[2]
@@ -60,7 +86,11 @@ pub(crate) fn filter_platform_specific_cfg(
                 })?;
 
             if applicable_platforms.contains(&current_platform) {
-                tles.push(configuration.clone());
+                tles.extend(filter_platform_specific_cfg(
+                    vec![configuration.clone()],
+                    deflocalkeys_variant_to_apply,
+                    _lsp_hints,
+                )?);
             } else {
                 #[cfg(feature = "lsp")]
                 _lsp_hints.inactive_code.push(lsp_hints::InactiveCode {
@@ -76,6 +106,97 @@ pub(crate) fn filter_platform_specific_cfg(
         })
 }
 
+/// Replaces the lists `(platform (<platform> <items>...)...)` within the expressions with the
+/// items of the section for the current platform.
+fn filter_platform_sections(
+    exprs: Vec<SExpr>,
+    valid_platform_names: &[&str],
+    current_platform: &str,
+    lsp_hints: &mut lsp_hints::LspHints,
+) -> Result<Vec<SExpr>> {
+    let mut filtered = vec![];
+    for expr in exprs {
+        match expr {
+            SExpr::Atom(_) => filtered.push(expr),
+            SExpr::List(l) => {
+                if matches!(l.t.first().and_then(|m| m.atom(None)), Some("platform")) {
+                    let items = platform_section_items(
+                        &l,
+                        valid_platform_names,
+                        current_platform,
+                        lsp_hints,
+                    )?;
+                    filtered.extend(filter_platform_sections(
+                        items,
+                        valid_platform_names,
+                        current_platform,
+                        lsp_hints,
+                    )?);
+                } else {
+                    filtered.push(SExpr::List(Spanned {
+                        t: filter_platform_sections(
+                            l.t,
+                            valid_platform_names,
+                            current_platform,
+                            lsp_hints,
+                        )?,
+                        span: l.span,
+                    }));
+                }
+            }
+        }
+    }
+    Ok(filtered)
+}
+
+/// Returns the items of the section for the current platform in
+/// `(platform (<platform> <items>...)...)`.
+fn platform_section_items(
+    platform: &Spanned<Vec<SExpr>>,
+    valid_platform_names: &[&str],
+    current_platform: &str,
+    _lsp_hints: &mut lsp_hints::LspHints,
+) -> Result<Vec<SExpr>> {
+    const ERR_MSG: &str = "platform sections must be lists that begin with a platform name, \
+                           followed by the configuration for that platform";
+    if platform.t.len() < 2 {
+        bail_span!(platform, "{ERR_MSG}\nFound no sections");
+    }
+    let mut section_platforms = vec![];
+    let mut items = vec![];
+    for section_expr in platform.t[1..].iter() {
+        let section = section_expr
+            .span_list(None)
+            .ok_or_else(|| anyhow_expr!(section_expr, "{ERR_MSG}"))?;
+        let pf = section
+            .t
+            .first()
+            .and_then(|pf| pf.atom(None))
+            .ok_or_else(|| anyhow_expr!(section_expr, "{ERR_MSG}"))?;
+        if !valid_platform_names.contains(&pf) {
+            bail_expr!(
+                &section.t[0],
+                "Unknown platform. Valid platforms:\n{}",
+                valid_platform_names.join(" ")
+            );
+        }
+        if section_platforms.contains(&pf) {
+            bail_expr!(&section.t[0], "Duplicate platform section: {pf}");
+        }
+        section_platforms.push(pf);
+        if pf == current_platform {
+            items.extend(section.t[1..].iter().cloned());
+        } else {
+            #[cfg(feature = "lsp")]
+            _lsp_hints.inactive_code.push(lsp_hints::InactiveCode {
+                span: section.span.clone(),
+                reason: format!("Current platform \"{current_platform}\" doesn't match {pf}"),
+            })
+        }
+    }
+    Ok(items)
+}
+
 pub(crate) fn filter_env_specific_cfg(
     top_levels: Vec<TopLevel>,
     env: &EnvVars,
@@ -0,0 +1,31 @@
+use super::*;
+
+use crate::anyhow_expr;
+use crate::bail;
+
+pub(crate) fn parse_secret_type(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "expects 1 parameter: <secret name>";
+    if !s.is_secrets_enabled {
+        bail!("To use {SECRET_TYPE} you must put in defcfg: danger-enable-secrets yes.");
+    }
+    if ac_params.len() != 1 {
+        bail!("{SECRET_TYPE} {ERR_MSG}, found {}", ac_params.len());
+    }
+    let name = ac_params[0]
+        .atom(s.vars())
+        .map(|name| name.trim_atom_quotes())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| {
+            anyhow_expr!(
+                &ac_params[0],
+                "{SECRET_TYPE} secret name should be a string, e.g. github-token"
+            )
+        })?;
+    custom(
+        CustomAction::SecretType(s.a.sref(find_secret(&s.secrets, name))),
+        &s.a,
+    )
+}
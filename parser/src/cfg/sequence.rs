@@ -49,8 +49,12 @@ pub(crate) fn parse_sequences(exprs: &[&Vec<SExpr>], s: &ParserState) -> Result<
         let mut subexprs = check_first_expr(expr.iter(), "defseq")?.peekable();
 
         while let Some(vkey_expr) = subexprs.next() {
+            let (vkey_expr, pass_mods) = parse_sequence_vkey_opts(vkey_expr, s)?;
             let vkey = vkey_expr.atom(s.vars()).ok_or_else(|| {
-                anyhow_expr!(vkey_expr, "{SEQ_ERR}\nvirtual_key_name must not be a list")
+                anyhow_expr!(
+                    vkey_expr,
+                    "{SEQ_ERR}\nvirtual_key_name must not be a list, unless it is (<name> pass-mods <yes|no>)"
+                )
             })?;
             #[cfg(feature = "lsp")]
             s.lsp_hints
@@ -74,7 +78,7 @@ pub(crate) fn parse_sequences(exprs: &[&Vec<SExpr>], s: &ParserState) -> Result<
                 bail_expr!(key_seq_expr, "{SEQ_ERR}\nkey_list cannot be empty");
             }
 
-            let keycode_seq = parse_sequence_keys(key_seq, s)?;
+            let (keycode_seq, unmodded_steps) = parse_sequence_steps(key_seq, s)?;
 
             // Generate permutations of sequences for overlapping keys.
             let mut permutations = vec![vec![]];
@@ -128,6 +132,12 @@ pub(crate) fn parse_sequences(exprs: &[&Vec<SExpr>], s: &ParserState) -> Result<
             }
 
             for p in permutations.into_iter() {
+                if sequences.contains(&p) {
+                    bail_expr!(
+                        key_seq_expr,
+                        "Sequence has a conflict: it is the same as an earlier defined sequence"
+                    );
+                }
                 if sequences.ancestor_exists(&p) {
                     bail_expr!(
                         key_seq_expr,
@@ -140,12 +150,18 @@ pub(crate) fn parse_sequences(exprs: &[&Vec<SExpr>], s: &ParserState) -> Result<
                         "Sequence has a conflict: its sequence is contained within an earlier defined seqence"
                     );
                 }
+                let coord = s
+                    .virtual_keys
+                    .get(vkey)
+                    .map(|(y, _)| get_fake_key_coords(*y))
+                    .expect("vk exists, checked earlier");
                 sequences.insert(
                     p,
-                    s.virtual_keys
-                        .get(vkey)
-                        .map(|(y, _)| get_fake_key_coords(*y))
-                        .expect("vk exists, checked earlier"),
+                    SequenceOutput {
+                        coord,
+                        unmodded_steps,
+                        pass_mods,
+                    },
                 );
             }
         }
@@ -153,14 +169,70 @@ pub(crate) fn parse_sequences(exprs: &[&Vec<SExpr>], s: &ParserState) -> Result<
     Ok(sequences)
 }
 
+/// Parses the virtual key name of a `defseq` pair, which can be `(<name> pass-mods <yes|no>)`.
+/// Returns the name and whether `pass-mods` is enabled.
+fn parse_sequence_vkey_opts<'a>(
+    vkey_expr: &'a SExpr,
+    s: &'a ParserState,
+) -> Result<(&'a SExpr, bool)> {
+    let Some(list) = vkey_expr.list(s.vars()) else {
+        return Ok((vkey_expr, false));
+    };
+    match list {
+        [name, opt, val] if opt.atom(s.vars()) == Some("pass-mods") => {
+            Ok((name, parse_defcfg_val_bool(val, "pass-mods")?))
+        }
+        _ => bail_expr!(
+            vkey_expr,
+            "{SEQ_ERR}\nA list for virtual_key_name must be (<name> pass-mods <yes|no>)"
+        ),
+    }
+}
+
+#[cfg(test)]
 pub(crate) fn parse_sequence_keys(exprs: &[SExpr], s: &ParserState) -> Result<Vec<u16>> {
+    parse_sequence_steps(exprs, s).map(|(keys, _)| keys)
+}
+
+/// Parses the key list of a `defseq` pair. Also returns the bits of
+/// [`SequenceOutput::unmodded_steps`].
+pub(crate) fn parse_sequence_steps(exprs: &[SExpr], s: &ParserState) -> Result<(Vec<u16>, u64)> {
     use SequenceEvent::*;
 
     // Reuse macro parsing but do some other processing since sequences don't support everything
     // that can go in a macro, and also change error messages of course.
     let mut exprs_remaining = exprs;
     let mut all_keys = Vec::new();
+    let mut unmodded_steps = 0u64;
     while !exprs_remaining.is_empty() {
+        if let Some(key) = exprs_remaining[0]
+            .atom(s.vars())
+            .and_then(|a| a.strip_prefix("N-"))
+        {
+            let expr = &exprs_remaining[0];
+            let osc = str_to_oscode(key)
+                .filter(|osc| !osc.is_modifier())
+                .ok_or_else(|| {
+                    anyhow_expr!(
+                        expr,
+                        "{SEQ_ERR}\nN- must be followed by a non-modifier key name"
+                    )
+                })?;
+            let step = all_keys
+                .iter()
+                .filter(|k| **k != KEY_OVERLAP_MARKER)
+                .count();
+            if step >= 64 {
+                bail_expr!(
+                    expr,
+                    "N- can only be used within the first 64 keys of a sequence"
+                );
+            }
+            unmodded_steps |= 1 << step;
+            all_keys.push(u16::from(osc));
+            exprs_remaining = &exprs_remaining[1..];
+            continue;
+        }
         let (mut keys, exprs_remaining_tmp) =
             match parse_macro_item_impl(exprs_remaining, s, MacroNumberParseMode::Action) {
                 Ok(res) => {
@@ -248,5 +320,5 @@ pub(crate) fn parse_sequence_keys(exprs: &[SExpr], s: &ParserState) -> Result<Ve
         all_keys.append(&mut keys);
         exprs_remaining = exprs_remaining_tmp;
     }
-    Ok(all_keys)
+    Ok((all_keys, unmodded_steps))
 }
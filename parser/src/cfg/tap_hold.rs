@@ -420,7 +420,7 @@ Followed by optional lists:
 
 /// Parse keys from a named option list like `(tap-on-press a b c)`.
 /// The first element is the keyword, remaining elements are key names.
-fn parse_key_list_from_option(
+pub(crate) fn parse_key_list_from_option(
     option: &[SExpr],
     option_expr: &SExpr,
     s: &ParserState,
@@ -453,3 +453,23 @@ fn parse_key_list_from_option(
     }
     Ok(keys)
 }
+
+/// Changes the hold timeout of the tap-hold actions on the keys of `tap-hold-key-timeouts`, in
+/// every layer.
+pub(crate) fn apply_tap_hold_key_timeouts(
+    layers: &mut IntermediateLayers,
+    timeouts: &[(OsCode, KeyTimeout)],
+    s: &ParserState,
+) {
+    for layer in layers.iter_mut() {
+        for (osc, timeout) in timeouts {
+            let cell = &mut layer[usize::from(NORMAL_KEY_ROW)][usize::from(*osc)];
+            if let Action::HoldTap(hold_tap) = cell {
+                *cell = Action::HoldTap(s.a.sref(HoldTapAction {
+                    timeout: timeout.apply(hold_tap.timeout),
+                    ..**hold_tap
+                }));
+            }
+        }
+    }
+}
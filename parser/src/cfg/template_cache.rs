@@ -0,0 +1,424 @@
+//! Caches the result of template expansion on disk.
+//!
+//! Expanding the templates of a large configuration can take much of its parse time. The expanded
+//! top-level expressions are therefore written to a cache file
+//! per configuration path, keyed by a SHA-256 digest of everything the expansion depends on: the
+//! expressions after includes and platform and environment filtering, the content of every file
+//! they come from and the parser version. Changing the configuration or one of its included
+//! files changes the key, so a stale cache is not used, short of a SHA-256 collision, and is
+//! replaced on the next parse.
+//!
+//! A cache file that can't be read or written is ignored, so the cache only affects speed.
+//!
+//! Only the expansion is cached. Resolving aliases and building the layout still run on every
+//! parse, since the layout is made of references into the allocations of the parser, which can't
+//! be written to a file and read back. With many templates, this saves about a third of the parse
+//! time rather than all of it.
+
+use super::sexpr::*;
+use super::*;
+
+use rustc_hash::FxHasher;
+use sha2::{Digest, Sha256};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+const MAGIC: &[u8; 4] = b"KTC2";
+
+/// The digest of the input of the expansion.
+type Key = [u8; 32];
+
+/// Feeds [`Hash`] implementations to SHA-256, which unlike the hashers of hash maps makes it
+/// infeasible for different inputs to get the same key.
+#[derive(Default)]
+struct KeyHasher(Sha256);
+
+impl KeyHasher {
+    fn key(self) -> Key {
+        self.0.finalize().into()
+    }
+}
+
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let key: Key = self.0.clone().finalize().into();
+        u64::from_le_bytes(key[..8].try_into().expect("key has 8 bytes"))
+    }
+}
+
+static CACHE_DIR: parking_lot::Mutex<Option<PathBuf>> = parking_lot::Mutex::new(None);
+
+/// Sets the directory to cache expanded templates in. `None`, the default, disables the cache.
+pub fn set_template_cache_dir(dir: Option<PathBuf>) {
+    *CACHE_DIR.lock() = dir;
+}
+
+/// Same as [`expand_templates`], but uses the cached result if the input did not change.
+pub(super) fn expand_templates_cached(
+    xs: Vec<TopLevel>,
+    cfg_path: &Path,
+    lsp_hints: &mut LspHints,
+) -> Result<Vec<TopLevel>> {
+    // The LSP needs the hints that the expansion collects.
+    let dir = CACHE_DIR.lock().clone().filter(|_| !cfg!(feature = "lsp"));
+    match dir {
+        Some(dir) if xs.iter().any(|x| x.t.iter().any(has_template_atom)) => {
+            expand_with_cache(xs, cfg_path, &dir, lsp_hints)
+        }
+        _ => expand_templates(xs, lsp_hints),
+    }
+}
+
+fn expand_with_cache(
+    xs: Vec<TopLevel>,
+    cfg_path: &Path,
+    dir: &Path,
+    lsp_hints: &mut LspHints,
+) -> Result<Vec<TopLevel>> {
+    let mut files = Files::default();
+    let key = files.hash_input(&xs);
+    let mut path_hasher = FxHasher::default();
+    cfg_path
+        .canonicalize()
+        .unwrap_or_else(|_| cfg_path.to_owned())
+        .hash(&mut path_hasher);
+    let path = dir.join(format!("{:016x}.templates", path_hasher.finish()));
+
+    if let Some(exprs) = std::fs::read(&path)
+        .ok()
+        .and_then(|data| Decoder::new(&data, &files).decode(key))
+    {
+        log::debug!("using the expanded templates cached in {}", path.display());
+        return Ok(exprs);
+    }
+    let exprs = expand_templates(xs, lsp_hints)?;
+    if let Some(data) = encode(&exprs, key, &files) {
+        if let Err(e) = write_atomically(dir, &path, &data) {
+            log::warn!(
+                "failed to cache expanded templates in {}: {e}",
+                path.display()
+            );
+        }
+    }
+    Ok(exprs)
+}
+
+/// Whether a template may be defined or used, in which case expansion does more than copy.
+fn has_template_atom(expr: &SExpr) -> bool {
+    match expr {
+        SExpr::Atom(a) => a.t == "deftemplate",
+        SExpr::List(l) => l.t.iter().any(has_template_atom),
+    }
+}
+
+fn write_atomically(dir: &Path, path: &Path, data: &[u8]) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })
+}
+
+/// The files that spans refer to, so that a span can be stored as an index.
+#[derive(Default)]
+struct Files {
+    index: HashMap<(*const u8, *const u8), u32>,
+    list: Vec<(Rc<str>, Rc<str>)>,
+}
+
+impl Files {
+    fn key(span: &Span) -> (*const u8, *const u8) {
+        (span.file_name.as_ptr(), span.file_content.as_ptr())
+    }
+
+    fn add(&mut self, span: &Span) -> u32 {
+        *self.index.entry(Self::key(span)).or_insert_with(|| {
+            self.list
+                .push((span.file_name.clone(), span.file_content.clone()));
+            (self.list.len() - 1) as u32
+        })
+    }
+
+    fn hash_input(&mut self, xs: &[TopLevel]) -> Key {
+        fn hash_exprs(exprs: &[SExpr], files: &mut Files, h: &mut KeyHasher) {
+            h.write_usize(exprs.len());
+            for expr in exprs {
+                match expr {
+                    SExpr::Atom(a) => {
+                        h.write_u8(0);
+                        a.t.hash(h);
+                        hash_span(&a.span, files, h);
+                    }
+                    SExpr::List(l) => {
+                        h.write_u8(1);
+                        hash_exprs(&l.t, files, h);
+                        hash_span(&l.span, files, h);
+                    }
+                }
+            }
+        }
+        fn hash_span(span: &Span, files: &mut Files, h: &mut KeyHasher) {
+            h.write_u32(files.add(span));
+            span.start.hash(h);
+            span.end.hash(h);
+        }
+
+        let mut h = KeyHasher::default();
+        MAGIC.hash(&mut h);
+        env!("CARGO_PKG_VERSION").hash(&mut h);
+        h.write_usize(xs.len());
+        for x in xs {
+            hash_exprs(&x.t, self, &mut h);
+            hash_span(&x.span, self, &mut h);
+        }
+        for (name, content) in &self.list {
+            name.hash(&mut h);
+            content.hash(&mut h);
+        }
+        h.key()
+    }
+}
+
+fn write_uint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Returns `None` if a span refers to a file that is not part of the input, which can't happen
+/// with the current expansion but would make the cache unusable.
+fn encode(xs: &[TopLevel], key: Key, files: &Files) -> Option<Vec<u8>> {
+    fn encode_span(span: &Span, files: &Files, out: &mut Vec<u8>) -> Option<()> {
+        write_uint(out, *files.index.get(&Files::key(span))? as usize);
+        for pos in [span.start, span.end] {
+            write_uint(out, pos.absolute);
+            write_uint(out, pos.line);
+            write_uint(out, pos.line_beginning);
+        }
+        Some(())
+    }
+    fn encode_exprs(exprs: &[SExpr], files: &Files, out: &mut Vec<u8>) -> Option<()> {
+        write_uint(out, exprs.len());
+        for expr in exprs {
+            match expr {
+                SExpr::Atom(a) => {
+                    out.push(0);
+                    write_uint(out, a.t.len());
+                    out.extend_from_slice(a.t.as_bytes());
+                    encode_span(&a.span, files, out)?;
+                }
+                SExpr::List(l) => {
+                    out.push(1);
+                    encode_exprs(&l.t, files, out)?;
+                    encode_span(&l.span, files, out)?;
+                }
+            }
+        }
+        Some(())
+    }
+
+    let mut out = Vec::with_capacity(1 << 16);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&key);
+    write_uint(&mut out, xs.len());
+    for x in xs {
+        encode_exprs(&x.t, files, &mut out)?;
+        encode_span(&x.span, files, &mut out)?;
+    }
+    Some(out)
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    files: &'a Files,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8], files: &'a Files) -> Self {
+        Self { data, files }
+    }
+
+    fn decode(mut self, key: Key) -> Option<Vec<TopLevel>> {
+        if self.bytes(MAGIC.len())? != MAGIC || self.bytes(key.len())? != key {
+            return None;
+        }
+        let len = self.uint()?;
+        let mut xs = Vec::with_capacity(len.min(self.data.len()));
+        for _ in 0..len {
+            let t = self.exprs()?;
+            xs.push(Spanned::new(t, self.span()?));
+        }
+        self.data.is_empty().then_some(xs)
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (bytes, rest) = self.data.split_at_checked(n)?;
+        self.data = rest;
+        Some(bytes)
+    }
+
+    fn uint(&mut self) -> Option<usize> {
+        let mut n = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let (&byte, rest) = self.data.split_first()?;
+            self.data = rest;
+            n |= usize::from(byte & 0x7f).checked_shl(shift)?;
+            if byte & 0x80 == 0 {
+                return Some(n);
+            }
+        }
+        None
+    }
+
+    fn span(&mut self) -> Option<Span> {
+        let files = self.files;
+        let (file_name, file_content) = files.list.get(self.uint()?)?;
+        let mut pos = || {
+            Some(Position {
+                absolute: self.uint()?,
+                line: self.uint()?,
+                line_beginning: self.uint()?,
+            })
+        };
+        Some(Span {
+            start: pos()?,
+            end: pos()?,
+            file_name: file_name.clone(),
+            file_content: file_content.clone(),
+        })
+    }
+
+    fn exprs(&mut self) -> Option<Vec<SExpr>> {
+        let len = self.uint()?;
+        let mut exprs = Vec::with_capacity(len.min(self.data.len()));
+        for _ in 0..len {
+            let (&tag, rest) = self.data.split_first()?;
+            self.data = rest;
+            exprs.push(match tag {
+                0 => {
+                    let len = self.uint()?;
+                    let t = std::str::from_utf8(self.bytes(len)?).ok()?.to_owned();
+                    SExpr::Atom(Spanned::new(t, self.span()?))
+                }
+                1 => {
+                    let t = self.exprs()?;
+                    SExpr::List(Spanned::new(t, self.span()?))
+                }
+                _ => return None,
+            });
+        }
+        Some(exprs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(text: &str) -> Vec<TopLevel> {
+        let xs = sexpr::parse(text, "cache-test.kbd").unwrap();
+        expand_templates(xs, &mut Default::default()).unwrap()
+    }
+
+    #[test]
+    fn cached_expansion_is_identical() {
+        let text = "
+(deftemplate hrm (k m) (tap-hold 200 200 $k $m))
+(defsrc a s)
+(deflayer base (t! hrm a lctl) (t! hrm s lalt))
+";
+        let input = sexpr::parse(text, "test.kbd").unwrap();
+        let mut files = Files::default();
+        let key = files.hash_input(&input);
+        let expanded = expand_templates(input, &mut Default::default()).unwrap();
+        let data = encode(&expanded, key, &files).unwrap();
+        assert_eq!(Decoder::new(&data, &files).decode(key).unwrap(), expanded);
+        let mut other_key = key;
+        other_key[31] ^= 1;
+        assert!(Decoder::new(&data, &files).decode(other_key).is_none());
+        assert!(
+            Decoder::new(&data[..data.len() - 1], &files)
+                .decode(key)
+                .is_none()
+        );
+
+        // A change anywhere in the input, here the hold action, changes the key.
+        let other = sexpr::parse(&text.replace("lalt", "ralt"), "test.kbd").unwrap();
+        assert_ne!(Files::default().hash_input(&other), key);
+    }
+
+    #[test]
+    fn cache_file_is_used_and_replaced() {
+        let dir = std::env::temp_dir().join(format!("kanata-tmpl-cache-{}", std::process::id()));
+        let cfg_path = Path::new("cache-test.kbd");
+        let text = "(deftemplate k (x) $x) (defsrc a) (deflayer base (t! k b))";
+        let parse = |text: &str| {
+            let xs = sexpr::parse(text, "cache-test.kbd").unwrap();
+            expand_with_cache(xs, cfg_path, &dir, &mut Default::default()).unwrap()
+        };
+        assert_eq!(parse(text), expand(text));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(parse(text), expand(text));
+        let changed = text.replace("(t! k b)", "(t! k c)");
+        assert_eq!(parse(&changed), expand(&changed));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cache_is_replaced_when_an_included_file_changes() {
+        let dir = std::env::temp_dir().join(format!("kanata-tmpl-include-{}", std::process::id()));
+        let cfg_path = Path::new("cache-include.kbd");
+        let text = "(include templates.kbd) (defsrc a) (deflayer base (t! k b))";
+        let with_included = |included: &str| {
+            let xs = sexpr::parse(text, "cache-include.kbd").unwrap();
+            let mut read = |_: &Path| Ok(included.to_owned());
+            let mut provider = FileContentProvider {
+                get_file_content_fn: &mut read,
+            };
+            expand_includes(xs, &mut provider, &mut Default::default()).unwrap()
+        };
+        let parse = |included: &str| {
+            expand_with_cache(
+                with_included(included),
+                cfg_path,
+                &dir,
+                &mut Default::default(),
+            )
+            .unwrap()
+        };
+        let cache_file = || {
+            let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+            assert_eq!(entries.len(), 1);
+            std::fs::read(entries[0].as_ref().unwrap().path()).unwrap()
+        };
+        let expand = |included: &str| {
+            expand_templates(with_included(included), &mut Default::default()).unwrap()
+        };
+
+        let included = "(deftemplate k (x) $x)";
+        assert_eq!(parse(included), expand(included));
+        let cached = cache_file();
+        // Only the included file changes, and the cache follows it.
+        let changed = "(deftemplate k (x) (multi $x c))";
+        assert_eq!(parse(changed), expand(changed));
+        assert_ne!(parse(changed), expand(included));
+        // A change that leaves the expressions as they were, here a comment, still changes the key,
+        // since the cached spans point into the text of the file.
+        let commented = ";; templates\n(deftemplate k (x) $x)";
+        assert_ne!(
+            Files::default().hash_input(&with_included(commented)),
+            Files::default().hash_input(&with_included(included))
+        );
+        assert_eq!(parse(commented), expand(commented));
+        assert_ne!(cache_file(), cached);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
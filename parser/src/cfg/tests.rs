@@ -6,6 +6,8 @@ use kanata_keyberon::action::BooleanOperator::*;
 use std::sync::{Mutex, MutexGuard};
 
 mod ambiguous;
+mod ast;
+mod builder;
 mod defcfg;
 mod defhands;
 mod device_detect;
@@ -58,9 +60,10 @@ fn parse_cfg(cfg: &str) -> Result<IntermediateCfg> {
         Err("env vars not implemented".into()),
     );
     if let Ok(ref icfg) = icfg {
-        assert!(icfg.klayers.layers.iter().all(|layer| {
-            layer[usize::from(NORMAL_KEY_ROW)]
-                .iter()
+        let layers = icfg.klayers.layers;
+        assert!((0..layers.len()).all(|layer| {
+            layers
+                .row(layer, usize::from(NORMAL_KEY_ROW))
                 .all(|action| *action != DEFAULT_ACTION)
         }));
         #[cfg(any(target_os = "linux", target_os = "android", target_os = "unknown"))]
@@ -77,6 +80,31 @@ fn sizeof_action_is_two_usizes() {
     );
 }
 
+#[test]
+fn compact_layers_keep_actions() {
+    let mut layers = new_layers(3);
+    layers[0][0].fill(Action::KeyCode(KeyCode::A));
+    layers[1][0].fill(Action::Trans);
+    layers[1][0][usize::from(OsCode::KEY_B)] = Action::KeyCode(KeyCode::C);
+    layers[2][0]
+        .iter_mut()
+        .enumerate()
+        .for_each(|(i, action)| *action = Action::Layer(i));
+    let a = unsafe { Allocations::new() };
+    let compact = compact_layers(&layers, &a);
+    for (i, layer) in layers.iter().enumerate() {
+        for (row, actions) in layer.iter().enumerate() {
+            assert!(compact.row(i, row).eq(actions.iter()));
+        }
+    }
+    let LayerStore::Rows(rows) = compact else {
+        panic!("layers should be rows");
+    };
+    assert!(matches!(rows[1][0], LayerRow::Sparse { actions, .. } if actions.len() == 1));
+    assert!(matches!(rows[1][1], LayerRow::Sparse { actions, .. } if actions.is_empty()));
+    assert!(matches!(rows[2][0], LayerRow::Dense(_)));
+}
+
 #[test]
 fn test_span_absolute_ranges() {
     let s = "(hello world my oyster)\n(row two)";
@@ -577,12 +605,67 @@ fn test_parse_macro_numbers() {
     parse_macro_item(exprs.as_slice(), &ParserState::default()).expect_err("errors");
 }
 
+#[test]
+fn oscode_to_str_round_trips() {
+    use crate::keys::*;
+    let _lk = lock(&CFG_PARSE_LOCK);
+    clear_custom_str_oscode_mapping();
+    assert_eq!(oscode_to_str(OsCode::KEY_LEFTSHIFT), Some("lshift"));
+    assert_eq!(oscode_to_str(OsCode::KEY_A), Some("a"));
+    for code in 0..u16::from(OsCode::KEY_MAX) {
+        let Some(code) = OsCode::from_u16(code) else {
+            continue;
+        };
+        if let Some(name) = oscode_to_str(code) {
+            assert_eq!(str_to_oscode(name), Some(code), "{name}");
+        }
+    }
+}
+
 #[test]
 fn test_include_good() {
     let _lk = lock(&CFG_PARSE_LOCK);
     new_from_file(&std::path::PathBuf::from("./test_cfgs/include-good.kbd")).unwrap();
 }
 
+#[test]
+fn test_include_good_files() {
+    let _lk = lock(&CFG_PARSE_LOCK);
+    let cfg = new_from_file(&std::path::PathBuf::from("./test_cfgs/include-good.kbd")).unwrap();
+    let names: Vec<_> = cfg
+        .files
+        .iter()
+        .map(|f| f.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(names, ["include-good.kbd", "included-good.kbd"]);
+}
+
+#[test]
+fn test_include_relative_to_unsaved_text() {
+    let _lk = lock(&CFG_PARSE_LOCK);
+    let path = std::path::Path::new("./test_cfgs/unsaved.kbd");
+    assert!(new_from_str_at_path("(defsrc a) (include included-good.kbd)", path).is_ok());
+    assert!(new_from_str_at_path("(defsrc a) (include include-good.kbd)", path).is_err());
+}
+
+#[test]
+fn test_include_read_with_reader() {
+    let _lk = lock(&CFG_PARSE_LOCK);
+    let path = std::path::Path::new("./test_cfgs/unsaved.kbd");
+    let mut read = Vec::new();
+    let mut refuse = |file: &std::path::Path| {
+        read.push(file.file_name().unwrap().to_owned());
+        Err("refused".to_owned())
+    };
+    let Err(err) =
+        new_from_str_at_path_with("(defsrc a) (include included-good.kbd)", path, &mut refuse)
+    else {
+        panic!("the include is refused");
+    };
+    assert_eq!(read, ["included-good.kbd"]);
+    assert!(format!("{err:?}").contains("Failed to include file: refused"));
+}
+
 #[test]
 fn test_include_bad_has_filename_included() {
     let _lk = lock(&CFG_PARSE_LOCK);
@@ -756,7 +839,7 @@ fn parse_switch() {
         OpCode::new_historical_input((NORMAL_KEY_ROW, u16::from(OsCode::KEY_LEFTSHIFT)), 7);
     let (klayers, _) = res.klayers.get();
     assert_eq!(
-        klayers[0][0][OsCode::KEY_A.as_u16() as usize],
+        *klayers.get(0, 0, OsCode::KEY_A.as_u16() as usize),
         Action::Switch(&Switch {
             cases: &[
                 (
@@ -936,28 +1019,28 @@ fn parse_virtualkeys() {
     .unwrap();
     let (klayers, _) = res.klayers.get();
     assert_eq!(
-        klayers[0][0][OsCode::KEY_A.as_u16() as usize],
+        *klayers.get(0, 0, OsCode::KEY_A.as_u16() as usize),
         Action::Custom(&CustomAction::FakeKey {
             coord: Coord { x: 1, y: 0 },
             action: FakeKeyAction::Press,
         }),
     );
     assert_eq!(
-        klayers[0][0][OsCode::KEY_F.as_u16() as usize],
+        *klayers.get(0, 0, OsCode::KEY_F.as_u16() as usize),
         Action::Custom(&CustomAction::FakeKey {
             coord: Coord { x: 1, y: 1 },
             action: FakeKeyAction::Release,
         }),
     );
     assert_eq!(
-        klayers[0][0][OsCode::KEY_K.as_u16() as usize],
+        *klayers.get(0, 0, OsCode::KEY_K.as_u16() as usize),
         Action::Custom(&CustomAction::FakeKeyOnRelease {
             coord: Coord { x: 1, y: 0 },
             action: FakeKeyAction::Toggle,
         }),
     );
     assert_eq!(
-        klayers[0][0][OsCode::KEY_P.as_u16() as usize],
+        *klayers.get(0, 0, OsCode::KEY_P.as_u16() as usize),
         Action::Custom(&CustomAction::FakeKeyOnRelease {
             coord: Coord { x: 1, y: 1 },
             action: FakeKeyAction::Tap,
@@ -989,7 +1072,7 @@ fn parse_on_idle_fakekey() {
         .expect("parses");
     let (klayers, _) = res.klayers.get();
     assert_eq!(
-        klayers[0][0][OsCode::KEY_A.as_u16() as usize],
+        *klayers.get(0, 0, OsCode::KEY_A.as_u16() as usize),
         Action::Custom(&CustomAction::FakeKeyOnIdle(FakeKeyOnIdle {
             coord: Coord { x: 1, y: 0 },
             action: FakeKeyAction::Tap,
@@ -1413,6 +1496,23 @@ fn parse_all_defcfg() {
         .expect("parses");
 }
 
+#[test]
+fn parse_defcfg_release_order_secrets_and_output() {
+    let source = r#"
+(defcfg
+  danger-enable-secrets yes
+  preserve-release-order yes
+  linux-output-absolute-pointer yes
+  linux-output-backend uinput
+)
+(defsrc a)
+(deflayer base a)
+"#;
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+}
+
 #[test]
 fn parse_defcfg_linux_output_bus() {
     let source = r#"
@@ -1443,6 +1543,209 @@ fn parse_defcfg_linux_output_bus() {
     );
 }
 
+#[test]
+fn parse_defcfg_linux_layer_leds() {
+    let source = r#"
+(defcfg linux-layer-leds (nav (caps scroll) base none))
+(defsrc a)
+(deflayer base a)
+(deflayer nav b)
+"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(
+        cfg.options.linux_opts.linux_layer_leds,
+        vec![
+            (
+                "nav".to_owned(),
+                LockLeds {
+                    caps: true,
+                    num: false,
+                    scroll: true
+                }
+            ),
+            ("base".to_owned(), LockLeds::default()),
+        ]
+    );
+    let bad = [
+        "(defcfg linux-layer-leds (nav (caps))) (defsrc a) (deflayer base a)",
+        "(defcfg linux-layer-leds (base (caps caps))) (defsrc a) (deflayer base a)",
+        "(defcfg linux-layer-leds (base (kana))) (defsrc a) (deflayer base a)",
+        "(defcfg linux-layer-leds (base caps)) (defsrc a) (deflayer base a)",
+        "(defcfg linux-layer-leds (base none base (num))) (defsrc a) (deflayer base a)",
+        "(defcfg linux-layer-leds (base)) (defsrc a) (deflayer base a)",
+    ];
+    for source in bad {
+        parse_cfg(source).expect_err(source);
+    }
+}
+
+#[test]
+fn parse_defcfg_linux_output_device_routes() {
+    let source = r#"
+(defcfg linux-output-device-routes (2 "kanata macro pad" 3 pedal))
+(definputdevices 2 ((name "Macro Pad")) 3 ((vendor_id 0x1209)))
+(defsrc a)
+(deflayer base a)
+"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(
+        cfg.options.linux_opts.linux_output_device_routes,
+        vec![
+            (
+                std::num::NonZeroU8::new(2).unwrap(),
+                "kanata macro pad".to_owned()
+            ),
+            (std::num::NonZeroU8::new(3).unwrap(), "pedal".to_owned()),
+        ]
+    );
+    let bad = [
+        "(defcfg linux-output-device-routes (2 pad)) (defsrc a) (deflayer base a)",
+        "(defcfg linux-output-device-routes (0 pad)) (definputdevices 2 ((name p))) (defsrc a) (deflayer base a)",
+        "(defcfg linux-output-device-routes (2 pad 2 other)) (definputdevices 2 ((name p))) (defsrc a) (deflayer base a)",
+        "(defcfg linux-output-device-routes (2 pad 3 pad)) (definputdevices 2 ((name p)) 3 ((name q))) (defsrc a) (deflayer base a)",
+        "(defcfg linux-output-device-routes (2 kanata)) (definputdevices 2 ((name p))) (defsrc a) (deflayer base a)",
+        "(defcfg linux-output-device-routes (2)) (definputdevices 2 ((name p))) (defsrc a) (deflayer base a)",
+        "(defcfg linux-output-backend xtest linux-output-device-routes (2 pad)) (definputdevices 2 ((name p))) (defsrc a) (deflayer base a)",
+    ];
+    for source in bad {
+        parse_cfg(source).expect_err(source);
+    }
+}
+
+#[test]
+fn parse_defcfg_windows_scancode_overrides() {
+    let source = r#"
+(defcfg windows-scancode-overrides (nubs 0x56 ralt 0xE038 kp0 82))
+(defsrc a)
+(deflayer base a)
+"#;
+    let _cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    #[cfg(target_os = "windows")]
+    assert_eq!(
+        _cfg.options.windows_opts.scancode_overrides,
+        vec![
+            (OsCode::KEY_102ND, 0x56),
+            (OsCode::KEY_RIGHTALT, 0xE038),
+            (OsCode::KEY_KP0, 82),
+        ]
+    );
+    let bad = [
+        "(defcfg windows-scancode-overrides (nubs)) (defsrc a) (deflayer base a)",
+        "(defcfg windows-scancode-overrides (notakey 0x56)) (defsrc a) (deflayer base a)",
+        "(defcfg windows-scancode-overrides (nubs 0x56 nubs 0x57)) (defsrc a) (deflayer base a)",
+        "(defcfg windows-scancode-overrides (nubs 0x1056)) (defsrc a) (deflayer base a)",
+        "(defcfg windows-scancode-overrides (nubs 0)) (defsrc a) (deflayer base a)",
+        "(defcfg windows-scancode-overrides nubs) (defsrc a) (deflayer base a)",
+    ];
+    for source in bad {
+        parse_cfg(source).expect_err(source);
+    }
+}
+
+#[test]
+fn parse_defcfg_linux_output_backend() {
+    let source = r#"
+(defcfg linux-output-backend xtest)
+(defsrc a)
+(deflayer base a)
+"#;
+    #[cfg(feature = "x11_xtest")]
+    {
+        let cfg = parse_cfg(source)
+            .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+            .expect("parses");
+        assert_eq!(
+            cfg.options.linux_opts.linux_output_backend,
+            LinuxCfgOutputBackend::Xtest
+        );
+    }
+    #[cfg(not(feature = "x11_xtest"))]
+    {
+        let err = parse_cfg(source).expect_err("xtest needs the x11_xtest feature");
+        assert!(err.msg.contains("\"x11_xtest\" feature"));
+    }
+    let source = r#"
+(defcfg linux-output-backend wayland)
+(defsrc a)
+(deflayer base a)
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("Invalid value for linux-output-backend"));
+    let source = r#"
+(defcfg
+  linux-output-backend remote
+  linux-output-remote-address 192.168.1.20:5840
+  linux-output-remote-token-file /etc/kanata/token)
+(defsrc a)
+(deflayer base a)
+"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let opts = &cfg.options.linux_opts;
+    assert_eq!(opts.linux_output_backend, LinuxCfgOutputBackend::Remote);
+    assert_eq!(
+        opts.linux_output_remote_address.as_deref(),
+        Some("192.168.1.20:5840")
+    );
+    assert_eq!(
+        opts.linux_output_remote_token_file.as_deref(),
+        Some("/etc/kanata/token")
+    );
+    let source = r#"
+(defcfg linux-output-backend remote linux-output-remote-address 192.168.1.20:5840)
+(defsrc a)
+(deflayer base a)
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("linux-output-remote-token-file"));
+}
+
+#[test]
+fn parse_defcfg_linux_input_backend() {
+    let source = "(defcfg linux-input-backend libinput) (defsrc a) (deflayer base a)";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(
+        cfg.options.linux_opts.linux_input_backend,
+        LinuxCfgInputBackend::Libinput
+    );
+    let source = "(defcfg linux-input-backend udev) (defsrc a) (deflayer base a)";
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("Invalid value for linux-input-backend"));
+}
+
+#[test]
+fn parse_defcfg_linux_output_absolute_pointer() {
+    let source = "(defcfg) (defsrc a) (deflayer base a)";
+    let cfg = parse_cfg(source).expect("parses");
+    assert!(!cfg.options.linux_opts.linux_output_absolute_pointer);
+    let source = "(defcfg linux-output-absolute-pointer yes) (defsrc a) (deflayer base a)";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert!(cfg.options.linux_opts.linux_output_absolute_pointer);
+}
+
+#[test]
+fn parse_linux_seat() {
+    let source = "(defcfg linux-seat seat1) (defsrc a) (deflayer base a)";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(cfg.options.linux_opts.linux_seat.as_deref(), Some("seat1"));
+    let source = r#"(defcfg linux-seat "") (defsrc a) (deflayer base a)"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("linux-seat must not be empty"));
+}
+
 #[test]
 fn parse_unmod() {
     let source = r#"
@@ -1535,6 +1838,122 @@ fn parse_cmd() {
         .expect("parses");
 }
 
+#[test]
+#[cfg(feature = "cmd")]
+fn parse_cmd_timeout_and_cancel() {
+    let source = r#"
+(defcfg danger-enable-cmd yes cmd-timeout 30000)
+(defsrc a)
+(deflayer base a)
+(defvar x blah)
+(defalias
+    1 (cmd-cancel)
+    2 (cmd-cancel $x)
+)
+"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(cfg.options.cmd_timeout, 30000);
+    parse_cfg("(defsrc a) (deflayer base a) (defalias c (cmd-cancel a b))")
+        .expect_err("cmd-cancel takes one program");
+}
+
+#[test]
+#[cfg(feature = "cmd")]
+fn parse_cmd_allowlist() {
+    let source = r#"
+(defcfg cmd-allowlist (
+    /usr/bin/notify-send
+    (/usr/bin/playerctl play-pause)
+    (/usr/bin/pactl set-sink-volume @DEFAULT_SINK@ "*%")
+    (/usr/bin/git -C ...)
+))
+(defsrc a)
+(deflayer base a)
+(defalias
+    1 (cmd /usr/bin/notify-send hello "from kanata")
+    2 (cmd /usr/bin/playerctl play-pause)
+    3 (cmd-log info warn /usr/bin/pactl set-sink-volume @DEFAULT_SINK@ +5%)
+    4 (cmd-output-keys /usr/bin/git -C repo log -1)
+)
+"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let allowlist = cfg.options.cmd_allowlist.expect("allowlist is set");
+    assert!(allowlist.allows(&["/usr/bin/git", "-C"]));
+    assert!(!allowlist.allows(&["/usr/bin/git", "push"]));
+    assert!(!allowlist.allows(&["/usr/bin/playerctl", "stop"]));
+
+    for denied in [
+        "(cmd notify-send hello)",
+        "(cmd /usr/bin/playerctl play-pause now)",
+        "(cmd-log info warn /usr/bin/pactl set-sink-volume @DEFAULT_SINK@ 5)",
+        "(clipboard-cmd-set /bin/sh -c id)",
+    ] {
+        let source = source.replace("(cmd /usr/bin/playerctl play-pause)", denied);
+        let err = parse_cfg(&source).expect_err(denied);
+        assert!(
+            err.msg.contains("cmd-allowlist in defcfg does not permit"),
+            "{denied}: {}",
+            err.msg
+        );
+    }
+
+    let err =
+        parse_cfg("(defcfg cmd-allowlist ((/usr/bin/git ... log))) (defsrc a) (deflayer base a)")
+            .expect_err("... is not last");
+    assert!(err.msg.contains("must be the last pattern"), "{}", err.msg);
+}
+
+#[test]
+#[cfg(feature = "cmd")]
+fn parse_cmd_argv() {
+    let source = r#"
+(defcfg danger-enable-cmd yes)
+(defsrc a)
+(deflayer base @1)
+(defvar file "my notes.txt; rm -rf ~")
+(defalias
+    1 (cmd-argv (/usr/bin/notify-send $file) env-clear yes env (LANG C) uid 65534 gid 65534)
+    2 (cmd-argv (/usr/bin/true))
+)
+"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let (klayers, _) = cfg.klayers.get();
+    let Action::Custom(CustomAction::CmdArgv(argv, sandbox)) =
+        *klayers.get(0, 0, OsCode::KEY_A.as_u16() as usize)
+    else {
+        panic!("not cmd-argv");
+    };
+    assert_eq!(*argv, ["/usr/bin/notify-send", "my notes.txt; rm -rf ~"]);
+    assert_eq!(
+        **sandbox,
+        CmdSandbox {
+            clear_env: true,
+            env: vec![("LANG".to_owned(), "C".into())],
+            uid: Some(65534),
+            gid: Some(65534),
+        }
+    );
+
+    for invalid in [
+        "(cmd-argv /usr/bin/true)",
+        "(cmd-argv ())",
+        "(cmd-argv (/usr/bin/echo (a b)))",
+        "(cmd-argv (/usr/bin/true) env (LANG))",
+        "(cmd-argv (/usr/bin/true) uid nobody)",
+        "(cmd-argv (/usr/bin/true) chroot /)",
+        "(cmd-argv (/usr/bin/true) env-clear)",
+    ] {
+        let source = source.replace("(cmd-argv (/usr/bin/true))", invalid);
+        parse_cfg(&source).map(|_| ()).expect_err(invalid);
+    }
+}
+
 #[test]
 #[cfg(feature = "cmd")]
 fn parse_cmd_log() {
@@ -1560,88 +1979,627 @@ fn parse_cmd_log() {
 }
 
 #[test]
-fn parse_defvar_concat() {
-    let _lk = lock(&CFG_PARSE_LOCK);
+fn parse_midi() {
     let source = r#"
-(defsrc a)
-(deflayer base a)
-(defvar
-    x (concat a b c)
-    y (concat d (e f))
-    z (squish squash (splish splosh))
-    xx (concat $x $y)
-    xy (concat $x ($y))
-    xz (notconcat a b " " c " d")
-    yx (concat a b " " c " d" ("efg" " hij ") "kl")
-    yz (concat "abc"def"ghi""jkl")
-
-    rootpath "/home/myuser/mysubdir"
-    ;; $otherpath will be the string: /home/myuser/mysubdir/helloworld
-    otherpath (concat $rootpath "/helloworld")
-)
+(defcfg midi-output-port "USB MIDI")
+(defsrc a b c)
+(deflayer base (midi-note 60) (midi-note 60 127 16) (midi-cc 1 64))
 "#;
-    let mut s = ParserState::default();
-    parse_cfg_raw_string(
-        source,
-        &mut s,
-        &PathBuf::from("test"),
-        &mut FileContentProvider {
-            get_file_content_fn: &mut |_| unimplemented!(),
-        },
-        DEF_LOCAL_KEYS,
-        Err("env vars not implemented".into()),
-    )
-    .expect("succeeds");
-    match s.vars().unwrap().get("x").unwrap() {
-        SExpr::Atom(a) => assert_eq!(&a.t, "abc"),
-        SExpr::List(l) => panic!("expected string not list: {l:?}"),
-    }
-    match s.vars().unwrap().get("y").unwrap() {
-        SExpr::Atom(a) => assert_eq!(&a.t, "def"),
-        SExpr::List(l) => panic!("expected string not list: {l:?}"),
-    }
-    match s.vars().unwrap().get("z").unwrap() {
-        SExpr::Atom(a) => panic!("expected list not string: {a:?}"),
-        SExpr::List(_) => {}
-    }
-    match s.vars().unwrap().get("xx").unwrap() {
-        SExpr::Atom(a) => assert_eq!(&a.t, "abcdef"),
-        SExpr::List(l) => panic!("expected string not list: {l:?}"),
-    }
-    match s.vars().unwrap().get("xy").unwrap() {
-        SExpr::Atom(a) => assert_eq!(&a.t, "abcdef"),
-        SExpr::List(l) => panic!("expected string not list: {l:?}"),
-    }
-    match s.vars().unwrap().get("xz").unwrap() {
-        SExpr::Atom(a) => panic!("expected list not string {a:?}"),
-        SExpr::List(_) => {}
-    }
-    match s.vars().unwrap().get("yx").unwrap() {
-        SExpr::Atom(a) => assert_eq!(&a.t, "ab c defg hij kl"),
-        SExpr::List(l) => panic!("expected string not list: {l:?}"),
-    }
-    match s.vars().unwrap().get("yz").unwrap() {
-        SExpr::Atom(a) => assert_eq!(&a.t, "abcdefghijkl"),
-        SExpr::List(l) => panic!("expected string not list: {l:?}"),
-    }
-    match s.vars().unwrap().get("otherpath").unwrap() {
-        SExpr::Atom(a) => assert_eq!(&a.t, "/home/myuser/mysubdir/helloworld"),
-        SExpr::List(l) => panic!("expected string not list: {l:?}"),
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(cfg.options.midi_output_port.as_deref(), Some("USB MIDI"));
+    for bad in [
+        "(midi-note 128)",
+        "(midi-note 60 100 0)",
+        "(midi-note 60 100 17)",
+        "(midi-note)",
+        "(midi-cc 1)",
+        "(midi-cc 1 128)",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
     }
 }
 
 #[test]
-fn parse_template_1() {
+fn parse_obs() {
     let source = r#"
-(deftemplate home-row (j-behaviour)
-  a s d f g h $j-behaviour k l ; '
-)
-
-(defsrc
-  grv  1    2    3    4    5    6    7    8    9    0    -    =    bspc
-  tab  q    w    e    r    t    y    u    i    o    p    [    ]    \
-  caps (template-expand home-row j)                            ret
-  lsft z    x    c    v    b    n    m    ,    .    /    rsft
+(defcfg obs-websocket-address "192.168.1.5:4455" obs-websocket-password hunter2)
+(defsrc a b c)
+(deflayer base (obs switch-scene "Be right back") (obs toggle-source Main Camera) (obs toggle-recording))
+"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(
+        cfg.options.obs_websocket_address.as_deref(),
+        Some("192.168.1.5:4455")
+    );
+    assert_eq!(
+        cfg.options.obs_websocket_password.as_deref(),
+        Some("hunter2")
+    );
+    for bad in [
+        "(obs)",
+        "(obs switch-scene)",
+        "(obs switch-scene a b)",
+        "(obs toggle-source a)",
+        "(obs start-recording now)",
+        "(obs start-streaming)",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_openrgb() {
+    let source = r##"
+(defcfg openrgb-address "192.168.1.5:6742" openrgb-device keychron openrgb-layer-colors yes)
+(defsrc a b c)
+(deflayer (base color #336699) (openrgb #f80) (openrgb "#00ff00") (openrgb off))
+"##;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(
+        cfg.options.openrgb_address.as_deref(),
+        Some("192.168.1.5:6742")
+    );
+    assert_eq!(cfg.options.openrgb_device.as_deref(), Some("keychron"));
+    assert!(cfg.options.openrgb_layer_colors);
+    for bad in [
+        "(openrgb)",
+        "(openrgb red)",
+        "(openrgb #12345)",
+        "(openrgb #fff #000)",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_defwebhooks() {
+    let source = r#"
+(defwebhooks
+  ha "http://homeassistant.local:8123/api/webhook/kanata" (layer-change reload error)
+  relay http://[::1] ()
+)
+(defsrc a b)
+(deflayer base (webhook relay) (webhook ha "lights on"))
+"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(cfg.webhooks.len(), 2);
+    assert_eq!(cfg.webhooks[0].address, "homeassistant.local:8123");
+    assert_eq!(cfg.webhooks[0].path, "/api/webhook/kanata");
+    assert_eq!(
+        cfg.webhooks[0].events,
+        [
+            WebhookEvent::LayerChange,
+            WebhookEvent::Reload,
+            WebhookEvent::Error
+        ]
+    );
+    assert_eq!(cfg.webhooks[1].address, "[::1]:80");
+    assert_eq!(cfg.webhooks[1].host, "[::1]");
+    assert_eq!(cfg.webhooks[1].path, "/");
+    assert!(cfg.webhooks[1].events.is_empty());
+    for bad in [
+        "(defwebhooks a http://localhost)",
+        "(defwebhooks a https://example.com ())",
+        "(defwebhooks a localhost:80 ())",
+        "(defwebhooks a http://localhost (press))",
+        "(defwebhooks a http://localhost () a http://localhost:81 ())",
+    ] {
+        let source = format!("{bad} (defsrc a) (deflayer base a)");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+    for bad in ["(webhook)", "(webhook b)", "(webhook a x y)"] {
+        let source =
+            format!("(defwebhooks a http://localhost ()) (defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_defplugins() {
+    set_plugin_actions(vec![(
+        "weather".to_owned(),
+        vec!["show".to_owned(), "type-forecast".to_owned()],
+    )]);
+    let source = r#"
+(defplugins weather (output layer))
+(defsrc a b)
+(deflayer base (plugin weather show) (plugin weather type-forecast berlin "3 days"))
+"#;
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for bad in [
+        "(defplugins weather)",
+        "(defplugins weather (cmd))",
+        "(defplugins weather (output output))",
+        "(defplugins weather () weather ())",
+        "(defplugins clock ())",
+    ] {
+        let source = format!("{bad} (defsrc a) (deflayer base a)");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+    for bad in [
+        "(plugin weather)",
+        "(plugin clock show)",
+        "(plugin weather hide)",
+        "(plugin weather show (berlin))",
+    ] {
+        let source = format!("(defplugins weather ()) (defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+    parse_cfg("(defsrc a) (deflayer base (plugin weather show))")
+        .map(|_| ())
+        .expect_err("not in defplugins");
+}
+
+#[test]
+fn parse_notify() {
+    let source = r#"
+(defsrc a b)
+(deflayer base (notify "Gaming layer" "ON") (notify Saved))
+"#;
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for bad in ["(notify)", "(notify a b c)", "(notify (a) b)"] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_mpris() {
+    let source = r#"
+(defsrc a b c)
+(deflayer base (mpris play-pause spotify) (mpris next) (mpris previous "mpv"))
+"#;
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for bad in [
+        "(mpris)",
+        "(mpris shuffle)",
+        "(mpris next spotify mpv)",
+        "(mpris next \"\")",
+        "(mpris next (spotify))",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_mod_morph() {
+    let source = r#"
+(defsrc a b)
+(deflayer base (mod-morph a b (lsft rsft)) (mod-morph b ; (lsft) consume-mods))
+"#;
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let source = r#"
+(defsrc a)
+(deflayer base (mod-morph a b (lsft) consume))
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("The 4th param must be consume-mods"));
+    let source = r#"
+(defsrc a)
+(deflayer base (mod-morph a (macro b c) (lsft) consume-mods))
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("must be a key name"));
+}
+
+#[test]
+fn parse_deflocalkeys_layout() {
+    let source = format!(
+        r#"
+({DEF_LOCAL_KEYS} (layout de de-DE) lkde 300)
+({DEF_LOCAL_KEYS} lkany 301)
+(defsrc lkde)
+(deflayer base _)
+"#
+    );
+    set_os_keyboard_layout(Some("DE-de".into()));
+    let result = parse_cfg(&source);
+    set_os_keyboard_layout(None);
+    result
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let err = parse_cfg(&source).expect_err("should err");
+    assert!(err.msg.contains("Unknown key in defsrc"));
+    let source = format!(
+        r#"
+({DEF_LOCAL_KEYS} lkany 301)
+({DEF_LOCAL_KEYS} lkany 301)
+(defsrc lkany)
+(deflayer base _)
+"#
+    );
+    let err = parse_cfg(&source).expect_err("should err");
+    assert!(err.msg.contains("Only one"));
+    let source = format!(
+        r#"
+({DEF_LOCAL_KEYS} (lang de) lkde 300)
+(defsrc)
+(deflayer base)
+"#
+    );
+    let err = parse_cfg(&source).expect_err("should err");
+    assert!(err.msg.contains("(layout <name> ...)"));
+}
+
+#[test]
+fn parse_layout_translation() {
+    let source = r#"
+(defcfg layout-translation workman)
+(defsrc a)
+(deflayer (base layout colemak) _)
+(deflayermap (dv layout dvorak))
+"#;
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let source = r#"
+(defcfg layout-translation azerty)
+(defsrc a)
+(deflayer base _)
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("this option must be one of: colemak"));
+    let source = r#"
+(defsrc a)
+(deflayer (base layout azerty) _)
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("Unknown layout azerty"));
+}
+
+#[test]
+fn parse_defcompose() {
+    let source = r#"
+(defsrc a)
+(deflayer base (compose 1000))
+(defcompose (apo e) é (S-apo AG-o) ö (o o) "°C")
+"#;
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let source = r#"
+(defsrc a)
+(deflayer base (compose 1000))
+(defcompose (a e) æ (a e x) ä)
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("conflicts with an earlier one"));
+    let source = r#"
+(defsrc a)
+(deflayer base (compose 1000))
+(defcompose (lsft e) é)
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("Unknown key"));
+    let source = r#"
+(defsrc a)
+(deflayer base (compose))
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("compose expects one param"));
+}
+
+#[test]
+fn parse_defcustomshift() {
+    let source = r#"
+(defsrc)
+(deflayer base)
+(defcustomshift 1 7 2 S-[ ' S-')
+"#;
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let source = r#"
+(defsrc)
+(deflayer base)
+(defcustomshift 1 7 2)
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("Missing the shifted output"));
+    let source = r#"
+(defsrc)
+(deflayer base)
+(defcustomshift 1 7 1 8)
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("already has a shifted output"));
+    let source = r#"
+(defsrc)
+(deflayer base)
+(defcustomshift 1 7)
+(defcustomshift 2 8)
+"#;
+    let err = parse_cfg(source).expect_err("should err");
+    assert!(err.msg.contains("Only one defcustomshift allowed"));
+}
+
+#[test]
+fn parse_defautoshift() {
+    let source = "
+(defsrc a b c)
+(deflayer base a b c)
+(defautoshift 180 a b (c S-1))
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for bad in [
+        "(defautoshift 180)",
+        "(defautoshift 0 a)",
+        "(defautoshift 180 a a)",
+        "(defautoshift 180 (a))",
+        "(defautoshift 180 (a (tap-hold 1 1 a b)))",
+        "(defautoshift 180 a) (defautoshift 180 b)",
+    ] {
+        let source = format!("(defsrc a b) (deflayer base a b) {bad}");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_mouse_grid() {
+    let source = "
+(defsrc a b c)
+(deflayer base (mouse-grid 3 2 3 2) mouse-grid-reset (mouse-grid-monitor 2))
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for bad in [
+        "(mouse-grid 3 3 1)",
+        "(mouse-grid 0 3 1 1)",
+        "(mouse-grid 3 3 4 1)",
+        "(mouse-grid 3 3 1 0)",
+        "(mouse-grid-monitor 0)",
+        "(mouse-grid-monitor)",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_hid_usage() {
+    let source = "
+(defsrc a b c)
+(deflayer base (hid-usage consumer 0x0183) (hid-usage system 0x82) (hid-usage consumer 65535))
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for bad in [
+        "(hid-usage consumer)",
+        "(hid-usage keyboard 0x04)",
+        "(hid-usage consumer 0)",
+        "(hid-usage consumer 0x10000)",
+        "(hid-usage consumer 0xzz)",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_mwheel_options() {
+    let source = "
+(defsrc a b c)
+(deflayer base (mwheel-up 50 120 smooth) (mwheel-down 50 40 1000 240) (mwheel-left 50 40 1000 240 smooth))
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for bad in [
+        "(mwheel-up 50 120 1000)",
+        "(mwheel-up 50 120 1000 60)",
+        "(mwheel-up 50 120 0 240)",
+        "(mwheel-up 50 120 smooth smooth)",
+        "(mwheel-up 50 smooth)",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_mouse_toward() {
+    let source = "
+(defsrc a b c)
+(deflayer base (mouse-toward top-left 100) (mouse-toward center 1) (mouse-toward monitor-16 50))
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for bad in [
+        "(mouse-toward center)",
+        "(mouse-toward middle 50)",
+        "(mouse-toward center 0)",
+        "(mouse-toward center 101)",
+        "(mouse-toward monitor-0 50)",
+        "(mouse-toward monitor-17 50)",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_jiggle() {
+    let source = "
+(defsrc a b)
+(deflayer base (jiggle 30000 1) (jiggle 100 5 zero-sum))
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for bad in [
+        "(jiggle 1000)",
+        "(jiggle 0 1)",
+        "(jiggle 1000 0)",
+        "(jiggle 1000 1 zero)",
+        "(jiggle 1000 1 zero-sum 1)",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {bad})");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_defrepeat() {
+    let source = "
+(defsrc a b c)
+(deflayer base a b c)
+(deflayer nav left right c)
+(defrepeat a none (left right) (200 25))
+(defrepeat b os)
+(defrepeat-layer nav left (150 10) right os)
+";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(
+        cfg.key_repeat.behaviour(0, OsCode::KEY_A),
+        KeyRepeatBehaviour::Disabled
+    );
+    assert_eq!(
+        cfg.key_repeat.behaviour(0, OsCode::KEY_LEFT),
+        KeyRepeatBehaviour::Software {
+            delay: 200,
+            interval: 25
+        }
+    );
+    assert_eq!(
+        cfg.key_repeat.behaviour(1, OsCode::KEY_LEFT),
+        KeyRepeatBehaviour::Software {
+            delay: 150,
+            interval: 10
+        }
+    );
+    assert_eq!(
+        cfg.key_repeat.behaviour(1, OsCode::KEY_RIGHT),
+        KeyRepeatBehaviour::Os
+    );
+    assert_eq!(
+        cfg.key_repeat.behaviour(1, OsCode::KEY_C),
+        KeyRepeatBehaviour::Os
+    );
+    for bad in [
+        "(defrepeat a)",
+        "(defrepeat a fast)",
+        "(defrepeat a (10))",
+        "(defrepeat a (0 10))",
+        "(defrepeat a none a os)",
+        "(defrepeat-layer)",
+        "(defrepeat-layer nope a none)",
+        "(defrepeat-layer base a none) (defrepeat-layer base b none)",
+    ] {
+        let source = format!("(defsrc a b) (deflayer base a b) {bad}");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_defvar_concat() {
+    let _lk = lock(&CFG_PARSE_LOCK);
+    let source = r#"
+(defsrc a)
+(deflayer base a)
+(defvar
+    x (concat a b c)
+    y (concat d (e f))
+    z (squish squash (splish splosh))
+    xx (concat $x $y)
+    xy (concat $x ($y))
+    xz (notconcat a b " " c " d")
+    yx (concat a b " " c " d" ("efg" " hij ") "kl")
+    yz (concat "abc"def"ghi""jkl")
+
+    rootpath "/home/myuser/mysubdir"
+    ;; $otherpath will be the string: /home/myuser/mysubdir/helloworld
+    otherpath (concat $rootpath "/helloworld")
+)
+"#;
+    let mut s = ParserState::default();
+    parse_cfg_raw_string(
+        source,
+        &mut s,
+        &PathBuf::from("test"),
+        &mut FileContentProvider {
+            get_file_content_fn: &mut |_| unimplemented!(),
+        },
+        DEF_LOCAL_KEYS,
+        Err("env vars not implemented".into()),
+    )
+    .expect("succeeds");
+    match s.vars().unwrap().get("x").unwrap() {
+        SExpr::Atom(a) => assert_eq!(&a.t, "abc"),
+        SExpr::List(l) => panic!("expected string not list: {l:?}"),
+    }
+    match s.vars().unwrap().get("y").unwrap() {
+        SExpr::Atom(a) => assert_eq!(&a.t, "def"),
+        SExpr::List(l) => panic!("expected string not list: {l:?}"),
+    }
+    match s.vars().unwrap().get("z").unwrap() {
+        SExpr::Atom(a) => panic!("expected list not string: {a:?}"),
+        SExpr::List(_) => {}
+    }
+    match s.vars().unwrap().get("xx").unwrap() {
+        SExpr::Atom(a) => assert_eq!(&a.t, "abcdef"),
+        SExpr::List(l) => panic!("expected string not list: {l:?}"),
+    }
+    match s.vars().unwrap().get("xy").unwrap() {
+        SExpr::Atom(a) => assert_eq!(&a.t, "abcdef"),
+        SExpr::List(l) => panic!("expected string not list: {l:?}"),
+    }
+    match s.vars().unwrap().get("xz").unwrap() {
+        SExpr::Atom(a) => panic!("expected list not string {a:?}"),
+        SExpr::List(_) => {}
+    }
+    match s.vars().unwrap().get("yx").unwrap() {
+        SExpr::Atom(a) => assert_eq!(&a.t, "ab c defg hij kl"),
+        SExpr::List(l) => panic!("expected string not list: {l:?}"),
+    }
+    match s.vars().unwrap().get("yz").unwrap() {
+        SExpr::Atom(a) => assert_eq!(&a.t, "abcdefghijkl"),
+        SExpr::List(l) => panic!("expected string not list: {l:?}"),
+    }
+    match s.vars().unwrap().get("otherpath").unwrap() {
+        SExpr::Atom(a) => assert_eq!(&a.t, "/home/myuser/mysubdir/helloworld"),
+        SExpr::List(l) => panic!("expected string not list: {l:?}"),
+    }
+}
+
+#[test]
+fn parse_template_1() {
+    let source = r#"
+(deftemplate home-row (j-behaviour)
+  a s d f g h $j-behaviour k l ; '
+)
+
+(defsrc
+  grv  1    2    3    4    5    6    7    8    9    0    -    =    bspc
+  tab  q    w    e    r    t    y    u    i    o    p    [    ]    \
+  caps (template-expand home-row j)                            ret
+  lsft z    x    c    v    b    n    m    ,    .    /    rsft
   lctl lmet lalt           spc            ralt rmet rctl
 )
 
@@ -1857,6 +2815,24 @@ fn parse_template_8() {
     .expect("parses");
 }
 
+#[test]
+fn parse_deeply_nested_template_alias() {
+    // Each template wraps the action of the one before it in another multi, tap-hold and layer
+    // action, which the parser nests without a limit.
+    let mut source = "(deftemplate wrap0 (x) (multi b $x))".to_owned();
+    for i in 1..32 {
+        source += &format!(
+            "(deftemplate wrap{i} (x)
+               (t! wrap{} (tap-hold 200 200 (multi c $x) (layer-while-held base))))",
+            i - 1
+        );
+    }
+    source += "(defsrc a) (defalias deep (t! wrap31 a)) (deflayer base @deep)";
+    parse_cfg(&source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+}
+
 #[test]
 fn test_deflayermap() {
     let source = r#"
@@ -1991,30 +2967,215 @@ fn parse_platform_specific() {
 }
 
 #[test]
-fn parse_defseq_overlap_limits() {
+fn parse_platform_sections() {
+    let source = r#"
+(defcfg
+  (platform (win process-unmapped-keys yes) (winiov2 process-unmapped-keys yes)
+    (wintercept process-unmapped-keys yes) (linux process-unmapped-keys yes)
+    (macos process-unmapped-keys yes))
+)
+(defsrc a b)
+(deflayer base
+  (platform (win @x) (winiov2 @x) (wintercept @x) (linux @x) (macos @x))
+  (platform (win c) (winiov2 c) (wintercept c) (linux c) (macos d))
+)
+(defalias
+  (platform (win x a) (winiov2 x b) (wintercept x c) (linux x d) (macos x e))
+)
+;; Tests for no duplication.
+(platform
+  (win (deflayer 2 a b))
+  (winiov2 (deflayer 2 a b))
+  (wintercept (deflayer 2 a b))
+  (linux (deflayer 2 a b) (deflayer 3 a b))
+  (macos (deflayer 2 a b) (deflayer 3 a b))
+)
+"#;
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert!(icfg.options.process_unmapped_keys);
+
+    let source = "(defsrc a) (deflayer base (platform (lnux a) (macos b)))";
+    let err = parse_cfg(source).map(|_| ()).expect_err("fails");
+    assert!(err.msg.contains("Unknown platform"), "{}", err.msg);
+}
+
+#[test]
+fn parse_defseq_overlap_limits() {
+    let source = r#"
+(defsrc)
+(deflayer base)
+(defvirtualkeys v v)
+(defseq v (O-(a b c d e f)))
+(defseq v (O-(a b)))
+"#;
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+}
+
+#[test]
+fn parse_deftest() {
+    let source = r#"
+(defsrc a)
+(deflayer base (tap-hold 200 200 a lsft))
+(deftest hold input (d:a t:250 r:a u:a) expect (d:lsft u:lsft))
+"#;
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(
+        icfg.tests,
+        [ConfigTest {
+            name: "hold".to_owned(),
+            inputs: vec![
+                TestEvent::Press(OsCode::KEY_A),
+                TestEvent::Wait(250),
+                TestEvent::Repeat(OsCode::KEY_A),
+                TestEvent::Release(OsCode::KEY_A),
+            ],
+            expected: vec![
+                TestEvent::Press(OsCode::KEY_LEFTSHIFT),
+                TestEvent::Release(OsCode::KEY_LEFTSHIFT),
+            ],
+        }]
+    );
+    for (test, err) in [
+        ("(deftest t input (d:a))", "needs both input and expect"),
+        (
+            "(deftest t input (d:a) expect (t:10))",
+            "times are not compared",
+        ),
+        (
+            "(deftest t input (d:notakey) expect ())",
+            "Unknown key name",
+        ),
+        ("(deftest t input (x:a) expect ())", "Unknown event kind"),
+        (
+            "(deftest t input () expect ()) (deftest t input () expect ())",
+            "Duplicate test name",
+        ),
+    ] {
+        let source = format!("(defsrc a) (deflayer base a) {test}");
+        let e = parse_cfg(&source).expect_err("fails");
+        assert!(e.msg.contains(err), "{test}: {}", e.msg);
+    }
+}
+
+#[test]
+fn parse_defseq_duplicate() {
+    let source = r#"
+(defsrc)
+(deflayer base)
+(defvirtualkeys v v w w)
+(defseq v (a b) w (a b))
+"#;
+    let err = parse_cfg(source).expect_err("fails");
+    assert!(err.msg.contains("same as an earlier defined sequence"));
+}
+
+#[test]
+fn chord_conflicts_are_found() {
+    use kanata_keyberon::chord::{ChordV2, ChordsForKey, ChordsForKeys, ReleaseBehaviour};
+    let leak = |keys: &[OsCode]| -> &'static [u16] {
+        Box::leak(keys.iter().map(|k| u16::from(*k)).collect::<Box<[u16]>>())
+    };
+    let chord = |keys: &[OsCode], timeout, disabled: &'static [u16]| {
+        &*Box::leak(Box::new(ChordV2 {
+            action: &Action::NoOp,
+            participating_keys: leak(keys),
+            pending_duration: timeout,
+            disabled_layers: disabled,
+            release_behaviour: ReleaseBehaviour::OnFirstRelease,
+        }))
+    };
+    let (a, b, c, d) = (OsCode::KEY_A, OsCode::KEY_B, OsCode::KEY_C, OsCode::KEY_D);
+    let chords = [
+        chord(&[a, b], 30, &[]),
+        chord(&[a, b, c], 100, &[]),
+        chord(&[b, c], 100, &[]),
+        chord(&[a, c], 30, &[0, 1]),
+        chord(&[c, d], 30, &[]),
+    ];
+    let mut cfks = ChordsForKeys::<'static, KanataCustom> {
+        mapping: Default::default(),
+    };
+    for chord in chords {
+        for key in chord.participating_keys {
+            cfks.mapping
+                .entry(*key)
+                .or_insert(ChordsForKey { chords: vec![] })
+                .chords
+                .push(chord);
+        }
+    }
+    let mapped_keys = [a, b, c].into_iter().collect();
+    assert_eq!(
+        chord_conflicts(&cfks, &mapped_keys, 2),
+        [
+            "chord (a c) can never activate: it is disabled on every layer",
+            "chord (a b) with timeout 30 is a subset of chord (a b c) with timeout 100, \
+             so (a b) waits up to 100 ms for the other keys before it activates",
+            "chord (c d) can never activate: d is not in defsrc and process-unmapped-keys \
+             is not enabled",
+        ]
+    );
+}
+
+#[test]
+fn parse_defseq_overlap_too_many() {
     let source = r#"
 (defsrc)
 (deflayer base)
 (defvirtualkeys v v)
-(defseq v (O-(a b c d e f)))
-(defseq v (O-(a b)))
+(defseq v (O-(a b c d e f g)))
 "#;
     parse_cfg(source)
         .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
-        .expect("parses");
+        .expect_err("fails");
 }
 
 #[test]
-fn parse_defseq_overlap_too_many() {
+fn parse_defseq_unmodded_and_pass_mods() {
     let source = r#"
 (defsrc)
 (deflayer base)
-(defvirtualkeys v v)
-(defseq v (O-(a b c d e f g)))
+(defvirtualkeys v v w w)
+(defseq (v pass-mods yes) (N-a S-b N-c) w (O-(d e) N-f))
 "#;
-    parse_cfg(source)
+    let icfg = parse_cfg(source)
         .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
-        .expect_err("fails");
+        .expect("parses");
+    let keys = [
+        u16::from(OsCode::KEY_A),
+        u16::from(OsCode::KEY_LEFTSHIFT) | 0x8000,
+        u16::from(OsCode::KEY_B) | 0x8000,
+        u16::from(OsCode::KEY_C),
+    ];
+    let crate::trie::GetOrDescendentExistsResult::HasValue(output) =
+        icfg.sequences.get_or_descendant_exists(keys)
+    else {
+        panic!("sequence is missing");
+    };
+    assert!(output.pass_mods);
+    assert_eq!(output.unmodded_steps, 0b1001);
+    let source = r#"
+(defsrc)
+(deflayer base)
+(defvirtualkeys v v)
+(defseq v (N-lsft a))
+"#;
+    let err = parse_cfg(source).expect_err("fails");
+    assert!(err.msg.contains("non-modifier key"));
+    let source = r#"
+(defsrc)
+(deflayer base)
+(defvirtualkeys v v)
+(defseq (v mods-pass yes) (a))
+"#;
+    let err = parse_cfg(source).expect_err("fails");
+    assert!(err.msg.contains("(<name> pass-mods <yes|no>)"));
 }
 
 #[test]
@@ -2041,6 +3202,361 @@ fn disallow_dupe_layer_opts_icon_layermap() {
     parse_cfg(source).map(|_| ()).expect_err("fails");
 }
 
+#[test]
+fn parse_layer_opts_hooks() {
+    let source = "
+(defalias x (macro x))
+(defsrc)
+(deflayer (base on-enter @x on-exit (multi a b)))
+(deflayermap (other on-exit c) 0 0)
+";
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert!(icfg.layer_info[0].on_enter.is_some());
+    assert!(icfg.layer_info[0].on_exit.is_some());
+    assert!(icfg.layer_info[1].on_enter.is_none());
+    assert!(icfg.layer_info[1].on_exit.is_some());
+    let source = "
+(defsrc)
+(deflayer (base icon (a b)))
+";
+    let err = parse_cfg(source).expect_err("fails");
+    assert!(err.msg.contains("No lists are allowed"));
+}
+
+#[test]
+fn parse_layer_opts_auto_return() {
+    let source = "
+(defsrc)
+(deflayer base)
+(deflayermap (mouse auto-return 5000) 0 0)
+";
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(icfg.layer_info[0].auto_return, None);
+    assert_eq!(icfg.layer_info[1].auto_return, Some(5000));
+    let source = "
+(defsrc)
+(deflayer (base auto-return 0))
+";
+    let err = parse_cfg(source).expect_err("fails");
+    assert!(err.msg.contains("auto-return must be 1-65535"));
+}
+
+#[test]
+fn parse_layer_opts_repeat() {
+    let source = "
+(defsrc a)
+(deflayer base a)
+(deflayer (sym repeat none) a)
+(deflayer (nav repeat os) a)
+";
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let no_repeats: Vec<_> = icfg.layer_info.iter().map(|info| info.no_repeat).collect();
+    assert_eq!(no_repeats, [false, true, false]);
+    assert!(icfg.key_repeat.layer_has_no_repeat(1));
+    assert_eq!(
+        icfg.key_repeat.behaviour(1, OsCode::KEY_A),
+        KeyRepeatBehaviour::Disabled
+    );
+    let source = "
+(defsrc)
+(deflayer (base repeat fast))
+";
+    let err = parse_cfg(source).expect_err("fails");
+    assert!(err.msg.contains("repeat must be none or os"));
+}
+
+#[test]
+fn parse_layer_opts_display() {
+    let source = r##"
+(defsrc a)
+(deflayer base a)
+(deflayer (nav display-name "Nav keys" icon 🧭 color "#3366ff") a)
+(deflayer (num 🎨 #f80) a)
+"##;
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let displays: Vec<_> = icfg
+        .layer_info
+        .iter()
+        .map(|info| (info.display_name.as_deref(), info.color.as_deref()))
+        .collect();
+    assert_eq!(
+        displays,
+        [
+            (None, None),
+            (Some("Nav keys"), Some("#3366ff")),
+            (None, Some("#f80"))
+        ]
+    );
+    let source = "
+(defsrc)
+(deflayer (base color red))
+";
+    let err = parse_cfg(source).expect_err("fails");
+    assert!(err.msg.contains("color must be #rgb or #rrggbb"));
+}
+
+#[test]
+fn parse_layer_opts_cmd_env() {
+    let source = "
+(defvar dir ~/src/web)
+(defsrc a)
+(deflayer base a)
+(deflayer (web cmd-env (PROJECT web PROJECT_DIR $dir)) a)
+";
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert!(icfg.layer_info[0].cmd_env.is_empty());
+    assert_eq!(
+        icfg.layer_info[1].cmd_env,
+        [
+            ("PROJECT".to_owned(), "web".into()),
+            ("PROJECT_DIR".to_owned(), "~/src/web".into()),
+        ]
+    );
+    for source in [
+        "(defsrc a) (deflayer (web cmd-env (PROJECT)) a)",
+        "(defsrc a) (deflayer (web cmd-env PROJECT) a)",
+        "(defsrc a) (deflayer (web cmd-env (A=B c)) a)",
+    ] {
+        parse_cfg(source).map(|_| ()).expect_err(source);
+    }
+}
+
+#[test]
+fn parse_docs() {
+    let source = r#"
+(defsrc a b)
+(defalias
+  copy (doc "Copy to clipboard") C-c
+  paste C-v
+  cut (doc Cut) C-x
+)
+(deflayer base @copy @paste)
+(deflayer (nav doc "Arrows and paging") a @cut)
+(defvirtualkeys vk-save (doc "Save") C-s vk-quit C-q)
+"#;
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let mut docs: Vec<_> = icfg.alias_docs.into_iter().collect();
+    docs.sort();
+    assert_eq!(
+        docs,
+        [
+            ("copy".to_owned(), "Copy to clipboard".to_owned()),
+            ("cut".to_owned(), "Cut".to_owned())
+        ]
+    );
+    let layer_docs: Vec<_> = icfg
+        .layer_info
+        .iter()
+        .map(|info| info.doc.as_deref())
+        .collect();
+    assert_eq!(layer_docs, [None, Some("Arrows and paging")]);
+    let vk_docs: Vec<_> = icfg.virtual_key_docs.into_iter().collect();
+    assert_eq!(vk_docs, [("vk-save".to_owned(), "Save".to_owned())]);
+    for source in [
+        "(defsrc a) (defalias x (doc) a) (deflayer base @x)",
+        "(defsrc a) (defalias x (doc one two) a) (deflayer base @x)",
+        "(defsrc a) (defalias x (doc (one)) a) (deflayer base @x)",
+    ] {
+        let err = parse_cfg(source).expect_err("fails");
+        assert!(err.msg.contains("doc expects one string"), "{}", err.msg);
+    }
+    let err =
+        parse_cfg("(defsrc a) (defalias x (doc \"x\")) (deflayer base a)").expect_err("fails");
+    assert!(
+        err.msg.contains("Found doc without an action"),
+        "{}",
+        err.msg
+    );
+}
+
+#[test]
+fn parse_fork_inputs() {
+    let source = "
+(defvirtualkeys vk XX)
+(defsrc a)
+(deflayer base (fork a b (c (input real spc) (input virtual vk))))
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let bad = [
+        "(defsrc a) (deflayer base (fork a b ((input real))))",
+        "(defsrc a) (deflayer base (fork a b ((input fake nope))))",
+        "(defsrc a) (deflayer base (fork a b ((input other a))))",
+        "(defsrc a) (deflayer base (fork a b ((output real a))))",
+    ];
+    for source in bad {
+        parse_cfg(source).expect_err(source);
+    }
+}
+
+#[test]
+fn parse_deftaphold_flavor() {
+    let source = "
+(deftaphold-flavor a decision release reset-timeout-on-press yes require-prior-idle 150)
+(deftaphold-flavor b decision order order-buffer 20 timeout-action tap)
+(deftaphold-flavor c tap-on-press (a) tap-on-press-release (b) hold-on-press (c))
+(defsrc a b c)
+(deflayer base
+  (tap-hold-flavor a 200 200 a lctl)
+  (tap-hold-flavor b 200 200 b lctl)
+  (tap-hold-flavor c 200 200 c lctl))
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let bad = [
+        "(deftaphold-flavor) (defsrc a) (deflayer base a)",
+        "(deftaphold-flavor a decision) (defsrc a) (deflayer base a)",
+        "(deftaphold-flavor a decision slow) (defsrc a) (deflayer base a)",
+        "(deftaphold-flavor a decision press decision release) (defsrc a) (deflayer base a)",
+        "(deftaphold-flavor a order-buffer 20) (defsrc a) (deflayer base a)",
+        "(deftaphold-flavor a decision order hold-on-press (a)) (defsrc a) (deflayer base a)",
+        "(deftaphold-flavor a tap-on-press (a) hold-on-press (a)) (defsrc a) (deflayer base a)",
+        "(deftaphold-flavor a) (deftaphold-flavor a) (defsrc a) (deflayer base a)",
+        "(deftaphold-flavor a unknown yes) (defsrc a) (deflayer base a)",
+        "(defsrc a) (deflayer base (tap-hold-flavor missing 200 200 a lctl))",
+        "(deftaphold-flavor a) (defsrc a) (deflayer base (tap-hold-flavor a 200 200 a))",
+    ];
+    for source in bad {
+        parse_cfg(source).expect_err(source);
+    }
+}
+
+#[test]
+fn parse_layer_opts_unmapped() {
+    let source = "
+(defcfg process-unmapped-keys yes)
+(defsrc a)
+(deflayer (base unmapped (macro b c)) a)
+(deflayermap (other unmapped XX) a b)
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let bad = [
+        "(defsrc a) (deflayer (base unmapped XX) a)",
+        "(defcfg process-unmapped-keys yes) (defsrc a) (deflayermap (base unmapped XX) __ b)",
+    ];
+    for source in bad {
+        parse_cfg(source).expect_err(source);
+    }
+}
+
+#[test]
+fn parse_alias_concat() {
+    let source = "
+(defvar lang en)
+(defalias en-accent a fr-accent b other c)
+(defsrc a b)
+(deflayer (base on-enter (var-set lang fr)) (alias-concat $lang \"-accent\") (var-set $lang en))
+";
+    parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    for (source, err_msg) in [
+        (
+            "(defvar lang en) (defsrc a) (deflayer base (alias-concat $lang -accent))",
+            "No alias has a name",
+        ),
+        (
+            "(defalias x-y a) (defsrc a) (deflayer base (alias-concat $lang -y))",
+            "must be declared in defvar",
+        ),
+        (
+            "(defalias x-y a) (defsrc a) (deflayer base (alias-concat x-y))",
+            "use @<name> instead",
+        ),
+        (
+            "(defsrc a) (deflayer base (var-set lang fr))",
+            "must be declared in defvar",
+        ),
+    ] {
+        let err = parse_cfg(source).expect_err("fails");
+        assert!(err.msg.contains(err_msg), "{source}: {}", err.msg);
+    }
+}
+
+#[test]
+fn parse_defapp() {
+    let source = "
+(defalias cpy C-c term-cpy C-S-c)
+(defsrc a)
+(deflayer base @cpy)
+(deflayer other a)
+(defapp firefox layer other)
+(defapp \"Alacritty\" aliases (@cpy @term-cpy) layer other)
+(defapp idea64 windows-altgr add-lctl-release cmd-env (PROJECT ide))
+";
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(
+        icfg.apps,
+        vec![
+            App {
+                id: "firefox".into(),
+                layer: Some(1),
+                windows_altgr: None,
+                cmd_env: vec![],
+            },
+            App {
+                id: "Alacritty".into(),
+                layer: Some(1),
+                windows_altgr: None,
+                cmd_env: vec![],
+            },
+            App {
+                id: "idea64".into(),
+                layer: None,
+                windows_altgr: Some(AltGrBehaviour::AddLctlRelease),
+                cmd_env: vec![("PROJECT".into(), "ide".into())],
+            },
+        ]
+    );
+    for (source, err_msg) in [
+        (
+            "(defsrc a) (deflayer base a) (defapp firefox layer nope)",
+            "Unknown layer name",
+        ),
+        (
+            "(defsrc a) (deflayer base a) (defapp firefox) (defapp FIREFOX)",
+            "Duplicate application",
+        ),
+        (
+            "(defalias cpy c) (defsrc a) (deflayer base a) (defapp firefox aliases (cpy nope))",
+            "unknown alias nope",
+        ),
+        (
+            "(defsrc a) (deflayer base a) (defapp firefox icon x)",
+            "Unknown option",
+        ),
+        (
+            "(defsrc a) (deflayer base a) (defapp firefox windows-altgr cancel)",
+            "Invalid value for windows-altgr",
+        ),
+        (
+            "(defalias cpy c x (multi @cpy)) (defsrc a) (deflayer base a) (defapp ff aliases (cpy x))",
+            "must be defined before @cpy is used",
+        ),
+    ] {
+        let err = parse_cfg(source).expect_err("fails");
+        assert!(err.msg.contains(err_msg), "{source}: {}", err.msg);
+    }
+}
+
 #[test]
 fn layer_name_allows_var() {
     let source = "
@@ -2126,3 +3642,269 @@ fn parse_mouse_movement_key() {
         Some(crate::keys::OsCode::KEY_766),
     );
 }
+
+#[test]
+fn parse_foreach_errors() {
+    for (source, err) in [
+        ("(defsrc (foreach $l))", "foreach expects"),
+        ("(defsrc (foreach l (a b) l))", "must begin with $"),
+        ("(defvar l a) (defsrc (foreach $x $l $x))", "must be a list"),
+    ] {
+        let e = parse_cfg(&format!("{source} (deflayer base a b)"))
+            .map(|_| ())
+            .expect_err("fails");
+        assert!(e.msg.contains(err), "{source}: {}", e.msg);
+    }
+}
+
+#[test]
+fn parse_macro_repeat_errors() {
+    for (action, msg) in [
+        ("(macro (repeat 2))", "repeat within a macro expects"),
+        ("(macro (repeat 0 a))", "repeat count"),
+        ("(macro (repeat 300 (repeat 300 a)))", "too many events"),
+    ] {
+        let source = format!("(defsrc a) (deflayer base {action})");
+        let err = parse_cfg(&source).map(|_| ()).expect_err("fails");
+        assert!(err.msg.contains(msg), "{action}: {}", err.msg);
+    }
+}
+
+#[test]
+fn parse_datetime_format() {
+    parse_cfg(r#"(defsrc a) (deflayer base (datetime "%F %T %%"))"#).expect("parses");
+    for (action, msg) in [
+        (r#"(datetime "%Q")"#, "does not support %Q"),
+        (r#"(datetime "100%")"#, "format ends with %"),
+        ("(datetime)", "expects 1 parameter"),
+    ] {
+        let source = format!("(defsrc a) (deflayer base {action})");
+        let err = parse_cfg(&source).map(|_| ()).expect_err("fails");
+        assert!(err.msg.contains(msg), "{action}: {}", err.msg);
+    }
+}
+
+#[test]
+fn parse_secret_type_requires_danger_flag() {
+    let source = "(defsrc a) (deflayer base (secret-type token))";
+    let err = parse_cfg(source).map(|_| ()).expect_err("fails");
+    assert!(err.msg.contains("danger-enable-secrets yes"), "{}", err.msg);
+    parse_cfg(&format!("(defcfg danger-enable-secrets yes) {source}")).expect("parses");
+}
+
+#[test]
+fn parse_defsecrets() {
+    let source = r#"
+(defsecrets
+  gh-token credential-store
+  ha-token (file "/etc/kanata/ha-token")
+)
+(defwebhooks ha http://homeassistant.local:8123/api/kanata () (bearer ha-token))
+(defsrc a)
+(deflayer (base cmd-env (GH_TOKEN (secret gh-token) OTHER (secret other) LANG C)) a)
+"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let ha_token = Secret {
+        name: "ha-token".into(),
+        source: SecretSource::File("/etc/kanata/ha-token".into()),
+    };
+    assert_eq!(cfg.webhooks[0].bearer, Some(ha_token));
+    let stored = |name: &str| {
+        EnvValue::Secret(Secret {
+            name: name.into(),
+            source: SecretSource::CredentialStore,
+        })
+    };
+    assert_eq!(
+        cfg.layer_info[0].cmd_env,
+        [
+            ("GH_TOKEN".to_owned(), stored("gh-token")),
+            ("OTHER".to_owned(), stored("other")),
+            ("LANG".to_owned(), "C".into()),
+        ]
+    );
+    assert_eq!(
+        format!("{:?}", cfg.layer_info[0].cmd_env[0]),
+        r#"("GH_TOKEN", (secret gh-token))"#
+    );
+    for bad in [
+        "(defsecrets a) (defsrc a) (deflayer base a)",
+        "(defsecrets a keychain) (defsrc a) (deflayer base a)",
+        "(defsecrets a credential-store a credential-store) (defsrc a) (deflayer base a)",
+        "(defsecrets a (file)) (defsrc a) (deflayer base a)",
+        "(defsrc a) (deflayer (base cmd-env (A (secret))) a)",
+        "(defsrc a) (deflayer (base cmd-env (A (secret a b))) a)",
+        "(defwebhooks a http://localhost () (token a)) (defsrc a) (deflayer base a)",
+    ] {
+        parse_cfg(bad).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn parse_macro_delay_scale() {
+    parse_cfg("(defsrc a b) (deflayer base (macro-delay-scale 200) (macro-delay-scale 100))")
+        .expect("parses");
+    for action in [
+        "(macro-delay-scale 0)",
+        "(macro-delay-scale)",
+        "(macro-delay-scale 50 100)",
+    ] {
+        let source = format!("(defsrc a) (deflayer base {action})");
+        parse_cfg(&source).map(|_| ()).expect_err(action);
+    }
+}
+
+#[test]
+fn parse_log_level_action() {
+    parse_cfg("(defsrc a b) (deflayer base (log-level trace 30000) (log-level info))")
+        .expect("parses");
+    for (action, msg) in [
+        ("(log-level loud)", "level must be one of"),
+        ("(log-level debug 0)", "duration must be a positive number"),
+        ("(log-level)", "expects 1 or 2 parameters"),
+    ] {
+        let source = format!("(defsrc a) (deflayer base {action})");
+        let err = parse_cfg(&source).map(|_| ()).expect_err("fails");
+        assert!(err.msg.contains(msg), "{action}: {}", err.msg);
+    }
+}
+
+#[test]
+fn parse_training_mode_action() {
+    parse_cfg(
+        "(defsrc a b) (deflayer base (training-mode) (training-mode qwerty)) (deflayer qwerty a b)",
+    )
+    .expect("parses");
+    for (action, msg) in [
+        ("(training-mode nope)", "layer name is not declared"),
+        ("(training-mode a b)", "expects 0 or 1 parameters"),
+    ] {
+        let source = format!("(defsrc a) (deflayer base {action})");
+        let err = parse_cfg(&source).map(|_| ()).expect_err("fails");
+        assert!(err.msg.contains(msg), "{action}: {}", err.msg);
+    }
+}
+
+#[test]
+fn parse_defdebounce() {
+    let source = "
+(definputdevices 2 ((name old)))
+(defdebounce ms 8 keys ((a b) 20 c 0))
+(defdebounce device 2 mode defer)
+(defsrc a b c d)
+(deflayer base a b c d)
+";
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    let [all, device] = icfg.debounce.as_slice() else {
+        panic!("expected two defdebounce");
+    };
+    assert_eq!((all.device, all.mode), (None, DebounceMode::Eager));
+    assert_eq!(all.ms_of(OsCode::KEY_B), 20);
+    assert_eq!(all.ms_of(OsCode::KEY_C), 0);
+    assert_eq!(all.ms_of(OsCode::KEY_D), 8);
+    assert_eq!(device.device.map(|id| id.get()), Some(2));
+    assert_eq!((device.mode, device.ms), (DebounceMode::Defer, 5));
+    for (debounce, err) in [
+        ("(defdebounce) (defdebounce ms 2)", "already exists"),
+        ("(defdebounce device 3)", "not defined in definputdevices"),
+        ("(defdebounce mode slow)", "eager or defer"),
+        ("(defdebounce ms 2 ms 3)", "already defined"),
+        ("(defdebounce keys (a 2 a 3))", "more than once"),
+    ] {
+        let source = format!("(defsrc a) (deflayer base a) {debounce}");
+        let e = parse_cfg(&source).expect_err("fails");
+        assert!(e.msg.contains(err), "{debounce}: {}", e.msg);
+    }
+}
+
+#[test]
+fn parse_unicode_paste() {
+    let exprs = parse(r#"("½ ≤" paste)"#, "test").expect("parses")[0]
+        .t
+        .clone();
+    // The allocations of the state must outlive the action.
+    let s = ParserState::default();
+    let action = parse_unicode(&exprs, &s).expect("parses");
+    let Action::Sequence { events } = action else {
+        panic!("expected a macro, {action:?}");
+    };
+    let customs: Vec<_> = events
+        .iter()
+        .filter_map(|ev| match ev {
+            SequenceEvent::Custom(ca) => Some(**ca),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        customs,
+        [
+            &CustomAction::ClipboardSave(u16::MAX),
+            &CustomAction::ClipboardSet("½ ≤"),
+            &CustomAction::ClipboardRestore(u16::MAX),
+        ]
+    );
+    assert!(events.contains(&SequenceEvent::Press(KeyCode::V)));
+
+    let exprs = parse("(🙂 type)", "test").expect("parses")[0].t.clone();
+    let e = parse_unicode(&exprs, &s).expect_err("fails");
+    assert!(e.msg.contains("Unknown output method"), "{}", e.msg);
+}
+
+#[test]
+fn parse_defmeta() {
+    let source = r#"
+(defmeta
+  author "Jane Doe"
+  description "Home row mods and a nav layer"
+  version 1.2
+  keyboard "Corne v4"
+  tags (hrm nav "split keyboard"))
+(defsrc a)
+(deflayer base a)
+"#;
+    let icfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(
+        icfg.meta,
+        ConfigMeta {
+            author: Some("Jane Doe".to_owned()),
+            description: Some("Home row mods and a nav layer".to_owned()),
+            version: Some("1.2".to_owned()),
+            keyboard: Some("Corne v4".to_owned()),
+            tags: vec![
+                "hrm".to_owned(),
+                "nav".to_owned(),
+                "split keyboard".to_owned()
+            ],
+        }
+    );
+    let icfg = parse_cfg("(defsrc a) (deflayer base a)").expect("parses");
+    assert_eq!(icfg.meta, ConfigMeta::default());
+    let bad = [
+        (
+            "(defmeta) (defmeta) (defsrc) (deflayer base)",
+            "Only one defmeta",
+        ),
+        (
+            "(defmeta license mit) (defsrc) (deflayer base)",
+            "Unknown option",
+        ),
+        (
+            "(defmeta tags hrm) (defsrc) (deflayer base)",
+            "tags must be a list",
+        ),
+        (
+            "(defmeta author (a b)) (defsrc) (deflayer base)",
+            "author must be a string",
+        ),
+    ];
+    for (source, msg) in bad {
+        let err = parse_cfg(source).expect_err("fails");
+        assert!(err.msg.contains(msg), "{source}: {}", err.msg);
+    }
+}
@@ -0,0 +1,84 @@
+use super::*;
+use crate::cfg::ast::*;
+
+#[test]
+fn ast_groups_items_into_parts() {
+    let source = "
+;; remap caps
+(defcfg process-unmapped-keys yes)
+(defsrc caps a)
+(defvar tt 200)
+(defalias cap (tap-hold $tt $tt esc lctl))
+(deflayer (base icon base.png) @cap b)
+(deflayermap (nav) a left)
+#| unparsed |#
+(defoverrides (a) (b))
+";
+    let ast = parse_ast(source, "test.kbd").expect("parses");
+    let keywords: Vec<_> = ast.items.iter().map(|i| i.keyword.t.as_str()).collect();
+    assert_eq!(
+        keywords,
+        [
+            "defcfg",
+            "defsrc",
+            "defvar",
+            "defalias",
+            "deflayer",
+            "deflayermap",
+            "defoverrides"
+        ]
+    );
+    let comments: Vec<_> = ast.comments.iter().map(|c| c.t.as_str()).collect();
+    assert_eq!(comments, [";; remap caps\n", "#| unparsed |#"]);
+
+    let ItemKind::Defcfg(defcfg) = &ast.items[0].kind else {
+        panic!("expected defcfg");
+    };
+    assert_eq!(
+        defcfg
+            .option("process-unmapped-keys")
+            .and_then(|v| v.atom(None)),
+        Some("yes")
+    );
+    let ItemKind::Defalias(aliases) = &ast.items[3].kind else {
+        panic!("expected defalias");
+    };
+    assert_eq!(aliases[0].name.t, "cap");
+    assert_eq!(
+        &source[aliases[0].value.span()],
+        "(tap-hold $tt $tt esc lctl)"
+    );
+    let ItemKind::Deflayer(layer) = &ast.items[4].kind else {
+        panic!("expected deflayer");
+    };
+    assert_eq!(layer.name.name.t, "base");
+    assert_eq!(layer.name.options[0].name.t, "icon");
+    assert_eq!(layer.keys.len(), 2);
+    let ItemKind::Deflayermap(layer) = &ast.items[5].kind else {
+        panic!("expected deflayermap");
+    };
+    assert_eq!(layer.name.name.t, "nav");
+    assert_eq!(layer.mappings[0].name.t, "a");
+    assert!(matches!(&ast.items[6].kind, ItemKind::Other(args) if args.len() == 2));
+    assert_eq!(&source[ast.items[1].span.clone()], "(defsrc caps a)");
+}
+
+#[test]
+fn ast_defcfg_to_options() {
+    let _lk = lock(&CFG_PARSE_LOCK);
+    let ast = parse_ast("(defcfg process-unmapped-keys yes)", "test.kbd").expect("parses");
+    let ItemKind::Defcfg(defcfg) = &ast.items[0].kind else {
+        panic!("expected defcfg");
+    };
+    assert!(defcfg.to_options().expect("valid").process_unmapped_keys);
+}
+
+#[test]
+fn ast_rejects_malformed_items() {
+    let err = parse_ast("(defalias a)", "test.kbd").expect_err("no value");
+    assert_eq!(err.msg, "defalias has no value after this name");
+    parse_ast("(deflayer)", "test.kbd").expect_err("no layer name");
+    parse_ast("(deflayer (()) a)", "test.kbd").expect_err("no layer name");
+    parse_ast("(defvar (a) b)", "test.kbd").expect_err("list as name");
+    parse_ast("()", "test.kbd").expect_err("empty item");
+}
@@ -0,0 +1,54 @@
+use super::*;
+use crate::cfg::builder::{Action, ConfigBuilder};
+
+#[test]
+fn builder_output_parses() {
+    let cfg = ConfigBuilder::new()
+        .option("process-unmapped-keys", "yes")
+        .source(["caps", "a", "s"])
+        .var("tt", "200")
+        .alias(
+            "cap",
+            Action::tap_hold(
+                200,
+                200,
+                Action::key("esc"),
+                Action::layer_while_held("nav"),
+            ),
+        )
+        .alias("os", Action::one_shot(500, Action::key("lsft")))
+        .layer(
+            "base",
+            [
+                Action::alias("cap"),
+                Action::Transparent,
+                Action::alias("os"),
+            ],
+        )
+        .layer(
+            "nav",
+            [
+                Action::NoOp,
+                Action::multi([Action::key("lctl"), Action::key("left")]),
+                Action::layer_switch("map"),
+            ],
+        )
+        .layer_map("map", [("a", Action::raw("(unicode é)"))])
+        .to_kbd();
+    let icfg = parse_cfg(&cfg).unwrap_or_else(|e| panic!("{cfg}\n{e:?}"));
+    let names: Vec<_> = icfg.layer_info.iter().map(|l| l.name.as_str()).collect();
+    assert_eq!(names, ["base", "nav", "map"]);
+}
+
+#[test]
+fn builder_quotes_names() {
+    let cfg = ConfigBuilder::new()
+        .source(["a"])
+        .layer("my layer", [Action::raw("(cmd echo \"hi\")")])
+        .layer_map("(", [("a", Action::key("b"))])
+        .to_kbd();
+    assert_eq!(
+        cfg,
+        "(defsrc a)\n(deflayer \"my layer\" (cmd echo \"hi\"))\n(deflayermap (\"(\")\n  a b\n)\n"
+    );
+}
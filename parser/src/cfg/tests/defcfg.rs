@@ -110,6 +110,41 @@ fn unmapped_except_keys_is_removed_from_mapping() {
     }
 }
 
+#[test]
+fn unmapped_mouse_buttons_are_mapped_only_with_mouse_in_defsrc() {
+    let icfg = parse_cfg("(defcfg process-unmapped-keys yes) (defsrc a) (deflayer base b)")
+        .expect("parses");
+    assert!(!icfg.mapped_keys.contains(&OsCode::BTN_LEFT));
+
+    // Without process-unmapped-mouse-buttons, only the mouse buttons in defsrc are processed.
+    let icfg = parse_cfg("(defcfg process-unmapped-keys yes) (defsrc mrgt) (deflayer base b)")
+        .expect("parses");
+    assert!(icfg.mapped_keys.contains(&OsCode::BTN_RIGHT));
+    assert!(!icfg.mapped_keys.contains(&OsCode::BTN_LEFT));
+}
+
+#[test]
+fn process_unmapped_mouse_buttons_maps_the_other_buttons() {
+    // With a mouse button in defsrc, the other buttons of the mouse are processed too.
+    let icfg = parse_cfg(
+        "(defcfg process-unmapped-keys (all-except mmid) process-unmapped-mouse-buttons yes)
+         (defsrc mrgt) (deflayer base b)",
+    )
+    .expect("parses");
+    assert!(icfg.mapped_keys.contains(&OsCode::BTN_LEFT));
+    assert!(icfg.mapped_keys.contains(&OsCode::BTN_EXTRA));
+    assert!(!icfg.mapped_keys.contains(&OsCode::BTN_MIDDLE));
+    assert!(!icfg.mapped_keys.contains(&OsCode::MouseWheelUp));
+
+    // Without a mouse button in defsrc, no mouse button is processed.
+    let icfg = parse_cfg(
+        "(defcfg process-unmapped-keys yes process-unmapped-mouse-buttons yes)
+         (defsrc a) (deflayer base b)",
+    )
+    .expect("parses");
+    assert!(!icfg.mapped_keys.contains(&OsCode::BTN_LEFT));
+}
+
 #[test]
 fn non_applicable_os_deflocalkeys_always_succeeds_parsing() {
     let source = "
@@ -311,3 +346,181 @@ fn per_action_require_prior_idle_on_tap_hold_release_tap_keys_release() {
         .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
         .expect("passes");
 }
+
+#[test]
+fn sound_cues_parse() {
+    let source = "
+(defcfg
+  sound-layer-change beep
+  sound-caps-word \"C:\\sounds\\caps.wav\"
+  sound-sequence-timeout none
+)
+(defsrc a)
+(deflayer (base sound base.wav) a)
+(deflayer (other 🔊 beep icon other.ico) a)
+";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(cfg.options.sound_layer_change, Some(SoundCue::Beep));
+    assert_eq!(
+        cfg.options.sound_caps_word,
+        Some(SoundCue::File("C:\\sounds\\caps.wav".into()))
+    );
+    assert_eq!(cfg.options.sound_sequence_timeout, Some(SoundCue::Silent));
+    assert_eq!(
+        cfg.layer_info[0].sound,
+        Some(SoundCue::File("base.wav".into()))
+    );
+    assert_eq!(cfg.layer_info[1].sound, Some(SoundCue::Beep));
+}
+
+#[test]
+fn disallow_dupe_layer_opts_sound() {
+    let source = "
+(defsrc a)
+(deflayer (base sound beep 🔊 none) a)
+";
+    parse_cfg(source).map(|_| ()).expect_err("fails");
+}
+
+#[test]
+fn emergency_chords_parse() {
+    let cfg = parse_cfg("(defsrc a)(deflayer base a)").expect("parses");
+    assert_eq!(
+        cfg.options.emergency_exit_chord,
+        Some(vec![
+            OsCode::KEY_LEFTCTRL,
+            OsCode::KEY_SPACE,
+            OsCode::KEY_ESC
+        ])
+    );
+    assert_eq!(cfg.options.emergency_passthrough_chord, None);
+    assert_eq!(cfg.options.emergency_reengage_chord, None);
+
+    let source = "
+(defcfg
+  emergency-exit-chord none
+  emergency-passthrough-chord (rctl bspc)
+  emergency-reengage-chord (rctl ret)
+)
+(defsrc a)
+(deflayer base a)
+";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(cfg.options.emergency_exit_chord, None);
+    assert_eq!(
+        cfg.options.emergency_passthrough_chord,
+        Some(vec![OsCode::KEY_RIGHTCTRL, OsCode::KEY_BACKSPACE])
+    );
+    assert_eq!(
+        cfg.options.emergency_reengage_chord,
+        Some(vec![OsCode::KEY_RIGHTCTRL, OsCode::KEY_ENTER])
+    );
+
+    for bad in [
+        "emergency-exit-chord lctl",
+        "emergency-exit-chord (lctl)",
+        "emergency-passthrough-chord (rctl rctl)",
+        "emergency-passthrough-chord (rctl notakey)",
+        "emergency-reengage-chord ()",
+        "emergency-passthrough-chord none",
+    ] {
+        let source = format!("(defcfg {bad})(defsrc a)(deflayer base a)");
+        parse_cfg(&source).map(|_| ()).expect_err(bad);
+    }
+}
+
+#[test]
+fn tap_hold_key_timeouts_parse() {
+    let source = "
+(defcfg tap-hold-key-timeouts (a +60 s -20 d 300))
+(defsrc a)
+(deflayer base a)
+";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("passes");
+    assert_eq!(
+        cfg.options.tap_hold_key_timeouts,
+        vec![
+            (OsCode::KEY_A, KeyTimeout::Offset(60)),
+            (OsCode::KEY_S, KeyTimeout::Offset(-20)),
+            (OsCode::KEY_D, KeyTimeout::Set(300)),
+        ]
+    );
+    for value in ["(a)", "(a +x)", "(a 70000)", "(a 1 a 2)", "(nokey 1)", "a"] {
+        let source = format!("(defcfg tap-hold-key-timeouts {value}) (defsrc a) (deflayer base a)");
+        parse_cfg(&source).map(|_| ()).expect_err(value);
+    }
+}
+
+#[test]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn linux_output_device_ids_parse() {
+    let source = "
+(defcfg
+  linux-output-device-vendor-id 0x046d
+  linux-output-device-product-id 49948
+  linux-output-device-bus-type bluetooth
+)
+(defsrc a)
+(deflayer base a)
+";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("passes");
+    assert_eq!(cfg.options.linux_opts.linux_output_vendor_id, 0x046d);
+    assert_eq!(cfg.options.linux_opts.linux_output_product_id, 49948);
+    assert_eq!(cfg.options.linux_opts.linux_output_version, 1);
+    for value in ["0x10000", "-1", "0xzz", "(1)"] {
+        let source =
+            format!("(defcfg linux-output-device-version {value}) (defsrc a) (deflayer base a)");
+        parse_cfg(&source).map(|_| ()).expect_err(value);
+    }
+}
+
+#[test]
+fn fallback_cfg_parses() {
+    let source = r#"(defcfg fallback-cfg "safe mode.kbd") (defsrc a) (deflayer base a)"#;
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("parses");
+    assert_eq!(cfg.options.fallback_cfg.as_deref(), Some("safe mode.kbd"));
+    for value in [r#""""#, "(a.kbd)"] {
+        let source = format!("(defcfg fallback-cfg {value}) (defsrc a) (deflayer base a)");
+        parse_cfg(&source).map(|_| ()).expect_err(value);
+    }
+}
+
+#[test]
+fn stuck_key_timeout_parses() {
+    let source = "
+(defcfg stuck-key-timeout 5000)
+(defsrc a)
+(deflayer base a)
+";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("passes");
+    assert_eq!(cfg.options.stuck_key_timeout, 5000);
+    let cfg = parse_cfg("(defsrc a) (deflayer base a)").expect("passes");
+    assert_eq!(cfg.options.stuck_key_timeout, 0);
+}
+
+#[test]
+fn dedupe_input_ms_parses() {
+    let source = "
+(defcfg dedupe-input-ms 10)
+(defsrc a)
+(deflayer base a)
+";
+    let cfg = parse_cfg(source)
+        .map_err(|e| eprintln!("{:?}", miette::Error::from(e)))
+        .expect("passes");
+    assert_eq!(cfg.options.dedupe_input_ms, 10);
+    let cfg = parse_cfg("(defsrc a) (deflayer base a)").expect("passes");
+    assert_eq!(cfg.options.dedupe_input_ms, 0);
+}
@@ -0,0 +1,17 @@
+use super::*;
+
+use crate::bail;
+
+pub(crate) fn parse_training_mode(
+    ac_params: &[SExpr],
+    s: &ParserState,
+) -> Result<&'static KanataAction> {
+    let reference_layer = match ac_params.len() {
+        0 => None,
+        1 => Some(layer_idx(ac_params, &s.layer_idxs, s)?),
+        n => bail!(
+            "{TRAINING_MODE} expects 0 or 1 parameters: the layer of the layout to compare with, found {n}"
+        ),
+    };
+    custom(CustomAction::TrainingMode { reference_layer }, &s.a)
+}
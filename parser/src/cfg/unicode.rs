@@ -5,10 +5,13 @@ use crate::bail;
 use crate::bail_expr;
 
 pub(crate) fn parse_unicode(ac_params: &[SExpr], s: &ParserState) -> Result<&'static KanataAction> {
-    const ERR_STR: &str = "unicode expects exactly one (not combos looking like one) unicode character as an argument\nor a unicode hex number, prefixed by U+. Example: U+1F686.";
-    if ac_params.len() != 1 {
-        bail!(ERR_STR)
-    }
+    const ERR_STR: &str = "unicode expects exactly one string of unicode characters as an argument\nor a unicode hex number, prefixed by U+. Example: U+1F686.\nIt may be followed by paste to output it through the clipboard.";
+    let paste = match ac_params {
+        [_] => false,
+        [_, method] if method.atom(s.vars()) == Some("paste") => true,
+        [_, method] => bail_expr!(method, "{ERR_STR}\nUnknown output method"),
+        _ => bail!(ERR_STR),
+    };
     ac_params[0]
         .atom(s.vars())
         .map(|a| {
@@ -19,7 +22,10 @@ pub(crate) fn parse_unicode(ac_params: &[SExpr], s: &ParserState) -> Result<&'st
                 _ => {
                     let normalized = a.to_uppercase();
                     let Some(hexnum) = normalized.strip_prefix("U+") else {
-                        bail_expr!(&ac_params[0], "{ERR_STR}.\nMust begin with U+")
+                        if paste {
+                            return unicode_paste_action(a, s);
+                        }
+                        return unicode_str_action(a, s);
                     };
                     let Ok(u_val) = u32::from_str_radix(hexnum, 16) else {
                         bail_expr!(&ac_params[0], "{ERR_STR}.\nInvalid number after U+")
@@ -30,7 +36,51 @@ pub(crate) fn parse_unicode(ac_params: &[SExpr], s: &ParserState) -> Result<&'st
                     }
                 }
             };
+            if paste {
+                return unicode_paste_action(&unicode_char.to_string(), s);
+            }
             custom(CustomAction::Unicode(unicode_char), &s.a)
         })
         .ok_or_else(|| anyhow_expr!(&ac_params[0], "{ERR_STR}"))?
 }
+
+/// The clipboard save ID that `unicode` with `paste` saves the clipboard to while it pastes.
+const UNICODE_PASTE_CLIPBOARD_ID: u16 = u16::MAX;
+
+/// Returns the macro that pastes the text through the clipboard: it saves the clipboard, sets
+/// it to the text, presses the paste chord of the platform and restores the clipboard. The
+/// delays give the clipboard of the OS and the application time to take each change.
+fn unicode_paste_action(text: &str, s: &ParserState) -> Result<&'static KanataAction> {
+    #[cfg(target_os = "macos")]
+    const PASTE_MODIFIER: KeyCode = KeyCode::LGui;
+    #[cfg(not(target_os = "macos"))]
+    const PASTE_MODIFIER: KeyCode = KeyCode::LCtrl;
+    let custom = |action| SequenceEvent::Custom(s.a.sref(s.a.sref(action)));
+    let events = vec![
+        custom(CustomAction::ClipboardSave(UNICODE_PASTE_CLIPBOARD_ID)),
+        custom(CustomAction::ClipboardSet(s.a.sref_str(text.to_owned()))),
+        SequenceEvent::Delay { duration: 50 },
+        SequenceEvent::Press(PASTE_MODIFIER),
+        SequenceEvent::Press(KeyCode::V),
+        SequenceEvent::Release(KeyCode::V),
+        SequenceEvent::Release(PASTE_MODIFIER),
+        SequenceEvent::Delay { duration: 300 },
+        custom(CustomAction::ClipboardRestore(UNICODE_PASTE_CLIPBOARD_ID)),
+        SequenceEvent::Complete,
+    ];
+    Ok(s.a.sref(Action::Sequence {
+        events: s.a.sref(s.a.sref(s.a.sref_vec(events))),
+    }))
+}
+
+/// Returns the action that types the whole string at once, or the action for a single character.
+pub(crate) fn unicode_str_action(text: &str, s: &ParserState) -> Result<&'static KanataAction> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => custom(CustomAction::Unicode(c), &s.a),
+        _ => custom(
+            CustomAction::UnicodeStr(s.a.sref_str(text.to_owned())),
+            &s.a,
+        ),
+    }
+}
@@ -30,17 +30,7 @@ pub(crate) fn parse_unmod(
                 let flag = mod_key
                     .atom(s.vars())
                     .and_then(str_to_oscode)
-                    .and_then(|osc| match osc {
-                        OsCode::KEY_LEFTSHIFT => Some(UnmodMods::LSft),
-                        OsCode::KEY_RIGHTSHIFT => Some(UnmodMods::RSft),
-                        OsCode::KEY_LEFTCTRL => Some(UnmodMods::LCtl),
-                        OsCode::KEY_RIGHTCTRL => Some(UnmodMods::RCtl),
-                        OsCode::KEY_LEFTMETA => Some(UnmodMods::LMet),
-                        OsCode::KEY_RIGHTMETA => Some(UnmodMods::RMet),
-                        OsCode::KEY_LEFTALT => Some(UnmodMods::LAlt),
-                        OsCode::KEY_RIGHTALT => Some(UnmodMods::RAlt),
-                        _ => None,
-                    })
+                    .and_then(unmod_flag)
                     .ok_or_else(|| {
                         anyhow_expr!(
                             mod_key,
@@ -86,3 +76,18 @@ pub(crate) fn parse_unmod(
         _ => panic!("Unknown unmod type {unmod_type}"),
     }
 }
+
+/// The flag of a modifier key in [`UnmodMods`].
+pub(crate) fn unmod_flag(osc: OsCode) -> Option<UnmodMods> {
+    match osc {
+        OsCode::KEY_LEFTSHIFT => Some(UnmodMods::LSft),
+        OsCode::KEY_RIGHTSHIFT => Some(UnmodMods::RSft),
+        OsCode::KEY_LEFTCTRL => Some(UnmodMods::LCtl),
+        OsCode::KEY_RIGHTCTRL => Some(UnmodMods::RCtl),
+        OsCode::KEY_LEFTMETA => Some(UnmodMods::LMet),
+        OsCode::KEY_RIGHTMETA => Some(UnmodMods::RMet),
+        OsCode::KEY_LEFTALT => Some(UnmodMods::LAlt),
+        OsCode::KEY_RIGHTALT => Some(UnmodMods::RAlt),
+        _ => None,
+    }
+}
@@ -7,15 +7,33 @@ use anyhow::{Result, anyhow};
 use core::fmt;
 use kanata_keyberon::key_code::KeyCode;
 
-use crate::{cfg::SimpleSExpr, keys::OsCode};
+use crate::{
+    cfg::{CmdEnv, PluginAction, Secret, SimpleSExpr},
+    keys::OsCode,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum CustomAction {
     Cmd(&'static [&'static str]),
     CmdLog(LogLevel, LogLevel, &'static [&'static str]),
     CmdOutputKeys(&'static [&'static str]),
+    /// Runs the program with exactly these arguments, in the sandbox.
+    CmdArgv(&'static [&'static str], &'static CmdSandbox),
+    /// Kills the most recent command that runs, or that runs the program.
+    CmdCancel(Option<&'static str>),
     PushMessage(&'static [SimpleSExpr]),
     Unicode(char),
+    /// Text that is typed in one go, rather than one character at a time.
+    UnicodeStr(&'static str),
+    /// Type the time of the press, formatted with the `strftime` format.
+    DateTime(&'static str),
+    /// Type the secret with this name from the credential store of the OS.
+    SecretType(&'static Secret),
+    /// Set the level of the logs, for the duration or until it is set again if 0.
+    LogLevel {
+        level: log::LevelFilter,
+        duration_ms: u32,
+    },
     Mouse(Btn),
     MouseTap(Btn),
     FakeKey {
@@ -36,6 +54,9 @@ pub enum CustomAction {
         interval: u16,
         distance: u16,
         inertial_scroll_params: Option<&'static MWheelInertial>,
+        accel: Option<&'static MWheelAccel>,
+        /// Spread the distance of each activation over the interval.
+        smooth: bool,
     },
     MWheelNotch {
         direction: MWheelDirection,
@@ -56,6 +77,8 @@ pub enum CustomAction {
         speed: u16,
     },
     SequenceCancel,
+    /// Starts a compose sequence that is looked up in `defcompose`, with the timeout.
+    Compose(u16),
     SequenceLeader(u16, SequenceInputMode),
     /// Purpose:
     /// In case the user has dead keys in their OS layout, they may wish to send fewer backspaces upon
@@ -71,9 +94,16 @@ pub enum CustomAction {
     /// as the user-facing value though.
     LiveReloadNum(u16),
     LiveReloadFile(&'static str),
+    /// Live-reload the kept configuration from before the loaded one.
+    LiveReloadVersionPrev,
+    /// Live-reload the kept configuration from after the loaded one.
+    LiveReloadVersionNext,
     Repeat,
     CancelMacroOnRelease,
     CancelMacroOnNextPress(u32),
+    CancelMacros,
+    /// Scale the delays of macros by the percentage.
+    MacroDelayScale(u16),
     DynamicMacroRecord(u16),
     DynamicMacroRecordStop(u16),
     DynamicMacroPlay(u16),
@@ -83,6 +113,18 @@ pub enum CustomAction {
         x: u16,
         y: u16,
     },
+    MouseGridCell(MouseGridCell),
+    MouseGridReset,
+    /// The 0-based index of the monitor.
+    MouseGridMonitor(u8),
+    MouseToward(MouseToward),
+    Jiggle(MouseJiggle),
+    ToggleProcessing,
+    /// Toggles training mode, which sends what each pressed key does to TCP clients together with
+    /// what it does on the reference layer, or in defsrc if there is none.
+    TrainingMode {
+        reference_layer: Option<usize>,
+    },
     Unmodded {
         keys: &'static [KeyCode],
         mods: UnmodMods,
@@ -98,6 +140,36 @@ pub enum CustomAction {
     ClipboardSaveSet(u16, &'static str),
     ClipboardSaveCmdSet(u16, &'static [&'static str]),
     ClipboardSaveSwap(u16, u16),
+    MidiNote(MidiNote),
+    MidiControlChange(MidiControlChange),
+    Obs(ObsAction),
+    /// Set the color of the RGB lights of the devices in OpenRGB, as red, green and blue.
+    OpenRgb([u8; 3]),
+    /// Send a request to the webhook from `defwebhooks`, with the optional data in the body.
+    Webhook {
+        name: &'static str,
+        data: Option<&'static str>,
+    },
+    /// Run an action of a plugin from `defplugins`.
+    Plugin(&'static PluginAction),
+    /// Show a desktop notification. The body may be empty.
+    Notify {
+        title: &'static str,
+        body: &'static str,
+    },
+    Mpris(MprisAction),
+    /// Hold a usage of the consumer or system control page of HID.
+    HidUsage(HidUsage),
+    /// Switch the keyboard layout or input method of the OS, e.g. `de` or `ibus:mozc-jp` on
+    /// Linux, `00000407` or `de-DE` on Windows, `German` on macOS.
+    OsLayout(&'static str),
+    /// Set the value of a variable that `alias-concat` reads.
+    VarSet {
+        name: &'static str,
+        value: &'static str,
+    },
+    AliasConcat(&'static AliasConcat),
+    AppAlias(&'static AppAlias),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -127,6 +199,34 @@ pub struct Coord {
     pub y: u16,
 }
 
+/// An alias that is looked up when the key is pressed, by the name that the parts form with the
+/// current values of their variables.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AliasConcat {
+    pub parts: &'static [AliasConcatPart],
+    /// The aliases that the parts can name, with the coordinates of the virtual keys that hold
+    /// their actions.
+    pub targets: &'static [(&'static str, Coord)],
+}
+
+/// An alias that `defapp` replaces for some applications, by the virtual keys of the aliases.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AppAlias {
+    pub default: Coord,
+    /// The application identifiers of `defapp` with their replacements.
+    pub replacements: &'static [(&'static str, Coord)],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AliasConcatPart {
+    Text(&'static str),
+    /// A variable of `defvar`, whose value can be changed by `var-set`.
+    Var {
+        name: &'static str,
+        default: &'static str,
+    },
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FakeKeyAction {
     Press,
@@ -151,6 +251,200 @@ pub struct FakeKeyHoldForDuration {
     pub hold_duration: u16,
 }
 
+/// A MIDI note that is sent as note-on when pressed and note-off when released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MidiNote {
+    /// MIDI channel on the wire, i.e. 0-15.
+    pub channel: u8,
+    pub note: u8,
+    pub velocity: u8,
+}
+
+impl MidiNote {
+    pub fn note_on(&self) -> [u8; 3] {
+        [0x90 | self.channel, self.note, self.velocity]
+    }
+
+    pub fn note_off(&self) -> [u8; 3] {
+        [0x80 | self.channel, self.note, 0]
+    }
+}
+
+/// A MIDI control change message that is sent when pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MidiControlChange {
+    /// MIDI channel on the wire, i.e. 0-15.
+    pub channel: u8,
+    pub controller: u8,
+    pub value: u8,
+}
+
+impl MidiControlChange {
+    pub fn message(&self) -> [u8; 3] {
+        [0xB0 | self.channel, self.controller, self.value]
+    }
+}
+
+/// A request to OBS Studio that is sent with obs-websocket when pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObsAction {
+    SwitchScene(&'static str),
+    /// Show the source in the scene if it is hidden and hide it otherwise.
+    ToggleSource {
+        scene: &'static str,
+        source: &'static str,
+    },
+    StartRecording,
+    StopRecording,
+    ToggleRecording,
+}
+
+impl ObsAction {
+    /// The name of the action in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SwitchScene(_) => "switch-scene",
+            Self::ToggleSource { .. } => "toggle-source",
+            Self::StartRecording => "start-recording",
+            Self::StopRecording => "stop-recording",
+            Self::ToggleRecording => "toggle-recording",
+        }
+    }
+
+    pub fn args(&self) -> Vec<&'static str> {
+        match *self {
+            Self::SwitchScene(scene) => vec![scene],
+            Self::ToggleSource { scene, source } => vec![scene, source],
+            Self::StartRecording | Self::StopRecording | Self::ToggleRecording => vec![],
+        }
+    }
+}
+
+/// Periodic small mouse movements, toggled by the `jiggle` action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MouseJiggle {
+    pub interval: u16,
+    pub distance: u16,
+    /// Move back within the same interval instead of on the next one.
+    pub zero_sum: bool,
+}
+
+/// Selects a cell of a grid laid over the current mouse grid region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MouseGridCell {
+    pub columns: u8,
+    pub rows: u8,
+    /// 0-based column, less than `columns`.
+    pub column: u8,
+    /// 0-based row, less than `rows`.
+    pub row: u8,
+}
+
+/// The HID usage pages that `hid-usage` can send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HidUsagePage {
+    /// The system controls of the generic desktop page, e.g. sleep.
+    System,
+    /// The consumer page, e.g. media and application launch keys.
+    Consumer,
+}
+
+/// A usage of the consumer or system control page of HID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HidUsage {
+    pub page: HidUsagePage,
+    pub usage: u16,
+}
+
+impl HidUsage {
+    /// The number of the usage page.
+    pub fn page_id(self) -> u32 {
+        match self.page {
+            HidUsagePage::System => 0x01,
+            HidUsagePage::Consumer => 0x0C,
+        }
+    }
+
+    /// The key that the usage is mapped to on OSes that have no HID output, which is the key
+    /// that Linux maps the usage to. Returns None for usages that such OSes cannot send.
+    pub fn oscode(self) -> Option<OsCode> {
+        use OsCode::*;
+        Some(match (self.page, self.usage) {
+            (HidUsagePage::System, 0x81) => KEY_POWER,
+            (HidUsagePage::System, 0x82) => KEY_SLEEP,
+            (HidUsagePage::System, 0x83) => KEY_WAKEUP,
+            (HidUsagePage::System, _) => return None,
+            (HidUsagePage::Consumer, usage) => match usage {
+                0x30 => KEY_POWER,
+                0x32 | 0x34 => KEY_SLEEP,
+                0x40 => KEY_MENU,
+                0x6F => KEY_BRIGHTNESSUP,
+                0x70 => KEY_BRIGHTNESSDOWN,
+                0xB0 => KEY_PLAY,
+                0xB1 => KEY_PAUSE,
+                0xB2 => KEY_RECORD,
+                0xB3 => KEY_FASTFORWARD,
+                0xB4 => KEY_REWIND,
+                0xB5 => KEY_NEXTSONG,
+                0xB6 => KEY_PREVIOUSSONG,
+                0xB7 => KEY_STOPCD,
+                0xB8 => KEY_EJECTCD,
+                0xCD => KEY_PLAYPAUSE,
+                0xE2 => KEY_MUTE,
+                0xE9 => KEY_VOLUMEUP,
+                0xEA => KEY_VOLUMEDOWN,
+                0x183 => KEY_CONFIG,
+                0x18A => KEY_MAIL,
+                0x192 => KEY_CALC,
+                0x194 => KEY_FILE,
+                0x196 => KEY_WWW,
+                0x19E => KEY_COFFEE,
+                0x1A7 => KEY_DOCUMENTS,
+                0x201 => KEY_NEW,
+                0x202 => KEY_OPEN,
+                0x203 => KEY_CLOSE,
+                0x207 => KEY_SAVE,
+                0x208 => KEY_PRINT,
+                0x21A => KEY_UNDO,
+                0x21B => KEY_COPY,
+                0x21C => KEY_CUT,
+                0x21D => KEY_PASTE,
+                0x221 => KEY_SEARCH,
+                0x223 => KEY_HOMEPAGE,
+                0x224 => KEY_BACK,
+                0x225 => KEY_FORWARD,
+                0x226 => KEY_STOP,
+                0x227 => KEY_REFRESH,
+                0x22A => KEY_BOOKMARKS,
+                _ => return None,
+            },
+        })
+    }
+}
+
+impl fmt::Display for HidUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let page = match self.page {
+            HidUsagePage::System => "system",
+            HidUsagePage::Consumer => "consumer",
+        };
+        write!(f, "{page}:0x{:04X}", self.usage)
+    }
+}
+
+/// Moves the pointer part of the way toward an anchor point of a monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MouseToward {
+    /// The 0-based monitor of the anchor, or None for the monitor that contains the pointer.
+    pub monitor: Option<u8>,
+    /// The anchor's position across the monitor's width, in percent.
+    pub anchor_x: u8,
+    /// The anchor's position down the monitor's height, in percent.
+    pub anchor_y: u8,
+    /// How far to move, in percent of the distance to the anchor.
+    pub percent: u8,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MWheelDirection {
     Up,
@@ -183,6 +477,17 @@ impl TryFrom<OsCode> for MWheelDirection {
     }
 }
 
+/// What a `cmd-argv` command may see and do.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CmdSandbox {
+    /// Start the command without the environment of kanata, with only `env`.
+    pub clear_env: bool,
+    pub env: CmdEnv,
+    /// The user and group to run the command as, on Unix, e.g. to drop root.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
 pub struct MWheelInertial {
     pub initial_velocity: ordered_float::OrderedFloat<f32>,
@@ -191,6 +496,14 @@ pub struct MWheelInertial {
     pub deceleration_multiplier: ordered_float::OrderedFloat<f32>,
 }
 
+/// Linear acceleration of `mwheel-*`, like that of `movemouse-accel-*`.
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
+pub struct MWheelAccel {
+    /// Milliseconds of holding until the distance per activation is `max_distance`.
+    pub accel_time: u16,
+    pub max_distance: u16,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MoveDirection {
     Up,
@@ -321,3 +634,47 @@ bitflags::bitflags! {
         const RMet = 0b10000000;
     }
 }
+
+/// A command to a media player over MPRIS, sent when pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MprisAction {
+    pub command: MprisCommand,
+    /// The player to control, e.g. `spotify`. Any player is controlled if not set.
+    pub player: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MprisCommand {
+    PlayPause,
+    Play,
+    Pause,
+    Stop,
+    Next,
+    Previous,
+}
+
+impl MprisCommand {
+    /// The name of the command in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PlayPause => "play-pause",
+            Self::Play => "play",
+            Self::Pause => "pause",
+            Self::Stop => "stop",
+            Self::Next => "next",
+            Self::Previous => "previous",
+        }
+    }
+
+    /// The method of the `org.mpris.MediaPlayer2.Player` interface.
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::PlayPause => "PlayPause",
+            Self::Play => "Play",
+            Self::Pause => "Pause",
+            Self::Stop => "Stop",
+            Self::Next => "Next",
+            Self::Previous => "Previous",
+        }
+    }
+}
@@ -1307,6 +1307,12 @@ impl TryFrom<PageCode> for OsCode {
                 page: 0xFF,
                 code: 0x03,
             } => Ok(OsCode::KEY_FN),
+            // The Globe/Fn key of external Apple keyboards, on the Apple vendor keyboard page. It is
+            // sent on the Apple vendor top case page like the key of built-in keyboards.
+            PageCode {
+                page: 0xFF01,
+                code: 0x03,
+            } => Ok(OsCode::KEY_FN),
             PageCode {
                 page: 0x0C,
                 code: 0x6F,
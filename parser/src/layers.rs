@@ -16,10 +16,10 @@ pub const DEFAULT_ACTION: KanataAction = KanataAction::KeyCode(KeyCode::ErrorUnd
 pub type IntermediateLayers = Box<[[Row; LAYER_ROWS]]>;
 pub type KanataCustom = &'static CustomAction;
 
-pub type KLayers = Layers<'static, KEYS_IN_ROW, LAYER_ROWS, KanataCustom>;
+pub type KLayers = LayerStore<'static, KEYS_IN_ROW, LAYER_ROWS, KanataCustom>;
 
 pub struct KanataLayers {
-    pub(crate) layers: Layers<'static, KEYS_IN_ROW, LAYER_ROWS, KanataCustom>,
+    pub(crate) layers: KLayers,
     _allocations: Arc<Allocations>,
 }
 
@@ -43,6 +43,46 @@ pub fn new_layers(layers: usize) -> IntermediateLayers {
     layers.into_boxed_slice()
 }
 
+/// Copies the layers into `allocations` as sparse rows where possible. Most keys of a layer are
+/// usually transparent or unused, so this saves most of the memory of a dense layer.
+pub(crate) fn compact_layers(layers: &[[Row; LAYER_ROWS]], allocations: &Allocations) -> KLayers {
+    let rows = layers
+        .iter()
+        .map(|layer| layer.each_ref().map(|row| compact_row(row, allocations)))
+        .collect::<Vec<_>>();
+    LayerStore::Rows(allocations.sref_vec(rows))
+}
+
+fn compact_row(row: &Row, allocations: &Allocations) -> LayerRow<'static, KanataCustom> {
+    // Boyer-Moore majority vote: if an action is in more than half of the row, it is the one left.
+    let mut fill = row[0];
+    let mut count = 0;
+    for action in row.iter() {
+        if count == 0 {
+            fill = *action;
+        }
+        count = if *action == fill {
+            count + 1
+        } else {
+            count - 1
+        };
+    }
+    let actions = row
+        .iter()
+        .enumerate()
+        .filter(|(_, action)| **action != fill)
+        .map(|(col, action)| (col as u16, *action))
+        .collect::<Vec<_>>();
+    if actions.len() * 2 < KEYS_IN_ROW {
+        LayerRow::Sparse {
+            fill,
+            actions: allocations.sref_vec(actions),
+        }
+    } else {
+        LayerRow::Dense(allocations.sref_vec(row.to_vec()))
+    }
+}
+
 impl KanataLayers {
     /// # Safety
     ///
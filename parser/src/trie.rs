@@ -37,6 +37,10 @@ impl<T> Trie<T> {
         }
     }
 
+    pub fn contains(&self, key: impl AsRef<[u16]>) -> bool {
+        self.inner.contains_key(cast_slice(key.as_ref()))
+    }
+
     pub fn ancestor_exists(&self, key: impl AsRef<[u16]>) -> bool {
         self.inner
             .get_longest_common_prefix(cast_slice(key.as_ref()))
@@ -71,6 +75,20 @@ impl<T> Trie<T> {
         }
     }
 
+    /// Returns the keys that start with `key`, including `key` itself, and their values.
+    pub fn descendants(&self, key: impl AsRef<[u16]>) -> Vec<(Vec<u16>, &T)> {
+        self.inner
+            .iter_prefix(cast_slice(key.as_ref()))
+            .map(|(k, v)| {
+                let k = k
+                    .chunks_exact(2)
+                    .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+                    .collect();
+                (k, v)
+            })
+            .collect()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
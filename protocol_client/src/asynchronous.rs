@@ -0,0 +1,369 @@
+//! A client for tokio, with the methods of [`blocking::Client`] as `async fn`s.
+
+use crate::session::{Expect, Session, check_auth, encode, handshake, server_info};
+use crate::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::net::{TcpStream, UdpSocket, lookup_host};
+use tokio::time::{Instant, sleep, timeout, timeout_at};
+
+enum Transport {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+struct Connection {
+    transport: Transport,
+    framing: Framing,
+    /// `Authenticate` is sent before each request on UDP, since the server does not remember
+    /// senders.
+    udp_token: Option<String>,
+}
+
+impl Connection {
+    async fn open(address: &Address, options: &Options) -> Result<Self, Error> {
+        let transport = match address {
+            Address::Tcp(address) => {
+                let stream = timeout(options.timeout, TcpStream::connect(address.as_str()))
+                    .await
+                    .map_err(|_| Error::Timeout)??;
+                let _ = stream.set_nodelay(true);
+                Transport::Tcp(stream)
+            }
+            Address::Udp(address) => {
+                let server = lookup_host(address.as_str())
+                    .await?
+                    .next()
+                    .ok_or_else(|| Error::Address(format!("{address} resolves to no address")))?;
+                let local = if server.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(server).await?;
+                Transport::Udp(socket)
+            }
+            #[cfg(unix)]
+            Address::Unix(path) => Transport::Unix(UnixStream::connect(path).await?),
+            #[cfg(not(unix))]
+            Address::Unix(_) => {
+                return Err(Error::Unsupported(
+                    "unix sockets are not supported on this platform",
+                ));
+            }
+        };
+        let udp_token = match transport {
+            Transport::Udp(_) => options.token.clone(),
+            _ => None,
+        };
+        Ok(Self {
+            transport,
+            framing: Framing::Newline,
+            udp_token,
+        })
+    }
+
+    fn is_udp(&self) -> bool {
+        matches!(self.transport, Transport::Udp(_))
+    }
+
+    /// Sends the messages, in one datagram on UDP.
+    async fn send(&mut self, msgs: &[ClientMessage]) -> Result<(), Error> {
+        let bytes: Vec<u8> = msgs
+            .iter()
+            .flat_map(|msg| encode(msg, self.framing))
+            .collect();
+        match &mut self.transport {
+            Transport::Tcp(stream) => stream.write_all(&bytes).await?,
+            Transport::Udp(socket) => {
+                socket.send(&bytes).await?;
+            }
+            #[cfg(unix)]
+            Transport::Unix(stream) => stream.write_all(&bytes).await?,
+        }
+        Ok(())
+    }
+
+    /// Reads what the server sent, waiting until the deadline at most.
+    async fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> Result<usize, Error> {
+        let read = async {
+            match &mut self.transport {
+                Transport::Tcp(stream) => stream.read(buf).await,
+                Transport::Udp(socket) => socket.recv(buf).await,
+                #[cfg(unix)]
+                Transport::Unix(stream) => stream.read(buf).await,
+            }
+        };
+        let n = match deadline {
+            Some(deadline) => timeout_at(deadline, read)
+                .await
+                .map_err(|_| Error::Timeout)??,
+            None => read.await?,
+        };
+        if n == 0 && !self.is_udp() {
+            return Err(Error::Disconnected);
+        }
+        Ok(n)
+    }
+}
+
+/// A client of a listener of kanata.
+pub struct Client {
+    address: Address,
+    options: Options,
+    connection: Option<Connection>,
+    session: Session,
+    server: ServerInfo,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    /// Connects, authenticates if `options` has a token, and says `Hello`.
+    pub async fn connect(address: Address, options: Options) -> Result<Self, Error> {
+        let mut client = Self {
+            session: Session::new(options.subscriptions.clone()),
+            address,
+            options,
+            connection: None,
+            server: ServerInfo {
+                version: String::new(),
+                protocol: 0,
+                capabilities: vec![],
+                seat: None,
+            },
+            buf: vec![0; 64 * 1024],
+        };
+        client.open().await?;
+        Ok(client)
+    }
+
+    /// What the server said in `HelloOk` on the last connection.
+    pub fn server(&self) -> &ServerInfo {
+        &self.server
+    }
+
+    /// See [`blocking::Client::subscribe`].
+    pub fn subscribe<S: Into<String>>(&mut self, kinds: impl IntoIterator<Item = S>) {
+        self.session.subscriptions = Some(kinds.into_iter().map(Into::into).collect());
+    }
+
+    /// Keeps every notification from now on, which is the default.
+    pub fn subscribe_all(&mut self) {
+        self.session.subscriptions = None;
+    }
+
+    async fn open(&mut self) -> Result<(), Error> {
+        self.connection = None;
+        self.session.reset();
+        let mut connection = Connection::open(&self.address, &self.options).await?;
+        let msgs = handshake(&self.options, !connection.is_udp());
+        let deadline = Some(Instant::now() + self.options.timeout);
+        connection.send(&msgs).await?;
+        self.connection = Some(connection);
+        if self.options.token.is_some() {
+            check_auth(self.wait(Expect::Response, deadline).await?)?;
+        }
+        let hello = self
+            .wait(Expect::of(&msgs[msgs.len() - 1]), deadline)
+            .await?;
+        self.server = server_info(hello)?;
+        self.sync_framing();
+        Ok(())
+    }
+
+    /// Writes with the framing that the server switched to.
+    fn sync_framing(&mut self) {
+        if let Some(connection) = &mut self.connection {
+            connection.framing = self.session.decoder.framing();
+        }
+    }
+
+    /// Connects again with the reconnect policy, and queues [`Event::Reconnected`].
+    async fn reconnect(&mut self) -> Result<(), Error> {
+        let Some(reconnect) = self.options.reconnect else {
+            self.connection = None;
+            return Err(Error::Disconnected);
+        };
+        let mut attempt = 0;
+        loop {
+            sleep(reconnect.delay(attempt)).await;
+            match self.open().await {
+                Ok(()) => {
+                    self.session.events.push_back(Event::Reconnected);
+                    return Ok(());
+                }
+                Err(e @ Error::Auth(_)) => return Err(e),
+                Err(_) if !reconnect.gives_up(attempt + 1) => attempt += 1,
+                Err(_) => return Err(Error::Disconnected),
+            }
+        }
+    }
+
+    /// Waits for the reply of a message that was sent, queueing other messages as events.
+    async fn wait(&mut self, expect: Expect, deadline: Option<Instant>) -> Result<Reply, Error> {
+        let mut waiting = Some(expect);
+        loop {
+            while let Some(incoming) = self.session.decoder.next()? {
+                if let Some(reply) = self.session.receive(incoming, &mut waiting) {
+                    return Ok(reply);
+                }
+            }
+            self.read_some(deadline).await?;
+        }
+    }
+
+    async fn read_some(&mut self, deadline: Option<Instant>) -> Result<(), Error> {
+        let connection = self.connection.as_mut().ok_or(Error::Disconnected)?;
+        match connection.read(&mut self.buf, deadline).await {
+            Ok(n) => {
+                self.session.decoder.push(&self.buf[..n]);
+                Ok(())
+            }
+            Err(e) => {
+                if e.is_disconnect() {
+                    self.connection = None;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn try_request(&mut self, msg: &ClientMessage) -> Result<Option<Reply>, Error> {
+        if self.connection.is_none() {
+            self.reconnect().await?;
+        }
+        let connection = self.connection.as_mut().ok_or(Error::Disconnected)?;
+        let result = match connection.udp_token.clone() {
+            Some(token) => {
+                connection
+                    .send(&[ClientMessage::Authenticate { token }, msg.clone()])
+                    .await
+            }
+            None => connection.send(std::slice::from_ref(msg)).await,
+        };
+        if let Err(e) = result {
+            if e.is_disconnect() {
+                self.connection = None;
+            }
+            return Err(e);
+        }
+        let expect = Expect::of(msg);
+        if expect == Expect::Nothing {
+            return Ok(None);
+        }
+        let deadline = Some(Instant::now() + self.options.timeout);
+        if self
+            .connection
+            .as_ref()
+            .is_some_and(|c| c.udp_token.is_some())
+        {
+            check_auth(self.wait(Expect::Response, deadline).await?)?;
+        }
+        let reply = self.wait(expect, deadline).await?;
+        self.sync_framing();
+        Ok(Some(reply))
+    }
+
+    /// See [`blocking::Client::request`].
+    pub async fn request(&mut self, msg: &ClientMessage) -> Result<Option<Reply>, Error> {
+        match self.try_request(msg).await {
+            Err(e) if e.is_disconnect() && self.options.reconnect.is_some() => {
+                self.reconnect().await?;
+                match msg.changes_state() {
+                    true => Err(Error::Disconnected),
+                    false => self.try_request(msg).await,
+                }
+            }
+            result => result,
+        }
+    }
+
+    /// Sends a command and returns an error if the server replied with one.
+    pub async fn command(&mut self, msg: ClientMessage) -> Result<(), Error> {
+        match self.request(&msg).await? {
+            Some(reply) => reply.into_result().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends a query, like `RequestLayerNames`, and returns the message that answers it.
+    pub async fn query(&mut self, msg: ClientMessage) -> Result<ServerMessage, Error> {
+        if !matches!(Expect::of(&msg), Expect::Message { .. }) {
+            return Err(Error::Unsupported("the message is not a query"));
+        }
+        match self
+            .request(&msg)
+            .await?
+            .map(Reply::into_result)
+            .transpose()?
+            .flatten()
+        {
+            Some(reply) => Ok(reply),
+            None => Err(Error::InvalidMessage("the query was not answered".into())),
+        }
+    }
+
+    /// See [`blocking::Client::next_event`].
+    pub async fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<Event>, Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            while let Some(incoming) = self.session.decoder.next()? {
+                self.session.receive(incoming, &mut None);
+            }
+            if let Some(event) = self.session.events.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.connection.as_ref().is_some_and(Connection::is_udp) {
+                return Err(Error::Unsupported("UDP listeners send no notifications"));
+            }
+            match self.read_some(deadline).await {
+                Ok(()) => {}
+                Err(Error::Timeout) => return Ok(None),
+                Err(e) if e.is_disconnect() => self.reconnect().await?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+
+    #[tokio::test]
+    async fn queries_and_receives_notifications() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = Address::Tcp(listener.local_addr().unwrap().to_string());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let replies = [
+                r#"{"HelloOk":{"version":"1.12.0","protocol":1,"capabilities":[]}}"#,
+                "{\"ConfigFileReload\":{\"new\":\"a.kbd\"}}\n{\"LayerNames\":{\"names\":[\"base\"]}}",
+            ];
+            for reply in replies {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                stream.write_all(format!("{reply}\n").as_bytes()).unwrap();
+            }
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+        });
+        let mut client = Client::connect(address, Options::default()).await.unwrap();
+        let names = client.query(ClientMessage::RequestLayerNames {}).await;
+        assert!(matches!(names, Ok(ServerMessage::LayerNames { names }) if names == ["base"]));
+        assert!(matches!(
+            client.next_event(None).await,
+            Ok(Some(Event::Message(ServerMessage::ConfigFileReload { .. })))
+        ));
+        assert!(matches!(
+            client.next_event(Some(Duration::from_millis(10))).await,
+            Ok(None)
+        ));
+    }
+}
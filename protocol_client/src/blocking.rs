@@ -0,0 +1,542 @@
+//! A client that blocks the calling thread until the server replies.
+
+use crate::session::{Expect, Session, check_auth, encode, handshake, server_info};
+use crate::*;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Instant;
+
+enum Transport {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+struct Connection {
+    transport: Transport,
+    framing: Framing,
+    /// `Authenticate` is sent before each request on UDP, since the server does not remember
+    /// senders.
+    udp_token: Option<String>,
+}
+
+impl Connection {
+    fn open(address: &Address, options: &Options) -> Result<Self, Error> {
+        let transport = match address {
+            Address::Tcp(address) => {
+                let stream = connect_tcp(address, options.timeout)?;
+                let _ = stream.set_nodelay(true);
+                Transport::Tcp(stream)
+            }
+            Address::Udp(address) => {
+                let server = resolve(address)?;
+                let local = if server.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(server)?;
+                Transport::Udp(socket)
+            }
+            #[cfg(unix)]
+            Address::Unix(path) => Transport::Unix(UnixStream::connect(path)?),
+            #[cfg(not(unix))]
+            Address::Unix(_) => {
+                return Err(Error::Unsupported(
+                    "unix sockets are not supported on this platform",
+                ));
+            }
+        };
+        let write_timeout = Some(options.timeout);
+        match &transport {
+            Transport::Tcp(stream) => stream.set_write_timeout(write_timeout)?,
+            Transport::Udp(socket) => socket.set_write_timeout(write_timeout)?,
+            #[cfg(unix)]
+            Transport::Unix(stream) => stream.set_write_timeout(write_timeout)?,
+        }
+        let udp_token = match transport {
+            Transport::Udp(_) => options.token.clone(),
+            _ => None,
+        };
+        Ok(Self {
+            transport,
+            framing: Framing::Newline,
+            udp_token,
+        })
+    }
+
+    fn is_udp(&self) -> bool {
+        matches!(self.transport, Transport::Udp(_))
+    }
+
+    /// Sends the messages, in one datagram on UDP.
+    fn send(&mut self, msgs: &[ClientMessage]) -> Result<(), Error> {
+        let bytes: Vec<u8> = msgs
+            .iter()
+            .flat_map(|msg| encode(msg, self.framing))
+            .collect();
+        match &mut self.transport {
+            Transport::Tcp(stream) => stream.write_all(&bytes)?,
+            Transport::Udp(socket) => {
+                socket.send(&bytes)?;
+            }
+            #[cfg(unix)]
+            Transport::Unix(stream) => stream.write_all(&bytes)?,
+        }
+        Ok(())
+    }
+
+    /// Reads what the server sent, waiting until the deadline at most.
+    fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> Result<usize, Error> {
+        let timeout = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(timeout) if !timeout.is_zero() => Some(timeout),
+                _ => return Err(Error::Timeout),
+            },
+            None => None,
+        };
+        let n = match &mut self.transport {
+            Transport::Tcp(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.read(buf)?
+            }
+            Transport::Udp(socket) => {
+                socket.set_read_timeout(timeout)?;
+                socket.recv(buf)?
+            }
+            #[cfg(unix)]
+            Transport::Unix(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.read(buf)?
+            }
+        };
+        if n == 0 && !self.is_udp() {
+            return Err(Error::Disconnected);
+        }
+        Ok(n)
+    }
+}
+
+fn resolve(address: &str) -> Result<std::net::SocketAddr, Error> {
+    address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::Address(format!("{address} resolves to no address")))
+}
+
+fn connect_tcp(address: &str, timeout: Duration) -> Result<TcpStream, Error> {
+    let mut last_error = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => e.into(),
+        None => Error::Address(format!("{address} resolves to no address")),
+    })
+}
+
+/// A client of a listener of kanata.
+pub struct Client {
+    address: Address,
+    options: Options,
+    connection: Option<Connection>,
+    session: Session,
+    server: ServerInfo,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    /// Connects, authenticates if `options` has a token, and says `Hello`.
+    pub fn connect(address: Address, options: Options) -> Result<Self, Error> {
+        let mut client = Self {
+            session: Session::new(options.subscriptions.clone()),
+            address,
+            options,
+            connection: None,
+            server: ServerInfo {
+                version: String::new(),
+                protocol: 0,
+                capabilities: vec![],
+                seat: None,
+            },
+            buf: vec![0; 64 * 1024],
+        };
+        client.open()?;
+        Ok(client)
+    }
+
+    /// What the server said in `HelloOk` on the last connection.
+    pub fn server(&self) -> &ServerInfo {
+        &self.server
+    }
+
+    /// Keeps only the notifications of the kinds, e.g. `LayerChange` or `ConfigFileReload`, from
+    /// now on. Other notifications are dropped as they arrive.
+    pub fn subscribe<S: Into<String>>(&mut self, kinds: impl IntoIterator<Item = S>) {
+        self.session.subscriptions = Some(kinds.into_iter().map(Into::into).collect());
+    }
+
+    /// Keeps every notification from now on, which is the default.
+    pub fn subscribe_all(&mut self) {
+        self.session.subscriptions = None;
+    }
+
+    fn open(&mut self) -> Result<(), Error> {
+        self.connection = None;
+        self.session.reset();
+        let mut connection = Connection::open(&self.address, &self.options)?;
+        let msgs = handshake(&self.options, !connection.is_udp());
+        let deadline = Some(Instant::now() + self.options.timeout);
+        connection.send(&msgs)?;
+        self.connection = Some(connection);
+        if self.options.token.is_some() {
+            check_auth(self.wait(Expect::Response, deadline)?)?;
+        }
+        let hello = self.wait(Expect::of(&msgs[msgs.len() - 1]), deadline)?;
+        self.server = server_info(hello)?;
+        self.sync_framing();
+        Ok(())
+    }
+
+    /// Writes with the framing that the server switched to.
+    fn sync_framing(&mut self) {
+        if let Some(connection) = &mut self.connection {
+            connection.framing = self.session.decoder.framing();
+        }
+    }
+
+    /// Connects again with the reconnect policy, and queues [`Event::Reconnected`].
+    fn reconnect(&mut self) -> Result<(), Error> {
+        let Some(reconnect) = self.options.reconnect else {
+            self.connection = None;
+            return Err(Error::Disconnected);
+        };
+        let mut attempt = 0;
+        loop {
+            std::thread::sleep(reconnect.delay(attempt));
+            match self.open() {
+                Ok(()) => {
+                    self.session.events.push_back(Event::Reconnected);
+                    return Ok(());
+                }
+                Err(e @ Error::Auth(_)) => return Err(e),
+                Err(_) if !reconnect.gives_up(attempt + 1) => attempt += 1,
+                Err(_) => return Err(Error::Disconnected),
+            }
+        }
+    }
+
+    /// Waits for the reply of a message that was sent, queueing other messages as events.
+    fn wait(&mut self, expect: Expect, deadline: Option<Instant>) -> Result<Reply, Error> {
+        let mut waiting = Some(expect);
+        loop {
+            while let Some(incoming) = self.session.decoder.next()? {
+                if let Some(reply) = self.session.receive(incoming, &mut waiting) {
+                    return Ok(reply);
+                }
+            }
+            self.read_some(deadline)?;
+        }
+    }
+
+    fn read_some(&mut self, deadline: Option<Instant>) -> Result<(), Error> {
+        let connection = self.connection.as_mut().ok_or(Error::Disconnected)?;
+        match connection.read(&mut self.buf, deadline) {
+            Ok(n) => {
+                self.session.decoder.push(&self.buf[..n]);
+                Ok(())
+            }
+            Err(e) => {
+                if e.is_disconnect() {
+                    self.connection = None;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn try_request(&mut self, msg: &ClientMessage) -> Result<Option<Reply>, Error> {
+        if self.connection.is_none() {
+            self.reconnect()?;
+        }
+        let connection = self.connection.as_mut().ok_or(Error::Disconnected)?;
+        let result = match connection.udp_token.clone() {
+            Some(token) => connection.send(&[ClientMessage::Authenticate { token }, msg.clone()]),
+            None => connection.send(std::slice::from_ref(msg)),
+        };
+        if let Err(e) = result {
+            if e.is_disconnect() {
+                self.connection = None;
+            }
+            return Err(e);
+        }
+        let expect = Expect::of(msg);
+        if expect == Expect::Nothing {
+            return Ok(None);
+        }
+        let deadline = Some(Instant::now() + self.options.timeout);
+        if self
+            .connection
+            .as_ref()
+            .is_some_and(|c| c.udp_token.is_some())
+        {
+            check_auth(self.wait(Expect::Response, deadline)?)?;
+        }
+        let reply = self.wait(expect, deadline)?;
+        self.sync_framing();
+        Ok(Some(reply))
+    }
+
+    /// Sends the message and returns its reply, or None for messages that are not answered
+    /// unless they fail, like `ChangeLayer`; their errors are received as events.
+    ///
+    /// If the connection was lost, it is opened again with the reconnect policy. Queries are
+    /// then sent again, but commands return [`Error::Disconnected`] since they may have been run.
+    pub fn request(&mut self, msg: &ClientMessage) -> Result<Option<Reply>, Error> {
+        match self.try_request(msg) {
+            Err(e) if e.is_disconnect() && self.options.reconnect.is_some() => {
+                self.reconnect()?;
+                match msg.changes_state() {
+                    true => Err(Error::Disconnected),
+                    false => self.try_request(msg),
+                }
+            }
+            result => result,
+        }
+    }
+
+    /// Sends a command and returns an error if the server replied with one.
+    pub fn command(&mut self, msg: ClientMessage) -> Result<(), Error> {
+        match self.request(&msg)? {
+            Some(reply) => reply.into_result().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends a query, like `RequestLayerNames`, and returns the message that answers it.
+    pub fn query(&mut self, msg: ClientMessage) -> Result<ServerMessage, Error> {
+        if !matches!(Expect::of(&msg), Expect::Message { .. }) {
+            return Err(Error::Unsupported("the message is not a query"));
+        }
+        match self
+            .request(&msg)?
+            .map(Reply::into_result)
+            .transpose()?
+            .flatten()
+        {
+            Some(reply) => Ok(reply),
+            None => Err(Error::InvalidMessage("the query was not answered".into())),
+        }
+    }
+
+    /// Returns the next event, waiting for it up to `timeout`, or forever if it is None. Returns
+    /// None if the timeout passed.
+    ///
+    /// UDP listeners send no notifications, so on UDP this only returns the events that arrived
+    /// with replies.
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<Event>, Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            while let Some(incoming) = self.session.decoder.next()? {
+                self.session.receive(incoming, &mut None);
+            }
+            if let Some(event) = self.session.events.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.connection.as_ref().is_some_and(Connection::is_udp) {
+                return Err(Error::Unsupported("UDP listeners send no notifications"));
+            }
+            match self.read_some(deadline) {
+                Ok(()) => {}
+                Err(Error::Timeout) => return Ok(None),
+                Err(e) if e.is_disconnect() => self.reconnect()?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    const HELLO_OK: &str =
+        r#"{"HelloOk":{"version":"1.12.0","protocol":1,"capabilities":["reload"]}}"#;
+
+    /// Runs a fake server on a thread and returns its address.
+    fn serve(server: impl FnOnce(TcpListener) + Send + 'static) -> Address {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = Address::Tcp(listener.local_addr().unwrap().to_string());
+        std::thread::spawn(move || server(listener));
+        address
+    }
+
+    fn accept(listener: &TcpListener) -> (BufReader<TcpStream>, TcpStream) {
+        let (stream, _) = listener.accept().unwrap();
+        (BufReader::new(stream.try_clone().unwrap()), stream)
+    }
+
+    fn read_msg(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    fn write_lines(stream: &mut TcpStream, lines: &[&str]) {
+        for line in lines {
+            stream.write_all(format!("{line}\n").as_bytes()).unwrap();
+        }
+    }
+
+    #[test]
+    fn authenticates_and_tells_replies_from_notifications() {
+        let address = serve(|listener| {
+            let (mut reader, mut stream) = accept(&listener);
+            assert!(read_msg(&mut reader).contains(r#"{"Authenticate":{"token":"hunter2"}}"#));
+            write_lines(
+                &mut stream,
+                &[r#"{"status":"Ok"}"#, r#"{"LayerChange":{"new":"base"}}"#],
+            );
+            assert!(read_msg(&mut reader).contains(r#""client_name":"test""#));
+            write_lines(&mut stream, &[HELLO_OK]);
+            assert!(read_msg(&mut reader).contains("RequestLayerNames"));
+            write_lines(
+                &mut stream,
+                &[
+                    r#"{"TapActivated":{"key":"a"}}"#,
+                    r#"{"LayerNames":{"names":["base","nav"]}}"#,
+                ],
+            );
+            assert!(read_msg(&mut reader).contains("SetActiveApp"));
+            write_lines(&mut stream, &[r#"{"status":"Error","msg":"no defapp"}"#]);
+            read_msg(&mut reader);
+        });
+        let options = Options {
+            token: Some("hunter2".into()),
+            client_name: Some("test".into()),
+            ..Options::default()
+        };
+        let mut client = Client::connect(address, options).unwrap();
+        assert!(client.server().has_capability("reload"));
+        let names = client.query(ClientMessage::RequestLayerNames {}).unwrap();
+        assert!(matches!(names, ServerMessage::LayerNames { names } if names == ["base", "nav"]));
+        let error = client.command(ClientMessage::SetActiveApp { app: "x".into() });
+        assert!(matches!(error, Err(Error::Server(msg)) if msg == "no defapp"));
+        assert!(matches!(
+            client.next_event(None).unwrap(),
+            Some(Event::Message(ServerMessage::LayerChange { .. }))
+        ));
+        assert!(matches!(
+            client.next_event(None).unwrap(),
+            Some(Event::Message(ServerMessage::TapActivated { .. }))
+        ));
+        assert!(
+            client
+                .next_event(Some(Duration::from_millis(10)))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn switches_to_length_prefixed_framing() {
+        let address = serve(|listener| {
+            let (mut reader, mut stream) = accept(&listener);
+            assert!(read_msg(&mut reader).contains(r#""framing":"length-prefixed""#));
+            let hello_ok = HELLO_OK.replace("]}}", r#"],"framing":"length-prefixed"}}"#);
+            write_lines(&mut stream, &[&hello_ok]);
+            let mut len = [0; 4];
+            reader.read_exact(&mut len).unwrap();
+            let mut msg = vec![0; u32::from_be_bytes(len) as usize];
+            reader.read_exact(&mut msg).unwrap();
+            assert_eq!(msg, br#"{"RequestCurrentLayerName":{}}"#);
+            let reply = br#"{"CurrentLayerName":{"name":"nav"}}"#;
+            stream
+                .write_all(&(reply.len() as u32).to_be_bytes())
+                .unwrap();
+            stream.write_all(reply).unwrap();
+            read_msg(&mut reader);
+        });
+        let options = Options {
+            framing: Framing::LengthPrefixed,
+            ..Options::default()
+        };
+        let mut client = Client::connect(address, options).unwrap();
+        let name = client.query(ClientMessage::RequestCurrentLayerName {});
+        assert!(matches!(name, Ok(ServerMessage::CurrentLayerName { name }) if name == "nav"));
+    }
+
+    #[test]
+    fn reconnects_and_resends_queries() {
+        let address = serve(|listener| {
+            let (mut reader, mut stream) = accept(&listener);
+            read_msg(&mut reader);
+            write_lines(&mut stream, &[HELLO_OK]);
+            // Closes the connection once the query arrives.
+            read_msg(&mut reader);
+            drop((reader, stream));
+            let (mut reader, mut stream) = accept(&listener);
+            read_msg(&mut reader);
+            write_lines(&mut stream, &[HELLO_OK]);
+            assert!(read_msg(&mut reader).contains("RequestStats"));
+            write_lines(&mut stream, &[r#"{"Stats":{"presses":3}}"#]);
+            read_msg(&mut reader);
+        });
+        let options = Options {
+            reconnect: Some(Reconnect {
+                delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(10),
+                attempts: Some(3),
+            }),
+            ..Options::default()
+        };
+        let mut client = Client::connect(address, options).unwrap();
+        let stats = client.query(ClientMessage::RequestStats {});
+        assert!(matches!(stats, Ok(ServerMessage::Stats { presses: 3, .. })));
+        assert!(matches!(
+            client.next_event(Some(Duration::ZERO)),
+            Ok(Some(Event::Reconnected))
+        ));
+    }
+
+    #[test]
+    fn udp_authenticates_every_request() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = Address::Udp(socket.local_addr().unwrap().to_string());
+        std::thread::spawn(move || {
+            let mut buf = [0; 1024];
+            for reply in [HELLO_OK, r#"{"CurrentLayerName":{"name":"base"}}"#] {
+                let (n, peer) = socket.recv_from(&mut buf).unwrap();
+                let datagram = std::str::from_utf8(&buf[..n]).unwrap();
+                assert!(
+                    datagram.starts_with(r#"{"Authenticate":{"token":"t"}}"#),
+                    "{datagram}"
+                );
+                socket.send_to(b"{\"status\":\"Ok\"}\n", peer).unwrap();
+                socket
+                    .send_to(format!("{reply}\n").as_bytes(), peer)
+                    .unwrap();
+            }
+        });
+        let options = Options {
+            token: Some("t".into()),
+            ..Options::default()
+        };
+        let mut client = Client::connect(address, options).unwrap();
+        let name = client.query(ClientMessage::RequestCurrentLayerName {});
+        assert!(matches!(name, Ok(ServerMessage::CurrentLayerName { name }) if name == "base"));
+        assert!(matches!(
+            client.next_event(None),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
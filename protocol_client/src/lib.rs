@@ -0,0 +1,318 @@
+//! Kanata Protocol Client
+//!
+//! Clients for the protocol of the kanata TCP server, which is defined by
+//! [`kanata_tcp_protocol`]. They connect to a TCP, UDP or Unix socket listener, authenticate with
+//! the token of the listener, say `Hello`, and tell the replies of requests apart from the
+//! notifications that kanata sends at any time, which are kept as [`Event`]s until they are read.
+//!
+//! [`blocking::Client`] blocks the calling thread. With the `tokio` feature,
+//! [`asynchronous::Client`] has the same methods as `async fn`s.
+//!
+//! ```no_run
+//! use kanata_protocol_client::{blocking::Client, ClientMessage, Options};
+//!
+//! let mut client = Client::connect("127.0.0.1:5829".parse()?, Options::default())?;
+//! let names = client.query(ClientMessage::RequestLayerNames {})?;
+//! println!("{names:?}");
+//! client.command(ClientMessage::ChangeLayer { new: "nav".into() })?;
+//! client.subscribe(["LayerChange"]);
+//! while let Some(event) = client.next_event(None)? {
+//!     println!("{event:?}");
+//! }
+//! # Ok::<(), kanata_protocol_client::Error>(())
+//! ```
+//!
+//! Noise listeners, WebSocket and MQTT are not supported.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub use kanata_tcp_protocol::{
+    self as protocol, ClientMessage, Framing, ServerMessage, ServerResponse,
+};
+
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+pub mod blocking;
+mod session;
+
+/// The address of a listener of kanata, in the format of `--listen`:
+/// `[tcp:|udp:|unix:]ADDRESS`, where a TCP or UDP address is a port on localhost or `IP:PORT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Tcp(String),
+    Udp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let host_port = |address: &str| match address.parse::<u16>() {
+            Ok(port) => format!("127.0.0.1:{port}"),
+            Err(_) => address.to_owned(),
+        };
+        let address = match s.split_once(':') {
+            Some(("tcp", address)) => Self::Tcp(host_port(address)),
+            Some(("udp", address)) => Self::Udp(host_port(address)),
+            Some(("unix", path)) => Self::Unix(path.into()),
+            _ => Self::Tcp(host_port(s)),
+        };
+        match &address {
+            Address::Tcp(a) | Address::Udp(a) if a.is_empty() => {
+                Err(Error::Address(format!("missing address in {s}")))
+            }
+            Address::Unix(_) if cfg!(not(unix)) => Err(Error::Address(format!(
+                "unix sockets are not supported on this platform: {s}"
+            ))),
+            _ => Ok(address),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "tcp:{address}"),
+            Address::Udp(address) => write!(f, "udp:{address}"),
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// How a client connects.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The token of the listener, which is sent with `Authenticate` on every connection.
+    pub token: Option<String>,
+    /// The name of the client in `Hello`, e.g. the name of the program, which kanata records in
+    /// its audit log.
+    pub client_name: Option<String>,
+    /// The framing to switch to after `Hello`. UDP always uses newlines.
+    pub framing: Framing,
+    /// How long to wait for the connection and for each reply.
+    pub timeout: Duration,
+    /// How to connect again when the connection is lost, or None to return
+    /// [`Error::Disconnected`].
+    pub reconnect: Option<Reconnect>,
+    /// The kinds of notifications to keep, e.g. `LayerChange`, or None to keep every kind. See
+    /// [`blocking::Client::subscribe`].
+    pub subscriptions: Option<BTreeSet<String>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            token: None,
+            client_name: None,
+            framing: Framing::Newline,
+            timeout: Duration::from_secs(5),
+            reconnect: None,
+            subscriptions: None,
+        }
+    }
+}
+
+/// When to connect again after the connection is lost. The delay doubles after each failed
+/// attempt, up to `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reconnect {
+    pub delay: Duration,
+    pub max_delay: Duration,
+    /// The number of attempts before giving up, or None to try forever.
+    pub attempts: Option<u32>,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            attempts: None,
+        }
+    }
+}
+
+impl Reconnect {
+    /// The delay before the attempt, counting from 0.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay)
+    }
+
+    fn gives_up(&self, attempt: u32) -> bool {
+        self.attempts.is_some_and(|attempts| attempt >= attempts)
+    }
+}
+
+/// What the server said in `HelloOk`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub version: String,
+    pub protocol: u8,
+    pub capabilities: Vec<String>,
+    pub seat: Option<String>,
+}
+
+impl ServerInfo {
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/// Something that the server sent that is not the reply of a request.
+#[derive(Debug)]
+pub enum Event {
+    /// A notification, e.g. `LayerChange`.
+    Message(ServerMessage),
+    /// A response to a message that is usually not answered, e.g. `Forbidden` for `ChangeLayer`
+    /// from a read-only client.
+    Response(ServerResponse),
+    /// The connection was lost and is open again. Notifications may have been missed meanwhile.
+    Reconnected,
+}
+
+/// The reply to a request.
+#[derive(Debug)]
+pub enum Reply {
+    Response(ServerResponse),
+    Message(ServerMessage),
+}
+
+impl Reply {
+    /// Returns the message of the reply, or the error that the server replied with.
+    pub fn into_result(self) -> Result<Option<ServerMessage>, Error> {
+        match self {
+            Reply::Response(ServerResponse::Ok) => Ok(None),
+            Reply::Response(ServerResponse::Error { msg })
+            | Reply::Message(ServerMessage::Error { msg }) => Err(Error::Server(msg)),
+            Reply::Response(ServerResponse::Forbidden { command, msg }) => {
+                Err(Error::Forbidden { command, msg })
+            }
+            Reply::Message(ServerMessage::ReloadResult {
+                ok: false,
+                timeout_ms,
+            }) => Err(Error::Server(match timeout_ms {
+                Some(ms) => format!("the reload did not complete within {ms} ms"),
+                None => "the reload failed".to_owned(),
+            })),
+            Reply::Message(msg) => Ok(Some(msg)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Address(String),
+    /// The server sent something that is not a message of the protocol.
+    InvalidMessage(String),
+    /// The server did not accept the token.
+    Auth(String),
+    /// The server replied with an error.
+    Server(String),
+    /// The client is read-only and the command changes the state of kanata.
+    Forbidden {
+        command: String,
+        msg: String,
+    },
+    Timeout,
+    /// The connection was lost, and reconnecting is disabled or gave up. A command that was sent
+    /// while the connection was lost may or may not have been run.
+    Disconnected,
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Address(msg) => write!(f, "invalid address: {msg}"),
+            Error::InvalidMessage(msg) => write!(f, "the server sent an invalid message: {msg}"),
+            Error::Auth(msg) => write!(f, "authentication failed: {msg}"),
+            Error::Server(msg) => write!(f, "{msg}"),
+            Error::Forbidden { command, msg } => write!(f, "{command} is forbidden: {msg}"),
+            Error::Timeout => write!(f, "the server did not reply in time"),
+            Error::Disconnected => write!(f, "the connection to the server was lost"),
+            Error::Unsupported(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => Error::Timeout,
+            std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::BrokenPipe
+            | std::io::ErrorKind::UnexpectedEof => Error::Disconnected,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl Error {
+    /// Whether connecting again may help.
+    fn is_disconnect(&self) -> bool {
+        matches!(self, Error::Disconnected | Error::Io(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_address() {
+        assert_eq!(
+            "5829".parse::<Address>().unwrap(),
+            Address::Tcp("127.0.0.1:5829".into())
+        );
+        assert_eq!(
+            "tcp:192.168.1.5:5829".parse::<Address>().unwrap(),
+            Address::Tcp("192.168.1.5:5829".into())
+        );
+        assert_eq!(
+            "udp:5830".parse::<Address>().unwrap(),
+            Address::Udp("127.0.0.1:5830".into())
+        );
+        assert_eq!(
+            "localhost:5829".parse::<Address>().unwrap(),
+            Address::Tcp("localhost:5829".into())
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            "unix:/tmp/k.sock".parse::<Address>().unwrap(),
+            Address::Unix("/tmp/k.sock".into())
+        );
+        assert!("tcp:".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_max() {
+        let reconnect = Reconnect {
+            delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            attempts: Some(4),
+        };
+        let delays: Vec<_> = (0..4).map(|a| reconnect.delay(a).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 500]);
+        assert!(!reconnect.gives_up(3));
+        assert!(reconnect.gives_up(4));
+    }
+}
@@ -0,0 +1,443 @@
+//! The parts of a client that do not depend on how it reads and writes: framing, telling replies
+//! apart from notifications, and the queue of events.
+
+use super::*;
+use std::collections::VecDeque;
+
+/// Server messages that are longer than this are treated as invalid.
+const MAX_FRAME_LEN: usize = 16 << 20;
+
+/// What a client message is answered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Expect {
+    /// Nothing, unless it fails in some way.
+    Nothing,
+    Response,
+    /// A response and, if it is Ok, a `ReloadResult` once the reload completed.
+    ReloadResult,
+    /// The server message of the kind, or an `Error` message if `or_error`.
+    Message {
+        kind: &'static str,
+        or_error: bool,
+    },
+}
+
+impl Expect {
+    pub(crate) fn of(msg: &ClientMessage) -> Self {
+        let message = |kind| Expect::Message {
+            kind,
+            or_error: false,
+        };
+        match msg {
+            ClientMessage::ChangeLayer { .. }
+            | ClientMessage::ActOnFakeKey { .. }
+            | ClientMessage::SetMouse { .. } => Expect::Nothing,
+            ClientMessage::RequestLayerNames {} => message("LayerNames"),
+            ClientMessage::RequestFakeKeyNames {} => message("FakeKeyNames"),
+            ClientMessage::RequestCurrentLayerInfo {} => message("CurrentLayerInfo"),
+            ClientMessage::RequestCurrentLayerName {} => message("CurrentLayerName"),
+            ClientMessage::RequestStats {} => message("Stats"),
+            ClientMessage::RequestNgramStats {} => Expect::Message {
+                kind: "NgramStats",
+                or_error: true,
+            },
+            ClientMessage::RequestMousePosition {} => Expect::Message {
+                kind: "MousePosition",
+                or_error: true,
+            },
+            ClientMessage::RequestTapHolds {} => message("TapHolds"),
+            ClientMessage::RequestLayerMeta {} => message("LayerMeta"),
+            ClientMessage::RequestConfigMeta {} => message("ConfigMeta"),
+            ClientMessage::RequestKeyDocs {} => message("KeyDocs"),
+            ClientMessage::SubscribeCmdOutput { .. } => Expect::Response,
+            ClientMessage::Hello { .. } => message("HelloOk"),
+            ClientMessage::Reload { wait, .. }
+            | ClientMessage::ReloadNext { wait, .. }
+            | ClientMessage::ReloadPrev { wait, .. }
+            | ClientMessage::ReloadNum { wait, .. }
+            | ClientMessage::ReloadFile { wait, .. }
+            | ClientMessage::ReloadRollback { wait, .. }
+            | ClientMessage::ReloadVersion { wait, .. }
+                if *wait == Some(true) =>
+            {
+                Expect::ReloadResult
+            }
+            ClientMessage::Reload { .. }
+            | ClientMessage::ReloadNext { .. }
+            | ClientMessage::ReloadPrev { .. }
+            | ClientMessage::ReloadNum { .. }
+            | ClientMessage::ReloadFile { .. }
+            | ClientMessage::ReloadTry { .. }
+            | ClientMessage::ConfirmReload {}
+            | ClientMessage::ReloadRollback { .. }
+            | ClientMessage::ReloadVersion { .. }
+            | ClientMessage::Authenticate { .. }
+            | ClientMessage::RevokeToken { .. }
+            | ClientMessage::SetLayerFallback { .. }
+            | ClientMessage::SetLayerAlias { .. }
+            | ClientMessage::SetActiveApp { .. }
+            | ClientMessage::SetLogLevel { .. }
+            | ClientMessage::SetMacroDelayScale { .. }
+            | ClientMessage::SetTapHold { .. }
+            | ClientMessage::SetTrainingMode { .. }
+            | ClientMessage::CancelCmd { .. } => Expect::Response,
+        }
+    }
+}
+
+/// A message from the server.
+#[derive(Debug)]
+pub(crate) enum Incoming {
+    Response(ServerResponse),
+    Message {
+        kind: String,
+        msg: ServerMessage,
+    },
+    /// A server message of a newer protocol, which is skipped.
+    Unknown,
+}
+
+impl Incoming {
+    fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let invalid = |e: serde_json::Error| Error::InvalidMessage(e.to_string());
+        let value: serde_json::Value = serde_json::from_slice(bytes).map_err(invalid)?;
+        if value.get("status").is_some() {
+            return serde_json::from_value(value)
+                .map(Incoming::Response)
+                .map_err(invalid);
+        }
+        let kind = match value.as_object().and_then(|object| object.keys().next()) {
+            Some(kind) => kind.clone(),
+            None => return Err(Error::InvalidMessage(value.to_string())),
+        };
+        Ok(match serde_json::from_value(value) {
+            Ok(msg) => Incoming::Message { kind, msg },
+            Err(_) => Incoming::Unknown,
+        })
+    }
+}
+
+/// Splits what the server sent into messages.
+#[derive(Debug, Default)]
+pub(crate) struct Decoder {
+    buf: Vec<u8>,
+    framing: Framing,
+}
+
+impl Decoder {
+    pub(crate) fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns the next whole message, if there is one.
+    pub(crate) fn next(&mut self) -> Result<Option<Incoming>, Error> {
+        let frame = match self.framing {
+            Framing::Newline => {
+                let Some(end) = self.buf.iter().position(|b| *b == b'\n') else {
+                    return Ok(None);
+                };
+                let line: Vec<u8> = self.buf.drain(..=end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    return self.next();
+                }
+                line
+            }
+            Framing::LengthPrefixed => {
+                let Some(len) = self.buf.first_chunk::<4>() else {
+                    return Ok(None);
+                };
+                let len = u32::from_be_bytes(*len) as usize;
+                if len > MAX_FRAME_LEN {
+                    return Err(Error::InvalidMessage(format!(
+                        "the message length {len} is too large"
+                    )));
+                }
+                if self.buf.len() < 4 + len {
+                    return Ok(None);
+                }
+                self.buf.drain(..4 + len).skip(4).collect()
+            }
+        };
+        let incoming = Incoming::parse(&frame)?;
+        // The server switches the framing right after `HelloOk`.
+        if let Incoming::Message {
+            msg:
+                ServerMessage::HelloOk {
+                    framing: Some(framing),
+                    ..
+                },
+            ..
+        } = &incoming
+        {
+            self.framing = *framing;
+        }
+        Ok(Some(incoming))
+    }
+}
+
+/// Returns the message with the framing.
+pub(crate) fn encode(msg: &ClientMessage, framing: Framing) -> Vec<u8> {
+    let json = serde_json::to_vec(msg).expect("ClientMessage should serialize");
+    match framing {
+        Framing::Newline => {
+            let mut bytes = json;
+            bytes.push(b'\n');
+            bytes
+        }
+        Framing::LengthPrefixed => {
+            let mut bytes = (json.len() as u32).to_be_bytes().to_vec();
+            bytes.extend(json);
+            bytes
+        }
+    }
+}
+
+/// The state of a client that outlives its connections.
+#[derive(Debug)]
+pub(crate) struct Session {
+    pub(crate) decoder: Decoder,
+    pub(crate) events: VecDeque<Event>,
+    pub(crate) subscriptions: Option<BTreeSet<String>>,
+}
+
+impl Session {
+    pub(crate) fn new(subscriptions: Option<BTreeSet<String>>) -> Self {
+        Self {
+            decoder: Decoder::default(),
+            events: VecDeque::new(),
+            subscriptions,
+        }
+    }
+
+    /// Starts over for a new connection, keeping the events.
+    pub(crate) fn reset(&mut self) {
+        self.decoder = Decoder::default();
+    }
+
+    /// Handles a message while waiting for a reply of `waiting`, if any, and returns the reply
+    /// once it is complete. Other messages are queued as events.
+    pub(crate) fn receive(
+        &mut self,
+        incoming: Incoming,
+        waiting: &mut Option<Expect>,
+    ) -> Option<Reply> {
+        match (incoming, *waiting) {
+            (Incoming::Response(ServerResponse::Ok), Some(Expect::ReloadResult)) => {
+                *waiting = Some(Expect::Message {
+                    kind: "ReloadResult",
+                    or_error: false,
+                });
+                None
+            }
+            // Errors such as Forbidden or a failed authentication answer any request.
+            (
+                Incoming::Response(response),
+                Some(Expect::Response | Expect::ReloadResult | Expect::Message { .. }),
+            ) => {
+                *waiting = None;
+                Some(Reply::Response(response))
+            }
+            (
+                Incoming::Message { kind, msg },
+                Some(Expect::Message {
+                    kind: expected,
+                    or_error,
+                }),
+            ) if kind == expected || (or_error && kind == "Error") => {
+                *waiting = None;
+                Some(Reply::Message(msg))
+            }
+            (Incoming::Response(response), _) => {
+                self.events.push_back(Event::Response(response));
+                None
+            }
+            (Incoming::Message { kind, msg }, _) => {
+                if self
+                    .subscriptions
+                    .as_ref()
+                    .is_none_or(|kinds| kinds.contains(&kind))
+                {
+                    self.events.push_back(Event::Message(msg));
+                }
+                None
+            }
+            (Incoming::Unknown, _) => None,
+        }
+    }
+}
+
+/// Returns the server info of a `HelloOk` reply.
+pub(crate) fn server_info(reply: Reply) -> Result<ServerInfo, Error> {
+    match reply.into_result()? {
+        Some(ServerMessage::HelloOk {
+            version,
+            protocol,
+            capabilities,
+            seat,
+            ..
+        }) => Ok(ServerInfo {
+            version,
+            protocol,
+            capabilities,
+            seat,
+        }),
+        reply => Err(Error::InvalidMessage(format!(
+            "expected HelloOk, found {reply:?}"
+        ))),
+    }
+}
+
+/// Returns an error unless the reply of `Authenticate` is Ok.
+pub(crate) fn check_auth(reply: Reply) -> Result<(), Error> {
+    match reply {
+        Reply::Response(ServerResponse::Ok) => Ok(()),
+        Reply::Response(ServerResponse::Error { msg }) => Err(Error::Auth(msg)),
+        reply => Err(Error::InvalidMessage(format!(
+            "expected a response to Authenticate, found {reply:?}"
+        ))),
+    }
+}
+
+/// The messages that start a connection: `Authenticate` if there is a token, then `Hello`.
+pub(crate) fn handshake(options: &Options, stream: bool) -> Vec<ClientMessage> {
+    let mut msgs = vec![];
+    if let Some(token) = &options.token {
+        msgs.push(ClientMessage::Authenticate {
+            token: token.clone(),
+        });
+    }
+    msgs.push(ClientMessage::Hello {
+        framing: (stream && options.framing != Framing::Newline).then_some(options.framing),
+        client_name: options.client_name.clone(),
+    });
+    msgs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(decoder: &mut Decoder) -> Vec<Incoming> {
+        std::iter::from_fn(|| decoder.next().unwrap()).collect()
+    }
+
+    #[test]
+    fn decoder_splits_lines_and_switches_framing() {
+        let mut decoder = Decoder::default();
+        decoder.push(b"{\"LayerChange\":{\"new\":\"base\"}}\n{\"status\":");
+        assert!(matches!(
+            decode_all(&mut decoder)[..],
+            [Incoming::Message { ref kind, .. }] if kind == "LayerChange"
+        ));
+        let hello = br#"{"HelloOk":{"version":"1.12.0","protocol":1,"capabilities":[],"framing":"length-prefixed"}}"#;
+        decoder.push(b"\"Ok\"}\n");
+        decoder.push(hello);
+        decoder.push(b"\n");
+        let msg = br#"{"LayerChange":{"new":"nav"}}"#;
+        decoder.push(&(msg.len() as u32).to_be_bytes());
+        decoder.push(&msg[..5]);
+        let incoming = decode_all(&mut decoder);
+        assert!(matches!(
+            incoming[..],
+            [
+                Incoming::Response(ServerResponse::Ok),
+                Incoming::Message { ref kind, .. }
+            ] if kind == "HelloOk"
+        ));
+        decoder.push(&msg[5..]);
+        assert!(matches!(
+            decode_all(&mut decoder)[..],
+            [Incoming::Message {
+                msg: ServerMessage::LayerChange { ref new },
+                ..
+            }] if new == "nav"
+        ));
+    }
+
+    #[test]
+    fn decoder_skips_messages_of_newer_protocols() {
+        let mut decoder = Decoder::default();
+        decoder.push(b"{\"FromTheFuture\":{}}\n");
+        assert!(matches!(decoder.next(), Ok(Some(Incoming::Unknown))));
+        decoder.push(b"not json\n");
+        assert!(matches!(decoder.next(), Err(Error::InvalidMessage(_))));
+    }
+
+    #[test]
+    fn encode_frames_messages() {
+        let msg = ClientMessage::RequestLayerNames {};
+        assert_eq!(
+            encode(&msg, Framing::Newline),
+            b"{\"RequestLayerNames\":{}}\n"
+        );
+        assert_eq!(
+            encode(&msg, Framing::LengthPrefixed),
+            b"\0\0\0\x18{\"RequestLayerNames\":{}}"
+        );
+    }
+
+    #[test]
+    fn session_tells_replies_from_notifications() {
+        let mut session = Session::new(Some(BTreeSet::from(["LayerChange".to_owned()])));
+        let incoming = |json: &str| {
+            let mut decoder = Decoder::default();
+            decoder.push(json.as_bytes());
+            decoder.push(b"\n");
+            decoder.next().unwrap().unwrap()
+        };
+        let mut waiting = Some(Expect::of(&ClientMessage::RequestLayerNames {}));
+        assert!(
+            session
+                .receive(incoming(r#"{"LayerChange":{"new":"nav"}}"#), &mut waiting)
+                .is_none()
+        );
+        assert!(
+            session
+                .receive(incoming(r#"{"TapActivated":{"key":"a"}}"#), &mut waiting)
+                .is_none()
+        );
+        let reply = session.receive(
+            incoming(r#"{"LayerNames":{"names":["base"]}}"#),
+            &mut waiting,
+        );
+        assert!(matches!(
+            reply,
+            Some(Reply::Message(ServerMessage::LayerNames { .. }))
+        ));
+        assert_eq!(waiting, None);
+        // Only the subscribed notification is kept.
+        assert!(matches!(
+            session.events.drain(..).collect::<Vec<_>>()[..],
+            [Event::Message(ServerMessage::LayerChange { .. })]
+        ));
+
+        let reload = ClientMessage::Reload {
+            wait: Some(true),
+            timeout_ms: None,
+        };
+        let mut waiting = Some(Expect::of(&reload));
+        assert!(
+            session
+                .receive(incoming(r#"{"status":"Ok"}"#), &mut waiting)
+                .is_none()
+        );
+        let reply = session.receive(incoming(r#"{"ReloadResult":{"ok":true}}"#), &mut waiting);
+        assert!(matches!(
+            reply,
+            Some(Reply::Message(ServerMessage::ReloadResult { ok: true, .. }))
+        ));
+
+        // A response without a request is an event.
+        let mut waiting = None;
+        let forbidden = r#"{"status":"Forbidden","command":"ChangeLayer","msg":"read-only"}"#;
+        assert!(session.receive(incoming(forbidden), &mut waiting).is_none());
+        assert!(matches!(
+            session.events.pop_front(),
+            Some(Event::Response(ServerResponse::Forbidden { .. }))
+        ));
+    }
+}
@@ -0,0 +1,344 @@
+//! Python bindings for kanata.
+//!
+//! - `check_config` and `layer_names` parse configurations.
+//! - `Simulator` runs the remapping engine on input given by Python, e.g. to count the keystrokes
+//!   that a layout needs for a corpus of text.
+//! - `Client` talks to a running kanata over the TCP protocol.
+
+use kanata_parser::custom_action::FakeKeyAction;
+use kanata_state_machine::engine::Engine;
+use kanata_state_machine::kanata::handle_fakekey_action;
+use kanata_state_machine::oskbd::{KeyEvent, KeyValue, OutputEvent};
+use kanata_state_machine::{FAKE_KEY_ROW, Kanata, str_to_oscode};
+use kanata_tcp_protocol::{ClientMessage, FakeKeyActionMessage};
+use pyo3::IntoPyObjectExt;
+use pyo3::exceptions::{PyConnectionError, PyValueError};
+use pyo3::prelude::*;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn value_err(e: impl std::fmt::Debug) -> PyErr {
+    PyValueError::new_err(format!("{e:?}"))
+}
+
+fn conn_err(e: impl std::fmt::Display) -> PyErr {
+    PyConnectionError::new_err(e.to_string())
+}
+
+/// Raises ValueError if the configuration is not valid.
+#[pyfunction]
+fn check_config(cfg: &str) -> PyResult<()> {
+    Kanata::new_from_str(cfg, Default::default()).map_err(value_err)?;
+    Ok(())
+}
+
+/// Returns the names of the layers in the configuration.
+#[pyfunction]
+fn layer_names(cfg: &str) -> PyResult<Vec<String>> {
+    let k = Kanata::new_from_str(cfg, Default::default()).map_err(value_err)?;
+    Ok(k.layer_info.iter().map(|l| l.name.clone()).collect())
+}
+
+/// Runs a configuration on simulated input.
+///
+/// Outputs are tuples where the first item is the kind of output, e.g. `("press", "a")`,
+/// `("release", "a")`, `("unicode", "é")` or `("mouse_move", "right", 3)`.
+#[pyclass(unsendable)]
+struct Simulator {
+    engine: Engine,
+    outputs: Arc<Mutex<Vec<OutputEvent>>>,
+}
+
+fn key(name: &str) -> PyResult<kanata_parser::keys::OsCode> {
+    str_to_oscode(name).ok_or_else(|| PyValueError::new_err(format!("unknown key: {name}")))
+}
+
+fn key_name(osc: kanata_parser::keys::OsCode) -> String {
+    osc.to_string().to_lowercase()
+}
+
+fn output_to_py(py: Python<'_>, event: OutputEvent) -> PyResult<PyObject> {
+    use kanata_parser::cfg::SoundCue;
+    let lower = |s: String| s.to_lowercase();
+    match event {
+        OutputEvent::Key { code, value } => {
+            let kind = match value {
+                KeyValue::Press => "press",
+                KeyValue::Release => "release",
+                KeyValue::Repeat => "repeat",
+                KeyValue::Tap => "tap",
+                KeyValue::WakeUp => "wakeup",
+            };
+            (kind, key_name(code)).into_py_any(py)
+        }
+        OutputEvent::Code { code, value } => {
+            ("code", code, lower(format!("{value:?}"))).into_py_any(py)
+        }
+        OutputEvent::Unicode(c) => ("unicode", c).into_py_any(py),
+        OutputEvent::UnicodeStr(text) => ("unicode", text).into_py_any(py),
+        OutputEvent::MousePress(btn) => ("mouse_press", lower(format!("{btn:?}"))).into_py_any(py),
+        OutputEvent::MouseRelease(btn) => {
+            ("mouse_release", lower(format!("{btn:?}"))).into_py_any(py)
+        }
+        OutputEvent::Scroll {
+            direction,
+            distance,
+        } => ("scroll", lower(format!("{direction:?}")), distance).into_py_any(py),
+        OutputEvent::MouseMove {
+            direction,
+            distance,
+        } => ("mouse_move", lower(format!("{direction:?}")), distance).into_py_any(py),
+        OutputEvent::MouseSet { x, y } => ("mouse_set", x, y).into_py_any(py),
+        OutputEvent::MouseWarp { monitor, x, y } => ("mouse_warp", monitor, x, y).into_py_any(py),
+        OutputEvent::Midi(msg) => ("midi", msg.to_vec()).into_py_any(py),
+        OutputEvent::Obs(action) => ("obs", action.name(), action.args()).into_py_any(py),
+        OutputEvent::OpenRgb(rgb) => ("openrgb", rgb.to_vec()).into_py_any(py),
+        OutputEvent::Webhook { name, body } => ("webhook", name, body.to_string()).into_py_any(py),
+        OutputEvent::Notify { title, body } => ("notify", title, body).into_py_any(py),
+        OutputEvent::Mpris(action) => {
+            ("mpris", action.command.name(), action.player).into_py_any(py)
+        }
+        OutputEvent::OsLayout(layout) => ("os_layout", layout).into_py_any(py),
+        OutputEvent::Secret(name) => ("secret", name).into_py_any(py),
+        OutputEvent::HidUsage { usage, value } => (
+            "hid_usage",
+            usage.page_id(),
+            usage.usage,
+            lower(format!("{value:?}")),
+        )
+            .into_py_any(py),
+        OutputEvent::MouseToward(action) => (
+            "mouse_toward",
+            action.monitor.map(|monitor| monitor + 1),
+            f64::from(action.anchor_x) / 100.0,
+            f64::from(action.anchor_y) / 100.0,
+            action.percent,
+        )
+            .into_py_any(py),
+        OutputEvent::Sound(cue) => match cue {
+            SoundCue::Silent | SoundCue::Beep => ("sound", "beep").into_py_any(py),
+            SoundCue::File(path) => ("sound", path).into_py_any(py),
+        },
+    }
+}
+
+impl Simulator {
+    fn input(&mut self, name: &str, value: KeyValue) -> PyResult<()> {
+        self.engine
+            .handle_input(KeyEvent::new(key(name)?, value))
+            .map_err(value_err)
+    }
+}
+
+#[pymethods]
+impl Simulator {
+    #[new]
+    fn new(cfg: &str) -> PyResult<Self> {
+        let outputs = Arc::new(Mutex::new(vec![]));
+        let sink_outputs = outputs.clone();
+        let engine = Engine::new(cfg, move |ev| {
+            sink_outputs
+                .lock()
+                .expect("output lock is not poisoned")
+                .push(ev)
+        })
+        .map_err(value_err)?;
+        Ok(Self { engine, outputs })
+    }
+
+    fn press(&mut self, key: &str) -> PyResult<()> {
+        self.input(key, KeyValue::Press)
+    }
+
+    fn release(&mut self, key: &str) -> PyResult<()> {
+        self.input(key, KeyValue::Release)
+    }
+
+    fn repeat(&mut self, key: &str) -> PyResult<()> {
+        self.input(key, KeyValue::Repeat)
+    }
+
+    /// Advances time by `ms` milliseconds. Returns True if the engine is idle.
+    #[pyo3(signature = (ms=1))]
+    fn tick(&mut self, ms: u16) -> PyResult<bool> {
+        self.engine.tick(ms).map_err(value_err)
+    }
+
+    /// Runs simulator input in the same format as the kanata simulator,
+    /// e.g. `"d:a t:50 u:a t:50"`.
+    fn run(&mut self, sim: &str) -> PyResult<()> {
+        for item in sim.split_whitespace() {
+            let (kind, val) = item
+                .split_once(':')
+                .ok_or_else(|| PyValueError::new_err(format!("invalid item: {item}")))?;
+            match kind {
+                "tick" | "🕐" | "t" => {
+                    let ms = val
+                        .parse::<u64>()
+                        .map_err(|e| PyValueError::new_err(format!("invalid tick {val}: {e}")))?;
+                    let mut remaining = ms;
+                    while remaining > 0 {
+                        let step = remaining.min(u64::from(u16::MAX));
+                        self.tick(step as u16)?;
+                        remaining -= step;
+                    }
+                }
+                "press" | "↓" | "d" | "down" => self.press(val)?,
+                "release" | "↑" | "u" | "up" => self.release(val)?,
+                "repeat" | "⟳" | "r" => self.repeat(val)?,
+                "vk" | "fakekey" | "virtualkey" | "🎭" => {
+                    let (name, action) = match val.split_once(':') {
+                        Some((name, action)) => (name, action),
+                        None => (val, "press"),
+                    };
+                    let action = match action {
+                        "press" | "p" => FakeKeyAction::Press,
+                        "release" => FakeKeyAction::Release,
+                        "tap" | "t" => FakeKeyAction::Tap,
+                        "toggle" | "g" => FakeKeyAction::Toggle,
+                        _ => {
+                            return Err(PyValueError::new_err(format!(
+                                "unknown virtual key action: {action}"
+                            )));
+                        }
+                    };
+                    let k = self.engine.kanata();
+                    let index = *k.virtual_keys.get(name).ok_or_else(|| {
+                        PyValueError::new_err(format!("unknown virtual key: {name}"))
+                    })?;
+                    handle_fakekey_action(action, k.layout.bm(), FAKE_KEY_ROW, index as u16);
+                }
+                "ls" | "layer-switch" | "🔀" => {
+                    let k = self.engine.kanata();
+                    let layer = k
+                        .layer_info
+                        .iter()
+                        .position(|l| l.name == val)
+                        .ok_or_else(|| PyValueError::new_err(format!("unknown layer: {val}")))?;
+                    k.layout.bm().set_default_layer(layer);
+                }
+                _ => return Err(PyValueError::new_err(format!("invalid action: {kind}"))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the outputs since the last call.
+    fn outputs(&mut self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        let outputs = std::mem::take(&mut *self.outputs.lock().expect("not poisoned"));
+        outputs.into_iter().map(|ev| output_to_py(py, ev)).collect()
+    }
+
+    /// Returns the name of the active layer.
+    fn layer(&mut self) -> String {
+        let k = self.engine.kanata();
+        let idx = k.layout.b().current_layer();
+        k.layer_info[idx].name.clone()
+    }
+}
+
+/// A connection to the TCP server of a running kanata.
+///
+/// Messages are dicts in the JSON format of the TCP protocol,
+/// e.g. `{"ChangeLayer": {"new": "nav"}}`.
+#[pyclass]
+struct Client {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+#[pymethods]
+impl Client {
+    /// Connects to `address`, which is either a port on localhost or `host:port`.
+    #[new]
+    #[pyo3(signature = (address, timeout=None))]
+    fn new(address: &str, timeout: Option<f64>) -> PyResult<Self> {
+        let address = match address.parse::<u16>() {
+            Ok(port) => format!("127.0.0.1:{port}"),
+            Err(_) => address.to_owned(),
+        };
+        let writer = TcpStream::connect(&address).map_err(conn_err)?;
+        writer
+            .set_read_timeout(timeout.map(Duration::from_secs_f64))
+            .map_err(conn_err)?;
+        let reader = BufReader::new(writer.try_clone().map_err(conn_err)?);
+        Ok(Self { writer, reader })
+    }
+
+    /// Sends a client message. Raises ValueError if it is not a valid message.
+    fn send(&mut self, py: Python<'_>, message: Bound<'_, PyAny>) -> PyResult<()> {
+        let json: String = py
+            .import("json")?
+            .call_method1("dumps", (message,))?
+            .extract()?;
+        let message: ClientMessage = json.parse().map_err(value_err)?;
+        self.send_message(&message)
+    }
+
+    /// Receives the next message from the server, which may be a notification such as
+    /// `{"LayerChange": {"new": "nav"}}` or a response such as `{"status": "Ok"}`.
+    fn recv(&mut self, py: Python<'_>) -> PyResult<PyObject> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).map_err(conn_err)? == 0 {
+            return Err(conn_err("connection closed"));
+        }
+        Ok(py
+            .import("json")?
+            .call_method1("loads", (line.trim_end(),))?
+            .unbind())
+    }
+
+    /// Requests the server version and capabilities. The reply is a `HelloOk` message.
+    fn hello(&mut self) -> PyResult<()> {
+        self.send_message(&ClientMessage::Hello {
+            framing: None,
+            client_name: None,
+        })
+    }
+
+    fn change_layer(&mut self, name: String) -> PyResult<()> {
+        self.send_message(&ClientMessage::ChangeLayer { new: name })
+    }
+
+    /// Requests the layer names. The reply is a `LayerNames` message.
+    fn request_layer_names(&mut self) -> PyResult<()> {
+        self.send_message(&ClientMessage::RequestLayerNames {})
+    }
+
+    /// Acts on a virtual key. `action` is one of "Press", "Release", "Tap" or "Toggle".
+    #[pyo3(signature = (name, action="Tap"))]
+    fn act_on_fake_key(&mut self, name: String, action: &str) -> PyResult<()> {
+        let action = match action {
+            "Press" => FakeKeyActionMessage::Press,
+            "Release" => FakeKeyActionMessage::Release,
+            "Tap" => FakeKeyActionMessage::Tap,
+            "Toggle" => FakeKeyActionMessage::Toggle,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "unknown virtual key action: {action}"
+                )));
+            }
+        };
+        self.send_message(&ClientMessage::ActOnFakeKey { name, action })
+    }
+}
+
+impl Client {
+    fn send_message(&mut self, message: &ClientMessage) -> PyResult<()> {
+        let mut msg = serde_json::to_vec(message).map_err(value_err)?;
+        msg.push(b'\n');
+        self.writer.write_all(&msg).map_err(conn_err)
+    }
+}
+
+#[pymodule]
+fn kanata(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(check_config, m)?)?;
+    m.add_function(wrap_pyfunction!(layer_names, m)?)?;
+    m.add_class::<Simulator>()?;
+    m.add_class::<Client>()?;
+    Ok(())
+}
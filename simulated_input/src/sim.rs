@@ -141,6 +141,7 @@ fn cli_init_fsim() -> Result<(ValidatedArgs, Vec<PathBuf>, Option<String>)> {
             #[cfg(any(target_os = "linux", target_os = "android"))]
             symlink_path: None,
             nodelay: true,
+            watch: false,
         },
         sim_paths,
         sim_appendix,
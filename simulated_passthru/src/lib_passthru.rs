@@ -53,6 +53,7 @@ fn cli_init(cfg_path: &str) -> Result<ValidatedArgs> {
         #[cfg(feature = "tcp_server")]
         tcp_server_address: None, //todo: any need in a dll?
         nodelay: true,
+        watch: false,
     })
 }
 
@@ -82,7 +83,7 @@ fn lib_impl(cfg_path: &str) -> Result<()> {
     // The reason for two different event loops is that the "event loop" only listens for keyboard
     // events, which it sends to the "processing loop". The processing loop handles keyboard events
     // while also maintaining `tick()` calls to keyberon.
-    let (tx, rx) = std::sync::mpsc::sync_channel(100);
+    let (tx, rx) = event_queue(100);
     let ntx = None;
     Kanata::start_processing_loop(cfg_arc.clone(), rx, ntx, args.nodelay); // 2 handles keyboard
     // events while also
@@ -0,0 +1,253 @@
+//! Use kanata's remapping engine as a library.
+//!
+//! The [`Engine`] takes input events and elapsed time from the application embedding it, instead
+//! of reading them from the OS, and passes outputs to an [`OutputSink`] instead of writing them to
+//! the OS.
+//!
+//! This module requires the `simulated_output` feature, which replaces the OS output backend.
+//! Embedders should depend on kanata with `default-features = false` and enable only the features
+//! they need in addition to it.
+//!
+//! ```
+//! use kanata_state_machine::engine::Engine;
+//! use kanata_state_machine::oskbd::{KeyEvent, KeyValue, OutputEvent};
+//! use kanata_state_machine::str_to_oscode;
+//!
+//! let (tx, rx) = std::sync::mpsc::channel();
+//! let mut engine = Engine::new("(defsrc a) (deflayer base b)", move |ev| {
+//!     let _ = tx.send(ev);
+//! })
+//! .unwrap();
+//! let a = str_to_oscode("a").unwrap();
+//! engine.handle_input(KeyEvent::new(a, KeyValue::Press)).unwrap();
+//! engine.tick(1).unwrap();
+//! assert_eq!(
+//!     rx.try_recv(),
+//!     Ok(OutputEvent::Key {
+//!         code: str_to_oscode("b").unwrap(),
+//!         value: KeyValue::Press,
+//!     })
+//! );
+//! ```
+//!
+//! For tests of a configuration, [`simulate`] runs a list of timed inputs to completion and
+//! returns the timed outputs.
+
+use anyhow::{Result, bail};
+use kanata_parser::cfg::builder::ConfigBuilder;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::kanata::PRESSED_KEYS;
+use crate::oskbd::{KeyEvent, KeyValue, OutputEvent, OutputSink};
+use crate::{Kanata, ValidatedArgs};
+
+/// A kanata instance driven by the embedding application.
+pub struct Engine {
+    kanata: Kanata,
+}
+
+impl Engine {
+    /// Parses the configuration and creates an engine that passes its outputs to `sink`.
+    pub fn new(cfg: &str, sink: impl OutputSink + 'static) -> Result<Self> {
+        Self::new_with_files(cfg, Default::default(), sink)
+    }
+
+    /// Loads the configuration file at `path`. Unlike [`Engine::new`], the files it includes are
+    /// read from disk.
+    pub fn from_file(path: &Path, sink: impl OutputSink + 'static) -> Result<Self> {
+        let mut kanata = Kanata::new(&ValidatedArgs {
+            paths: vec![path.to_owned()],
+            #[cfg(feature = "tcp_server")]
+            tcp_server_address: None,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            symlink_path: None,
+            nodelay: true,
+            watch: false,
+        })?;
+        kanata.kbd_out.set_output_sink(Box::new(sink));
+        Ok(Self { kanata })
+    }
+
+    /// Creates an engine from a configuration built in code.
+    pub fn from_builder(cfg: &ConfigBuilder, sink: impl OutputSink + 'static) -> Result<Self> {
+        Self::new(&cfg.to_kbd(), sink)
+    }
+
+    /// Like [`Engine::new`], with the contents of files that the configuration includes, keyed by
+    /// file name.
+    pub fn new_with_files(
+        cfg: &str,
+        files: FxHashMap<String, String>,
+        sink: impl OutputSink + 'static,
+    ) -> Result<Self> {
+        let mut kanata = Kanata::new_from_str(cfg, files)?;
+        kanata.kbd_out.set_output_sink(Box::new(sink));
+        Ok(Self { kanata })
+    }
+
+    /// Processes an input event. Outputs are produced by this call or by later ticks.
+    pub fn handle_input(&mut self, event: KeyEvent) -> Result<()> {
+        match event.value {
+            KeyValue::Press => {
+                #[cfg(not(all(target_os = "windows", not(feature = "interception_driver"))))]
+                PRESSED_KEYS.lock().insert(event.code);
+                #[cfg(all(target_os = "windows", not(feature = "interception_driver")))]
+                PRESSED_KEYS
+                    .lock()
+                    .insert(event.code, web_time::Instant::now());
+            }
+            KeyValue::Release => {
+                PRESSED_KEYS.lock().remove(&event.code);
+            }
+            _ => {}
+        }
+        self.kanata.handle_input_event(&event)
+    }
+
+    /// Advances the engine by `ms` milliseconds.
+    ///
+    /// Returns true if the engine is idle, in which case the application does not need to call
+    /// this again until the next input event.
+    pub fn tick(&mut self, ms: u16) -> Result<bool> {
+        let mut idle = self.kanata.can_block_update_idle_waiting(0);
+        for _ in 0..ms {
+            self.kanata.tick_ms(1, &None)?;
+            crate::kanata::clock::advance_virtual_clock(1);
+            idle = self.kanata.can_block_update_idle_waiting(1);
+        }
+        Ok(idle)
+    }
+
+    /// Returns the underlying state, e.g. to inspect the active layer.
+    pub fn kanata(&mut self) -> &mut Kanata {
+        &mut self.kanata
+    }
+}
+
+/// An event at a time in milliseconds since the start of a simulation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timed<T> {
+    pub time: u64,
+    pub event: T,
+}
+
+/// The maximum number of milliseconds that [`simulate`] keeps running after the last input for
+/// the engine to become idle.
+pub const SIMULATION_SETTLE_MS: u64 = 60_000;
+
+/// Runs `inputs`, which must be ordered by time, on a new engine and returns the outputs with the
+/// times at which they happened. An input at time `t` is processed during millisecond `t`, so its
+/// outputs are at time `t` or later.
+///
+/// After the last input, the simulation continues until the engine is idle, e.g. until pending
+/// tap-hold actions have resolved, or for at most [`SIMULATION_SETTLE_MS`].
+///
+/// ```
+/// use kanata_state_machine::engine::{Timed, simulate};
+/// use kanata_state_machine::oskbd::{KeyEvent, KeyValue, OutputEvent};
+/// use kanata_state_machine::str_to_oscode;
+///
+/// let a = str_to_oscode("a").unwrap();
+/// let b = str_to_oscode("b").unwrap();
+/// let outputs = simulate(
+///     "(defsrc a) (deflayer base (tap-hold 200 200 a b))",
+///     &[
+///         Timed { time: 0, event: KeyEvent::new(a, KeyValue::Press) },
+///         Timed { time: 300, event: KeyEvent::new(a, KeyValue::Release) },
+///     ],
+/// )
+/// .unwrap();
+/// assert_eq!(
+///     outputs,
+///     [
+///         Timed { time: 200, event: OutputEvent::Key { code: b, value: KeyValue::Press } },
+///         Timed { time: 300, event: OutputEvent::Key { code: b, value: KeyValue::Release } },
+///     ]
+/// );
+/// ```
+pub fn simulate(cfg: &str, inputs: &[Timed<KeyEvent>]) -> Result<Vec<Timed<OutputEvent>>> {
+    simulate_with_files(cfg, Default::default(), inputs)
+}
+
+/// Like [`simulate`], with the contents of files that the configuration includes, keyed by file
+/// name.
+pub fn simulate_with_files(
+    cfg: &str,
+    files: FxHashMap<String, String>,
+    inputs: &[Timed<KeyEvent>],
+) -> Result<Vec<Timed<OutputEvent>>> {
+    simulate_on(|sink| Engine::new_with_files(cfg, files, sink), inputs)
+}
+
+/// Like [`simulate`], with the configuration file at `path`, whose includes are read from disk.
+pub fn simulate_file(path: &Path, inputs: &[Timed<KeyEvent>]) -> Result<Vec<Timed<OutputEvent>>> {
+    simulate_on(|sink| Engine::from_file(path, sink), inputs)
+}
+
+type Sink = Box<dyn FnMut(OutputEvent) + Send>;
+
+fn simulate_on(
+    new_engine: impl FnOnce(Sink) -> Result<Engine>,
+    inputs: &[Timed<KeyEvent>],
+) -> Result<Vec<Timed<OutputEvent>>> {
+    let pending = Arc::new(Mutex::new(vec![]));
+    let sink_pending = pending.clone();
+    let mut engine = new_engine(Box::new(move |ev| {
+        sink_pending
+            .lock()
+            .expect("output lock is not poisoned")
+            .push(ev)
+    }))?;
+    let mut outputs = vec![];
+    let mut collect = |time: u64| {
+        let mut pending = pending.lock().expect("output lock is not poisoned");
+        outputs.extend(pending.drain(..).map(|event| Timed { time, event }));
+    };
+    let mut now = 0;
+    let mut pressed = FxHashSet::default();
+    let result = (|| {
+        for input in inputs {
+            if input.time < now {
+                bail!(
+                    "input at {}ms is before the previous input at {now}ms",
+                    input.time
+                );
+            }
+            while now < input.time {
+                engine.tick(1)?;
+                collect(now);
+                now += 1;
+            }
+            match input.event.value {
+                KeyValue::Press => {
+                    pressed.insert(input.event.code);
+                }
+                KeyValue::Release => {
+                    pressed.remove(&input.event.code);
+                }
+                _ => {}
+            }
+            engine.handle_input(input.event)?;
+            collect(now);
+        }
+        let settled_by = now + SIMULATION_SETTLE_MS;
+        while now < settled_by {
+            let idle = engine.tick(1)?;
+            collect(now);
+            now += 1;
+            if idle {
+                break;
+            }
+        }
+        Ok(())
+    })();
+    // Keys that are held at the end would otherwise stay pressed for later engines.
+    let mut pressed_keys = PRESSED_KEYS.lock();
+    for code in pressed {
+        pressed_keys.remove(&code);
+    }
+    result.map(|()| outputs)
+}
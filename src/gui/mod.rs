@@ -16,3 +16,7 @@ pub static GUI_CFG_TX: OnceLock<native_windows_gui::NoticeSender> = OnceLock::ne
 pub static GUI_ERR_TX: OnceLock<native_windows_gui::NoticeSender> = OnceLock::new();
 pub static GUI_ERR_MSG_TX: OnceLock<ASender<(String, String)>> = OnceLock::new();
 pub static GUI_EXIT_TX: OnceLock<native_windows_gui::NoticeSender> = OnceLock::new();
+pub static GUI_NOTIFY_TX: OnceLock<native_windows_gui::NoticeSender> = OnceLock::new();
+pub static GUI_NOTIFY_MSG_TX: OnceLock<ASender<(String, String)>> = OnceLock::new();
+/// The `--log-file` that the tray menu opens.
+pub static GUI_LOG_FILE: OnceLock<std::path::PathBuf> = OnceLock::new();
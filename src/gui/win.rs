@@ -1,4 +1,5 @@
 use crate::Kanata;
+use crate::kanata::{is_emergency_passthrough_active, set_emergency_passthrough};
 use anyhow::{Result, bail};
 use core::cell::RefCell;
 use kanata_parser::cfg::CfgOptionsGui;
@@ -98,11 +99,15 @@ pub struct SystemTray {
     pub cfg_notice: nwg::Notice,
     pub err_notice: nwg::Notice,
     pub exit_notice: nwg::Notice,
+    /// Shows the notifications of `notify` actions, received by `notify_recv`.
+    pub notify_notice: nwg::Notice,
     pub tt_notice: nwg::Notice,
     /// Receiver of error message content sent from other threads
     /// (e.g., from key event thread via WinDbgLogger that will also notify our GUI
     /// (but not pass data) after sending data to this receiver)
     pub err_recv: Option<Receiver<(String, String)>>,
+    /// Receiver of the title and body of notifications from `notify` actions.
+    pub notify_recv: Option<Receiver<(String, String)>>,
     pub tt2m_channel: Option<(ASender<bool>, Receiver<bool>)>,
     // receiver will be created before a thread is spawned and moved there
     pub m2tt_sender: RefCell<Option<ASender<bool>>>,
@@ -111,7 +116,11 @@ pub struct SystemTray {
     pub tray_menu: nwg::Menu,
     pub tray_1cfg_m: nwg::Menu,
     pub tray_2reload: nwg::MenuItem,
-    pub tray_3exit: nwg::MenuItem,
+    /// Checked while emergency passthrough is active.
+    pub tray_3pause: nwg::MenuItem,
+    /// Opens the `--log-file`, disabled without one.
+    pub tray_4log: nwg::MenuItem,
+    pub tray_5exit: nwg::MenuItem,
     pub img_reload: nwg::Bitmap,
     pub img_exit: nwg::Bitmap,
 }
@@ -131,7 +140,10 @@ const ASSET_FD: [&str; 4] = ["", "icon", "img", "icons"];
 const IMG_EXT: [&str; 7] = ["ico", "jpg", "jpeg", "png", "bmp", "dds", "tiff"];
 const PRE_LAYER: &str = "\n🗍: "; // : invalid path marker, so should be safe to use as a separator
 const TTTIMER_L: u16 = 9; // lifetime delta to duration for a tooltip timer
-use crate::gui::{CFG, GUI_CFG_TX, GUI_ERR_MSG_TX, GUI_ERR_TX, GUI_EXIT_TX, GUI_TX};
+use crate::gui::{
+    CFG, GUI_CFG_TX, GUI_ERR_MSG_TX, GUI_ERR_TX, GUI_EXIT_TX, GUI_LOG_FILE, GUI_NOTIFY_MSG_TX,
+    GUI_NOTIFY_TX, GUI_TX,
+};
 
 pub fn send_gui_notice() {
     if let Some(gui_tx) = GUI_TX.get() {
@@ -161,6 +173,19 @@ pub fn send_gui_exit_notice() {
         error!("no GUI_EXIT_TX to ask GUI thread to exit");
     }
 }
+/// Shows a notification from the tray icon, for the `notify` action.
+pub fn send_gui_notification(title: String, body: String) {
+    match (GUI_NOTIFY_MSG_TX.get(), GUI_NOTIFY_TX.get()) {
+        (Some(msg_tx), Some(gui_tx)) => {
+            if msg_tx.send((title, body)).is_ok() {
+                gui_tx.notice();
+            } else {
+                error!("could not send the notification to the GUI thread");
+            }
+        }
+        _ => error!("no GUI_NOTIFY_TX to show a notification"),
+    }
+}
 pub fn show_err_msg_nofail(title: String, msg: String) {
     // log gets insalized before gui, so some errors might have no target to log to, ignore them
     if let Some(gui_msg_tx) = GUI_ERR_MSG_TX.get() {
@@ -406,11 +431,11 @@ macro_rules! mouse_scale_factor {
             match res as DWORD {
                 ERROR_SUCCESS => {}
                 ERROR_FILE_NOT_FOUND => {
-                    log::error!(r"Registry '{}\{}' not found", key_path_s, key_name_s);
+                    tracing::error!(r"Registry '{}\{}' not found", key_path_s, key_name_s);
                     mouse_scale = 1;
                 }
                 _ => {
-                    log::error!(
+                    tracing::error!(
                         r"Registry '{}\{}' couldn't be read as DWORD {}",
                         key_path_s,
                         key_name_s,
@@ -691,6 +716,9 @@ impl SystemTray {
     }
     fn show_menu(&self) {
         self.update_tray_icon_cfg_group(false);
+        // Passthrough may also have been toggled by the emergency chord or the watchdog.
+        self.tray_3pause
+            .set_checked(is_emergency_passthrough_active());
         let (x, y) = nwg::GlobalCursor::position();
         self.tray_menu.popup(x, y);
     }
@@ -1028,6 +1056,16 @@ impl SystemTray {
             Some(&self.icon),
         );
     }
+    /// Show OS notification message from a `notify` action
+    fn notify_action(&self) {
+        use nwg::TrayNotificationFlags as f_tray;
+        let Some(Ok((title, body))) = self.notify_recv.as_ref().map(|rx| rx.try_recv()) else {
+            return;
+        };
+        let flags = f_tray::USER_ICON | f_tray::LARGE_ICON;
+        self.tray
+            .show(&body, Some(&title), Some(flags), Some(&self.icon));
+    }
     /// Update tray icon data on config reload
     fn reload_cfg_icon(&self) {
         let _ = self.reload_cfg_or_layer_icon(true);
@@ -1275,6 +1313,24 @@ impl SystemTray {
             }
         }
     }
+    /// Pass input through to the OS without remapping, or process it again.
+    fn toggle_pause(&self) {
+        let paused = !is_emergency_passthrough_active();
+        set_emergency_passthrough(paused);
+        self.tray_3pause.set_checked(paused);
+        match paused {
+            true => info!("paused from the tray menu, passing input through to the OS"),
+            false => info!("resumed from the tray menu"),
+        }
+    }
+    fn open_log(&self) {
+        if let Some(log_file) = GUI_LOG_FILE.get() {
+            // detached to open the log that is still opened for writing
+            if let Err(e) = open::that_detached(log_file) {
+                error!("failed to open {} due to {e:?}", log_file.display());
+            }
+        }
+    }
     fn exit(&self) {
         let handlers = self.handlers_dyn.borrow();
         for handler in handlers.iter() {
@@ -1355,6 +1411,11 @@ pub mod system_tray_ui {
             if GUI_ERR_MSG_TX.set(sndr).is_err() {
                 warn!("Someone else set our ‘GUI_ERR_MSG_TX’");
             };
+            let (sndr, rcvr) = std::sync::mpsc::channel();
+            d.notify_recv = Some(rcvr);
+            if GUI_NOTIFY_MSG_TX.set(sndr).is_err() {
+                warn!("Someone else set our ‘GUI_NOTIFY_MSG_TX’");
+            };
 
             // Controls
             nwg::MessageWindow::builder().build(&mut d.window)?;
@@ -1377,6 +1438,9 @@ pub mod system_tray_ui {
             nwg::Notice::builder()
                 .parent(&d.window)
                 .build(&mut d.exit_notice)?;
+            nwg::Notice::builder()
+                .parent(&d.window)
+                .build(&mut d.notify_notice)?;
             nwg::Menu::builder()
                 .parent(&d.tray_menu)
                 .text("&F Load config") //
@@ -1385,10 +1449,20 @@ pub mod system_tray_ui {
                 .parent(&d.tray_menu)
                 .text("&R Reload config") //
                 .build(&mut d.tray_2reload)?;
+            nwg::MenuItem::builder()
+                .parent(&d.tray_menu)
+                .text("&P Pause") //
+                .check(is_emergency_passthrough_active())
+                .build(&mut d.tray_3pause)?;
+            nwg::MenuItem::builder()
+                .parent(&d.tray_menu)
+                .text("&L Open log") //
+                .disabled(GUI_LOG_FILE.get().is_none())
+                .build(&mut d.tray_4log)?;
             nwg::MenuItem::builder()
                 .parent(&d.tray_menu)
                 .text("&X Exit\t‹⎈␠⎋") //
-                .build(&mut d.tray_3exit)?;
+                .build(&mut d.tray_5exit)?;
 
             if app_data.gui_opts.tooltip_layer_changes {
                 d.win_tt = d.build_win_tt().expect("Tooltip window");
@@ -1436,7 +1510,7 @@ pub mod system_tray_ui {
                 .build(&mut tmp_bitmap)?;
             let img_exit = nwg::Bitmap::from_system_icon(SIID_DELETE);
             d.tray_2reload.set_bitmap(Some(&tmp_bitmap));
-            d.tray_3exit.set_bitmap(Some(&img_exit));
+            d.tray_5exit.set_bitmap(Some(&img_exit));
             d.img_reload = tmp_bitmap;
             d.img_exit = img_exit;
 
@@ -1583,6 +1657,8 @@ pub mod system_tray_ui {
                                 SystemTray::notify_error(&evt_ui);
                             } else if handle == evt_ui.exit_notice {
                                 SystemTray::exit(&evt_ui);
+                            } else if handle == evt_ui.notify_notice {
+                                SystemTray::notify_action(&evt_ui);
                             } else if handle == evt_ui.tt_notice {
                                 SystemTray::update_tooltip_pos(&evt_ui);}
                         E::OnWindowClose if handle == evt_ui.window => {SystemTray::exit  (&evt_ui);}
@@ -1596,7 +1672,9 @@ pub mod system_tray_ui {
                             if        handle == evt_ui.tray_2reload   {
                             let _ = SystemTray::reload_cfg(&evt_ui,None);
                             SystemTray::update_tray_icon_cfg_group(&evt_ui,true);
-                        } else if handle == evt_ui.tray_3exit     {SystemTray::exit  (&evt_ui);
+                        } else if handle == evt_ui.tray_3pause    {SystemTray::toggle_pause(&evt_ui);
+                        } else if handle == evt_ui.tray_4log      {SystemTray::open_log(&evt_ui);
+                        } else if handle == evt_ui.tray_5exit     {SystemTray::exit  (&evt_ui);
                         } else if let
                             ControlHandle::MenuItem(_parent, _id) = handle {
                               {let tray_item_dyn    = &evt_ui.tray_item_dyn.borrow(); //
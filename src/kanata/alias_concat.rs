@@ -0,0 +1,49 @@
+//! The runtime state of `var-set` and `alias-concat`: the values of variables and the aliases
+//! that held `alias-concat` keys pressed.
+
+use super::*;
+
+#[derive(Default)]
+pub(super) struct AliasConcatState {
+    /// Values set by `var-set`. Variables that are not here have their `defvar` value.
+    vars: HashMap<&'static str, &'static str>,
+    /// The `alias-concat` actions whose aliases are pressed, with the virtual keys of the aliases.
+    held: Vec<(&'static AliasConcat, Coord)>,
+}
+
+impl AliasConcatState {
+    pub(super) fn set_var(&mut self, name: &'static str, value: &'static str) {
+        tracing::debug!("setting variable {name} to {value}");
+        self.vars.insert(name, value);
+    }
+
+    /// Returns the virtual key of the alias that the action names with the current values of its
+    /// variables, which is held until the action is released. A name that is not an alias does
+    /// nothing.
+    pub(super) fn press(&mut self, action: &'static AliasConcat) -> Option<Coord> {
+        let name = action
+            .parts
+            .iter()
+            .map(|part| match part {
+                AliasConcatPart::Text(text) => *text,
+                AliasConcatPart::Var { name, default } => self.vars.get(name).unwrap_or(default),
+            })
+            .collect::<String>();
+        let Some((_, coord)) = action.targets.iter().find(|(target, _)| *target == name) else {
+            tracing::warn!("alias-concat formed {name}, which is not an alias");
+            return None;
+        };
+        tracing::debug!("alias-concat pressing @{name}");
+        self.held.push((action, *coord));
+        Some(*coord)
+    }
+
+    /// Returns the virtual key that was held for the action.
+    pub(super) fn release(&mut self, action: &AliasConcat) -> Option<Coord> {
+        let idx = self
+            .held
+            .iter()
+            .position(|(held, _)| std::ptr::eq(*held, action))?;
+        Some(self.held.remove(idx).1)
+    }
+}
@@ -0,0 +1,83 @@
+//! Switching between the applications of `defapp`. kanata does not watch the focused window
+//! itself; the active application is set by a TCP client or by the simulator.
+
+use super::*;
+
+#[derive(Default)]
+pub(super) struct AppState {
+    /// The identifier of the active application as written in `defapp`, or None if the active
+    /// application is not in `defapp`.
+    active: Option<String>,
+    /// The default layer from before the layer of the active application was switched to.
+    layer_before_app: Option<usize>,
+    /// The aliases that `defapp` replaces whose keys are held, with the pressed virtual keys.
+    held: Vec<(&'static AppAlias, Coord)>,
+}
+
+impl AppState {
+    /// Returns the virtual key of the alias for the active application, which is held until the
+    /// action is released.
+    pub(super) fn press(&mut self, action: &'static AppAlias) -> Coord {
+        let coord = self
+            .active
+            .as_deref()
+            .and_then(|active| action.replacements.iter().find(|(app, _)| *app == active))
+            .map(|(_, coord)| *coord)
+            .unwrap_or(action.default);
+        self.held.push((action, coord));
+        coord
+    }
+
+    /// Returns the virtual key that was held for the action.
+    pub(super) fn release(&mut self, action: &AppAlias) -> Option<Coord> {
+        let idx = self
+            .held
+            .iter()
+            .position(|(held, _)| std::ptr::eq(*held, action))?;
+        Some(self.held.remove(idx).1)
+    }
+}
+
+impl Kanata {
+    /// The application of `defapp` that is active.
+    #[cfg(feature = "cmd")]
+    pub(super) fn active_app(&self) -> Option<&App> {
+        let active = self.app_state.active.as_deref()?;
+        self.apps.iter().find(|app| app.id == active)
+    }
+
+    /// Sets the active application, which is compared without case to the identifiers of
+    /// `defapp`. The layer of the application is switched to, and leaving the application switches
+    /// back to the layer from before. On Windows, its `windows-altgr` is used while it is active.
+    pub fn set_active_app(&mut self, app_id: &str) {
+        let app = self
+            .apps
+            .iter()
+            .find(|app| app.id.eq_ignore_ascii_case(app_id));
+        let layout = self.layout.bm();
+        match app.and_then(|app| app.layer) {
+            Some(layer) => {
+                if self.app_state.layer_before_app.is_none() {
+                    self.app_state.layer_before_app = Some(layout.default_layer);
+                }
+                layout.set_default_layer(layer.into());
+            }
+            None => {
+                if let Some(layer) = self.app_state.layer_before_app.take() {
+                    layout.set_default_layer(layer);
+                }
+            }
+        }
+        tracing::info!(
+            "active application is {app_id}{}",
+            if app.is_some() {
+                ""
+            } else {
+                ", which is not in defapp"
+            }
+        );
+        #[cfg(target_os = "windows")]
+        set_win_app_altgr_behaviour(app.and_then(|app| app.windows_altgr));
+        self.app_state.active = app.map(|app| app.id.clone());
+    }
+}
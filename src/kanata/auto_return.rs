@@ -0,0 +1,51 @@
+//! The `auto-return` layer option, which switches the default layer back after a time without
+//! input.
+
+#[derive(Default)]
+pub(super) struct AutoReturnState {
+    /// The default layer as of the previous tick.
+    prev_default_layer: usize,
+    /// While the default layer has `auto-return`, the layer to return to and the ticks since the
+    /// last input.
+    active: Option<(usize, u16)>,
+}
+
+impl AutoReturnState {
+    /// Returns the layer to switch to if the default layer has been without input for its
+    /// `auto-return` duration.
+    pub(super) fn tick(&mut self, default_layer: usize, timeout: Option<u16>) -> Option<usize> {
+        let prev_default_layer = std::mem::replace(&mut self.prev_default_layer, default_layer);
+        let Some(timeout) = timeout else {
+            self.active = None;
+            return None;
+        };
+        if prev_default_layer != default_layer {
+            // Switching between layers with auto-return keeps the layer to return to, so that
+            // the return goes back to the layer that has none.
+            let return_to = self
+                .active
+                .map(|(layer, _)| layer)
+                .unwrap_or(prev_default_layer);
+            self.active = (return_to != default_layer).then_some((return_to, 0));
+        }
+        let (return_to, ticks) = self.active.as_mut()?;
+        *ticks = ticks.saturating_add(1);
+        if *ticks < timeout {
+            return None;
+        }
+        let return_to = *return_to;
+        self.active = None;
+        Some(return_to)
+    }
+
+    /// Restarts the timeout on input.
+    pub(super) fn input(&mut self) {
+        if let Some((_, ticks)) = &mut self.active {
+            *ticks = 0;
+        }
+    }
+
+    pub(super) fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+}
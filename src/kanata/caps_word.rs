@@ -44,7 +44,7 @@ impl CapsWordState {
         active_keys: &mut Vec<KeyCode>,
     ) -> CapsWordNextState {
         if self.timeout_ticks == 0 {
-            log::trace!("caps-word ended");
+            tracing::trace!("caps-word ended");
             return End;
         }
         for kc in active_keys.iter() {
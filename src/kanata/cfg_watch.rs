@@ -0,0 +1,109 @@
+//! `--watch`: live reloads the configuration when it or one of its included files changes.
+//!
+//! The files are polled for their modification time and size instead of using OS notifications,
+//! so that editors which save by replacing the file behave the same on every platform.
+
+use super::*;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Editors may write a file in several steps, so wait for the files to stop changing before
+/// reloading.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// The state of a file on disk, `None` if it can't be read, e.g. in the middle of a save.
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[derive(Default)]
+struct Watcher {
+    stamps: HashMap<PathBuf, Stamp>,
+    changed_at: Option<Instant>,
+}
+
+impl Watcher {
+    /// Records the state of `files` and returns whether a change has settled and should be
+    /// reloaded.
+    fn poll(&mut self, files: &[PathBuf], now: Instant) -> bool {
+        let mut changed = false;
+        let mut stamps = HashMap::default();
+        for file in files {
+            let new = stamp(file);
+            // Files that are new to the list came with a reload and are not a change.
+            changed |= self.stamps.get(file).is_some_and(|old| *old != new);
+            stamps.insert(file.clone(), new);
+        }
+        self.stamps = stamps;
+        if changed {
+            self.changed_at = Some(now);
+            return false;
+        }
+        match self.changed_at {
+            Some(at) if now.duration_since(at) >= DEBOUNCE => {
+                self.changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Kanata {
+    /// Starts a thread that requests a live reload when the files of the current configuration
+    /// change. A configuration that fails to parse is not applied, so the previous one stays
+    /// active until the next change.
+    pub fn start_cfg_watcher(kanata: Arc<Mutex<Self>>, wakeup: EventSender) {
+        info!("watching the configuration files for changes");
+        std::thread::spawn(move || {
+            let mut watcher = Watcher::default();
+            loop {
+                let files = kanata.lock().cfg_files.clone();
+                if watcher.poll(&files, Instant::now()) {
+                    info!("configuration files changed, reloading");
+                    kanata.lock().request_live_reload();
+                    // The processing loop may be waiting for input, and the channel being full
+                    // means that it will wake up anyway.
+                    let _ = wakeup.try_send(KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp));
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloads_once_changes_settle() {
+        let path = std::env::temp_dir().join(format!("kanata-watch-{}.kbd", std::process::id()));
+        std::fs::write(&path, "(defsrc a)").unwrap();
+        let files = [path.clone()];
+        let start = Instant::now();
+        let mut watcher = Watcher::default();
+        assert!(!watcher.poll(&files, start));
+        assert!(!watcher.poll(&files, start + DEBOUNCE));
+
+        std::fs::write(&path, "(defsrc a b)").unwrap();
+        assert!(!watcher.poll(&files, start + DEBOUNCE));
+        assert!(!watcher.poll(&files, start + DEBOUNCE + POLL_INTERVAL));
+        assert!(watcher.poll(&files, start + DEBOUNCE * 2));
+        assert!(!watcher.poll(&files, start + DEBOUNCE * 3));
+
+        // A file that is added to the list, e.g. a new include, is not a change by itself.
+        let other = std::env::temp_dir().join(format!("kanata-watch-{}.inc", std::process::id()));
+        std::fs::write(&other, "").unwrap();
+        let files = [path.clone(), other.clone()];
+        assert!(!watcher.poll(&files, start + DEBOUNCE * 4));
+        assert!(!watcher.poll(&files, start + DEBOUNCE * 5));
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(other).unwrap();
+    }
+}
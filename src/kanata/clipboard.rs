@@ -21,7 +21,7 @@ mod real {
         for _ in 0..10 {
             let c = arboard::Clipboard::new();
             if let Ok(goodclip) = c {
-                log::trace!("clipboard init");
+                tracing::trace!("clipboard init");
                 return Mutex::new(goodclip);
             }
             std::thread::sleep(std::time::Duration::from_millis(25));
@@ -33,11 +33,11 @@ mod real {
         for _ in 0..10 {
             match CLIPBOARD.lock().set_text(clipboard_string) {
                 Ok(()) => {
-                    log::trace!("clipboard set to {clipboard_string}");
+                    tracing::trace!("clipboard set to {clipboard_string}");
                     return;
                 }
                 Err(e) => {
-                    log::error!("error setting clipboard: {e:?}");
+                    tracing::error!("error setting clipboard: {e:?}");
                 }
             }
             std::thread::sleep(std::time::Duration::from_millis(25));
@@ -45,6 +45,10 @@ mod real {
     }
 
     pub(crate) fn clpb_cmd_set(cmd_and_args: &[&str]) {
+        #[cfg(feature = "cmd")]
+        if !cmd_allowed(cmd_and_args) {
+            return;
+        }
         let mut newclip = None;
         for _ in 0..10 {
             match CLIPBOARD.lock().get_text() {
@@ -54,10 +58,10 @@ mod real {
                 }
                 Err(e) => {
                     if matches!(e, arboard::Error::ContentNotAvailable) {
-                        log::warn!("clipboard is unset or is image data; no-op for cmd-set");
+                        tracing::warn!("clipboard is unset or is image data; no-op for cmd-set");
                         return;
                     }
-                    log::error!("error setting clipboard: {e:?}");
+                    tracing::error!("error setting clipboard: {e:?}");
                 }
             }
             std::thread::sleep(std::time::Duration::from_millis(25));
@@ -74,30 +78,30 @@ mod real {
         let executable = args
             .next()
             .expect("parsing should have forbidden empty cmd");
-        log::trace!("executing {executable}");
+        tracing::trace!("executing {executable}");
         let mut cmd = Command::new(executable);
         cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
         for arg in args {
-            log::trace!("arg is {arg}");
+            tracing::trace!("arg is {arg}");
             cmd.arg(arg);
         }
         let mut child = match cmd.spawn() {
             Ok(c) => c,
             Err(e) => {
-                log::warn!("failed to spawn cmd, returning empty string for cmd-set: {e:?}");
+                tracing::warn!("failed to spawn cmd, returning empty string for cmd-set: {e:?}");
                 return String::new();
             }
         };
 
         let child_stdin = child.stdin.as_mut().unwrap();
         if let Err(e) = child_stdin.write_all(stdin.as_bytes()) {
-            log::warn!("failed to write to stdin: {e:?}");
+            tracing::warn!("failed to write to stdin: {e:?}");
         }
         child
             .wait_with_output()
             .map(|out| String::from_utf8_lossy(&out.stdout).to_string())
             .unwrap_or_else(|e| {
-                log::error!("failed to execute cmd: {e:?}");
+                tracing::error!("failed to execute cmd: {e:?}");
                 String::new()
             })
     }
@@ -106,7 +110,7 @@ mod real {
         for _ in 0..10 {
             match CLIPBOARD.lock().get_text() {
                 Ok(cliptext) => {
-                    log::trace!("saving to id {id}: {cliptext}");
+                    tracing::trace!("saving to id {id}: {cliptext}");
                     save_data.insert(id, Text(cliptext));
                     return;
                 }
@@ -115,7 +119,7 @@ mod real {
                         // ContentNotAvailable could be an image or missing data
                         break;
                     }
-                    log::error!("error setting clipboard: {e:?}");
+                    tracing::error!("error setting clipboard: {e:?}");
                 }
             }
             std::thread::sleep(std::time::Duration::from_millis(25));
@@ -123,14 +127,14 @@ mod real {
         for _ in 0..10 {
             match CLIPBOARD.lock().get_image() {
                 Ok(clipimg) => {
-                    log::trace!("saving to id {id}: <imgdata>");
+                    tracing::trace!("saving to id {id}: <imgdata>");
                     save_data.insert(id, Image(clipimg));
                 }
                 Err(e) => {
                     if matches!(e, arboard::Error::ContentNotAvailable) {
                         break;
                     }
-                    log::error!("error setting clipboard: {e:?}");
+                    tracing::error!("error setting clipboard: {e:?}");
                 }
             }
             std::thread::sleep(std::time::Duration::from_millis(25));
@@ -139,33 +143,33 @@ mod real {
 
     pub(crate) fn clpb_restore(id: u16, save_data: &SavedClipboardData) {
         let Some(restore_data) = save_data.get(&id) else {
-            log::warn!("tried to set clipboard with missing data in id {id}, doing nothing");
+            tracing::warn!("tried to set clipboard with missing data in id {id}, doing nothing");
             return;
         };
         for _ in 0..10 {
             let e = match restore_data {
                 Text(s) => match CLIPBOARD.lock().set_text(s) {
                     Ok(()) => {
-                        log::trace!("restored clipboard with id {id}: {s}");
+                        tracing::trace!("restored clipboard with id {id}: {s}");
                         return;
                     }
                     Err(e) => e,
                 },
                 Image(img) => match CLIPBOARD.lock().set_image(img.clone()) {
                     Ok(()) => {
-                        log::trace!("restored clipboard with id {id}: <imgdata>");
+                        tracing::trace!("restored clipboard with id {id}: <imgdata>");
                         return;
                     }
                     Err(e) => e,
                 },
             };
-            log::error!("error setting clipboard: {e:?}");
+            tracing::error!("error setting clipboard: {e:?}");
             std::thread::sleep(std::time::Duration::from_millis(25));
         }
     }
 
     pub(crate) fn clpb_save_set(id: u16, content: &str, save_data: &mut SavedClipboardData) {
-        log::trace!("setting save id {id} with {content}");
+        tracing::trace!("setting save id {id} with {content}");
         save_data.insert(id, Text(content.into()));
     }
 
@@ -186,6 +190,10 @@ mod real {
         cmd_and_args: &[&str],
         save_data: &mut SavedClipboardData,
     ) {
+        #[cfg(feature = "cmd")]
+        if !cmd_allowed(cmd_and_args) {
+            return;
+        }
         let stdin_content = match save_data.get(&id) {
             Some(slot_data) => match slot_data {
                 Text(s) => s.as_str(),
@@ -194,7 +202,7 @@ mod real {
             None => "",
         };
         let content = run_cmd_get_stdout(cmd_and_args, stdin_content);
-        log::trace!("setting save id {id} with {content}");
+        tracing::trace!("setting save id {id} with {content}");
         save_data.insert(id, Text(content));
     }
 
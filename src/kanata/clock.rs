@@ -0,0 +1,46 @@
+//! The clock that timestamps are taken from, e.g. for the event trace, JSON outputs, typing
+//! statistics and the `datetime` action.
+//!
+//! It is the wall clock unless a replay, e.g. `--replay-trace`, switches to the virtual clock.
+//! The virtual clock only advances with the ticks of the engine, which follow the timestamps of
+//! the replayed events, so that a replay outputs exactly the same on every run.
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use web_time::Instant;
+
+/// The instant at which the virtual clock started, if it is used.
+static VIRTUAL_START: OnceLock<Instant> = OnceLock::new();
+static VIRTUAL_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Uses the virtual clock from now on, starting at 0.
+pub fn use_virtual_clock() {
+    VIRTUAL_START.get_or_init(Instant::now);
+}
+
+pub fn virtual_clock_used() -> bool {
+    VIRTUAL_START.get().is_some()
+}
+
+/// Advances the virtual clock, if it is used, by the milliseconds of engine ticks.
+pub fn advance_virtual_clock(ms: u64) {
+    if virtual_clock_used() {
+        VIRTUAL_MS.fetch_add(ms, Ordering::Relaxed);
+    }
+}
+
+/// The time since the virtual clock started, if it is used.
+pub(crate) fn virtual_elapsed() -> Option<Duration> {
+    VIRTUAL_START
+        .get()
+        .map(|_| Duration::from_millis(VIRTUAL_MS.load(Ordering::Relaxed)))
+}
+
+/// The current instant of the clock.
+pub fn now() -> Instant {
+    match VIRTUAL_START.get() {
+        Some(start) => *start + Duration::from_millis(VIRTUAL_MS.load(Ordering::Relaxed)),
+        None => Instant::now(),
+    }
+}
@@ -1,65 +1,33 @@
 #![cfg_attr(feature = "simulated_output", allow(dead_code, unused_imports))]
 
-use std::fmt::Write;
-
+use kanata_parser::cfg::CmdAllowlist;
 use kanata_parser::cfg::parse_mod_prefix;
 use kanata_parser::cfg::sexpr::*;
 use kanata_parser::keys::*;
+use parking_lot::Mutex;
 
 // local log prefix
 const LP: &str = "cmd-out:";
 
-#[cfg(not(feature = "simulated_output"))]
-pub(super) fn run_cmd_in_thread(
-    cmd_and_args: Vec<String>,
-    log_level: Option<log::Level>,
-    error_log_level: Option<log::Level>,
-) -> std::thread::JoinHandle<()> {
-    std::thread::spawn(move || {
-        let mut args = cmd_and_args.iter();
-        let mut printable_cmd = String::new();
-        let executable = args
-            .next()
-            .expect("parsing should have forbidden empty cmd");
-        write!(
-            printable_cmd,
-            "Program: {}, Arguments:",
-            executable.as_str()
-        )
-        .expect("write to string should succeed");
-        let mut cmd = std::process::Command::new(executable);
-        for arg in args {
-            cmd.arg(arg);
-            printable_cmd.push(' ');
-            printable_cmd.push_str(arg.as_str());
-        }
-        if let Some(level) = log_level {
-            log::log!(level, "Running cmd: {}", printable_cmd);
-        }
-        match cmd.output() {
-            Ok(output) => {
-                if let Some(level) = log_level {
-                    log::log!(
-                        level,
-                        "Successfully ran cmd: {}\nstdout:\n{}\nstderr:\n{}",
-                        printable_cmd,
-                        String::from_utf8_lossy(&output.stdout),
-                        String::from_utf8_lossy(&output.stderr)
-                    );
-                };
-            }
-            Err(e) => {
-                if let Some(level) = error_log_level {
-                    log::log!(
-                        level,
-                        "Failed to execute program {:?}: {}",
-                        cmd.get_program(),
-                        e
-                    )
-                }
-            }
-        };
-    })
+/// The `cmd-allowlist` of the active configuration, or None if every command may run.
+static CMD_ALLOWLIST: Mutex<Option<CmdAllowlist>> = Mutex::new(None);
+
+pub(super) fn set_cmd_allowlist(allowlist: Option<CmdAllowlist>) {
+    *CMD_ALLOWLIST.lock() = allowlist;
+}
+
+/// Whether the command may run, which parsing already checked. This checks again before the
+/// command runs, in case it was built some other way.
+pub(super) fn cmd_allowed(cmd_and_args: &[impl AsRef<str>]) -> bool {
+    let allowed = CMD_ALLOWLIST
+        .lock()
+        .as_ref()
+        .is_none_or(|allowlist| allowlist.allows(cmd_and_args));
+    if !allowed {
+        let cmd: Vec<_> = cmd_and_args.iter().map(AsRef::as_ref).collect();
+        tracing::error!("cmd-allowlist does not permit {cmd:?}, not running it");
+    }
+    allowed
 }
 
 pub(super) type Item = KeyAction;
@@ -125,7 +93,7 @@ fn try_parse_chord<'a>(chord: &str, exprs: &'a [SExpr], items: &mut Vec<Item>) -
             }
         },
         Err(e) => {
-            log::warn!("{LP} found invalid chord {chord}: {}", e.msg);
+            tracing::warn!("{LP} found invalid chord {chord}: {}", e.msg);
             &exprs[1..]
         }
     }
@@ -133,7 +101,7 @@ fn try_parse_chord<'a>(chord: &str, exprs: &'a [SExpr], items: &mut Vec<Item>) -
 
 fn try_parse_chorded_key(mods: &[KeyCode], osc: &str, chord: &str, items: &mut Vec<Item>) {
     if mods.is_empty() {
-        log::warn!("{LP} found invalid key: {osc}");
+        tracing::warn!("{LP} found invalid key: {osc}");
         return;
     }
     match str_to_oscode(osc) {
@@ -148,7 +116,7 @@ fn try_parse_chorded_key(mods: &[KeyCode], osc: &str, chord: &str, items: &mut V
             items.push(Release(osc));
         }
         None => {
-            log::warn!("{LP} found chord {chord} with invalid key: {osc}");
+            tracing::warn!("{LP} found chord {chord} with invalid key: {osc}");
         }
     };
 }
@@ -160,14 +128,14 @@ fn try_parse_chorded_list<'a>(
     items: &mut Vec<Item>,
 ) -> &'a [SExpr] {
     if exprs.is_empty() {
-        log::warn!(
+        tracing::warn!(
             "{LP} found chord modifiers with no attached key or list - ignoring it: {chord}"
         );
         return exprs;
     }
     match &exprs[0] {
         SExpr::Atom(osc) => {
-            log::warn!("{LP} expected list after {chord}, got string {}", &osc.t);
+            tracing::warn!("{LP} expected list after {chord}, got string {}", &osc.t);
             exprs
         }
         SExpr::List(subexprs) => {
@@ -186,41 +154,26 @@ fn try_parse_chorded_list<'a>(
     }
 }
 
-#[cfg(not(feature = "simulated_output"))]
-pub(super) fn keys_for_cmd_output(cmd_and_args: &[&str]) -> impl Iterator<Item = Item> {
-    let mut args = cmd_and_args.iter();
-    let mut cmd = std::process::Command::new(
-        args.next()
-            .expect("parsing should have forbidden empty cmd"),
-    );
-    for arg in args {
-        cmd.arg(arg);
-    }
-    let output = match cmd.output() {
-        Ok(o) => o,
-        Err(e) => {
-            log::error!("Failed to execute cmd: {e}");
-            return empty();
-        }
-    };
-    log::debug!("{LP} stderr: {}", String::from_utf8_lossy(&output.stderr));
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    match parse(&stdout, "cmd") {
+/// The key actions that the stdout of a `cmd-output-keys` command describes.
+pub(super) fn keys_for_cmd_stdout(stdout: &str) -> std::vec::IntoIter<Item> {
+    match parse(stdout, "cmd") {
         Ok(lists) => match lists.len() {
             0 => {
-                log::warn!("{LP} got zero top-level S-expression from cmd, expected 1:\n{stdout}");
+                tracing::warn!(
+                    "{LP} got zero top-level S-expression from cmd, expected 1:\n{stdout}"
+                );
                 empty()
             }
             1 => from_sexpr(lists.into_iter().next().expect("len 1").t),
             _ => {
-                log::warn!(
+                tracing::warn!(
                     "{LP} got multiple top-level S-expression from cmd, expected 1:\n{stdout}"
                 );
                 empty()
             }
         },
         Err(e) => {
-            log::warn!(
+            tracing::warn!(
                 "{LP} could not parse an S-expression from cmd:\n{stdout}\n{}",
                 e.msg
             );
@@ -228,20 +181,3 @@ pub(super) fn keys_for_cmd_output(cmd_and_args: &[&str]) -> impl Iterator<Item =
         }
     }
 }
-
-#[cfg(feature = "simulated_output")]
-pub(super) fn keys_for_cmd_output(cmd_and_args: &[&str]) -> impl Iterator<Item = Item> {
-    println!("cmd-keys:{cmd_and_args:?}");
-    [].iter().copied()
-}
-
-#[cfg(feature = "simulated_output")]
-pub(super) fn run_cmd_in_thread(
-    cmd_and_args: Vec<String>,
-    _log_level: Option<log::Level>,
-    _error_log_level: Option<log::Level>,
-) -> std::thread::JoinHandle<()> {
-    std::thread::spawn(move || {
-        println!("cmd:{cmd_and_args:?}");
-    })
-}
@@ -0,0 +1,486 @@
+//! The executor of cmd actions.
+//!
+//! Each command runs in a thread of the executor, so that a command that runs for long never
+//! stalls the processing loop. The lines that a command writes and its exit code are sent to the
+//! TCP clients that sent `SubscribeCmdOutput`, besides being logged with the levels of
+//! `cmd-log`. The output of `cmd-output-keys` goes back to the processing loop, which types it
+//! once the command has exited and handles other keys meanwhile.
+//!
+//! A command is killed when it runs for longer than `cmd-timeout`, or by `cmd-cancel` and
+//! `CancelCmd`, so that a command that hangs does not live on.
+
+#![cfg_attr(feature = "simulated_output", allow(dead_code, unused_imports))]
+
+use super::*;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// A command of a cmd action, with the levels to log its run and its failure at.
+pub(super) struct CmdRun {
+    pub(super) log_level: Option<log::Level>,
+    pub(super) error_log_level: Option<log::Level>,
+    pub(super) cmd: Vec<String>,
+    /// The environment variables of the active application and layers, see [`Kanata::cmd_env`].
+    pub(super) env: CmdEnv,
+    /// The sandbox of `cmd-argv`.
+    pub(super) sandbox: Option<&'static CmdSandbox>,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+static NOTIFICATIONS: Mutex<Option<Sender<ServerMessage>>> = Mutex::new(None);
+
+/// Milliseconds after which a command is killed, or 0 to let it run, see `cmd-timeout`.
+static TIMEOUT_MS: AtomicU32 = AtomicU32::new(0);
+
+/// How often a command that runs is checked for its timeout and for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The commands that run, most recent last.
+static RUNNING: Mutex<Vec<RunningCmd>> = Mutex::new(Vec::new());
+
+struct RunningCmd {
+    id: u64,
+    program: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Removes the command from [`RUNNING`] when it has exited.
+struct Registered(u64);
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        RUNNING.lock().retain(|cmd| cmd.id != self.0);
+    }
+}
+
+/// The key actions of `cmd-output-keys` commands that exited, for the processing loop to type.
+static OUTPUT_KEYS: Mutex<VecDeque<KeyAction>> = Mutex::new(VecDeque::new());
+/// The number of `cmd-output-keys` commands that still run.
+static OUTPUT_KEYS_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets where the notifications of commands go, which is the channel of the processing loop.
+pub(super) fn set_cmd_notifications(tx: Option<Sender<ServerMessage>>) {
+    *NOTIFICATIONS.lock() = tx;
+}
+
+/// Sends the notification that `msg` makes, if a client wants it.
+fn notify(msg: impl FnOnce() -> ServerMessage) {
+    #[cfg(feature = "tcp_server")]
+    if crate::tcp_server::cmd_output_subscribed()
+        && let Some(tx) = &*NOTIFICATIONS.lock()
+        && let Err(e) = tx.try_send(msg())
+    {
+        tracing::debug!("could not send cmd notification: {e}");
+    }
+    #[cfg(not(feature = "tcp_server"))]
+    let _ = msg;
+}
+
+/// Sets the timeout of commands from `cmd-timeout`.
+pub(super) fn set_cmd_timeout(ms: u32) {
+    TIMEOUT_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Kills the command with the id, or else the most recent command that runs the program, or else
+/// the most recent command. Returns whether there was a command to kill.
+pub(crate) fn cancel_cmd(id: Option<u64>, program: Option<&str>) -> bool {
+    let running = RUNNING.lock();
+    let cmd = running.iter().rev().find(|cmd| match (id, program) {
+        (Some(id), _) => cmd.id == id,
+        (None, Some(program)) => cmd.program == program,
+        (None, None) => true,
+    });
+    match cmd {
+        Some(cmd) => {
+            tracing::info!("cancelling cmd {}: {}", cmd.id, cmd.program);
+            cmd.cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+fn spawn(f: impl FnOnce() + Send + 'static) {
+    if let Err(e) = std::thread::Builder::new().name("cmd".into()).spawn(f) {
+        tracing::error!("could not start a thread for cmd: {e}");
+    }
+}
+
+struct Finished {
+    stdout: String,
+    stderr: String,
+}
+
+/// Runs the command until it exits or is killed, streaming its output.
+fn execute(
+    cmd_and_args: &[String],
+    env: &[(String, EnvValue)],
+    sandbox: Option<&CmdSandbox>,
+) -> std::io::Result<Finished> {
+    let _running = CMD_EXECUTOR.running();
+    CMD_EXECUTOR.busy();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (program, args) = cmd_and_args
+        .split_first()
+        .expect("parsing should have forbidden empty cmd");
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if sandbox.is_some_and(|sandbox| sandbox.clear_env) {
+        command.env_clear();
+    }
+    set_env(&mut command, env)?;
+    if let Some(sandbox) = sandbox {
+        apply_sandbox(&mut command, sandbox)?;
+    }
+    let spawned = command.spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            notify(|| ServerMessage::CmdExited {
+                id,
+                code: None,
+                error: Some(e.to_string()),
+            });
+            return Err(e);
+        }
+    };
+    notify(|| ServerMessage::CmdStarted {
+        id,
+        cmd: cmd_and_args.to_vec(),
+    });
+    let cancelled = Arc::new(AtomicBool::new(false));
+    RUNNING.lock().push(RunningCmd {
+        id,
+        program: program.clone(),
+        cancelled: cancelled.clone(),
+    });
+    let _registered = Registered(id);
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stdout = std::thread::spawn(move || read_lines(id, "stdout", stdout));
+    let stderr = std::thread::spawn(move || read_lines(id, "stderr", stderr));
+    let timeout_ms = TIMEOUT_MS.load(Ordering::Relaxed);
+    let deadline =
+        (timeout_ms > 0).then(|| Instant::now() + Duration::from_millis(u64::from(timeout_ms)));
+    let (kind, killed) = loop {
+        if let Some(status) = child.try_wait()? {
+            let stdout = stdout.join().unwrap_or_default();
+            let stderr = stderr.join().unwrap_or_default();
+            notify(|| ServerMessage::CmdExited {
+                id,
+                code: status.code(),
+                error: None,
+            });
+            return Ok(Finished { stdout, stderr });
+        }
+        if cancelled.load(Ordering::Relaxed) {
+            break (std::io::ErrorKind::Interrupted, "cancelled".to_owned());
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break (
+                std::io::ErrorKind::TimedOut,
+                format!("killed after the cmd-timeout of {timeout_ms} ms"),
+            );
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    // The output is not waited for, since processes that the command started may keep it open.
+    let _ = child.kill();
+    let _ = child.wait();
+    notify(|| ServerMessage::CmdExited {
+        id,
+        code: None,
+        error: Some(killed.clone()),
+    });
+    Err(std::io::Error::new(kind, killed))
+}
+
+/// Sets the environment variables, reading the secrets of their values.
+fn set_env(command: &mut Command, env: &[(String, EnvValue)]) -> std::io::Result<()> {
+    for (name, value) in env {
+        match value {
+            EnvValue::Text(text) => command.env(name, text),
+            EnvValue::Secret(secret) => command.env(name, read_secret(secret)?),
+        };
+    }
+    Ok(())
+}
+
+fn apply_sandbox(command: &mut Command, sandbox: &CmdSandbox) -> std::io::Result<()> {
+    set_env(command, &sandbox.env)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Dropping root with a user also clears the supplementary groups of kanata.
+        if let Some(gid) = sandbox.gid {
+            command.gid(gid);
+        }
+        if let Some(uid) = sandbox.uid {
+            command.uid(uid);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the output of a command until it closes, sending each line.
+fn read_lines(id: u64, stream: &'static str, output: impl Read) -> String {
+    let mut output = BufReader::new(output);
+    let mut all = String::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match output.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&line);
+                all.push_str(&line);
+                notify(|| ServerMessage::CmdOutput {
+                    id,
+                    stream: stream.to_owned(),
+                    line: line.trim_end_matches(['\r', '\n']).to_owned(),
+                });
+            }
+        }
+    }
+    all
+}
+
+fn run_cmd(run: CmdRun) {
+    let CmdRun {
+        log_level,
+        error_log_level,
+        cmd: cmd_and_args,
+        env,
+        sandbox,
+    } = run;
+    if !cmd_allowed(&cmd_and_args) {
+        return;
+    }
+    let printable_cmd = format!(
+        "Program: {}, Arguments: {}",
+        cmd_and_args[0],
+        cmd_and_args[1..].join(" ")
+    );
+    if let Some(level) = log_level {
+        log_at(level, format_args!("Running cmd: {}", printable_cmd));
+    }
+    match execute(&cmd_and_args, &env, sandbox) {
+        Ok(finished) => {
+            if let Some(level) = log_level {
+                log_at(
+                    level,
+                    format_args!(
+                        "Successfully ran cmd: {}\nstdout:\n{}\nstderr:\n{}",
+                        printable_cmd, finished.stdout, finished.stderr
+                    ),
+                );
+            };
+        }
+        Err(e) => {
+            if let Some(level) = error_log_level {
+                log_at(
+                    level,
+                    format_args!("Failed to execute program {:?}: {}", cmd_and_args[0], e),
+                )
+            }
+        }
+    }
+}
+
+/// Logs at the level of `cmd-log`. The levels of tracing events are constants, so each level has
+/// its own event.
+fn log_at(level: log::Level, msg: std::fmt::Arguments) {
+    match level {
+        log::Level::Error => tracing::error!("{msg}"),
+        log::Level::Warn => tracing::warn!("{msg}"),
+        log::Level::Info => tracing::info!("{msg}"),
+        log::Level::Debug => tracing::debug!("{msg}"),
+        log::Level::Trace => tracing::trace!("{msg}"),
+    }
+}
+
+/// Runs the commands of an action one after another, in a thread of the executor.
+#[cfg(not(feature = "simulated_output"))]
+pub(super) fn run_cmds(cmds: Vec<CmdRun>) {
+    if cmds.is_empty() {
+        return;
+    }
+    spawn(move || {
+        for run in cmds {
+            run_cmd(run);
+        }
+    });
+}
+
+#[cfg(feature = "simulated_output")]
+pub(super) fn run_cmds(cmds: Vec<CmdRun>) {
+    for run in cmds {
+        println!("cmd:{:?}{}", run.cmd, sim_env(&run.env));
+    }
+}
+
+/// Runs a `cmd-output-keys` command in a thread of the executor, whose output
+/// [`Kanata::tick_cmd_output_keys`] types once it exited.
+#[cfg(not(feature = "simulated_output"))]
+pub(super) fn start_cmd_output_keys(cmd_and_args: Vec<String>, env: CmdEnv) {
+    if !cmd_allowed(&cmd_and_args) {
+        return;
+    }
+    OUTPUT_KEYS_RUNNING.fetch_add(1, Ordering::Relaxed);
+    spawn(move || {
+        let keys = match execute(&cmd_and_args, &env, None) {
+            Ok(finished) => {
+                tracing::debug!("cmd-out: stderr: {}", finished.stderr);
+                keys_for_cmd_stdout(&finished.stdout).collect()
+            }
+            Err(e) => {
+                tracing::error!("Failed to execute cmd: {e}");
+                vec![]
+            }
+        };
+        OUTPUT_KEYS.lock().extend(keys);
+        OUTPUT_KEYS_RUNNING.fetch_sub(1, Ordering::Relaxed);
+    });
+}
+
+#[cfg(feature = "simulated_output")]
+pub(super) fn start_cmd_output_keys(cmd_and_args: Vec<String>, env: CmdEnv) {
+    println!("cmd-keys:{cmd_and_args:?}{}", sim_env(&env));
+}
+
+#[cfg(feature = "simulated_output")]
+fn sim_env(env: &[(String, EnvValue)]) -> String {
+    match env.is_empty() {
+        true => String::new(),
+        false => format!(" env:{env:?}"),
+    }
+}
+
+/// Whether a `cmd-output-keys` command runs or its output waits to be typed.
+pub(super) fn cmd_output_keys_pending() -> bool {
+    OUTPUT_KEYS_RUNNING.load(Ordering::Relaxed) > 0 || !OUTPUT_KEYS.lock().is_empty()
+}
+
+impl Kanata {
+    /// The environment variables of cmd actions from `cmd-env` of the active application, the
+    /// default layer and the current layer, of which the later ones override the earlier ones.
+    pub(super) fn cmd_env(&self) -> CmdEnv {
+        let layout = self.layout.b();
+        let (default, current) = (layout.default_layer, layout.current_layer());
+        let layer_env = |layer: usize| {
+            self.layer_info
+                .get(layer)
+                .map(|info| info.cmd_env.as_slice())
+                .unwrap_or_default()
+        };
+        let app_env = self
+            .active_app()
+            .map(|app| app.cmd_env.as_slice())
+            .unwrap_or_default();
+        let current_env = match current == default {
+            true => &[],
+            false => layer_env(current),
+        };
+        app_env
+            .iter()
+            .chain(layer_env(default))
+            .chain(current_env)
+            .cloned()
+            .collect()
+    }
+
+    /// Types the output of the `cmd-output-keys` commands that exited.
+    pub(super) fn tick_cmd_output_keys(&mut self) -> Result<()> {
+        let keys = std::mem::take(&mut *OUTPUT_KEYS.lock());
+        for key_action in keys {
+            match key_action {
+                KeyAction::Press(osc) => press_key(&mut self.kbd_out, osc)?,
+                KeyAction::Release(osc) => release_key(&mut self.kbd_out, osc)?,
+                KeyAction::Delay(delay) => {
+                    std::thread::sleep(std::time::Duration::from_millis(u64::from(delay)))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "simulated_output")]
+    #[test]
+    fn cmd_env_of_app_and_layers() {
+        let mut k = {
+            let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            Kanata::new_from_str(
+                "(defsrc a)
+                 (deflayer (base cmd-env (PROJECT none)) a)
+                 (deflayer (web cmd-env (PROJECT web)) a)
+                 (defapp code cmd-env (EDITOR code PROJECT app))",
+                Default::default(),
+            )
+            .expect("cfg parses")
+        };
+        let env = |pairs: &[(&str, &str)]| -> CmdEnv {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), EnvValue::Text(value.to_string())))
+                .collect()
+        };
+        assert_eq!(k.cmd_env(), env(&[("PROJECT", "none")]));
+        k.set_active_app("code");
+        k.layout.bm().set_default_layer(1);
+        assert_eq!(
+            k.cmd_env(),
+            env(&[("EDITOR", "code"), ("PROJECT", "app"), ("PROJECT", "web")])
+        );
+    }
+
+    #[test]
+    fn commands_are_cancelled_and_time_out() {
+        let sleep = || vec!["sleep".to_owned(), "5".to_owned()];
+        let running = std::thread::spawn(move || execute(&sleep(), &[], None));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !cancel_cmd(None, Some("sleep")) {
+            assert!(Instant::now() < deadline, "sleep did not start");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let err = running.join().unwrap().err().expect("sleep is cancelled");
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+        assert!(!cancel_cmd(None, None));
+
+        set_cmd_timeout(50);
+        let start = Instant::now();
+        let err = execute(&sleep(), &[], None).err().expect("sleep times out");
+        set_cmd_timeout(0);
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(4));
+        let finished = execute(&["echo".to_owned(), "hi".to_owned()], &[], None).unwrap();
+        assert_eq!(finished.stdout, "hi\n");
+
+        let sandbox = CmdSandbox {
+            clear_env: true,
+            env: vec![("GREETING".to_owned(), EnvValue::Text("hi there".to_owned()))],
+            ..Default::default()
+        };
+        let layer_env = [
+            ("GREETING".to_owned(), EnvValue::Text("hi".to_owned())),
+            ("LAYER".to_owned(), EnvValue::Text("web".to_owned())),
+        ];
+        let env = execute(&["/usr/bin/env".to_owned()], &layer_env, Some(&sandbox)).unwrap();
+        assert_eq!(env.stdout, "GREETING=hi there\nLAYER=web\n");
+    }
+}
@@ -0,0 +1,60 @@
+//! The state of the `compose` action, which looks up the keys typed after it in `defcompose`.
+
+use super::*;
+
+use kanata_parser::trie::GetOrDescendentExistsResult::*;
+
+pub(super) struct ComposeState {
+    /// The keys typed so far, in the format of [`cfg::ComposeTable`].
+    keys: Vec<u16>,
+    timeout: u16,
+    ticks_until_timeout: u16,
+}
+
+impl ComposeState {
+    pub(super) fn new(timeout: u16) -> Self {
+        Self {
+            keys: vec![],
+            timeout,
+            ticks_until_timeout: timeout,
+        }
+    }
+
+    /// Returns whether the compose sequence timed out.
+    pub(super) fn tick(&mut self) -> bool {
+        self.ticks_until_timeout = self.ticks_until_timeout.saturating_sub(1);
+        self.ticks_until_timeout == 0
+    }
+}
+
+pub(super) enum ComposeNextState {
+    Active,
+    End,
+}
+
+/// Adds a key press to the compose sequence, which is consumed, and writes the output when the
+/// sequence is complete. Sequences that are not in the table end without output.
+pub(super) fn do_compose_press_logic(
+    state: &mut ComposeState,
+    osc: OsCode,
+    mod_mask: u16,
+    table: &cfg::ComposeTable,
+    kbd_out: &mut KbdOut,
+) -> Result<ComposeNextState> {
+    state.ticks_until_timeout = state.timeout;
+    state.keys.push(u16::from(osc) | mod_mask);
+    match table.get_or_descendant_exists(&state.keys) {
+        HasValue(output) => {
+            tracing::debug!("compose sequence outputs {output}");
+            for c in output.chars() {
+                kbd_out.send_unicode(c)?;
+            }
+            Ok(ComposeNextState::End)
+        }
+        InTrie => Ok(ComposeNextState::Active),
+        NotInTrie => {
+            tracing::debug!("compose sequence is unknown, ending it");
+            Ok(ComposeNextState::End)
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! `--crash-bundle`: on a panic or a fatal error, writes a file for reproducing it with the
+//! sanitized configuration, the recent events, the platform and the backtrace.
+//!
+//! The recent events are the events of the [event trace](super::event_trace), kept in memory
+//! while the bundle is enabled. With `--crash-bundle-hash-keys`, their keys are hashed with a key
+//! that is not written, so that the bundle does not show what was typed.
+
+use super::*;
+use kanata_parser::cfg::sexpr::{self, SExpr, SExprMetaData};
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// How many of the most recent events a bundle has.
+const RECENT_EVENTS: usize = 1000;
+
+/// What a bundle replaces sensitive config values with.
+const REDACTED: &str = "redacted";
+
+struct CrashBundle {
+    dir: PathBuf,
+    hash_keys: bool,
+    hasher: std::collections::hash_map::RandomState,
+    start: web_time::Instant,
+}
+
+static CRASH_BUNDLE: OnceLock<CrashBundle> = OnceLock::new();
+static RECENT: Mutex<VecDeque<serde_json::Value>> = Mutex::new(VecDeque::new());
+static CONFIG: Mutex<Option<BundleConfig>> = Mutex::new(None);
+
+/// The sanitized text of the configuration file and of the files it includes, by the name that
+/// the configuration includes them with.
+#[derive(Clone, Default)]
+struct BundleConfig {
+    text: String,
+    files: Vec<(String, String)>,
+}
+
+/// The directory that bundles are written to by default.
+pub fn default_crash_bundle_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("kanata")
+        .join("crash")
+}
+
+/// Keeps the recent events and writes a bundle to the directory on a panic.
+pub fn enable_crash_bundle(dir: PathBuf, hash_keys: bool) {
+    let _ = CRASH_BUNDLE.set(CrashBundle {
+        dir,
+        hash_keys,
+        hasher: Default::default(),
+        start: web_time::Instant::now(),
+    });
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let location = info
+            .location()
+            .map(|l| format!(" at {}:{}", l.file(), l.line()))
+            .unwrap_or_default();
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        write_crash_bundle(&format!("panic{location}: {message}"));
+    }));
+}
+
+pub(crate) fn crash_bundle_enabled() -> bool {
+    CRASH_BUNDLE.get().is_some()
+}
+
+/// Keeps the event for the bundle.
+pub(super) fn record_recent_event(event: &'static str, fields: &serde_json::Value) {
+    let Some(bundle) = CRASH_BUNDLE.get() else {
+        return;
+    };
+    let mut record = serde_json::json!({
+        "t_us": bundle.start.elapsed().as_micros() as u64,
+        "event": event,
+    });
+    if let (Some(record), Some(fields)) = (record.as_object_mut(), fields.as_object()) {
+        for (name, value) in fields {
+            let value = match (name.as_str(), value) {
+                ("key", serde_json::Value::String(key)) if bundle.hash_keys => {
+                    bundle.hash_key(key).into()
+                }
+                ("keys", serde_json::Value::Array(keys)) if bundle.hash_keys => keys
+                    .iter()
+                    .map(|key| {
+                        serde_json::Value::from(bundle.hash_key(key.as_str().unwrap_or_default()))
+                    })
+                    .collect(),
+                _ => value.clone(),
+            };
+            record.insert(name.clone(), value);
+        }
+    }
+    let mut recent = RECENT.lock();
+    if recent.len() >= RECENT_EVENTS {
+        recent.pop_front();
+    }
+    recent.push_back(record);
+}
+
+impl CrashBundle {
+    fn hash_key(&self, key: &str) -> String {
+        format!("{:016x}", self.hasher.hash_one(key))
+    }
+}
+
+/// Keeps the sanitized configuration files for the bundle. The first file is the configuration
+/// and the others are included by it.
+pub(super) fn set_crash_bundle_config(files: &[PathBuf]) {
+    if !crash_bundle_enabled() {
+        return;
+    }
+    let Some((main, includes)) = files.split_first() else {
+        return;
+    };
+    let read = |path: &Path| {
+        std::fs::read_to_string(path)
+            .map(|text| sanitize_config(&text))
+            .unwrap_or_else(|e| format!(";; could not read {}: {e}", path.display()))
+    };
+    let dir = main.parent().unwrap_or(Path::new(""));
+    let files = includes
+        .iter()
+        .map(|path| {
+            let name = path.strip_prefix(dir).unwrap_or(path);
+            (name.to_string_lossy().into_owned(), read(path))
+        })
+        .collect();
+    *CONFIG.lock() = Some(BundleConfig {
+        text: read(main),
+        files,
+    });
+}
+
+/// Writes a bundle for the reason if bundles are enabled, and returns its path.
+pub fn write_crash_bundle(reason: &str) -> Option<PathBuf> {
+    let bundle = CRASH_BUNDLE.get()?;
+    // The panic may have happened while a lock was held.
+    const LOCK_TIMEOUT: Duration = Duration::from_millis(100);
+    let events: Vec<serde_json::Value> = RECENT
+        .try_lock_for(LOCK_TIMEOUT)
+        .map(|recent| recent.iter().cloned().collect())
+        .unwrap_or_default();
+    let config = CONFIG
+        .try_lock_for(LOCK_TIMEOUT)
+        .and_then(|config| config.clone())
+        .unwrap_or_default();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let contents = serde_json::json!({
+        "kanata_version": env!("CARGO_PKG_VERSION"),
+        "reason": reason,
+        "time_unix_s": now.as_secs(),
+        "platform": {
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+            "features": enabled_features(),
+        },
+        "backtrace": std::backtrace::Backtrace::force_capture().to_string(),
+        "config": {
+            "text": config.text,
+            "files": config.files.into_iter().collect::<std::collections::BTreeMap<_, _>>(),
+        },
+        "keys_hashed": bundle.hash_keys,
+        "events": events,
+    });
+    let path = bundle
+        .dir
+        .join(format!("kanata-crash-{}.json", now.as_millis()));
+    let result = std::fs::create_dir_all(&bundle.dir).and_then(|_| {
+        let text = serde_json::to_string_pretty(&contents).map_err(std::io::Error::other)?;
+        std::fs::write(&path, text)
+    });
+    match result {
+        Ok(()) => {
+            tracing::error!("wrote a crash bundle to {}", path.display());
+            Some(path)
+        }
+        Err(e) => {
+            tracing::error!("could not write a crash bundle to {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+fn enabled_features() -> Vec<&'static str> {
+    [
+        ("cmd", cfg!(feature = "cmd")),
+        ("gui", cfg!(feature = "gui")),
+        ("interception_driver", cfg!(feature = "interception_driver")),
+        ("simulated_output", cfg!(feature = "simulated_output")),
+        ("tcp_server", cfg!(feature = "tcp_server")),
+        ("zippychord", cfg!(feature = "zippychord")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+/// Removes the comments and redacts the values that may be secret from the configuration: URLs,
+/// the values of options whose names contain `password` or `token`, and the arguments of the
+/// `cmd` actions. A configuration that doesn't parse is left out.
+fn sanitize_config(text: &str) -> String {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let Ok((exprs, metadata)) = sexpr::parse_(text, "", false) else {
+        return ";; the configuration could not be parsed".to_owned();
+    };
+    let mut replacements: Vec<(std::ops::Range<usize>, &str)> = metadata
+        .iter()
+        .filter(|m| !matches!(m, SExprMetaData::Whitespace(_)))
+        .map(|m| {
+            let span = m.span();
+            (span.start.absolute..span.end.absolute, "")
+        })
+        .collect();
+    for expr in exprs.iter() {
+        redact_list(&expr.t, &mut replacements);
+    }
+    replacements.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    let mut text = text.to_owned();
+    for (range, replacement) in replacements {
+        text.replace_range(range, replacement);
+    }
+    text
+}
+
+fn redact_list(list: &[SExpr], replacements: &mut Vec<(std::ops::Range<usize>, &'static str)>) {
+    let redact = |expr: &SExpr, replacements: &mut Vec<_>| {
+        let span = expr.span();
+        replacements.push((span.start.absolute..span.end.absolute, REDACTED));
+    };
+    if list
+        .first()
+        .and_then(|first| first.atom(None))
+        .is_some_and(|name| name.starts_with("cmd"))
+    {
+        list[1..].iter().for_each(|arg| redact(arg, replacements));
+        return;
+    }
+    let mut redact_next = false;
+    for expr in list {
+        match expr {
+            SExpr::Atom(atom) => {
+                if redact_next || atom.t.contains("://") {
+                    redact(expr, replacements);
+                }
+                redact_next = atom.t.contains("password") || atom.t.contains("token");
+            }
+            SExpr::List(l) => {
+                if redact_next {
+                    redact(expr, replacements);
+                } else {
+                    redact_list(&l.t, replacements);
+                }
+                redact_next = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitized_config_has_no_secrets() {
+        let config = r#"
+;; my config
+(defcfg obs-websocket-password "hunter2" danger-enable-cmd yes)
+(defwebhooks ha http://example.com/api/webhook/abc123 (reload))
+(defsrc a b) #| block
+comment |#
+(deflayer base (cmd curl -H "Authorization: x") b)
+"#;
+        assert_eq!(
+            sanitize_config(config),
+            r#"
+(defcfg obs-websocket-password redacted danger-enable-cmd yes)
+(defwebhooks ha redacted (reload))
+(defsrc a b) 
+(deflayer base (cmd redacted redacted redacted) b)
+"#
+        );
+    }
+}
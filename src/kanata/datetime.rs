@@ -0,0 +1,113 @@
+//! The `datetime` action, which types the time of the press formatted like `strftime`.
+
+use std::fmt::Write;
+
+use time::{OffsetDateTime, UtcOffset};
+
+/// Returns the current time in the local time zone, or in UTC if the local offset is unknown. The
+/// virtual clock starts at the Unix epoch in UTC.
+pub(super) fn now_local() -> OffsetDateTime {
+    if let Some(elapsed) = super::clock::virtual_elapsed() {
+        return OffsetDateTime::UNIX_EPOCH + elapsed;
+    }
+    let now = OffsetDateTime::now_utc();
+    match local_offset(now) {
+        Some(offset) => now.to_offset(offset),
+        None => now,
+    }
+}
+
+/// The `time` crate does not read the local offset on Unix while other threads are running,
+/// because they could change the environment concurrently. kanata does not change it.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn local_offset(now: OffsetDateTime) -> Option<UtcOffset> {
+    let timestamp = now.unix_timestamp() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&timestamp, &mut tm) }.is_null() {
+        return None;
+    }
+    UtcOffset::from_whole_seconds(tm.tm_gmtoff as i32).ok()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn local_offset(now: OffsetDateTime) -> Option<UtcOffset> {
+    UtcOffset::local_offset_at(now).ok()
+}
+
+/// Formats the time with the specifiers of `strftime` that the parser accepts.
+pub(super) fn format_datetime(format: &str, dt: OffsetDateTime) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let Some(spec) = chars.next() else {
+            out.push('%');
+            break;
+        };
+        let _ = match spec {
+            'Y' => write!(out, "{}", dt.year()),
+            'C' => write!(out, "{:02}", dt.year().div_euclid(100)),
+            'y' => write!(out, "{:02}", dt.year().rem_euclid(100)),
+            'G' => write!(out, "{}", dt.to_iso_week_date().0),
+            'V' => write!(out, "{:02}", dt.iso_week()),
+            'm' => write!(out, "{:02}", u8::from(dt.month())),
+            'B' => write!(out, "{}", dt.month()),
+            'b' | 'h' => write!(out, "{}", &dt.month().to_string()[..3]),
+            'd' => write!(out, "{:02}", dt.day()),
+            'e' => write!(out, "{:2}", dt.day()),
+            'j' => write!(out, "{:03}", dt.ordinal()),
+            'A' => write!(out, "{}", dt.weekday()),
+            'a' => write!(out, "{}", &dt.weekday().to_string()[..3]),
+            'u' => write!(out, "{}", dt.weekday().number_from_monday()),
+            'w' => write!(out, "{}", dt.weekday().number_days_from_sunday()),
+            'H' => write!(out, "{:02}", dt.hour()),
+            'I' => write!(out, "{:02}", (dt.hour() + 11) % 12 + 1),
+            'M' => write!(out, "{:02}", dt.minute()),
+            'S' => write!(out, "{:02}", dt.second()),
+            'p' => write!(out, "{}", if dt.hour() < 12 { "AM" } else { "PM" }),
+            's' => write!(out, "{}", dt.unix_timestamp()),
+            'z' => {
+                let (hours, minutes, _) = dt.offset().as_hms();
+                let sign = if dt.offset().is_negative() { '-' } else { '+' };
+                write!(out, "{sign}{:02}{:02}", hours.abs(), minutes.abs())
+            }
+            'F' => write!(out, "{}", format_datetime("%Y-%m-%d", dt)),
+            'T' => write!(out, "{}", format_datetime("%H:%M:%S", dt)),
+            'D' => write!(out, "{}", format_datetime("%m/%d/%y", dt)),
+            'R' => write!(out, "{}", format_datetime("%H:%M", dt)),
+            'r' => write!(out, "{}", format_datetime("%I:%M:%S %p", dt)),
+            'n' => out.write_char('\n'),
+            't' => out.write_char('\t'),
+            '%' => out.write_char('%'),
+            _ => write!(out, "%{spec}"),
+        };
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month};
+
+    #[test]
+    fn formats_like_strftime() {
+        let dt = Date::from_calendar_date(2024, Month::March, 5)
+            .unwrap()
+            .with_hms(14, 7, 9)
+            .unwrap()
+            .assume_offset(UtcOffset::from_hms(-5, -30, 0).unwrap());
+        assert_eq!(format_datetime("%F %T %z", dt), "2024-03-05 14:07:09 -0530");
+        assert_eq!(
+            format_datetime("%a %A %b %B %e %j %u %w", dt),
+            "Tue Tuesday Mar March  5 065 2 2"
+        );
+        assert_eq!(
+            format_datetime("%D %r %I%p %C %y %G-W%V %% %s", dt),
+            "03/05/24 02:07:09 PM 02PM 20 24 2024-W10 % 1709667429"
+        );
+    }
+}
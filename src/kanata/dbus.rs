@@ -0,0 +1,48 @@
+//! The connection to the D-Bus session bus of the actions that talk to desktop services on Linux,
+//! and of the tray. The marshalling and the object server are those of `zbus`, with its blocking
+//! API, so that the threads of the clients stay plain threads.
+
+use std::time::Duration;
+use zbus::blocking::Connection;
+use zbus::blocking::connection::Builder;
+
+/// How long a method call waits for its reply, so that a service that hangs doesn't stop the
+/// thread that calls it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connects to the session bus.
+pub(crate) fn session() -> zbus::Result<Connection> {
+    session_builder()?.build()
+}
+
+/// A builder for a connection to the session bus, e.g. to serve objects on it.
+pub(crate) fn session_builder() -> zbus::Result<Builder<'static>> {
+    Ok(Builder::session()?.method_timeout(TIMEOUT))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    /// A connection to a peer that serves what `serve` adds to its builder, instead of a bus. The
+    /// peer connection is returned too, so that it lives as long as the test needs it.
+    pub(crate) fn connection_pair(
+        serve: impl FnOnce(Builder<'static>) -> zbus::Result<Builder<'static>> + Send + 'static,
+    ) -> (Connection, Connection) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = std::thread::spawn(move || {
+            let builder = Builder::async_io_unix_stream(server)
+                .server(zbus::Guid::generate())
+                .unwrap()
+                .p2p();
+            serve(builder).unwrap().build().unwrap()
+        });
+        let client = Builder::async_io_unix_stream(client)
+            .p2p()
+            .method_timeout(TIMEOUT)
+            .build()
+            .unwrap();
+        (client, server.join().unwrap())
+    }
+}
@@ -0,0 +1,156 @@
+//! Debouncing of the input with `defdebounce`, before the events reach the layout.
+//!
+//! A worn switch can chatter, i.e. be read as pressed and released several times within a few
+//! milliseconds at a single press, which kanata would otherwise use as several taps. A key that
+//! changed is debounced for its debounce time: with `eager`, the change is used at once and
+//! further changes are ignored until the time has passed, after which the key is changed to
+//! the state it is in if that is different. With `defer`, a change is only used once the key
+//! has stayed in that state for the debounce time.
+
+use super::*;
+use std::num::NonZeroU8;
+
+#[derive(Debug, Default)]
+pub(crate) struct Debouncer {
+    cfgs: Vec<Debounce>,
+    /// The keys that are being debounced.
+    keys: Vec<DebouncedKey>,
+}
+
+#[derive(Debug)]
+struct DebouncedKey {
+    device: Option<NonZeroU8>,
+    code: OsCode,
+    mode: DebounceMode,
+    /// Whether the key is pressed as far as the layout is concerned.
+    pressed: bool,
+    /// Whether the key is pressed as far as the device is concerned.
+    device_pressed: bool,
+    remaining_ms: u16,
+    ms: u16,
+}
+
+impl Debouncer {
+    pub(crate) fn new(cfgs: Vec<Debounce>) -> Self {
+        Self { cfgs, keys: vec![] }
+    }
+
+    /// Uses the debouncing of a reloaded configuration. Keys that are being debounced finish
+    /// with the debouncing that they started with.
+    pub(crate) fn set_cfgs(&mut self, cfgs: Vec<Debounce>) {
+        self.cfgs = cfgs;
+    }
+
+    /// Whether keys are being debounced, during which the processing loop must keep ticking.
+    pub(crate) fn pending(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    fn cfg_of(&self, device: Option<NonZeroU8>) -> Option<&Debounce> {
+        self.cfgs
+            .iter()
+            .find(|cfg| cfg.device.is_some() && cfg.device == device)
+            .or_else(|| self.cfgs.iter().find(|cfg| cfg.device.is_none()))
+    }
+
+    /// Returns the event to handle now, or None if it is debounced.
+    fn input(&mut self, event: &KeyEvent) -> Option<KeyEvent> {
+        let pressed = match event.value {
+            KeyValue::Press => true,
+            KeyValue::Release => false,
+            // The key is only repeated while it is pressed as far as the layout is concerned.
+            KeyValue::Repeat => {
+                return self
+                    .keys
+                    .iter()
+                    .find(|key| key.code == event.code && key.device == event.device_id())
+                    .is_none_or(|key| key.pressed)
+                    .then_some(*event);
+            }
+            KeyValue::Tap | KeyValue::WakeUp => return Some(*event),
+        };
+        let device = event.device_id();
+        if let Some(key) = self
+            .keys
+            .iter_mut()
+            .find(|key| key.code == event.code && key.device == device)
+        {
+            key.device_pressed = pressed;
+            if key.mode == DebounceMode::Defer {
+                key.remaining_ms = key.ms;
+            }
+            return None;
+        }
+        let Some(cfg) = self.cfg_of(device) else {
+            return Some(*event);
+        };
+        let (mode, ms) = (cfg.mode, cfg.ms_of(event.code));
+        if ms == 0 {
+            return Some(*event);
+        }
+        self.keys.push(DebouncedKey {
+            device,
+            code: event.code,
+            mode,
+            pressed: match mode {
+                DebounceMode::Eager => pressed,
+                DebounceMode::Defer => !pressed,
+            },
+            device_pressed: pressed,
+            remaining_ms: ms,
+            ms,
+        });
+        match mode {
+            DebounceMode::Eager => Some(*event),
+            DebounceMode::Defer => None,
+        }
+    }
+
+    /// Counts down a millisecond and returns the changes of keys that are due.
+    fn tick(&mut self) -> Vec<KeyEvent> {
+        let mut due = vec![];
+        self.keys.retain_mut(|key| {
+            key.remaining_ms -= 1;
+            if key.remaining_ms > 0 {
+                return true;
+            }
+            if key.pressed == key.device_pressed {
+                return false;
+            }
+            key.pressed = key.device_pressed;
+            let value = match key.pressed {
+                true => KeyValue::Press,
+                false => KeyValue::Release,
+            };
+            let mut event = KeyEvent::new(key.code, value);
+            event.set_device_id(key.device);
+            due.push(event);
+            // The change that eager debouncing used late is debounced like any other.
+            key.remaining_ms = key.ms;
+            key.mode == DebounceMode::Eager
+        });
+        due
+    }
+}
+
+impl Kanata {
+    /// Returns the event to handle now, or None if it is debounced.
+    pub(crate) fn debounce_input_event(&mut self, event: &KeyEvent) -> Option<KeyEvent> {
+        let event = self.debouncer.input(event);
+        if event.is_none() {
+            tracing::debug!("debounced");
+        }
+        event
+    }
+
+    /// Handles the changes of keys that debouncing used late.
+    pub(crate) fn tick_debounce(&mut self) -> Result<()> {
+        if !self.debouncer.pending() {
+            return Ok(());
+        }
+        for event in self.debouncer.tick() {
+            self.handle_debounced_input_event(&event)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! Drops the duplicate input events of `dedupe-input-ms`.
+//!
+//! Some keyboards, e.g. with some wireless receivers, expose two device nodes that both report
+//! every event of the keyboard, so that every key would be typed twice. The duplicate of an
+//! event arrives within a few milliseconds and changes the key to the state that it is already
+//! in, which a real keyboard never does: a key is released before it is pressed again. So an
+//! event with the same value as the previous event of the key is dropped if it arrives within
+//! `dedupe-input-ms` of it. A fast double tap alternates presses and releases and is kept.
+
+use super::*;
+
+#[derive(Debug, Default)]
+pub(crate) struct Deduplicator {
+    /// Milliseconds within which a duplicate is dropped, or 0 if none are.
+    window_ms: u16,
+    /// The previous event of the keys that changed within the window, with the milliseconds
+    /// that remain of it.
+    recent: Vec<(OsCode, KeyValue, u16)>,
+}
+
+impl Deduplicator {
+    pub(crate) fn new(window_ms: u16) -> Self {
+        Self {
+            window_ms,
+            recent: vec![],
+        }
+    }
+
+    /// Whether the window of an event is open, during which the processing loop must keep
+    /// ticking.
+    pub(crate) fn pending(&self) -> bool {
+        !self.recent.is_empty()
+    }
+
+    /// Returns whether the event is a duplicate of the previous event of the key.
+    fn is_duplicate(&mut self, event: &KeyEvent) -> bool {
+        if self.window_ms == 0
+            || !matches!(
+                event.value,
+                KeyValue::Press | KeyValue::Release | KeyValue::Repeat
+            )
+        {
+            return false;
+        }
+        match self
+            .recent
+            .iter_mut()
+            .find(|(code, ..)| *code == event.code)
+        {
+            Some((_, value, _)) if *value == event.value => true,
+            Some((_, value, remaining_ms)) => {
+                *value = event.value;
+                *remaining_ms = self.window_ms;
+                false
+            }
+            None => {
+                self.recent.push((event.code, event.value, self.window_ms));
+                false
+            }
+        }
+    }
+
+    /// Counts down a millisecond of the windows.
+    fn tick(&mut self) {
+        self.recent.retain_mut(|(_, _, remaining_ms)| {
+            *remaining_ms -= 1;
+            *remaining_ms > 0
+        });
+    }
+}
+
+impl Kanata {
+    /// Returns whether the event is a duplicate to drop.
+    pub(crate) fn dedupe_input_event(&mut self, event: &KeyEvent) -> bool {
+        let duplicate = self.deduplicator.is_duplicate(event);
+        if duplicate {
+            tracing::debug!("dropped as a duplicate");
+        }
+        duplicate
+    }
+
+    pub(crate) fn tick_dedupe(&mut self) {
+        if self.deduplicator.pending() {
+            self.deduplicator.tick();
+        }
+    }
+}
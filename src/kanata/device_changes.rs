@@ -0,0 +1,54 @@
+//! Notifies TCP clients when input devices connect and disconnect, e.g. a Bluetooth keyboard
+//! that goes to sleep and wakes up again.
+//!
+//! The event loop releases the keys of a device that disconnected and grabs it again when it
+//! reconnects, so the state of kanata, e.g. toggled layers, stays as it was.
+
+use super::*;
+
+/// An input device that was registered or removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceChange {
+    pub path: String,
+    pub name: String,
+    pub connected: bool,
+}
+
+impl Kanata {
+    /// Keeps the device changes for the processing loop, which notifies TCP clients of them.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn push_device_changes(&mut self, changes: Vec<DeviceChange>) {
+        for change in changes.iter() {
+            match change.connected {
+                true => info!("device connected: {} {:?}", change.path, change.name),
+                false => info!("device disconnected: {} {:?}", change.path, change.name),
+            }
+        }
+        self.device_changes.extend(changes);
+    }
+
+    pub(crate) fn check_handle_device_changes(&mut self, _tx: &Option<Sender<ServerMessage>>) {
+        if self.device_changes.is_empty() {
+            return;
+        }
+        let _changes = std::mem::take(&mut self.device_changes);
+        #[cfg(feature = "tcp_server")]
+        if let Some(tx) = _tx {
+            for DeviceChange {
+                path,
+                name,
+                connected,
+            } in _changes
+            {
+                let msg = ServerMessage::DeviceChange {
+                    path,
+                    name,
+                    connected,
+                };
+                if let Err(error) = tx.try_send(msg) {
+                    tracing::error!("could not send event notification: {}", error);
+                }
+            }
+        }
+    }
+}
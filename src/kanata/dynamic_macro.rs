@@ -116,7 +116,7 @@ pub fn tick_replay_state(
             match state.macro_items.pop_front() {
                 None => {
                     *replay_state = None;
-                    log::debug!("finished macro replay");
+                    tracing::debug!("finished macro replay");
                     None
                 }
                 Some(i) => match i {
@@ -162,7 +162,7 @@ pub fn begin_record_macro(
 ) -> Option<(u16, Vec<DynamicMacroItem>)> {
     match record_state.take() {
         None => {
-            log::info!("starting dynamic macro {macro_id} recording");
+            tracing::info!("starting dynamic macro {macro_id} recording");
             *record_state = Some(DynamicMacroRecordState::new(macro_id));
             None
         }
@@ -184,13 +184,13 @@ pub fn begin_record_macro(
             state.add_release_for_all_unreleased_presses();
 
             if state.starting_macro_id == macro_id {
-                log::info!(
+                tracing::info!(
                     "same macro id pressed. saving and stopping dynamic macro {} recording",
                     state.starting_macro_id
                 );
                 *record_state = None;
             } else {
-                log::info!(
+                tracing::info!(
                     "saving dynamic macro {} recording then starting new macro recording {macro_id}",
                     state.starting_macro_id,
                 );
@@ -215,7 +215,7 @@ pub fn record_press(
         // considering the number of keys they want, kanata does the multiplication
         // instead.
         if state.macro_items.len() > usize::from(max_presses) * 2 {
-            log::warn!(
+            tracing::warn!(
                 "saving and stopping dynamic macro {} recording due to exceeding limit",
                 state.starting_macro_id,
             );
@@ -223,7 +223,7 @@ pub fn record_press(
             let state = record_state.take().unwrap();
             Some((state.starting_macro_id, state.macro_items))
         } else {
-            log::debug!("delay to press: {}", state.current_delay);
+            tracing::debug!("delay to press: {}", state.current_delay);
             state.add_event(osc, WaitingEventType::Press);
             None
         }
@@ -234,7 +234,7 @@ pub fn record_press(
 
 pub fn record_release(record_state: &mut Option<DynamicMacroRecordState>, osc: OsCode) {
     if let Some(state) = record_state {
-        log::debug!("delay to release: {}", state.current_delay);
+        tracing::debug!("delay to release: {}", state.current_delay);
         state.add_event(osc, WaitingEventType::Release);
     }
 }
@@ -260,7 +260,7 @@ pub fn stop_macro(
         // since it's almost certainly a "macro record stop" key press
         // action which we don't want to keep.
         state.macro_items.remove(state.macro_items.len() - 1);
-        log::info!(
+        tracing::info!(
             "saving and stopping dynamic macro {} recording with {num_actions_to_remove} actions at the end removed",
             state.starting_macro_id,
         );
@@ -284,11 +284,11 @@ pub fn play_macro(
 ) {
     match replay_state {
         None => {
-            log::info!("replaying macro {macro_id}");
+            tracing::info!("replaying macro {macro_id}");
             *replay_state = recorded_macros.get(&macro_id).map(|macro_items| {
                 let mut active_macros = HashSet::default();
                 active_macros.insert(macro_id);
-                log::debug!("playing macro {macro_items:?}");
+                tracing::debug!("playing macro {macro_items:?}");
                 DynamicMacroReplayState {
                     active_macros,
                     delay_remaining: 0,
@@ -298,10 +298,10 @@ pub fn play_macro(
         }
         Some(state) => {
             if state.active_macros.contains(&macro_id) {
-                log::warn!("refusing to recurse into macro {macro_id}");
+                tracing::warn!("refusing to recurse into macro {macro_id}");
             } else if let Some(items) = recorded_macros.get(&macro_id) {
-                log::debug!("prepending macro {macro_id} items to current replay");
-                log::debug!("playing macro {items:?}");
+                tracing::debug!("prepending macro {macro_id} items to current replay");
+                tracing::debug!("playing macro {items:?}");
                 state.active_macros.insert(macro_id);
                 state
                     .macro_items
@@ -0,0 +1,377 @@
+//! Emergency key chords, configured with the `emergency-*-chord` defcfg options.
+//!
+//! The chords are checked in the event loops, before events reach the processing loop, so that
+//! they keep working even if kanata's processing is stuck or the configuration makes the
+//! keyboard unusable.
+//!
+//! With the `passthru_ahk` feature, the chords are not checked at all.
+
+#![cfg_attr(feature = "passthru_ahk", allow(dead_code))]
+
+use super::*;
+
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+
+static EMERGENCY_CHORDS: Lazy<Mutex<EmergencyChords>> =
+    Lazy::new(|| Mutex::new(EmergencyChords::new(&CfgOptions::default())));
+
+/// While true, the event loops hand input events directly to the OS instead of sending them
+/// to the processing loop.
+static EMERGENCY_PASSTHROUGH: AtomicBool = AtomicBool::new(false);
+
+/// What an event loop should do with an input event after checking the emergency chords.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EmergencyChordCheck {
+    /// Process the event as usual.
+    Continue,
+    /// Hand the event to the OS without processing it.
+    Forward,
+    /// The event completed a chord that entered or left passthrough. The event should be
+    /// consumed and the processing loop woken up so that it can react to the new state.
+    Toggled,
+    /// The event completed the exit chord.
+    Exit,
+}
+
+#[derive(Debug)]
+struct EmergencyChords {
+    exit: Option<Vec<OsCode>>,
+    passthrough: Option<Vec<OsCode>>,
+    reengage: Option<Vec<OsCode>>,
+    pressed: HashSet<OsCode>,
+    /// Keys whose press was handed to the OS during passthrough. Their release must also be
+    /// handed to the OS even if kanata has been re-engaged in the meantime.
+    forwarded: HashSet<OsCode>,
+}
+
+impl EmergencyChords {
+    fn new(cfg: &CfgOptions) -> Self {
+        Self {
+            exit: cfg.emergency_exit_chord.clone(),
+            passthrough: cfg.emergency_passthrough_chord.clone(),
+            reengage: cfg.emergency_reengage_chord.clone(),
+            pressed: Default::default(),
+            forwarded: Default::default(),
+        }
+    }
+
+    fn is_completed_by(&self, chord: Option<&Vec<OsCode>>, osc: OsCode) -> bool {
+        chord.is_some_and(|keys| {
+            keys.contains(&osc) && keys.iter().all(|k| self.pressed.contains(k))
+        })
+    }
+
+    fn check(&mut self, event: &KeyEvent, passthrough: bool) -> EmergencyChordCheck {
+        use EmergencyChordCheck::*;
+        let forward_if_passthrough = if passthrough { Forward } else { Continue };
+        match event.value {
+            KeyValue::Press => {
+                // Windows reports repeats as presses.
+                if !self.pressed.insert(event.code) {
+                    return self.repeat_check(event.code, passthrough);
+                }
+            }
+            KeyValue::Release => {
+                self.pressed.remove(&event.code);
+                if self.forwarded.remove(&event.code) {
+                    return Forward;
+                }
+                return forward_if_passthrough;
+            }
+            KeyValue::Repeat => return self.repeat_check(event.code, passthrough),
+            _ => return forward_if_passthrough,
+        }
+        if self.is_completed_by(self.exit.as_ref(), event.code) {
+            return Exit;
+        }
+        let toggle_chord = match passthrough {
+            false => self.passthrough.as_ref(),
+            true => self.reengage.as_ref().or(self.passthrough.as_ref()),
+        };
+        if self.is_completed_by(toggle_chord, event.code) {
+            return Toggled;
+        }
+        if passthrough {
+            self.forwarded.insert(event.code);
+        }
+        forward_if_passthrough
+    }
+
+    fn repeat_check(&self, osc: OsCode, passthrough: bool) -> EmergencyChordCheck {
+        match passthrough || self.forwarded.contains(&osc) {
+            true => EmergencyChordCheck::Forward,
+            false => EmergencyChordCheck::Continue,
+        }
+    }
+}
+
+/// Replaces the configured emergency chords.
+pub(crate) fn set_emergency_chords(cfg: &CfgOptions) {
+    let mut chords = EMERGENCY_CHORDS.lock();
+    let pressed = std::mem::take(&mut chords.pressed);
+    let forwarded = std::mem::take(&mut chords.forwarded);
+    *chords = EmergencyChords::new(cfg);
+    chords.pressed = pressed;
+    chords.forwarded = forwarded;
+}
+
+/// Forgets which keys are pressed. Used when key releases might have been missed, e.g. while
+/// input devices are grabbed again.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn reset_emergency_chord_keys() {
+    let mut chords = EMERGENCY_CHORDS.lock();
+    chords.pressed.clear();
+    chords.forwarded.clear();
+}
+
+/// Returns true if an emergency chord has put kanata into passthrough.
+pub(crate) fn is_emergency_passthrough_active() -> bool {
+    EMERGENCY_PASSTHROUGH.load(SeqCst)
+}
+
+/// Enters or leaves passthrough without a chord, e.g. when the watchdog finds processing stuck.
+/// Returns whether passthrough changed.
+pub(crate) fn set_emergency_passthrough(active: bool) -> bool {
+    EMERGENCY_PASSTHROUGH.swap(active, SeqCst) != active
+}
+
+/// Checks the event against the emergency chords. Exits kanata if the exit chord was pressed.
+/// Never returns [`EmergencyChordCheck::Exit`].
+pub(crate) fn check_emergency_chords(_event: &KeyEvent) -> EmergencyChordCheck {
+    #[cfg(feature = "passthru_ahk")]
+    {
+        EmergencyChordCheck::Continue
+    }
+    #[cfg(not(feature = "passthru_ahk"))]
+    {
+        let passthrough = is_emergency_passthrough_active();
+        let check = EMERGENCY_CHORDS.lock().check(_event, passthrough);
+        match check {
+            EmergencyChordCheck::Exit => {
+                emergency_exit();
+                EmergencyChordCheck::Continue
+            }
+            EmergencyChordCheck::Toggled => {
+                EMERGENCY_PASSTHROUGH.store(!passthrough, SeqCst);
+                match passthrough {
+                    false => tracing::warn!("pressed the emergency passthrough chord"),
+                    true => tracing::info!("pressed the emergency re-engage chord"),
+                }
+                check
+            }
+            _ => check,
+        }
+    }
+}
+
+/// Logs the emergency chords that are configured, for display at startup.
+pub(crate) fn log_emergency_chords() {
+    fn chord_str(chord: &[OsCode]) -> String {
+        chord
+            .iter()
+            .map(|osc| osc.to_string())
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+    let chords = EMERGENCY_CHORDS.lock();
+    if let Some(exit) = chords.exit.as_ref() {
+        tracing::info!(
+            "You may forcefully exit kanata by pressing {} at any time. \
+                These keys refer to defsrc input, meaning BEFORE kanata remaps keys.",
+            chord_str(exit)
+        );
+    }
+    if let Some(passthrough) = chords.passthrough.as_ref() {
+        tracing::info!(
+            "You may stop kanata from remapping keys by pressing {} at any time \
+                and re-engage it with {}.",
+            chord_str(passthrough),
+            chord_str(chords.reengage.as_ref().unwrap_or(passthrough)),
+        );
+    }
+}
+
+fn emergency_exit() {
+    const EXIT_MSG: &str = "pressed the emergency exit chord, exiting";
+    tracing::info!("{EXIT_MSG}");
+    #[cfg(all(target_os = "windows", feature = "gui"))]
+    {
+        #[cfg(not(feature = "interception_driver"))]
+        native_windows_gui::stop_thread_dispatch();
+        #[cfg(feature = "interception_driver")]
+        send_gui_exit_notice(); // interception driver is running in another thread to allow
+        // GUI take the main one, so it's calling check_emergency_chords
+        // from a thread that has no access to the main one, so
+        // can't stop main thread's dispatch
+    }
+    // macOS: use `libc::_exit` instead of `std::process::exit` to
+    // skip C++ static destructors. The underlying pqrs shared
+    // dispatcher (used by the karabiner-driverkit crate) has a
+    // teardown race where its destructor kills its own worker
+    // threads while they still hold a `std::mutex`, which then
+    // throws `std::system_error: mutex lock failed` on
+    // `pthread_mutex_destroy`. That exception is uncaught and
+    // aborts the process with a cryptic libc++abi message right
+    // after the user's kill chord. `_exit` bypasses the whole
+    // mess by jumping straight to the kernel exit syscall.
+    //
+    // Flush stdio first so any buffered log output (including the
+    // "exiting" line we just emitted) actually reaches the user.
+    #[cfg(target_os = "macos")]
+    {
+        use std::io::Write;
+        let _ = std::io::stderr().flush();
+        let _ = std::io::stdout().flush();
+        let code = EMERGENCY_EXIT_CODE.load(SeqCst);
+        // SAFETY: `_exit` has no preconditions; it terminates the
+        // process immediately without running user destructors.
+        unsafe {
+            libc::_exit(code);
+        }
+    }
+    // Linux/Android: Use SIGTERM to trigger signal handler for cleanup
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        signal_hook::low_level::raise(signal_hook::consts::SIGTERM).expect("raise signal");
+    }
+    // Windows non-GUI: Direct exit (no cleanup needed)
+    #[cfg(all(target_os = "windows", not(feature = "gui")))]
+    {
+        let code = EMERGENCY_EXIT_CODE.load(SeqCst);
+        std::process::exit(code);
+    }
+    // Unsupported platforms: panic to indicate emergency exit isn't implemented
+    #[cfg(not(any(
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "android",
+        target_os = "windows"
+    )))]
+    {
+        panic!("{EXIT_MSG}");
+    }
+}
+
+impl Kanata {
+    /// Reacts to an emergency chord entering or leaving passthrough by releasing all active
+    /// keys and notifying TCP clients.
+    pub(crate) fn check_handle_emergency_passthrough(
+        &mut self,
+        _tx: &Option<Sender<ServerMessage>>,
+    ) {
+        let active = is_emergency_passthrough_active();
+        if active == self.emergency_passthrough {
+            return;
+        }
+        self.emergency_passthrough = active;
+        if active {
+            tracing::warn!(
+                "emergency passthrough: input goes directly to the OS without remapping"
+            );
+        } else {
+            tracing::info!("emergency passthrough ended: kanata is remapping input again");
+        }
+        // Inputs kanata saw before the switch will have their releases handled elsewhere, so
+        // drop them here to avoid stuck keys.
+        release_normalkey_states(self.layout.bm());
+        PRESSED_KEYS.lock().clear();
+        self.software_repeat = None;
+        #[cfg(feature = "tcp_server")]
+        if let Some(tx) = _tx {
+            match tx.try_send(ServerMessage::EmergencyPassthrough { active }) {
+                Ok(_) => {}
+                Err(error) => {
+                    tracing::error!("could not send event notification: {}", error);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use EmergencyChordCheck::*;
+
+    fn chords(passthrough: &[OsCode], reengage: Option<&[OsCode]>) -> EmergencyChords {
+        EmergencyChords::new(&CfgOptions {
+            emergency_passthrough_chord: Some(passthrough.to_vec()),
+            emergency_reengage_chord: reengage.map(|keys| keys.to_vec()),
+            ..Default::default()
+        })
+    }
+
+    fn press(osc: OsCode) -> KeyEvent {
+        KeyEvent::new(osc, KeyValue::Press)
+    }
+
+    fn release(osc: OsCode) -> KeyEvent {
+        KeyEvent::new(osc, KeyValue::Release)
+    }
+
+    #[test]
+    fn exit_chord_is_lctl_spc_esc_by_default() {
+        let mut c = EmergencyChords::new(&CfgOptions::default());
+        assert_eq!(c.check(&press(OsCode::KEY_LEFTCTRL), false), Continue);
+        assert_eq!(c.check(&press(OsCode::KEY_SPACE), false), Continue);
+        assert_eq!(c.check(&press(OsCode::KEY_ESC), false), Exit);
+    }
+
+    #[test]
+    fn exit_chord_can_be_disabled() {
+        let mut c = EmergencyChords::new(&CfgOptions {
+            emergency_exit_chord: None,
+            ..Default::default()
+        });
+        assert_eq!(c.check(&press(OsCode::KEY_LEFTCTRL), false), Continue);
+        assert_eq!(c.check(&press(OsCode::KEY_SPACE), false), Continue);
+        assert_eq!(c.check(&press(OsCode::KEY_ESC), false), Continue);
+    }
+
+    #[test]
+    fn passthrough_chord_toggles() {
+        let chord = [OsCode::KEY_RIGHTCTRL, OsCode::KEY_BACKSPACE];
+        let mut c = chords(&chord, None);
+        assert_eq!(c.check(&press(OsCode::KEY_RIGHTCTRL), false), Continue);
+        assert_eq!(c.check(&press(OsCode::KEY_BACKSPACE), false), Toggled);
+        // Repeats of the chord keys must not toggle again.
+        assert_eq!(c.check(&press(OsCode::KEY_BACKSPACE), true), Forward);
+        assert_eq!(c.check(&release(OsCode::KEY_BACKSPACE), true), Forward);
+        assert_eq!(c.check(&release(OsCode::KEY_RIGHTCTRL), true), Forward);
+        assert_eq!(c.check(&press(OsCode::KEY_A), true), Forward);
+        assert_eq!(c.check(&release(OsCode::KEY_A), true), Forward);
+        assert_eq!(c.check(&press(OsCode::KEY_RIGHTCTRL), true), Forward);
+        assert_eq!(c.check(&press(OsCode::KEY_BACKSPACE), true), Toggled);
+        // The OS saw the press of rctl so it must also see the release.
+        assert_eq!(c.check(&release(OsCode::KEY_BACKSPACE), false), Continue);
+        assert_eq!(c.check(&release(OsCode::KEY_RIGHTCTRL), false), Forward);
+        assert_eq!(c.check(&press(OsCode::KEY_A), false), Continue);
+    }
+
+    #[test]
+    fn reengage_chord_differs_from_passthrough_chord() {
+        let mut c = chords(
+            &[OsCode::KEY_RIGHTCTRL, OsCode::KEY_BACKSPACE],
+            Some(&[OsCode::KEY_RIGHTCTRL, OsCode::KEY_ENTER]),
+        );
+        assert_eq!(c.check(&press(OsCode::KEY_RIGHTCTRL), false), Continue);
+        assert_eq!(c.check(&press(OsCode::KEY_BACKSPACE), false), Toggled);
+        assert_eq!(c.check(&release(OsCode::KEY_BACKSPACE), true), Forward);
+        assert_eq!(c.check(&press(OsCode::KEY_BACKSPACE), true), Forward);
+        assert_eq!(c.check(&release(OsCode::KEY_BACKSPACE), true), Forward);
+        assert_eq!(c.check(&press(OsCode::KEY_ENTER), true), Toggled);
+    }
+
+    #[test]
+    fn repeats_of_forwarded_keys_are_forwarded() {
+        let mut c = chords(&[OsCode::KEY_RIGHTCTRL, OsCode::KEY_BACKSPACE], None);
+        assert_eq!(c.check(&press(OsCode::KEY_A), true), Forward);
+        assert_eq!(c.check(&press(OsCode::KEY_A), false), Forward);
+        let repeat = KeyEvent::new(OsCode::KEY_A, KeyValue::Repeat);
+        assert_eq!(c.check(&repeat, false), Forward);
+        assert_eq!(c.check(&release(OsCode::KEY_A), false), Forward);
+        assert_eq!(c.check(&press(OsCode::KEY_A), false), Continue);
+        assert_eq!(c.check(&repeat, false), Continue);
+        assert_eq!(c.check(&release(OsCode::KEY_A), false), Continue);
+    }
+}
@@ -0,0 +1,430 @@
+//! The queue that carries input events from the event loop to the processing loop.
+//!
+//! It is a bounded ring buffer that senders and the receiver access without locks, so that a key
+//! event is never delayed by another thread holding a lock. Only a receiver that has nothing to do
+//! sleeps, and the sender that wakes it up is the only one to take a lock.
+//!
+//! Overflow policy: events are never dropped, since a lost release leaves a key stuck.
+//! [`EventSender::try_send`] fails when the queue is full and [`EventSender::send`] waits for the
+//! processing loop to make room. Both are counted in [`QueueMetrics`].
+//!
+//! The queue has several producers rather than the one of an SPSC queue, because the processing
+//! loop also wakes up for events that don't come from the event loop: the TCP server, the
+//! configuration and OS layout watchers, signals, the watchdog, cmd notifications, the user
+//! session watcher and the tray send wakeups through clones of the sender, macOS's mouse tap sends
+//! mouse events from its own thread, and on Windows the keyboard and mouse hooks both send to the
+//! preprocessor. Giving each of them its own SPSC queue would make the receiver poll all of them,
+//! so the senders claim slots with a compare-and-swap on `tail` instead, which costs a key event
+//! nothing more than an SPSC push when no other thread sends at the same time.
+//!
+//! The mutex of the sleep path holds the handle of the receiving thread. A sender only takes it
+//! after seeing that the receiver is asleep, i.e. when the processing loop has nothing to do, so
+//! it can't delay the handling of an event that is already queued.
+
+use crate::oskbd::{KeyEvent, KeyValue};
+use kanata_parser::keys::OsCode;
+use parking_lot::Mutex;
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+use std::thread::Thread;
+use std::time::{Duration, Instant};
+
+/// Creates a queue that holds up to `capacity` events.
+pub fn event_queue(capacity: usize) -> (EventSender, EventReceiver) {
+    assert!(capacity > 0, "event queue needs a capacity");
+    let queue = Arc::new(Queue {
+        slots: (0..capacity)
+            .map(|i| Slot {
+                seq: AtomicUsize::new(i),
+                event: UnsafeCell::new(KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp)),
+            })
+            .collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        senders: AtomicUsize::new(1),
+        receiver_dropped: AtomicBool::new(false),
+        receiver_sleeping: AtomicBool::new(false),
+        receiver_thread: Mutex::new(None),
+        sent: AtomicU64::new(0),
+        full: AtomicU64::new(0),
+        max_len: AtomicUsize::new(0),
+    });
+    (
+        EventSender {
+            queue: queue.clone(),
+        },
+        EventReceiver { queue },
+    )
+}
+
+/// Counters of an event queue since it was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueMetrics {
+    /// Events that were queued.
+    pub sent: u64,
+    /// Sends that found the queue full.
+    pub full: u64,
+    /// The most events that were waiting at once.
+    pub max_len: usize,
+}
+
+struct Slot {
+    /// Equals the position of the slot when it is free to write and the position plus one when it
+    /// holds an event to read.
+    seq: AtomicUsize,
+    event: UnsafeCell<KeyEvent>,
+}
+
+struct Queue {
+    slots: Box<[Slot]>,
+    /// Position of the next event to read. Only the receiver changes it.
+    head: AtomicUsize,
+    /// Position of the next event to write.
+    tail: AtomicUsize,
+    senders: AtomicUsize,
+    receiver_dropped: AtomicBool,
+    receiver_sleeping: AtomicBool,
+    receiver_thread: Mutex<Option<Thread>>,
+    sent: AtomicU64,
+    full: AtomicU64,
+    max_len: AtomicUsize,
+}
+
+// SAFETY: a slot's event is only written by the sender that claimed the slot through `tail`, and
+// only read by the receiver once `seq` says the write is done.
+unsafe impl Sync for Queue {}
+
+impl Queue {
+    fn push(&self, event: KeyEvent) -> Result<(), KeyEvent> {
+        let cap = self.slots.len();
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % cap];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match (seq.wrapping_sub(pos) as isize).signum() {
+                0 => match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { *slot.event.get() = event };
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        break;
+                    }
+                    Err(current) => pos = current,
+                },
+                // The slot still holds an event from one lap ago.
+                -1 => return Err(event),
+                _ => pos = self.tail.load(Ordering::Relaxed),
+            }
+        }
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        let len = pos
+            .wrapping_add(1)
+            .wrapping_sub(self.head.load(Ordering::Relaxed));
+        self.max_len.fetch_max(len.min(cap), Ordering::Relaxed);
+        self.wake_receiver();
+        Ok(())
+    }
+
+    fn has_event(&self) -> bool {
+        let pos = self.head.load(Ordering::Relaxed);
+        self.slots[pos % self.slots.len()]
+            .seq
+            .load(Ordering::Acquire)
+            == pos.wrapping_add(1)
+    }
+
+    fn pop(&self) -> Option<KeyEvent> {
+        if !self.has_event() {
+            return None;
+        }
+        let pos = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[pos % self.slots.len()];
+        let event = unsafe { *slot.event.get() };
+        slot.seq
+            .store(pos.wrapping_add(self.slots.len()), Ordering::Release);
+        self.head.store(pos.wrapping_add(1), Ordering::Relaxed);
+        Some(event)
+    }
+
+    fn disconnected(&self) -> bool {
+        self.senders.load(Ordering::Acquire) == 0
+    }
+
+    fn wake_receiver(&self) {
+        // Pairs with the fence in `EventReceiver::wait`: either the receiver sees the new event
+        // before sleeping, or this sees that it sleeps.
+        std::sync::atomic::fence(Ordering::SeqCst);
+        if self.receiver_sleeping.load(Ordering::Relaxed)
+            && let Some(thread) = &*self.receiver_thread.lock()
+        {
+            thread.unpark();
+        }
+    }
+
+    fn len(&self) -> usize {
+        let len = self
+            .tail
+            .load(Ordering::Relaxed)
+            .wrapping_sub(self.head.load(Ordering::Relaxed));
+        len.min(self.slots.len())
+    }
+
+    fn metrics(&self) -> QueueMetrics {
+        QueueMetrics {
+            sent: self.sent.load(Ordering::Relaxed),
+            full: self.full.load(Ordering::Relaxed),
+            max_len: self.max_len.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Sends events to the [`EventReceiver`] of the same queue.
+pub struct EventSender {
+    queue: Arc<Queue>,
+}
+
+impl EventSender {
+    /// Queues the event, or returns it if the queue is full or the receiver is gone.
+    pub fn try_send(&self, event: KeyEvent) -> Result<(), TrySendError<KeyEvent>> {
+        if self.queue.receiver_dropped.load(Ordering::Relaxed) {
+            return Err(TrySendError::Disconnected(event));
+        }
+        self.queue.push(event).map_err(|event| {
+            self.queue.full.fetch_add(1, Ordering::Relaxed);
+            TrySendError::Full(event)
+        })
+    }
+
+    /// Queues the event, waiting for the receiver to make room if the queue is full.
+    pub fn send(&self, mut event: KeyEvent) -> Result<(), SendError<KeyEvent>> {
+        let mut waited = false;
+        loop {
+            if self.queue.receiver_dropped.load(Ordering::Relaxed) {
+                return Err(SendError(event));
+            }
+            match self.queue.push(event) {
+                Ok(()) => return Ok(()),
+                Err(ev) => {
+                    if !waited {
+                        waited = true;
+                        self.queue.full.fetch_add(1, Ordering::Relaxed);
+                        tracing::warn!(
+                            "event queue full, waiting for the processing loop: {:?}",
+                            self.metrics()
+                        );
+                    }
+                    event = ev;
+                    std::thread::sleep(Duration::from_micros(100));
+                }
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.queue.metrics()
+    }
+}
+
+impl Clone for EventSender {
+    fn clone(&self) -> Self {
+        self.queue.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        if self.queue.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.queue.wake_receiver();
+        }
+    }
+}
+
+/// Receives the events of an [`EventSender`] in the order they were sent.
+pub struct EventReceiver {
+    queue: Arc<Queue>,
+}
+
+impl EventReceiver {
+    pub fn try_recv(&self) -> Result<KeyEvent, TryRecvError> {
+        match self.queue.pop() {
+            Some(event) => Ok(event),
+            // Events sent before the last sender dropped are still received.
+            None if self.queue.disconnected() => self.queue.pop().ok_or(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn recv(&self) -> Result<KeyEvent, RecvError> {
+        self.wait(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<KeyEvent, RecvTimeoutError> {
+        self.wait(Some(Instant::now() + timeout))
+    }
+
+    fn wait(&self, deadline: Option<Instant>) -> Result<KeyEvent, RecvTimeoutError> {
+        loop {
+            match self.try_recv() {
+                Ok(event) => return Ok(event),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) if !timeout.is_zero() => Some(timeout),
+                    _ => return Err(RecvTimeoutError::Timeout),
+                },
+                None => None,
+            };
+            self.queue
+                .receiver_thread
+                .lock()
+                .get_or_insert_with(std::thread::current);
+            self.queue.receiver_sleeping.store(true, Ordering::Relaxed);
+            std::sync::atomic::fence(Ordering::SeqCst);
+            if !self.queue.has_event() && !self.queue.disconnected() {
+                match timeout {
+                    Some(timeout) => std::thread::park_timeout(timeout),
+                    None => std::thread::park(),
+                }
+            }
+            self.queue.receiver_sleeping.store(false, Ordering::Relaxed);
+        }
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.queue.metrics()
+    }
+
+    /// A handle that reads the length of the queue from another thread.
+    pub fn monitor(&self) -> QueueMonitor {
+        QueueMonitor {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.queue.receiver_dropped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Reads the length of a queue without sending or receiving, e.g. for `RequestStats`. It does not
+/// count as a sender, so the queue still disconnects when the last [`EventSender`] drops.
+#[derive(Clone)]
+pub struct QueueMonitor {
+    queue: Arc<Queue>,
+}
+
+impl QueueMonitor {
+    /// The number of events that wait to be received.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.queue.slots.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: OsCode) -> KeyEvent {
+        KeyEvent::new(code, KeyValue::Press)
+    }
+
+    #[test]
+    fn keeps_order_and_reports_full() {
+        let (tx, rx) = event_queue(2);
+        let monitor = rx.monitor();
+        tx.try_send(key(OsCode::KEY_A)).unwrap();
+        tx.try_send(key(OsCode::KEY_B)).unwrap();
+        assert_eq!((monitor.len(), monitor.capacity()), (2, 2));
+        assert!(matches!(
+            tx.try_send(key(OsCode::KEY_C)),
+            Err(TrySendError::Full(_))
+        ));
+        assert_eq!(rx.try_recv().unwrap().code, OsCode::KEY_A);
+        tx.try_send(key(OsCode::KEY_C)).unwrap();
+        assert_eq!(rx.try_recv().unwrap().code, OsCode::KEY_B);
+        assert_eq!(rx.try_recv().unwrap().code, OsCode::KEY_C);
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+        assert!(monitor.is_empty());
+        assert_eq!(
+            rx.metrics(),
+            QueueMetrics {
+                sent: 3,
+                full: 1,
+                max_len: 2
+            }
+        );
+    }
+
+    #[test]
+    fn disconnects_after_last_sender() {
+        let (tx, rx) = event_queue(4);
+        let wakeup = tx.clone();
+        tx.send(key(OsCode::KEY_A)).unwrap();
+        drop(tx);
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_millis(1)),
+            Ok(KeyEvent {
+                code: OsCode::KEY_A,
+                ..
+            })
+        ));
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+        drop(wakeup);
+        assert!(rx.recv().is_err());
+
+        let (tx, rx) = event_queue(4);
+        drop(rx);
+        assert!(tx.send(key(OsCode::KEY_A)).is_err());
+    }
+
+    #[test]
+    fn senders_wake_up_blocked_receiver() {
+        const PER_SENDER: usize = 10_000;
+        let (tx, rx) = event_queue(8);
+        let senders = [OsCode::KEY_A, OsCode::KEY_B]
+            .map(|code| {
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..PER_SENDER {
+                        tx.send(key(code)).unwrap();
+                    }
+                })
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+        drop(tx);
+        let mut received = 0;
+        while rx.recv().is_ok() {
+            received += 1;
+        }
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        assert_eq!(received, 2 * PER_SENDER);
+        assert_eq!(rx.metrics().sent, 2 * PER_SENDER as u64);
+    }
+}
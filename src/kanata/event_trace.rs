@@ -0,0 +1,269 @@
+//! `--event-trace`: writes every input event, decision of the layout and output event as a line
+//! of JSON to a file, to find out why keys came out as they did.
+//!
+//! The lines are written by a separate thread so that the file does not delay the processing.
+//! The file is a ring buffer of two files: when it has the maximum number of events, it is moved
+//! to `FILE.1` and a new file is started, so the last events are always kept.
+
+use super::*;
+use kanata_keyberon::decision_trace::{Decision, WaitingKind};
+use kanata_keyberon::layout::{KCoord, WaitingAction};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+enum TraceMessage {
+    Line(String),
+    /// Answered once the lines before it are written.
+    Flush(std::sync::mpsc::SyncSender<()>),
+}
+
+struct EventTrace {
+    tx: std::sync::mpsc::Sender<TraceMessage>,
+    start: web_time::Instant,
+}
+
+static EVENT_TRACE: OnceLock<EventTrace> = OnceLock::new();
+
+/// Starts writing the trace to the file, which is replaced. 0 events means no limit.
+pub fn enable_event_trace(path: PathBuf, max_events: usize) -> io::Result<()> {
+    let file = TraceFile::create(path, max_events)?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::Builder::new()
+        .name("event-trace".into())
+        .spawn(move || file.write_lines(rx))?;
+    let _ = EVENT_TRACE.set(EventTrace {
+        tx,
+        start: clock::now(),
+    });
+    Ok(())
+}
+
+/// Waits until the events so far are written, e.g. before exiting.
+pub fn flush_event_trace() {
+    let Some(trace) = EVENT_TRACE.get() else {
+        return;
+    };
+    let (tx, rx) = std::sync::mpsc::sync_channel(1);
+    if trace.tx.send(TraceMessage::Flush(tx)).is_ok() {
+        let _ = rx.recv_timeout(std::time::Duration::from_secs(1));
+    }
+}
+
+/// Whether events are traced, to the file or for the crash bundle.
+pub fn event_trace_enabled() -> bool {
+    EVENT_TRACE.get().is_some() || crash_bundle_enabled()
+}
+
+/// Adds an event to the trace if it is enabled, and to the recent events of the crash bundle. The
+/// line starts with the microseconds since the trace started as `t_us` and the kind of event,
+/// followed by the fields.
+pub(crate) fn trace_event(event: &'static str, fields: impl FnOnce() -> serde_json::Value) {
+    let trace = EVENT_TRACE.get();
+    let crash_bundle = crash_bundle_enabled();
+    if trace.is_none() && !crash_bundle {
+        return;
+    }
+    let fields = fields();
+    if let Some(trace) = trace {
+        let t_us = clock::now().duration_since(trace.start).as_micros();
+        let _ = trace
+            .tx
+            .send(TraceMessage::Line(trace_line(t_us, event, &fields)));
+    }
+    if crash_bundle {
+        record_recent_event(event, &fields);
+    }
+}
+
+fn trace_line(t_us: u128, event: &str, fields: &serde_json::Value) -> String {
+    let fields = fields.to_string();
+    match fields.strip_prefix('{').filter(|rest| *rest != "}") {
+        Some(rest) => format!(r#"{{"t_us":{t_us},"event":"{event}",{rest}"#),
+        None => format!(r#"{{"t_us":{t_us},"event":"{event}"}}"#),
+    }
+}
+
+/// The name of the key in the configuration, so that the trace can be replayed.
+pub(super) fn key_name(osc: OsCode) -> String {
+    oscode_to_str(osc)
+        .map(str::to_owned)
+        .unwrap_or_else(|| osc.to_string().to_lowercase())
+}
+
+fn key_value_name(value: KeyValue) -> &'static str {
+    match value {
+        KeyValue::Press => "press",
+        KeyValue::Release => "release",
+        KeyValue::Repeat => "repeat",
+        KeyValue::Tap => "tap",
+        KeyValue::WakeUp => "wakeup",
+    }
+}
+
+/// The physical key at the coordinate, or the coordinate for virtual keys and chords.
+fn coord_fields(coord: KCoord) -> serde_json::Value {
+    match coord {
+        (NORMAL_KEY_ROW, y) => serde_json::json!({ "key": key_name(OsCode::from(y)) }),
+        (x, y) => serde_json::json!({ "coord": [x, y] }),
+    }
+}
+
+pub(super) fn trace_input(event: &KeyEvent) {
+    trace_event(
+        "input",
+        || serde_json::json!({ "key": key_name(event.code), "value": key_value_name(event.value) }),
+    );
+}
+
+pub(super) fn trace_output(osc: OsCode, value: KeyValue) {
+    trace_event(
+        "output",
+        || serde_json::json!({ "key": key_name(osc), "value": key_value_name(value) }),
+    );
+}
+
+pub(super) fn trace_layer(name: &str) {
+    trace_event("layer", || serde_json::json!({ "layer": name }));
+}
+
+pub(super) fn trace_stuck_key(osc: OsCode, held_ms: u64) {
+    trace_event(
+        "stuck-key",
+        || serde_json::json!({ "key": key_name(osc), "held_ms": held_ms }),
+    );
+}
+
+/// Traces the decisions made in the tick of the layout.
+pub(super) fn trace_decisions<const C: usize, const R: usize, T: std::fmt::Debug>(
+    layout: &mut Layout<'_, C, R, T>,
+) {
+    for decision in layout.decision_trace.drain() {
+        match decision {
+            Decision::Waiting {
+                coord,
+                kind,
+                action,
+                ticks,
+            } => trace_event("decision", || {
+                let mut fields = coord_fields(coord);
+                fields["kind"] = match kind {
+                    WaitingKind::HoldTap => "tap-hold",
+                    WaitingKind::TapDance => "tap-dance",
+                    WaitingKind::Chord => "chord",
+                }
+                .into();
+                fields["decision"] = match action {
+                    WaitingAction::Tap => "tap",
+                    WaitingAction::Hold => "hold",
+                    WaitingAction::Timeout => "timeout",
+                    WaitingAction::NoOp => "none",
+                }
+                .into();
+                fields["after_ms"] = ticks.into();
+                fields
+            }),
+            Decision::ChordV2 { keys, .. } => trace_event("chord", || {
+                let keys: Vec<String> = keys.iter().map(|k| key_name(OsCode::from(*k))).collect();
+                serde_json::json!({ "keys": keys })
+            }),
+        }
+    }
+}
+
+struct TraceFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    events: usize,
+    max_events: usize,
+}
+
+impl TraceFile {
+    fn create(path: PathBuf, max_events: usize) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(&path)?);
+        // The previous part of the ring is from an earlier trace.
+        let _ = std::fs::remove_file(Self::previous_path(&path));
+        Ok(Self {
+            path,
+            file,
+            events: 0,
+            max_events,
+        })
+    }
+
+    fn previous_path(path: &Path) -> PathBuf {
+        let mut previous = path.as_os_str().to_owned();
+        previous.push(".1");
+        previous.into()
+    }
+
+    fn write_lines(mut self, rx: Receiver<TraceMessage>) {
+        while let Ok(message) = rx.recv() {
+            // Flush once no more lines are waiting, so that the file is current when kanata
+            // stops without the file being written for every line when many arrive at once.
+            let mut flushed = vec![];
+            let result = std::iter::once(message)
+                .chain(rx.try_iter())
+                .try_for_each(|message| match message {
+                    TraceMessage::Line(line) => self.write_line(&line),
+                    TraceMessage::Flush(tx) => {
+                        flushed.push(tx);
+                        Ok(())
+                    }
+                })
+                .and_then(|_| self.file.flush());
+            for tx in flushed {
+                let _ = tx.send(());
+            }
+            if let Err(e) = result {
+                tracing::error!("stopping the event trace: {e}");
+                return;
+            }
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.max_events > 0 && self.events >= self.max_events {
+            self.file.flush()?;
+            std::fs::rename(&self.path, Self::previous_path(&self.path))?;
+            self.file = BufWriter::new(File::create(&self.path)?);
+            self.events = 0;
+        }
+        self.events += 1;
+        writeln!(self.file, "{line}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_line_starts_with_time_and_event() {
+        assert_eq!(
+            trace_line(12, "input", &serde_json::json!({ "key": "a" })),
+            r#"{"t_us":12,"event":"input","key":"a"}"#
+        );
+        assert_eq!(
+            trace_line(12, "start", &serde_json::json!({})),
+            r#"{"t_us":12,"event":"start"}"#
+        );
+    }
+
+    #[test]
+    fn trace_file_keeps_the_last_events() {
+        let dir = std::env::temp_dir().join(format!("kanata-event-trace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("trace.jsonl");
+        let mut file = TraceFile::create(path.clone(), 2).unwrap();
+        for i in 0..5 {
+            file.write_line(&i.to_string()).unwrap();
+        }
+        file.file.flush().unwrap();
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&TraceFile::previous_path(&path)), "2\n3\n");
+        assert_eq!(read(&path), "4\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! `fallback-cfg`: a configuration that kanata starts with when the configuration cannot be
+//! used, e.g. because of a typo synced from elsewhere, so that the keyboard keeps working.
+//!
+//! A configuration that fails to parse has no options, so the option is then looked up in its
+//! text. Reloading loads the configuration again, not the fallback.
+
+use super::*;
+use std::path::Path;
+
+/// The value of the `fallback-cfg` option in the text of a configuration that doesn't parse.
+/// Comments are skipped, but the rest of the text doesn't need to be valid.
+fn fallback_cfg_in_text(text: &str) -> Option<String> {
+    const OPTION: &str = "fallback-cfg";
+    let mut in_block_comment = false;
+    for line in text.lines() {
+        let mut line = line;
+        if in_block_comment {
+            let Some(end) = line.find("|#") else {
+                continue;
+            };
+            line = &line[end + 2..];
+            in_block_comment = false;
+        }
+        if let Some(start) = line.find("#|") {
+            in_block_comment = !line[start..].contains("|#");
+            line = &line[..start];
+        }
+        let line = line.split(";;").next().unwrap_or_default();
+        let Some(start) = line.find(OPTION) else {
+            continue;
+        };
+        let value = line[start + OPTION.len()..].trim_start();
+        let value = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split('"').next(),
+            None => value.split([' ', '\t', ')', '(']).next(),
+        };
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            return Some(value.to_owned());
+        }
+    }
+    None
+}
+
+/// The path of the fallback of the configuration file, from its options or, if it didn't parse,
+/// from its text. A relative path is relative to the directory of the configuration.
+pub(super) fn fallback_cfg_path(cfg_path: &Path, options: Option<&CfgOptions>) -> Option<PathBuf> {
+    let fallback = match options {
+        Some(options) => options.fallback_cfg.clone(),
+        None => fallback_cfg_in_text(&std::fs::read_to_string(cfg_path).ok()?),
+    }?;
+    Some(cfg_path.parent().unwrap_or(Path::new("")).join(fallback))
+}
+
+/// Parses the fallback of the configuration file, which could not be used for the reason.
+pub(super) fn load_fallback_cfg(
+    cfg_path: &Path,
+    options: Option<&CfgOptions>,
+    reason: &str,
+) -> Option<Cfg> {
+    let path = fallback_cfg_path(cfg_path, options)?;
+    tracing::error!(
+        "{reason}, starting with the fallback configuration {}",
+        path.display()
+    );
+    match cfg::new_from_file(&path) {
+        Ok(cfg) => {
+            tracing::warn!(
+                "running with the fallback configuration, fix {} and reload",
+                cfg_path.display()
+            );
+            Some(cfg)
+        }
+        Err(e) => {
+            tracing::error!("the fallback configuration failed to parse too: {e:?}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_cfg_is_found_in_broken_text() {
+        let text = r#"
+;; fallback-cfg commented.kbd
+#| fallback-cfg
+   block.kbd |#
+(defcfg process-unmapped-keys yes fallback-cfg "safe mode.kbd")
+(defsrc a b
+(deflayer base a"#;
+        assert_eq!(fallback_cfg_in_text(text).as_deref(), Some("safe mode.kbd"));
+        assert_eq!(
+            fallback_cfg_in_text("(defcfg fallback-cfg safe.kbd)").as_deref(),
+            Some("safe.kbd")
+        );
+        assert_eq!(fallback_cfg_in_text("(defsrc a) (deflayer base a"), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! The health of the parts of kanata that run in their own threads, for `RequestStats`, so that a
+//! monitoring client can tell which one is stuck when keys stop responding.
+//!
+//! Each part counts its running threads, and marks when it starts working and when it waits,
+//! e.g. for input. A part that has been working for long, or whose threads are gone, is the one to
+//! look at. The depth of the input queue tells whether events pile up in front of the processing
+//! loop.
+
+#![cfg_attr(not(feature = "tcp_server"), allow(dead_code))]
+
+use super::*;
+use kanata_tcp_protocol::{ChannelDepth, ThreadHealth};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// The event loop that reads input from the OS and sends it to the processing loop.
+pub(crate) static INPUT_READER: Subsystem = Subsystem::new("input-reader");
+/// The processing loop, see [`Kanata::start_processing_loop`].
+pub(crate) static PROCESSOR: Subsystem = Subsystem::new("processor");
+/// The TCP server and the thread that relays notifications to its clients.
+pub(crate) static TCP_SERVER: Subsystem = Subsystem::new("tcp-server");
+/// The threads of `cmd` actions, one per command that runs.
+pub(crate) static CMD_EXECUTOR: Subsystem = Subsystem::new("cmd-executor");
+
+static SUBSYSTEMS: [&Subsystem; 4] = [&INPUT_READER, &PROCESSOR, &TCP_SERVER, &CMD_EXECUTOR];
+
+static INPUT_QUEUE: Mutex<Option<QueueMonitor>> = Mutex::new(None);
+
+pub(crate) struct Subsystem {
+    name: &'static str,
+    started: AtomicBool,
+    threads: AtomicU32,
+    /// Milliseconds since the start of kanata at which the work it is doing started, or [`IDLE`]
+    /// while it waits.
+    busy_since: AtomicU64,
+    /// Milliseconds since the start of kanata at which it last started or stopped working, or
+    /// [`IDLE`] if it never did.
+    last_active: AtomicU64,
+}
+
+impl Subsystem {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            started: AtomicBool::new(false),
+            threads: AtomicU32::new(0),
+            busy_since: AtomicU64::new(IDLE),
+            last_active: AtomicU64::new(IDLE),
+        }
+    }
+
+    /// Counts the current thread as running for as long as the guard lives.
+    pub(crate) fn running(&'static self) -> Running {
+        self.started.store(true, Ordering::Relaxed);
+        self.threads.fetch_add(1, Ordering::Relaxed);
+        Running(self)
+    }
+
+    /// Marks that it is working.
+    pub(crate) fn busy(&self) {
+        let now = now_ms();
+        self.busy_since.store(now, Ordering::Relaxed);
+        self.last_active.store(now, Ordering::Relaxed);
+    }
+
+    /// Marks that it waits, which is not a stall however long it takes.
+    pub(crate) fn idle(&self) {
+        self.busy_since.store(IDLE, Ordering::Relaxed);
+        self.last_active.store(now_ms(), Ordering::Relaxed);
+    }
+
+    /// Marks that it did something without staying busy, e.g. in a callback of the OS.
+    #[cfg(all(target_os = "windows", not(feature = "interception_driver")))]
+    pub(crate) fn active(&self) {
+        self.last_active.store(now_ms(), Ordering::Relaxed);
+    }
+
+    /// Marks that it is working for as long as the guard lives.
+    pub(crate) fn working(&self) -> Working<'_> {
+        self.busy();
+        Working(self)
+    }
+
+    pub(super) fn busy_since(&self) -> u64 {
+        self.busy_since.load(Ordering::Relaxed)
+    }
+
+    /// The health at `now_ms`, or None if it never ran.
+    fn health(&self, now_ms: u64) -> Option<ThreadHealth> {
+        if !self.started.load(Ordering::Relaxed) {
+            return None;
+        }
+        let ms_ago = |at: u64| (at != IDLE).then(|| now_ms.saturating_sub(at));
+        Some(ThreadHealth {
+            name: self.name.to_owned(),
+            threads: self.threads.load(Ordering::Relaxed),
+            busy_ms: ms_ago(self.busy_since()),
+            last_active_ms_ago: ms_ago(self.last_active.load(Ordering::Relaxed)),
+        })
+    }
+}
+
+pub(crate) struct Running(&'static Subsystem);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if self.0.threads.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.0.idle();
+        }
+    }
+}
+
+pub(crate) struct Working<'a>(&'a Subsystem);
+
+impl Drop for Working<'_> {
+    fn drop(&mut self) {
+        self.0.idle();
+    }
+}
+
+/// Reports the depth of the queue that the processing loop receives input from.
+pub(super) fn watch_input_queue(queue: QueueMonitor) {
+    *INPUT_QUEUE.lock() = Some(queue);
+}
+
+/// The health of the parts that ran since kanata started.
+pub(crate) fn thread_health() -> Vec<ThreadHealth> {
+    let now = now_ms();
+    SUBSYSTEMS.iter().filter_map(|s| s.health(now)).collect()
+}
+
+/// The depth of the input queue of the processing loop, once it runs.
+pub(crate) fn input_queue_depth() -> Option<ChannelDepth> {
+    INPUT_QUEUE.lock().as_ref().map(|queue| ChannelDepth {
+        name: "input-events".to_owned(),
+        len: queue.len() as u64,
+        capacity: queue.capacity() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_tracks_threads_and_work() {
+        static PART: Subsystem = Subsystem::new("part");
+        assert_eq!(PART.health(0), None);
+        let running = PART.running();
+        let health = PART.health(now_ms()).unwrap();
+        assert_eq!((health.threads, health.busy_ms), (1, None));
+        assert_eq!(health.last_active_ms_ago, None);
+        {
+            let _working = PART.working();
+            let health = PART.health(now_ms() + 50).unwrap();
+            assert!(health.busy_ms.is_some_and(|ms| ms >= 50));
+        }
+        assert_eq!(PART.health(now_ms()).unwrap().busy_ms, None);
+        PART.busy();
+        drop(running);
+        let health = PART.health(now_ms()).unwrap();
+        assert_eq!((health.threads, health.busy_ms), (0, None));
+        assert!(health.last_active_ms_ago.is_some());
+    }
+}
@@ -43,24 +43,26 @@ impl Kanata {
         for layer in active_held_layers {
             held_layer_active = true;
             if let Some(outputs_for_key) = self.key_outputs[usize::from(layer)].get(&event.code) {
-                log::debug!("key outs for active layer-while-held: {outputs_for_key:?};");
+                tracing::debug!("key outs for active layer-while-held: {outputs_for_key:?};");
                 for osc in outputs_for_key.iter().rev().copied() {
                     let kc = osc.into();
                     if self.cur_keys.contains(&kc)
                         || self.unshifted_keys.contains(&kc)
                         || self.unmodded_keys.contains(&kc)
                     {
-                        log::debug!("repeat    {:?}", KeyCode::from(osc));
-                        if let Err(e) = write_key(&mut self.kbd_out, osc, KeyValue::Repeat) {
-                            bail!("could not write key {e:?}")
-                        }
-                        return Ok(());
+                        return write_os_repeat(
+                            &mut self.kbd_out,
+                            &self.key_repeat,
+                            &self.no_repeat_keys,
+                            usize::from(layer),
+                            osc,
+                        );
                     }
                 }
             }
         }
         if held_layer_active {
-            log::debug!("empty layer-while-held outputs, probably transparent");
+            tracing::debug!("empty layer-while-held outputs, probably transparent");
         }
 
         if let Some(outputs_for_key) =
@@ -72,18 +74,20 @@ impl Kanata {
             // 1. current layer is the default layer
             // 2. current layer is layer-while-held but did not find a match in the code above, e.g. a
             //    transparent key was pressed.
-            log::debug!("key outs for default layer: {outputs_for_key:?};");
+            tracing::debug!("key outs for default layer: {outputs_for_key:?};");
             for osc in outputs_for_key.iter().rev().copied() {
                 let kc = osc.into();
                 if self.cur_keys.contains(&kc)
                     || self.unshifted_keys.contains(&kc)
                     || self.unmodded_keys.contains(&kc)
                 {
-                    log::debug!("repeat    {:?}", KeyCode::from(osc));
-                    if let Err(e) = write_key(&mut self.kbd_out, osc, KeyValue::Repeat) {
-                        bail!("could not write key {e:?}")
-                    }
-                    return Ok(());
+                    return write_os_repeat(
+                        &mut self.kbd_out,
+                        &self.key_repeat,
+                        &self.no_repeat_keys,
+                        self.layout.b().default_layer,
+                        osc,
+                    );
                 }
             }
         }
@@ -91,15 +95,105 @@ impl Kanata {
         // Reached here and have not exited yet.
         // Check the standard key output itself because default layer might also be transparent
         // and have delegated to defsrc handling.
-        log::debug!("checking defsrc output");
+        tracing::debug!("checking defsrc output");
         let kc = event.code.into();
-        if (self.cur_keys.contains(&kc)
+        if self.cur_keys.contains(&kc)
             || self.unshifted_keys.contains(&kc)
-            || self.unmodded_keys.contains(&kc))
-            && let Err(e) = write_key(&mut self.kbd_out, event.code, KeyValue::Repeat)
+            || self.unmodded_keys.contains(&kc)
         {
-            bail!("could not write key {e:?}");
+            return write_os_repeat(
+                &mut self.kbd_out,
+                &self.key_repeat,
+                &self.no_repeat_keys,
+                self.layout.b().current_layer(),
+                event.code,
+            );
         }
         Ok(())
     }
+
+    /// Outputs repeats for keys configured with software repeat in `defrepeat`.
+    ///
+    /// This runs before the key state changes of the tick are processed, so `prev_keys` holds the
+    /// keys that are currently pressed.
+    pub(super) fn tick_software_repeat(&mut self) -> Result<()> {
+        let Some(state) = &mut self.software_repeat else {
+            return Ok(());
+        };
+        if !self.prev_keys.contains(&state.osc.into()) {
+            self.software_repeat = None;
+            return Ok(());
+        }
+        state.ticks_until_repeat -= 1;
+        if state.ticks_until_repeat > 0 {
+            return Ok(());
+        }
+        state.ticks_until_repeat = state.interval;
+        if let Some(seq) = self.sequence_state.get_active()
+            && seq.sequence_input_mode != SequenceInputMode::VisibleBackspaced
+        {
+            // Same as hardware repeat, see handle_repeat_actual.
+            return Ok(());
+        }
+        tracing::debug!("sw repeat {:?}", KeyCode::from(state.osc));
+        if let Err(e) = write_key(&mut self.kbd_out, state.osc, KeyValue::Repeat) {
+            bail!("could not write key {e:?}")
+        }
+        Ok(())
+    }
+}
+
+pub(super) struct SoftwareRepeatState {
+    osc: OsCode,
+    ticks_until_repeat: u16,
+    interval: u16,
+}
+
+impl SoftwareRepeatState {
+    /// Returns the repeat state for a newly pressed key. Like typical OS repeat, only the most
+    /// recently pressed key repeats, so this replaces any previous state.
+    pub(super) fn on_press(
+        key_repeat: &cfg::KeyRepeatCfg,
+        layer: usize,
+        osc: OsCode,
+    ) -> Option<Self> {
+        match key_repeat.behaviour(layer, osc) {
+            KeyRepeatBehaviour::Software { delay, interval } => Some(Self {
+                osc,
+                ticks_until_repeat: delay,
+                interval,
+            }),
+            KeyRepeatBehaviour::Os | KeyRepeatBehaviour::Disabled => None,
+        }
+    }
+}
+
+/// Writes a repeat received from the OS or keyboard, unless `defrepeat` configures the key to not
+/// use OS repeats or the key was pressed on a layer with `repeat none`.
+fn write_os_repeat(
+    kbd_out: &mut KbdOut,
+    key_repeat: &cfg::KeyRepeatCfg,
+    no_repeat_keys: &[KeyCode],
+    layer: usize,
+    osc: OsCode,
+) -> Result<()> {
+    if no_repeat_keys.contains(&osc.into()) {
+        tracing::debug!(
+            "skip repeat {:?} pressed on a layer without repeat",
+            KeyCode::from(osc)
+        );
+        return Ok(());
+    }
+    match key_repeat.behaviour(layer, osc) {
+        KeyRepeatBehaviour::Os => {}
+        KeyRepeatBehaviour::Disabled | KeyRepeatBehaviour::Software { .. } => {
+            tracing::debug!("skip repeat {:?}", KeyCode::from(osc));
+            return Ok(());
+        }
+    }
+    tracing::debug!("repeat    {:?}", KeyCode::from(osc));
+    if let Err(e) = write_key(kbd_out, osc, KeyValue::Repeat) {
+        bail!("could not write key {e:?}")
+    }
+    Ok(())
 }
@@ -0,0 +1,209 @@
+//! `--measure-latency`: measures how long kanata takes to handle input events.
+//!
+//! Events are timestamped when the event loop receives them, and the latency is recorded once
+//! the tick that processed them has written its output. Events whose output is deferred, e.g. the
+//! press of a tap-hold key, count the time until kanata was done with them, not until the
+//! deferred output.
+
+use super::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static MEASURE_LATENCY: AtomicBool = AtomicBool::new(false);
+
+/// How often the latency is logged while events are measured.
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Latencies below this many microseconds each have their own bucket. Above it, every power of
+/// two is split into `SUB_BUCKETS` buckets, which keeps the error of a percentile below 1/32.
+const LINEAR_US: u64 = 64;
+const SUB_BUCKETS: u64 = 32;
+const SUB_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+/// Enough buckets for latencies up to 2^32 µs, longer ones are put into the last bucket.
+const BUCKETS: usize =
+    (LINEAR_US + (32 - LINEAR_US.trailing_zeros() as u64) * SUB_BUCKETS) as usize;
+
+pub fn enable_latency_measurement() {
+    MEASURE_LATENCY.store(true, Ordering::Relaxed);
+}
+
+pub fn latency_measurement_enabled() -> bool {
+    MEASURE_LATENCY.load(Ordering::Relaxed)
+}
+
+/// The time to stamp a received input event with, if latency is measured.
+pub(crate) fn received_at() -> Option<web_time::Instant> {
+    latency_measurement_enabled().then(web_time::Instant::now)
+}
+
+/// Latencies in microseconds, see [`LatencyHistogram::summary`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+/// A histogram of latencies with a bounded relative error, so that it takes constant memory
+/// regardless of how many events are measured.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    buckets: Box<[u64; BUCKETS]>,
+    count: u64,
+    max_us: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: Box::new([0; BUCKETS]),
+            count: 0,
+            max_us: 0,
+        }
+    }
+}
+
+fn bucket(us: u64) -> usize {
+    if us < LINEAR_US {
+        return us as usize;
+    }
+    let exp = us.ilog2();
+    let sub = (us >> (exp - SUB_BITS)) & (SUB_BUCKETS - 1);
+    let idx = LINEAR_US + u64::from(exp - LINEAR_US.trailing_zeros()) * SUB_BUCKETS + sub;
+    (idx as usize).min(BUCKETS - 1)
+}
+
+/// The largest latency that falls into the bucket.
+fn bucket_max(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < LINEAR_US {
+        return idx;
+    }
+    let exp = (idx - LINEAR_US) / SUB_BUCKETS + u64::from(LINEAR_US.trailing_zeros());
+    let sub = (idx - LINEAR_US) % SUB_BUCKETS;
+    ((SUB_BUCKETS + sub + 1) << (exp - u64::from(SUB_BITS))) - 1
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let us = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket(us)] += 1;
+        self.count += 1;
+        self.max_us = self.max_us.max(us);
+    }
+
+    /// Returns the median, the 99th percentile and the maximum of the recorded latencies. The
+    /// percentiles are rounded up to the end of their bucket but never exceed the maximum.
+    pub fn summary(&self) -> LatencySummary {
+        let percentile = |p: u64| {
+            // The rank of the percentile, rounded up so that p99 of few samples is the largest.
+            let rank = (self.count * p).div_ceil(100).max(1);
+            let mut seen = 0;
+            for (idx, n) in self.buckets.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    return bucket_max(idx).min(self.max_us);
+                }
+            }
+            self.max_us
+        };
+        if self.count == 0 {
+            return LatencySummary::default();
+        }
+        LatencySummary {
+            count: self.count,
+            p50_us: percentile(50),
+            p99_us: percentile(99),
+            max_us: self.max_us,
+        }
+    }
+}
+
+impl Kanata {
+    /// Records the latency of the events that the last tick processed.
+    pub(super) fn record_latency(&mut self, events: &[KeyEvent]) {
+        let Some(latency) = &mut self.latency else {
+            return;
+        };
+        let now = web_time::Instant::now();
+        let received = events
+            .iter()
+            .filter(|ev| ev.value != KeyValue::WakeUp)
+            .filter_map(|ev| ev.received());
+        for received in received {
+            latency.histogram.record(now.duration_since(received));
+        }
+        if now.duration_since(latency.logged_at) >= LOG_INTERVAL {
+            latency.logged_at = now;
+            let s = latency.histogram.summary();
+            tracing::info!(
+                "latency over {} events: p50 {}µs, p99 {}µs, max {}µs",
+                s.count,
+                s.p50_us,
+                s.p99_us,
+                s.max_us
+            );
+        }
+    }
+
+    /// The latency measured so far, if it is measured.
+    pub fn latency_summary(&self) -> Option<LatencySummary> {
+        self.latency.as_ref().map(|l| l.histogram.summary())
+    }
+}
+
+/// The state of `--measure-latency` in [`Kanata`].
+#[derive(Debug)]
+pub(super) struct LatencyMeasurement {
+    histogram: LatencyHistogram,
+    logged_at: web_time::Instant,
+}
+
+impl LatencyMeasurement {
+    pub(super) fn new_if_enabled() -> Option<Self> {
+        latency_measurement_enabled().then(|| Self {
+            histogram: LatencyHistogram::default(),
+            logged_at: web_time::Instant::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_cover_latencies_in_order() {
+        let mut prev_max = None;
+        for idx in 0..BUCKETS {
+            let max = bucket_max(idx);
+            assert_eq!(bucket(max), idx);
+            if let Some(prev) = prev_max {
+                assert!(max > prev);
+                assert_eq!(bucket(prev + 1), idx);
+            }
+            prev_max = Some(max);
+        }
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn summary_percentiles() {
+        let mut h = LatencyHistogram::default();
+        assert_eq!(h.summary(), LatencySummary::default());
+        for us in 1..=1000 {
+            h.record(Duration::from_micros(us));
+        }
+        h.record(Duration::from_millis(50));
+        let s = h.summary();
+        assert_eq!(s.count, 1001);
+        assert_eq!(s.max_us, 50_000);
+        assert!((501..=501 + 501 / 32).contains(&s.p50_us), "{s:?}");
+        assert!((991..=991 + 991 / 32).contains(&s.p99_us), "{s:?}");
+
+        let mut h = LatencyHistogram::default();
+        h.record(Duration::from_micros(700));
+        assert_eq!(h.summary().p99_us, 700);
+    }
+}
@@ -5,21 +5,23 @@
 
 use anyhow::{Result, anyhow, bail};
 use evdev::{EventSummary, InputEvent, RelativeAxisCode};
-use log::info;
 use parking_lot::Mutex;
 use std::convert::TryFrom;
 use std::sync::Arc;
-use std::sync::mpsc::SyncSender as Sender;
+use tracing::info;
 
 use super::*;
 
 impl Kanata {
     /// Enter an infinite loop that listens for OS key events and sends them to the processing
     /// thread.
-    pub fn event_loop(kanata: Arc<Mutex<Self>>, tx: Sender<KeyEvent>) -> Result<()> {
+    pub fn event_loop(kanata: Arc<Mutex<Self>>, tx: EventSender) -> Result<()> {
         info!("entering the event loop");
 
-        let k = kanata.lock();
+        let mut k = kanata.lock();
+        if k.realtime_priority {
+            raise_thread_priority("input");
+        }
         let allow_hardware_repeat = k.allow_hardware_repeat;
         let mouse_movement_key = k.mouse_movement_key.clone();
         let mut kbd_in = match KbdIn::new(
@@ -28,6 +30,9 @@ impl Kanata {
             k.include_names.clone(),
             k.exclude_names.clone(),
             k.device_detect_mode,
+            k.seat.clone(),
+            k.input_backend,
+            k.input_devices.clone().unwrap_or_default(),
         ) {
             Ok(kbd_in) => kbd_in,
             Err(e) => {
@@ -35,40 +40,93 @@ impl Kanata {
             }
         };
 
+        let cur_layer = k.layout.bm().current_layer();
+        let leds = k.layer_leds[cur_layer];
+        k.kbd_out.set_lock_leds(leds);
+
         // In some environments, this needs to be done after the input device grab otherwise it
         // does not work on kanata startup.
         Kanata::set_repeat_rate(k.x11_repeat_rate)?;
         drop(k);
 
+        let _running = INPUT_READER.running();
+        let mut events = Vec::new();
+        let mut device_ids = Vec::new();
         loop {
-            let events = kbd_in.read().map_err(|e| anyhow!("failed read: {}", e))?;
-            log::trace!("event count: {}\nevents:\n{events:?}", events.len());
+            INPUT_READER.idle();
+            kbd_in
+                .read(&mut events, &mut device_ids)
+                .map_err(|e| anyhow!("failed read: {}", e))?;
+            INPUT_READER.busy();
+            tracing::trace!("event count: {}\nevents:\n{events:?}", events.len());
+
+            let device_changes = kbd_in.take_device_changes();
+            if !device_changes.is_empty() {
+                kanata.lock().push_device_changes(device_changes);
+                let wakeup = KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp);
+                if let Err(e) = tx.send(wakeup) {
+                    bail!("failed to send on channel: {}", e)
+                }
+            }
 
-            for in_event in events.iter().copied() {
+            // The watchdog starts passthrough without a chord when processing is stuck.
+            let passthrough = is_emergency_passthrough_active();
+            if kbd_in.is_grabbed() == passthrough {
+                kbd_in.set_grabbed(!passthrough);
+                if !passthrough {
+                    reset_emergency_chord_keys();
+                }
+            }
+
+            for (in_event, device_id) in events.iter().copied().zip(device_ids.iter().copied()) {
                 if let Some(ms_mvmt_key) = *mouse_movement_key.lock()
+                    && !is_emergency_passthrough_active()
                     && let EventSummary::RelativeAxis(_, _, _) = in_event.destructure()
                 {
                     let fake_event = KeyEvent::new(ms_mvmt_key, KeyValue::Tap);
-                    if let Err(e) = tx.try_send(fake_event) {
+                    if let Err(e) = tx.send(fake_event) {
                         bail!("failed to send on channel: {}", e)
                     }
                 }
 
-                let key_event = match KeyEvent::try_from(in_event) {
+                let mut key_event = match KeyEvent::try_from(in_event) {
                     Ok(ev) => ev,
                     _ => {
+                        // The OS already receives events from ungrabbed devices.
+                        if is_emergency_passthrough_active() {
+                            continue;
+                        }
                         // Pass-through non-key and non-scroll events
                         let mut kanata = kanata.lock();
                         #[cfg(not(feature = "simulated_output"))]
                         kanata
                             .kbd_out
-                            .write_raw(in_event)
+                            .write_raw(in_event, device_id)
                             .map_err(|e| anyhow!("failed write: {}", e))?;
                         continue;
                     }
                 };
+                key_event.set_device_id(device_id);
 
-                check_for_exit(&key_event);
+                match check_emergency_chords(&key_event) {
+                    EmergencyChordCheck::Continue | EmergencyChordCheck::Exit => {}
+                    // Devices are not grabbed during passthrough, so the OS already has the
+                    // event.
+                    EmergencyChordCheck::Forward => continue,
+                    EmergencyChordCheck::Toggled => {
+                        let passthrough = is_emergency_passthrough_active();
+                        kbd_in.set_grabbed(!passthrough);
+                        if !passthrough {
+                            // Releases were not read while waiting to grab the devices.
+                            reset_emergency_chord_keys();
+                        }
+                        let wakeup = KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp);
+                        if let Err(e) = tx.send(wakeup) {
+                            bail!("failed to send on channel: {}", e)
+                        }
+                        continue;
+                    }
+                }
 
                 if key_event.value == KeyValue::Repeat && !allow_hardware_repeat {
                     continue;
@@ -99,13 +157,13 @@ impl Kanata {
                     #[cfg(not(feature = "simulated_output"))]
                     kanata
                         .kbd_out
-                        .write_raw(in_event)
+                        .write_raw(in_event, device_id)
                         .map_err(|e| anyhow!("failed write: {}", e))?;
                     continue;
                 };
 
                 // Send key events to the processing loop
-                if let Err(e) = tx.try_send(key_event) {
+                if let Err(e) = tx.send(key_event) {
                     bail!("failed to send on channel: {}", e)
                 }
             }
@@ -118,7 +176,7 @@ impl Kanata {
 
     pub fn set_repeat_rate(s: Option<KeyRepeatSettings>) -> Result<()> {
         if let Some(s) = s {
-            log::info!(
+            tracing::info!(
                 "Using xset to set X11 repeat delay to {} and repeat rate to {}",
                 s.delay,
                 s.rate,
@@ -132,14 +190,14 @@ impl Kanata {
                 ])
                 .output()
                 .map_err(|e| {
-                    log::error!("failed to run xset: {e:?}");
+                    tracing::error!("failed to run xset: {e:?}");
                     e
                 })?;
-            log::info!(
+            tracing::info!(
                 "xset stdout: {}",
                 String::from_utf8_lossy(&cmd_output.stdout)
             );
-            log::info!(
+            tracing::info!(
                 "xset stderr: {}",
                 String::from_utf8_lossy(&cmd_output.stderr)
             );
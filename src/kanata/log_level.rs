@@ -0,0 +1,104 @@
+//! Changing the log level while kanata runs, with the `log-level` action and the TCP
+//! `SetLogLevel` command, e.g. to trace while reproducing a problem without restarting, and
+//! starting a new log file on request.
+
+use super::*;
+use log::LevelFilter;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+type SetLevel = Box<dyn Fn(LevelFilter) + Send + Sync>;
+
+static SET_LEVEL: OnceLock<SetLevel> = OnceLock::new();
+static ROTATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+static LEVELS: Mutex<LogLevels> = Mutex::new(LogLevels {
+    level: LevelFilter::Info,
+    generation: 0,
+});
+
+struct LogLevels {
+    /// The level that a temporary level returns to.
+    level: LevelFilter,
+    /// Changed by every change of the level, so that a temporary level that was replaced is not
+    /// restored when it expires.
+    generation: u64,
+}
+
+/// Sets the level that the loggers were started with and how to change it. The loggers must let
+/// through every level that may be set, e.g. with `log::set_max_level` filtering them instead.
+pub fn init_log_level(level: LevelFilter, set_level: impl Fn(LevelFilter) + Send + Sync + 'static) {
+    set_level(level);
+    LEVELS.lock().level = level;
+    let _ = SET_LEVEL.set(Box::new(set_level));
+}
+
+fn apply_log_level(level: LevelFilter) {
+    match SET_LEVEL.get() {
+        Some(set_level) => set_level(level),
+        None => log::set_max_level(level),
+    }
+}
+
+/// Sets the log level for the duration, after which the previous level is restored, or until it
+/// is set again without a duration.
+pub fn set_log_level(level: LevelFilter, duration: Option<Duration>) {
+    let mut levels = LEVELS.lock();
+    levels.generation += 1;
+    match duration {
+        Some(duration) => {
+            tracing::info!("log level set to {level} for {} ms", duration.as_millis());
+            let generation = levels.generation;
+            let spawned = std::thread::Builder::new()
+                .name("log-level".into())
+                .spawn(move || {
+                    std::thread::sleep(duration);
+                    let levels = LEVELS.lock();
+                    if levels.generation == generation {
+                        apply_log_level(levels.level);
+                        tracing::info!("log level restored to {}", levels.level);
+                    }
+                });
+            if let Err(e) = spawned {
+                tracing::error!("not changing the log level, could not start its timer: {e}");
+                return;
+            }
+        }
+        None => {
+            tracing::info!("log level set to {level}");
+            levels.level = level;
+        }
+    }
+    apply_log_level(level);
+}
+
+/// Asks the log file to start over before its next line, e.g. on a signal from logrotate.
+pub fn request_log_rotation() {
+    tracing::info!("rotating the log file");
+    ROTATION_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Whether a rotation was requested since the last call.
+pub fn take_log_rotation_request() -> bool {
+    ROTATION_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temporary_log_level_is_restored() {
+        set_log_level(LevelFilter::Warn, None);
+        set_log_level(LevelFilter::Trace, Some(Duration::from_millis(20)));
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(log::max_level(), LevelFilter::Warn);
+
+        // A level that replaced the temporary one stays.
+        set_log_level(LevelFilter::Trace, Some(Duration::from_millis(20)));
+        set_log_level(LevelFilter::Debug, None);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(log::max_level(), LevelFilter::Debug);
+    }
+}
@@ -1,11 +1,10 @@
 use super::*;
 use anyhow::{Result, anyhow, bail};
-use log::info;
 use parking_lot::Mutex;
 use std::convert::TryFrom;
 use std::sync::Arc;
-use std::sync::mpsc::SyncSender as Sender;
 use std::time::Duration;
+use tracing::info;
 
 impl Kanata {
     /// Enter an infinite loop that listens for OS key events and sends them to the processing thread.
@@ -19,7 +18,7 @@ impl Kanata {
     /// re-initializing the pqrs client (via `init_sink()`). A second client
     /// causes duplicate connection callbacks that race with the IOHIDManager,
     /// leading to "exclusive access" errors on the input device.
-    pub fn event_loop(kanata: Arc<Mutex<Self>>, tx: Sender<KeyEvent>) -> Result<()> {
+    pub fn event_loop(kanata: Arc<Mutex<Self>>, tx: EventSender) -> Result<()> {
         info!("entering the event loop");
 
         {
@@ -32,7 +31,7 @@ impl Kanata {
             if rc == 0 {
                 info!("macOS: event loop thread QoS set to USER_INTERACTIVE");
             } else {
-                log::warn!("macOS: failed to set event loop thread QoS (rc={rc})");
+                tracing::warn!("macOS: failed to set event loop thread QoS (rc={rc})");
             }
         }
 
@@ -54,7 +53,7 @@ impl Kanata {
                 .kbd_out
                 .wait_until_ready(Some(Duration::from_secs(10)))
             {
-                log::warn!(
+                tracing::warn!(
                     "output backend not ready after 10s. Key output may fail until the backend recovers."
                 );
             }
@@ -71,7 +70,7 @@ impl Kanata {
             if kanata.kbd_out.output_ready() {
                 use kanata_parser::keys::OsCode;
                 if let Err(e) = kanata.kbd_out.release_key(OsCode::KEY_F24) {
-                    log::warn!("failed to clear stale virtual HID state: {e}");
+                    tracing::warn!("failed to clear stale virtual HID state: {e}");
                 } else {
                     info!("cleared stale virtual HID keyboard state");
                 }
@@ -111,27 +110,31 @@ impl Kanata {
         // See `oskbd::start_screen_lock_poller` for the design notes.
         crate::oskbd::start_screen_lock_poller();
 
+        let _running = INPUT_READER.running();
         loop {
             // --- Event processing loop ---
             let needs_recovery = loop {
                 // Check output health before blocking on input
                 if !kanata.lock().kbd_out.output_ready() {
-                    log::warn!("output backend unavailable — releasing input devices");
+                    tracing::warn!("output backend unavailable — releasing input devices");
                     break true;
                 }
 
                 if crate::oskbd::is_screen_grab_paused() {
-                    log::info!(
+                    tracing::info!(
                         "console session paused (lock/user-switch) — releasing input devices"
                     );
                     break true;
                 }
 
-                let event = match kb.read() {
+                INPUT_READER.idle();
+                let read = kb.read();
+                INPUT_READER.busy();
+                let event = match read {
                     Ok(ev) => ev,
                     Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
                         // Pipe closed by release_input_only() — expected during recovery
-                        log::info!("input pipe EOF — devices were released");
+                        tracing::info!("input pipe EOF — devices were released");
                         break true;
                     }
                     Err(e) => return Err(anyhow!("failed read: {}", e)),
@@ -143,7 +146,7 @@ impl Kanata {
                 // and must be dropped, not remapped. See the caveat in
                 // `oskbd::macos`.
                 if crate::oskbd::is_screen_grab_paused() {
-                    log::info!(
+                    tracing::info!(
                         "console session paused (lock/user-switch) — dropping read event and releasing input devices"
                     );
                     break true;
@@ -155,12 +158,12 @@ impl Kanata {
                         ev
                     }
                     _ => {
-                        log::debug!("{event:?} is unrecognized!");
+                        tracing::debug!("{event:?} is unrecognized!");
                         let mut kanata = kanata.lock();
                         match kanata.kbd_out.write(event) {
                             Ok(()) => continue,
                             Err(e) if e.kind() == std::io::ErrorKind::NotConnected => {
-                                log::warn!(
+                                tracing::warn!(
                                     "output backend unavailable during write — releasing input devices"
                                 );
                                 break true;
@@ -170,19 +173,41 @@ impl Kanata {
                     }
                 };
 
-                check_for_exit(&key_event);
+                match check_emergency_chords(&key_event) {
+                    EmergencyChordCheck::Continue | EmergencyChordCheck::Exit => {}
+                    EmergencyChordCheck::Forward => {
+                        let mut kanata = kanata.lock();
+                        match kanata.kbd_out.write(event) {
+                            Ok(()) => continue,
+                            Err(e) if e.kind() == std::io::ErrorKind::NotConnected => {
+                                tracing::warn!(
+                                    "output backend unavailable during write — releasing input devices"
+                                );
+                                break true;
+                            }
+                            Err(e) => return Err(anyhow!("failed write: {}", e)),
+                        }
+                    }
+                    EmergencyChordCheck::Toggled => {
+                        let wakeup = KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp);
+                        if let Err(e) = tx.try_send(wakeup) {
+                            bail!("failed to send on channel: {}", e)
+                        }
+                        continue;
+                    }
+                }
 
                 if key_event.value == KeyValue::Repeat && !allow_hardware_repeat {
                     continue;
                 }
 
                 if !MAPPED_KEYS.lock().contains(&key_event.code) {
-                    log::debug!("{key_event:?} is not mapped");
+                    tracing::debug!("{key_event:?} is not mapped");
                     let mut kanata = kanata.lock();
                     match kanata.kbd_out.write(event) {
                         Ok(()) => continue,
                         Err(e) if e.kind() == std::io::ErrorKind::NotConnected => {
-                            log::warn!(
+                            tracing::warn!(
                                 "output backend unavailable during write — releasing input devices"
                             );
                             break true;
@@ -191,7 +216,7 @@ impl Kanata {
                     }
                 }
 
-                log::debug!("sending {key_event:?} to processing loop");
+                tracing::debug!("sending {key_event:?} to processing loop");
 
                 match key_event.value {
                     KeyValue::Release => {
@@ -207,11 +232,9 @@ impl Kanata {
                     }
                     _ => {}
                 }
-                if let Err(std::sync::mpsc::TrySendError::Full(ke)) = tx.try_send(key_event) {
-                    log::warn!("channel full, blocking until processing thread drains");
-                    if let Err(e) = tx.send(ke) {
-                        bail!("failed to send key event: channel disconnected: {e}");
-                    }
+                // Waits for the processing thread to drain the queue if it is full.
+                if let Err(e) = tx.send(key_event) {
+                    bail!("failed to send key event: channel disconnected: {e}");
                 }
             };
 
@@ -0,0 +1,276 @@
+//! `--menu-bar`: a menu bar extra on macOS that shows the active layer, with a menu to pause
+//! kanata, to reload the configuration and to switch between the configuration files of `-c`.
+//!
+//! AppKit only runs on the main thread, so with `--menu-bar` the event loop runs in its own thread
+//! while the main thread runs the application, see [`Kanata::run_menu_bar`]. A timer of the
+//! application polls the state of kanata, like the tray does on Linux.
+
+// Caused by unmaintained objc crate triggering warnings.
+#![allow(unexpected_cfgs)]
+
+use super::*;
+use core_foundation::base::TCFType;
+use core_foundation::string::CFString;
+use objc::declare::ClassDecl;
+use objc::runtime::{BOOL, Class, NO, Object, Sel, YES};
+use objc::{class, msg_send, sel, sel_impl};
+use std::cell::RefCell;
+use std::ptr;
+
+const POLL_INTERVAL_SECS: f64 = 0.25;
+/// `NSVariableStatusItemLength`
+const VARIABLE_LENGTH: f64 = -1.0;
+/// `NSApplicationActivationPolicyAccessory`: no Dock icon and no menu of the application.
+const ACTIVATION_POLICY_ACCESSORY: isize = 1;
+
+/// What the menu bar shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MenuState {
+    layer: String,
+    paused: bool,
+    configs: Vec<String>,
+    config: usize,
+}
+
+impl MenuState {
+    fn of(k: &mut Kanata) -> Self {
+        let layer = k.layout.bm().current_layer();
+        Self {
+            layer: k.layer_info[layer].name.clone(),
+            paused: is_emergency_passthrough_active(),
+            configs: k
+                .cfg_paths
+                .iter()
+                .map(|path| match path.file_stem() {
+                    Some(stem) => stem.to_string_lossy().into_owned(),
+                    None => path.display().to_string(),
+                })
+                .collect(),
+            config: k.cur_cfg_idx,
+        }
+    }
+
+    fn title(&self) -> String {
+        match self.paused {
+            true => format!("{} (paused)", self.layer),
+            false => self.layer.clone(),
+        }
+    }
+}
+
+struct MenuBar {
+    kanata: Arc<Mutex<Kanata>>,
+    wakeup: EventSender,
+    /// The `NSStatusItem`, retained for the lifetime of kanata.
+    item: *mut Object,
+    /// The object whose methods the menu items and the timer call.
+    target: *mut Object,
+    state: Option<MenuState>,
+}
+
+thread_local! {
+    /// Only the main thread, which runs the application, has the menu bar.
+    static MENU_BAR: RefCell<Option<MenuBar>> = const { RefCell::new(None) };
+}
+
+impl Kanata {
+    /// Shows kanata in the menu bar and runs the application on the current thread, which must
+    /// be the main thread. Only returns if the application stops.
+    pub fn run_menu_bar(kanata: Arc<Mutex<Self>>, wakeup: EventSender) {
+        unsafe {
+            let app: *mut Object = msg_send![class!(NSApplication), sharedApplication];
+            let _: BOOL = msg_send![app, setActivationPolicy: ACTIVATION_POLICY_ACCESSORY];
+            let bar: *mut Object = msg_send![class!(NSStatusBar), systemStatusBar];
+            let item: *mut Object = msg_send![bar, statusItemWithLength: VARIABLE_LENGTH];
+            let _: *mut Object = msg_send![item, retain];
+            let target: *mut Object = msg_send![target_class(), new];
+            MENU_BAR.with_borrow_mut(|menu_bar| {
+                *menu_bar = Some(MenuBar {
+                    kanata,
+                    wakeup,
+                    item,
+                    target,
+                    state: None,
+                })
+            });
+            refresh();
+            let _: *mut Object = msg_send![
+                class!(NSTimer),
+                scheduledTimerWithTimeInterval: POLL_INTERVAL_SECS
+                target: target
+                selector: sel!(tick:)
+                userInfo: ptr::null_mut::<Object>()
+                repeats: YES
+            ];
+            tracing::info!("menu bar: showing kanata");
+            let _: () = msg_send![app, run];
+        }
+    }
+}
+
+/// The class of [`MenuBar::target`], declared on the first call.
+fn target_class() -> &'static Class {
+    const NAME: &str = "KanataMenuBarTarget";
+    Class::get(NAME).unwrap_or_else(|| {
+        let mut decl = ClassDecl::new(NAME, class!(NSObject))
+            .expect("the class of the menu bar is declared once");
+        unsafe {
+            decl.add_method(
+                sel!(tick:),
+                tick as extern "C" fn(&Object, Sel, *mut Object),
+            );
+            decl.add_method(
+                sel!(togglePause:),
+                toggle_pause as extern "C" fn(&Object, Sel, *mut Object),
+            );
+            decl.add_method(
+                sel!(reload:),
+                reload as extern "C" fn(&Object, Sel, *mut Object),
+            );
+            decl.add_method(
+                sel!(loadConfig:),
+                load_config as extern "C" fn(&Object, Sel, *mut Object),
+            );
+        }
+        decl.register()
+    })
+}
+
+extern "C" fn tick(_: &Object, _: Sel, _: *mut Object) {
+    refresh();
+}
+
+extern "C" fn toggle_pause(_: &Object, _: Sel, _: *mut Object) {
+    let paused = !is_emergency_passthrough_active();
+    set_emergency_passthrough(paused);
+    match paused {
+        true => tracing::info!("paused from the menu bar, passing input through"),
+        false => tracing::info!("resumed from the menu bar"),
+    }
+    acted(|_| {});
+}
+
+extern "C" fn reload(_: &Object, _: Sel, _: *mut Object) {
+    acted(|k| k.request_live_reload());
+}
+
+extern "C" fn load_config(_: &Object, _: Sel, sender: *mut Object) {
+    let index: isize = unsafe { msg_send![sender, tag] };
+    acted(|k| {
+        if let Err(e) = k.request_live_reload_num(index as usize) {
+            tracing::error!("menu bar: {e}");
+        }
+    });
+}
+
+/// Runs what a menu item asks for and wakes the processing loop to do it.
+fn acted(f: impl FnOnce(&mut Kanata)) {
+    MENU_BAR.with_borrow(|menu_bar| {
+        let Some(menu_bar) = menu_bar else {
+            return;
+        };
+        f(&mut menu_bar.kanata.lock());
+        // The processing loop may be waiting for input, and the channel being full means that it
+        // will wake up anyway.
+        let _ = menu_bar
+            .wakeup
+            .try_send(KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp));
+    });
+    refresh();
+}
+
+/// Updates the title and the menu if the state of kanata changed.
+fn refresh() {
+    MENU_BAR.with_borrow_mut(|menu_bar| {
+        let Some(menu_bar) = menu_bar else {
+            return;
+        };
+        let state = MenuState::of(&mut menu_bar.kanata.lock());
+        if menu_bar.state.as_ref() == Some(&state) {
+            return;
+        }
+        unsafe {
+            let button: *mut Object = msg_send![menu_bar.item, button];
+            let title = CFString::new(&state.title());
+            let _: () = msg_send![button, setTitle: nsstring(&title)];
+            let menu = menu(&state, menu_bar.target);
+            let _: () = msg_send![menu_bar.item, setMenu: menu];
+            let _: () = msg_send![menu, release];
+        }
+        menu_bar.state = Some(state);
+    });
+}
+
+/// A new `NSMenu`, owned by the caller.
+unsafe fn menu(state: &MenuState, target: *mut Object) -> *mut Object {
+    unsafe {
+        let menu = new_menu();
+        add_item(menu, &format!("Layer: {}", state.layer), None, target);
+        add_separator(menu);
+        let pause = add_item(menu, "Pause", Some(sel!(togglePause:)), target);
+        let _: () = msg_send![pause, setState: state.paused as isize];
+        add_item(menu, "Reload config", Some(sel!(reload:)), target);
+        if state.configs.len() > 1 {
+            let configs = add_item(menu, "Load config", None, target);
+            let submenu = new_menu();
+            for (i, name) in state.configs.iter().enumerate() {
+                let item = add_item(submenu, name, Some(sel!(loadConfig:)), target);
+                let _: () = msg_send![item, setTag: i as isize];
+                let _: () = msg_send![item, setState: (i == state.config) as isize];
+            }
+            let _: () = msg_send![configs, setEnabled: YES];
+            let _: () = msg_send![configs, setSubmenu: submenu];
+            let _: () = msg_send![submenu, release];
+        }
+        add_separator(menu);
+        let app: *mut Object = msg_send![class!(NSApplication), sharedApplication];
+        let quit = add_item(menu, "Quit kanata", Some(sel!(terminate:)), target);
+        let _: () = msg_send![quit, setTarget: app];
+        menu
+    }
+}
+
+unsafe fn new_menu() -> *mut Object {
+    unsafe {
+        let menu: *mut Object = msg_send![class!(NSMenu), new];
+        let _: () = msg_send![menu, setAutoenablesItems: NO];
+        menu
+    }
+}
+
+/// Adds an item that calls `action` on `target`, or a disabled one without an action.
+unsafe fn add_item(
+    menu: *mut Object,
+    title: &str,
+    action: Option<Sel>,
+    target: *mut Object,
+) -> *mut Object {
+    unsafe {
+        let title = CFString::new(title);
+        let no_key = CFString::new("");
+        let item: *mut Object = msg_send![class!(NSMenuItem), alloc];
+        let item: *mut Object = msg_send![
+            item,
+            initWithTitle: nsstring(&title)
+            action: action.unwrap_or_else(|| Sel::from_ptr(ptr::null()))
+            keyEquivalent: nsstring(&no_key)
+        ];
+        let _: () = msg_send![item, setTarget: target];
+        let _: () = msg_send![item, setEnabled: if action.is_some() { YES } else { NO }];
+        let _: () = msg_send![menu, addItem: item];
+        let _: () = msg_send![item, release];
+        item
+    }
+}
+
+unsafe fn add_separator(menu: *mut Object) {
+    unsafe {
+        let separator: *mut Object = msg_send![class!(NSMenuItem), separatorItem];
+        let _: () = msg_send![menu, addItem: separator];
+    }
+}
+
+/// `CFString` is toll-free bridged with `NSString`.
+fn nsstring(s: &CFString) -> *mut Object {
+    s.as_concrete_TypeRef() as *mut Object
+}
@@ -0,0 +1,275 @@
+//! Output of MIDI messages for the `midi-note` and `midi-cc` actions.
+//!
+//! The port is opened lazily on the first message so that configurations that do not use MIDI
+//! never touch any MIDI device.
+
+use super::*;
+
+/// Send a MIDI message. With simulated output, the message is written to the simulated keyboard
+/// output instead of a MIDI port.
+pub(crate) fn send_midi(_midi_out: &mut MidiOut, _kbd_out: &mut KbdOut, msg: [u8; 3]) {
+    tracing::debug!("midi out: {msg:02X?}");
+    #[cfg(feature = "simulated_output")]
+    _kbd_out.write_midi(msg);
+    #[cfg(not(feature = "simulated_output"))]
+    _midi_out.send(msg);
+}
+
+#[cfg(feature = "simulated_output")]
+pub(crate) struct MidiOut;
+
+#[cfg(feature = "simulated_output")]
+impl MidiOut {
+    pub(crate) fn new(_port: Option<String>) -> Self {
+        Self
+    }
+
+    pub(crate) fn set_port(&mut self, _port: Option<String>) {}
+}
+
+#[cfg(not(feature = "simulated_output"))]
+pub(crate) use real::*;
+
+#[cfg(not(feature = "simulated_output"))]
+mod real {
+    /// A lazily-opened connection to a MIDI output port.
+    pub(crate) struct MidiOut {
+        port: Option<String>,
+        conn: Option<MidiConn>,
+        /// Set when opening the port fails, so that the error is not logged on every press.
+        /// Cleared when the port is changed by live reload.
+        open_failed: bool,
+    }
+
+    impl MidiOut {
+        pub(crate) fn new(port: Option<String>) -> Self {
+            Self {
+                port,
+                conn: None,
+                open_failed: false,
+            }
+        }
+
+        /// Changes the configured port. The existing connection is closed if the port differs.
+        pub(crate) fn set_port(&mut self, port: Option<String>) {
+            if port != self.port {
+                self.port = port;
+                self.conn = None;
+                self.open_failed = false;
+            }
+        }
+
+        pub(crate) fn send(&mut self, msg: [u8; 3]) {
+            if self.conn.is_none() {
+                if self.open_failed {
+                    return;
+                }
+                match MidiConn::open(self.port.as_deref()) {
+                    Ok(conn) => self.conn = Some(conn),
+                    Err(e) => {
+                        tracing::error!("failed to open MIDI output port: {e}");
+                        self.open_failed = true;
+                        return;
+                    }
+                }
+            }
+            if let Some(conn) = &mut self.conn
+                && let Err(e) = conn.send(msg)
+            {
+                tracing::error!("failed to send MIDI message: {e}");
+                // Re-open on the next message in case the device was reconnected.
+                self.conn = None;
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    use linux::MidiConn;
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "windows")))]
+    use unsupported::MidiConn;
+    #[cfg(target_os = "windows")]
+    use windows::MidiConn;
+
+    /// Uses the ALSA raw MIDI character devices, e.g. `/dev/snd/midiC1D0`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    mod linux {
+        use std::fs::{File, OpenOptions};
+        use std::io::{self, Write};
+        use std::path::PathBuf;
+
+        pub(super) struct MidiConn(File);
+
+        impl MidiConn {
+            /// The port may be a device path, or a name matching either the device file name or
+            /// the ALSA card id. With no port, the first raw MIDI device is used.
+            pub(super) fn open(port: Option<&str>) -> io::Result<Self> {
+                if let Some(path) = port.filter(|p| p.starts_with('/')) {
+                    return Ok(Self(OpenOptions::new().write(true).open(path)?));
+                }
+                let devices = raw_midi_devices();
+                let device = devices.iter().find(|(path, card_id)| match port {
+                    None => true,
+                    Some(port) => {
+                        path.file_name()
+                            .is_some_and(|f| f.eq_ignore_ascii_case(port))
+                            || card_id.eq_ignore_ascii_case(port)
+                    }
+                });
+                match device {
+                    Some((path, card_id)) => {
+                        tracing::info!("using MIDI output {} ({card_id})", path.display());
+                        Ok(Self(OpenOptions::new().write(true).open(path)?))
+                    }
+                    None => {
+                        let available = devices
+                            .iter()
+                            .map(|(path, card_id)| format!("{} ({card_id})", path.display()))
+                            .collect::<Vec<_>>();
+                        Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!(
+                                "no MIDI output matching {port:?}, available: {}",
+                                if available.is_empty() {
+                                    "none".to_string()
+                                } else {
+                                    available.join(", ")
+                                }
+                            ),
+                        ))
+                    }
+                }
+            }
+
+            pub(super) fn send(&mut self, msg: [u8; 3]) -> io::Result<()> {
+                self.0.write_all(&msg)?;
+                self.0.flush()
+            }
+        }
+
+        /// Returns the raw MIDI device paths along with the id of the card they belong to.
+        fn raw_midi_devices() -> Vec<(PathBuf, String)> {
+            let Ok(entries) = std::fs::read_dir("/dev/snd") else {
+                return vec![];
+            };
+            let mut devices = entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| {
+                    let name = e.file_name().into_string().ok()?;
+                    let card = name.strip_prefix("midiC")?.split('D').next()?.to_string();
+                    let card_id = std::fs::read_to_string(format!("/proc/asound/card{card}/id"))
+                        .map(|id| id.trim().to_string())
+                        .unwrap_or_default();
+                    Some((e.path(), card_id))
+                })
+                .collect::<Vec<_>>();
+            devices.sort();
+            devices
+        }
+    }
+
+    /// Uses the Windows multimedia MIDI API.
+    #[cfg(target_os = "windows")]
+    mod windows {
+        use std::io;
+        use winapi::um::mmeapi::*;
+        use winapi::um::mmsystem::*;
+
+        pub(super) struct MidiConn(HMIDIOUT);
+
+        // The handle is only ever used from the processing thread that owns the Kanata state.
+        unsafe impl Send for MidiConn {}
+
+        impl MidiConn {
+            /// The port may be a device index, or a name or a part of the name of the device.
+            /// With no port, the first device is used.
+            pub(super) fn open(port: Option<&str>) -> io::Result<Self> {
+                let names = device_names();
+                let id = match port {
+                    None if !names.is_empty() => Some(0),
+                    None => None,
+                    Some(port) => port
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|i| *i < names.len())
+                        .or_else(|| {
+                            let port = port.to_lowercase();
+                            names.iter().position(|n| n.to_lowercase().contains(&port))
+                        }),
+                };
+                let Some(id) = id else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no MIDI output matching {port:?}, available: {names:?}"),
+                    ));
+                };
+                tracing::info!("using MIDI output {id} ({})", names[id]);
+                let mut handle: HMIDIOUT = std::ptr::null_mut();
+                let res = unsafe { midiOutOpen(&mut handle, id as u32, 0, 0, CALLBACK_NULL) };
+                if res != MMSYSERR_NOERROR {
+                    return Err(io::Error::other(format!("midiOutOpen returned {res}")));
+                }
+                Ok(Self(handle))
+            }
+
+            pub(super) fn send(&mut self, msg: [u8; 3]) -> io::Result<()> {
+                let packed = u32::from_le_bytes([msg[0], msg[1], msg[2], 0]);
+                let res = unsafe { midiOutShortMsg(self.0, packed) };
+                if res != MMSYSERR_NOERROR {
+                    return Err(io::Error::other(format!("midiOutShortMsg returned {res}")));
+                }
+                Ok(())
+            }
+        }
+
+        impl Drop for MidiConn {
+            fn drop(&mut self) {
+                unsafe {
+                    midiOutReset(self.0);
+                    midiOutClose(self.0);
+                }
+            }
+        }
+
+        fn device_names() -> Vec<String> {
+            let count = unsafe { midiOutGetNumDevs() };
+            (0..count)
+                .map(|id| {
+                    let mut caps: MIDIOUTCAPSW = unsafe { std::mem::zeroed() };
+                    let res = unsafe {
+                        midiOutGetDevCapsW(
+                            id as usize,
+                            &mut caps,
+                            std::mem::size_of::<MIDIOUTCAPSW>() as u32,
+                        )
+                    };
+                    if res != MMSYSERR_NOERROR {
+                        return String::new();
+                    }
+                    let name = caps.szPname;
+                    let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+                    String::from_utf16_lossy(&name[..len])
+                })
+                .collect()
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "windows")))]
+    mod unsupported {
+        use std::io;
+
+        pub(super) struct MidiConn;
+
+        impl MidiConn {
+            pub(super) fn open(_port: Option<&str>) -> io::Result<Self> {
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "MIDI output is not supported on this platform",
+                ))
+            }
+
+            pub(super) fn send(&mut self, _msg: [u8; 3]) -> io::Result<()> {
+                Ok(())
+            }
+        }
+    }
+}
@@ -4,23 +4,19 @@
 use crate::gui::win::*;
 use anyhow::{Result, bail};
 use kanata_parser::sequences::*;
-use log::{error, info};
 use parking_lot::Mutex;
-use std::sync::mpsc::{Receiver, SyncSender as Sender, TryRecvError};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender as Sender};
+use tracing::{error, info};
 
 /// Reorders events so modifiers are processed first on press, last on release.
-fn collect_and_sort_events(
-    first_event: KeyEvent,
-    rx: &Receiver<KeyEvent>,
-    events: &mut Vec<KeyEvent>,
-) {
+fn collect_and_sort_events(first_event: KeyEvent, rx: &EventReceiver, events: &mut Vec<KeyEvent>) {
     events.clear();
     events.push(first_event);
     while let Ok(ev) = rx.try_recv() {
         events.push(ev);
     }
     if events.len() > 1 {
-        log::debug!("collected {} events, reordering", events.len());
+        tracing::debug!("collected {} events, reordering", events.len());
         events.sort_by(|a, b| {
             let a_is_mod = a.code.is_modifier();
             let b_is_mod = b.code.is_modifier();
@@ -52,7 +48,7 @@ fn collect_and_sort_events(
                 }
             }
         });
-        log::debug!("reordered: {:?}", events);
+        tracing::debug!("reordered: {:?}", events);
     }
 }
 
@@ -62,7 +58,7 @@ fn collect_and_sort_events(
 ))]
 use std::sync::mpsc::Sender as ASender;
 
-use kanata_keyberon::action::ReleasableState;
+use kanata_keyberon::action::{HoldTapAction, ReleasableState};
 use kanata_keyberon::key_code::*;
 use kanata_keyberon::layout::{CustomEvent, Event, Layout, State};
 
@@ -70,8 +66,6 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time;
 
-#[cfg(feature = "tcp_server")]
-use crate::SocketAddrWrapper;
 use crate::ValidatedArgs;
 use crate::oskbd::{KeyEvent, *};
 #[cfg(feature = "tcp_server")]
@@ -81,15 +75,154 @@ use kanata_parser::cfg::list_actions::*;
 use kanata_parser::cfg::*;
 use kanata_parser::custom_action::*;
 pub use kanata_parser::keys::*;
-use kanata_tcp_protocol::ServerMessage;
+use kanata_parser::layers::KanataCustom;
+use kanata_tcp_protocol::{ReloadChanges, ServerMessage};
+
+mod alias_concat;
+use alias_concat::*;
+
+mod apps;
+use apps::*;
+
+mod auto_return;
+use auto_return::*;
 
 mod clipboard;
 use clipboard::*;
 
+mod compose;
+use compose::*;
+
+mod datetime;
+use datetime::*;
 mod dynamic_macro;
 use dynamic_macro::*;
 
+mod emergency;
+pub(crate) use emergency::*;
+
+mod watchdog;
+pub use watchdog::*;
+
+mod health;
+pub(crate) use health::*;
+
 mod key_repeat;
+use key_repeat::*;
+
+mod midi;
+use midi::*;
+#[cfg(all(
+    target_os = "linux",
+    feature = "dbus",
+    not(feature = "simulated_output")
+))]
+mod dbus;
+#[cfg(all(
+    target_os = "linux",
+    feature = "dbus",
+    not(feature = "simulated_output")
+))]
+mod status_notifier;
+#[cfg(all(
+    target_os = "linux",
+    feature = "dbus",
+    not(feature = "simulated_output")
+))]
+pub(crate) use status_notifier::tray_error;
+#[cfg(all(target_os = "macos", not(feature = "simulated_output")))]
+mod menu_bar;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod user_session;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use user_session::check_user_cfg;
+mod mpris;
+use mpris::*;
+mod notify;
+use notify::*;
+mod obs;
+use obs::*;
+mod openrgb;
+use openrgb::*;
+mod webhook;
+use webhook::*;
+#[cfg(feature = "plugins")]
+mod plugins;
+#[cfg(feature = "plugins")]
+pub use plugins::*;
+
+mod mouse_grid;
+use mouse_grid::*;
+
+mod mouse_jiggle;
+use mouse_jiggle::*;
+
+mod cfg_watch;
+
+mod event_queue;
+pub use event_queue::*;
+
+mod reload_state;
+
+mod reload_trial;
+use reload_trial::*;
+
+mod reload_history;
+use reload_history::*;
+
+mod reload_changes;
+use reload_changes::*;
+
+mod os_layout;
+
+mod fallback_cfg;
+use fallback_cfg::*;
+
+mod device_changes;
+pub use device_changes::*;
+pub(crate) use os_layout::*;
+mod processing_pause;
+use processing_pause::*;
+
+mod release_order;
+use release_order::*;
+
+mod latency;
+pub use latency::*;
+
+mod ngram_stats;
+pub use ngram_stats::*;
+mod typing_speed;
+pub use typing_speed::*;
+mod training;
+use training::*;
+mod stuck_keys;
+use stuck_keys::*;
+mod debounce;
+use debounce::*;
+mod dedupe;
+use dedupe::*;
+mod tap_hold_tuning;
+
+mod event_trace;
+pub use event_trace::*;
+
+mod crash_bundle;
+pub use crash_bundle::*;
+
+mod log_level;
+pub use log_level::*;
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+mod signals;
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+pub use signals::*;
+
+mod thread_priority;
+use thread_priority::*;
+
+mod sound;
+use sound::*;
 
 mod millisecond_counting;
 pub use millisecond_counting::*;
@@ -97,16 +230,27 @@ pub use millisecond_counting::*;
 mod scroll;
 use scroll::*;
 
+mod secrets;
+use secrets::*;
+#[cfg(feature = "tcp_server")]
+pub(crate) use secrets::{check_secret_file, read_credential};
 mod sequences;
 use sequences::*;
+mod sequence_menu;
 
 pub mod cfg_forced;
 use cfg_forced::*;
 
+pub mod clock;
+
 #[cfg(feature = "cmd")]
 mod cmd;
 #[cfg(feature = "cmd")]
 use cmd::*;
+#[cfg(feature = "cmd")]
+mod cmd_executor;
+#[cfg(feature = "cmd")]
+pub(crate) use cmd_executor::*;
 
 #[cfg(target_os = "windows")]
 mod windows;
@@ -153,7 +297,9 @@ pub struct Kanata {
     /// Index into `cfg_paths`, used to know which file to live reload. Changes when cycling
     /// through the configuration files.
     pub cur_cfg_idx: usize,
-    /// Files included via (include "path") in the configuration.
+    /// Files of the current configuration: the file itself and the files included via
+    /// (include "path"). Watched for changes with `--watch`.
+    pub cfg_files: Vec<PathBuf>,
     /// The potential key outputs of every key input. Used for managing key repeat.
     pub key_outputs: cfg::KeyOutputs,
     /// Handle to the keyberon library layout.
@@ -196,6 +342,15 @@ pub struct Kanata {
     pub sequence_state: SequenceState,
     /// Valid sequences defined in the user configuration.
     pub sequences: cfg::KeySeqsToFKeys,
+    /// The keys of the active sequence when `SequenceMenu` was last sent, or None if it was sent
+    /// for no active sequence.
+    sequence_menu_shown: Option<Vec<u16>>,
+    /// Compose sequences from `defcompose`.
+    compose: cfg::ComposeTable,
+    /// Tracks the progress of a `compose` action. Is Some(...) while composing and None otherwise.
+    compose_state: Option<ComposeState>,
+    /// Variables of `var-set` and the held keys of `alias-concat`.
+    alias_concat_state: AliasConcatState,
     /// Stores the user recored dynamic macros.
     pub dynamic_macros: HashMap<u16, Vec<DynamicMacroItem>>,
     /// Tracks the progress of an active dynamic macro. Is Some(...) when a dynamic macro is being
@@ -217,6 +372,14 @@ pub struct Kanata {
     time_remainder: u128,
     /// Is true if a live reload was requested by the user and false otherwise.
     live_reload_requested: bool,
+    /// The confirmation timeout of the requested live reload, if it is a trial reload.
+    requested_trial_ms: Option<u64>,
+    /// The reload from `ReloadTry` that is rolled back unless it is confirmed.
+    reload_trial: Option<ReloadTrial>,
+    /// The index and text of the configuration file when it was loaded.
+    loaded_cfg: Option<(usize, String)>,
+    /// The configurations that were loaded last, for `ReloadRollback` and `ReloadVersion`.
+    reload_history: ReloadHistory,
     #[cfg(any(target_os = "linux", target_os = "android"))]
     /// Linux input paths in the user configuration.
     pub kbd_in_paths: Vec<String>,
@@ -264,6 +427,12 @@ pub struct Kanata {
     /// Determines what types of devices to grab based on autodetection mode.
     #[cfg(any(target_os = "linux", target_os = "android"))]
     pub device_detect_mode: DeviceDetectMode,
+    /// The logind seat from `linux-seat` whose devices are used.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub seat: Option<String>,
+    /// How the input devices are read, from `linux-input-backend`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub input_backend: LinuxCfgInputBackend,
     /// Fake key actions that are waiting for a certain duration of kanata idling.
     pub waiting_for_idle: HashSet<FakeKeyOnIdle>,
     /// Fake key actions that are waiting for a certain duration of physical keyboard idling,
@@ -289,6 +458,8 @@ pub struct Kanata {
     /// than the one stored in the buffer, both events are outputted at the same time.
     movemouse_buffer: Option<(Axis, CalculatedMouseMove)>,
     override_release_on_activation: bool,
+    /// Set if `preserve-release-order` is enabled.
+    release_order: Option<ReleaseOrder>,
     /// Configured maximum for dynamic macro recording, to protect users from themselves if they
     /// have accidentally left it on.
     dynamic_macro_max_presses: u16,
@@ -304,19 +475,97 @@ pub struct Kanata {
     last_pressed_key: KeyCode,
     /// Names of fake keys mapped to their index in the fake keys row
     pub virtual_keys: HashMap<String, usize>,
+    /// Names of layers mapped to their index, for the layers that commands give by name.
+    layer_ids: HashMap<String, usize>,
+    /// The tap-hold actions of aliases, which `SetTapHold` tunes.
+    hold_tap_aliases: HashMap<String, &'static HoldTapAction<'static, KanataCustom>>,
     /// The maximum value of the any time-dependent check in the configuration.
     pub max_key_timing_check: u16,
-    #[cfg(feature = "tcp_server")]
-    tcp_server_address: Option<SocketAddrWrapper>,
     #[cfg(all(target_os = "windows", feature = "gui"))]
     /// Various GUI-related options.
     pub gui_opts: CfgOptionsGui,
     pub allow_hardware_repeat: bool,
+    /// Raise the input and processing threads to real-time priority when they start.
+    pub realtime_priority: bool,
     /// When > 0, it means macros should be cancelled on the next press.
     /// Upon cancelling this should be set to 0.
     pub macro_on_press_cancel_duration: u32,
     /// Stores user's saved clipboard contents.
     pub saved_clipboard_content: SavedClipboardData,
+    /// Output port for MIDI actions.
+    midi_out: MidiOut,
+    /// Connection to OBS Studio for `obs` actions.
+    obs: ObsClient,
+    /// Connection to OpenRGB for `openrgb` actions and `openrgb-layer-colors`.
+    openrgb: OpenRgbClient,
+    /// The RGB color of each layer from `openrgb-layer-colors`.
+    openrgb_layer_colors: Vec<Option<[u8; 3]>>,
+    /// Webhooks from `defwebhooks`.
+    webhooks: Webhooks,
+    /// Applications from `defapp`.
+    apps: Vec<cfg::App>,
+    /// The description of the configuration from `defmeta`.
+    pub config_meta: cfg::ConfigMeta,
+    /// The descriptions of aliases from `(doc ...)` in `defalias`.
+    pub alias_docs: HashMap<String, String>,
+    /// The descriptions of virtual keys from `(doc ...)`.
+    pub virtual_key_docs: HashMap<String, String>,
+    /// The active application and the held keys of aliases that `defapp` replaces.
+    app_state: AppState,
+    auto_return_state: AutoReturnState,
+    /// Shows the notifications of `notify` actions.
+    notifier: Notifier,
+    /// Sends the commands of `mpris` actions to media players.
+    mpris: MprisClient,
+    /// Per-key repeat behaviour from `defrepeat` and `defrepeat-layer`.
+    key_repeat: cfg::KeyRepeatCfg,
+    /// The key that kanata is repeating itself, for keys configured with software repeat.
+    software_repeat: Option<SoftwareRepeatState>,
+    /// Output keys pressed on a layer with `repeat none`, which are not repeated until released.
+    no_repeat_keys: Vec<KeyCode>,
+    /// Whether the processing loop has handled emergency passthrough being active.
+    emergency_passthrough: bool,
+    /// Some while the `toggle-processing` action has paused processing.
+    processing_pause: Option<ProcessingPause>,
+    prev_processing_paused: bool,
+    /// Whether the keyboard layout of the OS changed and TCP clients were not notified yet.
+    os_layout_changed: bool,
+    /// Input devices that connected or disconnected and TCP clients were not notified of yet.
+    device_changes: Vec<DeviceChange>,
+    /// Whether `deflocalkeys` depends on the keyboard layout of the OS, which reloads the
+    /// configuration when the layout changes.
+    localkeys_for_os_layouts: bool,
+    /// Latency of handling input events, measured with `--measure-latency`.
+    latency: Option<LatencyMeasurement>,
+    /// Bigrams and trigrams of key presses, counted with `--ngram-stats`.
+    ngram_stats: Option<NgramStats>,
+    /// Number of key presses received since kanata started.
+    pub key_presses: u64,
+    /// The typing speed over the last seconds.
+    pub typing_speed: TypingSpeed,
+    /// Some while training mode is on.
+    training: Option<Training>,
+    /// Changes of training mode and presses of keys in training mode, to send to TCP clients.
+    training_messages: Vec<ServerMessage>,
+    /// Output keys held without their physical key, released after `stuck-key-timeout`.
+    stuck_keys: StuckKeys,
+    /// Filters the chatter of keys out of the input with `defdebounce`.
+    debouncer: Debouncer,
+    /// Drops the duplicate input events of mirrored device nodes with `dedupe-input-ms`.
+    deduplicator: Deduplicator,
+    /// The screen region used by the `mouse-grid` actions.
+    mouse_grid: MouseGridState,
+    /// Some while the `jiggle` action is active.
+    mouse_jiggle: Option<MouseJiggleState>,
+    /// The lock LEDs of each layer from `linux-layer-leds`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub layer_leds: Vec<Option<LockLeds>>,
+    /// Sound played on layer changes for layers without their own sound.
+    sound_layer_change: Option<SoundCue>,
+    /// Sound played when caps-word activates.
+    sound_caps_word: Option<SoundCue>,
+    /// Sound played when a sequence times out.
+    sound_sequence_timeout: Option<SoundCue>,
     // if set, key taps of this code are sent whenever mouse movement events are passed through
     #[cfg(any(
         all(target_os = "windows", feature = "interception_driver"),
@@ -356,6 +605,8 @@ enum ReloadAction {
     ReloadPrev,
     ReloadNum(usize),
     ReloadFile(String),
+    ReloadVersionPrev,
+    ReloadVersionNext,
 }
 
 #[derive(Clone, Copy)]
@@ -390,38 +641,76 @@ const LINUX_PERMISSIONS_ERROR: &str = "Failed to open the output uinput device.
 
 impl Kanata {
     pub fn new(args: &ValidatedArgs) -> Result<Self> {
-        let cfg = match cfg::new_from_file(&args.paths[0]) {
+        cfg::set_os_keyboard_layout(detect_os_keyboard_layout());
+        let (text, cfg) = parse_cfg_file(&args.paths[0]);
+        let mut loaded_cfg = text.map(|text| (0, text));
+        let cfg = match cfg {
             Ok(c) => c,
             Err(e) => {
-                log::error!("{e:?}");
-                bail!("failed to parse file");
+                tracing::error!("{e:?}");
+                loaded_cfg = None;
+                match load_fallback_cfg(&args.paths[0], None, "the configuration failed to parse") {
+                    Some(cfg) => cfg,
+                    None => bail!("failed to parse file"),
+                }
             }
         };
 
-        let kbd_out = match KbdOut::new(
-            #[cfg(any(target_os = "linux", target_os = "android"))]
-            &args.symlink_path,
-            #[cfg(any(target_os = "linux", target_os = "android"))]
-            cfg.options.linux_opts.linux_use_trackpoint_property,
-            #[cfg(any(target_os = "linux", target_os = "android"))]
-            &cfg.options.linux_opts.linux_output_name,
-            #[cfg(any(target_os = "linux", target_os = "android"))]
-            match cfg.options.linux_opts.linux_output_bus_type {
-                LinuxCfgOutputBusType::BusUsb => evdev::BusType::BUS_USB,
-                LinuxCfgOutputBusType::BusI8042 => evdev::BusType::BUS_I8042,
-                LinuxCfgOutputBusType::BusVirtual => evdev::BusType::BUS_VIRTUAL,
-            },
-        ) {
-            Ok(kbd_out) => kbd_out,
+        #[cfg_attr(
+            not(any(target_os = "linux", target_os = "android")),
+            allow(unused_variables)
+        )]
+        let new_kbd_out = |cfg: &Cfg| {
+            KbdOut::new(
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                &args.symlink_path,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                cfg.options.linux_opts.linux_use_trackpoint_property,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                &cfg.options.linux_opts.linux_output_name,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                match cfg.options.linux_opts.linux_output_bus_type {
+                    LinuxCfgOutputBusType::BusUsb => evdev::BusType::BUS_USB,
+                    LinuxCfgOutputBusType::BusI8042 => evdev::BusType::BUS_I8042,
+                    LinuxCfgOutputBusType::BusVirtual => evdev::BusType::BUS_VIRTUAL,
+                    LinuxCfgOutputBusType::BusBluetooth => evdev::BusType::BUS_BLUETOOTH,
+                },
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                &cfg.options.linux_opts,
+            )
+        };
+        let (cfg, kbd_out) = match new_kbd_out(&cfg) {
+            Ok(kbd_out) => (cfg, kbd_out),
             Err(err) => {
-                error!("{LINUX_PERMISSIONS_ERROR}");
-                bail!(err)
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                let uinput =
+                    cfg.options.linux_opts.linux_output_backend == LinuxCfgOutputBackend::Uinput;
+                #[cfg(not(any(target_os = "linux", target_os = "android")))]
+                let uinput = true;
+                if uinput {
+                    error!("{LINUX_PERMISSIONS_ERROR}");
+                }
+                let reason = format!("the output device could not be created: {err}");
+                let Some(fallback) = load_fallback_cfg(&args.paths[0], Some(&cfg.options), &reason)
+                else {
+                    bail!(err)
+                };
+                loaded_cfg = None;
+                match new_kbd_out(&fallback) {
+                    Ok(kbd_out) => (fallback, kbd_out),
+                    Err(_) => bail!(err),
+                }
             }
         };
+        set_crash_bundle_config(&cfg.files);
+        #[cfg(feature = "cmd")]
+        set_cmd_allowlist(cfg.options.cmd_allowlist.clone());
+        #[cfg(feature = "cmd")]
+        set_cmd_timeout(cfg.options.cmd_timeout);
 
         #[cfg(target_os = "windows")]
         unsafe {
-            log::info!("Asking Windows to improve timer precision");
+            tracing::info!("Asking Windows to improve timer precision");
             if winapi::um::timeapi::timeBeginPeriod(1) == winapi::um::mmsystem::TIMERR_NOCANDO {
                 bail!("failed to improve timer precision");
             }
@@ -429,7 +718,7 @@ impl Kanata {
 
         #[cfg(target_os = "windows")]
         unsafe {
-            log::info!("Asking Windows to increase process priority");
+            tracing::info!("Asking Windows to increase process priority");
             winapi::um::processthreadsapi::SetPriorityClass(
                 winapi::um::processthreadsapi::GetCurrentProcess(),
                 winapi::um::winbase::REALTIME_PRIORITY_CLASS,
@@ -437,9 +726,12 @@ impl Kanata {
         }
 
         update_kbd_out(&cfg.options, &kbd_out)?;
+        set_emergency_chords(&cfg.options);
 
         #[cfg(target_os = "windows")]
         set_win_altgr_behaviour(cfg.options.windows_opts.windows_altgr);
+        #[cfg(all(target_os = "windows", not(feature = "simulated_input")))]
+        crate::oskbd::set_win_scancode_overrides(&cfg.options.windows_opts.scancode_overrides);
 
         *MAPPED_KEYS.lock() = cfg.mapped_keys;
         #[cfg(feature = "zippychord")]
@@ -447,10 +739,14 @@ impl Kanata {
             zch().zch_configure(cfg.zippy.unwrap_or_default());
         }
 
+        let openrgb_layer_colors = openrgb_layer_colors(&cfg.options, &cfg.layer_info);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let layer_leds = layer_leds(&cfg.options, &cfg.layer_info);
         Ok(Self {
             kbd_out,
             cfg_paths: args.paths.clone(),
             cur_cfg_idx: 0,
+            cfg_files: cfg.files,
             key_outputs: cfg.key_outputs,
             layout: cfg.layout,
             layer_info: cfg.layer_info,
@@ -468,9 +764,17 @@ impl Kanata {
             sequence_timeout: cfg.options.sequence_timeout,
             sequence_state: SequenceState::new(),
             sequences: cfg.sequences,
+            sequence_menu_shown: None,
+            compose: cfg.compose,
+            compose_state: None,
+            alias_concat_state: Default::default(),
             last_tick: web_time::Instant::now(),
             time_remainder: 0,
             live_reload_requested: false,
+            requested_trial_ms: None,
+            reload_trial: None,
+            reload_history: ReloadHistory::new(loaded_cfg.as_ref()),
+            loaded_cfg,
             overrides: cfg.overrides,
             override_states: OverrideStates::new(),
             #[cfg(target_os = "macos")]
@@ -512,6 +816,10 @@ impl Kanata {
             caps_word: None,
             movemouse_smooth_diagonals: cfg.options.movemouse_smooth_diagonals,
             override_release_on_activation: cfg.options.override_release_on_activation,
+            release_order: cfg
+                .options
+                .preserve_release_order
+                .then(ReleaseOrder::default),
             movemouse_inherit_accel_state: cfg.options.movemouse_inherit_accel_state,
             dynamic_macro_max_presses: cfg.options.dynamic_macro_max_presses,
             dynamic_macro_replay_behaviour: ReplayBehaviour {
@@ -525,6 +833,10 @@ impl Kanata {
                 .linux_opts
                 .linux_device_detect_mode
                 .expect("parser should default to some"),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            seat: cfg.options.linux_opts.linux_seat,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            input_backend: cfg.options.linux_opts.linux_input_backend,
             waiting_for_idle: HashSet::default(),
             waiting_for_physical_idle: HashSet::default(),
             vkeys_pending_release: HashMap::default(),
@@ -536,15 +848,60 @@ impl Kanata {
             unshifted_keys: vec![],
             last_pressed_key: KeyCode::No,
             virtual_keys: cfg.fake_keys,
+            layer_ids: cfg.layer_ids,
+            hold_tap_aliases: cfg.hold_tap_aliases,
             max_key_timing_check: cfg.max_key_timing_check,
             input_devices: cfg.input_devices,
-            #[cfg(feature = "tcp_server")]
-            tcp_server_address: args.tcp_server_address.clone(),
             #[cfg(all(target_os = "windows", feature = "gui"))]
             gui_opts: cfg.options.gui_opts,
             allow_hardware_repeat: cfg.options.allow_hardware_repeat,
+            realtime_priority: cfg.options.realtime_priority,
             macro_on_press_cancel_duration: 0,
             saved_clipboard_content: Default::default(),
+            midi_out: MidiOut::new(cfg.options.midi_output_port.clone()),
+            obs: ObsClient::new(
+                cfg.options.obs_websocket_address.clone(),
+                cfg.options.obs_websocket_password.clone(),
+            ),
+            openrgb: OpenRgbClient::new(
+                cfg.options.openrgb_address.clone(),
+                cfg.options.openrgb_device.clone(),
+            ),
+            openrgb_layer_colors,
+            key_repeat: cfg.key_repeat,
+            webhooks: Webhooks::new(cfg.webhooks),
+            apps: cfg.apps,
+            config_meta: cfg.meta,
+            alias_docs: cfg.alias_docs,
+            virtual_key_docs: cfg.virtual_key_docs,
+            app_state: Default::default(),
+            auto_return_state: Default::default(),
+            notifier: Notifier::default(),
+            mpris: MprisClient::default(),
+            software_repeat: None,
+            no_repeat_keys: vec![],
+            emergency_passthrough: false,
+            processing_pause: None,
+            prev_processing_paused: false,
+            os_layout_changed: false,
+            device_changes: vec![],
+            localkeys_for_os_layouts: cfg.localkeys_for_os_layouts,
+            latency: LatencyMeasurement::new_if_enabled(),
+            ngram_stats: NgramStats::new_if_enabled(),
+            key_presses: 0,
+            typing_speed: Default::default(),
+            training: None,
+            training_messages: vec![],
+            stuck_keys: StuckKeys::new(cfg.options.stuck_key_timeout),
+            debouncer: Debouncer::new(cfg.debounce),
+            deduplicator: Deduplicator::new(cfg.options.dedupe_input_ms),
+            mouse_grid: MouseGridState::default(),
+            mouse_jiggle: None,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            layer_leds,
+            sound_layer_change: cfg.options.sound_layer_change.clone(),
+            sound_caps_word: cfg.options.sound_caps_word.clone(),
+            sound_sequence_timeout: cfg.options.sound_sequence_timeout.clone(),
             #[cfg(any(
                 all(target_os = "windows", feature = "interception_driver"),
                 any(target_os = "linux", target_os = "android"),
@@ -584,11 +941,21 @@ impl Kanata {
                 LinuxCfgOutputBusType::BusUsb => evdev::BusType::BUS_USB,
                 LinuxCfgOutputBusType::BusI8042 => evdev::BusType::BUS_I8042,
                 LinuxCfgOutputBusType::BusVirtual => evdev::BusType::BUS_VIRTUAL,
+                LinuxCfgOutputBusType::BusBluetooth => evdev::BusType::BUS_BLUETOOTH,
             },
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            &cfg.options.linux_opts,
         ) {
             Ok(kbd_out) => kbd_out,
             Err(err) => {
-                error!("{LINUX_PERMISSIONS_ERROR}");
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                let uinput =
+                    cfg.options.linux_opts.linux_output_backend == LinuxCfgOutputBackend::Uinput;
+                #[cfg(not(any(target_os = "linux", target_os = "android")))]
+                let uinput = true;
+                if uinput {
+                    error!("{LINUX_PERMISSIONS_ERROR}");
+                }
                 bail!(err)
             }
         };
@@ -598,11 +965,19 @@ impl Kanata {
         {
             zch().zch_configure(cfg.zippy.unwrap_or_default());
         }
+        #[cfg(feature = "cmd")]
+        set_cmd_allowlist(cfg.options.cmd_allowlist.clone());
+        #[cfg(feature = "cmd")]
+        set_cmd_timeout(cfg.options.cmd_timeout);
 
+        let openrgb_layer_colors = openrgb_layer_colors(&cfg.options, &cfg.layer_info);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let layer_leds = layer_leds(&cfg.options, &cfg.layer_info);
         Ok(Self {
             kbd_out,
             cfg_paths: vec!["config string".into()],
             cur_cfg_idx: 0,
+            cfg_files: cfg.files,
             key_outputs: cfg.key_outputs,
             layout: cfg.layout,
             layer_info: cfg.layer_info,
@@ -620,9 +995,17 @@ impl Kanata {
             sequence_timeout: cfg.options.sequence_timeout,
             sequence_state: SequenceState::new(),
             sequences: cfg.sequences,
+            sequence_menu_shown: None,
+            compose: cfg.compose,
+            compose_state: None,
+            alias_concat_state: Default::default(),
             last_tick: web_time::Instant::now(),
             time_remainder: 0,
             live_reload_requested: false,
+            requested_trial_ms: None,
+            reload_trial: None,
+            loaded_cfg: None,
+            reload_history: Default::default(),
             overrides: cfg.overrides,
             override_states: OverrideStates::new(),
             #[cfg(target_os = "macos")]
@@ -664,6 +1047,10 @@ impl Kanata {
             caps_word: None,
             movemouse_smooth_diagonals: cfg.options.movemouse_smooth_diagonals,
             override_release_on_activation: cfg.options.override_release_on_activation,
+            release_order: cfg
+                .options
+                .preserve_release_order
+                .then(ReleaseOrder::default),
             movemouse_inherit_accel_state: cfg.options.movemouse_inherit_accel_state,
             dynamic_macro_max_presses: cfg.options.dynamic_macro_max_presses,
             dynamic_macro_replay_behaviour: ReplayBehaviour {
@@ -677,6 +1064,10 @@ impl Kanata {
                 .linux_opts
                 .linux_device_detect_mode
                 .expect("parser should default to some"),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            seat: cfg.options.linux_opts.linux_seat,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            input_backend: cfg.options.linux_opts.linux_input_backend,
             waiting_for_idle: HashSet::default(),
             waiting_for_physical_idle: HashSet::default(),
             vkeys_pending_release: HashMap::default(),
@@ -688,15 +1079,60 @@ impl Kanata {
             unshifted_keys: vec![],
             last_pressed_key: KeyCode::No,
             virtual_keys: cfg.fake_keys,
+            layer_ids: cfg.layer_ids,
+            hold_tap_aliases: cfg.hold_tap_aliases,
             max_key_timing_check: cfg.max_key_timing_check,
             input_devices: cfg.input_devices,
-            #[cfg(feature = "tcp_server")]
-            tcp_server_address: None,
             #[cfg(all(target_os = "windows", feature = "gui"))]
             gui_opts: cfg.options.gui_opts,
             allow_hardware_repeat: cfg.options.allow_hardware_repeat,
+            realtime_priority: cfg.options.realtime_priority,
             macro_on_press_cancel_duration: 0,
             saved_clipboard_content: Default::default(),
+            midi_out: MidiOut::new(cfg.options.midi_output_port.clone()),
+            obs: ObsClient::new(
+                cfg.options.obs_websocket_address.clone(),
+                cfg.options.obs_websocket_password.clone(),
+            ),
+            openrgb: OpenRgbClient::new(
+                cfg.options.openrgb_address.clone(),
+                cfg.options.openrgb_device.clone(),
+            ),
+            openrgb_layer_colors,
+            key_repeat: cfg.key_repeat,
+            webhooks: Webhooks::new(cfg.webhooks),
+            apps: cfg.apps,
+            config_meta: cfg.meta,
+            alias_docs: cfg.alias_docs,
+            virtual_key_docs: cfg.virtual_key_docs,
+            app_state: Default::default(),
+            auto_return_state: Default::default(),
+            notifier: Notifier::default(),
+            mpris: MprisClient::default(),
+            software_repeat: None,
+            no_repeat_keys: vec![],
+            emergency_passthrough: false,
+            processing_pause: None,
+            prev_processing_paused: false,
+            os_layout_changed: false,
+            device_changes: vec![],
+            localkeys_for_os_layouts: cfg.localkeys_for_os_layouts,
+            latency: LatencyMeasurement::new_if_enabled(),
+            ngram_stats: NgramStats::new_if_enabled(),
+            key_presses: 0,
+            typing_speed: Default::default(),
+            training: None,
+            training_messages: vec![],
+            stuck_keys: StuckKeys::new(cfg.options.stuck_key_timeout),
+            debouncer: Debouncer::new(cfg.debounce),
+            deduplicator: Deduplicator::new(cfg.options.dedupe_input_ms),
+            mouse_grid: MouseGridState::default(),
+            mouse_jiggle: None,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            layer_leds,
+            sound_layer_change: cfg.options.sound_layer_change.clone(),
+            sound_caps_word: cfg.options.sound_caps_word.clone(),
+            sound_sequence_timeout: cfg.options.sound_sequence_timeout.clone(),
             #[cfg(any(
                 all(target_os = "windows", feature = "interception_driver"),
                 target_os = "linux",
@@ -726,44 +1162,146 @@ impl Kanata {
     }
 
     fn do_live_reload(&mut self, _tx: &Option<Sender<ServerMessage>>) -> Result<()> {
-        let cfg = match cfg::new_from_file(&self.cfg_paths[self.cur_cfg_idx]) {
+        let _watchdog = watchdog_pause();
+        if let Some(version) = self.take_requested_version() {
+            return self.load_cfg_version(version, _tx);
+        }
+        let _span = tracing::info_span!(
+            "reload",
+            path = %self.cfg_paths[self.cur_cfg_idx].display()
+        )
+        .entered();
+        let (text, cfg) = parse_cfg_file(&self.cfg_paths[self.cur_cfg_idx]);
+        let cfg = match cfg {
             Ok(c) => c,
             Err(e) => {
-                log::error!("{e:?}");
+                tracing::error!("{e:?}");
                 #[cfg(feature = "tcp_server")]
                 {
                     self.last_reload_ok = false;
                 }
+                let message = e.help().map(|h| h.to_string()).unwrap_or(e.to_string());
+                #[cfg(all(
+                    target_os = "linux",
+                    feature = "dbus",
+                    not(feature = "simulated_output")
+                ))]
+                tray_error(message.clone());
+                if self.webhooks.has_event(WebhookEvent::Error) {
+                    self.webhooks.send_event(
+                        &mut self.kbd_out,
+                        WebhookEvent::Error,
+                        serde_json::json!({ "message": message }),
+                    );
+                }
+                self.requested_trial_ms = None;
                 bail!("failed to parse config file");
             }
         };
+        #[cfg(any(target_os = "linux", target_os = "windows"))]
+        if let Err(e) = check_user_cfg(&self.cfg_paths[self.cur_cfg_idx], &cfg) {
+            tracing::error!("{e}");
+            #[cfg(feature = "tcp_server")]
+            {
+                self.last_reload_ok = false;
+            }
+            self.requested_trial_ms = None;
+            bail!("failed to load config file");
+        }
+        let changes = self.reload_changes(text.as_deref(), &cfg.layer_info);
+        let previous = std::mem::replace(
+            &mut self.loaded_cfg,
+            text.map(|text| (self.cur_cfg_idx, text)),
+        );
+        self.apply_live_reload(cfg, changes, _tx)?;
+        self.record_loaded_cfg();
+        self.start_reload_trial(previous);
+        Ok(())
+    }
+
+    /// Replaces the configuration with the parsed configuration of a live reload.
+    fn apply_live_reload(
+        &mut self,
+        mut cfg: Cfg,
+        changes: ReloadChanges,
+        _tx: &Option<Sender<ServerMessage>>,
+    ) -> Result<()> {
         update_kbd_out(&cfg.options, &self.kbd_out)?;
+        set_emergency_chords(&cfg.options);
         #[cfg(target_os = "windows")]
         set_win_altgr_behaviour(cfg.options.windows_opts.windows_altgr);
+        #[cfg(all(target_os = "windows", not(feature = "simulated_input")))]
+        crate::oskbd::set_win_scancode_overrides(&cfg.options.windows_opts.scancode_overrides);
         self.sequence_backtrack_modcancel = cfg.options.sequence_backtrack_modcancel;
         self.sequence_always_on = cfg.options.sequence_always_on;
         self.sequence_input_mode = cfg.options.sequence_input_mode;
         self.sequence_timeout = cfg.options.sequence_timeout;
+        let held_keys = self.carry_over_layout_state(&mut cfg.layout, &cfg.layer_info);
         self.layout = cfg.layout;
         self.key_outputs = cfg.key_outputs;
         self.layer_info = cfg.layer_info;
         self.sequences = cfg.sequences;
+        self.compose = cfg.compose;
+        self.compose_state = None;
+        self.alias_concat_state = Default::default();
         self.overrides = cfg.overrides;
         self.log_layer_changes =
             get_forced_log_layer_changes().unwrap_or(cfg.options.log_layer_changes);
         self.movemouse_smooth_diagonals = cfg.options.movemouse_smooth_diagonals;
         self.override_release_on_activation = cfg.options.override_release_on_activation;
+        self.release_order = cfg
+            .options
+            .preserve_release_order
+            .then(ReleaseOrder::default);
         self.movemouse_inherit_accel_state = cfg.options.movemouse_inherit_accel_state;
         self.dynamic_macro_max_presses = cfg.options.dynamic_macro_max_presses;
         self.dynamic_macro_replay_behaviour = ReplayBehaviour {
             delay: cfg.options.dynamic_macro_replay_delay_behaviour,
         };
         self.max_key_timing_check = cfg.max_key_timing_check;
+        self.midi_out.set_port(cfg.options.midi_output_port.clone());
+        self.obs.set_config(
+            cfg.options.obs_websocket_address.clone(),
+            cfg.options.obs_websocket_password.clone(),
+        );
+        self.openrgb.set_config(
+            cfg.options.openrgb_address.clone(),
+            cfg.options.openrgb_device.clone(),
+        );
+        self.openrgb_layer_colors = openrgb_layer_colors(&cfg.options, &self.layer_info);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            self.layer_leds = layer_leds(&cfg.options, &self.layer_info);
+        }
+        self.sound_layer_change = cfg.options.sound_layer_change.clone();
+        self.sound_caps_word = cfg.options.sound_caps_word.clone();
+        self.sound_sequence_timeout = cfg.options.sound_sequence_timeout.clone();
+        self.stuck_keys = StuckKeys::new(cfg.options.stuck_key_timeout);
+        self.debouncer.set_cfgs(cfg.debounce);
+        self.deduplicator = Deduplicator::new(cfg.options.dedupe_input_ms);
+        self.key_repeat = cfg.key_repeat;
+        self.localkeys_for_os_layouts = cfg.localkeys_for_os_layouts;
+        self.webhooks.set_webhooks(cfg.webhooks);
+        self.apps = cfg.apps;
+        self.config_meta = cfg.meta;
+        self.alias_docs = cfg.alias_docs;
+        self.virtual_key_docs = cfg.virtual_key_docs;
+        self.app_state = Default::default();
+        self.auto_return_state = Default::default();
+        self.software_repeat = None;
+        set_crash_bundle_config(&cfg.files);
+        #[cfg(feature = "cmd")]
+        set_cmd_allowlist(cfg.options.cmd_allowlist.clone());
+        #[cfg(feature = "cmd")]
+        set_cmd_timeout(cfg.options.cmd_timeout);
+        self.cfg_files = cfg.files;
         // Note: input_devices is intentionally not updated on live reload.
         // The KbdIn device_hash_to_id map is built at startup and not rebuilt.
         // This matches behavior of other device configs (macos-dev-names-include, etc.).
         // See: https://github.com/malpern/kanata/issues/13
         self.virtual_keys = cfg.fake_keys;
+        self.layer_ids = cfg.layer_ids;
+        self.hold_tap_aliases = cfg.hold_tap_aliases;
         #[cfg(target_os = "windows")]
         {
             self.windows_sync_keystates = cfg.options.windows_opts.sync_keystates;
@@ -792,7 +1330,16 @@ impl Kanata {
         // The macOS mouse-tap reload hook is invoked further down, *after* the
         // `mouse_movement_key` mutate, so its install gate sees fresh state
         // for both `MAPPED_KEYS` and `mouse_movement_key`.
-        log::info!("Live reload successful");
+        tracing::info!("Live reload successful");
+        log_reload_changes(&changes);
+        if self.webhooks.has_event(WebhookEvent::Reload) {
+            let path = self.cfg_paths[self.cur_cfg_idx].display().to_string();
+            self.webhooks.send_event(
+                &mut self.kbd_out,
+                WebhookEvent::Reload,
+                serde_json::json!({ "path": path }),
+            );
+        }
         #[cfg(feature = "tcp_server")]
         if let Some(tx) = _tx {
             match tx.try_send(ServerMessage::ConfigFileReload {
@@ -800,10 +1347,11 @@ impl Kanata {
                     .to_str()
                     .unwrap()
                     .to_string(),
+                changes: Some(changes),
             }) {
                 Ok(_) => {}
                 Err(error) => {
-                    log::error!(
+                    tracing::error!(
                         "could not send ConfigFileReload event notification: {}",
                         error
                     );
@@ -814,6 +1362,9 @@ impl Kanata {
         let cur_layer = self.layout.bm().current_layer();
         self.prev_layer = cur_layer;
         self.print_layer(cur_layer);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        self.kbd_out.set_lock_leds(self.layer_leds[cur_layer]);
+        self.set_openrgb_layer_color(cur_layer);
         self.macro_on_press_cancel_duration = 0;
 
         #[cfg(any(
@@ -829,7 +1380,7 @@ impl Kanata {
                 if self.mouse_movement_key.lock().is_none()
                     && cfg.options.mouse_movement_key.is_some()
                 {
-                    log::warn!(
+                    tracing::warn!(
                         "defcfg option mouse-movement-key will not take effect until kanata is restarted!"
                     );
                 }
@@ -841,7 +1392,10 @@ impl Kanata {
             crate::oskbd::ensure_mouse_listener_installed_after_reload();
         }
 
-        PRESSED_KEYS.lock().clear();
+        #[cfg(not(all(target_os = "windows", not(feature = "interception_driver"))))]
+        PRESSED_KEYS.lock().retain(|osc| held_keys.contains(osc));
+        #[cfg(all(target_os = "windows", not(feature = "interception_driver")))]
+        PRESSED_KEYS.lock().retain(|osc, _| held_keys.contains(osc));
 
         #[cfg(feature = "tcp_server")]
         if let Some(tx) = _tx {
@@ -849,7 +1403,7 @@ impl Kanata {
             match tx.try_send(ServerMessage::LayerChange { new }) {
                 Ok(_) => {}
                 Err(error) => {
-                    log::error!("could not send LayerChange event notification: {}", error);
+                    tracing::error!("could not send LayerChange event notification: {}", error);
                 }
             }
         }
@@ -866,15 +1420,48 @@ impl Kanata {
 
     /// Update keyberon layout state for press/release, handle repeat separately
     pub fn handle_input_event(&mut self, event: &KeyEvent) -> Result<()> {
-        log::debug!("process recv ev {event:?}");
+        let _span =
+            tracing::debug_span!("input", key = %event.code, value = ?event.value).entered();
+        tracing::debug!("process recv ev {event:?}");
+        trace_input(event);
+        if self.dedupe_input_event(event) {
+            return Ok(());
+        }
+        match self.debounce_input_event(event) {
+            Some(event) => self.handle_debounced_input_event(&event),
+            None => Ok(()),
+        }
+    }
+
+    /// Handles an input event that debouncing let through.
+    fn handle_debounced_input_event(&mut self, event: &KeyEvent) -> Result<()> {
         if event.value == KeyValue::Press {
+            self.key_presses += 1;
+            self.record_typing_speed(event.code);
             self.layout
                 .bm()
                 .device_history
                 .push_front(event.device_id());
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            self.kbd_out.set_output_route(event.device_id());
         }
         let evc: u16 = event.code.into();
         self.ticks_since_idle = 0;
+        self.auto_return_state.input();
+        if self.handle_paused_input_event(event)? {
+            return Ok(());
+        }
+        if event.value == KeyValue::Press {
+            self.record_ngram(event.code);
+            self.record_training_key(event.code);
+        }
+        if let Some(release_order) = &mut self.release_order {
+            match event.value {
+                KeyValue::Press => release_order.physical_press(event.code),
+                KeyValue::Release => release_order.physical_release(event.code),
+                _ => {}
+            }
+        }
         let kbrn_ev = match event.value {
             KeyValue::Press => {
                 if let Some((macro_id, recorded_macro)) = record_press(
@@ -885,7 +1472,7 @@ impl Kanata {
                     self.dynamic_macros.insert(macro_id, recorded_macro);
                 }
                 if self.macro_on_press_cancel_duration > 0 {
-                    log::debug!("cancelling all macros: other press");
+                    tracing::debug!("cancelling all macros: other press");
                     self.macro_on_press_cancel_duration = 0;
                     let layout = self.layout.bm();
                     layout.active_sequences.clear();
@@ -942,7 +1529,14 @@ impl Kanata {
     /// Returns the number of ticks that elapsed.
     fn handle_time_ticks(&mut self, tx: &Option<Sender<ServerMessage>>) -> Result<u16> {
         let ms_elapsed = self.get_ms_elapsed();
-        self.tick_ms(ms_elapsed, tx)?;
+        if let Err(e) = self.tick_ms(ms_elapsed, tx) {
+            // A trial reload is rolled back instead of stopping kanata.
+            if self.reload_trial.is_none() {
+                return Err(e);
+            }
+            self.roll_back_reload(&format!("processing failed: {e}"), tx);
+        }
+        self.tick_reload_trial(ms_elapsed, tx);
 
         if self.live_reload_requested
             && ((self.prev_keys.is_empty() && self.cur_keys.is_empty())
@@ -959,12 +1553,12 @@ impl Kanata {
             // kanata states.
             self.live_reload_requested = false;
             if let Err(e) = self.do_live_reload(tx) {
-                log::error!("live reload failed {e}");
+                tracing::error!("live reload failed {e}");
             }
         }
 
         #[cfg(feature = "perf_logging")]
-        log::info!("ms elapsed: {ms_elapsed}");
+        tracing::info!("ms elapsed: {ms_elapsed}");
         // Note regarding `as` casting. It doesn't really matter if the result would truncate and
         // end up being wrong. Prefer to do the cheaper operation, as compared to doing the min of
         // u16::MAX and ms_elapsed.
@@ -981,7 +1575,7 @@ impl Kanata {
             ) {
                 self.layout.bm().event(event.key_event());
                 extra_ticks = extra_ticks.saturating_add(event.delay());
-                log::debug!("dyn macro extra ticks: {extra_ticks}, ms_elapsed: {ms_elapsed}");
+                tracing::debug!("dyn macro extra ticks: {extra_ticks}, ms_elapsed: {ms_elapsed}");
             }
         }
         for i in 0..(extra_ticks.saturating_sub(ms_elapsed as u16)) {
@@ -992,7 +1586,7 @@ impl Kanata {
             )
             .is_some()
             {
-                log::error!("overshot to next event at iteration #{i}, the code is broken!");
+                tracing::error!("overshot to next event at iteration #{i}, the code is broken!");
                 break;
             }
         }
@@ -1017,18 +1611,36 @@ impl Kanata {
     }
 
     fn tick_states(&mut self, _tx: &Option<Sender<ServerMessage>>) -> Result<()> {
+        begin_burst(&mut self.kbd_out);
+        self.check_handle_emergency_passthrough(_tx);
+        self.check_handle_processing_pause_change(_tx);
+        self.check_handle_os_layout_change(_tx);
+        self.check_handle_device_changes(_tx);
+        self.check_push_typing_speed(_tx);
+        self.check_handle_training_messages(_tx);
+        self.tick_dedupe();
+        self.tick_debounce()?;
+        self.tick_software_repeat()?;
+        self.tick_stuck_keys(_tx);
+        #[cfg(feature = "cmd")]
+        self.tick_cmd_output_keys()?;
         self.live_reload_requested |= self.handle_keystate_changes(_tx)?;
         self.handle_scrolling()?;
         self.handle_move_mouse()?;
+        self.tick_mouse_jiggle()?;
         self.tick_sequence_state()?;
+        self.check_handle_sequence_menu(_tx);
+        self.tick_compose_state();
         self.tick_idle_timeout();
         self.tick_physical_idle_timeout();
+        self.tick_auto_return();
         self.macro_on_press_cancel_duration = self.macro_on_press_cancel_duration.saturating_sub(1);
         tick_record_state(&mut self.dynamic_macro_record_state);
         zippy_tick(self.caps_word.is_some());
         self.prev_keys.clear();
         self.prev_keys.append(&mut self.cur_keys);
         self.tick_held_vkeys();
+        end_burst(&mut self.kbd_out)?;
         #[cfg(feature = "simulated_output")]
         {
             self.kbd_out.tick();
@@ -1080,7 +1692,7 @@ impl Kanata {
                 mmsv.ticks_until_move = mmsv.interval - 1;
                 let scaled_distance =
                     apply_mouse_distance_modifiers(mmsv.distance, &self.move_mouse_speed_modifiers);
-                log::debug!("handle_move_mouse: scaled vdistance: {}", scaled_distance);
+                tracing::debug!("handle_move_mouse: scaled vdistance: {}", scaled_distance);
 
                 let current_move = CalculatedMouseMove {
                     direction: mmsv.direction,
@@ -1129,7 +1741,7 @@ impl Kanata {
                 mmsh.ticks_until_move = mmsh.interval - 1;
                 let scaled_distance =
                     apply_mouse_distance_modifiers(mmsh.distance, &self.move_mouse_speed_modifiers);
-                log::debug!("handle_move_mouse: scaled hdistance: {}", scaled_distance);
+                tracing::debug!("handle_move_mouse: scaled hdistance: {}", scaled_distance);
 
                 let current_move = CalculatedMouseMove {
                     direction: mmsh.direction,
@@ -1167,13 +1779,27 @@ impl Kanata {
         if let Some(state) = self.sequence_state.get_active() {
             state.ticks_until_timeout -= 1;
             if state.ticks_until_timeout == 0 {
-                log::debug!("sequence timeout; exiting sequence state");
+                tracing::debug!("sequence timeout; exiting sequence state");
                 cancel_sequence(state, &mut self.kbd_out)?;
+                if let Some(sound) = &self.sound_sequence_timeout {
+                    play_sound(&mut self.kbd_out, sound);
+                }
             }
         }
         Ok(())
     }
 
+    fn tick_compose_state(&mut self) {
+        if self
+            .compose_state
+            .as_mut()
+            .is_some_and(|state| state.tick())
+        {
+            tracing::debug!("compose timeout; exiting compose state");
+            self.compose_state = None;
+        }
+    }
+
     fn tick_idle_timeout(&mut self) {
         if self.waiting_for_idle.is_empty() {
             return;
@@ -1208,6 +1834,15 @@ impl Kanata {
         })
     }
 
+    fn tick_auto_return(&mut self) {
+        let layout = self.layout.bm();
+        let timeout = self.layer_info[layout.default_layer].auto_return;
+        if let Some(layer) = self.auto_return_state.tick(layout.default_layer, timeout) {
+            tracing::debug!("no input within auto-return; switching to layer {layer}");
+            layout.set_default_layer(layer);
+        }
+    }
+
     /// Sends OS key events according to the change in key state between the current and the
     /// previous keyberon keystate. Also processes any custom actions.
     ///
@@ -1216,7 +1851,14 @@ impl Kanata {
     /// Returns whether live reload was requested.
     fn handle_keystate_changes(&mut self, _tx: &Option<Sender<ServerMessage>>) -> Result<bool> {
         let layout = self.layout.bm();
+        let event_trace = event_trace_enabled();
+        if event_trace {
+            layout.decision_trace.set_enabled(true);
+        }
         let custom_event = layout.tick();
+        if event_trace {
+            trace_decisions(layout);
+        }
 
         #[cfg(feature = "tcp_server")]
         if let Some(hold_info) = layout.tap_hold_tracker.take_hold_activated()
@@ -1225,11 +1867,11 @@ impl Kanata {
         {
             let osc = OsCode::from(hold_info.coord.1);
             let key = osc.to_string().to_lowercase();
-            log::debug!("HoldActivated: key={key} coord={:?}", hold_info.coord);
+            tracing::debug!("HoldActivated: key={key} coord={:?}", hold_info.coord);
             match tx.try_send(ServerMessage::HoldActivated { key }) {
                 Ok(_) => {}
                 Err(error) => {
-                    log::error!("could not send HoldActivated event: {}", error);
+                    tracing::error!("could not send HoldActivated event: {}", error);
                 }
             }
         }
@@ -1240,11 +1882,11 @@ impl Kanata {
         {
             let osc = OsCode::from(tap_info.coord.1);
             let key = osc.to_string().to_lowercase();
-            log::debug!("TapActivated: key={key} coord={:?}", tap_info.coord);
+            tracing::debug!("TapActivated: key={key} coord={:?}", tap_info.coord);
             match tx.try_send(ServerMessage::TapActivated { key }) {
                 Ok(_) => {}
                 Err(error) => {
-                    log::error!("could not send TapActivated event: {}", error);
+                    tracing::error!("could not send TapActivated event: {}", error);
                 }
             }
         }
@@ -1272,7 +1914,7 @@ impl Kanata {
                 }
                 CustomAction::SequenceNoerase(noerase_count) => {
                     if let Some(state) = self.sequence_state.get_active() {
-                        log::debug!("adding noerase: {noerase_count}");
+                        tracing::debug!("adding noerase: {noerase_count}");
                         add_noerase(state, *noerase_count);
                     }
                 }
@@ -1372,22 +2014,31 @@ impl Kanata {
         //
         // Given that there appears to be no practical negative consequences for this bug
         // remaining.
-        log::trace!("{:?}", &self.prev_keys);
-        let mut fwd_release = self.prev_keys.iter();
-        let mut rev_release = self.prev_keys.iter().rev();
+        tracing::trace!("{:?}", &self.prev_keys);
+        let released = |k: &&KeyCode| !cur_keys.contains(k);
+        let mut fwd_release = self.prev_keys.iter().filter(released);
+        let mut rev_release = self.prev_keys.iter().rev().filter(released);
         let keys: &mut dyn Iterator<Item = &KeyCode> = match reverse_release_order {
             false => &mut fwd_release,
             true => &mut rev_release,
         };
-        for k in keys {
-            if cur_keys.contains(k) {
-                continue;
+        let ordered_release;
+        let mut ordered_release_iter;
+        let keys: &mut dyn Iterator<Item = &KeyCode> = match &mut self.release_order {
+            Some(release_order) => {
+                ordered_release = release_order.order_releases(keys, cur_keys);
+                ordered_release_iter = ordered_release.iter();
+                &mut ordered_release_iter
             }
-            log::debug!("key release   {:?}", k);
+            None => keys,
+        };
+        for k in keys {
+            tracing::debug!("key release   {:?}", k);
             if let Err(e) = release_key(&mut self.kbd_out, k.into()) {
                 bail!("failed to release key: {:?}", e);
             }
         }
+        self.no_repeat_keys.retain(|k| cur_keys.contains(k));
 
         if cur_keys.is_empty()
             && !self.prev_keys.is_empty()
@@ -1399,17 +2050,18 @@ impl Kanata {
                 .sequences
                 .get_or_descendant_exists(&state.overlapped_sequence)
             {
-                HasValue((i, j)) => {
+                HasValue(output)
+                    if unmodded_steps_match(state, &state.overlapped_sequence, output) =>
+                {
                     do_successful_sequence_termination(
                         &mut self.kbd_out,
                         state,
                         layout,
-                        i,
-                        j,
+                        output,
                         EndSequenceType::Overlap,
                     )?;
                 }
-                NotInTrie => {
+                HasValue(..) | NotInTrie => {
                     // Overwrite overlapped with non-overlapped tracking
                     state.overlapped_sequence.clear();
                     state
@@ -1422,10 +2074,10 @@ impl Kanata {
 
         // Press keys that exist in the current state but are missing from the previous state.
         // Comment above regarding Vec/HashSet also applies here.
-        log::trace!("{cur_keys:?}");
+        tracing::trace!("{cur_keys:?}");
         for k in cur_keys.iter() {
             if self.prev_keys.contains(k) {
-                log::trace!("{k:?} is old press");
+                tracing::trace!("{k:?} is old press");
                 continue;
             }
             // Note - keyberon can return duplicates of a key in the keycodes()
@@ -1437,13 +2089,39 @@ impl Kanata {
             // allocations and logic.
             self.prev_keys.push(*k);
             self.last_pressed_key = *k;
+            if let Some(release_order) = &mut self.release_order {
+                release_order.output_press(*k, &layout.states);
+            }
+            if !self.key_repeat.is_empty() {
+                self.software_repeat = SoftwareRepeatState::on_press(
+                    &self.key_repeat,
+                    layout.current_layer(),
+                    k.into(),
+                );
+                if self.key_repeat.layer_has_no_repeat(layout.current_layer()) {
+                    self.no_repeat_keys.push(*k);
+                }
+            }
 
             if self.sequence_always_on && self.sequence_state.is_inactive() {
                 self.sequence_state
                     .activate(self.sequence_input_mode, self.sequence_timeout);
             }
 
-            if let Some(state) = self.sequence_state.get_active() {
+            if let Some(state) = &mut self.compose_state
+                && !OsCode::from(*k).is_modifier()
+            {
+                let next = do_compose_press_logic(
+                    state,
+                    k.into(),
+                    get_mod_mask_for_cur_keys(cur_keys),
+                    &self.compose,
+                    &mut self.kbd_out,
+                )?;
+                if let ComposeNextState::End = next {
+                    self.compose_state = None;
+                }
+            } else if let Some(state) = self.sequence_state.get_active() {
                 do_sequence_press_logic(
                     state,
                     k,
@@ -1454,7 +2132,7 @@ impl Kanata {
                     layout,
                 )?;
             } else {
-                log::debug!("key press     {:?}", k);
+                tracing::debug!("key press     {:?}", k);
                 if let Err(e) = press_key(&mut self.kbd_out, k.into()) {
                     bail!("failed to press key: {:?}", e);
                 }
@@ -1473,6 +2151,16 @@ impl Kanata {
                     // For unicode, only send on the press. No repeat action is supported for this for
                     // now.
                     CustomAction::Unicode(c) => self.kbd_out.send_unicode(*c)?,
+                    CustomAction::UnicodeStr(text) => self.kbd_out.send_unicode_str(text)?,
+                    CustomAction::SecretType(name) => type_secret(&mut self.kbd_out, name)?,
+                    CustomAction::DateTime(format) => self
+                        .kbd_out
+                        .send_unicode_str(&format_datetime(format, now_local()))?,
+                    CustomAction::LogLevel { level, duration_ms } => set_log_level(
+                        *level,
+                        (*duration_ms > 0)
+                            .then(|| time::Duration::from_millis((*duration_ms).into())),
+                    ),
                     CustomAction::LiveReload => {
                         reload_action = Some(ReloadAction::Reload);
                     }
@@ -1488,13 +2176,19 @@ impl Kanata {
                     CustomAction::LiveReloadFile(path) => {
                         reload_action = Some(ReloadAction::ReloadFile(path.to_string()));
                     }
+                    CustomAction::LiveReloadVersionPrev => {
+                        reload_action = Some(ReloadAction::ReloadVersionPrev);
+                    }
+                    CustomAction::LiveReloadVersionNext => {
+                        reload_action = Some(ReloadAction::ReloadVersionNext);
+                    }
                     CustomAction::Mouse(btn) => {
                         self.kbd_out.click_btn(*btn)?;
                     }
                     CustomAction::MouseTap(btn) => {
-                        log::debug!("click     {:?}", btn);
+                        tracing::debug!("click     {:?}", btn);
                         self.kbd_out.click_btn(*btn)?;
-                        log::debug!("unclick   {:?}", btn);
+                        tracing::debug!("unclick   {:?}", btn);
                         self.kbd_out.release_btn(*btn)?;
                     }
                     CustomAction::MWheel {
@@ -1502,32 +2196,34 @@ impl Kanata {
                         interval,
                         distance,
                         inertial_scroll_params,
+                        accel,
+                        smooth,
                     } => match direction {
                         MWheelDirection::Up | MWheelDirection::Down => {
-                            self.scroll_state = Some(ScrollState {
-                                direction: *direction,
-                                distance: *distance,
-                                ticks_until_scroll: 0,
-                                interval: *interval,
-                                scroll_accel_state: inertial_scroll_params.as_ref().map(|isp|
-                                    ScrollAccelState {
+                            self.scroll_state = Some(ScrollState::new(
+                                *direction,
+                                *interval,
+                                *distance,
+                                inertial_scroll_params.as_ref().map(|isp| ScrollAccelState {
                                     deceleration_multiplier: isp.deceleration_multiplier.0,
                                     acceleration_multiplier: isp.acceleration_multiplier.0,
                                     max_velocity: isp.maximum_velocity.0,
                                     current_velocity: isp.initial_velocity.0,
                                     scroll_released: false,
-                                    }
-                                ),
-                            })
+                                }),
+                                *accel,
+                                *smooth,
+                            ))
                         }
                         MWheelDirection::Left | MWheelDirection::Right => {
-                            self.hscroll_state = Some(ScrollState {
-                                direction: *direction,
-                                distance: *distance,
-                                ticks_until_scroll: 0,
-                                interval: *interval,
-                                scroll_accel_state: None,
-                            })
+                            self.hscroll_state = Some(ScrollState::new(
+                                *direction,
+                                *interval,
+                                *distance,
+                                None,
+                                *accel,
+                                *smooth,
+                            ))
                         }
                     },
                     CustomAction::MWheelNotch { direction } => {
@@ -1626,57 +2322,64 @@ impl Kanata {
                     }
                     CustomAction::MoveMouseSpeed { speed } => {
                         self.move_mouse_speed_modifiers.push(*speed);
-                        log::debug!(
+                        tracing::debug!(
                             "movemousespeed modifiers: {:?}",
                             self.move_mouse_speed_modifiers
                         );
                     }
                     CustomAction::Cmd(_cmd) => {
                         #[cfg(feature = "cmd")]
-                        cmds.push((
-                            Some(log::Level::Info),
-                            Some(log::Level::Error),
-                            Vec::from_iter(_cmd.iter().map(|s| s.to_string())),
-                        ));
+                        cmds.push(CmdRun {
+                            log_level: Some(log::Level::Info),
+                            error_log_level: Some(log::Level::Error),
+                            cmd: Vec::from_iter(_cmd.iter().map(|s| s.to_string())),
+                            env: self.cmd_env(),
+                            sandbox: None,
+                        });
                     }
                     CustomAction::CmdLog(_log_level, _error_log_level, _cmd) => {
                         #[cfg(feature = "cmd")]
-                        cmds.push((
-                            _log_level.get_level(),
-                            _error_log_level.get_level(),
-                            Vec::from_iter(_cmd.iter().map(|s| s.to_string())),
-                        ));
+                        cmds.push(CmdRun {
+                            log_level: _log_level.get_level(),
+                            error_log_level: _error_log_level.get_level(),
+                            cmd: Vec::from_iter(_cmd.iter().map(|s| s.to_string())),
+                            env: self.cmd_env(),
+                            sandbox: None,
+                        });
+                    }
+                    CustomAction::CmdArgv(_argv, _sandbox) => {
+                        #[cfg(feature = "cmd")]
+                        cmds.push(CmdRun {
+                            log_level: Some(log::Level::Info),
+                            error_log_level: Some(log::Level::Error),
+                            cmd: Vec::from_iter(_argv.iter().map(|s| s.to_string())),
+                            sandbox: Some(*_sandbox),
+                            env: self.cmd_env(),
+                        });
                     }
                     CustomAction::CmdOutputKeys(_cmd) => {
                         #[cfg(feature = "cmd")]
-                        {
-                            // Maybe improvement in the future:
-                            // A delay here, as in KeyAction::Delay, will pause the entire
-                            // state machine loop. That is _probably_ OK, but ideally this
-                            // would be done in a separate thread or somehow
-                            for key_action in keys_for_cmd_output(_cmd) {
-                                match key_action {
-                                    KeyAction::Press(osc) => press_key(&mut self.kbd_out, osc)?,
-                                    KeyAction::Release(osc) => {
-                                        release_key(&mut self.kbd_out, osc)?
-                                    }
-                                    KeyAction::Delay(delay) => std::thread::sleep(
-                                        std::time::Duration::from_millis(u64::from(delay)),
-                                    ),
-                                }
-                            }
+                        start_cmd_output_keys(
+                            _cmd.iter().map(|s| s.to_string()).collect(),
+                            self.cmd_env(),
+                        );
+                    }
+                    CustomAction::CmdCancel(_program) => {
+                        #[cfg(feature = "cmd")]
+                        if !cancel_cmd(None, *_program) {
+                            tracing::info!("cmd-cancel: no running command to cancel");
                         }
                     }
                     CustomAction::PushMessage(_message) => {
-                        log::debug!("Action push-msg");
+                        tracing::debug!("Action push-msg");
                         #[cfg(feature = "tcp_server")]
                         if let Some(tx) = _tx {
                             let message = simple_sexpr_to_json_array(_message);
-                            log::debug!("Action push-msg message: {}", message);
+                            tracing::debug!("Action push-msg message: {}", message);
                             match tx.try_send(ServerMessage::MessagePush { message }) {
                                 Ok(_) => {}
                                 Err(error) => {
-                                    log::error!(
+                                    tracing::error!(
                                         "could not send {} event notification: {}",
                                         PUSH_MESSAGE,
                                         error
@@ -1685,48 +2388,54 @@ impl Kanata {
                             }
                         }
                         #[cfg(feature = "tcp_server")]
-                        if self.tcp_server_address.is_none() {
-                            log::warn!("{} was used, but TCP server is not running. did you specify a port?", PUSH_MESSAGE);
+                        if !crate::tcp_server::is_running() {
+                            tracing::warn!("{} was used, but TCP server is not running. did you specify a port or listener?", PUSH_MESSAGE);
                         }
                         #[cfg(not(feature = "tcp_server"))]
-                        log::warn!(
+                        tracing::warn!(
                             "{} was used, but Kanata was compiled with TCP server disabled.",
                             PUSH_MESSAGE
                         );
                     }
                     CustomAction::FakeKey { coord, action } => {
                         let (x, y) = (coord.x, coord.y);
-                        log::debug!(
+                        tracing::debug!(
                             "fake key on press   {action:?} {:?},{x:?},{y:?} {:?}",
                             layout.default_layer,
-                            layout.layers[layout.default_layer][x as usize][y as usize]
+                            layout.layers.get(layout.default_layer, x as usize, y as usize)
                         );
                         handle_fakekey_action(*action, layout, x, y);
                     }
                     CustomAction::Delay(delay) => {
-                        log::debug!("on-press: sleeping for {delay} ms");
+                        tracing::debug!("on-press: sleeping for {delay} ms");
+                        flush_burst(&mut self.kbd_out)?;
+                        let _watchdog = watchdog_pause();
                         std::thread::sleep(time::Duration::from_millis((*delay).into()));
                     }
                     CustomAction::SequenceCancel => {
                         if let Some(state) = self.sequence_state.get_active() {
-                            log::debug!("pressed cancel sequence key");
+                            tracing::debug!("pressed cancel sequence key");
                             cancel_sequence(state, &mut self.kbd_out)?;
                         }
                     }
                     CustomAction::SequenceLeader(timeout, input_mode) => {
                         if self.sequence_state.is_inactive() {
-                            log::debug!("entering sequence mode");
+                            tracing::debug!("entering sequence mode");
                             self.sequence_state.activate(*input_mode, *timeout);
                         } else if *input_mode == SequenceInputMode::HiddenSuppressed {
-                            log::debug!("retriggering sequence mode");
+                            tracing::debug!("retriggering sequence mode");
                             self.sequence_state.activate(*input_mode, *timeout);
                         }
                     }
                     CustomAction::SequenceNoerase(..) => {}
+                    CustomAction::Compose(timeout) => {
+                        tracing::debug!("entering compose mode");
+                        self.compose_state = Some(ComposeState::new(*timeout));
+                    }
                     CustomAction::Repeat => {
                         let keycode = self.last_pressed_key;
                         let osc: OsCode = keycode.into();
-                        log::debug!("repeating a keypress {osc:?}");
+                        tracing::debug!("repeating a keypress {osc:?}");
                         let mut do_caps_word = false;
                         if !cur_keys.contains(&KeyCode::LShift)
                             && let Some(ref mut cw) = self.caps_word {
@@ -1750,7 +2459,7 @@ impl Kanata {
                         if let Some((macro_id, prev_recorded_macro)) =
                             begin_record_macro(*macro_id, &mut self.dynamic_macro_record_state)
                         {
-                            log::debug!("saving macro {prev_recorded_macro:?}");
+                            tracing::debug!("saving macro {prev_recorded_macro:?}");
                             self.dynamic_macros.insert(macro_id, prev_recorded_macro);
                         }
                     }
@@ -1759,7 +2468,7 @@ impl Kanata {
                             &mut self.dynamic_macro_record_state,
                             *num_actions_to_remove,
                         ) {
-                            log::debug!("saving macro {prev_recorded_macro:?}");
+                            tracing::debug!("saving macro {prev_recorded_macro:?}");
                             self.dynamic_macros.insert(macro_id, prev_recorded_macro);
                         }
                     }
@@ -1771,7 +2480,20 @@ impl Kanata {
                         );
                     }
                     CustomAction::CancelMacroOnNextPress(duration) => {
-                        self.macro_on_press_cancel_duration = *duration;
+                        self.macro_on_press_cancel_duration =
+                            layout.scale_sequence_delay(*duration).max(*duration);
+                    }
+                    CustomAction::MacroDelayScale(percent) => {
+                        layout.set_sequence_delay_percent(*percent);
+                        tracing::info!("macro delays scaled to {percent}%");
+                    }
+                    CustomAction::CancelMacros => {
+                        tracing::debug!("cancelling all macros: macro-cancel");
+                        self.macro_on_press_cancel_duration = 0;
+                        layout.active_sequences.clear();
+                        layout.states.retain(|s| {
+                            !matches!(s, State::FakeKey { .. } | State::RepeatingSequence { .. })
+                        });
                     }
                     CustomAction::SendArbitraryCode(code) => {
                         #[cfg(all(not(feature = "simulated_output"), target_os = "windows"))]
@@ -1785,20 +2507,67 @@ impl Kanata {
                     }
                     CustomAction::CapsWord(cfg) => match cfg.repress_behaviour {
                         CapsWordRepressBehaviour::Overwrite => {
-                            log::trace!("caps-word overwrite");
+                            tracing::trace!("caps-word overwrite");
                             self.caps_word = Some(CapsWordState::new(cfg));
+                            if let Some(sound) = &self.sound_caps_word {
+                                play_sound(&mut self.kbd_out, sound);
+                            }
                         }
                         CapsWordRepressBehaviour::Toggle => {
-                            log::trace!("caps-word toggle");
+                            tracing::trace!("caps-word toggle");
                             self.caps_word = match self.caps_word {
                                 Some(_) => None,
                                 None => Some(CapsWordState::new(cfg)),
                             };
+                            if self.caps_word.is_some()
+                                && let Some(sound) = &self.sound_caps_word
+                            {
+                                play_sound(&mut self.kbd_out, sound);
+                            }
                         }
                     },
                     CustomAction::SetMouse { x, y } => {
                         self.kbd_out.set_mouse(*x, *y)?;
                     }
+                    CustomAction::MouseGridCell(cell) => {
+                        self.mouse_grid.select_cell(cell);
+                        self.mouse_grid.warp(&mut self.kbd_out)?;
+                    }
+                    CustomAction::MouseGridReset => {
+                        self.mouse_grid.reset();
+                        self.mouse_grid.warp(&mut self.kbd_out)?;
+                    }
+                    CustomAction::MouseGridMonitor(monitor) => {
+                        self.mouse_grid.set_monitor(*monitor);
+                        self.mouse_grid.warp(&mut self.kbd_out)?;
+                    }
+                    CustomAction::MouseToward(action) => {
+                        self.kbd_out.move_mouse_toward(action)?;
+                    }
+                    CustomAction::Jiggle(cfg) => {
+                        toggle_mouse_jiggle(&mut self.mouse_jiggle, *cfg);
+                    }
+                    CustomAction::ToggleProcessing => {
+                        // The action stays in the layout states while its key is held, which
+                        // identifies the key that should resume processing.
+                        let resume_key = layout.states.iter().find_map(|state| match state {
+                            State::Custom {
+                                value: CustomAction::ToggleProcessing,
+                                coord: (NORMAL_KEY_ROW, y),
+                            } => Some(OsCode::from(*y)),
+                            _ => None,
+                        });
+                        match resume_key {
+                            Some(osc) => self.pause_processing(osc),
+                            None => tracing::warn!(
+                                "toggle-processing was not activated by a key; ignoring it"
+                            ),
+                        }
+                    }
+                    CustomAction::TrainingMode { reference_layer } => {
+                        let reference_layer = *reference_layer;
+                        self.toggle_training_mode(reference_layer);
+                    }
                     CustomAction::FakeKeyOnIdle(fkd) => {
                         self.ticks_since_idle = 0;
                         self.waiting_for_idle.insert(*fkd);
@@ -1838,6 +2607,59 @@ impl Kanata {
                     CustomAction::ClipboardSaveSwap(id1, id2) => {
                         clpb_save_swap(*id1, *id2, &mut self.saved_clipboard_content);
                     }
+                    CustomAction::MidiNote(note) => {
+                        send_midi(&mut self.midi_out, &mut self.kbd_out, note.note_on());
+                    }
+                    CustomAction::MidiControlChange(cc) => {
+                        send_midi(&mut self.midi_out, &mut self.kbd_out, cc.message());
+                    }
+                    CustomAction::Obs(action) => {
+                        send_obs(&mut self.obs, &mut self.kbd_out, *action);
+                    }
+                    CustomAction::OpenRgb(color) => {
+                        send_openrgb(&mut self.openrgb, &mut self.kbd_out, *color);
+                    }
+                    CustomAction::Webhook { name, data } => {
+                        self.webhooks.send_action(&mut self.kbd_out, name, *data);
+                    }
+                    CustomAction::Plugin(_action) => {
+                        #[cfg(feature = "plugins")]
+                        {
+                            for request in run_plugin_action(_action, &self.layer_ids) {
+                                match request {
+                                    PluginRequest::Press(key) => self.kbd_out.press_key(key)?,
+                                    PluginRequest::Release(key) => {
+                                        self.kbd_out.release_key(key)?
+                                    }
+                                    PluginRequest::Layer(layer) => layout.set_default_layer(layer),
+                                }
+                            }
+                        }
+                    }
+                    CustomAction::Notify { title, body } => {
+                        send_notification(&mut self.notifier, &mut self.kbd_out, title, body);
+                    }
+                    CustomAction::Mpris(action) => {
+                        send_mpris(&mut self.mpris, &mut self.kbd_out, *action);
+                    }
+                    CustomAction::HidUsage(usage) => {
+                        self.kbd_out.write_hid_usage(*usage, KeyValue::Press)?;
+                    }
+                    CustomAction::OsLayout(layout) => {
+                        switch_os_keyboard_layout(&mut self.kbd_out, layout);
+                    }
+                    CustomAction::VarSet { name, value } => {
+                        self.alias_concat_state.set_var(name, value);
+                    }
+                    CustomAction::AliasConcat(action) => {
+                        if let Some(coord) = self.alias_concat_state.press(action) {
+                            handle_fakekey_action(FakeKeyAction::Press, layout, coord.x, coord.y);
+                        }
+                    }
+                    CustomAction::AppAlias(action) => {
+                        let coord = self.app_state.press(action);
+                        handle_fakekey_action(FakeKeyAction::Press, layout, coord.x, coord.y);
+                    }
                     CustomAction::FakeKeyOnRelease { .. }
                     | CustomAction::DelayOnRelease(_)
                     | CustomAction::Unmodded { .. }
@@ -1847,7 +2669,7 @@ impl Kanata {
                     | CustomAction::CancelMacroOnRelease => {}
                 }
                 #[cfg(feature = "cmd")]
-                run_multi_cmd(cmds);
+                run_cmds(cmds);
 
                 // Process reload actions after releasing the layout borrow
                 if let Some(action) = reload_action {
@@ -1866,7 +2688,7 @@ impl Kanata {
                         }
                         ReloadAction::ReloadNum(n) => {
                             if let Err(e) = self.request_live_reload_num(n) {
-                                log::error!("{}", e);
+                                tracing::error!("{}", e);
                                 false
                             } else {
                                 true
@@ -1874,7 +2696,23 @@ impl Kanata {
                         }
                         ReloadAction::ReloadFile(path) => {
                             if let Err(e) = self.request_live_reload_file(path) {
-                                log::error!("{}", e);
+                                tracing::error!("{}", e);
+                                false
+                            } else {
+                                true
+                            }
+                        }
+                        ReloadAction::ReloadVersionPrev => {
+                            if let Err(e) = self.request_reload_version_cycle(true) {
+                                tracing::error!("{}", e);
+                                false
+                            } else {
+                                true
+                            }
+                        }
+                        ReloadAction::ReloadVersionNext => {
+                            if let Err(e) = self.request_reload_version_cycle(false) {
+                                tracing::error!("{}", e);
                                 false
                             } else {
                                 true
@@ -1892,6 +2730,9 @@ impl Kanata {
                 CustomAction::Mouse(btn) => {
                     self.kbd_out.release_btn(*btn)?;
                 }
+                CustomAction::HidUsage(usage) => {
+                    self.kbd_out.write_hid_usage(*usage, KeyValue::Release)?;
+                }
                 CustomAction::MWheel { direction, .. } => match direction {
                     MWheelDirection::Up | MWheelDirection::Down => {
                         if let Some(ss) = &mut self.scroll_state
@@ -1945,22 +2786,37 @@ impl Kanata {
                     {
                         self.move_mouse_speed_modifiers.remove(idx);
                     }
-                    log::debug!(
+                    tracing::debug!(
                         "movemousespeed modifiers: {:?}",
                         self.move_mouse_speed_modifiers
                     );
                 }
                 CustomAction::DelayOnRelease(delay) => {
-                    log::debug!("on-release: sleeping for {delay} ms");
+                    tracing::debug!("on-release: sleeping for {delay} ms");
+                    flush_burst(&mut self.kbd_out)?;
+                    let _watchdog = watchdog_pause();
                     std::thread::sleep(time::Duration::from_millis((*delay).into()));
                 }
                 CustomAction::FakeKeyOnRelease { coord, action } => {
                     let (x, y) = (coord.x, coord.y);
-                    log::debug!("fake key on release {action:?} {x:?},{y:?}");
+                    tracing::debug!("fake key on release {action:?} {x:?},{y:?}");
                     handle_fakekey_action(*action, layout, x, y);
                 }
+                CustomAction::AliasConcat(action) => {
+                    if let Some(coord) = self.alias_concat_state.release(action) {
+                        handle_fakekey_action(FakeKeyAction::Release, layout, coord.x, coord.y);
+                    }
+                }
+                CustomAction::AppAlias(action) => {
+                    if let Some(coord) = self.app_state.release(action) {
+                        handle_fakekey_action(FakeKeyAction::Release, layout, coord.x, coord.y);
+                    }
+                }
+                CustomAction::MidiNote(note) => {
+                    send_midi(&mut self.midi_out, &mut self.kbd_out, note.note_off());
+                }
                 CustomAction::CancelMacroOnRelease => {
-                    log::debug!("cancelling all macros: releasable macro");
+                    tracing::debug!("cancelling all macros: releasable macro");
                     layout.active_sequences.clear();
                     self.macro_on_press_cancel_duration = 0;
                     layout.states.retain(|s| {
@@ -1978,7 +2834,7 @@ impl Kanata {
                             self.kbd_out.write_code(*code as u32, KeyValue::Release)
                         }
                     } {
-                        log::error!("failed to release arbitrary code {e:?}");
+                        tracing::error!("failed to release arbitrary code {e:?}");
                     }
                 }
                 _ => {}
@@ -1993,18 +2849,64 @@ impl Kanata {
 
     #[cfg(feature = "tcp_server")]
     pub fn change_layer(&mut self, layer_name: String) {
-        for (i, l) in self.layer_info.iter().enumerate() {
-            if l.name == layer_name {
-                self.layout.bm().set_default_layer(i);
-                return;
-            }
+        if let Ok(layer) = self.layer_idx(&layer_name) {
+            self.layout.bm().set_default_layer(layer.into());
         }
     }
 
+    /// The index of a layer that a command from outside gives by name, e.g. from a TCP client.
+    fn layer_idx(&self, layer_name: &str) -> Result<u16> {
+        match self.layer_ids.get(layer_name) {
+            Some(&i) => Ok(i as u16),
+            None => bail!("unknown layer: {layer_name}"),
+        }
+    }
+
+    /// Sets the layers that transparent keys fall back to, in priority order, after the active
+    /// layers. An empty list restores the configured behaviour. Lasts until the next reload.
+    pub fn set_layer_fallback(&mut self, layer_names: &[String]) -> Result<()> {
+        let layers = layer_names
+            .iter()
+            .map(|name| self.layer_idx(name))
+            .collect::<Result<Vec<_>>>()?;
+        if !self.layout.bm().set_fallback_layers(&layers) {
+            bail!(
+                "too many fallback layers: {}, the maximum is {}",
+                layers.len(),
+                kanata_keyberon::layout::MAX_ACTIVE_LAYERS
+            );
+        }
+        tracing::info!("layer fallback set to: {layer_names:?}");
+        Ok(())
+    }
+
+    /// Makes the keys of a layer behave like the keys of the target layer. Aliasing a layer to
+    /// itself removes the alias. Lasts until the next reload.
+    pub fn set_layer_alias(&mut self, layer_name: &str, target_name: &str) -> Result<()> {
+        let layer = self.layer_idx(layer_name)?;
+        let target = self.layer_idx(target_name)?;
+        // Indices come from layer_info so they are in range.
+        self.layout.bm().set_layer_alias(layer, target);
+        tracing::info!("layer {layer_name} now uses the keys of {target_name}");
+        Ok(())
+    }
+
+    /// Scales the delays of macros by the percentage, e.g. 200 to play them twice as slow, for
+    /// `SetMacroDelayScale`.
+    pub fn set_macro_delay_scale(&mut self, percent: u16) -> Result<()> {
+        if percent == 0 {
+            bail!("the macro delay scale must be a positive percentage");
+        }
+        self.layout.bm().set_sequence_delay_percent(percent);
+        tracing::info!("macro delays scaled to {percent}%");
+        Ok(())
+    }
+
     /// Request a live reload of the current configuration file.
     pub fn request_live_reload(&mut self) {
+        self.cancel_requested_version();
         self.live_reload_requested = true;
-        log::info!(
+        tracing::info!(
             "Requested live reload of file: {}",
             self.cfg_paths[self.cur_cfg_idx].display()
         );
@@ -2034,6 +2936,34 @@ impl Kanata {
             }
             ClientMessage::ReloadNum { index, .. } => self.request_live_reload_num(index),
             ClientMessage::ReloadFile { path, .. } => self.request_live_reload_file(path),
+            ClientMessage::ReloadTry { confirm_timeout_ms } => {
+                self.request_live_reload_try(confirm_timeout_ms)
+            }
+            ClientMessage::ConfirmReload {} => self.confirm_reload(),
+            ClientMessage::ReloadRollback { .. } => self.request_reload_rollback(),
+            ClientMessage::ReloadVersion { n, .. } => self.request_reload_version(n),
+            ClientMessage::SetLayerFallback { names } => self.set_layer_fallback(&names),
+            ClientMessage::SetLayerAlias { name, target } => self.set_layer_alias(&name, &target),
+            ClientMessage::SetActiveApp { app } => {
+                self.set_active_app(&app);
+                Ok(())
+            }
+            ClientMessage::SetMacroDelayScale { percent } => self.set_macro_delay_scale(percent),
+            ClientMessage::SetTapHold {
+                alias,
+                hold_timeout_ms,
+                tap_repress_timeout_ms,
+                flavor,
+            } => self.set_tap_hold(
+                &alias,
+                hold_timeout_ms,
+                tap_repress_timeout_ms,
+                flavor.as_deref(),
+            ),
+            ClientMessage::SetTrainingMode {
+                enabled,
+                reference_layer,
+            } => self.set_training_mode(enabled, reference_layer.as_deref()),
             _ => {
                 // For non-reload commands, we don't validate here - they're handled directly in tcp_server
                 Ok(())
@@ -2043,13 +2973,14 @@ impl Kanata {
 
     /// Request a live reload of the next configuration file.
     pub fn request_live_reload_next(&mut self) {
+        self.cancel_requested_version();
         self.live_reload_requested = true;
         self.cur_cfg_idx = if self.cur_cfg_idx == self.cfg_paths.len() - 1 {
             0
         } else {
             self.cur_cfg_idx + 1
         };
-        log::info!(
+        tracing::info!(
             "Requested live reload of next file: {}",
             self.cfg_paths[self.cur_cfg_idx].display()
         );
@@ -2057,13 +2988,14 @@ impl Kanata {
 
     /// Request a live reload of the previous configuration file.
     pub fn request_live_reload_prev(&mut self) {
+        self.cancel_requested_version();
         self.live_reload_requested = true;
         if self.cur_cfg_idx == 0 {
             self.cur_cfg_idx = self.cfg_paths.len() - 1;
         } else {
             self.cur_cfg_idx -= 1;
         }
-        log::info!(
+        tracing::info!(
             "Requested live reload of previous file: {}",
             self.cfg_paths[self.cur_cfg_idx].display()
         );
@@ -2078,9 +3010,10 @@ impl Kanata {
                 self.cfg_paths.len()
             );
         }
+        self.cancel_requested_version();
         self.live_reload_requested = true;
         self.cur_cfg_idx = index;
-        log::info!(
+        tracing::info!(
             "Requested live reload of config file {}: {}",
             index,
             self.cfg_paths[self.cur_cfg_idx].display()
@@ -2094,10 +3027,11 @@ impl Kanata {
         if !new_path.exists() {
             bail!("config file does not exist: {}", path);
         }
+        self.cancel_requested_version();
         self.live_reload_requested = true;
         self.cfg_paths.push(new_path);
         self.cur_cfg_idx = self.cfg_paths.len() - 1;
-        log::info!(
+        tracing::info!(
             "Requested live reload of file: {}",
             self.cfg_paths[self.cur_cfg_idx].display()
         );
@@ -2110,16 +3044,48 @@ impl Kanata {
     fn check_handle_layer_change(&mut self, tx: &Option<Sender<ServerMessage>>) {
         let cur_layer = self.layout.bm().current_layer();
         if cur_layer != self.prev_layer {
-            let new = self.layer_info[cur_layer].name.clone();
-            self.prev_layer = cur_layer;
+            let prev_layer = std::mem::replace(&mut self.prev_layer, cur_layer);
             self.print_layer(cur_layer);
+            trace_layer(&self.layer_info[cur_layer].name);
+            let hooks = [
+                self.layer_info.get(prev_layer).and_then(|l| l.on_exit),
+                self.layer_info[cur_layer].on_enter,
+            ];
+            for (x, y) in hooks.into_iter().flatten() {
+                handle_fakekey_action(FakeKeyAction::Tap, self.layout.bm(), x, y);
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            if self.layer_leds.get(prev_layer) != self.layer_leds.get(cur_layer) {
+                self.kbd_out.set_lock_leds(self.layer_leds[cur_layer]);
+            }
+            if self.openrgb_layer_colors.get(prev_layer) != self.openrgb_layer_colors.get(cur_layer)
+            {
+                self.set_openrgb_layer_color(cur_layer);
+            }
+            if let Some(sound) = self.layer_info[cur_layer]
+                .sound
+                .as_ref()
+                .or(self.sound_layer_change.as_ref())
+            {
+                play_sound(&mut self.kbd_out, sound);
+            }
+            if self.webhooks.has_event(WebhookEvent::LayerChange) {
+                let layer = self.layer_info[cur_layer].name.clone();
+                self.webhooks.send_event(
+                    &mut self.kbd_out,
+                    WebhookEvent::LayerChange,
+                    serde_json::json!({ "layer": layer }),
+                );
+            }
 
+            // The name is only copied for clients, so that layer changes don't allocate.
             #[cfg(feature = "tcp_server")]
             if let Some(tx) = tx {
+                let new = self.layer_info[cur_layer].name.clone();
                 match tx.try_send(ServerMessage::LayerChange { new }) {
                     Ok(_) => {}
                     Err(error) => {
-                        log::error!("could not send event notification: {}", error);
+                        tracing::error!("could not send event notification: {}", error);
                     }
                 }
             }
@@ -2130,7 +3096,7 @@ impl Kanata {
 
     fn print_layer(&self, layer: usize) {
         if self.log_layer_changes {
-            log::info!("Entered layer:\n\n{}", self.layer_info[layer].cfg_text);
+            tracing::info!("Entered layer:\n\n{}", self.layer_info[layer].cfg_text);
         }
     }
 
@@ -2157,37 +3123,18 @@ impl Kanata {
         rx: Receiver<ServerMessage>,
         clients: crate::tcp_server::Connections,
     ) {
-        use std::io::Write;
         info!("listening for event notifications to relay to connected clients");
         std::thread::spawn(move || {
+            let _running = TCP_SERVER.running();
             loop {
                 match rx.recv() {
                     Err(_) => {
                         panic!("channel disconnected")
                     }
                     Ok(event) => {
-                        let notification = event.as_bytes();
-                        let mut clients = clients.lock();
-                        let mut stale_clients = vec![];
-                        for (id, client) in &mut *clients {
-                            match client.write_all(&notification) {
-                                Ok(_) => {
-                                    log::debug!("layer change notification sent");
-                                }
-                                Err(e) => {
-                                    log::warn!(
-                                        "removing tcp client where write failed: {id}, {e:?}"
-                                    );
-                                    // the client is no longer connected, let's remove them
-                                    stale_clients.push(id.clone());
-                                }
-                            }
-                        }
-
-                        for id in &stale_clients {
-                            log::warn!("removing disconnected tcp client: {id}");
-                            clients.remove(id);
-                        }
+                        let _working = TCP_SERVER.working();
+                        crate::tcp_server::broadcast(&clients, &event);
+                        tracing::debug!("notification sent");
                     }
                 }
             }
@@ -2204,12 +3151,17 @@ impl Kanata {
     /// Starts a new thread that processes OS key events and advances the keyberon layout's state.
     pub fn start_processing_loop(
         kanata: Arc<Mutex<Self>>,
-        rx: Receiver<KeyEvent>,
+        rx: EventReceiver,
         tx: Option<Sender<ServerMessage>>,
         nodelay: bool,
     ) {
         info!("entering the processing loop");
+        Kanata::start_watchdog(kanata.clone(), tx.clone());
+        watch_input_queue(rx.monitor());
+        #[cfg(feature = "cmd")]
+        set_cmd_notifications(tx.clone());
         std::thread::spawn(move || {
+            let _running = PROCESSOR.running();
             // Elevate the processing thread to the highest QoS class so that
             // CPU-intensive background work (compilation, indexing, etc.) does
             // not starve the 1 ms tick loop. Without this, delayed key-release
@@ -2227,9 +3179,12 @@ impl Kanata {
                 if rc == 0 {
                     info!("macOS: processing thread QoS set to USER_INTERACTIVE");
                 } else {
-                    log::warn!("macOS: failed to set processing thread QoS (rc={rc})");
+                    tracing::warn!("macOS: failed to set processing thread QoS (rc={rc})");
                 }
             }
+            if kanata.lock().realtime_priority {
+                raise_thread_priority("processing");
+            }
             if !nodelay {
                 info!("Init: catching only releases and sending immediately");
                 for _ in 0..500 {
@@ -2246,12 +3201,14 @@ impl Kanata {
             let mut ms_elapsed = 0;
 
             info!("Starting kanata proper");
+            {
+                let mut k = kanata.lock();
+                let cur_layer = k.layout.bm().current_layer();
+                k.set_openrgb_layer_color(cur_layer);
+            }
 
             #[cfg(not(feature = "passthru_ahk"))]
-            info!(
-                "You may forcefully exit kanata by pressing lctl+spc+esc at any time. \
-                        These keys refer to defsrc input, meaning BEFORE kanata remaps keys."
-            );
+            log_emergency_chords();
 
             #[cfg(all(not(feature = "interception_driver"), target_os = "windows"))]
             let mut idle_clear_happened = false;
@@ -2260,6 +3217,7 @@ impl Kanata {
 
             let mut events = Vec::new();
             let err = loop {
+                watchdog_progress();
                 let can_block = {
                     let mut k = kanata.lock();
                     k.can_block_update_idle_waiting(ms_elapsed)
@@ -2272,8 +3230,11 @@ impl Kanata {
                     ))]
                     kanata.lock().win_synchronize_keystates();
 
-                    log::trace!("blocking on channel");
-                    match rx.recv() {
+                    tracing::trace!("blocking on channel");
+                    watchdog_idle();
+                    let received = rx.recv();
+                    watchdog_progress();
+                    match received {
                         Ok(kev) => {
                             collect_and_sort_events(kev, &rx, &mut events);
 
@@ -2301,13 +3262,16 @@ impl Kanata {
                             {
                                 k.live_reload_requested = false;
                                 if let Err(e) = k.do_live_reload(&tx) {
-                                    log::error!("live reload failed {e}");
+                                    tracing::error!("live reload failed {e}");
                                 }
                             }
 
                             #[cfg(feature = "perf_logging")]
                             let start = web_time::Instant::now();
 
+                            #[cfg(any(target_os = "linux", target_os = "android"))]
+                            k.kbd_out
+                                .set_event_time(events.iter().filter_map(|ev| ev.time()).max());
                             let mut event_error = None;
                             for ev in &events {
                                 if let Err(e) = k.handle_input_event(ev) {
@@ -2334,7 +3298,7 @@ impl Kanata {
                             }
 
                             #[cfg(feature = "perf_logging")]
-                            log::info!(
+                            tracing::info!(
                                 "[PERF]: handle key event: {} ns",
                                 (start.elapsed()).as_nanos()
                             );
@@ -2345,20 +3309,25 @@ impl Kanata {
                                 Ok(ms) => ms_elapsed = ms,
                                 Err(e) => break e,
                             };
+                            #[cfg(any(target_os = "linux", target_os = "android"))]
+                            k.kbd_out.set_event_time(None);
+                            k.record_latency(&events);
 
                             #[cfg(feature = "perf_logging")]
-                            log::info!(
+                            tracing::info!(
                                 "[PERF]: handle time ticks: {} ns",
                                 (start.elapsed()).as_nanos()
                             );
                         }
                         Err(_) => {
-                            log::error!("channel disconnected");
+                            tracing::error!("channel disconnected");
                             return;
                         }
                     }
                 } else {
-                    match rx.try_recv() {
+                    // Wait for input until the next tick instead of sleeping, so that input is
+                    // handled as soon as it arrives.
+                    match rx.recv_timeout(time::Duration::from_millis(1)) {
                         Ok(kev) => {
                             collect_and_sort_events(kev, &rx, &mut events);
 
@@ -2370,13 +3339,16 @@ impl Kanata {
                             {
                                 k.live_reload_requested = false;
                                 if let Err(e) = k.do_live_reload(&tx) {
-                                    log::error!("live reload failed {e}");
+                                    tracing::error!("live reload failed {e}");
                                 }
                             }
 
                             #[cfg(feature = "perf_logging")]
                             let start = web_time::Instant::now();
 
+                            #[cfg(any(target_os = "linux", target_os = "android"))]
+                            k.kbd_out
+                                .set_event_time(events.iter().filter_map(|ev| ev.time()).max());
                             let mut event_error = None;
                             for ev in &events {
                                 if let Err(e) = k.handle_input_event(ev) {
@@ -2403,7 +3375,7 @@ impl Kanata {
                             }
 
                             #[cfg(feature = "perf_logging")]
-                            log::info!(
+                            tracing::info!(
                                 "[PERF]: handle key event: {} ns",
                                 (start.elapsed()).as_nanos()
                             );
@@ -2414,14 +3386,17 @@ impl Kanata {
                                 Ok(ms) => ms_elapsed = ms,
                                 Err(e) => break e,
                             };
+                            #[cfg(any(target_os = "linux", target_os = "android"))]
+                            k.kbd_out.set_event_time(None);
+                            k.record_latency(&events);
 
                             #[cfg(feature = "perf_logging")]
-                            log::info!(
+                            tracing::info!(
                                 "[PERF]: handle time ticks: {} ns",
                                 (start.elapsed()).as_nanos()
                             );
                         }
-                        Err(TryRecvError::Empty) => {
+                        Err(RecvTimeoutError::Timeout) => {
                             let mut k = kanata.lock();
 
                             #[cfg(feature = "perf_logging")]
@@ -2433,7 +3408,7 @@ impl Kanata {
                             };
 
                             #[cfg(feature = "perf_logging")]
-                            log::info!(
+                            tracing::info!(
                                 "[PERF]: handle time ticks: {} ns",
                                 (start.elapsed()).as_nanos()
                             );
@@ -2448,12 +3423,9 @@ impl Kanata {
                                 last_input_time,
                                 &mut idle_clear_happened,
                             );
-
-                            drop(k);
-                            std::thread::sleep(time::Duration::from_millis(1));
                         }
-                        Err(TryRecvError::Disconnected) => {
-                            log::error!("channel disconnected");
+                        Err(RecvTimeoutError::Disconnected) => {
+                            tracing::error!("channel disconnected");
                             return;
                         }
                     }
@@ -2480,8 +3452,11 @@ impl Kanata {
         } else if is_idle && counting_idle_ticks {
             k.ticks_since_idle = k.ticks_since_idle.saturating_add(ms_elapsed);
             #[cfg(feature = "perf_logging")]
-            log::info!("ticks since idle: {}", k.ticks_since_idle);
+            tracing::info!("ticks since idle: {}", k.ticks_since_idle);
         }
+        // Idle ticks only start counting once the held keys are released, and the release event
+        // wakes up the processing loop, so there is nothing to tick for until then.
+        let waiting_for_release = counting_idle_ticks && !is_idle && k.is_idle_with(false);
 
         let counting_physical_idle_ticks = if k.waiting_for_physical_idle.is_empty() {
             false
@@ -2493,7 +3468,8 @@ impl Kanata {
             } else {
                 k.ticks_since_physical_idle = 0;
             }
-            true
+            // Same as above, the release of the last key wakes up the processing loop.
+            is_physical_idle
         };
 
         // NOTE: this check must not be part of `is_idle` because its falsiness
@@ -2514,16 +3490,30 @@ impl Kanata {
             .as_ref()
             .map(|cv2| cv2.accepts_chords_chv2())
             .unwrap_or(true);
-        is_idle
-            && !counting_idle_ticks
+        // The output of a cmd-output-keys command is typed on a tick once the command exits.
+        #[cfg(feature = "cmd")]
+        let cmd_output_keys_pending = cmd_output_keys_pending();
+        #[cfg(not(feature = "cmd"))]
+        let cmd_output_keys_pending = false;
+        ((is_idle && !counting_idle_ticks) || waiting_for_release)
             && !counting_physical_idle_ticks
             && passed_max_timing_check
             && chordsv2_accepts_chords
+            && !k.typing_speed.push_pending()
+            && k.training_messages.is_empty()
+            && !k.stuck_keys.pending()
+            && !k.debouncer.pending()
+            && !k.deduplicator.pending()
+            && !cmd_output_keys_pending
     }
 
     pub fn is_idle(&self) -> bool {
-        let pressed_keys_means_not_idle =
-            !self.waiting_for_idle.is_empty() || self.live_reload_requested;
+        self.is_idle_with(!self.waiting_for_idle.is_empty() || self.live_reload_requested)
+    }
+
+    /// Returns whether there is no time-based state, and whether no keys are held if
+    /// `pressed_keys_means_not_idle`.
+    fn is_idle_with(&self, pressed_keys_means_not_idle: bool) -> bool {
         let layout = self.layout.b();
         layout.queue.is_empty()
             && zippy_is_idle()
@@ -2535,6 +3525,7 @@ impl Kanata {
             && layout.action_queue.is_empty()
             && layout.custom_event_release_queue.is_empty()
             && self.sequence_state.is_inactive()
+            && self.compose_state.is_none()
             && self.scroll_state.is_none()
             && self.hscroll_state.is_none()
             && self.move_mouse_state_vertical.is_none()
@@ -2542,7 +3533,10 @@ impl Kanata {
             && self.move_mouse_state_horizontal.is_none()
             && self.dynamic_macro_replay_state.is_none()
             && self.caps_word.is_none()
+            && self.software_repeat.is_none()
+            && self.mouse_jiggle.is_none()
             && self.vkeys_pending_release.is_empty()
+            && !self.auto_return_state.is_active()
             && !layout.states.iter().any(|s| {
                 matches!(s, State::SeqCustomPending(_) | State::SeqCustomActive(_))
                     || (pressed_keys_means_not_idle && matches!(s, State::NormalKey { .. }))
@@ -2561,17 +3555,6 @@ fn test_unmodmods_bits() {
     assert_eq!(UnmodMods::all().bits(), 255u8);
 }
 
-#[cfg(feature = "cmd")]
-fn run_multi_cmd(cmds: Vec<(Option<log::Level>, Option<log::Level>, Vec<String>)>) {
-    std::thread::spawn(move || {
-        for (cmd_log_level, cmd_error_log_level, cmd) in cmds {
-            if let Err(e) = run_cmd_in_thread(cmd, cmd_log_level, cmd_error_log_level).join() {
-                log::error!("problem joining thread {:?}", e);
-            }
-        }
-    });
-}
-
 fn apply_mouse_distance_modifiers(initial_distance: u16, mods: &Vec<u16>) -> u16 {
     let mut scaled_distance = initial_distance;
     for &modifier in mods {
@@ -2639,88 +3622,19 @@ pub fn clean_state(kanata: &Arc<Mutex<Kanata>>, tick: u128) -> Result<()> {
     Ok(())
 }
 
-/// Checks if kanata should exit based on the fixed key combination of:
-/// Lctl+Spc+Esc
-fn check_for_exit(_event: &KeyEvent) {
-    #[cfg(not(feature = "passthru_ahk"))]
-    {
-        use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
-        static IS_LCL_PRESSED: AtomicBool = AtomicBool::new(false);
-        static IS_SPC_PRESSED: AtomicBool = AtomicBool::new(false);
-        static IS_ESC_PRESSED: AtomicBool = AtomicBool::new(false);
-        let is_pressed = match _event.value {
-            KeyValue::Press => true,
-            KeyValue::Release => false,
-            _ => return,
-        };
-        match _event.code {
-            OsCode::KEY_ESC => IS_ESC_PRESSED.store(is_pressed, SeqCst),
-            OsCode::KEY_SPACE => IS_SPC_PRESSED.store(is_pressed, SeqCst),
-            OsCode::KEY_LEFTCTRL => IS_LCL_PRESSED.store(is_pressed, SeqCst),
-            _ => return,
-        }
-        const EXIT_MSG: &str = "pressed LControl+Space+Escape, exiting";
-        if IS_ESC_PRESSED.load(SeqCst) && IS_SPC_PRESSED.load(SeqCst) && IS_LCL_PRESSED.load(SeqCst)
-        {
-            log::info!("{EXIT_MSG}");
-            #[cfg(all(target_os = "windows", feature = "gui"))]
-            {
-                #[cfg(not(feature = "interception_driver"))]
-                native_windows_gui::stop_thread_dispatch();
-                #[cfg(feature = "interception_driver")]
-                send_gui_exit_notice(); // interception driver is running in another thread to allow
-                // GUI take the main one, so it's calling check_for_exit
-                // from a thread that has no access to the main one, so
-                // can't stop main thread's dispatch
-            }
-            // macOS: use `libc::_exit` instead of `std::process::exit` to
-            // skip C++ static destructors. The underlying pqrs shared
-            // dispatcher (used by the karabiner-driverkit crate) has a
-            // teardown race where its destructor kills its own worker
-            // threads while they still hold a `std::mutex`, which then
-            // throws `std::system_error: mutex lock failed` on
-            // `pthread_mutex_destroy`. That exception is uncaught and
-            // aborts the process with a cryptic libc++abi message right
-            // after the user's kill chord. `_exit` bypasses the whole
-            // mess by jumping straight to the kernel exit syscall.
-            //
-            // Flush stdio first so any buffered log output (including the
-            // "exiting" line we just emitted) actually reaches the user.
-            #[cfg(target_os = "macos")]
-            {
-                use std::io::Write;
-                let _ = std::io::stderr().flush();
-                let _ = std::io::stdout().flush();
-                let code = EMERGENCY_EXIT_CODE.load(std::sync::atomic::Ordering::SeqCst);
-                // SAFETY: `_exit` has no preconditions; it terminates the
-                // process immediately without running user destructors.
-                unsafe {
-                    libc::_exit(code);
-                }
-            }
-            // Linux/Android: Use SIGTERM to trigger signal handler for cleanup
-            #[cfg(any(target_os = "linux", target_os = "android"))]
-            {
-                signal_hook::low_level::raise(signal_hook::consts::SIGTERM).expect("raise signal");
-            }
-            // Windows non-GUI: Direct exit (no cleanup needed)
-            #[cfg(all(target_os = "windows", not(feature = "gui")))]
-            {
-                let code = EMERGENCY_EXIT_CODE.load(std::sync::atomic::Ordering::SeqCst);
-                std::process::exit(code);
-            }
-            // Unsupported platforms: panic to indicate emergency exit isn't implemented
-            #[cfg(not(any(
-                target_os = "macos",
-                target_os = "linux",
-                target_os = "android",
-                target_os = "windows"
-            )))]
-            {
-                panic!("{EXIT_MSG}");
-            }
-        }
-    }
+/// Looks up the lock LEDs of each layer from `linux-layer-leds`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn layer_leds(cfg: &CfgOptions, layer_info: &[LayerInfo]) -> Vec<Option<LockLeds>> {
+    layer_info
+        .iter()
+        .map(|layer| {
+            cfg.linux_opts
+                .linux_layer_leds
+                .iter()
+                .find(|(name, _)| *name == layer.name)
+                .map(|(_, leds)| *leds)
+        })
+        .collect()
 }
 
 fn update_kbd_out(_cfg: &CfgOptions, _kbd_out: &KbdOut) -> Result<()> {
@@ -2773,7 +3687,6 @@ fn states_has_coord<T>(states: &[State<T>], x: u8, y: u16) -> bool {
     })
 }
 
-#[cfg(all(not(feature = "interception_driver"), target_os = "windows"))]
 fn release_normalkey_states<'a, const C: usize, const R: usize, T>(layout: &mut Layout<'a, C, R, T>)
 where
     T: 'a + std::fmt::Debug + Copy,
@@ -2811,7 +3724,6 @@ where
 mod collect_and_sort_events_tests {
     use super::*;
     use kanata_parser::keys::OsCode;
-    use std::sync::mpsc::sync_channel;
 
     fn make_event(code: OsCode, value: KeyValue) -> KeyEvent {
         KeyEvent::new(code, value)
@@ -2819,7 +3731,7 @@ mod collect_and_sort_events_tests {
 
     #[test]
     fn single_event_unchanged() {
-        let (_tx, rx) = sync_channel::<KeyEvent>(10);
+        let (_tx, rx) = event_queue(10);
         let first = make_event(OsCode::KEY_A, KeyValue::Press);
 
         let mut result = Vec::new();
@@ -2831,7 +3743,7 @@ mod collect_and_sort_events_tests {
 
     #[test]
     fn modifiers_first_on_press() {
-        let (tx, rx) = sync_channel::<KeyEvent>(10);
+        let (tx, rx) = event_queue(10);
         let first = make_event(OsCode::KEY_A, KeyValue::Press);
         tx.send(make_event(OsCode::KEY_LEFTCTRL, KeyValue::Press))
             .unwrap();
@@ -2848,7 +3760,7 @@ mod collect_and_sort_events_tests {
 
     #[test]
     fn modifiers_last_on_release() {
-        let (tx, rx) = sync_channel::<KeyEvent>(10);
+        let (tx, rx) = event_queue(10);
         let first = make_event(OsCode::KEY_A, KeyValue::Release);
         tx.send(make_event(OsCode::KEY_LEFTCTRL, KeyValue::Release))
             .unwrap();
@@ -2866,7 +3778,7 @@ mod collect_and_sort_events_tests {
 
     #[test]
     fn multiple_modifiers_on_press() {
-        let (tx, rx) = sync_channel::<KeyEvent>(10);
+        let (tx, rx) = event_queue(10);
         let first = make_event(OsCode::KEY_A, KeyValue::Press);
         tx.send(make_event(OsCode::KEY_LEFTCTRL, KeyValue::Press))
             .unwrap();
@@ -2886,7 +3798,7 @@ mod collect_and_sort_events_tests {
 
     #[test]
     fn multiple_modifiers_on_release() {
-        let (tx, rx) = sync_channel::<KeyEvent>(10);
+        let (tx, rx) = event_queue(10);
         let first = make_event(OsCode::KEY_A, KeyValue::Release);
         tx.send(make_event(OsCode::KEY_LEFTCTRL, KeyValue::Release))
             .unwrap();
@@ -2907,7 +3819,7 @@ mod collect_and_sort_events_tests {
 
     #[test]
     fn repeat_treated_like_press() {
-        let (tx, rx) = sync_channel::<KeyEvent>(10);
+        let (tx, rx) = event_queue(10);
         let first = make_event(OsCode::KEY_A, KeyValue::Repeat);
         tx.send(make_event(OsCode::KEY_LEFTCTRL, KeyValue::Repeat))
             .unwrap();
@@ -2922,7 +3834,7 @@ mod collect_and_sort_events_tests {
 
     #[test]
     fn all_modifiers_no_reorder_needed() {
-        let (tx, rx) = sync_channel::<KeyEvent>(10);
+        let (tx, rx) = event_queue(10);
         let first = make_event(OsCode::KEY_LEFTCTRL, KeyValue::Press);
         tx.send(make_event(OsCode::KEY_LEFTSHIFT, KeyValue::Press))
             .unwrap();
@@ -2940,7 +3852,7 @@ mod collect_and_sort_events_tests {
 
     #[test]
     fn all_non_modifiers_no_reorder_needed() {
-        let (tx, rx) = sync_channel::<KeyEvent>(10);
+        let (tx, rx) = event_queue(10);
         let first = make_event(OsCode::KEY_A, KeyValue::Press);
         tx.send(make_event(OsCode::KEY_B, KeyValue::Press)).unwrap();
         tx.send(make_event(OsCode::KEY_C, KeyValue::Press)).unwrap();
@@ -2956,7 +3868,7 @@ mod collect_and_sort_events_tests {
 
     #[test]
     fn mixed_press_release_preserves_interleaving() {
-        let (tx, rx) = sync_channel::<KeyEvent>(10);
+        let (tx, rx) = event_queue(10);
         let first = make_event(OsCode::KEY_A, KeyValue::Press);
         tx.send(make_event(OsCode::KEY_LEFTCTRL, KeyValue::Release))
             .unwrap();
@@ -0,0 +1,56 @@
+//! Keyboard-driven mouse warping: the monitor is divided into a grid, the pointer is warped to the
+//! center of a selected cell, and the cell becomes the region that the next selection divides.
+
+use super::*;
+
+/// The region that the next `mouse-grid` action divides, in fractions of the monitor's width and
+/// height.
+pub(crate) struct MouseGridState {
+    monitor: u8,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Default for MouseGridState {
+    fn default() -> Self {
+        Self::whole_monitor(0)
+    }
+}
+
+impl MouseGridState {
+    fn whole_monitor(monitor: u8) -> Self {
+        Self {
+            monitor,
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+
+    pub(crate) fn select_cell(&mut self, cell: &MouseGridCell) {
+        self.width /= f64::from(cell.columns);
+        self.height /= f64::from(cell.rows);
+        self.x += self.width * f64::from(cell.column);
+        self.y += self.height * f64::from(cell.row);
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::whole_monitor(self.monitor);
+    }
+
+    pub(crate) fn set_monitor(&mut self, monitor: u8) {
+        *self = Self::whole_monitor(monitor);
+    }
+
+    /// Warps the pointer to the center of the current region.
+    pub(crate) fn warp(&self, kbd_out: &mut KbdOut) -> Result<()> {
+        let x = self.x + self.width / 2.0;
+        let y = self.y + self.height / 2.0;
+        tracing::debug!("mouse-grid: monitor {} at {x:.4},{y:.4}", self.monitor + 1);
+        kbd_out.warp_mouse_in_monitor(usize::from(self.monitor), x, y)?;
+        Ok(())
+    }
+}
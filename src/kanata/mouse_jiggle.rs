@@ -0,0 +1,69 @@
+//! The `jiggle` action, which moves the mouse a small distance back and forth periodically
+//! until it is toggled off.
+
+use super::*;
+
+pub(crate) struct MouseJiggleState {
+    cfg: MouseJiggle,
+    ticks_until_move: u16,
+    /// Whether the next movement is to the left, moving back from the previous one.
+    move_back: bool,
+}
+
+impl MouseJiggleState {
+    pub(crate) fn new(cfg: MouseJiggle) -> Self {
+        Self {
+            cfg,
+            ticks_until_move: cfg.interval,
+            move_back: false,
+        }
+    }
+}
+
+/// Starts jiggling with `cfg`. Stops instead if jiggling with the same configuration is already
+/// active.
+pub(crate) fn toggle_mouse_jiggle(jiggle: &mut Option<MouseJiggleState>, cfg: MouseJiggle) {
+    match jiggle {
+        Some(state) if state.cfg == cfg => {
+            tracing::info!("stopping mouse jiggle");
+            *jiggle = None;
+        }
+        _ => {
+            tracing::info!(
+                "starting mouse jiggle every {}ms by {}px",
+                cfg.interval,
+                cfg.distance
+            );
+            *jiggle = Some(MouseJiggleState::new(cfg));
+        }
+    }
+}
+
+impl Kanata {
+    pub(crate) fn tick_mouse_jiggle(&mut self) -> Result<()> {
+        let Some(state) = &mut self.mouse_jiggle else {
+            return Ok(());
+        };
+        state.ticks_until_move -= 1;
+        if state.ticks_until_move > 0 {
+            return Ok(());
+        }
+        state.ticks_until_move = state.cfg.interval;
+        let right = CalculatedMouseMove {
+            direction: MoveDirection::Right,
+            distance: state.cfg.distance,
+        };
+        let left = CalculatedMouseMove {
+            direction: MoveDirection::Left,
+            distance: state.cfg.distance,
+        };
+        if state.cfg.zero_sum {
+            self.kbd_out.move_mouse_many(&[right, left])?;
+        } else {
+            let mouse_move = if state.move_back { left } else { right };
+            state.move_back = !state.move_back;
+            self.kbd_out.move_mouse(mouse_move)?;
+        }
+        Ok(())
+    }
+}
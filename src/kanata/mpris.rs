@@ -0,0 +1,245 @@
+//! Media player control for the `mpris` action, with the MPRIS D-Bus interface on Linux and the
+//! `dbus` feature.
+//!
+//! Unlike the media keys, which go to whichever player the desktop picks, the action can target
+//! one player, e.g. `spotify` or `mpv`. The name of the player is the part of its bus name after
+//! `org.mpris.MediaPlayer2.`, and players that add an instance suffix such as
+//! `org.mpris.MediaPlayer2.chromium.instance1234` match their name without it.
+
+use super::*;
+
+/// Send a command to a media player. With simulated output, the command is written to the
+/// simulated keyboard output instead.
+pub(crate) fn send_mpris(_mpris: &mut MprisClient, _kbd_out: &mut KbdOut, action: MprisAction) {
+    tracing::debug!("mpris {} {:?}", action.command.name(), action.player);
+    #[cfg(feature = "simulated_output")]
+    _kbd_out.write_mpris(action);
+    #[cfg(not(feature = "simulated_output"))]
+    _mpris.send(action);
+}
+
+#[derive(Default)]
+pub(crate) struct MprisClient {
+    #[cfg(all(
+        target_os = "linux",
+        feature = "dbus",
+        not(feature = "simulated_output")
+    ))]
+    commands: Option<std::sync::mpsc::Sender<MprisAction>>,
+    /// Set once the missing support was logged, so that it is not logged on every press.
+    #[cfg(all(
+        not(all(target_os = "linux", feature = "dbus")),
+        not(feature = "simulated_output")
+    ))]
+    warned: bool,
+}
+
+#[cfg(all(
+    target_os = "linux",
+    feature = "dbus",
+    not(feature = "simulated_output")
+))]
+impl MprisClient {
+    fn send(&mut self, action: MprisAction) {
+        let commands = self.commands.get_or_insert_with(|| {
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || linux::run(rx));
+            tx
+        });
+        if commands.send(action).is_err() {
+            tracing::error!(
+                "the MPRIS thread stopped, dropping mpris {}",
+                action.command.name()
+            );
+            self.commands = None;
+        }
+    }
+}
+
+#[cfg(all(
+    not(all(target_os = "linux", feature = "dbus")),
+    not(feature = "simulated_output")
+))]
+impl MprisClient {
+    fn send(&mut self, action: MprisAction) {
+        if !self.warned {
+            self.warned = true;
+            #[cfg(target_os = "linux")]
+            tracing::error!(
+                "ignoring mpris {}: kanata was compiled without the dbus feature",
+                action.command.name()
+            );
+            #[cfg(not(target_os = "linux"))]
+            tracing::error!(
+                "ignoring mpris {}: MPRIS is only supported on Linux",
+                action.command.name()
+            );
+        }
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    feature = "dbus",
+    not(feature = "simulated_output")
+))]
+mod linux {
+    use super::dbus;
+    use kanata_parser::custom_action::MprisAction;
+    use std::sync::mpsc::Receiver;
+    use zbus::blocking::Connection;
+
+    const PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+    /// Sends the commands until the client is dropped.
+    pub(super) fn run(commands: Receiver<MprisAction>) {
+        let mut conn: Option<Connection> = None;
+        for action in commands {
+            // The bus may have closed a connection that was open, so a failure on an open
+            // connection is retried once with a new one.
+            for retry in [conn.is_some(), false] {
+                let result = match &mut conn {
+                    Some(conn) => command(conn, action),
+                    None => dbus::session().and_then(|c| command(conn.insert(c), action)),
+                };
+                match result {
+                    Ok(()) => {}
+                    Err(_) if retry => {
+                        conn = None;
+                        continue;
+                    }
+                    Err(e) => {
+                        conn = None;
+                        tracing::error!("could not send mpris {}: {e}", action.command.name());
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    /// Calls the method of the player. A player that is not running is not an error of the bus,
+    /// so it is only logged.
+    fn command(conn: &Connection, action: MprisAction) -> zbus::Result<()> {
+        let names: Vec<String> = conn
+            .call_method(
+                Some("org.freedesktop.DBus"),
+                "/org/freedesktop/DBus",
+                Some("org.freedesktop.DBus"),
+                "ListNames",
+                &(),
+            )?
+            .body()
+            .deserialize()?;
+        let Some(player) = find_player(&names, action.player) else {
+            match action.player {
+                Some(player) => tracing::warn!(
+                    "ignoring mpris {}: no media player named {player} is running",
+                    action.command.name()
+                ),
+                None => tracing::warn!(
+                    "ignoring mpris {}: no media player is running",
+                    action.command.name()
+                ),
+            }
+            return Ok(());
+        };
+        conn.call_method(
+            Some(player),
+            "/org/mpris/MediaPlayer2",
+            Some("org.mpris.MediaPlayer2.Player"),
+            action.command.method(),
+            &(),
+        )?;
+        Ok(())
+    }
+
+    fn find_player<'a>(names: &'a [String], player: Option<&str>) -> Option<&'a str> {
+        names
+            .iter()
+            .filter_map(|name| Some((name.as_str(), name.strip_prefix(PREFIX)?)))
+            .find(|(_, name)| match player {
+                Some(player) => {
+                    *name == player
+                        || name
+                            .strip_prefix(player)
+                            .is_some_and(|instance| instance.starts_with('.'))
+                }
+                None => true,
+            })
+            .map(|(name, _)| name)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::super::dbus::tests::connection_pair;
+        use super::*;
+        use kanata_parser::custom_action::MprisCommand;
+        use std::sync::{Arc, Mutex};
+
+        /// The bus, which only lists the names of its connections.
+        struct Bus;
+
+        #[zbus::interface(name = "org.freedesktop.DBus")]
+        impl Bus {
+            fn list_names(&self) -> Vec<&str> {
+                vec!["org.freedesktop.DBus", "org.mpris.MediaPlayer2.mpv"]
+            }
+        }
+
+        /// A player, which records the methods that were called.
+        struct Player(Arc<Mutex<Vec<&'static str>>>);
+
+        #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+        impl Player {
+            fn next(&self) {
+                self.0.lock().unwrap().push("Next");
+            }
+
+            fn play_pause(&self) {
+                self.0.lock().unwrap().push("PlayPause");
+            }
+        }
+
+        #[test]
+        fn mpris_finds_player_by_name() {
+            let names = [
+                "org.freedesktop.DBus",
+                "org.mpris.MediaPlayer2.mpv",
+                "org.mpris.MediaPlayer2.spotifyd",
+                "org.mpris.MediaPlayer2.spotify",
+                "org.mpris.MediaPlayer2.chromium.instance42",
+            ]
+            .map(String::from);
+            assert_eq!(
+                find_player(&names, Some("spotify")),
+                Some("org.mpris.MediaPlayer2.spotify")
+            );
+            assert_eq!(
+                find_player(&names, Some("chromium")),
+                Some("org.mpris.MediaPlayer2.chromium.instance42")
+            );
+            assert_eq!(
+                find_player(&names, None),
+                Some("org.mpris.MediaPlayer2.mpv")
+            );
+            assert_eq!(find_player(&names, Some("vlc")), None);
+        }
+
+        #[test]
+        fn mpris_calls_player_method() {
+            let calls = Arc::new(Mutex::new(vec![]));
+            let player = Player(calls.clone());
+            let (conn, _server) = connection_pair(move |builder| {
+                builder
+                    .serve_at("/org/freedesktop/DBus", Bus)?
+                    .serve_at("/org/mpris/MediaPlayer2", player)
+            });
+            let action = |command, player| MprisAction { command, player };
+            command(&conn, action(MprisCommand::Next, Some("mpv"))).unwrap();
+            command(&conn, action(MprisCommand::PlayPause, None)).unwrap();
+            command(&conn, action(MprisCommand::Next, Some("vlc"))).unwrap();
+            assert_eq!(*calls.lock().unwrap(), ["Next", "PlayPause"]);
+        }
+    }
+}
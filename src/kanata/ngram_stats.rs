@@ -0,0 +1,338 @@
+//! `--ngram-stats`: counts how often keys are pressed after each other, as bigrams and trigrams
+//! per layer, for tools that optimize keyboard layouts from real typing.
+//!
+//! An n-gram counts for the layer that its last key was pressed on. A pause between presses
+//! starts a new sequence, so that n-grams don't join unrelated bursts of typing. With
+//! `--ngram-stats classes`, keys are reduced to their class, e.g. vowel or digit, before they
+//! are counted, so that the statistics don't reveal what was typed.
+
+use super::*;
+use kanata_tcp_protocol::{LayerNgrams, Ngram};
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// A longer pause between two presses starts a new sequence.
+const SEQUENCE_BREAK: Duration = Duration::from_secs(2);
+
+/// How often the statistics file is written while keys are pressed.
+const WRITE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The most distinct bigrams and trigrams of each layer, so that memory stays bounded. Once a
+/// layer has this many, only the ones already seen are counted.
+const MAX_NGRAMS: usize = 100_000;
+
+/// The settings of `--ngram-stats`.
+#[derive(Debug, Clone)]
+pub struct NgramStatsConfig {
+    /// Count the classes of keys instead of the keys.
+    pub classes: bool,
+    /// N-grams counted fewer times are left out of the statistics.
+    pub min_count: u64,
+    /// The file that the statistics are written to.
+    pub file: Option<PathBuf>,
+}
+
+static NGRAM_STATS: OnceLock<NgramStatsConfig> = OnceLock::new();
+
+pub fn enable_ngram_stats(config: NgramStatsConfig) {
+    let _ = NGRAM_STATS.set(config);
+}
+
+/// The class that a key is counted as with `--ngram-stats classes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub(super) enum KeyClass {
+    Vowel,
+    Consonant,
+    Digit,
+    Space,
+    Punctuation,
+    Modifier,
+    Editing,
+    Navigation,
+    Other,
+}
+
+impl KeyClass {
+    const ALL: [KeyClass; 9] = [
+        KeyClass::Vowel,
+        KeyClass::Consonant,
+        KeyClass::Digit,
+        KeyClass::Space,
+        KeyClass::Punctuation,
+        KeyClass::Modifier,
+        KeyClass::Editing,
+        KeyClass::Navigation,
+        KeyClass::Other,
+    ];
+
+    pub(super) fn of(osc: OsCode) -> Self {
+        use OsCode::*;
+        match osc {
+            KEY_A | KEY_E | KEY_I | KEY_O | KEY_U => KeyClass::Vowel,
+            KEY_B | KEY_C | KEY_D | KEY_F | KEY_G | KEY_H | KEY_J | KEY_K | KEY_L | KEY_M
+            | KEY_N | KEY_P | KEY_Q | KEY_R | KEY_S | KEY_T | KEY_V | KEY_W | KEY_X | KEY_Y
+            | KEY_Z => KeyClass::Consonant,
+            KEY_1 | KEY_2 | KEY_3 | KEY_4 | KEY_5 | KEY_6 | KEY_7 | KEY_8 | KEY_9 | KEY_0 => {
+                KeyClass::Digit
+            }
+            KEY_SPACE => KeyClass::Space,
+            KEY_MINUS | KEY_EQUAL | KEY_LEFTBRACE | KEY_RIGHTBRACE | KEY_SEMICOLON
+            | KEY_APOSTROPHE | KEY_GRAVE | KEY_BACKSLASH | KEY_COMMA | KEY_DOT | KEY_SLASH
+            | KEY_102ND => KeyClass::Punctuation,
+            KEY_LEFTSHIFT | KEY_RIGHTSHIFT | KEY_LEFTCTRL | KEY_RIGHTCTRL | KEY_LEFTALT
+            | KEY_RIGHTALT | KEY_LEFTMETA | KEY_RIGHTMETA | KEY_CAPSLOCK => KeyClass::Modifier,
+            KEY_ENTER | KEY_TAB | KEY_BACKSPACE | KEY_DELETE | KEY_ESC => KeyClass::Editing,
+            KEY_UP | KEY_DOWN | KEY_LEFT | KEY_RIGHT | KEY_HOME | KEY_END | KEY_PAGEUP
+            | KEY_PAGEDOWN => KeyClass::Navigation,
+            _ => KeyClass::Other,
+        }
+    }
+
+    /// Whether the keys of the class type a character, for the words per minute.
+    pub(super) fn types_character(self) -> bool {
+        matches!(
+            self,
+            KeyClass::Vowel
+                | KeyClass::Consonant
+                | KeyClass::Digit
+                | KeyClass::Space
+                | KeyClass::Punctuation
+        )
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            KeyClass::Vowel => "vowel",
+            KeyClass::Consonant => "consonant",
+            KeyClass::Digit => "digit",
+            KeyClass::Space => "space",
+            KeyClass::Punctuation => "punctuation",
+            KeyClass::Modifier => "modifier",
+            KeyClass::Editing => "editing",
+            KeyClass::Navigation => "navigation",
+            KeyClass::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LayerCounts {
+    bigrams: FxHashMap<[u16; 2], u64>,
+    trigrams: FxHashMap<[u16; 3], u64>,
+}
+
+fn count<const N: usize>(counts: &mut FxHashMap<[u16; N], u64>, ngram: [u16; N]) {
+    if let Some(n) = counts.get_mut(&ngram) {
+        *n += 1;
+    } else if counts.len() < MAX_NGRAMS {
+        counts.insert(ngram, 1);
+    }
+}
+
+/// The state of `--ngram-stats` in [`Kanata`].
+#[derive(Debug)]
+pub(super) struct NgramStats {
+    classes: bool,
+    min_count: u64,
+    file: Option<PathBuf>,
+    layers: FxHashMap<String, LayerCounts>,
+    /// The last two keys of the sequence, the most recent last.
+    recent: [Option<u16>; 2],
+    last_press: web_time::Instant,
+    written_at: web_time::Instant,
+    /// Whether there are counts that the file doesn't have yet.
+    unwritten: bool,
+}
+
+impl NgramStats {
+    pub(super) fn new_if_enabled() -> Option<Self> {
+        NGRAM_STATS.get().map(|config| Self {
+            classes: config.classes,
+            min_count: config.min_count,
+            file: config.file.clone(),
+            layers: FxHashMap::default(),
+            recent: [None; 2],
+            last_press: clock::now(),
+            written_at: clock::now(),
+            unwritten: false,
+        })
+    }
+
+    fn record_press(&mut self, osc: OsCode, layer: &str, now: web_time::Instant) {
+        if now.duration_since(self.last_press) > SEQUENCE_BREAK {
+            self.recent = [None; 2];
+        }
+        self.last_press = now;
+        let key = match self.classes {
+            true => KeyClass::of(osc) as u16,
+            false => osc.into(),
+        };
+        if let Some(prev) = self.recent[1] {
+            let counts = match self.layers.get_mut(layer) {
+                Some(counts) => counts,
+                None => self.layers.entry(layer.to_owned()).or_default(),
+            };
+            count(&mut counts.bigrams, [prev, key]);
+            if let Some(first) = self.recent[0] {
+                count(&mut counts.trigrams, [first, prev, key]);
+            }
+            self.unwritten = true;
+        }
+        self.recent = [self.recent[1], Some(key)];
+    }
+
+    fn key_name(&self, key: u16) -> String {
+        if self.classes {
+            return KeyClass::ALL
+                .get(usize::from(key))
+                .unwrap_or(&KeyClass::Other)
+                .name()
+                .to_owned();
+        }
+        let osc = OsCode::from(key);
+        oscode_to_str(osc)
+            .map(str::to_owned)
+            .unwrap_or_else(|| osc.to_string().to_lowercase())
+    }
+
+    fn ngrams<const N: usize>(&self, counts: &FxHashMap<[u16; N], u64>) -> Vec<Ngram> {
+        let mut ngrams: Vec<Ngram> = counts
+            .iter()
+            .filter(|(_, n)| **n >= self.min_count)
+            .map(|(keys, n)| Ngram {
+                keys: keys.iter().map(|k| self.key_name(*k)).collect(),
+                count: *n,
+            })
+            .collect();
+        ngrams.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.keys.cmp(&b.keys)));
+        ngrams
+    }
+
+    /// The n-grams of each layer, the most frequent first.
+    fn layers(&self) -> BTreeMap<String, LayerNgrams> {
+        self.layers
+            .iter()
+            .map(|(layer, counts)| {
+                let ngrams = LayerNgrams {
+                    bigrams: self.ngrams(&counts.bigrams),
+                    trigrams: self.ngrams(&counts.trigrams),
+                };
+                (layer.clone(), ngrams)
+            })
+            .collect()
+    }
+
+    fn keys_kind(&self) -> &'static str {
+        match self.classes {
+            true => "classes",
+            false => "keys",
+        }
+    }
+
+    fn write_if_due(&mut self, now: web_time::Instant) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if !self.unwritten || now.duration_since(self.written_at) < WRITE_INTERVAL {
+            return;
+        }
+        self.written_at = now;
+        self.unwritten = false;
+        let contents = serde_json::json!({
+            "keys": self.keys_kind(),
+            "layers": self.layers(),
+        });
+        if let Err(e) = std::fs::write(file, contents.to_string()) {
+            tracing::error!(
+                "could not write the n-gram statistics to {}: {e}",
+                file.display()
+            );
+        }
+    }
+}
+
+impl Kanata {
+    /// Counts the n-grams that the press of the key ends.
+    pub(super) fn record_ngram(&mut self, osc: OsCode) {
+        if self.ngram_stats.is_none() {
+            return;
+        }
+        let layer = &self.layer_info[self.layout.bm().current_layer()].name;
+        let Some(ngrams) = &mut self.ngram_stats else {
+            return;
+        };
+        let now = clock::now();
+        ngrams.record_press(osc, layer, now);
+        ngrams.write_if_due(now);
+    }
+
+    /// The kind of keys that are counted, `keys` or `classes`, and the n-grams of each layer, if
+    /// they are counted.
+    pub fn ngram_stats(&self) -> Option<(&'static str, BTreeMap<String, LayerNgrams>)> {
+        self.ngram_stats
+            .as_ref()
+            .map(|ngrams| (ngrams.keys_kind(), ngrams.layers()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(classes: bool, min_count: u64) -> NgramStats {
+        NgramStats {
+            classes,
+            min_count,
+            file: None,
+            layers: FxHashMap::default(),
+            recent: [None; 2],
+            last_press: web_time::Instant::now(),
+            written_at: web_time::Instant::now(),
+            unwritten: false,
+        }
+    }
+
+    fn ngram(keys: &[&str], count: u64) -> Ngram {
+        Ngram {
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            count,
+        }
+    }
+
+    #[test]
+    fn counts_ngrams_per_layer_of_the_last_key() {
+        let mut s = stats(false, 1);
+        let t = web_time::Instant::now();
+        s.record_press(OsCode::KEY_T, "base", t);
+        s.record_press(OsCode::KEY_H, "base", t);
+        s.record_press(OsCode::KEY_E, "base", t);
+        s.record_press(OsCode::KEY_1, "num", t);
+        // A pause starts a new sequence.
+        let t = t + SEQUENCE_BREAK + Duration::from_millis(1);
+        s.record_press(OsCode::KEY_T, "base", t);
+        s.record_press(OsCode::KEY_H, "base", t);
+        let layers = s.layers();
+        assert_eq!(
+            layers["base"].bigrams,
+            [ngram(&["t", "h"], 2), ngram(&["h", "e"], 1)]
+        );
+        assert_eq!(layers["base"].trigrams, [ngram(&["t", "h", "e"], 1)]);
+        assert_eq!(layers["num"].bigrams, [ngram(&["e", "1"], 1)]);
+        assert_eq!(layers["num"].trigrams, [ngram(&["h", "e", "1"], 1)]);
+    }
+
+    #[test]
+    fn classes_and_min_count_hide_the_keys() {
+        let mut s = stats(true, 2);
+        let t = web_time::Instant::now();
+        for osc in [OsCode::KEY_T, OsCode::KEY_O, OsCode::KEY_N, OsCode::KEY_A] {
+            s.record_press(osc, "base", t);
+        }
+        let layers = s.layers();
+        assert_eq!(layers["base"].bigrams, [ngram(&["consonant", "vowel"], 2)]);
+        assert_eq!(layers["base"].trigrams, []);
+    }
+}
@@ -0,0 +1,241 @@
+//! Desktop notifications for the `notify` action.
+//!
+//! - Linux: the freedesktop notification service over the D-Bus session bus, with the `dbus`
+//!   feature. A notification replaces the previous one, so that toggling a layer a few times does
+//!   not stack them up.
+//! - macOS: Notification Center, through `osascript`, which is part of macOS.
+//! - Windows: the tray notification of the GUI build.
+
+use super::*;
+
+/// Show a notification. With simulated output, it is written to the simulated keyboard output
+/// instead.
+pub(crate) fn send_notification(
+    _notifier: &mut Notifier,
+    _kbd_out: &mut KbdOut,
+    title: &str,
+    body: &str,
+) {
+    tracing::debug!("notification: {title}: {body}");
+    #[cfg(feature = "simulated_output")]
+    _kbd_out.write_notification(title, body);
+    #[cfg(not(feature = "simulated_output"))]
+    _notifier.show(title, body);
+}
+
+#[derive(Default)]
+pub(crate) struct Notifier {
+    #[cfg(all(
+        target_os = "linux",
+        feature = "dbus",
+        not(feature = "simulated_output")
+    ))]
+    notifications: Option<std::sync::mpsc::Sender<(String, String)>>,
+    /// Set once the missing support was logged, so that it is not logged on every press.
+    #[cfg(all(
+        not(feature = "simulated_output"),
+        not(any(
+            all(target_os = "linux", feature = "dbus"),
+            target_os = "macos",
+            all(target_os = "windows", feature = "gui")
+        ))
+    ))]
+    warned: bool,
+}
+
+#[cfg(all(
+    target_os = "linux",
+    feature = "dbus",
+    not(feature = "simulated_output")
+))]
+impl Notifier {
+    fn show(&mut self, title: &str, body: &str) {
+        let notifications = self.notifications.get_or_insert_with(|| {
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || linux::run(rx));
+            tx
+        });
+        if notifications
+            .send((title.to_owned(), body.to_owned()))
+            .is_err()
+        {
+            tracing::error!("the notification thread stopped, dropping the notification");
+            self.notifications = None;
+        }
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    feature = "dbus",
+    not(feature = "simulated_output")
+))]
+mod linux {
+    use super::dbus;
+    use std::collections::HashMap;
+    use std::sync::mpsc::Receiver;
+    use zbus::blocking::Connection;
+    use zbus::zvariant::OwnedValue;
+
+    /// Shows the notifications until the notifier is dropped.
+    pub(super) fn run(notifications: Receiver<(String, String)>) {
+        let mut conn: Option<Connection> = None;
+        let mut last_id = 0;
+        for (title, body) in notifications {
+            // The bus may have closed a connection that was open, so a failure on an open
+            // connection is retried once with a new one.
+            for retry in [conn.is_some(), false] {
+                let result = match &mut conn {
+                    Some(conn) => notify(conn, last_id, &title, &body),
+                    None => {
+                        dbus::session().and_then(|c| notify(conn.insert(c), last_id, &title, &body))
+                    }
+                };
+                match result {
+                    Ok(id) => last_id = id,
+                    Err(_) if retry => {
+                        conn = None;
+                        continue;
+                    }
+                    Err(e) => {
+                        conn = None;
+                        tracing::error!("could not show the notification {title:?}: {e}");
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    fn notify(conn: &Connection, replaces_id: u32, title: &str, body: &str) -> zbus::Result<u32> {
+        let actions: &[&str] = &[];
+        let hints = HashMap::<&str, OwnedValue>::new();
+        // The default timeout of the notification server.
+        let timeout = -1i32;
+        let reply = conn.call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "Notify",
+            &(
+                "kanata",
+                replaces_id,
+                "",
+                title,
+                body,
+                actions,
+                hints,
+                timeout,
+            ),
+        )?;
+        reply.body().deserialize()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::super::dbus::tests::connection_pair;
+        use super::*;
+        use std::sync::{Arc, Mutex};
+
+        /// The notification server, which records the arguments of the notifications.
+        struct Notifications(Arc<Mutex<Vec<String>>>);
+
+        #[zbus::interface(name = "org.freedesktop.Notifications")]
+        impl Notifications {
+            #[allow(clippy::too_many_arguments)]
+            fn notify(
+                &self,
+                app_name: &str,
+                replaces_id: u32,
+                _app_icon: &str,
+                summary: &str,
+                body: &str,
+                _actions: Vec<String>,
+                _hints: HashMap<String, OwnedValue>,
+                _expire_timeout: i32,
+            ) -> u32 {
+                let call = format!("{app_name} {replaces_id} {summary}: {body}");
+                self.0.lock().unwrap().push(call);
+                9
+            }
+        }
+
+        #[test]
+        fn notify_replaces_previous_notification() {
+            let calls = Arc::new(Mutex::new(vec![]));
+            let server = Notifications(calls.clone());
+            let (conn, _server) = connection_pair(move |builder| {
+                builder.serve_at("/org/freedesktop/Notifications", server)
+            });
+            assert_eq!(notify(&conn, 0, "Gaming", "layer on").unwrap(), 9);
+            assert_eq!(notify(&conn, 9, "Gaming", "").unwrap(), 9);
+            assert_eq!(
+                *calls.lock().unwrap(),
+                ["kanata 0 Gaming: layer on", "kanata 9 Gaming: "]
+            );
+        }
+    }
+}
+
+#[cfg(all(target_os = "macos", not(feature = "simulated_output")))]
+impl Notifier {
+    fn show(&mut self, title: &str, body: &str) {
+        // AppleScript strings only need backslashes and quotes escaped.
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let script = format!(
+            "display notification {} with title {}",
+            quote(body),
+            quote(title)
+        );
+        std::thread::spawn(move || {
+            match std::process::Command::new("osascript")
+                .args(["-e", &script])
+                .output()
+            {
+                Ok(out) if out.status.success() => {}
+                Ok(out) => tracing::error!(
+                    "could not show the notification: {}",
+                    String::from_utf8_lossy(&out.stderr).trim()
+                ),
+                Err(e) => tracing::error!("could not run osascript to show a notification: {e}"),
+            }
+        });
+    }
+}
+
+#[cfg(all(
+    target_os = "windows",
+    feature = "gui",
+    not(feature = "simulated_output")
+))]
+impl Notifier {
+    fn show(&mut self, title: &str, body: &str) {
+        crate::gui::send_gui_notification(title.to_owned(), body.to_owned());
+    }
+}
+
+#[cfg(all(
+    not(feature = "simulated_output"),
+    not(any(
+        all(target_os = "linux", feature = "dbus"),
+        target_os = "macos",
+        all(target_os = "windows", feature = "gui")
+    ))
+))]
+impl Notifier {
+    fn show(&mut self, title: &str, _body: &str) {
+        if !self.warned {
+            self.warned = true;
+            #[cfg(target_os = "windows")]
+            tracing::error!(
+                "ignoring notification {title:?}: notifications need the GUI build of kanata on Windows"
+            );
+            #[cfg(target_os = "linux")]
+            tracing::error!(
+                "ignoring notification {title:?}: kanata was compiled without the dbus feature"
+            );
+            #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+            tracing::error!("ignoring notification {title:?}: not supported on this platform");
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! Requests to OBS Studio for the `obs` action, sent with obs-websocket 5.
+//!
+//! The requests are sent by a background thread so that a slow or unreachable OBS never delays
+//! processing. The thread connects when the first request is sent and keeps the connection open
+//! for the next ones. A request that can't be sent is dropped with an error in the log rather than
+//! sent later, when e.g. starting a recording would be a surprise.
+
+use super::*;
+
+/// Send a request to OBS. With simulated output, the request is written to the simulated keyboard
+/// output instead.
+pub(crate) fn send_obs(_obs: &mut ObsClient, _kbd_out: &mut KbdOut, action: ObsAction) {
+    tracing::debug!("obs request: {} {:?}", action.name(), action.args());
+    #[cfg(feature = "simulated_output")]
+    _kbd_out.write_obs(action);
+    #[cfg(not(feature = "simulated_output"))]
+    _obs.send(action);
+}
+
+#[cfg(not(all(feature = "obs", not(feature = "simulated_output"))))]
+pub(crate) struct ObsClient {
+    /// Set once the missing feature was logged, so that it is not logged on every press.
+    #[cfg(not(feature = "simulated_output"))]
+    warned: bool,
+}
+
+#[cfg(not(all(feature = "obs", not(feature = "simulated_output"))))]
+impl ObsClient {
+    pub(crate) fn new(_address: Option<String>, _password: Option<String>) -> Self {
+        Self {
+            #[cfg(not(feature = "simulated_output"))]
+            warned: false,
+        }
+    }
+
+    pub(crate) fn set_config(&mut self, _address: Option<String>, _password: Option<String>) {}
+
+    #[cfg(not(feature = "simulated_output"))]
+    pub(crate) fn send(&mut self, action: ObsAction) {
+        if !self.warned {
+            self.warned = true;
+            tracing::error!(
+                "ignoring obs {}: kanata was compiled without the obs feature",
+                action.name()
+            );
+        }
+    }
+}
+
+#[cfg(all(feature = "obs", not(feature = "simulated_output")))]
+pub(crate) use real::*;
+
+#[cfg(all(feature = "obs", not(feature = "simulated_output")))]
+mod real {
+    use kanata_parser::custom_action::ObsAction;
+    use serde_json::{Value, json};
+    use std::net::{TcpStream, ToSocketAddrs};
+    use std::sync::mpsc::{Receiver, Sender};
+    use std::time::Duration;
+    use tungstenite::{Message, WebSocket};
+
+    const DEFAULT_ADDRESS: &str = "127.0.0.1:4455";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct ObsConfig {
+        address: String,
+        password: Option<String>,
+    }
+
+    /// The connection to OBS, which is opened by a thread when the first request is sent.
+    pub(crate) struct ObsClient {
+        config: ObsConfig,
+        requests: Option<Sender<ObsAction>>,
+    }
+
+    impl ObsClient {
+        pub(crate) fn new(address: Option<String>, password: Option<String>) -> Self {
+            Self {
+                config: ObsConfig {
+                    address: address.unwrap_or_else(|| DEFAULT_ADDRESS.to_owned()),
+                    password,
+                },
+                requests: None,
+            }
+        }
+
+        /// Changes the configured address and password. The thread and its connection are stopped
+        /// if they differ.
+        pub(crate) fn set_config(&mut self, address: Option<String>, password: Option<String>) {
+            let config = Self::new(address, password).config;
+            if config != self.config {
+                self.config = config;
+                self.requests = None;
+            }
+        }
+
+        pub(crate) fn send(&mut self, action: ObsAction) {
+            let requests = self.requests.get_or_insert_with(|| {
+                let (tx, rx) = std::sync::mpsc::channel();
+                let config = self.config.clone();
+                std::thread::spawn(move || run(config, rx));
+                tx
+            });
+            if requests.send(action).is_err() {
+                tracing::error!("the OBS thread stopped, dropping obs {}", action.name());
+                self.requests = None;
+            }
+        }
+    }
+
+    enum ObsError {
+        /// The connection failed, so the request might succeed with a new connection.
+        Connection(String),
+        /// OBS could not do what was requested, e.g. because a scene does not exist.
+        Request(String),
+    }
+
+    impl<E: std::fmt::Display> From<E> for ObsError {
+        fn from(e: E) -> Self {
+            Self::Connection(e.to_string())
+        }
+    }
+
+    /// Sends the requests until the client is dropped.
+    fn run(config: ObsConfig, requests: Receiver<ObsAction>) {
+        let mut conn: Option<Connection> = None;
+        for action in requests {
+            // OBS may have closed a connection that was open, e.g. by restarting, so a failure
+            // on an open connection is retried once with a new one.
+            for retry in [conn.is_some(), false] {
+                let result = match &mut conn {
+                    Some(conn) => conn.execute(action),
+                    None => Connection::open(&config).and_then(|c| conn.insert(c).execute(action)),
+                };
+                match result {
+                    Ok(()) => {}
+                    Err(ObsError::Request(msg)) => {
+                        tracing::warn!("OBS could not do {}: {msg}", action.name());
+                    }
+                    Err(ObsError::Connection(_)) if retry => {
+                        conn = None;
+                        continue;
+                    }
+                    Err(ObsError::Connection(e)) => {
+                        conn = None;
+                        tracing::error!(
+                            "could not send obs {} to OBS at {}: {e}",
+                            action.name(),
+                            config.address
+                        );
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    struct Connection {
+        ws: WebSocket<TcpStream>,
+        next_id: u64,
+    }
+
+    impl Connection {
+        fn open(config: &ObsConfig) -> Result<Self, ObsError> {
+            let mut last_error = None;
+            let mut stream = None;
+            for address in config.address.to_socket_addrs()? {
+                match TcpStream::connect_timeout(&address, TIMEOUT) {
+                    Ok(s) => {
+                        stream = Some(s);
+                        break;
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            let stream = match (stream, last_error) {
+                (Some(stream), _) => stream,
+                (None, Some(e)) => return Err(e.into()),
+                (None, None) => return Err(ObsError::Connection("no address found".into())),
+            };
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            let _ = stream.set_nodelay(true);
+            let (ws, _) = tungstenite::client(format!("ws://{}", config.address), stream)?;
+            let mut conn = Self { ws, next_id: 0 };
+
+            let hello = conn.receive()?;
+            let mut identify = json!({ "rpcVersion": 1, "eventSubscriptions": 0 });
+            let auth = &hello["d"]["authentication"];
+            if let (Some(challenge), Some(salt)) =
+                (auth["challenge"].as_str(), auth["salt"].as_str())
+            {
+                let Some(password) = &config.password else {
+                    return Err(ObsError::Connection(
+                        "OBS requires a password, set obs-websocket-password in defcfg".into(),
+                    ));
+                };
+                identify["authentication"] = json!(authentication(password, salt, challenge));
+            }
+            conn.send(json!({ "op": 1, "d": identify }))?;
+            // OBS closes the connection instead if the password is wrong.
+            match conn.receive() {
+                Ok(msg) if msg["op"] == 2 => {}
+                Ok(msg) => return Err(ObsError::Connection(format!("unexpected message {msg}"))),
+                Err(ObsError::Connection(e)) => {
+                    return Err(ObsError::Connection(format!(
+                        "{e}, is obs-websocket-password right?"
+                    )));
+                }
+                Err(e) => return Err(e),
+            }
+            tracing::info!("connected to OBS at {}", config.address);
+            Ok(conn)
+        }
+
+        fn send(&mut self, msg: Value) -> Result<(), ObsError> {
+            Ok(self.ws.send(Message::text(msg.to_string()))?)
+        }
+
+        fn receive(&mut self) -> Result<Value, ObsError> {
+            loop {
+                match self.ws.read()? {
+                    Message::Text(text) => return Ok(serde_json::from_str(&text)?),
+                    Message::Close(frame) => {
+                        let reason = frame.map(|f| f.reason.to_string()).unwrap_or_default();
+                        return Err(ObsError::Connection(format!(
+                            "OBS closed the connection: {reason}"
+                        )));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        fn request(&mut self, request_type: &str, data: Value) -> Result<Value, ObsError> {
+            self.next_id += 1;
+            let id = self.next_id.to_string();
+            self.send(json!({
+                "op": 6,
+                "d": { "requestType": request_type, "requestId": id, "requestData": data },
+            }))?;
+            loop {
+                let mut msg = self.receive()?;
+                if msg["op"] != 7 || msg["d"]["requestId"] != id.as_str() {
+                    continue;
+                }
+                let status = &msg["d"]["requestStatus"];
+                if status["result"] != true {
+                    let comment = status["comment"].as_str().unwrap_or("unknown error");
+                    return Err(ObsError::Request(format!("{request_type}: {comment}")));
+                }
+                return Ok(msg["d"]["responseData"].take());
+            }
+        }
+
+        fn execute(&mut self, action: ObsAction) -> Result<(), ObsError> {
+            match action {
+                ObsAction::SwitchScene(scene) => {
+                    self.request("SetCurrentProgramScene", json!({ "sceneName": scene }))?;
+                }
+                ObsAction::ToggleSource { scene, source } => {
+                    let item = self.request(
+                        "GetSceneItemId",
+                        json!({ "sceneName": scene, "sourceName": source }),
+                    )?["sceneItemId"]
+                        .take();
+                    let enabled = self.request(
+                        "GetSceneItemEnabled",
+                        json!({ "sceneName": scene, "sceneItemId": item }),
+                    )?["sceneItemEnabled"]
+                        .as_bool()
+                        .unwrap_or(false);
+                    self.request(
+                        "SetSceneItemEnabled",
+                        json!({
+                            "sceneName": scene,
+                            "sceneItemId": item,
+                            "sceneItemEnabled": !enabled,
+                        }),
+                    )?;
+                }
+                ObsAction::StartRecording => {
+                    self.request("StartRecord", json!({}))?;
+                }
+                ObsAction::StopRecording => {
+                    self.request("StopRecord", json!({}))?;
+                }
+                ObsAction::ToggleRecording => {
+                    self.request("ToggleRecord", json!({}))?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// The authentication string of obs-websocket 5.
+    fn authentication(password: &str, salt: &str, challenge: &str) -> String {
+        use base64::Engine;
+        use base64::engine::general_purpose::STANDARD as BASE64;
+        use sha2::{Digest, Sha256};
+        let secret = BASE64.encode(Sha256::digest(format!("{password}{salt}")));
+        BASE64.encode(Sha256::digest(format!("{secret}{challenge}")))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn obs_authentication() {
+            assert_eq!(
+                authentication(
+                    "supersecretpassword",
+                    "lM1GncleQOaCu9lT1yeUZhFYnqhsLLP1G5lAGo3ixaI=",
+                    "+IxH4CnCiqpX1rM9scsNynZzbOe4KhDeYcTNS3PDaeY="
+                ),
+                "1Ct943GAT+6YQUUX47Ia/ncufilbe6+oD6lY+5kaCu4="
+            );
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! Colors of RGB lights, set through the SDK server of OpenRGB for the `openrgb` action and, with
+//! `openrgb-layer-colors`, for the `color` of the layer that is entered.
+//!
+//! Like requests to OBS, colors are sent by a background thread that connects when the first
+//! color is set and keeps the connection open. A color that can't be set is dropped with an error
+//! in the log, since a later color replaces it anyway.
+
+use super::*;
+
+/// Set the color of the lights. With simulated output, the color is written to the simulated
+/// keyboard output instead.
+pub(crate) fn send_openrgb(_openrgb: &mut OpenRgbClient, _kbd_out: &mut KbdOut, color: [u8; 3]) {
+    tracing::debug!("openrgb color: {color:02x?}");
+    #[cfg(feature = "simulated_output")]
+    _kbd_out.write_openrgb(color);
+    #[cfg(not(feature = "simulated_output"))]
+    _openrgb.send(color);
+}
+
+/// The colors of the layers for `openrgb-layer-colors`, by layer index.
+pub(crate) fn openrgb_layer_colors(
+    cfg: &CfgOptions,
+    layer_info: &[LayerInfo],
+) -> Vec<Option<[u8; 3]>> {
+    if !cfg.openrgb_layer_colors {
+        return vec![];
+    }
+    layer_info
+        .iter()
+        .map(|info| {
+            info.color
+                .as_deref()
+                .and_then(kanata_parser::cfg::layer_opts::hex_color_rgb)
+        })
+        .collect()
+}
+
+impl Kanata {
+    /// Sets the color of the layer if `openrgb-layer-colors` is enabled and the layer has one.
+    pub(crate) fn set_openrgb_layer_color(&mut self, layer: usize) {
+        if let Some(color) = self.openrgb_layer_colors.get(layer).copied().flatten() {
+            send_openrgb(&mut self.openrgb, &mut self.kbd_out, color);
+        }
+    }
+}
+
+#[cfg(feature = "simulated_output")]
+pub(crate) struct OpenRgbClient;
+
+#[cfg(feature = "simulated_output")]
+impl OpenRgbClient {
+    pub(crate) fn new(_address: Option<String>, _device: Option<String>) -> Self {
+        Self
+    }
+
+    pub(crate) fn set_config(&mut self, _address: Option<String>, _device: Option<String>) {}
+}
+
+#[cfg(not(feature = "simulated_output"))]
+pub(crate) use real::*;
+
+#[cfg(not(feature = "simulated_output"))]
+mod real {
+    use std::io::{Read, Write};
+    use std::net::{TcpStream, ToSocketAddrs};
+    use std::sync::mpsc::{Receiver, Sender};
+    use std::time::Duration;
+
+    const DEFAULT_ADDRESS: &str = "127.0.0.1:6742";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    const MAGIC: &[u8; 4] = b"ORGB";
+    const REQUEST_CONTROLLER_COUNT: u32 = 0;
+    const REQUEST_CONTROLLER_DATA: u32 = 1;
+    const SET_CLIENT_NAME: u32 = 50;
+    const UPDATE_LEDS: u32 = 1050;
+    const SET_CUSTOM_MODE: u32 = 1100;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct OpenRgbConfig {
+        address: String,
+        /// Only the devices whose name contains this, ignoring case, are changed.
+        device: Option<String>,
+    }
+
+    /// The connection to OpenRGB, which is opened by a thread when the first color is set.
+    pub(crate) struct OpenRgbClient {
+        config: OpenRgbConfig,
+        colors: Option<Sender<[u8; 3]>>,
+    }
+
+    impl OpenRgbClient {
+        pub(crate) fn new(address: Option<String>, device: Option<String>) -> Self {
+            Self {
+                config: OpenRgbConfig {
+                    address: address.unwrap_or_else(|| DEFAULT_ADDRESS.to_owned()),
+                    device: device.map(|device| device.to_lowercase()),
+                },
+                colors: None,
+            }
+        }
+
+        /// Changes the configured address and devices. The thread and its connection are stopped
+        /// if they differ.
+        pub(crate) fn set_config(&mut self, address: Option<String>, device: Option<String>) {
+            let config = Self::new(address, device).config;
+            if config != self.config {
+                self.config = config;
+                self.colors = None;
+            }
+        }
+
+        pub(crate) fn send(&mut self, color: [u8; 3]) {
+            let colors = self.colors.get_or_insert_with(|| {
+                let (tx, rx) = std::sync::mpsc::channel();
+                let config = self.config.clone();
+                std::thread::spawn(move || run(config, rx));
+                tx
+            });
+            if colors.send(color).is_err() {
+                tracing::error!("the OpenRGB thread stopped, dropping color {color:02x?}");
+                self.colors = None;
+            }
+        }
+    }
+
+    /// Sets the colors until the client is dropped.
+    fn run(config: OpenRgbConfig, colors: Receiver<[u8; 3]>) {
+        let mut conn: Option<Connection> = None;
+        while let Ok(mut color) = colors.recv() {
+            // Only the latest color matters if they arrive faster than they are set.
+            while let Ok(next) = colors.try_recv() {
+                color = next;
+            }
+            // OpenRGB may have closed a connection that was open, e.g. by restarting, so a
+            // failure on an open connection is retried once with a new one.
+            for retry in [conn.is_some(), false] {
+                let result = match &mut conn {
+                    Some(conn) => conn.set_color(color),
+                    None => Connection::open(&config).and_then(|c| conn.insert(c).set_color(color)),
+                };
+                match result {
+                    Ok(()) => {}
+                    Err(_) if retry => {
+                        conn = None;
+                        continue;
+                    }
+                    Err(e) => {
+                        conn = None;
+                        tracing::error!(
+                            "could not set color {color:02x?} with OpenRGB at {}: {e}",
+                            config.address
+                        );
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    struct Connection {
+        stream: TcpStream,
+        /// The index and the number of LEDs of the devices to change.
+        controllers: Vec<(u32, u16)>,
+    }
+
+    impl Connection {
+        fn open(config: &OpenRgbConfig) -> Result<Self, String> {
+            let mut last_error = None;
+            let mut stream = None;
+            for address in config
+                .address
+                .to_socket_addrs()
+                .map_err(|e| e.to_string())?
+            {
+                match TcpStream::connect_timeout(&address, TIMEOUT) {
+                    Ok(s) => {
+                        stream = Some(s);
+                        break;
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            let stream = match (stream, last_error) {
+                (Some(stream), _) => stream,
+                (None, Some(e)) => return Err(e.to_string()),
+                (None, None) => return Err("no address found".into()),
+            };
+            let io = |e: std::io::Error| e.to_string();
+            stream.set_read_timeout(Some(TIMEOUT)).map_err(io)?;
+            stream.set_write_timeout(Some(TIMEOUT)).map_err(io)?;
+            let _ = stream.set_nodelay(true);
+            let mut conn = Self {
+                stream,
+                controllers: vec![],
+            };
+            conn.send(0, SET_CLIENT_NAME, b"kanata\0")?;
+            conn.send(0, REQUEST_CONTROLLER_COUNT, &[])?;
+            let count = conn.receive(REQUEST_CONTROLLER_COUNT)?;
+            let count = Reader(&count).u32().ok_or("invalid controller count")?;
+            let mut names = vec![];
+            for idx in 0..count {
+                conn.send(idx, REQUEST_CONTROLLER_DATA, &[])?;
+                let data = conn.receive(REQUEST_CONTROLLER_DATA)?;
+                let (name, leds) = parse_controller(&data).ok_or("invalid controller data")?;
+                let wanted = config
+                    .device
+                    .as_ref()
+                    .is_none_or(|device| name.to_lowercase().contains(device));
+                if wanted && leds > 0 {
+                    conn.controllers.push((idx, leds));
+                    names.push(name);
+                }
+            }
+            if names.is_empty() {
+                return Err("found no devices with LEDs, is openrgb-device right?".into());
+            }
+            tracing::info!(
+                "connected to OpenRGB at {}, setting colors of {}",
+                config.address,
+                names.join(", ")
+            );
+            Ok(conn)
+        }
+
+        fn send(&mut self, device: u32, packet: u32, data: &[u8]) -> Result<(), String> {
+            let mut msg = Vec::with_capacity(16 + data.len());
+            msg.extend_from_slice(MAGIC);
+            msg.extend_from_slice(&device.to_le_bytes());
+            msg.extend_from_slice(&packet.to_le_bytes());
+            msg.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            msg.extend_from_slice(data);
+            self.stream.write_all(&msg).map_err(|e| e.to_string())
+        }
+
+        /// Returns the data of the next packet of the id, skipping others such as notifications
+        /// that the devices changed.
+        fn receive(&mut self, packet: u32) -> Result<Vec<u8>, String> {
+            loop {
+                let mut header = [0; 16];
+                self.stream
+                    .read_exact(&mut header)
+                    .map_err(|e| e.to_string())?;
+                if &header[..4] != MAGIC {
+                    return Err("OpenRGB sent an invalid packet".into());
+                }
+                let field = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+                let mut data = vec![0; field(12) as usize];
+                self.stream
+                    .read_exact(&mut data)
+                    .map_err(|e| e.to_string())?;
+                if field(8) == packet {
+                    return Ok(data);
+                }
+            }
+        }
+
+        fn set_color(&mut self, color: [u8; 3]) -> Result<(), String> {
+            for (idx, leds) in self.controllers.clone() {
+                self.send(idx, SET_CUSTOM_MODE, &[])?;
+                self.send(idx, UPDATE_LEDS, &update_leds_data(color, leds))?;
+            }
+            Ok(())
+        }
+    }
+
+    /// The data of an `UPDATE_LEDS` packet that sets every LED to the color.
+    fn update_leds_data(color: [u8; 3], leds: u16) -> Vec<u8> {
+        let size = 4 + 2 + 4 * u32::from(leds);
+        let mut data = Vec::with_capacity(size as usize);
+        data.extend_from_slice(&size.to_le_bytes());
+        data.extend_from_slice(&leds.to_le_bytes());
+        for _ in 0..leds {
+            data.extend_from_slice(&[color[0], color[1], color[2], 0]);
+        }
+        data
+    }
+
+    struct Reader<'a>(&'a [u8]);
+
+    impl Reader<'_> {
+        fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+            if self.0.len() < len {
+                return None;
+            }
+            let (bytes, rest) = self.0.split_at(len);
+            self.0 = rest;
+            Some(bytes)
+        }
+
+        fn u16(&mut self) -> Option<u16> {
+            Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+        }
+
+        fn u32(&mut self) -> Option<u32> {
+            Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+        }
+
+        /// A string with its length and a terminating null.
+        fn string(&mut self) -> Option<String> {
+            let len = self.u16()?.into();
+            let bytes = self.bytes(len)?;
+            let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+            Some(String::from_utf8_lossy(bytes).into_owned())
+        }
+    }
+
+    /// Returns the name and the number of LEDs of a device from the data of protocol version 0
+    /// that OpenRGB sends for `REQUEST_CONTROLLER_DATA`.
+    fn parse_controller(data: &[u8]) -> Option<(String, u16)> {
+        let mut r = Reader(data);
+        r.u32()?; // data size
+        r.u32()?; // device type
+        let name = r.string()?;
+        for _ in ["description", "version", "serial", "location"] {
+            r.string()?;
+        }
+        let modes = r.u16()?;
+        r.u32()?; // active mode
+        for _ in 0..modes {
+            r.string()?;
+            // value, flags, speed min and max, colors min and max, speed, direction, color mode
+            r.bytes(9 * 4)?;
+            let colors = r.u16()?;
+            r.bytes(usize::from(colors) * 4)?;
+        }
+        let zones = r.u16()?;
+        for _ in 0..zones {
+            r.string()?;
+            // type, LEDs min, max and count
+            r.bytes(4 * 4)?;
+            let matrix_len = r.u16()?;
+            r.bytes(matrix_len.into())?;
+        }
+        let leds = r.u16()?;
+        Some((name, leds))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn string(data: &mut Vec<u8>, s: &str) {
+            data.extend_from_slice(&(s.len() as u16 + 1).to_le_bytes());
+            data.extend_from_slice(s.as_bytes());
+            data.push(0);
+        }
+
+        #[test]
+        fn parses_controller_data() {
+            let mut data = vec![];
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.extend_from_slice(&5u32.to_le_bytes());
+            for s in ["Keychron K8", "keyboard", "1.0", "", "HID: /dev/hidraw3"] {
+                string(&mut data, s);
+            }
+            data.extend_from_slice(&2u16.to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes());
+            for (mode, colors) in [("Direct", 0u16), ("Static", 1)] {
+                string(&mut data, mode);
+                data.extend_from_slice(&[0; 9 * 4]);
+                data.extend_from_slice(&colors.to_le_bytes());
+                data.extend(std::iter::repeat_n(0xff, usize::from(colors) * 4));
+            }
+            data.extend_from_slice(&1u16.to_le_bytes());
+            string(&mut data, "Keys");
+            data.extend_from_slice(&[0; 4 * 4]);
+            // A matrix of 1 by 2.
+            data.extend_from_slice(&16u16.to_le_bytes());
+            data.extend_from_slice(&[0; 16]);
+            data.extend_from_slice(&87u16.to_le_bytes());
+            assert_eq!(
+                parse_controller(&data),
+                Some(("Keychron K8".to_owned(), 87))
+            );
+            assert_eq!(parse_controller(&data[..data.len() - 1]), None);
+        }
+
+        #[test]
+        fn update_leds_sets_every_led() {
+            assert_eq!(
+                update_leds_data([0x33, 0x66, 0xff], 2),
+                [14, 0, 0, 0, 2, 0, 0x33, 0x66, 0xff, 0, 0x33, 0x66, 0xff, 0]
+            );
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Detection of the keyboard layout of the OS, which selects the `deflocalkeys` blocks that start
+//! with `(layout ...)`, and switching it with the `os-layout` action.
+//!
+//! The layout is detected when kanata starts. On Windows, the layout of the foreground window is
+//! also polled, and a change reloads the configuration if it has blocks for specific layouts.
+
+use super::*;
+
+/// Returns the name of the keyboard layout of the OS, e.g. `de` on Linux, `de-DE` on Windows or
+/// `German` on macOS.
+pub(crate) fn detect_os_keyboard_layout() -> Option<String> {
+    let layout = detect();
+    match &layout {
+        Some(layout) => info!("the OS keyboard layout is {layout}"),
+        None => tracing::debug!("could not detect the OS keyboard layout"),
+    }
+    layout
+}
+
+/// kanata usually runs without access to the session of the display server, so the layout is the
+/// configured default of the system.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn detect() -> Option<String> {
+    if let Ok(layouts) = std::env::var("XKB_DEFAULT_LAYOUT") {
+        return first_xkb_layout(&layouts);
+    }
+    let content = std::fs::read_to_string("/etc/default/keyboard").ok()?;
+    default_keyboard_layout(&content)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn first_xkb_layout(layouts: &str) -> Option<String> {
+    let layout = layouts.split(',').next()?.trim();
+    (!layout.is_empty()).then(|| layout.to_owned())
+}
+
+/// Reads `XKBLAYOUT` of the format of `/etc/default/keyboard`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn default_keyboard_layout(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("XKBLAYOUT="))
+        .and_then(|layouts| first_xkb_layout(layouts.trim_matches(['"', '\''])))
+}
+
+#[cfg(target_os = "windows")]
+fn detect() -> Option<String> {
+    use winapi::um::winnls::LCIDToLocaleName;
+    use winapi::um::winuser::{GetForegroundWindow, GetKeyboardLayout, GetWindowThreadProcessId};
+
+    // LOCALE_NAME_MAX_LENGTH
+    let mut name = [0u16; 85];
+    let len = unsafe {
+        let thread = GetWindowThreadProcessId(GetForegroundWindow(), std::ptr::null_mut());
+        // The low word of the layout handle is its language.
+        let language = GetKeyboardLayout(thread) as usize & 0xffff;
+        LCIDToLocaleName(language as u32, name.as_mut_ptr(), name.len() as i32, 0)
+    };
+    // The length includes the null terminator.
+    (len > 1).then(|| String::from_utf16_lossy(&name[..len as usize - 1]))
+}
+
+/// Whether the keyboard layout of the foreground window types some characters with AltGr, i.e.
+/// ctrl+alt, and so makes Windows send `lctl` with `ralt`.
+#[cfg(target_os = "windows")]
+fn layout_has_altgr() -> bool {
+    use winapi::um::winuser::{
+        GetForegroundWindow, GetKeyboardLayout, GetWindowThreadProcessId, VkKeyScanExW,
+    };
+
+    const CTRL_ALT: i16 = 0x600;
+    let layout = unsafe {
+        GetKeyboardLayout(GetWindowThreadProcessId(
+            GetForegroundWindow(),
+            std::ptr::null_mut(),
+        ))
+    };
+    (0x21u16..0x180)
+        .chain([0x20AC]) // €
+        .any(|c| {
+            let scan = unsafe { VkKeyScanExW(c, layout) };
+            scan != -1 && scan & CTRL_ALT == CTRL_ALT
+        })
+}
+
+#[cfg(target_os = "macos")]
+fn detect() -> Option<String> {
+    use core_foundation::base::{CFRelease, CFTypeRef, TCFType};
+    use core_foundation::string::{CFString, CFStringRef};
+
+    #[link(name = "Carbon", kind = "framework")]
+    unsafe extern "C" {
+        static kTISPropertyInputSourceID: CFStringRef;
+        fn TISCopyCurrentKeyboardLayoutInputSource() -> CFTypeRef;
+        fn TISGetInputSourceProperty(source: CFTypeRef, key: CFStringRef) -> CFTypeRef;
+    }
+
+    unsafe {
+        let source = TISCopyCurrentKeyboardLayoutInputSource();
+        if source.is_null() {
+            return None;
+        }
+        let id = TISGetInputSourceProperty(source, kTISPropertyInputSourceID);
+        let id = (!id.is_null()).then(|| CFString::wrap_under_get_rule(id as CFStringRef));
+        let id = id.map(|id| id.to_string());
+        CFRelease(source);
+        // The IDs look like com.apple.keylayout.German.
+        id.map(|id| id.rsplit('.').next().unwrap_or(&id).to_owned())
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "windows",
+    target_os = "macos"
+)))]
+fn detect() -> Option<String> {
+    None
+}
+
+/// Switches the keyboard layout or input method of the OS for the `os-layout` action.
+pub(crate) fn switch_os_keyboard_layout(_kbd_out: &mut KbdOut, layout: &str) {
+    tracing::debug!("switching the OS keyboard layout to {layout}");
+    #[cfg(feature = "simulated_output")]
+    _kbd_out.write_os_layout(layout);
+    #[cfg(not(feature = "simulated_output"))]
+    if let Err(e) = switch(layout) {
+        tracing::error!("could not switch the OS keyboard layout to {layout}: {e}");
+    }
+}
+
+/// Runs `setxkbmap` with the layout and its optional variant, e.g. `de nodeadkeys`, or
+/// `ibus engine` for a layout beginning with `ibus:`. Both need access to the session of the
+/// display server.
+#[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    not(feature = "simulated_output")
+))]
+fn switch(layout: &str) -> std::io::Result<()> {
+    use std::process::{Command, Stdio};
+
+    let mut cmd = match layout.strip_prefix("ibus:") {
+        Some(engine) => {
+            let mut cmd = Command::new("ibus");
+            cmd.args(["engine", engine]);
+            cmd
+        }
+        None => {
+            let mut cmd = Command::new("setxkbmap");
+            cmd.args(layout.split_whitespace());
+            cmd
+        }
+    };
+    let child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let layout = layout.to_owned();
+    std::thread::spawn(move || match child.wait_with_output() {
+        Ok(output) if !output.status.success() => tracing::error!(
+            "could not switch the OS keyboard layout to {layout}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Ok(_) => {}
+        Err(e) => tracing::error!("could not switch the OS keyboard layout to {layout}: {e}"),
+    });
+    Ok(())
+}
+
+/// Loads the layout, which is a layout ID like `00000407` or a locale name like `de-DE`, and
+/// requests the foreground window to switch to it.
+#[cfg(all(target_os = "windows", not(feature = "simulated_output")))]
+fn switch(layout: &str) -> std::io::Result<()> {
+    use winapi::um::winnls::LocaleNameToLCID;
+    use winapi::um::winuser::{
+        GetForegroundWindow, KLF_ACTIVATE, LoadKeyboardLayoutW, PostMessageW,
+        WM_INPUTLANGCHANGEREQUEST,
+    };
+
+    let layout_id = if layout.len() == 8 && layout.chars().all(|c| c.is_ascii_hexdigit()) {
+        layout.to_owned()
+    } else {
+        let name: Vec<u16> = layout.encode_utf16().chain([0]).collect();
+        let language = unsafe { LocaleNameToLCID(name.as_ptr(), 0) };
+        if language == 0 {
+            return Err(std::io::Error::other("unknown locale name"));
+        }
+        format!("{:08X}", language & 0xffff)
+    };
+    let layout_id: Vec<u16> = layout_id.encode_utf16().chain([0]).collect();
+    unsafe {
+        let hkl = LoadKeyboardLayoutW(layout_id.as_ptr(), KLF_ACTIVATE);
+        if hkl.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        // ActivateKeyboardLayout only switches the layout of the calling thread.
+        PostMessageW(
+            GetForegroundWindow(),
+            WM_INPUTLANGCHANGEREQUEST,
+            0,
+            hkl as isize,
+        );
+    }
+    Ok(())
+}
+
+/// Selects the input source, which is a keyboard layout name like `German` or a full ID like
+/// `com.apple.inputmethod.Kotoeri.RomajiTyping.Japanese`.
+#[cfg(all(target_os = "macos", not(feature = "simulated_output")))]
+fn switch(layout: &str) -> std::io::Result<()> {
+    use core_foundation::array::{CFArrayGetCount, CFArrayGetValueAtIndex, CFArrayRef};
+    use core_foundation::base::{CFRelease, CFTypeRef, TCFType};
+    use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+    use core_foundation::string::{CFString, CFStringRef};
+
+    #[link(name = "Carbon", kind = "framework")]
+    unsafe extern "C" {
+        static kTISPropertyInputSourceID: CFStringRef;
+        fn TISCreateInputSourceList(properties: CFDictionaryRef, include_all: u8) -> CFArrayRef;
+        fn TISSelectInputSource(source: CFTypeRef) -> i32;
+    }
+
+    let id = if layout.contains('.') {
+        layout.to_owned()
+    } else {
+        format!("com.apple.keylayout.{layout}")
+    };
+    unsafe {
+        let key = CFString::wrap_under_get_rule(kTISPropertyInputSourceID);
+        let properties = CFDictionary::from_CFType_pairs(&[(key, CFString::new(&id))]);
+        let sources = TISCreateInputSourceList(properties.as_concrete_TypeRef(), 0);
+        if sources.is_null() {
+            return Err(std::io::Error::other(format!(
+                "no enabled input source {id}"
+            )));
+        }
+        let status = match CFArrayGetCount(sources) {
+            0 => None,
+            _ => Some(TISSelectInputSource(CFArrayGetValueAtIndex(sources, 0))),
+        };
+        CFRelease(sources as CFTypeRef);
+        match status {
+            None => Err(std::io::Error::other(format!(
+                "no enabled input source {id}"
+            ))),
+            Some(0) => Ok(()),
+            Some(status) => Err(std::io::Error::other(format!(
+                "selecting the input source failed with {status}"
+            ))),
+        }
+    }
+}
+
+#[cfg(all(
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "windows",
+        target_os = "macos"
+    )),
+    not(feature = "simulated_output")
+))]
+fn switch(_layout: &str) -> std::io::Result<()> {
+    Err(std::io::Error::other("not supported on this platform"))
+}
+
+impl Kanata {
+    /// Starts a thread that polls the keyboard layout of the foreground window. When it changes,
+    /// TCP clients are notified, the configuration is reloaded if its `deflocalkeys` depends on
+    /// the layout, and whether it has AltGr is updated for `windows-altgr layout-aware`.
+    #[cfg(target_os = "windows")]
+    pub fn start_os_layout_watcher(kanata: Arc<Mutex<Self>>, wakeup: EventSender) {
+        const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+        std::thread::spawn(move || {
+            let mut layout = cfg::os_keyboard_layout();
+            set_win_layout_has_altgr(layout_has_altgr());
+            loop {
+                std::thread::sleep(POLL_INTERVAL);
+                let new = detect();
+                if new.is_none() || new == layout {
+                    continue;
+                }
+                layout = new;
+                info!(
+                    "the OS keyboard layout changed to {}",
+                    layout.as_deref().unwrap_or_default()
+                );
+                cfg::set_os_keyboard_layout(layout.clone());
+                set_win_layout_has_altgr(layout_has_altgr());
+                let mut k = kanata.lock();
+                k.os_layout_changed = true;
+                if k.localkeys_for_os_layouts {
+                    k.request_live_reload();
+                }
+                drop(k);
+                let _ = wakeup.try_send(KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp));
+            }
+        });
+    }
+
+    /// Notifies TCP clients when the keyboard layout of the OS changes.
+    pub(crate) fn check_handle_os_layout_change(&mut self, _tx: &Option<Sender<ServerMessage>>) {
+        if !self.os_layout_changed {
+            return;
+        }
+        self.os_layout_changed = false;
+        #[cfg(feature = "tcp_server")]
+        if let Some(tx) = _tx {
+            let layout = cfg::os_keyboard_layout().unwrap_or_default();
+            match tx.try_send(ServerMessage::OsLayoutChange { layout }) {
+                Ok(_) => {}
+                Err(error) => {
+                    tracing::error!("could not send event notification: {}", error);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "android")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_default_keyboard_layout() {
+        let content = "XKBMODEL=\"pc105\"\nXKBLAYOUT=\"de,us\"\nXKBVARIANT=\"\"\n";
+        assert_eq!(default_keyboard_layout(content).as_deref(), Some("de"));
+        assert_eq!(
+            default_keyboard_layout("XKBLAYOUT=fr").as_deref(),
+            Some("fr")
+        );
+        assert_eq!(default_keyboard_layout("XKBLAYOUT=\"\""), None);
+    }
+}
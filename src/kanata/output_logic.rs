@@ -32,45 +32,90 @@ pub(crate) use zippychord::*;
 // that can be assumed to be used by devices still in production.
 pub(super) const KEY_IGNORE_MIN: u16 = 0x2a4; // KEY_MACRO21
 pub(super) const KEY_IGNORE_MAX: u16 = 0x2ad; // KEY_MACRO30
+/// Collects the key events of a tick to write them as one frame. Only uinput output supports this,
+/// the other outputs write every event immediately.
+pub(super) fn begin_burst(_kb: &mut KbdOut) {
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        not(feature = "simulated_output"),
+        not(feature = "passthru_ahk")
+    ))]
+    _kb.begin_burst();
+}
+
+/// Writes the key events collected since [`begin_burst`] and stops collecting.
+pub(super) fn end_burst(_kb: &mut KbdOut) -> Result<(), std::io::Error> {
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        not(feature = "simulated_output"),
+        not(feature = "passthru_ahk")
+    ))]
+    _kb.end_burst()?;
+    Ok(())
+}
+
+/// Writes the key events collected so far, e.g. before sleeping in the middle of a tick.
+pub(super) fn flush_burst(_kb: &mut KbdOut) -> Result<(), std::io::Error> {
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        not(feature = "simulated_output"),
+        not(feature = "passthru_ahk")
+    ))]
+    _kb.flush_burst()?;
+    Ok(())
+}
+
 pub(super) fn write_key(kb: &mut KbdOut, osc: OsCode, val: KeyValue) -> Result<(), std::io::Error> {
     match u16::from(osc) {
         KEY_IGNORE_MIN..=KEY_IGNORE_MAX => Ok(()),
-        _ => kb.write_key(osc, val),
+        _ => {
+            trace_output(osc, val);
+            let _watchdog = watchdog_output_write();
+            kb.write_key(osc, val)
+        }
     }
 }
 pub(super) fn press_key(kb: &mut KbdOut, osc: OsCode) -> Result<(), std::io::Error> {
     use OsCode::*;
     match u16::from(osc) {
         KEY_IGNORE_MIN..=KEY_IGNORE_MAX => Ok(()),
-        _ => match osc {
-            BTN_LEFT | BTN_RIGHT | BTN_MIDDLE | BTN_SIDE | BTN_EXTRA => {
-                let btn = osc_to_btn(osc);
-                kb.click_btn(btn)
+        _ => {
+            trace_output(osc, KeyValue::Press);
+            let _watchdog = watchdog_output_write();
+            match osc {
+                BTN_LEFT | BTN_RIGHT | BTN_MIDDLE | BTN_SIDE | BTN_EXTRA => {
+                    let btn = osc_to_btn(osc);
+                    kb.click_btn(btn)
+                }
+                MouseWheelUp | MouseWheelDown | MouseWheelLeft | MouseWheelRight => {
+                    let direction = osc_to_wheel_direction(osc);
+                    kb.scroll(direction, HI_RES_SCROLL_UNITS_IN_LO_RES)
+                }
+                _ => post_filter_press(kb, osc),
             }
-            MouseWheelUp | MouseWheelDown | MouseWheelLeft | MouseWheelRight => {
-                let direction = osc_to_wheel_direction(osc);
-                kb.scroll(direction, HI_RES_SCROLL_UNITS_IN_LO_RES)
-            }
-            _ => post_filter_press(kb, osc),
-        },
+        }
     }
 }
 pub(super) fn release_key(kb: &mut KbdOut, osc: OsCode) -> Result<(), std::io::Error> {
     use OsCode::*;
     match u16::from(osc) {
         KEY_IGNORE_MIN..=KEY_IGNORE_MAX => Ok(()),
-        _ => match osc {
-            BTN_LEFT | BTN_RIGHT | BTN_MIDDLE | BTN_SIDE | BTN_EXTRA => {
-                let btn = osc_to_btn(osc);
-                kb.release_btn(btn)
-            }
-            MouseWheelUp | MouseWheelDown | MouseWheelLeft | MouseWheelRight => {
-                // no-op: these are handled as scroll events in the press but scroll has no notion
-                // of release.
-                Ok(())
+        _ => {
+            trace_output(osc, KeyValue::Release);
+            let _watchdog = watchdog_output_write();
+            match osc {
+                BTN_LEFT | BTN_RIGHT | BTN_MIDDLE | BTN_SIDE | BTN_EXTRA => {
+                    let btn = osc_to_btn(osc);
+                    kb.release_btn(btn)
+                }
+                MouseWheelUp | MouseWheelDown | MouseWheelLeft | MouseWheelRight => {
+                    // no-op: these are handled as scroll events in the press but scroll has no
+                    // notion of release.
+                    Ok(())
+                }
+                _ => post_filter_release(kb, osc),
             }
-            _ => post_filter_release(kb, osc),
-        },
+        }
     }
 }
 fn osc_to_btn(osc: OsCode) -> Btn {
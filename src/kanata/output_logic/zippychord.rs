@@ -119,7 +119,7 @@ impl ZchDynamicState {
             ZchEnabledState::WaitEnable => {
                 self.zchd_ticks_until_enabled = self.zchd_ticks_until_enabled.saturating_sub(1);
                 if self.zchd_ticks_until_enabled == 0 {
-                    log::debug!("zippy wait enable->enable");
+                    tracing::debug!("zippy wait enable->enable");
                     self.zchd_enabled_state = ZchEnabledState::Enabled;
                     self.zchd_ticks_until_disable = 0;
                 }
@@ -130,7 +130,7 @@ impl ZchDynamicState {
                 if self.zchd_ticks_until_disable > 0 {
                     self.zchd_ticks_until_disable = self.zchd_ticks_until_disable.saturating_sub(1);
                     if self.zchd_ticks_until_disable == 0 {
-                        log::debug!("zippy enable->disable");
+                        tracing::debug!("zippy enable->disable");
                         self.zchd_soft_reset();
                     }
                 }
@@ -160,7 +160,7 @@ impl ZchDynamicState {
     /// Clean up the state, potentially causing inaccuracies with regards to what the user is
     /// currently still pressing.
     fn zchd_reset(&mut self) {
-        log::debug!("zchd reset state");
+        tracing::debug!("zchd reset state");
         self.zchd_soft_reset();
         self.zchd_is_caps_word_active = false;
         self.zchd_is_lsft_active = false;
@@ -171,7 +171,7 @@ impl ZchDynamicState {
     }
 
     fn zchd_soft_reset(&mut self) {
-        log::debug!("zchd soft reset state");
+        tracing::debug!("zchd soft reset state");
         self.zchd_last_press = ZchLastPressClassification::NotChord;
         self.zchd_enabled_state = ZchEnabledState::Disabled;
         self.zchd_input_keys.zchik_clear();
@@ -183,7 +183,7 @@ impl ZchDynamicState {
     }
 
     fn zchd_clear_history(&mut self) {
-        log::debug!("zchd clear historical data");
+        tracing::debug!("zchd clear historical data");
         self.zchd_characters_to_delete_on_next_activation = 0;
         self.zchd_prioritized_chords = None;
         self.zchd_prior_activation = None;
@@ -194,7 +194,7 @@ impl ZchDynamicState {
     fn zchd_is_idle(&self) -> bool {
         let is_idle = self.zchd_enabled_state == ZchEnabledState::Enabled
             && self.zchd_input_keys.zchik_is_empty();
-        log::trace!("zch is idle: {is_idle}");
+        tracing::trace!("zch is idle: {is_idle}");
         is_idle
     }
 
@@ -206,18 +206,18 @@ impl ZchDynamicState {
         self.zchd_input_keys.zchik_remove(osc);
         match (self.zchd_last_press, self.zchd_input_keys.zchik_is_empty()) {
             (ZchLastPressClassification::NotChord, true) => {
-                log::debug!("all released->zippy wait enable");
+                tracing::debug!("all released->zippy wait enable");
                 self.zchd_enabled_state = ZchEnabledState::WaitEnable;
                 self.zchd_clear_history();
             }
             (ZchLastPressClassification::NotChord, false) => {
-                log::debug!("release but not all->zippy disable");
+                tracing::debug!("release but not all->zippy disable");
                 self.zchd_soft_reset();
             }
             (ZchLastPressClassification::IsChord, true) => {
-                log::debug!("all released->zippy enabled");
+                tracing::debug!("all released->zippy enabled");
                 if self.zchd_prioritized_chords.is_none() {
-                    log::debug!("no continuation->zippy clear key erase state");
+                    tracing::debug!("no continuation->zippy clear key erase state");
                     self.zchd_clear_history();
                 }
                 self.zchd_characters_to_delete_on_next_activation = 0;
@@ -226,7 +226,7 @@ impl ZchDynamicState {
                 self.zchd_same_hold_activation_count = 0;
             }
             (ZchLastPressClassification::IsChord, false) => {
-                log::debug!("some released->zippy enabled");
+                tracing::debug!("some released->zippy enabled");
                 self.zchd_ticks_until_disable = 0;
             }
         }
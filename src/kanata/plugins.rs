@@ -0,0 +1,407 @@
+//! `--plugin-dir`: plugins are dynamic libraries that add actions to kanata, which configurations
+//! run with `(plugin <plugin> <action> <args>...)` once `defplugins` grants the plugin.
+//!
+//! A plugin exports two functions with the C ABI:
+//!
+//! - `kanata_plugin_abi_version`, which returns the [`PLUGIN_ABI_VERSION`] that the plugin was
+//!   built for. It is called first, so that plugins for another version are skipped before
+//!   anything of a different layout is read.
+//! - `kanata_plugin_init`, which returns the [`PluginInfo`] of the plugin.
+//!
+//! Actions run on the processing thread and must return quickly. They ask kanata to do things
+//! through the [`PluginHost`], which only does what `defplugins` grants the plugin. A plugin is
+//! native code that runs with the rights of kanata, so the capabilities limit what it can do
+//! through kanata, not what it can do by itself.
+
+use super::*;
+use anyhow::anyhow;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::path::Path;
+
+/// The version of [`PluginInfo`] and [`PluginHost`], which changes whenever either does.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Returned by the functions of [`PluginHost`] for a capability that is not granted.
+pub const PLUGIN_NOT_GRANTED: i32 = -1;
+/// Returned by the functions of [`PluginHost`] for an invalid argument, e.g. an unknown key.
+pub const PLUGIN_INVALID_ARGUMENT: i32 = -2;
+
+/// Runs the action with the arguments, and returns 0 on success.
+pub type PluginRunFn = unsafe extern "C" fn(
+    host: *const PluginHost,
+    action: *const c_char,
+    args: *const *const c_char,
+    arg_count: usize,
+) -> i32;
+
+/// What `kanata_plugin_init` returns. The strings are NUL-terminated UTF-8, and everything must
+/// stay valid while the plugin is loaded.
+#[repr(C)]
+pub struct PluginInfo {
+    /// The name that configurations use for the plugin.
+    pub name: *const c_char,
+    /// The names of the actions.
+    pub actions: *const *const c_char,
+    pub action_count: usize,
+    pub run: PluginRunFn,
+}
+
+/// What a plugin can ask kanata to do while one of its actions runs, only valid until the action
+/// returns. Kanata does what the action asks once it returns. The functions return 0 on success,
+/// [`PLUGIN_NOT_GRANTED`] or [`PLUGIN_INVALID_ARGUMENT`].
+#[repr(C)]
+pub struct PluginHost {
+    /// Passed to the functions.
+    pub ctx: *mut c_void,
+    /// Logs the message.
+    pub log: unsafe extern "C" fn(ctx: *mut c_void, message: *const c_char),
+    /// Presses the key with the name of `defsrc`, with the `output` capability.
+    pub press_key: unsafe extern "C" fn(ctx: *mut c_void, key: *const c_char) -> i32,
+    /// Releases the key with the name of `defsrc`, with the `output` capability.
+    pub release_key: unsafe extern "C" fn(ctx: *mut c_void, key: *const c_char) -> i32,
+    /// Switches the base layer to the layer with the name, with the `layer` capability.
+    pub set_layer: unsafe extern "C" fn(ctx: *mut c_void, layer: *const c_char) -> i32,
+}
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type InitFn = unsafe extern "C" fn() -> *const PluginInfo;
+
+/// What an action asked kanata to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PluginRequest {
+    Press(OsCode),
+    Release(OsCode),
+    Layer(usize),
+}
+
+struct LoadedPlugin {
+    name: String,
+    actions: Vec<String>,
+    run: PluginRunFn,
+    /// The library that `run` is in, which stays loaded until kanata exits.
+    _library: Option<libloading::Library>,
+}
+
+static PLUGINS: Mutex<Vec<LoadedPlugin>> = Mutex::new(Vec::new());
+
+/// Loads the plugins of the directory, and tells the parser about their actions. A plugin that
+/// cannot be loaded is logged and skipped.
+pub fn load_plugins(dir: &Path) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("could not read the plugin directory {}: {e}", dir.display());
+            return;
+        }
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == std::env::consts::DLL_EXTENSION)
+        })
+        .collect();
+    paths.sort();
+    for path in paths {
+        // SAFETY: loading a plugin runs its code, which the user chose by putting it in the
+        // directory.
+        match unsafe { load_plugin(&path) } {
+            Ok(name) => info!("loaded the plugin {name} from {}", path.display()),
+            Err(e) => error!("skipping the plugin {}: {e}", path.display()),
+        }
+    }
+    cfg::set_plugin_actions(
+        PLUGINS
+            .lock()
+            .iter()
+            .map(|plugin| (plugin.name.clone(), plugin.actions.clone()))
+            .collect(),
+    );
+}
+
+/// # Safety
+///
+/// Runs the initialization of the library and of the plugin.
+unsafe fn load_plugin(path: &Path) -> Result<String> {
+    let library = unsafe { libloading::Library::new(path) }?;
+    let plugin = {
+        let abi_version = unsafe { library.get::<AbiVersionFn>(b"kanata_plugin_abi_version\0") }
+            .map_err(|_| anyhow!("it has no kanata_plugin_abi_version, is it a kanata plugin?"))?;
+        let version = unsafe { abi_version() };
+        if version != PLUGIN_ABI_VERSION {
+            bail!(
+                "it is built for version {version} of the plugin ABI, \
+                 but this kanata has version {PLUGIN_ABI_VERSION}"
+            );
+        }
+        let init = unsafe { library.get::<InitFn>(b"kanata_plugin_init\0") }?;
+        unsafe { plugin_of_info(init()) }?
+    };
+    register_plugin(plugin, Some(library))
+}
+
+/// # Safety
+///
+/// `info` must be null or point to a valid [`PluginInfo`].
+unsafe fn plugin_of_info(info: *const PluginInfo) -> Result<LoadedPlugin> {
+    let Some(info) = (unsafe { info.as_ref() }) else {
+        bail!("kanata_plugin_init returned no plugin");
+    };
+    let name = unsafe { string_of(info.name) }?;
+    if name.is_empty() {
+        bail!("the plugin has no name");
+    }
+    let mut actions = vec![];
+    if info.action_count > 0 {
+        if info.actions.is_null() {
+            bail!("the plugin {name} has no list of actions");
+        }
+        for i in 0..info.action_count {
+            actions.push(unsafe { string_of(*info.actions.add(i)) }?);
+        }
+    }
+    Ok(LoadedPlugin {
+        name,
+        actions,
+        run: info.run,
+        _library: None,
+    })
+}
+
+fn register_plugin(
+    mut plugin: LoadedPlugin,
+    library: Option<libloading::Library>,
+) -> Result<String> {
+    let mut plugins = PLUGINS.lock();
+    if plugins.iter().any(|loaded| loaded.name == plugin.name) {
+        bail!("a plugin named {} is already loaded", plugin.name);
+    }
+    plugin._library = library;
+    let name = plugin.name.clone();
+    plugins.push(plugin);
+    Ok(name)
+}
+
+/// # Safety
+///
+/// `s` must be null or a NUL-terminated string.
+unsafe fn string_of(s: *const c_char) -> Result<String> {
+    if s.is_null() {
+        bail!("a string of the plugin is null");
+    }
+    let s = unsafe { CStr::from_ptr(s) };
+    Ok(s.to_str()
+        .map_err(|_| anyhow!("a string of the plugin is not UTF-8"))?
+        .to_owned())
+}
+
+/// The state of an action while it runs, behind [`PluginHost::ctx`].
+struct HostCtx<'a> {
+    action: &'a PluginAction,
+    layer_ids: &'a HashMap<String, usize>,
+    requests: Vec<PluginRequest>,
+}
+
+/// Runs the action of a plugin and returns what it asked kanata to do.
+pub(crate) fn run_plugin_action(
+    action: &PluginAction,
+    layer_ids: &HashMap<String, usize>,
+) -> Vec<PluginRequest> {
+    let run = PLUGINS
+        .lock()
+        .iter()
+        .find(|plugin| plugin.name == action.plugin)
+        .map(|plugin| plugin.run);
+    let Some(run) = run else {
+        error!("the plugin {} is not loaded", action.plugin);
+        return vec![];
+    };
+    let (Ok(name), Ok(args)) = (
+        CString::new(action.action),
+        action
+            .args
+            .iter()
+            .map(|arg| CString::new(*arg))
+            .collect::<std::result::Result<Vec<_>, _>>(),
+    ) else {
+        error!("plugin {}: the arguments cannot contain NUL", action.plugin);
+        return vec![];
+    };
+    let args: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+    let mut ctx = HostCtx {
+        action,
+        layer_ids,
+        requests: vec![],
+    };
+    let host = PluginHost {
+        ctx: &mut ctx as *mut HostCtx as *mut c_void,
+        log: host_log,
+        press_key: host_press_key,
+        release_key: host_release_key,
+        set_layer: host_set_layer,
+    };
+    tracing::debug!("plugin {}: running {}", action.plugin, action.action);
+    // SAFETY: the host and the strings outlive the call.
+    let result = unsafe { run(&host, name.as_ptr(), args.as_ptr(), args.len()) };
+    if result != 0 {
+        tracing::warn!(
+            "plugin {}: the action {} failed with {result}",
+            action.plugin,
+            action.action
+        );
+    }
+    ctx.requests
+}
+
+/// # Safety
+///
+/// `ctx` must be the [`HostCtx`] of a running action.
+unsafe fn host_ctx<'a>(ctx: *mut c_void) -> &'a mut HostCtx<'a> {
+    unsafe { &mut *(ctx as *mut HostCtx) }
+}
+
+unsafe extern "C" fn host_log(ctx: *mut c_void, message: *const c_char) {
+    let ctx = unsafe { host_ctx(ctx) };
+    match unsafe { string_of(message) } {
+        Ok(message) => info!("plugin {}: {message}", ctx.action.plugin),
+        Err(e) => tracing::warn!("plugin {}: {e}", ctx.action.plugin),
+    }
+}
+
+unsafe extern "C" fn host_press_key(ctx: *mut c_void, key: *const c_char) -> i32 {
+    unsafe { request_key(ctx, key, PluginRequest::Press) }
+}
+
+unsafe extern "C" fn host_release_key(ctx: *mut c_void, key: *const c_char) -> i32 {
+    unsafe { request_key(ctx, key, PluginRequest::Release) }
+}
+
+unsafe fn request_key(
+    ctx: *mut c_void,
+    key: *const c_char,
+    request: fn(OsCode) -> PluginRequest,
+) -> i32 {
+    let ctx = unsafe { host_ctx(ctx) };
+    if !ctx.action.capabilities.output {
+        tracing::warn!(
+            "plugin {}: cannot output keys without the output capability in defplugins",
+            ctx.action.plugin
+        );
+        return PLUGIN_NOT_GRANTED;
+    }
+    let Some(key) = unsafe { string_of(key) }
+        .ok()
+        .and_then(|key| str_to_oscode(&key))
+    else {
+        return PLUGIN_INVALID_ARGUMENT;
+    };
+    ctx.requests.push(request(key));
+    0
+}
+
+unsafe extern "C" fn host_set_layer(ctx: *mut c_void, layer: *const c_char) -> i32 {
+    let ctx = unsafe { host_ctx(ctx) };
+    if !ctx.action.capabilities.layer {
+        tracing::warn!(
+            "plugin {}: cannot change the layer without the layer capability in defplugins",
+            ctx.action.plugin
+        );
+        return PLUGIN_NOT_GRANTED;
+    }
+    let Some(layer) = unsafe { string_of(layer) }
+        .ok()
+        .and_then(|layer| ctx.layer_ids.get(&layer).copied())
+    else {
+        return PLUGIN_INVALID_ARGUMENT;
+    };
+    ctx.requests.push(PluginRequest::Layer(layer));
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Presses the key of the first argument and switches to the layer of the second one.
+    unsafe extern "C" fn run_test_action(
+        host: *const PluginHost,
+        action: *const c_char,
+        args: *const *const c_char,
+        arg_count: usize,
+    ) -> i32 {
+        unsafe {
+            let host = &*host;
+            (host.log)(host.ctx, action);
+            if arg_count != 2 {
+                return 1;
+            }
+            let pressed = (host.press_key)(host.ctx, *args);
+            let switched = (host.set_layer)(host.ctx, *args.add(1));
+            pressed.min(switched)
+        }
+    }
+
+    #[test]
+    fn plugin_actions_respect_capabilities() {
+        struct Actions([*const c_char; 1]);
+        // SAFETY: the pointers are to static strings.
+        unsafe impl Sync for Actions {}
+        static ACTIONS: Actions = Actions([c"type".as_ptr()]);
+        let info = PluginInfo {
+            name: c"test-plugin".as_ptr(),
+            actions: ACTIONS.0.as_ptr(),
+            action_count: 1,
+            run: run_test_action,
+        };
+        let plugin = unsafe { plugin_of_info(&info) }.unwrap();
+        assert_eq!(plugin.actions, ["type"]);
+        register_plugin(plugin, None).unwrap();
+        let plugin = unsafe { plugin_of_info(&info) }.unwrap();
+        assert!(register_plugin(plugin, None).is_err());
+
+        let mut action = PluginAction {
+            plugin: "test-plugin",
+            action: "type",
+            args: &["a", "nav"],
+            capabilities: PluginCapabilities {
+                output: true,
+                layer: true,
+            },
+        };
+        let layers: HashMap<String, usize> = [("base".to_owned(), 0), ("nav".to_owned(), 1)]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            run_plugin_action(&action, &layers),
+            [PluginRequest::Press(OsCode::KEY_A), PluginRequest::Layer(1)]
+        );
+        action.capabilities.layer = false;
+        assert_eq!(
+            run_plugin_action(&action, &layers),
+            [PluginRequest::Press(OsCode::KEY_A)]
+        );
+        action.capabilities.output = false;
+        action.args = &["nokey", "nav"];
+        assert!(run_plugin_action(&action, &layers).is_empty());
+    }
+
+    #[test]
+    fn invalid_plugins_are_rejected() {
+        assert!(unsafe { plugin_of_info(std::ptr::null()) }.is_err());
+        let info = PluginInfo {
+            name: c"".as_ptr(),
+            actions: std::ptr::null(),
+            action_count: 0,
+            run: run_test_action,
+        };
+        assert!(unsafe { plugin_of_info(&info) }.is_err());
+        let info = PluginInfo {
+            name: c"listless".as_ptr(),
+            actions: std::ptr::null(),
+            action_count: 1,
+            run: run_test_action,
+        };
+        assert!(unsafe { plugin_of_info(&info) }.is_err());
+        assert!(unsafe { load_plugin(Path::new("/nonexistent/plugin.so")) }.is_err());
+    }
+}
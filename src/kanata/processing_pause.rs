@@ -0,0 +1,123 @@
+//! The `toggle-processing` action, which pauses remapping while input devices stay grabbed.
+//!
+//! While paused, kanata outputs input events without changes, except for the key that paused
+//! processing; pressing it again resumes processing.
+
+use super::*;
+
+#[derive(Debug)]
+pub(crate) struct ProcessingPause {
+    /// The physical key that paused processing.
+    resume_key: OsCode,
+    /// Keys that were pressed while paused and output without changes. Releases of other keys
+    /// belong to presses from before the pause, so they are processed as usual.
+    passed_through: HashSet<OsCode>,
+}
+
+impl ProcessingPause {
+    pub(crate) fn new(resume_key: OsCode) -> Self {
+        Self {
+            resume_key,
+            passed_through: Default::default(),
+        }
+    }
+}
+
+/// What to do with an input event while processing is paused.
+enum PausedInput {
+    /// Process the event as usual.
+    Process,
+    /// The event was handled without processing.
+    Handled,
+    /// Resume processing and drop the event.
+    Resume,
+}
+
+impl Kanata {
+    /// Pauses processing until `resume_key` is pressed.
+    pub(crate) fn pause_processing(&mut self, resume_key: OsCode) {
+        tracing::info!("pausing processing until {resume_key} is pressed");
+        self.processing_pause = Some(ProcessingPause::new(resume_key));
+    }
+
+    /// Handles the event if processing is paused. Returns true if the event should not be
+    /// processed further.
+    pub(crate) fn handle_paused_input_event(&mut self, event: &KeyEvent) -> Result<bool> {
+        let Some(pause) = self.processing_pause.as_mut() else {
+            return Ok(false);
+        };
+        use PausedInput::*;
+        let action = match event.value {
+            KeyValue::Press if event.code == pause.resume_key => Resume,
+            KeyValue::Press => {
+                pause.passed_through.insert(event.code);
+                press_key(&mut self.kbd_out, event.code)?;
+                Handled
+            }
+            KeyValue::Release if pause.passed_through.remove(&event.code) => {
+                release_key(&mut self.kbd_out, event.code)?;
+                Handled
+            }
+            KeyValue::Release => Process,
+            KeyValue::Repeat if pause.passed_through.contains(&event.code) => {
+                write_key(&mut self.kbd_out, event.code, KeyValue::Repeat)?;
+                Handled
+            }
+            KeyValue::Repeat => Handled,
+            KeyValue::Tap => {
+                // Scrolls are the only physical input that produce taps.
+                if matches!(
+                    event.code,
+                    OsCode::MouseWheelUp
+                        | OsCode::MouseWheelDown
+                        | OsCode::MouseWheelLeft
+                        | OsCode::MouseWheelRight
+                ) {
+                    press_key(&mut self.kbd_out, event.code)?;
+                    release_key(&mut self.kbd_out, event.code)?;
+                }
+                Handled
+            }
+            KeyValue::WakeUp => Process,
+        };
+        match action {
+            Process => Ok(false),
+            Handled => Ok(true),
+            Resume => {
+                tracing::info!("resuming processing");
+                // Keys that are still held were output without changes. Release them now since
+                // their physical release will be processed as usual.
+                let passed_through = self
+                    .processing_pause
+                    .take()
+                    .map(|pause| pause.passed_through)
+                    .unwrap_or_default();
+                for osc in passed_through {
+                    release_key(&mut self.kbd_out, osc)?;
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Notifies TCP clients when processing is paused or resumed.
+    pub(crate) fn check_handle_processing_pause_change(
+        &mut self,
+        _tx: &Option<Sender<ServerMessage>>,
+    ) {
+        let paused = self.processing_pause.is_some();
+        if paused == self.prev_processing_paused {
+            return;
+        }
+        self.prev_processing_paused = paused;
+        #[cfg(feature = "tcp_server")]
+        if let Some(tx) = _tx {
+            match tx.try_send(ServerMessage::ProcessingPaused { paused }) {
+                Ok(_) => {}
+                Err(error) => {
+                    tracing::error!("could not send event notification: {}", error);
+                }
+            }
+        }
+    }
+}
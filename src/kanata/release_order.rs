@@ -0,0 +1,87 @@
+//! The `preserve-release-order` mode, which sends the releases of output keys in the order that
+//! their physical keys were released. Each output key belongs to the physical key of the layout
+//! state that pressed it, e.g. the tap-hold key for its tap or hold output.
+
+use super::*;
+
+#[derive(Default)]
+pub(super) struct ReleaseOrder {
+    /// Counts physical releases to order them.
+    release_count: u64,
+    /// When each physical key was released, for keys that were not pressed again since.
+    released: HashMap<OsCode, u64>,
+    /// The pressed output keys that belong to a physical key.
+    owners: Vec<(KeyCode, OsCode)>,
+    /// Output keys that were released before the layout released them.
+    released_early: Vec<KeyCode>,
+}
+
+impl ReleaseOrder {
+    pub(super) fn physical_press(&mut self, osc: OsCode) {
+        self.released.remove(&osc);
+    }
+
+    pub(super) fn physical_release(&mut self, osc: OsCode) {
+        self.release_count += 1;
+        self.released.insert(osc, self.release_count);
+    }
+
+    /// Records the physical key that the output key belongs to.
+    pub(super) fn output_press<T>(&mut self, key: KeyCode, states: &[State<T>]) {
+        let owner = states.iter().find_map(|state| match state {
+            State::NormalKey {
+                keycode,
+                coord: (NORMAL_KEY_ROW, y),
+                ..
+            } if *keycode == key => Some(OsCode::from(*y)),
+            _ => None,
+        });
+        if let Some(owner) = owner {
+            self.owners.push((key, owner));
+        }
+    }
+
+    fn release_time(&self, key: KeyCode) -> Option<u64> {
+        let (_, owner) = self.owners.iter().find(|(k, _)| *k == key)?;
+        self.released.get(owner).copied()
+    }
+
+    /// Returns the output keys to release, out of the keys that the layout released. The keys are
+    /// ordered by the releases of their physical keys, and held keys whose physical keys were
+    /// released before are released first.
+    pub(super) fn order_releases<'a>(
+        &mut self,
+        keys: impl Iterator<Item = &'a KeyCode>,
+        held: &[KeyCode],
+    ) -> Vec<KeyCode> {
+        let mut releases = vec![];
+        let mut timed_releases = vec![];
+        for key in keys {
+            if let Some(idx) = self.released_early.iter().position(|k| k == key) {
+                self.released_early.remove(idx);
+                continue;
+            }
+            match self.release_time(*key) {
+                Some(time) => timed_releases.push((time, *key)),
+                None => releases.push(*key),
+            }
+        }
+        if let Some(latest) = timed_releases.iter().map(|(time, _)| *time).max() {
+            for key in held.iter() {
+                if self.released_early.contains(key) || timed_releases.iter().any(|(_, k)| k == key)
+                {
+                    continue;
+                }
+                if let Some(time) = self.release_time(*key).filter(|time| *time < latest) {
+                    tracing::debug!("releasing {key:?} early to preserve the release order");
+                    timed_releases.push((time, *key));
+                    self.released_early.push(*key);
+                }
+            }
+        }
+        timed_releases.sort_by_key(|(time, _)| *time);
+        releases.extend(timed_releases.into_iter().map(|(_, key)| key));
+        self.owners.retain(|(key, _)| !releases.contains(key));
+        releases
+    }
+}
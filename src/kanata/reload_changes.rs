@@ -0,0 +1,157 @@
+//! The summary of what a live reload changed, which is logged and sent to TCP clients with
+//! `ConfigFileReload`, so that GUIs can update only what changed and users can check that the
+//! reload did what they expected.
+//!
+//! Layers are compared after parsing, so layers of included files count too. `defcfg` options
+//! and aliases are compared in the text of the main configuration file.
+
+use super::*;
+use kanata_parser::cfg::sexpr;
+use std::collections::BTreeMap;
+
+/// The `defcfg` options and aliases of a configuration file with the text of their values.
+#[derive(Default)]
+struct Definitions {
+    options: BTreeMap<String, String>,
+    aliases: BTreeMap<String, String>,
+}
+
+fn definitions(text: &str) -> Definitions {
+    let mut definitions = Definitions::default();
+    let Ok(items) = sexpr::parse(text, "") else {
+        return definitions;
+    };
+    for item in items {
+        let values = match item.t.first().and_then(|e| e.atom(None)) {
+            Some("defcfg") => &mut definitions.options,
+            Some("defalias") => &mut definitions.aliases,
+            _ => continue,
+        };
+        for pair in item.t[1..].chunks(2) {
+            if let [name, value] = pair
+                && let Some(name) = name.atom(None)
+            {
+                // The debug format of expressions doesn't depend on whitespace and comments.
+                values.insert(name.to_owned(), format!("{value:?}"));
+            }
+        }
+    }
+    definitions
+}
+
+fn changed_names(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<String> {
+    let mut names = old
+        .keys()
+        .chain(new.keys())
+        .filter(|name| old.get(*name) != new.get(*name))
+        .cloned()
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn layer_text(layer: &LayerInfo) -> String {
+    layer
+        .cfg_text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Kanata {
+    /// What replacing the current configuration with the configuration of the layers and of the
+    /// text of its file changes.
+    pub(super) fn reload_changes(
+        &self,
+        new_text: Option<&str>,
+        new_layers: &[LayerInfo],
+    ) -> ReloadChanges {
+        let mut changes = ReloadChanges::default();
+        for old in self.layer_info.iter() {
+            match new_layers.iter().find(|new| new.name == old.name) {
+                None => changes.removed_layers.push(old.name.clone()),
+                Some(new) if layer_text(new) != layer_text(old) => {
+                    changes.modified_layers.push(old.name.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        for new in new_layers {
+            if !self.layer_info.iter().any(|old| old.name == new.name) {
+                changes.added_layers.push(new.name.clone());
+            }
+        }
+        if let (Some((_, old_text)), Some(new_text)) = (&self.loaded_cfg, new_text) {
+            let (old, new) = (definitions(old_text), definitions(new_text));
+            changes.changed_options = changed_names(&old.options, &new.options);
+            changes.changed_aliases = changed_names(&old.aliases, &new.aliases);
+        }
+        changes
+    }
+}
+
+pub(super) fn log_reload_changes(changes: &ReloadChanges) {
+    let ReloadChanges {
+        added_layers,
+        removed_layers,
+        modified_layers,
+        changed_options,
+        changed_aliases,
+    } = changes;
+    for (names, what) in [
+        (added_layers, "added layers"),
+        (removed_layers, "removed layers"),
+        (modified_layers, "modified layers"),
+        (changed_options, "changed defcfg options"),
+        (changed_aliases, "changed aliases"),
+    ] {
+        if !names.is_empty() {
+            tracing::info!("reload {what}: {}", names.join(", "));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "simulated_output"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_changes_name_what_changed() {
+        let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let old = "
+(defcfg process-unmapped-keys yes)
+(defalias x a y b)
+(defsrc a b)
+(deflayer base @x @y)
+(deflayer nav a b)
+";
+        let new = "
+(defcfg process-unmapped-keys yes ;; unchanged
+  concurrent-tap-hold yes)
+(defalias x a y c)
+(defsrc a b)
+(deflayer base @x @y)
+(deflayer nav a   b)
+(deflayer num 1 2)
+";
+        let mut k = Kanata::new_from_str(old, Default::default()).expect("failed to parse cfg");
+        k.loaded_cfg = Some((0, old.to_owned()));
+        let cfg = cfg::new_from_str(new, Default::default()).expect("failed to parse cfg");
+        let changes = k.reload_changes(Some(new), &cfg.layer_info);
+        assert_eq!(
+            changes,
+            ReloadChanges {
+                added_layers: vec!["num".to_owned()],
+                removed_layers: vec![],
+                // The layer text is the same, the alias that it uses is reported separately.
+                modified_layers: vec![],
+                changed_options: vec!["concurrent-tap-hold".to_owned()],
+                changed_aliases: vec!["y".to_owned()],
+            }
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! The configurations that were loaded last, which the TCP `ReloadRollback` and `ReloadVersion`
+//! commands and the `lrld-version-prev` and `lrld-version-next` actions load again, so that a live
+//! reload of a bad edit can be undone.
+//!
+//! Like for trial reloads, a version is kept in memory as the text of its file, so it can be loaded
+//! even if the file is broken now; included files are read again from disk.
+
+use super::*;
+use std::collections::VecDeque;
+
+/// How many of the configurations that were loaded last are kept.
+const HISTORY_LEN: usize = 10;
+
+#[derive(Debug, Default)]
+pub(super) struct ReloadHistory {
+    /// The index and text of the configuration file of each version, from the oldest to the
+    /// newest.
+    versions: VecDeque<(usize, String)>,
+    /// The position of the loaded version in `versions`.
+    active: usize,
+    /// The position of the version that the requested live reload loads instead of a file.
+    requested: Option<usize>,
+}
+
+impl ReloadHistory {
+    pub(super) fn new(loaded_cfg: Option<&(usize, String)>) -> Self {
+        let mut history = Self::default();
+        if let Some((idx, text)) = loaded_cfg {
+            history.record(*idx, text);
+        }
+        history
+    }
+
+    /// Adds the configuration that was loaded as the newest version, unless it is the loaded
+    /// version already.
+    pub(super) fn record(&mut self, idx: usize, text: &str) {
+        if self
+            .versions
+            .get(self.active)
+            .is_some_and(|(i, t)| *i == idx && t == text)
+        {
+            return;
+        }
+        self.versions.push_back((idx, text.to_owned()));
+        if self.versions.len() > HISTORY_LEN {
+            self.versions.pop_front();
+            self.requested = self.requested.and_then(|pos| pos.checked_sub(1));
+        }
+        self.active = self.versions.len() - 1;
+    }
+
+    /// The number of a version for `ReloadVersion`, which counts back from the newest one.
+    fn number(&self, pos: usize) -> usize {
+        self.versions.len() - 1 - pos
+    }
+}
+
+impl Kanata {
+    /// Request a live reload of the version from before the loaded one.
+    pub fn request_reload_rollback(&mut self) -> Result<()> {
+        let history = &self.reload_history;
+        let pos = history.requested.unwrap_or(history.active);
+        if pos == 0 {
+            bail!("there is no kept configuration from before the loaded one");
+        }
+        self.request_reload_pos(pos - 1);
+        Ok(())
+    }
+
+    /// Request a live reload of the version `n` versions before the newest one.
+    pub fn request_reload_version(&mut self, n: usize) -> Result<()> {
+        let len = self.reload_history.versions.len();
+        if n >= len {
+            bail!("version {n} out of bounds: only {len} versions are kept");
+        }
+        self.request_reload_pos(len - 1 - n);
+        Ok(())
+    }
+
+    /// Request a live reload of the version after the loaded one, or before it for `older`.
+    /// Cycles to the other end of the kept versions.
+    pub fn request_reload_version_cycle(&mut self, older: bool) -> Result<()> {
+        let history = &self.reload_history;
+        let len = history.versions.len();
+        if len < 2 {
+            bail!("there is no other kept configuration to load");
+        }
+        let pos = history.requested.unwrap_or(history.active);
+        self.request_reload_pos(match older {
+            true => (pos + len - 1) % len,
+            false => (pos + 1) % len,
+        });
+        Ok(())
+    }
+
+    fn request_reload_pos(&mut self, pos: usize) {
+        self.live_reload_requested = true;
+        self.requested_trial_ms = None;
+        self.reload_history.requested = Some(pos);
+        tracing::info!(
+            "Requested live reload of version {} of the configuration",
+            self.reload_history.number(pos)
+        );
+    }
+
+    /// Called by the live reload, which loads the requested version if there is one.
+    pub(super) fn take_requested_version(&mut self) -> Option<usize> {
+        self.reload_history.requested.take()
+    }
+
+    /// Forgets the requested version, for a live reload of a file that is requested after it.
+    pub(super) fn cancel_requested_version(&mut self) {
+        self.reload_history.requested = None;
+    }
+
+    /// Adds the loaded configuration to the history.
+    pub(super) fn record_loaded_cfg(&mut self) {
+        if let Some((idx, text)) = &self.loaded_cfg {
+            self.reload_history.record(*idx, text);
+        }
+    }
+
+    /// Loads the version at the position in the history, which also ends a trial reload.
+    pub(super) fn load_cfg_version(
+        &mut self,
+        pos: usize,
+        tx: &Option<Sender<ServerMessage>>,
+    ) -> Result<()> {
+        let Some((idx, text)) = self.reload_history.versions.get(pos).cloned() else {
+            bail!("the requested version is no longer kept");
+        };
+        let path = self.cfg_paths[idx].clone();
+        let _span = tracing::info_span!(
+            "reload",
+            path = %path.display(),
+            version = self.reload_history.number(pos)
+        )
+        .entered();
+        let cfg = match parse_cfg_text(&text, &path) {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                tracing::error!("{e:?}");
+                #[cfg(feature = "tcp_server")]
+                {
+                    self.last_reload_ok = false;
+                }
+                bail!("failed to parse the kept configuration");
+            }
+        };
+        let changes = self.reload_changes(Some(&text), &cfg.layer_info);
+        self.cur_cfg_idx = idx;
+        self.loaded_cfg = Some((idx, text));
+        self.reload_trial = None;
+        self.reload_history.active = pos;
+        self.apply_live_reload(cfg, changes, tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "simulated_output")]
+    fn press_a(k: &mut Kanata) -> Vec<KeyCode> {
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Press))
+            .unwrap();
+        tick(k);
+        let keys = k.prev_keys.clone();
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Release))
+            .unwrap();
+        tick(k);
+        keys
+    }
+
+    #[cfg(feature = "simulated_output")]
+    fn tick(k: &mut Kanata) {
+        k.last_tick = web_time::Instant::now() - std::time::Duration::from_millis(1);
+        k.handle_time_ticks(&None).expect("tick should succeed");
+    }
+
+    #[test]
+    fn history_keeps_the_last_versions() {
+        let mut history = ReloadHistory::new(Some(&(0, "v0".to_owned())));
+        history.record(0, "v0");
+        assert_eq!(history.versions.len(), 1);
+        for i in 1..=HISTORY_LEN {
+            history.record(0, &format!("v{i}"));
+        }
+        assert_eq!(history.versions.len(), HISTORY_LEN);
+        assert_eq!(history.versions[0].1, "v1");
+        assert_eq!(history.active, HISTORY_LEN - 1);
+        history.requested = Some(0);
+        history.record(1, "other");
+        assert_eq!(history.requested, None);
+        assert_eq!(history.number(history.active), 0);
+    }
+
+    #[cfg(feature = "simulated_output")]
+    #[test]
+    fn reload_versions_survive_broken_files() {
+        let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let path = std::env::temp_dir().join(format!("kanata-history-{}.kbd", std::process::id()));
+        let cfg = "(defsrc a) (deflayer base a)";
+        std::fs::write(&path, cfg).unwrap();
+        let mut k = Kanata::new_from_str(cfg, Default::default()).expect("failed to parse cfg");
+        k.cfg_paths = vec![path.clone()];
+        assert!(k.request_reload_rollback().is_err());
+        k.do_live_reload(&None).unwrap();
+        std::fs::write(&path, "(defsrc a) (deflayer base b)").unwrap();
+        k.do_live_reload(&None).unwrap();
+        std::fs::write(&path, "(defsrc a) (deflayer base").unwrap();
+        assert!(k.do_live_reload(&None).is_err());
+        assert_eq!(press_a(&mut k), [KeyCode::B]);
+
+        k.request_reload_rollback().unwrap();
+        tick(&mut k);
+        assert_eq!(press_a(&mut k), [KeyCode::A]);
+        assert!(k.request_reload_rollback().is_err());
+        k.request_reload_version_cycle(true).unwrap();
+        tick(&mut k);
+        assert_eq!(press_a(&mut k), [KeyCode::B]);
+        assert!(k.request_reload_version(2).is_err());
+        k.request_reload_version(1).unwrap();
+        tick(&mut k);
+        assert_eq!(press_a(&mut k), [KeyCode::A]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
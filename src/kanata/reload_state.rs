@@ -0,0 +1,285 @@
+//! Keeps the runtime state that still applies after a live reload.
+//!
+//! The new configuration is parsed in full, but the layout state for the parts that did not change
+//! is moved into the new layout instead of being reset: the default layer, held keys and layers,
+//! active one-shot keys and the scale of macro delays. Recorded dynamic macros, saved clipboard content and caps-word live
+//! outside of the layout and are kept regardless.
+
+use super::*;
+use kanata_keyberon::layout::{KCoord, OneShotRetainableState};
+
+impl Kanata {
+    /// Moves the state of the current layout that `new` leaves unchanged into `new`. Returns the
+    /// physical keys that are still held in the new layout.
+    ///
+    /// Layers are matched by name, so reordering layers keeps their state. Physically held keys
+    /// keep their current output until they are released, even if their action changed, and held
+    /// layers stay active if the new configuration has a layer with the same name. The state of
+    /// virtual keys is kept only when their actions are the same on every layer.
+    pub(super) fn carry_over_layout_state(
+        &self,
+        new: &mut cfg::KanataLayout,
+        new_layer_info: &[LayerInfo],
+    ) -> Vec<OsCode> {
+        let new_layer = |old: usize| {
+            new_layer_info
+                .iter()
+                .position(|layer| layer.name == self.layer_info[old].name)
+        };
+        let old = self.layout.b();
+        let new = new.bm();
+        if let Some(layer) = new_layer(old.default_layer) {
+            new.set_default_layer(layer);
+        }
+        new.set_sequence_delay_percent(old.sequence_delay_percent());
+
+        let mut held = vec![];
+        let unchanged = |(row, col): (u8, u16)| {
+            let (row, col) = (usize::from(row), usize::from(col));
+            (row != usize::from(NORMAL_KEY_ROW) || old.src_keys[col] == new.src_keys[col])
+                && (0..self.layer_info.len()).all(|layer| {
+                    new_layer(layer).is_some_and(|new_layer| {
+                        old.layers.get(layer, row, col) == new.layers.get(new_layer, row, col)
+                    })
+                })
+        };
+        // A physical key is released by its own release event, whatever its new action is.
+        let kept = |coord: KCoord| coord.0 == NORMAL_KEY_ROW || unchanged(coord);
+        let states = old
+            .states
+            .iter()
+            .filter_map(|state| match *state {
+                State::NormalKey {
+                    keycode,
+                    coord,
+                    flags,
+                } if kept(coord) => Some(State::NormalKey {
+                    keycode,
+                    coord,
+                    flags,
+                }),
+                State::LayerModifier { value, coord } if kept(coord) => {
+                    Some(State::LayerModifier {
+                        value: new_layer(value)?,
+                        coord,
+                    })
+                }
+                State::NoOpInput { coord } if kept(coord) => Some(State::NoOpInput { coord }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for state in states {
+            if let Some(coord) = state.coord()
+                && coord.0 == NORMAL_KEY_ROW
+                && let Some(osc) = OsCode::from_u16(coord.1)
+            {
+                held.push(osc);
+            }
+            let _ = new.states.push(state);
+        }
+
+        // Active one-shot keys keep their timeout, and their held layers map by name like above.
+        if !old.oneshot.keys.is_empty() {
+            let retained = old
+                .oneshot
+                .state_to_retain_on_release
+                .iter()
+                .filter_map(|state| {
+                    Some(match *state {
+                        OneShotRetainableState::KeyCode { coord, kc } => {
+                            OneShotRetainableState::KeyCode { coord, kc }
+                        }
+                        OneShotRetainableState::Layer { coord, layer } => {
+                            OneShotRetainableState::Layer {
+                                coord,
+                                layer: u16::try_from(new_layer(usize::from(layer))?).ok()?,
+                            }
+                        }
+                    })
+                });
+            let oneshot = &mut new.oneshot;
+            oneshot.state_to_retain_on_release = retained.collect();
+            oneshot.keys = old.oneshot.keys.clone();
+            oneshot.released_keys = old.oneshot.released_keys.clone();
+            oneshot.other_pressed_keys = old.oneshot.other_pressed_keys.clone();
+            oneshot.timeout = old.oneshot.timeout;
+            oneshot.end_config = old.oneshot.end_config;
+        }
+        if !held.is_empty() || old.default_layer != 0 {
+            tracing::info!(
+                "kept the state of {} held keys, one-shot keys and the default layer across the reload",
+                held.len()
+            );
+        }
+        held
+    }
+}
+
+#[cfg(all(test, feature = "simulated_output"))]
+mod tests {
+    use super::*;
+
+    fn write_cfg(name: &str, cfg: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("kanata-{name}-{}.kbd", std::process::id()));
+        std::fs::write(&path, cfg).unwrap();
+        path
+    }
+
+    fn tick(k: &mut Kanata) {
+        k.last_tick = web_time::Instant::now() - std::time::Duration::from_millis(1);
+        k.handle_time_ticks(&None).expect("tick should succeed");
+    }
+
+    const CFG: &str = "
+(defsrc a b c)
+(deflayer base (layer-while-held nav) b (layer-switch other))
+(deflayer nav x y _)
+(deflayer other _ _ _)
+";
+
+    #[test]
+    fn reload_keeps_unchanged_layer_state() {
+        let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let path = write_cfg("reload-state", CFG);
+        let mut k = Kanata::new_from_str(CFG, Default::default()).expect("failed to parse cfg");
+        k.cfg_paths = vec![path.clone()];
+
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Press))
+            .unwrap();
+        tick(&mut k);
+        assert_eq!(k.layout.b().current_layer(), 1);
+
+        // Only the key on another layer changed, so the held layer stays active.
+        std::fs::write(&path, CFG.replace("x y", "x z")).unwrap();
+        k.do_live_reload(&None).unwrap();
+        assert_eq!(k.layout.b().current_layer(), 1);
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_B, KeyValue::Press))
+            .unwrap();
+        tick(&mut k);
+        assert_eq!(k.prev_keys, [KeyCode::Z]);
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_B, KeyValue::Release))
+            .unwrap();
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Release))
+            .unwrap();
+        tick(&mut k);
+        tick(&mut k);
+        assert_eq!(k.layout.b().current_layer(), 0);
+
+        // The default layer is kept by name even when the layers are reordered.
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_C, KeyValue::Press))
+            .unwrap();
+        tick(&mut k);
+        assert_eq!(k.layout.b().default_layer, 2);
+        std::fs::write(
+            &path,
+            "
+(defsrc a b c)
+(deflayer base (layer-while-held nav) b (layer-switch other))
+(deflayer other _ _ _)
+(deflayer nav x y _)
+",
+        )
+        .unwrap();
+        k.do_live_reload(&None).unwrap();
+        assert_eq!(k.layout.b().default_layer, 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reload_keeps_held_keys_that_changed_until_release() {
+        let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let path = write_cfg("reload-state-changed", CFG);
+        let mut k = Kanata::new_from_str(CFG, Default::default()).expect("failed to parse cfg");
+        k.cfg_paths = vec![path.clone()];
+
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_B, KeyValue::Press))
+            .unwrap();
+        tick(&mut k);
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Press))
+            .unwrap();
+        tick(&mut k);
+        // Both held keys changed and a layer was added, but they keep working until released.
+        std::fs::write(
+            &path,
+            CFG.replace("(layer-while-held nav) b", "a d") + "(deflayer extra _ _ _)",
+        )
+        .unwrap();
+        k.do_live_reload(&None).unwrap();
+        tick(&mut k);
+        assert_eq!(k.layout.b().current_layer(), 1);
+        assert_eq!(k.prev_keys, [KeyCode::B]);
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_B, KeyValue::Release))
+            .unwrap();
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Release))
+            .unwrap();
+        tick(&mut k);
+        tick(&mut k);
+        assert_eq!(k.layout.b().current_layer(), 0);
+        assert!(k.prev_keys.is_empty());
+        assert!(k.layout.b().states.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reload_drops_held_layers_that_were_removed() {
+        let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let path = write_cfg("reload-state-removed", CFG);
+        let mut k = Kanata::new_from_str(CFG, Default::default()).expect("failed to parse cfg");
+        k.cfg_paths = vec![path.clone()];
+
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Press))
+            .unwrap();
+        tick(&mut k);
+        std::fs::write(
+            &path,
+            "(defsrc a b c) (deflayer base a b (layer-switch other)) (deflayer other _ _ _)",
+        )
+        .unwrap();
+        k.do_live_reload(&None).unwrap();
+        assert_eq!(k.layout.b().current_layer(), 0);
+        assert!(k.layout.b().states.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reload_keeps_active_one_shot_keys() {
+        let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let cfg = "(defsrc a b c) (deflayer base (one-shot 2000 lsft) b c)";
+        let path = write_cfg("reload-state-one-shot", cfg);
+        let mut k = Kanata::new_from_str(cfg, Default::default()).expect("failed to parse cfg");
+        k.cfg_paths = vec![path.clone()];
+
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Press))
+            .unwrap();
+        tick(&mut k);
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Release))
+            .unwrap();
+        tick(&mut k);
+        std::fs::write(&path, cfg.replace("b c)", "b d)")).unwrap();
+        k.do_live_reload(&None).unwrap();
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_B, KeyValue::Press))
+            .unwrap();
+        tick(&mut k);
+        assert_eq!(k.prev_keys, [KeyCode::LShift, KeyCode::B]);
+        // The one-shot ends after the press, like it would have without the reload.
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_B, KeyValue::Release))
+            .unwrap();
+        for _ in 0..10 {
+            tick(&mut k);
+        }
+        assert!(k.prev_keys.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
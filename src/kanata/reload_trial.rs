@@ -0,0 +1,222 @@
+//! Trial reloads with the TCP `ReloadTry` command: the configuration from before is restored
+//! unless the new one is confirmed with `ConfirmReload` in time, like `netplan try`, so that a
+//! configuration that makes the keyboard unusable goes away by itself.
+//!
+//! The configuration from before is kept as the text of its file, which is parsed again on
+//! rollback; included files are read again from disk.
+
+use super::*;
+use std::path::Path;
+
+const DEFAULT_CONFIRM_TIMEOUT_MS: u64 = 30000;
+
+/// A reload from `ReloadTry` that was not confirmed yet.
+pub(super) struct ReloadTrial {
+    /// The index of the configuration file from before the trial.
+    previous_idx: usize,
+    /// The text of the configuration file from before the trial.
+    previous_text: String,
+    /// The time left to confirm the trial.
+    remaining_ms: u64,
+}
+
+/// Parses the configuration file and returns its text too, which a trial reload can roll back to.
+pub(super) fn parse_cfg_file(path: &Path) -> (Option<String>, MResult<Cfg>) {
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    if let Some(user) = user_session::user_of_cfg(path) {
+        return match user_session::read_user_file(path, &user) {
+            Ok(text) => {
+                let cfg = parse_cfg_text(&text, path);
+                (Some(text), cfg)
+            }
+            Err(e) => (None, Err(miette::miette!("{e}"))),
+        };
+    }
+    match std::fs::read_to_string(path) {
+        Ok(text) => {
+            let cfg = parse_cfg_text(&text, path);
+            (Some(text), cfg)
+        }
+        // Parsing the file reports why it can't be read.
+        Err(_) => (None, cfg::new_from_file(path)),
+    }
+}
+
+/// Parses the text of the configuration file at the path. The files that the configuration of a
+/// user includes are read only if they belong to that user.
+pub(super) fn parse_cfg_text(text: &str, path: &Path) -> MResult<Cfg> {
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    if let Some(user) = user_session::user_of_cfg(path) {
+        return cfg::new_from_str_at_path_with(text, path, &mut |file| {
+            user_session::read_user_file(file, &user)
+        });
+    }
+    cfg::new_from_str_at_path(text, path)
+}
+
+impl Kanata {
+    /// Request a live reload of the current configuration file that is rolled back unless it is
+    /// confirmed within the timeout.
+    pub fn request_live_reload_try(&mut self, confirm_timeout_ms: Option<u64>) -> Result<()> {
+        if self.loaded_cfg.is_none() && self.reload_trial.is_none() {
+            bail!("the current configuration was not loaded from a file and can't be restored");
+        }
+        self.cancel_requested_version();
+        self.live_reload_requested = true;
+        self.requested_trial_ms = Some(confirm_timeout_ms.unwrap_or(DEFAULT_CONFIRM_TIMEOUT_MS));
+        tracing::info!("Requested trial live reload");
+        Ok(())
+    }
+
+    /// Keeps the configuration of the trial reload.
+    pub fn confirm_reload(&mut self) -> Result<()> {
+        if self.reload_trial.take().is_none() {
+            bail!("there is no trial reload to confirm");
+        }
+        tracing::info!("trial reload confirmed");
+        Ok(())
+    }
+
+    /// Called after the configuration was reloaded, with the configuration file and its text from
+    /// before. A trial started again keeps the configuration from before the first trial.
+    pub(super) fn start_reload_trial(&mut self, previous: Option<(usize, String)>) {
+        let Some(confirm_timeout_ms) = self.requested_trial_ms.take() else {
+            // Reloading without a trial keeps the configuration of an unconfirmed trial.
+            self.reload_trial = None;
+            return;
+        };
+        let trial = match (self.reload_trial.take(), previous) {
+            (Some(trial), _) => trial,
+            (None, Some((previous_idx, previous_text))) => ReloadTrial {
+                previous_idx,
+                previous_text,
+                remaining_ms: 0,
+            },
+            (None, None) => {
+                tracing::warn!(
+                    "the configuration from before can't be restored, keeping the reload"
+                );
+                return;
+            }
+        };
+        tracing::info!(
+            "trial reload, rolling back unless confirmed within {confirm_timeout_ms} ms"
+        );
+        self.reload_trial = Some(ReloadTrial {
+            remaining_ms: confirm_timeout_ms,
+            ..trial
+        });
+    }
+
+    /// Rolls back the trial reload once its timeout is over.
+    pub(super) fn tick_reload_trial(
+        &mut self,
+        ms_elapsed: u128,
+        tx: &Option<Sender<ServerMessage>>,
+    ) {
+        let Some(trial) = self.reload_trial.as_mut() else {
+            return;
+        };
+        trial.remaining_ms = trial
+            .remaining_ms
+            .saturating_sub(u64::try_from(ms_elapsed).unwrap_or(u64::MAX));
+        if trial.remaining_ms == 0 {
+            self.roll_back_reload("the reload was not confirmed in time", tx);
+        }
+    }
+
+    /// Restores the configuration from before the trial reload.
+    pub(super) fn roll_back_reload(&mut self, reason: &str, tx: &Option<Sender<ServerMessage>>) {
+        let Some(trial) = self.reload_trial.take() else {
+            return;
+        };
+        tracing::warn!("rolling back the trial reload: {reason}");
+        self.requested_trial_ms = None;
+        self.cur_cfg_idx = trial.previous_idx;
+        let path = self.cfg_paths[self.cur_cfg_idx].clone();
+        let cfg = match parse_cfg_text(&trial.previous_text, &path) {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                tracing::error!("could not restore the configuration from before: {e:?}");
+                return;
+            }
+        };
+        let changes = self.reload_changes(Some(&trial.previous_text), &cfg.layer_info);
+        self.loaded_cfg = Some((trial.previous_idx, trial.previous_text));
+        match self.apply_live_reload(cfg, changes, tx) {
+            Ok(()) => {
+                self.record_loaded_cfg();
+                notify_rolled_back(reason, tx);
+            }
+            Err(e) => tracing::error!("could not restore the configuration from before: {e}"),
+        }
+    }
+}
+
+fn notify_rolled_back(_reason: &str, _tx: &Option<Sender<ServerMessage>>) {
+    #[cfg(feature = "tcp_server")]
+    if let Some(tx) = _tx {
+        let msg = ServerMessage::ReloadRolledBack {
+            reason: _reason.to_owned(),
+        };
+        if let Err(error) = tx.try_send(msg) {
+            tracing::error!("could not send ReloadRolledBack event notification: {error}");
+        }
+    }
+}
+
+#[cfg(all(test, feature = "simulated_output"))]
+mod tests {
+    use super::*;
+
+    fn press_a(k: &mut Kanata) -> Vec<KeyCode> {
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Press))
+            .unwrap();
+        tick(k);
+        let keys = k.prev_keys.clone();
+        k.handle_input_event(&KeyEvent::new(OsCode::KEY_A, KeyValue::Release))
+            .unwrap();
+        tick(k);
+        keys
+    }
+
+    fn tick(k: &mut Kanata) {
+        k.last_tick = web_time::Instant::now() - std::time::Duration::from_millis(1);
+        k.handle_time_ticks(&None).expect("tick should succeed");
+    }
+
+    #[test]
+    fn trial_reload_rolls_back_unless_confirmed() {
+        let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let path = std::env::temp_dir().join(format!("kanata-trial-{}.kbd", std::process::id()));
+        let cfg = "(defsrc a) (deflayer base a)";
+        std::fs::write(&path, cfg).unwrap();
+        let mut k = Kanata::new_from_str(cfg, Default::default()).expect("failed to parse cfg");
+        k.cfg_paths = vec![path.clone()];
+        assert!(k.request_live_reload_try(None).is_err());
+        k.do_live_reload(&None).unwrap();
+        assert!(k.confirm_reload().is_err());
+
+        std::fs::write(&path, "(defsrc a) (deflayer base b)").unwrap();
+        k.request_live_reload_try(Some(5)).unwrap();
+        tick(&mut k);
+        assert_eq!(press_a(&mut k), [KeyCode::B]);
+        for _ in 0..5 {
+            tick(&mut k);
+        }
+        assert!(k.reload_trial.is_none());
+        assert_eq!(press_a(&mut k), [KeyCode::A]);
+
+        k.request_live_reload_try(Some(5)).unwrap();
+        tick(&mut k);
+        k.confirm_reload().unwrap();
+        for _ in 0..5 {
+            tick(&mut k);
+        }
+        assert_eq!(press_a(&mut k), [KeyCode::B]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
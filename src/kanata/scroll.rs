@@ -6,6 +6,68 @@ pub struct ScrollState {
     pub ticks_until_scroll: u16,
     pub distance: u16,
     pub scroll_accel_state: Option<ScrollAccelState>,
+    pub linear_accel_state: Option<ScrollLinearAccelState>,
+    /// Spread the distance of each activation over the interval.
+    pub smooth: bool,
+    /// The distance of the current activation that smooth scrolling has yet to send.
+    pub smooth_remaining: u16,
+    pub smooth_ticks_left: u16,
+}
+
+impl ScrollState {
+    pub(crate) fn new(
+        direction: MWheelDirection,
+        interval: u16,
+        distance: u16,
+        scroll_accel_state: Option<ScrollAccelState>,
+        accel: Option<&MWheelAccel>,
+        smooth: bool,
+    ) -> Self {
+        Self {
+            direction,
+            interval,
+            ticks_until_scroll: 0,
+            distance,
+            scroll_accel_state,
+            linear_accel_state: accel.map(|accel| ScrollLinearAccelState {
+                min_distance: distance,
+                max_distance: accel.max_distance,
+                accel_ticks: accel.accel_time,
+                held_ticks: 0,
+            }),
+            smooth,
+            smooth_remaining: 0,
+            smooth_ticks_left: 0,
+        }
+    }
+
+    /// Sends an even share of what is left of the activation's distance.
+    fn smooth_step(&mut self) -> Option<(MWheelDirection, u16)> {
+        if self.smooth_ticks_left == 0 {
+            return None;
+        }
+        let step = (self.smooth_remaining + self.smooth_ticks_left / 2) / self.smooth_ticks_left;
+        self.smooth_remaining -= step;
+        self.smooth_ticks_left -= 1;
+        (step > 0).then_some((self.direction, step))
+    }
+}
+
+/// The distance per activation grows linearly while the scroll action is held.
+pub struct ScrollLinearAccelState {
+    pub min_distance: u16,
+    pub max_distance: u16,
+    pub accel_ticks: u16,
+    pub held_ticks: u16,
+}
+
+impl ScrollLinearAccelState {
+    fn tick(&mut self) -> u16 {
+        self.held_ticks = self.accel_ticks.min(self.held_ticks + 1);
+        let range = u32::from(self.max_distance - self.min_distance);
+        let increase = range * u32::from(self.held_ticks) / u32::from(self.accel_ticks);
+        self.min_distance + increase as u16
+    }
 }
 
 pub struct ScrollAccelState {
@@ -22,12 +84,18 @@ pub(crate) fn update_scrollstate_get_result(
     let Some(state) = state else {
         return None;
     };
+    // A distance of 0 means that the action was released.
+    if state.distance != 0
+        && let Some(accel) = &mut state.linear_accel_state
+    {
+        state.distance = accel.tick();
+    }
     if state.ticks_until_scroll == 0 {
         state.ticks_until_scroll = state.interval - 1;
         let direction = state.direction;
         let distance = state.distance;
 
-        Some(match &mut state.scroll_accel_state {
+        let result = match &mut state.scroll_accel_state {
             Some(acs) => match acs.scroll_released {
                 false => {
                     let new_velocity = f32::min(
@@ -47,9 +115,18 @@ pub(crate) fn update_scrollstate_get_result(
                 }
             },
             None => (direction, distance),
-        })
+        };
+        if !state.smooth || result.1 == 0 {
+            return Some(result);
+        }
+        state.smooth_remaining = result.1;
+        state.smooth_ticks_left = state.interval;
+        state.smooth_step()
     } else {
         state.ticks_until_scroll -= 1;
-        None
+        match state.smooth {
+            true => state.smooth_step(),
+            false => None,
+        }
     }
 }
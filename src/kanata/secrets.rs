@@ -0,0 +1,207 @@
+//! The secrets of `defsecrets` and the `secret-type` action, which types a secret. A secret is
+//! read each time it is used, from the credential store of the OS or from a file, so that kanata
+//! does not keep it, and it is never logged.
+
+use super::*;
+use std::path::Path;
+
+/// The service name or prefix of the secrets in the credential store.
+const SERVICE: &str = "kanata";
+
+pub(crate) fn type_secret(kbd_out: &mut KbdOut, secret: &Secret) -> Result<()> {
+    let name = &secret.name;
+    tracing::debug!("typing the secret {name}");
+    #[cfg(feature = "simulated_output")]
+    kbd_out.write_secret(name);
+    #[cfg(not(feature = "simulated_output"))]
+    match read_secret(secret) {
+        Ok(secret) if secret.is_empty() => tracing::warn!("the secret {name} is empty"),
+        Ok(secret) => kbd_out.send_unicode_str(&secret)?,
+        Err(e) => tracing::error!("{e}"),
+    }
+    Ok(())
+}
+
+/// Reads the secret from where `defsecrets` says it is. The error names the secret.
+#[cfg_attr(feature = "simulated_output", allow(dead_code))]
+pub(crate) fn read_secret(secret: &Secret) -> std::io::Result<String> {
+    match &secret.source {
+        SecretSource::CredentialStore => read_credential(&secret.name),
+        SecretSource::File(path) => read_secret_file(path),
+    }
+    .map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("could not read the secret {}: {e}", secret.name),
+        )
+    })
+}
+
+/// Reads the file without its final line break, refusing it like [`check_secret_file`].
+fn read_secret_file(path: &Path) -> std::io::Result<String> {
+    let with_path =
+        |e: std::io::Error| std::io::Error::new(e.kind(), format!("{}: {e}", path.display()));
+    check_secret_file(path)?;
+    let mut secret = std::fs::read_to_string(path).map_err(with_path)?;
+    if secret.ends_with('\n') {
+        secret.pop();
+        if secret.ends_with('\r') {
+            secret.pop();
+        }
+    }
+    Ok(secret)
+}
+
+/// Fails for a file with a secret, e.g. a secret of `defsecrets` or the token of a listener, if
+/// other users can read it or if it belongs to another user than the one of kanata or root.
+pub(crate) fn check_secret_file(path: &Path) -> std::io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        // SAFETY: plain libc call with no args.
+        let euid = unsafe { libc::geteuid() };
+        if let Some(e) = secret_file_error(metadata.mode(), metadata.uid(), euid) {
+            return Err(std::io::Error::other(format!("{} {e}", path.display())));
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+    let _ = path;
+    Ok(())
+}
+
+/// Why a secret file with the mode and owner must not be read by a process of `euid`. The owner of
+/// the file could replace the secret, so it must be the user of kanata or root.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn secret_file_error(mode: u32, owner: u32, euid: u32) -> Option<&'static str> {
+    if owner != euid && owner != 0 {
+        return Some("belongs to another user, change its owner with chown");
+    }
+    if mode & 0o077 != 0 {
+        return Some("can be read by other users, restrict it with chmod 600");
+    }
+    None
+}
+
+/// Runs a program that prints the secret to stdout.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn secret_from_program(cmd: &mut std::process::Command) -> std::io::Result<String> {
+    let output = cmd.stdin(std::process::Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "{} failed: {}",
+            cmd.get_program().to_string_lossy(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let mut secret = String::from_utf8(output.stdout)
+        .map_err(|_| std::io::Error::other("the secret is not valid UTF-8"))?;
+    if secret.ends_with('\n') {
+        secret.pop();
+    }
+    Ok(secret)
+}
+
+/// Looks up the Secret Service item with the attribute `kanata` set to the name, e.g. stored with
+/// `secret-tool store --label=... kanata <name>`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn read_credential(name: &str) -> std::io::Result<String> {
+    secret_from_program(std::process::Command::new("secret-tool").args(["lookup", SERVICE, name]))
+}
+
+/// Looks up the generic password of the service `kanata` with the name as its account, e.g.
+/// stored with `security add-generic-password -s kanata -a <name> -w`.
+#[cfg(target_os = "macos")]
+pub(crate) fn read_credential(name: &str) -> std::io::Result<String> {
+    secret_from_program(std::process::Command::new("security").args([
+        "find-generic-password",
+        "-s",
+        SERVICE,
+        "-a",
+        name,
+        "-w",
+    ]))
+}
+
+/// Reads the generic credential with the target `kanata:<name>`, e.g. stored with
+/// `cmdkey /generic:kanata:<name> /user:kanata /pass`.
+#[cfg(target_os = "windows")]
+pub(crate) fn read_credential(name: &str) -> std::io::Result<String> {
+    use winapi::um::wincred::{CRED_TYPE_GENERIC, CredFree, CredReadW, PCREDENTIALW};
+
+    let target: Vec<u16> = format!("{SERVICE}:{name}")
+        .encode_utf16()
+        .chain([0])
+        .collect();
+    let mut credential: PCREDENTIALW = std::ptr::null_mut();
+    unsafe {
+        if CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let (blob, blob_size) = (
+            (*credential).CredentialBlob,
+            (*credential).CredentialBlobSize as usize,
+        );
+        // The passwords of cmdkey and the Credential Manager are stored as UTF-16.
+        let secret = match blob.is_null() {
+            true => String::new(),
+            false => {
+                let units = std::slice::from_raw_parts(blob, blob_size)
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .collect::<Vec<_>>();
+                String::from_utf16_lossy(&units)
+            }
+        };
+        CredFree(credential as _);
+        Ok(secret)
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "windows",
+    target_os = "macos"
+)))]
+pub(crate) fn read_credential(_name: &str) -> std::io::Result<String> {
+    Err(std::io::Error::other("not supported on this platform"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn secret_files_must_be_private() {
+        let path = std::env::temp_dir().join(format!("kanata-secret-{}", std::process::id()));
+        std::fs::write(&path, "hunter2\n").unwrap();
+        let secret = Secret {
+            name: "test".into(),
+            source: SecretSource::File(path.clone()),
+        };
+        std::fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+        let err = read_secret(&secret).unwrap_err().to_string();
+        assert!(err.starts_with("could not read the secret test: "), "{err}");
+        assert!(err.ends_with("restrict it with chmod 600"), "{err}");
+        std::fs::set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(read_secret(&secret).unwrap(), "hunter2");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+    #[test]
+    fn secret_files_must_belong_to_the_user_or_root() {
+        assert_eq!(secret_file_error(0o100600, 1000, 1000), None);
+        assert_eq!(secret_file_error(0o100600, 0, 1000), None);
+        let err = secret_file_error(0o100600, 1001, 1000).unwrap();
+        assert!(err.contains("belongs to another user"), "{err}");
+        let err = secret_file_error(0o100600, 1000, 0).unwrap();
+        assert!(err.contains("belongs to another user"), "{err}");
+        let err = secret_file_error(0o100640, 1000, 1000).unwrap();
+        assert!(err.contains("chmod 600"), "{err}");
+    }
+}
@@ -0,0 +1,124 @@
+//! The `SequenceMenu` message, which tells TCP clients which keys continue the active sequence,
+//! e.g. for a which-key style menu that opens when a leader key is pressed. It is sent when the
+//! sequence starts, after every key typed into it and when it ends.
+
+use super::*;
+
+use kanata_tcp_protocol::SequenceMenuEntry;
+use std::collections::BTreeMap;
+
+/// Returns the name of a key of a sequence, with prefixes for the modifiers that are held for
+/// it, or None for the marker that ends overlapping keys.
+fn sequence_key_label(key: u16) -> Option<String> {
+    if key == KEY_OVERLAP_MARKER {
+        return None;
+    }
+    let mut label = String::new();
+    for (mask, prefix) in [
+        (0x4000, "C-"),
+        (0x8000, "S-"),
+        (0x2000, "A-"),
+        (0x1000, "RA-"),
+        (0x0800, "M-"),
+        (KEY_OVERLAP_MARKER, "O-"),
+    ] {
+        if key & mask != 0 {
+            label.push_str(prefix);
+        }
+    }
+    let osc = OsCode::from(key & MASK_KEYCODES);
+    match oscode_to_str(osc) {
+        Some(name) => label.push_str(name),
+        None => label.push_str(&osc.to_string()),
+    }
+    Some(label)
+}
+
+impl Kanata {
+    /// Sends `SequenceMenu` to TCP clients if a sequence started, continued or ended since the
+    /// last tick.
+    pub(crate) fn check_handle_sequence_menu(&mut self, _tx: &Option<Sender<ServerMessage>>) {
+        if self.sequences.is_empty() {
+            return;
+        }
+        let sequence = self
+            .sequence_state
+            .is_active()
+            .then_some(&self.sequence_state.sequence);
+        if self.sequence_menu_shown.as_ref() == sequence {
+            return;
+        }
+        self.sequence_menu_shown = sequence.cloned();
+        let _msg = self.sequence_menu();
+        #[cfg(feature = "tcp_server")]
+        if let Some(tx) = _tx
+            && let Err(error) = tx.try_send(_msg)
+        {
+            tracing::error!("could not send event notification: {}", error);
+        }
+    }
+
+    fn sequence_menu(&self) -> ServerMessage {
+        let Some(typed) = &self.sequence_menu_shown else {
+            return ServerMessage::SequenceMenu {
+                active: false,
+                typed: vec![],
+                next: vec![],
+            };
+        };
+        let layout = self.layout.b();
+        let layer_names: Vec<&str> = self
+            .layer_info
+            .iter()
+            .map(|info| info.name.as_str())
+            .collect();
+        let mut next: BTreeMap<String, SequenceMenuEntry> = BTreeMap::new();
+        for (sequence, output) in self.sequences.descendants(typed) {
+            let mut rest = sequence[typed.len()..]
+                .iter()
+                .copied()
+                .skip_while(|key| *key == KEY_OVERLAP_MARKER);
+            let Some(label) = rest.next().and_then(sequence_key_label) else {
+                continue;
+            };
+            let ends = rest.all(|key| key == KEY_OVERLAP_MARKER);
+            let entry = next
+                .entry(label.clone())
+                .or_insert_with(|| SequenceMenuEntry {
+                    key: label,
+                    virtual_key: None,
+                    description: None,
+                    continues: false,
+                });
+            if !ends {
+                entry.continues = true;
+                continue;
+            }
+            let idx = usize::from(output.coord.1);
+            let Some(name) = self
+                .virtual_keys
+                .iter()
+                .find_map(|(name, vk_idx)| (*vk_idx == idx).then_some(name))
+            else {
+                continue;
+            };
+            entry.description = Some(match self.virtual_key_docs.get(name) {
+                Some(doc) => doc.clone(),
+                None => action_label(
+                    layout.layers.get(0, usize::from(FAKE_KEY_ROW), idx),
+                    &layer_names,
+                ),
+            });
+            entry.virtual_key = Some(name.clone());
+        }
+        ServerMessage::SequenceMenu {
+            active: true,
+            typed: typed
+                .iter()
+                .copied()
+                .filter_map(sequence_key_label)
+                .collect(),
+            next: next.into_values().collect(),
+        }
+    }
+}
@@ -11,6 +11,8 @@ use SequenceActivity::*;
 pub struct SequenceState {
     /// Unmangled sequence of keys pressed for hidden-delay-type.
     pub raw_oscs: Vec<OsCode>,
+    /// The modifiers that were held for each key pushed into the sequence, to check `N-` keys.
+    pub raw_mod_masks: Vec<u16>,
     /// Keeps track of standard sequence state.
     /// This includes regular keys, e.g. `a b c`
     /// and chorded keys, e.g. `S-(d e f)`.
@@ -36,6 +38,7 @@ impl SequenceState {
     pub fn new() -> Self {
         Self {
             raw_oscs: vec![],
+            raw_mod_masks: vec![],
             sequence: vec![],
             overlapped_sequence: vec![],
             sequence_input_mode: SequenceInputMode::HiddenSuppressed,
@@ -52,6 +55,7 @@ impl SequenceState {
         self.sequence_timeout = timeout;
         self.ticks_until_timeout = timeout;
         self.raw_oscs.clear();
+        self.raw_mod_masks.clear();
         self.sequence.clear();
         self.overlapped_sequence.clear();
         self.activity = Active;
@@ -97,13 +101,14 @@ pub(super) fn do_sequence_press_logic(
     k: &KeyCode,
     mod_mask: u16,
     kbd_out: &mut KbdOut,
-    sequences: &kanata_parser::trie::Trie<(u8, u16)>,
+    sequences: &cfg::KeySeqsToFKeys,
     sequence_backtrack_modcancel: bool,
     layout: &mut BorrowedKLayout,
 ) -> Result<(), anyhow::Error> {
     state.ticks_until_timeout = state.sequence_timeout;
     let osc = OsCode::from(*k);
     state.raw_oscs.push(osc);
+    state.raw_mod_masks.push(mod_mask);
     use kanata_parser::trie::GetOrDescendentExistsResult::*;
     let pushed_into_seq = {
         // Transform to OsCode and convert modifiers other than altgr/ralt
@@ -123,7 +128,12 @@ pub(super) fn do_sequence_press_logic(
         }
         SequenceInputMode::HiddenSuppressed | SequenceInputMode::HiddenDelayType => {}
     }
-    log::debug!("sequence got {k:?}");
+    tracing::debug!("sequence got {k:?}");
+    // A modifier that fits no sequence is ignored instead of ending the sequence, so that it can
+    // be held for the next keys.
+    let state_before_mod = osc
+        .is_modifier()
+        .then(|| (state.sequence.clone(), state.overlapped_sequence.clone()));
     state.sequence.push(pushed_into_seq);
     let pushed_into_overlap_seq = (pushed_into_seq & MASK_KEYCODES) | KEY_OVERLAP_MARKER;
     state.overlapped_sequence.push(pushed_into_overlap_seq);
@@ -201,7 +211,7 @@ pub(super) fn do_sequence_press_logic(
     ) {
         (false, false) => {}
         (false, true) => {
-            log::debug!("overlap seq is invalid; filling with standard seq");
+            tracing::debug!("overlap seq is invalid; filling with standard seq");
             // Overwrite overlapped with non-overlapped tracking
             state.overlapped_sequence.clear();
             state
@@ -210,7 +220,7 @@ pub(super) fn do_sequence_press_logic(
             res_overlapped = sequences.get_or_descendant_exists(&state.overlapped_sequence);
         }
         (true, false) => {
-            log::debug!("standard seq is invalid; filling with overlap seq");
+            tracing::debug!("standard seq is invalid; filling with overlap seq");
             state.sequence.clear();
             state
                 .sequence
@@ -231,28 +241,46 @@ pub(super) fn do_sequence_press_logic(
                 res = sequences.get_or_descendant_exists(&state.sequence);
             }
             if res == NotInTrie || state.sequence.is_empty() {
-                log::debug!("invalid keys for seq");
+                if let Some((sequence, overlapped_sequence)) = state_before_mod {
+                    tracing::debug!("ignoring modifier that fits no seq");
+                    state.sequence = sequence;
+                    state.overlapped_sequence = overlapped_sequence;
+                    state.raw_mod_masks.pop();
+                    return Ok(());
+                }
+                tracing::debug!("invalid keys for seq");
                 cancel_sequence(state, kbd_out)?;
             }
         }
     }
 
     // Check for successful sequence termination.
-    if let HasValue((i, j)) = res_overlapped {
+    if let HasValue(output) = res_overlapped
+        && unmodded_steps_match(state, &state.overlapped_sequence, output)
+    {
         // First, check for a valid simultaneous completion.
         // Simultaneous completion should take priority.
-        do_successful_sequence_termination(kbd_out, state, layout, i, j, EndSequenceType::Overlap)?;
-    } else if let HasValue((i, j)) = res {
+        do_successful_sequence_termination(
+            kbd_out,
+            state,
+            layout,
+            output,
+            EndSequenceType::Overlap,
+        )?;
+    } else if let HasValue(output) = res
+        && unmodded_steps_match(state, &state.sequence, output)
+    {
         // Try terminating the overlapping and check if simultaneous termination worked.
         // Simultaneous completion should take priority.
         state.overlapped_sequence.push(KEY_OVERLAP_MARKER);
-        if let HasValue((oi, oj)) = sequences.get_or_descendant_exists(&state.overlapped_sequence) {
+        if let HasValue(o_output) = sequences.get_or_descendant_exists(&state.overlapped_sequence)
+            && unmodded_steps_match(state, &state.overlapped_sequence, o_output)
+        {
             do_successful_sequence_termination(
                 kbd_out,
                 state,
                 layout,
-                oi,
-                oj,
+                o_output,
                 EndSequenceType::Overlap,
             )?;
         } else {
@@ -260,33 +288,82 @@ pub(super) fn do_sequence_press_logic(
                 kbd_out,
                 state,
                 layout,
-                i,
-                j,
+                output,
                 EndSequenceType::Standard,
             )?;
         }
+    } else if (matches!(res, HasValue(..)) || matches!(res_overlapped, HasValue(..)))
+        && res != InTrie
+        && res_overlapped != InTrie
+    {
+        tracing::debug!("modifiers were held for N- keys of seq");
+        cancel_sequence(state, kbd_out)?;
     }
     Ok(())
 }
 
+/// Checks that the keys written with `N-` in the completed sequence were typed without
+/// modifiers. The keys of `sequence` are the most recently typed ones.
+pub(super) fn unmodded_steps_match(
+    state: &SequenceState,
+    sequence: &[u16],
+    output: cfg::SequenceOutput,
+) -> bool {
+    if output.unmodded_steps == 0 {
+        return true;
+    }
+    let steps = sequence
+        .iter()
+        .filter(|k| **k != KEY_OVERLAP_MARKER)
+        .count();
+    let typed_mod_masks = &state.raw_mod_masks[state.raw_mod_masks.len().saturating_sub(steps)..];
+    typed_mod_masks
+        .iter()
+        .enumerate()
+        .take(64)
+        .all(|(step, mod_mask)| output.unmodded_steps & (1 << step) == 0 || *mod_mask == 0)
+}
+
 use kanata_keyberon::key_code::KeyCode::*;
 
 pub(super) fn do_successful_sequence_termination(
     kbd_out: &mut KbdOut,
     state: &mut SequenceState,
     layout: &mut Layout<'_, 767, 2, &CustomAction>,
-    i: u8,
-    j: u16,
+    output: cfg::SequenceOutput,
     seq_type: EndSequenceType,
 ) -> Result<(), anyhow::Error> {
-    log::debug!("sequence complete; tapping fake key");
+    tracing::debug!("sequence complete; tapping fake key");
     state.activity = Inactive;
     let sequence = match seq_type {
         EndSequenceType::Standard => &state.sequence,
         EndSequenceType::Overlap => &state.overlapped_sequence,
     };
+    // With pass-mods, the held modifiers stay in the layout state so that they apply to the
+    // virtual key and get released normally later.
+    let passed_mods: Vec<KeyCode> = match output.pass_mods {
+        true => layout
+            .states
+            .iter()
+            .filter_map(|s| match s {
+                State::NormalKey { keycode, .. } if OsCode::from(keycode).is_modifier() => {
+                    Some(*keycode)
+                }
+                _ => None,
+            })
+            .collect(),
+        false => vec![],
+    };
     match state.sequence_input_mode {
-        SequenceInputMode::HiddenSuppressed | SequenceInputMode::HiddenDelayType => {}
+        SequenceInputMode::HiddenSuppressed | SequenceInputMode::HiddenDelayType => {
+            // The modifiers pressed while the sequence was active have not been output yet.
+            for kc in passed_mods.iter() {
+                let osc = OsCode::from(*kc);
+                if state.raw_oscs.contains(&osc) {
+                    press_key(kbd_out, osc)?;
+                }
+            }
+        }
         SequenceInputMode::VisibleBackspaced => {
             // Release mod keys and backspace because they can cause backspaces to mess up.
             layout.states.retain(|s| match s {
@@ -295,7 +372,7 @@ pub(super) fn do_successful_sequence_termination(
                         // Ignore the error, ugly to return it from retain, and
                         // this is very unlikely to happen anyway.
                         let _ = release_key(kbd_out, keycode.into());
-                        false
+                        passed_mods.contains(keycode)
                     } else {
                         true
                     }
@@ -333,6 +410,11 @@ pub(super) fn do_successful_sequence_termination(
                     }
                 }
             }
+            for kc in passed_mods.iter() {
+                if matches!(kc, LCtrl | RCtrl | LAlt | RAlt | LGui | RGui) {
+                    press_key(kbd_out, kc.into())?;
+                }
+            }
         }
     }
     for k in sequence.iter().copied() {
@@ -340,11 +422,15 @@ pub(super) fn do_successful_sequence_termination(
             continue;
         };
         let kc = KeyCode::from(OsCode::from(k & MASK_KEYCODES));
+        if passed_mods.contains(&kc) {
+            continue;
+        }
         layout.states.retain(|s| match s {
             State::NormalKey { keycode, .. } => kc != *keycode,
             _ => true,
         });
     }
+    let (i, j) = output.coord;
     layout.event_to_front(Event::Release(i, j));
     layout.event_to_front(Event::Press(i, j));
     Ok(())
@@ -352,7 +438,7 @@ pub(super) fn do_successful_sequence_termination(
 
 pub(super) fn cancel_sequence(state: &mut SequenceState, kbd_out: &mut KbdOut) -> Result<()> {
     state.activity = Inactive;
-    log::debug!("sequence cancelled");
+    tracing::debug!("sequence cancelled");
     match state.sequence_input_mode {
         SequenceInputMode::HiddenDelayType => {
             for osc in state.raw_oscs.iter().copied() {
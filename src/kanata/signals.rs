@@ -0,0 +1,194 @@
+//! `--sigusr1`, `--sigusr2` and `--sighup`: controlling kanata with signals, e.g. from scripts
+//! and service managers, without enabling the TCP server.
+//!
+//! Signals without an action keep their default behaviour, so that e.g. closing the terminal
+//! still stops kanata with SIGHUP.
+
+use super::*;
+use signal_hook::consts::{SIGHUP, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
+use std::time::Duration;
+
+/// What kanata does when it receives a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction {
+    /// Live reload the configuration.
+    Reload,
+    /// Enter or leave emergency passthrough.
+    TogglePassthrough,
+    /// Start a new `--log-file`.
+    RotateLogs,
+    /// Log the active layer and the pressed keys, and write a crash bundle if they are enabled.
+    DumpState,
+}
+
+impl std::str::FromStr for SignalAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "reload" => Ok(SignalAction::Reload),
+            "toggle-passthrough" => Ok(SignalAction::TogglePassthrough),
+            "rotate-logs" => Ok(SignalAction::RotateLogs),
+            "dump-state" => Ok(SignalAction::DumpState),
+            _ => Err(format!(
+                "unknown action {s}, expected reload, toggle-passthrough, rotate-logs or dump-state"
+            )),
+        }
+    }
+}
+
+/// The actions of the signals that kanata handles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SignalActions {
+    pub sigusr1: Option<SignalAction>,
+    pub sigusr2: Option<SignalAction>,
+    pub sighup: Option<SignalAction>,
+}
+
+impl SignalActions {
+    fn mapped(&self) -> Vec<(i32, SignalAction)> {
+        [
+            (SIGUSR1, self.sigusr1),
+            (SIGUSR2, self.sigusr2),
+            (SIGHUP, self.sighup),
+        ]
+        .into_iter()
+        .filter_map(|(signal, action)| Some((signal, action?)))
+        .collect()
+    }
+}
+
+impl Kanata {
+    /// Starts the thread that runs the actions of signals, unless no signal has one.
+    pub fn start_signal_handler(
+        kanata: Arc<Mutex<Self>>,
+        wakeup: EventSender,
+        actions: SignalActions,
+    ) {
+        let mapped = actions.mapped();
+        if mapped.is_empty() {
+            return;
+        }
+        let mut signals = match Signals::new(mapped.iter().map(|(signal, _)| *signal)) {
+            Ok(signals) => signals,
+            Err(e) => {
+                tracing::error!("could not handle signals: {e}");
+                return;
+            }
+        };
+        let spawned = std::thread::Builder::new()
+            .name("signals".into())
+            .spawn(move || {
+                for signal in signals.forever() {
+                    let Some((_, action)) = mapped.iter().find(|(s, _)| *s == signal) else {
+                        continue;
+                    };
+                    tracing::info!("got signal {signal}, running {action:?}");
+                    run_signal_action(*action, &kanata);
+                    // The processing loop may be waiting for input, and the channel being full
+                    // means that it will wake up anyway.
+                    let _ = wakeup.try_send(KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp));
+                }
+            });
+        if let Err(e) = spawned {
+            tracing::error!("could not handle signals: {e}");
+        }
+    }
+}
+
+fn run_signal_action(action: SignalAction, kanata: &Mutex<Kanata>) {
+    match action {
+        SignalAction::Reload => kanata.lock().request_live_reload(),
+        SignalAction::TogglePassthrough => {
+            let active = !is_emergency_passthrough_active();
+            set_emergency_passthrough(active);
+            match active {
+                true => tracing::warn!("signal: passing input through to the OS"),
+                false => tracing::info!("signal: processing input again"),
+            }
+        }
+        SignalAction::RotateLogs => request_log_rotation(),
+        SignalAction::DumpState => dump_state(kanata),
+    }
+}
+
+/// Logs what the watchdog logs for a stall, without waiting for a processing loop that may be
+/// stuck.
+fn dump_state(kanata: &Mutex<Kanata>) {
+    const LOCK_TIMEOUT: Duration = Duration::from_millis(100);
+    match kanata.try_lock_for(LOCK_TIMEOUT) {
+        Some(mut k) => {
+            let layer = k.layout.bm().current_layer();
+            tracing::info!(
+                "state: layer {}, {} active keys, {} key presses so far, reload pending: {}",
+                k.layer_info[layer].name,
+                k.cur_keys.len(),
+                k.key_presses,
+                k.live_reload_requested,
+            );
+        }
+        None => tracing::warn!("state: the kanata state is locked by another thread"),
+    }
+    match PRESSED_KEYS.try_lock_for(LOCK_TIMEOUT) {
+        Some(pressed) => tracing::info!("state: physically pressed keys: {:?}", *pressed),
+        None => tracing::warn!("state: the pressed keys are locked by another thread"),
+    }
+    tracing::info!(
+        "state: emergency passthrough: {}",
+        is_emergency_passthrough_active()
+    );
+    write_crash_bundle("signal: dump-state");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_signal_actions() {
+        assert_eq!("reload".parse(), Ok(SignalAction::Reload));
+        assert_eq!(
+            "toggle-passthrough".parse(),
+            Ok(SignalAction::TogglePassthrough)
+        );
+        assert_eq!("rotate-logs".parse(), Ok(SignalAction::RotateLogs));
+        assert_eq!("dump-state".parse(), Ok(SignalAction::DumpState));
+        assert!("restart".parse::<SignalAction>().is_err());
+        let actions = SignalActions {
+            sigusr2: Some(SignalAction::DumpState),
+            sighup: Some(SignalAction::Reload),
+            ..Default::default()
+        };
+        assert_eq!(
+            actions.mapped(),
+            [
+                (SIGUSR2, SignalAction::DumpState),
+                (SIGHUP, SignalAction::Reload)
+            ]
+        );
+    }
+
+    #[cfg(feature = "simulated_output")]
+    #[test]
+    fn signals_run_their_actions() {
+        let kanata = {
+            let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            Kanata::new_from_str("(defsrc a) (deflayer base a)", Default::default()).unwrap()
+        };
+        let kanata = Arc::new(Mutex::new(kanata));
+        let (tx, rx) = event_queue(10);
+        let actions = SignalActions {
+            sigusr1: Some(SignalAction::RotateLogs),
+            ..Default::default()
+        };
+        Kanata::start_signal_handler(kanata, tx, actions);
+        signal_hook::low_level::raise(SIGUSR1).unwrap();
+        let woken = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(woken.value, KeyValue::WakeUp);
+        assert!(take_log_rotation_request());
+    }
+}
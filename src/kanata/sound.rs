@@ -0,0 +1,87 @@
+//! Sound cues for layer changes, caps-word activation and sequence timeouts.
+//!
+//! Sounds are played asynchronously so that the processing loop is never blocked.
+
+use super::*;
+
+/// Play a sound cue. With simulated output, the cue is written to the simulated keyboard output
+/// instead of being played.
+pub(crate) fn play_sound(_kbd_out: &mut KbdOut, cue: &SoundCue) {
+    if *cue == SoundCue::Silent {
+        return;
+    }
+    tracing::debug!("sound: {cue:?}");
+    #[cfg(feature = "simulated_output")]
+    _kbd_out.write_sound(cue);
+    #[cfg(not(feature = "simulated_output"))]
+    platform::play(cue);
+}
+
+#[cfg(all(not(feature = "simulated_output"), target_os = "windows"))]
+mod platform {
+    use kanata_parser::cfg::SoundCue;
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::playsoundapi::*;
+    use winapi::um::winuser::{MB_OK, MessageBeep};
+
+    pub(super) fn play(cue: &SoundCue) {
+        match cue {
+            SoundCue::Silent => {}
+            SoundCue::Beep => unsafe {
+                MessageBeep(MB_OK);
+            },
+            SoundCue::File(path) => {
+                let wide: Vec<u16> = std::ffi::OsStr::new(path)
+                    .encode_wide()
+                    .chain(std::iter::once(0))
+                    .collect();
+                let ok = unsafe {
+                    PlaySoundW(
+                        wide.as_ptr(),
+                        std::ptr::null_mut(),
+                        SND_FILENAME | SND_ASYNC | SND_NODEFAULT,
+                    )
+                };
+                if ok == 0 {
+                    tracing::warn!("failed to play sound file {path}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(not(feature = "simulated_output"), not(target_os = "windows")))]
+mod platform {
+    use kanata_parser::cfg::SoundCue;
+    use std::process::Command;
+
+    #[cfg(target_os = "macos")]
+    const PLAYERS: &[&str] = &["afplay"];
+    #[cfg(not(target_os = "macos"))]
+    const PLAYERS: &[&str] = &["paplay", "aplay"];
+
+    #[cfg(target_os = "macos")]
+    const BEEP_FILE: &str = "/System/Library/Sounds/Tink.aiff";
+    #[cfg(not(target_os = "macos"))]
+    const BEEP_FILE: &str = "/usr/share/sounds/freedesktop/stereo/bell.oga";
+
+    pub(super) fn play(cue: &SoundCue) {
+        let path = match cue {
+            SoundCue::Silent => return,
+            SoundCue::Beep => BEEP_FILE.to_owned(),
+            SoundCue::File(path) => path.clone(),
+        };
+        // Wait for the player in a separate thread so that it does not stay around as a zombie
+        // process and does not block processing.
+        std::thread::spawn(move || {
+            for player in PLAYERS {
+                match Command::new(player).arg(&path).status() {
+                    Ok(status) if status.success() => return,
+                    Ok(status) => tracing::debug!("{player} {path} exited with {status}"),
+                    Err(e) => tracing::debug!("could not run {player}: {e}"),
+                }
+            }
+            tracing::warn!("failed to play sound {path} with any of {PLAYERS:?}");
+        });
+    }
+}
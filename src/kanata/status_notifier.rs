@@ -0,0 +1,535 @@
+//! `--tray`: a StatusNotifierItem on the session bus, which desktop panels like KDE Plasma, waybar
+//! and the AppIndicator extension of GNOME show as a tray icon.
+//!
+//! The item shows the active layer in its label and tooltip, and its menu, which is exported with
+//! the `com.canonical.dbusmenu` interface, pauses kanata, reloads the configuration and lists the
+//! errors of recent reloads. The state is polled, so that the processing loop doesn't wait for the
+//! bus, and the clicks, which the object server of `zbus` receives on its own thread, are passed
+//! to the thread of the tray.
+
+use super::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::Duration;
+use zbus::blocking::Connection;
+use zbus::zvariant::{ObjectPath, OwnedValue, Str, Value};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+const MENU_INTERFACE: &str = "com.canonical.dbusmenu";
+const WATCHER: &str = "org.kde.StatusNotifierWatcher";
+const ICON: &str = "input-keyboard";
+
+const RECENT_ERRORS: usize = 5;
+static ERRORS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+const LAYER_ID: i32 = 1;
+const PAUSE_ID: i32 = 3;
+const RELOAD_ID: i32 = 4;
+const ERRORS_ID: i32 = 5;
+
+/// Remembers an error for the menu of the tray.
+pub(crate) fn tray_error(msg: String) {
+    let mut errors = ERRORS.lock();
+    if errors.len() == RECENT_ERRORS {
+        errors.pop_front();
+    }
+    errors.push_back(msg);
+}
+
+/// What the tray shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TrayState {
+    layer: String,
+    paused: bool,
+    errors: Vec<String>,
+}
+
+impl TrayState {
+    fn of(k: &mut Kanata) -> Self {
+        let layer = k.layout.bm().current_layer();
+        Self {
+            layer: k.layer_info[layer].name.clone(),
+            paused: is_emergency_passthrough_active(),
+            errors: ERRORS.lock().iter().cloned().collect(),
+        }
+    }
+}
+
+/// What a click in the menu asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TrayAction {
+    TogglePause,
+    Reload,
+}
+
+struct Tray {
+    state: TrayState,
+    /// The revision of the menu layout, which changes with the state.
+    revision: u32,
+}
+
+/// The `a{sv}` of the properties of a menu item.
+type Properties = HashMap<&'static str, OwnedValue>;
+
+/// The `(ia{sv}av)` of a menu item and its children.
+type Layout = (i32, Properties, Vec<OwnedValue>);
+
+struct MenuItem {
+    id: i32,
+    properties: Properties,
+    children: Vec<MenuItem>,
+}
+
+impl MenuItem {
+    fn new(id: i32, label: String, enabled: bool) -> Self {
+        let properties = HashMap::from([
+            ("label", Str::from(label).into()),
+            ("enabled", enabled.into()),
+        ]);
+        Self {
+            id,
+            properties,
+            children: vec![],
+        }
+    }
+
+    fn find(self, id: i32) -> Option<MenuItem> {
+        match self.id == id {
+            true => Some(self),
+            false => self.children.into_iter().find_map(|child| child.find(id)),
+        }
+    }
+
+    fn flatten(self, items: &mut Vec<(i32, Properties)>) {
+        items.push((self.id, self.properties));
+        self.children
+            .into_iter()
+            .for_each(|child| child.flatten(items));
+    }
+
+    fn layout(self) -> zbus::zvariant::Result<Layout> {
+        let children = self.children.into_iter().map(|child| {
+            let layout = child.layout()?;
+            OwnedValue::try_from(Value::from(layout))
+        });
+        Ok((
+            self.id,
+            self.properties,
+            children.collect::<Result<_, _>>()?,
+        ))
+    }
+}
+
+impl Tray {
+    fn status(&self) -> &'static str {
+        match self.state.paused {
+            true => "NeedsAttention",
+            false => "Active",
+        }
+    }
+
+    /// The `(sa(iiay)ss)` of the icon, the title and the description.
+    #[allow(clippy::type_complexity)]
+    fn tooltip(&self) -> (&'static str, Vec<(i32, i32, Vec<u8>)>, &'static str, String) {
+        let mut description = format!("layer: {}", self.state.layer);
+        if self.state.paused {
+            description.push_str("\npaused");
+        }
+        if let Some(error) = self.state.errors.last() {
+            description.push_str(&format!("\nlast error: {error}"));
+        }
+        (ICON, vec![], "kanata", description)
+    }
+
+    fn menu(&self) -> MenuItem {
+        let mut separator = MenuItem::new(2, String::new(), true);
+        separator.properties = HashMap::from([("type", Str::from("separator").into())]);
+        let mut pause = MenuItem::new(PAUSE_ID, "Pause".into(), true);
+        pause
+            .properties
+            .insert("toggle-type", Str::from("checkmark").into());
+        pause
+            .properties
+            .insert("toggle-state", i32::from(self.state.paused).into());
+        let mut errors = match self.state.errors.is_empty() {
+            true => MenuItem::new(ERRORS_ID, "No recent errors".into(), false),
+            false => MenuItem::new(ERRORS_ID, "Recent errors".into(), true),
+        };
+        if !self.state.errors.is_empty() {
+            errors
+                .properties
+                .insert("children-display", Str::from("submenu").into());
+            errors.children = (self.state.errors.iter().rev().enumerate())
+                .map(|(i, error)| MenuItem::new(100 + i as i32, error.clone(), false))
+                .collect();
+        }
+        MenuItem {
+            id: 0,
+            properties: HashMap::from([("children-display", Str::from("submenu").into())]),
+            children: vec![
+                MenuItem::new(LAYER_ID, format!("Layer: {}", self.state.layer), false),
+                separator,
+                pause,
+                MenuItem::new(RELOAD_ID, "Reload config".into(), true),
+                errors,
+            ],
+        }
+    }
+}
+
+/// The StatusNotifierItem, which the panel shows as the icon.
+struct Item(Arc<Mutex<Tray>>);
+
+#[zbus::interface(name = "org.kde.StatusNotifierItem")]
+impl Item {
+    fn activate(&self, _x: i32, _y: i32) {}
+
+    fn secondary_activate(&self, _x: i32, _y: i32) {}
+
+    fn context_menu(&self, _x: i32, _y: i32) {}
+
+    fn scroll(&self, _delta: i32, _orientation: &str) {}
+
+    #[zbus(property)]
+    fn category(&self) -> &str {
+        "ApplicationStatus"
+    }
+
+    #[zbus(property)]
+    fn id(&self) -> &str {
+        "kanata"
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> &str {
+        "kanata"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        self.0.lock().status()
+    }
+
+    #[zbus(property)]
+    fn window_id(&self) -> i32 {
+        0
+    }
+
+    #[zbus(property)]
+    fn icon_name(&self) -> &str {
+        ICON
+    }
+
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> &str {
+        ""
+    }
+
+    #[zbus(property)]
+    fn attention_icon_name(&self) -> &str {
+        ICON
+    }
+
+    #[zbus(property)]
+    fn overlay_icon_name(&self) -> &str {
+        ""
+    }
+
+    #[zbus(property)]
+    #[allow(clippy::type_complexity)]
+    fn tool_tip(&self) -> (&str, Vec<(i32, i32, Vec<u8>)>, &str, String) {
+        self.0.lock().tooltip()
+    }
+
+    #[zbus(property)]
+    fn item_is_menu(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn menu(&self) -> ObjectPath<'_> {
+        ObjectPath::from_static_str_unchecked(MENU_PATH)
+    }
+
+    #[zbus(property, name = "XAyatanaLabel")]
+    fn x_ayatana_label(&self) -> String {
+        self.0.lock().state.layer.clone()
+    }
+
+    #[zbus(property, name = "XAyatanaLabelGuide")]
+    fn x_ayatana_label_guide(&self) -> &str {
+        ""
+    }
+}
+
+/// The menu of the item.
+struct Menu {
+    tray: Arc<Mutex<Tray>>,
+    clicks: Sender<TrayAction>,
+}
+
+impl Menu {
+    fn clicked(&self, id: i32, event_id: &str) {
+        if event_id != "clicked" {
+            return;
+        }
+        let action = match id {
+            PAUSE_ID => TrayAction::TogglePause,
+            RELOAD_ID => TrayAction::Reload,
+            _ => return,
+        };
+        // The thread of the tray only stops with the connection.
+        let _ = self.clicks.send(action);
+    }
+}
+
+#[zbus::interface(name = "com.canonical.dbusmenu")]
+impl Menu {
+    fn get_layout(
+        &self,
+        parent_id: i32,
+        _recursion_depth: i32,
+        _property_names: Vec<String>,
+    ) -> zbus::fdo::Result<(u32, Layout)> {
+        let tray = self.tray.lock();
+        let item = tray.menu().find(parent_id).unwrap_or_else(|| tray.menu());
+        let layout = item
+            .layout()
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
+        Ok((tray.revision, layout))
+    }
+
+    fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        _property_names: Vec<String>,
+    ) -> Vec<(i32, Properties)> {
+        let mut items = vec![];
+        self.tray.lock().menu().flatten(&mut items);
+        items.retain(|(id, _)| ids.is_empty() || ids.contains(id));
+        items
+    }
+
+    fn event(&self, id: i32, event_id: &str, _data: OwnedValue, _timestamp: u32) {
+        self.clicked(id, event_id);
+    }
+
+    fn event_group(&self, events: Vec<(i32, String, OwnedValue, u32)>) -> Vec<i32> {
+        for (id, event_id, _, _) in events {
+            self.clicked(id, &event_id);
+        }
+        vec![]
+    }
+
+    fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    fn about_to_show_group(&self, _ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (vec![], vec![])
+    }
+
+    #[zbus(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[zbus(property)]
+    fn text_direction(&self) -> &str {
+        "ltr"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        "normal"
+    }
+
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// Tells the panel that the state changed.
+fn update(conn: &Connection, tray: &Mutex<Tray>, state: TrayState) -> zbus::Result<()> {
+    let (status, layer, revision) = {
+        let mut tray = tray.lock();
+        tray.state = state;
+        tray.revision += 1;
+        (tray.status(), tray.state.layer.clone(), tray.revision)
+    };
+    conn.emit_signal(None::<&str>, ITEM_PATH, ITEM_INTERFACE, "NewToolTip", &())?;
+    conn.emit_signal(
+        None::<&str>,
+        ITEM_PATH,
+        ITEM_INTERFACE,
+        "NewStatus",
+        &(status,),
+    )?;
+    conn.emit_signal(
+        None::<&str>,
+        ITEM_PATH,
+        ITEM_INTERFACE,
+        "XAyatanaNewLabel",
+        &(layer, ""),
+    )?;
+    conn.emit_signal(
+        None::<&str>,
+        MENU_PATH,
+        MENU_INTERFACE,
+        "LayoutUpdated",
+        &(revision, 0i32),
+    )
+}
+
+impl Kanata {
+    /// Starts the thread that shows kanata in the tray of the desktop.
+    pub fn start_status_notifier(kanata: Arc<Mutex<Self>>, wakeup: EventSender) {
+        let spawned = std::thread::Builder::new()
+            .name("tray".into())
+            .spawn(move || {
+                if let Err(e) = serve_status_notifier(&kanata, &wakeup) {
+                    tracing::error!(
+                        "tray: {e}. The desktop needs a StatusNotifierWatcher, \
+                         e.g. KDE Plasma, waybar or the AppIndicator extension of GNOME"
+                    );
+                }
+            });
+        if let Err(e) = spawned {
+            tracing::error!("could not start the tray: {e}");
+        }
+    }
+}
+
+fn serve_status_notifier(kanata: &Mutex<Kanata>, wakeup: &EventSender) -> zbus::Result<()> {
+    let name = format!("org.kde.StatusNotifierItem-{}-1", std::process::id());
+    let tray = Arc::new(Mutex::new(Tray {
+        state: TrayState::of(&mut kanata.lock()),
+        revision: 1,
+    }));
+    let (clicks, clicked) = std::sync::mpsc::channel();
+    let conn = dbus::session_builder()?
+        .name(name.clone())?
+        .serve_at(ITEM_PATH, Item(tray.clone()))?
+        .serve_at(
+            MENU_PATH,
+            Menu {
+                tray: tray.clone(),
+                clicks,
+            },
+        )?
+        .build()?;
+    conn.call_method(
+        Some(WATCHER),
+        "/StatusNotifierWatcher",
+        Some(WATCHER),
+        "RegisterStatusNotifierItem",
+        &(name.as_str(),),
+    )?;
+    tracing::info!("tray: showing kanata as {name}");
+    loop {
+        match clicked.recv_timeout(POLL_INTERVAL) {
+            Ok(action) => {
+                match action {
+                    TrayAction::TogglePause => {
+                        let paused = !is_emergency_passthrough_active();
+                        set_emergency_passthrough(paused);
+                        match paused {
+                            true => tracing::info!("paused from the tray, passing input through"),
+                            false => tracing::info!("resumed from the tray"),
+                        }
+                    }
+                    TrayAction::Reload => kanata.lock().request_live_reload(),
+                }
+                // The processing loop may be waiting for input, and the channel being full means
+                // that it will wake up anyway.
+                let _ = wakeup.try_send(KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp));
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(zbus::Error::Failure(
+                    "the menu was removed from the bus".into(),
+                ));
+            }
+        }
+        let state = TrayState::of(&mut kanata.lock());
+        let changed = tray.lock().state != state;
+        if changed {
+            update(&conn, &tray, state)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::dbus::tests::connection_pair;
+    use super::*;
+
+    #[test]
+    fn tray_answers_the_panel() {
+        let tray = Arc::new(Mutex::new(Tray {
+            state: TrayState {
+                layer: "nav".into(),
+                paused: false,
+                errors: vec!["bad config".into()],
+            },
+            revision: 7,
+        }));
+        let (clicks, clicked) = std::sync::mpsc::channel();
+        let menu = Menu {
+            tray: tray.clone(),
+            clicks,
+        };
+        let (panel, _item) = connection_pair(move |builder| {
+            builder
+                .serve_at(ITEM_PATH, Item(tray))?
+                .serve_at(MENU_PATH, menu)
+        });
+        let get = |path: &str, interface: &str, name: &str| -> OwnedValue {
+            let properties = Some("org.freedesktop.DBus.Properties");
+            let reply =
+                panel.call_method(None::<&str>, path, properties, "Get", &(interface, name));
+            reply.unwrap().body().deserialize().unwrap()
+        };
+        let label = get(ITEM_PATH, ITEM_INTERFACE, "XAyatanaLabel");
+        assert_eq!(String::try_from(label).unwrap(), "nav");
+        let version = get(MENU_PATH, MENU_INTERFACE, "Version");
+        assert_eq!(u32::try_from(version).unwrap(), 3);
+
+        let no_properties: &[&str] = &[];
+        let reply = panel.call_method(
+            None::<&str>,
+            MENU_PATH,
+            Some(MENU_INTERFACE),
+            "GetLayout",
+            &(ERRORS_ID, -1i32, no_properties),
+        );
+        type Reply = (u32, (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>));
+        let (revision, (id, properties, children)): Reply =
+            reply.unwrap().body().deserialize().unwrap();
+        assert_eq!(revision, 7);
+        assert_eq!(id, ERRORS_ID);
+        assert!(properties.contains_key("children-display"));
+        assert_eq!(children.len(), 1);
+
+        for id in [RELOAD_ID, LAYER_ID] {
+            let data = Value::from(0i32);
+            let reply = panel.call_method(
+                None::<&str>,
+                MENU_PATH,
+                Some(MENU_INTERFACE),
+                "Event",
+                &(id, "clicked", data, 0u32),
+            );
+            reply.unwrap();
+        }
+        let err = panel.call_method(None::<&str>, "/nope", Some("a.b"), "C", &());
+        let err = err.unwrap_err();
+        assert!(err.to_string().contains("Unknown"), "{err}");
+        assert_eq!(clicked.try_iter().collect::<Vec<_>>(), [TrayAction::Reload]);
+    }
+}
@@ -0,0 +1,110 @@
+//! Releases output keys that are held without their physical key for longer than
+//! `stuck-key-timeout`, e.g. because the release of the physical key was lost when its device
+//! disconnected or the configuration was reloaded. A stuck modifier otherwise changes every key
+//! that is typed until it is pressed again.
+//!
+//! Active one-shot keys and virtual keys are held without a physical key on purpose and are not
+//! released.
+
+use super::*;
+
+#[derive(Debug, Default)]
+pub(crate) struct StuckKeys {
+    /// Milliseconds after which a key is released, or 0 if they are not.
+    timeout_ms: u16,
+    /// The output keys that are held without their physical key, by the layout coordinate of
+    /// the physical key, with the milliseconds that they have been.
+    held: Vec<(u16, KeyCode, u16)>,
+}
+
+impl StuckKeys {
+    pub(crate) fn new(timeout_ms: u16) -> Self {
+        Self {
+            timeout_ms,
+            held: vec![],
+        }
+    }
+
+    /// Whether a key is held without its physical key, during which the processing loop must
+    /// keep ticking.
+    pub(crate) fn pending(&self) -> bool {
+        !self.held.is_empty()
+    }
+}
+
+/// Whether the layout coordinate has no physical key, e.g. the outputs of `defchordsv2`, or the
+/// physical key is pressed.
+fn physically_pressed(y: u16) -> bool {
+    let Some(osc) = OsCode::from_u16(y) else {
+        return true;
+    };
+    #[cfg(not(all(target_os = "windows", not(feature = "interception_driver"))))]
+    return PRESSED_KEYS.lock().contains(&osc);
+    #[cfg(all(target_os = "windows", not(feature = "interception_driver")))]
+    return PRESSED_KEYS.lock().contains_key(&osc);
+}
+
+impl Kanata {
+    /// Counts how long output keys are held without their physical key and releases those that
+    /// reached `stuck-key-timeout`.
+    pub(crate) fn tick_stuck_keys(&mut self, _tx: &Option<Sender<ServerMessage>>) {
+        let timeout_ms = self.stuck_keys.timeout_ms;
+        if timeout_ms == 0 {
+            return;
+        }
+        if self.emergency_passthrough || self.processing_pause.is_some() {
+            self.stuck_keys.held.clear();
+            return;
+        }
+        let layout = self.layout.bm();
+        let held = &mut self.stuck_keys.held;
+        let is_candidate =
+            |y: u16| !physically_pressed(y) && !layout.oneshot.keys.contains(&(NORMAL_KEY_ROW, y));
+        let mut still_held = Vec::with_capacity(held.len());
+        for state in layout.states.iter() {
+            if let State::NormalKey {
+                keycode,
+                coord: (NORMAL_KEY_ROW, y),
+                ..
+            } = *state
+                && is_candidate(y)
+            {
+                let ms = held
+                    .iter()
+                    .find(|(y2, kc, _)| *y2 == y && *kc == keycode)
+                    .map(|(_, _, ms)| *ms)
+                    .unwrap_or(0);
+                still_held.push((y, keycode, ms.saturating_add(1)));
+            }
+        }
+        *held = still_held;
+        let mut released = vec![];
+        held.retain(|&(y, keycode, ms)| {
+            if ms < timeout_ms {
+                return true;
+            }
+            layout.event(Event::Release(NORMAL_KEY_ROW, y));
+            released.push((keycode, u64::from(ms)));
+            false
+        });
+        for (keycode, held_ms) in released {
+            let osc = OsCode::from_u16(keycode as u16);
+            let key = osc
+                .map(key_name)
+                .unwrap_or_else(|| format!("{keycode:?}").to_lowercase());
+            tracing::warn!(
+                "released {key}, which was held for {held_ms} ms without its physical key"
+            );
+            if let Some(osc) = osc {
+                trace_stuck_key(osc, held_ms);
+            }
+            #[cfg(feature = "tcp_server")]
+            if let Some(tx) = _tx {
+                let msg = ServerMessage::StuckKeyReleased { key, held_ms };
+                if let Err(error) = tx.try_send(msg) {
+                    tracing::error!("could not send event notification: {}", error);
+                }
+            }
+        }
+    }
+}
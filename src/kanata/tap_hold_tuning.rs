@@ -0,0 +1,87 @@
+//! Runtime tuning of the tap-hold actions of aliases with `SetTapHold`, e.g. for a tool that
+//! searches for the timing of home row mods while its user types. The tunings last until the
+//! next reload, which creates a new layout.
+
+use super::*;
+use anyhow::anyhow;
+use kanata_keyberon::action::{HoldTapConfig, HoldTapTuning};
+use kanata_tcp_protocol::TapHoldParams;
+use std::collections::BTreeMap;
+
+fn flavor_name(config: &HoldTapConfig) -> &'static str {
+    match config {
+        HoldTapConfig::Default => "tap-hold",
+        HoldTapConfig::HoldOnOtherKeyPress => "tap-hold-press",
+        HoldTapConfig::PermissiveHold => "tap-hold-release",
+        HoldTapConfig::Order { .. } => "tap-hold-order",
+        _ => "custom",
+    }
+}
+
+fn flavor_config(flavor: &str) -> Option<HoldTapConfig<'static>> {
+    match flavor {
+        "tap-hold" => Some(HoldTapConfig::Default),
+        "tap-hold-press" => Some(HoldTapConfig::HoldOnOtherKeyPress),
+        "tap-hold-release" => Some(HoldTapConfig::PermissiveHold),
+        _ => None,
+    }
+}
+
+impl Kanata {
+    /// Sets parameters of the tap-hold action of an alias for `SetTapHold`. Parameters that are
+    /// None keep their value.
+    pub fn set_tap_hold(
+        &mut self,
+        alias: &str,
+        hold_timeout_ms: Option<u16>,
+        tap_repress_timeout_ms: Option<u16>,
+        flavor: Option<&str>,
+    ) -> Result<()> {
+        let Some(&hold_tap) = self.hold_tap_aliases.get(alias) else {
+            bail!("{alias} is not an alias of a tap-hold action");
+        };
+        let config = flavor
+            .map(|flavor| {
+                flavor_config(flavor).ok_or_else(|| {
+                    anyhow!(
+                        "unknown tap-hold flavor {flavor}, expected tap-hold, tap-hold-press or tap-hold-release"
+                    )
+                })
+            })
+            .transpose()?;
+        let layout = self.layout.bm();
+        let mut tuning = layout
+            .hold_tap_tuning(hold_tap)
+            .unwrap_or_else(|| HoldTapTuning::from(hold_tap));
+        tuning.timeout = hold_timeout_ms.unwrap_or(tuning.timeout);
+        tuning.tap_hold_interval = tap_repress_timeout_ms.unwrap_or(tuning.tap_hold_interval);
+        tuning.config = config.unwrap_or(tuning.config);
+        layout.set_hold_tap_tuning(hold_tap, tuning);
+        tracing::info!(
+            "tap-hold of {alias} set to hold timeout {} ms, tap repress timeout {} ms, {}",
+            tuning.timeout,
+            tuning.tap_hold_interval,
+            flavor_name(&tuning.config)
+        );
+        Ok(())
+    }
+
+    /// Returns the parameters of the tap-hold actions of aliases for `RequestTapHolds`.
+    pub fn tap_holds(&mut self) -> BTreeMap<String, TapHoldParams> {
+        let layout = self.layout.b();
+        self.hold_tap_aliases
+            .iter()
+            .map(|(alias, &hold_tap)| {
+                let tuned = layout.hold_tap_tuning(hold_tap);
+                let tuning = tuned.unwrap_or_else(|| HoldTapTuning::from(hold_tap));
+                let params = TapHoldParams {
+                    hold_timeout_ms: tuning.timeout,
+                    tap_repress_timeout_ms: tuning.tap_hold_interval,
+                    flavor: flavor_name(&tuning.config).to_owned(),
+                    tuned: tuned.is_some(),
+                };
+                (alias.clone(), params)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,65 @@
+//! `realtime-priority`: runs the input and processing threads at real-time priority.
+//!
+//! This keeps tap-hold and other timing decisions accurate while the system is under heavy load.
+//! Raising the priority usually needs extra permissions. Without them, kanata logs a warning and
+//! keeps running at the normal priority.
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "windows"))]
+use tracing::info;
+use tracing::warn;
+
+/// The SCHED_FIFO priority to use, in the middle of the range so that kernel threads and
+/// audio servers that need it more can still preempt kanata.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SCHED_FIFO_PRIORITY: i32 = 50;
+
+/// Raises the priority of the calling thread, which is named in the logs as `thread`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn raise_thread_priority(thread: &str) {
+    let param = libc::sched_param {
+        sched_priority: SCHED_FIFO_PRIORITY,
+    };
+    let rc = unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) };
+    match rc {
+        0 => info!("{thread} thread priority set to SCHED_FIFO {SCHED_FIFO_PRIORITY}"),
+        libc::EPERM => warn!(
+            "not permitted to set the {thread} thread to real-time priority, continuing at normal \
+            priority. Give kanata the CAP_SYS_NICE capability or raise its RLIMIT_RTPRIO to allow it."
+        ),
+        _ => warn!(
+            "failed to set the {thread} thread to real-time priority: {}",
+            std::io::Error::from_raw_os_error(rc)
+        ),
+    }
+}
+
+/// Raises the priority of the calling thread, which is named in the logs as `thread`.
+#[cfg(target_os = "windows")]
+pub(crate) fn raise_thread_priority(thread: &str) {
+    use winapi::um::processthreadsapi::{GetCurrentThread, SetThreadPriority};
+    use winapi::um::winbase::THREAD_PRIORITY_TIME_CRITICAL;
+
+    let ok = unsafe {
+        SetThreadPriority(
+            GetCurrentThread(),
+            THREAD_PRIORITY_TIME_CRITICAL as winapi::ctypes::c_int,
+        )
+    };
+    if ok != 0 {
+        info!("{thread} thread priority set to TIME_CRITICAL");
+    } else {
+        warn!(
+            "failed to set the {thread} thread to real-time priority, continuing at normal \
+            priority: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Raises the priority of the calling thread, which is named in the logs as `thread`.
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "windows")))]
+pub(crate) fn raise_thread_priority(thread: &str) {
+    warn!(
+        "realtime-priority is not supported on this platform, ignoring it for the {thread} thread"
+    );
+}
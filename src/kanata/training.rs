@@ -0,0 +1,119 @@
+//! Training mode, toggled by the `training-mode` action or set with the TCP `SetTrainingMode`
+//! command. While it is on, every key press is sent to TCP clients as a `TrainingKey`, with what
+//! the key does on the active layer and what it did in the old layout, e.g. for a trainer that
+//! scores its user while they learn a new layout. The old layout is a layer of the
+//! configuration, e.g. `qwerty`, or else the keys of defsrc.
+
+use super::*;
+use kanata_keyberon::action::Action;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Training {
+    /// The layer of the old layout, or None for defsrc.
+    reference_layer: Option<usize>,
+}
+
+fn key_name(osc: OsCode) -> String {
+    oscode_to_str(osc).map_or_else(|| osc.to_string(), str::to_owned)
+}
+
+impl Kanata {
+    /// Toggles training mode for the `training-mode` action.
+    pub(crate) fn toggle_training_mode(&mut self, reference_layer: Option<usize>) {
+        let training = match self.training {
+            Some(_) => None,
+            None => Some(Training { reference_layer }),
+        };
+        self.change_training_mode(training);
+    }
+
+    /// Turns training mode on or off for `SetTrainingMode`.
+    pub fn set_training_mode(
+        &mut self,
+        enabled: bool,
+        reference_layer: Option<&str>,
+    ) -> Result<()> {
+        let reference_layer = reference_layer
+            .map(|name| {
+                self.layer_info
+                    .iter()
+                    .position(|info| info.name == name)
+                    .ok_or_else(|| anyhow::anyhow!("unknown layer {name}"))
+            })
+            .transpose()?;
+        self.change_training_mode(enabled.then_some(Training { reference_layer }));
+        Ok(())
+    }
+
+    fn change_training_mode(&mut self, training: Option<Training>) {
+        if training == self.training {
+            return;
+        }
+        self.training = training;
+        let reference_layer = training
+            .and_then(|training| training.reference_layer)
+            .map(|layer| self.layer_info[layer].name.clone());
+        match training {
+            Some(_) => tracing::info!(
+                "training mode on, comparing with {}",
+                reference_layer.as_deref().unwrap_or("defsrc")
+            ),
+            None => tracing::info!("training mode off"),
+        }
+        self.training_messages.push(ServerMessage::TrainingMode {
+            enabled: training.is_some(),
+            reference_layer,
+        });
+    }
+
+    /// Records what the pressed key does on the active layer and in the old layout, before the
+    /// press changes the layer.
+    pub(crate) fn record_training_key(&mut self, osc: OsCode) {
+        let Some(training) = self.training else {
+            return;
+        };
+        let layout = self.layout.b();
+        let layer_names: Vec<&str> = self
+            .layer_info
+            .iter()
+            .map(|info| info.name.as_str())
+            .collect();
+        let idx = usize::from(osc);
+        let action = action_label(
+            layout.active_action((NORMAL_KEY_ROW, idx as u16)),
+            &layer_names,
+        );
+        let reference = match training.reference_layer {
+            Some(layer) if layer < layer_names.len() => {
+                match layout.layers.get(layer, usize::from(NORMAL_KEY_ROW), idx) {
+                    Action::Trans => key_name(osc),
+                    action => action_label(action, &layer_names),
+                }
+            }
+            _ => key_name(osc),
+        };
+        let msg = ServerMessage::TrainingKey {
+            key: key_name(osc),
+            layer: layer_names[layout.current_layer()].to_owned(),
+            action,
+            reference,
+        };
+        self.training_messages.push(msg);
+    }
+
+    /// Sends the changes of training mode and the recorded key presses to TCP clients.
+    pub(crate) fn check_handle_training_messages(&mut self, _tx: &Option<Sender<ServerMessage>>) {
+        if self.training_messages.is_empty() {
+            return;
+        }
+        let _messages = std::mem::take(&mut self.training_messages);
+        #[cfg(feature = "tcp_server")]
+        if let Some(tx) = _tx {
+            for msg in _messages {
+                if let Err(error) = tx.try_send(msg) {
+                    tracing::error!("could not send event notification: {}", error);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! The typing speed, as words per minute and keys per second over the last seconds, for the
+//! TCP `RequestStats` command and, with `--typing-speed-interval-ms`, `TypingSpeed`
+//! notifications, e.g. for an on-screen display.
+//!
+//! Words per minute count the presses of keys that type characters, five per word as usual,
+//! and keys per second count the presses of every key. The keys are the keys that were pressed,
+//! before they are remapped.
+
+use super::*;
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+use std::time::Duration;
+use web_time::Instant;
+
+/// The presses that the speed is computed from.
+const WINDOW: Duration = Duration::from_secs(10);
+
+/// The speed is computed over at least this long, so that the first presses after a pause
+/// don't count as a burst.
+const MIN_SPAN: Duration = Duration::from_secs(1);
+
+const CHARS_PER_WORD: f32 = 5.0;
+
+/// At most this many presses are kept, which is 50 presses per second over the window. They are
+/// allocated up front so that recording a press does not allocate.
+const MAX_PRESSES: usize = 500;
+
+static PUSH_INTERVAL: OnceLock<Duration> = OnceLock::new();
+
+/// Sends the typing speed to TCP clients at this interval while keys are typed.
+pub fn enable_typing_speed_notifications(interval: Duration) {
+    let _ = PUSH_INTERVAL.set(interval);
+}
+
+#[derive(Debug)]
+pub struct TypingSpeed {
+    /// The presses in the window, with whether they typed a character.
+    presses: VecDeque<(Instant, bool)>,
+    pushed_at: Option<Instant>,
+    /// Whether the last notification had a speed, to send a last one when it drops to zero.
+    pushed_typing: bool,
+}
+
+impl Default for TypingSpeed {
+    fn default() -> Self {
+        Self {
+            presses: VecDeque::with_capacity(MAX_PRESSES),
+            pushed_at: None,
+            pushed_typing: false,
+        }
+    }
+}
+
+impl TypingSpeed {
+    fn record_press(&mut self, osc: OsCode, now: Instant) {
+        self.forget_old(now);
+        if self.presses.len() == MAX_PRESSES {
+            self.presses.pop_front();
+        }
+        self.presses
+            .push_back((now, KeyClass::of(osc).types_character()));
+    }
+
+    fn forget_old(&mut self, now: Instant) {
+        while self
+            .presses
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > WINDOW)
+        {
+            self.presses.pop_front();
+        }
+    }
+
+    /// Returns the words per minute and keys per second, rounded to one decimal.
+    pub fn speed(&mut self, now: Instant) -> (f32, f32) {
+        self.forget_old(now);
+        let Some((first, _)) = self.presses.front() else {
+            return (0.0, 0.0);
+        };
+        let span = now.duration_since(*first).clamp(MIN_SPAN, WINDOW);
+        let minutes = span.as_secs_f32() / 60.0;
+        let chars = self.presses.iter().filter(|(_, is_char)| *is_char).count() as f32;
+        let round = |speed: f32| (speed * 10.0).round() / 10.0;
+        (
+            round(chars / CHARS_PER_WORD / minutes),
+            round(self.presses.len() as f32 / span.as_secs_f32()),
+        )
+    }
+
+    /// Whether a notification is still to be sent, during which the processing loop must keep
+    /// ticking.
+    pub fn push_pending(&self) -> bool {
+        PUSH_INTERVAL.get().is_some() && (!self.presses.is_empty() || self.pushed_typing)
+    }
+
+    /// Returns the speed to notify clients of, if the interval passed since the last one.
+    fn due_push(&mut self, now: Instant) -> Option<(f32, f32)> {
+        let interval = *PUSH_INTERVAL.get()?;
+        if !self.push_pending()
+            || self
+                .pushed_at
+                .is_some_and(|at| now.duration_since(at) < interval)
+        {
+            return None;
+        }
+        let speed = self.speed(now);
+        self.pushed_at = Some(now);
+        self.pushed_typing = speed != (0.0, 0.0);
+        Some(speed)
+    }
+}
+
+impl Kanata {
+    pub(crate) fn record_typing_speed(&mut self, osc: OsCode) {
+        if !osc.is_mouse_code() {
+            self.typing_speed.record_press(osc, clock::now());
+        }
+    }
+
+    /// Sends `TypingSpeed` to TCP clients at the interval of `--typing-speed-interval-ms`.
+    pub(crate) fn check_push_typing_speed(&mut self, _tx: &Option<Sender<ServerMessage>>) {
+        let Some((_wpm, _keys_per_second)) = self.typing_speed.due_push(clock::now()) else {
+            return;
+        };
+        #[cfg(feature = "tcp_server")]
+        if let Some(tx) = _tx {
+            let msg = ServerMessage::TypingSpeed {
+                wpm: _wpm,
+                keys_per_second: _keys_per_second,
+            };
+            if let Err(error) = tx.try_send(msg) {
+                tracing::error!("could not send event notification: {}", error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_speed_is_computed_over_the_window() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut speed = TypingSpeed::default();
+        assert_eq!(speed.speed(start), (0.0, 0.0));
+        // 10 characters and 2 other keys in 2 seconds.
+        for i in 0..10 {
+            speed.record_press(OsCode::KEY_A, at(i * 200));
+        }
+        speed.record_press(OsCode::KEY_LEFTSHIFT, at(1000));
+        speed.record_press(OsCode::KEY_BACKSPACE, at(1000));
+        assert_eq!(speed.speed(at(2000)), (60.0, 6.0));
+        // The first presses after a pause count over at least a second.
+        assert_eq!(speed.speed(at(12_100)), (0.0, 0.0));
+        speed.record_press(OsCode::KEY_SPACE, at(12_100));
+        assert_eq!(speed.speed(at(12_200)), (12.0, 1.0));
+    }
+}
@@ -0,0 +1,571 @@
+//! `--per-user-cfg`: when kanata runs as a system service, it loads the configuration of the user
+//! of the active session from their home directory, and switches when another user becomes
+//! active, e.g. with fast user switching.
+//!
+//! The active user is polled, from the state files of logind on Linux and from the console session
+//! on Windows. Without an active user, e.g. at the login screen, or if the user has no
+//! configuration, the first configuration of `-c` is loaded.
+//!
+//! Kanata may run as root and parse errors show the text of the files, so the configuration of a
+//! user and the files that it includes are only read if they belong to that user, see
+//! [`read_user_file`].
+
+use super::*;
+use std::path::Path;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The configurations that were loaded from the home directory of a user, with that user.
+static USER_CFGS: Mutex<Vec<(PathBuf, ActiveUser)>> = Mutex::new(Vec::new());
+
+/// The user of the active session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ActiveUser {
+    name: String,
+    home: PathBuf,
+    #[cfg(target_os = "linux")]
+    uid: u32,
+}
+
+/// The user whose home directory the configuration was loaded from, if it is the configuration
+/// of a user.
+pub(super) fn user_of_cfg(path: &Path) -> Option<ActiveUser> {
+    USER_CFGS
+        .lock()
+        .iter()
+        .find(|(p, _)| p == path)
+        .map(|(_, user)| user.clone())
+}
+
+/// Reads a file of the configuration of the user, which must belong to them: on Linux the file
+/// must be owned by the user, and on Windows it must be in their profile directory.
+pub(super) fn read_user_file(
+    path: &Path,
+    user: &ActiveUser,
+) -> std::result::Result<String, String> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::io::Read;
+        use std::os::unix::fs::MetadataExt;
+
+        // The owner is checked on the opened file, which can't be replaced in between.
+        let mut file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        if file.metadata().map_err(|e| e.to_string())?.uid() != user.uid {
+            return Err(format!(
+                "{} does not belong to {}",
+                path.display(),
+                user.name
+            ));
+        }
+        let mut text = String::new();
+        file.read_to_string(&mut text).map_err(|e| e.to_string())?;
+        Ok(text)
+    }
+    #[cfg(target_os = "windows")]
+    {
+        if let Some(e) = user_file_error(path, user) {
+            return Err(format!("{} {e}", path.display()));
+        }
+        std::fs::read_to_string(path).map_err(|e| e.to_string())
+    }
+}
+
+/// Why the file must not be read for the configuration of the user, if it must not.
+fn user_file_error(path: &Path, user: &ActiveUser) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        match std::fs::metadata(path) {
+            Ok(metadata) if metadata.uid() == user.uid => None,
+            Ok(_) => Some(format!("does not belong to {}", user.name)),
+            Err(e) => Some(format!("can't be read: {e}")),
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        let (Ok(path), Ok(home)) = (path.canonicalize(), user.home.canonicalize()) else {
+            return Some("can't be read".to_owned());
+        };
+        (!path.starts_with(home))
+            .then(|| format!("is not in the profile directory of {}", user.name))
+    }
+}
+
+impl Kanata {
+    /// Starts the thread that loads the configuration at `cfg_path` in the home directory of the
+    /// active user.
+    pub fn start_user_session_watcher(
+        kanata: Arc<Mutex<Self>>,
+        wakeup: EventSender,
+        cfg_path: PathBuf,
+    ) {
+        #[cfg(target_os = "linux")]
+        let seat = kanata
+            .lock()
+            .seat
+            .clone()
+            .unwrap_or_else(|| "seat0".to_owned());
+        info!(
+            "loading {} from the home directory of the active user",
+            cfg_path.display()
+        );
+        let spawned = std::thread::Builder::new()
+            .name("user-session".into())
+            .spawn(move || {
+                let mut loaded: Option<PathBuf> = None;
+                loop {
+                    #[cfg(target_os = "linux")]
+                    let user = active_user(&seat);
+                    #[cfg(target_os = "windows")]
+                    let user = active_user();
+                    let wanted = user.as_ref().and_then(|user| {
+                        let path = user.home.join(&cfg_path);
+                        path.is_file().then_some(path)
+                    });
+                    if wanted != loaded {
+                        match (&user, &wanted) {
+                            (Some(user), Some(_)) => {
+                                info!("{} is active, loading their configuration", user.name)
+                            }
+                            (Some(user), None) => info!(
+                                "{} is active and has no configuration, loading the default one",
+                                user.name
+                            ),
+                            (None, _) => {
+                                info!("no user is active, loading the default configuration")
+                            }
+                        }
+                        load_user_cfg(&mut kanata.lock(), wanted.as_deref().zip(user.as_ref()));
+                        loaded = wanted;
+                        // The processing loop may be waiting for input, and the channel being
+                        // full means that it will wake up anyway.
+                        let _ =
+                            wakeup.try_send(KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp));
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            });
+        if let Err(e) = spawned {
+            tracing::error!("could not watch the active user: {e}");
+        }
+    }
+}
+
+/// Requests a reload of the configuration of the user, or of the first one of `-c` without one.
+fn load_user_cfg(k: &mut Kanata, cfg: Option<(&Path, &ActiveUser)>) {
+    let Some((path, user)) = cfg else {
+        let _ = k.request_live_reload_num(0);
+        return;
+    };
+    let index = match k.cfg_paths.iter().position(|p| p == path) {
+        Some(index) => index,
+        None => {
+            k.cfg_paths.push(path.to_owned());
+            k.cfg_paths.len() - 1
+        }
+    };
+    let mut user_cfgs = USER_CFGS.lock();
+    match user_cfgs.iter_mut().find(|(p, _)| p == path) {
+        Some((_, owner)) => *owner = user.clone(),
+        None => user_cfgs.push((path.to_owned(), user.clone())),
+    }
+    drop(user_cfgs);
+    let _ = k.request_live_reload_num(index);
+}
+
+/// The `defcfg` options that the configuration of a user can set. The others name devices, files,
+/// network addresses or commands, which kanata would open or run with its own rights, or change
+/// how kanata runs for every user. `fallback-cfg`, `midi-output-port` and the sounds are checked
+/// by `check_user_cfg`.
+const USER_CFG_OPTIONS: &[&str] = &[
+    "sequence-timeout",
+    "sequence-input-mode",
+    "sequence-always-on",
+    "sequence-backtrack-modcancel",
+    "dynamic-macro-max-presses",
+    "dynamic-macro-replay-delay-behaviour",
+    "linux-unicode-u-code",
+    "linux-unicode-termination",
+    "linux-x11-repeat-delay-rate",
+    "linux-use-trackpoint-property",
+    "linux-output-absolute-pointer",
+    "linux-layer-leds",
+    "windows-altgr",
+    "windows-scancode-overrides",
+    "windows-sync-keystates",
+    "icon-match-layer-name",
+    "tooltip-layer-changes",
+    "tooltip-show-blank",
+    "tooltip-no-base",
+    "tooltip-duration",
+    "tooltip-size",
+    "notify-cfg-reload",
+    "notify-cfg-reload-silent",
+    "notify-error",
+    "process-unmapped-keys",
+    "process-unmapped-mouse-buttons",
+    "block-unmapped-keys",
+    "allow-hardware-repeat",
+    "alias-to-trigger-on-load",
+    "log-layer-changes",
+    "delegate-to-first-layer",
+    "layout-translation",
+    "movemouse-smooth-diagonals",
+    "movemouse-inherit-accel-state",
+    "override-release-on-activation",
+    "preserve-release-order",
+    "concurrent-tap-hold",
+    "rapid-event-delay",
+    "stuck-key-timeout",
+    "dedupe-input-ms",
+    "transparent-key-resolution",
+    "chords-v2-min-idle",
+    "chords-v2-min-idle-experimental",
+    "tap-hold-require-prior-idle",
+    "tap-hold-key-timeouts",
+    "openrgb-layer-colors",
+    "emergency-exit-chord",
+    "emergency-passthrough-chord",
+    "emergency-reengage-chord",
+    "mouse-movement-key",
+    "fallback-cfg",
+    "midi-output-port",
+    "sound-layer-change",
+    "sound-caps-word",
+    "sound-sequence-timeout",
+];
+
+/// Fails for the configuration of a user that uses what runs with the rights of kanata, e.g. as
+/// root: only the options of `USER_CFG_OPTIONS` can be set, cmd actions and plugins run code,
+/// secrets files may only be readable by kanata, and webhooks and `secret-type` can send secrets
+/// elsewhere. Like included files, the fallback configuration and sound files must belong to the
+/// user, and a MIDI port given as a path must be a device of `/dev/snd`.
+pub(super) fn check_user_cfg(path: &Path, cfg: &Cfg) -> Result<()> {
+    let Some(user) = user_of_cfg(path) else {
+        return Ok(());
+    };
+    let options = &cfg.options;
+    if let Some(option) = options
+        .defined_options
+        .iter()
+        .find(|option| !USER_CFG_OPTIONS.contains(&option.as_str()))
+    {
+        bail!(
+            "{} is the configuration of a user, which cannot set {option}",
+            path.display()
+        );
+    }
+    if let Some(fallback) = fallback_cfg_path(path, Some(options))
+        && let Some(e) = user_file_error(&fallback, &user)
+    {
+        bail!(
+            "{} is the configuration of a user, its fallback-cfg {} {e}",
+            path.display(),
+            fallback.display()
+        );
+    }
+    if let Some(port) = &options.midi_output_port
+        && port.starts_with('/')
+        && !is_sound_device(Path::new(port))
+    {
+        bail!(
+            "{} is the configuration of a user, its midi-output-port {port} is not a device of /dev/snd",
+            path.display()
+        );
+    }
+    let sounds = [
+        ("sound-layer-change", &options.sound_layer_change),
+        ("sound-caps-word", &options.sound_caps_word),
+        ("sound-sequence-timeout", &options.sound_sequence_timeout),
+    ];
+    for (option, sound) in sounds {
+        if let Some(SoundCue::File(file)) = sound
+            && let Some(e) = user_file_error(Path::new(file), &user)
+        {
+            bail!(
+                "{} is the configuration of a user, its {option} {file} {e}",
+                path.display()
+            );
+        }
+    }
+    let denied = if options.enable_cmd || options.cmd_allowlist.is_some() {
+        "enable cmd actions"
+    } else if options.enable_secrets {
+        "enable danger-enable-secrets"
+    } else if cfg
+        .secrets
+        .iter()
+        .any(|secret| matches!(secret.source, SecretSource::File(_)))
+    {
+        "read secrets from files"
+    } else if !cfg.webhooks.is_empty() {
+        "use defwebhooks"
+    } else if !cfg.plugins.is_empty() {
+        "use defplugins"
+    } else {
+        return Ok(());
+    };
+    bail!(
+        "{} is the configuration of a user, which cannot {denied}",
+        path.display()
+    );
+}
+
+/// Whether the path is in `/dev/snd`, without `..` that would lead out of it.
+fn is_sound_device(path: &Path) -> bool {
+    path.starts_with("/dev/snd")
+        && !path
+            .components()
+            .any(|c| matches!(c, std::path::Component::ParentDir))
+}
+
+/// The user of the active session of the seat, from the state file of logind that
+/// `sd_seat_get_active` also reads.
+#[cfg(target_os = "linux")]
+fn active_user(seat: &str) -> Option<ActiveUser> {
+    let state = std::fs::read_to_string(format!("/run/systemd/seats/{seat}")).ok()?;
+    user_of_uid(active_uid(&state)?)
+}
+
+#[cfg(target_os = "linux")]
+fn active_uid(seat_state: &str) -> Option<u32> {
+    seat_state
+        .lines()
+        .find_map(|line| line.strip_prefix("ACTIVE_UID="))?
+        .parse()
+        .ok()
+}
+
+#[cfg(target_os = "linux")]
+fn user_of_uid(uid: u32) -> Option<ActiveUser> {
+    use std::ffi::{CStr, OsStr};
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let rc =
+        unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    let (name, home) = unsafe {
+        (
+            CStr::from_ptr(passwd.pw_name),
+            CStr::from_ptr(passwd.pw_dir),
+        )
+    };
+    Some(ActiveUser {
+        name: name.to_string_lossy().into_owned(),
+        home: PathBuf::from(OsStr::from_bytes(home.to_bytes())),
+        uid,
+    })
+}
+
+/// The user of the console session, whose token only a service running as SYSTEM can get.
+#[cfg(target_os = "windows")]
+fn active_user() -> Option<ActiveUser> {
+    use std::os::windows::ffi::OsStringExt;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::userenv::GetUserProfileDirectoryW;
+    use winapi::um::winbase::WTSGetActiveConsoleSessionId;
+    use winapi::um::wtsapi32::WTSQueryUserToken;
+
+    let session = unsafe { WTSGetActiveConsoleSessionId() };
+    if session == u32::MAX {
+        return None;
+    }
+    let mut token = std::ptr::null_mut();
+    // Fails without a user in the session, e.g. at the login screen.
+    if unsafe { WTSQueryUserToken(session, &mut token) } == 0 {
+        return None;
+    }
+    let mut buf = [0u16; 1024];
+    let mut len = buf.len() as u32;
+    let found = unsafe { GetUserProfileDirectoryW(token, buf.as_mut_ptr(), &mut len) };
+    unsafe { CloseHandle(token) };
+    if found == 0 {
+        return None;
+    }
+    let home = PathBuf::from(std::ffi::OsString::from_wide(
+        &buf[..len.saturating_sub(1) as usize],
+    ));
+    Some(ActiveUser {
+        name: home
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        home,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn active_uid_of_seat() {
+        let state = "# This is private data. Do not parse.\nIS_SEAT0=1\nCAN_MULTI_SESSION=1\n\
+                     ACTIVE=3\nACTIVE_UID=1000\nSESSIONS=3 c1\nUIDS=1000 120\n";
+        assert_eq!(active_uid(state), Some(1000));
+        assert_eq!(active_uid("IS_SEAT0=1\nSESSIONS=c1\n"), None);
+        assert_eq!(
+            user_of_uid(0).map(|user| user.name),
+            Some("root".to_owned())
+        );
+    }
+
+    fn someone() -> ActiveUser {
+        ActiveUser {
+            name: "someone".to_owned(),
+            home: PathBuf::from("/home/someone"),
+            #[cfg(target_os = "linux")]
+            uid: unsafe { libc::geteuid() } + 1,
+        }
+    }
+
+    fn user_cfg(name: &str) -> PathBuf {
+        let path = PathBuf::from(format!("/home/someone/.config/kanata/{name}.kbd"));
+        USER_CFGS.lock().push((path.clone(), someone()));
+        path
+    }
+
+    fn parse(cfg: &str) -> Cfg {
+        let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        cfg::new_from_str(cfg, Default::default()).expect("failed to parse cfg")
+    }
+
+    #[test]
+    fn user_cfgs_cannot_enable_cmd() {
+        let path = Path::new("/home/someone/.config/kanata/kanata.kbd");
+        let mut cfg = parse("(defsrc a) (deflayer base a)");
+        cfg.options.enable_cmd = true;
+        assert!(check_user_cfg(path, &cfg).is_ok());
+        USER_CFGS.lock().push((path.to_owned(), someone()));
+        assert!(check_user_cfg(path, &cfg).is_err());
+        assert!(check_user_cfg(path, &parse("(defsrc a) (deflayer base a)")).is_ok());
+    }
+
+    #[test]
+    fn user_cfgs_cannot_enable_secrets() {
+        let mut cfg = parse("(defsrc a) (deflayer base a)");
+        cfg.options.enable_secrets = true;
+        assert!(check_user_cfg(&user_cfg("secrets"), &cfg).is_err());
+    }
+
+    #[test]
+    fn user_cfgs_cannot_read_secret_files() {
+        let cfg = parse(
+            "(defsecrets token (file /etc/kanata/token) other credential-store)
+             (defsrc a) (deflayer base a)",
+        );
+        assert!(check_user_cfg(&user_cfg("secret-files"), &cfg).is_err());
+        let cfg = parse("(defsecrets other credential-store) (defsrc a) (deflayer base a)");
+        assert!(check_user_cfg(&user_cfg("secret-store"), &cfg).is_ok());
+    }
+
+    #[test]
+    fn user_cfgs_cannot_use_webhooks() {
+        let cfg = parse(
+            "(defwebhooks ha http://127.0.0.1:8123/api/kanata (reload))
+             (defsrc a) (deflayer base a)",
+        );
+        assert!(check_user_cfg(&user_cfg("webhooks"), &cfg).is_err());
+    }
+
+    #[test]
+    fn user_cfgs_cannot_use_plugins() {
+        let mut cfg = parse("(defsrc a) (deflayer base a)");
+        cfg.plugins.push(PluginGrant {
+            name: "plugin".to_owned(),
+            capabilities: Default::default(),
+        });
+        assert!(check_user_cfg(&user_cfg("plugins"), &cfg).is_err());
+    }
+
+    #[test]
+    fn user_cfgs_only_set_allowed_options() {
+        let cfg = parse("(defcfg midi-output-port /etc/shadow) (defsrc a) (deflayer base a)");
+        let e = check_user_cfg(&user_cfg("midi-file"), &cfg).unwrap_err();
+        assert!(e.to_string().contains("/etc/shadow"), "{e}");
+        let cfg = parse(
+            "(defcfg midi-output-port /dev/snd/../../etc/shadow) (defsrc a) (deflayer base a)",
+        );
+        assert!(check_user_cfg(&user_cfg("midi-escape"), &cfg).is_err());
+        let cfg = parse("(defcfg midi-output-port /dev/snd/midiC1D0) (defsrc a) (deflayer base a)");
+        assert!(check_user_cfg(&user_cfg("midi-device"), &cfg).is_ok());
+        let cfg = parse("(defcfg midi-output-port midiC1D0) (defsrc a) (deflayer base a)");
+        assert!(check_user_cfg(&user_cfg("midi-name"), &cfg).is_ok());
+
+        for option in [
+            "linux-dev /dev/input/event0",
+            "obs-websocket-address 127.0.0.1:4455",
+            "realtime-priority yes",
+        ] {
+            let cfg = parse(&format!("(defcfg {option}) (defsrc a) (deflayer base a)"));
+            let e = check_user_cfg(&user_cfg("denied-option"), &cfg).unwrap_err();
+            let name = option.split(' ').next().unwrap();
+            assert!(e.to_string().contains(&format!("cannot set {name}")), "{e}");
+        }
+        let cfg = parse(
+            "(defcfg process-unmapped-keys yes concurrent-tap-hold yes) (defsrc a) (deflayer base a)",
+        );
+        assert!(check_user_cfg(&user_cfg("allowed-options"), &cfg).is_ok());
+
+        let cfg = parse("(defcfg sound-caps-word /etc/shadow) (defsrc a) (deflayer base a)");
+        let e = check_user_cfg(&user_cfg("sound-file"), &cfg).unwrap_err();
+        assert!(e.to_string().contains("sound-caps-word"), "{e}");
+        let cfg = parse("(defcfg sound-caps-word beep) (defsrc a) (deflayer base a)");
+        assert!(check_user_cfg(&user_cfg("sound-beep"), &cfg).is_ok());
+    }
+
+    /// Kanata runs as another user than `someone` here, so the files it writes belong to another
+    /// user like root's files for a user.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn user_cfgs_only_read_files_of_the_user() {
+        let dir = std::env::temp_dir().join(format!("kanata-user-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("private.kbd"), "(private hunter2").unwrap();
+        let cfg_text = "(defsrc a) (deflayer base a) (include private.kbd)";
+        std::fs::write(dir.join("kanata.kbd"), cfg_text).unwrap();
+        let path = dir.join("kanata.kbd");
+        let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // Includes parse errors that would show the text of the file.
+        let Err(e) = cfg::new_from_str_at_path(cfg_text, &path) else {
+            panic!("the included file does not parse");
+        };
+        assert!(format!("{e:?}").contains("hunter2"));
+
+        USER_CFGS.lock().push((path.clone(), someone()));
+        let (text, cfg) = parse_cfg_file(&path);
+        assert!(text.is_none());
+        let Err(e) = cfg else {
+            panic!("the configuration does not belong to the user");
+        };
+        assert!(e.to_string().contains("does not belong to someone"), "{e}");
+        let Err(e) = parse_cfg_text(cfg_text, &path) else {
+            panic!("the included file does not belong to the user");
+        };
+        assert!(!format!("{e:?}").contains("hunter2"), "{e:?}");
+        let help = e.help().map(|h| h.to_string()).unwrap_or_default();
+        assert!(help.contains("does not belong to someone"), "{help}");
+
+        let mut cfg = cfg::new_from_str("(defsrc a) (deflayer base a)", Default::default())
+            .expect("failed to parse cfg");
+        cfg.options.fallback_cfg = Some("private.kbd".to_owned());
+        let e = check_user_cfg(&path, &cfg).unwrap_err().to_string();
+        assert!(e.contains("fallback-cfg"), "{e}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+//! A watchdog that detects when the processing loop is stuck, e.g. in an output write to a hung
+//! uinput device or in a command that doesn't return.
+//!
+//! The processing loop marks when it starts working and when it waits for input, and output
+//! writes mark when they start and end. When either has been busy for longer than the timeout,
+//! the watchdog logs what it knows, notifies TCP clients and puts kanata into emergency
+//! passthrough, so that input reaches the OS without kanata. If the stall lasts for three
+//! timeouts, kanata exits so that the OS releases the grabbed devices. Once the processing loop
+//! makes progress again, the passthrough that the watchdog started ends.
+
+use super::*;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static TIMEOUT_MS: AtomicU64 = AtomicU64::new(10_000);
+
+/// Milliseconds since [`START`] at which the current output write started, or [`IDLE`].
+static OUTPUT_BUSY_SINCE: AtomicU64 = AtomicU64::new(IDLE);
+static START: OnceLock<web_time::Instant> = OnceLock::new();
+
+pub(super) const IDLE: u64 = u64::MAX;
+
+/// How many timeouts a stall lasts before kanata exits.
+const EXIT_AFTER_TIMEOUTS: u64 = 3;
+
+/// Sets the timeout of the watchdog. 0 disables it.
+pub fn set_watchdog_timeout(ms: u64) {
+    TIMEOUT_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Milliseconds since the first call.
+pub(super) fn now_ms() -> u64 {
+    START
+        .get_or_init(web_time::Instant::now)
+        .elapsed()
+        .as_millis() as u64
+}
+
+/// Marks that the processing loop is working.
+pub(super) fn watchdog_progress() {
+    PROCESSOR.busy();
+}
+
+/// Marks that the processing loop waits for input, which is not a stall however long it takes.
+pub(super) fn watchdog_idle() {
+    PROCESSOR.idle();
+}
+
+/// Stops the processing loop from counting as stalled while the guard lives, e.g. while a large
+/// configuration is parsed for a reload.
+pub(super) fn watchdog_pause() -> WatchdogPause {
+    watchdog_idle();
+    WatchdogPause
+}
+
+pub(super) struct WatchdogPause;
+
+impl Drop for WatchdogPause {
+    fn drop(&mut self) {
+        watchdog_progress();
+    }
+}
+
+/// Marks an output write for as long as the guard lives.
+pub(super) fn watchdog_output_write() -> OutputWrite {
+    OUTPUT_BUSY_SINCE.store(now_ms(), Ordering::Relaxed);
+    OutputWrite
+}
+
+pub(super) struct OutputWrite;
+
+impl Drop for OutputWrite {
+    fn drop(&mut self) {
+        OUTPUT_BUSY_SINCE.store(IDLE, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stall {
+    Output { ms: u64 },
+    Loop { ms: u64 },
+}
+
+impl Stall {
+    fn ms(self) -> u64 {
+        match self {
+            Stall::Output { ms } | Stall::Loop { ms } => ms,
+        }
+    }
+}
+
+impl std::fmt::Display for Stall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stall::Output { ms } => write!(f, "an output write has been blocked for {ms} ms"),
+            Stall::Loop { ms } => {
+                write!(f, "the processing loop has not made progress for {ms} ms")
+            }
+        }
+    }
+}
+
+/// The stall at the time, if the output or the loop was busy for longer than the timeout. A
+/// blocked output write is reported rather than the loop that waits for it.
+fn stall(now_ms: u64, loop_since: u64, output_since: u64, timeout_ms: u64) -> Option<Stall> {
+    let busy_ms = |since: u64| match since {
+        IDLE => None,
+        since => Some(now_ms.saturating_sub(since)).filter(|ms| *ms > timeout_ms),
+    };
+    busy_ms(output_since)
+        .map(|ms| Stall::Output { ms })
+        .or_else(|| busy_ms(loop_since).map(|ms| Stall::Loop { ms }))
+}
+
+impl Kanata {
+    /// Starts the watchdog thread of the processing loop, unless the watchdog is disabled.
+    pub(super) fn start_watchdog(kanata: Arc<Mutex<Self>>, tx: Option<Sender<ServerMessage>>) {
+        let timeout_ms = TIMEOUT_MS.load(Ordering::Relaxed);
+        if timeout_ms == 0 {
+            return;
+        }
+        watchdog_progress();
+        let check_interval = Duration::from_millis((timeout_ms / 4).clamp(10, 1000));
+        let spawned = std::thread::Builder::new()
+            .name("watchdog".into())
+            .spawn(move || {
+                let mut stalled = false;
+                let mut started_passthrough = false;
+                loop {
+                    std::thread::sleep(check_interval);
+                    let stall = stall(
+                        now_ms(),
+                        PROCESSOR.busy_since(),
+                        OUTPUT_BUSY_SINCE.load(Ordering::Relaxed),
+                        timeout_ms,
+                    );
+                    match (stall, stalled) {
+                        (Some(stall), false) => {
+                            stalled = true;
+                            started_passthrough = handle_stall(stall, &kanata, &tx);
+                        }
+                        (Some(stall), true) if stall.ms() > timeout_ms * EXIT_AFTER_TIMEOUTS => {
+                            tracing::error!("watchdog: {stall}, exiting to release the devices");
+                            write_crash_bundle(&format!("watchdog: {stall}"));
+                            flush_event_trace();
+                            std::process::exit(1);
+                        }
+                        (None, true) => {
+                            stalled = false;
+                            tracing::warn!("watchdog: the processing loop made progress again");
+                            if started_passthrough {
+                                set_emergency_passthrough(false);
+                                started_passthrough = false;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            tracing::error!("could not start the watchdog: {e}");
+        }
+    }
+}
+
+/// Logs the stall, notifies TCP clients and starts emergency passthrough. Returns whether the
+/// passthrough was started by this call.
+fn handle_stall(stall: Stall, kanata: &Mutex<Kanata>, _tx: &Option<Sender<ServerMessage>>) -> bool {
+    const LOCK_TIMEOUT: Duration = Duration::from_millis(100);
+    tracing::error!("watchdog: {stall}, passing input through to the OS");
+    match kanata.try_lock_for(LOCK_TIMEOUT) {
+        Some(mut k) => {
+            let layer = k.layout.bm().current_layer();
+            tracing::error!(
+                "watchdog: layer {}, {} active keys, {} key presses so far",
+                k.layer_info[layer].name,
+                k.cur_keys.len(),
+                k.key_presses,
+            );
+        }
+        None => tracing::error!("watchdog: the kanata state is locked by the stalled thread"),
+    }
+    match PRESSED_KEYS.try_lock_for(LOCK_TIMEOUT) {
+        Some(pressed) => tracing::error!("watchdog: physically pressed keys: {:?}", *pressed),
+        None => tracing::error!("watchdog: the pressed keys are locked by the stalled thread"),
+    }
+    write_crash_bundle(&format!("watchdog: {stall}"));
+    #[cfg(feature = "tcp_server")]
+    if let Some(tx) = _tx {
+        let msg = format!("watchdog: {stall}");
+        if let Err(e) = tx.try_send(ServerMessage::Error { msg }) {
+            tracing::error!("could not send event notification: {e}");
+        }
+    }
+    set_emergency_passthrough(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stall_after_the_timeout() {
+        assert_eq!(stall(5000, IDLE, IDLE, 1000), None);
+        assert_eq!(stall(5000, 4500, IDLE, 1000), None);
+        assert_eq!(
+            stall(5000, 3000, IDLE, 1000),
+            Some(Stall::Loop { ms: 2000 })
+        );
+        assert_eq!(
+            stall(5000, 1000, 3500, 1000),
+            Some(Stall::Output { ms: 1500 })
+        );
+        assert_eq!(
+            stall(5000, 1000, 4500, 1000),
+            Some(Stall::Loop { ms: 4000 })
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! HTTP POST requests to the webhooks of `defwebhooks`, for events and the `webhook` action.
+//!
+//! The requests are sent one at a time by a background thread, so that a slow or unreachable
+//! server never delays processing. A request that fails is logged and not retried.
+
+use super::*;
+use kanata_parser::cfg::{Webhook, WebhookEvent};
+use serde_json::json;
+
+pub(crate) struct Webhooks {
+    webhooks: Vec<Webhook>,
+    #[cfg(not(feature = "simulated_output"))]
+    requests: Option<std::sync::mpsc::Sender<(Webhook, serde_json::Value)>>,
+}
+
+impl Webhooks {
+    pub(crate) fn new(webhooks: Vec<Webhook>) -> Self {
+        Self {
+            webhooks,
+            #[cfg(not(feature = "simulated_output"))]
+            requests: None,
+        }
+    }
+
+    pub(crate) fn set_webhooks(&mut self, webhooks: Vec<Webhook>) {
+        self.webhooks = webhooks;
+    }
+
+    /// Whether any webhook is sent the event, to skip building the body otherwise.
+    pub(crate) fn has_event(&self, event: WebhookEvent) -> bool {
+        self.webhooks.iter().any(|w| w.events.contains(&event))
+    }
+
+    /// Sends the event to the webhooks that list it, with `fields` added to the body.
+    pub(crate) fn send_event(
+        &mut self,
+        kbd_out: &mut KbdOut,
+        event: WebhookEvent,
+        fields: serde_json::Value,
+    ) {
+        let mut body = json!({ "event": event.name() });
+        if let (Some(body), serde_json::Value::Object(fields)) = (body.as_object_mut(), fields) {
+            body.extend(fields);
+        }
+        for i in 0..self.webhooks.len() {
+            if self.webhooks[i].events.contains(&event) {
+                self.send(kbd_out, i, body.clone());
+            }
+        }
+    }
+
+    /// Sends the `webhook` action to the named webhook.
+    pub(crate) fn send_action(&mut self, kbd_out: &mut KbdOut, name: &str, data: Option<&str>) {
+        let Some(i) = self.webhooks.iter().position(|w| w.name == name) else {
+            // The parser checks the name, so this only happens if the action outlives a reload.
+            tracing::warn!("webhook {name} is not defined");
+            return;
+        };
+        let mut body = json!({ "event": "action" });
+        if let Some(data) = data {
+            body["data"] = json!(data);
+        }
+        self.send(kbd_out, i, body);
+    }
+
+    fn send(&mut self, _kbd_out: &mut KbdOut, i: usize, body: serde_json::Value) {
+        let webhook = &self.webhooks[i];
+        tracing::debug!("webhook {}: {body}", webhook.name);
+        #[cfg(feature = "simulated_output")]
+        _kbd_out.write_webhook(&webhook.name, body);
+        #[cfg(not(feature = "simulated_output"))]
+        {
+            let requests = self.requests.get_or_insert_with(|| {
+                let (tx, rx) = std::sync::mpsc::channel();
+                std::thread::spawn(move || {
+                    for (webhook, body) in rx {
+                        post(&webhook, &body);
+                    }
+                });
+                tx
+            });
+            if requests.send((webhook.clone(), body)).is_err() {
+                tracing::error!("the webhook thread stopped, dropping a request");
+                self.requests = None;
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "simulated_output"))]
+fn post(webhook: &Webhook, body: &serde_json::Value) {
+    match try_post(webhook, body) {
+        Ok(status) if status.split(' ').nth(1).is_some_and(|c| c.starts_with('2')) => {}
+        Ok(status) => tracing::warn!("webhook {} responded: {status}", webhook.url()),
+        Err(e) => tracing::error!("could not send webhook {}: {e}", webhook.url()),
+    }
+}
+
+/// Sends the request and returns the status line of the response.
+#[cfg(not(feature = "simulated_output"))]
+fn try_post(webhook: &Webhook, body: &serde_json::Value) -> std::io::Result<String> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpStream, ToSocketAddrs};
+
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+    let authorization = match &webhook.bearer {
+        Some(secret) => format!("Authorization: Bearer {}\r\n", read_secret(secret)?),
+        None => String::new(),
+    };
+    let mut last_error = std::io::Error::other("no address found");
+    let mut stream = None;
+    for address in webhook.address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(s) => {
+                stream = Some(s);
+                break;
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    let mut stream = stream.ok_or(last_error)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let body = body.to_string();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: kanata/{}\r\n\
+         {authorization}Content-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        webhook.path,
+        webhook.host,
+        env!("CARGO_PKG_VERSION"),
+        body.len(),
+    );
+    stream.write_all(request.as_bytes())?;
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    Ok(status.trim_end().to_owned())
+}
+
+#[cfg(all(test, not(feature = "simulated_output")))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn webhook_post_sends_json() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !String::from_utf8_lossy(&request).ends_with("}") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        let webhook = Webhook {
+            name: "test".into(),
+            address: format!("127.0.0.1:{port}"),
+            host: format!("localhost:{port}"),
+            path: "/hook?x=1".into(),
+            events: vec![],
+            bearer: None,
+        };
+        let status = try_post(&webhook, &json!({ "event": "action" })).unwrap();
+        assert_eq!(status, "HTTP/1.1 204 No Content");
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("POST /hook?x=1 HTTP/1.1\r\n"),
+            "{request}"
+        );
+        assert!(request.contains(&format!("\r\nHost: localhost:{port}\r\n")));
+        assert!(request.contains("\r\nContent-Length: 18\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"event\":\"action\"}"));
+    }
+}
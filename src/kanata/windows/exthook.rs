@@ -1,7 +1,7 @@
 use parking_lot::Mutex;
 use std::convert::TryFrom;
 use std::sync::Arc;
-use std::sync::mpsc::{Receiver, SyncSender as Sender, TryRecvError, sync_channel};
+use std::sync::mpsc::TryRecvError;
 use std::time;
 
 use super::PRESSED_KEYS;
@@ -9,24 +9,31 @@ use crate::kanata::*;
 
 impl Kanata {
     /// Initialize the callback that is passed to the Windows low level hook to receive key events and run the native_windows_gui event loop.
-    pub fn event_loop(_cfg: Arc<Mutex<Self>>, tx: Sender<KeyEvent>) -> Result<()> {
-        let (preprocess_tx, preprocess_rx) = sync_channel(100);
+    pub fn event_loop(kanata: Arc<Mutex<Self>>, tx: EventSender) -> Result<()> {
+        if kanata.lock().realtime_priority {
+            raise_thread_priority("input");
+        }
+        let (preprocess_tx, preprocess_rx) = event_queue(100);
         start_event_preprocessor(preprocess_rx, tx);
 
+        // The hook outlives this function, and the guard lives with its callback.
+        let running = INPUT_READER.running();
         let _ = KeyboardHook::set_input_cb(move |input_event| {
+            let _running = &running;
+            INPUT_READER.active();
             // →true if input event was handled, false otherwise, informs input_ev_listener whether to look for the output key event
             let mut key_event = match KeyEvent::try_from(input_event) {
                 // InputEvent{code:u32      , up   :bool}
                 Ok(ev) => ev, // KeyEvent  {code:OsCode   , value:KeyValue}
                 _ => return false,
             }; // Some(OsCode::KEY_0)←0x30        Release0 Press1 Repeat2 Tap WakeUp
-            check_for_exit(&key_event); //noop
+            check_emergency_chords(&key_event); //noop
 
             let oscode = OsCode::from(input_event.code);
             if !MAPPED_KEYS.lock().contains(&oscode) {
                 return false;
             }
-            log::debug!("event loop: {}", key_event);
+            tracing::debug!("event loop: {}", key_event);
             match key_event.value {
                 // Unlike Linux, Windows does not use a separate value for repeat. However, our code needs to differentiate between initial press and repeat press.
                 KeyValue::Release => {
@@ -54,13 +61,13 @@ impl Kanata {
     }
 }
 
-fn try_send_panic(tx: &Sender<KeyEvent>, kev: KeyEvent) {
+fn try_send_panic(tx: &EventSender, kev: KeyEvent) {
     if let Err(e) = tx.try_send(kev) {
         panic!("failed to send on channel: {e:?}")
     }
 }
 
-fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sender<KeyEvent>) {
+fn start_event_preprocessor(preprocess_rx: EventReceiver, process_tx: EventSender) {
     #[derive(Debug, Clone, Copy, PartialEq)]
     enum LctlState {
         Pressed,
@@ -74,7 +81,7 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
         let mut lctl_state = LctlState::None;
         loop {
             match preprocess_rx.try_recv() {
-                Ok(kev) => match (*ALTGR_BEHAVIOUR.lock(), kev) {
+                Ok(kev) => match (altgr_behaviour(), kev) {
                     (AltGrBehaviour::DoNothing, _) => try_send_panic(&process_tx, kev),
                     (
                         AltGrBehaviour::AddLctlRelease,
@@ -84,7 +91,7 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                             ..
                         },
                     ) => {
-                        log::debug!("altgr add: adding lctl release");
+                        tracing::debug!("altgr add: adding lctl release");
                         try_send_panic(&process_tx, kev);
                         try_send_panic(
                             &process_tx,
@@ -100,7 +107,7 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                             ..
                         },
                     ) => {
-                        log::debug!("altgr cancel: lctl state->pressed");
+                        tracing::debug!("altgr cancel: lctl state->pressed");
                         lctl_state = LctlState::Pressed;
                     }
                     (
@@ -112,11 +119,11 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                         },
                     ) => match lctl_state {
                         LctlState::Pressed => {
-                            log::debug!("altgr cancel: lctl state->released");
+                            tracing::debug!("altgr cancel: lctl state->released");
                             lctl_state = LctlState::Released;
                         }
                         LctlState::Pending => {
-                            log::debug!("altgr cancel: lctl state->pending-released");
+                            tracing::debug!("altgr cancel: lctl state->pending-released");
                             lctl_state = LctlState::PendingReleased;
                         }
                         LctlState::None => try_send_panic(&process_tx, kev),
@@ -130,25 +137,28 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                             ..
                         },
                     ) => {
-                        log::debug!("altgr cancel: lctl state->none");
+                        tracing::debug!("altgr cancel: lctl state->none");
                         lctl_state = LctlState::None;
                         try_send_panic(&process_tx, kev);
                     }
                     (_, _) => try_send_panic(&process_tx, kev),
                 },
                 Err(TryRecvError::Empty) => {
-                    if *ALTGR_BEHAVIOUR.lock() == AltGrBehaviour::CancelLctlPress {
+                    // A pending lctl is sent even if the behaviour changed since, e.g. for an app.
+                    if altgr_behaviour() == AltGrBehaviour::CancelLctlPress
+                        || !matches!(lctl_state, LctlState::None)
+                    {
                         match lctl_state {
                             LctlState::Pressed => {
-                                log::debug!("altgr cancel: lctl state->pending");
+                                tracing::debug!("altgr cancel: lctl state->pending");
                                 lctl_state = LctlState::Pending;
                             }
                             LctlState::Released => {
-                                log::debug!("altgr cancel: lctl state->pending-released");
+                                tracing::debug!("altgr cancel: lctl state->pending-released");
                                 lctl_state = LctlState::PendingReleased;
                             }
                             LctlState::Pending => {
-                                log::debug!("altgr cancel: lctl state->send");
+                                tracing::debug!("altgr cancel: lctl state->send");
                                 try_send_panic(
                                     &process_tx,
                                     KeyEvent::new(OsCode::KEY_LEFTCTRL, KeyValue::Press),
@@ -156,7 +166,7 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                                 lctl_state = LctlState::None;
                             }
                             LctlState::PendingReleased => {
-                                log::debug!("altgr cancel: lctl state->send+release");
+                                tracing::debug!("altgr cancel: lctl state->send+release");
                                 try_send_panic(
                                     &process_tx,
                                     KeyEvent::new(OsCode::KEY_LEFTCTRL, KeyValue::Press),
@@ -2,7 +2,6 @@ use anyhow::{Result, anyhow};
 use kanata_interception as ic;
 use parking_lot::Mutex;
 use std::sync::Arc;
-use std::sync::mpsc::SyncSender as Sender;
 
 use super::PRESSED_KEYS;
 use crate::kanata::*;
@@ -10,7 +9,7 @@ use crate::oskbd::KeyValue;
 use kanata_parser::keys::OsCode;
 
 impl Kanata {
-    pub fn event_loop_inner(kanata: Arc<Mutex<Self>>, tx: Sender<KeyEvent>) -> Result<()> {
+    pub fn event_loop_inner(kanata: Arc<Mutex<Self>>, tx: EventSender) -> Result<()> {
         let intrcptn = ic::Interception::new().ok_or_else(|| anyhow!("interception driver should init: have you completed the interception driver installation?"))?;
         intrcptn.set_filter(ic::is_keyboard, ic::Filter::KeyFilter(ic::KeyFilter::all()));
         let mut strokes = [ic::Stroke::Keyboard {
@@ -19,6 +18,9 @@ impl Kanata {
             information: 0,
         }; 32];
 
+        if kanata.lock().realtime_priority {
+            raise_thread_priority("input");
+        }
         let keyboards_to_intercept_hwids = kanata.lock().intercept_kb_hwids.clone();
         let keyboards_to_intercept_hwids_exclude = kanata.lock().intercept_kb_hwids_exclude.clone();
         let mouse_to_intercept_hwids: Option<Vec<[u8; HWID_ARR_SZ]>> =
@@ -37,8 +39,11 @@ impl Kanata {
             }
         }
         let mut is_dev_interceptable: HashMap<ic::Device, bool> = HashMap::default();
+        let _running = INPUT_READER.running();
         loop {
+            INPUT_READER.idle();
             let dev = intrcptn.wait();
+            INPUT_READER.busy();
             if dev > 0 {
                 let num_strokes = intrcptn.receive(dev, &mut strokes) as usize;
                 for i in 0..num_strokes {
@@ -51,15 +56,15 @@ impl Kanata {
                                 &keyboards_to_intercept_hwids_exclude,
                                 &mut is_dev_interceptable,
                             ) {
-                                log::debug!("stroke {:?} is from undesired device", strokes[i]);
+                                tracing::debug!("stroke {:?} is from undesired device", strokes[i]);
                                 intrcptn.send(dev, &strokes[i..i + 1]);
                                 continue;
                             }
-                            log::debug!("got stroke {:?}", strokes[i]);
+                            tracing::debug!("got stroke {:?}", strokes[i]);
                             let code = match OsCodeWrapper::try_from(strokes[i]) {
                                 Ok(c) => c.0,
                                 _ => {
-                                    log::debug!("could not map code to oscode");
+                                    tracing::debug!("could not map code to oscode");
                                     intrcptn.send(dev, &strokes[i..i + 1]);
                                     continue;
                                 }
@@ -85,9 +90,10 @@ impl Kanata {
                             );
 
                             if allow_this_dev {
-                                log::trace!("checking mouse stroke {:?}", strokes[i]);
+                                tracing::trace!("checking mouse stroke {:?}", strokes[i]);
 
                                 if let Some(ms_mvmt_key) = *mouse_movement_key.lock()
+                                    && !is_emergency_passthrough_active()
                                     && flags.contains(ic::MouseFlags::MOVE_RELATIVE)
                                 {
                                     tx.try_send(KeyEvent::new(ms_mvmt_key, KeyValue::Tap))?;
@@ -104,13 +110,23 @@ impl Kanata {
                             }
                         }
                     };
-                    check_for_exit(&key_event);
+                    match check_emergency_chords(&key_event) {
+                        EmergencyChordCheck::Continue | EmergencyChordCheck::Exit => {}
+                        EmergencyChordCheck::Forward => {
+                            intrcptn.send(dev, &strokes[i..i + 1]);
+                            continue;
+                        }
+                        EmergencyChordCheck::Toggled => {
+                            tx.try_send(KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp))?;
+                            continue;
+                        }
+                    }
                     if !MAPPED_KEYS.lock().contains(&key_event.code) {
-                        log::debug!("{key_event:?} is not mapped");
+                        tracing::debug!("{key_event:?} is not mapped");
                         intrcptn.send(dev, &strokes[i..i + 1]);
                         continue;
                     }
-                    log::debug!("sending {key_event:?} to processing loop");
+                    tracing::debug!("sending {key_event:?} to processing loop");
                     match key_event.value {
                         KeyValue::Release => {
                             PRESSED_KEYS.lock().remove(&key_event.code);
@@ -132,7 +148,7 @@ impl Kanata {
     }
     pub fn event_loop(
         kanata: Arc<Mutex<Self>>,
-        tx: Sender<KeyEvent>,
+        tx: EventSender,
         #[cfg(feature = "gui")] ui: crate::gui::system_tray_ui::SystemTrayUi,
     ) -> Result<()> {
         #[cfg(not(feature = "gui"))]
@@ -162,10 +178,10 @@ fn is_device_interceptable(
             Some(v) => *v,
             None => {
                 let mut hwid = [0u8; HWID_ARR_SZ];
-                log::trace!("getting hardware id for input dev: {input_dev}");
+                tracing::trace!("getting hardware id for input dev: {input_dev}");
                 let res = intrcptn.get_hardware_id(input_dev, &mut hwid);
                 let dev_is_interceptable = allowed.contains(&hwid);
-                log::info!(
+                tracing::info!(
                     "include check - res {res}; device #{input_dev} is intercepted: {dev_is_interceptable}; hwid {hwid:?} "
                 );
                 cache.insert(input_dev, dev_is_interceptable);
@@ -176,10 +192,10 @@ fn is_device_interceptable(
             Some(v) => *v,
             None => {
                 let mut hwid = [0u8; HWID_ARR_SZ];
-                log::trace!("getting hardware id for input dev: {input_dev}");
+                tracing::trace!("getting hardware id for input dev: {input_dev}");
                 let res = intrcptn.get_hardware_id(input_dev, &mut hwid);
                 let dev_is_interceptable = !excluded.contains(&hwid);
-                log::info!(
+                tracing::info!(
                     "exclude check - res {res}; device #{input_dev} is intercepted: {dev_is_interceptable}; hwid {hwid:?} "
                 );
                 cache.insert(input_dev, dev_is_interceptable);
@@ -1,7 +1,7 @@
 use parking_lot::Mutex;
 use std::convert::TryFrom;
 use std::sync::Arc;
-use std::sync::mpsc::{Receiver, RecvError, SyncSender as Sender, TryRecvError, sync_channel};
+use std::sync::mpsc::{RecvError, TryRecvError};
 use std::time;
 
 use super::PRESSED_KEYS;
@@ -11,8 +11,8 @@ impl Kanata {
     /// Initialize the callback that is passed to the Windows low level hook to receive key events
     /// and run the native_windows_gui event loop.
     pub fn event_loop(
-        _cfg: Arc<Mutex<Self>>,
-        tx: Sender<KeyEvent>,
+        kanata: Arc<Mutex<Self>>,
+        tx: EventSender,
         #[cfg(all(target_os = "windows", feature = "gui"))]
         ui: crate::gui::system_tray_ui::SystemTrayUi,
     ) -> Result<()> {
@@ -25,7 +25,11 @@ impl Kanata {
             }
         };
 
-        let (preprocess_tx, preprocess_rx) = sync_channel(100);
+        if kanata.lock().realtime_priority {
+            raise_thread_priority("input");
+        }
+
+        let (preprocess_tx, preprocess_rx) = event_queue(100);
         start_event_preprocessor(preprocess_rx, tx);
         let kb_preprocess_tx = preprocess_tx.clone();
 
@@ -33,13 +37,25 @@ impl Kanata {
         // callback and `true` if the input event **is** handled by the callback. Returning false
         // informs the callback caller that the input event should be handed back to the OS for
         // normal processing.
+        let _running = INPUT_READER.running();
         let _kbhook = KeyboardHook::set_input_cb(move |input_event| {
+            INPUT_READER.active();
             let mut key_event = match KeyEvent::try_from(input_event) {
                 Ok(ev) => ev,
                 _ => return false,
             };
 
-            check_for_exit(&key_event);
+            match check_emergency_chords(&key_event) {
+                EmergencyChordCheck::Continue | EmergencyChordCheck::Exit => {}
+                EmergencyChordCheck::Forward => return false,
+                EmergencyChordCheck::Toggled => {
+                    try_send_panic(
+                        &kb_preprocess_tx,
+                        KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp),
+                    );
+                    return true;
+                }
+            }
             let oscode = key_event.code;
             if !MAPPED_KEYS.lock().contains(&oscode) {
                 return false;
@@ -47,10 +63,10 @@ impl Kanata {
 
             // Unlike Linux, Windows does not use a separate value for repeat. However, our code
             // needs to differentiate between initial press and repeat press.
-            log::debug!("event loop: {:?}", key_event);
+            tracing::debug!("event loop: {:?}", key_event);
             match key_event.value {
                 KeyValue::Release if PRESSED_KEYS.lock().remove(&key_event.code).is_none() => {
-                    log::debug!("forwarding release of never-seen press {:?}", key_event);
+                    tracing::debug!("forwarding release of never-seen press {:?}", key_event);
                     // If we never saw the initial press for this release,
                     // we should not pass it along to the processing loop.
                     // There is likely a pressed key in Windows that Kanata never knew about.
@@ -95,25 +111,36 @@ impl Kanata {
             .iter()
             .any(|osc| MAPPED_KEYS.lock().contains(osc))
         {
-            log::info!("Installing mouse hook callback.");
+            tracing::info!("Installing mouse hook callback.");
             let mousehook = MouseHook::set_input_cb(move |mouse_event| {
-                log::debug!("llhook mouse event: {mouse_event:?}");
+                tracing::debug!("llhook mouse event: {mouse_event:?}");
                 let key_event = match KeyEvent::try_from(mouse_event) {
                     Ok(ev) => ev,
                     _ => return false,
                 };
+                match check_emergency_chords(&key_event) {
+                    EmergencyChordCheck::Continue | EmergencyChordCheck::Exit => {}
+                    EmergencyChordCheck::Forward => return false,
+                    EmergencyChordCheck::Toggled => {
+                        try_send_panic(
+                            &preprocess_tx,
+                            KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp),
+                        );
+                        return true;
+                    }
+                }
                 let oscode = key_event.code;
                 if !MAPPED_KEYS.lock().contains(&oscode) {
                     return false;
                 }
-                log::debug!("event loop - mouse: {:?}", key_event);
+                tracing::debug!("event loop - mouse: {:?}", key_event);
                 try_send_panic(&preprocess_tx, key_event);
                 true
             });
-            log::info!("Installed mouse hook callback successfully.");
+            tracing::info!("Installed mouse hook callback successfully.");
             Some(mousehook)
         } else {
-            log::info!("No mouse inputs were in defsrc on startup. Not activating mouse hook.");
+            tracing::info!("No mouse inputs were in defsrc on startup. Not activating mouse hook.");
             None
         };
 
@@ -197,7 +224,7 @@ impl Kanata {
         // Revisiting it later, it should be reasonably safe to always run this,
         // because releasing a Kanata state should not interfere with other programs
         // in contrast to releasing Windows VK codes which could.
-        log::debug!("synchronizing kanata keystates with windows");
+        tracing::debug!("synchronizing kanata keystates with windows");
         for pvk in self.prev_keys.iter() {
             // Check 1 : each pvk is expected to be pressed.
             let osc: OsCode = pvk.into();
@@ -212,7 +239,7 @@ impl Kanata {
                 continue;
             }
 
-            log::error!(
+            tracing::error!(
                 "Unexpected keycode is pressed in kanata but not in Windows. Clearing kanata states: {pvk:?}"
             );
             // Need to clear internal state about this key.
@@ -252,7 +279,7 @@ impl Kanata {
             return;
         }
 
-        log::debug!("releasing windows keystates with kanata");
+        tracing::debug!("releasing windows keystates with kanata");
         let mapped_keys = MAPPED_KEYS.lock();
         for mapped_osc in mapped_keys.iter().copied() {
             // Check 2: each active win vk mapped in Kanata should have a value in pvk
@@ -283,7 +310,7 @@ impl Kanata {
             if self.prev_keys.contains(&osc.into()) {
                 continue;
             }
-            log::error!(
+            tracing::error!(
                 "Unexpected keycode is pressed in Windows but not Kanata. Releasing in Windows: {osc}"
             );
             let _ = release_key(&mut self.kbd_out, osc);
@@ -292,7 +319,7 @@ impl Kanata {
     }
 }
 
-fn try_send_panic(tx: &Sender<KeyEvent>, kev: KeyEvent) {
+fn try_send_panic(tx: &EventSender, kev: KeyEvent) {
     if let Err(e) = tx.try_send(kev) {
         panic!("failed to send on channel: {e:?}")
     }
@@ -319,7 +346,7 @@ enum CanBlock {
     NoMustPeriodicPoll,
 }
 
-fn preprocessor_recv(preprocess_rx: &Receiver<KeyEvent>, can_block: CanBlock) -> RecvValue {
+fn preprocessor_recv(preprocess_rx: &EventReceiver, can_block: CanBlock) -> RecvValue {
     match can_block {
         CanBlock::YesCanBlock => match preprocess_rx.recv() {
             Ok(kev) => RecvValue::Ok(kev),
@@ -333,7 +360,7 @@ fn preprocessor_recv(preprocess_rx: &Receiver<KeyEvent>, can_block: CanBlock) ->
     }
 }
 
-fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sender<KeyEvent>) {
+fn start_event_preprocessor(preprocess_rx: EventReceiver, process_tx: EventSender) {
     use CanBlock::*;
     use RecvValue::*;
     std::thread::spawn(move || {
@@ -342,7 +369,7 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
         loop {
             can_block = match preprocessor_recv(&preprocess_rx, can_block) {
                 Ok(kev) => {
-                    match (*ALTGR_BEHAVIOUR.lock(), kev) {
+                    match (altgr_behaviour(), kev) {
                         (AltGrBehaviour::DoNothing, _) => {
                             try_send_panic(&process_tx, kev);
                         }
@@ -354,7 +381,7 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                                 ..
                             },
                         ) => {
-                            log::debug!("altgr add: adding lctl release");
+                            tracing::debug!("altgr add: adding lctl release");
                             try_send_panic(&process_tx, kev);
                             try_send_panic(
                                 &process_tx,
@@ -370,7 +397,7 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                                 ..
                             },
                         ) => {
-                            log::debug!("altgr cancel: lctl state->pressed");
+                            tracing::debug!("altgr cancel: lctl state->pressed");
                             lctl_state = LctlState::Pressed;
                         }
                         (
@@ -382,11 +409,11 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                             },
                         ) => match lctl_state {
                             LctlState::Pressed => {
-                                log::debug!("altgr cancel: lctl state->released");
+                                tracing::debug!("altgr cancel: lctl state->released");
                                 lctl_state = LctlState::Released;
                             }
                             LctlState::Pending => {
-                                log::debug!("altgr cancel: lctl state->pending-released");
+                                tracing::debug!("altgr cancel: lctl state->pending-released");
                                 lctl_state = LctlState::PendingReleased;
                             }
                             LctlState::None => {
@@ -402,7 +429,7 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                                 ..
                             },
                         ) => {
-                            log::debug!("altgr cancel: lctl state->none");
+                            tracing::debug!("altgr cancel: lctl state->none");
                             lctl_state = LctlState::None;
                             try_send_panic(&process_tx, kev);
                         }
@@ -413,20 +440,23 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                     NoMustPeriodicPoll
                 }
                 Empty => {
-                    can_block = if *ALTGR_BEHAVIOUR.lock() == AltGrBehaviour::CancelLctlPress {
+                    // A pending lctl is sent even if the behaviour changed since, e.g. for an app.
+                    can_block = if altgr_behaviour() == AltGrBehaviour::CancelLctlPress
+                        || !matches!(lctl_state, LctlState::None)
+                    {
                         match lctl_state {
                             LctlState::Pressed => {
-                                log::debug!("altgr cancel: lctl state->pending");
+                                tracing::debug!("altgr cancel: lctl state->pending");
                                 lctl_state = LctlState::Pending;
                                 NoMustPeriodicPoll
                             }
                             LctlState::Released => {
-                                log::debug!("altgr cancel: lctl state->pending-released");
+                                tracing::debug!("altgr cancel: lctl state->pending-released");
                                 lctl_state = LctlState::PendingReleased;
                                 NoMustPeriodicPoll
                             }
                             LctlState::Pending => {
-                                log::debug!("altgr cancel: lctl state->send");
+                                tracing::debug!("altgr cancel: lctl state->send");
                                 try_send_panic(
                                     &process_tx,
                                     KeyEvent::new(OsCode::KEY_LEFTCTRL, KeyValue::Press),
@@ -435,7 +465,7 @@ fn start_event_preprocessor(preprocess_rx: Receiver<KeyEvent>, process_tx: Sende
                                 NoMustPeriodicPoll
                             }
                             LctlState::PendingReleased => {
-                                log::debug!("altgr cancel: lctl state->send+release");
+                                tracing::debug!("altgr cancel: lctl state->send+release");
                                 try_send_panic(
                                     &process_tx,
                                     KeyEvent::new(OsCode::KEY_LEFTCTRL, KeyValue::Press),
@@ -11,11 +11,45 @@ mod interception;
 #[cfg(all(not(feature = "simulated_input"), not(feature = "interception_driver")))]
 mod llhook;
 
-pub static ALTGR_BEHAVIOUR: Lazy<Mutex<AltGrBehaviour>> =
-    Lazy::new(|| Mutex::new(AltGrBehaviour::default()));
+struct AltGr {
+    /// The `windows-altgr` of `defcfg`.
+    configured: AltGrBehaviour,
+    /// The `windows-altgr` of the active application of `defapp`, if it has one.
+    app: Option<AltGrBehaviour>,
+    /// Whether the keyboard layout of the OS has AltGr, for `layout-aware`.
+    layout_has_altgr: bool,
+}
+
+static ALTGR: Mutex<AltGr> = Mutex::new(AltGr {
+    configured: AltGrBehaviour::DoNothing,
+    app: None,
+    layout_has_altgr: false,
+});
 
+/// Sets the `windows-altgr` of the configuration, which is used until an application of `defapp`
+/// that has its own is active.
 pub fn set_win_altgr_behaviour(b: AltGrBehaviour) {
-    *ALTGR_BEHAVIOUR.lock() = b;
+    let mut altgr = ALTGR.lock();
+    altgr.configured = b;
+    altgr.app = None;
+}
+
+pub fn set_win_app_altgr_behaviour(b: Option<AltGrBehaviour>) {
+    ALTGR.lock().app = b;
+}
+
+pub fn set_win_layout_has_altgr(has_altgr: bool) {
+    ALTGR.lock().layout_has_altgr = has_altgr;
+}
+
+/// The AltGr behaviour to use now, which is never `LayoutAware`.
+pub fn altgr_behaviour() -> AltGrBehaviour {
+    let altgr = ALTGR.lock();
+    match altgr.app.unwrap_or(altgr.configured) {
+        AltGrBehaviour::LayoutAware if altgr.layout_has_altgr => AltGrBehaviour::CancelLctlPress,
+        AltGrBehaviour::LayoutAware => AltGrBehaviour::DoNothing,
+        b => b,
+    }
 }
 
 impl Kanata {
@@ -106,7 +140,9 @@ impl Kanata {
                 }
                 _ => continue,
             };
-            log::debug!("lsft-arrowkey workaround: removing {keycode:?} at its typical coordinate");
+            tracing::debug!(
+                "lsft-arrowkey workaround: removing {keycode:?} at its typical coordinate"
+            );
             self.layout.bm().states.retain(|s| match s {
                 State::LayerModifier { coord, .. }
                 | State::Custom { coord, .. }
@@ -116,7 +152,7 @@ impl Kanata {
                 }
                 _ => true,
             });
-            log::debug!("removing {keycode:?} from pressed keys");
+            tracing::debug!("removing {keycode:?} from pressed keys");
             PRESSED_KEYS.lock().remove(&keycode.into());
         }
 
@@ -148,10 +184,10 @@ impl Kanata {
         match self.cfg_paths.get(n) {
             Some(path) => {
                 self.cur_cfg_idx = n;
-                log::info!("Requested live reload of file: {}", path.display(),);
+                tracing::info!("Requested live reload of file: {}", path.display(),);
             }
             None => {
-                log::error!(
+                tracing::error!(
                     "Requested live reload of config file number {}, but only {} config files were passed",
                     n + 1,
                     self.cfg_paths.len()
@@ -192,7 +228,7 @@ pub fn clear_states_from_inactivity(
         && !*idle_clear_happened
     {
         *idle_clear_happened = true;
-        log::debug!("clearing keyberon normal key states due to inactivity");
+        tracing::debug!("clearing keyberon normal key states due to inactivity");
         let layout = k.layout.bm();
         release_normalkey_states(layout);
         let now = web_time::Instant::now();
@@ -3,6 +3,8 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 
+#[cfg(all(feature = "simulated_output", not(feature = "simulated_input")))]
+pub mod engine;
 #[cfg(all(target_os = "windows", feature = "gui"))]
 pub mod gui;
 pub mod kanata;
@@ -25,6 +27,8 @@ pub struct ValidatedArgs {
     #[cfg(any(target_os = "linux", target_os = "android"))]
     pub symlink_path: Option<String>,
     pub nodelay: bool,
+    /// Reload the configuration when its files change.
+    pub watch: bool,
 }
 
 pub fn default_cfg() -> Vec<PathBuf> {
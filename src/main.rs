@@ -11,7 +11,7 @@ use kanata_parser::cfg;
 #[cfg(not(feature = "gui"))]
 use kanata_state_machine::*;
 #[cfg(not(feature = "gui"))]
-use main_lib::args::Args;
+use main_lib::args::{Args, LogFormat, NgramKeys};
 #[cfg(not(feature = "gui"))]
 use simplelog::{format_description, *};
 
@@ -23,6 +23,54 @@ mod cli {
     fn cli_init() -> Result<(ValidatedArgs, Option<String>)> {
         let args = Args::parse();
 
+        #[cfg(any(
+            target_os = "macos",
+            any(target_os = "linux", target_os = "android"),
+            all(target_os = "windows", feature = "interception_driver")
+        ))]
+        if let Some(main_lib::args::Command::ListDevices { json }) = args.command {
+            let cfg_paths = args.cfg.clone().unwrap_or_else(default_cfg);
+            main_lib::list_devices::list_devices(&cfg_paths, json);
+            std::process::exit(0);
+        }
+
+        if let Some(main_lib::args::Command::Migrate { from }) = &args.command {
+            main_lib::migrate::migrate(from)?;
+            std::process::exit(0);
+        }
+
+        if let Some(main_lib::args::Command::Export {
+            path,
+            format,
+            layer,
+            output,
+        }) = &args.command
+        {
+            main_lib::export::export(path, *format, layer.as_deref(), output.as_deref())?;
+            std::process::exit(0);
+        }
+
+        if let Some(main_lib::args::Command::Doctor) = args.command {
+            let cfg_paths = args.cfg.clone().unwrap_or_else(default_cfg);
+            #[cfg(feature = "tcp_server")]
+            let tcp_address = args.tcp_server_address.as_ref().map(|a| *a.get_ref());
+            #[cfg(not(feature = "tcp_server"))]
+            let tcp_address = None;
+            let healthy = main_lib::doctor::doctor(&cfg_paths, tcp_address);
+            std::process::exit(if healthy { 0 } else { 1 });
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(main_lib::args::Command::Record {
+            devices,
+            grab,
+            output,
+        }) = &args.command
+        {
+            main_lib::record::record(devices, *grab, output.as_ref())?;
+            std::process::exit(0);
+        }
+
         #[cfg(all(target_os = "macos", not(feature = "gui")))]
         if args.list {
             main_lib::list_devices_macos();
@@ -48,27 +96,107 @@ mod cli {
             (_, _, true) => LevelFilter::Error,
         };
 
-        let mut log_cfg = ConfigBuilder::new();
-        if let Err(e) = log_cfg.set_time_offset_to_local() {
-            eprintln!("WARNING: could not set log TZ to local: {e:?}");
+        // Keep text logs off stdout when it carries the JSON outputs, the test results, the
+        // dependency graph or the check results.
+        #[cfg(feature = "simulated_output")]
+        let stdout_has_output = matches!(args.output_json, Some(oskbd::JsonOutputTarget::Stdout))
+            || matches!(args.command, Some(main_lib::args::Command::Test { .. }));
+        #[cfg(not(feature = "simulated_output"))]
+        let stdout_has_output = false;
+        let stdout_has_output = stdout_has_output
+            || matches!(
+                args.command,
+                Some(main_lib::args::Command::Check { graph: Some(_), .. })
+                    | Some(main_lib::args::Command::Check { json: true, .. })
+            );
+        let terminal_mode = if stdout_has_output {
+            TerminalMode::Stderr
+        } else {
+            TerminalMode::Mixed
         };
-        log_cfg.set_time_format_custom(format_description!(
-            version = 2,
-            "[hour]:[minute]:[second].[subsecond digits:4]"
-        ));
-        CombinedLogger::init(vec![TermLogger::new(
-            log_lvl,
-            log_cfg.build(),
-            TerminalMode::Mixed,
-            ColorChoice::AlwaysAnsi,
-        )])
-        .expect("logger can init");
-
-        log::info!("kanata v{} starting", env!("CARGO_PKG_VERSION"));
+
+        let log_file = main_lib::log_file::from_args(&args)?;
+        match args.log_format {
+            LogFormat::Text => {
+                let mut log_cfg = ConfigBuilder::new();
+                if let Err(e) = log_cfg.set_time_offset_to_local() {
+                    eprintln!("WARNING: could not set log TZ to local: {e:?}");
+                };
+                log_cfg.set_time_format_custom(format_description!(
+                    version = 2,
+                    "[hour]:[minute]:[second].[subsecond digits:4]"
+                ));
+                // The loggers let everything through and the max level filters, so that the level
+                // can be changed at runtime.
+                let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
+                    LevelFilter::Trace,
+                    log_cfg.build(),
+                    terminal_mode,
+                    ColorChoice::AlwaysAnsi,
+                )];
+                if let Some(log_file) = log_file {
+                    loggers.push(WriteLogger::new(
+                        LevelFilter::Trace,
+                        log_cfg.build(),
+                        log_file,
+                    ));
+                }
+                CombinedLogger::init(loggers).expect("logger can init");
+                init_log_level(log_lvl, log::set_max_level);
+            }
+            LogFormat::Json => {
+                use tracing_subscriber::filter::LevelFilter as TracingLevel;
+                use tracing_subscriber::prelude::*;
+                let tracing_lvl = |log_lvl| match log_lvl {
+                    LevelFilter::Trace => TracingLevel::TRACE,
+                    LevelFilter::Debug => TracingLevel::DEBUG,
+                    LevelFilter::Info => TracingLevel::INFO,
+                    LevelFilter::Warn => TracingLevel::WARN,
+                    LevelFilter::Error => TracingLevel::ERROR,
+                    LevelFilter::Off => TracingLevel::OFF,
+                };
+                use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
+                let writer = match log_file {
+                    Some(log_file) => {
+                        BoxMakeWriter::new(std::io::stderr.and(std::sync::Mutex::new(log_file)))
+                    }
+                    None => BoxMakeWriter::new(std::io::stderr),
+                };
+                let (filter, filter_handle) =
+                    tracing_subscriber::reload::Layer::new(tracing_lvl(log_lvl));
+                // This also forwards records from the log crate, which dependencies use.
+                tracing_subscriber::registry()
+                    .with(filter)
+                    .with(
+                        tracing_subscriber::fmt::layer()
+                            .json()
+                            .with_current_span(true)
+                            .with_span_list(true)
+                            .with_writer(writer),
+                    )
+                    .init();
+                init_log_level(log_lvl, move |log_lvl| {
+                    let _ = filter_handle.reload(tracing_lvl(log_lvl));
+                    // The forwarding of the log crate filters by its max level.
+                    log::set_max_level(log_lvl);
+                });
+            }
+        }
+
+        tracing::info!("kanata v{} starting", env!("CARGO_PKG_VERSION"));
         #[cfg(all(not(feature = "interception_driver"), target_os = "windows"))]
-        log::info!("using LLHOOK+SendInput for keyboard IO");
+        tracing::info!("using LLHOOK+SendInput for keyboard IO");
         #[cfg(all(feature = "interception_driver", target_os = "windows"))]
-        log::info!("using the Interception driver for keyboard IO");
+        tracing::info!("using the Interception driver for keyboard IO");
+
+        if !args.no_template_cache {
+            cfg::set_template_cache_dir(dirs::cache_dir().map(|dir| dir.join("kanata")));
+        }
+
+        #[cfg(feature = "plugins")]
+        if let Some(dir) = &args.plugin_dir {
+            load_plugins(dir);
+        }
 
         #[cfg(target_os = "macos")]
         if args.macos_request_permissions {
@@ -90,6 +218,132 @@ mod cli {
             std::process::exit(1);
         }
 
+        if let Some(main_lib::args::Command::Check {
+            paths,
+            stdin,
+            stdin_path,
+            graph,
+            run_tests,
+            json,
+        }) = &args.command
+        {
+            use main_lib::check::Source;
+            let mut paths = paths.clone();
+            if paths.is_empty() && !stdin {
+                paths = args.cfg.clone().unwrap_or_else(default_cfg);
+                if paths.is_empty() {
+                    bail!(
+                        "No config files provided\nFor more info, pass the `-h` or `--help` flags."
+                    );
+                }
+            }
+            let mut sources: Vec<_> = paths.into_iter().map(Source::File).collect();
+            if *stdin {
+                use std::io::Read;
+                let mut text = String::new();
+                std::io::stdin().read_to_string(&mut text)?;
+                sources.push(Source::Text {
+                    text,
+                    path: stdin_path.clone(),
+                });
+            }
+            let valid = main_lib::check::check(&sources, *graph, *run_tests, *json);
+            std::process::exit(if valid { 0 } else { 1 });
+        }
+
+        // Replays use the virtual clock, so that they are the same on every run, also
+        // in the event trace.
+        #[cfg(feature = "simulated_output")]
+        if args.replay_crash_bundle.is_some()
+            || args.shadow_trace.is_some()
+            || args.replay_trace.is_some()
+        {
+            kanata_state_machine::clock::use_virtual_clock();
+        }
+
+        if args.crash_bundle {
+            let dir = args
+                .crash_bundle_dir
+                .clone()
+                .unwrap_or_else(default_crash_bundle_dir);
+            enable_crash_bundle(dir, args.crash_bundle_hash_keys);
+        }
+
+        if let Some(path) = &args.event_trace {
+            enable_event_trace(path.clone(), args.event_trace_max_events).map_err(|e| {
+                anyhow::anyhow!("could not create the event trace {}: {e}", path.display())
+            })?;
+        }
+
+        #[cfg(feature = "simulated_output")]
+        if let Some(path) = &args.replay_crash_bundle {
+            main_lib::replay_crash::replay(path)?;
+            flush_event_trace();
+            std::process::exit(0);
+        }
+
+        #[cfg(feature = "simulated_output")]
+        if let Some(trace) = &args.shadow_trace {
+            let Some(path) = args
+                .cfg
+                .clone()
+                .unwrap_or_else(default_cfg)
+                .into_iter()
+                .next()
+            else {
+                bail!("No config files provided\nFor more info, pass the `-h` or `--help` flags.");
+            };
+            let differ = main_lib::shadow::run(&path, trace)?;
+            std::process::exit(i32::from(differ));
+        }
+
+        #[cfg(feature = "simulated_output")]
+        if let Some(trace) = &args.replay_trace {
+            let Some(path) = args
+                .cfg
+                .clone()
+                .unwrap_or_else(default_cfg)
+                .into_iter()
+                .next()
+            else {
+                bail!("No config files provided\nFor more info, pass the `-h` or `--help` flags.");
+            };
+            main_lib::replay_trace::replay(&path, trace)?;
+            flush_event_trace();
+            std::process::exit(0);
+        }
+
+        #[cfg(feature = "simulated_output")]
+        if let Some(main_lib::args::Command::Test { dir, bless }) = &args.command {
+            let Some(path) = args
+                .cfg
+                .clone()
+                .unwrap_or_else(default_cfg)
+                .into_iter()
+                .next()
+            else {
+                bail!("No config files provided\nFor more info, pass the `-h` or `--help` flags.");
+            };
+            let passed = main_lib::golden::run(&path, dir, *bless)?;
+            std::process::exit(i32::from(!passed));
+        }
+
+        #[cfg(feature = "simulated_output")]
+        if let Some(main_lib::args::Command::Repl) = args.command {
+            let Some(path) = args
+                .cfg
+                .clone()
+                .unwrap_or_else(default_cfg)
+                .into_iter()
+                .next()
+            else {
+                bail!("No config files provided\nFor more info, pass the `-h` or `--help` flags.");
+            };
+            main_lib::repl::run(&path)?;
+            flush_event_trace();
+            std::process::exit(0);
+        }
+
         let config_string = if args.cfg_stdin {
             use std::io::Read;
             let mut buf = String::new();
@@ -119,13 +373,13 @@ mod cli {
         }
 
         if args.check {
-            log::info!("validating config only and exiting");
+            tracing::info!("validating config only and exiting");
             let status = if let Some(ref cfg_str) = config_string {
                 use rustc_hash::FxHashMap;
                 match cfg::new_from_str(cfg_str, FxHashMap::default()) {
                     Ok(_) => 0,
                     Err(e) => {
-                        log::error!("{e:?}");
+                        tracing::error!("{e:?}");
                         1
                     }
                 }
@@ -133,7 +387,7 @@ mod cli {
                 match cfg::new_from_file(&cfg_paths[0]) {
                     Ok(_) => 0,
                     Err(e) => {
-                        log::error!("{e:?}");
+                        tracing::error!("{e:?}");
                         1
                     }
                 }
@@ -144,7 +398,7 @@ mod cli {
         #[cfg(any(target_os = "linux", target_os = "android"))]
         if let Some(wait) = args.wait_device_ms {
             use std::sync::atomic::Ordering;
-            log::info!("Setting device registration wait time to {wait} ms.");
+            tracing::info!("Setting device registration wait time to {wait} ms.");
             oskbd::WAIT_DEVICE_MS.store(wait, Ordering::SeqCst);
         }
 
@@ -152,6 +406,32 @@ mod cli {
             cfg_forced::force_log_layer_changes(true);
         }
 
+        if args.measure_latency {
+            enable_latency_measurement();
+        }
+
+        #[cfg(feature = "tcp_server")]
+        if let Some(interval) = args.typing_speed_interval_ms {
+            enable_typing_speed_notifications(std::time::Duration::from_millis(interval));
+        }
+
+        set_watchdog_timeout(args.watchdog_timeout_ms);
+
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+        if args.log_file.is_none()
+            && [args.sigusr1, args.sigusr2, args.sighup].contains(&Some(SignalAction::RotateLogs))
+        {
+            bail!("the rotate-logs signal action needs --log-file");
+        }
+
+        if let Some(keys) = args.ngram_stats {
+            enable_ngram_stats(NgramStatsConfig {
+                classes: keys == NgramKeys::Classes,
+                min_count: args.ngram_stats_min_count,
+                file: args.ngram_stats_file.clone(),
+            });
+        }
+
         // Set emergency exit code from CLI args
         kanata::EMERGENCY_EXIT_CODE.store(
             args.emergency_exit_code,
@@ -169,6 +449,7 @@ mod cli {
                 #[cfg(any(target_os = "linux", target_os = "android"))]
                 symlink_path: args.symlink_path,
                 nodelay: args.nodelay,
+                watch: args.watch,
             },
             config_string,
         ))
@@ -177,6 +458,14 @@ mod cli {
     pub(crate) fn main_impl() -> Result<()> {
         let (args, config_string) = cli_init()?;
 
+        if !Args::parse().no_self_test {
+            #[cfg(feature = "tcp_server")]
+            let tcp_address = args.tcp_server_address.as_ref().map(|a| *a.get_ref());
+            #[cfg(not(feature = "tcp_server"))]
+            let tcp_address = None;
+            main_lib::doctor::startup_self_test(&args.paths, tcp_address)?;
+        }
+
         let kanata_arc = if let Some(cfg_str) = config_string {
             use rustc_hash::FxHashMap;
             let kanata = Kanata::new_from_str(&cfg_str, FxHashMap::default())?;
@@ -185,8 +474,15 @@ mod cli {
             Kanata::new_arc(&args)?
         };
 
+        #[cfg(feature = "simulated_output")]
+        if let Some(target) = Args::parse().output_json {
+            let output = oskbd::JsonOutput::open(&target)
+                .map_err(|e| anyhow::anyhow!("could not open JSON output {target:?}: {e}"))?;
+            kanata_arc.lock().kbd_out.set_output_sink(Box::new(output));
+        }
+
         if !args.nodelay {
-            log::info!(
+            tracing::info!(
                 "Sleeping for 2s. Please release all keys and don't press additional ones. Run kanata with --help to see how understand more and how to disable this sleep."
             );
             std::thread::sleep(std::time::Duration::from_secs(2));
@@ -198,20 +494,26 @@ mod cli {
         // keyboard events, which it sends to the "processing loop". The processing loop handles
         // keyboard events while also maintaining `tick()` calls to keyberon.
 
-        let (tx, rx) = std::sync::mpsc::sync_channel(100);
+        let (tx, rx) = event_queue(100);
 
-        let (server, ntx, nrx) = if let Some(address) = {
+        let listeners: Vec<tcp_server::Listener> = {
             #[cfg(feature = "tcp_server")]
             {
-                args.tcp_server_address
+                let port = args.tcp_server_address.map(|a| a.into_inner().into());
+                port.into_iter().chain(Args::parse().listen).collect()
             }
             #[cfg(not(feature = "tcp_server"))]
             {
-                None::<SocketAddrWrapper>
+                Vec::new()
             }
-        } {
-            let mut server = TcpServer::new(address.into_inner(), tx.clone());
-            server.start(kanata_arc.clone());
+        };
+        let (server, ntx, nrx) = if !listeners.is_empty() {
+            let mut server = TcpServer::with_listeners(listeners, tx.clone());
+            #[cfg(feature = "tcp_server")]
+            {
+                server.audit_log = Args::parse().audit_log;
+            }
+            server.start(kanata_arc.clone())?;
             let (ntx, nrx) = std::sync::mpsc::sync_channel(100);
             (Some(server), Some(ntx), Some(nrx))
         } else {
@@ -220,6 +522,34 @@ mod cli {
 
         Kanata::start_processing_loop(kanata_arc.clone(), rx, ntx, args.nodelay);
 
+        if args.watch {
+            Kanata::start_cfg_watcher(kanata_arc.clone(), tx.clone());
+        }
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+        {
+            let cli = Args::parse();
+            let actions = SignalActions {
+                sigusr1: cli.sigusr1,
+                sigusr2: cli.sigusr2,
+                sighup: cli.sighup,
+            };
+            Kanata::start_signal_handler(kanata_arc.clone(), tx.clone(), actions);
+        }
+        #[cfg(any(target_os = "linux", target_os = "windows"))]
+        if let Some(cfg_path) = Args::parse().per_user_cfg {
+            Kanata::start_user_session_watcher(kanata_arc.clone(), tx.clone(), cfg_path);
+        }
+        #[cfg(all(
+            target_os = "linux",
+            feature = "dbus",
+            not(feature = "simulated_output")
+        ))]
+        if Args::parse().tray {
+            Kanata::start_status_notifier(kanata_arc.clone(), tx.clone());
+        }
+        #[cfg(target_os = "windows")]
+        Kanata::start_os_layout_watcher(kanata_arc.clone(), tx.clone());
+
         if let (Some(server), Some(nrx)) = (server, nrx) {
             #[allow(clippy::unit_arg)]
             Kanata::start_notification_loop(nrx, server.connections);
@@ -228,7 +558,26 @@ mod cli {
         #[cfg(any(target_os = "linux", target_os = "android"))]
         sd_notify::notify(true, &[sd_notify::NotifyState::Ready])?;
 
-        Kanata::event_loop(kanata_arc, tx)
+        // AppKit needs the main thread, so the event loop gets its own.
+        #[cfg(all(target_os = "macos", not(feature = "simulated_output")))]
+        if Args::parse().menu_bar {
+            let (kanata, wakeup) = (kanata_arc.clone(), tx.clone());
+            std::thread::Builder::new()
+                .name("event-loop".into())
+                .spawn(move || {
+                    if let Err(e) = Kanata::event_loop(kanata_arc, tx) {
+                        write_crash_bundle(&format!("fatal error: {e:#}"));
+                        tracing::error!("{e:#}");
+                        std::process::exit(1);
+                    }
+                })?;
+            Kanata::run_menu_bar(kanata, wakeup);
+            return Ok(());
+        }
+
+        Kanata::event_loop(kanata_arc, tx).inspect_err(|e| {
+            write_crash_bundle(&format!("fatal error: {e:#}"));
+        })
     }
 
     #[cfg(target_os = "macos")]
@@ -245,12 +594,19 @@ pub fn main() -> Result<()> {
     let no_wait = args.no_wait;
     let ret = cli::main_impl();
     if let Err(ref e) = ret {
-        log::error!("{e}\n");
+        tracing::error!("{e}\n");
     }
     if !no_wait {
         eprintln!("\nPress enter to exit");
         let _ = std::io::stdin().read_line(&mut String::new());
     }
+    if let Some(failed) = ret
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<main_lib::doctor::SelfTestFailed>())
+    {
+        std::process::exit(failed.exit_code);
+    }
     ret
 }
 
@@ -1,8 +1,52 @@
+use super::log_file::parse_size;
 use clap::Parser;
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+use kanata_state_machine::SignalAction;
 #[cfg(feature = "tcp_server")]
 use kanata_state_machine::SocketAddrWrapper;
+#[cfg(feature = "simulated_output")]
+use kanata_state_machine::oskbd::JsonOutputTarget;
+#[cfg(feature = "tcp_server")]
+use kanata_state_machine::tcp_server::{AuditLog, Listener};
 use std::path::PathBuf;
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRotateEvery {
+    Never,
+    Hour,
+    Day,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NgramKeys {
+    /// The names of the keys.
+    Keys,
+    /// Classes of keys, e.g. vowel, consonant, digit and punctuation.
+    Classes,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    Text,
+    /// Graphviz DOT.
+    Dot,
+}
+
+/// Format of the diagram of `kanata export`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Svg,
+    Json,
+    /// A keymap JSON of QMK Configurator, with every layer.
+    Qmk,
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, verbatim_doc_comment)]
 /// kanata: an advanced software key remapper
@@ -33,7 +77,7 @@ kanata.kbd in the current working directory and
 kanata.kbd in the current working directory and
 '$XDG_CONFIG_HOME/kanata/kanata.kbd'."
     )]
-    #[arg(short, long, verbatim_doc_comment)]
+    #[arg(short, long, global = true, verbatim_doc_comment)]
     pub cfg: Option<Vec<PathBuf>>,
 
     /// Read configuration from stdin instead of a file.
@@ -51,6 +95,44 @@ kanata.kbd in the current working directory and
     )]
     pub tcp_server_address: Option<SocketAddrWrapper>,
 
+    /// Additional listener for the server, can be given several times.
+    /// The format is `[tcp:|udp:|unix:]ADDRESS[,token-file=PATH]`, e.g.
+    /// `unix:/run/kanata.sock` or `udp:5830,token-file=/etc/kanata/token`.
+    /// With a token file, clients must first send `Authenticate` with its
+    /// content. `tokens-file=PATH` accepts any token of the file, one per
+    /// line, and is read again when it changes. Network listeners also take
+    /// `interface=NAME` to bind to the address of an interface and
+    /// `allow=CIDR`, repeatable, to accept only clients from those addresses.
+    /// With `read-only`, clients may only send queries.
+    /// `via:ADDRESS` serves the VIA protocol to keymap editors, and
+    /// `output:ADDRESS,token-file=PATH` injects the output of another kanata.
+    /// With the `mqtt` feature, `mqtt:HOST[:PORT][,OPTIONS]` connects to an
+    /// MQTT broker instead, read-only unless `allow-command=NAME` or
+    /// `read-write` is given.
+    #[cfg(feature = "tcp_server")]
+    #[arg(long, value_name = "LISTENER", verbatim_doc_comment)]
+    pub listen: Vec<Listener>,
+
+    /// Record each command of the clients of the server, with the time, the
+    /// client and the result, as JSON lines appended to a file, or with
+    /// `syslog` to the local syslog daemon on Unix.
+    #[cfg(feature = "tcp_server")]
+    #[arg(long, value_name = "FILE|syslog", verbatim_doc_comment)]
+    pub audit_log: Option<AuditLog>,
+
+    /// Send the typing speed to the clients of the server as a `TypingSpeed`
+    /// notification every MS milliseconds while keys are typed.
+    #[cfg(feature = "tcp_server")]
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..), verbatim_doc_comment)]
+    pub typing_speed_interval_ms: Option<u64>,
+
+    /// Write outputs as lines of JSON instead of sending them to the OS.
+    /// The target is `-` for stdout, `tcp:HOST:PORT` to connect to a TCP
+    /// listener, or `unix:PATH` to connect to a Unix socket.
+    #[cfg(feature = "simulated_output")]
+    #[arg(long, value_name = "TARGET", verbatim_doc_comment)]
+    pub output_json: Option<JsonOutputTarget>,
+
     /// Path for the symlink pointing to the newly-created device. If blank, no
     /// symlink will be created.
     #[cfg(any(target_os = "linux", target_os = "android"))]
@@ -78,11 +160,211 @@ kanata.kbd in the current working directory and
     #[arg(short, long)]
     pub trace: bool,
 
+    /// Format of the log output. With json, each log line is a JSON object
+    /// that includes the spans it was logged in, e.g. the input event being
+    /// processed.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, verbatim_doc_comment)]
+    pub log_format: LogFormat,
+
+    /// Also write the logs to this file, e.g. when kanata runs without a
+    /// terminal. Old logs are moved to FILE.1, FILE.2 and so on.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment)]
+    pub log_file: Option<PathBuf>,
+
+    /// Start a new log file before it gets bigger than this, e.g. 100K or
+    /// 10M. 0 means no limit.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "10M",
+        value_parser = parse_size,
+        verbatim_doc_comment
+    )]
+    pub log_max_size: u64,
+
+    /// Also start a new log file when the current one is this old.
+    #[arg(long, value_enum, default_value_t = LogRotateEvery::Never)]
+    pub log_rotate_every: LogRotateEvery,
+
+    /// How many old log files to keep.
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    pub log_keep: usize,
+
     /// Remove the startup delay.
     /// In some cases, removing the delay may cause keyboard issues on startup.
     #[arg(short, long, verbatim_doc_comment)]
     pub nodelay: bool,
 
+    /// Reload the configuration when it or one of its included files
+    /// changes. A change that fails to parse is logged and the previous
+    /// configuration stays active.
+    #[arg(long, verbatim_doc_comment)]
+    pub watch: bool,
+
+    /// Measure how long input events take from being received to their
+    /// output being written. The median, 99th percentile and maximum are
+    /// logged every minute and sent in response to the TCP `RequestStats`
+    /// command.
+    #[arg(long, verbatim_doc_comment)]
+    pub measure_latency: bool,
+
+    /// Milliseconds that processing or an output write may be stuck before
+    /// the watchdog passes input through to the OS, e.g. when uinput hangs.
+    /// After three times as long, kanata exits so that the OS releases the
+    /// devices. 0 disables the watchdog.
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 10_000,
+        verbatim_doc_comment
+    )]
+    pub watchdog_timeout_ms: u64,
+
+    /// Show kanata in the tray of the desktop, with the active layer and a
+    /// menu to pause and reload. The desktop needs a StatusNotifierWatcher,
+    /// e.g. KDE Plasma, waybar or the AppIndicator extension of GNOME.
+    #[cfg(all(
+        target_os = "linux",
+        feature = "dbus",
+        not(feature = "simulated_output")
+    ))]
+    #[arg(long, verbatim_doc_comment)]
+    pub tray: bool,
+
+    /// Show kanata in the menu bar, with the active layer and a menu to
+    /// pause, reload and load the other configuration files of -c.
+    #[cfg(all(target_os = "macos", not(feature = "simulated_output")))]
+    #[arg(long, verbatim_doc_comment)]
+    pub menu_bar: bool,
+
+    /// Load the configuration at this path in the home directory of the user
+    /// of the active session, e.g. .config/kanata/kanata.kbd, when kanata runs
+    /// as a system service. Without an active user, or if the user has no such
+    /// file, the first configuration of --cfg is loaded.
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    #[arg(long, value_name = "PATH", verbatim_doc_comment)]
+    pub per_user_cfg: Option<PathBuf>,
+
+    /// What to do on SIGUSR1: reload, toggle-passthrough, rotate-logs or
+    /// dump-state. Without an action, the signal stops kanata.
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+    #[arg(long, value_name = "ACTION", verbatim_doc_comment)]
+    pub sigusr1: Option<SignalAction>,
+
+    /// What to do on SIGUSR2, like --sigusr1.
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+    #[arg(long, value_name = "ACTION", verbatim_doc_comment)]
+    pub sigusr2: Option<SignalAction>,
+
+    /// What to do on SIGHUP, like --sigusr1.
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+    #[arg(long, value_name = "ACTION", verbatim_doc_comment)]
+    pub sighup: Option<SignalAction>,
+
+    /// Count how often keys are pressed after each other, as bigrams and
+    /// trigrams per layer, e.g. for layout optimizers. With `classes`, keys
+    /// are counted as their class, e.g. vowel or digit, so that the counts
+    /// don't reveal what was typed. The counts are sent in response to the
+    /// TCP `RequestNgramStats` command.
+    #[arg(long, value_name = "KEYS", verbatim_doc_comment)]
+    pub ngram_stats: Option<NgramKeys>,
+
+    /// Write the n-gram counts as JSON to this file, at most once a minute
+    /// while keys are pressed.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "ngram_stats",
+        verbatim_doc_comment
+    )]
+    pub ngram_stats_file: Option<PathBuf>,
+
+    /// Leave out n-grams counted fewer times than this from the counts that
+    /// are sent and written, e.g. to hide rarely typed sequences.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        requires = "ngram_stats",
+        verbatim_doc_comment
+    )]
+    pub ngram_stats_min_count: u64,
+
+    /// Write every input event, tap-hold, tap-dance and chord decision,
+    /// layer change and output event as a line of JSON to this file. When
+    /// the file has --event-trace-max-events events, it is moved to FILE.1
+    /// and a new file is started.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment)]
+    pub event_trace: Option<PathBuf>,
+
+    /// How many events the event trace file has before a new one is
+    /// started. 0 means no limit.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100_000,
+        requires = "event_trace",
+        verbatim_doc_comment
+    )]
+    pub event_trace_max_events: usize,
+
+    /// On a panic or a fatal error, write a crash bundle for reproducing it:
+    /// the configuration without comments and secrets, the last 1000 input,
+    /// decision and output events, the platform and the backtrace.
+    #[arg(long, verbatim_doc_comment)]
+    pub crash_bundle: bool,
+
+    /// Directory to write crash bundles to. Defaults to the crash directory
+    /// in the kanata cache directory, e.g. ~/.cache/kanata/crash.
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "crash_bundle",
+        verbatim_doc_comment
+    )]
+    pub crash_bundle_dir: Option<PathBuf>,
+
+    /// Hash the keys of the events in crash bundles, so that they don't
+    /// show what was typed. Such bundles cannot be replayed.
+    #[arg(long, requires = "crash_bundle", verbatim_doc_comment)]
+    pub crash_bundle_hash_keys: bool,
+
+    /// Replay the input events of a crash bundle on its configuration with
+    /// simulated time, print the outputs and exit.
+    #[cfg(feature = "simulated_output")]
+    #[arg(long, value_name = "BUNDLE", verbatim_doc_comment)]
+    pub replay_crash_bundle: Option<PathBuf>,
+
+    /// Run the input events of an event trace, written with --event-trace
+    /// while another configuration was active, on the configuration given
+    /// with --cfg. Print every input after which the key outputs differ
+    /// from the outputs in the trace and exit, with status 1 if any do.
+    #[cfg(feature = "simulated_output")]
+    #[arg(long, value_name = "TRACE", verbatim_doc_comment)]
+    pub shadow_trace: Option<PathBuf>,
+
+    /// Run the input events of an event trace, written with --event-trace,
+    /// on the configuration given with --cfg, print the outputs and exit.
+    /// Time is a virtual clock driven by the timestamps of the events, so
+    /// every run prints the same, as does the event trace of the replay.
+    #[cfg(feature = "simulated_output")]
+    #[arg(long, value_name = "TRACE", verbatim_doc_comment)]
+    pub replay_trace: Option<PathBuf>,
+
+    /// Don't cache the configuration with its templates expanded. The
+    /// cache makes startup and reloads of configurations with many
+    /// templates faster and is kept in the user's cache directory, e.g.
+    /// ~/.cache/kanata.
+    #[arg(long, verbatim_doc_comment)]
+    pub no_template_cache: bool,
+
+    /// Load the plugins in DIR at startup, which configurations can run the
+    /// actions of with `defplugins`. Plugins are dynamic libraries that run
+    /// with the rights of kanata, so only put trusted plugins there.
+    #[cfg(feature = "plugins")]
+    #[arg(long, value_name = "DIR", verbatim_doc_comment)]
+    pub plugin_dir: Option<PathBuf>,
+
     /// Milliseconds to wait before attempting to register a newly connected
     /// device. The default is 200.
     ///
@@ -108,6 +390,12 @@ kanata.kbd in the current working directory and
     #[arg(long, verbatim_doc_comment)]
     pub no_wait: bool,
 
+    /// Don't check for problems that stop kanata from working before
+    /// starting, e.g. that /dev/uinput is writable. A failed check exits
+    /// with its own exit code and how to fix the problem.
+    #[arg(long, verbatim_doc_comment)]
+    pub no_self_test: bool,
+
     /// Exit code to use when emergency exit is triggered (LCtrl+Space+Escape).
     /// Default is 0 (success). Set to non-zero if your service manager should
     /// treat emergency exit as a failure and restart.
@@ -128,6 +416,202 @@ kanata.kbd in the current working directory and
     #[cfg(target_os = "macos")]
     #[arg(long, verbatim_doc_comment)]
     pub macos_request_permissions: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Check configuration files and exit. The exit status is non-zero if
+    /// any of them is invalid.
+    #[command(verbatim_doc_comment)]
+    Check {
+        /// Files to check. Defaults to the files given with --cfg or the
+        /// default configuration file.
+        #[arg(verbatim_doc_comment)]
+        paths: Vec<PathBuf>,
+
+        /// Also check a configuration read from stdin, e.g. an unsaved
+        /// editor buffer.
+        #[arg(long, verbatim_doc_comment)]
+        stdin: bool,
+
+        /// Path of the configuration read from stdin. Includes are read
+        /// relative to it and errors refer to it.
+        #[arg(
+            long,
+            value_name = "PATH",
+            requires = "stdin",
+            default_value = "<stdin>",
+            verbatim_doc_comment
+        )]
+        stdin_path: PathBuf,
+
+        /// Print which files each configuration includes and which
+        /// templates are defined and expanded where.
+        #[arg(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            default_missing_value = "text",
+            verbatim_doc_comment
+        )]
+        graph: Option<GraphFormat>,
+
+        /// Also run the tests of deftest in each configuration on simulated
+        /// time. A failing test makes the exit status non-zero. This needs
+        /// kanata built with the simulated_output feature.
+        #[arg(long, verbatim_doc_comment)]
+        run_tests: bool,
+
+        /// Print a JSON array with, for each configuration, its path,
+        /// whether it is valid, its error or its defmeta, and with
+        /// --run-tests whether its tests passed.
+        #[arg(long, conflicts_with = "graph", verbatim_doc_comment)]
+        json: bool,
+    },
+
+    /// Run the configuration on input typed in the simulator format, e.g.
+    /// `d:lsft t:10 d:a u:a u:lsft`, and print the outputs and the active
+    /// layer after each line. The configuration is the first file given
+    /// with --cfg or the default configuration file.
+    #[cfg(feature = "simulated_output")]
+    #[command(verbatim_doc_comment)]
+    Repl,
+
+    /// Run every simulator input file NAME.sim in a directory on the
+    /// configuration and compare what repl prints for it to the file
+    /// NAME.out next to it. The configuration is the first file given with
+    /// --cfg or the default configuration file. The exit status is non-zero
+    /// if an output differs.
+    #[cfg(feature = "simulated_output")]
+    #[command(verbatim_doc_comment)]
+    Test {
+        /// Directory with the input files.
+        dir: PathBuf,
+
+        /// Write the outputs to the NAME.out files instead of comparing
+        /// them, e.g. after an intended change.
+        #[arg(long, verbatim_doc_comment)]
+        bless: bool,
+    },
+
+    /// Record key events of input devices in the simulator format, e.g. to
+    /// attach a sequence that misbehaves to a bug report for exact replay.
+    /// Press LCtrl+Space+Esc to stop.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[command(verbatim_doc_comment)]
+    Record {
+        /// Device to record, e.g. /dev/input/event3. May be given more than
+        /// once. Defaults to all keyboards.
+        #[arg(long = "device", value_name = "PATH", verbatim_doc_comment)]
+        devices: Vec<PathBuf>,
+
+        /// Grab the devices so that other programs do not receive the events.
+        /// Otherwise the events are read passively.
+        #[arg(long, verbatim_doc_comment)]
+        grab: bool,
+
+        /// File to write the events to instead of stdout.
+        #[arg(short, long, value_name = "FILE", verbatim_doc_comment)]
+        output: Option<PathBuf>,
+    },
+
+    /// List every input device with its capabilities and whether the
+    /// device filters in defcfg would grab it, then exit.
+    #[cfg(any(
+        target_os = "macos",
+        any(target_os = "linux", target_os = "android"),
+        all(target_os = "windows", feature = "interception_driver")
+    ))]
+    #[command(verbatim_doc_comment)]
+    ListDevices {
+        /// Print the devices as a JSON array instead of a table.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check the environment for common setup problems, e.g. missing device
+    /// permissions, drivers or conflicting remappers, print how to fix them
+    /// and exit. The exit status is non-zero if a check failed.
+    #[command(verbatim_doc_comment)]
+    Doctor,
+
+    /// Convert the configuration of another remapper to a kanata
+    /// configuration. What does not convert exactly is printed to stderr
+    /// and added as comments at the top of the result.
+    #[command(verbatim_doc_comment)]
+    Migrate {
+        #[command(subcommand)]
+        from: MigrateFrom,
+    },
+
+    /// Render what the keys of a layer do as a keyboard diagram, with the
+    /// keys placed as they are written in defsrc, e.g. for documentation
+    /// and cheat sheets.
+    #[command(verbatim_doc_comment)]
+    Export {
+        /// The configuration file.
+        path: PathBuf,
+
+        #[arg(long, value_enum, default_value = "svg")]
+        format: ExportFormat,
+
+        /// The layer to render. Defaults to the first layer.
+        #[arg(long)]
+        layer: Option<String>,
+
+        /// File to write the diagram to instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Subcommand, Debug, PartialEq, Eq)]
+pub enum MigrateFrom {
+    /// Convert a kmonad configuration.
+    Kmonad {
+        /// The kmonad configuration file.
+        path: PathBuf,
+
+        /// File to write the kanata configuration to instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Convert a keymap exported as JSON from QMK Configurator or VIA.
+    Qmk {
+        /// The keymap JSON file.
+        path: PathBuf,
+
+        /// File to write the kanata configuration to instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Convert the simple and complex modifications of a Karabiner-Elements
+    /// karabiner.json, or a file of complex modifications.
+    #[command(verbatim_doc_comment)]
+    Karabiner {
+        /// The karabiner.json or complex modifications file.
+        path: PathBuf,
+
+        /// File to write the kanata configuration to instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Convert the layers, behaviors and combos of a ZMK keymap.
+    Zmk {
+        /// The .keymap file.
+        path: PathBuf,
+
+        /// File to write the kanata configuration to instead of stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
 }
 
 #[cfg(test)]
@@ -166,6 +650,247 @@ mod tests {
         assert_eq!(args.emergency_exit_code, 42);
     }
 
+    #[test]
+    #[cfg(feature = "tcp_server")]
+    fn listen_options() {
+        let args =
+            Args::try_parse_from(["kanata", "--listen", "udp:5830", "--listen", "5831"]).unwrap();
+        assert_eq!(args.listen.len(), 2);
+        assert_eq!(
+            args.listen[1],
+            Listener::from("127.0.0.1:5831".parse::<std::net::SocketAddr>().unwrap())
+        );
+        assert!(Args::try_parse_from(["kanata", "--listen", "udp:"]).is_err());
+    }
+
+    #[test]
+    fn log_file_options() {
+        let args = Args::try_parse_from(["kanata"]).unwrap();
+        assert_eq!(args.log_file, None);
+        assert_eq!(args.log_max_size, 10 * 1024 * 1024);
+        assert_eq!(args.log_rotate_every, LogRotateEvery::Never);
+        assert_eq!(args.log_keep, 5);
+        let args = Args::try_parse_from([
+            "kanata",
+            "--log-file",
+            "kanata.log",
+            "--log-max-size",
+            "100K",
+            "--log-rotate-every",
+            "day",
+            "--log-keep",
+            "2",
+        ])
+        .unwrap();
+        assert_eq!(args.log_file, Some(PathBuf::from("kanata.log")));
+        assert_eq!(args.log_max_size, 100 * 1024);
+        assert_eq!(args.log_rotate_every, LogRotateEvery::Day);
+        assert_eq!(args.log_keep, 2);
+        assert!(Args::try_parse_from(["kanata", "--log-max-size", "big"]).is_err());
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+    fn signal_actions() {
+        let args = Args::try_parse_from(["kanata"]).unwrap();
+        assert_eq!(
+            (args.sigusr1, args.sigusr2, args.sighup),
+            (None, None, None)
+        );
+        let args =
+            Args::try_parse_from(["kanata", "--sigusr1", "reload", "--sighup", "rotate-logs"])
+                .unwrap();
+        assert_eq!(args.sigusr1, Some(SignalAction::Reload));
+        assert_eq!(args.sighup, Some(SignalAction::RotateLogs));
+        assert!(Args::try_parse_from(["kanata", "--sigusr2", "restart"]).is_err());
+    }
+
+    #[test]
+    fn log_format() {
+        let args = Args::try_parse_from(["kanata"]).unwrap();
+        assert_eq!(args.log_format, LogFormat::Text);
+        let args = Args::try_parse_from(["kanata", "--log-format", "json"]).unwrap();
+        assert_eq!(args.log_format, LogFormat::Json);
+        assert!(Args::try_parse_from(["kanata", "--log-format", "xml"]).is_err());
+    }
+
+    #[cfg(feature = "simulated_output")]
+    #[test]
+    fn output_json() {
+        let args = Args::try_parse_from(["kanata"]).unwrap();
+        assert_eq!(args.output_json, None);
+        let args = Args::try_parse_from(["kanata", "--output-json", "-"]).unwrap();
+        assert_eq!(args.output_json, Some(JsonOutputTarget::Stdout));
+        assert!(Args::try_parse_from(["kanata", "--output-json", "stderr"]).is_err());
+    }
+
+    #[test]
+    fn check_command() {
+        let args = Args::try_parse_from(["kanata", "check", "a.kbd", "b.kbd", "--graph"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Check {
+                paths: vec!["a.kbd".into(), "b.kbd".into()],
+                stdin: false,
+                stdin_path: "<stdin>".into(),
+                graph: Some(GraphFormat::Text),
+                run_tests: false,
+                json: false,
+            })
+        );
+        let args = Args::try_parse_from(["kanata", "check", "--graph", "dot", "--stdin"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Check {
+                stdin: true,
+                graph: Some(GraphFormat::Dot),
+                ..
+            })
+        ));
+        let args = Args::try_parse_from(["kanata", "check", "--run-tests"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Check {
+                run_tests: true,
+                ..
+            })
+        ));
+        let args = Args::try_parse_from(["kanata", "check", "--json"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Check { json: true, .. })
+        ));
+        assert!(Args::try_parse_from(["kanata", "check", "--json", "--graph"]).is_err());
+        assert!(Args::try_parse_from(["kanata", "check", "--stdin-path", "a.kbd"]).is_err());
+    }
+
+    #[cfg(feature = "simulated_output")]
+    #[test]
+    fn test_command() {
+        let args = Args::try_parse_from(["kanata", "test", "tests", "--bless"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Test {
+                dir: "tests".into(),
+                bless: true,
+            })
+        );
+        assert!(Args::try_parse_from(["kanata", "test"]).is_err());
+    }
+
+    #[cfg(feature = "simulated_output")]
+    #[test]
+    fn repl_command() {
+        let args = Args::try_parse_from(["kanata", "repl", "--cfg", "a.kbd"]).unwrap();
+        assert_eq!(args.command, Some(Command::Repl));
+        assert_eq!(args.cfg, Some(vec![PathBuf::from("a.kbd")]));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn record_command() {
+        let args = Args::try_parse_from([
+            "kanata",
+            "record",
+            "--device",
+            "/dev/input/event3",
+            "--grab",
+            "-o",
+            "sim.txt",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Record {
+                devices: vec!["/dev/input/event3".into()],
+                grab: true,
+                output: Some("sim.txt".into()),
+            })
+        );
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn list_devices_command() {
+        let args = Args::try_parse_from(["kanata"]).unwrap();
+        assert_eq!(args.command, None);
+        let args =
+            Args::try_parse_from(["kanata", "list-devices", "--json", "-c", "a.kbd"]).unwrap();
+        assert_eq!(args.command, Some(Command::ListDevices { json: true }));
+        assert_eq!(args.cfg, Some(vec![PathBuf::from("a.kbd")]));
+    }
+
+    #[test]
+    fn doctor_command() {
+        let args = Args::try_parse_from(["kanata", "doctor", "-c", "a.kbd"]).unwrap();
+        assert_eq!(args.command, Some(Command::Doctor));
+        assert_eq!(args.cfg, Some(vec![PathBuf::from("a.kbd")]));
+    }
+
+    #[test]
+    fn migrate_command() {
+        let args =
+            Args::try_parse_from(["kanata", "migrate", "kmonad", "k.kbd", "-o", "a.kbd"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Migrate {
+                from: MigrateFrom::Kmonad {
+                    path: "k.kbd".into(),
+                    output: Some("a.kbd".into()),
+                }
+            })
+        );
+        assert!(Args::try_parse_from(["kanata", "migrate", "kmonad"]).is_err());
+        let args = Args::try_parse_from(["kanata", "migrate", "qmk", "keymap.json"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Migrate {
+                from: MigrateFrom::Qmk {
+                    path: "keymap.json".into(),
+                    output: None,
+                }
+            })
+        );
+        let args =
+            Args::try_parse_from(["kanata", "migrate", "karabiner", "karabiner.json"]).unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Migrate {
+                from: MigrateFrom::Karabiner {
+                    path: "karabiner.json".into(),
+                    output: None,
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn export_command() {
+        let args = Args::try_parse_from([
+            "kanata", "export", "--format", "json", "--layer", "nav", "cfg.kbd",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Export {
+                path: "cfg.kbd".into(),
+                format: ExportFormat::Json,
+                layer: Some("nav".into()),
+                output: None,
+            })
+        );
+        let args = Args::try_parse_from(["kanata", "export", "cfg.kbd"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Export {
+                format: ExportFormat::Svg,
+                layer: None,
+                ..
+            })
+        ));
+        assert!(Args::try_parse_from(["kanata", "export", "--format", "png", "cfg.kbd"]).is_err());
+    }
+
     #[cfg(target_os = "macos")]
     #[test]
     fn release_grab_on_lock_default_false() {
@@ -0,0 +1,388 @@
+//! `kanata check`: validates configurations without running them, e.g. in pre-commit hooks or
+//! editors, and prints which files include which and where templates are used. With
+//! `--run-tests`, the tests of `deftest` are run on simulated time. With `--json`, the results
+//! and the `defmeta` of the configurations are printed for tools.
+
+use super::args::GraphFormat;
+use kanata_parser::cfg::sexpr::{self, SExpr};
+use kanata_parser::cfg::{ConfigMeta, ConfigTest, new_from_file, new_from_str_at_path};
+use serde_json::json;
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// A configuration to check.
+pub(crate) enum Source {
+    File(PathBuf),
+    /// Text from stdin, checked as if it were the content of the file at the path.
+    Text {
+        text: String,
+        path: PathBuf,
+    },
+}
+
+impl Source {
+    fn path(&self) -> &Path {
+        match self {
+            Source::File(path) | Source::Text { path, .. } => path,
+        }
+    }
+}
+
+/// Checks every configuration and logs the errors. Returns whether all of them are valid, and
+/// with `run_tests` whether their tests pass. With `json`, prints a JSON array with the result
+/// of each configuration.
+pub(crate) fn check(
+    sources: &[Source],
+    graph: Option<GraphFormat>,
+    run_tests: bool,
+    json: bool,
+) -> bool {
+    let mut valid = true;
+    let mut results = vec![];
+    for source in sources {
+        let res = match source {
+            Source::File(path) => new_from_file(path),
+            Source::Text { text, path } => new_from_str_at_path(text, path),
+        };
+        let path = source.path().display().to_string();
+        match res {
+            Ok(cfg) => {
+                tracing::info!("{path}: valid");
+                let mut result = json!({
+                    "path": path,
+                    "valid": true,
+                    "meta": meta_json(&cfg.meta),
+                });
+                if run_tests {
+                    let passed = run_config_tests(source, &cfg.tests);
+                    valid &= passed;
+                    result["tests_passed"] = passed.into();
+                }
+                results.push(result);
+            }
+            Err(e) => {
+                tracing::error!("{path}: {e:?}");
+                valid = false;
+                results.push(json!({
+                    "path": path,
+                    "valid": false,
+                    "error": e.help().map_or_else(|| e.to_string(), |help| help.to_string()),
+                }));
+            }
+        }
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&results).expect("JSON values serialize")
+        );
+    }
+    if let Some(format) = graph {
+        let mut edges = BTreeSet::new();
+        for source in sources {
+            let text = match source {
+                Source::File(path) => std::fs::read_to_string(path).unwrap_or_default(),
+                Source::Text { text, .. } => text.clone(),
+            };
+            collect_edges(source.path(), &text, &mut edges);
+        }
+        print_graph(&edges, format);
+    }
+    valid
+}
+
+/// Runs the tests of a configuration and logs the failures. Returns whether all of them pass.
+///
+/// A configuration from stdin is run without the files it includes.
+#[cfg(feature = "simulated_output")]
+fn run_config_tests(source: &Source, tests: &[ConfigTest]) -> bool {
+    use kanata_parser::cfg::TestEvent;
+    use kanata_state_machine::engine::{Timed, simulate, simulate_file};
+    use kanata_state_machine::oskbd::{KeyEvent, KeyValue, OutputEvent};
+
+    let mut passed = true;
+    for test in tests {
+        let mut inputs = vec![];
+        let mut time = 0;
+        for event in &test.inputs {
+            let (code, value) = match *event {
+                TestEvent::Press(code) => (code, KeyValue::Press),
+                TestEvent::Release(code) => (code, KeyValue::Release),
+                TestEvent::Repeat(code) => (code, KeyValue::Repeat),
+                TestEvent::Wait(ms) => {
+                    time += u64::from(ms);
+                    continue;
+                }
+            };
+            inputs.push(Timed {
+                time,
+                event: KeyEvent::new(code, value),
+            });
+        }
+        let outputs = match source {
+            Source::File(path) => simulate_file(path, &inputs),
+            Source::Text { text, .. } => simulate(text, &inputs),
+        };
+        let name = format!("{}: test {}", source.path().display(), test.name);
+        let outputs = match outputs {
+            Ok(outputs) => outputs,
+            Err(e) => {
+                tracing::error!("{name} could not run: {e:?}");
+                passed = false;
+                continue;
+            }
+        };
+        let actual = outputs
+            .iter()
+            .filter_map(|output| match output.event {
+                OutputEvent::Key {
+                    code,
+                    value: KeyValue::Press,
+                } => Some(TestEvent::Press(code)),
+                OutputEvent::Key {
+                    code,
+                    value: KeyValue::Release,
+                } => Some(TestEvent::Release(code)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if actual == test.expected {
+            tracing::info!("{name} passed");
+        } else {
+            tracing::error!(
+                "{name} failed\nexpected: {}\n  actual: {}",
+                describe_key_outputs(&test.expected),
+                describe_key_outputs(&actual)
+            );
+            passed = false;
+        }
+    }
+    passed
+}
+
+#[cfg(not(feature = "simulated_output"))]
+fn run_config_tests(source: &Source, _: &[ConfigTest]) -> bool {
+    tracing::error!(
+        "{}: tests can not run, kanata was built without the simulated_output feature",
+        source.path().display()
+    );
+    false
+}
+
+/// Describes key outputs in the format of `deftest`, e.g. `d:lsft d:a u:a u:lsft`.
+#[cfg(feature = "simulated_output")]
+fn describe_key_outputs(outputs: &[kanata_parser::cfg::TestEvent]) -> String {
+    use kanata_parser::cfg::TestEvent;
+    use kanata_parser::keys::oscode_to_str;
+
+    let describe = |kind, code| match oscode_to_str(code) {
+        Some(name) => format!("{kind}:{name}"),
+        None => format!("{kind}:{code}"),
+    };
+    outputs
+        .iter()
+        .map(|output| match *output {
+            TestEvent::Press(code) => describe("d", code),
+            TestEvent::Release(code) => describe("u", code),
+            TestEvent::Repeat(code) => describe("r", code),
+            TestEvent::Wait(ms) => format!("t:{ms}"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The `defmeta` of a configuration, without the options that it does not have.
+fn meta_json(meta: &ConfigMeta) -> serde_json::Value {
+    let mut value = json!({ "tags": meta.tags });
+    for (key, val) in [
+        ("author", &meta.author),
+        ("description", &meta.description),
+        ("version", &meta.version),
+        ("keyboard", &meta.keyboard),
+    ] {
+        if let Some(val) = val {
+            value[key] = val.as_str().into();
+        }
+    }
+    value
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Node {
+    File(String),
+    Template(String),
+}
+
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Node::File(path) => write!(f, "file {path}"),
+            Node::Template(name) => write!(f, "template {name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Edge {
+    /// A file includes another file.
+    Includes,
+    /// A file defines a template.
+    Defines,
+    /// A file or template expands a template.
+    Expands,
+}
+
+impl Edge {
+    fn label(self) -> &'static str {
+        match self {
+            Edge::Includes => "includes",
+            Edge::Defines => "defines",
+            Edge::Expands => "expands",
+        }
+    }
+}
+
+/// Adds the includes and templates of the file at `path` with content `text`, and of the files it
+/// includes. Files that can not be read or parsed add no edges, since checking them reports why.
+fn collect_edges(path: &Path, text: &str, edges: &mut BTreeSet<(Node, Edge, Node)>) {
+    // Includes are relative to the directory of the main file, also in included files.
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let mut pending = vec![(path.to_path_buf(), Some(text.to_owned()))];
+    let mut visited = BTreeSet::new();
+    while let Some((path, text)) = pending.pop() {
+        let file = path.display().to_string();
+        if !visited.insert(file.clone()) {
+            continue;
+        }
+        let Some(text) = text.or_else(|| std::fs::read_to_string(&path).ok()) else {
+            continue;
+        };
+        let Ok(items) = sexpr::parse(&text, &file) else {
+            continue;
+        };
+        let file_node = Node::File(file);
+        for item in items {
+            match item.t.first().and_then(|e| e.atom(None)) {
+                Some("include") => {
+                    let Some(included) = item.t.get(1).and_then(|e| e.atom(None)) else {
+                        continue;
+                    };
+                    let included = dir.join(included.trim_matches('"'));
+                    edges.insert((
+                        file_node.clone(),
+                        Edge::Includes,
+                        Node::File(included.display().to_string()),
+                    ));
+                    pending.push((included, None));
+                }
+                Some("deftemplate") => {
+                    let Some(name) = item.t.get(1).and_then(|e| e.atom(None)) else {
+                        continue;
+                    };
+                    let template = Node::Template(name.trim_matches('"').to_owned());
+                    edges.insert((file_node.clone(), Edge::Defines, template.clone()));
+                    collect_expansions(&item.t[2..], &template, edges);
+                }
+                _ => collect_expansions(&item.t, &file_node, edges),
+            }
+        }
+    }
+}
+
+fn collect_expansions(exprs: &[SExpr], from: &Node, edges: &mut BTreeSet<(Node, Edge, Node)>) {
+    for expr in exprs {
+        let Some(list) = expr.list(None) else {
+            continue;
+        };
+        if let [SExpr::Atom(keyword), SExpr::Atom(name), ..] = list
+            && matches!(keyword.t.as_str(), "template-expand" | "t!")
+        {
+            edges.insert((
+                from.clone(),
+                Edge::Expands,
+                Node::Template(name.t.trim_matches('"').to_owned()),
+            ));
+        }
+        collect_expansions(list, from, edges);
+    }
+}
+
+fn print_graph(edges: &BTreeSet<(Node, Edge, Node)>, format: GraphFormat) {
+    match format {
+        GraphFormat::Text => {
+            for (from, edge, to) in edges {
+                println!("{from} {} {to}", edge.label());
+            }
+        }
+        GraphFormat::Dot => {
+            let id = |node: &Node| format!("{:?}", node.to_string());
+            println!("digraph kanata {{");
+            for (from, edge, to) in edges {
+                println!("  {} -> {} [label={}];", id(from), id(to), edge.label());
+            }
+            println!("}}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_includes_and_templates() {
+        let mut edges = BTreeSet::new();
+        collect_edges(
+            Path::new("dir/main.kbd"),
+            r#"
+(include "other.kbd")
+(deftemplate outer () (t! inner))
+(defalias a (template-expand outer))
+"#,
+            &mut edges,
+        );
+        let lines: Vec<_> = edges
+            .iter()
+            .map(|(from, edge, to)| format!("{from} {} {to}", edge.label()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "file dir/main.kbd includes file dir/other.kbd",
+                "file dir/main.kbd defines template outer",
+                "file dir/main.kbd expands template outer",
+                "template outer expands template inner",
+            ]
+        );
+    }
+
+    #[test]
+    fn meta_json_leaves_out_missing_options() {
+        let meta = ConfigMeta {
+            author: Some("jane".to_owned()),
+            keyboard: Some("Corne".to_owned()),
+            tags: vec!["hrm".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(
+            meta_json(&meta).to_string(),
+            r#"{"author":"jane","keyboard":"Corne","tags":["hrm"]}"#
+        );
+    }
+
+    #[cfg(feature = "simulated_output")]
+    #[test]
+    fn runs_config_tests() {
+        let source = |expect: &str| Source::Text {
+            text: format!(
+                "(defsrc a s) (deflayer base (tap-hold 200 200 a lsft) s)
+                 (deftest hold input (d:a t:250 d:s u:s u:a) expect ({expect}))"
+            ),
+            path: "<stdin>".into(),
+        };
+        assert!(check(&[source("d:lsft d:s u:s u:lsft")], None, true, false));
+        assert!(!check(&[source("d:a d:s u:s u:a")], None, true, false));
+        assert!(check(&[source("d:a d:s u:s u:a")], None, false, false));
+    }
+}
@@ -0,0 +1,608 @@
+//! `kanata doctor`: checks the environment for common setup problems and prints how to fix them.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    /// Might stop kanata from working.
+    Warn,
+    /// Stops kanata from working.
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Finding {
+    status: Status,
+    check: &'static str,
+    message: String,
+    fix: Option<String>,
+}
+
+impl Finding {
+    fn ok(check: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Ok,
+            check,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(check: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warn,
+            check,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(check: &'static str, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            check,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Runs all checks and prints the findings. Returns false if any check failed.
+pub(crate) fn doctor(cfg_paths: &[PathBuf], tcp_address: Option<SocketAddr>) -> bool {
+    let mut findings = vec![check_config(cfg_paths)];
+    platform::check(&mut findings);
+    findings.extend(check_remappers(&running_processes()));
+    if let Some(address) = tcp_address {
+        findings.push(check_tcp_port(address));
+    }
+    for finding in &findings {
+        let status = match finding.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        println!("[{status:<4}] {}: {}", finding.check, finding.message);
+        if let Some(fix) = &finding.fix {
+            for line in fix.lines() {
+                println!("       {line}");
+            }
+        }
+    }
+    !findings.iter().any(|f| f.status == Status::Fail)
+}
+
+/// The checks that the startup self-test runs, with the exit code of kanata when they fail, so
+/// that scripts and service managers can tell the problems apart.
+const STARTUP_CHECKS: &[(&str, i32)] = &[
+    ("uinput", 3),
+    ("input devices", 4),
+    ("karabiner driver", 5),
+    ("input monitoring", 6),
+    ("interception driver", 7),
+    ("tcp port", 8),
+];
+
+/// A failed check of the startup self-test, which kanata exits with after printing how to fix it.
+#[derive(Debug)]
+pub(crate) struct SelfTestFailed {
+    pub(crate) exit_code: i32,
+    check: &'static str,
+    message: String,
+}
+
+impl std::fmt::Display for SelfTestFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "startup self-test failed: {}: {}",
+            self.check, self.message
+        )
+    }
+}
+
+impl std::error::Error for SelfTestFailed {}
+
+/// Runs the checks of problems that stop kanata from working before it starts, so that kanata
+/// fails with how to fix the problem instead of a permission error. The first failed check is
+/// logged with its fix and returned.
+pub(crate) fn startup_self_test(
+    cfg_paths: &[PathBuf],
+    tcp_address: Option<SocketAddr>,
+) -> Result<(), SelfTestFailed> {
+    let mut findings = vec![];
+    platform::check(&mut findings);
+    if let Some(address) = tcp_address {
+        findings.push(check_tcp_port(address));
+    }
+    let failed: Vec<(Finding, i32)> = findings
+        .into_iter()
+        .filter(|finding| finding.status == Status::Fail)
+        .filter_map(|finding| {
+            let code = STARTUP_CHECKS
+                .iter()
+                .find(|(check, _)| *check == finding.check)?
+                .1;
+            Some((finding, code))
+        })
+        .collect();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    // The options decide which checks matter. A configuration that doesn't parse is reported
+    // when kanata loads it.
+    let options = cfg_paths
+        .first()
+        .and_then(|path| kanata_parser::cfg::new_from_file(path).ok())
+        .map(|cfg| cfg.options)
+        .unwrap_or_default();
+    let Some((finding, exit_code)) = failed
+        .into_iter()
+        .find(|(finding, _)| needed_at_startup(finding.check, &options))
+    else {
+        return Ok(());
+    };
+    let failed = SelfTestFailed {
+        exit_code,
+        check: finding.check,
+        message: finding.message,
+    };
+    tracing::error!("{failed}");
+    for line in finding.fix.iter().flat_map(|fix| fix.lines()) {
+        tracing::error!("    {line}");
+    }
+    tracing::error!(
+        "Run `kanata doctor` for all checks, or pass --no-self-test to start without them."
+    );
+    Err(failed)
+}
+
+/// Whether a failed check stops kanata with these options.
+fn needed_at_startup(check: &str, _options: &kanata_parser::cfg::CfgOptions) -> bool {
+    match check {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        "uinput" => {
+            !cfg!(feature = "simulated_output")
+                && _options.linux_opts.linux_output_backend
+                    == kanata_parser::cfg::LinuxCfgOutputBackend::Uinput
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        "input devices" => !_options.linux_opts.linux_continue_if_no_devs_found,
+        _ => true,
+    }
+}
+
+fn check_config(cfg_paths: &[PathBuf]) -> Finding {
+    const CHECK: &str = "config";
+    let Some(path) = cfg_paths.first() else {
+        return Finding::warn(
+            CHECK,
+            "no configuration file found",
+            "Create kanata.kbd in the current directory or the kanata config directory, \
+             or pass --cfg.",
+        );
+    };
+    match kanata_parser::cfg::new_from_file(path) {
+        Ok(_) => Finding::ok(CHECK, format!("{} is valid", path.display())),
+        Err(e) => Finding::fail(
+            CHECK,
+            format!("{} is invalid: {e}", path.display()),
+            format!("Run `kanata check {}` to see the error.", path.display()),
+        ),
+    }
+}
+
+fn check_tcp_port(address: SocketAddr) -> Finding {
+    const CHECK: &str = "tcp port";
+    match std::net::TcpListener::bind(address) {
+        Ok(_) => Finding::ok(CHECK, format!("{address} is available")),
+        Err(e) => Finding::fail(
+            CHECK,
+            format!("cannot listen on {address}: {e}"),
+            "Stop the program using the port, e.g. another kanata, or pass a different --port.",
+        ),
+    }
+}
+
+/// Programs that grab or remap the keyboard, which conflicts with kanata, by the start of their
+/// process name. The names are compared case-insensitively.
+const REMAPPERS: &[(&str, &str)] = &[
+    ("xremap", "xremap"),
+    ("keyd", "keyd"),
+    ("kmonad", "KMonad"),
+    ("udevmon", "interception-tools"),
+    ("input-remapper", "input-remapper"),
+    ("evremap", "evremap"),
+    ("karabiner_grabber", "Karabiner-Elements"),
+    ("autohotkey", "AutoHotkey"),
+    ("powertoys.keyboardmanager", "PowerToys Keyboard Manager"),
+];
+
+fn check_remappers(processes: &[String]) -> Vec<Finding> {
+    const CHECK: &str = "other remappers";
+    let lower: Vec<String> = processes.iter().map(|p| p.to_lowercase()).collect();
+    let mut findings: Vec<Finding> = REMAPPERS
+        .iter()
+        .filter(|(process, _)| lower.iter().any(|p| p.starts_with(process)))
+        .map(|(_, name)| {
+            Finding::warn(
+                CHECK,
+                format!("{name} is running"),
+                format!("Stop {name} while using kanata, or make sure they use different devices."),
+            )
+        })
+        .collect();
+    // This process is one of them.
+    if lower.iter().filter(|p| p.starts_with("kanata")).count() > 1 {
+        findings.push(Finding::warn(
+            CHECK,
+            "another kanata is running",
+            "Stop the other kanata, e.g. a service started at login.",
+        ));
+    }
+    if findings.is_empty() {
+        findings.push(Finding::ok(CHECK, "none running"));
+    }
+    findings
+}
+
+/// Returns the names of the running processes, or none if they can not be listed.
+fn running_processes() -> Vec<String> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        std::fs::read_dir("/proc")
+            .into_iter()
+            .flatten()
+            .flatten()
+            // Skips /proc/self, which would list this process twice.
+            .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+            .filter_map(|entry| std::fs::read_to_string(entry.path().join("comm")).ok())
+            .map(|comm| comm.trim().to_owned())
+            .collect()
+    }
+    #[cfg(target_os = "macos")]
+    {
+        command_lines("ps", &["-Ao", "comm="])
+            .into_iter()
+            .map(|path| path.rsplit('/').next().unwrap_or(&path).to_owned())
+            .collect()
+    }
+    #[cfg(target_os = "windows")]
+    {
+        command_lines("tasklist", &["/fo", "csv", "/nh"])
+            .into_iter()
+            .filter_map(|line| {
+                line.split(',')
+                    .next()
+                    .map(|n| n.trim_matches('"').to_owned())
+            })
+            .collect()
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "windows"
+    )))]
+    {
+        vec![]
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn command_lines(program: &str, args: &[&str]) -> Vec<String> {
+    std::process::Command::new(program)
+        .args(args)
+        .output()
+        .map(|out| {
+            String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod platform {
+    use super::Finding;
+
+    use std::io::ErrorKind;
+
+    const SETUP: &str = "See https://github.com/jtroo/kanata/blob/main/docs/setup-linux.md";
+
+    pub(super) fn check(findings: &mut Vec<Finding>) {
+        let ids = ProcessIds::read();
+        let root = ids.as_ref().is_some_and(|ids| ids.uid == 0);
+        findings.push(check_uinput());
+        findings.push(check_input_devices());
+        if root {
+            findings.push(Finding::ok("groups", "running as root"));
+        } else if let Some(ids) = ids {
+            let groups = std::fs::read_to_string("/etc/group").unwrap_or_default();
+            for group in ["input", "uinput"] {
+                findings.push(check_group(group, &groups, &ids));
+            }
+        }
+    }
+
+    fn check_uinput() -> Finding {
+        const CHECK: &str = "uinput";
+        match std::fs::OpenOptions::new().write(true).open("/dev/uinput") {
+            Ok(_) => Finding::ok(CHECK, "/dev/uinput is writable"),
+            Err(e) if e.kind() == ErrorKind::NotFound => Finding::fail(
+                CHECK,
+                "/dev/uinput does not exist",
+                format!("Load the uinput module: sudo modprobe uinput\n{SETUP}"),
+            ),
+            Err(e) => Finding::fail(
+                CHECK,
+                format!("cannot open /dev/uinput: {e}"),
+                format!(
+                    "Give the uinput group access with a udev rule and join the group.\n{SETUP}"
+                ),
+            ),
+        }
+    }
+
+    fn check_input_devices() -> Finding {
+        const CHECK: &str = "input devices";
+        let devices: Vec<_> = std::fs::read_dir("/dev/input")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("event"))
+            })
+            .collect();
+        let readable = devices
+            .iter()
+            .filter(|path| std::fs::File::open(path).is_ok())
+            .count();
+        match (devices.len(), readable) {
+            (0, _) => Finding::fail(
+                CHECK,
+                "no devices in /dev/input",
+                "Check that the keyboard is connected and that /dev is mounted.",
+            ),
+            (total, 0) => Finding::fail(
+                CHECK,
+                format!("none of the {total} devices in /dev/input are readable"),
+                format!("Join the input group: sudo usermod -aG input $USER\n{SETUP}"),
+            ),
+            (total, readable) => Finding::ok(
+                CHECK,
+                format!("{readable} of {total} devices in /dev/input are readable"),
+            ),
+        }
+    }
+
+    /// The user and the groups of this process.
+    pub(super) struct ProcessIds {
+        pub(super) uid: u32,
+        pub(super) user: Option<String>,
+        pub(super) gids: Vec<u32>,
+    }
+
+    impl ProcessIds {
+        fn read() -> Option<Self> {
+            let status = std::fs::read_to_string("/proc/self/status").ok()?;
+            let field = |name: &str| {
+                status
+                    .lines()
+                    .find_map(|l| l.strip_prefix(name))
+                    .map(|v| {
+                        v.split_whitespace()
+                            .filter_map(|id| id.parse::<u32>().ok())
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default()
+            };
+            let uid = *field("Uid:").get(1)?;
+            let mut gids = field("Groups:");
+            gids.extend(field("Gid:").get(1));
+            let user = std::fs::read_to_string("/etc/passwd")
+                .ok()?
+                .lines()
+                .find(|l| l.split(':').nth(2) == Some(&uid.to_string()))
+                .and_then(|l| l.split(':').next())
+                .map(str::to_owned);
+            Some(Self { uid, user, gids })
+        }
+    }
+
+    /// Checks membership in `group` given the contents of `/etc/group`.
+    pub(super) fn check_group(group: &str, etc_group: &str, ids: &ProcessIds) -> Finding {
+        const CHECK: &str = "groups";
+        let Some(fields) = etc_group
+            .lines()
+            .map(|l| l.split(':').collect::<Vec<_>>())
+            .find(|fields| fields.first() == Some(&group))
+        else {
+            return Finding::warn(
+                CHECK,
+                format!("the {group} group does not exist"),
+                format!("Create it: sudo groupadd --system {group}\n{SETUP}"),
+            );
+        };
+        let gid = fields.get(2).and_then(|gid| gid.parse::<u32>().ok());
+        if gid.is_some_and(|gid| ids.gids.contains(&gid)) {
+            return Finding::ok(CHECK, format!("in the {group} group"));
+        }
+        let members = fields.get(3).copied().unwrap_or("");
+        let listed = ids
+            .user
+            .as_deref()
+            .is_some_and(|user| members.split(',').any(|m| m == user));
+        if listed {
+            Finding::warn(
+                CHECK,
+                format!("added to the {group} group, but not in this session"),
+                format!("Log out and back in, or run: newgrp {group}"),
+            )
+        } else {
+            Finding::warn(
+                CHECK,
+                format!("not in the {group} group"),
+                format!("sudo usermod -aG {group} $USER, then log out and back in"),
+            )
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::Finding;
+    use kanata_state_machine::oskbd::{accessibility_trusted, input_monitoring_granted};
+
+    const SETTINGS: &str = "System Settings -> Privacy & Security";
+
+    pub(super) fn check(findings: &mut Vec<Finding>) {
+        findings.push(if karabiner_driverkit::driver_activated() {
+            Finding::ok(
+                "karabiner driver",
+                "Karabiner-VirtualHIDDevice is activated",
+            )
+        } else {
+            Finding::fail(
+                "karabiner driver",
+                "Karabiner-VirtualHIDDevice is not activated",
+                "Install and activate the driver as described in \
+                 https://github.com/jtroo/kanata/blob/main/docs/setup-macos.md",
+            )
+        });
+        findings.push(match input_monitoring_granted() {
+            Some(true) => Finding::ok("input monitoring", "granted"),
+            Some(false) => Finding::fail(
+                "input monitoring",
+                "denied",
+                format!("Enable kanata in {SETTINGS} -> Input Monitoring."),
+            ),
+            None => Finding::warn(
+                "input monitoring",
+                "not decided yet",
+                format!("Run kanata once, then enable it in {SETTINGS} -> Input Monitoring."),
+            ),
+        });
+        findings.push(if accessibility_trusted() {
+            Finding::ok("accessibility", "granted to this process")
+        } else {
+            Finding::warn(
+                "accessibility",
+                "not granted to this process",
+                format!(
+                    "Run kanata --macos-request-permissions, then enable kanata in \
+                     {SETTINGS} -> Accessibility."
+                ),
+            )
+        });
+        // SAFETY: plain libc call with no args.
+        if unsafe { libc::geteuid() } != 0 {
+            findings.push(Finding::warn(
+                "root",
+                "not running as root",
+                "kanata needs root to use the Karabiner driver: run it with sudo.",
+            ));
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::Finding;
+
+    pub(super) fn check(findings: &mut Vec<Finding>) {
+        const CHECK: &str = "interception driver";
+        let system_root = std::env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".into());
+        let installed = std::path::Path::new(&system_root)
+            .join(r"System32\drivers\keyboard.sys")
+            .is_file();
+        let used = cfg!(feature = "interception_driver");
+        findings.push(match (installed, used) {
+            (true, true) => Finding::ok(CHECK, "installed"),
+            (false, true) => Finding::fail(
+                CHECK,
+                "not installed, but this kanata uses it",
+                "Install it from https://github.com/oblitum/Interception and reboot, \
+                 or use a kanata build without interception.",
+            ),
+            (true, false) => Finding::ok(CHECK, "installed, but not used by this kanata"),
+            (false, false) => Finding::ok(CHECK, "not installed, not needed by this kanata"),
+        });
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "windows"
+)))]
+mod platform {
+    pub(super) fn check(_: &mut Vec<super::Finding>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_running_remappers() {
+        let findings = check_remappers(&["bash".into(), "xremap".into(), "kanata".into()]);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].status, Status::Warn);
+        assert_eq!(findings[0].message, "xremap is running");
+        let findings = check_remappers(&["kanata".into(), "kanata".into()]);
+        assert_eq!(findings[0].message, "another kanata is running");
+        assert_eq!(check_remappers(&["kanata".into()])[0].status, Status::Ok);
+    }
+
+    #[test]
+    fn finds_used_tcp_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        assert_eq!(check_tcp_port(address).status, Status::Fail);
+        drop(listener);
+        assert_eq!(check_tcp_port(address).status, Status::Ok);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn startup_checks_depend_on_options() {
+        use kanata_parser::cfg::{CfgOptions, LinuxCfgOutputBackend};
+        let mut options = CfgOptions::default();
+        assert_eq!(
+            needed_at_startup("uinput", &options),
+            !cfg!(feature = "simulated_output")
+        );
+        assert!(needed_at_startup("input devices", &options));
+        options.linux_opts.linux_output_backend = LinuxCfgOutputBackend::Remote;
+        options.linux_opts.linux_continue_if_no_devs_found = true;
+        assert!(!needed_at_startup("uinput", &options));
+        assert!(!needed_at_startup("input devices", &options));
+        assert!(needed_at_startup("tcp port", &options));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn checks_group_membership() {
+        use platform::{ProcessIds, check_group};
+        let ids = ProcessIds {
+            uid: 1000,
+            user: Some("me".into()),
+            gids: vec![1000, 104],
+        };
+        let etc_group = "input:x:104:\nuinput:x:990:me\n";
+        assert_eq!(check_group("input", etc_group, &ids).status, Status::Ok);
+        let finding = check_group("uinput", etc_group, &ids);
+        assert_eq!(
+            finding.message,
+            "added to the uinput group, but not in this session"
+        );
+        let finding = check_group("plugdev", etc_group, &ids);
+        assert_eq!(finding.message, "the plugdev group does not exist");
+    }
+}
@@ -0,0 +1,302 @@
+//! `kanata export`: renders what the keys of a layer do as a keyboard diagram, so that
+//! documentation and cheat sheets are made from the configuration itself. The keys are placed
+//! where they are written in `defsrc`, i.e. each line of `defsrc` is a row and the columns go by
+//! the position of the keys in their line. The configuration can also be exported as a QMK
+//! keymap.
+
+mod qmk;
+
+use super::args::ExportFormat;
+use anyhow::{Result, anyhow, bail};
+use kanata_keyberon::action::Action;
+use kanata_parser::cfg::sexpr::{self, SExpr};
+use kanata_parser::cfg::{Cfg, action_label, new_from_file};
+use kanata_parser::keys::str_to_oscode;
+use serde_json::json;
+
+use std::path::{Path, PathBuf};
+
+/// Width and height of a key in the SVG, and the space between keys.
+const KEY_SIZE: f64 = 54.0;
+const KEY_GAP: f64 = 4.0;
+/// Labels longer than this are cut in the SVG, which still shows them in full on hover.
+const MAX_LABEL_CHARS: usize = 8;
+
+/// A key of `defsrc` and what it does on the exported layer.
+#[derive(Debug, Clone, PartialEq)]
+struct Key {
+    name: String,
+    /// The position in keys from the top left key.
+    x: f64,
+    y: f64,
+    label: String,
+    /// The label of the hold action of a tap-hold key.
+    hold: Option<String>,
+    transparent: bool,
+}
+
+/// Prints or writes the diagram of the layer, or of the first layer if it is None. What does not
+/// export exactly is printed to stderr.
+pub(crate) fn export(
+    path: &Path,
+    format: ExportFormat,
+    layer: Option<&str>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let cfg = new_from_file(path).map_err(|e| anyhow!("{e:?}"))?;
+    // The defsrc can be in an included file.
+    let texts = std::iter::once(path.to_path_buf())
+        .chain(cfg.files.iter().cloned())
+        .filter_map(|file| Some((std::fs::read_to_string(&file).ok()?, file)))
+        .collect::<Vec<(String, PathBuf)>>();
+    let positions = texts
+        .iter()
+        .find_map(|(text, file)| defsrc_positions(text, &file.display().to_string()))
+        .ok_or_else(|| anyhow!("could not find defsrc in {}", path.display()))?;
+    let (rendered, notes) = render(&cfg, &positions, format, layer)?;
+    for note in &notes {
+        eprintln!("{}: {note}", path.display());
+    }
+    if !notes.is_empty() {
+        eprintln!("{} keys need a manual check.", notes.len());
+    }
+    match output {
+        Some(output) => std::fs::write(output, rendered)?,
+        None => print!("{rendered}"),
+    }
+    Ok(())
+}
+
+/// Returns the export and the notes about what did not export exactly.
+fn render(
+    cfg: &Cfg,
+    positions: &[(String, usize, usize)],
+    format: ExportFormat,
+    layer: Option<&str>,
+) -> Result<(String, Vec<String>)> {
+    if format == ExportFormat::Qmk {
+        if layer.is_some() {
+            bail!("--layer can not be used with --format qmk, which exports every layer");
+        }
+        return qmk::convert(cfg, positions);
+    }
+    let layer_idx = match layer {
+        Some(name) => cfg
+            .layer_info
+            .iter()
+            .position(|info| info.name == name)
+            .ok_or_else(|| anyhow!("unknown layer {name}"))?,
+        None => 0,
+    };
+    let info = &cfg.layer_info[layer_idx];
+    let keys = layer_keys(cfg, layer_idx, positions)?;
+    let rendered = match format {
+        ExportFormat::Json => {
+            let keys = keys
+                .iter()
+                .map(|key| {
+                    let mut value = json!({
+                        "key": key.name,
+                        "x": key.x,
+                        "y": key.y,
+                        "label": key.label,
+                        "transparent": key.transparent,
+                    });
+                    if let Some(hold) = &key.hold {
+                        value["hold"] = hold.as_str().into();
+                    }
+                    value
+                })
+                .collect::<Vec<_>>();
+            let mut value = json!({ "layer": info.name, "keys": keys });
+            if let Some(display_name) = &info.display_name {
+                value["display_name"] = display_name.as_str().into();
+            }
+            serde_json::to_string_pretty(&value).expect("JSON values serialize") + "\n"
+        }
+        ExportFormat::Svg => svg(info.display_name.as_deref().unwrap_or(&info.name), &keys),
+        ExportFormat::Qmk => unreachable!("exported above"),
+    };
+    Ok((rendered, vec![]))
+}
+
+/// Returns the keys of `defsrc` with their line and column in the text, or None if the text has
+/// no `defsrc`.
+fn defsrc_positions(text: &str, file: &str) -> Option<Vec<(String, usize, usize)>> {
+    let items = sexpr::parse(text, file).ok()?;
+    let defsrc = items
+        .iter()
+        .find(|item| item.t.first().and_then(|e| e.atom(None)) == Some("defsrc"))?;
+    let positions = defsrc.t[1..]
+        .iter()
+        .filter_map(|expr| match expr {
+            SExpr::Atom(atom) => {
+                let before = &text[..atom.span.start()];
+                let line = before.matches('\n').count();
+                let column = before.rsplit('\n').next().unwrap_or("").chars().count();
+                Some((atom.t.clone(), line, column))
+            }
+            SExpr::List(_) => None,
+        })
+        .collect();
+    Some(positions)
+}
+
+/// Returns the keys at the positions in key units, with what they do on the layer.
+fn layer_keys(cfg: &Cfg, layer: usize, positions: &[(String, usize, usize)]) -> Result<Vec<Key>> {
+    let first_line = positions.iter().map(|p| p.1).min().unwrap_or(0);
+    let first_column = positions.iter().map(|p| p.2).min().unwrap_or(0);
+    // A key is as wide as the smallest distance between keys in a line.
+    let width = positions
+        .windows(2)
+        .filter(|pair| pair[0].1 == pair[1].1 && pair[1].2 > pair[0].2)
+        .map(|pair| pair[1].2 - pair[0].2)
+        .min()
+        .unwrap_or(1);
+    let layer_names = cfg
+        .layer_info
+        .iter()
+        .map(|info| info.name.as_str())
+        .collect::<Vec<_>>();
+    positions
+        .iter()
+        .map(|(name, line, column)| {
+            let Some(osc) = str_to_oscode(name) else {
+                bail!("unknown key in defsrc: {name}");
+            };
+            let action = cfg.layout.b().layers.get(layer, 0, usize::from(osc));
+            let (label, hold) = match action {
+                Action::HoldTap(hold_tap) => (
+                    action_label(&hold_tap.tap, &layer_names),
+                    Some(action_label(&hold_tap.hold, &layer_names)),
+                ),
+                action => (action_label(action, &layer_names), None),
+            };
+            Ok(Key {
+                name: name.clone(),
+                x: (column - first_column) as f64 / width as f64,
+                y: (line - first_line) as f64,
+                label,
+                hold,
+                transparent: matches!(action, Action::Trans),
+            })
+        })
+        .collect()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn svg(title: &str, keys: &[Key]) -> String {
+    let step = KEY_SIZE + KEY_GAP;
+    let width = keys.iter().map(|key| key.x).fold(0.0, f64::max) * step + step + KEY_GAP;
+    let height = keys.iter().map(|key| key.y).fold(0.0, f64::max) * step + step + KEY_GAP + 24.0;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         font-family=\"sans-serif\" text-anchor=\"middle\">\n\
+         <text x=\"{}\" y=\"18\" font-size=\"16\">{}</text>\n",
+        width / 2.0,
+        xml_escape(title)
+    );
+    for key in keys {
+        let x = KEY_GAP + key.x * step;
+        let y = 24.0 + KEY_GAP + key.y * step;
+        let (fill, color) = match key.transparent {
+            true => ("#f4f4f4", "#aaaaaa"),
+            false => ("#ffffff", "#000000"),
+        };
+        let label = match key.transparent {
+            true => key.name.as_str(),
+            false => key.label.as_str(),
+        };
+        let mut tooltip = format!("{}: {}", key.name, key.label);
+        if let Some(hold) = &key.hold {
+            tooltip += &format!(" / {hold}");
+        }
+        svg += &format!(
+            "<g><title>{}</title>\
+             <rect x=\"{x}\" y=\"{y}\" width=\"{KEY_SIZE}\" height=\"{KEY_SIZE}\" rx=\"6\" \
+             fill=\"{fill}\" stroke=\"#888888\"/>\
+             <text x=\"{}\" y=\"{}\" font-size=\"13\" fill=\"{color}\">{}</text>",
+            xml_escape(&tooltip),
+            x + KEY_SIZE / 2.0,
+            y + KEY_SIZE / 2.0 + 4.0,
+            xml_escape(&cut(label))
+        );
+        if let Some(hold) = &key.hold {
+            svg += &format!(
+                "<text x=\"{}\" y=\"{}\" font-size=\"10\" fill=\"#555555\">{}</text>",
+                x + KEY_SIZE / 2.0,
+                y + KEY_SIZE - 6.0,
+                xml_escape(&cut(hold))
+            );
+        }
+        svg += "</g>\n";
+    }
+    svg + "</svg>\n"
+}
+
+fn cut(label: &str) -> String {
+    match label.chars().count() > MAX_LABEL_CHARS {
+        true => label.chars().take(MAX_LABEL_CHARS - 1).collect::<String>() + "…",
+        false => label.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kanata_parser::cfg::new_from_str_at_path;
+
+    const CFG: &str = "
+(defsrc
+  q    w    e
+  caps a    s
+)
+(deflayer base q w e caps a s)
+(deflayer (nav display-name Navigation)
+  XX   C-w  (layer-switch base)
+  _    (tap-hold 200 200 a lsft) 🔣→
+)
+";
+
+    #[test]
+    fn positions_follow_defsrc_columns() {
+        let positions = defsrc_positions(CFG, "cfg.kbd").unwrap();
+        assert_eq!(positions[0], ("q".to_owned(), 2, 2));
+        assert_eq!(positions[4], ("a".to_owned(), 3, 7));
+    }
+
+    #[test]
+    fn exports_resolved_layer() {
+        let cfg = new_from_str_at_path(CFG, Path::new("cfg.kbd")).unwrap();
+        let positions = defsrc_positions(CFG, "cfg.kbd").unwrap();
+        let keys = layer_keys(&cfg, 1, &positions).unwrap();
+        let described = keys
+            .iter()
+            .map(|key| (key.x, key.y, key.label.as_str(), key.hold.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            described,
+            [
+                (0.0, 0.0, "XX", None),
+                (1.0, 0.0, "lctrl+w", None),
+                (2.0, 0.0, "to base", None),
+                (0.0, 1.0, "_", None),
+                (1.0, 1.0, "a", Some("lshift")),
+                (2.0, 1.0, "→", None),
+            ]
+        );
+        assert!(keys[3].transparent);
+        let (json, _) = render(&cfg, &positions, ExportFormat::Json, Some("nav")).unwrap();
+        assert!(json.contains(r#""display_name": "Navigation""#), "{json}");
+        let (svg, _) = render(&cfg, &positions, ExportFormat::Svg, Some("nav")).unwrap();
+        assert!(svg.starts_with("<svg"), "{svg}");
+        assert!(svg.contains(">Navigation</text>"), "{svg}");
+        assert!(render(&cfg, &positions, ExportFormat::Svg, Some("num")).is_err());
+    }
+}
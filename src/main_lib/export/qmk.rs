@@ -0,0 +1,315 @@
+//! Exports a configuration as a keymap JSON of QMK Configurator, which `qmk json2c` converts to a
+//! `keymap.c`. The keys of each layer are in the order of `defsrc`, which must be the order of the
+//! `LAYOUT` macro of the keyboard. Keys, keys with modifiers, mod-taps, layer-taps, layer keys,
+//! one-shots and macros are exported; other actions become `KC_NO` with a note.
+
+use crate::main_lib::migrate::qmk::basic;
+use anyhow::{Result, bail};
+use kanata_keyberon::action::{Action, HoldTapAction, HoldTapConfig, SequenceEvent};
+use kanata_keyberon::key_code::KeyCode;
+use kanata_parser::cfg::{Cfg, action_label};
+use kanata_parser::custom_action::CustomAction;
+use kanata_parser::keys::{OsCode, oscode_to_str, str_to_oscode};
+use serde_json::json;
+
+/// The default `TAPPING_TERM` of QMK.
+const TAPPING_TERM: u16 = 200;
+
+/// QMK keycodes of keys whose kanata name is not the QMK name without `KC_`. They are tried
+/// before the QMK name made from the kanata name.
+const KEYS: &[&str] = &[
+    "KC_ENT", "KC_ESC", "KC_BSPC", "KC_TAB", "KC_SPC", "KC_MINS", "KC_EQL", "KC_LBRC", "KC_RBRC",
+    "KC_BSLS", "KC_SCLN", "KC_QUOT", "KC_GRV", "KC_COMM", "KC_DOT", "KC_SLSH", "KC_CAPS",
+    "KC_PSCR", "KC_SCRL", "KC_PAUS", "KC_INS", "KC_HOME", "KC_PGUP", "KC_DEL", "KC_END", "KC_PGDN",
+    "KC_RGHT", "KC_LEFT", "KC_DOWN", "KC_UP", "KC_NUM", "KC_PSLS", "KC_PAST", "KC_PMNS", "KC_PPLS",
+    "KC_PENT", "KC_PDOT", "KC_P0", "KC_P1", "KC_P2", "KC_P3", "KC_P4", "KC_P5", "KC_P6", "KC_P7",
+    "KC_P8", "KC_P9", "KC_NUBS", "KC_APP", "KC_LCTL", "KC_LSFT", "KC_LALT", "KC_LGUI", "KC_RCTL",
+    "KC_RSFT", "KC_RALT", "KC_RGUI", "KC_MUTE", "KC_VOLU", "KC_VOLD", "KC_MNXT", "KC_MPRV",
+    "KC_MPLY", "KC_BRIU", "KC_BRID",
+];
+
+/// Modifiers with their modifier function, e.g. `LCTL(KC_A)`, their `MOD_*` constant and their
+/// mod-tap function, e.g. `LCTL_T(KC_A)`.
+const MODIFIERS: &[(KeyCode, &str, &str, &str)] = &[
+    (KeyCode::LCtrl, "LCTL", "MOD_LCTL", "LCTL_T"),
+    (KeyCode::LShift, "LSFT", "MOD_LSFT", "LSFT_T"),
+    (KeyCode::LAlt, "LALT", "MOD_LALT", "LALT_T"),
+    (KeyCode::LGui, "LGUI", "MOD_LGUI", "LGUI_T"),
+    (KeyCode::RCtrl, "RCTL", "MOD_RCTL", "RCTL_T"),
+    (KeyCode::RShift, "RSFT", "MOD_RSFT", "RSFT_T"),
+    (KeyCode::RAlt, "RALT", "MOD_RALT", "RALT_T"),
+    (KeyCode::RGui, "RGUI", "MOD_RGUI", "RGUI_T"),
+];
+
+struct Exporter<'a> {
+    layer_names: Vec<&'a str>,
+    macros: Vec<Vec<serde_json::Value>>,
+    notes: Vec<String>,
+    /// Where the action being exported is, for the notes.
+    at: String,
+}
+
+/// Returns the keymap JSON and the notes about what did not export exactly.
+pub(super) fn convert(
+    cfg: &Cfg,
+    positions: &[(String, usize, usize)],
+) -> Result<(String, Vec<String>)> {
+    let keys = positions
+        .iter()
+        .map(|(name, ..)| match str_to_oscode(name) {
+            Some(osc) => Ok((name.as_str(), osc)),
+            None => bail!("unknown key in defsrc: {name}"),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut e = Exporter {
+        layer_names: cfg
+            .layer_info
+            .iter()
+            .map(|info| info.name.as_str())
+            .collect(),
+        macros: vec![],
+        notes: vec![],
+        at: String::new(),
+    };
+    let layers = cfg
+        .layer_info
+        .iter()
+        .enumerate()
+        .map(|(layer, info)| {
+            keys.iter()
+                .map(|&(name, osc)| {
+                    e.at = format!("layer {}, key {name}", info.name);
+                    e.action(cfg.layout.b().layers.get(layer, 0, usize::from(osc)))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    e.notes.push(
+        "set keyboard and layout to the keyboard and its LAYOUT macro, \
+         whose keys must be in the order of defsrc"
+            .to_owned(),
+    );
+    let mut keymap = json!({
+        "version": 1,
+        "notes": "Exported by kanata export",
+        "keyboard": "",
+        "keymap": "kanata",
+        "layout": "",
+        "layers": layers,
+    });
+    if !e.macros.is_empty() {
+        keymap["macros"] = e.macros.into();
+    }
+    let keymap = serde_json::to_string_pretty(&keymap).expect("JSON values serialize") + "\n";
+    Ok((keymap, e.notes))
+}
+
+/// Returns the QMK keycode of the key, e.g. `KC_A`.
+fn keycode(key: KeyCode) -> Option<String> {
+    let osc = OsCode::from(key);
+    let sends = |keycode: &str| basic(keycode).and_then(|key| str_to_oscode(&key)) == Some(osc);
+    if let Some(keycode) = KEYS.iter().find(|keycode| sends(keycode)) {
+        return Some((*keycode).to_owned());
+    }
+    let keycode = format!("KC_{}", oscode_to_str(osc)?.to_uppercase());
+    sends(&keycode).then_some(keycode)
+}
+
+fn modifier(key: KeyCode) -> Option<&'static (KeyCode, &'static str, &'static str, &'static str)> {
+    MODIFIERS.iter().find(|m| m.0 == key)
+}
+
+/// Returns the `MOD_*` constants of an action that holds only modifiers.
+fn mod_constants(action: &Action<&CustomAction>) -> Option<Vec<&'static str>> {
+    let keys = match action {
+        Action::KeyCode(key) => vec![*key],
+        Action::MultipleKeyCodes(keys) => keys.to_vec(),
+        Action::MultipleActions(actions) => actions
+            .iter()
+            .map(|action| match action {
+                Action::KeyCode(key) => Some(*key),
+                _ => None,
+            })
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+    keys.iter().map(|key| modifier(*key).map(|m| m.2)).collect()
+}
+
+impl Exporter<'_> {
+    fn note(&mut self, message: impl Into<String>) {
+        self.notes.push(format!("{}: {}", self.at, message.into()));
+    }
+
+    fn unsupported(&mut self, action: &Action<&CustomAction>) -> String {
+        let label = action_label(action, &self.layer_names);
+        self.note(format!(
+            "`{label}` has no QMK equivalent, exported as KC_NO"
+        ));
+        "KC_NO".to_owned()
+    }
+
+    fn action(&mut self, action: &Action<&CustomAction>) -> String {
+        let exported = match action {
+            Action::Trans => Some("KC_TRNS".to_owned()),
+            Action::NoOp => Some("KC_NO".to_owned()),
+            Action::KeyCode(key) => keycode(*key),
+            Action::MultipleKeyCodes(keys) => with_modifiers(keys),
+            Action::Layer(layer) => Some(format!("MO({layer})")),
+            Action::DefaultLayer(layer) => Some(format!("TO({layer})")),
+            Action::HoldTap(hold_tap) => self.hold_tap(hold_tap),
+            Action::OneShot(one_shot) => match one_shot.action {
+                Action::Layer(layer) => Some(format!("OSL({layer})")),
+                action => mod_constants(action).map(|mods| format!("OSM({})", mods.join("|"))),
+            },
+            Action::Sequence { events } => self.macro_keycode(events),
+            _ => None,
+        };
+        exported.unwrap_or_else(|| self.unsupported(action))
+    }
+
+    fn hold_tap(&mut self, hold_tap: &HoldTapAction<&CustomAction>) -> Option<String> {
+        let Action::KeyCode(tap) = hold_tap.tap else {
+            return None;
+        };
+        let tap = keycode(tap)?;
+        let exported = match (&hold_tap.hold, mod_constants(&hold_tap.hold)) {
+            (Action::Layer(layer), _) => format!("LT({layer}, {tap})"),
+            (Action::KeyCode(key), _) if modifier(*key).is_some() => {
+                format!("{}({tap})", modifier(*key)?.3)
+            }
+            (_, Some(mods)) => format!("MT({}, {tap})", mods.join("|")),
+            _ => return None,
+        };
+        if hold_tap.timeout != TAPPING_TERM {
+            self.note(format!(
+                "the timeout of {} ms needs TAPPING_TERM or TAPPING_TERM_PER_KEY, \
+                 which default to {TAPPING_TERM} ms",
+                hold_tap.timeout
+            ));
+        }
+        if hold_tap.config != HoldTapConfig::Default {
+            self.note(
+                "the flavor of the tap-hold is set for the whole keyboard in QMK, \
+                 e.g. with PERMISSIVE_HOLD or HOLD_ON_OTHER_KEY_PRESS",
+            );
+        }
+        Some(exported)
+    }
+
+    /// Adds the macro to the keymap and returns its keycode, e.g. `QK_MACRO_0`.
+    fn macro_keycode(&mut self, events: &[SequenceEvent<&CustomAction>]) -> Option<String> {
+        let key = |key: KeyCode| keycode(key).map(|k| k.trim_start_matches("KC_").to_owned());
+        let mut steps: Vec<serde_json::Value> = vec![];
+        let mut events = events.iter().peekable();
+        while let Some(event) = events.next() {
+            let step = match event {
+                // A press that is released at once is a tap.
+                SequenceEvent::Press(pressed) if matches!(events.peek(), Some(SequenceEvent::Release(released)) if released == pressed) =>
+                {
+                    events.next();
+                    json!({ "action": "tap", "keycodes": [key(*pressed)?] })
+                }
+                SequenceEvent::Press(k) => json!({ "action": "down", "keycodes": [key(*k)?] }),
+                SequenceEvent::Release(k) => json!({ "action": "up", "keycodes": [key(*k)?] }),
+                SequenceEvent::Tap(k) => json!({ "action": "tap", "keycodes": [key(*k)?] }),
+                SequenceEvent::Delay { duration } => {
+                    json!({ "action": "delay", "duration": duration })
+                }
+                SequenceEvent::NoOp | SequenceEvent::Complete => continue,
+                _ => return None,
+            };
+            steps.push(step);
+        }
+        self.macros.push(steps);
+        Some(format!("QK_MACRO_{}", self.macros.len() - 1))
+    }
+}
+
+/// Returns the keycode of a key with modifiers, e.g. `LCTL(LSFT(KC_Z))`.
+fn with_modifiers(keys: &[KeyCode]) -> Option<String> {
+    let (mods, others): (Vec<KeyCode>, Vec<KeyCode>) =
+        keys.iter().partition(|key| modifier(**key).is_some());
+    let (mods, key) = match others.as_slice() {
+        [key] => (mods.as_slice(), *key),
+        // Only modifiers, the last one is sent as the key.
+        [] => mods.split_last().map(|(key, mods)| (mods, *key))?,
+        _ => return None,
+    };
+    let mut exported = keycode(key)?;
+    for m in mods.iter().rev() {
+        exported = format!("{}({exported})", modifier(*m)?.1);
+    }
+    Some(exported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kanata_parser::cfg::new_from_str_at_path;
+    use std::path::Path;
+
+    #[test]
+    fn table_keys_export_back() {
+        for keycode in KEYS {
+            assert!(
+                basic(keycode).and_then(|key| str_to_oscode(&key)).is_some(),
+                "{keycode} does not convert to a kanata key"
+            );
+        }
+    }
+
+    #[test]
+    fn exports_qmk_keymap() {
+        let text = "
+(defsrc esc a s d f spc)
+(deflayer base
+  esc (tap-hold 200 200 a lctl) (tap-hold 200 200 s (layer-while-held nav)) C-S-d
+  (macro h i 10 S-1) (one-shot 500 (layer-while-held nav)))
+(deflayer nav
+  (layer-switch base) (tap-hold 150 150 a (multi lctl lsft)) XX _
+  (one-shot 500 lsft) (unicode λ))
+";
+        let cfg = new_from_str_at_path(text, Path::new("cfg.kbd")).unwrap();
+        let positions = super::super::defsrc_positions(text, "cfg.kbd").unwrap();
+        let (keymap, notes) = convert(&cfg, &positions).unwrap();
+        let keymap: serde_json::Value = serde_json::from_str(&keymap).unwrap();
+        assert_eq!(
+            keymap["layers"],
+            json!([
+                [
+                    "KC_ESC",
+                    "LCTL_T(KC_A)",
+                    "LT(1, KC_S)",
+                    "LCTL(LSFT(KC_D))",
+                    "QK_MACRO_0",
+                    "OSL(1)"
+                ],
+                [
+                    "TO(0)",
+                    "MT(MOD_LCTL|MOD_LSFT, KC_A)",
+                    "KC_NO",
+                    "KC_TRNS",
+                    "OSM(MOD_LSFT)",
+                    "KC_NO"
+                ]
+            ])
+        );
+        assert_eq!(
+            keymap["macros"][0][0],
+            json!({"action": "tap", "keycodes": ["H"]})
+        );
+        assert_eq!(
+            keymap["macros"][0][2],
+            json!({"action": "delay", "duration": 10})
+        );
+        assert_eq!(
+            notes[..2],
+            [
+                "layer nav, key a: the timeout of 150 ms needs TAPPING_TERM or \
+                 TAPPING_TERM_PER_KEY, which default to 200 ms",
+                "layer nav, key spc: `λ` has no QMK equivalent, exported as KC_NO",
+            ]
+        );
+    }
+}
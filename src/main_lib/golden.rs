@@ -0,0 +1,131 @@
+//! `kanata test`: runs every simulator input file `NAME.sim` of a directory on a configuration
+//! and compares what `kanata repl` would print for it to the expected output in `NAME.out`, e.g.
+//! for keyboard regression tests of a dotfile repository in CI. `--bless` writes the outputs to
+//! the expected files instead.
+
+use super::repl::simulate_lines;
+use anyhow::{Context, Result, bail};
+
+use std::path::{Path, PathBuf};
+
+const INPUT_EXTENSION: &str = "sim";
+const EXPECTED_EXTENSION: &str = "out";
+
+/// Runs the input files of `dir` on the configuration at `cfg`. Returns whether every output is
+/// the expected one.
+pub(crate) fn run(cfg: &Path, dir: &Path, bless: bool) -> Result<bool> {
+    let mut inputs = std::fs::read_dir(dir)
+        .with_context(|| format!("could not read {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    inputs.retain(|path| path.extension().is_some_and(|ext| ext == INPUT_EXTENSION));
+    inputs.sort();
+    if inputs.is_empty() {
+        bail!("{} has no .{INPUT_EXTENSION} files", dir.display());
+    }
+    let mut failed = 0;
+    for input in &inputs {
+        let name = input.display();
+        let expected_path = input.with_extension(EXPECTED_EXTENSION);
+        let actual = std::fs::read_to_string(input)
+            .with_context(|| format!("could not read {name}"))
+            .and_then(|text| simulate_lines(cfg, &text));
+        let actual = match actual {
+            Ok(lines) => lines.join("\n") + "\n",
+            Err(e) => {
+                println!("FAIL {name}: {e:#}");
+                failed += 1;
+                continue;
+            }
+        };
+        let expected = std::fs::read_to_string(&expected_path).ok();
+        if bless {
+            if expected.as_deref() != Some(&actual) {
+                std::fs::write(&expected_path, &actual)
+                    .with_context(|| format!("could not write {}", expected_path.display()))?;
+                println!("blessed {name}");
+            }
+            continue;
+        }
+        match expected {
+            Some(expected) if expected == actual => println!("ok   {name}"),
+            Some(expected) => {
+                println!("FAIL {name}: the output differs, - expected + actual");
+                print!("{}", diff(&expected, &actual));
+                failed += 1;
+            }
+            None => {
+                println!(
+                    "FAIL {name}: {} is missing, run with --bless to create it",
+                    expected_path.display()
+                );
+                failed += 1;
+            }
+        }
+    }
+    if !bless {
+        println!("{} passed, {failed} failed", inputs.len() - failed);
+    }
+    Ok(failed == 0)
+}
+
+/// Returns the lines of both texts, unchanged lines with a leading space, lines only in
+/// `expected` with `-` and lines only in `actual` with `+`.
+fn diff(expected: &str, actual: &str) -> String {
+    let (old, new): (Vec<_>, Vec<_>) = (expected.lines().collect(), actual.lines().collect());
+    // The lengths of the longest common subsequences of the remaining lines.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            out += &format!(" {}\n", old[i]);
+            (i, j) = (i + 1, j + 1);
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out += &format!("-{}\n", old[i]);
+            i += 1;
+        } else {
+            out += &format!("+{}\n", new[j]);
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_lines() {
+        assert_eq!(diff("a\nb\nc\n", "a\nx\nc\nd\n"), " a\n-b\n+x\n c\n+d\n");
+    }
+
+    #[test]
+    fn compares_and_blesses_outputs() {
+        let dir = std::env::temp_dir().join(format!("kanata-golden-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cfg = dir.join("cfg.kbd");
+        std::fs::write(&cfg, "(defsrc a) (deflayer base (tap-hold 100 100 c d))").unwrap();
+        std::fs::write(dir.join("tap.sim"), "d:a t:10 u:a t:20").unwrap();
+        assert!(!run(&cfg, &dir, false).unwrap());
+        assert!(run(&cfg, &dir, true).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("tap.out")).unwrap(),
+            "    10ms press c\n    16ms release c\nlayer: base\n"
+        );
+        assert!(run(&cfg, &dir, false).unwrap());
+        std::fs::write(dir.join("tap.sim"), "d:a t:150 u:a t:20").unwrap();
+        assert!(!run(&cfg, &dir, false).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+//! `kanata list-devices`: lists every input device kanata could use, and whether the `defcfg`
+//! device filters of the configuration would grab it.
+
+use kanata_parser::cfg::Cfg;
+use serde_json::json;
+
+use std::path::PathBuf;
+
+/// A device as shown by `list-devices`.
+struct ListedDevice {
+    name: String,
+    /// The path on Linux, the hash on macOS and the hardware ID on Windows.
+    id: String,
+    vendor_id: u32,
+    product_id: u32,
+    capabilities: Vec<&'static str>,
+    /// `None` if there is no configuration or the filters can not be checked on this platform.
+    grabbed: Option<bool>,
+    /// The logind seat of the device on Linux.
+    seat: Option<String>,
+}
+
+/// Prints the devices as a table, or as a JSON array if `json` is set. The device filters are
+/// read from the first configuration file, if there is one.
+pub(crate) fn list_devices(cfg_paths: &[PathBuf], json: bool) {
+    let cfg = match cfg_paths.first() {
+        Some(path) => match kanata_parser::cfg::new_from_file(path) {
+            Ok(cfg) => Some(cfg),
+            Err(e) => {
+                eprintln!(
+                    "Could not parse {}, not checking device filters: {e}",
+                    path.display()
+                );
+                None
+            }
+        },
+        None => None,
+    };
+    let devices = match devices(cfg.as_ref()) {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("Could not list devices: {e}");
+            std::process::exit(1);
+        }
+    };
+    if json {
+        let devices: Vec<_> = devices
+            .iter()
+            .map(|d| {
+                json!({
+                    "name": d.name,
+                    "id": d.id,
+                    "vendor_id": d.vendor_id,
+                    "product_id": d.product_id,
+                    "capabilities": d.capabilities,
+                    "grabbed": d.grabbed,
+                    "seat": d.seat,
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&devices).expect("JSON values serialize")
+        );
+        return;
+    }
+    if devices.is_empty() {
+        println!("No devices found.");
+        return;
+    }
+    let id_width = devices.iter().map(|d| d.id.len()).max().unwrap_or(0).max(2);
+    let caps: Vec<String> = devices.iter().map(|d| d.capabilities.join(",")).collect();
+    let caps_width = caps.iter().map(String::len).max().unwrap_or(0).max(12);
+    println!(
+        "{:<5} {:<9} {:<id_width$} {:<caps_width$} NAME",
+        "GRAB", "VID:PID", "ID", "CAPABILITIES"
+    );
+    for (d, caps) in devices.iter().zip(caps) {
+        let grabbed = match d.grabbed {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "?",
+        };
+        let vid_pid = format!("{:04x}:{:04x}", d.vendor_id, d.product_id);
+        println!(
+            "{grabbed:<5} {vid_pid:<9} {:<id_width$} {caps:<caps_width$} {}",
+            d.id, d.name
+        );
+    }
+    if cfg.is_none() {
+        println!("\nPass a configuration with --cfg to check which devices would be grabbed.");
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn devices(cfg: Option<&Cfg>) -> Result<Vec<ListedDevice>, String> {
+    use kanata_state_machine::oskbd::{device_capabilities, device_seat, would_grab_device};
+
+    let mut devices: Vec<_> = evdev::enumerate()
+        .map(|(path, device)| {
+            let path = path.display().to_string();
+            let input_id = device.input_id();
+            ListedDevice {
+                name: device.name().unwrap_or("").to_owned(),
+                grabbed: cfg.map(|cfg| would_grab_device(&device, &path, &cfg.options.linux_opts)),
+                vendor_id: input_id.vendor().into(),
+                product_id: input_id.product().into(),
+                capabilities: device_capabilities(&device),
+                seat: Some(device_seat(&path)),
+                id: path,
+            }
+        })
+        .collect();
+    if devices.is_empty() {
+        return Err("no devices in /dev/input are readable, \
+             is this user in the input group?"
+            .into());
+    }
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(devices)
+}
+
+#[cfg(target_os = "macos")]
+fn devices(cfg: Option<&Cfg>) -> Result<Vec<ListedDevice>, String> {
+    use kanata_state_machine::oskbd::would_grab_device;
+
+    Ok(karabiner_driverkit::fetch_devices()
+        .into_iter()
+        .map(|device| ListedDevice {
+            grabbed: cfg.map(|cfg| {
+                let opts = &cfg.options.macos_opts;
+                would_grab_device(
+                    &device,
+                    opts.macos_dev_names_include.as_deref(),
+                    opts.macos_dev_names_exclude.as_deref(),
+                )
+            }),
+            name: device.product_key.trim().to_owned(),
+            id: format!("0x{:X}", device.hash),
+            vendor_id: device.vendor_id,
+            product_id: device.product_id,
+            capabilities: vec!["keyboard"],
+            seat: None,
+        })
+        .collect())
+}
+
+/// Interception identifies devices by its own hardware IDs, which Raw Input does not show, so
+/// whether a device would be grabbed is unknown.
+#[cfg(all(target_os = "windows", feature = "interception_driver"))]
+fn devices(_cfg: Option<&Cfg>) -> Result<Vec<ListedDevice>, String> {
+    let parse_id = |hwid: &str, prefix: &str| {
+        hwid.split(['#', '&'])
+            .find_map(|part| part.strip_prefix(prefix))
+            .and_then(|id| u32::from_str_radix(id, 16).ok())
+            .unwrap_or(0)
+    };
+    Ok(super::raw_input_keyboards()?
+        .into_iter()
+        .filter_map(super::get_device_info)
+        .map(|info| {
+            let id = info.hardware_id.unwrap_or_default();
+            ListedDevice {
+                vendor_id: parse_id(&id, "VID_"),
+                product_id: parse_id(&id, "PID_"),
+                name: info.display_name,
+                id,
+                capabilities: vec!["keyboard"],
+                grabbed: None,
+                seat: None,
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,184 @@
+//! Log file for `--log-file`, rotated by size and age so that long-running kanata processes don't
+//! fill the disk, e.g. when started from the Windows tray where there is no journald.
+
+use super::args::{Args, LogRotateEvery};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// When to start a new log file, and how many old ones to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rotation {
+    /// Rotate before the file gets bigger than this many bytes.
+    pub(crate) max_size: Option<u64>,
+    /// Rotate when the file is older than this.
+    pub(crate) max_age: Option<Duration>,
+    /// Old files are kept as `FILE.1` (the newest) up to `FILE.<keep>`.
+    pub(crate) keep: usize,
+}
+
+/// A log file that moves itself to `FILE.1` and starts over according to its [`Rotation`].
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: SystemTime,
+    /// Loggers may write a record in parts, so rotate only at the start of a line.
+    line_start: bool,
+    rotation: Rotation,
+}
+
+impl RotatingFile {
+    /// Opens the file for appending, creating it if needed.
+    pub(crate) fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        Ok(Self {
+            path: path.to_owned(),
+            size: metadata.len(),
+            opened: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+            line_start: true,
+            file,
+            rotation,
+        })
+    }
+
+    fn should_rotate(&self, len: usize) -> bool {
+        if !self.line_start {
+            return false;
+        }
+        let requested = kanata_state_machine::take_log_rotation_request();
+        let too_big = self
+            .rotation
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + len as u64 > max);
+        let too_old = self.rotation.max_age.is_some_and(|max| {
+            SystemTime::now()
+                .duration_since(self.opened)
+                .is_ok_and(|age| age >= max)
+        });
+        requested || too_big || too_old
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let keep = self.rotation.keep;
+        if keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated(keep));
+            for n in (1..keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        self.line_start = buf.ends_with(b"\n");
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Opens the file of `--log-file`, if given, with the rotation of the other log file options.
+pub(crate) fn from_args(args: &Args) -> Result<Option<RotatingFile>> {
+    let Some(path) = &args.log_file else {
+        return Ok(None);
+    };
+    let rotation = Rotation {
+        max_size: Some(args.log_max_size).filter(|size| *size > 0),
+        max_age: match args.log_rotate_every {
+            LogRotateEvery::Never => None,
+            LogRotateEvery::Hour => Some(Duration::from_secs(60 * 60)),
+            LogRotateEvery::Day => Some(Duration::from_secs(24 * 60 * 60)),
+        },
+        keep: args.log_keep,
+    };
+    RotatingFile::open(path, rotation)
+        .map(Some)
+        .with_context(|| format!("Could not open the log file {}", path.display()))
+}
+
+/// Parses a size like `512`, `100K`, `10M` or `1G`, in bytes with binary multiples.
+pub(crate) fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size {s}, expected e.g. 512, 100K, 10M or 1G"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("100K"), Ok(100 * 1024));
+        assert_eq!(parse_size("10m"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("10MB").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("kanata-log-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("kanata.log");
+        let rotation = Rotation {
+            max_size: Some(10),
+            max_age: None,
+            keep: 2,
+        };
+        let mut file = RotatingFile::open(&path, rotation).unwrap();
+        for line in ["first\n", "sec", "ond\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+        let read = |p: &Path| std::fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read(&dir.join("kanata.log.1")), "third\n");
+        assert_eq!(read(&dir.join("kanata.log.2")), "first\nsecond\n");
+        assert!(!dir.join("kanata.log.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
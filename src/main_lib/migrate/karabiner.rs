@@ -0,0 +1,682 @@
+//! Converts Karabiner-Elements configurations, i.e. `karabiner.json` or a file of complex
+//! modifications like the ones in `assets/complex_modifications`.
+//!
+//! The `from` keys of the modifications become defsrc. Manipulators with a `variable_if`
+//! condition go on a layer named after the variable, and `set_variable` becomes a layer action:
+//! `layer-while-held` if `to_after_key_up` sets the variable back, `layer-switch` otherwise.
+//! Complex modifications come before simple ones and the first manipulator of a key on a layer
+//! wins, like in Karabiner.
+
+use super::Note;
+use anyhow::{Result, anyhow, bail};
+use kanata_parser::keys::str_to_oscode;
+use serde_json::Value;
+
+/// The default `basic.to_if_alone_timeout_milliseconds` of Karabiner.
+const ALONE_TIMEOUT: u64 = 1000;
+
+/// The default `basic.to_if_held_down_threshold_milliseconds` of Karabiner.
+const HELD_THRESHOLD: u64 = 500;
+
+/// The tap timeout of the converted tap-hold actions, for which Karabiner has no equivalent.
+const TAP_TIMEOUT: u64 = 200;
+
+/// Keys per row of the converted layers.
+const ROW_LEN: usize = 12;
+
+const BASE: &str = "base";
+
+/// Karabiner key names that kanata spells differently. Names like `a`, `1`, `f1` or `home` are
+/// the same in both.
+const KEYS: &[(&str, &str)] = &[
+    ("return_or_enter", "ret"),
+    ("escape", "esc"),
+    ("delete_or_backspace", "bspc"),
+    ("delete_forward", "del"),
+    ("spacebar", "spc"),
+    ("hyphen", "min"),
+    ("equal_sign", "eql"),
+    ("open_bracket", "lbrc"),
+    ("close_bracket", "rbrc"),
+    ("backslash", "bksl"),
+    ("semicolon", "scln"),
+    ("quote", "apos"),
+    ("grave_accent_and_tilde", "grv"),
+    ("comma", "comm"),
+    ("period", "."),
+    ("slash", "/"),
+    ("caps_lock", "caps"),
+    ("print_screen", "prnt"),
+    ("scroll_lock", "slck"),
+    ("insert", "ins"),
+    ("page_up", "pgup"),
+    ("page_down", "pgdn"),
+    ("right_arrow", "rght"),
+    ("left_arrow", "left"),
+    ("down_arrow", "down"),
+    ("up_arrow", "up"),
+    ("keypad_num_lock", "nlck"),
+    ("keypad_slash", "kp/"),
+    ("keypad_asterisk", "kp*"),
+    ("keypad_hyphen", "kp-"),
+    ("keypad_plus", "kp+"),
+    ("keypad_enter", "kprt"),
+    ("keypad_period", "kp."),
+    ("keypad_equal_sign", "kp="),
+    ("keypad_0", "kp0"),
+    ("keypad_1", "kp1"),
+    ("keypad_2", "kp2"),
+    ("keypad_3", "kp3"),
+    ("keypad_4", "kp4"),
+    ("keypad_5", "kp5"),
+    ("keypad_6", "kp6"),
+    ("keypad_7", "kp7"),
+    ("keypad_8", "kp8"),
+    ("keypad_9", "kp9"),
+    ("non_us_backslash", "nubs"),
+    ("application", "menu"),
+    ("left_control", "lctl"),
+    ("left_shift", "lsft"),
+    ("left_option", "lalt"),
+    ("left_alt", "lalt"),
+    ("left_command", "lmet"),
+    ("left_gui", "lmet"),
+    ("right_control", "rctl"),
+    ("right_shift", "rsft"),
+    ("right_option", "ralt"),
+    ("right_alt", "ralt"),
+    ("right_command", "rmet"),
+    ("right_gui", "rmet"),
+    ("japanese_eisuu", "eisu"),
+    ("japanese_kana", "kana"),
+    ("lang1", "kana"),
+    ("lang2", "eisu"),
+    ("display_brightness_decrement", "brdn"),
+    ("display_brightness_increment", "brup"),
+    ("volume_decrement", "voldwn"),
+    ("volume_increment", "volu"),
+    ("mission_control", "mctl"),
+    ("launchpad", "lpad"),
+    ("play_or_pause", "pp"),
+    ("scan_next_track", "next"),
+    ("scan_previous_track", "prev"),
+    ("button1", "mlft"),
+    ("button2", "mrgt"),
+    ("button3", "mmid"),
+    ("button4", "mbck"),
+    ("button5", "mfwd"),
+];
+
+/// Modifiers of `to` events, with the kanata prefix.
+const MODS: &[(&str, &str)] = &[
+    ("left_control", "C-"),
+    ("control", "C-"),
+    ("left_shift", "S-"),
+    ("shift", "S-"),
+    ("left_option", "A-"),
+    ("left_alt", "A-"),
+    ("option", "A-"),
+    ("left_command", "M-"),
+    ("left_gui", "M-"),
+    ("command", "M-"),
+    ("right_control", "RC-"),
+    ("right_shift", "RS-"),
+    ("right_option", "RA-"),
+    ("right_alt", "RA-"),
+    ("right_command", "RM-"),
+    ("right_gui", "RM-"),
+];
+
+/// A layer and the actions of the defsrc keys mapped on it.
+struct Layer {
+    name: String,
+    keys: Vec<(String, String)>,
+}
+
+struct Converter {
+    notes: Vec<Note>,
+    /// Where the manipulator being converted is, for the notes.
+    at: String,
+    /// The defsrc keys, in the order they first appear.
+    src: Vec<String>,
+    layers: Vec<Layer>,
+    alone_timeout: u64,
+    held_threshold: u64,
+}
+
+/// Returns the kanata configuration and the notes about what did not convert exactly.
+pub(super) fn convert(text: &str) -> Result<(String, Vec<Note>)> {
+    let json: Value = serde_json::from_str(text)
+        .map_err(|e| anyhow!("Could not parse the Karabiner configuration: {e}"))?;
+    let mut c = Converter {
+        notes: vec![],
+        at: String::new(),
+        src: vec![],
+        layers: vec![Layer {
+            name: BASE.to_owned(),
+            keys: vec![],
+        }],
+        alone_timeout: ALONE_TIMEOUT,
+        held_threshold: HELD_THRESHOLD,
+    };
+
+    let (rules, simple) = if let Some(profiles) = json.get("profiles").and_then(Value::as_array) {
+        let Some(profile) = profiles
+            .iter()
+            .find(|p| p.get("selected").and_then(Value::as_bool) == Some(true))
+            .or_else(|| profiles.first())
+        else {
+            bail!("The Karabiner configuration has no profiles");
+        };
+        if profiles.len() > 1 {
+            let name = profile.get("name").and_then(Value::as_str).unwrap_or("");
+            c.note(format!(
+                "only the profile {name:?} is converted, the other profiles are ignored"
+            ));
+        }
+        let complex = profile.get("complex_modifications");
+        (c.alone_timeout, c.held_threshold) = c.timeouts(complex.and_then(|m| m.get("parameters")));
+        if array(profile.get("devices"))
+            .iter()
+            .any(|d| !array(d.get("simple_modifications")).is_empty())
+        {
+            c.note("the simple modifications of devices are not converted");
+        }
+        (
+            array(complex.and_then(|m| m.get("rules"))),
+            array(profile.get("simple_modifications")),
+        )
+    } else if let Some(rules) = json.get("rules").and_then(Value::as_array) {
+        (rules.as_slice(), &[][..])
+    } else {
+        bail!(
+            "The file has no profiles or rules, is it a karabiner.json or a file of complex modifications?"
+        );
+    };
+
+    for rule in rules {
+        let description = rule.get("description").and_then(Value::as_str);
+        for manipulator in array(rule.get("manipulators")) {
+            c.at = match description {
+                Some(description) => format!("rule {description:?}"),
+                None => "rule without description".to_owned(),
+            };
+            c.manipulator(manipulator);
+        }
+    }
+    for modification in simple {
+        let from = modification.get("from").map(key_name).unwrap_or_default();
+        c.at = format!("simple modification {from:?}");
+        let Some(src) = c.source_key(modification.get("from")) else {
+            continue;
+        };
+        let action = c
+            .events(modification.get("to"), None)
+            .unwrap_or_else(|| "XX".to_owned());
+        c.map(BASE, src, action);
+    }
+    if c.src.is_empty() {
+        bail!("The Karabiner configuration has no modifications that can be converted");
+    }
+
+    let mut out = format!("(defsrc\n{})\n\n", rows(&c.src));
+    for layer in &c.layers {
+        let keys: Vec<String> = c
+            .src
+            .iter()
+            .map(|src| match layer.keys.iter().find(|(key, _)| key == src) {
+                Some((_, action)) => action.clone(),
+                None if layer.name == BASE => src.clone(),
+                None => "_".to_owned(),
+            })
+            .collect();
+        out.push_str(&format!("(deflayer {}\n{})\n\n", layer.name, rows(&keys)));
+    }
+    Ok((out, c.notes))
+}
+
+fn array(value: Option<&Value>) -> &[Value] {
+    value.and_then(Value::as_array).map_or(&[], Vec::as_slice)
+}
+
+fn rows(keys: &[String]) -> String {
+    keys.chunks(ROW_LEN)
+        .map(|row| format!("  {}\n", row.join(" ")))
+        .collect()
+}
+
+/// The Karabiner name of the key of a `from` or `to` event.
+fn key_name(event: &Value) -> &str {
+    ["key_code", "consumer_key_code", "pointing_button"]
+        .iter()
+        .find_map(|field| event.get(field).and_then(Value::as_str))
+        .unwrap_or_default()
+}
+
+/// Converts a Karabiner key name, or returns `None` if it is not a key kanata knows.
+fn key(name: &str) -> Option<String> {
+    if let Some((_, key)) = KEYS.iter().find(|(k, _)| *k == name) {
+        return Some((*key).to_owned());
+    }
+    str_to_oscode(name).map(|_| name.to_owned())
+}
+
+/// The kanata layer for a variable, or the base layer for the value 0.
+fn layer_name(variable: &str, value: &Value) -> String {
+    let variable: String = variable
+        .chars()
+        .map(|ch| match ch.is_alphanumeric() || ch == '-' || ch == '_' {
+            true => ch,
+            false => '-',
+        })
+        .collect();
+    match value {
+        Value::Number(n) if n.as_i64() == Some(0) => BASE.to_owned(),
+        Value::Bool(false) => BASE.to_owned(),
+        Value::Number(n) if n.as_i64() == Some(1) => variable,
+        Value::Bool(true) => variable,
+        Value::String(s) => format!("{variable}-{s}"),
+        value => format!("{variable}-{value}"),
+    }
+}
+
+impl Converter {
+    fn note(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        self.notes.push(Note {
+            line: None,
+            message: match self.at.is_empty() {
+                true => message,
+                false => format!("{}: {message}", self.at),
+            },
+        });
+    }
+
+    /// Returns the `to_if_alone` timeout and the `to_if_held_down` threshold of the parameters,
+    /// or the current ones if they are not set.
+    fn timeouts(&self, parameters: Option<&Value>) -> (u64, u64) {
+        let get = |name| parameters.and_then(|p| p.get(name)).and_then(Value::as_u64);
+        (
+            get("basic.to_if_alone_timeout_milliseconds").unwrap_or(self.alone_timeout),
+            get("basic.to_if_held_down_threshold_milliseconds").unwrap_or(self.held_threshold),
+        )
+    }
+
+    /// Returns the defsrc key of a `from` event, adding it to defsrc.
+    fn source_key(&mut self, from: Option<&Value>) -> Option<String> {
+        let Some(from) = from else {
+            self.note("has no from key, skipped");
+            return None;
+        };
+        if from.get("simultaneous").is_some() {
+            self.note("simultaneous keys are not converted, use defchordsv2 instead; skipped");
+            return None;
+        }
+        let name = key_name(from);
+        let Some(src) = key(name) else {
+            self.note(format!("the from key {name:?} is not supported, skipped"));
+            return None;
+        };
+        let mandatory = from
+            .get("modifiers")
+            .map(|m| array(m.get("mandatory")))
+            .unwrap_or_default();
+        if !mandatory.is_empty() {
+            self.note(format!(
+                "mandatory modifiers of the from key {name:?} are not converted, skipped"
+            ));
+            return None;
+        }
+        if !self.src.contains(&src) {
+            self.src.push(src.clone());
+        }
+        Some(src)
+    }
+
+    /// Returns the layer, adding it if it is new.
+    fn layer(&mut self, name: &str) -> &mut Layer {
+        let i = match self.layers.iter().position(|l| l.name == name) {
+            Some(i) => i,
+            None => {
+                self.layers.push(Layer {
+                    name: name.to_owned(),
+                    keys: vec![],
+                });
+                self.layers.len() - 1
+            }
+        };
+        &mut self.layers[i]
+    }
+
+    fn map(&mut self, layer: &str, src: String, action: String) {
+        if self.layer(layer).keys.iter().any(|(key, _)| *key == src) {
+            self.note(format!(
+                "{src} is already mapped on layer {layer} by an earlier manipulator, skipped"
+            ));
+            return;
+        }
+        self.layer(layer).keys.push((src, action));
+    }
+
+    fn manipulator(&mut self, manipulator: &Value) {
+        let kind = manipulator.get("type").and_then(Value::as_str);
+        if kind != Some("basic") {
+            self.note(format!(
+                "manipulators of type {} are not converted, skipped",
+                kind.unwrap_or("none")
+            ));
+            return;
+        }
+        let mut layer = BASE.to_owned();
+        let mut variables = 0;
+        for condition in array(manipulator.get("conditions")) {
+            let kind = condition.get("type").and_then(Value::as_str).unwrap_or("");
+            let name = condition.get("name").and_then(Value::as_str);
+            match (kind, name, condition.get("value")) {
+                ("variable_if", Some(name), Some(value)) => {
+                    variables += 1;
+                    layer = layer_name(name, value);
+                }
+                _ => {
+                    self.note(format!(
+                        "conditions of type {kind} are not converted, skipped"
+                    ));
+                    return;
+                }
+            }
+        }
+        if variables > 1 {
+            self.note("kanata has one active layer, a condition on several variables is skipped");
+            return;
+        }
+        let Some(src) = self.source_key(manipulator.get("from")) else {
+            return;
+        };
+        let (alone_timeout, held_threshold) = self.timeouts(manipulator.get("parameters"));
+
+        for field in [
+            "to_delayed_action",
+            "to_if_canceled",
+            "to_if_other_key_pressed",
+        ] {
+            if manipulator.get(field).is_some() {
+                self.note(format!("{field} is not converted"));
+            }
+        }
+        let after_key_up = manipulator.get("to_after_key_up");
+        let to = self.events(manipulator.get("to"), after_key_up);
+        let alone = self.events(manipulator.get("to_if_alone"), None);
+        let held = self.events(manipulator.get("to_if_held_down"), None);
+        let action = match (to, alone, held) {
+            (to, None, None) => to.unwrap_or_else(|| "XX".to_owned()),
+            (Some(to), Some(alone), None) => {
+                format!("(tap-hold-press {TAP_TIMEOUT} {alone_timeout} {alone} {to})")
+            }
+            (to, alone, Some(held)) => {
+                if to.is_some() && alone.is_some() {
+                    self.note("to is dropped, only to_if_alone and to_if_held_down are converted");
+                }
+                let tap = alone.or(to).unwrap_or_else(|| "XX".to_owned());
+                format!("(tap-hold {TAP_TIMEOUT} {held_threshold} {tap} {held})")
+            }
+            (None, Some(alone), None) => alone,
+        };
+        self.map(&layer, src, action);
+    }
+
+    /// Converts a list of `to` events to one action. `after_key_up` are the events sent on
+    /// release, which make a `set_variable` a `layer-while-held` if they set the variable back.
+    fn events(&mut self, events: Option<&Value>, after_key_up: Option<&Value>) -> Option<String> {
+        let events = match events {
+            Some(Value::Array(events)) => events.as_slice(),
+            Some(event) => std::slice::from_ref(event),
+            None => return None,
+        };
+        let resets: Vec<&str> = array(after_key_up)
+            .iter()
+            .filter_map(|e| e.get("set_variable")?.get("name")?.as_str())
+            .collect();
+        let mut actions = vec![];
+        // Layer actions and modifiers can be held together with the last key.
+        let mut hold_together = true;
+        for (i, event) in events.iter().enumerate() {
+            let action = if let Some(variable) = event.get("set_variable") {
+                let name = variable.get("name").and_then(Value::as_str).unwrap_or("");
+                let layer = layer_name(name, variable.get("value").unwrap_or(&Value::Null));
+                self.layer(&layer);
+                match resets.contains(&name) && layer != BASE {
+                    true => format!("(layer-while-held {layer})"),
+                    false => format!("(layer-switch {layer})"),
+                }
+            } else {
+                let action = self.event(event)?;
+                if i + 1 < events.len() {
+                    hold_together &= matches!(
+                        action.as_str(),
+                        "lctl" | "lsft" | "lalt" | "lmet" | "rctl" | "rsft" | "ralt" | "rmet"
+                    );
+                }
+                action
+            };
+            actions.push(action);
+        }
+        for event in array(after_key_up) {
+            if event.get("set_variable").is_none() {
+                self.note("to_after_key_up is only converted for set_variable");
+                break;
+            }
+        }
+        match actions.len() {
+            0 => Some("XX".to_owned()),
+            1 => actions.pop(),
+            _ if hold_together => Some(format!("(multi {})", actions.join(" "))),
+            _ => {
+                if actions.iter().any(|a| a.starts_with("(layer")) {
+                    self.note("layer actions in a sequence of keys are not converted");
+                    actions.retain(|a| !a.starts_with("(layer"));
+                }
+                self.note("a sequence of keys is converted to a macro, the last key is not held");
+                Some(format!("(macro {})", actions.join(" ")))
+            }
+        }
+    }
+
+    /// Converts a `to` event that sends a key, or returns `None` after a note if it can't.
+    fn event(&mut self, event: &Value) -> Option<String> {
+        let name = key_name(event);
+        if name.is_empty() {
+            let kind = event
+                .as_object()
+                .and_then(|o| o.keys().find(|k| !matches!(k.as_str(), "repeat" | "lazy")))
+                .map_or("empty", String::as_str);
+            self.note(format!("to events of type {kind} are not converted"));
+            return None;
+        }
+        if name == "vk_none" {
+            return Some("XX".to_owned());
+        }
+        let Some(key) = key(name) else {
+            self.note(format!("the key {name:?} is not supported"));
+            return None;
+        };
+        let mut prefix = String::new();
+        let modifiers = match event.get("modifiers") {
+            Some(Value::String(m)) => vec![m.as_str()],
+            Some(Value::Array(m)) => m.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        for modifier in modifiers {
+            match MODS.iter().find(|(m, _)| *m == modifier) {
+                Some((_, p)) if !prefix.contains(p) => prefix.push_str(p),
+                Some(_) => {}
+                None => self.note(format!("the modifier {modifier:?} is not supported")),
+            }
+        }
+        Some(format!("{prefix}{key}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_keys_exist() {
+        for (name, key) in KEYS {
+            assert!(
+                matches!(*key, "eisu" | "mlft" | "mrgt" | "mmid" | "mbck" | "mfwd")
+                    || str_to_oscode(key).is_some(),
+                "{name} converts to unknown key {key}"
+            );
+        }
+    }
+
+    #[test]
+    fn converts_karabiner_config() {
+        let (out, notes) = convert(
+            r#"{
+  "profiles": [{
+    "name": "Default",
+    "selected": true,
+    "simple_modifications": [
+      { "from": { "key_code": "right_command" }, "to": [{ "key_code": "right_option" }] },
+      { "from": { "key_code": "caps_lock" }, "to": [{ "key_code": "escape" }] }
+    ],
+    "complex_modifications": {
+      "parameters": { "basic.to_if_alone_timeout_milliseconds": 300 },
+      "rules": [
+        {
+          "description": "Caps Lock to Control, Escape if alone",
+          "manipulators": [{
+            "type": "basic",
+            "from": { "key_code": "caps_lock", "modifiers": { "optional": ["any"] } },
+            "to": [{ "key_code": "left_control" }],
+            "to_if_alone": [{ "key_code": "escape" }]
+          }]
+        },
+        {
+          "description": "Space navigation layer",
+          "manipulators": [
+            {
+              "type": "basic",
+              "from": { "key_code": "spacebar" },
+              "to": [{ "set_variable": { "name": "nav mode", "value": 1 } }],
+              "to_after_key_up": [{ "set_variable": { "name": "nav mode", "value": 0 } }],
+              "to_if_alone": [{ "key_code": "spacebar" }]
+            },
+            {
+              "type": "basic",
+              "conditions": [{ "type": "variable_if", "name": "nav mode", "value": 1 }],
+              "from": { "key_code": "h" },
+              "to": [{ "key_code": "left_arrow", "modifiers": ["left_shift"] }]
+            },
+            {
+              "type": "basic",
+              "conditions": [{ "type": "frontmost_application_if", "bundle_identifiers": ["^com\\.apple\\.Terminal$"] }],
+              "from": { "key_code": "j" },
+              "to": [{ "key_code": "down_arrow" }]
+            },
+            {
+              "type": "basic",
+              "from": { "key_code": "q", "modifiers": { "mandatory": ["command"] } },
+              "to": [{ "key_code": "vk_none" }]
+            },
+            {
+              "type": "basic",
+              "from": { "key_code": "f1" },
+              "to": [{ "shell_command": "open -a Terminal" }]
+            }
+          ]
+        }
+      ]
+    }
+  }]
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "\
+(defsrc
+  caps spc h f1 rmet
+)
+
+(deflayer base
+  (tap-hold-press 200 300 esc lctl) (tap-hold-press 200 300 spc (layer-while-held nav-mode)) h XX ralt
+)
+
+(deflayer nav-mode
+  _ _ S-left _ _
+)
+
+"
+        );
+        let notes: Vec<_> = notes.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            notes,
+            [
+                "rule \"Space navigation layer\": conditions of type frontmost_application_if \
+                 are not converted, skipped",
+                "rule \"Space navigation layer\": mandatory modifiers of the from key \"q\" \
+                 are not converted, skipped",
+                "rule \"Space navigation layer\": to events of type shell_command are not converted",
+                "simple modification \"caps_lock\": caps is already mapped on layer base \
+                 by an earlier manipulator, skipped",
+            ]
+        );
+    }
+
+    #[test]
+    fn converts_complex_modifications_file() {
+        let (out, notes) = convert(
+            r#"{
+  "title": "Typing",
+  "rules": [{
+    "manipulators": [
+      {
+        "type": "basic",
+        "from": { "key_code": "right_shift" },
+        "to": [{ "key_code": "left_shift" }, { "key_code": "left_command" }, { "key_code": "a" }]
+      },
+      {
+        "type": "basic",
+        "from": { "key_code": "right_option" },
+        "to": [{ "key_code": "h" }, { "key_code": "i" }]
+      },
+      {
+        "type": "basic",
+        "from": { "key_code": "tab" },
+        "to": [{ "set_variable": { "name": "mode", "value": "symbols" } }]
+      }
+    ]
+  }]
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "\
+(defsrc
+  rsft ralt tab
+)
+
+(deflayer base
+  (multi lsft lmet a) (macro h i) (layer-switch mode-symbols)
+)
+
+(deflayer mode-symbols
+  _ _ _
+)
+
+"
+        );
+        let notes: Vec<_> = notes.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            notes,
+            [
+                "rule without description: a sequence of keys is converted to a macro, \
+              the last key is not held"
+            ]
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! Converts kmonad configurations. The syntax is close to kanata's: `defsrc`, `deflayer` and
+//! `defalias` keep their shape, and most buttons have a kanata action that behaves the same.
+
+use super::{NO_TIMEOUT, Note};
+use anyhow::{Result, anyhow};
+use kanata_parser::cfg::sexpr::{self, SExpr};
+use kanata_parser::keys::str_to_oscode;
+
+/// Shifted characters that kmonad accepts as keys.
+const SHIFTED: &[(&str, &str)] = &[
+    ("~", "S-grv"),
+    ("!", "S-1"),
+    ("$", "S-4"),
+    ("%", "S-5"),
+    ("^", "S-6"),
+    ("&", "S-7"),
+    ("*", "S-8"),
+    ("+", "S-eql"),
+    ("{", "S-lbrc"),
+    ("}", "S-rbrc"),
+    ("|", "S-bksl"),
+    (":", "S-scln"),
+    ("<", "S-comm"),
+    (">", "S-."),
+    ("?", "S-/"),
+];
+
+/// kmonad key names that kanata spells differently.
+const RENAMED: &[(&str, &str)] = &[
+    ("dot", "."),
+    ("slsh", "/"),
+    ("previoussong", "prev"),
+    ("nextsong", "next"),
+    ("playpause", "pp"),
+];
+
+struct Converter {
+    notes: Vec<Note>,
+}
+
+/// Returns the kanata configuration and the notes about what did not convert exactly.
+pub(super) fn convert(text: &str, file_name: &str) -> Result<(String, Vec<Note>)> {
+    let items = sexpr::parse(text, file_name)
+        .map_err(|e| anyhow!("Could not parse {file_name}: {}", e.msg))?;
+    let mut c = Converter { notes: vec![] };
+    let mut out = String::new();
+    for item in &items {
+        let line = item.span.start.line + 1;
+        let exprs = &item.t;
+        match exprs.first().and_then(|e| e.atom(None)) {
+            Some("defcfg") => out.push_str(&c.defcfg(&exprs[1..], line)),
+            Some("defsrc") => out.push_str(&format!("(defsrc\n{})\n\n", c.rows(&exprs[1..]))),
+            Some("deflayer") => {
+                let Some(name) = exprs.get(1).and_then(|e| e.atom(None)) else {
+                    c.note(line, "deflayer without a name, skipped");
+                    continue;
+                };
+                out.push_str(&format!("(deflayer {name}\n{})\n\n", c.rows(&exprs[2..])));
+            }
+            Some("defalias") => out.push_str(&c.defalias(&exprs[1..])),
+            Some(other) => c.note(line, format!("`{other}` is not supported, skipped")),
+            None => c.note(line, "unexpected list, skipped"),
+        }
+    }
+    Ok((out, c.notes))
+}
+
+fn line_of(expr: &SExpr) -> usize {
+    expr.span().start.line + 1
+}
+
+impl Converter {
+    fn note(&mut self, line: usize, message: impl Into<String>) {
+        self.notes.push(Note {
+            line: Some(line),
+            message: message.into(),
+        });
+    }
+
+    fn defcfg(&mut self, exprs: &[SExpr], line: usize) -> String {
+        let mut options = vec![];
+        for pair in exprs.chunks(2) {
+            let [key, value] = pair else {
+                self.note(line, "defcfg has an option without a value");
+                break;
+            };
+            let line = line_of(key);
+            let Some(key) = key.atom(None) else {
+                self.note(line, "defcfg option is a list, skipped");
+                continue;
+            };
+            match key {
+                // Keys that are not in defsrc are passed through by kanata, and it creates its own
+                // output device.
+                "fallthrough" | "output" => {}
+                "allow-cmd" => {
+                    if value.atom(None) == Some("true") {
+                        options.push("danger-enable-cmd yes".to_owned());
+                    }
+                }
+                "input" => options.extend(self.input(value, line)),
+                _ => self.note(
+                    line,
+                    format!("defcfg option `{key}` is not supported, skipped"),
+                ),
+            }
+        }
+        if options.is_empty() {
+            return String::new();
+        }
+        let options: String = options.iter().map(|o| format!("  {o}\n")).collect();
+        format!("(defcfg\n{options})\n\n")
+    }
+
+    /// Converts the input device of defcfg to the kanata option that selects it, if any.
+    fn input(&mut self, value: &SExpr, line: usize) -> Option<String> {
+        let list = value.list(None).unwrap_or_default();
+        let arg = list
+            .get(1)
+            .and_then(|e| e.atom(None))
+            .map(|a| a.trim_matches('"'));
+        match (list.first().and_then(|e| e.atom(None)), arg) {
+            (Some("device-file"), Some(path)) => Some(format!("linux-dev {}", quote(path))),
+            (Some("iokit-name"), Some(name)) => {
+                Some(format!("macos-dev-names-include ({})", quote(name)))
+            }
+            (Some("iokit-name" | "low-level-hook"), None) => None,
+            _ => {
+                self.note(line, "input device is not supported, skipped");
+                None
+            }
+        }
+    }
+
+    fn defalias(&mut self, exprs: &[SExpr]) -> String {
+        let mut aliases = String::new();
+        let mut i = 0;
+        while i < exprs.len() {
+            let Some(name) = exprs[i].atom(None) else {
+                self.note(line_of(&exprs[i]), "alias name is a list, skipped");
+                i += 1;
+                continue;
+            };
+            if i + 1 == exprs.len() {
+                self.note(line_of(&exprs[i]), format!("alias {name} has no button"));
+                break;
+            }
+            let (button, used) = self.button(&exprs[i + 1..]);
+            aliases.push_str(&format!("  {name} {button}\n"));
+            i += 1 + used;
+        }
+        format!("(defalias\n{aliases})\n\n")
+    }
+
+    /// Converts the buttons of defsrc or deflayer, keeping the rows of the source.
+    fn rows(&mut self, exprs: &[SExpr]) -> String {
+        let mut rows: Vec<(usize, Vec<String>)> = vec![];
+        let mut i = 0;
+        while i < exprs.len() {
+            let line = line_of(&exprs[i]);
+            let (button, used) = self.button(&exprs[i..]);
+            match rows.last_mut() {
+                Some((last, row)) if *last == line => row.push(button),
+                _ => rows.push((line, vec![button])),
+            }
+            i += used;
+        }
+        rows.iter()
+            .map(|(_, row)| format!("  {}\n", row.join(" ")))
+            .collect()
+    }
+
+    /// Converts the button at the start of `exprs`. Returns it with the number of expressions it
+    /// used, which is 2 for the `#(...)` shorthand of `tap-macro`.
+    fn button(&mut self, exprs: &[SExpr]) -> (String, usize) {
+        let line = line_of(&exprs[0]);
+        match (&exprs[0], exprs.get(1)) {
+            (SExpr::Atom(a), Some(SExpr::List(list))) if a.t == "#" => {
+                (self.tap_macro(&list.t, line), 2)
+            }
+            (SExpr::Atom(a), _) => (self.key(&a.t, line), 1),
+            (SExpr::List(list), _) => (self.list(&list.t, line), 1),
+        }
+    }
+
+    fn key(&mut self, key: &str, line: usize) -> String {
+        if matches!(key, "_" | "XX")
+            || key.starts_with('@')
+            || key.starts_with('"')
+            || key.parse::<u32>().is_ok()
+        {
+            return key.to_owned();
+        }
+        if let Some((_, shifted)) = SHIFTED.iter().find(|(k, _)| *k == key) {
+            return (*shifted).to_owned();
+        }
+        let mut base = key;
+        while let Some(rest) = ["C-", "S-", "A-", "M-"]
+            .iter()
+            .find_map(|prefix| base.strip_prefix(prefix))
+            .filter(|rest| !rest.is_empty())
+        {
+            base = rest;
+        }
+        let prefix = &key[..key.len() - base.len()];
+        let base = RENAMED
+            .iter()
+            .find(|(k, _)| *k == base)
+            .map_or(base, |(_, renamed)| renamed);
+        if str_to_oscode(base).is_none() {
+            self.note(line, format!("unknown key `{key}`, kept as is"));
+        }
+        format!("{prefix}{base}")
+    }
+
+    /// Converts the arguments of a button, dropping the `:keyword value` options.
+    fn args(&mut self, exprs: &[SExpr], line: usize) -> Vec<String> {
+        let mut args = vec![];
+        let mut i = 0;
+        while i < exprs.len() {
+            if let Some(keyword) = exprs[i].atom(None).filter(|a| a.starts_with(':'))
+                && keyword.len() > 1
+            {
+                self.note(
+                    line,
+                    format!("option `{keyword}` is not supported, dropped"),
+                );
+                i += 2;
+                continue;
+            }
+            let (arg, used) = self.button(&exprs[i..]);
+            args.push(arg);
+            i += used;
+        }
+        args
+    }
+
+    fn list(&mut self, exprs: &[SExpr], line: usize) -> String {
+        let Some(name) = exprs.first().and_then(|e| e.atom(None)) else {
+            self.note(line, "button is an empty list, converted to XX");
+            return "XX".to_owned();
+        };
+        let rest = &exprs[1..];
+        let layer = rest.first().and_then(|e| e.atom(None));
+        match (name, layer) {
+            ("layer-toggle", Some(layer)) => return format!("(layer-while-held {layer})"),
+            ("layer-switch", Some(layer)) => return format!("(layer-switch {layer})"),
+            ("layer-next", Some(layer)) => {
+                self.note(line, "layer-next is converted to a one-shot layer");
+                return format!("(one-shot-press {NO_TIMEOUT} (layer-while-held {layer}))");
+            }
+            ("cmd-button", Some(cmd)) => {
+                if rest.len() > 1 {
+                    self.note(line, "cmd-button release command is not supported, dropped");
+                }
+                return format!("(cmd sh -c {cmd})");
+            }
+            ("tap-macro" | "tap-macro-release", _) => {
+                if name == "tap-macro-release" {
+                    self.note(line, "tap-macro-release is converted to macro");
+                }
+                return self.tap_macro(rest, line);
+            }
+            (
+                "tap-hold"
+                | "tap-hold-next"
+                | "tap-hold-next-release"
+                | "tap-next"
+                | "tap-next-release"
+                | "around"
+                | "sticky-key"
+                | "multi-tap",
+                _,
+            ) => {}
+            _ => {
+                self.note(line, format!("`{name}` is not supported, converted to XX"));
+                return "XX".to_owned();
+            }
+        }
+        let args = self.args(rest, line);
+        match (name, &args[..]) {
+            ("tap-hold", [ms, tap, hold]) => format!("(tap-hold {ms} {ms} {tap} {hold})"),
+            ("tap-hold-next", [ms, tap, hold]) => {
+                format!("(tap-hold-press {ms} {ms} {tap} {hold})")
+            }
+            ("tap-hold-next-release", [ms, tap, hold]) => {
+                format!("(tap-hold-release {ms} {ms} {tap} {hold})")
+            }
+            ("tap-next" | "tap-next-release", [tap, hold]) => {
+                self.note(
+                    line,
+                    format!("{name} has no timeout, converted with the longest one"),
+                );
+                let kanata = match name {
+                    "tap-next" => "tap-hold-press",
+                    _ => "tap-hold-release",
+                };
+                format!("({kanata} {NO_TIMEOUT} {NO_TIMEOUT} {tap} {hold})")
+            }
+            ("around", [outer, inner]) => format!("(multi {outer} {inner})"),
+            ("sticky-key", [ms, button]) => format!("(one-shot-press {ms} {button})"),
+            ("multi-tap", args) if args.len() % 2 == 1 => self.multi_tap(args, line),
+            _ => {
+                self.note(
+                    line,
+                    format!("`{name}` has unexpected arguments, converted to XX"),
+                );
+                "XX".to_owned()
+            }
+        }
+    }
+
+    /// Converts `multi-tap`, which has a timeout per tap, to `tap-dance`, which has one timeout.
+    fn multi_tap(&mut self, args: &[String], line: usize) -> String {
+        let last = args.len() - 1;
+        let timeouts: Vec<_> = args[..last].iter().step_by(2).collect();
+        let buttons: Vec<_> = args[1..]
+            .iter()
+            .step_by(2)
+            .chain(std::iter::once(&args[last]))
+            .map(String::as_str)
+            .collect();
+        let Some(timeout) = timeouts.first() else {
+            return args[last].clone();
+        };
+        if timeouts.iter().any(|t| t != timeout) {
+            self.note(
+                line,
+                format!(
+                    "multi-tap has different timeouts, converted with the first one: {timeout}"
+                ),
+            );
+        }
+        format!("(tap-dance {timeout} ({}))", buttons.join(" "))
+    }
+
+    fn tap_macro(&mut self, exprs: &[SExpr], line: usize) -> String {
+        let mut items = vec![];
+        let mut i = 0;
+        while i < exprs.len() {
+            let pause = match &exprs[i] {
+                SExpr::Atom(a) => a.t.strip_prefix('P').filter(|ms| ms.parse::<u32>().is_ok()),
+                SExpr::List(l) => match &l.t[..] {
+                    [SExpr::Atom(p), SExpr::Atom(ms)] if p.t == "pause" => Some(ms.t.as_str()),
+                    _ => None,
+                },
+            };
+            if let Some(ms) = pause {
+                items.push(ms.to_owned());
+                i += 1;
+                continue;
+            }
+            let (item, used) = self.button(&exprs[i..]);
+            items.push(item);
+            i += used;
+        }
+        if items.is_empty() {
+            self.note(line, "empty tap-macro, converted to XX");
+            return "XX".to_owned();
+        }
+        format!("(macro {})", items.join(" "))
+    }
+}
+
+/// Quotes the string if kanata would not read it as one atom otherwise.
+fn quote(s: &str) -> String {
+    if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == '(' || c == ')') {
+        format!("\"{s}\"")
+    } else {
+        s.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_kmonad_config() {
+        let (out, notes) = convert(
+            r#"
+(defcfg
+  input (device-file "/dev/input/by-id/kbd")
+  output (uinput-sink "kmonad")
+  fallthrough true
+  allow-cmd true
+  cmp-seq ralt)
+
+(defsrc
+  esc  a    s
+  caps lsft)
+
+(defalias
+  sa (tap-hold-next 200 a lsft :timeout-button b)
+  nv (layer-toggle nav)
+  dn (multi-tap 200 d 300 e f)
+  hi #(h P50 i)
+  rn (layer-add nav))
+
+(deflayer base
+  (around lctl c) @sa !
+  @nv  S-previoussong)
+
+(deflayer nav
+  _ (tap-next x spc) foo
+  @dn XX)
+"#,
+            "kmonad.kbd",
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "\
+(defcfg
+  linux-dev /dev/input/by-id/kbd
+  danger-enable-cmd yes
+)
+
+(defsrc
+  esc a s
+  caps lsft
+)
+
+(defalias
+  sa (tap-hold-press 200 200 a lsft)
+  nv (layer-while-held nav)
+  dn (tap-dance 200 (d e f))
+  hi (macro h 50 i)
+  rn XX
+)
+
+(deflayer base
+  (multi lctl c) @sa S-1
+  @nv S-prev
+)
+
+(deflayer nav
+  _ (tap-hold-press 65535 65535 x spc) foo
+  @dn XX
+)
+
+"
+        );
+        let notes: Vec<_> = notes.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            notes,
+            [
+                "line 7: defcfg option `cmp-seq` is not supported, skipped",
+                "line 14: option `:timeout-button` is not supported, dropped",
+                "line 16: multi-tap has different timeouts, converted with the first one: 200",
+                "line 18: `layer-add` is not supported, converted to XX",
+                "line 25: tap-next has no timeout, converted with the longest one",
+                "line 25: unknown key `foo`, kept as is",
+            ]
+        );
+    }
+}
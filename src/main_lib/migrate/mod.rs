@@ -0,0 +1,79 @@
+//! `kanata migrate`: converts configurations of other remappers into kanata configurations.
+//! Constructs that don't translate exactly are listed as notes, both on stderr and as comments at
+//! the top of the converted configuration.
+
+mod karabiner;
+mod kmonad;
+pub(super) mod qmk;
+mod zmk;
+
+use super::args::MigrateFrom;
+use anyhow::{Context, Result};
+
+use std::path::Path;
+
+/// The longest timeout kanata accepts, for actions that have no timeout in the source, e.g.
+/// `tap-next` in kmonad.
+const NO_TIMEOUT: &str = "65535";
+
+/// Something in the source that was not converted, or was converted differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Note {
+    /// 1-based line in the source, if known.
+    line: Option<usize>,
+    message: String,
+}
+
+impl std::fmt::Display for Note {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Converts the source to a kanata configuration, and writes it to `output` or stdout.
+pub(crate) fn migrate(from: &MigrateFrom) -> Result<()> {
+    let (format, path, output) = match from {
+        MigrateFrom::Kmonad { path, output } => ("kmonad", path, output),
+        MigrateFrom::Qmk { path, output } => ("qmk", path, output),
+        MigrateFrom::Karabiner { path, output } => ("karabiner", path, output),
+        MigrateFrom::Zmk { path, output } => ("zmk", path, output),
+    };
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    let (converted, mut notes) = match from {
+        MigrateFrom::Kmonad { .. } => kmonad::convert(&text, &path.display().to_string())?,
+        MigrateFrom::Qmk { .. } => qmk::convert(&text)?,
+        MigrateFrom::Karabiner { .. } => karabiner::convert(&text)?,
+        MigrateFrom::Zmk { .. } => zmk::convert(&text)?,
+    };
+    let at = output.as_deref().unwrap_or(Path::new("migrated.kbd"));
+    if let Err(e) = kanata_parser::cfg::new_from_str_at_path(&converted, at) {
+        notes.push(Note {
+            line: None,
+            message: format!("the converted configuration is not valid: {e}"),
+        });
+    }
+
+    let mut out = format!(
+        ";; Converted from {} by kanata migrate {format}.\n",
+        path.display()
+    );
+    for note in &notes {
+        eprintln!("{}: {note}", path.display());
+        out.push_str(&format!(";; TODO {note}\n"));
+    }
+    out.push('\n');
+    out.push_str(&converted);
+    match output {
+        Some(output) => std::fs::write(output, out)
+            .with_context(|| format!("Could not write {}", output.display()))?,
+        None => print!("{out}"),
+    }
+    if !notes.is_empty() {
+        eprintln!("{} constructs need a manual check.", notes.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,486 @@
+//! Converts keymaps exported as JSON from QMK Configurator or VIA. Both have a `layers` array with
+//! a keycode string per key position, e.g. `"KC_A"` or `"LT(1,KC_SPC)"`.
+//!
+//! The keyboard kanata runs on sends the keys of layer 0, so those become defsrc. Positions that
+//! send no key on layer 0, e.g. `MO(1)`, can't be pressed there and are dropped from all layers.
+
+use super::{NO_TIMEOUT, Note};
+use anyhow::{Result, anyhow, bail};
+use kanata_parser::keys::str_to_oscode;
+
+/// The default `TAPPING_TERM` of QMK.
+const TAPPING_TERM: u32 = 200;
+
+/// Keys per row of the converted layers, since the export has no rows.
+const ROW_LEN: usize = 12;
+
+/// QMK keycodes that don't convert by removing `KC_` and lowercasing.
+const KEYS: &[(&str, &str)] = &[
+    ("KC_TRNS", "_"),
+    ("KC_TRANSPARENT", "_"),
+    ("_______", "_"),
+    ("KC_NO", "XX"),
+    ("XXXXXXX", "XX"),
+    ("KC_ENT", "ret"),
+    ("KC_ENTER", "ret"),
+    ("KC_ESCAPE", "esc"),
+    ("KC_BACKSPACE", "bspc"),
+    ("KC_SPACE", "spc"),
+    ("KC_MINS", "min"),
+    ("KC_MINUS", "min"),
+    ("KC_EQL", "eql"),
+    ("KC_EQUAL", "eql"),
+    ("KC_LEFT_BRACKET", "lbrc"),
+    ("KC_RIGHT_BRACKET", "rbrc"),
+    ("KC_BSLS", "bksl"),
+    ("KC_BACKSLASH", "bksl"),
+    ("KC_SEMICOLON", "scln"),
+    ("KC_QUOT", "apos"),
+    ("KC_QUOTE", "apos"),
+    ("KC_GRAVE", "grv"),
+    ("KC_COMMA", "comm"),
+    ("KC_DOT", "."),
+    ("KC_SLSH", "/"),
+    ("KC_SLASH", "/"),
+    ("KC_CAPS_LOCK", "caps"),
+    ("KC_PSCR", "prnt"),
+    ("KC_PRINT_SCREEN", "prnt"),
+    ("KC_SCRL", "slck"),
+    ("KC_SCROLL_LOCK", "slck"),
+    ("KC_PAUS", "pause"),
+    ("KC_INSERT", "ins"),
+    ("KC_PAGE_UP", "pgup"),
+    ("KC_DELETE", "del"),
+    ("KC_PAGE_DOWN", "pgdn"),
+    ("KC_RIGHT", "rght"),
+    ("KC_NUM", "nlck"),
+    ("KC_NUM_LOCK", "nlck"),
+    ("KC_PSLS", "kp/"),
+    ("KC_PAST", "kp*"),
+    ("KC_PMNS", "kp-"),
+    ("KC_PPLS", "kp+"),
+    ("KC_PENT", "kprt"),
+    ("KC_PDOT", "kp."),
+    ("KC_P0", "kp0"),
+    ("KC_P1", "kp1"),
+    ("KC_P2", "kp2"),
+    ("KC_P3", "kp3"),
+    ("KC_P4", "kp4"),
+    ("KC_P5", "kp5"),
+    ("KC_P6", "kp6"),
+    ("KC_P7", "kp7"),
+    ("KC_P8", "kp8"),
+    ("KC_P9", "kp9"),
+    ("KC_NUBS", "nubs"),
+    ("KC_APP", "menu"),
+    ("KC_LGUI", "lmet"),
+    ("KC_LCMD", "lmet"),
+    ("KC_LWIN", "lmet"),
+    ("KC_LOPT", "lalt"),
+    ("KC_RGUI", "rmet"),
+    ("KC_RCMD", "rmet"),
+    ("KC_RWIN", "rmet"),
+    ("KC_ROPT", "ralt"),
+    ("KC_ALGR", "ralt"),
+    ("KC_MNXT", "next"),
+    ("KC_MPRV", "prev"),
+    ("KC_MPLY", "pp"),
+    ("KC_BRIU", "brup"),
+    ("KC_BRID", "brdown"),
+    ("KC_BTN1", "mlft"),
+    ("KC_MS_BTN1", "mlft"),
+    ("KC_BTN2", "mrgt"),
+    ("KC_MS_BTN2", "mrgt"),
+    ("KC_BTN3", "mmid"),
+    ("KC_MS_BTN3", "mmid"),
+    ("KC_EXLM", "S-1"),
+    ("KC_AT", "S-2"),
+    ("KC_HASH", "S-3"),
+    ("KC_DLR", "S-4"),
+    ("KC_PERC", "S-5"),
+    ("KC_CIRC", "S-6"),
+    ("KC_AMPR", "S-7"),
+    ("KC_ASTR", "S-8"),
+    ("KC_LPRN", "S-9"),
+    ("KC_RPRN", "S-0"),
+    ("KC_UNDS", "S-min"),
+    ("KC_PLUS", "S-eql"),
+    ("KC_LCBR", "S-lbrc"),
+    ("KC_RCBR", "S-rbrc"),
+    ("KC_PIPE", "S-bksl"),
+    ("KC_COLN", "S-scln"),
+    ("KC_DQUO", "S-apos"),
+    ("KC_DQT", "S-apos"),
+    ("KC_TILD", "S-grv"),
+    ("KC_LT", "S-comm"),
+    ("KC_GT", "S-."),
+    ("KC_QUES", "S-/"),
+];
+
+/// Functions that hold modifiers while sending a key, e.g. `LCTL(KC_A)`, with the kanata prefix.
+const MOD_FNS: &[(&str, &str)] = &[
+    ("LCTL", "C-"),
+    ("C", "C-"),
+    ("LSFT", "S-"),
+    ("S", "S-"),
+    ("LALT", "A-"),
+    ("A", "A-"),
+    ("LOPT", "A-"),
+    ("LGUI", "M-"),
+    ("G", "M-"),
+    ("LCMD", "M-"),
+    ("LWIN", "M-"),
+    ("RCTL", "RC-"),
+    ("RSFT", "RS-"),
+    ("RALT", "RA-"),
+    ("ALGR", "RA-"),
+    ("ROPT", "RA-"),
+    ("RGUI", "RM-"),
+    ("RCMD", "RM-"),
+    ("RWIN", "RM-"),
+    ("C_S", "C-S-"),
+    ("LCA", "C-A-"),
+    ("LSA", "S-A-"),
+    ("MEH", "C-S-A-"),
+    ("HYPR", "C-S-A-M-"),
+];
+
+/// Mod-tap functions, e.g. `LCTL_T(KC_A)`, with the modifiers they hold.
+const MOD_TAPS: &[(&str, &[&str])] = &[
+    ("LCTL_T", &["lctl"]),
+    ("CTL_T", &["lctl"]),
+    ("LSFT_T", &["lsft"]),
+    ("SFT_T", &["lsft"]),
+    ("LALT_T", &["lalt"]),
+    ("ALT_T", &["lalt"]),
+    ("LOPT_T", &["lalt"]),
+    ("OPT_T", &["lalt"]),
+    ("LGUI_T", &["lmet"]),
+    ("GUI_T", &["lmet"]),
+    ("LCMD_T", &["lmet"]),
+    ("CMD_T", &["lmet"]),
+    ("LWIN_T", &["lmet"]),
+    ("WIN_T", &["lmet"]),
+    ("RCTL_T", &["rctl"]),
+    ("RSFT_T", &["rsft"]),
+    ("RALT_T", &["ralt"]),
+    ("ROPT_T", &["ralt"]),
+    ("ALGR_T", &["ralt"]),
+    ("RGUI_T", &["rmet"]),
+    ("RCMD_T", &["rmet"]),
+    ("RWIN_T", &["rmet"]),
+    ("C_S_T", &["lctl", "lsft"]),
+    ("LCA_T", &["lctl", "lalt"]),
+    ("LSA_T", &["lsft", "lalt"]),
+    ("MEH_T", &["lctl", "lsft", "lalt"]),
+    ("ALL_T", &["lctl", "lsft", "lalt", "lmet"]),
+    ("HYPR_T", &["lctl", "lsft", "lalt", "lmet"]),
+];
+
+/// `MOD_*` constants of `MT` and `OSM`, with the modifiers they stand for.
+const MODS: &[(&str, &[&str])] = &[
+    ("MOD_LCTL", &["lctl"]),
+    ("MOD_LSFT", &["lsft"]),
+    ("MOD_LALT", &["lalt"]),
+    ("MOD_LGUI", &["lmet"]),
+    ("MOD_RCTL", &["rctl"]),
+    ("MOD_RSFT", &["rsft"]),
+    ("MOD_RALT", &["ralt"]),
+    ("MOD_RGUI", &["rmet"]),
+    ("MOD_MEH", &["lctl", "lsft", "lalt"]),
+    ("MOD_HYPR", &["lctl", "lsft", "lalt", "lmet"]),
+];
+
+struct Converter {
+    notes: Vec<Note>,
+    /// Where the keycode being converted is, for the notes.
+    at: String,
+}
+
+/// Returns the kanata configuration and the notes about what did not convert exactly.
+pub(super) fn convert(text: &str) -> Result<(String, Vec<Note>)> {
+    let json: serde_json::Value =
+        serde_json::from_str(text).map_err(|e| anyhow!("Could not parse the keymap: {e}"))?;
+    let Some(layers) = json.get("layers").and_then(|l| l.as_array()) else {
+        bail!("The keymap has no layers array, is it a QMK Configurator or VIA export?");
+    };
+    let layers = layers
+        .iter()
+        .enumerate()
+        .map(|(i, layer)| {
+            layer
+                .as_array()
+                .and_then(|keys| keys.iter().map(|k| k.as_str()).collect::<Option<Vec<_>>>())
+                .ok_or_else(|| anyhow!("Layer {i} is not an array of keycode strings"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let Some(base) = layers.first() else {
+        bail!("The keymap has no layers");
+    };
+
+    let mut c = Converter {
+        notes: vec![],
+        at: String::new(),
+    };
+    let mut src: Vec<String> = vec![];
+    let mut kept = vec![];
+    for (position, keycode) in base.iter().enumerate() {
+        c.at = format!("key {position}");
+        match source_key(keycode) {
+            Some(key) if src.contains(&key) => c.note(format!(
+                "`{keycode}` sends a key that an earlier key on layer 0 sends too, \
+                 dropped from all layers"
+            )),
+            Some(key) => {
+                src.push(key);
+                kept.push(position);
+            }
+            None => c.note(format!(
+                "`{keycode}` on layer 0 sends no key, dropped from all layers"
+            )),
+        }
+    }
+
+    let mut out = format!("(defsrc\n{})\n\n", rows(&src));
+    for (i, layer) in layers.iter().enumerate() {
+        let keys: Vec<String> = kept
+            .iter()
+            .map(|&position| {
+                c.at = format!("layer {i}, key {position}");
+                match layer.get(position) {
+                    Some(keycode) => c.keycode(keycode),
+                    None => "_".to_owned(),
+                }
+            })
+            .collect();
+        out.push_str(&format!("(deflayer layer{i}\n{})\n\n", rows(&keys)));
+    }
+    Ok((out, c.notes))
+}
+
+fn rows(keys: &[String]) -> String {
+    keys.chunks(ROW_LEN)
+        .map(|row| format!("  {}\n", row.join(" ")))
+        .collect()
+}
+
+/// Splits a keycode like `LT(1, KC_A)` into the function name and its arguments.
+fn call(keycode: &str) -> Option<(&str, Vec<&str>)> {
+    let (name, rest) = keycode.split_once('(')?;
+    let inner = rest.strip_suffix(')')?;
+    let mut args = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, ch) in inner.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(inner[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(inner[start..].trim());
+    Some((name.trim(), args))
+}
+
+/// Converts a keycode without arguments, or returns `None` if it is not a key kanata knows.
+pub(crate) fn basic(keycode: &str) -> Option<String> {
+    if let Some((_, key)) = KEYS.iter().find(|(k, _)| *k == keycode) {
+        return Some((*key).to_owned());
+    }
+    let key = keycode.strip_prefix("KC_")?.to_lowercase();
+    str_to_oscode(&key).map(|_| key)
+}
+
+/// Returns the key that the keycode sends when tapped, without modifiers.
+fn source_key(keycode: &str) -> Option<String> {
+    let keycode = keycode.trim();
+    let key = match call(keycode) {
+        Some(("LT" | "MT", args)) => return args.get(1).and_then(|a| source_key(a)),
+        Some((name, args))
+            if name == "ANY"
+                || MOD_TAPS.iter().any(|(n, _)| *n == name)
+                || MOD_FNS.iter().any(|(n, _)| *n == name) =>
+        {
+            return args.first().and_then(|a| source_key(a));
+        }
+        Some(_) => return None,
+        None => basic(keycode)?,
+    };
+    let key = key.strip_prefix("S-").map_or(key.clone(), str::to_owned);
+    str_to_oscode(&key).map(|_| key)
+}
+
+impl Converter {
+    fn note(&mut self, message: impl Into<String>) {
+        self.notes.push(Note {
+            line: None,
+            message: format!("{}: {}", self.at, message.into()),
+        });
+    }
+
+    fn unsupported(&mut self, keycode: &str) -> String {
+        self.note(format!("`{keycode}` is not supported, converted to XX"));
+        "XX".to_owned()
+    }
+
+    fn keycode(&mut self, keycode: &str) -> String {
+        let keycode = keycode.trim();
+        match call(keycode) {
+            Some((name, args)) => self.function(name, &args, keycode),
+            None => basic(keycode).unwrap_or_else(|| self.unsupported(keycode)),
+        }
+    }
+
+    fn function(&mut self, name: &str, args: &[&str], keycode: &str) -> String {
+        if let Some((_, prefix)) = MOD_FNS.iter().find(|(n, _)| *n == name) {
+            let [key] = args else {
+                return self.unsupported(keycode);
+            };
+            let key = self.keycode(key);
+            if key.contains('(') || matches!(key.as_str(), "_" | "XX") {
+                return self.unsupported(keycode);
+            }
+            return format!("{prefix}{key}");
+        }
+        if let Some((_, mods)) = MOD_TAPS.iter().find(|(n, _)| *n == name) {
+            let [tap] = args else {
+                return self.unsupported(keycode);
+            };
+            let tap = self.keycode(tap);
+            return tap_hold(&tap, &held(mods));
+        }
+        match (name, args) {
+            ("ANY", [inner]) => return self.keycode(inner),
+            ("MT", [mods, tap]) => {
+                let Some(mods) = self.mods(mods, keycode) else {
+                    return "XX".to_owned();
+                };
+                let tap = self.keycode(tap);
+                return tap_hold(&tap, &held(&mods));
+            }
+            ("OSM", [mods]) => {
+                let Some(mods) = self.mods(mods, keycode) else {
+                    return "XX".to_owned();
+                };
+                return format!("(one-shot-press {NO_TIMEOUT} {})", held(&mods));
+            }
+            _ => {}
+        }
+        let Some(layer) = args.first().and_then(|n| n.parse::<usize>().ok()) else {
+            return self.unsupported(keycode);
+        };
+        match (name, args) {
+            ("MO", [_]) => format!("(layer-while-held layer{layer})"),
+            ("TO" | "DF" | "PDF", [_]) => format!("(layer-switch layer{layer})"),
+            ("TG", [_]) => {
+                self.note(format!(
+                    "`{keycode}` is converted to layer-switch, \
+                     add a key on layer{layer} to switch back"
+                ));
+                format!("(layer-switch layer{layer})")
+            }
+            ("TT", [_]) => {
+                self.note(format!("`{keycode}` is converted to layer-while-held"));
+                format!("(layer-while-held layer{layer})")
+            }
+            ("OSL", [_]) => {
+                format!("(one-shot-press {NO_TIMEOUT} (layer-while-held layer{layer}))")
+            }
+            ("LT", [_, tap]) => {
+                let tap = self.keycode(tap);
+                tap_hold(&tap, &format!("(layer-while-held layer{layer})"))
+            }
+            _ => self.unsupported(keycode),
+        }
+    }
+
+    /// Converts `MOD_*` constants joined by `|` to the modifier keys.
+    fn mods(&mut self, mods: &str, keycode: &str) -> Option<Vec<&'static str>> {
+        let mut keys = vec![];
+        for m in mods.split('|').map(str::trim) {
+            match MODS.iter().find(|(n, _)| *n == m) {
+                Some((_, held)) => keys.extend(held.iter()),
+                None => {
+                    self.unsupported(keycode);
+                    return None;
+                }
+            }
+        }
+        Some(keys)
+    }
+}
+
+fn held(mods: &[&str]) -> String {
+    match mods {
+        [key] => (*key).to_owned(),
+        keys => format!("(multi {})", keys.join(" ")),
+    }
+}
+
+fn tap_hold(tap: &str, hold: &str) -> String {
+    format!("(tap-hold {TAPPING_TERM} {TAPPING_TERM} {tap} {hold})")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_keys_exist() {
+        for (keycode, key) in KEYS {
+            let base = key.strip_prefix("S-").unwrap_or(key);
+            assert!(
+                matches!(base, "_" | "XX" | "mlft" | "mrgt" | "mmid")
+                    || str_to_oscode(base).is_some(),
+                "{keycode} converts to unknown key {key}"
+            );
+        }
+    }
+
+    #[test]
+    fn converts_qmk_keymap() {
+        let (out, notes) = convert(
+            r#"{
+  "keyboard": "crkbd/rev1",
+  "layout": "LAYOUT_split_3x6_3",
+  "layers": [
+    ["KC_ESC", "LCTL_T(KC_A)", "LT(1, KC_SPC)", "MO(1)", "KC_EXLM", "KC_X", "ANY(KC_X)"],
+    ["_______", "C(S(KC_Z))", "XXXXXXX", "_______", "OSM(MOD_LCTL|MOD_LSFT)", "TG(0)", "QK_BOOT"]
+  ]
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "\
+(defsrc
+  esc a spc 1 x
+)
+
+(deflayer layer0
+  esc (tap-hold 200 200 a lctl) (tap-hold 200 200 spc (layer-while-held layer1)) S-1 x
+)
+
+(deflayer layer1
+  _ C-S-z XX (one-shot-press 65535 (multi lctl lsft)) (layer-switch layer0)
+)
+
+"
+        );
+        let notes: Vec<_> = notes.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            notes,
+            [
+                "key 3: `MO(1)` on layer 0 sends no key, dropped from all layers",
+                "key 6: `ANY(KC_X)` sends a key that an earlier key on layer 0 sends too, \
+                 dropped from all layers",
+                "layer 1, key 5: `TG(0)` is converted to layer-switch, \
+                 add a key on layer0 to switch back",
+            ]
+        );
+    }
+}
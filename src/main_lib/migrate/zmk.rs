@@ -0,0 +1,1342 @@
+//! Converts ZMK keymaps, the devicetree `.keymap` files of ZMK firmware. The layers of the
+//! `zmk,keymap` node, the hold-tap, sticky key, macro, tap-dance and mod-morph behaviors and the
+//! combos are converted. Behaviors that control the keyboard, e.g. `&bt`, have no kanata
+//! equivalent.
+//!
+//! As with QMK, the keys that the first layer sends become defsrc, and positions that send no key
+//! there, e.g. `&mo 1`, are dropped from all layers. The rows of the first layer are kept.
+
+use super::{NO_TIMEOUT, Note};
+use anyhow::{Result, bail};
+use kanata_parser::keys::str_to_oscode;
+use std::collections::HashMap;
+
+/// Behaviors that ZMK defines with properties that keymaps can change, e.g. with
+/// `&mt { tapping-term-ms = <250>; };`.
+const BUILTIN_BEHAVIORS: &str = r#"
+/ {
+    behaviors {
+        mt: mod_tap {
+            compatible = "zmk,behavior-hold-tap";
+            flavor = "hold-preferred";
+            tapping-term-ms = <200>;
+            bindings = <&kp>, <&kp>;
+        };
+        lt: layer_tap {
+            compatible = "zmk,behavior-hold-tap";
+            flavor = "tap-preferred";
+            tapping-term-ms = <200>;
+            bindings = <&mo>, <&kp>;
+        };
+        sk: sticky_key {
+            compatible = "zmk,behavior-sticky-key";
+            release-after-ms = <1000>;
+            bindings = <&kp>;
+        };
+        sl: sticky_layer {
+            compatible = "zmk,behavior-sticky-key";
+            release-after-ms = <1000>;
+            bindings = <&mo>;
+        };
+    };
+};
+"#;
+
+/// The default `tapping-term-ms` of hold-tap and tap-dance behaviors.
+const TAPPING_TERM: u32 = 200;
+
+/// The default `timeout-ms` of combos.
+const COMBO_TIMEOUT: u32 = 50;
+
+/// Behaviors that control the keyboard itself, e.g. its Bluetooth profiles or its lighting.
+const KEYBOARD_BEHAVIORS: &[&str] = &[
+    "bt",
+    "out",
+    "bootloader",
+    "sys_reset",
+    "soft_off",
+    "studio_unlock",
+    "rgb_ug",
+    "bl",
+    "ext_power",
+];
+
+/// ZMK key names that don't convert by lowercasing.
+const KEYS: &[(&str, &str)] = &[
+    ("RETURN", "ret"),
+    ("ENTER", "ret"),
+    ("ESCAPE", "esc"),
+    ("BACKSPACE", "bspc"),
+    ("SPACE", "spc"),
+    ("MINUS", "min"),
+    ("EQUAL", "eql"),
+    ("LEFT_BRACKET", "lbrc"),
+    ("LBKT", "lbrc"),
+    ("RIGHT_BRACKET", "rbrc"),
+    ("RBKT", "rbrc"),
+    ("BACKSLASH", "bksl"),
+    ("BSLH", "bksl"),
+    ("NON_US_BACKSLASH", "nubs"),
+    ("NUBS", "nubs"),
+    ("SEMICOLON", "scln"),
+    ("SEMI", "scln"),
+    ("SINGLE_QUOTE", "apos"),
+    ("SQT", "apos"),
+    ("APOSTROPHE", "apos"),
+    ("APOS", "apos"),
+    ("GRAVE", "grv"),
+    ("COMMA", "comm"),
+    ("PERIOD", "."),
+    ("DOT", "."),
+    ("SLASH", "/"),
+    ("FSLH", "/"),
+    ("CAPSLOCK", "caps"),
+    ("CLCK", "caps"),
+    ("PRINTSCREEN", "prnt"),
+    ("PSCRN", "prnt"),
+    ("SCROLLLOCK", "slck"),
+    ("SLCK", "slck"),
+    ("PAUSE_BREAK", "pause"),
+    ("INSERT", "ins"),
+    ("DELETE", "del"),
+    ("PAGE_UP", "pgup"),
+    ("PG_UP", "pgup"),
+    ("PAGE_DOWN", "pgdn"),
+    ("PG_DN", "pgdn"),
+    ("RIGHT_ARROW", "rght"),
+    ("RIGHT", "rght"),
+    ("LEFT_ARROW", "left"),
+    ("DOWN_ARROW", "down"),
+    ("UP_ARROW", "up"),
+    ("KP_NUMLOCK", "nlck"),
+    ("KP_NUM", "nlck"),
+    ("KP_DIVIDE", "kp/"),
+    ("KP_SLASH", "kp/"),
+    ("KP_MULTIPLY", "kp*"),
+    ("KP_ASTERISK", "kp*"),
+    ("KP_MINUS", "kp-"),
+    ("KP_SUBTRACT", "kp-"),
+    ("KP_PLUS", "kp+"),
+    ("KP_ENTER", "kprt"),
+    ("KP_DOT", "kp."),
+    ("K_APPLICATION", "menu"),
+    ("K_APP", "menu"),
+    ("K_CONTEXT_MENU", "menu"),
+    ("K_CMENU", "menu"),
+    ("C_MUTE", "mute"),
+    ("K_MUTE", "mute"),
+    ("C_VOLUME_UP", "volu"),
+    ("C_VOL_UP", "volu"),
+    ("K_VOLUME_UP", "volu"),
+    ("K_VOL_UP", "volu"),
+    ("C_VOLUME_DOWN", "voldwn"),
+    ("C_VOL_DN", "voldwn"),
+    ("K_VOLUME_DOWN", "voldwn"),
+    ("K_VOL_DN", "voldwn"),
+    ("C_NEXT", "next"),
+    ("C_PREVIOUS", "prev"),
+    ("C_PREV", "prev"),
+    ("C_PLAY_PAUSE", "pp"),
+    ("C_PP", "pp"),
+    ("C_BRIGHTNESS_INC", "brup"),
+    ("C_BRI_INC", "brup"),
+    ("C_BRI_UP", "brup"),
+    ("C_BRIGHTNESS_DEC", "brdown"),
+    ("C_BRI_DEC", "brdown"),
+    ("C_BRI_DN", "brdown"),
+    ("LEFT_CONTROL", "lctl"),
+    ("LCTRL", "lctl"),
+    ("LEFT_SHIFT", "lsft"),
+    ("LSHIFT", "lsft"),
+    ("LSHFT", "lsft"),
+    ("LEFT_ALT", "lalt"),
+    ("LALT", "lalt"),
+    ("LEFT_GUI", "lmet"),
+    ("LGUI", "lmet"),
+    ("LEFT_COMMAND", "lmet"),
+    ("LCMD", "lmet"),
+    ("LEFT_WIN", "lmet"),
+    ("LWIN", "lmet"),
+    ("LEFT_META", "lmet"),
+    ("LMETA", "lmet"),
+    ("RIGHT_CONTROL", "rctl"),
+    ("RCTRL", "rctl"),
+    ("RIGHT_SHIFT", "rsft"),
+    ("RSHIFT", "rsft"),
+    ("RSHFT", "rsft"),
+    ("RIGHT_ALT", "ralt"),
+    ("RALT", "ralt"),
+    ("RIGHT_GUI", "rmet"),
+    ("RGUI", "rmet"),
+    ("RIGHT_COMMAND", "rmet"),
+    ("RCMD", "rmet"),
+    ("RIGHT_WIN", "rmet"),
+    ("RWIN", "rmet"),
+    ("RIGHT_META", "rmet"),
+    ("RMETA", "rmet"),
+    ("EXCLAMATION", "S-1"),
+    ("EXCL", "S-1"),
+    ("AT_SIGN", "S-2"),
+    ("AT", "S-2"),
+    ("HASH", "S-3"),
+    ("POUND", "S-3"),
+    ("DOLLAR", "S-4"),
+    ("DLLR", "S-4"),
+    ("PERCENT", "S-5"),
+    ("PRCNT", "S-5"),
+    ("CARET", "S-6"),
+    ("AMPERSAND", "S-7"),
+    ("AMPS", "S-7"),
+    ("ASTERISK", "S-8"),
+    ("ASTRK", "S-8"),
+    ("STAR", "S-8"),
+    ("LEFT_PARENTHESIS", "S-9"),
+    ("LPAR", "S-9"),
+    ("RIGHT_PARENTHESIS", "S-0"),
+    ("RPAR", "S-0"),
+    ("UNDERSCORE", "S-min"),
+    ("UNDER", "S-min"),
+    ("PLUS", "S-eql"),
+    ("LEFT_BRACE", "S-lbrc"),
+    ("LBRC", "S-lbrc"),
+    ("RIGHT_BRACE", "S-rbrc"),
+    ("RBRC", "S-rbrc"),
+    ("PIPE", "S-bksl"),
+    ("COLON", "S-scln"),
+    ("DOUBLE_QUOTES", "S-apos"),
+    ("DQT", "S-apos"),
+    ("TILDE", "S-grv"),
+    ("LESS_THAN", "S-comm"),
+    ("LT", "S-comm"),
+    ("GREATER_THAN", "S-."),
+    ("GT", "S-."),
+    ("QUESTION", "S-/"),
+    ("QMARK", "S-/"),
+];
+
+/// Functions that hold modifiers while sending a key, e.g. `LC(A)`, with the kanata prefix.
+const MOD_FNS: &[(&str, &str)] = &[
+    ("LC", "C-"),
+    ("LS", "S-"),
+    ("LA", "A-"),
+    ("LG", "M-"),
+    ("RC", "RC-"),
+    ("RS", "RS-"),
+    ("RA", "RA-"),
+    ("RG", "RM-"),
+];
+
+/// `MOD_*` constants of the `mods` of mod-morphs, with the modifier they stand for.
+const MODS: &[(&str, &str)] = &[
+    ("MOD_LCTL", "lctl"),
+    ("MOD_LSFT", "lsft"),
+    ("MOD_LALT", "lalt"),
+    ("MOD_LGUI", "lmet"),
+    ("MOD_RCTL", "rctl"),
+    ("MOD_RSFT", "rsft"),
+    ("MOD_RALT", "ralt"),
+    ("MOD_RGUI", "rmet"),
+];
+
+/// Mouse buttons of `&mkp`.
+const MOUSE_BUTTONS: &[(&str, &str)] = &[
+    ("LCLK", "mlft"),
+    ("MB1", "mlft"),
+    ("RCLK", "mrgt"),
+    ("MB2", "mrgt"),
+    ("MCLK", "mmid"),
+    ("MB3", "mmid"),
+    ("MB4", "mbck"),
+    ("MB5", "mfwd"),
+];
+
+/// Preprocessor directives, which are read or skipped before the devicetree is parsed.
+const DIRECTIVES: &[&str] = &[
+    "include", "define", "undef", "if", "ifdef", "ifndef", "elif", "else", "endif", "pragma",
+    "error", "warning",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Word(String),
+    Str(String),
+    Punct(char),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    /// 1-based line in the source.
+    line: usize,
+}
+
+const PUNCT: &str = "{};=<>,:";
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    name: String,
+    label: Option<String>,
+    props: Vec<Prop>,
+    children: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+struct Prop {
+    name: String,
+    values: Vec<Value>,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    /// The cells between `<` and `>`, with their lines.
+    Cells(Vec<(String, usize)>),
+    Str(String),
+}
+
+/// A behavior with its parameters, e.g. `&mt LSHIFT A`.
+#[derive(Debug, Clone)]
+struct Binding {
+    behavior: String,
+    params: Vec<String>,
+    line: usize,
+}
+
+impl std::fmt::Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "&{}", self.behavior)?;
+        self.params
+            .iter()
+            .try_for_each(|param| write!(f, " {param}"))
+    }
+}
+
+impl Node {
+    fn prop(&self, name: &str) -> Option<&Prop> {
+        self.props.iter().find(|prop| prop.name == name)
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.prop(name)?
+            .values
+            .iter()
+            .find_map(|value| match value {
+                Value::Str(s) => Some(s.as_str()),
+                Value::Cells(_) => None,
+            })
+    }
+
+    fn cells(&self, name: &str) -> Vec<(String, usize)> {
+        let Some(prop) = self.prop(name) else {
+            return vec![];
+        };
+        prop.values
+            .iter()
+            .flat_map(|value| match value {
+                Value::Cells(cells) => cells.clone(),
+                Value::Str(_) => vec![],
+            })
+            .collect()
+    }
+
+    fn number(&self, name: &str) -> Option<u32> {
+        let cells = self.cells(name);
+        let (cell, _) = cells.first()?;
+        cell.trim_matches(|c| c == '(' || c == ')').parse().ok()
+    }
+
+    fn compatible(&self) -> Option<&str> {
+        self.string("compatible")
+    }
+
+    fn bindings(&self) -> Vec<Binding> {
+        bindings(&self.cells("bindings"))
+    }
+
+    /// Returns the node and its descendants.
+    fn descendants(&self) -> Vec<&Node> {
+        let mut nodes = vec![self];
+        for child in &self.children {
+            nodes.extend(child.descendants());
+        }
+        nodes
+    }
+}
+
+/// Splits cells into bindings, which start with the `&` of the behavior.
+fn bindings(cells: &[(String, usize)]) -> Vec<Binding> {
+    let mut bindings: Vec<Binding> = vec![];
+    for (cell, line) in cells {
+        match (cell.strip_prefix('&'), bindings.last_mut()) {
+            (None, Some(binding)) => binding.params.push(cell.clone()),
+            (behavior, _) => bindings.push(Binding {
+                behavior: behavior.unwrap_or(cell).to_owned(),
+                params: vec![],
+                line: *line,
+            }),
+        }
+    }
+    bindings
+}
+
+/// Removes comments and preprocessor directives, keeping the lines, and returns the text with the
+/// values of object-like `#define` macros.
+fn preprocess(text: &str, notes: &mut Vec<Note>) -> Result<(String, HashMap<String, Vec<Tok>>)> {
+    let mut code = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.peek()) {
+            ('"', _) => {
+                code.push(ch);
+                for ch in chars.by_ref() {
+                    code.push(ch);
+                    if ch == '"' || ch == '\n' {
+                        break;
+                    }
+                }
+            }
+            ('/', Some('/')) => while chars.next_if(|&ch| ch != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = ' ';
+                for ch in chars.by_ref() {
+                    if ch == '\n' {
+                        code.push('\n');
+                    }
+                    if prev == '*' && ch == '/' {
+                        break;
+                    }
+                    prev = ch;
+                }
+            }
+            _ => code.push(ch),
+        }
+    }
+
+    let mut defines = HashMap::new();
+    let mut lines: Vec<String> = code.lines().map(str::to_owned).collect();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = i + 1;
+        let Some(rest) = lines[i].trim_start().strip_prefix('#') else {
+            i += 1;
+            continue;
+        };
+        let mut directive = rest.trim_start().to_owned();
+        let name = directive
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .unwrap_or_default();
+        if !DIRECTIVES.contains(&name) {
+            i += 1;
+            continue;
+        }
+        lines[i].clear();
+        while directive.ends_with('\\') && i + 1 < lines.len() {
+            directive.pop();
+            i += 1;
+            directive.push(' ');
+            directive.push_str(&std::mem::take(&mut lines[i]));
+        }
+        i += 1;
+        let (name, args) = directive
+            .split_once(char::is_whitespace)
+            .unwrap_or((&directive, ""));
+        match name {
+            "define" => {
+                let args = args.trim_start();
+                let macro_name: String = args
+                    .chars()
+                    .take_while(|&c| c.is_ascii_alphanumeric() || c == '_')
+                    .collect();
+                let value = &args[macro_name.len()..];
+                if value.starts_with('(') {
+                    notes.push(Note {
+                        line: Some(line),
+                        message: format!(
+                            "`#define {macro_name}(...)` has arguments and is not expanded"
+                        ),
+                    });
+                    continue;
+                }
+                let value = tokenize(value, line)?.into_iter().map(|t| t.tok).collect();
+                defines.insert(macro_name, value);
+            }
+            "if" | "ifdef" | "ifndef" | "elif" | "else" => notes.push(Note {
+                line: Some(line),
+                message: format!("`#{name}` is not evaluated, all its branches are converted"),
+            }),
+            _ => {}
+        }
+    }
+    Ok((lines.join("\n"), defines))
+}
+
+fn tokenize(text: &str, first_line: usize) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = first_line;
+    while let Some(&ch) = chars.peek() {
+        let start = line;
+        if ch.is_whitespace() {
+            if ch == '\n' {
+                line += 1;
+            }
+            chars.next();
+        } else if PUNCT.contains(ch) {
+            chars.next();
+            tokens.push(Token {
+                tok: Tok::Punct(ch),
+                line,
+            });
+        } else if ch == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => s.extend(chars.next()),
+                    Some('\n') | None => bail!("line {start}: the string does not end"),
+                    Some(ch) => s.push(ch),
+                }
+            }
+            tokens.push(Token {
+                tok: Tok::Str(s),
+                line,
+            });
+        } else {
+            let mut word = String::new();
+            let mut depth = 0usize;
+            while let Some(&ch) = chars.peek() {
+                if depth == 0 && (ch.is_whitespace() || PUNCT.contains(ch) || ch == '"') {
+                    break;
+                }
+                match ch {
+                    '(' => depth += 1,
+                    ')' => depth = depth.saturating_sub(1),
+                    '\n' => line += 1,
+                    _ => {}
+                }
+                if !ch.is_whitespace() {
+                    word.push(ch);
+                }
+                chars.next();
+            }
+            tokens.push(Token {
+                tok: Tok::Word(word),
+                line: start,
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Replaces the macros of `#define` with their values.
+fn expand(tokens: Vec<Token>, defines: &HashMap<String, Vec<Tok>>, depth: usize) -> Vec<Token> {
+    tokens
+        .into_iter()
+        .flat_map(|token| match &token.tok {
+            Tok::Word(word) if depth < 8 && defines.contains_key(word) => {
+                let value = defines[word].iter().map(|tok| Token {
+                    tok: tok.clone(),
+                    line: token.line,
+                });
+                expand(value.collect(), defines, depth + 1)
+            }
+            _ => vec![token],
+        })
+        .collect()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos).map(|t| &t.tok)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            let line = self.tokens.last().map_or(1, |t| t.line);
+            bail!("line {line}: the keymap ends early, a `}}` or `;` is missing");
+        };
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, punct: char) -> Result<()> {
+        let token = self.next()?;
+        if token.tok != Tok::Punct(punct) {
+            bail!("line {}: expected `{punct}`", token.line);
+        }
+        Ok(())
+    }
+
+    fn word(&mut self) -> Result<String> {
+        let token = self.next()?;
+        match token.tok {
+            Tok::Word(word) => Ok(word),
+            _ => bail!("line {}: expected a name", token.line),
+        }
+    }
+
+    /// Parses the top-level nodes, `/ { ... };` and `&label { ... };`.
+    fn nodes(&mut self) -> Result<Vec<Node>> {
+        let mut nodes = vec![];
+        while self.peek().is_some() {
+            let mut node = Node {
+                name: self.word()?,
+                ..Default::default()
+            };
+            self.body(&mut node)?;
+            nodes.push(node);
+        }
+        Ok(nodes)
+    }
+
+    /// Parses the properties and children of a node, from `{` to `};`.
+    fn body(&mut self, node: &mut Node) -> Result<()> {
+        self.expect('{')?;
+        while self.peek() != Some(&Tok::Punct('}')) {
+            let mut name = self.word()?;
+            let mut label = None;
+            if self.peek() == Some(&Tok::Punct(':')) {
+                self.pos += 1;
+                label = Some(name);
+                name = self.word()?;
+            }
+            match self.peek() {
+                Some(Tok::Punct('{')) => {
+                    let mut child = Node {
+                        name,
+                        label,
+                        ..Default::default()
+                    };
+                    self.body(&mut child)?;
+                    node.children.push(child);
+                }
+                Some(Tok::Punct('=')) => {
+                    self.pos += 1;
+                    let values = self.values()?;
+                    node.props.push(Prop { name, values });
+                }
+                _ => {
+                    self.expect(';')?;
+                    node.props.push(Prop {
+                        name,
+                        values: vec![],
+                    });
+                }
+            }
+        }
+        self.expect('}')?;
+        self.expect(';')
+    }
+
+    /// Parses the values of a property, up to the `;`.
+    fn values(&mut self) -> Result<Vec<Value>> {
+        let mut values = vec![];
+        loop {
+            let token = self.next()?;
+            match token.tok {
+                Tok::Punct('<') => {
+                    let mut cells = vec![];
+                    loop {
+                        let token = self.next()?;
+                        match token.tok {
+                            Tok::Punct('>') => break,
+                            Tok::Word(cell) => cells.push((cell, token.line)),
+                            _ => bail!("line {}: expected a cell or `>`", token.line),
+                        }
+                    }
+                    values.push(Value::Cells(cells));
+                }
+                Tok::Str(s) => values.push(Value::Str(s)),
+                _ => bail!("line {}: expected `<` or a string", token.line),
+            }
+            let token = self.next()?;
+            match token.tok {
+                Tok::Punct(',') => {}
+                Tok::Punct(';') => return Ok(values),
+                _ => bail!("line {}: expected `,` or `;`", token.line),
+            }
+        }
+    }
+}
+
+fn parse(text: &str, notes: &mut Vec<Note>) -> Result<Vec<Node>> {
+    let (code, defines) = preprocess(text, notes)?;
+    let tokens = expand(tokenize(&code, 1)?, &defines, 0);
+    Parser { tokens, pos: 0 }.nodes()
+}
+
+struct Converter {
+    /// Behaviors by label.
+    behaviors: HashMap<String, Node>,
+    layer_names: Vec<String>,
+    /// Aliases of the behaviors without parameters, e.g. macros, in the order of their first use.
+    aliases: Vec<(String, String)>,
+    /// Behaviors being converted, to not convert one that uses itself forever.
+    converting: Vec<String>,
+    notes: Vec<Note>,
+    /// The line of the binding being converted, for the notes.
+    line: usize,
+}
+
+/// Returns the kanata configuration and the notes about what did not convert exactly.
+pub(super) fn convert(text: &str) -> Result<(String, Vec<Note>)> {
+    let mut notes = vec![];
+    let builtin = parse(BUILTIN_BEHAVIORS, &mut vec![])?;
+    let nodes = parse(text, &mut notes).map_err(|e| e.context("Could not parse the keymap"))?;
+    let (roots, overrides): (Vec<_>, Vec<_>) = builtin
+        .iter()
+        .chain(&nodes)
+        .partition(|node| !node.name.starts_with('&'));
+    let all: Vec<&Node> = roots.iter().flat_map(|node| node.descendants()).collect();
+
+    let mut behaviors = HashMap::new();
+    for node in &all {
+        if let Some(label) = &node.label
+            && node
+                .compatible()
+                .is_some_and(|c| c.starts_with("zmk,behavior-"))
+        {
+            behaviors.insert(label.clone(), (*node).clone());
+        }
+    }
+    for node in overrides {
+        let label = &node.name[1..];
+        let Some(behavior) = behaviors.get_mut(label) else {
+            notes.push(Note {
+                line: None,
+                message: format!("`&{label}` is not a behavior, its changes are skipped"),
+            });
+            continue;
+        };
+        for prop in &node.props {
+            behavior.props.retain(|p| p.name != prop.name);
+            behavior.props.push(prop.clone());
+        }
+    }
+
+    let Some(keymap) = all
+        .iter()
+        .find(|node| node.compatible() == Some("zmk,keymap"))
+    else {
+        bail!("The file has no node with compatible = \"zmk,keymap\", is it a ZMK keymap?");
+    };
+    let layers = &keymap.children;
+    let Some(base) = layers.first() else {
+        bail!("The keymap has no layers");
+    };
+    let mut c = Converter {
+        behaviors,
+        layer_names: layers.iter().map(|layer| layer.name.clone()).collect(),
+        aliases: vec![],
+        converting: vec![],
+        notes,
+        line: 0,
+    };
+
+    let mut src: Vec<String> = vec![];
+    // The positions kept in defsrc, by position, with the row of each.
+    let mut kept: Vec<(usize, usize)> = vec![];
+    for (position, binding) in base.bindings().iter().enumerate() {
+        c.line = binding.line;
+        match c.source_key(binding) {
+            Some(key) if src.contains(&key) => c.note(format!(
+                "`{binding}` sends a key that an earlier key on {} sends too, \
+                 dropped from all layers",
+                base.name
+            )),
+            Some(key) => {
+                src.push(key);
+                kept.push((position, binding.line));
+            }
+            None => c.note(format!(
+                "`{binding}` on {} sends no key, dropped from all layers",
+                base.name
+            )),
+        }
+    }
+    let rows = |keys: &[String]| {
+        let mut out = String::new();
+        for (i, key) in keys.iter().enumerate() {
+            match i {
+                0 => out.push_str("  "),
+                _ if kept[i].1 != kept[i - 1].1 => out.push_str("\n  "),
+                _ => out.push(' '),
+            }
+            out.push_str(key);
+        }
+        out + "\n"
+    };
+
+    let mut deflayers = String::new();
+    for layer in layers {
+        let bindings = layer.bindings();
+        let keys: Vec<String> = kept
+            .iter()
+            .map(|&(position, _)| match bindings.get(position) {
+                Some(binding) => c.binding(binding),
+                None => "_".to_owned(),
+            })
+            .collect();
+        let name = match layer.string("display-name") {
+            Some(display_name) => format!("({} display-name {})", layer.name, quote(display_name)),
+            None => layer.name.clone(),
+        };
+        if let Some(prop) = layer.prop("sensor-bindings") {
+            c.line = prop.values.iter().find_map(line_of).unwrap_or(c.line);
+            c.note(format!(
+                "sensor-bindings of {} are not converted, kanata has no encoders",
+                layer.name
+            ));
+        }
+        deflayers.push_str(&format!("(deflayer {name}\n{})\n\n", rows(&keys)));
+    }
+
+    let combos: Vec<&Node> = all
+        .iter()
+        .filter(|node| node.compatible() == Some("zmk,combos"))
+        .flat_map(|node| &node.children)
+        .collect();
+    let chords: Vec<String> = combos
+        .iter()
+        .filter_map(|combo| c.combo(combo, &src, &kept))
+        .collect();
+    for node in &all {
+        if let Some(compatible @ "zmk,conditional-layers") = node.compatible() {
+            c.notes.push(Note {
+                line: None,
+                message: format!("`{compatible}` is not converted, add the layer keys by hand"),
+            });
+        }
+    }
+
+    let mut out = String::new();
+    if !chords.is_empty() {
+        out.push_str("(defcfg\n  concurrent-tap-hold yes\n)\n\n");
+    }
+    out.push_str(&format!("(defsrc\n{})\n\n", rows(&src)));
+    if !c.aliases.is_empty() {
+        let aliases: String = c
+            .aliases
+            .iter()
+            .map(|(name, action)| format!("  {name} {action}\n"))
+            .collect();
+        out.push_str(&format!("(defalias\n{aliases})\n\n"));
+    }
+    out.push_str(&deflayers);
+    if !chords.is_empty() {
+        out.push_str(&format!("(defchordsv2\n{})\n", chords.concat()));
+    }
+    Ok((out, c.notes))
+}
+
+fn line_of(value: &Value) -> Option<usize> {
+    match value {
+        Value::Cells(cells) => cells.first().map(|(_, line)| *line),
+        Value::Str(_) => None,
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+/// Converts a key without modifier functions, or returns `None` if it is not a key kanata knows.
+fn basic(name: &str) -> Option<String> {
+    if let Some((_, key)) = KEYS.iter().find(|(k, _)| *k == name) {
+        return Some((*key).to_owned());
+    }
+    let digit = |prefixes: &[&str]| {
+        prefixes
+            .iter()
+            .find_map(|prefix| name.strip_prefix(prefix))
+            .filter(|d| d.len() == 1 && d.chars().all(|c| c.is_ascii_digit()))
+    };
+    if let Some(d) = digit(&["KP_NUMBER_", "KP_N"]) {
+        return Some(format!("kp{d}"));
+    }
+    if let Some(d) = digit(&["NUMBER_", "N"]) {
+        return Some(d.to_owned());
+    }
+    let key = name.to_lowercase();
+    str_to_oscode(&key).map(|_| key)
+}
+
+/// Returns the key without the modifier prefixes, e.g. `a` for `C-S-a`.
+fn unmodified(mut key: &str) -> &str {
+    while let Some(rest) = MOD_FNS
+        .iter()
+        .find_map(|(_, prefix)| key.strip_prefix(prefix))
+        .filter(|rest| !rest.is_empty())
+    {
+        key = rest;
+    }
+    key
+}
+
+impl Converter {
+    fn note(&mut self, message: impl Into<String>) {
+        self.notes.push(Note {
+            line: Some(self.line),
+            message: message.into(),
+        });
+    }
+
+    fn unsupported(&mut self, binding: &Binding) -> String {
+        self.note(format!("`{binding}` is not supported, converted to XX"));
+        "XX".to_owned()
+    }
+
+    /// Converts a key name of `&kp`, e.g. `LC(A)`.
+    fn key(&self, name: &str) -> Option<String> {
+        let Some((function, rest)) = name.split_once('(') else {
+            return basic(name);
+        };
+        let (_, prefix) = MOD_FNS.iter().find(|(f, _)| *f == function)?;
+        let key = self.key(rest.strip_suffix(')')?)?;
+        Some(format!("{prefix}{key}"))
+    }
+
+    fn layer(&self, param: &str) -> Option<&str> {
+        let index: usize = param.parse().ok()?;
+        self.layer_names.get(index).map(String::as_str)
+    }
+
+    /// Returns the key that the binding sends when tapped, without modifiers. For tap-dances and
+    /// mod-morphs, it is the key of the first binding.
+    fn source_key(&self, binding: &Binding) -> Option<String> {
+        let key = match (binding.behavior.as_str(), binding.params.as_slice()) {
+            ("kp", [key]) => self.key(key)?,
+            (label, []) => {
+                let behavior = self.behaviors.get(label)?;
+                if !matches!(
+                    behavior.compatible(),
+                    Some("zmk,behavior-tap-dance" | "zmk,behavior-mod-morph")
+                ) {
+                    return None;
+                }
+                let first = behavior.bindings().into_iter().next()?;
+                return self.source_key(&first);
+            }
+            (label, [_, tap]) => {
+                let behavior = self.behaviors.get(label)?;
+                if behavior.compatible() != Some("zmk,behavior-hold-tap") {
+                    return None;
+                }
+                let tap_binding = behavior.bindings().into_iter().nth(1)?;
+                return self.source_key(&Binding {
+                    params: vec![tap.clone()],
+                    ..tap_binding
+                });
+            }
+            _ => return None,
+        };
+        let key = unmodified(&key);
+        str_to_oscode(key).map(|_| key.to_owned())
+    }
+
+    fn binding(&mut self, binding: &Binding) -> String {
+        self.line = binding.line;
+        let converted = match (binding.behavior.as_str(), binding.params.as_slice()) {
+            ("kp", [key]) => self.key(key),
+            ("trans", []) => Some("_".to_owned()),
+            ("none", []) => Some("XX".to_owned()),
+            ("mo", [layer]) => self
+                .layer(layer)
+                .map(|layer| format!("(layer-while-held {layer})")),
+            ("to", [layer]) => self
+                .layer(layer)
+                .map(|layer| format!("(layer-switch {layer})")),
+            ("tog", [layer]) => self.layer(layer).map(str::to_owned).map(|layer| {
+                self.note(format!(
+                    "`{binding}` is converted to layer-switch, \
+                     add a key on {layer} to switch back"
+                ));
+                format!("(layer-switch {layer})")
+            }),
+            ("mkp", [button]) => MOUSE_BUTTONS
+                .iter()
+                .find(|(b, _)| b == button)
+                .map(|(_, key)| (*key).to_owned()),
+            ("key_repeat", []) => Some("rpt".to_owned()),
+            ("caps_word", []) => Some(format!("(caps-word {NO_TIMEOUT})")),
+            (behavior, _) if KEYBOARD_BEHAVIORS.contains(&behavior) => {
+                self.note(format!(
+                    "`{binding}` controls the keyboard and has no kanata equivalent, \
+                     converted to XX"
+                ));
+                return "XX".to_owned();
+            }
+            (label, params) => match self.behaviors.get(label).cloned() {
+                Some(behavior) if !self.converting.iter().any(|l| l == label) => {
+                    self.converting.push(label.to_owned());
+                    let converted = self.behavior(label, &behavior, params);
+                    self.converting.pop();
+                    converted
+                }
+                _ => None,
+            },
+        };
+        converted.unwrap_or_else(|| self.unsupported(binding))
+    }
+
+    /// Converts a use of a behavior defined in the keymap or by ZMK.
+    fn behavior(&mut self, label: &str, behavior: &Node, params: &[String]) -> Option<String> {
+        let with_param = |binding: Binding, param: &String| Binding {
+            params: vec![param.clone()],
+            ..binding
+        };
+        let line = self.line;
+        match (behavior.compatible()?, params) {
+            ("zmk,behavior-hold-tap", [hold, tap]) => {
+                let [hold_binding, tap_binding] =
+                    <[Binding; 2]>::try_from(behavior.bindings()).ok()?;
+                let hold = self.binding(&with_param(
+                    Binding {
+                        line,
+                        ..hold_binding
+                    },
+                    hold,
+                ));
+                let tap = self.binding(&with_param(
+                    Binding {
+                        line,
+                        ..tap_binding
+                    },
+                    tap,
+                ));
+                Some(self.hold_tap(label, behavior, &tap, &hold))
+            }
+            ("zmk,behavior-sticky-key", [param]) => {
+                let binding = behavior.bindings().into_iter().next()?;
+                let action = self.binding(&with_param(Binding { line, ..binding }, param));
+                let timeout = behavior.number("release-after-ms").unwrap_or(1000);
+                let one_shot = match behavior.prop("quick-release") {
+                    Some(_) => "one-shot-press",
+                    None => "one-shot-release",
+                };
+                for prop in ["lazy", "ignore-modifiers"] {
+                    self.not_converted(label, behavior, prop);
+                }
+                Some(format!("({one_shot} {timeout} {action})"))
+            }
+            (
+                compatible @ ("zmk,behavior-macro"
+                | "zmk,behavior-tap-dance"
+                | "zmk,behavior-mod-morph"),
+                [],
+            ) => {
+                if !self.aliases.iter().any(|(name, _)| name == label) {
+                    let action = match compatible {
+                        "zmk,behavior-macro" => self.macro_action(label, behavior)?,
+                        "zmk,behavior-tap-dance" => {
+                            let timeout =
+                                behavior.number("tapping-term-ms").unwrap_or(TAPPING_TERM);
+                            let actions: Vec<String> = behavior
+                                .bindings()
+                                .iter()
+                                .map(|binding| {
+                                    self.binding(&Binding {
+                                        line,
+                                        ..binding.clone()
+                                    })
+                                })
+                                .collect();
+                            format!("(tap-dance {timeout} ({}))", actions.join(" "))
+                        }
+                        _ => self.mod_morph(label, behavior)?,
+                    };
+                    self.line = line;
+                    self.aliases.push((label.to_owned(), action));
+                }
+                Some(format!("@{label}"))
+            }
+            _ => None,
+        }
+    }
+
+    fn not_converted(&mut self, label: &str, behavior: &Node, prop: &str) {
+        if behavior.prop(prop).is_some() {
+            self.note(format!("`{prop}` of `&{label}` is not converted"));
+        }
+    }
+
+    fn hold_tap(&mut self, label: &str, behavior: &Node, tap: &str, hold: &str) -> String {
+        let timeout = behavior.number("tapping-term-ms").unwrap_or(TAPPING_TERM);
+        let repress = behavior.number("quick-tap-ms").unwrap_or(0);
+        for prop in [
+            "hold-trigger-key-positions",
+            "hold-trigger-on-release",
+            "require-prior-idle-ms",
+            "retro-tap",
+            "hold-while-undecided",
+        ] {
+            self.not_converted(label, behavior, prop);
+        }
+        match behavior.string("flavor").unwrap_or("hold-preferred") {
+            "tap-preferred" => format!("(tap-hold {repress} {timeout} {tap} {hold})"),
+            "balanced" => format!("(tap-hold-release {repress} {timeout} {tap} {hold})"),
+            "tap-unless-interrupted" => {
+                format!("(tap-hold-press-timeout {repress} {timeout} {tap} {hold} {tap})")
+            }
+            flavor => {
+                if flavor != "hold-preferred" {
+                    self.note(format!(
+                        "flavor `{flavor}` of `&{label}` is unknown, converted as hold-preferred"
+                    ));
+                }
+                format!("(tap-hold-press {repress} {timeout} {tap} {hold})")
+            }
+        }
+    }
+
+    /// Converts a macro that taps keys.
+    fn macro_action(&mut self, label: &str, behavior: &Node) -> Option<String> {
+        let mut keys = vec![];
+        let mut timing_noted = false;
+        for binding in behavior.bindings() {
+            match (binding.behavior.as_str(), binding.params.as_slice()) {
+                ("macro_tap", []) => {}
+                ("macro_wait_time" | "macro_tap_time", _) if !timing_noted => {
+                    timing_noted = true;
+                    self.note(format!("the timing of `&{label}` is not converted"));
+                }
+                ("macro_wait_time" | "macro_tap_time", _) => {}
+                ("kp", [key]) => keys.push(self.key(key)?),
+                _ => return None,
+            }
+        }
+        Some(format!("(macro {})", keys.join(" ")))
+    }
+
+    /// Converts a mod-morph to a fork on its modifiers. ZMK releases the modifiers for the morphed
+    /// key unless `keep-mods` is set, which `unmod` does for a key.
+    fn mod_morph(&mut self, label: &str, behavior: &Node) -> Option<String> {
+        let line = self.line;
+        let [default, morphed] = <[Binding; 2]>::try_from(behavior.bindings()).ok()?;
+        let default = self.binding(&Binding { line, ..default });
+        let mut morphed = self.binding(&Binding { line, ..morphed });
+        let cells = behavior.cells("mods");
+        let (mods, _) = cells.first()?;
+        let mods = mods
+            .trim_matches(|c| c == '(' || c == ')')
+            .split('|')
+            .map(|m| {
+                MODS.iter()
+                    .find(|(n, _)| *n == m.trim())
+                    .map(|(_, key)| *key)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if behavior.prop("keep-mods").is_some() {
+            self.note(format!(
+                "`keep-mods` of `&{label}` is converted as keeping all modifiers"
+            ));
+        } else if str_to_oscode(&morphed).is_some() {
+            morphed = format!("(unmod {morphed})");
+        } else {
+            self.note(format!(
+                "the modifiers are not released for the morphed key of `&{label}`"
+            ));
+        }
+        Some(format!("(fork {default} {morphed} ({}))", mods.join(" ")))
+    }
+
+    /// Converts a combo to a chord of `defchordsv2`.
+    fn combo(&mut self, combo: &Node, src: &[String], kept: &[(usize, usize)]) -> Option<String> {
+        let positions = combo.cells("key-positions");
+        self.line = positions.first().map_or(0, |(_, line)| *line);
+        let mut keys = vec![];
+        for (position, _) in &positions {
+            let key = position
+                .parse::<usize>()
+                .ok()
+                .and_then(|position| kept.iter().position(|&(p, _)| p == position));
+            match key {
+                Some(i) => keys.push(src[i].as_str()),
+                None => {
+                    self.note(format!(
+                        "combo {} uses position {position}, which is not in defsrc, skipped",
+                        combo.name
+                    ));
+                    return None;
+                }
+            }
+        }
+        let Some(binding) = combo.bindings().into_iter().next() else {
+            self.note(format!("combo {} has no bindings, skipped", combo.name));
+            return None;
+        };
+        let action = self.binding(&binding);
+        let timeout = combo.number("timeout-ms").unwrap_or(COMBO_TIMEOUT);
+        let release = match combo.prop("slow-release") {
+            Some(_) => "all-released",
+            None => "first-release",
+        };
+        if combo.prop("require-prior-idle-ms").is_some() {
+            self.note(format!(
+                "`require-prior-idle-ms` of combo {} is not converted, \
+                 see chords-v2-min-idle in defcfg",
+                combo.name
+            ));
+        }
+        let enabled = combo.cells("layers");
+        let disabled: Vec<&str> = match enabled.is_empty() {
+            true => vec![],
+            false => self
+                .layer_names
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !enabled.iter().any(|(layer, _)| *layer == i.to_string()))
+                .map(|(_, name)| name.as_str())
+                .collect(),
+        };
+        Some(format!(
+            "  ({}) {action} {timeout} {release} ({})\n",
+            keys.join(" "),
+            disabled.join(" ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_keys_exist() {
+        let keys = KEYS.iter().map(|(_, key)| *key);
+        for key in keys.chain(MODS.iter().map(|(_, key)| *key)) {
+            let base = key.strip_prefix("S-").unwrap_or(key);
+            assert!(str_to_oscode(base).is_some(), "unknown key {key}");
+        }
+        for name in [
+            "A", "N1", "NUMBER_0", "KP_N5", "F12", "HOME", "TAB", "ESC", "UP",
+        ] {
+            assert!(basic(name).is_some(), "{name} does not convert");
+        }
+    }
+
+    #[test]
+    fn converts_zmk_keymap() {
+        let (out, notes) = convert(
+            r#"
+#include <behaviors.dtsi>
+#include <dt-bindings/zmk/keys.h>
+#include <dt-bindings/zmk/bt.h>
+
+#define BASE 0
+#define NAV 1
+
+&mt { tapping-term-ms = <250>; };
+
+/ {
+    behaviors {
+        hm: homerow_mods {
+            compatible = "zmk,behavior-hold-tap";
+            #binding-cells = <2>;
+            flavor = "balanced";
+            tapping-term-ms = <180>;
+            quick-tap-ms = <150>;
+            bindings = <&kp>, <&kp>;
+        };
+        td_q: tap_dance_q {
+            compatible = "zmk,behavior-tap-dance";
+            #binding-cells = <0>;
+            bindings = <&kp Q>, <&kp ESC>;
+        };
+        cse: comma_semi {
+            compatible = "zmk,behavior-mod-morph";
+            #binding-cells = <0>;
+            bindings = <&kp COMMA>, <&kp SEMI>;
+            mods = <(MOD_LSFT|MOD_RSFT)>;
+        };
+    };
+
+    macros {
+        hi: hi {
+            compatible = "zmk,behavior-macro";
+            #binding-cells = <0>;
+            bindings = <&macro_tap &kp H &kp I>;
+        };
+    };
+
+    combos {
+        compatible = "zmk,combos";
+        combo_esc {
+            timeout-ms = <40>;
+            key-positions = <0 1>;
+            bindings = <&kp ESC>;
+            layers = <BASE>;
+        };
+    };
+
+    keymap {
+        compatible = "zmk,keymap";
+        default_layer {
+            display-name = "Base";
+            bindings = <
+                &td_q   &kp W       &mt LSHIFT E    // home row
+                &hm LGUI A  &lt NAV SPACE  &mo NAV  &cse
+            >;
+        };
+        nav_layer {
+            bindings = <
+                &trans  &kp LC(LS(Z))  &bt BT_CLR
+                &hi     &none          &trans    &sk LCTRL
+            >;
+        };
+    };
+};
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "\
+(defcfg
+  concurrent-tap-hold yes
+)
+
+(defsrc
+  q w e
+  a spc comm
+)
+
+(defalias
+  td_q (tap-dance 200 (q esc))
+  cse (fork comm (unmod scln) (lsft rsft))
+  hi (macro h i)
+)
+
+(deflayer (default_layer display-name \"Base\")
+  @td_q w (tap-hold-press 0 250 e lsft)
+  (tap-hold-release 150 180 a lmet) (tap-hold 0 200 spc (layer-while-held nav_layer)) @cse
+)
+
+(deflayer nav_layer
+  _ C-S-z XX
+  @hi XX (one-shot-release 1000 lctl)
+)
+
+(defchordsv2
+  (q w) esc 40 first-release (nav_layer)
+)
+"
+        );
+        let notes: Vec<_> = notes.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            notes,
+            [
+                "line 58: `&mo 1` on default_layer sends no key, dropped from all layers",
+                "line 63: `&bt BT_CLR` controls the keyboard and has no kanata equivalent, \
+                 converted to XX",
+            ]
+        );
+    }
+}
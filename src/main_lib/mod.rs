@@ -1,4 +1,36 @@
 pub(crate) mod args;
+#[cfg(not(feature = "gui"))]
+pub(crate) mod check;
+#[cfg(not(feature = "gui"))]
+pub(crate) mod doctor;
+#[cfg(not(feature = "gui"))]
+pub(crate) mod export;
+#[cfg(all(feature = "simulated_output", not(feature = "gui")))]
+pub(crate) mod golden;
+pub(crate) mod log_file;
+#[cfg(not(feature = "gui"))]
+pub(crate) mod migrate;
+#[cfg(all(any(target_os = "linux", target_os = "android"), not(feature = "gui")))]
+pub(crate) mod record;
+#[cfg(all(feature = "simulated_output", not(feature = "gui")))]
+pub(crate) mod repl;
+#[cfg(all(feature = "simulated_output", not(feature = "gui")))]
+pub(crate) mod replay_crash;
+#[cfg(all(feature = "simulated_output", not(feature = "gui")))]
+pub(crate) mod replay_trace;
+#[cfg(all(feature = "simulated_output", not(feature = "gui")))]
+pub(crate) mod shadow;
+
+#[cfg(all(
+    any(
+        target_os = "macos",
+        target_os = "linux",
+        target_os = "android",
+        all(target_os = "windows", feature = "interception_driver")
+    ),
+    not(feature = "gui")
+))]
+pub(crate) mod list_devices;
 
 #[cfg(all(target_os = "windows", feature = "gui"))]
 pub(crate) mod win_gui;
@@ -189,15 +221,13 @@ fn get_device_info(device_handle: winapi::um::winnt::HANDLE) -> Option<WindowsDe
     None
 }
 
+/// Returns the handles of the keyboards known to Raw Input.
 #[cfg(all(target_os = "windows", feature = "interception_driver"))]
-pub(crate) fn list_devices_windows() {
+fn raw_input_keyboards() -> Result<Vec<winapi::um::winnt::HANDLE>, &'static str> {
     use std::ptr::null_mut;
     use winapi::shared::minwindef::{PUINT, UINT};
     use winapi::um::winuser::{GetRawInputDeviceList, RAWINPUTDEVICELIST, RIM_TYPEKEYBOARD};
 
-    println!("Available keyboard devices:");
-    println!("===========================");
-
     unsafe {
         // First, get the number of devices
         let mut num_devices: UINT = 0;
@@ -208,13 +238,11 @@ pub(crate) fn list_devices_windows() {
         );
 
         if result == u32::MAX {
-            println!("Error: Failed to get device count");
-            return;
+            return Err("Failed to get device count");
         }
 
         if num_devices == 0 {
-            println!("No input devices found.");
-            return;
+            return Ok(vec![]);
         }
 
         // Allocate buffer for device list
@@ -227,66 +255,79 @@ pub(crate) fn list_devices_windows() {
         );
 
         if result == u32::MAX {
-            println!("Error: Failed to get device list");
-            return;
+            return Err("Failed to get device list");
         }
 
-        // Filter for keyboards and get device info
-        let keyboards: Vec<&RAWINPUTDEVICELIST> = devices
+        Ok(devices
             .iter()
             .filter(|device| device.dwType == RIM_TYPEKEYBOARD)
-            .collect();
+            .map(|device| device.hDevice)
+            .collect())
+    }
+}
+
+#[cfg(all(target_os = "windows", feature = "interception_driver"))]
+pub(crate) fn list_devices_windows() {
+    println!("Available keyboard devices:");
+    println!("===========================");
 
-        if keyboards.is_empty() {
-            println!("No keyboard devices found.");
-            println!("\nTroubleshooting:");
-            println!("  1. Ensure keyboards are connected and working");
-            println!("  2. Try running as administrator if needed");
+    let keyboards = match raw_input_keyboards() {
+        Ok(keyboards) => keyboards,
+        Err(e) => {
+            println!("Error: {e}");
             return;
         }
+    };
 
-        println!("Found {} keyboard device(s):\n", keyboards.len());
+    if keyboards.is_empty() {
+        println!("No keyboard devices found.");
+        println!("\nTroubleshooting:");
+        println!("  1. Ensure keyboards are connected and working");
+        println!("  2. Try running as administrator if needed");
+        return;
+    }
 
-        for (i, device) in keyboards.iter().enumerate() {
-            if let Some(device_info) = get_device_info(device.hDevice) {
-                println!("  {}. Device: {}", i + 1, device_info.display_name);
+    println!("Found {} keyboard device(s):\n", keyboards.len());
 
-                // Show hardware ID if available
-                if let Some(hwid) = &device_info.hardware_id {
-                    println!("     Hardware ID: {hwid}");
-                }
+    for (i, device) in keyboards.iter().enumerate() {
+        if let Some(device_info) = get_device_info(*device) {
+            println!("  {}. Device: {}", i + 1, device_info.display_name);
 
-                // Show raw wide string bytes for kanata configuration
-                println!(
-                    "     Raw wide string bytes: {:?}",
-                    device_info.raw_wide_bytes
-                );
-                println!();
+            // Show hardware ID if available
+            if let Some(hwid) = &device_info.hardware_id {
+                println!("     Hardware ID: {hwid}");
             }
+
+            // Show raw wide string bytes for kanata configuration
+            println!(
+                "     Raw wide string bytes: {:?}",
+                device_info.raw_wide_bytes
+            );
+            println!();
         }
+    }
+
+    if !keyboards.is_empty() {
+        println!("Configuration example:");
+        println!("  (defcfg");
+        println!("    windows-interception-keyboard-hwids (");
+
+        for device in keyboards.iter() {
+            if let Some(device_info) = get_device_info(*device) {
+                // Use the preserved raw wide string bytes for configuration
+                print!("      {:?}", device_info.raw_wide_bytes);
 
-        if !keyboards.is_empty() {
-            println!("Configuration example:");
-            println!("  (defcfg");
-            println!("    windows-interception-keyboard-hwids (");
-
-            for device in keyboards.iter() {
-                if let Some(device_info) = get_device_info(device.hDevice) {
-                    // Use the preserved raw wide string bytes for configuration
-                    print!("      {:?}", device_info.raw_wide_bytes);
-
-                    // Add comment with hardware ID and display name for clarity
-                    if let Some(hwid) = &device_info.hardware_id {
-                        println!("  ; {} ({})", hwid, device_info.display_name);
-                    } else {
-                        println!("  ; {}", device_info.display_name);
-                    }
+                // Add comment with hardware ID and display name for clarity
+                if let Some(hwid) = &device_info.hardware_id {
+                    println!("  ; {} ({})", hwid, device_info.display_name);
+                } else {
+                    println!("  ; {}", device_info.display_name);
                 }
             }
-
-            println!("    )");
-            println!("  )");
         }
+
+        println!("    )");
+        println!("  )");
     }
 }
 
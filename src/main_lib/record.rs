@@ -0,0 +1,181 @@
+//! `kanata record`: writes the key events of input devices in the simulator format, so that a
+//! sequence that misbehaves can be replayed exactly, e.g. when attached to a bug report.
+
+use anyhow::{Context, Result, bail};
+use evdev::{Device, EventType};
+use kanata_parser::cfg::DeviceDetectMode;
+use kanata_parser::keys::{OsCode, oscode_to_str};
+use kanata_state_machine::oskbd::discover_devices;
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+
+/// Formats key events as simulator input, with a `t:` item for the time since the previous event.
+struct Recorder {
+    last: Option<SystemTime>,
+    held: Vec<OsCode>,
+}
+
+/// Pressing these together ends the recording, like the emergency exit of kanata. This is the
+/// only way to stop when the devices are grabbed.
+const STOP_KEYS: [OsCode; 3] = [OsCode::KEY_LEFTCTRL, OsCode::KEY_SPACE, OsCode::KEY_ESC];
+
+impl Recorder {
+    fn new() -> Self {
+        Self {
+            last: None,
+            held: vec![],
+        }
+    }
+
+    /// Returns the line to write for the event, or `None` if it is not recorded.
+    fn line(&mut self, time: SystemTime, code: OsCode, value: i32) -> Option<String> {
+        let kind = match value {
+            0 => "u",
+            1 => "d",
+            2 => "r",
+            _ => return None,
+        };
+        match value {
+            0 => self.held.retain(|c| *c != code),
+            1 if !self.held.contains(&code) => self.held.push(code),
+            _ => {}
+        }
+        let Some(name) = oscode_to_str(code) else {
+            eprintln!("Skipping key without a name: {code:?}");
+            return None;
+        };
+        let elapsed = self
+            .last
+            .map(|last| time.duration_since(last).unwrap_or(Duration::ZERO));
+        self.last = Some(time);
+        Some(match elapsed {
+            Some(elapsed) => format!("t:{} {kind}:{name}", elapsed.as_millis()),
+            None => format!("{kind}:{name}"),
+        })
+    }
+
+    fn should_stop(&self) -> bool {
+        STOP_KEYS.iter().all(|k| self.held.contains(k))
+    }
+}
+
+/// Records the devices at `paths`, or all keyboards if there are none, until the stop keys are
+/// pressed or the process is interrupted.
+pub(crate) fn record(paths: &[PathBuf], grab: bool, output: Option<&PathBuf>) -> Result<()> {
+    let devices = if paths.is_empty() {
+        let devices = discover_devices(None, None, DeviceDetectMode::KeyboardOnly);
+        if devices.is_empty() {
+            bail!("No keyboards found, is this user in the input group?");
+        }
+        devices
+    } else {
+        paths
+            .iter()
+            .map(|path| {
+                Device::open(path)
+                    .map(|device| (device, path.display().to_string()))
+                    .with_context(|| format!("Could not open {}", path.display()))
+            })
+            .collect::<Result<_>>()?
+    };
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(
+            std::fs::File::create(path)
+                .with_context(|| format!("Could not create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout()),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    for (mut device, path) in devices {
+        if grab {
+            device
+                .grab()
+                .with_context(|| format!("Could not grab {path}"))?;
+        }
+        eprintln!("Recording {path} ({})", device.name().unwrap_or("unnamed"));
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            loop {
+                let events = match device.fetch_events() {
+                    Ok(events) => events,
+                    Err(e) => {
+                        eprintln!("Stopped reading {path}: {e}");
+                        return;
+                    }
+                };
+                for event in events {
+                    if event.event_type() != EventType::KEY {
+                        continue;
+                    }
+                    let Some(code) = OsCode::from_u16(event.code()) else {
+                        continue;
+                    };
+                    if tx.send((event.timestamp(), code, event.value())).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+    drop(tx);
+    eprintln!("Press LCtrl+Space+Esc to stop.");
+
+    let mut recorder = Recorder::new();
+    for (time, code, value) in rx {
+        if let Some(line) = recorder.line(time, code, value) {
+            writeln!(out, "{line}")?;
+            out.flush()?;
+        }
+        if recorder.should_stop() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_time_between_events() {
+        let start = SystemTime::UNIX_EPOCH;
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut recorder = Recorder::new();
+        let lines: Vec<_> = [
+            (at(1000), OsCode::KEY_LEFTSHIFT, 1),
+            (at(1010), OsCode::KEY_A, 1),
+            (at(1300), OsCode::KEY_A, 2),
+            (at(1350), OsCode::KEY_A, 0),
+            (at(1360), OsCode::KEY_LEFTSHIFT, 0),
+        ]
+        .into_iter()
+        .filter_map(|(time, code, value)| recorder.line(time, code, value))
+        .collect();
+        assert_eq!(
+            lines,
+            [
+                "d:lshift",
+                "t:10 d:a",
+                "t:290 r:a",
+                "t:50 u:a",
+                "t:10 u:lshift"
+            ]
+        );
+        assert!(!recorder.should_stop());
+    }
+
+    #[test]
+    fn stops_on_emergency_exit_keys() {
+        let mut recorder = Recorder::new();
+        for code in STOP_KEYS {
+            assert!(!recorder.should_stop());
+            recorder.line(SystemTime::UNIX_EPOCH, code, 1);
+        }
+        assert!(recorder.should_stop());
+    }
+}
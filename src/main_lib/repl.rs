@@ -0,0 +1,198 @@
+//! `kanata repl`: runs a configuration on input typed in the simulator format, e.g.
+//! `d:lsft t:10 d:a u:a u:lsft`, and prints the outputs and the active layer after each line.
+
+use anyhow::{Result, anyhow, bail};
+use kanata_state_machine::engine::Engine;
+use kanata_state_machine::oskbd::{KeyEvent, KeyValue, OutputEvent};
+use kanata_state_machine::str_to_oscode;
+
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const HELP: &str = "\
+Enter inputs separated by spaces:
+  d:KEY  press KEY          (also press:, down:, ↓:)
+  u:KEY  release KEY        (also release:, up:, ↑:)
+  r:KEY  repeat KEY         (also repeat:, ⟳:)
+  t:MS   wait MS ms         (also tick:, 🕐:)
+Commands:
+  :reset  reload the configuration and restart the time
+  :help   show this help
+  :quit   exit (or end the input)";
+
+/// An engine together with the outputs it produced and the simulated time.
+struct Repl {
+    path: PathBuf,
+    engine: Engine,
+    outputs: Arc<Mutex<Vec<OutputEvent>>>,
+    /// Milliseconds since the engine was created.
+    now: u64,
+}
+
+impl Repl {
+    fn new(path: &Path) -> Result<Self> {
+        let outputs = Arc::new(Mutex::new(vec![]));
+        let sink_outputs = outputs.clone();
+        let engine = Engine::from_file(path, move |ev| {
+            sink_outputs
+                .lock()
+                .expect("output lock is not poisoned")
+                .push(ev)
+        })?;
+        Ok(Self {
+            path: path.to_owned(),
+            ..Self::from_engine(engine, outputs)
+        })
+    }
+
+    fn from_engine(engine: Engine, outputs: Arc<Mutex<Vec<OutputEvent>>>) -> Self {
+        Self {
+            path: PathBuf::new(),
+            engine,
+            outputs,
+            now: 0,
+        }
+    }
+
+    /// Runs one line of input and returns the lines to print: the outputs with their times,
+    /// followed by the active layer.
+    fn run_line(&mut self, line: &str) -> Result<Vec<String>> {
+        let mut printed = vec![];
+        for item in line.split_whitespace() {
+            let (kind, val) = split_item(item)
+                .ok_or_else(|| anyhow!("invalid item: {item}, expected e.g. d:a or t:10"))?;
+            match kind {
+                "tick" | "🕐" | "t" => {
+                    let ms = val
+                        .parse::<u64>()
+                        .map_err(|e| anyhow!("invalid tick {val}: {e}"))?;
+                    for _ in 0..ms {
+                        self.engine.tick(1)?;
+                        self.collect(&mut printed);
+                        self.now += 1;
+                    }
+                }
+                "press" | "↓" | "d" | "down" => self.input(val, KeyValue::Press)?,
+                "release" | "↑" | "u" | "up" => self.input(val, KeyValue::Release)?,
+                "repeat" | "⟳" | "r" => self.input(val, KeyValue::Repeat)?,
+                _ => bail!("invalid action: {kind}, enter :help to list them"),
+            }
+            self.collect(&mut printed);
+        }
+        let k = self.engine.kanata();
+        let layer = &k.layer_info[k.layout.b().current_layer()].name;
+        printed.push(format!("layer: {layer}"));
+        Ok(printed)
+    }
+
+    fn input(&mut self, key: &str, value: KeyValue) -> Result<()> {
+        let code = str_to_oscode(key).ok_or_else(|| anyhow!("unknown key: {key}"))?;
+        self.engine.handle_input(KeyEvent::new(code, value))
+    }
+
+    fn collect(&mut self, printed: &mut Vec<String>) {
+        let outputs = std::mem::take(&mut *self.outputs.lock().expect("not poisoned"));
+        for output in outputs {
+            printed.push(format!("{:>6}ms {}", self.now, describe(&output)));
+        }
+    }
+}
+
+/// Splits an input item into its kind and value, e.g. `d:a`, or `↓a` for which the `:` is optional.
+fn split_item(item: &str) -> Option<(&str, &str)> {
+    match item.split_once(':') {
+        Some(split) => Some(split),
+        None => ["🕐", "↓", "↑", "⟳"]
+            .into_iter()
+            .find_map(|kind| Some((kind, item.strip_prefix(kind)?))),
+    }
+}
+
+/// Runs the input of the simulator format on the configuration at `path` and returns the lines
+/// that the repl prints for it as one line, for `kanata test`.
+pub(crate) fn simulate_lines(path: &Path, input: &str) -> Result<Vec<String>> {
+    Repl::new(path)?.run_line(input)
+}
+
+/// Describes an output in a line, e.g. `press leftshift`.
+pub(crate) fn describe(output: &OutputEvent) -> String {
+    let json = output.to_json();
+    let Some(fields) = json.as_object() else {
+        return json.to_string();
+    };
+    let mut parts = vec![];
+    for (name, value) in fields {
+        match value {
+            serde_json::Value::String(s) if name == "kind" => parts.insert(0, s.clone()),
+            serde_json::Value::String(s) => parts.push(s.clone()),
+            value => parts.push(format!("{name}={value}")),
+        }
+    }
+    parts.join(" ")
+}
+
+/// Reads lines from stdin until it ends or `:quit` is entered.
+pub(crate) fn run(path: &Path) -> Result<()> {
+    let mut repl = Repl::new(path)?;
+    println!("Simulating {}. Enter :help for help.", path.display());
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            return Ok(());
+        };
+        let line = line?;
+        match line.trim() {
+            "" => continue,
+            ":quit" | ":q" | ":exit" => return Ok(()),
+            ":help" | ":h" => println!("{HELP}"),
+            ":reset" => match Repl::new(&repl.path) {
+                Ok(new) => {
+                    repl = new;
+                    println!("Reloaded {}.", path.display());
+                }
+                Err(e) => println!("error: {e}"),
+            },
+            line => match repl.run_line(line) {
+                Ok(printed) => printed.iter().for_each(|l| println!("{l}")),
+                Err(e) => println!("error: {e}"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prints_outputs_with_times_and_layer() {
+        let outputs = Arc::new(Mutex::new(vec![]));
+        let sink_outputs = outputs.clone();
+        let engine = Engine::new(
+            "(defsrc a b) (deflayer base (tap-hold 100 100 c (layer-while-held nav)) b)
+             (deflayer nav _ d)",
+            move |ev| sink_outputs.lock().unwrap().push(ev),
+        )
+        .unwrap();
+        let mut repl = Repl::from_engine(engine, outputs);
+        assert_eq!(
+            repl.run_line("d:a t:10 u:a t:200").unwrap(),
+            ["    10ms press c", "    16ms release c", "layer: base"]
+        );
+        assert_eq!(
+            repl.run_line("d:a t:150 d:b t:10").unwrap(),
+            ["   360ms press d", "layer: nav"]
+        );
+        assert_eq!(
+            repl.run_line("↑b 🕐5 ↓b 🕐:5").unwrap(),
+            ["   370ms release d", "   375ms press d", "layer: nav"]
+        );
+        assert!(repl.run_line("x:a").is_err());
+        assert!(repl.run_line("d:notakey").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! `--replay-crash-bundle`: runs the input events of a crash bundle on its configuration with
+//! simulated time and prints the outputs, so that a crash can be reproduced deterministically.
+
+use super::repl::describe;
+use anyhow::{Context, Result, anyhow, bail};
+use kanata_state_machine::engine::{Timed, simulate_with_files};
+use kanata_state_machine::oskbd::{KeyEvent, KeyValue};
+use kanata_state_machine::str_to_oscode;
+use rustc_hash::FxHashMap;
+use serde_json::Value;
+
+use std::path::Path;
+
+/// What is replayed of a bundle.
+struct Replay {
+    config: String,
+    /// The files that the configuration includes.
+    files: FxHashMap<String, String>,
+    inputs: Vec<Timed<KeyEvent>>,
+}
+
+fn read_bundle(bundle: &Value) -> Result<Replay> {
+    if bundle["keys_hashed"].as_bool() == Some(true) {
+        bail!("the keys of this bundle are hashed, so its events cannot be replayed");
+    }
+    let config = bundle["config"]["text"]
+        .as_str()
+        .ok_or_else(|| anyhow!("the bundle has no configuration"))?
+        .to_owned();
+    let files = bundle["config"]["files"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(name, text)| (name.clone(), text.as_str().unwrap_or_default().to_owned()))
+        .collect();
+    let mut inputs = vec![];
+    let mut start_us = None;
+    for event in bundle["events"].as_array().into_iter().flatten() {
+        let Some((t_us, event)) = input_event(event)? else {
+            continue;
+        };
+        let start_us = *start_us.get_or_insert(t_us);
+        inputs.push(Timed {
+            time: t_us.saturating_sub(start_us) / 1000,
+            event,
+        });
+    }
+    Ok(Replay {
+        config,
+        files,
+        inputs,
+    })
+}
+
+/// The time in microseconds and the key event of an `input` event of the trace format, or
+/// `None` for other events.
+pub(super) fn input_event(event: &Value) -> Result<Option<(u64, KeyEvent)>> {
+    if event["event"] != "input" {
+        return Ok(None);
+    }
+    let (Some(t_us), Some(key), Some(value)) = (
+        event["t_us"].as_u64(),
+        event["key"].as_str(),
+        event["value"].as_str(),
+    ) else {
+        bail!("invalid input event: {event}");
+    };
+    let code = str_to_oscode(key).ok_or_else(|| anyhow!("unknown key: {key}"))?;
+    let value = match value {
+        "press" => KeyValue::Press,
+        "release" => KeyValue::Release,
+        "repeat" => KeyValue::Repeat,
+        "tap" => KeyValue::Tap,
+        _ => return Ok(None),
+    };
+    Ok(Some((t_us, KeyEvent::new(code, value))))
+}
+
+pub(crate) fn replay(path: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read the crash bundle {}", path.display()))?;
+    let bundle: Value = serde_json::from_str(&text)
+        .with_context(|| format!("{} is not a crash bundle", path.display()))?;
+    let Replay {
+        config,
+        files,
+        inputs,
+    } = read_bundle(&bundle)?;
+    println!(
+        "Replaying {} input events of kanata v{}, which stopped with: {}",
+        inputs.len(),
+        bundle["kanata_version"].as_str().unwrap_or("?"),
+        bundle["reason"].as_str().unwrap_or("?"),
+    );
+    for output in simulate_with_files(&config, files, &inputs)? {
+        println!("{:>6}ms {}", output.time, describe(&output.event));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_inputs_relative_to_the_first() {
+        let bundle = serde_json::json!({
+            "config": { "text": "(defsrc a) (deflayer base b)", "files": { "x.kbd": "" } },
+            "keys_hashed": false,
+            "events": [
+                { "t_us": 1_500, "event": "output", "key": "b", "value": "press" },
+                { "t_us": 2_000, "event": "input", "key": "a", "value": "press" },
+                { "t_us": 52_999, "event": "input", "key": "a", "value": "release" },
+            ],
+        });
+        let Replay {
+            config,
+            files,
+            inputs,
+        } = read_bundle(&bundle).unwrap();
+        assert_eq!(config, "(defsrc a) (deflayer base b)");
+        assert_eq!(files.get("x.kbd").map(String::as_str), Some(""));
+        let a = str_to_oscode("a").unwrap();
+        let inputs: Vec<_> = inputs
+            .iter()
+            .map(|input| (input.time, input.event.code, input.event.value))
+            .collect();
+        assert_eq!(
+            inputs,
+            [(0, a, KeyValue::Press), (50, a, KeyValue::Release)]
+        );
+
+        let hashed = serde_json::json!({ "keys_hashed": true });
+        assert!(read_bundle(&hashed).is_err());
+    }
+}
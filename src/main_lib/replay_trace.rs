@@ -0,0 +1,49 @@
+//! `--replay-trace`: runs the input events of an event trace on a configuration with the virtual
+//! clock, which is driven by the timestamps of the events instead of the wall clock, and prints
+//! the outputs. Every run of a trace prints the same, as does the event trace of the replay, so
+//! the outputs of timing-sensitive configurations can be compared between versions.
+
+use super::repl::describe;
+use super::shadow::read_trace;
+use anyhow::{Context, Result};
+use kanata_state_machine::engine::simulate_file;
+
+use std::path::Path;
+
+/// Returns the lines to print: the outputs with the virtual time at which they happened.
+fn replay_lines(cfg: &Path, text: &str) -> Result<Vec<String>> {
+    let trace = read_trace(text)?;
+    Ok(simulate_file(cfg, &trace.inputs)?
+        .iter()
+        .map(|output| format!("{:>6}ms {}", output.time, describe(&output.event)))
+        .collect())
+}
+
+pub(crate) fn replay(cfg: &Path, trace: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(trace)
+        .with_context(|| format!("could not read the event trace {}", trace.display()))?;
+    for line in replay_lines(cfg, &text)? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_inputs_at_the_times_of_the_trace() {
+        let cfg = std::env::temp_dir().join(format!("kanata-replay-{}.kbd", std::process::id()));
+        std::fs::write(&cfg, "(defsrc a) (deflayer base (tap-hold 100 100 c d))").unwrap();
+        let trace = r#"
+{"t_us":5000,"event":"input","key":"a","value":"press"}
+{"t_us":5100,"event":"output","key":"c","value":"press"}
+{"t_us":155999,"event":"input","key":"a","value":"release"}
+"#;
+        let lines = replay_lines(&cfg, trace).unwrap();
+        assert_eq!(lines, ["   100ms press d", "   150ms release d"]);
+        assert_eq!(replay_lines(&cfg, trace).unwrap(), lines);
+        std::fs::remove_file(cfg).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+//! `--shadow-trace`: runs the input events of an event trace, written while another
+//! configuration was active, on a candidate configuration with simulated time, and prints every
+//! input after which the candidate's key outputs differ from the outputs in the trace.
+//!
+//! This validates a changed configuration against real typing before switching to it.
+
+use super::replay_crash::input_event;
+use anyhow::{Context, Result, bail};
+use kanata_state_machine::engine::{Engine, SIMULATION_SETTLE_MS, Timed};
+use kanata_state_machine::oskbd::{KeyEvent, KeyValue, OutputEvent};
+use kanata_state_machine::{OsCode, oscode_to_str};
+use serde_json::Value;
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The inputs of a trace and the key outputs that followed each of them.
+pub(super) struct Trace {
+    pub(super) inputs: Vec<Timed<KeyEvent>>,
+    outputs: Vec<Vec<String>>,
+}
+
+pub(super) fn read_trace(text: &str) -> Result<Trace> {
+    let mut trace = Trace {
+        inputs: vec![],
+        outputs: vec![],
+    };
+    let mut start_us = None;
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: Value =
+            serde_json::from_str(line).with_context(|| format!("line {} is not JSON", i + 1))?;
+        if let Some((t_us, input)) = input_event(&event)? {
+            let start_us = *start_us.get_or_insert(t_us);
+            trace.inputs.push(Timed {
+                time: t_us.saturating_sub(start_us) / 1000,
+                event: input,
+            });
+            trace.outputs.push(vec![]);
+        } else if event["event"] == "output" {
+            // Outputs before the first input were caused by inputs that the trace doesn't have.
+            let (Some(outputs), Some(key), Some(value)) = (
+                trace.outputs.last_mut(),
+                event["key"].as_str(),
+                event["value"].as_str(),
+            ) else {
+                continue;
+            };
+            outputs.push(format!("{key} {value}"));
+        }
+    }
+    Ok(trace)
+}
+
+fn key_output(output: &OutputEvent) -> Option<String> {
+    let OutputEvent::Key { code, value } = output else {
+        return None;
+    };
+    let key = oscode_to_str(*code)
+        .map(str::to_owned)
+        .unwrap_or_else(|| OsCode::to_string(code).to_lowercase());
+    Some(format!("{key} {}", value_name(*value)))
+}
+
+fn value_name(value: KeyValue) -> &'static str {
+    match value {
+        KeyValue::Press => "press",
+        KeyValue::Release => "release",
+        KeyValue::Repeat => "repeat",
+        KeyValue::Tap => "tap",
+        KeyValue::WakeUp => "wakeup",
+    }
+}
+
+type Sink = Box<dyn FnMut(OutputEvent) + Send>;
+
+/// Runs the inputs on the configuration and returns the key outputs that followed each input.
+fn shadow_outputs(
+    engine_for: impl FnOnce(Sink) -> Result<Engine>,
+    inputs: &[Timed<KeyEvent>],
+) -> Result<Vec<Vec<String>>> {
+    let pending = Arc::new(Mutex::new(vec![]));
+    let sink_pending = pending.clone();
+    let mut engine = engine_for(Box::new(move |ev| {
+        sink_pending
+            .lock()
+            .expect("output lock is not poisoned")
+            .push(ev)
+    }))?;
+    let mut outputs: Vec<Vec<String>> = vec![];
+    let collect = |outputs: &mut Vec<Vec<String>>| {
+        let mut pending = pending.lock().expect("output lock is not poisoned");
+        if let Some(last) = outputs.last_mut() {
+            last.extend(pending.iter().filter_map(key_output));
+        }
+        pending.clear();
+    };
+    let mut now = 0;
+    for input in inputs {
+        if input.time < now {
+            bail!(
+                "input at {}ms is before the previous input at {now}ms",
+                input.time
+            );
+        }
+        while now < input.time {
+            let idle = engine.tick(1)?;
+            collect(&mut outputs);
+            now += 1;
+            // A day of typing is mostly idle, which doesn't need to be simulated.
+            if idle {
+                now = input.time;
+            }
+        }
+        outputs.push(vec![]);
+        engine.handle_input(input.event)?;
+        collect(&mut outputs);
+    }
+    for _ in 0..SIMULATION_SETTLE_MS {
+        let idle = engine.tick(1)?;
+        collect(&mut outputs);
+        if idle {
+            break;
+        }
+    }
+    Ok(outputs)
+}
+
+/// Prints the differences and returns whether there were any.
+pub(crate) fn run(cfg: &Path, trace: &Path) -> Result<bool> {
+    let text = std::fs::read_to_string(trace)
+        .with_context(|| format!("could not read the event trace {}", trace.display()))?;
+    let Trace { inputs, outputs } = read_trace(&text)?;
+    println!(
+        "Running {} input events of {} on {}",
+        inputs.len(),
+        trace.display(),
+        cfg.display()
+    );
+    let shadow = shadow_outputs(|sink| Engine::from_file(cfg, sink), &inputs)?;
+    let mut differences = 0;
+    for ((input, active), shadow) in inputs.iter().zip(&outputs).zip(&shadow) {
+        if active == shadow {
+            continue;
+        }
+        differences += 1;
+        let key = oscode_to_str(input.event.code).unwrap_or("?");
+        println!(
+            "{:>9}ms after {key} {}:\n    active:    {}\n    candidate: {}",
+            input.time,
+            value_name(input.event.value),
+            active.join(", "),
+            shadow.join(", "),
+        );
+    }
+    println!(
+        "{differences} of {} inputs had different outputs",
+        inputs.len()
+    );
+    Ok(differences > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shadow_outputs_differ_after_the_changed_key() {
+        let trace = r#"
+{"t_us":1000,"event":"input","key":"a","value":"press"}
+{"t_us":1010,"event":"output","key":"a","value":"press"}
+{"t_us":50000,"event":"input","key":"a","value":"release"}
+{"t_us":50010,"event":"output","key":"a","value":"release"}
+{"t_us":90000,"event":"input","key":"b","value":"press"}
+{"t_us":90010,"event":"output","key":"b","value":"press"}
+{"t_us":95000,"event":"input","key":"b","value":"release"}
+{"t_us":95010,"event":"output","key":"b","value":"release"}
+"#;
+        let Trace { inputs, outputs } = read_trace(trace).unwrap();
+        assert_eq!(inputs.len(), 4);
+        assert_eq!(inputs[1].time, 49);
+        let shadow = shadow_outputs(
+            |sink| Engine::new("(defsrc a b) (deflayer base a c)", sink),
+            &inputs,
+        )
+        .unwrap();
+        assert_eq!(outputs[..2], shadow[..2]);
+        assert_eq!(shadow[2], ["c press"]);
+        assert_eq!(outputs[2], ["b press"]);
+    }
+}
@@ -1,12 +1,12 @@
-use super::args::Args;
+use super::args::{Args, NgramKeys};
 use anyhow::{Context, Result, anyhow, bail};
 use clap::{CommandFactory, Parser, error::ErrorKind};
 use kanata_parser::cfg;
 use kanata_state_machine::gui::*;
 use kanata_state_machine::*;
 use simplelog::{
-    ColorChoice, CombinedLogger, Config, ConfigBuilder, LevelFilter, TermLogger, TerminalMode,
-    WriteLogger, format_description,
+    ColorChoice, CombinedLogger, Config, ConfigBuilder, LevelFilter, SharedLogger, TermLogger,
+    TerminalMode, WriteLogger, format_description,
 };
 use std::fs::File;
 
@@ -77,7 +77,7 @@ fn cli_init() -> Result<ValidatedArgs> {
         }
     }
 
-    let cfg_paths = args.cfg.unwrap_or_else(default_cfg);
+    let cfg_paths = args.cfg.clone().unwrap_or_else(default_cfg);
 
     let log_lvl = match (args.debug, args.trace) {
         (_, true) => LevelFilter::Trace,
@@ -93,26 +93,35 @@ fn cli_init() -> Result<ValidatedArgs> {
         version = 2,
         "[hour]:[minute]:[second].[subsecond digits:4]"
     ));
+    // The loggers let everything through and the max level filters, so that the level can be
+    // changed at runtime.
+    let mut loggers: Vec<Box<dyn SharedLogger>> =
+        vec![log_win::windbg_simple_combo(LevelFilter::Trace, noti_lvl)];
     if *IS_TERM {
-        CombinedLogger::init(vec![
-            TermLogger::new(
-                log_lvl,
-                log_cfg.build(),
-                TerminalMode::Mixed,
-                ColorChoice::AlwaysAnsi,
-            ),
-            log_win::windbg_simple_combo(log_lvl, noti_lvl),
-        ])
-        .expect("logger can init");
-    } else {
-        CombinedLogger::init(vec![log_win::windbg_simple_combo(log_lvl, noti_lvl)])
-            .expect("logger can init");
+        loggers.push(TermLogger::new(
+            LevelFilter::Trace,
+            log_cfg.build(),
+            TerminalMode::Mixed,
+            ColorChoice::AlwaysAnsi,
+        ));
+    }
+    if let Some(path) = &args.log_file {
+        let _ = GUI_LOG_FILE.set(path.clone());
+    }
+    if let Some(log_file) = super::log_file::from_args(&args)? {
+        loggers.push(WriteLogger::new(
+            LevelFilter::Trace,
+            log_cfg.build(),
+            log_file,
+        ));
     }
-    log::info!("kanata v{} starting", env!("CARGO_PKG_VERSION"));
+    CombinedLogger::init(loggers).expect("logger can init");
+    init_log_level(log_lvl, log::set_max_level);
+    tracing::info!("kanata v{} starting", env!("CARGO_PKG_VERSION"));
     #[cfg(all(not(feature = "interception_driver"), target_os = "windows"))]
-    log::info!("using LLHOOK+SendInput for keyboard IO");
+    tracing::info!("using LLHOOK+SendInput for keyboard IO");
     #[cfg(all(feature = "interception_driver", target_os = "windows"))]
-    log::info!("using the Interception driver for keyboard IO");
+    tracing::info!("using the Interception driver for keyboard IO");
 
     if let Some(config_file) = cfg_paths.first() {
         if !config_file.exists() {
@@ -126,22 +135,60 @@ fn cli_init() -> Result<ValidatedArgs> {
     }
 
     if args.check {
-        log::info!("validating config only and exiting");
+        tracing::info!("validating config only and exiting");
         let status = match cfg::new_from_file(&cfg_paths[0]) {
             Ok(_) => 0,
             Err(e) => {
-                log::error!("{e:?}");
+                tracing::error!("{e:?}");
                 1
             }
         };
         std::process::exit(status);
     }
 
+    if args.measure_latency {
+        enable_latency_measurement();
+    }
+
+    set_watchdog_timeout(args.watchdog_timeout_ms);
+
+    if let Some(keys) = args.ngram_stats {
+        enable_ngram_stats(NgramStatsConfig {
+            classes: keys == NgramKeys::Classes,
+            min_count: args.ngram_stats_min_count,
+            file: args.ngram_stats_file.clone(),
+        });
+    }
+
+    if args.crash_bundle {
+        let dir = args
+            .crash_bundle_dir
+            .clone()
+            .unwrap_or_else(default_crash_bundle_dir);
+        enable_crash_bundle(dir, args.crash_bundle_hash_keys);
+    }
+
+    if let Some(path) = &args.event_trace {
+        enable_event_trace(path.clone(), args.event_trace_max_events).map_err(|e| {
+            anyhow::anyhow!("could not create the event trace {}: {e}", path.display())
+        })?;
+    }
+
+    if !args.no_template_cache {
+        cfg::set_template_cache_dir(dirs::cache_dir().map(|dir| dir.join("kanata")));
+    }
+
+    #[cfg(feature = "plugins")]
+    if let Some(dir) = &args.plugin_dir {
+        load_plugins(dir);
+    }
+
     Ok(ValidatedArgs {
         paths: cfg_paths,
         #[cfg(feature = "tcp_server")]
         tcp_server_address: args.tcp_server_address,
         nodelay: args.nodelay,
+        watch: args.watch,
     })
 }
 
@@ -166,20 +213,26 @@ fn main_impl() -> Result<()> {
     // events, which it sends to the "processing loop". The processing loop handles keyboard events
     // while also maintaining `tick()` calls to keyberon.
 
-    let (tx, rx) = std::sync::mpsc::sync_channel(100);
+    let (tx, rx) = event_queue(100);
 
-    let (server, ntx, nrx) = if let Some(address) = {
+    let listeners: Vec<tcp_server::Listener> = {
         #[cfg(feature = "tcp_server")]
         {
-            args.tcp_server_address
+            let port = args.tcp_server_address.map(|a| a.into_inner().into());
+            port.into_iter().chain(Args::parse().listen).collect()
         }
         #[cfg(not(feature = "tcp_server"))]
         {
-            None::<SocketAddrWrapper>
+            Vec::new()
         }
-    } {
-        let mut server = TcpServer::new(address.into_inner(), tx.clone());
-        server.start(kanata_arc.clone());
+    };
+    let (server, ntx, nrx) = if !listeners.is_empty() {
+        let mut server = TcpServer::with_listeners(listeners, tx.clone());
+        #[cfg(feature = "tcp_server")]
+        {
+            server.audit_log = Args::parse().audit_log;
+        }
+        server.start(kanata_arc.clone())?;
         let (ntx, nrx) = std::sync::mpsc::sync_channel(100);
         (Some(server), Some(ntx), Some(nrx))
     } else {
@@ -192,6 +245,7 @@ fn main_impl() -> Result<()> {
     let gui_cfg_tx = ui.cfg_notice.sender(); // allows notifying GUI on config reloads
     let gui_err_tx = ui.err_notice.sender(); // allows notifying GUI on erorrs (from logger)
     let gui_exit_tx = ui.exit_notice.sender(); // allows notifying GUI on app quit
+    let gui_notify_tx = ui.notify_notice.sender(); // allows showing notifications of actions
     if GUI_TX.set(gui_tx).is_err() {
         warn!("Someone else set our ‘GUI_TX’");
     };
@@ -204,14 +258,24 @@ fn main_impl() -> Result<()> {
     if GUI_EXIT_TX.set(gui_exit_tx).is_err() {
         warn!("Someone else set our ‘GUI_EXIT_TX’");
     };
+    if GUI_NOTIFY_TX.set(gui_notify_tx).is_err() {
+        warn!("Someone else set our ‘GUI_NOTIFY_TX’");
+    };
     Kanata::start_processing_loop(kanata_arc.clone(), rx, ntx, args.nodelay);
 
+    if args.watch {
+        Kanata::start_cfg_watcher(kanata_arc.clone(), tx.clone());
+    }
+    Kanata::start_os_layout_watcher(kanata_arc.clone(), tx.clone());
+
     if let (Some(server), Some(nrx)) = (server, nrx) {
         #[allow(clippy::unit_arg)]
         Kanata::start_notification_loop(nrx, server.connections);
     }
 
-    Kanata::event_loop(kanata_arc, tx, ui)?;
+    Kanata::event_loop(kanata_arc, tx, ui).inspect_err(|e| {
+        write_crash_bundle(&format!("fatal error: {e:#}"));
+    })?;
 
     Ok(())
 }
@@ -220,7 +284,7 @@ pub fn lib_main_gui() {
     let _attach_console = *IS_CONSOLE;
     let ret = main_impl();
     if let Err(ref e) = ret {
-        log::error!("{e}\n");
+        tracing::error!("{e}\n");
     }
 
     unsafe {
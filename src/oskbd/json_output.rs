@@ -0,0 +1,231 @@
+//! An [`OutputSink`] that writes each output as a line of JSON, for running kanata without injecting
+//! OS events, e.g. in integration tests or to pipe outputs into other tools.
+
+use serde_json::{Value, json};
+use web_time::Instant;
+
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+use super::{KeyValue, OutputEvent, OutputSink};
+use kanata_parser::cfg::SoundCue;
+
+impl OutputEvent {
+    /// Returns the output as a JSON object whose `kind` is the kind of output, e.g.
+    /// `{"kind":"press","key":"a"}`.
+    pub fn to_json(&self) -> Value {
+        let lower = |s: String| s.to_lowercase();
+        match self {
+            Self::Key { code, value } => {
+                let kind = match value {
+                    KeyValue::Press => "press",
+                    KeyValue::Release => "release",
+                    KeyValue::Repeat => "repeat",
+                    KeyValue::Tap => "tap",
+                    KeyValue::WakeUp => "wakeup",
+                };
+                json!({ "kind": kind, "key": lower(code.to_string()) })
+            }
+            Self::Code { code, value } => {
+                json!({ "kind": "code", "code": code, "value": lower(format!("{value:?}")) })
+            }
+            Self::Unicode(c) => json!({ "kind": "unicode", "text": c.to_string() }),
+            Self::UnicodeStr(text) => json!({ "kind": "unicode", "text": text }),
+            Self::MousePress(btn) => {
+                json!({ "kind": "mouse_press", "button": lower(format!("{btn:?}")) })
+            }
+            Self::MouseRelease(btn) => {
+                json!({ "kind": "mouse_release", "button": lower(format!("{btn:?}")) })
+            }
+            Self::Scroll {
+                direction,
+                distance,
+            } => json!({
+                "kind": "scroll",
+                "direction": lower(format!("{direction:?}")),
+                "distance": distance,
+            }),
+            Self::MouseMove {
+                direction,
+                distance,
+            } => json!({
+                "kind": "mouse_move",
+                "direction": lower(format!("{direction:?}")),
+                "distance": distance,
+            }),
+            Self::MouseSet { x, y } => json!({ "kind": "mouse_set", "x": x, "y": y }),
+            Self::MouseWarp { monitor, x, y } => {
+                json!({ "kind": "mouse_warp", "monitor": monitor, "x": x, "y": y })
+            }
+            Self::Midi(msg) => json!({ "kind": "midi", "bytes": msg }),
+            Self::Obs(action) => {
+                json!({ "kind": "obs", "request": action.name(), "args": action.args() })
+            }
+            Self::OpenRgb(rgb) => json!({ "kind": "openrgb", "rgb": rgb }),
+            Self::Webhook { name, body } => {
+                json!({ "kind": "webhook", "name": name, "body": body })
+            }
+            Self::Notify { title, body } => {
+                json!({ "kind": "notify", "title": title, "body": body })
+            }
+            Self::Mpris(action) => {
+                json!({ "kind": "mpris", "command": action.command.name(), "player": action.player })
+            }
+            Self::OsLayout(layout) => json!({ "kind": "os_layout", "layout": layout }),
+            Self::Secret(name) => json!({ "kind": "secret", "name": name }),
+            Self::HidUsage { usage, value } => json!({
+                "kind": "hid_usage",
+                "page": usage.page_id(),
+                "usage": usage.usage,
+                "value": lower(format!("{value:?}")),
+            }),
+            Self::MouseToward(action) => json!({
+                "kind": "mouse_toward",
+                "monitor": action.monitor.map(|monitor| monitor + 1),
+                "x": f64::from(action.anchor_x) / 100.0,
+                "y": f64::from(action.anchor_y) / 100.0,
+                "percent": action.percent,
+            }),
+            Self::Sound(cue) => match cue {
+                SoundCue::Silent | SoundCue::Beep => json!({ "kind": "sound" }),
+                SoundCue::File(path) => json!({ "kind": "sound", "file": path }),
+            },
+        }
+    }
+}
+
+/// Where [`JsonOutput`] writes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonOutputTarget {
+    Stdout,
+    /// An address to connect to, e.g. `127.0.0.1:9000`.
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl FromStr for JsonOutputTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" || s == "stdout" {
+            return Ok(Self::Stdout);
+        }
+        if let Some(addr) = s.strip_prefix("tcp:") {
+            return Ok(Self::Tcp(addr.to_owned()));
+        }
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Self::Unix(path.into()));
+        }
+        Err(format!(
+            "expected -, tcp:HOST:PORT{}, found {s}",
+            if cfg!(unix) { " or unix:PATH" } else { "" }
+        ))
+    }
+}
+
+/// Writes outputs as lines of JSON, e.g. `{"kind":"press","key":"a","time_ms":1520}`, where
+/// `time_ms` is the number of milliseconds since the output was opened.
+pub struct JsonOutput {
+    writer: Box<dyn Write + Send>,
+    start: Instant,
+    failed: bool,
+}
+
+impl JsonOutput {
+    pub fn open(target: &JsonOutputTarget) -> io::Result<Self> {
+        let writer: Box<dyn Write + Send> = match target {
+            JsonOutputTarget::Stdout => Box::new(io::stdout()),
+            JsonOutputTarget::Tcp(addr) => {
+                let stream = std::net::TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            #[cfg(unix)]
+            JsonOutputTarget::Unix(path) => {
+                Box::new(std::os::unix::net::UnixStream::connect(path)?)
+            }
+        };
+        Ok(Self::new(writer))
+    }
+
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: Box::new(BufWriter::new(writer)),
+            start: Instant::now(),
+            failed: false,
+        }
+    }
+}
+
+impl OutputSink for JsonOutput {
+    fn output(&mut self, event: OutputEvent) {
+        let mut line = event.to_json();
+        line["time_ms"] = json!(self.start.elapsed().as_millis() as u64);
+        let res = serde_json::to_writer(&mut self.writer, &line)
+            .map_err(io::Error::from)
+            .and_then(|()| self.writer.write_all(b"\n"))
+            .and_then(|()| self.writer.flush());
+        match res {
+            Ok(()) => self.failed = false,
+            Err(e) if !self.failed => {
+                // Log once per outage instead of for every output.
+                tracing::error!("could not write JSON output: {e}");
+                self.failed = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::str_to_oscode;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let buf = Buf::default();
+        let mut out = JsonOutput::new(Box::new(buf.clone()));
+        out.output(OutputEvent::Key {
+            code: str_to_oscode("lsft").unwrap(),
+            value: KeyValue::Press,
+        });
+        out.output(OutputEvent::Unicode('é'));
+        let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["kind"], "press");
+        assert_eq!(lines[0]["key"], "leftshift");
+        assert!(lines[0]["time_ms"].is_u64());
+        assert_eq!(lines[1]["text"], "é");
+    }
+
+    #[test]
+    fn parse_target() {
+        assert_eq!("-".parse(), Ok(JsonOutputTarget::Stdout));
+        assert_eq!(
+            "tcp:127.0.0.1:9000".parse(),
+            Ok(JsonOutputTarget::Tcp("127.0.0.1:9000".into()))
+        );
+        assert!("127.0.0.1:9000".parse::<JsonOutputTarget>().is_err());
+    }
+}
@@ -3,7 +3,10 @@
 #![cfg_attr(feature = "simulated_output", allow(dead_code, unused_imports))]
 
 pub use evdev::BusType;
-use evdev::{Device, EventType, InputEvent, KeyCode, PropType, RelativeAxisCode, uinput};
+use evdev::{
+    AbsInfo, AbsoluteAxisCode, Device, EventType, InputEvent, KeyCode, PropType, RelativeAxisCode,
+    UinputAbsSetup, uinput,
+};
 use inotify::{Inotify, WatchMask};
 use mio::{Events, Interest, Poll, Token, unix::SourceFd};
 use nix::ioctl_read_buf;
@@ -16,15 +19,22 @@ use signal_hook::{
 use std::convert::TryFrom;
 use std::fs;
 use std::io;
+use std::num::NonZeroU8;
 use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
 
 use super::*;
-use crate::{kanata::CalculatedMouseMove, oskbd::KeyEvent};
-use kanata_parser::cfg::DeviceDetectMode;
+use crate::{
+    kanata::{CalculatedMouseMove, DeviceChange},
+    oskbd::KeyEvent,
+};
 use kanata_parser::cfg::UnicodeTermination;
+use kanata_parser::cfg::{
+    CfgLinuxOptions, DeviceDetectMode, InputDeviceMatcher, LinuxCfgInputBackend,
+    LinuxCfgOutputBackend, LockLeds,
+};
 use kanata_parser::custom_action::*;
 use kanata_parser::keys::*;
 
@@ -35,27 +45,83 @@ pub struct KbdIn {
     poll: Poll,
     events: Events,
     token_counter: usize,
-    /// stored to prevent dropping
-    _inotify: Inotify,
+    /// stored to prevent dropping. Libinput watches the devices itself.
+    _inotify: Option<Inotify>,
+    /// With `linux-input-backend libinput`, which reads all devices instead of `devices`.
+    libinput: Option<libinput::Libinput>,
     include_names: Option<Vec<String>>,
     exclude_names: Option<Vec<String>>,
     device_detect_mode: DeviceDetectMode,
+    /// With `linux-seat`, only the devices of this seat are discovered.
+    seat: Option<String>,
+    /// False while devices are released for emergency passthrough.
+    grabbed: bool,
+    /// Devices registered or removed since the last [`KbdIn::take_device_changes`].
+    device_changes: Vec<DeviceChange>,
+    /// The devices of `definputdevices`, which registered devices get the ID of.
+    input_devices: Vec<(NonZeroU8, InputDeviceMatcher)>,
+    device_ids: HashMap<Token, NonZeroU8>,
+    /// Reused to merge the events of several devices together with their device IDs.
+    merge_buf: Vec<(InputEvent, Option<NonZeroU8>)>,
 }
 
 const INOTIFY_TOKEN_VALUE: usize = 0;
 const INOTIFY_TOKEN: Token = Token(INOTIFY_TOKEN_VALUE);
+const LIBINPUT_TOKEN: Token = Token(usize::MAX);
 
 pub static WAIT_DEVICE_MS: AtomicU64 = AtomicU64::new(200);
 
 impl KbdIn {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         dev_paths: &[String],
         continue_if_no_devices: bool,
         include_names: Option<Vec<String>>,
         exclude_names: Option<Vec<String>>,
         device_detect_mode: DeviceDetectMode,
+        seat: Option<String>,
+        input_backend: LinuxCfgInputBackend,
+        input_devices: Vec<(NonZeroU8, InputDeviceMatcher)>,
     ) -> Result<Self, io::Error> {
         let poll = Poll::new()?;
+        if input_backend == LinuxCfgInputBackend::Libinput {
+            let selection = libinput::Selection {
+                include_names: include_names.clone(),
+                exclude_names: exclude_names.clone(),
+                detect_mode: device_detect_mode,
+            };
+            let libinput = libinput::Libinput::new(dev_paths, selection, seat.as_deref())?;
+            if libinput.device_count() == 0 {
+                if continue_if_no_devices {
+                    tracing::warn!("no keyboard devices found; kanata is waiting");
+                } else {
+                    return Err(no_devices_error());
+                }
+            }
+            poll.registry().register(
+                &mut SourceFd(&libinput.fd()),
+                LIBINPUT_TOKEN,
+                Interest::READABLE,
+            )?;
+            return Ok(Self {
+                poll,
+                missing_device_paths: None,
+                _inotify: None,
+                libinput: Some(libinput),
+                events: Events::with_capacity(32),
+                devices: HashMap::default(),
+                token_counter: INOTIFY_TOKEN_VALUE + 1,
+                include_names,
+                exclude_names,
+                device_detect_mode,
+                seat,
+                grabbed: true,
+                device_changes: vec![],
+                input_devices,
+                device_ids: HashMap::default(),
+                merge_buf: vec![],
+            });
+        }
 
         let mut missing_device_paths = None;
         let devices = if !dev_paths.is_empty() {
@@ -70,23 +136,19 @@ impl KbdIn {
                 exclude_names.as_deref(),
                 device_detect_mode,
             )
+            .into_iter()
+            .filter(|(_, path)| is_on_seat(path, seat.as_deref()))
+            .collect()
         };
         if devices.is_empty() {
             if continue_if_no_devices {
-                log::warn!("no keyboard devices found; kanata is waiting");
+                tracing::warn!("no keyboard devices found; kanata is waiting");
             } else {
-                return Err(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "No keyboard devices were found. Try:\n\
-                     1. Run 'kanata --list' to see available devices\n\
-                     2. Check permissions: sudo usermod -a -G input $USER\n\
-                     3. Log out and back in for group changes to take effect\n\
-                     4. Ensure devices are connected and working",
-                ));
+                return Err(no_devices_error());
             }
         }
         let _inotify = watch_devinput().map_err(|e| {
-            log::error!("failed to watch files: {e:?}");
+            tracing::error!("failed to watch files: {e:?}");
             e
         })?;
         poll.registry().register(
@@ -98,29 +160,38 @@ impl KbdIn {
         let mut kbdin = Self {
             poll,
             missing_device_paths,
-            _inotify,
+            _inotify: Some(_inotify),
+            libinput: None,
             events: Events::with_capacity(32),
             devices: HashMap::default(),
             token_counter: INOTIFY_TOKEN_VALUE + 1,
             include_names,
             exclude_names,
             device_detect_mode,
+            seat,
+            grabbed: true,
+            device_changes: vec![],
+            input_devices,
+            device_ids: HashMap::default(),
+            merge_buf: vec![],
         };
 
         for (device, dev_path) in devices.into_iter() {
             if let Err(e) = kbdin.register_device(device, dev_path.clone()) {
-                log::warn!("found device {dev_path} but could not register it {e:?}");
+                tracing::warn!("found device {dev_path} but could not register it {e:?}");
                 if let Some(ref mut missing) = kbdin.missing_device_paths {
                     missing.push(dev_path);
                 }
             }
         }
+        // Only the devices that come and go later are changes.
+        kbdin.device_changes.clear();
 
         Ok(kbdin)
     }
 
     fn register_device(&mut self, mut dev: Device, path: String) -> Result<(), io::Error> {
-        log::info!("registering {path}: {:?}", dev.name().unwrap_or(""));
+        tracing::info!("registering {path}: {:?}", dev.name().unwrap_or(""));
         wait_for_all_keys_unpressed(&dev)?;
         // NOTE: This grab-ungrab-grab sequence magically fixes an issue with a Lenovo Yoga
         // trackpad not working. No idea why this works.
@@ -134,29 +205,122 @@ impl KbdIn {
         self.poll
             .registry()
             .register(&mut SourceFd(&fd), tok, Interest::READABLE)?;
+        if !self.grabbed {
+            dev.ungrab()?;
+        }
+        if let Some(id) = device_id_of(&dev, &self.input_devices) {
+            tracing::info!("definputdevices: device ID {id} matched {path}");
+            self.device_ids.insert(tok, id);
+        }
+        leds::add_device(&path);
+        self.device_changes.push(DeviceChange {
+            path: path.clone(),
+            name: dev.name().unwrap_or_default().to_owned(),
+            connected: true,
+        });
         self.devices.insert(tok, (dev, path));
         Ok(())
     }
 
-    pub fn read(&mut self) -> Result<Vec<InputEvent>, io::Error> {
-        let mut input_events = vec![];
+    /// The devices that were registered or removed since the last call.
+    pub fn take_device_changes(&mut self) -> Vec<DeviceChange> {
+        std::mem::take(&mut self.device_changes)
+    }
+
+    /// Removes a device that disconnected. Its keys that were pressed are released in
+    /// `input_events`, unless another device holds them too, so that a keyboard that e.g. goes
+    /// to sleep while a modifier is held doesn't leave it held.
+    fn remove_device(&mut self, token: Token, input_events: &mut Vec<InputEvent>) {
+        let Some((device, path)) = self.devices.remove(&token) else {
+            return;
+        };
+        tracing::warn!("removing kbd device: {path}");
+        self.device_ids.remove(&token);
+        if let Some(pressed) = device.cached_state().key_vals() {
+            let held_elsewhere = |key: KeyCode| {
+                self.devices.values().any(|(other, _)| {
+                    other
+                        .cached_state()
+                        .key_vals()
+                        .is_some_and(|keys| keys.contains(key))
+                })
+            };
+            let releases: Vec<InputEvent> = pressed
+                .iter()
+                .filter(|key| !held_elsewhere(*key))
+                .map(|key| InputEvent::new(EventType::KEY.0, key.code(), 0))
+                .collect();
+            if !releases.is_empty() {
+                tracing::info!("releasing the keys held on {path}: {releases:?}");
+                input_events.extend(releases);
+                input_events.push(InputEvent::new(EventType::SYNCHRONIZATION.0, 0, 0));
+            }
+        }
+        leds::remove_device(&path);
+        self.device_changes.push(DeviceChange {
+            name: device.name().unwrap_or_default().to_owned(),
+            path: path.clone(),
+            connected: false,
+        });
+        if let Some(ref mut missing) = self.missing_device_paths {
+            missing.push(path);
+        }
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        self.grabbed
+    }
+
+    /// Grabs or releases all registered devices. While released, events are still read but
+    /// the OS receives them too. Grabbing waits for all keys to be released first.
+    pub fn set_grabbed(&mut self, grab: bool) {
+        self.grabbed = grab;
+        if let Some(libinput) = &self.libinput {
+            libinput.set_grabbed(grab);
+        }
+        for (dev, path) in self.devices.values_mut() {
+            let res = match grab {
+                true => wait_for_all_keys_unpressed(dev).and_then(|_| dev.grab()),
+                false => dev.ungrab(),
+            };
+            if let Err(e) = res {
+                tracing::warn!("failed to change grab of {path}: {e:?}");
+            }
+        }
+    }
+
+    /// Waits for input and replaces the content of `input_events` with it, and the content of
+    /// `device_ids` with the `definputdevices` ID of the device of each event. The buffers are
+    /// reused between reads so that reading does not allocate.
+    pub fn read(
+        &mut self,
+        input_events: &mut Vec<InputEvent>,
+        device_ids: &mut Vec<Option<NonZeroU8>>,
+    ) -> Result<(), io::Error> {
+        input_events.clear();
+        device_ids.clear();
         loop {
-            log::trace!("polling");
+            tracing::trace!("polling");
 
             if let Err(e) = self.poll.poll(&mut self.events, None) {
-                log::error!("failed poll: {:?}", e);
-                return Ok(vec![]);
+                tracing::error!("failed poll: {:?}", e);
+                return Ok(());
             }
 
             const EVENT_LIMIT: usize = 48;
 
             let mut do_rediscover = false;
+            let mut reading_devices = 0;
+            let mut removed_devices = vec![];
             for event in &self.events {
                 if let Some((device, _)) = self.devices.get_mut(&event.token()) {
+                    reading_devices += 1;
+                    let device_id = self.device_ids.get(&event.token()).copied();
                     if let Err(e) = device.fetch_events().map(|evs| {
-                        evs.into_iter()
-                            .take(EVENT_LIMIT)
-                            .for_each(|ev| input_events.push(ev))
+                        evs.into_iter().take(EVENT_LIMIT).for_each(|ev| {
+                            input_events.push(ev);
+                            device_ids.push(device_id);
+                        })
                     }) {
                         // Currently the kind() is uncategorized... not helpful, need to match
                         // on os error. code 19 is ENODEV, "no such device".
@@ -165,31 +329,58 @@ impl KbdIn {
                                 self.poll
                                     .registry()
                                     .deregister(&mut SourceFd(&device.as_raw_fd()))?;
-                                if let Some((_, path)) = self.devices.remove(&event.token()) {
-                                    log::warn!("removing kbd device: {path}");
-                                    if let Some(ref mut missing) = self.missing_device_paths {
-                                        missing.push(path);
-                                    }
-                                }
+                                removed_devices.push(event.token());
                             }
                             _ => {
-                                log::error!("failed fetch events due to {e}, kind: {}", e.kind());
+                                tracing::error!(
+                                    "failed fetch events due to {e}, kind: {}",
+                                    e.kind()
+                                );
                                 return Err(e);
                             }
                         };
                     }
                 } else if event.token() == INOTIFY_TOKEN {
                     do_rediscover = true;
+                } else if event.token() == LIBINPUT_TOKEN
+                    && let Some(libinput) = &mut self.libinput
+                {
+                    libinput.read(input_events, &mut self.device_changes)?;
+                    device_ids.resize(input_events.len(), None);
                 } else {
                     panic!("encountered unexpected epoll event {event:?}");
                 }
             }
+            for token in removed_devices {
+                self.remove_device(token, input_events);
+            }
+            // Libinput and the releases of removed devices are not of a device with an ID.
+            device_ids.resize(input_events.len(), None);
             if do_rediscover {
-                log::info!("watch found file changes, looking for new devices");
+                tracing::info!("watch found file changes, looking for new devices");
                 self.rediscover_devices()?;
             }
+            if reading_devices > 1 {
+                // The events of each device are in order, but devices are read one after another.
+                // Merge them by the kernel timestamps so that keys pressed together on different
+                // devices, e.g. a foot pedal and a keyboard, reach chords and tap-hold in the
+                // order they were pressed. The sort is stable, so the SYN_REPORT of a frame stays
+                // after its events.
+                if device_ids.iter().all(Option::is_none) {
+                    input_events.sort_by_key(|ev| ev.timestamp());
+                } else {
+                    self.merge_buf.clear();
+                    self.merge_buf
+                        .extend(input_events.drain(..).zip(device_ids.drain(..)));
+                    self.merge_buf.sort_by_key(|(ev, _)| ev.timestamp());
+                    for (ev, device_id) in self.merge_buf.drain(..) {
+                        input_events.push(ev);
+                        device_ids.push(device_id);
+                    }
+                }
+            }
             if !input_events.is_empty() {
-                return Ok(input_events);
+                return Ok(());
             }
         }
     }
@@ -199,10 +390,10 @@ impl KbdIn {
         let mut paths_registered = vec![];
         if let Some(ref mut missing) = self.missing_device_paths {
             if missing.is_empty() {
-                log::info!("no devices are missing, doing nothing");
+                tracing::info!("no devices are missing, doing nothing");
                 return Ok(());
             }
-            log::info!("checking for {missing:?}");
+            tracing::info!("checking for {missing:?}");
             let discovered_devices = missing
                 .iter()
                 .filter_map(|dev_path| {
@@ -218,7 +409,7 @@ impl KbdIn {
                 .collect::<Vec<(_, _)>>();
             for (device, dev_path) in discovered_devices {
                 if let Err(e) = self.register_device(device, dev_path.clone()) {
-                    log::warn!("found device {dev_path} but could not register it {e:?}");
+                    tracing::warn!("found device {dev_path} but could not register it {e:?}");
                 } else {
                     paths_registered.push(dev_path);
                 }
@@ -227,7 +418,7 @@ impl KbdIn {
         if let Some(ref mut missing) = self.missing_device_paths {
             missing.retain(|path| !paths_registered.contains(path));
         } else {
-            log::info!("sleeping for a moment to let devices become ready");
+            tracing::info!("sleeping for a moment to let devices become ready");
             std::thread::sleep(std::time::Duration::from_millis(
                 WAIT_DEVICE_MS.load(Ordering::SeqCst),
             ));
@@ -238,10 +429,11 @@ impl KbdIn {
             )
             .into_iter()
             .try_for_each(|(dev, path)| {
-                if !self
-                    .devices
-                    .values()
-                    .any(|(_, registered_path)| &path == registered_path)
+                if is_on_seat(&path, self.seat.as_deref())
+                    && !self
+                        .devices
+                        .values()
+                        .any(|(_, registered_path)| &path == registered_path)
                 {
                     self.register_device(dev, path)
                 } else {
@@ -253,6 +445,48 @@ impl KbdIn {
     }
 }
 
+/// The ID of the first device of `definputdevices` that matches the device. Devices on Linux have
+/// no hash, so a device with a hash never matches.
+fn device_id_of(
+    device: &Device,
+    input_devices: &[(NonZeroU8, InputDeviceMatcher)],
+) -> Option<NonZeroU8> {
+    let name = device.name().unwrap_or_default();
+    let input_id = device.input_id();
+    input_devices
+        .iter()
+        .find(|(_, matcher)| {
+            matches_input_device(matcher, name, input_id.vendor(), input_id.product())
+        })
+        .map(|(id, _)| *id)
+}
+
+fn matches_input_device(
+    matcher: &InputDeviceMatcher,
+    name: &str,
+    vendor_id: u16,
+    product_id: u16,
+) -> bool {
+    matcher.hash.is_none()
+        && matcher
+            .name
+            .as_ref()
+            .is_none_or(|n| name.contains(n.as_str()))
+        && matcher.vendor_id.is_none_or(|v| v == vendor_id)
+        && matcher.product_id.is_none_or(|p| p == product_id)
+}
+
+fn no_devices_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        "No keyboard devices were found. Try:\n\
+         1. Run 'kanata --list' to see available devices\n\
+         2. Check permissions: sudo usermod -a -G input $USER\n\
+         3. Log out and back in for group changes to take effect\n\
+         4. Ensure devices are connected and working",
+    )
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum DeviceType {
     Keyboard,
@@ -261,8 +495,22 @@ enum DeviceType {
     Other,
 }
 
+/// The names of the uinput devices of kanata, which must not be read as input devices.
+static OUTPUT_DEVICE_NAMES: parking_lot::Mutex<Vec<String>> = parking_lot::Mutex::new(Vec::new());
+
+/// Added to the name of the output device for the name of the absolute pointer device.
+const ABSOLUTE_POINTER_SUFFIX: &str = " absolute pointer";
+
+/// The largest coordinate of the absolute pointer device, as with `setmouse` on Windows.
+const ABSOLUTE_POINTER_MAX: i32 = 65535;
+
 pub fn is_input_device(device: &Device, detect_mode: DeviceDetectMode) -> bool {
-    if device.name() == Some("kanata") {
+    let output_name = device
+        .name()
+        .map(|name| name.strip_suffix(ABSOLUTE_POINTER_SUFFIX).unwrap_or(name));
+    if output_name == Some("kanata")
+        || output_name.is_some_and(|name| OUTPUT_DEVICE_NAMES.lock().iter().any(|n| n == name))
+    {
         return false;
     }
     let is_keyboard = device.supported_keys().is_some_and(has_keyboard_keys);
@@ -281,7 +529,7 @@ pub fn is_input_device(device: &Device, detect_mode: DeviceDetectMode) -> bool {
         | (DeviceDetectMode::KeyboardMice, DeviceType::Keyboard | DeviceType::KeyboardMouse)
         | (DeviceDetectMode::KeyboardOnly, DeviceType::Keyboard) => {
             let use_input = true;
-            log::debug!(
+            tracing::debug!(
                 "Use for input autodetect: {use_input}. detect type {:?}; device type {:?}, device name: {}",
                 detect_mode,
                 device_type,
@@ -290,7 +538,7 @@ pub fn is_input_device(device: &Device, detect_mode: DeviceDetectMode) -> bool {
             use_input
         }
         (_, DeviceType::Other) => {
-            log::debug!(
+            tracing::debug!(
                 "Use for input autodetect: false. Non-input device: {}",
                 device_name,
             );
@@ -298,7 +546,7 @@ pub fn is_input_device(device: &Device, detect_mode: DeviceDetectMode) -> bool {
         }
         _ => {
             let use_input = false;
-            log::debug!(
+            tracing::debug!(
                 "Use for input autodetect: {use_input}. detect type {:?}; device type {:?}, device name: {}",
                 detect_mode,
                 device_type,
@@ -323,6 +571,14 @@ fn has_keyboard_keys(keys: &evdev::AttributeSetRef<KeyCode>) -> bool {
 impl TryFrom<InputEvent> for KeyEvent {
     type Error = ();
     fn try_from(item: InputEvent) -> Result<Self, Self::Error> {
+        let mut event = Self::key_or_scroll(item)?;
+        event.time = Some(item.timestamp());
+        Ok(event)
+    }
+}
+
+impl KeyEvent {
+    fn key_or_scroll(item: InputEvent) -> Result<Self, ()> {
         use OsCode::*;
         match item.destructure() {
             evdev::EventSummary::Key(_, k, _) => Ok(Self::new(
@@ -363,12 +619,130 @@ impl From<KeyEvent> for InputEvent {
 
 use std::cell::Cell;
 
+mod leds;
+pub use leds::set_layer_leds;
+mod libinput;
+#[cfg(all(not(feature = "simulated_output"), not(feature = "passthru_ahk")))]
+mod remote;
+#[cfg(feature = "x11_xtest")]
+mod xtest;
+
+/// Converts the time of an input event, which evdev reports on the realtime clock, to the
+/// monotonic clock that uinput takes the time of events on.
+#[cfg(all(not(feature = "simulated_output"), not(feature = "passthru_ahk")))]
+fn monotonic_timeval(time: std::time::SystemTime) -> Option<libc::timeval> {
+    let age = std::time::SystemTime::now().duration_since(time).ok()?;
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_gettime only writes to the timespec.
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        return None;
+    }
+    let at = std::time::Duration::new(now.tv_sec as u64, now.tv_nsec as u32).checked_sub(age)?;
+    Some(libc::timeval {
+        tv_sec: at.as_secs() as libc::time_t,
+        tv_usec: at.subsec_micros() as libc::suseconds_t,
+    })
+}
+
+/// Writes the events to the output device of the route of `routes`, or to `device` without one.
+#[cfg(all(not(feature = "simulated_output"), not(feature = "passthru_ahk")))]
+fn emit_to(
+    device: &mut OutputDevice,
+    routes: &mut [(NonZeroU8, uinput::VirtualDevice)],
+    route: Option<NonZeroU8>,
+    events: &[InputEvent],
+) -> Result<(), io::Error> {
+    match route.and_then(|route| routes.iter_mut().find(|(id, _)| *id == route)) {
+        Some((_, routed)) => emit_frame(routed, events),
+        None => device.emit(events),
+    }
+}
+
+/// Writes the events to the uinput device as one frame, see [`write_frame`].
+#[cfg(all(not(feature = "simulated_output"), not(feature = "passthru_ahk")))]
+fn emit_frame(device: &uinput::VirtualDevice, events: &[InputEvent]) -> Result<(), io::Error> {
+    use std::os::unix::io::FromRawFd;
+    // SAFETY: the file does not outlive the device and is not closed on drop.
+    let mut file =
+        std::mem::ManuallyDrop::new(unsafe { fs::File::from_raw_fd(device.as_raw_fd()) });
+    write_frame(&mut *file, events)
+}
+
+/// Writes the events and the SYN_REPORT that ends their frame with a single write. The
+/// SYN_REPORT has the time of the last event, since the kernel and applications take the time of
+/// a frame from it.
+#[cfg(all(not(feature = "simulated_output"), not(feature = "passthru_ahk")))]
+fn write_frame(out: &mut impl io::Write, events: &[InputEvent]) -> Result<(), io::Error> {
+    let mut frame: Vec<libc::input_event> = events.iter().map(|&ev| ev.into()).collect();
+    let mut syn: libc::input_event = InputEvent::new(
+        EventType::SYNCHRONIZATION.0,
+        evdev::SynchronizationCode::SYN_REPORT.0,
+        0,
+    )
+    .into();
+    if let Some(last) = frame.last() {
+        syn.time = last.time;
+    }
+    frame.push(syn);
+    // SAFETY: `input_event` is a plain struct, which is what uinput reads.
+    let bytes = unsafe {
+        std::slice::from_raw_parts(
+            frame.as_ptr() as *const u8,
+            std::mem::size_of_val(frame.as_slice()),
+        )
+    };
+    out.write_all(bytes)
+}
+
+/// Where the output events go, see `linux-output-backend`.
+#[cfg(all(not(feature = "simulated_output"), not(feature = "passthru_ahk")))]
+enum OutputDevice {
+    Uinput(uinput::VirtualDevice),
+    #[cfg(feature = "x11_xtest")]
+    Xtest(Box<xtest::XtestDevice>),
+    Remote(remote::RemoteDevice),
+}
+
+#[cfg(all(not(feature = "simulated_output"), not(feature = "passthru_ahk")))]
+impl OutputDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> Result<(), io::Error> {
+        match self {
+            OutputDevice::Uinput(device) => emit_frame(device, events),
+            #[cfg(feature = "x11_xtest")]
+            OutputDevice::Xtest(device) => device.emit(events),
+            OutputDevice::Remote(device) => device.emit(events),
+        }
+    }
+}
+
 #[cfg(all(not(feature = "simulated_output"), not(feature = "passthru_ahk")))]
 pub struct KbdOut {
-    device: uinput::VirtualDevice,
+    device: OutputDevice,
+    /// The device that `setmouse` and `mouse-grid` move the pointer with, for
+    /// `linux-output-absolute-pointer`.
+    absolute_pointer: Option<uinput::VirtualDevice>,
+    /// The output devices of `linux-output-device-routes`, by the ID of their input devices.
+    routes: Vec<(NonZeroU8, uinput::VirtualDevice)>,
+    /// The route of the last key press, see [`KbdOut::set_output_route`].
+    route: Option<NonZeroU8>,
+    /// The routes that keys are pressed on, so that they are released on the same device.
+    routed_keys: HashMap<u16, NonZeroU8>,
+    /// The routes of the events in `burst` and in `raw_buf`.
+    burst_route: Option<NonZeroU8>,
+    raw_route: Option<NonZeroU8>,
     accumulated_scroll: u16,
     accumulated_hscroll: u16,
     raw_buf: Vec<InputEvent>,
+    /// Reused by `move_mouse_many` to avoid allocating for every movement.
+    mouse_buf: Vec<InputEvent>,
+    /// Key events of the current burst, see [`KbdOut::begin_burst`].
+    burst: Vec<InputEvent>,
+    in_burst: bool,
+    /// The time to stamp key events with, see [`KbdOut::set_event_time`].
+    event_time: Option<libc::timeval>,
     pub unicode_termination: Cell<UnicodeTermination>,
     pub unicode_u_code: Cell<OsCode>,
 }
@@ -380,7 +754,96 @@ impl KbdOut {
         trackpoint: bool,
         name: &str,
         bus_type: BusType,
+        opts: &CfgLinuxOptions,
     ) -> Result<Self, io::Error> {
+        let mut absolute_pointer = None;
+        let mut routes = vec![];
+        let device = match opts.linux_output_backend {
+            LinuxCfgOutputBackend::Uinput => {
+                let input_id = evdev::InputId::new(
+                    bus_type,
+                    opts.linux_output_vendor_id,
+                    opts.linux_output_product_id,
+                    opts.linux_output_version,
+                );
+                if opts.linux_output_absolute_pointer {
+                    absolute_pointer = Some(Self::new_absolute_pointer(name, input_id.clone())?);
+                }
+                for (id, route_name) in opts.linux_output_device_routes.iter() {
+                    let device = Self::build_uinput(false, route_name, input_id.clone())?;
+                    tracing::info!("Created device {route_name} for the input devices of ID {id}");
+                    routes.push((*id, device));
+                }
+                OutputDevice::Uinput(Self::new_uinput(symlink_path, trackpoint, name, input_id)?)
+            }
+            #[cfg(feature = "x11_xtest")]
+            LinuxCfgOutputBackend::Xtest => {
+                handle_signals(None);
+                OutputDevice::Xtest(Box::new(xtest::XtestDevice::new()?))
+            }
+            LinuxCfgOutputBackend::Remote => {
+                handle_signals(None);
+                let (Some(address), Some(token_file)) = (
+                    &opts.linux_output_remote_address,
+                    &opts.linux_output_remote_token_file,
+                ) else {
+                    unreachable!("the parser requires the address and token file");
+                };
+                OutputDevice::Remote(remote::RemoteDevice::new(address, token_file)?)
+            }
+        };
+        Ok(KbdOut {
+            device,
+            absolute_pointer,
+            routes,
+            route: None,
+            routed_keys: HashMap::default(),
+            burst_route: None,
+            raw_route: None,
+            accumulated_scroll: 0,
+            accumulated_hscroll: 0,
+            raw_buf: vec![],
+            mouse_buf: vec![],
+            burst: vec![],
+            in_burst: false,
+            event_time: None,
+
+            // historically was the only option, so make Enter the default
+            unicode_termination: Cell::new(UnicodeTermination::Enter),
+
+            // historically was the only option, so make KEY_U the default
+            unicode_u_code: Cell::new(OsCode::KEY_U),
+        })
+    }
+
+    fn new_uinput(
+        symlink_path: &Option<String>,
+        trackpoint: bool,
+        name: &str,
+        input_id: evdev::InputId,
+    ) -> Result<uinput::VirtualDevice, io::Error> {
+        let mut device = Self::build_uinput(trackpoint, name, input_id)?;
+        let devnode = device
+            .enumerate_dev_nodes_blocking()?
+            .next() // Expect only one. Using fold or calling next again blocks indefinitely
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "devnode is not found"))??;
+        tracing::info!("Created device {:#?}", devnode);
+        let symlink = if let Some(symlink_path) = symlink_path {
+            let dest = PathBuf::from(symlink_path);
+            let symlink = Symlink::new(devnode, dest)?;
+            Some(symlink)
+        } else {
+            None
+        };
+        handle_signals(symlink);
+        Ok(device)
+    }
+
+    fn build_uinput(
+        trackpoint: bool,
+        name: &str,
+        input_id: evdev::InputId,
+    ) -> Result<uinput::VirtualDevice, io::Error> {
         // Support pretty much every feature of a Keyboard or a Mouse in a VirtualDevice so that no event from the original input devices gets lost
         // TODO investigate the rare possibility that a device is e.g. a Joystick and a Keyboard or a Mouse at the same time, which could lead to lost events
 
@@ -405,7 +868,7 @@ impl KbdOut {
             .name(&name)
             // libinput's "disable while typing" feature don't work when bus_type
             // is set to BUS_USB, but appears to work when it's set to BUS_I8042.
-            .input_id(evdev::InputId::new(bus_type, 1, 1, 1))
+            .input_id(input_id)
             .with_keys(&keys)?
             .with_relative_axes(&relative_axes)?;
         let device = if trackpoint {
@@ -413,33 +876,54 @@ impl KbdOut {
         } else {
             device
         };
-        let mut device = device.build()?;
-        let devnode = device
-            .enumerate_dev_nodes_blocking()?
-            .next() // Expect only one. Using fold or calling next again blocks indefinitely
-            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "devnode is not found"))??;
-        log::info!("Created device {:#?}", devnode);
-        let symlink = if let Some(symlink_path) = symlink_path {
-            let dest = PathBuf::from(symlink_path);
-            let symlink = Symlink::new(devnode, dest)?;
-            Some(symlink)
-        } else {
-            None
-        };
-        handle_signals(symlink);
-
-        Ok(KbdOut {
-            device,
-            accumulated_scroll: 0,
-            accumulated_hscroll: 0,
-            raw_buf: vec![],
+        let device = device.build()?;
+        OUTPUT_DEVICE_NAMES.lock().push(name.to_owned());
+        Ok(device)
+    }
 
-            // historically was the only option, so make Enter the default
-            unicode_termination: Cell::new(UnicodeTermination::Enter),
+    /// A pointer with absolute coordinates, like a VM tablet, which the compositor maps to the
+    /// whole desktop. The mouse buttons make libinput treat it as a pointer, they are never
+    /// pressed.
+    fn new_absolute_pointer(
+        name: &str,
+        input_id: evdev::InputId,
+    ) -> Result<uinput::VirtualDevice, io::Error> {
+        let buttons = evdev::AttributeSet::from_iter([
+            KeyCode::BTN_LEFT,
+            KeyCode::BTN_RIGHT,
+            KeyCode::BTN_MIDDLE,
+        ]);
+        let axis =
+            |code| UinputAbsSetup::new(code, AbsInfo::new(0, 0, ABSOLUTE_POINTER_MAX, 0, 0, 1));
+        let name = format!("{name}{ABSOLUTE_POINTER_SUFFIX}");
+        let device = uinput::VirtualDevice::builder()?
+            .name(&name)
+            .input_id(input_id)
+            .with_keys(&buttons)?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_X))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_Y))?
+            .build()?;
+        tracing::info!("Created absolute pointer device {name}");
+        Ok(device)
+    }
 
-            // historically was the only option, so make KEY_U the default
-            unicode_u_code: Cell::new(OsCode::KEY_U),
-        })
+    /// Moves the absolute pointer to the coordinates, from 0 to [`ABSOLUTE_POINTER_MAX`].
+    fn set_absolute_pointer(&mut self, x: i32, y: i32) -> Result<bool, io::Error> {
+        let Some(device) = &mut self.absolute_pointer else {
+            return Ok(false);
+        };
+        let abs = |code: AbsoluteAxisCode, value: i32| {
+            InputEvent::new(
+                EventType::ABSOLUTE.0,
+                code.0,
+                value.clamp(0, ABSOLUTE_POINTER_MAX),
+            )
+        };
+        device.emit(&[
+            abs(AbsoluteAxisCode::ABS_X, x),
+            abs(AbsoluteAxisCode::ABS_Y, y),
+        ])?;
+        Ok(true)
     }
 
     pub fn update_unicode_termination(&self, t: UnicodeTermination) {
@@ -450,7 +934,137 @@ impl KbdOut {
         self.unicode_u_code.replace(u);
     }
 
-    pub fn write_raw(&mut self, event: InputEvent) -> Result<(), io::Error> {
+    /// Collects the key events written until [`KbdOut::end_burst`], to send them with a single
+    /// write and SYN_REPORT instead of a write and SYN_REPORT each. Many events in one tick, e.g.
+    /// from a macro or chord, then reach applications as whole frames with less latency.
+    pub fn begin_burst(&mut self) {
+        self.in_burst = true;
+    }
+
+    /// Sends the key events collected since [`KbdOut::begin_burst`].
+    pub fn end_burst(&mut self) -> Result<(), io::Error> {
+        self.in_burst = false;
+        self.flush_burst()
+    }
+
+    /// Sends the collected key events as one frame.
+    pub fn flush_burst(&mut self) -> Result<(), io::Error> {
+        if self.burst.is_empty() {
+            return Ok(());
+        }
+        let routed = self.burst_route.and_then(|route| {
+            self.routes
+                .iter()
+                .find(|(id, _)| *id == route)
+                .map(|(_, device)| device)
+        });
+        let result = match (routed, &mut self.device) {
+            (Some(device), _) => emit_frame(device, &self.burst),
+            (None, other) => other.emit(&self.burst),
+        };
+        self.burst.clear();
+        result
+    }
+
+    /// Stamps the key events written until the next call with the time of the input event that
+    /// they were output for, instead of the time at which they are written, so that applications
+    /// that measure latency or detect gestures see when the key was actually pressed. The
+    /// SYN_REPORT that ends their frame has the same time, see [`write_frame`]. Only uinput takes
+    /// the time of the events, and older kernels ignore it.
+    pub fn set_event_time(&mut self, time: Option<std::time::SystemTime>) {
+        self.event_time = time.and_then(monotonic_timeval);
+    }
+
+    /// Sends the keys written until the next call to the output device of
+    /// `linux-output-device-routes` of the input device with the ID, or to the main output device
+    /// for None or an ID without a route. Keys are released on the device that they were pressed
+    /// on.
+    pub fn set_output_route(&mut self, device_id: Option<NonZeroU8>) {
+        self.route = device_id.filter(|route| self.routes.iter().any(|(id, _)| id == route));
+    }
+
+    /// The route of a key event, recording which device a pressed key is on.
+    fn key_route(&mut self, code: u16, value: i32) -> Option<NonZeroU8> {
+        if self.routes.is_empty() {
+            return None;
+        }
+        match value {
+            1 => {
+                match self.route {
+                    Some(route) => self.routed_keys.insert(code, route),
+                    None => self.routed_keys.remove(&code),
+                };
+                self.route
+            }
+            0 => self.routed_keys.remove(&code),
+            _ => self.routed_keys.get(&code).copied(),
+        }
+    }
+
+    /// Writes the events to the output device of the route.
+    fn emit_routed(
+        &mut self,
+        route: Option<NonZeroU8>,
+        events: &[InputEvent],
+    ) -> Result<(), io::Error> {
+        emit_to(&mut self.device, &mut self.routes, route, events)
+    }
+
+    /// Writes the passed through events of `raw_buf`.
+    fn emit_raw_buf(&mut self) -> Result<(), io::Error> {
+        emit_to(
+            &mut self.device,
+            &mut self.routes,
+            self.raw_route,
+            &self.raw_buf,
+        )?;
+        self.raw_buf.clear();
+        Ok(())
+    }
+
+    fn with_time(&self, event: InputEvent, time: Option<libc::timeval>) -> InputEvent {
+        match (&self.device, time) {
+            (OutputDevice::Uinput(_), Some(time)) => {
+                let mut raw: libc::input_event = event.into();
+                raw.time = time;
+                raw.into()
+            }
+            _ => event,
+        }
+    }
+
+    /// Writes a key event now, or with the burst if one is active.
+    fn emit_key(&mut self, event: InputEvent) -> Result<(), io::Error> {
+        leds::key_output(event.code(), event.value());
+        let event = self.with_time(event, self.event_time);
+        let route = self.key_route(event.code(), event.value());
+        if !self.in_burst {
+            return self.emit_routed(route, &[event]);
+        }
+        // Applications may only look at the final state of a key in a frame, so a key that
+        // changes again, e.g. the release of a tap, starts a new frame, as does a key for another
+        // output device.
+        if route != self.burst_route || self.burst.iter().any(|ev| ev.code() == event.code()) {
+            self.flush_burst()?;
+        }
+        self.burst_route = route;
+        self.burst.push(event);
+        Ok(())
+    }
+
+    /// Passes through an event of the input device with the ID, to the output device of its
+    /// route if it has one.
+    pub fn write_raw(
+        &mut self,
+        event: InputEvent,
+        device_id: Option<NonZeroU8>,
+    ) -> Result<(), io::Error> {
+        self.flush_burst()?;
+        let route = device_id.filter(|route| self.routes.iter().any(|(id, _)| id == route));
+        if route != self.raw_route && !self.raw_buf.is_empty() {
+            self.emit_raw_buf()?;
+        }
+        self.raw_route = route;
         if event.event_type() == EventType::SYNCHRONIZATION {
             // Possible codes are:
             //
@@ -462,44 +1076,61 @@ impl KbdOut {
             //     this correctly.
             //
             // With this knowledge, seems fine to not bother checking.
-            self.device.emit(&self.raw_buf)?;
-            self.raw_buf.clear();
+            self.emit_raw_buf()?;
         } else {
-            self.raw_buf.push(event);
+            if event.event_type() == EventType::KEY {
+                leds::key_output(event.code(), event.value());
+            }
+            let time = monotonic_timeval(event.timestamp());
+            self.raw_buf.push(self.with_time(event, time));
         }
         Ok(())
     }
 
     pub fn write(&mut self, event: InputEvent) -> Result<(), io::Error> {
+        self.flush_burst()?;
         if !self.raw_buf.is_empty() {
-            self.device.emit(&self.raw_buf)?;
-            self.raw_buf.clear();
+            self.emit_raw_buf()?;
         }
         self.device.emit(&[event])?;
         Ok(())
     }
 
     pub fn write_many(&mut self, events: &[InputEvent]) -> Result<(), io::Error> {
+        self.flush_burst()?;
         if !self.raw_buf.is_empty() {
-            self.device.emit(&self.raw_buf)?;
-            self.raw_buf.clear();
+            self.emit_raw_buf()?;
         }
         self.device.emit(events)?;
         Ok(())
     }
 
+    /// Shows the LED pattern of a layer on the input devices, or the lock state for None.
+    pub fn set_lock_leds(&mut self, leds: Option<LockLeds>) {
+        set_layer_leds(leds);
+    }
+
     pub fn write_key(&mut self, key: OsCode, value: KeyValue) -> Result<(), io::Error> {
         let key_ev = KeyEvent::new(key, value);
         let input_ev = key_ev.into();
-        log::debug!("send to uinput: {:?}", input_ev);
-        self.device.emit(&[input_ev])?;
-        Ok(())
+        tracing::debug!("send to uinput: {:?}", input_ev);
+        self.emit_key(input_ev)
     }
 
     pub fn write_code(&mut self, code: u32, value: KeyValue) -> Result<(), io::Error> {
         let event = InputEvent::new(EventType::KEY.0, code as u16, value as i32);
-        self.device.emit(&[event])?;
-        Ok(())
+        self.emit_key(event)
+    }
+
+    /// Sends the key that Linux maps the HID usage to, since there is no HID output.
+    pub fn write_hid_usage(&mut self, usage: HidUsage, value: KeyValue) -> Result<(), io::Error> {
+        match usage.oscode() {
+            Some(key) => self.write_key(key, value),
+            None => {
+                tracing::warn!("hid-usage {usage} has no key on this OS, not sending it");
+                Ok(())
+            }
+        }
     }
 
     pub fn press_key(&mut self, key: OsCode) -> Result<(), io::Error> {
@@ -511,8 +1142,23 @@ impl KbdOut {
     }
 
     /// Send using C-S-u + <unicode hex number> + spc
+    /// The unicode input of Linux takes one character at a time, so each character is sent in
+    /// turn.
+    /// The text is not logged because it can be a secret.
+    pub fn send_unicode_str(&mut self, text: &str) -> Result<(), io::Error> {
+        tracing::debug!("sending {} unicode characters", text.chars().count());
+        for c in text.chars() {
+            self.send_unicode_char(c)?;
+        }
+        Ok(())
+    }
+
     pub fn send_unicode(&mut self, c: char) -> Result<(), io::Error> {
-        log::debug!("sending unicode {c}");
+        tracing::debug!("sending unicode {c}");
+        self.send_unicode_char(c)
+    }
+
+    fn send_unicode_char(&mut self, c: char) -> Result<(), io::Error> {
         let hex = format!("{:x}", c as u32);
         self.press_key(OsCode::KEY_LEFTCTRL)?;
         self.press_key(OsCode::KEY_LEFTSHIFT)?;
@@ -566,7 +1212,7 @@ impl KbdOut {
         direction: MWheelDirection,
         hi_res_distance: u16,
     ) -> Result<(), io::Error> {
-        log::debug!("scroll: {direction:?} {hi_res_distance:?}");
+        tracing::debug!("scroll: {direction:?} {hi_res_distance:?}");
 
         let mut lo_res_distance = hi_res_distance / HI_RES_SCROLL_UNITS_IN_LO_RES;
         let leftover_hi_res_distance = hi_res_distance % HI_RES_SCROLL_UNITS_IN_LO_RES;
@@ -635,7 +1281,8 @@ impl KbdOut {
     }
 
     pub fn move_mouse_many(&mut self, moves: &[CalculatedMouseMove]) -> Result<(), io::Error> {
-        let mut events = vec![];
+        let mut events = std::mem::take(&mut self.mouse_buf);
+        events.clear();
         for mv in moves {
             let (axis, distance) = match mv.direction {
                 MoveDirection::Up => (RelativeAxisCode::REL_Y, -i32::from(mv.distance)),
@@ -645,15 +1292,79 @@ impl KbdOut {
             };
             events.push(InputEvent::new(EventType::RELATIVE.0, axis.0, distance));
         }
-        self.write_many(&events)
+        let result = self.write_many(&events);
+        self.mouse_buf = events;
+        result
     }
 
-    pub fn set_mouse(&mut self, _x: u16, _y: u16) -> Result<(), io::Error> {
-        log::warn!(
-            "setmouse does not work in Linux yet. Maybe try out warpd:\n\thttps://github.com/rvaiya/warpd"
-        );
+    pub fn set_mouse(&mut self, x: u16, y: u16) -> Result<(), io::Error> {
+        match &self.device {
+            #[cfg(feature = "x11_xtest")]
+            OutputDevice::Xtest(device) => return device.set_mouse(x, y),
+            OutputDevice::Remote(device) => return device.set_mouse(x, y),
+            OutputDevice::Uinput(_) => {}
+        }
+        if !self.set_absolute_pointer(i32::from(x), i32::from(y))? {
+            tracing::warn!(
+                "setmouse needs linux-output-absolute-pointer yes on Linux. Maybe try out warpd:\n\thttps://github.com/rvaiya/warpd"
+            );
+        }
+        Ok(())
+    }
+
+    /// The absolute pointer covers the whole desktop, which is monitor 0.
+    pub fn warp_mouse_in_monitor(
+        &mut self,
+        monitor: usize,
+        x: f64,
+        y: f64,
+    ) -> Result<(), io::Error> {
+        if self.absolute_pointer.is_some() && monitor != 0 {
+            tracing::warn!(
+                "mouse-grid: monitor {} was not found, Linux has one monitor for the whole desktop",
+                monitor + 1
+            );
+            return Ok(());
+        }
+        let max = f64::from(ABSOLUTE_POINTER_MAX);
+        if !self.set_absolute_pointer((x * max).round() as i32, (y * max).round() as i32)? {
+            tracing::warn!(
+                "mouse-grid needs linux-output-absolute-pointer yes on Linux. Maybe try out warpd:\n\thttps://github.com/rvaiya/warpd"
+            );
+        }
         Ok(())
     }
+
+    /// Only `linux-output-backend xtest` can move toward anchors, and it treats the X screen as a
+    /// single monitor.
+    #[cfg(feature = "x11_xtest")]
+    pub fn move_mouse_toward(&mut self, action: &MouseToward) -> Result<(), io::Error> {
+        let OutputDevice::Xtest(device) = &self.device else {
+            tracing::warn!("mouse-toward only works with linux-output-backend xtest");
+            return Ok(());
+        };
+        let pointer = device.mouse_position()?;
+        let Some((x, y)) = mouse_toward_target(pointer, &[device.screen()], action) else {
+            tracing::warn!("mouse-toward: the monitor was not found");
+            return Ok(());
+        };
+        let clamp = |v: i32| v.clamp(0, i32::from(u16::MAX)) as u16;
+        device.set_mouse(clamp(x), clamp(y))
+    }
+
+    #[cfg(not(feature = "x11_xtest"))]
+    pub fn move_mouse_toward(&mut self, _action: &MouseToward) -> Result<(), io::Error> {
+        tracing::warn!("mouse-toward only works with linux-output-backend xtest");
+        Ok(())
+    }
+
+    pub fn mouse_position(&mut self) -> Result<Option<(i32, i32)>, io::Error> {
+        match &self.device {
+            #[cfg(feature = "x11_xtest")]
+            OutputDevice::Xtest(device) => device.mouse_position().map(Some),
+            OutputDevice::Remote(_) | OutputDevice::Uinput(_) => Ok(None),
+        }
+    }
 }
 
 fn devices_from_input_paths(
@@ -666,7 +1377,7 @@ fn devices_from_input_paths(
         .filter_map(|(dev_path, open_result)| match open_result {
             Ok(d) => Some((d, dev_path.clone())),
             Err(e) => {
-                log::warn!("failed to open device '{dev_path}': {e:?}");
+                tracing::warn!("failed to open device '{dev_path}': {e:?}");
                 missing_device_paths.push(dev_path.clone());
                 None
             }
@@ -679,7 +1390,7 @@ pub fn discover_devices(
     exclude_names: Option<&[String]>,
     device_detect_mode: DeviceDetectMode,
 ) -> Vec<(Device, String)> {
-    log::info!("looking for devices in /dev/input");
+    tracing::info!("looking for devices in /dev/input");
     let devices: Vec<_> = evdev::enumerate()
         .map(|(path, device)| {
             (
@@ -689,37 +1400,122 @@ pub fn discover_devices(
                     .to_owned(),
             )
         })
-        .filter(|pd| {
-            let is_input = is_input_device(&pd.0, device_detect_mode);
-            (match include_names {
-                None => is_input,
-                Some(include_names) => {
-                    let name = pd.0.name().unwrap_or("");
-                    if include_names.iter().any(|include| name == include) {
-                        log::info!("device [{}:{name}] is included", &pd.1);
-                        true
-                    } else {
-                        log::info!("device [{}:{name}] is ignored", &pd.1);
-                        false
-                    }
-                }
-            }) && (match exclude_names {
-                None => true,
-                Some(exclude_names) => {
-                    let name = pd.0.name().unwrap_or("");
-                    if exclude_names.iter().any(|exclude| name == exclude) {
-                        log::info!("device [{}:{name}] is excluded", &pd.1);
-                        false
-                    } else {
-                        true
-                    }
-                }
-            })
+        .filter(|(device, path)| {
+            is_selected_device(
+                device,
+                path,
+                include_names,
+                exclude_names,
+                device_detect_mode,
+            )
         })
         .collect();
     devices
 }
 
+/// Returns whether kanata would use the device when it discovers devices, i.e. when `linux-dev`
+/// is not set.
+fn is_selected_device(
+    device: &Device,
+    path: &str,
+    include_names: Option<&[String]>,
+    exclude_names: Option<&[String]>,
+    device_detect_mode: DeviceDetectMode,
+) -> bool {
+    let name = device.name().unwrap_or("");
+    (match include_names {
+        None => is_input_device(device, device_detect_mode),
+        Some(include_names) => {
+            if include_names.iter().any(|include| name == include) {
+                tracing::info!("device [{path}:{name}] is included");
+                true
+            } else {
+                tracing::info!("device [{path}:{name}] is ignored");
+                false
+            }
+        }
+    }) && (match exclude_names {
+        Some(exclude_names) if exclude_names.iter().any(|exclude| name == exclude) => {
+            tracing::info!("device [{path}:{name}] is excluded");
+            false
+        }
+        _ => true,
+    })
+}
+
+/// Returns whether kanata would grab the device at `path` with these options.
+pub fn would_grab_device(device: &Device, path: &str, opts: &CfgLinuxOptions) -> bool {
+    if !opts.linux_dev.is_empty() {
+        let canonical = |p: &str| fs::canonicalize(p).unwrap_or_else(|_| PathBuf::from(p));
+        let path = canonical(path);
+        return opts.linux_dev.iter().any(|dev| canonical(dev) == path);
+    }
+    is_selected_device(
+        device,
+        path,
+        opts.linux_dev_names_include.as_deref(),
+        opts.linux_dev_names_exclude.as_deref(),
+        opts.linux_device_detect_mode
+            .unwrap_or(DeviceDetectMode::KeyboardOnly),
+    ) && is_on_seat(path, opts.linux_seat.as_deref())
+}
+
+/// The logind seat that udev assigned the device at `path` to. Devices without a seat belong to
+/// `seat0`.
+pub fn device_seat(path: &str) -> String {
+    // udev keeps the properties of a device in a file named by its device number.
+    fs::canonicalize(path)
+        .ok()
+        .and_then(|path| {
+            let name = path.file_name()?.to_str()?.to_owned();
+            let dev = fs::read_to_string(format!("/sys/class/input/{name}/dev")).ok()?;
+            let data = fs::read_to_string(format!("/run/udev/data/c{}", dev.trim())).ok()?;
+            data.lines().find_map(|line| {
+                line.strip_prefix("E:ID_SEAT=")
+                    .filter(|seat| !seat.is_empty())
+                    .map(str::to_owned)
+            })
+        })
+        .unwrap_or_else(|| "seat0".to_owned())
+}
+
+/// Returns whether the device at `path` belongs to the seat, or true without a seat.
+fn is_on_seat(path: &str, seat: Option<&str>) -> bool {
+    let Some(seat) = seat else {
+        return true;
+    };
+    let device_seat = device_seat(path);
+    if device_seat != seat {
+        tracing::info!("device [{path}] is ignored, it is on {device_seat}");
+        return false;
+    }
+    true
+}
+
+/// Returns what kinds of input the device supports, e.g. `["keyboard", "mouse"]`.
+pub fn device_capabilities(device: &Device) -> Vec<&'static str> {
+    let mut caps = vec![];
+    if device.supported_keys().is_some_and(has_keyboard_keys) {
+        caps.push("keyboard");
+    }
+    if let Some(axes) = device.supported_relative_axes() {
+        if axes.contains(RelativeAxisCode::REL_X) {
+            caps.push("mouse");
+        }
+        if axes.contains(RelativeAxisCode::REL_WHEEL) || axes.contains(RelativeAxisCode::REL_HWHEEL)
+        {
+            caps.push("wheel");
+        }
+    }
+    if device.supported_absolute_axes().is_some() {
+        caps.push("absolute");
+    }
+    if device.properties().contains(PropType::POINTING_STICK) {
+        caps.push("trackpoint");
+    }
+    caps
+}
+
 fn watch_devinput() -> Result<Inotify, io::Error> {
     let inotify = Inotify::init().expect("Failed to initialize inotify");
     inotify.watches().add("/dev/input", WatchMask::CREATE)?;
@@ -747,7 +1543,7 @@ impl Symlink {
             }
         }
         std::os::unix::fs::symlink(&source, &dest)?;
-        log::info!("Created symlink {:#?} -> {:#?}", dest, source);
+        tracing::info!("Created symlink {:#?} -> {:#?}", dest, source);
         Ok(Self { dest })
     }
 }
@@ -759,13 +1555,17 @@ fn handle_signals(symlink: Option<Symlink>) {
             match signal {
                 SIGINT | SIGTERM => {
                     drop(symlink);
+                    set_layer_leds(None);
                     signal_hook::low_level::emulate_default_handler(signal)
                         .expect("run original sighandlers");
                     unreachable!();
                 }
                 SIGTSTP => {
                     drop(symlink);
-                    log::warn!("got SIGTSTP, exiting instead of pausing so keyboards don't hang");
+                    set_layer_leds(None);
+                    tracing::warn!(
+                        "got SIGTSTP, exiting instead of pausing so keyboards don't hang"
+                    );
                     std::process::exit(SIGTSTP);
                 }
                 _ => unreachable!(),
@@ -812,7 +1612,7 @@ fn wait_for_all_keys_unpressed(dev: &Device) -> Result<(), io::Error> {
                     vendor, product, version
                 )
             };
-            log::warn!(
+            tracing::warn!(
                 "timed out waiting for {} to release its keys ({} pressed)",
                 dev_str,
                 n_pressed_keys
@@ -831,6 +1631,71 @@ fn wait_for_all_keys_unpressed(dev: &Device) -> Result<(), io::Error> {
 impl Drop for Symlink {
     fn drop(&mut self) {
         let _ = fs::remove_file(&self.dest);
-        log::info!("Deleted symlink {:#?}", self.dest);
+        tracing::info!("Deleted symlink {:#?}", self.dest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_devices_match_by_name_and_ids() {
+        let matcher = InputDeviceMatcher {
+            name: Some("Macro Pad".to_owned()),
+            vendor_id: Some(0x1209),
+            ..Default::default()
+        };
+        assert!(matches_input_device(&matcher, "ACME Macro Pad", 0x1209, 7));
+        assert!(!matches_input_device(&matcher, "ACME Macro Pad", 0x1d50, 7));
+        assert!(!matches_input_device(&matcher, "ACME Keyboard", 0x1209, 7));
+        // Devices on Linux have no hash.
+        let matcher = InputDeviceMatcher {
+            hash: Some("1a2b".to_owned()),
+            ..Default::default()
+        };
+        assert!(!matches_input_device(&matcher, "ACME Macro Pad", 0x1209, 7));
+    }
+
+    #[cfg(all(not(feature = "simulated_output"), not(feature = "passthru_ahk")))]
+    #[test]
+    fn frames_end_with_a_syn_report_of_their_time() {
+        let time = libc::timeval {
+            tv_sec: 1234,
+            tv_usec: 5678,
+        };
+        let stamped = |code: KeyCode, value| {
+            let mut raw: libc::input_event =
+                InputEvent::new(EventType::KEY.0, code.0, value).into();
+            raw.time = time;
+            InputEvent::from(raw)
+        };
+        let burst = [stamped(KeyCode::KEY_A, 1), stamped(KeyCode::KEY_B, 1)];
+        let mut bytes = vec![];
+        write_frame(&mut bytes, &burst).unwrap();
+
+        let size = std::mem::size_of::<libc::input_event>();
+        assert_eq!(bytes.len(), 3 * size);
+        let written: Vec<libc::input_event> = bytes
+            .chunks_exact(size)
+            // SAFETY: each chunk has the size of an `input_event`, which is a plain struct.
+            .map(|chunk| unsafe { std::ptr::read_unaligned(chunk.as_ptr().cast()) })
+            .collect();
+        let fields =
+            |ev: &libc::input_event| (ev.time.tv_sec, ev.time.tv_usec, ev.type_, ev.code, ev.value);
+        assert_eq!(
+            written.iter().map(fields).collect::<Vec<_>>(),
+            [
+                (1234, 5678, EventType::KEY.0, KeyCode::KEY_A.0, 1),
+                (1234, 5678, EventType::KEY.0, KeyCode::KEY_B.0, 1),
+                (
+                    1234,
+                    5678,
+                    EventType::SYNCHRONIZATION.0,
+                    evdev::SynchronizationCode::SYN_REPORT.0,
+                    0
+                ),
+            ]
+        );
     }
 }
@@ -0,0 +1,141 @@
+//! The lock LEDs of the input devices for `linux-layer-leds`.
+//!
+//! While a layer with LEDs is active, the devices show its pattern instead of the lock state. The
+//! lock state is read from the devices before a pattern is first shown, and is then followed
+//! through the lock keys that are output, so that it can be shown again when the pattern ends.
+
+use evdev::raw_stream::RawDevice;
+use evdev::{EventType, InputEvent, LedCode, SynchronizationCode};
+use kanata_parser::cfg::LockLeds;
+use kanata_parser::keys::OsCode;
+use parking_lot::Mutex;
+
+struct LedState {
+    /// Handles of the registered input devices that have lock LEDs, by path. They are separate
+    /// from the handles that read the devices, and LEDs can be written while those grab them.
+    devices: Vec<(String, RawDevice)>,
+    /// The pattern of the active layer.
+    shown: Option<LockLeds>,
+    /// The lock state while a pattern is shown, if it could be read.
+    real: Option<LockLeds>,
+}
+
+static LEDS: Mutex<LedState> = Mutex::new(LedState {
+    devices: vec![],
+    shown: None,
+    real: None,
+});
+
+/// Opens the input device to write its LEDs, and shows the active pattern on it.
+pub(super) fn add_device(path: &str) {
+    let device = match RawDevice::open(path) {
+        Ok(device) => device,
+        Err(e) => {
+            tracing::warn!("could not open {path} for its LEDs: {e}");
+            return;
+        }
+    };
+    if !device
+        .supported_leds()
+        .is_some_and(|leds| leds.contains(LedCode::LED_CAPSL))
+    {
+        return;
+    }
+    let mut state = LEDS.lock();
+    if let Some(shown) = state.shown {
+        if state.real.is_none() {
+            state.real = read_leds(&device);
+        }
+        state.devices.push((path.to_owned(), device));
+        state.write(shown);
+    } else {
+        state.devices.push((path.to_owned(), device));
+    }
+}
+
+pub(super) fn remove_device(path: &str) {
+    LEDS.lock().devices.retain(|(p, _)| p != path);
+}
+
+/// Shows the LED pattern of a layer, or the lock state for None.
+pub fn set_layer_leds(leds: Option<LockLeds>) {
+    let mut state = LEDS.lock();
+    if state.shown == leds {
+        return;
+    }
+    if state.shown.is_none() {
+        state.real = state
+            .devices
+            .iter()
+            .find_map(|(_, device)| read_leds(device));
+    }
+    state.shown = leds;
+    match leds {
+        Some(leds) => state.write(leds),
+        None => {
+            if let Some(real) = state.real.take() {
+                state.write(real);
+            }
+        }
+    }
+}
+
+/// Follows the lock state through the output lock keys while a pattern is shown. The OS may
+/// write the lock state to the devices for the press, so the pattern is written again on the
+/// release.
+pub(super) fn key_output(code: u16, value: i32) {
+    const CAPS: u16 = OsCode::KEY_CAPSLOCK as u16;
+    const NUM: u16 = OsCode::KEY_NUMLOCK as u16;
+    const SCROLL: u16 = OsCode::KEY_SCROLLLOCK as u16;
+    if !matches!(code, CAPS | NUM | SCROLL) {
+        return;
+    }
+    let mut state = LEDS.lock();
+    let Some(shown) = state.shown else {
+        return;
+    };
+    match value {
+        1 => {
+            if let Some(real) = &mut state.real {
+                let led = match code {
+                    CAPS => &mut real.caps,
+                    NUM => &mut real.num,
+                    _ => &mut real.scroll,
+                };
+                *led = !*led;
+            }
+        }
+        0 => state.write(shown),
+        _ => {}
+    }
+}
+
+impl LedState {
+    fn write(&mut self, leds: LockLeds) {
+        let led = |code: LedCode, on: bool| InputEvent::new(EventType::LED.0, code.0, on.into());
+        let events = [
+            led(LedCode::LED_CAPSL, leds.caps),
+            led(LedCode::LED_NUML, leds.num),
+            led(LedCode::LED_SCROLLL, leds.scroll),
+            InputEvent::new(
+                EventType::SYNCHRONIZATION.0,
+                SynchronizationCode::SYN_REPORT.0,
+                0,
+            ),
+        ];
+        for (path, device) in self.devices.iter_mut() {
+            if let Err(e) = device.send_events(&events) {
+                tracing::warn!("could not write the LEDs of {path}: {e}");
+            }
+        }
+    }
+}
+
+fn read_leds(device: &RawDevice) -> Option<LockLeds> {
+    let leds = device.get_led_state().ok()?;
+    Some(LockLeds {
+        caps: leds.contains(LedCode::LED_CAPSL),
+        num: leds.contains(LedCode::LED_NUML),
+        scroll: leds.contains(LedCode::LED_SCROLLL),
+    })
+}
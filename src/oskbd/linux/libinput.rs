@@ -0,0 +1,441 @@
+//! Input through libinput, for `linux-input-backend libinput`.
+//!
+//! libinput finds the devices of the seat with udev, or opens the `linux-dev` paths, and applies
+//! its quirks database to them. It opens the devices through kanata, which grabs the ones that it
+//! would use with evdev and refuses the others, so that libinput only reads kanata's devices.
+//!
+//! libinput and libudev are loaded when the backend is used, so that kanata does not depend on
+//! them otherwise. Key repeats are not read, desktops that use libinput repeat keys themselves.
+
+use std::ffi::{CStr, c_char, c_double, c_int, c_void};
+use std::io;
+use std::os::unix::io::RawFd;
+
+use evdev::{Device, EventType, InputEvent, RelativeAxisCode};
+use kanata_parser::cfg::DeviceDetectMode;
+use parking_lot::Mutex;
+
+use crate::kanata::DeviceChange;
+
+const EVENT_DEVICE_ADDED: c_int = 1;
+const EVENT_DEVICE_REMOVED: c_int = 2;
+const EVENT_KEYBOARD_KEY: c_int = 300;
+const EVENT_POINTER_MOTION: c_int = 400;
+const EVENT_POINTER_BUTTON: c_int = 402;
+const EVENT_POINTER_SCROLL_WHEEL: c_int = 404;
+const POINTER_AXIS_SCROLL_VERTICAL: c_int = 0;
+const POINTER_AXIS_SCROLL_HORIZONTAL: c_int = 1;
+
+nix::ioctl_write_int!(eviocgrab, b'E', 0x90);
+
+macro_rules! functions {
+    ($($name:ident: fn($($arg:ty),*) $(-> $ret:ty)?;)*) => {
+        /// The functions of libinput and libudev that kanata calls.
+        struct Functions {
+            $($name: unsafe extern "C" fn($($arg),*) $(-> $ret)?,)*
+        }
+
+        impl Functions {
+            fn load(libraries: &[*mut c_void]) -> io::Result<Self> {
+                Ok(Self {
+                    $($name: {
+                        let name = concat!(stringify!($name), "\0");
+                        let symbol = libraries
+                            .iter()
+                            .map(|library| unsafe { libc::dlsym(*library, name.as_ptr().cast()) })
+                            .find(|symbol| !symbol.is_null())
+                            .ok_or_else(|| {
+                                io::Error::other(format!(
+                                    "{} was not found, libinput 1.19 or newer is needed",
+                                    stringify!($name)
+                                ))
+                            })?;
+                        // SAFETY: the symbol is the function with this signature in libinput.h
+                        // or libudev.h.
+                        unsafe {
+                            std::mem::transmute::<
+                                *mut c_void,
+                                unsafe extern "C" fn($($arg),*) $(-> $ret)?,
+                            >(symbol)
+                        }
+                    },)*
+                })
+            }
+        }
+    };
+}
+
+functions! {
+    udev_new: fn() -> *mut c_void;
+    udev_unref: fn(*mut c_void) -> *mut c_void;
+    libinput_udev_create_context: fn(*const Interface, *mut c_void, *mut c_void) -> *mut c_void;
+    libinput_udev_assign_seat: fn(*mut c_void, *const c_char) -> c_int;
+    libinput_path_create_context: fn(*const Interface, *mut c_void) -> *mut c_void;
+    libinput_path_add_device: fn(*mut c_void, *const c_char) -> *mut c_void;
+    libinput_unref: fn(*mut c_void) -> *mut c_void;
+    libinput_get_fd: fn(*mut c_void) -> c_int;
+    libinput_dispatch: fn(*mut c_void) -> c_int;
+    libinput_get_event: fn(*mut c_void) -> *mut c_void;
+    libinput_event_destroy: fn(*mut c_void);
+    libinput_event_get_type: fn(*mut c_void) -> c_int;
+    libinput_event_get_device: fn(*mut c_void) -> *mut c_void;
+    libinput_device_get_name: fn(*mut c_void) -> *const c_char;
+    libinput_device_get_sysname: fn(*mut c_void) -> *const c_char;
+    libinput_event_get_keyboard_event: fn(*mut c_void) -> *mut c_void;
+    libinput_event_keyboard_get_key: fn(*mut c_void) -> u32;
+    libinput_event_keyboard_get_key_state: fn(*mut c_void) -> c_int;
+    libinput_event_get_pointer_event: fn(*mut c_void) -> *mut c_void;
+    libinput_event_pointer_get_button: fn(*mut c_void) -> u32;
+    libinput_event_pointer_get_button_state: fn(*mut c_void) -> c_int;
+    libinput_event_pointer_get_dx_unaccelerated: fn(*mut c_void) -> c_double;
+    libinput_event_pointer_get_dy_unaccelerated: fn(*mut c_void) -> c_double;
+    libinput_event_pointer_has_axis: fn(*mut c_void, c_int) -> c_int;
+    libinput_event_pointer_get_scroll_value_v120: fn(*mut c_void, c_int) -> c_double;
+}
+
+/// `struct libinput_interface`, how libinput opens and closes devices.
+#[repr(C)]
+struct Interface {
+    open_restricted: unsafe extern "C" fn(*const c_char, c_int, *mut c_void) -> c_int,
+    close_restricted: unsafe extern "C" fn(c_int, *mut c_void),
+}
+
+static INTERFACE: Interface = Interface {
+    open_restricted,
+    close_restricted,
+};
+
+/// Which devices kanata uses when libinput discovers them with udev.
+pub(super) struct Selection {
+    pub(super) include_names: Option<Vec<String>>,
+    pub(super) exclude_names: Option<Vec<String>>,
+    pub(super) detect_mode: DeviceDetectMode,
+}
+
+/// The devices that libinput opened through kanata.
+struct Devices {
+    /// `None` for the `linux-dev` paths, which are all used.
+    selection: Option<Selection>,
+    grabbed: bool,
+    open: Vec<(RawFd, String)>,
+}
+
+fn set_grab(fd: RawFd, grab: bool) -> io::Result<()> {
+    unsafe { eviocgrab(fd, libc::c_ulong::from(grab)) }
+        .map(drop)
+        .map_err(io::Error::from)
+}
+
+impl Devices {
+    fn open(&mut self, c_path: &CStr, flags: c_int) -> io::Result<RawFd> {
+        let path = c_path.to_string_lossy().into_owned();
+        let device = Device::open(&path)?;
+        if let Some(selection) = &self.selection
+            && !super::is_selected_device(
+                &device,
+                &path,
+                selection.include_names.as_deref(),
+                selection.exclude_names.as_deref(),
+                selection.detect_mode,
+            )
+        {
+            return Err(io::Error::from_raw_os_error(libc::ENODEV));
+        }
+        tracing::info!(
+            "registering {path} through libinput: {:?}",
+            device.name().unwrap_or("")
+        );
+        if self.grabbed {
+            super::wait_for_all_keys_unpressed(&device)?;
+        }
+        drop(device);
+        let fd = unsafe { libc::open(c_path.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if self.grabbed
+            && let Err(e) = set_grab(fd, true)
+        {
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+        super::leds::add_device(&path);
+        self.open.push((fd, path));
+        Ok(fd)
+    }
+
+    fn close(&mut self, fd: RawFd) {
+        if let Some(i) = self.open.iter().position(|(open, _)| *open == fd) {
+            let (_, path) = self.open.remove(i);
+            super::leds::remove_device(&path);
+        }
+        unsafe { libc::close(fd) };
+    }
+}
+
+unsafe extern "C" fn open_restricted(
+    path: *const c_char,
+    flags: c_int,
+    data: *mut c_void,
+) -> c_int {
+    // SAFETY: the user data of the context is the `Devices` of its `Libinput`, and the path is a
+    // C string from libinput.
+    let (devices, path) = unsafe { (&*data.cast::<Mutex<Devices>>(), CStr::from_ptr(path)) };
+    match devices.lock().open(path, flags) {
+        Ok(fd) => fd,
+        Err(e) => -e.raw_os_error().unwrap_or(libc::EIO),
+    }
+}
+
+unsafe extern "C" fn close_restricted(fd: c_int, data: *mut c_void) {
+    // SAFETY: as in `open_restricted`.
+    let devices = unsafe { &*data.cast::<Mutex<Devices>>() };
+    devices.lock().close(fd);
+}
+
+fn load_library(names: &[&CStr]) -> io::Result<*mut c_void> {
+    names
+        .iter()
+        .map(|name| unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) })
+        .find(|library| !library.is_null())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "could not load {} for linux-input-backend libinput, is it installed?",
+                    names[0].to_string_lossy()
+                ),
+            )
+        })
+}
+
+fn to_string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+}
+
+pub(super) struct Libinput {
+    functions: Functions,
+    context: *mut c_void,
+    /// Null for the `linux-dev` paths.
+    udev: *mut c_void,
+    /// Boxed because libinput keeps a pointer to it.
+    devices: Box<Mutex<Devices>>,
+    /// The fractions of the unaccelerated motion that are not sent yet.
+    motion_remainder: (f64, f64),
+}
+
+impl Libinput {
+    /// Opens the `linux-dev` paths, or the devices of the seat that are selected if there are
+    /// none.
+    pub(super) fn new(
+        paths: &[String],
+        selection: Selection,
+        seat: Option<&str>,
+    ) -> io::Result<Self> {
+        let libraries = [
+            load_library(&[c"libinput.so.10", c"libinput.so"])?,
+            load_library(&[c"libudev.so.1", c"libudev.so"])?,
+        ];
+        let functions = Functions::load(&libraries)?;
+        let devices = Box::new(Mutex::new(Devices {
+            selection: paths.is_empty().then_some(selection),
+            grabbed: true,
+            open: vec![],
+        }));
+        let data = std::ptr::from_ref(&*devices).cast_mut().cast::<c_void>();
+        let mut libinput = Self {
+            context: std::ptr::null_mut(),
+            udev: std::ptr::null_mut(),
+            functions,
+            devices,
+            motion_remainder: (0.0, 0.0),
+        };
+        let f = &libinput.functions;
+        if paths.is_empty() {
+            libinput.udev = unsafe { (f.udev_new)() };
+            if libinput.udev.is_null() {
+                return Err(io::Error::other("could not create the udev context"));
+            }
+            libinput.context =
+                unsafe { (f.libinput_udev_create_context)(&INTERFACE, data, libinput.udev) };
+            if libinput.context.is_null() {
+                return Err(io::Error::other("could not create the libinput context"));
+            }
+            let seat = seat.unwrap_or("seat0");
+            let c_seat = std::ffi::CString::new(seat).map_err(io::Error::other)?;
+            if unsafe { (f.libinput_udev_assign_seat)(libinput.context, c_seat.as_ptr()) } != 0 {
+                return Err(io::Error::other(format!(
+                    "libinput could not use the devices of {seat}"
+                )));
+            }
+        } else {
+            libinput.context = unsafe { (f.libinput_path_create_context)(&INTERFACE, data) };
+            if libinput.context.is_null() {
+                return Err(io::Error::other("could not create the libinput context"));
+            }
+            for path in paths {
+                let c_path = std::ffi::CString::new(path.as_str()).map_err(io::Error::other)?;
+                if unsafe { (f.libinput_path_add_device)(libinput.context, c_path.as_ptr()) }
+                    .is_null()
+                {
+                    tracing::warn!("libinput could not open device {path}");
+                }
+            }
+        }
+        // The devices that were opened are not changes.
+        libinput.read(&mut vec![], &mut vec![])?;
+        Ok(libinput)
+    }
+
+    pub(super) fn fd(&self) -> RawFd {
+        unsafe { (self.functions.libinput_get_fd)(self.context) }
+    }
+
+    pub(super) fn device_count(&self) -> usize {
+        self.devices.lock().open.len()
+    }
+
+    /// Grabs or releases the devices, like [`super::KbdIn::set_grabbed`].
+    pub(super) fn set_grabbed(&self, grab: bool) {
+        let mut devices = self.devices.lock();
+        devices.grabbed = grab;
+        for (fd, path) in &devices.open {
+            let res = match grab {
+                true => Device::open(path)
+                    .and_then(|dev| super::wait_for_all_keys_unpressed(&dev))
+                    .and_then(|()| set_grab(*fd, true)),
+                false => set_grab(*fd, false),
+            };
+            if let Err(e) = res {
+                tracing::warn!("failed to change grab of {path}: {e:?}");
+            }
+        }
+    }
+
+    /// Adds the events that libinput has read to `input_events`, as evdev events.
+    pub(super) fn read(
+        &mut self,
+        input_events: &mut Vec<InputEvent>,
+        device_changes: &mut Vec<DeviceChange>,
+    ) -> io::Result<()> {
+        let rc = unsafe { (self.functions.libinput_dispatch)(self.context) };
+        if rc < 0 {
+            return Err(io::Error::from_raw_os_error(-rc));
+        }
+        loop {
+            let event = unsafe { (self.functions.libinput_get_event)(self.context) };
+            if event.is_null() {
+                return Ok(());
+            }
+            self.push_event(event, input_events, device_changes);
+            unsafe { (self.functions.libinput_event_destroy)(event) };
+        }
+    }
+
+    fn push_event(
+        &mut self,
+        event: *mut c_void,
+        input_events: &mut Vec<InputEvent>,
+        device_changes: &mut Vec<DeviceChange>,
+    ) {
+        let f = &self.functions;
+        let syn = InputEvent::new(EventType::SYNCHRONIZATION.0, 0, 0);
+        let rel = |code: RelativeAxisCode, value: i32| {
+            InputEvent::new(EventType::RELATIVE.0, code.0, value)
+        };
+        // SAFETY: the event is valid until it is destroyed, and the events of its type are
+        // taken from it.
+        unsafe {
+            match (f.libinput_event_get_type)(event) {
+                kind @ (EVENT_DEVICE_ADDED | EVENT_DEVICE_REMOVED) => {
+                    let device = (f.libinput_event_get_device)(event);
+                    device_changes.push(DeviceChange {
+                        path: format!(
+                            "/dev/input/{}",
+                            to_string((f.libinput_device_get_sysname)(device))
+                        ),
+                        name: to_string((f.libinput_device_get_name)(device)),
+                        connected: kind == EVENT_DEVICE_ADDED,
+                    });
+                }
+                EVENT_KEYBOARD_KEY => {
+                    let key = (f.libinput_event_get_keyboard_event)(event);
+                    let code = (f.libinput_event_keyboard_get_key)(key) as u16;
+                    let value = (f.libinput_event_keyboard_get_key_state)(key);
+                    input_events.extend([InputEvent::new(EventType::KEY.0, code, value), syn]);
+                }
+                EVENT_POINTER_BUTTON => {
+                    let pointer = (f.libinput_event_get_pointer_event)(event);
+                    let code = (f.libinput_event_pointer_get_button)(pointer) as u16;
+                    let value = (f.libinput_event_pointer_get_button_state)(pointer);
+                    input_events.extend([InputEvent::new(EventType::KEY.0, code, value), syn]);
+                }
+                EVENT_POINTER_MOTION => {
+                    // The output device accelerates the motion, like the device would.
+                    let pointer = (f.libinput_event_get_pointer_event)(event);
+                    let (x, y) = &mut self.motion_remainder;
+                    *x += (f.libinput_event_pointer_get_dx_unaccelerated)(pointer);
+                    *y += (f.libinput_event_pointer_get_dy_unaccelerated)(pointer);
+                    let (dx, dy) = (x.trunc(), y.trunc());
+                    (*x, *y) = (*x - dx, *y - dy);
+                    for (code, value) in
+                        [(RelativeAxisCode::REL_X, dx), (RelativeAxisCode::REL_Y, dy)]
+                    {
+                        if value != 0.0 {
+                            input_events.push(rel(code, value as i32));
+                        }
+                    }
+                    input_events.push(syn);
+                }
+                EVENT_POINTER_SCROLL_WHEEL => {
+                    let pointer = (f.libinput_event_get_pointer_event)(event);
+                    // libinput scrolls down with positive values, and evdev up.
+                    for (axis, sign, code, hi_res) in [
+                        (
+                            POINTER_AXIS_SCROLL_VERTICAL,
+                            -1,
+                            RelativeAxisCode::REL_WHEEL,
+                            RelativeAxisCode::REL_WHEEL_HI_RES,
+                        ),
+                        (
+                            POINTER_AXIS_SCROLL_HORIZONTAL,
+                            1,
+                            RelativeAxisCode::REL_HWHEEL,
+                            RelativeAxisCode::REL_HWHEEL_HI_RES,
+                        ),
+                    ] {
+                        if (f.libinput_event_pointer_has_axis)(pointer, axis) == 0 {
+                            continue;
+                        }
+                        let v120 = sign
+                            * (f.libinput_event_pointer_get_scroll_value_v120)(pointer, axis)
+                                as i32;
+                        input_events.push(rel(hi_res, v120));
+                        if v120 % 120 == 0 {
+                            input_events.push(rel(code, v120 / 120));
+                        }
+                    }
+                    input_events.push(syn);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for Libinput {
+    fn drop(&mut self) {
+        // libinput closes the devices through kanata.
+        unsafe {
+            if !self.context.is_null() {
+                (self.functions.libinput_unref)(self.context);
+            }
+            if !self.udev.is_null() {
+                (self.functions.udev_unref)(self.udev);
+            }
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Output to another kanata over the network, for `linux-output-backend remote`.
+//!
+//! The other kanata listens with `--listen output:ADDRESS,token-file=PATH` and injects the events
+//! on its computer, so that the keyboards of this computer control the other one with the layers
+//! of this configuration. The connection is made by a thread of its own, so that a receiver that
+//! is slow to answer does not hold up processing. Output is dropped while there is no connection.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use evdev::{EventType, InputEvent, RelativeAxisCode};
+use kanata_tcp_protocol::{ClientMessage, RemoteOutput, ServerResponse};
+
+use crate::oskbd::HI_RES_SCROLL_UNITS_IN_LO_RES;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(super) struct RemoteDevice {
+    output: Sender<Vec<u8>>,
+}
+
+impl RemoteDevice {
+    /// Starts connecting to the `output:` listener at the address with the token in the file.
+    pub(super) fn new(address: &str, token_file: &str) -> io::Result<Self> {
+        let token = std::fs::read_to_string(token_file).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("could not read token file {token_file}: {e}"),
+            )
+        })?;
+        let token = token.trim().to_owned();
+        if token.is_empty() {
+            return Err(io::Error::other(format!(
+                "token file {token_file} is empty"
+            )));
+        }
+        let (output, rx) = mpsc::channel();
+        let address = address.to_owned();
+        std::thread::Builder::new()
+            .name("remote-output".into())
+            .spawn(move || run(address, token, rx))?;
+        Ok(Self { output })
+    }
+
+    pub(super) fn emit(&self, events: &[InputEvent]) -> io::Result<()> {
+        let bytes: Vec<u8> = remote_outputs(events)
+            .iter()
+            .flat_map(RemoteOutput::as_bytes)
+            .collect();
+        self.send(bytes)
+    }
+
+    pub(super) fn set_mouse(&self, x: u16, y: u16) -> io::Result<()> {
+        self.send(RemoteOutput::MouseSet { x, y }.as_bytes())
+    }
+
+    fn send(&self, bytes: Vec<u8>) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.output
+            .send(bytes)
+            .map_err(|_| io::Error::other("the remote output thread stopped"))
+    }
+}
+
+/// Writes the output to the receiver until the device is dropped, and connects again after the
+/// connection is lost.
+fn run(address: String, token: String, output: Receiver<Vec<u8>>) {
+    let mut conn = Connection {
+        address,
+        token,
+        stream: None,
+        retry_at: Instant::now(),
+        failed: false,
+    };
+    conn.connect();
+    for bytes in output {
+        conn.connect();
+        let Some(stream) = &mut conn.stream else {
+            continue;
+        };
+        if let Err(e) = stream.write_all(&bytes) {
+            tracing::error!(
+                "lost the connection to the remote output {}: {e}",
+                conn.address
+            );
+            conn.stream = None;
+        }
+    }
+}
+
+struct Connection {
+    address: String,
+    token: String,
+    stream: Option<TcpStream>,
+    /// When to try connecting again after a failure.
+    retry_at: Instant,
+    failed: bool,
+}
+
+impl Connection {
+    /// Connects if there is no connection and it is time to try again.
+    fn connect(&mut self) {
+        if self.stream.is_some() || Instant::now() < self.retry_at {
+            return;
+        }
+        match connect(&self.address, &self.token) {
+            Ok(stream) => {
+                tracing::info!("sending output to {}", self.address);
+                self.failed = false;
+                self.stream = Some(stream);
+            }
+            Err(e) => {
+                // Log once per outage instead of for every output.
+                if !self.failed {
+                    tracing::error!(
+                        "could not connect to the remote output {}, dropping output until it connects: {e}",
+                        self.address
+                    );
+                }
+                self.failed = true;
+                self.retry_at = Instant::now() + RECONNECT_INTERVAL;
+            }
+        }
+    }
+}
+
+fn connect(address: &str, token: &str) -> io::Result<TcpStream> {
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other(format!("{address} has no address")))?;
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let mut auth = serde_json::to_vec(&ClientMessage::Authenticate {
+        token: token.to_owned(),
+    })?;
+    auth.push(b'\n');
+    stream.write_all(&auth)?;
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    match serde_json::from_str(&reply) {
+        Ok(ServerResponse::Ok) => Ok(stream),
+        Ok(ServerResponse::Error { msg } | ServerResponse::Forbidden { msg, .. }) => {
+            Err(io::Error::other(msg))
+        }
+        Err(e) => Err(io::Error::other(format!("unexpected reply {reply:?}: {e}"))),
+    }
+}
+
+/// Converts the events, which are in the format of uinput, to the protocol of the receiver.
+fn remote_outputs(events: &[InputEvent]) -> Vec<RemoteOutput> {
+    // The high resolution scroll events come with the low resolution ones when the scroll adds up
+    // to a notch, so the low resolution ones are only used without them, e.g. from an old mouse.
+    let hi_res = events.iter().any(|ev| {
+        ev.event_type() == EventType::RELATIVE
+            && matches!(
+                RelativeAxisCode(ev.code()),
+                RelativeAxisCode::REL_WHEEL_HI_RES | RelativeAxisCode::REL_HWHEEL_HI_RES
+            )
+    });
+    let notch = i32::from(HI_RES_SCROLL_UNITS_IN_LO_RES);
+    events
+        .iter()
+        .filter_map(|ev| {
+            let value = ev.value();
+            match ev.event_type() {
+                EventType::KEY => Some(RemoteOutput::Key {
+                    code: ev.code(),
+                    value: value.clamp(0, 2) as u8,
+                }),
+                EventType::RELATIVE => match RelativeAxisCode(ev.code()) {
+                    RelativeAxisCode::REL_X => Some(RemoteOutput::MouseMove { x: value, y: 0 }),
+                    RelativeAxisCode::REL_Y => Some(RemoteOutput::MouseMove { x: 0, y: value }),
+                    RelativeAxisCode::REL_WHEEL_HI_RES => Some(RemoteOutput::Scroll {
+                        vertical: value,
+                        horizontal: 0,
+                    }),
+                    RelativeAxisCode::REL_HWHEEL_HI_RES => Some(RemoteOutput::Scroll {
+                        vertical: 0,
+                        horizontal: value,
+                    }),
+                    RelativeAxisCode::REL_WHEEL if !hi_res => Some(RemoteOutput::Scroll {
+                        vertical: value * notch,
+                        horizontal: 0,
+                    }),
+                    RelativeAxisCode::REL_HWHEEL if !hi_res => Some(RemoteOutput::Scroll {
+                        vertical: 0,
+                        horizontal: value * notch,
+                    }),
+                    _ => None,
+                },
+                // SYN has nothing to send, the receiver writes every event as it comes.
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn ev(type_: EventType, code: u16, value: i32) -> InputEvent {
+        InputEvent::new(type_.0, code, value)
+    }
+
+    #[test]
+    fn converts_uinput_events() {
+        let events = [
+            ev(EventType::KEY, 30, 1),
+            ev(EventType::SYNCHRONIZATION, 0, 0),
+            ev(EventType::RELATIVE, RelativeAxisCode::REL_X.0, -4),
+            ev(
+                EventType::RELATIVE,
+                RelativeAxisCode::REL_WHEEL_HI_RES.0,
+                120,
+            ),
+            ev(EventType::RELATIVE, RelativeAxisCode::REL_WHEEL.0, 1),
+        ];
+        assert_eq!(
+            remote_outputs(&events),
+            [
+                RemoteOutput::Key { code: 30, value: 1 },
+                RemoteOutput::MouseMove { x: -4, y: 0 },
+                RemoteOutput::Scroll {
+                    vertical: 120,
+                    horizontal: 0
+                },
+            ]
+        );
+        let events = [ev(EventType::RELATIVE, RelativeAxisCode::REL_HWHEEL.0, -1)];
+        assert_eq!(
+            remote_outputs(&events),
+            [RemoteOutput::Scroll {
+                vertical: 0,
+                horizontal: -120
+            }]
+        );
+    }
+
+    #[test]
+    fn authenticates_before_sending() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let receiver = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut lines = BufReader::new(&stream).lines();
+            let auth = lines.next().unwrap().unwrap();
+            (&stream).write_all(&ServerResponse::Ok.as_bytes()).unwrap();
+            (auth, lines.next().unwrap().unwrap())
+        });
+        let token_file = std::env::temp_dir().join(format!("kanata-remote-{}", std::process::id()));
+        std::fs::write(&token_file, "secret\n").unwrap();
+        let device = RemoteDevice::new(&address, &token_file.to_string_lossy()).unwrap();
+        device.emit(&[ev(EventType::KEY, 30, 0)]).unwrap();
+        let (auth, event) = receiver.join().unwrap();
+        let _ = std::fs::remove_file(&token_file);
+        assert_eq!(auth, r#"{"Authenticate":{"token":"secret"}}"#);
+        assert_eq!(event, r#"{"Key":{"code":30,"value":0}}"#);
+    }
+}
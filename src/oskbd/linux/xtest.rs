@@ -0,0 +1,149 @@
+//! Output through the XTEST extension of the X server, for `linux-output-backend xtest`.
+//!
+//! This does not need access to `/dev/uinput`, but the events only reach the clients of the X
+//! server that kanata connects to, and the clients can tell that they are synthetic.
+
+use std::io;
+
+use evdev::{EventType, InputEvent, KeyCode, RelativeAxisCode};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT, ConnectionExt as _, KEY_PRESS_EVENT,
+    KEY_RELEASE_EVENT, MOTION_NOTIFY_EVENT, Window,
+};
+use x11rb::protocol::xtest::ConnectionExt;
+use x11rb::rust_connection::RustConnection;
+
+use crate::oskbd::MonitorRect;
+
+/// X keycodes are evdev keycodes shifted by 8, which is what the evdev driver of X does.
+const X_KEYCODE_OFFSET: u16 = 8;
+
+pub(super) struct XtestDevice {
+    conn: RustConnection,
+    root: Window,
+    /// The whole X screen, which is treated as a single monitor.
+    screen: MonitorRect,
+}
+
+impl XtestDevice {
+    /// Connects to the X server of `$DISPLAY`.
+    pub(super) fn new() -> io::Result<Self> {
+        let (conn, screen) = x11rb::connect(None).map_err(|e| {
+            io::Error::other(format!("could not connect to the X server for xtest: {e}"))
+        })?;
+        conn.xtest_get_version(2, 2)
+            .map_err(io::Error::other)?
+            .reply()
+            .map_err(|e| {
+                io::Error::other(format!(
+                    "the X server does not support the XTEST extension: {e}"
+                ))
+            })?;
+        let screen = &conn.setup().roots[screen];
+        let (root, screen) = (
+            screen.root,
+            MonitorRect {
+                x: 0,
+                y: 0,
+                width: screen.width_in_pixels.into(),
+                height: screen.height_in_pixels.into(),
+            },
+        );
+        tracing::info!("sending output through XTEST");
+        Ok(Self { conn, root, screen })
+    }
+
+    /// Sends the events, which are in the format of uinput, as X input events.
+    pub(super) fn emit(&self, events: &[InputEvent]) -> io::Result<()> {
+        for event in events {
+            match event.event_type() {
+                EventType::KEY => self.key(event.code(), event.value())?,
+                EventType::RELATIVE => self.relative(event.code(), event.value())?,
+                // SYN has nothing to send, X does not group events in frames.
+                _ => {}
+            }
+        }
+        self.conn.flush().map_err(io::Error::other)
+    }
+
+    /// Moves the pointer to the position on the screen.
+    pub(super) fn set_mouse(&self, x: u16, y: u16) -> io::Result<()> {
+        let (x, y) = (x.min(i16::MAX as u16) as i16, y.min(i16::MAX as u16) as i16);
+        self.fake_input(MOTION_NOTIFY_EVENT, 0, x, y)?;
+        self.conn.flush().map_err(io::Error::other)
+    }
+
+    /// Returns the pointer position on the screen.
+    pub(super) fn mouse_position(&self) -> io::Result<(i32, i32)> {
+        let reply = self
+            .conn
+            .query_pointer(self.root)
+            .map_err(io::Error::other)?
+            .reply()
+            .map_err(io::Error::other)?;
+        Ok((reply.root_x.into(), reply.root_y.into()))
+    }
+
+    pub(super) fn screen(&self) -> MonitorRect {
+        self.screen
+    }
+
+    fn key(&self, code: u16, value: i32) -> io::Result<()> {
+        let (press, release, detail) = match mouse_button(code) {
+            Some(button) => (BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT, button),
+            None => match u8::try_from(code + X_KEYCODE_OFFSET) {
+                Ok(keycode) => (KEY_PRESS_EVENT, KEY_RELEASE_EVENT, keycode),
+                Err(_) => {
+                    tracing::debug!("key code {code} has no X keycode, not sending it");
+                    return Ok(());
+                }
+            },
+        };
+        match value {
+            0 => self.fake_input(release, detail, 0, 0),
+            // X repeats keys itself, but an explicit repeat is sent as another press.
+            _ => self.fake_input(press, detail, 0, 0),
+        }
+    }
+
+    fn relative(&self, code: u16, value: i32) -> io::Result<()> {
+        let clamp = |v: i32| v.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+        match RelativeAxisCode(code) {
+            RelativeAxisCode::REL_X => self.fake_input(MOTION_NOTIFY_EVENT, 1, clamp(value), 0),
+            RelativeAxisCode::REL_Y => self.fake_input(MOTION_NOTIFY_EVENT, 1, 0, clamp(value)),
+            // X scrolls by buttons 4 to 7, one click per notch. The high resolution events are
+            // sent together with these once they add up to a notch, so they are ignored.
+            RelativeAxisCode::REL_WHEEL => self.scroll(if value > 0 { 4 } else { 5 }, value),
+            RelativeAxisCode::REL_HWHEEL => self.scroll(if value > 0 { 7 } else { 6 }, value),
+            _ => Ok(()),
+        }
+    }
+
+    fn scroll(&self, button: u8, notches: i32) -> io::Result<()> {
+        for _ in 0..notches.unsigned_abs() {
+            self.fake_input(BUTTON_PRESS_EVENT, button, 0, 0)?;
+            self.fake_input(BUTTON_RELEASE_EVENT, button, 0, 0)?;
+        }
+        Ok(())
+    }
+
+    fn fake_input(&self, type_: u8, detail: u8, x: i16, y: i16) -> io::Result<()> {
+        self.conn
+            .xtest_fake_input(type_, detail, x11rb::CURRENT_TIME, self.root, x, y, 0)
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// The X button of an evdev mouse button.
+fn mouse_button(code: u16) -> Option<u8> {
+    match KeyCode(code) {
+        KeyCode::BTN_LEFT => Some(1),
+        KeyCode::BTN_MIDDLE => Some(2),
+        KeyCode::BTN_RIGHT => Some(3),
+        KeyCode::BTN_SIDE => Some(8),
+        KeyCode::BTN_EXTRA => Some(9),
+        _ => None,
+    }
+}
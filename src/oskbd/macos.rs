@@ -9,6 +9,7 @@
 
 use super::*;
 use crate::kanata::CalculatedMouseMove;
+use crate::kanata::EventSender;
 use crate::oskbd::KeyEvent;
 use anyhow::anyhow;
 use core_foundation::base::{CFType, TCFType};
@@ -36,7 +37,6 @@ use std::io;
 use std::io::Error;
 use std::sync::OnceLock;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::SyncSender as Sender;
 use std::time::{Duration, Instant};
 
 /// Mouse `OsCode`s that, when present in `MAPPED_KEYS`, justify installing the
@@ -57,7 +57,7 @@ const MOUSE_OSCODES: [OsCode; 9] = [
 /// Sender stashed by the first `start_mouse_listener` call so that
 /// `ensure_mouse_listener_installed_after_reload` can install the tap on a
 /// later live reload without needing the original `event_loop` context.
-static MOUSE_TAP_TX: OnceLock<Sender<KeyEvent>> = OnceLock::new();
+static MOUSE_TAP_TX: OnceLock<EventSender> = OnceLock::new();
 
 /// Tracks whether `start_mouse_listener` has *claimed* the install slot —
 /// i.e. promised to spawn a thread that will create and enable a CGEventTap.
@@ -253,6 +253,24 @@ pub enum AccessibilityPermissionStatus {
     Requested,
 }
 
+/// Returns whether kanata has the Input Monitoring permission, or `None` if the user has not
+/// decided yet. Unlike the startup check, this never asks for the permission.
+pub fn input_monitoring_granted() -> Option<bool> {
+    // SAFETY: plain FFI call with a scalar arg.
+    match unsafe { IOHIDCheckAccess(K_IOHID_REQUEST_TYPE_LISTEN_EVENT) } {
+        K_IOHID_ACCESS_TYPE_GRANTED => Some(true),
+        K_IOHID_ACCESS_TYPE_DENIED => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns whether the current process is trusted for Accessibility, without prompting. See the
+/// caveat in [`request_accessibility_permission`] about processes launched from Terminal.
+pub fn accessibility_trusted() -> bool {
+    // SAFETY: plain FFI call with no args.
+    unsafe { AXIsProcessTrusted() }
+}
+
 /// Pre-flight check for Input Monitoring permission. Returns `Ok(())`
 /// if kanata is allowed to observe events; otherwise returns an
 /// `anyhow::Error` that surfaces as the startup failure reason,
@@ -272,7 +290,7 @@ fn ensure_input_monitoring_permission() -> Result<(), anyhow::Error> {
     match status {
         K_IOHID_ACCESS_TYPE_GRANTED => Ok(()),
         K_IOHID_ACCESS_TYPE_UNKNOWN => {
-            log::info!(
+            tracing::info!(
                 "macOS Input Monitoring permission not yet decided; \
                  asking IOKit to register kanata under System Settings"
             );
@@ -290,7 +308,7 @@ fn ensure_input_monitoring_permission() -> Result<(), anyhow::Error> {
             "macOS Input Monitoring permission is denied for kanata. {HINT}"
         )),
         other => {
-            log::warn!(
+            tracing::warn!(
                 "IOHIDCheckAccess returned unexpected status {other}; \
                  continuing and letting the driver layer report any failure"
             );
@@ -329,7 +347,7 @@ pub fn request_accessibility_permission() -> Result<AccessibilityPermissionStatu
         return Ok(AccessibilityPermissionStatus::Trusted);
     }
 
-    log::info!(
+    tracing::info!(
         "macOS Accessibility permission not yet granted; \
          asking ApplicationServices to register/prompt kanata under System Settings"
     );
@@ -528,7 +546,7 @@ pub fn start_screen_lock_poller() {
     // free 200ms grab window before the first poll iteration.
     let initial_paused = should_pause_for_session();
     SCREEN_GRAB_PAUSED.store(initial_paused, Ordering::Release);
-    log::info!(
+    tracing::info!(
         "screen-lock poller: starting (initial state: {})",
         if initial_paused { "paused" } else { "active" },
     );
@@ -542,11 +560,11 @@ pub fn start_screen_lock_poller() {
                 if now_paused != last_paused {
                     SCREEN_GRAB_PAUSED.store(now_paused, Ordering::Release);
                     if now_paused {
-                        log::info!(
+                        tracing::info!(
                             "screen lock or user-switch detected — keyboard grab will pause on next event"
                         );
                     } else {
-                        log::info!(
+                        tracing::info!(
                             "console session restored — keyboard grab will resume"
                         );
                     }
@@ -556,7 +574,7 @@ pub fn start_screen_lock_poller() {
             }
         })
     {
-        log::warn!("failed to spawn screen-lock poller thread: {e}");
+        tracing::warn!("failed to spawn screen-lock poller thread: {e}");
     }
 }
 
@@ -594,6 +612,8 @@ impl From<InputEvent> for DKEvent {
 pub struct KbdIn {
     grabbed: bool,
     device_hash_to_id: HashMap<u64, std::num::NonZeroU8>,
+    /// The pages on which the Fn key of each device is held, see [`KbdIn::is_duplicate_fn`].
+    fn_pages: HashMap<u64, Vec<u32>>,
 }
 
 impl Drop for KbdIn {
@@ -678,6 +698,7 @@ impl KbdIn {
                 Ok(Self {
                     grabbed: true,
                     device_hash_to_id,
+                    fn_pages: HashMap::new(),
                 })
             } else {
                 // We have already pre-flighted Input Monitoring and
@@ -711,23 +732,44 @@ impl KbdIn {
     }
 
     pub fn read(&mut self) -> Result<InputEvent, io::Error> {
-        let mut event = DKEvent {
-            value: 0,
-            page: 0,
-            code: 0,
-            device_hash: 0,
-        };
-
-        let got_event = wait_key(&mut event);
-        if got_event == 0 {
-            // Pipe returned EOF — input was released via release_input_only()
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "input pipe closed (devices released)",
-            ));
+        loop {
+            let mut event = DKEvent {
+                value: 0,
+                page: 0,
+                code: 0,
+                device_hash: 0,
+            };
+            let got_event = wait_key(&mut event);
+            if got_event == 0 {
+                // Pipe returned EOF — input was released via release_input_only()
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "input pipe closed (devices released)",
+                ));
+            }
+            let event = InputEvent::new(event);
+            if !self.is_duplicate_fn(&event) {
+                return Ok(event);
+            }
         }
+    }
 
-        Ok(InputEvent::new(event))
+    /// Some Apple keyboards send the Globe/Fn key on both the Apple vendor top case page and
+    /// the Apple vendor keyboard page, which are both the `fn` key. Only the first press and the
+    /// last release of a device are kept, so that `fn` is not pressed twice.
+    fn is_duplicate_fn(&mut self, event: &InputEvent) -> bool {
+        if !matches!((event.page, event.code), (0xFF | 0xFF01, 0x03)) {
+            return false;
+        }
+        let pages = self.fn_pages.entry(event.device_hash).or_default();
+        let was_held = !pages.is_empty();
+        pages.retain(|page| *page != event.page);
+        if event.value == 1 {
+            pages.push(event.page);
+            was_held
+        } else {
+            !pages.is_empty()
+        }
     }
 
     /// Look up the device ID for an event's device hash.
@@ -787,6 +829,30 @@ fn is_skipped_virtual_device(product_key: &str) -> bool {
         .any(|needle| lower.contains(needle))
 }
 
+/// Returns whether kanata would grab the device with these `macos-dev-names-include` and
+/// `macos-dev-names-exclude` options.
+pub fn would_grab_device(
+    device: &DeviceData,
+    include_names: Option<&[String]>,
+    exclude_names: Option<&[String]>,
+) -> bool {
+    match include_names {
+        Some(include_names) => {
+            !device.product_key.to_lowercase().contains("karabiner")
+                && include_names
+                    .iter()
+                    .any(|n| !n.trim().is_empty() && device.eq(n.as_str()))
+        }
+        None => {
+            !exclude_names
+                .unwrap_or_default()
+                .iter()
+                .any(|n| device.eq(n.as_str()))
+                && !is_skipped_virtual_device(&device.product_key)
+        }
+    }
+}
+
 /// Build a mapping from device hashes to configured device IDs by matching
 /// the connected devices against the `definputdevices` matchers.
 fn build_device_hash_to_id_map(
@@ -816,7 +882,7 @@ fn build_device_hash_to_id_map(
                 .is_none_or(|p| u16::try_from(kb.product_id) == Ok(p));
             if name_matches && hash_matches && vendor_matches && product_matches {
                 if let Some(existing_id) = map.get(&kb.hash) {
-                    log::warn!(
+                    tracing::warn!(
                         "definputdevices: device \"{}\" (hash {:x}) also matches ID {id}, \
                          keeping first match ID {existing_id}",
                         kb.product_key,
@@ -824,7 +890,7 @@ fn build_device_hash_to_id_map(
                     );
                     continue;
                 }
-                log::info!(
+                tracing::info!(
                     "definputdevices: device ID {id} matched \"{}\" (hash {:x})",
                     kb.product_key,
                     kb.hash
@@ -842,7 +908,7 @@ fn validate_and_register_devices(include_names: Vec<String>) -> Vec<String> {
         .filter_map(|dev| {
             // Defensive check: skip empty device names that could cause crashes
             if dev.trim().is_empty() {
-                log::warn!("Skipping empty device name (likely old keyboard without proper identification)");
+                tracing::warn!("Skipping empty device name (likely old keyboard without proper identification)");
                 return None;
             }
 
@@ -855,7 +921,7 @@ fn validate_and_register_devices(include_names: Vec<String>) -> Vec<String> {
             match device_matches(dev) {
                 true => Some(dev.to_string()),
                 false => {
-                    log::warn!("'{dev}' doesn't match any connected device");
+                    tracing::warn!("'{dev}' doesn't match any connected device");
                     None
                 }
             }
@@ -864,7 +930,7 @@ fn validate_and_register_devices(include_names: Vec<String>) -> Vec<String> {
             if register_device(&dev) {
                 Some(dev.to_string())
             } else {
-                log::warn!("Couldn't register device '{}' - device may be in use by another application or disconnected", dev);
+                tracing::warn!("Couldn't register device '{}' - device may be in use by another application or disconnected", dev);
                 None
             }
         })
@@ -953,7 +1019,7 @@ fn hid_system_param_connect() -> Option<u32> {
             IOServiceMatching(service_name.as_ptr())
         };
         if matching.is_null() {
-            log::warn!("failed to create IOHIDSystem matching dictionary");
+            tracing::warn!("failed to create IOHIDSystem matching dictionary");
             return None;
         }
 
@@ -963,7 +1029,7 @@ fn hid_system_param_connect() -> Option<u32> {
             IOServiceGetMatchingService(0, matching)
         };
         if service == 0 {
-            log::warn!("failed to find IOHIDSystem service for Caps Lock LED sync");
+            tracing::warn!("failed to find IOHIDSystem service for Caps Lock LED sync");
             return None;
         }
 
@@ -987,7 +1053,7 @@ fn hid_system_param_connect() -> Option<u32> {
         if rc == 0 && connect != 0 {
             Some(connect)
         } else {
-            log::warn!("failed to open IOHIDSystem parameter connection: rc={rc}");
+            tracing::warn!("failed to open IOHIDSystem parameter connection: rc={rc}");
             None
         }
     })
@@ -1005,7 +1071,7 @@ fn get_hid_caps_lock_state() -> Option<bool> {
     if rc == 0 {
         Some(state)
     } else {
-        log::warn!("failed to read IOHID Caps Lock state: rc={rc}");
+        tracing::warn!("failed to read IOHID Caps Lock state: rc={rc}");
         None
     }
 }
@@ -1049,7 +1115,7 @@ fn drop_if_sink_disconnected(
     value: KeyValue,
 ) -> Result<(), io::Error> {
     if err.kind() == io::ErrorKind::NotConnected {
-        log::warn!("dropping {key:?} {value:?}: output backend unavailable (will recover)");
+        tracing::warn!("dropping {key:?} {value:?}: output backend unavailable (will recover)");
         Ok(())
     } else {
         Err(err)
@@ -1072,7 +1138,7 @@ impl KbdOut {
 
         let next_state = self.caps_lock_state.unwrap_or_else(|| {
             get_hid_caps_lock_state().unwrap_or_else(|| {
-                log::warn!(
+                tracing::warn!(
                     "could not read current Caps Lock state before toggle; assuming it is off"
                 );
                 false
@@ -1086,12 +1152,12 @@ impl KbdOut {
 
     pub fn write(&mut self, event: InputEvent) -> Result<(), io::Error> {
         if event.page == 0x07 && event.code == 0x39 {
-            log::debug!("Attempting to set Caps Lock state from {event:?}");
+            tracing::debug!("Attempting to set Caps Lock state from {event:?}");
             return self.write_caps_lock(event.value);
         }
 
         let mut devent = event.into();
-        log::debug!("Attempting to write {event:?} {devent:?}");
+        tracing::debug!("Attempting to write {event:?} {devent:?}");
         let rc = send_key(&mut devent);
         if rc == 2 {
             return Err(io::Error::new(
@@ -1124,13 +1190,13 @@ impl KbdOut {
             attempt += 1;
             if attempt % 10 == 0 {
                 if let Some(timeout) = timeout {
-                    log::info!(
+                    tracing::info!(
                         "Waiting for DriverKit virtual keyboard... ({:.1}s/{:.1}s)",
                         start.elapsed().as_secs_f64(),
                         timeout.as_secs_f64()
                     );
                 } else {
-                    log::info!(
+                    tracing::info!(
                         "Waiting for DriverKit virtual keyboard... ({:.1}s)",
                         start.elapsed().as_secs_f64()
                     );
@@ -1151,14 +1217,14 @@ impl KbdOut {
                 Err(e) => drop_if_sink_disconnected(e, key, value),
             }
         } else {
-            log::debug!("couldn't write unrecognized {key:?}");
+            tracing::debug!("couldn't write unrecognized {key:?}");
             Err(io::Error::other("OsCode not recognized!"))
         }
     }
 
     pub fn write_code(&mut self, code: u32, value: KeyValue) -> Result<(), io::Error> {
         let Some(key) = OsCode::from_u16(code as u16) else {
-            log::debug!("couldn't write unrecognized OsCode {code}");
+            tracing::debug!("couldn't write unrecognized OsCode {code}");
             return Err(io::Error::other("OsCode not recognized!"));
         };
         if let Ok(event) = InputEvent::try_from(KeyEvent::new(key, value)) {
@@ -1167,11 +1233,21 @@ impl KbdOut {
                 Err(e) => drop_if_sink_disconnected(e, key, value),
             }
         } else {
-            log::debug!("couldn't write unrecognized OsCode {code}");
+            tracing::debug!("couldn't write unrecognized OsCode {code}");
             Err(io::Error::other("OsCode not recognized!"))
         }
     }
 
+    /// The virtual HID device sends the usage as is.
+    pub fn write_hid_usage(&mut self, usage: HidUsage, value: KeyValue) -> Result<(), io::Error> {
+        self.write(InputEvent {
+            value: u64::from(matches!(value, KeyValue::Press | KeyValue::Repeat)),
+            page: usage.page_id(),
+            code: u32::from(usage.usage),
+            device_hash: 0,
+        })
+    }
+
     pub fn press_key(&mut self, key: OsCode) -> Result<(), io::Error> {
         self.write_key(key, KeyValue::Press)
     }
@@ -1188,7 +1264,7 @@ impl KbdOut {
 
         for key in tracked_keys {
             if let Err(error) = self.write_key(key, KeyValue::Release) {
-                log::warn!(
+                tracing::warn!(
                     "failed to release tracked output key during {} recovery: key={key:?} error={error}",
                     reason
                 );
@@ -1211,6 +1287,32 @@ impl KbdOut {
         event.post(CGEventTapLocation::AnnotatedSession);
         Ok(())
     }
+    /// Sends the text in as few events as possible. An event holds at most 20 UTF-16 code units,
+    /// so longer text is split between characters.
+    pub fn send_unicode_str(&mut self, text: &str) -> Result<(), io::Error> {
+        const MAX_EVENT_UNITS: usize = 20;
+        let mut units: Vec<u16> = Vec::with_capacity(MAX_EVENT_UNITS);
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            let mut arr = [0u16; 2];
+            units.extend_from_slice(c.encode_utf16(&mut arr));
+            let next_fits = chars
+                .peek()
+                .is_some_and(|next| units.len() + next.len_utf16() <= MAX_EVENT_UNITS);
+            if next_fits {
+                continue;
+            }
+            let event = Self::make_event()?;
+            event.set_string_from_utf16_unchecked(&units);
+            event.set_type(CGEventType::KeyDown);
+            event.post(CGEventTapLocation::AnnotatedSession);
+            event.set_type(CGEventType::KeyUp);
+            event.post(CGEventTapLocation::AnnotatedSession);
+            units.clear();
+        }
+        Ok(())
+    }
+
     pub fn scroll(&mut self, direction: MWheelDirection, distance: u16) -> Result<(), io::Error> {
         let event = Self::make_event()?;
         event.set_type(CGEventType::ScrollWheel);
@@ -1378,6 +1480,61 @@ impl KbdOut {
         Ok(())
     }
 
+    /// Monitor 0 is the main display. The rest are in the order that macOS lists them.
+    pub fn warp_mouse_in_monitor(
+        &mut self,
+        monitor: usize,
+        x: f64,
+        y: f64,
+    ) -> Result<(), io::Error> {
+        let displays = CGDisplay::active_displays()
+            .map_err(|_| io::Error::other("failed to list displays"))?;
+        let Some(display) = displays.get(monitor) else {
+            tracing::warn!("mouse-grid: monitor {} was not found", monitor + 1);
+            return Ok(());
+        };
+        let bounds = CGDisplay::new(*display).bounds();
+        let point = CGPoint::new(
+            bounds.origin.x + bounds.size.width * x as CGFloat,
+            bounds.origin.y + bounds.size.height * y as CGFloat,
+        );
+        CGDisplay::warp_mouse_cursor_position(point)
+            .map_err(|_| io::Error::other("failed to move cursor to point"))?;
+        Ok(())
+    }
+
+    /// Monitors are numbered as in `warp_mouse_in_monitor`.
+    pub fn move_mouse_toward(&mut self, action: &MouseToward) -> Result<(), io::Error> {
+        let Some(pointer) = self.mouse_position()? else {
+            return Ok(());
+        };
+        let monitors = CGDisplay::active_displays()
+            .map_err(|_| io::Error::other("failed to list displays"))?
+            .into_iter()
+            .map(|display| {
+                let bounds = CGDisplay::new(display).bounds();
+                MonitorRect {
+                    x: bounds.origin.x as i32,
+                    y: bounds.origin.y as i32,
+                    width: bounds.size.width as i32,
+                    height: bounds.size.height as i32,
+                }
+            })
+            .collect::<Vec<_>>();
+        let Some((x, y)) = mouse_toward_target(pointer, &monitors, action) else {
+            tracing::warn!("mouse-toward: the monitor was not found");
+            return Ok(());
+        };
+        CGDisplay::warp_mouse_cursor_position(CGPoint::new(x as CGFloat, y as CGFloat))
+            .map_err(|_| io::Error::other("failed to move cursor to point"))?;
+        Ok(())
+    }
+
+    pub fn mouse_position(&mut self) -> Result<Option<(i32, i32)>, io::Error> {
+        let location = Self::make_event()?.location();
+        Ok(Some((location.x as i32, location.y as i32)))
+    }
+
     fn make_event_source() -> Result<CGEventSource, Error> {
         CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
             .map_err(|_| Error::other("failed to create core graphics event source"))
@@ -1494,7 +1651,7 @@ fn scroll_event_to_key_event(event: &CGEvent) -> Option<KeyEvent> {
 ///
 /// Requires Accessibility or Input Monitoring permission.
 pub fn start_mouse_listener(
-    tx: Sender<KeyEvent>,
+    tx: EventSender,
     mapped_keys: &MappedKeys,
     mouse_movement_key: std::sync::Arc<parking_lot::Mutex<Option<OsCode>>>,
 ) -> Option<std::thread::JoinHandle<()>> {
@@ -1521,7 +1678,7 @@ pub fn start_mouse_listener(
     let has_mouse_keys = MOUSE_OSCODES.iter().any(|c| mapped_keys.contains(c));
     let has_movement_key = mouse_movement_key.lock().is_some();
     if !has_mouse_keys && !has_movement_key {
-        log::info!(
+        tracing::info!(
             "No mouse buttons/wheel in defsrc and no mouse-movement-key configured. \
              Not installing mouse event tap."
         );
@@ -1599,7 +1756,7 @@ pub fn start_mouse_listener(
                                 // Drops are expected under high movement rates;
                                 // the user only needs one tap to refresh their
                                 // hold timer, so this is not user-visible.
-                                log::trace!("mouse tap (movement): drop synthetic tap: {e}");
+                                tracing::trace!("mouse tap (movement): drop synthetic tap: {e}");
                             }
                         }
                         return Some(event.clone());
@@ -1612,9 +1769,9 @@ pub fn start_mouse_listener(
                         if !crate::kanata::MAPPED_KEYS.lock().contains(&key_event.code) {
                             return Some(event.clone());
                         }
-                        log::debug!("mouse tap (wheel): {key_event:?}");
+                        tracing::debug!("mouse tap (wheel): {key_event:?}");
                         if let Err(e) = tx.try_send(key_event) {
-                            log::warn!("mouse tap: failed to send wheel event: {e}");
+                            tracing::warn!("mouse tap: failed to send wheel event: {e}");
                             return Some(event.clone());
                         }
                         return None;
@@ -1648,10 +1805,10 @@ pub fn start_mouse_listener(
                         _ => {}
                     }
 
-                    log::debug!("mouse tap: {key_event:?}");
+                    tracing::debug!("mouse tap: {key_event:?}");
 
                     if let Err(e) = tx.try_send(key_event) {
-                        log::warn!("mouse tap: failed to send event: {e}");
+                        tracing::warn!("mouse tap: failed to send event: {e}");
                         return Some(event.clone());
                     }
 
@@ -1661,7 +1818,7 @@ pub fn start_mouse_listener(
             ) {
                 Ok(tap) => tap,
                 Err(()) => {
-                    log::error!(
+                    tracing::error!(
                         "Failed to create mouse event tap. \
                          Ensure kanata has Accessibility or Input Monitoring permission \
                          in System Settings > Privacy & Security."
@@ -1674,7 +1831,7 @@ pub fn start_mouse_listener(
             };
 
             let Ok(loop_source) = tap.mach_port.create_runloop_source(0) else {
-                log::error!("failed to create CFRunLoop source for mouse event tap");
+                tracing::error!("failed to create CFRunLoop source for mouse event tap");
                 MOUSE_TAP_INSTALLED.store(false, Ordering::Release);
                 return;
             };
@@ -1685,14 +1842,14 @@ pub fn start_mouse_listener(
             tap.enable();
             // MOUSE_TAP_INSTALLED was already set by the caller via
             // compare_exchange before this thread was spawned.
-            log::info!("Mouse event tap installed and active.");
+            tracing::info!("Mouse event tap installed and active.");
             CFRunLoop::run_current();
         });
 
     match spawn_result {
         Ok(handle) => Some(handle),
         Err(e) => {
-            log::error!("failed to spawn mouse event tap thread: {e}");
+            tracing::error!("failed to spawn mouse event tap thread: {e}");
             MOUSE_TAP_INSTALLED.store(false, Ordering::Release);
             None
         }
@@ -1711,11 +1868,11 @@ pub fn ensure_mouse_listener_installed_after_reload() {
         return;
     }
     let Some(tx) = MOUSE_TAP_TX.get().cloned() else {
-        log::debug!("mouse tap reload hook: no tx stashed yet, skipping");
+        tracing::debug!("mouse tap reload hook: no tx stashed yet, skipping");
         return;
     };
     let Some(mmk) = MOUSE_MOVEMENT_KEY.get().cloned() else {
-        log::debug!("mouse tap reload hook: no mouse_movement_key stashed yet, skipping");
+        tracing::debug!("mouse tap reload hook: no mouse_movement_key stashed yet, skipping");
         return;
     };
     let mapped = crate::kanata::MAPPED_KEYS.lock();
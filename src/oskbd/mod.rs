@@ -41,6 +41,10 @@ mod simulated; // has KbdOut
     )
 ))]
 pub use simulated::*;
+#[cfg(all(feature = "simulated_output", not(feature = "simulated_input")))]
+mod json_output;
+#[cfg(all(feature = "simulated_output", not(feature = "simulated_input")))]
+pub use json_output::*;
 #[cfg(any(
     all(feature = "simulated_input", feature = "simulated_output"),
     all(
@@ -107,6 +111,10 @@ pub struct KeyEvent {
     pub value: KeyValue,
     #[cfg(not(all(target_os = "windows", not(feature = "interception_driver"))))]
     device_id: Option<std::num::NonZeroU8>,
+    /// When the event loop received the event, if latency is measured.
+    received: Option<web_time::Instant>,
+    /// When the event happened according to the OS, if it reports it.
+    time: Option<std::time::SystemTime>,
 }
 
 #[allow(dead_code, unused)]
@@ -117,9 +125,21 @@ impl KeyEvent {
             value,
             #[cfg(not(all(target_os = "windows", not(feature = "interception_driver"))))]
             device_id: None,
+            received: crate::kanata::received_at(),
+            time: None,
         }
     }
 
+    /// Returns when the event happened according to the OS, which is only known on Linux.
+    pub fn time(&self) -> Option<std::time::SystemTime> {
+        self.time
+    }
+
+    /// Returns when the event loop received this event, if latency is measured.
+    pub fn received(&self) -> Option<web_time::Instant> {
+        self.received
+    }
+
     /// Returns the device ID that produced this event, if known.
     /// Always returns `None` on Windows LLHOOK (which cannot distinguish devices).
     pub fn device_id(&self) -> Option<std::num::NonZeroU8> {
@@ -170,3 +190,50 @@ impl fmt::Debug for KeyEvent {
             .finish()
     }
 }
+
+// ------------------ MonitorRect --------------------
+
+/// The bounds of a monitor in pixels of the desktop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MonitorRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl MonitorRect {
+    fn contains(&self, (x, y): (i32, i32)) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+/// Returns the position that `mouse-toward` moves the pointer to, or None if its monitor does not
+/// exist. The monitors are in the order that `monitor-N` numbers them.
+#[allow(dead_code)]
+pub fn mouse_toward_target(
+    pointer: (i32, i32),
+    monitors: &[MonitorRect],
+    action: &kanata_parser::custom_action::MouseToward,
+) -> Option<(i32, i32)> {
+    let monitor = match action.monitor {
+        Some(monitor) => monitors.get(usize::from(monitor))?,
+        None => monitors
+            .iter()
+            .find(|monitor| monitor.contains(pointer))
+            .or(monitors.first())?,
+    };
+    let anchor = |start: i32, len: i32, percent: u8| {
+        start + (i64::from(len - 1) * i64::from(percent) / 100) as i32
+    };
+    let step = |from: i32, to: i32| {
+        from + (f64::from(to - from) * f64::from(action.percent) / 100.0).round() as i32
+    };
+    Some((
+        step(pointer.0, anchor(monitor.x, monitor.width, action.anchor_x)),
+        step(
+            pointer.1,
+            anchor(monitor.y, monitor.height, action.anchor_y),
+        ),
+    ))
+}
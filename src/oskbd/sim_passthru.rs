@@ -3,9 +3,10 @@
 // todo: only press/release_key is implemented
 use super::*;
 use anyhow::Result;
-use log::*;
+use tracing::{debug, error, trace};
 
 use crate::kanata::CalculatedMouseMove;
+use kanata_parser::cfg::SoundCue;
 use kanata_parser::custom_action::*;
 
 use std::io;
@@ -39,6 +40,7 @@ impl KbdOut {
         _tp: bool,
         _name: &str,
         _bustype: evdev::BusType,
+        _opts: &kanata_parser::cfg::CfgLinuxOptions,
     ) -> Result<Self, io::Error> {
         Ok(Self { tx_kout: None })
     }
@@ -98,6 +100,18 @@ impl KbdOut {
         trace!("out-code:{code};{value:?}");
         Ok(())
     }
+    pub fn write_hid_usage(&mut self, usage: HidUsage, value: KeyValue) -> Result<(), io::Error> {
+        trace!("out-hid:{usage};{value:?}");
+        Ok(())
+    }
+    #[cfg(target_os = "linux")]
+    pub fn set_lock_leds(&mut self, leds: Option<kanata_parser::cfg::LockLeds>) {
+        trace!("leds:{leds:?}");
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_event_time(&mut self, _time: Option<std::time::SystemTime>) {}
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_output_route(&mut self, _device_id: Option<std::num::NonZeroU8>) {}
     pub fn press_key(&mut self, key: OsCode) -> Result<(), io::Error> {
         self.write_key(key, KeyValue::Press)
     }
@@ -109,6 +123,10 @@ impl KbdOut {
         trace!("outU:{c}");
         Ok(())
     }
+    pub fn send_unicode_str(&mut self, text: &str) -> Result<(), io::Error> {
+        trace!("outU:{text}");
+        Ok(())
+    }
     pub fn click_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
         trace!("out🖰:↓{btn:?}");
         Ok(())
@@ -133,10 +151,56 @@ impl KbdOut {
         }
         Ok(())
     }
+    pub fn write_midi(&mut self, msg: [u8; 3]) {
+        trace!("out-midi:{msg:02X?}");
+    }
+    pub fn write_sound(&mut self, cue: &SoundCue) {
+        trace!("out-sound:{cue:?}");
+    }
+    pub fn write_obs(&mut self, action: ObsAction) {
+        trace!("out-obs:{}", action.name());
+    }
+    pub fn write_webhook(&mut self, name: &str, body: serde_json::Value) {
+        trace!("out-webhook:{name}:{body}");
+    }
+    pub fn write_notification(&mut self, title: &str, body: &str) {
+        trace!("out-notify:{title}:{body}");
+    }
+    pub fn write_mpris(&mut self, action: MprisAction) {
+        trace!("out-mpris:{}:{:?}", action.command.name(), action.player);
+    }
+    pub fn write_os_layout(&mut self, layout: &str) {
+        trace!("out-os-layout:{layout}");
+    }
+    pub fn write_secret(&mut self, name: &str) {
+        trace!("out-secret:{name}");
+    }
+    pub fn write_openrgb(&mut self, color: [u8; 3]) {
+        trace!(
+            "out-openrgb:{:02x}{:02x}{:02x}",
+            color[0], color[1], color[2]
+        );
+    }
     pub fn set_mouse(&mut self, x: u16, y: u16) -> Result<(), io::Error> {
-        log::info!("out🖰:@{x},{y}");
+        tracing::info!("out🖰:@{x},{y}");
         Ok(())
     }
+    pub fn warp_mouse_in_monitor(
+        &mut self,
+        monitor: usize,
+        x: f64,
+        y: f64,
+    ) -> Result<(), io::Error> {
+        tracing::info!("out🖰:@m{monitor}:{x:.4},{y:.4}");
+        Ok(())
+    }
+    pub fn move_mouse_toward(&mut self, action: &MouseToward) -> Result<(), io::Error> {
+        tracing::info!("out🖰:toward{action:?}");
+        Ok(())
+    }
+    pub fn mouse_position(&mut self) -> Result<Option<(i32, i32)>, io::Error> {
+        Ok(None)
+    }
     pub fn tick(&mut self) {}
 }
 
@@ -227,6 +227,9 @@ impl LogFmt {
     pub fn send_unicode(&mut self, c: char) {
         self.fmt(LogFmtT::Unicode, c.to_string())
     }
+    pub fn send_unicode_str(&mut self, text: &str) {
+        self.fmt(LogFmtT::Unicode, text.to_owned())
+    }
     pub fn click_btn(&mut self, btn: Btn) {
         self.fmt(LogFmtT::MouseDown, btn.to_string())
     }
@@ -236,6 +239,12 @@ impl LogFmt {
     pub fn set_mouse(&mut self, x: u16, y: u16) {
         self.fmt(LogFmtT::MouseMove, format!("@{x},{y}"))
     }
+    pub fn warp_mouse_in_monitor(&mut self, monitor: usize, x: f64, y: f64) {
+        self.fmt(LogFmtT::MouseMove, format!("@m{monitor}:{x:.4},{y:.4}"))
+    }
+    pub fn move_mouse_to(&mut self, x: i32, y: i32) {
+        self.fmt(LogFmtT::MouseMove, format!("to{x},{y}"))
+    }
     pub fn scroll(&mut self, dir: MWheelDirection, dist: u16) {
         self.fmt(LogFmtT::MouseMove, format!("{dir}{dist}"))
     }
@@ -304,6 +313,7 @@ impl LogFmt {
 use super::*;
 
 use crate::kanata::CalculatedMouseMove;
+use kanata_parser::cfg::SoundCue;
 use kanata_parser::custom_action::*;
 
 use std::io;
@@ -334,20 +344,135 @@ impl Outputs {
     }
 }
 
+/// An output action, passed to an [`OutputSink`] instead of being recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputEvent {
+    Key {
+        code: OsCode,
+        value: KeyValue,
+    },
+    Code {
+        code: u32,
+        value: KeyValue,
+    },
+    Unicode(char),
+    /// Text that is typed in one go.
+    UnicodeStr(String),
+    MousePress(Btn),
+    MouseRelease(Btn),
+    Scroll {
+        direction: MWheelDirection,
+        distance: u16,
+    },
+    MouseMove {
+        direction: MoveDirection,
+        distance: u16,
+    },
+    /// Absolute pointer position, with platform-specific units.
+    MouseSet {
+        x: u16,
+        y: u16,
+    },
+    /// Pointer position as a fraction of the monitor's width and height.
+    MouseWarp {
+        monitor: usize,
+        x: f64,
+        y: f64,
+    },
+    Midi([u8; 3]),
+    Sound(SoundCue),
+    Obs(ObsAction),
+    /// An RGB color for the lights from the `openrgb` action or `openrgb-layer-colors`.
+    OpenRgb([u8; 3]),
+    /// A request to a webhook from `defwebhooks` with its JSON body.
+    Webhook {
+        name: String,
+        body: serde_json::Value,
+    },
+    /// A desktop notification from the `notify` action.
+    Notify {
+        title: String,
+        body: String,
+    },
+    Mpris(MprisAction),
+    /// A switch of the OS keyboard layout from the `os-layout` action.
+    OsLayout(String),
+    /// A secret to type from the credential store, by its name in `secret-type`.
+    Secret(String),
+    /// A usage of the consumer or system control page of HID from the `hid-usage` action.
+    HidUsage {
+        usage: HidUsage,
+        value: KeyValue,
+    },
+    /// A move toward an anchor of a monitor from the `mouse-toward` action, which the receiver
+    /// resolves with its own pointer position and monitors.
+    MouseToward(MouseToward),
+}
+
+/// Receives the outputs of a [`KbdOut`], for applications that embed kanata and handle output
+/// themselves. See [`crate::engine::Engine`].
+pub trait OutputSink: Send {
+    fn output(&mut self, event: OutputEvent);
+}
+
+impl<F: FnMut(OutputEvent) + Send> OutputSink for F {
+    fn output(&mut self, event: OutputEvent) {
+        self(event)
+    }
+}
+
 /// Handle for writing keys to the OS.
 pub struct KbdOut {
     pub log: LogFmt,
     pub outputs: Outputs,
+    /// When set, outputs are passed here instead of being recorded in `log` and `outputs`.
+    sink: Option<Box<dyn OutputSink>>,
+    /// The pointer position on [`SIMULATED_MONITORS`], which only `mouse-toward` moves.
+    pointer: (i32, i32),
 }
 
+/// The monitors that `mouse-toward` moves the pointer on when outputs are recorded.
+pub const SIMULATED_MONITORS: [MonitorRect; 2] = [
+    MonitorRect {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    },
+    MonitorRect {
+        x: 1920,
+        y: 0,
+        width: 1280,
+        height: 1024,
+    },
+];
+
 impl KbdOut {
     fn new_actual() -> Result<Self, io::Error> {
         Ok(Self {
             log: LogFmt::new(),
             outputs: Outputs::new(),
+            sink: None,
+            pointer: (0, 0),
         })
     }
 
+    pub fn set_output_sink(&mut self, sink: Box<dyn OutputSink>) {
+        self.sink = Some(sink);
+    }
+
+    /// Passes the event to the output sink, if there is one. Returns true if the event should not
+    /// be recorded.
+    fn sink(&mut self, event: impl FnOnce() -> OutputEvent) -> bool {
+        match &mut self.sink {
+            Some(sink) => {
+                sink.output(event());
+                true
+            }
+            None => false,
+        }
+    }
+
     #[cfg(not(any(target_os = "linux", target_os = "android")))]
     pub fn new() -> Result<Self, io::Error> {
         Self::new_actual()
@@ -358,6 +483,7 @@ impl KbdOut {
         _tp: bool,
         _name: &str,
         _bustype: evdev::BusType,
+        _opts: &kanata_parser::cfg::CfgLinuxOptions,
     ) -> Result<Self, io::Error> {
         Self::new_actual()
     }
@@ -378,6 +504,9 @@ impl KbdOut {
         true
     }
     pub fn write_key(&mut self, key: OsCode, value: KeyValue) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::Key { code: key, value }) {
+            return Ok(());
+        }
         let key_ev = KeyEvent::new(key, value);
         let event = {
             #[cfg(target_os = "macos")]
@@ -392,35 +521,92 @@ impl KbdOut {
         self.write(event)
     }
     pub fn write_code(&mut self, code: u32, value: KeyValue) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::Code { code, value }) {
+            return Ok(());
+        }
         self.log.write_code(code, value);
         self.outputs.push(format!("out-code:{code};{value:?}"));
         Ok(())
     }
+    pub fn write_hid_usage(&mut self, usage: HidUsage, value: KeyValue) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::HidUsage { usage, value }) {
+            return Ok(());
+        }
+        self.outputs.push(format!("out-hid:{usage};{value:?}"));
+        Ok(())
+    }
+    #[cfg(target_os = "linux")]
+    pub fn set_lock_leds(&mut self, leds: Option<kanata_parser::cfg::LockLeds>) {
+        let leds = match leds {
+            None => "lock-state".to_owned(),
+            Some(leds) => [
+                (leds.caps, "caps"),
+                (leds.num, "num"),
+                (leds.scroll, "scroll"),
+            ]
+            .into_iter()
+            .filter_map(|(on, name)| on.then_some(name))
+            .collect::<Vec<_>>()
+            .join(","),
+        };
+        self.outputs.push(format!("leds:{leds}"));
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_event_time(&mut self, _time: Option<std::time::SystemTime>) {}
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn set_output_route(&mut self, _device_id: Option<std::num::NonZeroU8>) {}
     pub fn press_key(&mut self, key: OsCode) -> Result<(), io::Error> {
-        self.log.press_key(key);
+        if self.sink.is_none() {
+            self.log.press_key(key);
+        }
         self.write_key(key, KeyValue::Press)
     }
     pub fn release_key(&mut self, key: OsCode) -> Result<(), io::Error> {
-        self.log.release_key(key);
+        if self.sink.is_none() {
+            self.log.release_key(key);
+        }
         self.write_key(key, KeyValue::Release)
     }
     pub fn release_tracked_output_keys(&mut self, _reason: &str) {}
     pub fn send_unicode(&mut self, c: char) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::Unicode(c)) {
+            return Ok(());
+        }
         self.log.send_unicode(c);
         self.outputs.push(format!("outU:{c}"));
         Ok(())
     }
+    pub fn send_unicode_str(&mut self, text: &str) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::UnicodeStr(text.to_owned())) {
+            return Ok(());
+        }
+        self.log.send_unicode_str(text);
+        self.outputs.push(format!("outU:{text}"));
+        Ok(())
+    }
     pub fn click_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::MousePress(btn)) {
+            return Ok(());
+        }
         self.log.click_btn(btn);
         self.outputs.push(format!("out🖰:↓{btn:?}"));
         Ok(())
     }
     pub fn release_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::MouseRelease(btn)) {
+            return Ok(());
+        }
         self.log.release_btn(btn);
         self.outputs.push(format!("out🖰:↑{btn:?}"));
         Ok(())
     }
     pub fn scroll(&mut self, direction: MWheelDirection, distance: u16) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::Scroll {
+            direction,
+            distance,
+        }) {
+            return Ok(());
+        }
         self.log.scroll(direction, distance);
         self.outputs
             .push(format!("scroll:{direction:?},{distance:?}"));
@@ -428,6 +614,12 @@ impl KbdOut {
     }
     pub fn move_mouse(&mut self, mv: CalculatedMouseMove) -> Result<(), io::Error> {
         let (direction, distance) = (mv.direction, mv.distance);
+        if self.sink(|| OutputEvent::MouseMove {
+            direction,
+            distance,
+        }) {
+            return Ok(());
+        }
         self.log.move_mouse(direction, distance);
         self.outputs
             .push(format!("out🖰:move {direction:?},{distance:?}"));
@@ -436,18 +628,139 @@ impl KbdOut {
     pub fn move_mouse_many(&mut self, moves: &[CalculatedMouseMove]) -> Result<(), io::Error> {
         for mv in moves {
             let (direction, distance) = (&mv.direction, &mv.distance);
+            if self.sink(|| OutputEvent::MouseMove {
+                direction: *direction,
+                distance: *distance,
+            }) {
+                continue;
+            }
             self.log.move_mouse(*direction, *distance);
             self.outputs
                 .push(format!("out🖰:move {direction:?},{distance:?}"));
         }
         Ok(())
     }
+    pub fn write_midi(&mut self, msg: [u8; 3]) {
+        if self.sink(|| OutputEvent::Midi(msg)) {
+            return;
+        }
+        self.outputs.push(format!("out-midi:{msg:02X?}"));
+    }
+    pub fn write_obs(&mut self, action: ObsAction) {
+        if self.sink(|| OutputEvent::Obs(action)) {
+            return;
+        }
+        let mut out = format!("out-obs:{}", action.name());
+        for arg in action.args() {
+            out.push(':');
+            out.push_str(arg);
+        }
+        self.outputs.push(out);
+    }
+    pub fn write_openrgb(&mut self, color: [u8; 3]) {
+        if self.sink(|| OutputEvent::OpenRgb(color)) {
+            return;
+        }
+        self.outputs.push(format!(
+            "out-openrgb:{:02x}{:02x}{:02x}",
+            color[0], color[1], color[2]
+        ));
+    }
+    pub fn write_webhook(&mut self, name: &str, body: serde_json::Value) {
+        match &mut self.sink {
+            Some(sink) => sink.output(OutputEvent::Webhook {
+                name: name.to_owned(),
+                body,
+            }),
+            None => self.outputs.push(format!("out-webhook:{name}:{body}")),
+        }
+    }
+    pub fn write_notification(&mut self, title: &str, body: &str) {
+        if self.sink(|| OutputEvent::Notify {
+            title: title.to_owned(),
+            body: body.to_owned(),
+        }) {
+            return;
+        }
+        self.outputs.push(format!("out-notify:{title}:{body}"));
+    }
+    pub fn write_mpris(&mut self, action: MprisAction) {
+        if self.sink(|| OutputEvent::Mpris(action)) {
+            return;
+        }
+        match action.player {
+            Some(player) => self
+                .outputs
+                .push(format!("out-mpris:{}:{player}", action.command.name())),
+            None => self
+                .outputs
+                .push(format!("out-mpris:{}", action.command.name())),
+        }
+    }
+    pub fn write_os_layout(&mut self, layout: &str) {
+        if self.sink(|| OutputEvent::OsLayout(layout.to_owned())) {
+            return;
+        }
+        self.outputs.push(format!("out-os-layout:{layout}"));
+    }
+    pub fn write_secret(&mut self, name: &str) {
+        if self.sink(|| OutputEvent::Secret(name.to_owned())) {
+            return;
+        }
+        self.outputs.push(format!("out-secret:{name}"));
+    }
+    pub fn write_sound(&mut self, cue: &SoundCue) {
+        if *cue != SoundCue::Silent && self.sink(|| OutputEvent::Sound(cue.clone())) {
+            return;
+        }
+        match cue {
+            SoundCue::Silent => {}
+            SoundCue::Beep => self.outputs.push("out-sound:beep"),
+            SoundCue::File(path) => self.outputs.push(format!("out-sound:{path}")),
+        }
+    }
     pub fn set_mouse(&mut self, x: u16, y: u16) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::MouseSet { x, y }) {
+            return Ok(());
+        }
         self.log.set_mouse(x, y);
-        log::info!("out🖰:@{x},{y}");
+        tracing::info!("out🖰:@{x},{y}");
+        Ok(())
+    }
+    pub fn warp_mouse_in_monitor(
+        &mut self,
+        monitor: usize,
+        x: f64,
+        y: f64,
+    ) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::MouseWarp { monitor, x, y }) {
+            return Ok(());
+        }
+        self.log.warp_mouse_in_monitor(monitor, x, y);
+        self.outputs.push(format!("out🖰:@m{monitor}:{x:.4},{y:.4}"));
+        Ok(())
+    }
+    pub fn move_mouse_toward(&mut self, action: &MouseToward) -> Result<(), io::Error> {
+        if self.sink(|| OutputEvent::MouseToward(*action)) {
+            return Ok(());
+        }
+        let Some((x, y)) = mouse_toward_target(self.pointer, &SIMULATED_MONITORS, action) else {
+            tracing::warn!("mouse-toward: the monitor was not found");
+            return Ok(());
+        };
+        self.pointer = (x, y);
+        self.log.move_mouse_to(x, y);
+        self.outputs.push(format!("out🖰:to{x},{y}"));
         Ok(())
     }
+    /// Returns None when outputs are passed to a sink, which has the actual pointer.
+    pub fn mouse_position(&mut self) -> Result<Option<(i32, i32)>, io::Error> {
+        Ok(self.sink.is_none().then_some(self.pointer))
+    }
     pub fn tick(&mut self) {
+        if self.sink.is_some() {
+            return;
+        }
         self.outputs.ticks += 1;
         self.log.ticks += 1;
     }
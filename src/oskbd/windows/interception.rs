@@ -24,7 +24,7 @@ impl fmt::Display for InputEvent {
 impl InputEvent {
     fn from_oscode(code: OsCode, val: KeyValue) -> Self {
         let mut stroke = Stroke::try_from(OsCodeWrapper(code)).unwrap_or_else(|_| {
-            log::error!("Trying to send unmapped oscode '{code:?}', sending esc instead");
+            tracing::error!("Trying to send unmapped oscode '{code:?}', sending esc instead");
             Stroke::Keyboard {
                 code: ScanCode::Esc,
                 state: KeyState::empty(),
@@ -155,7 +155,7 @@ pub struct KbdOut {}
 
 fn write_interception(event: InputEvent) {
     let strokes = [event.0];
-    log::debug!("kanata sending {:?} to driver", strokes[0]);
+    tracing::debug!("kanata sending {:?} to driver", strokes[0]);
     INTRCPTN.with(|ic| {
         match strokes[0] {
             // Note regarding device numbers:
@@ -194,6 +194,17 @@ impl KbdOut {
         self.write(InputEvent::from_oscode(key, value))
     }
 
+    /// Sends the key that Linux maps the HID usage to, since there is no HID output.
+    pub fn write_hid_usage(&mut self, usage: HidUsage, value: KeyValue) -> Result<(), io::Error> {
+        match usage.oscode() {
+            Some(key) => self.write_key(key, value),
+            None => {
+                tracing::warn!("hid-usage {usage} has no key on this OS, not sending it");
+                Ok(())
+            }
+        }
+    }
+
     pub fn press_key(&mut self, key: OsCode) -> Result<(), io::Error> {
         self.write_key(key, KeyValue::Press)
     }
@@ -203,20 +214,20 @@ impl KbdOut {
     }
 
     pub fn click_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
-        log::debug!("click btn: {:?}", btn);
+        tracing::debug!("click btn: {:?}", btn);
         write_interception(InputEvent::from_mouse_btn(btn, false));
         Ok(())
     }
 
     pub fn release_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
-        log::debug!("release btn: {:?}", btn);
+        tracing::debug!("release btn: {:?}", btn);
         let event = InputEvent::from_mouse_btn(btn, true);
         write_interception(event);
         Ok(())
     }
 
     pub fn scroll(&mut self, direction: MWheelDirection, distance: u16) -> Result<(), io::Error> {
-        log::debug!("scroll: {direction:?} {distance:?}");
+        tracing::debug!("scroll: {direction:?} {distance:?}");
         write_interception(InputEvent::from_mouse_scroll(direction, distance));
         Ok(())
     }
@@ -228,6 +239,12 @@ impl KbdOut {
         Ok(())
     }
 
+    /// Send using VK_PACKET, all characters in one input
+    pub fn send_unicode_str(&mut self, text: &str) -> Result<(), io::Error> {
+        super::send_uc_str(text);
+        Ok(())
+    }
+
     pub fn move_mouse(&mut self, mv: CalculatedMouseMove) -> Result<(), io::Error> {
         write_interception(InputEvent::from_mouse_move(mv.direction, mv.distance));
         Ok(())
@@ -242,4 +259,27 @@ impl KbdOut {
         write_interception(InputEvent::from_mouse_set(x, y));
         Ok(())
     }
+
+    pub fn warp_mouse_in_monitor(
+        &mut self,
+        monitor: usize,
+        x: f64,
+        y: f64,
+    ) -> Result<(), io::Error> {
+        match super::monitor_point_to_absolute(monitor, x, y) {
+            Some((x, y)) => self.set_mouse(x, y),
+            None => {
+                tracing::warn!("mouse-grid: monitor {} was not found", monitor + 1);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn move_mouse_toward(&mut self, action: &MouseToward) -> Result<(), io::Error> {
+        super::move_mouse_toward(action)
+    }
+
+    pub fn mouse_position(&mut self) -> Result<Option<(i32, i32)>, io::Error> {
+        super::mouse_position()
+    }
 }
@@ -112,7 +112,7 @@ impl InputEvent {
                     0
                 };
                 let sc_with_ext = (lparam.scanCode as u16) | extended;
-                log::debug!("converting {sc_with_ext}");
+                tracing::debug!("converting {sc_with_ext}");
                 crate::oskbd::u16_to_osc(sc_with_ext)
                     .map(Into::into)
                     .unwrap_or(lparam.vkCode)
@@ -186,7 +186,7 @@ unsafe extern "system" fn hook_proc(code: c_int, wparam: WPARAM, lparam: LPARAM)
     unsafe {
         let hook_lparam = &*(lparam as *const KBDLLHOOKSTRUCT);
         let is_injected = hook_lparam.flags & LLKHF_INJECTED != 0;
-        log::trace!("{code} {}{wparam} {is_injected}", {
+        tracing::trace!("{code} {}{wparam} {is_injected}", {
             match wparam as u32 {
                 WM_KEYDOWN => "↓",
                 WM_KEYUP => "↑",
@@ -259,6 +259,17 @@ impl KbdOut {
         super::write_code_raw(code, value)
     }
 
+    /// Sends the key that Linux maps the HID usage to, since there is no HID output.
+    pub fn write_hid_usage(&mut self, usage: HidUsage, value: KeyValue) -> Result<(), io::Error> {
+        match usage.oscode() {
+            Some(key) => self.write_key(key, value),
+            None => {
+                tracing::warn!("hid-usage {usage} has no key on this OS, not sending it");
+                Ok(())
+            }
+        }
+    }
+
     pub fn press_key(&mut self, key: OsCode) -> Result<(), io::Error> {
         self.write_key(key, KeyValue::Press)
     }
@@ -274,8 +285,14 @@ impl KbdOut {
         Ok(())
     }
 
+    /// Send using VK_PACKET, all characters in one input
+    pub fn send_unicode_str(&mut self, text: &str) -> Result<(), io::Error> {
+        super::send_uc_str(text);
+        Ok(())
+    }
+
     pub fn click_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
-        log::debug!("click btn: {:?}", btn);
+        tracing::debug!("click btn: {:?}", btn);
         match btn {
             Btn::Left => send_btn(MOUSEEVENTF_LEFTDOWN),
             Btn::Right => send_btn(MOUSEEVENTF_RIGHTDOWN),
@@ -287,7 +304,7 @@ impl KbdOut {
     }
 
     pub fn release_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
-        log::debug!("release btn: {:?}", btn);
+        tracing::debug!("release btn: {:?}", btn);
         match btn {
             Btn::Left => send_btn(MOUSEEVENTF_LEFTUP),
             Btn::Right => send_btn(MOUSEEVENTF_RIGHTUP),
@@ -299,7 +316,7 @@ impl KbdOut {
     }
 
     pub fn scroll(&mut self, direction: MWheelDirection, distance: u16) -> Result<(), io::Error> {
-        log::debug!("scroll: {direction:?} {distance:?}");
+        tracing::debug!("scroll: {direction:?} {distance:?}");
         match direction {
             MWheelDirection::Up | MWheelDirection::Down => scroll(direction, distance),
             MWheelDirection::Left | MWheelDirection::Right => hscroll(direction, distance),
@@ -318,10 +335,33 @@ impl KbdOut {
     }
 
     pub fn set_mouse(&mut self, x: u16, y: u16) -> Result<(), io::Error> {
-        log::info!("setting mouse {x} {y}");
+        tracing::info!("setting mouse {x} {y}");
         set_mouse_xy(i32::from(x), i32::from(y));
         Ok(())
     }
+
+    pub fn warp_mouse_in_monitor(
+        &mut self,
+        monitor: usize,
+        x: f64,
+        y: f64,
+    ) -> Result<(), io::Error> {
+        match super::monitor_point_to_absolute(monitor, x, y) {
+            Some((x, y)) => self.set_mouse(x, y),
+            None => {
+                tracing::warn!("mouse-grid: monitor {} was not found", monitor + 1);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn move_mouse_toward(&mut self, action: &MouseToward) -> Result<(), io::Error> {
+        super::move_mouse_toward(action)
+    }
+
+    pub fn mouse_position(&mut self) -> Result<Option<(i32, i32)>, io::Error> {
+        super::mouse_position()
+    }
 }
 
 fn send_btn(flag: u32) {
@@ -390,7 +430,7 @@ fn hscroll(direction: MWheelDirection, distance: u16) {
 }
 
 fn move_mouse(direction: MoveDirection, distance: u16) {
-    log::debug!("move mouse: {direction:?} {distance:?}");
+    tracing::debug!("move mouse: {direction:?} {distance:?}");
     match direction {
         MoveDirection::Up => move_mouse_xy(0, -i32::from(distance)),
         MoveDirection::Down => move_mouse_xy(0, i32::from(distance)),
@@ -51,7 +51,7 @@ impl Drop for MouseHook {
 unsafe extern "system" fn mhook_proc(code: c_int, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     let mouse_lparam = unsafe { &*(lparam as *const MSLLHOOKSTRUCT) };
     let is_injected = mouse_lparam.flags & (LLMHF_INJECTED | LLMHF_LOWER_IL_INJECTED) != 0;
-    log::trace!("{code} {wparam} {is_injected}");
+    tracing::trace!("{code} {wparam} {is_injected}");
 
     // Regarding is_injected check:
     // `SendInput()` internally calls the hook function.
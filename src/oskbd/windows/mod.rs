@@ -40,7 +40,7 @@ pub use interception_convert::*;
 
 #[cfg(not(feature = "simulated_input"))]
 fn send_uc(c: char, up: bool) {
-    log::debug!("sending unicode {c}");
+    tracing::debug!("sending unicode {c}");
     let mut inputs: [INPUT; 2] = unsafe { mem::zeroed() };
 
     let n_inputs = inputs
@@ -67,6 +67,133 @@ fn send_uc(c: char, up: bool) {
     }
 }
 
+/// Sends the presses and releases of all characters of the text with a single `SendInput` so
+/// that other input cannot come between them. The text is not logged because it can be a secret.
+#[cfg(not(feature = "simulated_input"))]
+fn send_uc_str(text: &str) {
+    tracing::debug!("sending {} unicode characters", text.chars().count());
+    let key_input = |unit: u16, up: bool| {
+        let mut kb_input: KEYBDINPUT = unsafe { mem::zeroed() };
+        kb_input.wScan = unit;
+        kb_input.dwFlags |= KEYEVENTF_UNICODE;
+        if up {
+            kb_input.dwFlags |= KEYEVENTF_KEYUP;
+        }
+        let mut input: INPUT = unsafe { mem::zeroed() };
+        input.type_ = INPUT_KEYBOARD;
+        unsafe { *input.u.ki_mut() = kb_input };
+        input
+    };
+    let mut inputs: Vec<INPUT> = vec![];
+    for c in text.chars() {
+        let units = c.to_utf16();
+        inputs.extend(units.into_iter().map(|unit| key_input(unit, false)));
+        inputs.extend(units.into_iter().map(|unit| key_input(unit, true)));
+    }
+    unsafe {
+        SendInput(
+            inputs.len() as _,
+            inputs.as_mut_ptr(),
+            mem::size_of::<INPUT>() as _,
+        );
+    }
+}
+
+/// Returns the monitors with monitor 0 being the primary monitor and the rest ordered left to
+/// right, then top to bottom.
+#[cfg(not(feature = "simulated_output"))]
+fn monitor_rects() -> Vec<super::MonitorRect> {
+    use winapi::shared::minwindef::{BOOL, LPARAM, TRUE};
+    use winapi::shared::windef::{HDC, HMONITOR, LPRECT, RECT};
+
+    unsafe extern "system" fn collect_monitor(
+        hmonitor: HMONITOR,
+        _hdc: HDC,
+        _rect: LPRECT,
+        data: LPARAM,
+    ) -> BOOL {
+        unsafe {
+            let monitors = &mut *(data as *mut Vec<(bool, RECT)>);
+            let mut info: MONITORINFO = mem::zeroed();
+            info.cbSize = mem::size_of::<MONITORINFO>() as u32;
+            if GetMonitorInfoW(hmonitor, &mut info) != 0 {
+                monitors.push((info.dwFlags & MONITORINFOF_PRIMARY != 0, info.rcMonitor));
+            }
+        }
+        TRUE
+    }
+
+    let mut monitors: Vec<(bool, RECT)> = vec![];
+    unsafe {
+        EnumDisplayMonitors(
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            Some(collect_monitor),
+            &mut monitors as *mut _ as LPARAM,
+        );
+    }
+    monitors.sort_by_key(|(primary, rect)| (!primary, rect.left, rect.top));
+    monitors
+        .into_iter()
+        .map(|(_, rect)| super::MonitorRect {
+            x: rect.left,
+            y: rect.top,
+            width: rect.right - rect.left,
+            height: rect.bottom - rect.top,
+        })
+        .collect()
+}
+
+/// Converts a point, given as fractions of the width and height of a monitor, to the absolute
+/// virtual desktop coordinates in the 0-65535 range that `setmouse` uses.
+#[cfg(not(feature = "simulated_output"))]
+fn monitor_point_to_absolute(monitor: usize, x: f64, y: f64) -> Option<(u16, u16)> {
+    let rect = *monitor_rects().get(monitor)?;
+    let (vx, vy, vw, vh) = unsafe {
+        (
+            GetSystemMetrics(SM_XVIRTUALSCREEN),
+            GetSystemMetrics(SM_YVIRTUALSCREEN),
+            GetSystemMetrics(SM_CXVIRTUALSCREEN),
+            GetSystemMetrics(SM_CYVIRTUALSCREEN),
+        )
+    };
+    if vw <= 1 || vh <= 1 {
+        return None;
+    }
+    let px = f64::from(rect.x) + f64::from(rect.width) * x;
+    let py = f64::from(rect.y) + f64::from(rect.height) * y;
+    let ax = (px - f64::from(vx)) * 65535.0 / f64::from(vw - 1);
+    let ay = (py - f64::from(vy)) * 65535.0 / f64::from(vh - 1);
+    Some((ax.clamp(0.0, 65535.0) as u16, ay.clamp(0.0, 65535.0) as u16))
+}
+
+/// Returns the pointer position in pixels of the virtual desktop.
+#[cfg(not(feature = "simulated_output"))]
+fn mouse_position() -> Result<Option<(i32, i32)>, std::io::Error> {
+    let mut point: winapi::shared::windef::POINT = unsafe { mem::zeroed() };
+    if unsafe { GetCursorPos(&mut point) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(Some((point.x, point.y)))
+}
+
+#[cfg(not(feature = "simulated_output"))]
+fn move_mouse_toward(
+    action: &kanata_parser::custom_action::MouseToward,
+) -> Result<(), std::io::Error> {
+    let Some(pointer) = mouse_position()? else {
+        return Ok(());
+    };
+    let Some((x, y)) = super::mouse_toward_target(pointer, &monitor_rects(), action) else {
+        tracing::warn!("mouse-toward: the monitor was not found");
+        return Ok(());
+    };
+    if unsafe { SetCursorPos(x, y) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
 #[cfg(not(feature = "simulated_output"))]
 fn write_code_raw(code: u16, value: KeyValue) -> Result<(), std::io::Error> {
     let is_key_up = match value {
@@ -103,6 +230,19 @@ fn write_code(code: u16, value: KeyValue) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+/// The scancodes of `windows-scancode-overrides` by virtual key.
+#[cfg(not(feature = "simulated_input"))]
+static SCANCODE_OVERRIDES: parking_lot::Mutex<Vec<(u16, u16)>> =
+    parking_lot::Mutex::new(Vec::new());
+
+#[cfg(not(feature = "simulated_input"))]
+pub fn set_win_scancode_overrides(overrides: &[(kanata_parser::keys::OsCode, u16)]) {
+    *SCANCODE_OVERRIDES.lock() = overrides
+        .iter()
+        .map(|(osc, scancode)| (u16::from(*osc), *scancode))
+        .collect();
+}
+
 #[cfg(not(feature = "simulated_input"))]
 fn send_key_sendinput(code: u16, is_key_up: bool) {
     unsafe {
@@ -229,6 +369,22 @@ fn send_key_sendinput(code: u16, is_key_up: bool) {
             };
         }
 
+        // An override is sent as the scancode even when virtual keys are sent otherwise.
+        let scancode_override = SCANCODE_OVERRIDES
+            .lock()
+            .iter()
+            .find(|(vk, _)| *vk == code)
+            .map(|(_, scancode)| *scancode);
+        if let Some(scancode) = scancode_override {
+            kb_input.wVk = 0;
+            kb_input.wScan = scancode;
+            kb_input.dwFlags |= KEYEVENTF_SCANCODE;
+            kb_input.dwFlags &= !KEYEVENTF_EXTENDEDKEY;
+            if scancode >> 8 == 0xE0 {
+                kb_input.dwFlags |= KEYEVENTF_EXTENDEDKEY;
+            }
+        }
+
         let mut inputs: [INPUT; 1] = mem::zeroed();
         inputs[0].type_ = INPUT_KEYBOARD;
         *inputs[0].u.ki_mut() = kb_input;
@@ -1,24 +1,119 @@
-use crate::Kanata;
+//! The TCP server that clients use to control kanata and receive notifications.
+//!
+//! Connections are served by a tokio runtime on a single background thread. Each client has a
+//! bounded queue of outgoing messages that is written by its own task, so a client that does not
+//! read its messages cannot stall the others. Notifications are coalesced for clients that fall
+//! behind, see [`outbox`], and clients that fall too far behind are disconnected. Commands that
+//! use the kanata state wait for it on blocking threads, so that they do not stall other clients
+//! while the processing loop holds it.
+//!
+//! With the `tcp_server_websocket` feature, clients can also connect with WebSocket on the same
+//! port. Each text message then holds one or more client messages, and each server message is sent
+//! as its own text message.
+//!
+//! The server can have several listeners: TCP, UDP and Unix sockets. Each listener can require a
+//! token, which clients send with `Authenticate` before anything else. The clients of all the
+//! listeners share the same notifications, except UDP clients which only receive replies.
+
+#[cfg(feature = "tcp_server")]
 use crate::oskbd::*;
+use crate::{EventSender, Kanata};
 
 #[cfg(feature = "tcp_server")]
 use kanata_tcp_protocol::*;
 use parking_lot::Mutex;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::sync::mpsc::SyncSender as Sender;
+
+#[cfg(feature = "tcp_server")]
+use anyhow::{Error, anyhow, bail};
+#[cfg(all(feature = "tcp_server", unix))]
+use std::path::PathBuf;
+#[cfg(feature = "tcp_server")]
+use std::str::FromStr;
+#[cfg(feature = "tcp_server")]
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(feature = "tcp_server")]
 type HashMap<K, V> = rustc_hash::FxHashMap<K, V>;
 #[cfg(feature = "tcp_server")]
 use kanata_parser::cfg::SimpleSExpr;
 #[cfg(feature = "tcp_server")]
-use std::io::Write;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "tcp_server")]
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(feature = "tcp_server")]
+use tokio::sync::{Notify, mpsc};
+#[cfg(feature = "tcp_server")]
+use tracing::Instrument;
+
+#[cfg(feature = "tcp_server")]
+mod addresses;
+#[cfg(feature = "tcp_server")]
+mod audit;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "udp_noise")]
+mod noise;
+#[cfg(feature = "tcp_server")]
+mod outbox;
+#[cfg(feature = "tcp_server")]
+mod output;
 #[cfg(feature = "tcp_server")]
-use std::net::{TcpListener, TcpStream};
+mod tokens;
+#[cfg(feature = "tcp_server")]
+mod via;
+#[cfg(feature = "tcp_server")]
+pub use addresses::Cidr;
+#[cfg(feature = "tcp_server")]
+pub use audit::AuditLog;
+#[cfg(feature = "tcp_server")]
+use audit::Client;
+#[cfg(feature = "mqtt")]
+pub use mqtt::MqttOptions;
+#[cfg(feature = "tcp_server")]
+use outbox::Outbox;
+#[cfg(feature = "tcp_server")]
+use tokens::Credential;
+#[cfg(feature = "tcp_server")]
+pub use tokens::Tokens;
 
+/// Outgoing messages that a client has not received yet before it is disconnected.
 #[cfg(feature = "tcp_server")]
-pub type Connections = Arc<Mutex<HashMap<String, TcpStream>>>;
+const CLIENT_QUEUE_LEN: usize = 256;
+
+/// The largest length-prefixed message that a client may send.
+#[cfg(feature = "tcp_server")]
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+/// Queued instead of a message to make the writer of a stream switch to
+/// [`Framing::LengthPrefixed`], so that the messages queued before are sent with the framing from
+/// before.
+#[cfg(feature = "tcp_server")]
+const SWITCH_TO_LENGTH_PREFIXED: Vec<u8> = Vec::new();
+
+/// The outgoing message queue of a connected client.
+#[cfg(feature = "tcp_server")]
+pub struct ClientHandle {
+    tx: mpsc::Sender<Vec<u8>>,
+    /// The notifications that are not in `tx` yet.
+    outbox: Arc<Outbox>,
+    disconnect: Arc<Notify>,
+    /// The token that the client authenticated with, if its listener has tokens.
+    credential: Option<Credential>,
+    /// Whether the client sent `SubscribeCmdOutput`.
+    cmd_output: bool,
+}
+
+#[cfg(feature = "tcp_server")]
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.outbox.close();
+    }
+}
+
+#[cfg(feature = "tcp_server")]
+pub type Connections = Arc<Mutex<HashMap<String, ClientHandle>>>;
 
 #[cfg(not(feature = "tcp_server"))]
 pub type Connections = ();
@@ -26,19 +121,44 @@ pub type Connections = ();
 #[cfg(feature = "tcp_server")]
 use kanata_parser::custom_action::FakeKeyAction;
 
+/// Whether a client may have sent `SubscribeCmdOutput`, so that the output of commands is worth
+/// sending. It stays set when such a client disconnects, until another one unsubscribes.
 #[cfg(feature = "tcp_server")]
-fn send_response(
-    stream: &mut TcpStream,
-    response: ServerResponse,
-    connections: &Connections,
-    addr: &str,
-) -> bool {
-    if let Err(write_err) = stream.write_all(&response.as_bytes()) {
-        log::error!("stream write error: {write_err}");
-        connections.lock().remove(addr);
-        return false;
-    }
-    true
+static CMD_OUTPUT_SUBSCRIBED: AtomicBool = AtomicBool::new(false);
+
+/// Whether to send the notifications of commands, see `SubscribeCmdOutput`.
+#[cfg(feature = "tcp_server")]
+pub fn cmd_output_subscribed() -> bool {
+    CMD_OUTPUT_SUBSCRIBED.load(Ordering::Relaxed)
+}
+
+/// Queues a notification for every connected client without blocking. Clients whose outbox is
+/// full or whose connection has closed are disconnected.
+#[cfg(feature = "tcp_server")]
+pub fn broadcast(connections: &Connections, msg: &ServerMessage) {
+    let notification = msg.as_bytes();
+    let class = outbox::Class::of(msg);
+    let cmd_output = matches!(
+        msg,
+        ServerMessage::CmdStarted { .. }
+            | ServerMessage::CmdOutput { .. }
+            | ServerMessage::CmdExited { .. }
+    );
+    connections.lock().retain(|id, client| {
+        if client.tx.is_closed() {
+            tracing::warn!("removing disconnected tcp client: {id}");
+            return false;
+        }
+        if cmd_output && !client.cmd_output {
+            return true;
+        }
+        let queued = client.outbox.push(class, notification.clone());
+        if !queued {
+            tracing::warn!("disconnecting tcp client that is not reading its messages: {id}");
+            client.disconnect.notify_one();
+        }
+        queued
+    });
 }
 
 #[cfg(feature = "tcp_server")]
@@ -51,65 +171,292 @@ fn to_action(val: FakeKeyActionMessage) -> FakeKeyAction {
     }
 }
 
-/// Handles reload commands with optional wait/timeout for completion confirmation.
-/// Returns false if the connection should be closed, true otherwise.
 #[cfg(feature = "tcp_server")]
-fn handle_reload_with_wait(
-    reload_cmd: ClientMessage,
-    wait: Option<bool>,
-    timeout_ms: Option<u64>,
-    stream: &mut TcpStream,
-    kanata: &Arc<Mutex<Kanata>>,
-    connections: &Connections,
-    addr: &str,
-) -> bool {
-    let (response, reload_ok) = match kanata.lock().handle_client_command(reload_cmd) {
-        Ok(_) => (ServerResponse::Ok, true),
-        Err(e) => (
-            ServerResponse::Error {
-                msg: format!("{e}"),
-            },
-            false,
-        ),
-    };
-    if !send_response(stream, response, connections, addr) {
-        return false;
+fn capabilities() -> Vec<String> {
+    [
+        "reload",
+        "layer-names",
+        "fake-key-names",
+        "layer-change",
+        "hold-activated",
+        "tap-activated",
+        "current-layer-name",
+        "current-layer-info",
+        "fake-key",
+        "set-mouse",
+        "emergency-passthrough",
+        "processing-paused",
+        "layer-fallback",
+        "layer-alias",
+        "authenticate",
+        "stats",
+        "mouse-position",
+        "log-level",
+        "ngram-stats",
+        "device-change",
+        "reload-try",
+        "reload-history",
+        "length-prefixed-framing",
+        "revoke-token",
+        "read-only-clients",
+        "notification-coalescing",
+        "macro-delay-scale",
+        "typing-speed",
+        "stuck-key-released",
+        "tap-hold-tuning",
+        "layer-meta",
+        "config-meta",
+        "training-mode",
+        "key-docs",
+        "sequence-menu",
+        #[cfg(feature = "cmd")]
+        "cmd-output",
+        #[cfg(feature = "cmd")]
+        "cmd-cancel",
+        #[cfg(feature = "tcp_server_websocket")]
+        "websocket",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+/// Where a listener accepts clients.
+#[cfg(feature = "tcp_server")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    /// Each datagram holds one or more client messages, and the replies are sent back to the
+    /// sender.
+    Udp(SocketAddr),
+    /// Like `Udp`, with the datagrams encrypted with the Noise protocol, see [`noise`].
+    #[cfg(feature = "udp_noise")]
+    NoiseUdp {
+        address: SocketAddr,
+        key: noise::NoiseKey,
+    },
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// Connects to an MQTT broker instead of listening.
+    #[cfg(feature = "mqtt")]
+    Mqtt(MqttOptions),
+    /// TCP clients that send the keymap commands of the VIA protocol.
+    Via(SocketAddr),
+    /// TCP clients that send output to inject, see `linux-output-backend remote`.
+    Output(SocketAddr),
+}
+
+#[cfg(feature = "tcp_server")]
+impl Endpoint {
+    /// The address that the listener binds to, if it is a network socket.
+    fn address_mut(&mut self) -> Option<&mut SocketAddr> {
+        match self {
+            Endpoint::Tcp(address)
+            | Endpoint::Udp(address)
+            | Endpoint::Via(address)
+            | Endpoint::Output(address) => Some(address),
+            #[cfg(feature = "udp_noise")]
+            Endpoint::NoiseUdp { address, .. } => Some(address),
+            #[cfg(unix)]
+            Endpoint::Unix(_) => None,
+            #[cfg(feature = "mqtt")]
+            Endpoint::Mqtt(_) => None,
+        }
+    }
+}
+
+/// A listener of the server, with the tokens that its clients must authenticate with, if any.
+#[cfg(feature = "tcp_server")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct Listener {
+    pub endpoint: Endpoint,
+    pub token: Option<Arc<Tokens>>,
+    /// The addresses that clients may connect from, any if empty.
+    pub allow: Vec<Cidr>,
+    /// Whether clients may only send queries.
+    pub read_only: bool,
+}
+
+#[cfg(not(feature = "tcp_server"))]
+pub type Listener = ();
+
+/// A TCP listener without a token, like `--port`.
+#[cfg(feature = "tcp_server")]
+impl From<SocketAddr> for Listener {
+    fn from(address: SocketAddr) -> Self {
+        Self {
+            endpoint: Endpoint::Tcp(address),
+            token: None,
+            allow: vec![],
+            read_only: false,
+        }
+    }
+}
+
+#[cfg(feature = "tcp_server")]
+impl std::fmt::Debug for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listener")
+            .field("endpoint", &self.endpoint)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("allow", &self.allow)
+            .field("read_only", &self.read_only)
+            .finish()
     }
+}
 
-    // If wait flag is set and reload succeeded, poll for completion
-    if reload_ok && wait.unwrap_or(false) {
-        let timeout_ms = timeout_ms.unwrap_or(5000);
-        let start = std::time::Instant::now();
-        let timeout_duration = std::time::Duration::from_millis(timeout_ms);
+/// Parses `[tcp:|udp:|unix:|output:]ADDRESS[,token-file=PATH]`, `via:ADDRESS`, or
+/// `mqtt:HOST[:PORT]` with the options of [`MqttOptions::parse`]. TCP, UDP, VIA and output
+/// addresses are a port, which listens on localhost, or `IP:PORT`. Output listeners need a token.
+/// The token is the content of the file without surrounding whitespace, so that it does not show
+/// up in the process list, or with `token-secret=NAME` the secret of the credential store of the
+/// OS. `tokens-file=PATH` instead accepts any token of the file, see [`Tokens`]. UDP listeners also
+/// take `noise-key=PATH` to encrypt their datagrams with the keypair in the file, which is written
+/// if it does not exist. Network listeners also take `interface=NAME` to bind to the address of
+/// the network interface instead of the IP of the address, and `allow=CIDR`, which can be
+/// repeated, to accept only clients from those addresses. `read-only` only lets clients send
+/// queries; a token of a `tokens-file` can also be read-only on its own.
+#[cfg(feature = "tcp_server")]
+impl FromStr for Listener {
+    type Err = Error;
 
-        while start.elapsed() < timeout_duration {
-            if kanata.lock().is_reload_complete() {
-                break;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let endpoint = parts.next().unwrap_or_default();
+        #[cfg(feature = "mqtt")]
+        if let Some(broker) = endpoint.strip_prefix("mqtt:") {
+            return Ok(Self {
+                endpoint: Endpoint::Mqtt(MqttOptions::parse(broker, parts)?),
+                token: None,
+                allow: vec![],
+                read_only: false,
+            });
+        }
+        let address = |a: &str| {
+            a.parse::<crate::SocketAddrWrapper>()
+                .map(|a| a.into_inner())
+        };
+        let mut endpoint = match endpoint.split_once(':') {
+            Some(("tcp", a)) => Endpoint::Tcp(address(a)?),
+            Some(("udp", a)) => Endpoint::Udp(address(a)?),
+            Some(("via", a)) => Endpoint::Via(address(a)?),
+            Some(("output", a)) => Endpoint::Output(address(a)?),
+            #[cfg(unix)]
+            Some(("unix", path)) if !path.is_empty() => Endpoint::Unix(path.into()),
+            #[cfg(not(unix))]
+            Some(("unix", _)) => bail!("unix sockets are not supported on this OS"),
+            Some(("tls", _)) => bail!(
+                "TLS listeners are not supported yet, use a Unix socket, udp:ADDRESS,noise-key=PATH \
+                 or an SSH or WireGuard tunnel to reach kanata over a network"
+            ),
+            _ => Endpoint::Tcp(address(endpoint)?),
+        };
+        let mut token = None;
+        let mut allow = vec![];
+        let mut read_only = false;
+        #[cfg(feature = "udp_noise")]
+        let mut noise_key = None;
+        for option in parts {
+            match option.split_once('=') {
+                None if option == "read-only" => read_only = true,
+                Some(("allow", cidr)) => allow.push(cidr.parse()?),
+                Some(("interface", name)) => {
+                    let Some(address) = endpoint.address_mut() else {
+                        bail!("interface=NAME is only supported by network listeners");
+                    };
+                    address.set_ip(addresses::interface_addr(name, address.ip())?);
+                }
+                Some(_) if matches!(endpoint, Endpoint::Via(_)) => {
+                    bail!("via listeners have no tokens, the VIA protocol has no authentication")
+                }
+                Some(("token-file", path)) => {
+                    token = Some(Arc::new(Tokens::from_token_file(path)?))
+                }
+                Some(("token-secret", name)) => {
+                    token = Some(Arc::new(Tokens::from_token_secret(name)?))
+                }
+                Some(("tokens-file", path)) => {
+                    token = Some(Arc::new(Tokens::from_tokens_file(path)?))
+                }
+                #[cfg(feature = "udp_noise")]
+                Some(("noise-key", path)) => noise_key = Some(noise::load_noise_key(path)?),
+                _ => bail!(
+                    "unknown listener option {option}, expected token-file=PATH, \
+                     token-secret=NAME, tokens-file=PATH, interface=NAME, allow=CIDR or read-only"
+                ),
             }
-            std::thread::sleep(std::time::Duration::from_millis(50));
         }
-        let timed_out = start.elapsed() >= timeout_duration;
-
-        let ok = kanata.lock().last_reload_succeeded();
-        let msg = ServerMessage::ReloadResult {
-            ok,
-            timeout_ms: if timed_out { Some(timeout_ms) } else { None },
+        if read_only && matches!(endpoint, Endpoint::Via(_) | Endpoint::Output(_)) {
+            bail!("read-only is only supported by listeners of the kanata protocol");
+        }
+        if !allow.is_empty() && endpoint.address_mut().is_none() {
+            bail!("allow=CIDR is only supported by network listeners");
+        }
+        #[cfg(feature = "udp_noise")]
+        let endpoint = match (endpoint, noise_key) {
+            (Endpoint::Udp(address), Some(key)) => Endpoint::NoiseUdp { address, key },
+            (_, Some(_)) => bail!("noise-key=PATH is only supported by udp listeners"),
+            (endpoint, None) => endpoint,
         };
-        if let Err(err) = stream.write_all(&msg.as_bytes()) {
-            log::error!("Error writing ReloadResult: {err}");
-            connections.lock().remove(addr);
-            return false;
+        if matches!(endpoint, Endpoint::Output(_)) && token.is_none() {
+            bail!(
+                "output listeners need token-file=PATH, token-secret=NAME or tokens-file=PATH, \
+                 their clients type on this computer"
+            );
         }
-        let _ = stream.flush();
+        Ok(Self {
+            endpoint,
+            token,
+            allow,
+            read_only,
+        })
+    }
+}
+
+/// Reads a token or password without surrounding whitespace. Like the secrets of `defsecrets`, the
+/// file must only be readable by the user of kanata, see [`crate::kanata::check_secret_file`].
+#[cfg(feature = "tcp_server")]
+fn read_secret_file(path: &str) -> Result<Arc<str>, Error> {
+    crate::kanata::check_secret_file(std::path::Path::new(path))
+        .map_err(|e| anyhow!("refusing token file: {e}"))?;
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("could not read token file {path}: {e}"))?;
+    let content = content.trim();
+    if content.is_empty() {
+        bail!("token file {path} is empty");
+    }
+    Ok(Arc::from(content))
+}
+
+/// Reads a token or password from the credential store of the OS, like `secret-type`.
+#[cfg(feature = "tcp_server")]
+fn read_stored_secret(name: &str) -> Result<Arc<str>, Error> {
+    let secret = crate::kanata::read_credential(name)
+        .map_err(|e| anyhow!("could not read the secret {name}: {e}"))?;
+    let secret = secret.trim();
+    if secret.is_empty() {
+        bail!("the secret {name} is empty");
     }
-    true
+    Ok(Arc::from(secret))
+}
+
+/// Whether a server was started, to warn about actions that notify clients when there is none.
+#[cfg(feature = "tcp_server")]
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "tcp_server")]
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
 }
 
 #[cfg(feature = "tcp_server")]
 pub struct TcpServer {
-    pub address: SocketAddr,
+    /// The addresses are updated when the server starts, e.g. for port 0.
+    pub listeners: Vec<Listener>,
     pub connections: Connections,
-    pub wakeup_channel: Sender<KeyEvent>,
+    pub wakeup_channel: EventSender,
+    /// Where the commands of clients are recorded, see `--audit-log`.
+    pub audit_log: Option<AuditLog>,
 }
 
 #[cfg(not(feature = "tcp_server"))]
@@ -119,352 +466,1217 @@ pub struct TcpServer {
 
 impl TcpServer {
     #[cfg(feature = "tcp_server")]
-    pub fn new(address: SocketAddr, wakeup_channel: Sender<KeyEvent>) -> Self {
+    pub fn new(address: SocketAddr, wakeup_channel: EventSender) -> Self {
+        Self::with_listeners(vec![address.into()], wakeup_channel)
+    }
+
+    #[cfg(feature = "tcp_server")]
+    pub fn with_listeners(listeners: Vec<Listener>, wakeup_channel: EventSender) -> Self {
         Self {
-            address,
+            listeners,
             connections: Arc::new(Mutex::new(HashMap::default())),
             wakeup_channel,
+            audit_log: None,
         }
     }
 
+    /// Returns the address of the first TCP listener.
+    #[cfg(feature = "tcp_server")]
+    pub fn tcp_address(&self) -> Option<SocketAddr> {
+        self.listeners.iter().find_map(|l| match l.endpoint {
+            Endpoint::Tcp(address) => Some(address),
+            _ => None,
+        })
+    }
+
     #[cfg(not(feature = "tcp_server"))]
-    pub fn new(_address: SocketAddr, _wakeup_channel: Sender<KeyEvent>) -> Self {
+    pub fn new(_address: SocketAddr, _wakeup_channel: EventSender) -> Self {
         Self { connections: () }
     }
 
+    #[cfg(not(feature = "tcp_server"))]
+    pub fn with_listeners(_listeners: Vec<Listener>, _wakeup_channel: EventSender) -> Self {
+        Self { connections: () }
+    }
+
+    /// Binds the listeners and serves them on a background thread. Returns an error if an address
+    /// is not available, so that it is reported at startup.
     #[cfg(feature = "tcp_server")]
-    pub fn start(&mut self, kanata: Arc<Mutex<Kanata>>) {
-        use kanata_parser::cfg::FAKE_KEY_ROW;
+    pub fn start(&mut self, kanata: Arc<Mutex<Kanata>>) -> Result<(), Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .map_err(|e| anyhow!("could not start the TCP server runtime: {e}"))?;
+        // The sockets are registered with the runtime that serves them.
+        let bound = {
+            let _runtime = runtime.enter();
+            self.listeners
+                .iter_mut()
+                .map(|listener| {
+                    let allow = Arc::from(listener.allow.as_slice());
+                    let token = listener.token.clone();
+                    Ok((
+                        bind(&mut listener.endpoint)?,
+                        token,
+                        allow,
+                        listener.read_only,
+                    ))
+                })
+                .collect::<Result<Vec<_>, Error>>()?
+        };
+        RUNNING.store(true, Ordering::Relaxed);
 
-        use crate::kanata::handle_fakekey_action;
+        let server = Server {
+            kanata,
+            connections: self.connections.clone(),
+            wakeup_channel: self.wakeup_channel.clone(),
+            tokens: self
+                .listeners
+                .iter()
+                .filter_map(|l| l.token.clone())
+                .collect(),
+            audit_log: self.audit_log.clone(),
+            allow: Arc::new([]),
+            read_only: false,
+        };
+        std::thread::spawn(move || {
+            let _running = crate::kanata::TCP_SERVER.running();
+            runtime.block_on(async move {
+                for tokens in server.tokens.iter() {
+                    tokio::spawn(server.clone().watch_tokens(tokens.clone()));
+                }
+                for (socket, token, allow, read_only) in bound {
+                    let server = Server {
+                        allow,
+                        read_only,
+                        ..server.clone()
+                    };
+                    match socket {
+                        Bound::Tcp(listener) => tokio::spawn(server.accept_tcp(listener, token)),
+                        Bound::Udp(socket) => tokio::spawn(server.serve_udp(socket, token)),
+                        #[cfg(feature = "udp_noise")]
+                        Bound::NoiseUdp(socket, key) => {
+                            tokio::spawn(server.serve_noise_udp(socket, key, token))
+                        }
+                        #[cfg(unix)]
+                        Bound::Unix(listener, path) => {
+                            tokio::spawn(server.accept_unix(listener, path, token))
+                        }
+                        #[cfg(feature = "mqtt")]
+                        Bound::Mqtt(options) => tokio::spawn(server.serve_mqtt(options)),
+                        Bound::Via(listener) => tokio::spawn(server.accept_via(listener)),
+                        Bound::Output(listener) => {
+                            tokio::spawn(server.accept_output(listener, token))
+                        }
+                    };
+                }
+                std::future::pending::<()>().await
+            });
+        });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "tcp_server"))]
+    pub fn start(&mut self, _kanata: Arc<Mutex<Kanata>>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// A socket bound by [`TcpServer::start`], registered with the runtime that serves it.
+#[cfg(feature = "tcp_server")]
+enum Bound {
+    Tcp(TcpListener),
+    Udp(tokio::net::UdpSocket),
+    #[cfg(feature = "udp_noise")]
+    NoiseUdp(tokio::net::UdpSocket, noise::NoiseKey),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+    #[cfg(feature = "mqtt")]
+    Mqtt(MqttOptions),
+    Via(TcpListener),
+    Output(TcpListener),
+}
 
-        let listener = TcpListener::bind(self.address).expect("TCP server starts");
+/// Binds a TCP listener, for the clients described by `what`.
+#[cfg(feature = "tcp_server")]
+fn bind_tcp(address: &mut SocketAddr, what: &str) -> Result<TcpListener, Error> {
+    let listener = std::net::TcpListener::bind(*address)
+        .and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        })
+        .map_err(|e| anyhow!("could not listen for {what} on {address}: {e}"))?;
+    if let Ok(local) = listener.local_addr() {
+        // Port 0 binds to any free port.
+        *address = local;
+    }
+    Ok(listener)
+}
 
-        let connections = self.connections.clone();
-        let wakeup_channel = self.wakeup_channel.clone();
+#[cfg(feature = "tcp_server")]
+fn bind_udp(address: &mut SocketAddr) -> Result<tokio::net::UdpSocket, Error> {
+    let socket = std::net::UdpSocket::bind(*address)
+        .and_then(|socket| {
+            socket.set_nonblocking(true)?;
+            tokio::net::UdpSocket::from_std(socket)
+        })
+        .map_err(|e| anyhow!("could not listen for UDP clients on {address}: {e}"))?;
+    if let Ok(local) = socket.local_addr() {
+        *address = local;
+    }
+    Ok(socket)
+}
 
-        std::thread::spawn(move || {
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(mut stream) => {
-                        {
-                            let k = kanata.lock();
-                            log::info!(
-                                "new client connection, sending initial LayerChange event to inform them of current layer"
-                            );
-                            if let Err(e) = stream.write(
-                                &ServerMessage::LayerChange {
-                                    new: k.layer_info[k.layout.b().current_layer()].name.clone(),
-                                }
-                                .as_bytes(),
-                            ) {
-                                log::warn!("failed to write to stream, dropping it: {e:?}");
-                                continue;
-                            }
-                        }
+#[cfg(feature = "tcp_server")]
+fn bind(endpoint: &mut Endpoint) -> Result<Bound, Error> {
+    Ok(match endpoint {
+        Endpoint::Tcp(address) => {
+            let listener = bind_tcp(address, "TCP clients")?;
+            tracing::info!("listening for TCP clients on {address}");
+            Bound::Tcp(listener)
+        }
+        Endpoint::Udp(address) => {
+            let socket = bind_udp(address)?;
+            tracing::info!("listening for UDP clients on {address}");
+            Bound::Udp(socket)
+        }
+        #[cfg(feature = "udp_noise")]
+        Endpoint::NoiseUdp { address, key } => {
+            let socket = bind_udp(address)?;
+            tracing::info!(
+                "listening for encrypted UDP clients on {address}, Noise public key {}",
+                noise::hex(&key.public)
+            );
+            Bound::NoiseUdp(socket, key.clone())
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            // A socket file that nothing accepts on is left over from a previous run.
+            if path.exists() && std::os::unix::net::UnixStream::connect(&*path).is_err() {
+                let _ = std::fs::remove_file(&*path);
+            }
+            let listener = std::os::unix::net::UnixListener::bind(&*path)
+                .and_then(|listener| {
+                    listener.set_nonblocking(true)?;
+                    tokio::net::UnixListener::from_std(listener)
+                })
+                .map_err(|e| anyhow!("could not listen on unix socket {}: {e}", path.display()))?;
+            tracing::info!("listening for clients on unix socket {}", path.display());
+            Bound::Unix(listener, path.clone())
+        }
+        #[cfg(feature = "mqtt")]
+        Endpoint::Mqtt(options) => {
+            tracing::info!("connecting to MQTT broker {}", options.broker);
+            Bound::Mqtt(options.clone())
+        }
+        Endpoint::Via(address) => {
+            let listener = bind_tcp(address, "VIA clients")?;
+            tracing::info!("listening for VIA clients on {address}");
+            Bound::Via(listener)
+        }
+        Endpoint::Output(address) => {
+            let listener = bind_tcp(address, "output to inject")?;
+            tracing::info!("listening for output to inject on {address}");
+            Bound::Output(listener)
+        }
+    })
+}
+
+/// Compares the tokens in time that does not depend on where they differ.
+#[cfg(feature = "tcp_server")]
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// The command for logs, without tokens.
+#[cfg(feature = "tcp_server")]
+fn loggable(msg: &ClientMessage) -> String {
+    match msg {
+        ClientMessage::Authenticate { .. } => "Authenticate { .. }".to_owned(),
+        ClientMessage::RevokeToken { .. } => "RevokeToken { .. }".to_owned(),
+        msg => format!("{msg:?}"),
+    }
+}
+
+/// The name of the command, like `ChangeLayer`.
+#[cfg(feature = "tcp_server")]
+fn command_name(msg: &ClientMessage) -> String {
+    match serde_json::to_value(msg) {
+        Ok(serde_json::Value::Object(command)) => command.keys().next().cloned(),
+        _ => None,
+    }
+    .unwrap_or_default()
+}
+
+/// State shared by the tasks that serve clients.
+#[cfg(feature = "tcp_server")]
+#[derive(Clone)]
+struct Server {
+    kanata: Arc<Mutex<Kanata>>,
+    connections: Connections,
+    wakeup_channel: EventSender,
+    /// The tokens of all the listeners, for `RevokeToken`.
+    tokens: Arc<[Arc<Tokens>]>,
+    audit_log: Option<AuditLog>,
+    /// The addresses that the listener served by this task accepts clients from.
+    allow: Arc<[Cidr]>,
+    /// Whether the clients of the listener served by this task may only send queries.
+    read_only: bool,
+}
+
+/// What to do after handling a client message.
+#[cfg(feature = "tcp_server")]
+enum Handled {
+    Continue,
+    Disconnect,
+}
+
+#[cfg(feature = "tcp_server")]
+impl Server {
+    /// Runs `f` on a blocking thread of the runtime. The processing loop holds the kanata state
+    /// while it handles events, so waiting for it on the runtime thread would stall every client.
+    async fn blocking<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Mutex<Kanata>) -> R + Send + 'static,
+    ) -> R {
+        let kanata = self.kanata.clone();
+        match tokio::task::spawn_blocking(move || f(&kanata)).await {
+            Ok(r) => r,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    /// Like [`Server::blocking`], with the kanata state locked.
+    async fn with_kanata<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Kanata) -> R + Send + 'static,
+    ) -> R {
+        self.blocking(move |kanata| f(&mut kanata.lock())).await
+    }
+
+    async fn accept_tcp(self, listener: TcpListener, token: Option<Arc<Tokens>>) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) if addresses::is_allowed(&self.allow, &addr) => {
+                    tokio::spawn(self.clone().serve(stream, addr.to_string(), token.clone()));
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("not able to accept client connection: {e:?}"),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    async fn accept_unix(
+        self,
+        listener: tokio::net::UnixListener,
+        path: PathBuf,
+        token: Option<Arc<Tokens>>,
+    ) {
+        // Unix clients have no address, so number them to tell them apart.
+        for n in 0u64.. {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let (reader, writer) = tokio::io::split(stream);
+                    let (tx, rx) = mpsc::channel(CLIENT_QUEUE_LEN);
+                    tokio::spawn(write_stream(writer, rx));
+                    let addr = format!("{}#{n}", path.display());
+                    tokio::spawn(
+                        self.clone()
+                            .serve_client(reader, tx, addr, token.clone(), true),
+                    );
+                }
+                Err(e) => tracing::error!("not able to accept client connection: {e:?}"),
+            }
+        }
+    }
+
+    async fn serve(self, stream: TcpStream, addr: String, token: Option<Arc<Tokens>>) {
+        #[cfg(feature = "tcp_server_websocket")]
+        if is_websocket_handshake(&stream).await {
+            match tokio_tungstenite::accept_async(stream).await {
+                Ok(ws) => self.serve_websocket(ws, addr, token).await,
+                Err(e) => tracing::warn!("websocket handshake with {addr} failed: {e:?}"),
+            }
+            return;
+        }
+        let (reader, writer) = tokio::io::split(stream);
+        let (tx, rx) = mpsc::channel(CLIENT_QUEUE_LEN);
+        tokio::spawn(write_stream(writer, rx));
+        self.serve_client(reader, tx, addr, token, true).await;
+    }
+
+    /// Answers the messages in each datagram. UDP clients are not registered for notifications,
+    /// since there is no connection that tells when they go away.
+    async fn serve_udp(self, socket: tokio::net::UdpSocket, token: Option<Arc<Tokens>>) {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let (n, peer) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    tracing::warn!("udp receive error: {e:?}");
+                    continue;
+                }
+            };
+            if !addresses::is_allowed(&self.allow, &peer) {
+                continue;
+            }
+            let addr = format!("udp:{peer}");
+            for reply in self.handle_datagram(&buf[..n], token.as_ref(), &addr).await {
+                if let Err(e) = socket.send_to(&reply, peer).await {
+                    tracing::warn!("udp send error to {peer}: {e:?}");
+                }
+            }
+        }
+    }
+
+    /// Handles the messages of a UDP datagram and returns the replies.
+    async fn handle_datagram(
+        &self,
+        datagram: &[u8],
+        token: Option<&Arc<Tokens>>,
+        addr: &str,
+    ) -> Vec<Vec<u8>> {
+        let (tx, mut rx) = mpsc::channel(CLIENT_QUEUE_LEN);
+        let mut replies = vec![];
+        let mut authenticated = token.is_none();
+        let mut read_only = self.read_only;
+        let mut client_name = None;
+        for msg in serde_json::Deserializer::from_slice(datagram).into_iter::<ClientMessage>() {
+            let stop = match msg {
+                Err(e) => {
+                    let response = ServerResponse::Error {
+                        msg: format!("Failed to deserialize command: {e}"),
+                    };
+                    let _ = tx.send(response.as_bytes()).await;
+                    true
+                }
+                Ok(ClientMessage::Authenticate { token: given }) => {
+                    let credential = self.authenticate(token, &given, &tx, addr).await;
+                    authenticated = credential.is_ok();
+                    read_only = self.is_read_only(credential.ok().flatten().as_ref());
+                    !authenticated
+                }
+                Ok(_) if !authenticated => {
+                    self.reject_unauthenticated(&tx, addr).await;
+                    true
+                }
+                Ok(msg) => {
+                    if let ClientMessage::Hello {
+                        client_name: Some(name),
+                        ..
+                    } = &msg
+                    {
+                        client_name = Some(name.clone());
+                    }
+                    let span = tracing::info_span!(
+                        "client_request",
+                        client = %addr,
+                        command = %loggable(&msg)
+                    );
+                    let client = Client {
+                        addr,
+                        name: client_name.as_deref(),
+                        read_only,
+                    };
+                    let handled = self
+                        .handle_message(msg, &tx, &client)
+                        .instrument(span)
+                        .await;
+                    self.wake_up();
+                    matches!(handled, Handled::Disconnect)
+                }
+            };
+            while let Ok(reply) = rx.try_recv() {
+                replies.push(reply);
+            }
+            if stop {
+                break;
+            }
+        }
+        replies
+    }
+
+    #[cfg(feature = "tcp_server_websocket")]
+    async fn serve_websocket(
+        self,
+        ws: tokio_tungstenite::WebSocketStream<TcpStream>,
+        addr: String,
+        token: Option<Arc<Tokens>>,
+    ) {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let (mut ws_tx, mut ws_rx) = ws.split();
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(CLIENT_QUEUE_LEN);
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                let text = String::from_utf8_lossy(&msg).trim_end().to_owned();
+                if let Err(e) = ws_tx.send(Message::text(text)).await {
+                    tracing::warn!("websocket write error: {e:?}");
+                    break;
+                }
+            }
+            let _ = ws_tx.close().await;
+        });
+        // Adapt the WebSocket messages into a byte stream so that messages are parsed the same way
+        // as for TCP clients.
+        let (reader, mut writer) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move {
+            while let Some(msg) = ws_rx.next().await {
+                let data = match msg {
+                    Ok(Message::Text(text)) => text.as_bytes().to_vec(),
+                    Ok(Message::Binary(data)) => data.to_vec(),
+                    Ok(Message::Close(_)) | Err(_) => break,
+                    Ok(_) => continue,
+                };
+                if writer.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        });
+        self.serve_client(reader, tx, addr, token, false).await;
+    }
+
+    /// Sends the current layer to a new client and registers it for notifications. Returns false
+    /// if the client is gone.
+    async fn register(
+        &self,
+        tx: &mpsc::Sender<Vec<u8>>,
+        addr: &str,
+        disconnect: &Arc<Notify>,
+        credential: Option<Credential>,
+    ) -> bool {
+        tracing::info!(
+            "new client connection, sending initial LayerChange event to inform them of current layer"
+        );
+        let initial = self
+            .with_kanata(|k| ServerMessage::LayerChange {
+                new: k.layer_info[k.layout.b().current_layer()].name.clone(),
+            })
+            .await;
+        if tx.send(initial.as_bytes()).await.is_err() {
+            return false;
+        }
+        let outbox = Arc::new(Outbox::default());
+        tokio::spawn(outbox.clone().forward(addr.to_owned(), tx.clone()));
+        self.connections.lock().insert(
+            addr.to_owned(),
+            ClientHandle {
+                tx: tx.clone(),
+                outbox,
+                disconnect: disconnect.clone(),
+                credential,
+                cmd_output: false,
+            },
+        );
+        tracing::info!("listening for incoming messages {addr}");
+        true
+    }
+
+    /// Replies to `Authenticate` and returns the credential of the token if it is accepted.
+    /// Listeners without tokens accept any, with no credential.
+    async fn authenticate(
+        &self,
+        expected: Option<&Arc<Tokens>>,
+        given: &str,
+        tx: &mpsc::Sender<Vec<u8>>,
+        addr: &str,
+    ) -> Result<Option<Credential>, ()> {
+        let credential = match expected {
+            Some(tokens) => tokens.authenticate(given).map(Some).ok_or(()),
+            None => Ok(None),
+        };
+        let response = if credential.is_ok() {
+            ServerResponse::Ok
+        } else {
+            tracing::warn!("client {addr} sent a wrong token");
+            ServerResponse::Error {
+                msg: "authentication failed".to_owned(),
+            }
+        };
+        let response = response.as_bytes();
+        if let Some(log) = &self.audit_log {
+            let client = Client {
+                addr,
+                name: None,
+                read_only: self.read_only,
+            };
+            log.record(&client, "Authenticate { .. }", Some(&response));
+        }
+        let _ = tx.send(response).await;
+        credential
+    }
 
-                        let addr = match stream.peer_addr() {
-                            Ok(addr) => addr.to_string(),
-                            Err(e) => {
-                                log::warn!("failed to get peer address, using fallback: {e:?}");
-                                format!("unknown_{}", std::ptr::addr_of!(stream) as usize)
-                            }
+    /// Whether a client of the listener that authenticated with the credential may only send
+    /// queries.
+    fn is_read_only(&self, credential: Option<&Credential>) -> bool {
+        self.read_only || credential.is_some_and(Credential::is_read_only)
+    }
+
+    async fn reject_unauthenticated(&self, tx: &mpsc::Sender<Vec<u8>>, addr: &str) {
+        tracing::warn!("client {addr} sent a command without authenticating");
+        let response = ServerResponse::Error {
+            msg: "authentication required, send Authenticate first".to_owned(),
+        };
+        let _ = tx.send(response.as_bytes()).await;
+    }
+
+    async fn stats(&self) -> ServerMessage {
+        const LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+        let threads = crate::kanata::thread_health();
+        let mut channels: Vec<_> = crate::kanata::input_queue_depth().into_iter().collect();
+        let mut clients: Vec<_> = self
+            .connections
+            .lock()
+            .iter()
+            .map(|(addr, client)| ChannelDepth {
+                name: format!("tcp-client:{addr}"),
+                len: (client.outbox.len() + client.tx.max_capacity() - client.tx.capacity()) as u64,
+                capacity: 2 * CLIENT_QUEUE_LEN as u64,
+            })
+            .collect();
+        clients.sort_by(|a, b| a.name.cmp(&b.name));
+        channels.extend(clients);
+        self.blocking(move |kanata| {
+            let Some(mut k) = kanata.try_lock_for(LOCK_TIMEOUT) else {
+                tracing::warn!("stats: the kanata state stayed locked for {LOCK_TIMEOUT:?}");
+                return ServerMessage::Stats {
+                    presses: 0,
+                    wpm: 0.0,
+                    keys_per_second: 0.0,
+                    latency: None,
+                    state_locked: true,
+                    threads,
+                    channels,
+                };
+            };
+            let (wpm, keys_per_second) = k.typing_speed.speed(web_time::Instant::now());
+            ServerMessage::Stats {
+                presses: k.key_presses,
+                wpm,
+                keys_per_second,
+                latency: k.latency_summary().map(|s| LatencyStats {
+                    count: s.count,
+                    p50_us: s.p50_us,
+                    p99_us: s.p99_us,
+                    max_us: s.max_us,
+                }),
+                state_locked: false,
+                threads,
+                channels,
+            }
+        })
+        .await
+    }
+
+    /// The seat from `linux-seat`, which tells clients of kanatas that run for different seats
+    /// apart.
+    async fn seat(&self) -> Option<String> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        return self.with_kanata(|k| k.seat.clone()).await;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        None
+    }
+
+    async fn hello_ok(&self, framing: Option<Framing>) -> ServerMessage {
+        ServerMessage::HelloOk {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol: 1,
+            capabilities: capabilities(),
+            seat: self.seat().await,
+            framing,
+        }
+    }
+
+    /// Wakes up the processing loop so that it handles a command right away. If the channel is
+    /// full then a wakeup is already pending.
+    fn wake_up(&self) {
+        use kanata_parser::keys::*;
+        let _ = self
+            .wakeup_channel
+            .try_send(KeyEvent::new(OsCode::KEY_RESERVED, KeyValue::WakeUp));
+    }
+
+    /// Handles the messages of a client until it disconnects. `stream` is false for transports
+    /// that separate messages themselves, which can not change the framing.
+    async fn serve_client(
+        self,
+        mut reader: impl AsyncRead + Unpin,
+        tx: mpsc::Sender<Vec<u8>>,
+        addr: String,
+        token: Option<Arc<Tokens>>,
+        stream: bool,
+    ) {
+        let disconnect = Arc::new(Notify::new());
+        // Clients of listeners with a token are registered once they authenticate.
+        let mut authenticated = token.is_none();
+        if authenticated && !self.register(&tx, &addr, &disconnect, None).await {
+            return;
+        }
+
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let mut framing = Framing::Newline;
+        let mut client_name = None;
+        let mut read_only = self.read_only;
+        'read: loop {
+            let n = tokio::select! {
+                n = reader.read(&mut chunk) => n,
+                _ = disconnect.notified() => break,
+            };
+            match n {
+                Ok(0) => break,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                Err(e) => {
+                    tracing::warn!("tcp client {addr} read error: {e:?}");
+                    break;
+                }
+            }
+            loop {
+                let msg = match take_message(&mut buf, framing) {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::warn!(
+                            "client sent an invalid message, disconnecting them. Err: {e}"
+                        );
+                        // Send proper error response for malformed JSON
+                        let response = ServerResponse::Error {
+                            msg: format!("Failed to deserialize command: {e}"),
                         };
+                        let _ = tx.send(response.as_bytes()).await;
+                        break 'read;
+                    }
+                };
+                // Checked before logging, which would show the token.
+                if let ClientMessage::Authenticate { token: given } = &msg {
+                    let Ok(credential) = self.authenticate(token.as_ref(), given, &tx, &addr).await
+                    else {
+                        break 'read;
+                    };
+                    read_only = self.is_read_only(credential.as_ref());
+                    if !authenticated {
+                        authenticated = true;
+                        if !self.register(&tx, &addr, &disconnect, credential).await {
+                            break 'read;
+                        }
+                    } else if let Some(client) = self.connections.lock().get_mut(&addr) {
+                        client.credential = credential;
+                    }
+                    continue;
+                }
+                if !authenticated {
+                    self.reject_unauthenticated(&tx, &addr).await;
+                    break 'read;
+                }
+                if let ClientMessage::Hello {
+                    client_name: Some(name),
+                    ..
+                } = &msg
+                {
+                    client_name = Some(name.clone());
+                }
+                // The rest of `buf` is parsed with the new framing.
+                if let ClientMessage::Hello {
+                    framing: Some(requested),
+                    ..
+                } = msg
+                    && stream
+                {
+                    let switch =
+                        framing == Framing::Newline && requested == Framing::LengthPrefixed;
+                    if switch {
+                        framing = requested;
+                    }
+                    if tx
+                        .send(self.hello_ok(Some(framing)).await.as_bytes())
+                        .await
+                        .is_err()
+                        || (switch && tx.send(SWITCH_TO_LENGTH_PREFIXED).await.is_err())
+                    {
+                        break 'read;
+                    }
+                    continue;
+                }
+                tracing::debug!("tcp server received command: {}", loggable(&msg));
+                let span = tracing::info_span!("client_request", client = %addr, command = %loggable(&msg));
+                let client = Client {
+                    addr: &addr,
+                    name: client_name.as_deref(),
+                    read_only,
+                };
+                let handled = self
+                    .handle_message(msg, &tx, &client)
+                    .instrument(span)
+                    .await;
+                self.wake_up();
+                if let Handled::Disconnect = handled {
+                    break 'read;
+                }
+            }
+        }
+        self.connections.lock().remove(&addr);
+    }
+
+    async fn handle_message(
+        &self,
+        msg: ClientMessage,
+        tx: &mpsc::Sender<Vec<u8>>,
+        client: &Client<'_>,
+    ) -> Handled {
+        use kanata_parser::cfg::FAKE_KEY_ROW;
 
-                        connections.lock().insert(
-                            addr.clone(),
-                            stream.try_clone().expect("stream is clonable"),
+        use crate::kanata::handle_fakekey_action;
+
+        let _working = crate::kanata::TCP_SERVER.working();
+        let audit = self.audit_log.as_ref().map(|log| (log, loggable(&msg)));
+
+        if client.read_only && msg.changes_state() {
+            tracing::warn!(
+                "client {} is read-only, rejecting {}",
+                client.addr,
+                loggable(&msg)
+            );
+            let response = ServerResponse::Forbidden {
+                command: command_name(&msg),
+                msg: "this client is read-only, it may only send queries".to_owned(),
+            }
+            .as_bytes();
+            if let Some((log, command)) = audit {
+                log.record(client, &command, Some(&response));
+            }
+            if tx.send(response).await.is_err() {
+                return Handled::Disconnect;
+            }
+            return Handled::Continue;
+        }
+
+        let reply = match msg {
+            ClientMessage::ChangeLayer { new } => {
+                self.with_kanata(move |k| k.change_layer(new)).await;
+                None
+            }
+            ClientMessage::RequestLayerNames {} => Some(
+                ServerMessage::LayerNames {
+                    names: self
+                        .with_kanata(|k| {
+                            k.layer_info
+                                .iter()
+                                .map(|info| info.name.clone())
+                                .collect::<Vec<_>>()
+                        })
+                        .await,
+                }
+                .as_bytes(),
+            ),
+            ClientMessage::RequestLayerMeta {} => Some(
+                ServerMessage::LayerMeta {
+                    layers: self
+                        .with_kanata(|k| {
+                            k.layer_info
+                                .iter()
+                                .map(|info| LayerMeta {
+                                    name: info.name.clone(),
+                                    display_name: info.display_name.clone(),
+                                    icon: info.icon.clone(),
+                                    color: info.color.clone(),
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                        .await,
+                }
+                .as_bytes(),
+            ),
+            ClientMessage::RequestConfigMeta {} => {
+                let meta = self.with_kanata(|k| k.config_meta.clone()).await;
+                Some(
+                    ServerMessage::ConfigMeta {
+                        author: meta.author,
+                        description: meta.description,
+                        version: meta.version,
+                        keyboard: meta.keyboard,
+                        tags: meta.tags,
+                    }
+                    .as_bytes(),
+                )
+            }
+            ClientMessage::SubscribeCmdOutput { enabled } => {
+                let mut connections = self.connections.lock();
+                let response = match connections.get_mut(client.addr) {
+                    Some(handle) => {
+                        handle.cmd_output = enabled;
+                        ServerResponse::Ok
+                    }
+                    None => ServerResponse::Error {
+                        msg: "this connection receives no notifications".to_owned(),
+                    },
+                };
+                CMD_OUTPUT_SUBSCRIBED.store(
+                    connections.values().any(|c| c.cmd_output),
+                    Ordering::Relaxed,
+                );
+                Some(response.as_bytes())
+            }
+            ClientMessage::CancelCmd { id, program } => {
+                tracing::info!("tcp server CancelCmd: id {id:?} program {program:?}");
+                #[cfg(feature = "cmd")]
+                let cancelled = crate::kanata::cancel_cmd(id, program.as_deref());
+                #[cfg(not(feature = "cmd"))]
+                let cancelled = false;
+                let response = match cancelled {
+                    true => ServerResponse::Ok,
+                    false => ServerResponse::Error {
+                        msg: "no running command to cancel".to_owned(),
+                    },
+                };
+                Some(response.as_bytes())
+            }
+            ClientMessage::RequestKeyDocs {} => Some(
+                self.with_kanata(|k| {
+                    ServerMessage::KeyDocs {
+                        aliases: k
+                            .alias_docs
+                            .iter()
+                            .map(|(alias, doc)| (alias.clone(), doc.clone()))
+                            .collect(),
+                        layers: k
+                            .layer_info
+                            .iter()
+                            .filter_map(|info| Some((info.name.clone(), info.doc.clone()?)))
+                            .collect(),
+                        virtual_keys: k
+                            .virtual_key_docs
+                            .iter()
+                            .map(|(vk, doc)| (vk.clone(), doc.clone()))
+                            .collect(),
+                    }
+                    .as_bytes()
+                })
+                .await,
+            ),
+            ClientMessage::RequestFakeKeyNames {} => Some(
+                ServerMessage::FakeKeyNames {
+                    names: self
+                        .with_kanata(|k| k.virtual_keys.keys().cloned().collect::<Vec<_>>())
+                        .await,
+                }
+                .as_bytes(),
+            ),
+            ClientMessage::ActOnFakeKey { name, action } => {
+                self.with_kanata(move |k| match k.virtual_keys.get(&name) {
+                    Some(index) => {
+                        let index = *index as u16;
+                        tracing::info!("tcp server fake-key action: {name},{action:?}");
+                        handle_fakekey_action(
+                            to_action(action),
+                            k.layout.bm(),
+                            FAKE_KEY_ROW,
+                            index,
+                        );
+                        None
+                    }
+                    None => Some(
+                        ServerMessage::Error {
+                            msg: format!("unknown virtual/fake key: {name}"),
+                        }
+                        .as_bytes(),
+                    ),
+                })
+                .await
+            }
+            cmd @ (ClientMessage::SetLayerFallback { .. }
+            | ClientMessage::SetLayerAlias { .. }
+            | ClientMessage::SetActiveApp { .. }
+            | ClientMessage::SetMacroDelayScale { .. }
+            | ClientMessage::SetTapHold { .. }
+            | ClientMessage::SetTrainingMode { .. }
+            | ClientMessage::ReloadTry { .. }
+            | ClientMessage::ConfirmReload {}) => {
+                tracing::info!("tcp server command: {cmd:?}");
+                let result = self
+                    .with_kanata(move |k| k.handle_client_command(cmd))
+                    .await;
+                let response = match result {
+                    Ok(_) => ServerResponse::Ok,
+                    Err(e) => ServerResponse::Error {
+                        msg: format!("{e}"),
+                    },
+                };
+                Some(response.as_bytes())
+            }
+            ClientMessage::SetMouse { x, y } => {
+                tracing::info!("tcp server SetMouse action: x {x} y {y}");
+                match self.with_kanata(move |k| k.kbd_out.set_mouse(x, y)).await {
+                    Ok(_) => {
+                        tracing::info!("sucessfully did set mouse position to: x {x} y {y}");
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to set mouse position: {}", e);
+                    }
+                }
+                None
+            }
+            ClientMessage::RequestCurrentLayerInfo {} => Some(
+                self.with_kanata(|k| {
+                    let cur_layer = k.layout.bm().current_layer();
+                    ServerMessage::CurrentLayerInfo {
+                        name: k.layer_info[cur_layer].name.clone(),
+                        cfg_text: k.layer_info[cur_layer].cfg_text.clone(),
+                    }
+                    .as_bytes()
+                })
+                .await,
+            ),
+            ClientMessage::RequestCurrentLayerName {} => Some(
+                self.with_kanata(|k| {
+                    let cur_layer = k.layout.bm().current_layer();
+                    ServerMessage::CurrentLayerName {
+                        name: k.layer_info[cur_layer].name.clone(),
+                    }
+                    .as_bytes()
+                })
+                .await,
+            ),
+            ClientMessage::RequestStats {} => Some(self.stats().await.as_bytes()),
+            ClientMessage::RequestTapHolds {} => Some(
+                ServerMessage::TapHolds {
+                    aliases: self.with_kanata(|k| k.tap_holds()).await,
+                }
+                .as_bytes(),
+            ),
+            ClientMessage::RequestNgramStats {} => {
+                let stats = self
+                    .with_kanata(|k| {
+                        k.ngram_stats()
+                            .map(|(keys, layers)| (keys.to_owned(), layers))
+                    })
+                    .await;
+                let msg = match stats {
+                    Some((keys, layers)) => ServerMessage::NgramStats { keys, layers },
+                    None => ServerMessage::Error {
+                        msg: "n-grams are not counted, start kanata with --ngram-stats".into(),
+                    },
+                };
+                Some(msg.as_bytes())
+            }
+            ClientMessage::RequestMousePosition {} => {
+                let msg = match self.with_kanata(|k| k.kbd_out.mouse_position()).await {
+                    Ok(Some((x, y))) => ServerMessage::MousePosition { x, y },
+                    Ok(None) => ServerMessage::Error {
+                        msg: "the output backend cannot read the mouse position".into(),
+                    },
+                    Err(e) => ServerMessage::Error {
+                        msg: format!("failed to read the mouse position: {e}"),
+                    },
+                };
+                Some(msg.as_bytes())
+            }
+            ClientMessage::SetLogLevel { level, duration_ms } => {
+                let response = match level.parse::<log::LevelFilter>() {
+                    Ok(level) => {
+                        crate::set_log_level(
+                            level,
+                            duration_ms.map(std::time::Duration::from_millis),
                         );
-                        let reader = serde_json::Deserializer::from_reader(
-                            stream.try_clone().expect("stream is clonable"),
-                        )
-                        .into_iter::<ClientMessage>();
-
-                        log::info!("listening for incoming messages {addr}");
-
-                        let connections = connections.clone();
-                        let kanata = kanata.clone();
-                        let wakeup_channel = wakeup_channel.clone();
-                        std::thread::spawn(move || {
-                            for v in reader {
-                                match v {
-                                    Ok(event) => {
-                                        log::debug!("tcp server received command: {:?}", event);
-                                        match event {
-                                            ClientMessage::ChangeLayer { new } => {
-                                                kanata.lock().change_layer(new);
-                                            }
-                                            ClientMessage::RequestLayerNames {} => {
-                                                let msg = ServerMessage::LayerNames {
-                                                    names: kanata
-                                                        .lock()
-                                                        .layer_info
-                                                        .iter()
-                                                        .map(|info| info.name.clone())
-                                                        .collect::<Vec<_>>(),
-                                                };
-                                                match stream.write_all(&msg.as_bytes()) {
-                                                    Ok(_) => {}
-                                                    Err(err) => log::error!(
-                                                        "server could not send response: {err}"
-                                                    ),
-                                                }
-                                            }
-                                            ClientMessage::RequestFakeKeyNames {} => {
-                                                let msg = ServerMessage::FakeKeyNames {
-                                                    names: kanata
-                                                        .lock()
-                                                        .virtual_keys
-                                                        .keys()
-                                                        .cloned()
-                                                        .collect::<Vec<_>>(),
-                                                };
-                                                match stream.write_all(&msg.as_bytes()) {
-                                                    Ok(_) => {}
-                                                    Err(err) => log::error!(
-                                                        "server could not send response: {err}"
-                                                    ),
-                                                }
-                                            }
-                                            ClientMessage::ActOnFakeKey { name, action } => {
-                                                let mut k = kanata.lock();
-                                                let index = match k.virtual_keys.get(&name) {
-                                                    Some(index) => Some(*index as u16),
-                                                    None => {
-                                                        if let Err(e) = stream.write_all(
-                                                            &ServerMessage::Error {
-                                                                msg: format!(
-                                                                    "unknown virtual/fake key: {name}"
-                                                                ),
-                                                            }
-                                                            .as_bytes(),
-                                                        ) {
-                                                            log::error!("stream write error: {e}");
-                                                            connections.lock().remove(&addr);
-                                                            break;
-                                                        }
-                                                        continue;
-                                                    }
-                                                };
-                                                if let Some(index) = index {
-                                                    log::info!(
-                                                        "tcp server fake-key action: {name},{action:?}"
-                                                    );
-                                                    handle_fakekey_action(
-                                                        to_action(action),
-                                                        k.layout.bm(),
-                                                        FAKE_KEY_ROW,
-                                                        index,
-                                                    );
-                                                }
-                                                drop(k);
-                                            }
-                                            ClientMessage::SetMouse { x, y } => {
-                                                log::info!(
-                                                    "tcp server SetMouse action: x {x} y {y}"
-                                                );
-                                                match kanata.lock().kbd_out.set_mouse(x, y) {
-                                                    Ok(_) => {
-                                                        log::info!(
-                                                            "sucessfully did set mouse position to: x {x} y {y}"
-                                                        );
-                                                    }
-                                                    Err(e) => {
-                                                        log::error!(
-                                                            "Failed to set mouse position: {}",
-                                                            e
-                                                        );
-                                                    }
-                                                }
-                                            }
-                                            ClientMessage::RequestCurrentLayerInfo {} => {
-                                                let mut k = kanata.lock();
-                                                let cur_layer = k.layout.bm().current_layer();
-                                                let msg = ServerMessage::CurrentLayerInfo {
-                                                    name: k.layer_info[cur_layer].name.clone(),
-                                                    cfg_text: k.layer_info[cur_layer]
-                                                        .cfg_text
-                                                        .clone(),
-                                                };
-                                                drop(k);
-                                                match stream.write_all(&msg.as_bytes()) {
-                                                    Ok(_) => {}
-                                                    Err(err) => log::error!(
-                                                        "Error writing response to RequestCurrentLayerInfo: {err}"
-                                                    ),
-                                                }
-                                            }
-                                            ClientMessage::RequestCurrentLayerName {} => {
-                                                let mut k = kanata.lock();
-                                                let cur_layer = k.layout.bm().current_layer();
-                                                let msg = ServerMessage::CurrentLayerName {
-                                                    name: k.layer_info[cur_layer].name.clone(),
-                                                };
-                                                drop(k);
-                                                match stream.write_all(&msg.as_bytes()) {
-                                                    Ok(_) => {}
-                                                    Err(err) => log::error!(
-                                                        "Error writing response to RequestCurrentLayerName: {err}"
-                                                    ),
-                                                }
-                                            }
-                                            // New command: Hello - capability detection
-                                            ClientMessage::Hello {} => {
-                                                let version = env!("CARGO_PKG_VERSION").to_string();
-                                                let capabilities = vec![
-                                                    "reload".to_string(),
-                                                    "layer-names".to_string(),
-                                                    "fake-key-names".to_string(),
-                                                    "layer-change".to_string(),
-                                                    "hold-activated".to_string(),
-                                                    "tap-activated".to_string(),
-                                                    "current-layer-name".to_string(),
-                                                    "current-layer-info".to_string(),
-                                                    "fake-key".to_string(),
-                                                    "set-mouse".to_string(),
-                                                ];
-                                                let msg = ServerMessage::HelloOk {
-                                                    version,
-                                                    protocol: 1,
-                                                    capabilities,
-                                                };
-                                                match stream.write_all(&msg.as_bytes()) {
-                                                    Ok(_) => {
-                                                        let _ = stream.flush();
-                                                    }
-                                                    Err(err) => {
-                                                        log::error!(
-                                                            "Error writing HelloOk response: {err}"
-                                                        );
-                                                        connections.lock().remove(&addr);
-                                                        break;
-                                                    }
-                                                }
-                                            }
-                                            // Reload commands with optional wait/timeout
-                                            ClientMessage::Reload { wait, timeout_ms } => {
-                                                log::info!("tcp server Reload action");
-                                                if !handle_reload_with_wait(
-                                                    ClientMessage::Reload { wait, timeout_ms },
-                                                    wait,
-                                                    timeout_ms,
-                                                    &mut stream,
-                                                    &kanata,
-                                                    &connections,
-                                                    &addr,
-                                                ) {
-                                                    break;
-                                                }
-                                            }
-                                            ClientMessage::ReloadNext { wait, timeout_ms } => {
-                                                log::info!("tcp server ReloadNext action");
-                                                if !handle_reload_with_wait(
-                                                    ClientMessage::ReloadNext { wait, timeout_ms },
-                                                    wait,
-                                                    timeout_ms,
-                                                    &mut stream,
-                                                    &kanata,
-                                                    &connections,
-                                                    &addr,
-                                                ) {
-                                                    break;
-                                                }
-                                            }
-                                            ClientMessage::ReloadPrev { wait, timeout_ms } => {
-                                                log::info!("tcp server ReloadPrev action");
-                                                if !handle_reload_with_wait(
-                                                    ClientMessage::ReloadPrev { wait, timeout_ms },
-                                                    wait,
-                                                    timeout_ms,
-                                                    &mut stream,
-                                                    &kanata,
-                                                    &connections,
-                                                    &addr,
-                                                ) {
-                                                    break;
-                                                }
-                                            }
-                                            ClientMessage::ReloadNum {
-                                                index,
-                                                wait,
-                                                timeout_ms,
-                                            } => {
-                                                log::info!(
-                                                    "tcp server ReloadNum action: index {index}"
-                                                );
-                                                if !handle_reload_with_wait(
-                                                    ClientMessage::ReloadNum {
-                                                        index,
-                                                        wait,
-                                                        timeout_ms,
-                                                    },
-                                                    wait,
-                                                    timeout_ms,
-                                                    &mut stream,
-                                                    &kanata,
-                                                    &connections,
-                                                    &addr,
-                                                ) {
-                                                    break;
-                                                }
-                                            }
-                                            ClientMessage::ReloadFile {
-                                                path,
-                                                wait,
-                                                timeout_ms,
-                                            } => {
-                                                log::info!(
-                                                    "tcp server ReloadFile action: path {path}"
-                                                );
-                                                if !handle_reload_with_wait(
-                                                    ClientMessage::ReloadFile {
-                                                        path,
-                                                        wait,
-                                                        timeout_ms,
-                                                    },
-                                                    wait,
-                                                    timeout_ms,
-                                                    &mut stream,
-                                                    &kanata,
-                                                    &connections,
-                                                    &addr,
-                                                ) {
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                        use kanata_parser::keys::*;
-                                        wakeup_channel
-                                            .send(KeyEvent::new(
-                                                OsCode::KEY_RESERVED,
-                                                KeyValue::WakeUp,
-                                            ))
-                                            .expect("write key event");
-                                    }
-                                    Err(e) => {
-                                        log::warn!(
-                                            "client sent an invalid message, disconnecting them. Err: {e:?}"
-                                        );
-                                        // Send proper error response for malformed JSON
-                                        let response = ServerResponse::Error {
-                                            msg: format!("Failed to deserialize command: {e}"),
-                                        };
-                                        let _ = stream.write_all(&response.as_bytes());
-                                        connections.lock().remove(&addr);
-                                        break;
-                                    }
-                                }
-                            }
-                        });
+                        ServerResponse::Ok
                     }
-                    Err(_) => log::error!("not able to accept client connection"),
+                    Err(_) => ServerResponse::Error {
+                        msg: format!(
+                            "unknown log level {level}, expected trace, debug, info, warn, error or off"
+                        ),
+                    },
+                };
+                Some(response.as_bytes())
+            }
+            // New command: Hello - capability detection
+            ClientMessage::Hello { .. } => Some(self.hello_ok(None).await.as_bytes()),
+            // Checked by the listener before messages are handled.
+            ClientMessage::Authenticate { .. } => Some(ServerResponse::Ok.as_bytes()),
+            ClientMessage::RevokeToken { token } => Some(self.revoke_token(&token).as_bytes()),
+            // Reload commands with optional wait/timeout
+            cmd @ (ClientMessage::Reload { wait, timeout_ms }
+            | ClientMessage::ReloadNext { wait, timeout_ms }
+            | ClientMessage::ReloadPrev { wait, timeout_ms }
+            | ClientMessage::ReloadNum {
+                wait, timeout_ms, ..
+            }
+            | ClientMessage::ReloadFile {
+                wait, timeout_ms, ..
+            }
+            | ClientMessage::ReloadRollback { wait, timeout_ms }
+            | ClientMessage::ReloadVersion {
+                wait, timeout_ms, ..
+            }) => {
+                tracing::info!("tcp server reload action: {cmd:?}");
+                return self
+                    .handle_reload_with_wait(cmd, wait, timeout_ms, tx, audit, client)
+                    .await;
+            }
+        };
+        if let Some((log, command)) = audit {
+            log.record(client, &command, reply.as_deref());
+        }
+        if let Some(reply) = reply
+            && tx.send(reply).await.is_err()
+        {
+            return Handled::Disconnect;
+        }
+        Handled::Continue
+    }
+
+    /// Handles reload commands with optional wait/timeout for completion confirmation.
+    async fn handle_reload_with_wait(
+        &self,
+        reload_cmd: ClientMessage,
+        wait: Option<bool>,
+        timeout_ms: Option<u64>,
+        tx: &mpsc::Sender<Vec<u8>>,
+        audit: Option<(&AuditLog, String)>,
+        client: &Client<'_>,
+    ) -> Handled {
+        let result = self
+            .with_kanata(move |k| k.handle_client_command(reload_cmd))
+            .await;
+        let (response, reload_ok) = match result {
+            Ok(_) => (ServerResponse::Ok, true),
+            Err(e) => (
+                ServerResponse::Error {
+                    msg: format!("{e}"),
+                },
+                false,
+            ),
+        };
+        let response = response.as_bytes();
+        if let Some((log, command)) = audit {
+            log.record(client, &command, Some(&response));
+        }
+        if tx.send(response).await.is_err() {
+            return Handled::Disconnect;
+        }
+
+        // If wait flag is set and reload succeeded, poll for completion
+        if reload_ok && wait.unwrap_or(false) {
+            let timeout_ms = timeout_ms.unwrap_or(5000);
+            let start = std::time::Instant::now();
+            let timeout_duration = std::time::Duration::from_millis(timeout_ms);
+
+            while start.elapsed() < timeout_duration {
+                if self.with_kanata(|k| k.is_reload_complete()).await {
+                    break;
                 }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
             }
-        });
+            let timed_out = start.elapsed() >= timeout_duration;
+
+            let ok = self.with_kanata(|k| k.last_reload_succeeded()).await;
+            let msg = ServerMessage::ReloadResult {
+                ok,
+                timeout_ms: if timed_out { Some(timeout_ms) } else { None },
+            };
+            if tx.send(msg.as_bytes()).await.is_err() {
+                return Handled::Disconnect;
+            }
+        }
+        Handled::Continue
     }
+}
 
-    #[cfg(not(feature = "tcp_server"))]
-    pub fn start(&mut self, _kanata: Arc<Mutex<Kanata>>) {}
+/// Removes the next message from the start of `buf`, or returns `None` if `buf` does not hold a
+/// whole message yet.
+#[cfg(feature = "tcp_server")]
+fn take_message(buf: &mut Vec<u8>, framing: Framing) -> Result<Option<ClientMessage>, String> {
+    match framing {
+        // Messages do not need a separator, and one read can hold several messages or only
+        // part of one.
+        Framing::Newline => {
+            let mut stream = serde_json::Deserializer::from_slice(buf).into_iter::<ClientMessage>();
+            let msg = match stream.next() {
+                Some(Ok(msg)) => Some(msg),
+                Some(Err(e)) if e.is_eof() => return Ok(None),
+                Some(Err(e)) => return Err(e.to_string()),
+                None => None,
+            };
+            let consumed = stream.byte_offset();
+            buf.drain(..consumed);
+            Ok(msg)
+        }
+        Framing::LengthPrefixed => {
+            let Some(len) = buf.first_chunk::<4>() else {
+                return Ok(None);
+            };
+            let len = u32::from_be_bytes(*len) as usize;
+            if len > MAX_FRAME_LEN {
+                return Err(format!("the message length {len} is too large"));
+            }
+            let Some(msg) = buf.get(4..4 + len) else {
+                return Ok(None);
+            };
+            let msg = serde_json::from_slice(msg).map_err(|e| e.to_string());
+            buf.drain(..4 + len);
+            msg.map(Some)
+        }
+    }
+}
+
+/// Writes queued messages to the client until the queue is closed or writing fails.
+#[cfg(feature = "tcp_server")]
+async fn write_stream(mut writer: impl AsyncWrite + Unpin, mut rx: mpsc::Receiver<Vec<u8>>) {
+    let mut length_prefixed = false;
+    while let Some(msg) = rx.recv().await {
+        if msg == SWITCH_TO_LENGTH_PREFIXED {
+            length_prefixed = true;
+            continue;
+        }
+        let written = match length_prefixed {
+            true => {
+                let msg = msg.strip_suffix(b"\n").unwrap_or(&msg);
+                let len = u32::try_from(msg.len()).unwrap_or(u32::MAX).to_be_bytes();
+                match writer.write_all(&len).await {
+                    Ok(()) => writer.write_all(msg).await,
+                    Err(e) => Err(e),
+                }
+            }
+            false => writer.write_all(&msg).await,
+        };
+        if let Err(e) = written {
+            tracing::warn!("stream write error: {e}");
+            break;
+        }
+        let _ = writer.flush().await;
+    }
+}
+
+/// Returns true if the client starts the connection with an HTTP request, which can only be a
+/// WebSocket handshake since client messages are JSON.
+///
+/// TCP clients usually wait for the initial `LayerChange` message before sending anything, so a
+/// client that sends nothing for a short while is treated as a TCP client.
+#[cfg(feature = "tcp_server_websocket")]
+async fn is_websocket_handshake(stream: &TcpStream) -> bool {
+    const HANDSHAKE_WAIT: std::time::Duration = std::time::Duration::from_millis(100);
+    let peek = async {
+        let mut start = [0u8; 4];
+        loop {
+            match stream.peek(&mut start).await {
+                Ok(0) | Err(_) => return false,
+                Ok(n) if n < start.len() && start[..n] == b"GET "[..n] => {
+                    // Wait for the rest of the request line.
+                    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                }
+                Ok(n) => return start[..n] == b"GET "[..],
+            }
+        }
+    };
+    tokio::time::timeout(HANDSHAKE_WAIT, peek)
+        .await
+        .unwrap_or(false)
 }
 
 #[cfg(feature = "tcp_server")]
@@ -480,3 +1692,979 @@ pub fn simple_sexpr_to_json_array(exprs: &[SimpleSExpr]) -> serde_json::Value {
 
     serde_json::Value::Array(result)
 }
+
+#[cfg(all(test, feature = "tcp_server", feature = "simulated_output"))]
+mod tests {
+    use super::*;
+    use crate::{EventReceiver, event_queue};
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    fn start_server() -> (TcpServer, EventReceiver) {
+        start_server_with(vec!["127.0.0.1:0".parse().unwrap()])
+    }
+
+    fn start_server_with(listeners: Vec<Listener>) -> (TcpServer, EventReceiver) {
+        let (server, rx, _kanata) = start_server_with_kanata(listeners);
+        (server, rx)
+    }
+
+    fn start_server_with_kanata(
+        listeners: Vec<Listener>,
+    ) -> (TcpServer, EventReceiver, Arc<Mutex<Kanata>>) {
+        let kanata = {
+            let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            Kanata::new_from_str(
+                "(defsrc a) (deflayer base a) (deflayer nav b)",
+                Default::default(),
+            )
+            .expect("cfg parses")
+        };
+        let kanata = Arc::new(Mutex::new(kanata));
+        let (tx, rx) = event_queue(100);
+        let mut server = TcpServer::with_listeners(listeners, tx);
+        server.start(kanata.clone()).expect("server starts");
+        (server, rx, kanata)
+    }
+
+    #[test]
+    fn tcp_server_handles_split_and_batched_messages() {
+        let (server, _rx) = start_server();
+        let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"LayerChange\":{\"new\":\"base\"}}\n");
+
+        writer
+            .write_all(br#"{"RequestCurrentLayerName":{}}{"RequestLa"#)
+            .unwrap();
+        writer.flush().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        writer.write_all(br#"yerNames":{}}"#).unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"CurrentLayerName\":{\"name\":\"base\"}}\n");
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"LayerNames\":{\"names\":[\"base\",\"nav\"]}}\n");
+
+        writer.write_all(b"not json").unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.contains("Failed to deserialize command"), "{line}");
+        let mut rest = vec![];
+        assert_eq!(reader.read_to_end(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn tcp_server_sends_stats() {
+        let (server, _rx) = start_server();
+        let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        writer.write_all(br#"{"RequestStats":{}}"#).unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        // Latency is only measured with --measure-latency.
+        assert!(
+            line.starts_with("{\"Stats\":{\"presses\":0,\"wpm\":0.0,\"keys_per_second\":0.0,"),
+            "{line}"
+        );
+        let Ok(ServerMessage::Stats {
+            latency: None,
+            state_locked: false,
+            threads,
+            channels,
+            ..
+        }) = serde_json::from_str(&line)
+        else {
+            panic!("{line}");
+        };
+        assert!(
+            threads
+                .iter()
+                .any(|t| t.name == "tcp-server" && t.threads > 0 && t.busy_ms.is_some()),
+            "{line}"
+        );
+        assert!(
+            channels
+                .iter()
+                .any(|c| c.name.starts_with("tcp-client:") && c.capacity > 0),
+            "{line}"
+        );
+    }
+
+    #[test]
+    fn tcp_server_serves_clients_while_the_state_is_locked() {
+        let (server, _rx, kanata) = start_server_with_kanata(vec!["127.0.0.1:0".parse().unwrap()]);
+        let connect = || {
+            let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+            stream
+                .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+                .unwrap();
+            let writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            (writer, reader)
+        };
+        let (mut waiting, mut waiting_reader) = connect();
+        let (mut other, mut other_reader) = connect();
+
+        let locked = kanata.lock();
+        waiting.write_all(br#"{"RequestLayerNames":{}}"#).unwrap();
+        other.write_all(br#"{"RequestStats":{}}"#).unwrap();
+        let mut line = String::new();
+        other_reader.read_line(&mut line).unwrap();
+        assert!(line.contains("\"state_locked\":true"), "{line}");
+
+        drop(locked);
+        line.clear();
+        waiting_reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with("{\"LayerNames\""), "{line}");
+    }
+
+    #[test]
+    fn tcp_server_sends_cmd_output_to_subscribed_clients() {
+        let (server, _rx) = start_server();
+        let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let output = ServerMessage::CmdOutput {
+            id: 1,
+            stream: "stdout".to_owned(),
+            line: "done".to_owned(),
+        };
+        // Not subscribed, so only the second notification arrives.
+        broadcast(&server.connections, &output);
+        broadcast(
+            &server.connections,
+            &ServerMessage::LayerChange { new: "nav".into() },
+        );
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"LayerChange\":{\"new\":\"nav\"}}\n");
+        assert!(!cmd_output_subscribed());
+
+        writer
+            .write_all(br#"{"SubscribeCmdOutput":{"enabled":true}}"#)
+            .unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"status\":\"Ok\"}\n");
+        assert!(cmd_output_subscribed());
+        broadcast(&server.connections, &output);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(
+            line,
+            "{\"CmdOutput\":{\"id\":1,\"stream\":\"stdout\",\"line\":\"done\"}}\n"
+        );
+    }
+
+    #[test]
+    fn tcp_server_switches_to_length_prefixed_framing() {
+        let (server, _rx) = start_server();
+        let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+
+        // The first length-prefixed message is in the same write as the request to switch.
+        let request = br#"{"RequestCurrentLayerName":{}}"#;
+        let mut msgs = br#"{"Hello":{"framing":"length-prefixed"}}"#.to_vec();
+        msgs.extend((request.len() as u32).to_be_bytes());
+        msgs.extend(request);
+        writer.write_all(&msgs).unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.contains(r#""framing":"length-prefixed""#), "{line}");
+
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len).unwrap();
+        let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+        reader.read_exact(&mut msg).unwrap();
+        assert_eq!(msg, br#"{"CurrentLayerName":{"name":"base"}}"#);
+    }
+
+    #[test]
+    fn tcp_server_disconnects_clients_that_do_not_read() {
+        let (server, _rx) = start_server();
+        let slow = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        let fast = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while server.connections.lock().len() < 2 {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        // Large messages fill the socket buffers and then the queue of the client that does not
+        // read, which must not stop notifications from reaching the other client.
+        let msg = ServerMessage::MessagePush {
+            message: serde_json::Value::String("x".repeat(64 * 1024)),
+        };
+        let mut fast_reader = BufReader::new(fast);
+        let mut line = String::new();
+        fast_reader.read_line(&mut line).unwrap();
+        for _ in 0..CLIENT_QUEUE_LEN * 4 {
+            broadcast(&server.connections, &msg);
+            line.clear();
+            fast_reader.read_line(&mut line).unwrap();
+            if server.connections.lock().len() == 1 {
+                break;
+            }
+        }
+        assert_eq!(server.connections.lock().len(), 1);
+        drop(slow);
+    }
+
+    #[cfg(feature = "tcp_server_websocket")]
+    #[test]
+    fn tcp_server_accepts_websocket_clients() {
+        use tokio_tungstenite::tungstenite::{Message, client};
+
+        let (server, _rx) = start_server();
+        let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        let url = format!("ws://{}/", server.tcp_address().unwrap());
+        let (mut ws, _) = client(url.as_str(), stream).unwrap();
+        assert_eq!(
+            ws.read().unwrap(),
+            Message::text(r#"{"LayerChange":{"new":"base"}}"#)
+        );
+        ws.send(Message::text(r#"{"RequestCurrentLayerName":{}}"#))
+            .unwrap();
+        assert_eq!(
+            ws.read().unwrap(),
+            Message::text(r#"{"CurrentLayerName":{"name":"base"}}"#)
+        );
+    }
+
+    fn token_file(name: &str, token: &str) -> String {
+        let path = std::env::temp_dir().join(format!("kanata-{name}-{}", std::process::id()));
+        std::fs::write(&path, format!("{token}\n")).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        path.display().to_string()
+    }
+
+    #[test]
+    fn parses_listeners() {
+        let path = token_file("token-parse", "secret");
+        let listener: Listener = format!("udp:5830,token-file={path}").parse().unwrap();
+        assert_eq!(
+            listener.endpoint,
+            Endpoint::Udp("127.0.0.1:5830".parse().unwrap())
+        );
+        assert!(
+            listener
+                .token
+                .as_ref()
+                .unwrap()
+                .authenticate("secret")
+                .is_some()
+        );
+        assert!(!format!("{listener:?}").contains("secret"));
+        let listener: Listener = "0.0.0.0:5829".parse().unwrap();
+        assert_eq!(
+            listener.endpoint,
+            Endpoint::Tcp("0.0.0.0:5829".parse().unwrap())
+        );
+        assert!(listener.token.is_none());
+        #[cfg(unix)]
+        assert_eq!(
+            "unix:/tmp/k.sock".parse::<Listener>().unwrap().endpoint,
+            Endpoint::Unix("/tmp/k.sock".into())
+        );
+        assert_eq!(
+            "via:5831".parse::<Listener>().unwrap().endpoint,
+            Endpoint::Via("127.0.0.1:5831".parse().unwrap())
+        );
+        assert!(
+            format!("via:5831,token-file={path}")
+                .parse::<Listener>()
+                .is_err()
+        );
+        assert_eq!(
+            format!("output:5840,token-file={path}")
+                .parse::<Listener>()
+                .unwrap()
+                .endpoint,
+            Endpoint::Output("127.0.0.1:5840".parse().unwrap())
+        );
+        let err = "output:5840".parse::<Listener>().unwrap_err().to_string();
+        assert!(
+            err.contains("token-file=PATH, token-secret=NAME or tokens-file=PATH"),
+            "{err}"
+        );
+        assert!(
+            "tls:5829,cert=cert.pem,key=key.pem"
+                .parse::<Listener>()
+                .unwrap_err()
+                .to_string()
+                .contains("TLS listeners are not supported")
+        );
+        assert!("tcp:5829,token=secret".parse::<Listener>().is_err());
+        assert!(
+            "5829,token-file=/nonexistent/token"
+                .parse::<Listener>()
+                .is_err()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+            for option in ["token-file", "tokens-file"] {
+                let err = format!("5829,{option}={path}")
+                    .parse::<Listener>()
+                    .unwrap_err()
+                    .to_string();
+                assert!(err.contains("chmod 600"), "{err}");
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn listeners_bind_to_interfaces_and_allow_addresses() {
+        let listener: Listener = "0.0.0.0:5829,allow=192.168.1.0/24,allow=10.0.0.5"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            listener.allow,
+            [
+                "192.168.1.0/24".parse().unwrap(),
+                "10.0.0.5".parse().unwrap()
+            ]
+        );
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            "udp:0.0.0.0:5830,interface=lo"
+                .parse::<Listener>()
+                .unwrap()
+                .endpoint,
+            Endpoint::Udp("127.0.0.1:5830".parse().unwrap())
+        );
+        assert!(
+            "5829,interface=no-such-interface"
+                .parse::<Listener>()
+                .is_err()
+        );
+        assert!("5829,allow=lan".parse::<Listener>().is_err());
+        #[cfg(unix)]
+        assert!(
+            "unix:/tmp/k.sock,allow=10.0.0.0/8"
+                .parse::<Listener>()
+                .is_err()
+        );
+
+        let listener = "127.0.0.1:0,allow=10.0.0.0/8".parse().unwrap();
+        let (server, _rx) = start_server_with(vec![listener]);
+        let mut stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        // Closed without the LayerChange that connected clients receive first.
+        assert_eq!(stream.read(&mut [0; 64]).unwrap(), 0);
+    }
+
+    #[test]
+    fn audit_log_records_commands_with_client_and_result() {
+        let path = std::env::temp_dir().join(format!("kanata-audit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let kanata = {
+            let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            Kanata::new_from_str("(defsrc a) (deflayer base a)", Default::default())
+                .expect("cfg parses")
+        };
+        let (tx, _rx) = event_queue(100);
+        let mut server = TcpServer::with_listeners(vec!["127.0.0.1:0".parse().unwrap()], tx);
+        server.audit_log = Some(path.display().to_string().parse().unwrap());
+        server
+            .start(Arc::new(Mutex::new(kanata)))
+            .expect("server starts");
+        let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        writer
+            .write_all(
+                br#"{"Hello":{"client_name":"nightly-script"}}{"ActOnFakeKey":{"name":"x","action":"Tap"}}"#,
+            )
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        for _ in 0..3 {
+            reader.read_line(&mut line).unwrap();
+        }
+        assert!(line.contains("unknown virtual/fake key"), "{line}");
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["client_name"], "nightly-script");
+        assert_eq!(records[1]["ok"], false);
+        assert_eq!(records[1]["error"], "unknown virtual/fake key: x");
+        assert!(
+            records[1]["client"]
+                .as_str()
+                .unwrap()
+                .starts_with("127.0.0.1:")
+        );
+        assert!(
+            records[1]["command"]
+                .as_str()
+                .unwrap()
+                .starts_with("ActOnFakeKey")
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn revoking_a_token_disconnects_its_clients() {
+        let path = token_file("tokens-revoke", "# rotated monthly\nleaked\nadmin");
+        let listener = format!("127.0.0.1:0,tokens-file={path}").parse().unwrap();
+        let (server, _rx) = start_server_with(vec![listener]);
+        let connect = |token: &str| {
+            let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+            stream
+                .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+                .unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            writeln!(writer, r#"{{"Authenticate":{{"token":"{token}"}}}}"#).unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "{\"status\":\"Ok\"}\n");
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.contains("LayerChange"), "{line}");
+            (writer, reader)
+        };
+        let (_leaked_writer, mut leaked) = connect("leaked");
+        let (mut admin_writer, mut admin) = connect("admin");
+        admin_writer
+            .write_all(br#"{"RevokeToken":{"token":"leaked"}}"#)
+            .unwrap();
+        let mut line = String::new();
+        admin.read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"status\":\"Ok\"}\n");
+        line.clear();
+        assert_eq!(leaked.read_line(&mut line).unwrap(), 0);
+
+        let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        writer
+            .write_all(br#"{"Authenticate":{"token":"leaked"}}"#)
+            .unwrap();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert!(line.contains("authentication failed"), "{line}");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn read_only_clients_may_only_send_queries() {
+        let path = token_file("tokens-read-only", "viewer read-only\nadmin");
+        let listener = format!("127.0.0.1:0,tokens-file={path}").parse().unwrap();
+        let read_only = "127.0.0.1:0,read-only".parse::<Listener>().unwrap();
+        assert!(read_only.read_only);
+        assert!("via:5829,read-only".parse::<Listener>().is_err());
+        let (server, _rx) = start_server_with(vec![listener]);
+        let stream = std::net::TcpStream::connect(server.tcp_address().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut send = |msg: &str| {
+            writeln!(writer, "{msg}").unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            // Clients receive the current layer once they authenticate.
+            if line.contains("LayerChange") {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+            line
+        };
+        assert_eq!(
+            send(r#"{"Authenticate":{"token":"viewer"}}"#),
+            "{\"status\":\"Ok\"}\n"
+        );
+        let line = send(r#"{"RequestLayerNames":{}}"#);
+        assert!(line.contains("LayerNames"), "{line}");
+        let line = send(r#"{"ChangeLayer":{"new":"base"}}"#);
+        assert!(
+            line.starts_with(r#"{"status":"Forbidden","command":"ChangeLayer""#),
+            "{line}"
+        );
+        // Authenticating again with a read-write token lifts the restriction.
+        assert_eq!(
+            send(r#"{"Authenticate":{"token":"admin"}}"#),
+            "{\"status\":\"Ok\"}\n"
+        );
+        assert_eq!(
+            send(r#"{"RevokeToken":{"token":"viewer"}}"#),
+            "{\"status\":\"Ok\"}\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tcp_server_reports_addresses_in_use() {
+        let (server, _rx) = start_server();
+        let kanata = {
+            let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            Kanata::new_from_str("(defsrc a) (deflayer base a)", Default::default())
+                .expect("cfg parses")
+        };
+        let (tx, _rx) = event_queue(100);
+        let mut second = TcpServer::new(server.tcp_address().unwrap(), tx);
+        let err = second
+            .start(Arc::new(Mutex::new(kanata)))
+            .expect_err("address is in use");
+        assert!(err.to_string().contains("could not listen for TCP clients"));
+    }
+
+    #[test]
+    fn tcp_server_requires_token() {
+        let path = token_file("token-tcp", "secret");
+        let listener = format!("127.0.0.1:0,token-file={path}").parse().unwrap();
+        let (server, _rx) = start_server_with(vec![listener]);
+        let address = server.tcp_address().unwrap();
+        let connect = || {
+            let stream = std::net::TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+                .unwrap();
+            (stream.try_clone().unwrap(), BufReader::new(stream))
+        };
+        let mut line = String::new();
+
+        let (mut writer, mut reader) = connect();
+        writer
+            .write_all(br#"{"RequestCurrentLayerName":{}}"#)
+            .unwrap();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.contains("authentication required"), "{line}");
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+
+        let (mut writer, mut reader) = connect();
+        writer
+            .write_all(br#"{"Authenticate":{"token":"wrong"}}"#)
+            .unwrap();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.contains("authentication failed"), "{line}");
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+        assert!(server.connections.lock().is_empty());
+
+        let (mut writer, mut reader) = connect();
+        writer
+            .write_all(br#"{"Authenticate":{"token":"secret"}}{"RequestCurrentLayerName":{}}"#)
+            .unwrap();
+        for expected in [
+            "{\"status\":\"Ok\"}\n",
+            "{\"LayerChange\":{\"new\":\"base\"}}\n",
+            "{\"CurrentLayerName\":{\"name\":\"base\"}}\n",
+        ] {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, expected);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn udp_listener_replies_to_sender() {
+        let path = token_file("token-udp", "secret");
+        let listener = format!("udp:127.0.0.1:0,token-file={path}")
+            .parse()
+            .unwrap();
+        let (server, _rx) = start_server_with(vec![listener]);
+        let Endpoint::Udp(address) = server.listeners[0].endpoint else {
+            panic!("udp listener");
+        };
+        let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0u8; 1024];
+        let mut recv = || {
+            let n = client.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..n].to_vec()).unwrap()
+        };
+
+        client
+            .send_to(br#"{"RequestCurrentLayerName":{}}"#, address)
+            .unwrap();
+        assert!(recv().contains("authentication required"));
+
+        client
+            .send_to(
+                br#"{"Authenticate":{"token":"secret"}}{"RequestCurrentLayerName":{}}"#,
+                address,
+            )
+            .unwrap();
+        assert_eq!(recv(), "{\"status\":\"Ok\"}\n");
+        assert_eq!(recv(), "{\"CurrentLayerName\":{\"name\":\"base\"}}\n");
+        assert!(server.connections.lock().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "udp_noise")]
+    fn noise_udp_listener_encrypts_datagrams() {
+        let key_path = std::env::temp_dir().join(format!("kanata-noise-{}", std::process::id()));
+        let _ = std::fs::remove_file(&key_path);
+        let listener = format!("udp:127.0.0.1:0,noise-key={}", key_path.display())
+            .parse()
+            .unwrap();
+        let (server, _rx) = start_server_with(vec![listener]);
+        let Endpoint::NoiseUdp { address, ref key } = server.listeners[0].endpoint else {
+            panic!("noise udp listener");
+        };
+        // The generated key file is read back the same.
+        let key_file = key_path.display().to_string();
+        assert_eq!(&noise::load_noise_key(&key_file).unwrap(), key);
+        assert!(
+            format!("tcp:5829,noise-key={key_file}")
+                .parse::<Listener>()
+                .is_err()
+        );
+
+        let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut handshake =
+            snow::Builder::new("Noise_NK_25519_ChaChaPoly_BLAKE2s".parse().unwrap())
+                .remote_public_key(&key.public)
+                .unwrap()
+                .prologue(b"kanata-udp")
+                .unwrap()
+                .build_initiator()
+                .unwrap();
+        let mut buf = [0u8; 1024];
+        let len = handshake.write_message(&[], &mut buf[1..]).unwrap();
+        buf[0] = noise::HANDSHAKE;
+        client.send_to(&buf[..len + 1], address).unwrap();
+        let n = client.recv(&mut buf).unwrap();
+        assert_eq!(buf[0], noise::HANDSHAKE);
+        handshake.read_message(&buf[1..n], &mut [0u8; 64]).unwrap();
+        let transport = handshake.into_stateless_transport_mode().unwrap();
+
+        let request = br#"{"RequestCurrentLayerName":{}}"#;
+        let mut datagram = vec![noise::TRANSPORT];
+        datagram.extend(7u64.to_be_bytes());
+        let mut sealed = [0u8; 1024];
+        let len = transport.write_message(7, request, &mut sealed).unwrap();
+        datagram.extend(&sealed[..len]);
+        client.send_to(&datagram, address).unwrap();
+        let n = client.recv(&mut buf).unwrap();
+        assert_eq!(buf[0], noise::TRANSPORT);
+        let nonce = u64::from_be_bytes(buf[1..9].try_into().unwrap());
+        let mut plain = [0u8; 1024];
+        let len = transport
+            .read_message(nonce, &buf[9..n], &mut plain)
+            .unwrap();
+        assert_eq!(
+            &plain[..len],
+            b"{\"CurrentLayerName\":{\"name\":\"base\"}}\n"
+        );
+
+        // Replayed and plain datagrams get no reply.
+        client.send_to(&datagram, address).unwrap();
+        client.send_to(request, address).unwrap();
+        client
+            .set_read_timeout(Some(std::time::Duration::from_millis(200)))
+            .unwrap();
+        assert!(client.recv(&mut buf).is_err());
+        std::fs::remove_file(key_path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_listener_serves_clients() {
+        let path = std::env::temp_dir().join(format!("kanata-test-{}.sock", std::process::id()));
+        let (_server, _rx) = start_server_with(vec![Listener {
+            endpoint: Endpoint::Unix(path.clone()),
+            token: None,
+            allow: vec![],
+            read_only: false,
+        }]);
+        let stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"LayerChange\":{\"new\":\"base\"}}\n");
+        writer
+            .write_all(br#"{"RequestCurrentLayerName":{}}"#)
+            .unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"CurrentLayerName\":{\"name\":\"base\"}}\n");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn via_listener_edits_config_file() {
+        let path = std::env::temp_dir().join(format!("kanata-via-{}.kbd", std::process::id()));
+        std::fs::write(&path, "(defsrc a b)\n(deflayer base a b)\n").unwrap();
+        let mut kanata = {
+            let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            Kanata::new_from_str("(defsrc a b) (deflayer base a b)", Default::default())
+                .expect("cfg parses")
+        };
+        kanata.cfg_paths = vec![path.clone()];
+        let (tx, _rx) = event_queue(100);
+        let mut server = TcpServer::with_listeners(
+            vec![Listener {
+                endpoint: Endpoint::Via("127.0.0.1:0".parse().unwrap()),
+                token: None,
+                allow: vec![],
+                read_only: false,
+            }],
+            tx,
+        );
+        server
+            .start(Arc::new(Mutex::new(kanata)))
+            .expect("server starts");
+        let Endpoint::Via(address) = server.listeners[0].endpoint else {
+            unreachable!();
+        };
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let mut request = |bytes: &[u8]| {
+            let mut packet = [0; via::PACKET_LEN];
+            packet[..bytes.len()].copy_from_slice(bytes);
+            stream.write_all(&packet).unwrap();
+            stream.read_exact(&mut packet).unwrap();
+            packet
+        };
+        assert_eq!(request(&[0x01])[..3], [0x01, 0x00, 0x0C]);
+        assert_eq!(request(&[0x04, 0, 0, 1])[4..6], [0x00, 0x05]);
+        // b -> C-x
+        request(&[0x05, 0, 0, 1, 0x01, 0x1B]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "(defsrc a b)\n(deflayer base a C-x)\n"
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn output_listener_injects_and_releases_keys() {
+        use kanata_parser::custom_action::{Btn, MoveDirection};
+        use kanata_parser::keys::OsCode;
+
+        let mut kanata = {
+            let _lk = match crate::tests::CFG_PARSE_LOCK.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            Kanata::new_from_str("(defsrc a) (deflayer base a)", Default::default())
+                .expect("cfg parses")
+        };
+        let (out_tx, out_rx) = std::sync::mpsc::channel();
+        kanata
+            .kbd_out
+            .set_output_sink(Box::new(move |event| drop(out_tx.send(event))));
+        let (tx, _rx) = event_queue(100);
+        let mut server = TcpServer::with_listeners(
+            vec![Listener {
+                endpoint: Endpoint::Output("127.0.0.1:0".parse().unwrap()),
+                token: Some(Arc::new("secret".into())),
+                allow: vec![],
+                read_only: false,
+            }],
+            tx,
+        );
+        server
+            .start(Arc::new(Mutex::new(kanata)))
+            .expect("server starts");
+        let Endpoint::Output(address) = server.listeners[0].endpoint else {
+            unreachable!();
+        };
+        let connect = |token: &str| {
+            let mut stream = std::net::TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+                .unwrap();
+            writeln!(stream, r#"{{"Authenticate":{{"token":"{token}"}}}}"#).unwrap();
+            let mut reply = String::new();
+            BufReader::new(&stream).read_line(&mut reply).unwrap();
+            (stream, reply)
+        };
+        let (_, reply) = connect("wrong");
+        assert!(reply.contains("Error"), "{reply}");
+        let (mut stream, reply) = connect("secret");
+        assert_eq!(reply, "{\"status\":\"Ok\"}\n");
+        for event in [
+            RemoteOutput::Key { code: 30, value: 1 },
+            RemoteOutput::Key {
+                code: OsCode::BTN_LEFT as u16,
+                value: 1,
+            },
+            RemoteOutput::MouseMove { x: -3, y: 0 },
+        ] {
+            stream.write_all(&event.as_bytes()).unwrap();
+        }
+        drop(stream);
+        let timeout = std::time::Duration::from_secs(5);
+        let events: Vec<_> = (0..5)
+            .map(|_| out_rx.recv_timeout(timeout).unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            [
+                OutputEvent::Key {
+                    code: OsCode::KEY_A,
+                    value: KeyValue::Press
+                },
+                OutputEvent::MousePress(Btn::Left),
+                OutputEvent::MouseMove {
+                    direction: MoveDirection::Left,
+                    distance: 3
+                },
+                OutputEvent::Key {
+                    code: OsCode::KEY_A,
+                    value: KeyValue::Release
+                },
+                OutputEvent::MouseRelease(Btn::Left),
+            ]
+        );
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn mqtt_client_publishes_and_handles_commands() {
+        use mqtt::*;
+
+        fn read_packet(stream: &mut std::net::TcpStream) -> Packet {
+            let mut byte = [0u8];
+            stream.read_exact(&mut byte).unwrap();
+            let header = byte[0];
+            let mut len = 0;
+            for shift in (0..28).step_by(7) {
+                stream.read_exact(&mut byte).unwrap();
+                len |= usize::from(byte[0] & 0x7f) << shift;
+                if byte[0] & 0x80 == 0 {
+                    break;
+                }
+            }
+            let mut body = vec![0; len];
+            stream.read_exact(&mut body).unwrap();
+            Packet {
+                kind: header >> 4,
+                flags: header & 0xf,
+                body,
+            }
+        }
+        /// Returns the payload of the next message published to the topic.
+        fn next_publish(stream: &mut std::net::TcpStream, topic: &str) -> Vec<u8> {
+            loop {
+                let packet = read_packet(stream);
+                if packet.kind == PUBLISH {
+                    let publish = parse_publish(&packet).unwrap();
+                    if publish.topic == topic {
+                        return publish.payload.to_vec();
+                    }
+                }
+            }
+        }
+
+        let broker = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = broker.local_addr().unwrap();
+        let listener =
+            format!("mqtt:{address},prefix=test,home-assistant,allow-command=ChangeLayer")
+                .parse()
+                .unwrap();
+        let (_server, _rx) = start_server_with(vec![listener]);
+        let (mut stream, _) = broker.accept().unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(read_packet(&mut stream).kind, CONNECT);
+        stream.write_all(&[CONNACK << 4, 2, 0, 0]).unwrap();
+        let subscribe = read_packet(&mut stream);
+        assert_eq!(subscribe.kind, SUBSCRIBE);
+        assert_eq!(&subscribe.body[2..], b"\0\x0ctest/command\0");
+        assert_eq!(next_publish(&mut stream, "test/status"), b"online");
+        let sensor = next_publish(&mut stream, "homeassistant/sensor/test/layer/config");
+        let sensor: serde_json::Value = serde_json::from_slice(&sensor).unwrap();
+        assert_eq!(sensor["state_topic"], "test/layer");
+        assert_eq!(next_publish(&mut stream, "test/layer"), b"base");
+
+        stream
+            .write_all(&publish_packet(
+                "test/command",
+                br#"{"ChangeLayer":{"new":"nav"}}{"RequestCurrentLayerName":{}}"#,
+                false,
+            ))
+            .unwrap();
+        assert_eq!(
+            next_publish(&mut stream, "test/response"),
+            br#"{"CurrentLayerName":{"name":"nav"}}"#
+        );
+
+        stream
+            .write_all(&publish_packet(
+                "test/command",
+                br#"{"SetLogLevel":{"level":"trace"}}"#,
+                false,
+            ))
+            .unwrap();
+        let response = next_publish(&mut stream, "test/response");
+        assert!(
+            response.starts_with(br#"{"status":"Forbidden","command":"SetLogLevel""#),
+            "{}",
+            String::from_utf8_lossy(&response)
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Which addresses a network listener binds to and accepts clients from, for the `interface=NAME`
+//! and `allow=CIDR` listener options, so that the server can be reachable on one network of a
+//! machine with several and only from known hosts.
+//!
+//! Clients from other addresses are dropped before they can send anything, including
+//! `Authenticate`.
+
+use super::*;
+use std::net::IpAddr;
+
+/// A range of IP addresses, like `192.168.1.0/24`, or a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = s.split_once('/').unwrap_or((s, ""));
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|e| anyhow!("invalid address {addr} in allow={s}: {e}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            "" => max,
+            len => len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max)
+                .ok_or_else(|| anyhow!("invalid prefix length {len} in allow={s}"))?,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // IPv4 clients of sockets bound to IPv6 addresses have IPv4-mapped addresses.
+        let addr = addr.to_canonical();
+        let bits = |addr: IpAddr| match addr {
+            IpAddr::V4(addr) => u128::from(addr.to_bits()) << 96,
+            IpAddr::V6(addr) => addr.to_bits(),
+        };
+        if self.addr.is_ipv4() != addr.is_ipv4() {
+            return false;
+        }
+        let mask = u128::MAX
+            .checked_shl(128 - u32::from(self.prefix_len))
+            .unwrap_or(0);
+        bits(self.addr) & mask == bits(addr) & mask
+    }
+}
+
+/// Whether a client from the address may connect. An empty allowlist allows every client.
+pub(super) fn is_allowed(allow: &[Cidr], addr: &SocketAddr) -> bool {
+    let allowed = allow.is_empty() || allow.iter().any(|cidr| cidr.contains(addr.ip()));
+    if !allowed {
+        tracing::warn!("dropping client {addr} which is not in the allowlist of the listener");
+    }
+    allowed
+}
+
+/// The address of the network interface, in the same IP family as `like` if it has one.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+pub(super) fn interface_addr(name: &str, like: IpAddr) -> Result<IpAddr, Error> {
+    let mut addrs = vec![];
+    let mut list = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        bail!(
+            "could not list the network interfaces: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    let mut entry = list;
+    while let Some(ifaddr) = unsafe { entry.as_ref() } {
+        entry = ifaddr.ifa_next;
+        let ifname = unsafe { std::ffi::CStr::from_ptr(ifaddr.ifa_name) };
+        if ifaddr.ifa_addr.is_null() || ifname.to_bytes() != name.as_bytes() {
+            continue;
+        }
+        match i32::from(unsafe { (*ifaddr.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let addr = unsafe { &*ifaddr.ifa_addr.cast::<libc::sockaddr_in>() };
+                // The address is in network byte order.
+                addrs.push(IpAddr::from(addr.sin_addr.s_addr.to_ne_bytes()));
+            }
+            libc::AF_INET6 => {
+                let addr = unsafe { &*ifaddr.ifa_addr.cast::<libc::sockaddr_in6>() };
+                addrs.push(IpAddr::from(addr.sin6_addr.s6_addr));
+            }
+            _ => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(list) };
+    addrs
+        .iter()
+        .find(|addr| addr.is_ipv4() == like.is_ipv4())
+        .or(addrs.first())
+        .copied()
+        .ok_or_else(|| anyhow!("network interface {name} has no IP address"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+pub(super) fn interface_addr(_name: &str, _like: IpAddr) -> Result<IpAddr, Error> {
+    bail!("interface=NAME is not supported on this OS, use the address of the interface")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cidrs_contain_their_addresses() {
+        let lan: Cidr = "192.168.1.0/24".parse().unwrap();
+        assert!(lan.contains("192.168.1.200".parse().unwrap()));
+        assert!(lan.contains("::ffff:192.168.1.7".parse().unwrap()));
+        assert!(!lan.contains("192.168.2.1".parse().unwrap()));
+        assert!(!lan.contains("::1".parse().unwrap()));
+        let host: Cidr = "10.0.0.5".parse().unwrap();
+        assert!(host.contains("10.0.0.5".parse().unwrap()));
+        assert!(!host.contains("10.0.0.6".parse().unwrap()));
+        let any: Cidr = "::/0".parse().unwrap();
+        assert!(any.contains("fe80::1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("lan".parse::<Cidr>().is_err());
+    }
+}
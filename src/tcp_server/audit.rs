@@ -0,0 +1,144 @@
+//! `--audit-log`: a record of each command that the clients of the server send, with the time,
+//! the client and the result, to find out which client changed what and when.
+//!
+//! Records are JSON objects, written one per line to a file, or with `syslog` to the local syslog
+//! daemon on Unix. Tokens are not recorded.
+
+use super::*;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Who sent a command.
+pub(super) struct Client<'a> {
+    pub(super) addr: &'a str,
+    /// The name from `Hello`, if the client sent one.
+    pub(super) name: Option<&'a str>,
+    /// Whether the client may only send queries, from the listener or its token.
+    pub(super) read_only: bool,
+}
+
+enum Sink {
+    File(Mutex<std::fs::File>),
+    #[cfg(unix)]
+    Syslog(std::os::unix::net::UnixDatagram),
+}
+
+/// Where the commands of clients are recorded.
+#[derive(Clone)]
+pub struct AuditLog(Arc<Sink>);
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &*self.0 {
+            Sink::File(_) => f.write_str("AuditLog(file)"),
+            #[cfg(unix)]
+            Sink::Syslog(_) => f.write_str("AuditLog(syslog)"),
+        }
+    }
+}
+
+/// Parses `syslog` or the path of a file, which is appended to.
+impl FromStr for AuditLog {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sink = match s {
+            #[cfg(unix)]
+            "syslog" => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                ["/dev/log", "/var/run/syslog"]
+                    .iter()
+                    .find(|path| socket.connect(path).is_ok())
+                    .ok_or_else(|| anyhow!("could not connect to the syslog daemon"))?;
+                Sink::Syslog(socket)
+            }
+            #[cfg(not(unix))]
+            "syslog" => bail!("syslog is only supported on Unix, use the path of a file"),
+            path => {
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| anyhow!("could not open the audit log {path}: {e}"))?;
+                Sink::File(Mutex::new(file))
+            }
+        };
+        Ok(Self(Arc::new(sink)))
+    }
+}
+
+/// The error of a reply, from `ServerResponse` or `ServerMessage::Error`.
+fn reply_error(reply: Option<&[u8]>) -> Option<String> {
+    let reply: serde_json::Value = serde_json::from_slice(reply?).ok()?;
+    let msg = match reply.get("status") {
+        Some(status) if status != "Ok" => reply.get("msg"),
+        _ => reply.get("Error")?.get("msg"),
+    }?;
+    Some(msg.as_str().unwrap_or_default().to_owned())
+}
+
+impl AuditLog {
+    /// Records the command of the client with the result from its reply, if it has one.
+    pub(super) fn record(&self, client: &Client<'_>, command: &str, reply: Option<&[u8]>) {
+        let error = reply_error(reply);
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let record = serde_json::json!({
+            "time_unix_ms": time.as_millis() as u64,
+            "client": client.addr,
+            "client_name": client.name,
+            "command": command,
+            "ok": error.is_none(),
+            "error": error,
+        });
+        let written = match &*self.0 {
+            Sink::File(file) => writeln!(file.lock(), "{record}"),
+            #[cfg(unix)]
+            Sink::Syslog(socket) => {
+                // The user facility, with the notice severity for errors and info otherwise.
+                let priority = if error.is_some() { 13 } else { 14 };
+                let message = format!("<{priority}>kanata[{}]: {record}", std::process::id());
+                socket.send(message.as_bytes()).map(drop)
+            }
+        };
+        if let Err(e) = written {
+            tracing::error!("could not write to the audit log: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_tell_the_result_of_commands() {
+        assert_eq!(reply_error(None), None);
+        assert_eq!(reply_error(Some(&ServerResponse::Ok.as_bytes())), None);
+        let error = ServerResponse::Error {
+            msg: "unknown layer".to_owned(),
+        };
+        assert_eq!(
+            reply_error(Some(&error.as_bytes())).as_deref(),
+            Some("unknown layer")
+        );
+        let error = ServerMessage::Error {
+            msg: "unknown key".to_owned(),
+        };
+        assert_eq!(
+            reply_error(Some(&error.as_bytes())).as_deref(),
+            Some("unknown key")
+        );
+        let forbidden = ServerResponse::Forbidden {
+            command: "Reload".to_owned(),
+            msg: "this client is read-only".to_owned(),
+        };
+        assert_eq!(
+            reply_error(Some(&forbidden.as_bytes())).as_deref(),
+            Some("this client is read-only")
+        );
+        let names = ServerMessage::LayerNames { names: vec![] };
+        assert_eq!(reply_error(Some(&names.as_bytes())), None);
+    }
+}
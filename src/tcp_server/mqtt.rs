@@ -0,0 +1,625 @@
+//! A client that connects kanata to an MQTT broker, for home automation setups that are built
+//! around MQTT rather than around TCP clients.
+//!
+//! The client acts like any other client of the server: it is registered for the notifications,
+//! which it publishes, and it handles the client messages published on its command topic. It uses
+//! MQTT 3.1.1 with QoS 0 and reconnects with an increasing delay when the connection is lost.
+//!
+//! With the default prefix, the topics are:
+//! - `kanata/status`: `online` while connected, `offline` otherwise. Retained.
+//! - `kanata/layer`: the name of the current layer. Retained.
+//! - `kanata/event`: every notification, as JSON.
+//! - `kanata/stats`: the `Stats` message, published periodically. Retained.
+//! - `kanata/command`: subscribed to, each payload holds one or more client messages.
+//! - `kanata/response`: the responses to the commands.
+//!
+//! Anyone who can publish to the command topic can send commands, so the client is read-only by
+//! default: the commands that change the state are rejected unless `allow-command` names them or
+//! `read-write` allows all of them.
+//!
+//! The connection to the broker is plain TCP, without TLS, so the password and the messages can be
+//! read on the network. A broker that is not on the same host should be reached through a tunnel.
+//!
+//! With the `home-assistant` option, the client also publishes the discovery messages of Home
+//! Assistant.
+
+use super::*;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+mod home_assistant;
+use home_assistant::*;
+
+const DEFAULT_PORT: u16 = 1883;
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// Packets from the broker are only commands, so anything larger is a broken broker.
+const MAX_PACKET_LEN: usize = 1 << 20;
+
+pub(super) const CONNECT: u8 = 1;
+pub(super) const CONNACK: u8 = 2;
+pub(super) const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+pub(super) const SUBSCRIBE: u8 = 8;
+const PINGREQ: u8 = 12;
+
+/// The broker and options of an `mqtt:` listener.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MqttOptions {
+    /// `HOST:PORT` of the broker.
+    pub broker: String,
+    /// The prefix of the topics, `kanata` by default.
+    pub prefix: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    /// Read from a file so that it does not show up in the process list.
+    pub password: Option<Arc<str>>,
+    /// How often the stats are published, never if zero.
+    pub stats_interval: Duration,
+    /// The prefix of the Home Assistant discovery topics, if discovery is enabled.
+    pub home_assistant: Option<String>,
+    /// Whether all the commands that change the state are handled, with `read-write`.
+    pub read_write: bool,
+    /// The commands that change the state which are handled without `read-write`.
+    pub allowed_commands: Vec<String>,
+}
+
+impl std::fmt::Debug for MqttOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttOptions")
+            .field("broker", &self.broker)
+            .field("prefix", &self.prefix)
+            .field("client_id", &self.client_id)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("stats_interval", &self.stats_interval)
+            .field("home_assistant", &self.home_assistant)
+            .field("read_write", &self.read_write)
+            .field("allowed_commands", &self.allowed_commands)
+            .finish()
+    }
+}
+
+impl MqttOptions {
+    /// Parses `HOST[:PORT]` and the options of `prefix=TOPIC`, `client-id=ID`, `username=NAME`,
+    /// `password-file=PATH` or `password-secret=NAME`, `stats-interval=SECONDS`,
+    /// `home-assistant[=PREFIX]`, `allow-command=NAME`, which can be repeated, and `read-write`.
+    pub(super) fn parse<'a>(
+        broker: &str,
+        options: impl Iterator<Item = &'a str>,
+    ) -> Result<Self, Error> {
+        if broker.is_empty() {
+            bail!("expected an MQTT broker address after mqtt:");
+        }
+        let has_port = broker
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        let broker = if has_port {
+            broker.to_owned()
+        } else {
+            format!("{broker}:{DEFAULT_PORT}")
+        };
+        let mut prefix = "kanata".to_owned();
+        let mut client_id = None;
+        let mut username = None;
+        let mut password = None;
+        let mut stats_interval = DEFAULT_STATS_INTERVAL;
+        let mut home_assistant = None;
+        let mut read_write = false;
+        let mut allowed_commands = vec![];
+        for option in options {
+            match option {
+                "home-assistant" => {
+                    home_assistant = Some("homeassistant".to_owned());
+                    continue;
+                }
+                "read-write" => {
+                    read_write = true;
+                    continue;
+                }
+                _ => {}
+            }
+            match option.split_once('=') {
+                Some(("prefix", p)) if !p.is_empty() => prefix = p.trim_end_matches('/').into(),
+                Some(("client-id", id)) if !id.is_empty() => client_id = Some(id.to_owned()),
+                Some(("username", name)) => username = Some(name.to_owned()),
+                Some(("password-file", path)) => password = Some(read_secret_file(path)?),
+                Some(("password-secret", name)) => password = Some(read_stored_secret(name)?),
+                Some(("stats-interval", secs)) => {
+                    let secs = secs
+                        .parse()
+                        .map_err(|_| anyhow!("stats-interval must be a number of seconds"))?;
+                    stats_interval = Duration::from_secs(secs);
+                }
+                Some(("home-assistant", p)) if !p.is_empty() => {
+                    home_assistant = Some(p.trim_end_matches('/').into())
+                }
+                Some(("allow-command", name)) if !name.is_empty() => {
+                    allowed_commands.push(name.to_owned())
+                }
+                _ => bail!(
+                    "unknown MQTT option {option}, expected prefix, client-id, username, \
+                    password-file, password-secret, stats-interval, home-assistant, \
+                    allow-command or read-write"
+                ),
+            }
+        }
+        if password.is_some() && username.is_none() {
+            bail!("an MQTT password needs a username");
+        }
+        Ok(Self {
+            broker,
+            // Two clients with the same ID disconnect each other, so by default separate
+            // instances of kanata need different prefixes.
+            client_id: client_id.unwrap_or_else(|| prefix.replace('/', "-")),
+            prefix,
+            username,
+            password,
+            stats_interval,
+            home_assistant,
+            read_write,
+            allowed_commands,
+        })
+    }
+
+    /// Whether the client handles the command, which is only checked for the commands that change
+    /// the state.
+    pub(super) fn allows(&self, command: &str) -> bool {
+        self.read_write || self.allowed_commands.iter().any(|name| name == command)
+    }
+
+    /// Whether the broker is on this host, which is the only place where the plain connection
+    /// can't be read by others.
+    fn broker_is_local(&self) -> bool {
+        let host = self.broker.rsplit_once(':').map_or("", |(host, _)| host);
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{name}", self.prefix)
+    }
+}
+
+fn packet(kind: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 5);
+    out.push(kind << 4 | flags);
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        out.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+fn put_str(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s);
+}
+
+fn connect_packet(options: &MqttOptions) -> Vec<u8> {
+    const CLEAN_SESSION: u8 = 0x02;
+    const WILL: u8 = 0x04;
+    const WILL_RETAIN: u8 = 0x20;
+    const PASSWORD: u8 = 0x40;
+    const USERNAME: u8 = 0x80;
+
+    let mut body = Vec::new();
+    put_str(&mut body, b"MQTT");
+    body.push(4); // protocol level of MQTT 3.1.1
+    let mut flags = CLEAN_SESSION | WILL | WILL_RETAIN;
+    if options.username.is_some() {
+        flags |= USERNAME;
+    }
+    if options.password.is_some() {
+        flags |= PASSWORD;
+    }
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    put_str(&mut body, options.client_id.as_bytes());
+    // The broker publishes the will when the connection is lost without a disconnect.
+    put_str(&mut body, options.topic("status").as_bytes());
+    put_str(&mut body, b"offline");
+    if let Some(username) = &options.username {
+        put_str(&mut body, username.as_bytes());
+    }
+    if let Some(password) = &options.password {
+        put_str(&mut body, password.as_bytes());
+    }
+    packet(CONNECT, 0, &body)
+}
+
+pub(super) fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+    put_str(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(PUBLISH, retain.into(), &body)
+}
+
+fn subscribe_packet(packet_id: u16, filter: &str) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    put_str(&mut body, filter.as_bytes());
+    body.push(0); // QoS 0
+    packet(SUBSCRIBE, 0b0010, &body)
+}
+
+pub(super) struct Packet {
+    pub(super) kind: u8,
+    pub(super) flags: u8,
+    pub(super) body: Vec<u8>,
+}
+
+pub(super) async fn read_packet(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Packet> {
+    let header = reader.read_u8().await?;
+    let mut len = 0usize;
+    for shift in (0..).step_by(7) {
+        if shift > 21 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed packet length",
+            ));
+        }
+        let byte = reader.read_u8().await?;
+        len |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    if len > MAX_PACKET_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("packet of {len} bytes is too large"),
+        ));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+    Ok(Packet {
+        kind: header >> 4,
+        flags: header & 0xf,
+        body,
+    })
+}
+
+/// A received message, with the packet ID to acknowledge if it has QoS 1 or 2.
+pub(super) struct Publish<'a> {
+    pub(super) topic: &'a str,
+    pub(super) packet_id: Option<u16>,
+    pub(super) payload: &'a [u8],
+}
+
+pub(super) fn parse_publish(packet: &Packet) -> Option<Publish<'_>> {
+    let body = &packet.body[..];
+    let topic_len = usize::from(u16::from_be_bytes(body.get(..2)?.try_into().ok()?));
+    let topic = std::str::from_utf8(body.get(2..2 + topic_len)?).ok()?;
+    let mut payload = &body[2 + topic_len..];
+    let qos = (packet.flags >> 1) & 0b11;
+    let packet_id = if qos > 0 {
+        let id = u16::from_be_bytes(payload.get(..2)?.try_into().ok()?);
+        payload = &payload[2..];
+        Some(id)
+    } else {
+        None
+    };
+    Some(Publish {
+        topic,
+        packet_id,
+        payload,
+    })
+}
+
+impl Server {
+    /// Stays connected to the broker for as long as kanata runs.
+    pub(super) async fn serve_mqtt(self, options: MqttOptions) {
+        let addr = format!("mqtt:{}", options.broker);
+        if options.password.is_some() && !options.broker_is_local() {
+            tracing::warn!(
+                "the MQTT password is sent to {} without TLS, so it can be read on the network",
+                options.broker
+            );
+        }
+        let mut delay = Duration::from_secs(1);
+        loop {
+            let e = self.mqtt_session(&options, &addr, &mut delay).await;
+            self.connections.lock().remove(&addr);
+            tracing::warn!(
+                "MQTT connection to {} failed: {e}, reconnecting in {}s",
+                options.broker,
+                delay.as_secs()
+            );
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    /// Connects to the broker and bridges messages until the connection fails. The reconnect
+    /// delay is reset once the broker accepts the connection.
+    async fn mqtt_session(
+        &self,
+        options: &MqttOptions,
+        addr: &str,
+        delay: &mut Duration,
+    ) -> io::Error {
+        let stream = match TcpStream::connect(&options.broker).await {
+            Ok(stream) => stream,
+            Err(e) => return e,
+        };
+        let _ = stream.set_nodelay(true);
+        let (mut reader, writer) = stream.into_split();
+        // Packets are read by their own task so that a partly read packet is not lost when
+        // another event is handled first.
+        let (packets_tx, packets) = mpsc::channel(CLIENT_QUEUE_LEN);
+        let read_task = tokio::spawn(async move {
+            loop {
+                let packet = read_packet(&mut reader).await;
+                let failed = packet.is_err();
+                if packets_tx.send(packet).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let Err(e) = self
+            .bridge_mqtt(options, addr, writer, packets, delay)
+            .await;
+        read_task.abort();
+        e
+    }
+
+    async fn bridge_mqtt(
+        &self,
+        options: &MqttOptions,
+        addr: &str,
+        mut writer: impl AsyncWrite + Unpin,
+        mut packets: mpsc::Receiver<io::Result<Packet>>,
+        delay: &mut Duration,
+    ) -> io::Result<std::convert::Infallible> {
+        let closed = || io::Error::from(io::ErrorKind::UnexpectedEof);
+
+        writer.write_all(&connect_packet(options)).await?;
+        let connack = packets.recv().await.ok_or_else(closed)??;
+        match connack.body.get(1) {
+            Some(0) if connack.kind == CONNACK => {}
+            Some(code) if connack.kind == CONNACK => {
+                return Err(io::Error::other(format!(
+                    "broker refused the connection with code {code}"
+                )));
+            }
+            _ => {
+                return Err(io::Error::other(
+                    "broker did not acknowledge the connection",
+                ));
+            }
+        }
+        *delay = Duration::from_secs(1);
+        tracing::info!("connected to MQTT broker {}", options.broker);
+
+        let command_topic = options.topic("command");
+        let layer_topic = options.topic("layer");
+        let event_topic = options.topic("event");
+        let stats_topic = options.topic("stats");
+        let response_topic = options.topic("response");
+        writer
+            .write_all(&subscribe_packet(1, &command_topic))
+            .await?;
+        writer
+            .write_all(&publish_packet(&options.topic("status"), b"online", true))
+            .await?;
+        let mut discovery = options.home_assistant.as_deref().map(Discovery::new);
+        if let Some(discovery) = &mut discovery {
+            writer
+                .write_all(&subscribe_packet(2, &discovery.status_topic()))
+                .await?;
+            self.publish_discovery(discovery, options, &mut writer)
+                .await?;
+        }
+
+        let (tx, mut notifications) = mpsc::channel(CLIENT_QUEUE_LEN);
+        let disconnect = Arc::new(Notify::new());
+        if !self.register(&tx, addr, &disconnect, None).await {
+            return Err(closed());
+        }
+        let (reply_tx, mut replies) = mpsc::channel(CLIENT_QUEUE_LEN);
+
+        let ping_period = KEEP_ALIVE / 2;
+        let mut ping =
+            tokio::time::interval_at(tokio::time::Instant::now() + ping_period, ping_period);
+        let mut stats = tokio::time::interval(options.stats_interval.max(Duration::from_secs(1)));
+        let mut received_at = tokio::time::Instant::now();
+        loop {
+            tokio::select! {
+                packet = packets.recv() => {
+                    let packet = packet.ok_or_else(closed)??;
+                    received_at = tokio::time::Instant::now();
+                    if packet.kind != PUBLISH {
+                        continue;
+                    }
+                    let Some(publish) = parse_publish(&packet) else {
+                        tracing::warn!("ignoring malformed MQTT message");
+                        continue;
+                    };
+                    if let Some(id) = publish.packet_id {
+                        writer.write_all(&packet_ack(id)).await?;
+                    }
+                    if let Some(discovery) = &mut discovery
+                        && publish.topic == discovery.status_topic()
+                        && publish.payload == b"online"
+                    {
+                        self.publish_discovery(discovery, options, &mut writer).await?;
+                    }
+                    if publish.topic == command_topic {
+                        self.handle_mqtt_command(publish.payload, options, addr, &reply_tx).await;
+                        while let Ok(reply) = replies.try_recv() {
+                            let reply = publish_packet(&response_topic, reply.trim_ascii_end(), false);
+                            writer.write_all(&reply).await?;
+                        }
+                    }
+                }
+                Some(msg) = notifications.recv() => {
+                    match serde_json::from_slice(&msg) {
+                        Ok(ServerMessage::LayerChange { new }) => {
+                            writer.write_all(&publish_packet(&layer_topic, new.as_bytes(), true)).await?;
+                        }
+                        // The virtual keys may have changed.
+                        Ok(ServerMessage::ConfigFileReload { .. }) => {
+                            if let Some(discovery) = &mut discovery {
+                                self.publish_discovery(discovery, options, &mut writer).await?;
+                            }
+                        }
+                        _ => {}
+                    }
+                    writer.write_all(&publish_packet(&event_topic, msg.trim_ascii_end(), false)).await?;
+                }
+                _ = stats.tick(), if !options.stats_interval.is_zero() => {
+                    let msg = self.stats().await.as_bytes();
+                    writer.write_all(&publish_packet(&stats_topic, msg.trim_ascii_end(), true)).await?;
+                }
+                _ = ping.tick() => {
+                    if received_at.elapsed() > KEEP_ALIVE {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "broker stopped responding"));
+                    }
+                    writer.write_all(&packet(PINGREQ, 0, &[])).await?;
+                }
+                _ = disconnect.notified() => {
+                    return Err(io::Error::other("not keeping up with the notifications"));
+                }
+            }
+        }
+    }
+
+    async fn publish_discovery(
+        &self,
+        discovery: &mut Discovery,
+        options: &MqttOptions,
+        writer: &mut (impl AsyncWrite + Unpin),
+    ) -> io::Result<()> {
+        let virtual_keys = self
+            .with_kanata(|k| k.virtual_keys.keys().cloned().collect())
+            .await;
+        for (topic, config) in discovery.messages(options, virtual_keys) {
+            writer
+                .write_all(&publish_packet(&topic, &config, true))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_mqtt_command(
+        &self,
+        payload: &[u8],
+        options: &MqttOptions,
+        addr: &str,
+        tx: &mpsc::Sender<Vec<u8>>,
+    ) {
+        for msg in serde_json::Deserializer::from_slice(payload).into_iter::<ClientMessage>() {
+            match msg {
+                // The broker authenticates its clients, and the token should not be logged.
+                Ok(ClientMessage::Authenticate { .. }) => {
+                    let _ = tx.send(ServerResponse::Ok.as_bytes()).await;
+                }
+                Ok(msg) => {
+                    tracing::debug!("mqtt client received command: {}", loggable(&msg));
+                    let span = tracing::info_span!(
+                        "client_request",
+                        client = %addr,
+                        command = %loggable(&msg)
+                    );
+                    // The commands that are not allowed are rejected like those of read-only clients.
+                    let client = Client {
+                        addr,
+                        name: None,
+                        read_only: !options.allows(&command_name(&msg)),
+                    };
+                    self.handle_message(msg, tx, &client).instrument(span).await;
+                    self.wake_up();
+                }
+                Err(e) => {
+                    let response = ServerResponse::Error {
+                        msg: format!("Failed to deserialize command: {e}"),
+                    };
+                    let _ = tx.send(response.as_bytes()).await;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn packet_ack(packet_id: u16) -> Vec<u8> {
+    packet(PUBACK, 0, &packet_id.to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mqtt_listener_options() {
+        let listener: Listener =
+            "mqtt:broker.local,prefix=home/kanata/,username=me,stats-interval=0"
+                .parse()
+                .unwrap();
+        let Endpoint::Mqtt(options) = listener.endpoint else {
+            panic!("expected an MQTT listener");
+        };
+        assert_eq!(options.broker, "broker.local:1883");
+        assert_eq!(options.prefix, "home/kanata");
+        assert_eq!(options.client_id, "home-kanata");
+        assert_eq!(options.username.as_deref(), Some("me"));
+        assert_eq!(options.stats_interval, Duration::ZERO);
+        assert_eq!(options.topic("layer"), "home/kanata/layer");
+
+        let listener: Listener = "mqtt:[::1]:8883".parse().unwrap();
+        assert!(matches!(listener.endpoint, Endpoint::Mqtt(o) if o.broker == "[::1]:8883"));
+        assert!("mqtt:".parse::<Listener>().is_err());
+        assert!("mqtt:localhost,token-file=x".parse::<Listener>().is_err());
+    }
+
+    #[test]
+    fn mqtt_clients_are_read_only_by_default() {
+        let options = |options: &[&str]| MqttOptions::parse("localhost", options.iter().copied());
+        let read_only = options(&[]).unwrap();
+        assert!(!read_only.allows("ChangeLayer"));
+        let allowed = options(&["allow-command=ChangeLayer", "allow-command=Reload"]).unwrap();
+        assert!(allowed.allows("ChangeLayer"));
+        assert!(allowed.allows("Reload"));
+        assert!(!allowed.allows("SetLogLevel"));
+        assert!(options(&["read-write"]).unwrap().allows("SetLogLevel"));
+        assert!(options(&["allow-command="]).is_err());
+    }
+
+    #[test]
+    fn mqtt_brokers_on_this_host() {
+        let local = |broker: &str| MqttOptions::parse(broker, [].into_iter()).unwrap();
+        assert!(local("localhost").broker_is_local());
+        assert!(local("127.0.0.1:1883").broker_is_local());
+        assert!(local("[::1]:8883").broker_is_local());
+        assert!(!local("broker.local").broker_is_local());
+        assert!(!local("192.168.1.2").broker_is_local());
+    }
+
+    #[test]
+    fn publish_roundtrip() {
+        let payload = vec![b'x'; 300];
+        let data = publish_packet("kanata/event", &payload, true);
+        // 300 bytes of payload and 14 bytes of topic need two bytes of length.
+        assert_eq!(&data[..3], &[PUBLISH << 4 | 1, 0xba, 0x02]);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let packet = runtime.block_on(read_packet(&mut data.as_slice())).unwrap();
+        let publish = parse_publish(&packet).unwrap();
+        assert_eq!(publish.topic, "kanata/event");
+        assert_eq!(publish.packet_id, None);
+        assert_eq!(publish.payload, payload);
+
+        let mut truncated = &data[..data.len() - 1];
+        assert!(runtime.block_on(read_packet(&mut truncated)).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Home Assistant MQTT discovery, so that kanata shows up as a device without configuring it in
+//! Home Assistant.
+//!
+//! The device has a sensor with the current layer, a button that reloads the configuration and a
+//! button that taps each virtual key. They are unavailable while kanata is not connected to the
+//! broker, using the `status` topic of the MQTT client. The buttons are only added if the MQTT
+//! client allows their commands.
+
+use super::*;
+use rustc_hash::FxHashSet;
+use serde_json::json;
+
+/// The discovery messages that were published to one broker connection.
+pub(super) struct Discovery {
+    /// The topics of discovery messages start with this prefix, `homeassistant` by default.
+    prefix: String,
+    /// The virtual keys that have a button, to remove the buttons of keys that were removed.
+    buttons: FxHashSet<String>,
+}
+
+/// Home Assistant only allows these characters in IDs.
+fn object_id(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+impl Discovery {
+    pub(super) fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+            buttons: Default::default(),
+        }
+    }
+
+    /// Home Assistant publishes `online` to this topic when it starts, after which the discovery
+    /// messages need to be published again.
+    pub(super) fn status_topic(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    /// Returns the retained messages that describe the device with a button for each of the
+    /// virtual keys, and that remove the buttons of virtual keys that no longer exist.
+    pub(super) fn messages(
+        &mut self,
+        options: &MqttOptions,
+        mut virtual_keys: FxHashSet<String>,
+    ) -> Vec<(String, Vec<u8>)> {
+        if !options.allows("ActOnFakeKey") {
+            virtual_keys.clear();
+        }
+        let node_id = object_id(&options.client_id);
+        let device = json!({
+            "identifiers": [node_id],
+            "name": options.client_id,
+            "manufacturer": "kanata",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let entity = |component: &str, object: &str, config: serde_json::Value| {
+            let mut config = config;
+            config["unique_id"] = json!(format!("{node_id}_{object}"));
+            config["availability_topic"] = json!(options.topic("status"));
+            config["device"] = device.clone();
+            (
+                format!("{}/{component}/{node_id}/{object}/config", self.prefix),
+                serde_json::to_vec(&config).expect("discovery config serializes"),
+            )
+        };
+        let command =
+            |msg: ClientMessage| serde_json::to_string(&msg).expect("client message serializes");
+
+        let mut messages = vec![entity(
+            "sensor",
+            "layer",
+            json!({
+                "name": "Current layer",
+                "icon": "mdi:keyboard",
+                "state_topic": options.topic("layer"),
+            }),
+        )];
+        if options.allows("Reload") {
+            messages.push(entity(
+                "button",
+                "reload",
+                json!({
+                    "name": "Reload configuration",
+                    "icon": "mdi:reload",
+                    "command_topic": options.topic("command"),
+                    "payload_press": command(ClientMessage::Reload {
+                        wait: None,
+                        timeout_ms: None,
+                    }),
+                }),
+            ));
+        }
+        for name in &virtual_keys {
+            messages.push(entity(
+                "button",
+                &format!("virtual_key_{}", object_id(name)),
+                json!({
+                    "name": format!("Tap {name}"),
+                    "icon": "mdi:gesture-tap-button",
+                    "command_topic": options.topic("command"),
+                    "payload_press": command(ClientMessage::ActOnFakeKey {
+                        name: name.clone(),
+                        action: FakeKeyActionMessage::Tap,
+                    }),
+                }),
+            ));
+        }
+        // An empty retained message removes the entity.
+        for name in self.buttons.difference(&virtual_keys) {
+            let object = format!("virtual_key_{}", object_id(name));
+            messages.push((
+                format!("{}/button/{node_id}/{object}/config", self.prefix),
+                Vec::new(),
+            ));
+        }
+        self.buttons = virtual_keys;
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_adds_and_removes_buttons() {
+        let options = MqttOptions::parse(
+            "localhost",
+            ["prefix=desk/kanata", "read-write"].into_iter(),
+        )
+        .unwrap();
+        let mut discovery = Discovery::new("homeassistant");
+        let keys = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+
+        let messages = discovery.messages(&options, keys(&["nav mode"]));
+        assert_eq!(messages.len(), 3);
+        let (topic, sensor) = &messages[0];
+        assert_eq!(topic, "homeassistant/sensor/desk-kanata/layer/config");
+        let sensor: serde_json::Value = serde_json::from_slice(sensor).unwrap();
+        assert_eq!(sensor["state_topic"], "desk/kanata/layer");
+        assert_eq!(sensor["availability_topic"], "desk/kanata/status");
+        assert_eq!(sensor["device"]["identifiers"][0], "desk-kanata");
+        let (topic, button) = &messages[2];
+        assert_eq!(
+            topic,
+            "homeassistant/button/desk-kanata/virtual_key_nav_mode/config"
+        );
+        let button: serde_json::Value = serde_json::from_slice(button).unwrap();
+        assert_eq!(
+            button["payload_press"],
+            r#"{"ActOnFakeKey":{"name":"nav mode","action":"Tap"}}"#
+        );
+
+        let messages = discovery.messages(&options, keys(&[]));
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[2],
+            (
+                "homeassistant/button/desk-kanata/virtual_key_nav_mode/config".to_owned(),
+                Vec::new()
+            )
+        );
+        assert_eq!(discovery.messages(&options, keys(&[])).len(), 2);
+    }
+
+    #[test]
+    fn discovery_only_adds_allowed_buttons() {
+        let keys = || ["nav".to_string()].into_iter().collect();
+        let options = MqttOptions::parse("localhost", [].into_iter()).unwrap();
+        let messages = Discovery::new("homeassistant").messages(&options, keys());
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].0.starts_with("homeassistant/sensor/"));
+
+        let options =
+            MqttOptions::parse("localhost", ["allow-command=ActOnFakeKey"].into_iter()).unwrap();
+        let messages = Discovery::new("homeassistant").messages(&options, keys());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[1].0,
+            "homeassistant/button/kanata/virtual_key_nav/config"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Encryption of UDP listeners with the Noise protocol, for `udp:ADDRESS,noise-key=PATH`, so that
+//! tokens and commands can't be read or changed on the network path.
+//!
+//! The handshake is `Noise_NK_25519_ChaChaPoly_BLAKE2s` with the prologue `kanata-udp`: clients
+//! know the public key of the listener, which is the second line of its key file. The first byte
+//! of each datagram is its type:
+//!
+//! - [`HANDSHAKE`]: the first handshake message of a client, which starts a new session for its
+//!   address. The listener replies with the second handshake message, with the same type.
+//! - [`TRANSPORT`]: a big-endian `u64` nonce followed by the encryption of a datagram of a plain
+//!   UDP listener. Each reply of the listener is sent the same way with its own nonces.
+//!
+//! Nonces are explicit because datagrams can be lost or reordered. A nonce that was already
+//! received is rejected, so recorded datagrams can't be replayed.
+
+use super::*;
+use snow::{Builder, StatelessTransportState};
+use std::time::Instant;
+
+const PARAMS: &str = "Noise_NK_25519_ChaChaPoly_BLAKE2s";
+const PROLOGUE: &[u8] = b"kanata-udp";
+pub(super) const HANDSHAKE: u8 = 1;
+pub(super) const TRANSPORT: u8 = 2;
+/// Sessions of other clients are dropped, least recently used first, beyond this many.
+const MAX_SESSIONS: usize = 64;
+/// The length of a Noise message, including its authentication tag, is limited to this.
+const MAX_NOISE_LEN: usize = 65535;
+const TAG_LEN: usize = 16;
+
+/// The static keypair of a listener.
+#[derive(Clone, PartialEq, Eq)]
+pub struct NoiseKey {
+    private: Vec<u8>,
+    pub public: Vec<u8>,
+}
+
+impl std::fmt::Debug for NoiseKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NoiseKey")
+            .field("public", &hex(&self.public))
+            .finish_non_exhaustive()
+    }
+}
+
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    (text.len().is_multiple_of(2) && text.is_ascii())
+        .then(|| {
+            (0..text.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+                .collect()
+        })
+        .flatten()
+}
+
+fn builder() -> Builder<'static> {
+    Builder::new(PARAMS.parse().expect("the Noise parameters are valid"))
+}
+
+/// Reads the keypair of a listener from the file, which has the private key and then the public
+/// key in hex on separate lines, or writes a new keypair to it if it doesn't exist.
+pub(super) fn load_noise_key(path: &str) -> Result<NoiseKey, Error> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let keypair = builder()
+                .generate_keypair()
+                .map_err(|e| anyhow!("could not generate a Noise key: {e}"))?;
+            let text = format!("{}\n{}\n", hex(&keypair.private), hex(&keypair.public));
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            std::io::Write::write_all(&mut options.open(path)?, text.as_bytes())
+                .map_err(|e| anyhow!("could not write the Noise key file {path}: {e}"))?;
+            tracing::info!("wrote a new Noise key to {path}");
+            text
+        }
+        Err(e) => bail!("could not read the Noise key file {path}: {e}"),
+    };
+    let mut lines = text.lines().map(unhex);
+    let (Some(Some(private)), Some(Some(public))) = (lines.next(), lines.next()) else {
+        bail!("the Noise key file {path} must have the private and the public key in hex");
+    };
+    Ok(NoiseKey { private, public })
+}
+
+/// Which nonces of the last 64 were received, so that replays are rejected.
+#[derive(Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    /// Bit `i` is set if `highest - i` was received.
+    received: u64,
+}
+
+impl ReplayWindow {
+    fn is_new(&self, nonce: u64) -> bool {
+        match self.highest {
+            None => true,
+            Some(highest) if nonce > highest => true,
+            Some(highest) => {
+                let age = highest - nonce;
+                age < 64 && self.received & (1 << age) == 0
+            }
+        }
+    }
+
+    fn mark(&mut self, nonce: u64) {
+        match self.highest {
+            Some(highest) if nonce <= highest => self.received |= 1 << (highest - nonce),
+            _ => {
+                let shift = self.highest.map_or(64, |highest| nonce - highest);
+                self.received = self.received.checked_shl(shift as u32).unwrap_or(0) | 1;
+                self.highest = Some(nonce);
+            }
+        }
+    }
+}
+
+struct Session {
+    transport: StatelessTransportState,
+    next_nonce: u64,
+    replays: ReplayWindow,
+    last_used: Instant,
+}
+
+impl Session {
+    /// Returns the handshake reply and the session of the first handshake message of a client.
+    fn accept(key: &NoiseKey, message: &[u8]) -> Result<(Vec<u8>, Self), snow::Error> {
+        let mut handshake = builder()
+            .local_private_key(&key.private)?
+            .prologue(PROLOGUE)?
+            .build_responder()?;
+        let mut payload = vec![0u8; MAX_NOISE_LEN];
+        handshake.read_message(message, &mut payload)?;
+        let mut reply = vec![0u8; MAX_NOISE_LEN + 1];
+        reply[0] = HANDSHAKE;
+        let len = handshake.write_message(&[], &mut reply[1..])?;
+        reply.truncate(len + 1);
+        let session = Self {
+            transport: handshake.into_stateless_transport_mode()?,
+            next_nonce: 0,
+            replays: ReplayWindow::default(),
+            last_used: Instant::now(),
+        };
+        Ok((reply, session))
+    }
+
+    fn open(&mut self, datagram: &[u8]) -> Option<Vec<u8>> {
+        let nonce = u64::from_be_bytes(*datagram.first_chunk::<8>()?);
+        if !self.replays.is_new(nonce) {
+            return None;
+        }
+        let mut plain = vec![0u8; datagram.len()];
+        let len = self
+            .transport
+            .read_message(nonce, &datagram[8..], &mut plain)
+            .ok()?;
+        self.replays.mark(nonce);
+        self.last_used = Instant::now();
+        plain.truncate(len);
+        Some(plain)
+    }
+
+    fn seal(&mut self, plain: &[u8]) -> Result<Vec<u8>, snow::Error> {
+        let nonce = self.next_nonce;
+        self.next_nonce += 1;
+        let mut datagram = vec![0u8; 1 + 8 + plain.len() + TAG_LEN];
+        datagram[0] = TRANSPORT;
+        datagram[1..9].copy_from_slice(&nonce.to_be_bytes());
+        let len = self
+            .transport
+            .write_message(nonce, plain, &mut datagram[9..])?;
+        datagram.truncate(9 + len);
+        Ok(datagram)
+    }
+}
+
+impl Server {
+    /// Like [`Server::serve_udp`], with the datagrams encrypted.
+    pub(super) async fn serve_noise_udp(
+        self,
+        socket: tokio::net::UdpSocket,
+        key: NoiseKey,
+        token: Option<Arc<Tokens>>,
+    ) {
+        let mut sessions: HashMap<SocketAddr, Session> = HashMap::default();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let (n, peer) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    tracing::warn!("udp receive error: {e:?}");
+                    continue;
+                }
+            };
+            if !addresses::is_allowed(&self.allow, &peer) {
+                continue;
+            }
+            // Datagrams that are not part of a session are dropped without a reply, which would
+            // tell a scanner that the port is open.
+            let replies = match buf[..n].split_first() {
+                Some((&HANDSHAKE, message)) => match Session::accept(&key, message) {
+                    Ok((reply, session)) => {
+                        if sessions.len() >= MAX_SESSIONS
+                            && let Some(oldest) = sessions
+                                .iter()
+                                .min_by_key(|(_, session)| session.last_used)
+                                .map(|(peer, _)| *peer)
+                        {
+                            sessions.remove(&oldest);
+                        }
+                        sessions.insert(peer, session);
+                        vec![reply]
+                    }
+                    Err(e) => {
+                        tracing::warn!("udp client {peer} sent an invalid handshake: {e}");
+                        continue;
+                    }
+                },
+                Some((&TRANSPORT, datagram)) => {
+                    let Some(session) = sessions.get_mut(&peer) else {
+                        continue;
+                    };
+                    let Some(plain) = session.open(datagram) else {
+                        tracing::warn!(
+                            "dropping a datagram from {peer} that could not be decrypted"
+                        );
+                        continue;
+                    };
+                    let addr = format!("udp:{peer}");
+                    let replies = self.handle_datagram(&plain, token.as_ref(), &addr).await;
+                    replies
+                        .iter()
+                        .filter_map(|reply| match session.seal(reply) {
+                            Ok(reply) => Some(reply),
+                            Err(e) => {
+                                tracing::warn!("could not encrypt a reply to {peer}: {e}");
+                                None
+                            }
+                        })
+                        .collect()
+                }
+                _ => continue,
+            };
+            for reply in replies {
+                if let Err(e) = socket.send_to(&reply, peer).await {
+                    tracing::warn!("udp send error to {peer}: {e:?}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_window_rejects_received_nonces() {
+        let mut window = ReplayWindow::default();
+        for nonce in [0, 2, 1, 70] {
+            assert!(window.is_new(nonce));
+            window.mark(nonce);
+            assert!(!window.is_new(nonce));
+        }
+        assert!(window.is_new(69));
+        // Too old to tell whether it was received.
+        assert!(!window.is_new(2));
+    }
+}
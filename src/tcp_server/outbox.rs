@@ -0,0 +1,165 @@
+//! The notifications that a client has not been sent yet.
+//!
+//! Notifications are put in the outbox of each client without blocking, and a task per client
+//! moves them to the queue of its connection. While a client falls behind, notifications that
+//! only tell the latest state, like `LayerChange`, replace the one that is still pending, tap-hold
+//! and training events are dropped when the outbox is full, and the client is told how many were
+//! dropped with `NotificationsDropped`. Clients are disconnected when other notifications don't fit.
+
+use super::*;
+use std::collections::VecDeque;
+use std::mem::Discriminant;
+use std::time::Duration;
+
+/// The pause between batches of notifications while they arrive faster than they are sent,
+/// during which they are coalesced.
+const BATCH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How a notification is handled while the client falls behind.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum Class {
+    /// Replaces a pending notification of the same kind.
+    Latest(Discriminant<ServerMessage>),
+    /// Is dropped if the outbox is full.
+    Droppable,
+    /// Disconnects the client if the outbox is full.
+    Kept,
+}
+
+impl Class {
+    pub(super) fn of(msg: &ServerMessage) -> Self {
+        match msg {
+            ServerMessage::LayerChange { .. }
+            | ServerMessage::ProcessingPaused { .. }
+            | ServerMessage::EmergencyPassthrough { .. }
+            | ServerMessage::OsLayoutChange { .. }
+            | ServerMessage::TypingSpeed { .. }
+            | ServerMessage::TrainingMode { .. }
+            | ServerMessage::SequenceMenu { .. } => Self::Latest(std::mem::discriminant(msg)),
+            ServerMessage::HoldActivated { .. }
+            | ServerMessage::TapActivated { .. }
+            | ServerMessage::TrainingKey { .. }
+            | ServerMessage::CmdOutput { .. } => Self::Droppable,
+            _ => Self::Kept,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    pending: VecDeque<(Class, Vec<u8>)>,
+    /// Notifications that replaced a pending one since the last batch.
+    coalesced: u64,
+    /// Notifications that were dropped since the client was last told.
+    dropped: u64,
+    closed: bool,
+}
+
+#[derive(Default)]
+pub(super) struct Outbox {
+    state: Mutex<State>,
+    ready: Notify,
+}
+
+impl Outbox {
+    /// Adds the notification and returns false if it does not fit.
+    pub(super) fn push(&self, class: Class, msg: Vec<u8>) -> bool {
+        let mut state = self.state.lock();
+        if let Class::Latest(_) = class
+            && let Some(i) = state.pending.iter().position(|(c, _)| *c == class)
+        {
+            state.pending.remove(i);
+            state.coalesced += 1;
+        } else if state.pending.len() >= CLIENT_QUEUE_LEN {
+            if class != Class::Droppable {
+                return false;
+            }
+            state.dropped += 1;
+            return true;
+        }
+        state.pending.push_back((class, msg));
+        drop(state);
+        self.ready.notify_one();
+        true
+    }
+
+    /// The number of notifications that are pending.
+    pub(super) fn len(&self) -> usize {
+        self.state.lock().pending.len()
+    }
+
+    /// Stops [`Outbox::forward`], when the client is gone.
+    pub(super) fn close(&self) {
+        self.state.lock().closed = true;
+        self.ready.notify_one();
+    }
+
+    /// Sends the notifications to the queue of the connection until the outbox is closed.
+    pub(super) async fn forward(self: Arc<Self>, addr: String, tx: mpsc::Sender<Vec<u8>>) {
+        loop {
+            self.ready.notified().await;
+            let (batch, coalesced, dropped) = {
+                let mut state = self.state.lock();
+                if state.closed {
+                    return;
+                }
+                let batch = std::mem::take(&mut state.pending);
+                (
+                    batch,
+                    std::mem::take(&mut state.coalesced),
+                    std::mem::take(&mut state.dropped),
+                )
+            };
+            if dropped > 0 {
+                tracing::warn!("dropped {dropped} notifications for client {addr}");
+                let msg = ServerMessage::NotificationsDropped { count: dropped };
+                if tx.send(msg.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            let busy = batch.len() > 1 || coalesced > 0 || dropped > 0;
+            for (_, msg) in batch {
+                if tx.send(msg).await.is_err() {
+                    return;
+                }
+            }
+            if busy {
+                tokio::time::sleep(BATCH_INTERVAL).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outbox_coalesces_and_drops_notifications() {
+        let outbox = Outbox::default();
+        let layer = |new: &str| ServerMessage::LayerChange {
+            new: new.to_owned(),
+        };
+        let tap = ServerMessage::TapActivated {
+            key: "a".to_owned(),
+        };
+        for new in ["nav", "base", "nav"] {
+            assert!(outbox.push(Class::of(&layer(new)), layer(new).as_bytes()));
+        }
+        for _ in 1..CLIENT_QUEUE_LEN {
+            assert!(outbox.push(Class::of(&tap), tap.as_bytes()));
+        }
+        assert!(outbox.push(Class::of(&tap), tap.as_bytes()));
+        assert!(outbox.push(Class::of(&layer("base")), layer("base").as_bytes()));
+        let push = ServerMessage::MessagePush {
+            message: serde_json::Value::Null,
+        };
+        assert!(!outbox.push(Class::of(&push), push.as_bytes()));
+
+        let state = outbox.state.lock();
+        assert_eq!(state.pending.len(), CLIENT_QUEUE_LEN);
+        assert_eq!(state.coalesced, 3);
+        assert_eq!(state.dropped, 1);
+        assert_eq!(state.pending.back().unwrap().1, layer("base").as_bytes());
+    }
+}
@@ -0,0 +1,185 @@
+//! An `output:` listener that injects the output of another kanata, which sends it with
+//! `linux-output-backend remote`. This makes kanata a software KVM: the sender grabs its keyboards
+//! and does all the layer processing, and the receiver only types.
+//!
+//! Clients send `Authenticate` with the token of the listener and then one [`RemoteOutput`] per
+//! line. The keys that a client still holds when it disconnects are released.
+
+use super::*;
+use kanata_parser::custom_action::{Btn, MWheelDirection, MoveDirection};
+use kanata_parser::keys::OsCode;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::kanata::CalculatedMouseMove;
+
+/// The key of a code in the protocol, which is the Linux key code, if it exists on this OS.
+fn oscode(code: u16) -> Option<OsCode> {
+    static CODES: std::sync::OnceLock<HashMap<u16, OsCode>> = std::sync::OnceLock::new();
+    CODES
+        .get_or_init(|| {
+            (0..=u16::MAX)
+                .filter_map(OsCode::from_u16)
+                .map(|osc| (osc as u16, osc))
+                .collect()
+        })
+        .get(&code)
+        .copied()
+}
+
+fn button(osc: OsCode) -> Option<Btn> {
+    match osc {
+        OsCode::BTN_LEFT => Some(Btn::Left),
+        OsCode::BTN_RIGHT => Some(Btn::Right),
+        OsCode::BTN_MIDDLE => Some(Btn::Mid),
+        OsCode::BTN_EXTRA => Some(Btn::Forward),
+        OsCode::BTN_SIDE => Some(Btn::Backward),
+        _ => None,
+    }
+}
+
+/// Writes the event to the output of this kanata. `held` has the codes of the keys that the client
+/// pressed and did not release yet.
+fn inject(
+    kbd_out: &mut KbdOut,
+    event: RemoteOutput,
+    held: &mut Vec<u16>,
+) -> Result<(), std::io::Error> {
+    match event {
+        RemoteOutput::Key { code, value } => {
+            let Some(osc) = oscode(code) else {
+                tracing::debug!("key code {code} does not exist on this OS, not injecting it");
+                return Ok(());
+            };
+            let value = match value {
+                0 => KeyValue::Release,
+                1 => KeyValue::Press,
+                _ => KeyValue::Repeat,
+            };
+            held.retain(|&c| c != code);
+            if value != KeyValue::Release {
+                held.push(code);
+            }
+            match (button(osc), value) {
+                (Some(btn), KeyValue::Press) => kbd_out.click_btn(btn),
+                (Some(btn), KeyValue::Release) => kbd_out.release_btn(btn),
+                (Some(_), _) => Ok(()),
+                (None, value) => kbd_out.write_key(osc, value),
+            }
+        }
+        RemoteOutput::MouseMove { x, y } => {
+            let distance = |d: i32| d.unsigned_abs().min(u16::MAX.into()) as u16;
+            let moves: Vec<_> = [
+                (x, MoveDirection::Left, MoveDirection::Right),
+                (y, MoveDirection::Up, MoveDirection::Down),
+            ]
+            .into_iter()
+            .filter(|(d, _, _)| *d != 0)
+            .map(|(d, negative, positive)| CalculatedMouseMove {
+                direction: if d < 0 { negative } else { positive },
+                distance: distance(d),
+            })
+            .collect();
+            kbd_out.move_mouse_many(&moves)
+        }
+        RemoteOutput::Scroll {
+            vertical,
+            horizontal,
+        } => {
+            let distance = |d: i32| d.unsigned_abs().min(u16::MAX.into()) as u16;
+            if vertical != 0 {
+                let direction = match vertical > 0 {
+                    true => MWheelDirection::Up,
+                    false => MWheelDirection::Down,
+                };
+                kbd_out.scroll(direction, distance(vertical))?;
+            }
+            if horizontal != 0 {
+                let direction = match horizontal > 0 {
+                    true => MWheelDirection::Right,
+                    false => MWheelDirection::Left,
+                };
+                kbd_out.scroll(direction, distance(horizontal))?;
+            }
+            Ok(())
+        }
+        RemoteOutput::MouseSet { x, y } => kbd_out.set_mouse(x, y),
+    }
+}
+
+impl Server {
+    pub(super) async fn accept_output(self, listener: TcpListener, token: Option<Arc<Tokens>>) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) if addresses::is_allowed(&self.allow, &addr) => {
+                    tracing::info!("new output client: {addr}");
+                    tokio::spawn(self.clone().serve_output(stream, addr, token.clone()));
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("output client failed to connect: {e:?}"),
+            }
+        }
+    }
+
+    async fn serve_output(self, stream: TcpStream, addr: SocketAddr, token: Option<Arc<Tokens>>) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let Ok(Some(line)) = lines.next_line().await else {
+            return;
+        };
+        let credential = match line.parse::<ClientMessage>() {
+            Ok(ClientMessage::Authenticate { token: given }) => match &token {
+                Some(tokens) => tokens.authenticate(&given).map(Some).ok_or(()),
+                None => Ok(None),
+            },
+            _ => Err(()),
+        };
+        let authenticated = credential.is_ok();
+        let response = if authenticated {
+            ServerResponse::Ok
+        } else {
+            tracing::warn!("output client {addr} did not authenticate");
+            ServerResponse::Error {
+                msg: "authentication failed".to_owned(),
+            }
+        };
+        if writer.write_all(&response.as_bytes()).await.is_err() || !authenticated {
+            return;
+        }
+        let mut held = vec![];
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) | Err(_) => break,
+            };
+            if credential
+                .as_ref()
+                .is_ok_and(|c| c.as_ref().is_some_and(|c| !c.is_valid()))
+            {
+                tracing::warn!("disconnecting output client {addr}, its token was revoked");
+                break;
+            }
+            let event = match serde_json::from_str::<RemoteOutput>(&line) {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!("output client {addr} sent an invalid event: {e}");
+                    continue;
+                }
+            };
+            let result;
+            (result, held) = self
+                .with_kanata(move |k| (inject(&mut k.kbd_out, event, &mut held), held))
+                .await;
+            if let Err(e) = result {
+                tracing::error!("could not inject the output of {addr}: {e}");
+            }
+        }
+        tracing::info!("output client {addr} disconnected");
+        self.with_kanata(move |k| {
+            for code in std::mem::take(&mut held) {
+                let release = RemoteOutput::Key { code, value: 0 };
+                let _ = inject(&mut k.kbd_out, release, &mut held);
+            }
+        })
+        .await;
+    }
+}
@@ -0,0 +1,307 @@
+//! The tokens that the clients of a listener authenticate with.
+//!
+//! `token-file=PATH` has one token. `tokens-file=PATH` has one token per line, any of which is
+//! accepted, and is read again when it changes, so that tokens can be rotated one at a time
+//! without restarting kanata. Clients that authenticated with a token that is removed from the
+//! file, or revoked with `RevokeToken`, are disconnected.
+//!
+//! A token is followed by `read-only` on its line for clients that may only send queries.
+
+use super::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The state of a file on disk, `None` if it can't be read.
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// The tokens of a `tokens-file`, without blank lines and `#` comments.
+struct TokensFile {
+    valid: Vec<Arc<str>>,
+    read_only: Vec<Arc<str>>,
+}
+
+fn read_tokens_file(path: &Path) -> Result<TokensFile, Error> {
+    crate::kanata::check_secret_file(path).map_err(|e| anyhow!("refusing tokens file: {e}"))?;
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("could not read tokens file {}: {e}", path.display()))?;
+    let mut valid = vec![];
+    let mut read_only = vec![];
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let token: Arc<str> = match line.split_once(char::is_whitespace) {
+            None => Arc::from(line),
+            Some((token, mode)) if mode.trim() == "read-only" => {
+                let token = Arc::from(token);
+                read_only.push(Arc::clone(&token));
+                token
+            }
+            Some((_, mode)) => bail!(
+                "unknown mode {} in tokens file {}, expected read-only",
+                mode.trim(),
+                path.display()
+            ),
+        };
+        valid.push(token);
+    }
+    Ok(TokensFile { valid, read_only })
+}
+
+/// The tokens that are accepted by a listener.
+pub struct Tokens {
+    /// The file of `tokens-file`, which is read again when it changes.
+    file: Option<PathBuf>,
+    state: Mutex<TokensState>,
+}
+
+/// Tokens are the same if they are read from the same file and accept the same tokens.
+impl PartialEq for Tokens {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+            || (self.file == other.file && {
+                let (state, other) = (self.state.lock(), other.state.lock());
+                state.valid == other.valid && state.read_only == other.read_only
+            })
+    }
+}
+
+impl Eq for Tokens {}
+
+struct TokensState {
+    valid: Vec<Arc<str>>,
+    /// The valid tokens whose clients may only send queries.
+    read_only: Vec<Arc<str>>,
+    /// Revoked tokens are not accepted even if they are still in the file, until kanata restarts.
+    revoked: Vec<Arc<str>>,
+    stamp: Stamp,
+}
+
+/// The token that a client authenticated with, to disconnect it when the token is revoked.
+#[derive(Clone)]
+pub(super) struct Credential {
+    tokens: Arc<Tokens>,
+    token: Arc<str>,
+    read_only: bool,
+}
+
+impl Credential {
+    /// A token that becomes read-only or read-write in the file is not valid anymore either, its
+    /// clients authenticate again to get the new mode.
+    pub(super) fn is_valid(&self) -> bool {
+        let state = self.tokens.state.lock();
+        state.accepts(&self.token) && state.is_read_only(&self.token) == self.read_only
+    }
+
+    pub(super) fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+impl TokensState {
+    fn accepts(&self, token: &str) -> bool {
+        self.valid.iter().any(|valid| **valid == *token)
+            && !self.revoked.iter().any(|revoked| **revoked == *token)
+    }
+
+    fn is_read_only(&self, token: &str) -> bool {
+        self.read_only.iter().any(|read_only| **read_only == *token)
+    }
+}
+
+/// A single token.
+impl From<&str> for Tokens {
+    fn from(token: &str) -> Self {
+        Self::new(None, vec![Arc::from(token)], vec![])
+    }
+}
+
+impl Tokens {
+    fn new(file: Option<PathBuf>, valid: Vec<Arc<str>>, read_only: Vec<Arc<str>>) -> Self {
+        let stamp = file.as_deref().and_then(stamp);
+        Self {
+            file,
+            state: Mutex::new(TokensState {
+                valid,
+                read_only,
+                revoked: vec![],
+                stamp,
+            }),
+        }
+    }
+
+    /// The token of `token-file`.
+    pub(super) fn from_token_file(path: &str) -> Result<Self, Error> {
+        Ok(Self::new(None, vec![read_secret_file(path)?], vec![]))
+    }
+
+    /// The token of `token-secret`.
+    pub(super) fn from_token_secret(name: &str) -> Result<Self, Error> {
+        Ok(Self::new(None, vec![read_stored_secret(name)?], vec![]))
+    }
+
+    /// The tokens of `tokens-file`.
+    pub(super) fn from_tokens_file(path: &str) -> Result<Self, Error> {
+        let path = PathBuf::from(path);
+        let TokensFile { valid, read_only } = read_tokens_file(&path)?;
+        if valid.is_empty() {
+            bail!("tokens file {} has no tokens", path.display());
+        }
+        Ok(Self::new(Some(path), valid, read_only))
+    }
+
+    /// Returns the credential of the token if it is accepted. Every token is compared, in time
+    /// that does not depend on which one matches.
+    pub(super) fn authenticate(self: &Arc<Self>, given: &str) -> Option<Credential> {
+        let state = self.state.lock();
+        let token = state
+            .valid
+            .iter()
+            .fold(None, |found, valid| {
+                if token_matches(valid, given) {
+                    Some(valid.clone())
+                } else {
+                    found
+                }
+            })
+            .filter(|token| state.accepts(token))?;
+        Some(Credential {
+            tokens: self.clone(),
+            read_only: state.is_read_only(&token),
+            token,
+        })
+    }
+
+    /// Stops accepting the token and returns whether it was accepted.
+    pub(super) fn revoke(&self, given: &str) -> bool {
+        let mut state = self.state.lock();
+        let accepted =
+            state.valid.iter().any(|valid| token_matches(valid, given)) && state.accepts(given);
+        if accepted {
+            state.revoked.push(Arc::from(given));
+        }
+        accepted
+    }
+
+    /// Reads the tokens file again if it changed, and returns whether it did. A file that can't
+    /// be read, e.g. in the middle of a save, keeps the tokens from before.
+    fn reload(&self) -> bool {
+        let Some(path) = self.file.as_deref() else {
+            return false;
+        };
+        let new = stamp(path);
+        let mut state = self.state.lock();
+        if new.is_none() || new == state.stamp {
+            return false;
+        }
+        state.stamp = new;
+        match read_tokens_file(path) {
+            Ok(TokensFile { valid, read_only }) => {
+                if valid.is_empty() {
+                    tracing::warn!("tokens file {} has no tokens", path.display());
+                }
+                tracing::info!("reloaded {} tokens from {}", valid.len(), path.display());
+                state.valid = valid;
+                state.read_only = read_only;
+                true
+            }
+            Err(e) => {
+                tracing::warn!("{e}, keeping the tokens from before");
+                false
+            }
+        }
+    }
+}
+
+impl Server {
+    /// Reloads the tokens file when it changes, and disconnects the clients whose token is gone.
+    pub(super) async fn watch_tokens(self, tokens: Arc<Tokens>) {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if tokens.reload() {
+                self.disconnect_revoked();
+            }
+        }
+    }
+
+    /// Revokes the token on every listener that accepts it, for `RevokeToken`.
+    pub(super) fn revoke_token(&self, token: &str) -> ServerResponse {
+        // Every listener is checked, a token can be shared by several.
+        let revoked = self
+            .tokens
+            .iter()
+            .fold(false, |revoked, tokens| tokens.revoke(token) | revoked);
+        if !revoked {
+            return ServerResponse::Error {
+                msg: "no listener accepts this token".to_owned(),
+            };
+        }
+        tracing::warn!("revoked a token");
+        self.disconnect_revoked();
+        ServerResponse::Ok
+    }
+
+    fn disconnect_revoked(&self) {
+        self.connections.lock().retain(|id, client| {
+            let valid = client.credential.as_ref().is_none_or(Credential::is_valid);
+            if !valid {
+                tracing::warn!("disconnecting client {id}, its token is not accepted anymore");
+                client.disconnect.notify_one();
+            }
+            valid
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_file_is_reloaded_and_tokens_can_be_revoked() {
+        let path = std::env::temp_dir().join(format!("kanata-tokens-{}", std::process::id()));
+        std::fs::write(&path, "# clients\nold\n\nnew\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        let tokens = Arc::new(Tokens::from_tokens_file(&path.display().to_string()).unwrap());
+        let old = tokens.authenticate("old").unwrap();
+        assert!(tokens.authenticate("new").is_some());
+        assert!(tokens.authenticate("# clients").is_none());
+
+        // A different length changes the stamp even if the modification time doesn't.
+        std::fs::write(&path, "new\nnewer\n").unwrap();
+        assert!(tokens.reload());
+        assert!(!tokens.reload());
+        assert!(!old.is_valid());
+        assert!(tokens.authenticate("old").is_none());
+
+        let newer = tokens.authenticate("newer").unwrap();
+        assert!(tokens.revoke("newer"));
+        assert!(!tokens.revoke("newer"));
+        assert!(!newer.is_valid());
+        assert!(tokens.authenticate("newer").is_none());
+        assert!(tokens.authenticate("new").is_some());
+
+        std::fs::write(&path, "new read-only\n").unwrap();
+        let new = tokens.authenticate("new").unwrap();
+        assert!(!new.is_read_only());
+        assert!(tokens.reload());
+        assert!(!new.is_valid());
+        assert!(tokens.authenticate("new").unwrap().is_read_only());
+        std::fs::write(&path, "new readonly\n").unwrap();
+        assert!(!tokens.reload());
+        assert!(tokens.authenticate("new").unwrap().is_read_only());
+        std::fs::remove_file(path).unwrap();
+    }
+}
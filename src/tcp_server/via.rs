@@ -0,0 +1,718 @@
+//! A `via:` listener that speaks the keymap commands of the VIA protocol, so that graphical keymap
+//! editors can show and change the layers of the configuration while kanata runs.
+//!
+//! Clients send the 32-byte packets that VIA sends over raw HID, and each packet is answered with
+//! a 32-byte packet. The layers are the `deflayer`s of the configuration file in the order they
+//! are defined, and the matrix has 16 columns: the key at index `i` of `defsrc` is at row `i / 16`
+//! and column `i % 16`. A change to a key replaces its action in the file, which is then reloaded.
+//!
+//! Only actions that have a VIA keycode can be shown and set: keys with modifiers, `_`, `XX`,
+//! `layer-while-held`, `layer-switch`, `one-shot` of a layer, and `tap-hold` of a key with a layer
+//! or modifiers. Other actions, and the layers of `deflayermap`, show up as the keycode `QK_KB_0`
+//! and are not changed unless the client sets another keycode.
+
+use super::*;
+use kanata_parser::cfg::sexpr::{self, SExpr};
+use kanata_parser::keys::str_to_oscode;
+
+pub(super) const PACKET_LEN: usize = 32;
+const COLS: usize = 16;
+/// The VIA protocol version of the keycodes below.
+const PROTOCOL_VERSION: u16 = 12;
+
+const GET_PROTOCOL_VERSION: u8 = 0x01;
+const GET_KEYBOARD_VALUE: u8 = 0x02;
+const SET_KEYBOARD_VALUE: u8 = 0x03;
+const GET_KEYCODE: u8 = 0x04;
+const SET_KEYCODE: u8 = 0x05;
+const MACRO_GET_COUNT: u8 = 0x0C;
+const MACRO_GET_BUFFER_SIZE: u8 = 0x0D;
+const GET_LAYER_COUNT: u8 = 0x11;
+const GET_BUFFER: u8 = 0x12;
+const SET_BUFFER: u8 = 0x13;
+const UNHANDLED: u8 = 0xFF;
+
+const UPTIME: u8 = 0x01;
+const LAYOUT_OPTIONS: u8 = 0x02;
+const SWITCH_MATRIX_STATE: u8 = 0x03;
+const FIRMWARE_VERSION: u8 = 0x04;
+
+const KC_NO: u16 = 0x0000;
+const KC_TRNS: u16 = 0x0001;
+const QK_MOD_TAP: u16 = 0x2000;
+const QK_LAYER_TAP: u16 = 0x4000;
+const QK_TO: u16 = 0x5200;
+const QK_MOMENTARY: u16 = 0x5220;
+const QK_ONE_SHOT_LAYER: u16 = 0x5280;
+/// Stands for an action without a VIA keycode.
+const QK_KB_0: u16 = 0x7E00;
+
+/// Timeouts of the actions that are set through VIA, like the default of QMK.
+const TAPPING_TERM: u16 = 200;
+const ONE_SHOT_TIMEOUT: u16 = 2000;
+
+/// The QMK basic keycodes, which are USB HID usages, with the kanata key names.
+const BASIC: &[(u16, &str)] = &[
+    (0x04, "a"),
+    (0x05, "b"),
+    (0x06, "c"),
+    (0x07, "d"),
+    (0x08, "e"),
+    (0x09, "f"),
+    (0x0A, "g"),
+    (0x0B, "h"),
+    (0x0C, "i"),
+    (0x0D, "j"),
+    (0x0E, "k"),
+    (0x0F, "l"),
+    (0x10, "m"),
+    (0x11, "n"),
+    (0x12, "o"),
+    (0x13, "p"),
+    (0x14, "q"),
+    (0x15, "r"),
+    (0x16, "s"),
+    (0x17, "t"),
+    (0x18, "u"),
+    (0x19, "v"),
+    (0x1A, "w"),
+    (0x1B, "x"),
+    (0x1C, "y"),
+    (0x1D, "z"),
+    (0x1E, "1"),
+    (0x1F, "2"),
+    (0x20, "3"),
+    (0x21, "4"),
+    (0x22, "5"),
+    (0x23, "6"),
+    (0x24, "7"),
+    (0x25, "8"),
+    (0x26, "9"),
+    (0x27, "0"),
+    (0x28, "ret"),
+    (0x29, "esc"),
+    (0x2A, "bspc"),
+    (0x2B, "tab"),
+    (0x2C, "spc"),
+    (0x2D, "min"),
+    (0x2E, "eql"),
+    (0x2F, "lbrc"),
+    (0x30, "rbrc"),
+    (0x31, "bksl"),
+    (0x33, "scln"),
+    (0x34, "apos"),
+    (0x35, "grv"),
+    (0x36, "comm"),
+    (0x37, "."),
+    (0x38, "/"),
+    (0x39, "caps"),
+    (0x3A, "f1"),
+    (0x3B, "f2"),
+    (0x3C, "f3"),
+    (0x3D, "f4"),
+    (0x3E, "f5"),
+    (0x3F, "f6"),
+    (0x40, "f7"),
+    (0x41, "f8"),
+    (0x42, "f9"),
+    (0x43, "f10"),
+    (0x44, "f11"),
+    (0x45, "f12"),
+    (0x46, "prnt"),
+    (0x47, "slck"),
+    (0x48, "pause"),
+    (0x49, "ins"),
+    (0x4A, "home"),
+    (0x4B, "pgup"),
+    (0x4C, "del"),
+    (0x4D, "end"),
+    (0x4E, "pgdn"),
+    (0x4F, "rght"),
+    (0x50, "left"),
+    (0x51, "down"),
+    (0x52, "up"),
+    (0x53, "nlck"),
+    (0x54, "kp/"),
+    (0x55, "kp*"),
+    (0x56, "kp-"),
+    (0x57, "kp+"),
+    (0x58, "kprt"),
+    (0x59, "kp1"),
+    (0x5A, "kp2"),
+    (0x5B, "kp3"),
+    (0x5C, "kp4"),
+    (0x5D, "kp5"),
+    (0x5E, "kp6"),
+    (0x5F, "kp7"),
+    (0x60, "kp8"),
+    (0x61, "kp9"),
+    (0x62, "kp0"),
+    (0x63, "kp."),
+    (0x64, "nubs"),
+    (0x65, "menu"),
+    (0x67, "kp="),
+    (0x68, "f13"),
+    (0x69, "f14"),
+    (0x6A, "f15"),
+    (0x6B, "f16"),
+    (0x6C, "f17"),
+    (0x6D, "f18"),
+    (0x6E, "f19"),
+    (0x6F, "f20"),
+    (0x70, "f21"),
+    (0x71, "f22"),
+    (0x72, "f23"),
+    (0x73, "f24"),
+    (0xA8, "mute"),
+    (0xA9, "volu"),
+    (0xAA, "voldwn"),
+    (0xAB, "next"),
+    (0xAC, "prev"),
+    (0xAE, "pp"),
+    (0xBD, "brup"),
+    (0xBE, "brdn"),
+    (0xD1, "mlft"),
+    (0xD2, "mrgt"),
+    (0xD3, "mmid"),
+    (0xD4, "mbck"),
+    (0xD5, "mfwd"),
+    (0xE0, "lctl"),
+    (0xE1, "lsft"),
+    (0xE2, "lalt"),
+    (0xE3, "lmet"),
+    (0xE4, "rctl"),
+    (0xE5, "rsft"),
+    (0xE6, "ralt"),
+    (0xE7, "rmet"),
+];
+
+/// The modifier bits of QMK keycodes, with the kanata prefixes and keys. The right-hand bit
+/// applies to all the modifiers of a keycode.
+const MOD_CTL: u8 = 0x01;
+const MOD_SFT: u8 = 0x02;
+const MOD_ALT: u8 = 0x04;
+const MOD_GUI: u8 = 0x08;
+const MOD_RIGHT: u8 = 0x10;
+const MODS: &[(u8, &str, &str, &str, &str)] = &[
+    (MOD_CTL, "C-", "RC-", "lctl", "rctl"),
+    (MOD_SFT, "S-", "RS-", "lsft", "rsft"),
+    (MOD_ALT, "A-", "RA-", "lalt", "ralt"),
+    (MOD_GUI, "M-", "RM-", "lmet", "rmet"),
+];
+
+/// The layers of a configuration file, with where their keys are in the text.
+pub(super) struct Keymap {
+    text: String,
+    src_len: usize,
+    layers: Vec<Layer>,
+}
+
+struct Layer {
+    name: String,
+    /// The byte ranges of the actions, empty for `deflayermap` which is not supported.
+    keys: Vec<std::ops::Range<usize>>,
+}
+
+impl Keymap {
+    pub(super) fn parse(text: String, file_name: &str) -> Result<Self, Error> {
+        let items = sexpr::parse(&text, file_name).map_err(|e| anyhow!("{}", e.msg))?;
+        let mut src_len = 0;
+        let mut layers = vec![];
+        for item in &items {
+            let exprs = &item.t;
+            match exprs.first().and_then(|e| e.atom(None)) {
+                Some("defsrc") => src_len = exprs.len() - 1,
+                Some(kind @ ("deflayer" | "deflayermap")) => {
+                    let name = match exprs.get(1) {
+                        Some(SExpr::Atom(name)) => name.t.clone(),
+                        // The name of deflayermap is in a list.
+                        Some(SExpr::List(name)) => match name.t.first() {
+                            Some(SExpr::Atom(name)) => name.t.clone(),
+                            _ => String::new(),
+                        },
+                        None => String::new(),
+                    };
+                    let keys = match kind {
+                        "deflayer" => exprs
+                            .iter()
+                            .skip(2)
+                            .map(|e| e.span().start()..e.span().end())
+                            .collect(),
+                        _ => vec![],
+                    };
+                    layers.push(Layer { name, keys });
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            text,
+            src_len,
+            layers,
+        })
+    }
+
+    fn rows(&self) -> usize {
+        self.src_len.div_ceil(COLS)
+    }
+
+    fn layer_index(&self, name: &str) -> Option<u16> {
+        let i = self.layers.iter().position(|l| l.name == name)?;
+        u16::try_from(i).ok()
+    }
+
+    fn range(&self, layer: usize, position: usize) -> Option<std::ops::Range<usize>> {
+        if position >= self.src_len {
+            return None;
+        }
+        self.layers.get(layer)?.keys.get(position).cloned()
+    }
+
+    fn keycode(&self, layer: usize, position: usize) -> u16 {
+        if position >= self.src_len || layer >= self.layers.len() {
+            return KC_NO;
+        }
+        match self.range(layer, position) {
+            Some(range) => self.to_keycode(&self.text[range]),
+            None => QK_KB_0,
+        }
+    }
+
+    /// The keymap as the dynamic keymap buffer of VIA, two bytes per key of each layer.
+    fn buffer(&self) -> Vec<u8> {
+        let mut buffer = vec![];
+        for layer in 0..self.layers.len() {
+            for position in 0..self.rows() * COLS {
+                buffer.extend_from_slice(&self.keycode(layer, position).to_be_bytes());
+            }
+        }
+        buffer
+    }
+
+    /// Sets the keys to the keycodes, and returns whether the text changed.
+    fn set(&mut self, keys: &[(usize, usize, u16)]) -> bool {
+        let mut edits = vec![];
+        for &(layer, position, keycode) in keys {
+            if self.keycode(layer, position) == keycode {
+                continue;
+            }
+            let Some(range) = self.range(layer, position) else {
+                tracing::warn!("via: layer {layer} key {position} can't be changed");
+                continue;
+            };
+            match self.to_action(keycode) {
+                Some(action) => edits.push((range, action)),
+                None => tracing::warn!("via: keycode {keycode:#06x} is not supported"),
+            }
+        }
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        if edits.is_empty() {
+            return false;
+        }
+        for (range, action) in &edits {
+            self.text.replace_range(range.clone(), action);
+        }
+        // The ranges have moved, and the new actions are valid expressions.
+        if let Ok(keymap) = Keymap::parse(self.text.clone(), "") {
+            *self = keymap;
+        }
+        true
+    }
+
+    fn to_keycode(&self, action: &str) -> u16 {
+        // Top-level expressions must be lists.
+        match sexpr::parse(&format!("({action})"), "") {
+            Ok(items) => match items.first().map(|i| i.t.as_slice()) {
+                Some([SExpr::Atom(a)]) => key_keycode(&a.t).unwrap_or(QK_KB_0),
+                Some([SExpr::List(l)]) => self.list_keycode(&l.t).unwrap_or(QK_KB_0),
+                _ => QK_KB_0,
+            },
+            Err(_) => QK_KB_0,
+        }
+    }
+
+    fn list_keycode(&self, list: &[SExpr]) -> Option<u16> {
+        let atom = |i: usize| list.get(i).and_then(|e| e.atom(None));
+        let layer = |name: &str| self.layer_index(name);
+        match (atom(0)?, list.len()) {
+            ("layer-while-held" | "layer-toggle", 2) => {
+                Some(QK_MOMENTARY | layer(atom(1)?)? & 0x1F)
+            }
+            ("layer-switch", 2) => Some(QK_TO | layer(atom(1)?)? & 0x1F),
+            ("one-shot" | "one-shot-press" | "one-shot-release", 3) => match &list[2] {
+                SExpr::List(l)
+                    if l.t.len() == 2 && l.t[0].atom(None) == Some("layer-while-held") =>
+                {
+                    Some(QK_ONE_SHOT_LAYER | layer(l.t[1].atom(None)?)? & 0x1F)
+                }
+                _ => None,
+            },
+            ("tap-hold" | "tap-hold-press" | "tap-hold-release", 5) => {
+                let tap = key_keycode(atom(3)?).filter(|&k| k <= 0xFF)?;
+                match &list[4] {
+                    SExpr::List(l)
+                        if l.t.len() == 2 && l.t[0].atom(None) == Some("layer-while-held") =>
+                    {
+                        let layer = layer(l.t[1].atom(None)?).filter(|&l| l < 16)?;
+                        Some(QK_LAYER_TAP | layer << 8 | tap)
+                    }
+                    hold => Some(QK_MOD_TAP | u16::from(held_mods(hold)?) << 8 | tap),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn to_action(&self, keycode: u16) -> Option<String> {
+        let layer = |i: u16| self.layers.get(usize::from(i)).map(|l| l.name.as_str());
+        match keycode {
+            KC_NO => Some("XX".to_owned()),
+            KC_TRNS => Some("_".to_owned()),
+            0x0002..=0x1FFF => key_action(keycode),
+            0x2000..=0x3FFF => {
+                let tap = basic_name(keycode & 0xFF)?;
+                let hold = mods_keys(((keycode >> 8) & 0x1F) as u8)?;
+                Some(format!(
+                    "(tap-hold {TAPPING_TERM} {TAPPING_TERM} {tap} {hold})"
+                ))
+            }
+            0x4000..=0x4FFF => {
+                let tap = basic_name(keycode & 0xFF)?;
+                let layer = layer((keycode >> 8) & 0xF)?;
+                Some(format!(
+                    "(tap-hold {TAPPING_TERM} {TAPPING_TERM} {tap} (layer-while-held {layer}))"
+                ))
+            }
+            0x5200..=0x521F | 0x5240..=0x525F => {
+                Some(format!("(layer-switch {})", layer(keycode & 0x1F)?))
+            }
+            0x5220..=0x523F => Some(format!("(layer-while-held {})", layer(keycode & 0x1F)?)),
+            0x5280..=0x529F => Some(format!(
+                "(one-shot {ONE_SHOT_TIMEOUT} (layer-while-held {}))",
+                layer(keycode & 0x1F)?
+            )),
+            _ => None,
+        }
+    }
+}
+
+fn basic_name(keycode: u16) -> Option<&'static str> {
+    BASIC.iter().find(|(k, _)| *k == keycode).map(|(_, n)| *n)
+}
+
+/// The keycode of a key with modifier prefixes, e.g. `C-S-a`.
+fn key_keycode(action: &str) -> Option<u16> {
+    match action {
+        "_" => return Some(KC_TRNS),
+        "XX" | "✗" | "∅" | "•" => return Some(KC_NO),
+        _ => {}
+    }
+    let mut rest = action;
+    let mut mods = 0;
+    let mut sides = (false, false);
+    'prefixes: loop {
+        for (bit, left, right, _, _) in MODS {
+            if let Some(r) = rest.strip_prefix(right) {
+                (mods, sides.1, rest) = (mods | bit, true, r);
+                continue 'prefixes;
+            }
+            if let Some(r) = rest.strip_prefix(left) {
+                (mods, sides.0, rest) = (mods | bit, true, r);
+                continue 'prefixes;
+            }
+        }
+        break;
+    }
+    let osc = str_to_oscode(rest)?;
+    let (keycode, _) = BASIC
+        .iter()
+        .find(|(_, name)| str_to_oscode(name) == Some(osc))?;
+    match sides {
+        (true, true) => None,
+        (_, right) => {
+            let mods = u16::from(mods | if right { MOD_RIGHT } else { 0 });
+            Some(mods << 8 | keycode)
+        }
+    }
+}
+
+/// The key with modifier prefixes of a keycode below `QK_MOD_TAP`.
+fn key_action(keycode: u16) -> Option<String> {
+    let name = basic_name(keycode & 0xFF)?;
+    let mods = ((keycode >> 8) & 0x1F) as u8;
+    let mut action = String::new();
+    for (bit, left, right, _, _) in MODS {
+        if mods & bit != 0 {
+            action.push_str(if mods & MOD_RIGHT != 0 { right } else { left });
+        }
+    }
+    action.push_str(name);
+    Some(action)
+}
+
+/// The modifier bits of the hold action of a mod-tap: a modifier key or `multi` of them.
+fn held_mods(hold: &SExpr) -> Option<u8> {
+    let keys: Vec<&str> = match hold {
+        SExpr::Atom(a) => vec![&a.t],
+        SExpr::List(l) if l.t.first()?.atom(None) == Some("multi") => l.t[1..]
+            .iter()
+            .map(|e| e.atom(None))
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+    let mut mods = 0;
+    let mut sides = (false, false);
+    for key in keys {
+        let (bit, right) = MODS.iter().find_map(|(bit, _, _, left, right)| {
+            match (key == *left, key == *right) {
+                (true, _) => Some((bit, false)),
+                (_, true) => Some((bit, true)),
+                _ => None,
+            }
+        })?;
+        mods |= bit;
+        match right {
+            true => sides.1 = true,
+            false => sides.0 = true,
+        }
+    }
+    match sides {
+        (true, true) => None,
+        (_, right) => Some(mods | if right { MOD_RIGHT } else { 0 }),
+    }
+}
+
+/// The modifier keys of modifier bits, for the hold action of a mod-tap.
+fn mods_keys(mods: u8) -> Option<String> {
+    let keys: Vec<&str> = MODS
+        .iter()
+        .filter(|(bit, ..)| mods & bit != 0)
+        .map(|(_, _, _, left, right)| if mods & MOD_RIGHT != 0 { *right } else { *left })
+        .collect();
+    match keys.as_slice() {
+        [] => None,
+        [key] => Some((*key).to_owned()),
+        keys => Some(format!("(multi {})", keys.join(" "))),
+    }
+}
+
+/// Answers a packet in place and returns whether it changed the keymap.
+pub(super) fn handle(packet: &mut [u8; PACKET_LEN], keymap: &mut Keymap) -> bool {
+    let u16_at =
+        |packet: &[u8; PACKET_LEN], i: usize| u16::from_be_bytes([packet[i], packet[i + 1]]);
+    match packet[0] {
+        GET_PROTOCOL_VERSION => packet[1..3].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes()),
+        GET_KEYBOARD_VALUE => match packet[1] {
+            UPTIME => {
+                let uptime = uptime_ms();
+                packet[2..6].copy_from_slice(&uptime.to_be_bytes());
+            }
+            LAYOUT_OPTIONS | FIRMWARE_VERSION => packet[2..6].fill(0),
+            SWITCH_MATRIX_STATE => packet[2..].fill(0),
+            _ => packet[0] = UNHANDLED,
+        },
+        // The layout options and the device indication have nothing to change.
+        SET_KEYBOARD_VALUE => {}
+        GET_KEYCODE => {
+            let keycode = keymap.keycode(
+                usize::from(packet[1]),
+                usize::from(packet[2]) * COLS + usize::from(packet[3]),
+            );
+            packet[4..6].copy_from_slice(&keycode.to_be_bytes());
+        }
+        SET_KEYCODE => {
+            if usize::from(packet[3]) < COLS {
+                let position = usize::from(packet[2]) * COLS + usize::from(packet[3]);
+                return keymap.set(&[(usize::from(packet[1]), position, u16_at(packet, 4))]);
+            }
+        }
+        MACRO_GET_COUNT => packet[1] = 0,
+        MACRO_GET_BUFFER_SIZE => packet[1..3].fill(0),
+        GET_LAYER_COUNT => packet[1] = keymap.layers.len().min(255) as u8,
+        GET_BUFFER => {
+            let offset = usize::from(u16_at(packet, 1));
+            let size = usize::from(packet[3]).min(PACKET_LEN - 4);
+            let buffer = keymap.buffer();
+            for i in 0..size {
+                packet[4 + i] = buffer.get(offset + i).copied().unwrap_or(0);
+            }
+        }
+        SET_BUFFER => {
+            let offset = usize::from(u16_at(packet, 1));
+            let size = usize::from(packet[3]).min(PACKET_LEN - 4);
+            let mut buffer = keymap.buffer();
+            let end = (offset + size).min(buffer.len());
+            if offset < end {
+                buffer[offset..end].copy_from_slice(&packet[4..4 + end - offset]);
+            }
+            let keys_per_layer = keymap.rows() * COLS;
+            let keys: Vec<_> = (offset / 2..end.div_ceil(2))
+                .map(|i| {
+                    let keycode = u16::from_be_bytes([buffer[i * 2], buffer[i * 2 + 1]]);
+                    (i / keys_per_layer, i % keys_per_layer, keycode)
+                })
+                .collect();
+            return keymap.set(&keys);
+        }
+        _ => packet[0] = UNHANDLED,
+    }
+    false
+}
+
+fn uptime_ms() -> u32 {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START
+        .get_or_init(std::time::Instant::now)
+        .elapsed()
+        .as_millis() as u32
+}
+
+impl Server {
+    pub(super) async fn accept_via(self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) if addresses::is_allowed(&self.allow, &addr) => {
+                    tracing::info!("new VIA client: {addr}");
+                    tokio::spawn(self.clone().serve_via(stream));
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("VIA client failed to connect: {e:?}"),
+            }
+        }
+    }
+
+    async fn serve_via(self, mut stream: TcpStream) {
+        let mut packet = [0; PACKET_LEN];
+        while stream.read_exact(&mut packet).await.is_ok() {
+            let server = self.clone();
+            packet = self
+                .blocking(move |_| {
+                    server.handle_via(&mut packet);
+                    packet
+                })
+                .await;
+            if stream.write_all(&packet).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Answers the packet from the current configuration file, and writes the file and reloads
+    /// it if the packet changes it.
+    fn handle_via(&self, packet: &mut [u8; PACKET_LEN]) {
+        let path = {
+            let k = self.kanata.lock();
+            k.cfg_paths[k.cur_cfg_idx].clone()
+        };
+        let keymap = std::fs::read_to_string(&path)
+            .map_err(Error::from)
+            .and_then(|text| Keymap::parse(text, &path.to_string_lossy()));
+        let mut keymap = match keymap {
+            Ok(keymap) => keymap,
+            Err(e) => {
+                tracing::error!("via: could not read {}: {e}", path.display());
+                packet[0] = UNHANDLED;
+                return;
+            }
+        };
+        if !handle(packet, &mut keymap) {
+            return;
+        }
+        if let Err(e) = std::fs::write(&path, &keymap.text) {
+            tracing::error!("via: could not write {}: {e}", path.display());
+            return;
+        }
+        let reload = ClientMessage::Reload {
+            wait: None,
+            timeout_ms: None,
+        };
+        if let Err(e) = self.kanata.lock().handle_client_command(reload) {
+            tracing::error!("via: could not reload the configuration: {e}");
+        }
+        self.wake_up();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: &str = "\
+(defsrc a b caps spc)
+(deflayer base
+  a C-S-b (tap-hold 200 200 esc lctl) (tap-hold 150 150 spc (layer-while-held nav))) ;; comment
+(deflayer nav
+  _ RA-left (layer-switch base) (macro a b))
+";
+
+    fn keymap() -> Keymap {
+        Keymap::parse(CFG.to_owned(), "test.kbd").unwrap()
+    }
+
+    fn packet(bytes: &[u8]) -> [u8; PACKET_LEN] {
+        let mut packet = [0; PACKET_LEN];
+        packet[..bytes.len()].copy_from_slice(bytes);
+        packet
+    }
+
+    #[test]
+    fn basic_keys_exist() {
+        for (keycode, name) in BASIC {
+            assert!(
+                str_to_oscode(name).is_some(),
+                "{keycode:#04x} converts to unknown key {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn via_reads_keycodes() {
+        let mut keymap = keymap();
+        let mut p = packet(&[GET_LAYER_COUNT]);
+        assert!(!handle(&mut p, &mut keymap));
+        assert_eq!(p[1], 2);
+
+        let mut p = packet(&[GET_BUFFER, 0, 0, 8]);
+        handle(&mut p, &mut keymap);
+        assert_eq!(p[4..12], [0x00, 0x04, 0x03, 0x05, 0x21, 0x29, 0x41, 0x2C]);
+
+        // The second layer starts after the 16 keys of the first.
+        let mut p = packet(&[GET_BUFFER, 0, 32, 8]);
+        handle(&mut p, &mut keymap);
+        assert_eq!(p[4..12], [0x00, 0x01, 0x14, 0x50, 0x52, 0x00, 0x7E, 0x00]);
+
+        let mut p = packet(&[GET_KEYCODE, 0, 0, 4]);
+        handle(&mut p, &mut keymap);
+        assert_eq!(p[4..6], [0, 0]);
+
+        let mut p = packet(&[0x42]);
+        handle(&mut p, &mut keymap);
+        assert_eq!(p[0], UNHANDLED);
+    }
+
+    #[test]
+    fn via_sets_keycodes_in_the_text() {
+        let mut keymap = keymap();
+        // a -> LT(1, KC_Z)
+        let mut p = packet(&[SET_KEYCODE, 0, 0, 0, 0x41, 0x1D]);
+        assert!(handle(&mut p, &mut keymap));
+        // Setting the same keycode is no change.
+        let mut p = packet(&[SET_KEYCODE, 0, 0, 1, 0x03, 0x05]);
+        assert!(!handle(&mut p, &mut keymap));
+        // The macro of nav is kept, and _ becomes RSFT_T(KC_A) and RA-left switches to MO(0).
+        let mut p = packet(&[
+            SET_BUFFER, 0, 32, 8, 0x32, 0x04, 0x52, 0x20, 0x52, 0x00, 0x7E, 0x00,
+        ]);
+        assert!(handle(&mut p, &mut keymap));
+        assert_eq!(
+            keymap.text,
+            "\
+(defsrc a b caps spc)
+(deflayer base
+  (tap-hold 200 200 z (layer-while-held nav)) C-S-b (tap-hold 200 200 esc lctl) (tap-hold 150 150 spc (layer-while-held nav))) ;; comment
+(deflayer nav
+  (tap-hold 200 200 a rsft) (layer-while-held base) (layer-switch base) (macro a b))
+"
+        );
+    }
+}
@@ -9,6 +9,7 @@ fn passthru_args() -> ValidatedArgs {
         #[cfg(feature = "tcp_server")]
         tcp_server_address: None,
         nodelay: true,
+        watch: false,
     }
 }
 
@@ -0,0 +1,37 @@
+use super::*;
+
+const CFG: &str = "
+ (defvar lang en)
+ (defsrc a b c d)
+ (defalias
+   en-accent x
+   fr-accent lsft
+   de-accent (layer-while-held umlaut)
+ )
+ (deflayer base (alias-concat $lang -accent) (var-set lang fr) (var-set lang de) (var-set lang en))
+ (deflayer umlaut _ _ _ z)
+";
+
+#[test]
+fn alias_concat_uses_the_current_variable_value() {
+    let result = simulate(CFG, "d:a t:10 u:a t:10 d:b t:10 u:b t:10 d:a t:50 u:a t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:X up:X dn:LShift up:LShift", result);
+    let result = simulate(
+        CFG,
+        "d:c t:10 u:c t:10 d:a t:10 d:d t:10 u:d t:10 u:a t:10 d:d t:10 u:d t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:Z up:Z", result);
+}
+
+#[test]
+fn alias_concat_releases_the_pressed_alias() {
+    // Changing the variable while the key is held releases the alias that was pressed.
+    let result = simulate(CFG, "d:b t:10 u:b t:10 d:a t:10 d:d t:10 u:d t:10 u:a t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:LShift up:LShift", result);
+}
@@ -0,0 +1,44 @@
+use super::*;
+
+static CFG: &str = "
+(defsrc a b . c)
+(deflayer base a b . c)
+(defautoshift 150 a b (. S-;))
+";
+
+#[test]
+fn autoshift_tap_outputs_key() {
+    let result = simulate(CFG, "d:a t:50 u:a t:50").to_ascii().no_time();
+    assert_eq!("dn:A up:A", result);
+}
+
+#[test]
+fn autoshift_hold_outputs_shifted_key() {
+    let result = simulate(CFG, "d:a t:200 u:a t:50").to_ascii().no_time();
+    assert_eq!("dn:LShift dn:A up:LShift up:A", result);
+}
+
+#[test]
+fn autoshift_custom_pair() {
+    let result = simulate(CFG, "d:. t:200 u:. t:50 d:. t:20 u:. t:50")
+        .to_ascii()
+        .no_time();
+    assert_eq!(
+        "dn:LShift dn:SColon up:LShift up:SColon dn:Dot up:Dot",
+        result
+    );
+}
+
+#[test]
+fn autoshift_ignores_unlisted_keys() {
+    let result = simulate(CFG, "d:c t:200 u:c t:50").to_ascii().no_time();
+    assert_eq!("dn:C up:C", result);
+}
+
+#[test]
+fn autoshift_rolled_keys_keep_order() {
+    let result = simulate(CFG, "d:a t:20 d:b t:20 u:a t:20 u:b t:50")
+        .to_ascii()
+        .no_time();
+    assert_eq!("dn:A dn:B up:A up:B", result);
+}
@@ -532,3 +532,20 @@ d:t u:t t:10
         result
     );
 }
+
+#[test]
+fn sim_chord_held_with_stuck_key_timeout() {
+    let result = simulate(
+        "
+(defcfg stuck-key-timeout 100 concurrent-tap-hold yes)
+(defsrc a b)
+(deflayer base a b)
+(defchordsv2
+  (a b) c 200 all-released ()
+)
+        ",
+        "d:a d:b t:300 u:a u:b t:50",
+    )
+    .to_ascii();
+    assert_eq!("dn:C t:303ms up:C", result);
+}
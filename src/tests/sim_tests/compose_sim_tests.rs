@@ -0,0 +1,87 @@
+use super::*;
+
+const CFG: &str = "
+(defsrc caps apo a e o)
+(deflayer base (compose 1000) apo a e o)
+(defcompose
+  (apo e) é
+  (S-apo o) ö
+  (a e) æ
+  (o o) r#\"°C\"#
+)
+";
+
+#[test]
+fn compose_outputs_unicode() {
+    let result = simulate(
+        CFG,
+        "d:caps u:caps t:10 d:' u:' t:10 d:e u:e t:10 d:e u:e t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("up:Quote outU:é up:E dn:E up:E", result);
+    let result = simulate(CFG, "d:caps u:caps t:10 d:o u:o t:10 d:o u:o t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("up:O outU:° outU:C up:O", result);
+}
+
+#[test]
+fn compose_with_shift() {
+    let result = simulate(
+        CFG,
+        "d:caps u:caps t:10 d:lsft d:' u:' u:lsft t:10 d:o u:o t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:LShift up:Quote up:LShift outU:ö up:O", result);
+}
+
+#[test]
+fn compose_unknown_sequence_and_timeout() {
+    let result = simulate(CFG, "d:caps u:caps t:10 d:e u:e t:10 d:a u:a t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("up:E dn:A up:A", result);
+    let result = simulate(CFG, "d:caps u:caps t:1010 d:a u:a t:10 d:e u:e t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:A up:A dn:E up:E", result);
+}
+
+#[test]
+fn compose_imports_xcompose() {
+    let cfg = "
+(defsrc caps a e)
+(deflayer base (compose 1000) a e)
+(defcompose
+  (a e) æ
+  xcompose file
+)
+";
+    let xcompose = r#"
+# comment
+include "%L"
+<Multi_key> <a> <e> : "ä" adiaeresis
+<Multi_key> <e> <E> : "Ə" SCHWA
+<dead_acute> <e> : "é" eacute
+"#;
+    let mut content = FxHashMap::default();
+    content.insert("file".into(), xcompose.into());
+    let result = simulate_with_file_content(
+        cfg,
+        "d:caps u:caps t:10 d:a u:a d:e u:e t:10",
+        content.clone(),
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("up:A outU:æ up:E", result);
+    let result = simulate_with_file_content(
+        cfg,
+        "d:caps u:caps t:10 d:e u:e d:lsft d:e u:e u:lsft t:10",
+        content,
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("up:E dn:LShift outU:Ə up:E up:LShift", result);
+}
@@ -0,0 +1,43 @@
+use super::*;
+
+const CFG: &str = "
+(defsrc 1 2 a)
+(deflayer base 1 2 (one-shot 2000 lsft))
+(defcustomshift 1 7 2 S-[)
+";
+
+#[test]
+fn custom_shift_physical_shift() {
+    let result = simulate(CFG, "d:lsft t:10 d:1 t:10 u:1 t:10 u:lsft t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!(
+        "dn:LShift up:LShift dn:Kb7 up:Kb7 dn:LShift up:LShift",
+        result
+    );
+    let result = simulate(CFG, "d:rsft t:10 d:2 t:10 u:2 t:10 u:rsft t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!(
+        "dn:RShift up:RShift dn:LShift dn:LBracket up:LShift up:LBracket dn:RShift up:RShift",
+        result
+    );
+}
+
+#[test]
+fn custom_shift_one_shot_shift() {
+    let result = simulate(CFG, "d:a t:10 u:a t:10 d:1 t:10 u:1 t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:LShift up:LShift dn:Kb7 up:Kb7", result);
+}
+
+#[test]
+fn custom_shift_unshifted_and_shortcuts() {
+    let result = simulate(CFG, "d:1 t:10 u:1 t:10").no_time().to_ascii();
+    assert_eq!("dn:Kb1 up:Kb1", result);
+    let result = simulate(CFG, "d:lctl t:10 d:lsft t:10 d:1 t:10 u:1 t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:LCtrl dn:LShift dn:Kb1 up:Kb1", result);
+}
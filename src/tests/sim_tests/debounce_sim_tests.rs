@@ -0,0 +1,54 @@
+use super::*;
+
+#[test]
+fn eager_debounce_ignores_chatter_after_a_change() {
+    let result = simulate(
+        "(defdebounce ms 10) (defsrc a) (deflayer base a)",
+        "d:a t:2 u:a t:1 d:a t:20 u:a t:1 d:a t:1 u:a t:20",
+    )
+    .to_ascii();
+    assert_eq!("dn:A t:23ms up:A", result);
+}
+
+#[test]
+fn eager_debounce_uses_a_change_during_the_debounce_time_late() {
+    let result = simulate(
+        "(defdebounce ms 10) (defsrc a) (deflayer base a)",
+        "d:a t:2 u:a t:20",
+    )
+    .to_ascii();
+    assert_eq!("dn:A t:9ms up:A", result);
+}
+
+#[test]
+fn defer_debounce_waits_until_the_key_is_stable() {
+    let result = simulate(
+        "(defdebounce mode defer ms 10) (defsrc a) (deflayer base a)",
+        "d:a t:2 u:a t:1 d:a t:20 u:a t:1 d:a t:1 u:a t:20",
+    )
+    .to_ascii();
+    assert_eq!("t:12ms dn:A t:22ms up:A", result);
+}
+
+#[test]
+fn defer_debounce_drops_a_change_that_bounces_back() {
+    let result = simulate(
+        "(defdebounce mode defer ms 10) (defsrc a) (deflayer base a)",
+        "d:a t:2 u:a t:20",
+    )
+    .to_ascii();
+    assert_eq!("", result);
+}
+
+#[test]
+fn debounce_per_key() {
+    let result = simulate(
+        "(defdebounce ms 10 keys (b 0 c 2)) (defsrc a b c) (deflayer base a b c)",
+        "d:b t:1 u:b t:1 d:b t:1 u:b t:1 d:c t:3 u:c t:1 d:c t:3 u:c t:5",
+    )
+    .to_ascii();
+    assert_eq!(
+        "dn:B t:1ms up:B t:1ms dn:B t:1ms up:B t:1ms dn:C t:3ms up:C t:1ms dn:C t:3ms up:C",
+        result
+    );
+}
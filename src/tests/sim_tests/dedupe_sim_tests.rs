@@ -0,0 +1,31 @@
+use super::*;
+
+#[test]
+fn dedupe_drops_the_events_of_a_mirrored_device_node() {
+    let result = simulate(
+        "(defcfg dedupe-input-ms 5) (defsrc a) (deflayer base a)",
+        "d:a t:1 d:a t:30 r:a t:1 r:a t:20 u:a t:2 u:a t:10",
+    )
+    .to_ascii();
+    assert_eq!("dn:A t:31ms dn:A t:21ms up:A", result);
+}
+
+#[test]
+fn dedupe_keeps_a_fast_double_tap() {
+    let result = simulate(
+        "(defcfg dedupe-input-ms 5) (defsrc a) (deflayer base a)",
+        "d:a t:1 u:a t:1 d:a t:1 u:a t:10",
+    )
+    .to_ascii();
+    assert_eq!("dn:A t:1ms up:A t:1ms dn:A t:1ms up:A", result);
+}
+
+#[test]
+fn dedupe_keeps_a_repeated_event_after_the_window() {
+    let result = simulate(
+        "(defcfg dedupe-input-ms 5) (defsrc a) (deflayer base a)",
+        "d:a t:10 d:a t:10 u:a t:10",
+    )
+    .to_ascii();
+    assert_eq!("dn:A t:20ms up:A up:A", result);
+}
@@ -0,0 +1,40 @@
+use super::*;
+
+const CFG: &str = "
+ (defsrc a b)
+ (defalias
+   cpy C-c
+   term-cpy C-S-c
+ )
+ (deflayer base @cpy b)
+ (deflayer browser @cpy x)
+ (defapp firefox layer browser)
+ (defapp Alacritty aliases (cpy term-cpy))
+";
+
+#[test]
+fn defapp_switches_to_the_layer_of_the_app() {
+    let result = simulate(
+        CFG,
+        "app:firefox t:10 d:b t:10 u:b t:10 app:other t:10 d:b t:10 u:b t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:X up:X dn:B up:B", result);
+}
+
+#[test]
+fn defapp_replaces_aliases_of_the_app() {
+    let result = simulate(
+        CFG,
+        "d:a t:10 u:a t:10 app:alacritty t:10 d:a t:10 u:a t:10 app:firefox t:10 d:a t:10 u:a t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!(
+        "dn:LCtrl dn:C up:LCtrl up:C \
+         dn:LCtrl dn:LShift dn:C up:LCtrl up:LShift up:C \
+         dn:LCtrl dn:C up:LCtrl up:C",
+        result
+    );
+}
@@ -0,0 +1,242 @@
+use super::*;
+
+use crate::engine::{Engine, Timed};
+use crate::kanata::PRESSED_KEYS;
+use crate::oskbd::OutputEvent;
+use kanata_parser::custom_action::MoveDirection;
+
+use std::sync::{Arc, Mutex as StdMutex};
+
+fn engine(cfg: &str) -> (Engine, Arc<StdMutex<Vec<OutputEvent>>>) {
+    init_log();
+    let _lk = match CFG_PARSE_LOCK.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let outputs = Arc::new(StdMutex::new(vec![]));
+    let sink_outputs = outputs.clone();
+    let engine = Engine::new(cfg, move |ev| sink_outputs.lock().unwrap().push(ev))
+        .expect("failed to parse cfg");
+    (engine, outputs)
+}
+
+#[test]
+fn engine_from_builder() {
+    use kanata_parser::cfg::builder::{Action, ConfigBuilder};
+    let cfg = ConfigBuilder::new()
+        .source(["a"])
+        .layer("base", [Action::key("b")]);
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut engine = {
+        let _lk = match CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        Engine::from_builder(&cfg, move |ev| {
+            let _ = tx.send(ev);
+        })
+        .expect("valid cfg")
+    };
+    engine
+        .handle_input(KeyEvent::new(str_to_oscode("a").unwrap(), KeyValue::Press))
+        .unwrap();
+    engine.tick(1).unwrap();
+    assert_eq!(rx.try_recv(), Ok(key("b", KeyValue::Press)));
+}
+
+#[test]
+fn engine_from_file_reads_includes() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut engine = {
+        let _lk = match CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        Engine::from_file(
+            std::path::Path::new("parser/test_cfgs/include-good.kbd"),
+            move |ev| {
+                let _ = tx.send(ev);
+            },
+        )
+        .expect("valid cfg")
+    };
+    engine
+        .handle_input(KeyEvent::new(str_to_oscode("a").unwrap(), KeyValue::Press))
+        .unwrap();
+    engine.tick(1).unwrap();
+    assert_eq!(rx.try_recv(), Ok(key("a", KeyValue::Press)));
+}
+
+fn key(name: &str, value: KeyValue) -> OutputEvent {
+    OutputEvent::Key {
+        code: str_to_oscode(name).expect("valid keycode"),
+        value,
+    }
+}
+
+#[test]
+fn engine_outputs_to_sink() {
+    let (mut engine, outputs) = engine("(defsrc a b) (deflayer base (tap-hold 100 100 c d) e)");
+    let a = str_to_oscode("a").unwrap();
+    engine
+        .handle_input(KeyEvent::new(a, KeyValue::Press))
+        .unwrap();
+    engine.tick(50).unwrap();
+    assert!(outputs.lock().unwrap().is_empty());
+    engine
+        .handle_input(KeyEvent::new(a, KeyValue::Release))
+        .unwrap();
+    let idle = engine.tick(150).unwrap();
+    assert!(idle);
+    assert_eq!(
+        *outputs.lock().unwrap(),
+        vec![key("c", KeyValue::Press), key("c", KeyValue::Release)]
+    );
+    assert!(engine.kanata().kbd_out.outputs.events.is_empty());
+}
+
+#[test]
+fn engine_outputs_mouse_to_sink() {
+    let (mut engine, outputs) = engine("(defsrc a) (deflayer base (jiggle 10 3 zero-sum))");
+    let a = str_to_oscode("a").unwrap();
+    engine
+        .handle_input(KeyEvent::new(a, KeyValue::Press))
+        .unwrap();
+    let idle = engine.tick(10).unwrap();
+    assert!(!idle);
+    assert_eq!(
+        *outputs.lock().unwrap(),
+        vec![
+            OutputEvent::MouseMove {
+                direction: MoveDirection::Right,
+                distance: 3
+            },
+            OutputEvent::MouseMove {
+                direction: MoveDirection::Left,
+                distance: 3
+            },
+        ]
+    );
+}
+
+fn at(time: u64, name: &str, value: KeyValue) -> Timed<KeyEvent> {
+    Timed {
+        time,
+        event: KeyEvent::new(str_to_oscode(name).expect("valid keycode"), value),
+    }
+}
+
+fn simulate(cfg: &str, inputs: &[Timed<KeyEvent>]) -> anyhow::Result<Vec<Timed<OutputEvent>>> {
+    init_log();
+    let _lk = match CFG_PARSE_LOCK.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    crate::engine::simulate(cfg, inputs)
+}
+
+#[test]
+fn simulate_stamps_outputs_with_time() {
+    let outputs = simulate(
+        "(defsrc a b) (deflayer base (tap-hold 100 100 c d) e)",
+        &[
+            at(10, "a", KeyValue::Press),
+            at(50, "a", KeyValue::Release),
+            at(60, "b", KeyValue::Press),
+            at(70, "b", KeyValue::Release),
+        ],
+    )
+    .unwrap();
+    let times: Vec<_> = outputs.iter().map(|o| (o.time, o.event.clone())).collect();
+    assert_eq!(
+        times,
+        vec![
+            (50, key("c", KeyValue::Press)),
+            (56, key("c", KeyValue::Release)),
+            (60, key("e", KeyValue::Press)),
+            (70, key("e", KeyValue::Release)),
+        ]
+    );
+}
+
+#[test]
+fn simulate_settles_after_last_input() {
+    let outputs = simulate(
+        "(defsrc a) (deflayer base (tap-hold 100 100 c d))",
+        &[at(0, "a", KeyValue::Press)],
+    )
+    .unwrap();
+    assert_eq!(
+        outputs,
+        vec![Timed {
+            time: 100,
+            event: key("d", KeyValue::Press)
+        }]
+    );
+    assert!(!PRESSED_KEYS.lock().contains(&str_to_oscode("a").unwrap()));
+}
+
+#[test]
+fn simulate_rejects_unordered_inputs() {
+    simulate(
+        "(defsrc a) (deflayer base b)",
+        &[at(10, "a", KeyValue::Press), at(5, "a", KeyValue::Release)],
+    )
+    .expect_err("inputs out of order");
+}
+
+#[test]
+fn engine_blocks_while_on_idle_waits_for_release() {
+    let (mut engine, outputs) = engine(
+        "(defvirtualkeys v c)
+         (defsrc a b)
+         (deflayer base (on-idle 20 tap-vkey v) b)",
+    );
+    let input = |engine: &mut Engine, name, value| {
+        engine
+            .handle_input(KeyEvent::new(str_to_oscode(name).unwrap(), value))
+            .unwrap();
+    };
+    input(&mut engine, "a", KeyValue::Press);
+    input(&mut engine, "a", KeyValue::Release);
+    input(&mut engine, "b", KeyValue::Press);
+    // Nothing is counted while b is held, so the release is what needs to wake up the engine.
+    assert!(engine.tick(10).unwrap());
+    input(&mut engine, "b", KeyValue::Release);
+    assert!(!engine.tick(5).unwrap());
+    assert!(engine.tick(30).unwrap());
+    assert_eq!(
+        outputs.lock().unwrap().as_slice(),
+        [
+            key("b", KeyValue::Press),
+            key("b", KeyValue::Release),
+            key("c", KeyValue::Press),
+            key("c", KeyValue::Release),
+        ]
+    );
+}
+
+#[test]
+fn engine_releases_keys_held_without_their_physical_key() {
+    let (mut engine, outputs) =
+        engine("(defcfg stuck-key-timeout 100) (defsrc f24) (deflayer base f23)");
+    let f24 = str_to_oscode("f24").unwrap();
+    engine
+        .handle_input(KeyEvent::new(f24, KeyValue::Press))
+        .unwrap();
+    engine.tick(200).unwrap();
+    assert_eq!(
+        outputs.lock().unwrap().as_slice(),
+        [key("f23", KeyValue::Press)]
+    );
+    // The release of the physical key is lost.
+    PRESSED_KEYS.lock().remove(&f24);
+    assert!(!engine.tick(99).unwrap());
+    assert_eq!(outputs.lock().unwrap().len(), 1);
+    engine.tick(2).unwrap();
+    assert_eq!(
+        outputs.lock().unwrap().as_slice(),
+        [key("f23", KeyValue::Press), key("f23", KeyValue::Release)]
+    );
+    assert!(engine.tick(1).unwrap());
+}
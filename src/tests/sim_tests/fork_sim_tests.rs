@@ -0,0 +1,36 @@
+use super::*;
+
+const CFG: &str = r"
+    (defvirtualkeys vk XX)
+    (defsrc a b c d spc)
+    (deflayer base
+        (fork a x ((input real spc)))
+        (fork b y (lsft (input virtual vk)))
+        (on-press press-vkey vk)
+        (on-press release-vkey vk)
+        (layer-while-held nav))
+    (deflayer nav _ _ _ _ _)
+";
+
+#[test]
+fn fork_on_held_physical_key() {
+    // The space key outputs no key while it holds the layer, so only its input triggers fork.
+    let result = simulate(
+        CFG,
+        "d:spc t:10 d:a t:10 u:a t:10 u:spc t:10 d:a t:10 u:a t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:X up:X dn:A up:A", result);
+}
+
+#[test]
+fn fork_on_held_virtual_key() {
+    let result = simulate(
+        CFG,
+        "d:c t:10 u:c t:10 d:b t:10 u:b t:10 d:d t:10 u:d t:10 d:b t:10 u:b t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:Y up:Y dn:B up:B", result);
+}
@@ -0,0 +1,15 @@
+use super::*;
+
+#[test]
+fn hid_usage_is_held_while_the_key_is_held() {
+    let result = simulate(
+        "(defsrc a b) (deflayer base (hid-usage consumer 0x0183) (hid-usage system 130))",
+        "d:a t:10 d:b t:10 u:b t:10 u:a t:10",
+    )
+    .no_time();
+    assert_eq!(
+        "out-hid:consumer:0x0183;Press out-hid:system:0x0082;Press \
+         out-hid:system:0x0082;Release out-hid:consumer:0x0183;Release",
+        result
+    );
+}
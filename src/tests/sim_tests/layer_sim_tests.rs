@@ -185,3 +185,113 @@ fn ls_sim_transparent_no_delegate() {
 // =============================================================================
 // End Layer Switch Simulator Input Tests
 // =============================================================================
+
+#[test]
+fn runtime_layer_fallback_order() {
+    const CFG: &str = r"
+        (defcfg delegate-to-first-layer yes)
+        (defsrc a b)
+        (deflayer base x y)
+        (deflayer symbols 1 _)
+        (deflayer nav _ _)
+    ";
+    let result = simulate(
+        CFG,
+        "ls:nav d:a t:10 u:a t:10 lf:symbols d:a t:10 u:a t:10 d:b t:10 u:b t:10
+         lf: d:a t:10 u:a t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:X up:X dn:Kb1 up:Kb1 dn:B up:B dn:X up:X", result);
+}
+
+#[test]
+fn runtime_layer_alias() {
+    const CFG: &str = r"
+        (defsrc a b)
+        (deflayer base (layer-while-held nav) b)
+        (deflayer nav _ left)
+        (deflayer demo _ right)
+    ";
+    let result = simulate(
+        CFG,
+        "d:a t:10 d:b t:10 u:b t:10 la:nav=demo d:b t:10 u:b t:10
+         la:nav=nav d:b t:10 u:b t:10 u:a t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:Left up:Left dn:Right up:Right dn:Left up:Left", result);
+}
+
+#[test]
+fn layer_enter_exit_hooks() {
+    const CFG: &str = r"
+        (defalias out (macro z))
+        (defsrc a b)
+        (deflayer base (layer-while-held nav) b)
+        (deflayer (nav on-enter (macro x y) on-exit @out) _ c)
+    ";
+    let result = simulate(CFG, "d:a t:10 d:b t:10 u:b t:10 u:a t:10 d:b t:10 u:b t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:X up:X dn:Y up:Y dn:C up:C dn:Z up:Z dn:B up:B", result);
+}
+
+#[test]
+fn layer_auto_return() {
+    const CFG: &str = r"
+        (defsrc a b c)
+        (deflayer base (layer-switch mouse) b (layer-switch nav))
+        (deflayer (mouse auto-return 100) (layer-switch nav) left c)
+        (deflayer (nav auto-return 200) _ right _)
+    ";
+    let result = simulate(
+        CFG,
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:90 d:b t:10 u:b t:110 d:b t:10 u:b t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:Left up:Left dn:Left up:Left dn:B up:B", result);
+    // Returning from a layer entered from another auto-return layer goes back to the first layer
+    // without auto-return.
+    let result = simulate(
+        CFG,
+        "d:a t:10 u:a t:10 d:a t:10 u:a t:10 d:b t:10 u:b t:210 d:b t:10 u:b t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:Right up:Right dn:B up:B", result);
+}
+
+#[test]
+fn layer_unmapped_option() {
+    const CFG: &str = r"
+        (defcfg process-unmapped-keys yes)
+        (defsrc a b)
+        (deflayer (base unmapped XX) b (layer-while-held nav))
+        (deflayermap (nav unmapped d) a e)
+    ";
+    let result = simulate(CFG, "d:a t:10 u:a t:10 d:c t:10 u:c t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:B up:B", result);
+    let result = simulate(CFG, "d:b t:10 d:c t:10 u:c t:10 d:a t:10 u:a t:10 u:b t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:D up:D dn:E up:E", result);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn layer_lock_leds() {
+    const CFG: &str = r"
+        (defcfg linux-layer-leds (nav (caps num) mouse none))
+        (defsrc a b c)
+        (deflayer base (layer-while-held nav) (layer-while-held mouse) (layer-while-held other))
+        (deflayer nav _ (layer-while-held mouse) x)
+        (deflayer mouse _ _ y)
+        (deflayer other _ _ _)
+    ";
+    let result = simulate(CFG, "d:a t:10 d:b t:10 u:b t:10 u:a t:10 d:c t:10 u:c t:10").no_time();
+    assert_eq!("leds:caps,num leds: leds:caps,num leds:lock-state", result);
+}
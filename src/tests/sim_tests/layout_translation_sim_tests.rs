@@ -0,0 +1,31 @@
+use super::*;
+
+#[test]
+fn defcfg_layout_translation_changes_transparent_keys() {
+    let result = simulate(
+        "
+(defcfg layout-translation colemak-dh)
+(defsrc e r a)
+(deflayer base _ r _)
+",
+        "d:e u:e d:r u:r d:a u:a t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:F up:F dn:R up:R dn:A up:A", result);
+}
+
+#[test]
+fn layer_layout_option() {
+    let result = simulate(
+        "
+(defsrc caps q s)
+(deflayer qwerty (layer-while-held dvorak) q s)
+(deflayermap (dvorak layout dvorak))
+",
+        "d:q u:q d:caps d:q u:q d:s u:s u:caps t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:Q up:Q dn:Quote up:Quote dn:O up:O", result);
+}
@@ -143,3 +143,34 @@ fn test_release(cfg: &str) {
         result
     );
 }
+
+#[test]
+fn macro_repeat_count() {
+    let cfg = "\
+(defsrc a b c)
+(deflayer base (macro (repeat 2 x (repeat 2 y)) S-(repeat 2 z)) (macro (repeat 40 x 10)) macro-cancel)";
+    let result = simulate(cfg, "d:a u:a t:50").no_time().to_ascii();
+    assert_eq!(
+        "dn:X up:X dn:Y up:Y dn:Y up:Y dn:X up:X dn:Y up:Y dn:Y up:Y \
+         dn:LShift dn:Z up:Z dn:Z up:Z up:LShift",
+        result
+    );
+    // Cancelling stops the remaining repeats.
+    let result = simulate(cfg, "d:b u:b t:25 d:c u:c t:100")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:X up:X dn:X up:X dn:X up:X", result);
+}
+
+#[test]
+fn macro_delay_scale() {
+    let cfg = "\
+(defsrc a b c)
+(deflayer base (macro x 100 y) (macro-delay-scale 200) (macro-delay-scale 50))";
+    let result = simulate(cfg, "d:a u:a t:300").to_ascii();
+    assert_eq!("t:1ms dn:X t:1ms up:X t:101ms dn:Y t:1ms up:Y", result);
+    let result = simulate(cfg, "d:b u:b t:10 d:a u:a t:300").to_ascii();
+    assert_eq!("t:11ms dn:X t:1ms up:X t:201ms dn:Y t:1ms up:Y", result);
+    let result = simulate(cfg, "d:c u:c t:10 d:a u:a t:300").to_ascii();
+    assert_eq!("t:11ms dn:X t:1ms up:X t:51ms dn:Y t:1ms up:Y", result);
+}
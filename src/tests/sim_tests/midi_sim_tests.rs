@@ -0,0 +1,31 @@
+use super::*;
+
+#[test]
+fn midi_note_on_press_and_off_on_release() {
+    let result = simulate(
+        "
+(defsrc a b)
+(deflayer base (midi-note 60) (midi-note 64 127 10))
+        ",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10",
+    )
+    .no_time();
+    assert_eq!(
+        "out-midi:[90, 3C, 64] out-midi:[80, 3C, 00] out-midi:[99, 40, 7F] out-midi:[89, 40, 00]",
+        result
+    );
+}
+
+#[test]
+fn midi_cc_only_sends_on_press() {
+    let result = simulate(
+        "
+(defcfg midi-output-port \"test port\")
+(defsrc a)
+(deflayer base (midi-cc 7 100 2))
+        ",
+        "d:a t:10 u:a t:10",
+    )
+    .no_time();
+    assert_eq!("out-midi:[B1, 07, 64]", result);
+}
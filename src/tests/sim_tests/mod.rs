@@ -55,28 +55,52 @@ fn apply_layer_switch(k: &mut Kanata, layer_name: &str) {
     k.layout.bm().set_default_layer(layer_idx);
 }
 
+mod alias_concat_sim_tests;
+mod autoshift_sim_tests;
 mod block_keys_tests;
 mod capsword_sim_tests;
 mod chord_sim_tests;
+mod compose_sim_tests;
+mod custom_shift_sim_tests;
+mod debounce_sim_tests;
+mod dedupe_sim_tests;
+mod defapp_sim_tests;
 mod delay_tests;
+mod engine_sim_tests;
+mod fork_sim_tests;
+mod hid_usage_sim_tests;
 mod layer_sim_tests;
+mod layout_translation_sim_tests;
 mod macro_sim_tests;
+mod midi_sim_tests;
+mod mod_morph_sim_tests;
 mod mouse_sim_tests;
+mod mpris_sim_tests;
+mod notify_sim_tests;
+mod obs_sim_tests;
 mod oneshot_tests;
+mod openrgb_sim_tests;
+mod os_layout_sim_tests;
 mod output_chord_tests;
 mod override_tests;
+mod processing_pause_sim_tests;
 mod release_sim_tests;
 mod repeat_sim_tests;
+mod secret_sim_tests;
 mod seq_sim_tests;
+mod sequence_menu_sim_tests;
+mod sound_sim_tests;
 mod switch_sim_tests;
 mod tap_dance_tests;
 mod tap_hold_tests;
 mod template_sim_tests;
 mod timing_tests;
+mod training_sim_tests;
 mod unicode_sim_tests;
 mod unmod_sim_tests;
 mod use_defsrc_sim_tests;
 mod vkey_sim_tests;
+mod webhook_sim_tests;
 #[cfg(feature = "zippychord")]
 mod zippychord_sim_tests;
 
@@ -147,6 +171,24 @@ fn simulate_with_file_content<S: AsRef<str>>(
                 "ls" | "layer-switch" | "🔀" => {
                     apply_layer_switch(&mut k, val);
                 }
+                // Runtime layer fallback: lf:layer1,layer2 or lf: to clear
+                "lf" | "layer-fallback" => {
+                    let names = val
+                        .split(',')
+                        .filter(|name| !name.is_empty())
+                        .map(String::from)
+                        .collect::<Vec<_>>();
+                    k.set_layer_fallback(&names).expect("valid layer fallback");
+                }
+                // Runtime layer alias: la:layer=target
+                "la" | "layer-alias" => {
+                    let (layer, target) = val.split_once('=').expect("layer=target");
+                    k.set_layer_alias(layer, target).expect("valid layer alias");
+                }
+                // Active application of defapp: app:identifier
+                "app" => {
+                    k.set_active_app(val);
+                }
                 _ => panic!("invalid item {pair}"),
             },
             None => panic!("invalid item {pair}"),
@@ -156,6 +198,31 @@ fn simulate_with_file_content<S: AsRef<str>>(
     k.kbd_out.outputs.events.join("\n")
 }
 
+/// Taps the keys one after another and returns the messages that kanata sends to TCP clients.
+fn server_messages(cfg: &str, taps: &[&str]) -> Vec<kanata_tcp_protocol::ServerMessage> {
+    init_log();
+    let mut k = {
+        let _lk = match CFG_PARSE_LOCK.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        Kanata::new_from_str(cfg, Default::default()).expect("failed to parse cfg")
+    };
+    let (tx, rx) = std::sync::mpsc::sync_channel(100);
+    let tx = Some(tx);
+    for key in taps {
+        let key_code = str_to_oscode(key).expect("valid keycode");
+        for value in [KeyValue::Press, KeyValue::Release] {
+            k.handle_input_event(&KeyEvent::new(key_code, value))
+                .expect("input handles fine");
+            for _ in 0..10 {
+                let _ = k.tick_ms(1, &tx);
+            }
+        }
+    }
+    rx.try_iter().collect()
+}
+
 #[allow(unused)]
 trait SimTransform {
     /// Changes newlines to spaces.
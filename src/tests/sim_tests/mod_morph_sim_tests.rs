@@ -0,0 +1,45 @@
+use super::*;
+
+#[test]
+fn mod_morph_outputs_morphed_key_while_modifier_is_held() {
+    let result = simulate(
+        "
+         (defsrc lsft a)
+         (deflayer base lsft (mod-morph a b (lsft rsft)))
+        ",
+        "d:a t:10 u:a t:10 d:lsft t:10 d:a t:10 u:a t:10 u:lsft t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:A up:A dn:LShift dn:B up:B up:LShift", result);
+}
+
+#[test]
+fn mod_morph_consumes_modifiers_and_repeats() {
+    let result = simulate(
+        "
+         (defsrc lsft comm)
+         (deflayer base lsft (mod-morph comm ; (lsft rsft) consume-mods))
+        ",
+        "d:lsft t:10 d:comm t:10 r:comm t:10 u:comm t:10 u:lsft t:10 d:comm t:10 u:comm t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!(
+        "dn:LShift up:LShift dn:SColon dn:SColon up:SColon dn:LShift \
+         up:LShift dn:Comma up:Comma",
+        result
+    );
+}
+
+#[test]
+#[should_panic]
+fn mod_morph_rejects_non_modifier_keys() {
+    simulate(
+        "
+         (defsrc a)
+         (deflayer base (mod-morph a b (lsft c)))
+        ",
+        "",
+    );
+}
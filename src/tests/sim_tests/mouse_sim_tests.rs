@@ -12,3 +12,112 @@ fn multi_mouse_button_does_multi_click_release_single_hold() {
         result
     );
 }
+
+#[test]
+fn mouse_grid_subdivides_and_resets() {
+    let result = simulate(
+        "(defsrc)
+         (deflayermap (base)
+           a (mouse-grid 3 3 1 1)
+           b (mouse-grid 2 2 2 2)
+           c mouse-grid-reset
+           d (mouse-grid-monitor 2))",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10 d:c t:10 u:c t:10
+         d:d t:10 u:d t:10 d:b t:10 u:b t:10",
+    )
+    .no_time();
+    assert_eq!(
+        "out🖰:@m0:0.1667,0.1667 out🖰:@m0:0.2500,0.2500 out🖰:@m0:0.5000,0.5000 \
+         out🖰:@m1:0.5000,0.5000 out🖰:@m1:0.7500,0.7500",
+        result
+    );
+}
+
+#[test]
+fn mwheel_accel_and_smooth() {
+    let result = simulate(
+        "(defsrc) (deflayermap (base) a (mwheel-up 10 10 20 100))",
+        "d:a t:25 u:a t:20",
+    )
+    .no_time();
+    assert_eq!("scroll:Up,14 scroll:Up,59 scroll:Up,100", result);
+    let result = simulate(
+        "(defsrc) (deflayermap (base) a (mwheel-down 4 10 smooth))",
+        "d:a t:2 u:a t:10",
+    )
+    .no_time();
+    // The release does not cut off the activation that is being spread.
+    assert_eq!(
+        "scroll:Down,3 scroll:Down,2 scroll:Down,3 scroll:Down,2",
+        result
+    );
+}
+
+#[test]
+fn mouse_toward_moves_part_of_the_way_to_anchors() {
+    let result = simulate(
+        "(defsrc)
+         (deflayermap (base)
+           a (mouse-toward bottom-right 50)
+           b (mouse-toward monitor-2 100)
+           c (mouse-toward left 25))",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10 d:c t:10 u:c t:10",
+    )
+    .no_time();
+    assert_eq!("out🖰:to960,540 out🖰:to2559,511 out🖰:to2399,511", result);
+}
+
+#[test]
+fn jiggle_alternates_until_toggled_off() {
+    let result = simulate(
+        "(defsrc) (deflayermap (base) a (jiggle 100 1))",
+        "d:a t:10 u:a t:300 d:a t:10 u:a t:300",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!(
+        "out🖰:move Right,1 out🖰:move Left,1 out🖰:move Right,1",
+        result
+    );
+}
+
+#[test]
+fn jiggle_zero_sum_moves_back_immediately() {
+    let result = simulate(
+        "(defsrc) (deflayermap (base) a (jiggle 100 2 zero-sum))",
+        "d:a t:10 u:a t:250",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!(
+        "out🖰:move Right,2 out🖰:move Left,2 out🖰:move Right,2 out🖰:move Left,2",
+        result
+    );
+}
+
+#[test]
+fn tap_hold_on_mouse_button_taps_and_holds() {
+    let cfg = "(defsrc mrgt a)
+               (deflayer base (tap-hold 100 100 mrgt (layer-while-held nav)) a)
+               (deflayer nav _ b)";
+    let result = simulate(cfg, "d:mrgt t:50 u:mrgt t:50").no_time();
+    assert_eq!("out🖰:↓Right out🖰:↑Right", result);
+    let result = simulate(cfg, "d:mrgt t:150 d:a t:10 u:a t:10 u:mrgt t:10").no_time();
+    assert_eq!("out:↓B out:↑B", result);
+}
+
+#[test]
+fn horizontal_scroll_and_tilt_wheel_remap() {
+    let result = simulate(
+        "(defsrc) (deflayermap (base) a (mwheel-right 10 10 20 100))",
+        "d:a t:25 u:a t:20",
+    )
+    .no_time();
+    assert_eq!("scroll:Right,14 scroll:Right,59 scroll:Right,100", result);
+    let result = simulate(
+        "(defsrc mwl mwr) (deflayer base (mwheel-right 50 60) (mwheel-left 50 120))",
+        "d:mwl t:10 u:mwl t:10 d:mwr t:10 u:mwr t:10",
+    )
+    .no_time();
+    assert_eq!("scroll:Right,60 scroll:Left,120", result);
+}
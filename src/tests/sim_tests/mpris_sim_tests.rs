@@ -0,0 +1,14 @@
+use super::*;
+
+#[test]
+fn mpris_commands_are_sent_on_press() {
+    let result = simulate(
+        "
+(defsrc a b)
+(deflayer base (mpris play-pause spotify) (mpris next))
+        ",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10",
+    )
+    .no_time();
+    assert_eq!("out-mpris:play-pause:spotify out-mpris:next", result);
+}
@@ -0,0 +1,14 @@
+use super::*;
+
+#[test]
+fn notify_shows_on_press() {
+    let result = simulate(
+        "
+(defsrc a b)
+(deflayer base (notify \"Gaming layer\" ON) (notify \"Saved\"))
+        ",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10",
+    )
+    .no_time();
+    assert_eq!("out-notify:Gaming layer:ON out-notify:Saved:", result);
+}
@@ -0,0 +1,21 @@
+use super::*;
+
+#[test]
+fn obs_requests_are_sent_on_press() {
+    let result = simulate(
+        "
+(defsrc a b c)
+(deflayer base
+  (obs switch-scene \"Be right back\")
+  (obs toggle-source Main Camera)
+  (obs toggle-recording))
+        ",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10 d:c t:10 u:c t:10",
+    )
+    .no_time();
+    assert_eq!(
+        "out-obs:switch-scene:Be right back out-obs:toggle-source:Main:Camera \
+         out-obs:toggle-recording",
+        result
+    );
+}
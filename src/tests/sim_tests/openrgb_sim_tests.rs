@@ -0,0 +1,37 @@
+use super::*;
+
+#[test]
+fn openrgb_colors_are_set_on_press() {
+    let result = simulate(
+        "
+(defsrc a b c)
+(deflayer base (openrgb #ff8000) (openrgb #0af) (openrgb off))
+        ",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10 d:c t:10 u:c t:10",
+    )
+    .no_time();
+    assert_eq!(
+        "out-openrgb:ff8000 out-openrgb:00aaff out-openrgb:000000",
+        result
+    );
+}
+
+#[test]
+fn openrgb_layer_colors_are_set_on_layer_change() {
+    let result = simulate(
+        "
+(defcfg openrgb-layer-colors yes)
+(defsrc a b c)
+(deflayer (base color #000) (layer-while-held nav) (layer-while-held plain) c)
+(deflayer (nav color #00ff00) _ (layer-while-held plain) x)
+(deflayer plain _ _ y)
+        ",
+        "d:a t:10 d:c t:10 u:c t:10 d:b t:10 u:b t:10 u:a t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!(
+        "out-openrgb:00ff00 dn:X up:X out-openrgb:00ff00 out-openrgb:000000",
+        result
+    );
+}
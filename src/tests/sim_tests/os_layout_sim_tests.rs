@@ -0,0 +1,18 @@
+use super::*;
+
+#[test]
+fn os_layout_is_switched_on_press() {
+    let result = simulate(
+        "
+(defsrc a b)
+(deflayer base (multi (os-layout \"de nodeadkeys\") (layer-switch other)) b)
+(deflayer other a (os-layout ibus:mozc-jp))
+        ",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10",
+    )
+    .no_time();
+    assert_eq!(
+        "out-os-layout:de nodeadkeys out-os-layout:ibus:mozc-jp",
+        result
+    );
+}
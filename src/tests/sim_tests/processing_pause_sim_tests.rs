@@ -0,0 +1,41 @@
+use super::*;
+
+#[test]
+fn toggle_processing_passes_through_until_toggled_again() {
+    let result = simulate(
+        "(defsrc a b c)
+         (deflayer base toggle-processing c b)",
+        "d:b t:10 u:b t:10 d:a t:10 u:a t:10 d:b t:10 u:b t:10 d:c t:10 u:c t:10
+         d:a t:10 u:a t:10 d:b t:10 u:b t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:C up:C dn:B up:B dn:C up:C dn:C up:C", result);
+}
+
+#[test]
+fn toggle_processing_releases_keys_held_on_resume() {
+    let result = simulate(
+        "(defsrc a b c)
+         (deflayer base toggle-processing c (layer-while-held other))
+         (deflayer other _ x _)",
+        "d:c t:10 d:a t:10 u:a t:10 d:b t:10 u:c t:10 d:a t:10 u:b t:10 d:b t:10 u:b t:10",
+    )
+    .no_time()
+    .to_ascii();
+    // The layer-while-held release is processed as usual while paused, and the held b is
+    // released when processing resumes.
+    assert_eq!("dn:B up:B dn:C up:C", result);
+}
+
+#[test]
+fn toggle_processing_as_tap_action() {
+    let result = simulate(
+        "(defsrc a b)
+         (deflayer base (tap-hold 200 200 toggle-processing lctl) c)",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10 d:a t:10 u:a t:10 d:b t:10 u:b t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("dn:B up:B dn:C up:C", result);
+}
@@ -29,3 +29,33 @@ fn release_reversed() {
     .to_ascii();
     assert_eq!("dn:LAlt dn:A t:10ms up:A up:LAlt", result);
 }
+
+#[test]
+fn preserve_release_order_one_shot() {
+    let cfg = "
+         (defcfg preserve-release-order yes)
+         (defsrc a b c)
+         (deflayer base (one-shot 200 lctl) (one-shot 200 rctl) c)
+        ";
+    let result = simulate(cfg, "d:a t:10 d:c t:10 d:b t:10 u:b t:10 u:a t:10 u:c t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:LCtrl dn:C dn:RCtrl up:RCtrl up:LCtrl up:C", result);
+}
+
+#[test]
+fn preserve_release_order_tap_hold() {
+    let cfg = "
+         (defcfg preserve-release-order yes)
+         (defsrc a b c)
+         (deflayer base (tap-hold 200 200 a lctl) (multi lalt b reverse-release-order) c)
+        ";
+    let result = simulate(cfg, "d:a t:10 d:c t:10 u:a t:10 u:c t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:A dn:C up:A up:C", result);
+    let result = simulate(cfg, "d:a t:300 d:b t:10 u:a t:10 u:b t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:LCtrl dn:LAlt dn:B up:LCtrl up:B up:LAlt", result);
+}
@@ -134,3 +134,101 @@ fn repeat_delegate_to_base_layer_trans() {
         result
     );
 }
+
+#[test]
+fn repeat_defrepeat_none() {
+    let result = simulate(
+        "
+         (defsrc a b)
+         (deflayer base a b)
+         (defrepeat a none)
+        ",
+        "
+         d:a t:10 r:a t:10 r:a t:10 u:a t:10
+         d:b t:10 r:b t:10 u:b t:10
+        ",
+    )
+    .to_ascii()
+    .no_time();
+    assert_eq!("dn:A up:A dn:B dn:B up:B", result);
+}
+
+#[test]
+fn repeat_defrepeat_software() {
+    let result = simulate(
+        "
+         (defsrc a)
+         (deflayer base a)
+         (defrepeat a (30 10))
+        ",
+        "
+         d:a t:5 r:a t:20 r:a t:30 u:a t:50
+        ",
+    )
+    .to_ascii();
+    assert_eq!(
+        "dn:A t:30ms dn:A t:10ms dn:A t:10ms dn:A t:5ms up:A",
+        result
+    );
+}
+
+#[test]
+fn repeat_defrepeat_software_stops_on_other_press() {
+    let result = simulate(
+        "
+         (defsrc a b)
+         (deflayer base a b)
+         (defrepeat a (30 10))
+        ",
+        "
+         d:a t:35 d:b t:50 u:b t:10 u:a t:10
+        ",
+    )
+    .to_ascii();
+    assert_eq!(
+        "dn:A t:30ms dn:A t:5ms dn:B t:50ms up:B t:10ms up:A",
+        result
+    );
+}
+
+#[test]
+fn repeat_defrepeat_layer() {
+    let result = simulate(
+        "
+         (defsrc a b)
+         (deflayer base a (layer-while-held nav))
+         (deflayer nav left b)
+         (defrepeat (a left) none)
+         (defrepeat-layer nav left (20 5))
+        ",
+        "
+         d:a t:10 r:a t:10 u:a t:10
+         d:b t:10 d:a t:10 r:a t:15 u:a t:10 u:b t:10
+        ",
+    )
+    .to_ascii();
+    assert_eq!(
+        "dn:A t:20ms up:A t:20ms dn:Left t:20ms dn:Left t:5ms dn:Left up:Left",
+        result
+    );
+}
+
+#[test]
+fn repeat_layer_opt_none() {
+    let result = simulate(
+        "
+         (defsrc a b)
+         (deflayer base a (layer-while-held sym))
+         (deflayer (sym repeat none) _ b)
+        ",
+        "
+         d:b t:10 d:a t:10 r:a t:10 u:a t:10 u:b t:10
+         d:a t:10 r:a t:10 u:a t:10
+        ",
+    )
+    .to_ascii();
+    assert_eq!(
+        "t:10ms dn:A t:20ms up:A t:20ms dn:A t:10ms dn:A t:10ms up:A",
+        result
+    );
+}
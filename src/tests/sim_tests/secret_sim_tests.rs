@@ -0,0 +1,15 @@
+use super::*;
+
+#[test]
+fn secret_is_typed_on_press() {
+    let result = simulate(
+        "
+(defcfg danger-enable-secrets yes)
+(defsrc a b)
+(deflayer base (secret-type github-token) b)
+        ",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10",
+    )
+    .no_time();
+    assert_eq!("out-secret:github-token out:↓B out:↑B", result);
+}
@@ -259,3 +259,39 @@ d:KeyS t:10 d:KeyD t:10 u:KeyS t:10 u:KeyD t:10",
     .to_ascii();
     assert_eq!("outU:μ dn:D outU:μ dn:D", result,);
 }
+
+#[test]
+fn unmodded_step() {
+    let cfg = "
+    (defsrc 0)
+    (deflayer base sldr)
+    (defvirtualkeys s1 z)
+    (defseq s1 (N-a b))
+    ";
+    let result = simulate(cfg, "d:0 u:0 t:10 d:a u:a d:b u:b t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("up:A up:B dn:Z up:Z", result);
+    let result = simulate(cfg, "d:0 u:0 t:10 d:lsft d:a u:a u:lsft d:b u:b t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("up:A up:LShift up:B", result);
+}
+
+#[test]
+fn pass_mods() {
+    let cfg = "
+    (defsrc 0)
+    (deflayer base sldr)
+    (defvirtualkeys s1 z s2 y)
+    (defseq (s1 pass-mods yes) (a b) s2 (c d))
+    ";
+    let result = simulate(cfg, "d:0 u:0 t:10 d:lsft d:a u:a d:b u:b t:10 u:lsft t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("up:A dn:LShift up:B dn:Z up:Z up:LShift", result);
+    let result = simulate(cfg, "d:0 u:0 t:10 d:lsft d:c u:c d:d u:d t:10 u:lsft t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("up:C up:D dn:Y up:Y up:LShift", result);
+}
@@ -0,0 +1,35 @@
+use super::*;
+
+use kanata_tcp_protocol::ServerMessage;
+
+fn sequence_menus(cfg: &str, taps: &[&str]) -> Vec<String> {
+    server_messages(cfg, taps)
+        .into_iter()
+        .filter(|msg| matches!(msg, ServerMessage::SequenceMenu { .. }))
+        .map(|msg| serde_json::to_string(&msg).expect("serializable"))
+        .collect()
+}
+
+#[test]
+fn sequence_menu_lists_next_keys() {
+    let menus = sequence_menus(
+        r#"
+(defsrc a b c d)
+(deflayer base sldr b c d)
+(defvirtualkeys
+  vk-b (doc "Bold") b
+  vk-cd (layer-switch base)
+  vk-cc x)
+(defseq vk-b (b) vk-cd (c d) vk-cc (c c))
+"#,
+        &["a", "c", "d"],
+    );
+    assert_eq!(
+        menus,
+        [
+            r#"{"SequenceMenu":{"active":true,"typed":[],"next":[{"key":"b","virtual_key":"vk-b","description":"Bold","continues":false},{"key":"c","continues":true}]}}"#,
+            r#"{"SequenceMenu":{"active":true,"typed":["c"],"next":[{"key":"c","virtual_key":"vk-cc","description":"x","continues":false},{"key":"d","virtual_key":"vk-cd","description":"to base","continues":false}]}}"#,
+            r#"{"SequenceMenu":{"active":false,"typed":[],"next":[]}}"#,
+        ]
+    );
+}
@@ -0,0 +1,58 @@
+use super::*;
+
+#[test]
+fn sound_layer_change_default_and_per_layer() {
+    let result = simulate(
+        "(defcfg sound-layer-change beep)
+         (defsrc a b c)
+         (deflayer base (layer-while-held one) (layer-while-held two) c)
+         (deflayer one _ _ c)
+         (deflayer (two sound two.wav) _ _ c)",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10",
+    )
+    .no_time();
+    assert_eq!(
+        "out-sound:beep out-sound:beep out-sound:two.wav out-sound:beep",
+        result
+    );
+}
+
+#[test]
+fn sound_silent_layer() {
+    let result = simulate(
+        "(defcfg sound-layer-change beep)
+         (defsrc a)
+         (deflayer (base 🔊 none) (layer-while-held one))
+         (deflayer one _)",
+        "d:a t:10 u:a t:10",
+    )
+    .no_time();
+    assert_eq!("out-sound:beep", result);
+}
+
+#[test]
+fn sound_caps_word_activation() {
+    let result = simulate(
+        "(defcfg sound-caps-word \"cw.wav\")
+         (defsrc a b)
+         (deflayer base (caps-word-toggle 1000) b)",
+        "d:a t:10 u:a t:10 d:b t:10 u:b t:10 d:a t:10 u:a t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!("out-sound:cw.wav dn:LShift dn:B up:LShift up:B", result);
+}
+
+#[test]
+fn sound_sequence_timeout() {
+    let result = simulate(
+        "(defcfg sound-sequence-timeout beep sequence-timeout 50)
+         (defsrc a b)
+         (deflayer base sldr b)
+         (defvirtualkeys s1 z)
+         (defseq s1 (b b))",
+        "d:a t:10 u:a t:100",
+    )
+    .no_time();
+    assert_eq!("out-sound:beep", result);
+}
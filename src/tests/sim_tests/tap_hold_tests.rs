@@ -1234,3 +1234,54 @@ fn tap_hold_order_no_prior_idle_enters_normal_resolution() {
         result
     );
 }
+
+#[test]
+fn tap_hold_key_timeouts_change_the_hold_timeout_of_keys() {
+    let cfg = "
+(defcfg tap-hold-key-timeouts (a +60 s 100))
+(defsrc a s d)
+(deflayer base @a @s @d)
+(defalias
+  a (tap-hold 200 200 a lctl)
+  s (tap-hold 200 200 s lalt)
+  d (tap-hold 200 200 d lsft)
+)
+        ";
+    let result = simulate(cfg, "d:a t:250 u:a t:10").no_time().to_ascii();
+    assert_eq!("dn:A up:A", result);
+    let result = simulate(cfg, "d:a t:270 u:a t:10").no_time().to_ascii();
+    assert_eq!("dn:LCtrl up:LCtrl", result);
+    let result = simulate(cfg, "d:s t:110 u:s t:10").no_time().to_ascii();
+    assert_eq!("dn:LAlt up:LAlt", result);
+    let result = simulate(cfg, "d:d t:110 u:d t:10").no_time().to_ascii();
+    assert_eq!("dn:D up:D", result);
+}
+
+#[test]
+fn tap_hold_flavor_uses_the_flavor_settings() {
+    let cfg = "
+(deftaphold-flavor hrm decision press timeout-action tap tap-on-press (b))
+(deftaphold-flavor slow hold-on-press (c))
+(defsrc a b c d)
+(deflayer base (tap-hold-flavor hrm 0 200 x lctl) b c (tap-hold-flavor slow 0 200 y lalt))
+        ";
+    let result = simulate(cfg, "d:a t:10 d:c t:10 u:c t:10 u:a t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:LCtrl dn:C up:C up:LCtrl", result);
+    let result = simulate(cfg, "d:a t:10 d:b t:10 u:b t:10 u:a t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:X dn:B up:B up:X", result);
+    let result = simulate(cfg, "d:a t:250 u:a t:10").no_time().to_ascii();
+    assert_eq!("dn:X up:X", result);
+    // Without a decision, other keys wait for the timeout.
+    let result = simulate(cfg, "d:d t:10 d:b t:10 u:b t:10 u:d t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:Y dn:B up:B up:Y", result);
+    let result = simulate(cfg, "d:d t:10 d:c t:10 u:c t:10 u:d t:10")
+        .no_time()
+        .to_ascii();
+    assert_eq!("dn:LAlt dn:C up:C up:LAlt", result);
+}
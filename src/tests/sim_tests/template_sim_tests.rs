@@ -19,3 +19,45 @@ fn nested_template() {
     .no_time();
     assert_eq!("out:↓A out:↑A out:↓X out:↑X out:↓G out:↑G", result);
 }
+
+#[test]
+fn deeply_composed_multi() {
+    let result = simulate(
+        "
+        (deftemplate u3 (x y z)
+         (multi (unicode $x) (unicode $y) (unicode $z))
+        )
+        (deftemplate u9 (x y z)
+         (multi (t! u3 $x $y $z) (t! u3 $z $y $x) (t! u3 $x $x $x))
+        )
+        (defsrc a)
+        (deflayer base (tap-hold 100 100 (multi (t! u9 a b c) (t! u3 x y z)) (t! u9 d e f)))
+        ",
+        "d:a t:10 u:a t:50",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!(
+        "outU:a outU:b outU:c outU:c outU:b outU:a outU:a outU:a outU:a outU:x outU:y outU:z",
+        result
+    );
+}
+
+#[test]
+fn foreach_list_defvar() {
+    let result = simulate(
+        "
+        (defvar letters (a b c))
+        (defsrc (foreach $l $letters $l) d)
+        (deflayer base (foreach $l $letters (concat @s- $l)) (foreach $l (x) $l))
+        (defalias (foreach $l $letters (concat s- $l) (multi lsft $l)))
+        ",
+        "d:a t:10 u:a t:10 d:c t:10 u:c t:10 d:d t:10 u:d t:10",
+    )
+    .no_time()
+    .to_ascii();
+    assert_eq!(
+        "dn:LShift dn:A up:LShift up:A dn:LShift dn:C up:LShift up:C dn:X up:X",
+        result
+    );
+}
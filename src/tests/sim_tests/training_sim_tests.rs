@@ -0,0 +1,37 @@
+use super::*;
+
+use kanata_tcp_protocol::ServerMessage;
+
+fn training_messages(cfg: &str, presses: &[&str]) -> Vec<String> {
+    server_messages(cfg, presses)
+        .into_iter()
+        .filter(|msg| {
+            matches!(
+                msg,
+                ServerMessage::TrainingMode { .. } | ServerMessage::TrainingKey { .. }
+            )
+        })
+        .map(|msg| serde_json::to_string(&msg).expect("serializable"))
+        .collect()
+}
+
+#[test]
+fn training_mode_streams_action_and_reference() {
+    let messages = training_messages(
+        "(defsrc a s d)
+         (deflayer colemak (training-mode qwerty) r (layer-while-held nav))
+         (deflayer nav _ pgdn _)
+         (deflayer qwerty a s d)",
+        &["s", "a", "s", "d", "a", "s"],
+    );
+    assert_eq!(
+        messages,
+        [
+            r#"{"TrainingMode":{"enabled":true,"reference_layer":"qwerty"}}"#,
+            r#"{"TrainingKey":{"key":"s","layer":"colemak","action":"r","reference":"s"}}"#,
+            r#"{"TrainingKey":{"key":"d","layer":"colemak","action":"nav","reference":"d"}}"#,
+            r#"{"TrainingKey":{"key":"a","layer":"colemak","action":"training-mode","reference":"a"}}"#,
+            r#"{"TrainingMode":{"enabled":false}}"#,
+        ]
+    );
+}
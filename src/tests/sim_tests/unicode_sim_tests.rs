@@ -66,3 +66,29 @@ fn unicode_multi() {
     .to_ascii();
     assert_eq!("outU:a t:1ms outU:b", result);
 }
+
+#[test]
+fn unicode_string() {
+    let result = simulate(
+        r#"
+        (defsrc a b c)
+        (deflayer l (unicode "½ ≤ 🎉") (unicode 🤲🏿) 🔣ab)
+        "#,
+        "d:a t:10 u:a t:10 d:b t:10 d:c t:10",
+    )
+    .no_time();
+    assert_eq!("outU:½ ≤ 🎉 outU:🤲🏿 outU:ab", result);
+}
+
+#[test]
+fn datetime_is_typed_as_unicode() {
+    let result = simulate(
+        r#"
+        (defsrc a)
+        (deflayer l (datetime "100%% %%Y"))
+        "#,
+        "d:a t:10 u:a t:10",
+    )
+    .no_time();
+    assert_eq!("outU:100% %Y", result);
+}
@@ -0,0 +1,21 @@
+use super::*;
+
+#[test]
+fn webhooks_are_sent_on_layer_change_and_action() {
+    let result = simulate(
+        "
+(defwebhooks
+  status http://localhost:8080/kanata (layer-change)
+  lights http://localhost:8080/lights ())
+(defsrc a b)
+(deflayer base (layer-while-held nav) (webhook lights on))
+(deflayer nav _ (webhook lights))
+        ",
+        "d:b t:10 u:b t:10 d:a t:10 d:b t:10 u:b t:10 u:a t:10",
+    )
+    .no_time();
+    assert_eq!(
+        r#"out-webhook:lights:{"data":"on","event":"action"} out-webhook:status:{"event":"layer-change","layer":"nav"} out-webhook:lights:{"event":"action"} out-webhook:status:{"event":"layer-change","layer":"base"}"#,
+        result
+    );
+}
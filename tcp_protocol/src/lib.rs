@@ -4,6 +4,7 @@
 //! TCP clients and the Kanata keyboard remapping daemon.
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
 /// Messages sent from the server to connected clients.
@@ -24,6 +25,9 @@ pub enum ServerMessage {
     },
     ConfigFileReload {
         new: String,
+        /// What the reload changed. Introduced after protocol v1.11.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        changes: Option<ReloadChanges>,
     },
     CurrentLayerName {
         name: String,
@@ -40,6 +44,12 @@ pub enum ServerMessage {
         version: String,
         protocol: u8,
         capabilities: Vec<String>,
+        /// The logind seat that this kanata uses the devices of, with `linux-seat`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seat: Option<String>,
+        /// The framing of the messages after this one, if `Hello` requested one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        framing: Option<Framing>,
     },
     /// Response to Reload commands when `wait: true` was specified.
     /// Introduced in protocol v1.11.
@@ -58,13 +68,280 @@ pub enum ServerMessage {
     TapActivated {
         key: String,
     },
+    /// Sent when an emergency passthrough chord disengages or re-engages kanata.
+    /// While `active` is true, input goes directly to the OS without remapping.
+    EmergencyPassthrough {
+        active: bool,
+    },
+    /// Sent when the `toggle-processing` action pauses or resumes remapping.
+    ProcessingPaused {
+        paused: bool,
+    },
+    /// Sent when the keyboard layout of the OS changes, e.g. `"de-DE"` on Windows.
+    OsLayoutChange {
+        layout: String,
+    },
+    /// Sent when an input device connects or disconnects, e.g. a Bluetooth keyboard that goes
+    /// to sleep. Only sent on Linux.
+    DeviceChange {
+        path: String,
+        name: String,
+        connected: bool,
+    },
+    /// Sent when kanata released an output key that was held without its physical key for
+    /// longer than `stuck-key-timeout`. `key` is the name of the output key.
+    StuckKeyReleased {
+        key: String,
+        held_ms: u64,
+    },
+    /// Sent when a reload from `ReloadTry` was rolled back to the configuration from before,
+    /// because it was not confirmed in time or because of a runtime error.
+    ReloadRolledBack {
+        reason: String,
+    },
+    /// Response to `RequestStats`.
+    /// `latency` is only present when kanata runs with `--measure-latency`.
+    /// `threads` and `channels` tell which part of kanata is stuck when keys stop responding.
+    Stats {
+        /// Number of key presses received since kanata started.
+        presses: u64,
+        /// The typing speed over the last seconds, see `TypingSpeed`.
+        #[serde(default)]
+        wpm: f32,
+        #[serde(default)]
+        keys_per_second: f32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        latency: Option<LatencyStats>,
+        /// The state of kanata stayed locked, e.g. by a stuck processing loop, so only `threads`
+        /// and `channels` are filled in.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        state_locked: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        threads: Vec<ThreadHealth>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        channels: Vec<ChannelDepth>,
+    },
+    /// Response to `RequestNgramStats`.
+    NgramStats {
+        /// `keys` if the n-grams are of key names, or `classes` if they are of key classes such
+        /// as `vowel` and `digit`.
+        keys: String,
+        /// The n-grams by the layer that their last key was pressed on.
+        layers: BTreeMap<String, LayerNgrams>,
+    },
+    /// Response to `RequestMousePosition`, in pixels of the desktop.
+    MousePosition {
+        x: i32,
+        y: i32,
+    },
+    /// Sent before the next notifications when notifications were dropped because the client
+    /// did not read them fast enough. `count` is the number since the last `NotificationsDropped`.
+    NotificationsDropped {
+        count: u64,
+    },
+    /// The typing speed over the last 10 seconds, sent periodically while keys are typed and
+    /// once more when it drops to zero, when kanata runs with `--typing-speed-interval-ms`.
+    /// `wpm` counts the keys that type characters, five per word, and `keys_per_second` every
+    /// key press.
+    TypingSpeed {
+        wpm: f32,
+        keys_per_second: f32,
+    },
+    /// Response to `RequestTapHolds`: the parameters of the tap-hold actions of aliases, by
+    /// alias name.
+    TapHolds {
+        aliases: BTreeMap<String, TapHoldParams>,
+    },
+    /// Response to `RequestLayerMeta`: how to show the layers, in the order of `LayerNames`.
+    LayerMeta {
+        layers: Vec<LayerMeta>,
+    },
+    /// Response to `RequestConfigMeta`: the `defmeta` of the configuration. Options that it does
+    /// not have are left out.
+    ConfigMeta {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        keyboard: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    /// Sent when training mode is turned on or off, by the `training-mode` action or
+    /// `SetTrainingMode`. `reference_layer` is the layer of the old layout, if it is not defsrc.
+    TrainingMode {
+        enabled: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reference_layer: Option<String>,
+    },
+    /// Sent for every key press while training mode is on. `key` is the physical key, `action`
+    /// is what it does on `layer`, the active layer, and `reference` is what it does in the old
+    /// layout, both as short labels such as `lctrl+w` or `to nav`.
+    TrainingKey {
+        key: String,
+        layer: String,
+        action: String,
+        reference: String,
+    },
+    /// Response to `RequestKeyDocs`: the descriptions of aliases, layers and virtual keys from
+    /// `doc`, by name. Those without a description are left out.
+    KeyDocs {
+        aliases: BTreeMap<String, String>,
+        layers: BTreeMap<String, String>,
+        #[serde(default)]
+        virtual_keys: BTreeMap<String, String>,
+    },
+    /// Sent when a sequence starts, after every key typed into it and when it ends, with the
+    /// keys that continue the sequence, e.g. for a menu of what a leader key can do. `typed`
+    /// has the keys typed so far, and `next` is empty once the sequence has ended.
+    SequenceMenu {
+        active: bool,
+        typed: Vec<String>,
+        next: Vec<SequenceMenuEntry>,
+    },
+    /// Sent to clients that sent `SubscribeCmdOutput` when a cmd action starts a command. `id`
+    /// tells the messages of commands that run at the same time apart.
+    CmdStarted {
+        id: u64,
+        cmd: Vec<String>,
+    },
+    /// A line that a command wrote, without the line ending. `stream` is `stdout` or `stderr`.
+    CmdOutput {
+        id: u64,
+        stream: String,
+        line: String,
+    },
+    /// Sent when a command exited. `code` is absent if the command was killed, and `error` is the
+    /// reason if it could not start or was killed for its timeout or by `CancelCmd`.
+    CmdExited {
+        id: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// A key that continues a sequence in `SequenceMenu`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequenceMenuEntry {
+    /// The key, with prefixes such as `S-` for the modifiers that must be held.
+    pub key: String,
+    /// The virtual key that the sequence taps if it ends with this key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub virtual_key: Option<String>,
+    /// The `doc` of the virtual key, or else a short label of its action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Whether longer sequences continue after this key.
+    pub continues: bool,
+}
+
+/// How to show a layer, from the `display-name`, `icon` and `color` options of its `deflayer`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayerMeta {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// The icon file name or emoji.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// `#rgb` or `#rrggbb`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+/// The parameters of a tap-hold action that `SetTapHold` can change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TapHoldParams {
+    pub hold_timeout_ms: u16,
+    pub tap_repress_timeout_ms: u16,
+    /// `tap-hold`, `tap-hold-press` or `tap-hold-release`, or for actions with other
+    /// flavors, e.g. `tap-hold-release-keys`, `tap-hold-order` or `custom`.
+    pub flavor: String,
+    /// Whether `SetTapHold` changed the parameters from the configured ones.
+    pub tuned: bool,
+}
+
+/// Latency of handling input events in microseconds, from being received to the output being
+/// written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    /// Number of measured events.
+    pub count: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+/// The health of a part of kanata that runs in its own threads, in `Stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreadHealth {
+    /// `input-reader`, `processor`, `tcp-server` or `cmd-executor`. Parts that never ran are left
+    /// out.
+    pub name: String,
+    /// The number of its threads that run. 0 means that the part stopped, e.g. after a panic,
+    /// except for `cmd-executor`, which runs a thread per command.
+    pub threads: u32,
+    /// How long it has been working without waiting, e.g. for input. Absent while it waits. For
+    /// `cmd-executor`, how long ago the latest command that still runs started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub busy_ms: Option<u64>,
+    /// How long ago it last started or stopped working, e.g. read an input event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_active_ms_ago: Option<u64>,
+}
+
+/// The number of items that wait in a queue between parts of kanata, in `Stats`: `input-events`
+/// in front of the processing loop, and `tcp-client:ADDRESS` for the notifications that a client
+/// has not read yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelDepth {
+    pub name: String,
+    pub len: u64,
+    pub capacity: u64,
+}
+
+/// The names of the layers, `defcfg` options and aliases that a reload added, removed or
+/// modified. Options and aliases are compared in the text of the main configuration file.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ReloadChanges {
+    pub added_layers: Vec<String>,
+    pub removed_layers: Vec<String>,
+    pub modified_layers: Vec<String>,
+    pub changed_options: Vec<String>,
+    pub changed_aliases: Vec<String>,
+}
+
+/// The bigrams and trigrams of a layer, the most frequent first.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct LayerNgrams {
+    pub bigrams: Vec<Ngram>,
+    pub trigrams: Vec<Ngram>,
+}
+
+/// Keys that were pressed after each other and how often.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ngram {
+    pub keys: Vec<String>,
+    pub count: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "status")]
 pub enum ServerResponse {
     Ok,
-    Error { msg: String },
+    Error {
+        msg: String,
+    },
+    /// The command changes the state of kanata and the client may only send queries.
+    Forbidden {
+        command: String,
+        msg: String,
+    },
 }
 
 impl ServerResponse {
@@ -137,10 +414,147 @@ pub enum ClientMessage {
         #[serde(skip_serializing_if = "Option::is_none")]
         timeout_ms: Option<u64>,
     },
+    /// Reload the current configuration file, and roll back to the configuration from before
+    /// unless `ConfirmReload` is sent within `confirm_timeout_ms` (default: 30000) or if
+    /// processing fails in that time.
+    ReloadTry {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        confirm_timeout_ms: Option<u64>,
+    },
+    /// Keep the configuration loaded by `ReloadTry`.
+    ConfirmReload {},
+    /// Load the kept configuration from before the loaded one, even if its file changed since.
+    ReloadRollback {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        wait: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
+    /// Load the kept configuration that was loaded `n` configurations before the newest one,
+    /// which is `n = 0`.
+    ReloadVersion {
+        n: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        wait: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+    },
 
     /// Request server capabilities and version.
     /// Introduced in protocol v1.11.
-    Hello {},
+    Hello {
+        /// The framing of the messages after `HelloOk`, in both directions. Only TCP and Unix
+        /// socket clients can change it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        framing: Option<Framing>,
+        /// A name for the client, e.g. the name of the program, which the server records in
+        /// its audit log.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_name: Option<String>,
+    },
+    /// Authenticate with the token of the listener. On listeners that have a token, this must
+    /// be the first message; other messages are rejected and the client is disconnected.
+    Authenticate {
+        token: String,
+    },
+    /// Stops accepting the token on every listener and disconnects the clients that
+    /// authenticated with it, e.g. when it leaked. It stays revoked until kanata restarts, even
+    /// if it is still in a `tokens-file`. Server responds with `ServerResponse`.
+    RevokeToken {
+        token: String,
+    },
+    /// Sets the layers that transparent keys fall back to, in priority order, after the
+    /// active layers. An empty list restores the configured behaviour.
+    /// Lasts until the next reload.
+    SetLayerFallback {
+        names: Vec<String>,
+    },
+    /// Makes the keys of layer `name` behave like the keys of layer `target`.
+    /// Aliasing a layer to itself removes the alias.
+    /// Lasts until the next reload.
+    SetLayerAlias {
+        name: String,
+        target: String,
+    },
+    /// Sets the active application, which selects the layer and aliases of `defapp`.
+    /// An application that is not in `defapp` restores the layer from before.
+    SetActiveApp {
+        app: String,
+    },
+    /// Request statistics about processing, e.g. its latency. Server responds with `Stats`.
+    RequestStats {},
+    /// Request the bigrams and trigrams of key presses, counted with `--ngram-stats`. Server
+    /// responds with `NgramStats`, or with `Error` if they are not counted.
+    RequestNgramStats {},
+    /// Request the pointer position. Server responds with `MousePosition`, or with `Error` if the
+    /// output backend cannot read it.
+    RequestMousePosition {},
+    /// Sets the log level: trace, debug, info, warn, error or off. With `duration_ms`, the level
+    /// from before is restored after the duration.
+    SetLogLevel {
+        level: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        duration_ms: Option<u64>,
+    },
+    /// Scales the delays of macros by the percentage, e.g. 200 plays them twice as slow and 100
+    /// as configured. Lasts until it is set again.
+    SetMacroDelayScale {
+        percent: u16,
+    },
+    /// Sets parameters of the tap-hold action of an alias, which apply from its next press.
+    /// Parameters that are not given keep their value. The flavor is one of `tap-hold`,
+    /// `tap-hold-press` or `tap-hold-release`. Lasts until the next reload.
+    SetTapHold {
+        alias: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hold_timeout_ms: Option<u16>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tap_repress_timeout_ms: Option<u16>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        flavor: Option<String>,
+    },
+    /// Request the parameters of the tap-hold actions of aliases. Server responds with
+    /// `TapHolds`.
+    RequestTapHolds {},
+    /// Request the display names, icons and colors of the layers. Server responds with
+    /// `LayerMeta`.
+    RequestLayerMeta {},
+    /// Request the `defmeta` of the configuration. Server responds with `ConfigMeta`.
+    RequestConfigMeta {},
+    /// Turns training mode on or off. While it is on, the server sends a `TrainingKey` for every
+    /// key press, comparing with `reference_layer`, or with defsrc if it is not given.
+    SetTrainingMode {
+        enabled: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reference_layer: Option<String>,
+    },
+    /// Request the descriptions of aliases and layers. Server responds with `KeyDocs`.
+    RequestKeyDocs {},
+    /// Starts or stops sending `CmdStarted`, `CmdOutput` and `CmdExited` to this client, for the
+    /// commands of cmd actions.
+    SubscribeCmdOutput {
+        enabled: bool,
+    },
+    /// Kills the command of a cmd action with the `id` of `CmdStarted`, or the most recent
+    /// command that runs `program`, or the most recent command if both are left out.
+    CancelCmd {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        program: Option<String>,
+    },
+}
+
+/// How messages are separated on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Framing {
+    /// Each message is JSON followed by a newline.
+    #[default]
+    Newline,
+    /// Each message is JSON preceded by its length in bytes as a big-endian `u32`, without a
+    /// newline.
+    LengthPrefixed,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
@@ -151,6 +565,74 @@ pub enum FakeKeyActionMessage {
     Toggle,
 }
 
+/// Output events that a kanata with `linux-output-backend remote` sends to the `output:` listener
+/// of another kanata, which injects them. The sender first sends `Authenticate` and waits for
+/// `{"status":"Ok"}`, then sends one event per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RemoteOutput {
+    /// A key or mouse button with its kanata key code. The value is 0 for a release, 1 for a
+    /// press and 2 for a repeat.
+    Key { code: u16, value: u8 },
+    /// A relative mouse movement, positive to the right and down.
+    MouseMove { x: i32, y: i32 },
+    /// A scroll in units of 1/120 of a wheel notch, positive up and to the right.
+    Scroll { vertical: i32, horizontal: i32 },
+    /// Moves the pointer to a position on the screen.
+    MouseSet { x: u16, y: u16 },
+}
+
+impl RemoteOutput {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut msg = serde_json::to_vec(self).expect("RemoteOutput should serialize");
+        msg.push(b'\n');
+        msg
+    }
+}
+
+impl ClientMessage {
+    /// Whether the command changes the state of kanata, which read-only clients may not do.
+    /// Queries, `Hello` and `Authenticate` don't.
+    pub fn changes_state(&self) -> bool {
+        match self {
+            ClientMessage::RequestLayerNames {}
+            | ClientMessage::RequestFakeKeyNames {}
+            | ClientMessage::RequestCurrentLayerInfo {}
+            | ClientMessage::RequestCurrentLayerName {}
+            | ClientMessage::RequestStats {}
+            | ClientMessage::RequestNgramStats {}
+            | ClientMessage::RequestMousePosition {}
+            | ClientMessage::RequestTapHolds {}
+            | ClientMessage::RequestLayerMeta {}
+            | ClientMessage::RequestConfigMeta {}
+            | ClientMessage::RequestKeyDocs {}
+            | ClientMessage::SubscribeCmdOutput { .. }
+            | ClientMessage::Hello { .. }
+            | ClientMessage::Authenticate { .. } => false,
+            ClientMessage::ChangeLayer { .. }
+            | ClientMessage::ActOnFakeKey { .. }
+            | ClientMessage::SetMouse { .. }
+            | ClientMessage::Reload { .. }
+            | ClientMessage::ReloadNext { .. }
+            | ClientMessage::ReloadPrev { .. }
+            | ClientMessage::ReloadNum { .. }
+            | ClientMessage::ReloadFile { .. }
+            | ClientMessage::ReloadTry { .. }
+            | ClientMessage::ConfirmReload {}
+            | ClientMessage::ReloadRollback { .. }
+            | ClientMessage::ReloadVersion { .. }
+            | ClientMessage::RevokeToken { .. }
+            | ClientMessage::SetLayerFallback { .. }
+            | ClientMessage::SetLayerAlias { .. }
+            | ClientMessage::SetActiveApp { .. }
+            | ClientMessage::SetLogLevel { .. }
+            | ClientMessage::SetMacroDelayScale { .. }
+            | ClientMessage::SetTapHold { .. }
+            | ClientMessage::SetTrainingMode { .. }
+            | ClientMessage::CancelCmd { .. } => true,
+        }
+    }
+}
+
 impl FromStr for ClientMessage {
     type Err = serde_json::Error;
 
@@ -171,6 +653,12 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_remote_output_json_format() {
+        let event = RemoteOutput::Key { code: 30, value: 1 };
+        assert_eq!(event.as_bytes(), b"{\"Key\":{\"code\":30,\"value\":1}}\n");
+    }
+
     #[test]
     fn test_as_bytes_includes_newline() {
         let response = ServerResponse::Ok;
@@ -183,12 +671,49 @@ mod tests {
             version: "1.10.0".to_string(),
             protocol: 1,
             capabilities: vec!["reload".to_string()],
+            seat: None,
+            framing: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("HelloOk"));
         assert!(json.contains("\"version\":\"1.10.0\""));
     }
 
+    #[test]
+    fn test_hello_ok_seat_and_framing() {
+        let msg = ServerMessage::HelloOk {
+            version: "1.10.0".to_string(),
+            protocol: 1,
+            capabilities: vec![],
+            seat: None,
+            framing: None,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(!json.contains("seat"));
+        assert!(!json.contains("framing"));
+
+        let msg = ServerMessage::HelloOk {
+            version: "1.10.0".to_string(),
+            protocol: 1,
+            capabilities: vec![],
+            seat: Some("seat1".to_string()),
+            framing: Some(Framing::LengthPrefixed),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"seat\":\"seat1\""));
+        assert!(json.contains("\"framing\":\"length-prefixed\""));
+
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"Hello":{"framing":"length-prefixed"}}"#).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::Hello {
+                framing: Some(Framing::LengthPrefixed),
+                client_name: None,
+            }
+        ));
+    }
+
     #[test]
     fn test_reload_with_wait() {
         let msg = ClientMessage::Reload {
@@ -228,6 +753,262 @@ mod tests {
         let _msg: ClientMessage = serde_json::from_str(json).unwrap();
     }
 
+    #[test]
+    fn test_ngram_stats_json_format() {
+        let msg = ServerMessage::NgramStats {
+            keys: "keys".into(),
+            layers: [(
+                "base".to_string(),
+                LayerNgrams {
+                    bigrams: vec![Ngram {
+                        keys: vec!["t".into(), "h".into()],
+                        count: 3,
+                    }],
+                    trigrams: vec![],
+                },
+            )]
+            .into(),
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"NgramStats":{"keys":"keys","layers":{"base":{"bigrams":[{"keys":["t","h"],"count":3}],"trigrams":[]}}}}"#
+        );
+    }
+
+    #[test]
+    fn test_set_log_level() {
+        let json = r#"{"SetLogLevel":{"level":"trace","duration_ms":30000}}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::SetLogLevel { level, duration_ms: Some(30000) } if level == "trace"
+        ));
+        let json = r#"{"SetLogLevel":{"level":"info"}}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::SetLogLevel {
+                duration_ms: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_set_macro_delay_scale() {
+        let json = r#"{"SetMacroDelayScale":{"percent":200}}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::SetMacroDelayScale { percent: 200 }
+        ));
+        assert!(msg.changes_state());
+    }
+
+    #[test]
+    fn test_set_tap_hold() {
+        let json = r#"{"SetTapHold":{"alias":"a","hold_timeout_ms":180}}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            &msg,
+            ClientMessage::SetTapHold {
+                alias,
+                hold_timeout_ms: Some(180),
+                tap_repress_timeout_ms: None,
+                flavor: None,
+            } if alias == "a"
+        ));
+        assert!(msg.changes_state());
+        let msg: ClientMessage = serde_json::from_str(r#"{"RequestTapHolds":{}}"#).unwrap();
+        assert!(!msg.changes_state());
+    }
+
+    #[test]
+    fn test_layer_meta_json_format() {
+        let msg: ClientMessage = serde_json::from_str(r#"{"RequestLayerMeta":{}}"#).unwrap();
+        assert!(!msg.changes_state());
+        let msg = ServerMessage::LayerMeta {
+            layers: vec![
+                LayerMeta {
+                    name: "base".to_owned(),
+                    display_name: None,
+                    icon: None,
+                    color: None,
+                },
+                LayerMeta {
+                    name: "nav".to_owned(),
+                    display_name: Some("Navigation".to_owned()),
+                    icon: Some("🧭".to_owned()),
+                    color: Some("#3366ff".to_owned()),
+                },
+            ],
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r##"{"LayerMeta":{"layers":[{"name":"base"},{"name":"nav","display_name":"Navigation","icon":"🧭","color":"#3366ff"}]}}"##
+        );
+    }
+
+    #[test]
+    fn test_config_meta_json_format() {
+        let msg: ClientMessage = serde_json::from_str(r#"{"RequestConfigMeta":{}}"#).unwrap();
+        assert!(!msg.changes_state());
+        let msg = ServerMessage::ConfigMeta {
+            author: Some("jane".to_owned()),
+            description: None,
+            version: Some("2".to_owned()),
+            keyboard: None,
+            tags: vec!["hrm".to_owned()],
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ConfigMeta":{"author":"jane","version":"2","tags":["hrm"]}}"#
+        );
+    }
+
+    #[test]
+    fn test_training_mode_json_format() {
+        let json = r#"{"SetTrainingMode":{"enabled":true,"reference_layer":"qwerty"}}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            &msg,
+            ClientMessage::SetTrainingMode {
+                enabled: true,
+                reference_layer: Some(layer),
+            } if layer == "qwerty"
+        ));
+        assert!(msg.changes_state());
+        let msg = ServerMessage::TrainingMode {
+            enabled: false,
+            reference_layer: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"TrainingMode":{"enabled":false}}"#
+        );
+        let msg = ServerMessage::TrainingKey {
+            key: "e".to_owned(),
+            layer: "colemak".to_owned(),
+            action: "f".to_owned(),
+            reference: "e".to_owned(),
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"TrainingKey":{"key":"e","layer":"colemak","action":"f","reference":"e"}}"#
+        );
+    }
+
+    #[test]
+    fn test_key_docs_json_format() {
+        let msg: ClientMessage = serde_json::from_str(r#"{"RequestKeyDocs":{}}"#).unwrap();
+        assert!(!msg.changes_state());
+        let msg = ServerMessage::KeyDocs {
+            aliases: BTreeMap::from([("copy".to_owned(), "Copy to clipboard".to_owned())]),
+            layers: BTreeMap::from([("nav".to_owned(), "Arrows and paging".to_owned())]),
+            virtual_keys: BTreeMap::new(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"KeyDocs":{"aliases":{"copy":"Copy to clipboard"},"layers":{"nav":"Arrows and paging"},"virtual_keys":{}}}"#
+        );
+    }
+
+    #[test]
+    fn test_cmd_output_json_format() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"SubscribeCmdOutput":{"enabled":true}}"#).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::SubscribeCmdOutput { enabled: true }
+        ));
+        assert!(!msg.changes_state());
+        let msg: ClientMessage = serde_json::from_str(r#"{"CancelCmd":{}}"#).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::CancelCmd {
+                id: None,
+                program: None
+            }
+        ));
+        assert!(msg.changes_state());
+        let msg = ServerMessage::CmdStarted {
+            id: 3,
+            cmd: vec!["notify-send".to_owned(), "hi".to_owned()],
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"CmdStarted":{"id":3,"cmd":["notify-send","hi"]}}"#
+        );
+        let msg = ServerMessage::CmdOutput {
+            id: 3,
+            stream: "stderr".to_owned(),
+            line: "warning".to_owned(),
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"CmdOutput":{"id":3,"stream":"stderr","line":"warning"}}"#
+        );
+        let msg = ServerMessage::CmdExited {
+            id: 3,
+            code: Some(0),
+            error: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"CmdExited":{"id":3,"code":0}}"#
+        );
+    }
+
+    #[test]
+    fn test_sequence_menu_json_format() {
+        let msg = ServerMessage::SequenceMenu {
+            active: true,
+            typed: vec!["g".to_owned()],
+            next: vec![
+                SequenceMenuEntry {
+                    key: "d".to_owned(),
+                    virtual_key: Some("goto-def".to_owned()),
+                    description: Some("Go to definition".to_owned()),
+                    continues: false,
+                },
+                SequenceMenuEntry {
+                    key: "S-t".to_owned(),
+                    virtual_key: None,
+                    description: None,
+                    continues: true,
+                },
+            ],
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"SequenceMenu":{"active":true,"typed":["g"],"next":[{"key":"d","virtual_key":"goto-def","description":"Go to definition","continues":false},{"key":"S-t","continues":true}]}}"#
+        );
+    }
+
+    #[test]
+    fn test_tap_holds_json_format() {
+        let msg = ServerMessage::TapHolds {
+            aliases: BTreeMap::from([(
+                "a".to_owned(),
+                TapHoldParams {
+                    hold_timeout_ms: 180,
+                    tap_repress_timeout_ms: 200,
+                    flavor: "tap-hold-release".to_owned(),
+                    tuned: true,
+                },
+            )]),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"TapHolds":{"aliases":{"a":{"hold_timeout_ms":180,"tap_repress_timeout_ms":200,"flavor":"tap-hold-release","tuned":true}}}}"#
+        );
+    }
+
     #[test]
     fn test_request_fake_key_names() {
         let json = r#"{"RequestFakeKeyNames":{}}"#;
@@ -280,4 +1061,251 @@ mod tests {
         let json = serde_json::to_string(&msg).unwrap();
         assert_eq!(json, r#"{"TapActivated":{"key":"a"}}"#);
     }
+
+    #[test]
+    fn test_emergency_passthrough_json_format() {
+        let msg = ServerMessage::EmergencyPassthrough { active: true };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"EmergencyPassthrough":{"active":true}}"#);
+    }
+
+    #[test]
+    fn test_processing_paused_json_format() {
+        let msg = ServerMessage::ProcessingPaused { paused: false };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"ProcessingPaused":{"paused":false}}"#);
+    }
+
+    #[test]
+    fn test_os_layout_change_json_format() {
+        let msg = ServerMessage::OsLayoutChange {
+            layout: "de-DE".to_owned(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"OsLayoutChange":{"layout":"de-DE"}}"#);
+    }
+
+    #[test]
+    fn test_device_change_json_format() {
+        let msg = ServerMessage::DeviceChange {
+            path: "/dev/input/event3".to_owned(),
+            name: "BT Keyboard".to_owned(),
+            connected: false,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"DeviceChange":{"path":"/dev/input/event3","name":"BT Keyboard","connected":false}}"#
+        );
+    }
+
+    #[test]
+    fn test_stuck_key_released_json_format() {
+        let msg = ServerMessage::StuckKeyReleased {
+            key: "lsft".to_owned(),
+            held_ms: 5000,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"StuckKeyReleased":{"key":"lsft","held_ms":5000}}"#
+        );
+    }
+
+    #[test]
+    fn test_config_file_reload_json_format() {
+        let msg = ServerMessage::ConfigFileReload {
+            new: "a.kbd".to_owned(),
+            changes: Some(ReloadChanges {
+                modified_layers: vec!["base".to_owned()],
+                ..Default::default()
+            }),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"ConfigFileReload":{"new":"a.kbd","changes":{"added_layers":[],"removed_layers":[],"modified_layers":["base"],"changed_options":[],"changed_aliases":[]}}}"#
+        );
+        let msg: ServerMessage =
+            serde_json::from_str(r#"{"ConfigFileReload":{"new":"a.kbd"}}"#).unwrap();
+        assert!(matches!(
+            msg,
+            ServerMessage::ConfigFileReload { changes: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_revoke_token_json_format() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"RevokeToken":{"token":"leaked"}}"#).unwrap();
+        assert!(matches!(msg, ClientMessage::RevokeToken { token } if token == "leaked"));
+    }
+
+    #[test]
+    fn test_notifications_dropped_json_format() {
+        let msg = ServerMessage::NotificationsDropped { count: 12 };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"NotificationsDropped":{"count":12}}"#
+        );
+    }
+
+    #[test]
+    fn test_forbidden_json_format() {
+        let response = ServerResponse::Forbidden {
+            command: "ChangeLayer".to_string(),
+            msg: "this client is read-only".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"status":"Forbidden","command":"ChangeLayer","msg":"this client is read-only"}"#
+        );
+        let msg: ClientMessage = serde_json::from_str(r#"{"RequestLayerNames":{}}"#).unwrap();
+        assert!(!msg.changes_state());
+        let msg: ClientMessage = serde_json::from_str(r#"{"ChangeLayer":{"new":"base"}}"#).unwrap();
+        assert!(msg.changes_state());
+    }
+
+    #[test]
+    fn test_reload_try_json_format() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"ReloadTry":{"confirm_timeout_ms":10000}}"#).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::ReloadTry {
+                confirm_timeout_ms: Some(10000)
+            }
+        ));
+        let msg: ClientMessage = serde_json::from_str(r#"{"ConfirmReload":{}}"#).unwrap();
+        assert!(matches!(msg, ClientMessage::ConfirmReload {}));
+        let msg = ServerMessage::ReloadRolledBack {
+            reason: "not confirmed".to_owned(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"ReloadRolledBack":{"reason":"not confirmed"}}"#);
+    }
+
+    #[test]
+    fn test_reload_history_json_format() {
+        let msg: ClientMessage = serde_json::from_str(r#"{"ReloadRollback":{}}"#).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::ReloadRollback {
+                wait: None,
+                timeout_ms: None
+            }
+        ));
+        assert!(msg.changes_state());
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"ReloadVersion":{"n":2,"wait":true}}"#).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::ReloadVersion {
+                n: 2,
+                wait: Some(true),
+                timeout_ms: None
+            }
+        ));
+    }
+
+    #[test]
+    fn test_stats_json_format() {
+        let msg: ClientMessage = serde_json::from_str(r#"{"RequestStats":{}}"#).unwrap();
+        assert!(matches!(msg, ClientMessage::RequestStats {}));
+
+        let msg = ServerMessage::Stats {
+            presses: 0,
+            wpm: 0.0,
+            keys_per_second: 0.0,
+            latency: None,
+            state_locked: false,
+            threads: vec![],
+            channels: vec![],
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"Stats":{"presses":0,"wpm":0.0,"keys_per_second":0.0}}"#
+        );
+        let msg = ServerMessage::Stats {
+            presses: 42,
+            wpm: 62.5,
+            keys_per_second: 5.5,
+            latency: Some(LatencyStats {
+                count: 10,
+                p50_us: 120,
+                p99_us: 850,
+                max_us: 900,
+            }),
+            state_locked: false,
+            threads: vec![],
+            channels: vec![],
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"Stats":{"presses":42,"wpm":62.5,"keys_per_second":5.5,"latency":{"count":10,"p50_us":120,"p99_us":850,"max_us":900}}}"#
+        );
+        let msg = ServerMessage::Stats {
+            presses: 0,
+            wpm: 0.0,
+            keys_per_second: 0.0,
+            latency: None,
+            state_locked: true,
+            threads: vec![ThreadHealth {
+                name: "processor".into(),
+                threads: 1,
+                busy_ms: Some(12000),
+                last_active_ms_ago: Some(12000),
+            }],
+            channels: vec![ChannelDepth {
+                name: "input-events".into(),
+                len: 100,
+                capacity: 100,
+            }],
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"Stats":{"presses":0,"wpm":0.0,"keys_per_second":0.0,"state_locked":true,"threads":[{"name":"processor","threads":1,"busy_ms":12000,"last_active_ms_ago":12000}],"channels":[{"name":"input-events","len":100,"capacity":100}]}}"#
+        );
+        // Servers from before the typing speed send stats without it.
+        let msg: ServerMessage = serde_json::from_str(r#"{"Stats":{"presses":7}}"#).unwrap();
+        assert!(matches!(msg, ServerMessage::Stats { presses: 7, wpm, .. } if wpm == 0.0));
+        let msg = ServerMessage::TypingSpeed {
+            wpm: 48.0,
+            keys_per_second: 4.5,
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"TypingSpeed":{"wpm":48.0,"keys_per_second":4.5}}"#
+        );
+    }
+
+    #[test]
+    fn test_mouse_position_json_format() {
+        let msg: ClientMessage = serde_json::from_str(r#"{"RequestMousePosition":{}}"#).unwrap();
+        assert!(matches!(msg, ClientMessage::RequestMousePosition {}));
+        let msg = ServerMessage::MousePosition { x: -1280, y: 540 };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"MousePosition":{"x":-1280,"y":540}}"#
+        );
+    }
+
+    #[test]
+    fn test_runtime_layer_commands() {
+        let json = r#"{"SetLayerFallback":{"names":["nav","base"]}}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ClientMessage::SetLayerFallback { names } => assert_eq!(names, ["nav", "base"]),
+            _ => panic!("Expected SetLayerFallback"),
+        }
+        let json = r#"{"SetLayerAlias":{"name":"nav","target":"demo"}}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ClientMessage::SetLayerAlias { name, target } => {
+                assert_eq!(name, "nav");
+                assert_eq!(target, "demo");
+            }
+            _ => panic!("Expected SetLayerAlias"),
+        }
+    }
 }
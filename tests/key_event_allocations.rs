@@ -0,0 +1,82 @@
+//! Checks that handling key events does not allocate once the state has warmed up, since the
+//! allocator shows up in profiles of fast typing and mouse movement otherwise.
+//!
+//! The counting allocator replaces the global allocator of the whole test binary, which is why
+//! this test has a binary of its own instead of being with the simulated tests.
+
+#![cfg(feature = "simulated_output")]
+
+use kanata_parser::keys::str_to_oscode;
+use kanata_state_machine::engine::Engine;
+use kanata_state_machine::oskbd::{KeyEvent, KeyValue};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts the allocations of each thread so that the harness threads don't interfere.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|n| n.get())
+}
+
+#[test]
+fn key_events_do_not_allocate() {
+    let mut engine = Engine::new(
+        "(defsrc a b c d e)
+         (deflayer base a (tap-hold 50 50 b lsft) (layer-while-held nav) d e)
+         (deflayer nav (movemouse-right 1 2) _ _ (movemouse-down 1 2) ✗)",
+        |_| {},
+    )
+    .expect("valid cfg");
+    // Taps, a tap-hold both ways, then mouse movement while holding the layer key.
+    let inputs = [
+        ("a", KeyValue::Press, 5),
+        ("a", KeyValue::Release, 5),
+        ("b", KeyValue::Press, 10),
+        ("b", KeyValue::Release, 5),
+        ("b", KeyValue::Press, 100),
+        ("b", KeyValue::Release, 5),
+        ("c", KeyValue::Press, 5),
+        ("a", KeyValue::Press, 30),
+        ("d", KeyValue::Press, 30),
+        ("a", KeyValue::Release, 5),
+        ("d", KeyValue::Release, 5),
+        ("c", KeyValue::Release, 100),
+    ];
+    let typing = |engine: &mut Engine| {
+        for (name, value, ticks) in inputs {
+            let code = str_to_oscode(name).unwrap();
+            engine.handle_input(KeyEvent::new(code, value)).unwrap();
+            engine.tick(ticks).unwrap();
+        }
+    };
+    // Buffers grow to their working size on the first run.
+    typing(&mut engine);
+    let before = allocations();
+    typing(&mut engine);
+    assert_eq!(allocations() - before, 0);
+}